@@ -0,0 +1,23 @@
+// build.rs - Generates the protobuf message types for the `protobuf` feature
+
+fn main() {
+    println!("cargo:rerun-if-changed=proto");
+    println!("cargo:rerun-if-env-changed=PROTOC");
+    #[cfg(feature = "protobuf")]
+    compile_protos();
+}
+
+/// Compile the message types with the `protoc` in `PROTOC`, or the vendored
+/// one when it is unset, so the feature builds without protoc installed
+#[cfg(feature = "protobuf")]
+fn compile_protos() {
+    if std::env::var_os("PROTOC").is_none() {
+        let protoc = protoc_bin_vendored::protoc_bin_path()
+            .unwrap_or_else(|e| panic!("no vendored protoc for this host, set PROTOC: {}", e));
+        // SAFETY: the build script is single-threaded
+        unsafe { std::env::set_var("PROTOC", protoc) };
+    }
+    if let Err(e) = prost_build::compile_protos(&["proto/swarmhost.proto"], &["proto"]) {
+        panic!("failed to compile proto/swarmhost.proto: {}", e);
+    }
+}
@@ -0,0 +1,250 @@
+// consensus/action.rs - Signed player actions
+
+use crate::crypto::{self, Hash, KeyPair, PlayerId};
+use crate::error::Result;
+use serde::{Deserialize, Serialize};
+use std::sync::OnceLock;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::time::Instant;
+
+/// Identifier of an action: the hash of its signed bytes
+pub type ActionId = Hash;
+
+/// A player action, signed by the player who submitted it
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SignedAction {
+    /// Game session the action belongs to
+    pub game_id: String,
+
+    /// Player who submitted the action
+    pub actor: PlayerId,
+
+    /// Per-actor counter so identical moves get distinct ids
+    pub nonce: u64,
+
+    /// Game-defined action type
+    pub action_type: u32,
+
+    /// Game-defined payload
+    pub data: Vec<u8>,
+
+    /// Game-defined entities the action touches; actions in one round that
+    /// share a key conflict, and the game's
+    /// [`ConflictPolicy`](crate::node::ConflictPolicy) settles which stand
+    #[serde(default)]
+    pub conflict_keys: Vec<u64>,
+
+    /// Time on the [`clock_ms`] clock past which the action must not be
+    /// committed; none for an action that never goes stale
+    #[serde(default)]
+    pub deadline_ms: Option<u64>,
+
+    /// Actions that must be delivered before this one, for a
+    /// [`Causal`](super::OrderingClass::Causal) action type
+    #[serde(default)]
+    pub depends_on: Vec<ActionId>,
+
+    /// Actor's signature over [`SignedAction::signing_bytes`]
+    pub signature: Vec<u8>,
+}
+
+impl SignedAction {
+    /// Build and sign an action
+    pub fn new(
+        keypair: &KeyPair,
+        game_id: impl Into<String>,
+        nonce: u64,
+        action_type: u32,
+        data: Vec<u8>,
+    ) -> Self {
+        Self::touching(keypair, game_id, nonce, action_type, data, Vec::new())
+    }
+
+    /// Build and sign an action touching the entities in `conflict_keys`
+    pub fn touching(
+        keypair: &KeyPair,
+        game_id: impl Into<String>,
+        nonce: u64,
+        action_type: u32,
+        data: Vec<u8>,
+        conflict_keys: Vec<u64>,
+    ) -> Self {
+        Self::expiring(
+            keypair,
+            game_id,
+            nonce,
+            action_type,
+            data,
+            conflict_keys,
+            None,
+        )
+    }
+
+    /// Build and sign an action touching the entities in `conflict_keys`
+    /// that is only to be committed up to `deadline_ms`
+    pub fn expiring(
+        keypair: &KeyPair,
+        game_id: impl Into<String>,
+        nonce: u64,
+        action_type: u32,
+        data: Vec<u8>,
+        conflict_keys: Vec<u64>,
+        deadline_ms: Option<u64>,
+    ) -> Self {
+        let mut action = Self {
+            game_id: game_id.into(),
+            actor: keypair.public_key(),
+            nonce,
+            action_type,
+            data,
+            conflict_keys,
+            deadline_ms,
+            depends_on: Vec::new(),
+            signature: Vec::new(),
+        };
+        action.sign(keypair);
+        action
+    }
+
+    /// Sign the action as it stands, with `keypair`'s player its actor
+    pub fn sign(&mut self, keypair: &KeyPair) {
+        self.actor = keypair.public_key();
+        self.signature = keypair.sign(&self.signing_bytes());
+    }
+
+    /// Canonical bytes covered by the signature
+    pub fn signing_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(64 + self.game_id.len() + self.data.len());
+        bytes.extend_from_slice(b"swarmhost-action-v1");
+        bytes.extend_from_slice(&(self.game_id.len() as u32).to_be_bytes());
+        bytes.extend_from_slice(self.game_id.as_bytes());
+        bytes.extend_from_slice(&self.actor);
+        bytes.extend_from_slice(&self.nonce.to_be_bytes());
+        bytes.extend_from_slice(&self.action_type.to_be_bytes());
+        bytes.extend_from_slice(&(self.data.len() as u32).to_be_bytes());
+        bytes.extend_from_slice(&self.data);
+        bytes.extend_from_slice(&(self.conflict_keys.len() as u32).to_be_bytes());
+        for key in &self.conflict_keys {
+            bytes.extend_from_slice(&key.to_be_bytes());
+        }
+        // Only actions with a deadline or dependencies carry them, so others
+        // keep their ids
+        if let Some(deadline_ms) = self.deadline_ms {
+            bytes.extend_from_slice(&deadline_ms.to_be_bytes());
+        }
+        if !self.depends_on.is_empty() {
+            bytes.extend_from_slice(&(self.depends_on.len() as u32).to_be_bytes());
+            for dependency in &self.depends_on {
+                bytes.extend_from_slice(dependency);
+            }
+        }
+        bytes
+    }
+
+    /// Whether the deadline passed by `now_ms` on the [`clock_ms`] clock
+    pub fn is_expired(&self, now_ms: u64) -> bool {
+        self.deadline_ms
+            .is_some_and(|deadline_ms| now_ms > deadline_ms)
+    }
+
+    /// Action id (hash of the signed bytes)
+    pub fn id(&self) -> ActionId {
+        crypto::hash(&self.signing_bytes())
+    }
+
+    /// Check the signature against the actor's public key
+    pub fn verify(&self) -> Result<()> {
+        crypto::verify_signature(&self.actor, &self.signing_bytes(), &self.signature)
+    }
+}
+
+/// Milliseconds since the Unix epoch at `at`, as deadlines are set and
+/// checked: the wall clock when first read, moved on by tokio's clock since
+///
+/// Nodes' clocks agree as far as their wall clocks do, and deadlines pass
+/// with time paused in tests as they would in real time.
+pub fn clock_ms(at: Instant) -> u64 {
+    static EPOCH: OnceLock<(u64, Instant)> = OnceLock::new();
+    let (epoch_ms, epoch) = *EPOCH.get_or_init(|| {
+        let wall = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        (wall.as_millis() as u64, Instant::now())
+    });
+    match at.checked_duration_since(epoch) {
+        Some(since) => epoch_ms.saturating_add(since.as_millis() as u64),
+        None => epoch_ms.saturating_sub(epoch.duration_since(at).as_millis() as u64),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sign_and_verify() {
+        let keypair = KeyPair::generate();
+        let action = SignedAction::new(&keypair, "game", 1, 7, b"move".to_vec());
+        assert!(action.verify().is_ok());
+
+        let mut tampered = action.clone();
+        tampered.data = b"teleport".to_vec();
+        assert!(tampered.verify().is_err());
+        assert_ne!(tampered.id(), action.id());
+    }
+
+    #[test]
+    fn test_nonce_changes_id() {
+        let keypair = KeyPair::generate();
+        let first = SignedAction::new(&keypair, "game", 1, 7, b"move".to_vec());
+        let second = SignedAction::new(&keypair, "game", 2, 7, b"move".to_vec());
+        assert_ne!(first.id(), second.id());
+    }
+
+    #[test]
+    fn test_conflict_keys_are_signed() {
+        let keypair = KeyPair::generate();
+        let action = SignedAction::touching(&keypair, "game", 1, 7, vec![], vec![3, 5]);
+        assert!(action.verify().is_ok());
+
+        let mut stripped = action.clone();
+        stripped.conflict_keys.clear();
+        assert!(stripped.verify().is_err());
+    }
+
+    #[test]
+    fn test_deadline_is_signed() {
+        let keypair = KeyPair::generate();
+        let lasting = SignedAction::touching(&keypair, "game", 1, 7, vec![], vec![3]);
+        let action = SignedAction::expiring(&keypair, "game", 1, 7, vec![], vec![3], Some(1_000));
+        assert!(action.verify().is_ok());
+        assert_ne!(action.id(), lasting.id());
+        assert!(!action.is_expired(1_000));
+        assert!(action.is_expired(1_001));
+        assert!(!lasting.is_expired(u64::MAX));
+
+        let mut stripped = action.clone();
+        stripped.deadline_ms = None;
+        assert!(stripped.verify().is_err());
+        let mut extended = action;
+        extended.deadline_ms = Some(5_000);
+        assert!(extended.verify().is_err());
+    }
+
+    #[test]
+    fn test_dependencies_are_signed() {
+        let keypair = KeyPair::generate();
+        let first = SignedAction::new(&keypair, "game", 1, 7, vec![]);
+        let mut second = SignedAction::new(&keypair, "game", 2, 7, vec![]);
+        let unrelated = second.id();
+        second.depends_on = vec![first.id()];
+        assert!(second.verify().is_err());
+        second.sign(&keypair);
+        assert!(second.verify().is_ok());
+        assert_ne!(second.id(), unrelated);
+
+        let mut stripped = second.clone();
+        stripped.depends_on.clear();
+        assert!(stripped.verify().is_err());
+    }
+}
@@ -0,0 +1,187 @@
+// consensus/beacon.rs - Randomness every node draws alike and no one node
+// chose, hashed from the approvals that certify a round
+
+use super::membership::Membership;
+use super::vote::Vote;
+use crate::crypto::{self, Hash, short_id};
+use crate::error::{Result, SwarmhostError};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+
+/// A round's shared randomness
+///
+/// Drawn from the signatures of the approvals certifying the round's block,
+/// or an action approved on its own. Signatures are deterministic, and a
+/// quorum holds a validator besides the proposer unless the proposer alone
+/// weighs one, so the beacon cannot be known before the votes are cast.
+/// The sequencer signs the certificate into each commit, so every node
+/// draws the same beacon from it; what it can still do is leave out
+/// approvals past a quorum, choosing among as many beacons as it holds
+/// quorums.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct Randomness(pub Hash);
+
+impl Randomness {
+    /// The beacon of a certificate, whatever order its approvals are in
+    pub fn of(certificate: &[Vote]) -> Self {
+        let mut approvals: Vec<&Vote> = certificate.iter().collect();
+        approvals.sort_by_key(|vote| vote.voter);
+        let mut pieces: Vec<&[u8]> = vec![b"swarmhost-beacon-v1"];
+        for vote in approvals {
+            pieces.push(&vote.voter);
+            pieces.push(&vote.signature);
+        }
+        Self(crypto::hash_multiple(&pieces))
+    }
+
+    /// A number below `n`, each as likely as the next
+    ///
+    /// The beacon modulo `n` would favour low numbers whenever `n` does not
+    /// divide 2^64, so draws past the last whole multiple of `n` are thrown
+    /// away and drawn again.
+    ///
+    /// # Panics
+    ///
+    /// If `n` is zero.
+    pub fn range(&self, n: u64) -> u64 {
+        assert!(n > 0, "no number is below zero");
+        let zone = u64::MAX - u64::MAX % n;
+        (0u64..)
+            .map(|draw| {
+                let bytes =
+                    crypto::hash_multiple(&[b"swarmhost-range", &self.0, &draw.to_be_bytes()]);
+                u64::from_be_bytes(bytes[..8].try_into().expect("a hash is 32 bytes"))
+            })
+            .find(|value| *value < zone)
+            .expect("a draw lands below the zone")
+            % n
+    }
+}
+
+/// The fewest of `approvals`, taken in order of voter, that members of
+/// `validators` weighing `required` cast; empty when they fall short
+pub fn certificate(approvals: &[Vote], validators: &Membership, required: u64) -> Vec<Vote> {
+    let mut approvals: Vec<&Vote> = approvals
+        .iter()
+        .filter(|vote| vote.approves() && validators.contains(&vote.voter))
+        .collect();
+    approvals.sort_by_key(|vote| vote.voter);
+    approvals.dedup_by_key(|vote| vote.voter);
+    let mut certificate = Vec::new();
+    let mut weight = 0;
+    for vote in approvals {
+        if weight >= required {
+            break;
+        }
+        weight += validators.weight_of(&vote.voter);
+        certificate.push(vote.clone());
+    }
+    if weight < required {
+        return Vec::new();
+    }
+    certificate
+}
+
+/// Check a certificate is approvals of one action or block by distinct
+/// members of `validators` weighing `required`, returning what they approve
+pub fn verify(certificate: &[Vote], validators: &Membership, required: u64) -> Result<Hash> {
+    let Some(subject) = certificate.first().map(|vote| vote.action_id) else {
+        return Err(SwarmhostError::consensus("empty certificate"));
+    };
+    let mut voters = HashSet::new();
+    for vote in certificate {
+        if vote.action_id != subject
+            || !vote.approves()
+            || !validators.contains(&vote.voter)
+            || !voters.insert(vote.voter)
+        {
+            return Err(SwarmhostError::consensus(format!(
+                "vote by {} in the certificate of {} is not a validator's approval",
+                short_id(&vote.voter),
+                short_id(&subject)
+            )));
+        }
+        vote.verify()?;
+    }
+    let approving = validators.weight(&voters);
+    if approving < required {
+        return Err(SwarmhostError::consensus(format!(
+            "certificate of {} weighs {} of {}",
+            short_id(&subject),
+            approving,
+            required
+        )));
+    }
+    Ok(subject)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::consensus::{Decision, RoundProposal, SignedAction};
+    use crate::crypto::KeyPair;
+
+    #[test]
+    fn test_proposer_cannot_know_the_beacon_when_it_proposes() {
+        let proposer = KeyPair::generate();
+        let voters = [KeyPair::generate(), KeyPair::generate()];
+        let validators = Membership::equal(
+            std::iter::once(&proposer)
+                .chain(&voters)
+                .map(KeyPair::public_key),
+        );
+        let action = SignedAction::new(&proposer, "game", 0, 1, vec![]);
+        let block = RoundProposal::new(&proposer, 0, None, vec![action])
+            .header()
+            .hash();
+        let approve = |keypair: &KeyPair| Vote::new(keypair, block, 0, Decision::Approve);
+
+        // All the proposer can sign alone is no certificate
+        let own = [approve(&proposer)];
+        assert!(certificate(&own, &validators, 2).is_empty());
+        assert!(verify(&own, &validators, 2).is_err());
+
+        // Any quorum needs another validator's signature, and each one
+        // draws a different beacon
+        let with =
+            |voter: &KeyPair| certificate(&[approve(&proposer), approve(voter)], &validators, 2);
+        let first = with(&voters[0]);
+        let second = with(&voters[1]);
+        assert_eq!(verify(&first, &validators, 2).unwrap(), block);
+        assert_ne!(Randomness::of(&first), Randomness::of(&second));
+
+        // Every node draws the same from the same certificate
+        let mut reordered = first.clone();
+        reordered.reverse();
+        assert_eq!(Randomness::of(&first), Randomness::of(&reordered));
+
+        // One approving something else, or counted twice, spoils it
+        let mut padded = first.clone();
+        padded.push(first[0].clone());
+        assert!(verify(&padded, &validators, 2).is_err());
+        let mut mixed = first;
+        mixed[1] = Vote::new(&voters[0], [9; 32], 0, Decision::Approve);
+        assert!(verify(&mixed, &validators, 2).is_err());
+    }
+
+    #[test]
+    fn test_range_is_unbiased_and_in_bounds() {
+        // The beacon modulo 3 * 2^62 would land in the first third half
+        // the time; drawn again past it, a third
+        let n = 3 << 62;
+        let mut low = 0;
+        for seed in 0..3000u32 {
+            let beacon = Randomness(crypto::hash(&seed.to_be_bytes()));
+            let value = beacon.range(n);
+            assert!(value < n);
+            if value < n / 3 {
+                low += 1;
+            }
+        }
+        assert!((900..1100).contains(&low), "{} of 3000 low", low);
+
+        let beacon = Randomness([7; 32]);
+        assert_eq!(beacon.range(1), 0);
+        assert_eq!(beacon.range(6), beacon.range(6));
+    }
+}
@@ -0,0 +1,174 @@
+// consensus/conflict.rs - Actions in one round touching the same game entity
+
+use super::action::{ActionId, SignedAction};
+use crate::node::ConflictPolicy;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+
+/// Actions of one round sharing a conflict key, ordered by action id
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Conflict {
+    pub game_id: String,
+    pub key: u64,
+    pub actions: Vec<ActionId>,
+}
+
+/// How the conflicts of a round are settled
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct Resolution {
+    /// Actions that may not stand, each with an action it conflicted with
+    pub rejected: BTreeMap<ActionId, ActionId>,
+    /// Actions to commit only once those conflicting with them that come
+    /// first are committed or rejected
+    pub after: HashMap<ActionId, BTreeSet<ActionId>>,
+}
+
+/// Conflicts among `actions`, in order of game and key
+pub fn detect(actions: &[SignedAction]) -> Vec<Conflict> {
+    let mut touching: BTreeMap<(&str, u64), BTreeSet<ActionId>> = BTreeMap::new();
+    for action in actions {
+        let id = action.id();
+        for &key in &action.conflict_keys {
+            touching
+                .entry((&action.game_id, key))
+                .or_default()
+                .insert(id);
+        }
+    }
+    touching
+        .into_iter()
+        .filter(|(_, ids)| ids.len() > 1)
+        .map(|((game_id, key), ids)| Conflict {
+            game_id: game_id.to_string(),
+            key,
+            actions: ids.into_iter().collect(),
+        })
+        .collect()
+}
+
+/// Settle `conflicts` by the policy of each one's game
+///
+/// Actions are taken in order of id, so every node settles a round alike.
+/// Under [`ConflictPolicy::FirstWins`], an action stands unless one it
+/// conflicts with came first and stands; an action conflicting on two keys
+/// is weighed against both.
+pub fn resolve(conflicts: &[Conflict], policy: impl Fn(&str) -> ConflictPolicy) -> Resolution {
+    let mut conflicting: BTreeMap<ActionId, (ConflictPolicy, BTreeSet<ActionId>)> = BTreeMap::new();
+    for conflict in conflicts {
+        let policy = policy(&conflict.game_id);
+        for id in &conflict.actions {
+            let (_, others) = conflicting
+                .entry(*id)
+                .or_insert_with(|| (policy, BTreeSet::new()));
+            others.extend(conflict.actions.iter().filter(|other| *other != id));
+        }
+    }
+
+    let mut resolution = Resolution::default();
+    for (id, (policy, others)) in &conflicting {
+        let mut earlier = others.range(..*id);
+        match policy {
+            ConflictPolicy::FirstWins => {
+                let winner = earlier.find(|other| !resolution.rejected.contains_key(*other));
+                if let Some(winner) = winner {
+                    resolution.rejected.insert(*id, *winner);
+                }
+            }
+            ConflictPolicy::AllCommitOrdered => {
+                let earlier: BTreeSet<ActionId> = earlier.copied().collect();
+                if !earlier.is_empty() {
+                    resolution.after.insert(*id, earlier);
+                }
+            }
+            ConflictPolicy::RejectAll => {
+                if let Some(other) = others.first() {
+                    resolution.rejected.insert(*id, *other);
+                }
+            }
+        }
+    }
+    resolution
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::KeyPair;
+
+    #[test]
+    fn test_detects_shared_keys_within_a_game() {
+        let keypair = KeyPair::generate();
+        let touching = |nonce, game_id, keys: &[u64]| {
+            SignedAction::touching(&keypair, game_id, nonce, 1, vec![], keys.to_vec())
+        };
+        let all = [
+            touching(0, "game", &[1, 2]),
+            touching(1, "game", &[2]),
+            touching(2, "game", &[3]),
+            touching(3, "game", &[]),
+            touching(4, "other", &[2]),
+        ];
+
+        let mut expected = vec![all[0].id(), all[1].id()];
+        expected.sort();
+        assert_eq!(
+            detect(&all),
+            vec![Conflict {
+                game_id: "game".to_string(),
+                key: 2,
+                actions: expected,
+            }]
+        );
+    }
+
+    #[test]
+    fn test_policies_settle_a_chain_of_conflicts() {
+        // The first conflicts with the second, and the second with the third
+        let [a, b, c] = [[1; 32], [2; 32], [3; 32]];
+        let conflicts = [
+            Conflict {
+                game_id: "game".to_string(),
+                key: 1,
+                actions: vec![a, b],
+            },
+            Conflict {
+                game_id: "game".to_string(),
+                key: 2,
+                actions: vec![b, c],
+            },
+        ];
+
+        // The second loses to the first, so the third has nothing in its way
+        let first_wins = resolve(&conflicts, |_| ConflictPolicy::FirstWins);
+        assert_eq!(first_wins.rejected, BTreeMap::from([(b, a)]));
+        assert!(first_wins.after.is_empty());
+
+        let reject_all = resolve(&conflicts, |_| ConflictPolicy::RejectAll);
+        assert_eq!(
+            reject_all.rejected,
+            BTreeMap::from([(a, b), (b, a), (c, b)])
+        );
+
+        let ordered = resolve(&conflicts, |_| ConflictPolicy::AllCommitOrdered);
+        assert!(ordered.rejected.is_empty());
+        assert_eq!(
+            ordered.after,
+            HashMap::from([(b, BTreeSet::from([a])), (c, BTreeSet::from([b]))])
+        );
+
+        // Each game goes by its own policy
+        let d = [4; 32];
+        let elsewhere = Conflict {
+            game_id: "other".to_string(),
+            key: 1,
+            actions: vec![c, d],
+        };
+        let mixed = resolve(
+            &[conflicts[0].clone(), elsewhere],
+            |game_id| match game_id {
+                "game" => ConflictPolicy::RejectAll,
+                _ => ConflictPolicy::FirstWins,
+            },
+        );
+        assert_eq!(mixed.rejected, BTreeMap::from([(a, b), (b, a), (d, c)]));
+    }
+}
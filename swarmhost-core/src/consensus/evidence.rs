@@ -0,0 +1,141 @@
+// consensus/evidence.rs - Proof that a validator signed two conflicting
+// messages
+
+use super::rotation::RoundProposal;
+use super::sequence::Commit;
+use super::vote::Vote;
+use crate::crypto::{PlayerId, short_id};
+use crate::error::{Result, SwarmhostError};
+use serde::{Deserialize, Serialize};
+
+/// Two messages one validator signed for the same slot that cannot both
+/// stand, first as it reached us, second as it conflicted
+///
+/// Anyone can check it with [`Equivocation::verify`], without trusting
+/// whoever passed it on.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Equivocation {
+    /// Approving and rejecting the same action
+    Votes(Vote, Vote),
+    /// Putting different actions forward in the same round
+    Rounds(RoundProposal, RoundProposal),
+    /// Giving the same sequence in a game to different actions
+    Commits(Box<Commit>, Box<Commit>),
+}
+
+impl Equivocation {
+    /// Validator that signed both messages
+    pub fn offender(&self) -> PlayerId {
+        match self {
+            Equivocation::Votes(first, _) => first.voter,
+            Equivocation::Rounds(first, _) => first.proposer,
+            Equivocation::Commits(first, _) => first.sequencer,
+        }
+    }
+
+    /// Check that both messages are signed by the offender, are for the
+    /// same slot, and conflict
+    pub fn verify(&self) -> Result<()> {
+        let conflicts = match self {
+            Equivocation::Votes(first, second) => {
+                first.voter == second.voter
+                    && first.action_id == second.action_id
+                    && first.decision != second.decision
+            }
+            Equivocation::Rounds(first, second) => {
+                first.proposer == second.proposer
+                    && first.round == second.round
+                    && first.action_ids() != second.action_ids()
+            }
+            Equivocation::Commits(first, second) => {
+                first.sequencer == second.sequencer
+                    && first.action.game_id == second.action.game_id
+                    && first.sequence == second.sequence
+                    && first.action.id() != second.action.id()
+            }
+        };
+        if !conflicts {
+            return Err(SwarmhostError::consensus(format!(
+                "messages from {} do not conflict",
+                short_id(&self.offender())
+            )));
+        }
+        match self {
+            Equivocation::Votes(first, second) => {
+                first.verify()?;
+                second.verify()
+            }
+            Equivocation::Rounds(first, second) => {
+                first.verify()?;
+                second.verify()
+            }
+            Equivocation::Commits(first, second) => {
+                first.verify()?;
+                second.verify()
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::consensus::{Decision, SignedAction};
+    use crate::crypto::KeyPair;
+
+    #[test]
+    fn test_double_vote_is_verifiable_evidence() {
+        let voter = KeyPair::generate();
+        let approve = Vote::new(&voter, [1; 32], 0, Decision::Approve);
+        let reject = Vote::new(&voter, [1; 32], 0, Decision::Reject);
+        let evidence = Equivocation::Votes(approve.clone(), reject);
+        assert!(evidence.verify().is_ok());
+        assert_eq!(evidence.offender(), voter.public_key());
+
+        // The same vote twice, or votes on different actions, prove nothing
+        let repeated = Equivocation::Votes(approve.clone(), approve.clone());
+        assert!(repeated.verify().is_err());
+        let elsewhere = Vote::new(&voter, [2; 32], 0, Decision::Reject);
+        assert!(
+            Equivocation::Votes(approve.clone(), elsewhere)
+                .verify()
+                .is_err()
+        );
+
+        // Nor do votes signed by two different validators
+        let mut framed = Vote::new(&KeyPair::generate(), [1; 32], 0, Decision::Reject);
+        framed.voter = voter.public_key();
+        assert!(Equivocation::Votes(approve, framed).verify().is_err());
+    }
+
+    #[test]
+    fn test_double_proposal_and_double_commit_are_verifiable_evidence() {
+        let validator = KeyPair::generate();
+        let first = SignedAction::new(&validator, "game", 0, 1, vec![1]);
+        let second = SignedAction::new(&validator, "game", 1, 1, vec![2]);
+
+        let rounds = Equivocation::Rounds(
+            RoundProposal::new(&validator, 4, None, vec![first.clone()]),
+            RoundProposal::new(&validator, 4, None, vec![second.clone()]),
+        );
+        assert!(rounds.verify().is_ok());
+        let later = Equivocation::Rounds(
+            RoundProposal::new(&validator, 4, None, vec![first.clone()]),
+            RoundProposal::new(&validator, 5, None, vec![second.clone()]),
+        );
+        assert!(later.verify().is_err());
+
+        let commits = Equivocation::Commits(
+            Box::new(Commit::new(&validator, 7, first.clone())),
+            Box::new(Commit::new(&validator, 7, second.clone())),
+        );
+        assert!(commits.verify().is_ok());
+        let mut forged = Commit::new(&validator, 7, second);
+        forged.signature = vec![0; 64];
+        let forged = Equivocation::Commits(
+            Box::new(Commit::new(&validator, 7, first)),
+            Box::new(forged),
+        );
+        assert!(matches!(forged.verify(), Err(SwarmhostError::Crypto(_))));
+    }
+}
@@ -0,0 +1,42 @@
+// consensus/fork.rs - Two actions committed at one sequence of a game, on
+// different nodes, and which branch of the log is kept
+
+use super::sequence::Commit;
+
+/// A game whose log was found to differ from a peer's at `sequence`
+///
+/// Its commits are held back until the fork is resolved, and dropped for
+/// good once the game is halted.
+#[derive(Debug, Default)]
+pub struct Fork {
+    pub sequence: u64,
+    /// Commits that arrived meanwhile, delivered if our branch is kept
+    pub held: Vec<Commit>,
+    /// Both branches were certified, or neither: the game goes no further
+    pub halted: bool,
+}
+
+impl Fork {
+    pub fn new(sequence: u64) -> Self {
+        Self {
+            sequence,
+            ..Self::default()
+        }
+    }
+}
+
+/// How a fork was resolved, by which of its branches a quorum certified
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Resolution {
+    /// Only ours: the commits held meanwhile are delivered
+    Kept { delivered: Vec<Commit> },
+    /// Only the peer's: ours was rolled back from the fork on, and theirs
+    /// delivered in its place
+    Adopted {
+        rolled_back: Vec<Commit>,
+        delivered: Vec<Commit>,
+    },
+    /// Both, which quorums sharing an honest validator never certify, or
+    /// neither, so nothing tells them apart; the game is halted
+    Halted,
+}
@@ -0,0 +1,349 @@
+// consensus/membership.rs - A game's validators, the weight each one's
+// vote carries towards a quorum, and the changes agreed to them
+
+use super::action::SignedAction;
+use crate::crypto::{PlayerId, short_id};
+use crate::error::{Result, SwarmhostError};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashSet};
+
+/// Action type reserved for changing the validators; its data is a
+/// bincode-encoded [`ScheduledChange`]
+pub const MEMBERSHIP_CHANGE_ACTION: u32 = u32::MAX;
+
+/// Action type reserved for the validators still reachable going on alone
+/// under the grace rule; its data is a bincode-encoded [`GraceEpoch`]
+pub const GRACE_EPOCH_ACTION: u32 = u32::MAX - 1;
+
+/// A change to the validators, agreed on as an action by those before it
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum MembershipChange {
+    /// A player joins the validators with a vote of `weight`
+    AddValidator { validator: PlayerId, weight: u64 },
+    /// A validator leaves the validators
+    RemoveValidator { validator: PlayerId },
+    /// A validator's vote weighs `weight` from then on
+    ChangeWeight { validator: PlayerId, weight: u64 },
+}
+
+impl MembershipChange {
+    /// The validator the change is about
+    pub fn validator(&self) -> &PlayerId {
+        match self {
+            Self::AddValidator { validator, .. }
+            | Self::RemoveValidator { validator }
+            | Self::ChangeWeight { validator, .. } => validator,
+        }
+    }
+}
+
+/// A membership change and the sequence in its game's log from which it
+/// holds
+///
+/// Every node switches validators once the commit before `activation` is
+/// delivered, so each counts votes, takes turns proposing and checks
+/// checkpoints with the same set at the same point of the log.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ScheduledChange {
+    pub change: MembershipChange,
+    /// First sequence decided by the new validators; it must come after
+    /// the change's own
+    pub activation: u64,
+}
+
+impl ScheduledChange {
+    /// The change an action carries, if it is a membership change
+    pub fn of(action: &SignedAction) -> Option<Result<Self>> {
+        (action.action_type == MEMBERSHIP_CHANGE_ACTION).then(|| {
+            bincode::deserialize(&action.data).map_err(|e| {
+                SwarmhostError::validation(format!("Malformed membership change: {}", e))
+            })
+        })
+    }
+
+    /// Payload of the action making this change
+    pub fn encode(&self) -> Vec<u8> {
+        bincode::serialize(self).expect("a membership change always encodes")
+    }
+}
+
+/// The validators left to go on alone, each with its weight, needing all
+/// of them to agree from then on
+///
+/// It takes effect once every one of them approved it, which they do only
+/// after a quorum has been out of reach for the grace period; committed
+/// like any action, it marks in the log where the degraded epoch began.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct GraceEpoch {
+    /// In order of id
+    pub validators: Vec<PlayerId>,
+}
+
+impl GraceEpoch {
+    /// The epoch an action starts, if it is a grace epoch action
+    pub fn of(action: &SignedAction) -> Option<Result<Self>> {
+        (action.action_type == GRACE_EPOCH_ACTION).then(|| {
+            bincode::deserialize(&action.data)
+                .map_err(|e| SwarmhostError::validation(format!("Malformed grace epoch: {}", e)))
+        })
+    }
+
+    /// Payload of the action starting this epoch
+    pub fn encode(&self) -> Vec<u8> {
+        bincode::serialize(self).expect("a grace epoch always encodes")
+    }
+}
+
+/// The validators of a game with the weight of each one's vote
+///
+/// Quorums are a share of the total weight, so with every weight 1, the
+/// default, they are a share of the validators.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Membership {
+    weights: BTreeMap<PlayerId, u64>,
+}
+
+impl Membership {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Validators whose votes weigh the same
+    pub fn equal(validators: impl IntoIterator<Item = PlayerId>) -> Self {
+        Self::weighted(validators.into_iter().map(|validator| (validator, 1)))
+    }
+
+    /// Validators with the given weights; those weighing zero are left out
+    pub fn weighted(weights: impl IntoIterator<Item = (PlayerId, u64)>) -> Self {
+        Self {
+            weights: weights
+                .into_iter()
+                .filter(|(_, weight)| *weight > 0)
+                .collect(),
+        }
+    }
+
+    pub fn contains(&self, player: &PlayerId) -> bool {
+        self.weights.contains_key(player)
+    }
+
+    /// Weight of one validator's vote; zero for anyone else
+    pub fn weight_of(&self, player: &PlayerId) -> u64 {
+        self.weights.get(player).copied().unwrap_or(0)
+    }
+
+    /// Combined weight of `players`, each counted once and outsiders not
+    /// at all
+    pub fn weight<'a>(&self, players: impl IntoIterator<Item = &'a PlayerId>) -> u64 {
+        let mut counted = HashSet::new();
+        players
+            .into_iter()
+            .filter(|player| counted.insert(**player))
+            .map(|player| self.weight_of(player))
+            .sum()
+    }
+
+    pub fn total_weight(&self) -> u64 {
+        self.weights.values().sum()
+    }
+
+    /// Validators in order of id
+    pub fn ids(&self) -> impl Iterator<Item = &PlayerId> {
+        self.weights.keys()
+    }
+
+    pub fn players(&self) -> HashSet<PlayerId> {
+        self.weights.keys().copied().collect()
+    }
+
+    pub fn len(&self) -> usize {
+        self.weights.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.weights.is_empty()
+    }
+
+    /// Give a validator a new weight, adding it if it was not one; zero
+    /// removes it
+    pub fn set_weight(&mut self, player: PlayerId, weight: u64) {
+        if weight == 0 {
+            self.weights.remove(&player);
+        } else {
+            self.weights.insert(player, weight);
+        }
+    }
+
+    pub fn remove(&mut self, player: &PlayerId) -> bool {
+        self.weights.remove(player).is_some()
+    }
+
+    /// Make a membership change, refusing one adding a validator already
+    /// in, removing or reweighing one who is not, or giving a weight of
+    /// zero
+    pub fn apply(&mut self, change: &MembershipChange) -> Result<()> {
+        let validator = change.validator();
+        let (joining, weight) = match *change {
+            MembershipChange::AddValidator { weight, .. } => (true, weight),
+            MembershipChange::RemoveValidator { .. } => (false, 0),
+            MembershipChange::ChangeWeight { weight, .. } => (false, weight),
+        };
+        if joining == self.contains(validator) {
+            return Err(SwarmhostError::validation(format!(
+                "{} is {}a validator",
+                short_id(validator),
+                if joining { "already " } else { "not " }
+            )));
+        }
+        if weight == 0 && !matches!(change, MembershipChange::RemoveValidator { .. }) {
+            return Err(SwarmhostError::validation(format!(
+                "{} cannot weigh zero; remove it instead",
+                short_id(validator)
+            )));
+        }
+        self.set_weight(*validator, weight);
+        Ok(())
+    }
+
+    /// Refuse weights that let one validator make `required` on its own
+    /// while others validate too, unless `allow_dictatorship` is set
+    pub fn check(&self, required: u64, allow_dictatorship: bool) -> Result<()> {
+        if allow_dictatorship || self.weights.len() < 2 {
+            return Ok(());
+        }
+        match self.weights.iter().find(|(_, weight)| **weight >= required) {
+            Some((dictator, weight)) => Err(SwarmhostError::Config(format!(
+                "{} would hold {} of the {} weight a quorum needs on its own \
+                 (set allow_dictatorship to override)",
+                short_id(dictator),
+                weight,
+                required
+            ))),
+            None => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::KeyPair;
+    use crate::node::ConsensusConfig;
+
+    /// Ids of validators with `weights`, and where the default two-thirds
+    /// quorum puts the weight needed
+    fn weighted(weights: &[u64]) -> (Membership, Vec<PlayerId>, u64) {
+        let ids: Vec<PlayerId> = weights
+            .iter()
+            .map(|_| KeyPair::generate().public_key())
+            .collect();
+        let membership = Membership::weighted(ids.iter().copied().zip(weights.iter().copied()));
+        let required = ConsensusConfig::default().required_weight(membership.total_weight());
+        (membership, ids, required)
+    }
+
+    #[test]
+    fn test_quorum_is_a_share_of_the_total_weight() {
+        let (equal, _, required) = weighted(&[1, 1, 1, 1]);
+        assert_eq!((equal.total_weight(), required), (4, 3));
+        // A server weighing as much as its three clients together
+        let (server, ids, required) = weighted(&[3, 1, 1, 1]);
+        assert_eq!((server.total_weight(), required), (6, 4));
+        assert_eq!(server.weight(&[ids[0], ids[1]]), 4);
+        assert_eq!(server.weight(&ids[1..]), 3);
+        // Repeats and outsiders add nothing
+        let outsider = KeyPair::generate().public_key();
+        assert_eq!(server.weight(&[ids[1], ids[1], outsider]), 1);
+
+        let (zeroes, ids, _) = weighted(&[2, 0, 5]);
+        assert_eq!(zeroes.len(), 2);
+        assert!(!zeroes.contains(&ids[1]));
+    }
+
+    #[test]
+    fn test_one_validator_outweighing_a_quorum_needs_consent() {
+        let (server, ids, required) = weighted(&[4, 1, 1]);
+        assert_eq!(required, 4);
+        assert!(matches!(
+            server.check(required, false),
+            Err(SwarmhostError::Config(_))
+        ));
+        assert!(server.check(required, true).is_ok());
+
+        let mut balanced = server.clone();
+        balanced.set_weight(ids[0], 3);
+        let required = ConsensusConfig::default().required_weight(balanced.total_weight());
+        assert!(balanced.check(required, false).is_ok());
+
+        // Alone, a validator always decides
+        let (sole, _, required) = weighted(&[1]);
+        assert!(sole.check(required, false).is_ok());
+    }
+
+    #[test]
+    fn test_membership_change_round_trips_through_an_action() {
+        let keypair = KeyPair::generate();
+        let scheduled = ScheduledChange {
+            change: MembershipChange::ChangeWeight {
+                validator: [4; 32],
+                weight: 3,
+            },
+            activation: 10,
+        };
+        let action = SignedAction::new(
+            &keypair,
+            "game",
+            0,
+            MEMBERSHIP_CHANGE_ACTION,
+            scheduled.encode(),
+        );
+        assert_eq!(ScheduledChange::of(&action).unwrap().unwrap(), scheduled);
+
+        let other = SignedAction::new(&keypair, "game", 1, 1, scheduled.encode());
+        assert!(ScheduledChange::of(&other).is_none());
+        let garbled = SignedAction::new(&keypair, "game", 2, MEMBERSHIP_CHANGE_ACTION, vec![1]);
+        assert!(ScheduledChange::of(&garbled).unwrap().is_err());
+    }
+
+    #[test]
+    fn test_changes_refused_for_the_wrong_validators() {
+        let (mut membership, ids, _) = weighted(&[1, 1]);
+        let newcomer = KeyPair::generate().public_key();
+        let refused = [
+            MembershipChange::AddValidator {
+                validator: ids[0],
+                weight: 1,
+            },
+            MembershipChange::AddValidator {
+                validator: newcomer,
+                weight: 0,
+            },
+            MembershipChange::RemoveValidator {
+                validator: newcomer,
+            },
+            MembershipChange::ChangeWeight {
+                validator: newcomer,
+                weight: 2,
+            },
+            MembershipChange::ChangeWeight {
+                validator: ids[0],
+                weight: 0,
+            },
+        ];
+        for change in refused {
+            assert!(membership.clone().apply(&change).is_err(), "{:?}", change);
+        }
+
+        membership
+            .apply(&MembershipChange::AddValidator {
+                validator: newcomer,
+                weight: 2,
+            })
+            .unwrap();
+        membership
+            .apply(&MembershipChange::RemoveValidator { validator: ids[1] })
+            .unwrap();
+        assert_eq!(membership.weight_of(&newcomer), 2);
+        assert_eq!(membership.players(), [ids[0], newcomer].into());
+    }
+}
@@ -1,10 +1,4082 @@
-// consensus/mod.rs - Consensus mechanism (placeholder)
+// consensus/mod.rs - Consensus mechanism
 
-#[derive(Default)]
-pub struct ConsensusManager;
+pub mod action;
+pub mod beacon;
+pub mod conflict;
+pub mod evidence;
+pub mod fork;
+pub mod membership;
+pub mod observer;
+pub mod ordering;
+pub mod rotation;
+pub mod safety;
+pub mod sequence;
+pub mod sync;
+pub mod tally;
+pub mod validation;
+pub mod view;
+pub mod vote;
+
+pub use action::{ActionId, SignedAction};
+pub use beacon::Randomness;
+pub use conflict::Conflict;
+pub use evidence::Equivocation;
+pub use fork::{Fork, Resolution};
+pub use membership::{
+    GRACE_EPOCH_ACTION, GraceEpoch, MEMBERSHIP_CHANGE_ACTION, Membership, MembershipChange,
+    ScheduledChange,
+};
+pub use observer::{Observed, ObserverTally, ObserverVote};
+pub use ordering::{ActionClassifier, AllStrict, OrderingClass, Unsequenced};
+pub use rotation::{BlockHeader, Rotation, RoundProposal, TimeoutVote};
+pub use safety::{Cast, SafetyRecord};
+pub use sequence::{Commit, CommitLog};
+pub use sync::CertifiedCommits;
+pub use tally::{Outcome, Tally, VoteTracker};
+pub use validation::{
+    ActionValidator, RejectCode, ValidationContext, ValidationPipeline, ValidationResult,
+};
+pub use view::{NewView, Prepared, ViewChange};
+pub use vote::{Decision, Vote};
+
+use crate::crypto::{Hash, KeyPair, PlayerId, short_id};
+use crate::error::{Result, SwarmhostError};
+use crate::node::{
+    ConsensusConfig, InvalidInBlock, NodeEvent, NodeMetrics, RejectionReason, RoundMode,
+};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet, VecDeque};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::{Notify, broadcast};
+use tokio::time::Instant;
+
+/// Tracks pending actions and enforces per-action limits
+pub struct ConsensusManager {
+    config: ConsensusConfig,
+    /// Whose turn it is to propose
+    rotation: Rotation,
+    actions_this_round: HashMap<PlayerId, u32>,
+    pending: Vec<SignedAction>,
+    /// Accepted actions not yet in a round's proposal, oldest first
+    unproposed: Vec<SignedAction>,
+    /// Our own among them, which we keep handing to each new proposer
+    outstanding: Vec<SignedAction>,
+    /// Since when the oldest of them has been waiting for a block
+    unproposed_since: Option<Instant>,
+    /// Blocks of the last `BLOCK_HISTORY` rounds, by hash
+    blocks: HashMap<Hash, BlockHeader>,
+    /// Block each proposed action went out in
+    block_of: HashMap<ActionId, Hash>,
+    /// Blocks proposed and not yet settled, by round, oldest first: some
+    /// action in each is neither committed nor rejected
+    unsettled: BTreeMap<u64, Hash>,
+    /// Recent blocks rejected, or discarded for following one that was
+    failed: HashSet<Hash>,
+    /// When each block in flight reached us, for its round's latency
+    proposed_at: HashMap<Hash, Instant>,
+    /// Committed blocks with when they were proposed, and the validators
+    /// whose votes on them are still to come
+    awaiting_votes: HashMap<Hash, (Instant, HashSet<PlayerId>)>,
+    /// When we last asked peers for the votes each block in flight is
+    /// waiting on
+    asked: HashMap<Hash, Instant>,
+    /// Actions and blocks a quorum approved before every validator voted,
+    /// with when we stop waiting on the rest to number them
+    held: HashMap<Hash, Instant>,
+    /// Those we stopped waiting on, numbered on the votes there were
+    released: HashSet<Hash>,
+    /// Woken when something is first held, for whoever releases holds to
+    /// know there is one to wait on
+    holding: Arc<Notify>,
+    /// Since when we have been waiting on the current proposer
+    waiting_since: Option<Instant>,
+    /// In ticked rounds, when the next one is due to open
+    tick_at: Option<Instant>,
+    /// In ticked rounds, those over in order, with their block unless it
+    /// failed or never reached us, until their commits are delivered
+    ticks: VecDeque<(u64, Option<Hash>)>,
+    /// Round of the next tick to queue, from the first block settled
+    next_tick: Option<u64>,
+    /// View in progress; its leader is the sequencer
+    view: u64,
+    /// Rounds skipped in a row since a proposal last arrived
+    failed_rounds: u32,
+    /// Highest view we asked to move to
+    requested_view: u64,
+    /// Requests to move to each view past ours, by voter
+    view_changes: BTreeMap<u64, HashMap<PlayerId, ViewChange>>,
+    /// First proposal of each recent round, to catch a second one
+    proposals: BTreeMap<u64, RoundProposal>,
+    /// Validators convicted of equivocating, kept out of the validator set
+    ejected: HashSet<PlayerId>,
+    /// Evidence found here that peers have not been sent yet
+    evidence: Vec<Equivocation>,
+    /// Whether a membership change took effect since last asked
+    reweighed: bool,
+    /// Weight a membership change may not bring the quorum below, unless
+    /// `adaptive_quorum` is set: the quorum in effect
+    quorum_floor: u64,
+    /// Committed membership changes not in effect yet, with the game whose
+    /// log they wait on, in the order committed
+    scheduled: Vec<(String, ScheduledChange)>,
+    /// Validators each change replaced, with the weight their quorum
+    /// needed, per game by the sequence it took effect at, for checkpoints
+    /// below it
+    superseded: HashMap<String, BTreeMap<u64, (Membership, u64)>>,
+    /// Actions touching entities whose round we saw, so their conflicts
+    /// are settled
+    resolved: HashSet<ActionId>,
+    /// Actions held back until those conflicting with them that come first
+    /// are committed or rejected
+    after: HashMap<ActionId, BTreeSet<ActionId>>,
+    /// Actions rejected for conflicts, or put back from a discarded block,
+    /// whose speculation is still to be rolled back
+    revoked: Vec<SignedAction>,
+    votes: VoteTracker,
+    /// Order of committed actions, per game
+    logs: HashMap<String, CommitLog>,
+    /// Sequence of each game's latest checkpoint, up to which no commit
+    /// may put another action
+    finalized: HashMap<String, u64>,
+    /// Games whose log forked from a peer's, frozen until it is resolved
+    forks: HashMap<String, Fork>,
+    /// Actions of classes delivered without a sequence
+    unsequenced: Unsequenced,
+    /// Our own recent votes, to stand by after a restart
+    cast: Cast,
+    /// Votes of non-validators, which never count toward a quorum
+    observed: Observed,
+    events: broadcast::Sender<NodeEvent>,
+    metrics: Arc<NodeMetrics>,
+}
 
 impl ConsensusManager {
-    pub fn new() -> Self {
-        Self  // Remove ::default()
+    pub fn new(
+        config: ConsensusConfig,
+        events: broadcast::Sender<NodeEvent>,
+        metrics: Arc<NodeMetrics>,
+    ) -> Self {
+        Self {
+            config,
+            rotation: Rotation::new(),
+            actions_this_round: HashMap::new(),
+            pending: Vec::new(),
+            unproposed: Vec::new(),
+            outstanding: Vec::new(),
+            unproposed_since: None,
+            blocks: HashMap::new(),
+            block_of: HashMap::new(),
+            unsettled: BTreeMap::new(),
+            failed: HashSet::new(),
+            proposed_at: HashMap::new(),
+            awaiting_votes: HashMap::new(),
+            asked: HashMap::new(),
+            held: HashMap::new(),
+            released: HashSet::new(),
+            holding: Arc::new(Notify::new()),
+            waiting_since: None,
+            tick_at: None,
+            ticks: VecDeque::new(),
+            next_tick: None,
+            view: 0,
+            failed_rounds: 0,
+            requested_view: 0,
+            view_changes: BTreeMap::new(),
+            proposals: BTreeMap::new(),
+            ejected: HashSet::new(),
+            evidence: Vec::new(),
+            reweighed: false,
+            quorum_floor: 0,
+            scheduled: Vec::new(),
+            superseded: HashMap::new(),
+            resolved: HashSet::new(),
+            after: HashMap::new(),
+            revoked: Vec::new(),
+            votes: VoteTracker::new(),
+            logs: HashMap::new(),
+            finalized: HashMap::new(),
+            forks: HashMap::new(),
+            unsequenced: Unsequenced::new(),
+            cast: Cast::new(),
+            observed: Observed::new(),
+            events,
+            metrics,
+        }
+    }
+
+    /// Current consensus round
+    pub fn round(&self) -> u64 {
+        self.rotation.round()
+    }
+
+    /// Move to the next round, resetting per-player rate counters
+    pub fn advance_round(&mut self) {
+        self.rotation.finish(self.round());
+        self.actions_this_round.clear();
+    }
+
+    /// Validator whose turn it is to propose; none without validators
+    pub fn proposer(&self) -> Option<PlayerId> {
+        self.rotation.current()
+    }
+
+    /// Local actions not yet in a round's proposal, oldest first
+    pub fn outstanding(&self) -> &[SignedAction] {
+        &self.outstanding
+    }
+
+    /// Actions accepted but not yet decided
+    pub fn pending(&self) -> &[SignedAction] {
+        &self.pending
+    }
+
+    /// Queue an action submitted by the local player
+    ///
+    /// With validators set it is also outstanding until a proposer puts it
+    /// forward in a round, unless it starts a grace epoch: the validators
+    /// it names vote on it alone, since no round can go on without them.
+    ///
+    /// Refused as overloaded while `max_pending_actions` are undecided,
+    /// unless it starts a grace epoch.
+    pub fn submit_local(&mut self, action: SignedAction) -> Result<ActionId> {
+        self.check_size(&action)?;
+        if action.action_type != GRACE_EPOCH_ACTION {
+            self.check_room()?;
+        }
+        let id = action.id();
+        if self.proposer().is_some() && action.action_type != GRACE_EPOCH_ACTION {
+            self.unproposed.push(action.clone());
+            self.unproposed_since.get_or_insert_with(Instant::now);
+            self.outstanding.push(action.clone());
+            self.waiting_since.get_or_insert_with(Instant::now);
+        }
+        self.pending.push(action);
+        self.note_pending();
+        Ok(id)
+    }
+
+    /// Accept an action proposed by a remote peer, or forwarded for us to
+    /// propose in our turn
+    ///
+    /// Cheap checks (size, room, per-player rate) run before the signature
+    /// is verified so floods of junk don't cost us a signature check each.
+    /// An action we already have is taken even while overloaded.
+    pub fn receive_proposal(&mut self, action: SignedAction) -> Result<ActionId> {
+        self.check_size(&action)?;
+        if !self.is_pending(&action.id()) {
+            self.check_room()?;
+        }
+
+        let count = self.actions_this_round.entry(action.actor).or_insert(0);
+        if *count >= self.config.max_actions_per_player_per_round {
+            let limit = self.config.max_actions_per_player_per_round;
+            self.metrics.actions_rejected_rate_limited.inc();
+            return Err(self.reject(&action, RejectionReason::RateLimited { limit }));
+        }
+        *count += 1;
+
+        if let Err(e) = action.verify() {
+            self.metrics.actions_rejected_signature.inc();
+            self.reject(&action, RejectionReason::InvalidSignature);
+            return Err(e);
+        }
+
+        let id = action.id();
+        if self.is_pending(&id) {
+            return Ok(id);
+        }
+        if self.proposer().is_some() && action.action_type != GRACE_EPOCH_ACTION {
+            self.unproposed.push(action.clone());
+            self.unproposed_since.get_or_insert_with(Instant::now);
+        }
+        self.pending.push(action);
+        self.note_pending();
+        Ok(id)
+    }
+
+    /// Pending actions neither delivered nor rejected yet
+    pub fn undecided(&self) -> usize {
+        self.pending
+            .iter()
+            .filter(|action| {
+                let action_id = action.id();
+                !self.is_rejected(&action_id)
+                    && !self
+                        .logs
+                        .get(&action.game_id)
+                        .is_some_and(|log| log.is_delivered(&action_id))
+            })
+            .count()
+    }
+
+    /// Whether `max_pending_actions` are undecided, so no more are taken in
+    pub fn is_full(&self) -> bool {
+        self.undecided() >= self.config.max_pending_actions
+    }
+
+    /// Refuse an action as overloaded while we are full
+    fn check_room(&self) -> Result<()> {
+        if !self.is_full() {
+            return Ok(());
+        }
+        self.metrics.actions_overloaded.inc();
+        Err(SwarmhostError::Overloaded(format!(
+            "{} actions pending, as many as max_pending_actions",
+            self.config.max_pending_actions
+        )))
+    }
+
+    fn note_pending(&self) {
+        self.metrics.pending_actions.set(self.undecided() as u64);
+    }
+
+    /// Put the actions not yet proposed forward as one block, if it is our
+    /// turn and there are any, ending the round
+    ///
+    /// Whether it is our turn or not, actions whose deadline passed by
+    /// `now` are dropped first, never to go out in a block.
+    ///
+    /// A block carries no more than `batch_max_actions`, nor more bytes than
+    /// `batch_max_bytes` or one action of `max_action_size`, so it fits in a
+    /// message; at least one action goes in, and the rest wait for a later
+    /// round. Until the oldest action has waited `batch_interval`, only a
+    /// full block is proposed. Conflicts among them are settled as when
+    /// receiving a round.
+    ///
+    /// The block follows the latest one we know of that did not fail,
+    /// committed or not, unless `blocks_in_flight` blocks are still to be
+    /// committed; then nothing is proposed until the oldest is.
+    ///
+    /// In ticked rounds the block goes out once the tick is due instead,
+    /// full or not, and empty if no action is waiting. The first tick is
+    /// due a `tick_interval` after we are first asked, and each after the
+    /// one before, or after the round before reached us.
+    pub fn propose(&mut self, keypair: &KeyPair, now: Instant) -> Option<RoundProposal> {
+        self.drop_expired(now);
+        if self.proposer() != Some(keypair.public_key()) {
+            return None;
+        }
+        self.settle_blocks();
+        if self.unsettled.len() >= self.config.blocks_in_flight() {
+            return None;
+        }
+        let max_actions = self.config.batch_max_actions;
+        let max_bytes = self.config.batch_max_bytes.min(self.config.max_action_size);
+        let mut actions: Vec<SignedAction> = Vec::new();
+        let mut size = 0;
+        let mut full = false;
+        for action in &self.unproposed {
+            size += bincode::serialized_size(action).unwrap_or(u64::MAX) as usize;
+            if !actions.is_empty() && (size > max_bytes || actions.len() >= max_actions) {
+                full = true;
+                break;
+            }
+            actions.push(action.clone());
+        }
+        let tick = match self.config.round_mode {
+            RoundMode::FreeRunning => None,
+            RoundMode::Ticked { tick_interval } => {
+                let Some(due) = self.tick_at else {
+                    self.tick_at = Some(now + tick_interval);
+                    return None;
+                };
+                if now < due {
+                    return None;
+                }
+                Some((due, tick_interval))
+            }
+        };
+        if tick.is_none() {
+            if actions.is_empty() {
+                return None;
+            }
+            full |= actions.len() >= max_actions || size >= max_bytes;
+            if !full && self.batch_deadline().is_some_and(|deadline| now < deadline) {
+                return None;
+            }
+        }
+        let proposal = RoundProposal::new(keypair, self.round(), self.tip(), actions);
+        self.proposals.insert(proposal.round, proposal.clone());
+        self.end_round(proposal.round, &proposal.action_ids(), now);
+        if let Some((due, tick_interval)) = tick {
+            // Keep to the beat rather than to when we woke up for it
+            self.tick_at = Some((due + tick_interval).max(now));
+        }
+        self.record_block(&proposal, now);
+        self.resolve_conflicts(&proposal);
+        Some(proposal)
+    }
+
+    /// Reject the actions waiting for a block whose deadline passed by
+    /// `now`, for their speculation to be rolled back
+    fn drop_expired(&mut self, now: Instant) {
+        let now_ms = action::clock_ms(now);
+        let expired: Vec<ActionId> = self
+            .unproposed
+            .iter()
+            .filter(|action| action.is_expired(now_ms))
+            .map(SignedAction::id)
+            .collect();
+        for action_id in expired {
+            self.expire(&action_id);
+        }
+    }
+
+    /// Give up on a pending action that is not in a block, as expired,
+    /// returning whether it was waiting for one
+    pub fn expire(&mut self, action_id: &ActionId) -> bool {
+        if self.block_of.contains_key(action_id) {
+            return false;
+        }
+        let Some(at) = self
+            .pending
+            .iter()
+            .position(|action| &action.id() == action_id)
+        else {
+            return false;
+        };
+        let action = self.pending.remove(at);
+        self.note_pending();
+        self.unproposed.retain(|waiting| &waiting.id() != action_id);
+        self.outstanding
+            .retain(|waiting| &waiting.id() != action_id);
+        if self.unproposed.is_empty() {
+            self.unproposed_since = None;
+        }
+        let deadline_ms = action.deadline_ms.unwrap_or_default();
+        self.reject(&action, RejectionReason::Expired { deadline_ms });
+        self.revoked.push(action);
+        true
+    }
+
+    /// Deadline of a pending action, if it has one
+    pub fn deadline_of(&self, action_id: &ActionId) -> Option<u64> {
+        self.pending
+            .iter()
+            .find(|action| &action.id() == action_id)
+            .and_then(|action| action.deadline_ms)
+    }
+
+    /// Take in the proposal of a round, returning its actions new to us
+    ///
+    /// A proposal from anyone but that round's proposer is refused as a
+    /// consensus error. Proposals for rounds already over are ignored,
+    /// unless the proposer put other actions forward in the round before,
+    /// which convicts it of equivocation. Actions losing a conflict with
+    /// another in the round are rejected rather than returned.
+    pub fn receive_round(
+        &mut self,
+        proposal: RoundProposal,
+        now: Instant,
+    ) -> Result<Vec<SignedAction>> {
+        self.check_ejected(&proposal.proposer)?;
+        if let Some(first) = self.proposals.get(&proposal.round)
+            && first.proposer == proposal.proposer
+        {
+            if first.action_ids() == proposal.action_ids() {
+                return Ok(Vec::new());
+            }
+            let first = first.clone();
+            proposal.verify()?;
+            return Err(self.convict(Equivocation::Rounds(first, proposal)));
+        }
+        if self.rotation.proposer(proposal.round) != Some(proposal.proposer) {
+            return Err(SwarmhostError::consensus(format!(
+                "{} is not the proposer of round {}",
+                short_id(&proposal.proposer),
+                proposal.round
+            )));
+        }
+        if proposal.round < self.round()
+            || proposal.round > self.round() + rotation::MAX_ROUNDS_AHEAD
+        {
+            return Ok(Vec::new());
+        }
+        if proposal.actions.len() > rotation::MAX_ROUND_ACTIONS {
+            return Err(SwarmhostError::consensus(format!(
+                "{} actions in one round",
+                proposal.actions.len()
+            )));
+        }
+        for action in &proposal.actions {
+            self.check_size(action)?;
+        }
+        proposal.verify()?;
+        self.proposals.insert(proposal.round, proposal.clone());
+
+        let ids = proposal.action_ids();
+        self.end_round(proposal.round, &ids, now);
+        let mut fresh: Vec<SignedAction> = proposal
+            .actions
+            .iter()
+            .filter(|action| !self.is_pending(&action.id()))
+            .cloned()
+            .collect();
+        self.pending.extend(fresh.iter().cloned());
+        self.note_pending();
+        self.record_block(&proposal, now);
+        self.resolve_conflicts(&proposal);
+        fresh.retain(|action| self.is_pending(&action.id()));
+        Ok(fresh)
+    }
+
+    /// Count a validator's vote to skip a round, returning whether it
+    /// ended the round
+    ///
+    /// A vote on the round in progress means its voter is waiting on the
+    /// proposer, so we start waiting too.
+    pub fn receive_timeout(&mut self, vote: &TimeoutVote, now: Instant) -> Result<bool> {
+        self.check_ejected(&vote.voter)?;
+        let required = self.required_weight();
+        let round = self.round();
+        if !self.rotation.record_timeout(vote, required)? {
+            if vote.round == round {
+                self.waiting_since.get_or_insert(now);
+            }
+            return Ok(false);
+        }
+        tracing::debug!(
+            round = vote.round,
+            cause = "timeout",
+            "Round skipped by timeout votes"
+        );
+        self.metrics.rounds_failed_timeout.inc();
+        self.failed_rounds += 1;
+        self.next_round(now);
+        Ok(true)
+    }
+
+    /// Whether we have waited `timeout` on a proposer that is not us
+    pub fn timed_out(&self, local: &PlayerId, now: Instant, timeout: Duration) -> bool {
+        self.proposer().is_some_and(|proposer| &proposer != local)
+            && self
+                .waiting_since
+                .is_some_and(|since| now.duration_since(since) >= timeout)
+    }
+
+    /// Vote to skip the round in progress, unless we are its proposer or
+    /// already did; the vote is counted here before it is returned
+    pub fn skip(&mut self, keypair: &KeyPair, now: Instant) -> Option<TimeoutVote> {
+        let local = keypair.public_key();
+        let round = self.round();
+        if self.proposer() == Some(local)
+            || !self.rotation.order().contains(&local)
+            || self.rotation.voted(round, &local)
+        {
+            return None;
+        }
+        let vote = TimeoutVote::new(keypair, round);
+        self.receive_timeout(&vote, now).ok()?;
+        Some(vote)
+    }
+
+    fn is_pending(&self, action_id: &ActionId) -> bool {
+        self.pending.iter().any(|action| &action.id() == action_id)
+    }
+
+    /// Close `round`, whose proposal carried `proposed`
+    fn end_round(&mut self, round: u64, proposed: &[ActionId], now: Instant) {
+        self.unproposed
+            .retain(|action| !proposed.contains(&action.id()));
+        self.outstanding
+            .retain(|action| !proposed.contains(&action.id()));
+        if self.unproposed.is_empty() {
+            self.unproposed_since = None;
+        }
+        if self.rotation.finish(round) {
+            self.failed_rounds = 0;
+            self.next_round(now);
+        }
+        let kept = self.round().saturating_sub(rotation::MAX_ROUNDS_AHEAD);
+        self.proposals = self.proposals.split_off(&kept);
+        let kept = self.round().saturating_sub(rotation::BLOCK_HISTORY);
+        if self.blocks.values().any(|header| header.round < kept) {
+            self.forget_decided(kept);
+            let (votes, held, released) = (&mut self.votes, &mut self.held, &mut self.released);
+            self.blocks.retain(|block, header| {
+                let keep = header.round >= kept;
+                if !keep {
+                    votes.forget(block);
+                    held.remove(block);
+                    released.remove(block);
+                }
+                keep
+            });
+            let blocks = &self.blocks;
+            self.block_of.retain(|_, block| blocks.contains_key(block));
+            self.unsettled.retain(|_, block| blocks.contains_key(block));
+            self.failed.retain(|block| blocks.contains_key(block));
+            self.proposed_at
+                .retain(|block, _| blocks.contains_key(block));
+            self.awaiting_votes
+                .retain(|block, _| blocks.contains_key(block));
+        }
+    }
+
+    /// Let go of pending actions delivered or rejected in blocks of rounds
+    /// before `kept`, which are about to be, and of their votes, so that
+    /// neither grows with the game
+    fn forget_decided(&mut self, kept: u64) {
+        let forgotten: HashSet<ActionId> = self
+            .pending
+            .iter()
+            .map(SignedAction::id)
+            .filter(|action_id| {
+                self.block_of
+                    .get(action_id)
+                    .and_then(|block| self.blocks.get(block))
+                    .is_some_and(|header| header.round < kept)
+            })
+            .filter(|action_id| {
+                !self
+                    .unproposed
+                    .iter()
+                    .any(|action| &action.id() == action_id)
+            })
+            .filter(|action_id| {
+                self.is_rejected(action_id)
+                    || self.logs.values().any(|log| log.is_delivered(action_id))
+            })
+            .collect();
+        if forgotten.is_empty() {
+            return;
+        }
+        self.pending
+            .retain(|action| !forgotten.contains(&action.id()));
+        for action_id in &forgotten {
+            self.votes.forget(action_id);
+            self.held.remove(action_id);
+            self.released.remove(action_id);
+        }
+        self.note_pending();
+    }
+
+    /// Start afresh in a new round: rate counters reset, and we wait on the
+    /// new proposer only if we have actions for it
+    ///
+    /// In ticked rounds, the next tick is due a `tick_interval` from `now`,
+    /// and we wait on its proposer whether or not we have actions.
+    fn next_round(&mut self, now: Instant) {
+        self.actions_this_round.clear();
+        let tick = self.config.tick_interval();
+        self.tick_at = tick.map(|tick_interval| now + tick_interval);
+        self.waiting_since = (tick.is_some() || !self.outstanding.is_empty()).then_some(now);
+    }
+
+    /// Set who may vote, and take turns proposing, each weighing the same;
+    /// a quorum is `required_weight` of them
+    ///
+    /// Validators ejected for equivocating stay out.
+    pub fn set_validators(&mut self, validators: HashSet<PlayerId>) {
+        self.install(Membership::equal(validators));
+        self.quorum_floor = self.required_weight();
+    }
+
+    /// Set who may vote, and take turns proposing, with the weight of each
+    /// one's vote; a quorum is `required_weight` of their total
+    ///
+    /// Weights letting one validator make a quorum alone are refused as a
+    /// config error unless `allow_dictatorship` is set. Validators ejected
+    /// for equivocating stay out.
+    pub fn set_membership(&mut self, mut membership: Membership) -> Result<()> {
+        for ejected in &self.ejected {
+            membership.remove(ejected);
+        }
+        let required = self.config.required_weight(membership.total_weight());
+        membership.check(required, self.config.allow_dictatorship)?;
+        self.seat(membership, required);
+        self.quorum_floor = required;
+        Ok(())
+    }
+
+    /// Set the validators whatever their weights; ejecting one must not
+    /// wait on the guard
+    fn install(&mut self, mut membership: Membership) {
+        for ejected in &self.ejected {
+            membership.remove(ejected);
+        }
+        let required = self.config.required_weight(membership.total_weight());
+        self.seat(membership, required);
+    }
+
+    /// Put validators in place, approvals weighing `required` making a
+    /// quorum of them
+    fn seat(&mut self, membership: Membership, required: u64) {
+        let sequencer = self.sequencer();
+        self.rotation.set_validators(&membership);
+        self.votes.set_validators(membership, required);
+        if self.sequencer() != sequencer {
+            self.clear_holds();
+        }
+    }
+
+    /// Weight making a quorum of the validators a membership change leaves:
+    /// their share of `quorum_numerator`, raised to the quorum before the
+    /// change unless `adaptive_quorum` is set
+    fn quorum_of(&self, membership: &Membership) -> u64 {
+        let required = self.config.required_weight(membership.total_weight());
+        if self.config.adaptive_quorum {
+            required
+        } else {
+            required.max(self.quorum_floor)
+        }
+    }
+
+    /// Record a vote from a validator, returning the outcome if it settled
+    /// the action
+    ///
+    /// Each voter counts once per action; repeats are refused, and votes
+    /// from non-validators are refused as consensus errors. A vote that
+    /// contradicts the voter's earlier one convicts it of equivocation.
+    pub fn receive_vote(&mut self, vote: Vote) -> Result<Option<Outcome>> {
+        self.check_ejected(&vote.voter)?;
+        let first = self
+            .votes
+            .votes(&vote.action_id)
+            .iter()
+            .find(|first| first.voter == vote.voter && first.decision != vote.decision)
+            .cloned();
+        if let Some(first) = first {
+            vote.verify()?;
+            return Err(self.convict(Equivocation::Votes(first, vote)));
+        }
+        let (voted, voter) = (vote.action_id, vote.voter);
+        let outcome = self.votes.record(vote)?;
+        self.note_late(&voted, &voter);
+        let grace = self
+            .pending
+            .iter()
+            .find(|action| action.id() == voted && action.action_type == GRACE_EPOCH_ACTION)
+            .cloned();
+        if let Some(grace) = grace
+            && self.take_up_grace(&grace)
+        {
+            return Ok(Some(self.tally(&voted).outcome()));
+        }
+        Ok(outcome)
+    }
+
+    /// Record a vote of our own, remembered for the safety record,
+    /// returning the vote to send with the outcome if it settled the action
+    ///
+    /// If we voted on the action or block before, perhaps before a restart,
+    /// the earlier vote stands and is returned instead; one deciding
+    /// otherwise is refused, since sending it would be equivocating.
+    pub fn cast_vote(&mut self, vote: Vote) -> Result<(Vote, Option<Outcome>)> {
+        let vote = match self.cast.get(&vote.action_id) {
+            Some(earlier) if earlier.decision != vote.decision => {
+                return Err(SwarmhostError::InvalidState(format!(
+                    "Already voted {:?} on {}",
+                    earlier.decision,
+                    short_id(&vote.action_id)
+                )));
+            }
+            Some(earlier) => earlier.clone(),
+            None => vote,
+        };
+        let outcome = self.receive_vote(vote.clone())?;
+        self.cast.insert(vote.clone());
+        Ok((vote, outcome))
+    }
+
+    /// Record a non-validator's vote on an action or block we know of,
+    /// tallied apart from the quorum and announced with the tally it leaves
+    ///
+    /// Validators' own verdicts count in the tally proper, so their observer
+    /// votes are refused, as are votes on what we have not seen proposed.
+    /// An observer voting both ways is refused too, without being ejected
+    /// from anything, since it has no say.
+    pub fn receive_observer_vote(&mut self, vote: ObserverVote) -> Result<()> {
+        if self.validators().contains(&vote.observer) {
+            return Err(SwarmhostError::validation(format!(
+                "{} is a validator, not an observer",
+                short_id(&vote.observer)
+            )));
+        }
+        let subject = vote.action_id;
+        if !self.blocks.contains_key(&subject)
+            && !self.block_of.contains_key(&subject)
+            && !self.is_pending(&subject)
+        {
+            return Err(SwarmhostError::validation(format!(
+                "Observer vote on unknown {}",
+                short_id(&subject)
+            )));
+        }
+        let (observer, decision) = (vote.observer, vote.decision);
+        let tally = self.observed.record(vote)?;
+        let _ = self.events.send(NodeEvent::ObserverVoted {
+            action_id: subject,
+            observer,
+            decision,
+            tally,
+        });
+        Ok(())
+    }
+
+    /// An observer's vote on an action or block, if we counted one
+    pub fn observer_vote(
+        &self,
+        action_id: &ActionId,
+        observer: &PlayerId,
+    ) -> Option<&ObserverVote> {
+        self.observed
+            .votes(action_id)
+            .iter()
+            .find(|vote| &vote.observer == observer)
+    }
+
+    /// Observers' verdicts on each recent action or block they voted on
+    pub fn observer_tallies(&self) -> HashMap<ActionId, ObserverTally> {
+        self.observed.tallies()
+    }
+
+    /// Validators whose votes count, with their weights
+    pub fn validators(&self) -> &Membership {
+        self.votes.validators()
+    }
+
+    /// Weight of the votes or signatures that make a quorum
+    pub fn required_weight(&self) -> u64 {
+        self.votes.required()
+    }
+
+    /// Validators whose votes counted at `sequence` of a game's log, which
+    /// its checkpoint there is checked against
+    pub fn validators_at(&self, game_id: &str, sequence: u64) -> &Membership {
+        self.replaced_at(game_id, sequence)
+            .map_or(self.validators(), |(validators, _)| validators)
+    }
+
+    /// Weight of the signatures that make a quorum at `sequence` of a
+    /// game's log
+    pub fn required_weight_at(&self, game_id: &str, sequence: u64) -> u64 {
+        self.replaced_at(game_id, sequence)
+            .map_or(self.required_weight(), |(_, required)| *required)
+    }
+
+    /// Validators in place at `sequence` of a game's log and their quorum,
+    /// if a change replaced them since
+    fn replaced_at(&self, game_id: &str, sequence: u64) -> Option<&(Membership, u64)> {
+        self.superseded
+            .get(game_id)
+            .and_then(|replaced| replaced.range(sequence + 1..).next())
+            .map(|(_, replaced)| replaced)
+    }
+
+    /// The validators as `change` would leave them once the changes
+    /// already scheduled take effect
+    ///
+    /// A change for a validator ejected for equivocating, a removal
+    /// leaving fewer than `min_validators`, or, without `adaptive_quorum`,
+    /// one leaving too little weight for the quorum before it, is refused,
+    /// and one letting a validator make a quorum alone is refused as a
+    /// config error unless `allow_dictatorship` is set.
+    pub fn with_membership_change(&self, change: &MembershipChange) -> Result<Membership> {
+        let validator = change.validator();
+        if self.ejected.contains(validator) {
+            return Err(SwarmhostError::validation(format!(
+                "{} was ejected for equivocating",
+                short_id(validator)
+            )));
+        }
+        let mut membership = self.validators().clone();
+        for (_, scheduled) in &self.scheduled {
+            // Refused alike everywhere when it is taken up
+            let _ = membership.apply(&scheduled.change);
+        }
+        membership.apply(change)?;
+        if matches!(change, MembershipChange::RemoveValidator { .. })
+            && membership.len() < self.config.min_validators
+        {
+            return Err(SwarmhostError::validation(format!(
+                "removing {} would leave {} validators of the {} needed",
+                short_id(validator),
+                membership.len(),
+                self.config.min_validators
+            )));
+        }
+        let required = self.quorum_of(&membership);
+        if required > membership.total_weight() {
+            return Err(SwarmhostError::validation(format!(
+                "it would leave validators weighing {} of the {} a quorum needs \
+                 (set adaptive_quorum to let the quorum shrink)",
+                membership.total_weight(),
+                required
+            )));
+        }
+        membership.check(required, self.config.allow_dictatorship)?;
+        Ok(membership)
+    }
+
+    /// Check a membership change `actor` put forward in a game, to take
+    /// effect at `activation` when committed before `sequence`
+    fn check_membership_change(
+        &self,
+        game_id: &str,
+        actor: &PlayerId,
+        scheduled: &ScheduledChange,
+        sequence: u64,
+    ) -> Result<Membership> {
+        if !self.validators().contains(actor) {
+            return Err(SwarmhostError::validation(format!(
+                "proposed by {}, not a validator",
+                short_id(actor)
+            )));
+        }
+        if scheduled.activation <= sequence {
+            return Err(SwarmhostError::validation(format!(
+                "activation at {} is not after {}",
+                scheduled.activation, sequence
+            )));
+        }
+        let last = self
+            .scheduled
+            .iter()
+            .filter(|(game, _)| game == game_id)
+            .map(|(_, earlier)| earlier.activation)
+            .max();
+        if let Some(last) = last.filter(|last| scheduled.activation < *last) {
+            return Err(SwarmhostError::validation(format!(
+                "activation at {} comes before one scheduled at {}",
+                scheduled.activation, last
+            )));
+        }
+        self.with_membership_change(&scheduled.change)
+    }
+
+    /// Let the validators a grace epoch names go on alone, needing all of
+    /// them, once every one of them approved it; returns whether it took
+    /// effect
+    ///
+    /// Only with `quorum_grace` set, for at least `grace_min_validators` of
+    /// our validators falling short of a quorum together. The validators it
+    /// replaces still count for checkpoints below the next commit.
+    fn take_up_grace(&mut self, action: &SignedAction) -> bool {
+        if !self.config.quorum_grace {
+            return false;
+        }
+        let Some(Ok(grace)) = GraceEpoch::of(action) else {
+            return false;
+        };
+        let validators = self.validators();
+        if !grace
+            .validators
+            .iter()
+            .all(|player| validators.contains(player))
+        {
+            return false;
+        }
+        let membership = Membership::weighted(
+            grace
+                .validators
+                .iter()
+                .map(|player| (*player, validators.weight_of(player))),
+        );
+        let required = membership.total_weight();
+        if membership.len() < self.config.grace_min_validators || required >= self.required_weight()
+        {
+            return false;
+        }
+        let action_id = action.id();
+        let approving = self
+            .votes(&action_id)
+            .iter()
+            .filter(|vote| vote.approves())
+            .map(|vote| &vote.voter);
+        if membership.weight(approving) < required {
+            return false;
+        }
+
+        let game_id = action.game_id.clone();
+        let next = self
+            .logs
+            .get(&game_id)
+            .map_or(sequence::FIRST_SEQUENCE, CommitLog::next_deliver);
+        let replaced = (self.validators().clone(), self.required_weight());
+        self.seat(membership, required);
+        self.quorum_floor = required;
+        self.superseded
+            .entry(game_id.clone())
+            .or_default()
+            .insert(next, replaced);
+        self.reweighed = true;
+        tracing::warn!(
+            "Grace epoch in {} from {}: {} validators go on alone, all needed",
+            game_id,
+            next,
+            grace.validators.len()
+        );
+        let _ = self.events.send(NodeEvent::GraceEpochEntered {
+            game_id,
+            from: next,
+            validators: grace.validators,
+        });
+        true
+    }
+
+    /// The validators, if a membership change took effect since last asked
+    pub fn take_reweighed(&mut self) -> Option<Membership> {
+        std::mem::take(&mut self.reweighed).then(|| self.validators().clone())
+    }
+
+    /// Votes received so far for an action
+    pub fn votes(&self, action_id: &ActionId) -> &[Vote] {
+        self.votes.votes(action_id)
+    }
+
+    /// Where the validators' votes on an action stand
+    pub fn tally(&self, action_id: &ActionId) -> Tally {
+        self.votes.tally(action_id)
+    }
+
+    /// Round of the block an action was put forward in, if one was, and
+    /// the validators that voted neither on the action nor on that block
+    pub fn awaiting_votes_on(&self, action_id: &ActionId) -> (Option<u64>, Vec<PlayerId>) {
+        let block = self.block_of.get(action_id);
+        let round = block
+            .and_then(|block| self.blocks.get(block))
+            .map(|header| header.round);
+        let voted = |voter: &PlayerId| {
+            let on = |subject: &ActionId| self.votes(subject).iter().any(|v| &v.voter == voter);
+            on(action_id) || block.is_some_and(on)
+        };
+        let missing = self
+            .validators()
+            .ids()
+            .filter(|voter| !voted(voter))
+            .copied()
+            .collect();
+        (round, missing)
+    }
+
+    /// Tallies of every pending or voted-on action
+    pub fn tallies(&self) -> HashMap<ActionId, Tally> {
+        let mut tallies = self.votes.tallies();
+        for action in &self.pending {
+            let id = action.id();
+            tallies.entry(id).or_insert_with(|| self.votes.tally(&id));
+        }
+        tallies
+    }
+
+    /// Validator that numbers approved actions: the leader of the view in
+    /// progress, validators taking turns in the same order as proposers
+    pub fn sequencer(&self) -> Option<PlayerId> {
+        self.rotation.proposer(self.view)
+    }
+
+    /// View in progress
+    pub fn view(&self) -> u64 {
+        self.view
+    }
+
+    /// What we must not forget in a crash, to write down before sending a
+    /// vote, proposal or view change
+    pub fn safety_record(&self) -> SafetyRecord {
+        SafetyRecord {
+            round: self.round(),
+            view: self.view,
+            requested_view: self.requested_view,
+            votes: self.cast.to_vec(),
+        }
+    }
+
+    /// Pick up from the safety record written before a crash: no earlier
+    /// round or view than we had reached, and our votes standing
+    pub fn recover(&mut self, record: SafetyRecord) {
+        if record.round > self.round() {
+            self.rotation.finish(record.round - 1);
+        }
+        if record.view > self.view {
+            self.view = record.view;
+            self.clear_holds();
+        }
+        self.requested_view = self.requested_view.max(record.requested_view);
+        for vote in record.votes {
+            self.cast.insert(vote);
+        }
+    }
+
+    /// Number an action a quorum approved, or every action of a block, if we
+    /// are the sequencer and they have no number yet, followed by any
+    /// conflicting actions held back behind them that may now go, in order
+    ///
+    /// A block's actions get consecutive numbers in block order, skipping
+    /// those rejected on their own. Once the votes settle an action either
+    /// way, actions ordered after it in a conflict may be numbered.
+    ///
+    /// An action or block a quorum approved is numbered once every
+    /// validator has voted on it, or `fast_path_timeout` after the quorum,
+    /// whichever comes first; [`ConsensusManager::held_until`] says when
+    /// to try again. Each commit is counted as a fast-path commit if every
+    /// validator approved, and a normal one if not; its certificate is every
+    /// approval on the fast path, and the fewest making a quorum otherwise.
+    ///
+    /// Blocks are committed in the order they were proposed, so one the
+    /// votes approved ahead of an earlier block waits for it. A rejected
+    /// block fails, and takes every block built on it down with it.
+    pub fn sequence(&mut self, id: &Hash, keypair: &KeyPair, now: Instant) -> Vec<Commit> {
+        if self.tally(id).outcome() == Outcome::Rejected {
+            self.resolved.remove(id);
+            self.after.remove(id);
+            if self.blocks.contains_key(id) {
+                self.fail(id);
+            }
+        }
+        self.settle_blocks();
+        if self.sequencer() != Some(keypair.public_key()) {
+            return Vec::new();
+        }
+        let mut commits = match self.blocks.get(id).map(|header| header.actions.clone()) {
+            Some(block) => self.assign_block(block, keypair, now),
+            None => self.assign(id, keypair, now).into_iter().collect(),
+        };
+        // Blocks held behind those just committed may go now
+        loop {
+            self.settle_blocks();
+            let Some(oldest) = self
+                .unsettled
+                .values()
+                .next()
+                .and_then(|block| self.blocks.get(block))
+            else {
+                break;
+            };
+            let assigned = self.assign_block(oldest.actions.clone(), keypair, now);
+            if assigned.is_empty() {
+                break;
+            }
+            commits.extend(assigned);
+        }
+        let mut settled: Vec<ActionId> = commits.iter().map(|commit| commit.action.id()).collect();
+        settled.push(*id);
+        while let Some(settled_id) = settled.pop() {
+            let followers: BTreeSet<ActionId> = self
+                .after
+                .iter()
+                .filter(|(_, before)| before.contains(&settled_id))
+                .map(|(follower, _)| *follower)
+                .collect();
+            for follower in followers {
+                if let Some(commit) = self.assign(&follower, keypair, now) {
+                    settled.push(follower);
+                    commits.push(commit);
+                }
+            }
+        }
+        for commit in &commits {
+            if self.is_unanimous(&commit.action.id()) {
+                self.metrics.commits_fast_path.inc();
+            } else {
+                self.metrics.commits_normal_path.inc();
+            }
+        }
+        commits
+    }
+
+    /// Number the actions of an approved block in order, those held back by
+    /// a conflict once the actions before them in it are numbered
+    fn assign_block(
+        &mut self,
+        mut block: Vec<ActionId>,
+        keypair: &KeyPair,
+        now: Instant,
+    ) -> Vec<Commit> {
+        let mut commits = Vec::new();
+        loop {
+            let waiting = block.len();
+            block.retain(|action_id| match self.assign(action_id, keypair, now) {
+                Some(commit) => {
+                    commits.push(commit);
+                    false
+                }
+                None => true,
+            });
+            if block.len() == waiting {
+                return commits;
+            }
+        }
+    }
+
+    /// Number an approved action, unless it already has a number, it is
+    /// held for the rest of the votes, its round's conflicts are not settled
+    /// here yet, an action it conflicts with comes first and is still
+    /// undecided, or a block proposed before its own is not settled yet
+    fn assign(&mut self, action_id: &ActionId, keypair: &KeyPair, now: Instant) -> Option<Commit> {
+        if !self.is_approved(action_id) || self.is_held(action_id, now) {
+            return None;
+        }
+        let behind = self.block_of.get(action_id).is_some_and(|block| {
+            self.unsettled.values().next() != Some(block)
+                && self.unsettled.values().any(|unsettled| unsettled == block)
+        });
+        if behind {
+            return None;
+        }
+        let action = self
+            .pending
+            .iter()
+            .find(|action| &action.id() == action_id)?
+            .clone();
+        if !action.conflict_keys.is_empty() && !self.resolved.contains(action_id) {
+            return None;
+        }
+        let blocked = self.after.get(action_id).is_some_and(|before| {
+            before
+                .iter()
+                .any(|earlier| !self.is_decided(&action.game_id, earlier))
+        });
+        if blocked {
+            return None;
+        }
+        let certificate = self.certificate(action_id);
+        self.logs
+            .entry(action.game_id.clone())
+            .or_default()
+            .assign(keypair, action, certificate)
+    }
+
+    /// The approvals an action is numbered on, its own or else its
+    /// block's: every validator's if they all approved, and otherwise the
+    /// fewest that make a quorum
+    fn certificate(&self, action_id: &ActionId) -> Vec<Vote> {
+        let approvals = |id: &Hash| {
+            let required = if self.tally(id).is_unanimous() {
+                self.validators().total_weight()
+            } else {
+                self.required_weight()
+            };
+            beacon::certificate(self.votes(id), self.validators(), required)
+        };
+        let own = approvals(action_id);
+        if !own.is_empty() {
+            return own;
+        }
+        self.block_of
+            .get(action_id)
+            .map_or_else(Vec::new, approvals)
+    }
+
+    /// Check that the certificate of a commit, if it has one, approves its
+    /// action or a block holding it, one of ours or of `blocks`
+    ///
+    /// A certificate for a block we have not seen cannot be checked yet,
+    /// and is refused as a validation error.
+    fn check_certificate(&self, commit: &Commit, blocks: &[BlockHeader]) -> Result<()> {
+        if commit.certificate.is_empty() {
+            return Ok(());
+        }
+        let subject = beacon::verify(
+            &commit.certificate,
+            self.validators(),
+            self.required_weight(),
+        )?;
+        let action_id = commit.action.id();
+        if subject == action_id {
+            return Ok(());
+        }
+        let header = self
+            .blocks
+            .get(&subject)
+            .or_else(|| blocks.iter().find(|header| header.hash() == subject));
+        match header {
+            Some(header) if header.actions.contains(&action_id) => Ok(()),
+            Some(_) => Err(SwarmhostError::consensus(format!(
+                "commit at {} is certified by a block without {}",
+                commit.sequence,
+                short_id(&action_id)
+            ))),
+            None => Err(SwarmhostError::validation(format!(
+                "commit at {} is certified by block {}, not seen yet",
+                commit.sequence,
+                short_id(&subject)
+            ))),
+        }
+    }
+
+    /// Whether a quorum approved an action, on its own or with its block,
+    /// and it was not rejected on its own
+    fn is_approved(&self, action_id: &ActionId) -> bool {
+        match self.tally(action_id).outcome() {
+            Outcome::Approved => true,
+            Outcome::Rejected => false,
+            Outcome::Pending => self
+                .block_of
+                .get(action_id)
+                .is_some_and(|block| self.tally(block).outcome() == Outcome::Approved),
+        }
+    }
+
+    /// Whether every validator approved an action, on its own or with its
+    /// block
+    fn is_unanimous(&self, action_id: &ActionId) -> bool {
+        self.tally(action_id).is_unanimous()
+            || self
+                .block_of
+                .get(action_id)
+                .is_some_and(|block| self.tally(block).is_unanimous())
+    }
+
+    /// Whether an approved action waits on for the votes of validators yet
+    /// to vote on it, or on its block, which approved it
+    ///
+    /// The wait starts the first time it is asked about, and ends when the
+    /// last validator votes or `fast_path_timeout` later, after which the
+    /// action, and the rest of its block, are numbered on the quorum there
+    /// is.
+    fn is_held(&mut self, action_id: &ActionId, now: Instant) -> bool {
+        let subject = match self.block_of.get(action_id) {
+            Some(block) if self.tally(action_id).outcome() != Outcome::Approved => *block,
+            _ => *action_id,
+        };
+        let tally = self.tally(&subject);
+        let timeout = self.config.fast_path_timeout;
+        if timeout.is_zero()
+            || tally.approvals + tally.rejections >= tally.validators
+            || self.released.contains(&subject)
+        {
+            return false;
+        }
+        let until = *self.held.entry(subject).or_insert_with(|| {
+            self.holding.notify_one();
+            now + timeout
+        });
+        if now < until {
+            return true;
+        }
+        self.held.remove(&subject);
+        self.released.insert(subject);
+        false
+    }
+
+    /// When the first action or block held for the rest of the votes stops
+    /// waiting on them, if any is
+    pub fn held_until(&self) -> Option<Instant> {
+        self.held.values().min().copied()
+    }
+
+    /// Stop waiting on the rest of the votes for actions and blocks held
+    /// until `now` or earlier, returning them to
+    /// [sequence](ConsensusManager::sequence) again
+    pub fn release_holds(&mut self, now: Instant) -> Vec<Hash> {
+        let expired: Vec<Hash> = self
+            .held
+            .iter()
+            .filter(|(_, until)| **until <= now)
+            .map(|(id, _)| *id)
+            .collect();
+        for id in &expired {
+            self.held.remove(id);
+            self.released.insert(*id);
+        }
+        expired
+    }
+
+    /// Notified when an action or block is first held for the rest of the
+    /// votes, so whoever releases holds need not poll while there are none
+    pub fn holding(&self) -> Arc<Notify> {
+        self.holding.clone()
+    }
+
+    /// Forget every hold once the sequencer changes: only the sequencer
+    /// releases them, and a new one starts its own
+    fn clear_holds(&mut self) {
+        self.held.clear();
+        self.released.clear();
+    }
+
+    /// Whether the votes rejected an action, on its own or with its block
+    fn is_rejected(&self, action_id: &ActionId) -> bool {
+        self.tally(action_id).outcome() == Outcome::Rejected
+            || self
+                .block_of
+                .get(action_id)
+                .is_some_and(|block| self.tally(block).outcome() == Outcome::Rejected)
+    }
+
+    /// Whether an action of `game_id` was numbered, rejected, or dropped
+    fn is_decided(&self, game_id: &str, action_id: &ActionId) -> bool {
+        !self.is_pending(action_id)
+            || self.is_rejected(action_id)
+            || self
+                .logs
+                .get(game_id)
+                .is_some_and(|log| log.is_sequenced(action_id))
+    }
+
+    /// Whether the votes rejected a block, or it was discarded for following
+    /// one they did
+    fn has_failed(&self, block: &Hash) -> bool {
+        self.failed.contains(block) || self.tally(block).outcome() == Outcome::Rejected
+    }
+
+    /// Whether a block failed, or each of its actions was numbered,
+    /// rejected, dropped, or went out again in another block
+    fn is_settled(&self, block: &Hash) -> bool {
+        let Some(header) = self.blocks.get(block) else {
+            return true;
+        };
+        self.has_failed(block)
+            || header.actions.iter().all(|action_id| {
+                self.block_of.get(action_id) != Some(block)
+                    || self
+                        .pending
+                        .iter()
+                        .find(|action| &action.id() == action_id)
+                        .is_none_or(|action| self.is_decided(&action.game_id, action_id))
+            })
+    }
+
+    /// Whether a block is an empty tick, which nobody votes on
+    fn is_empty_block(&self, block: &Hash) -> bool {
+        self.blocks
+            .get(block)
+            .is_some_and(|header| header.actions.is_empty())
+    }
+
+    /// Let the oldest blocks in flight go while they are settled, so they
+    /// leave in the order they were proposed
+    fn settle_blocks(&mut self) {
+        while let Some((&round, &block)) = self.unsettled.first_key_value() {
+            if !self.is_settled(&block) {
+                break;
+            }
+            self.unsettled.remove(&round);
+            if let Some(proposed) = self.proposed_at.remove(&block)
+                && !self.has_failed(&block)
+                && !self.is_empty_block(&block)
+            {
+                self.record_round(round, &block, proposed);
+            }
+            if self.config.tick_interval().is_some() {
+                self.queue_tick(round, block);
+            }
+        }
+        self.metrics
+            .pipeline_occupancy
+            .set(self.unsettled.len() as u64);
+    }
+
+    /// Queue the tick of a settled block's round, after the rounds since
+    /// the last tick queued, which had no block that settled here
+    fn queue_tick(&mut self, round: u64, block: Hash) {
+        let next = *self.next_tick.get_or_insert(round);
+        if round < next {
+            return;
+        }
+        let skipped = (round - next).min(rotation::BLOCK_HISTORY);
+        self.ticks
+            .extend((round - skipped..round).map(|missed| (missed, None)));
+        self.ticks.push_back((round, Some(block)));
+        self.next_tick = Some(round + 1);
+    }
+
+    /// Ticks over whose commits are all delivered, in order, each with the
+    /// actions committed in it
+    ///
+    /// A tick waits for the commits of those of its actions that were
+    /// approved, and the ticks after it wait with it; one whose block
+    /// failed or never reached us has none.
+    pub fn take_ticks(&mut self) -> Vec<(u64, Vec<ActionId>)> {
+        self.settle_blocks();
+        let mut ticks = Vec::new();
+        while let Some(&(tick, block)) = self.ticks.front() {
+            let Some(actions) = block.map_or(Some(Vec::new()), |block| self.tick_actions(&block))
+            else {
+                break;
+            };
+            self.ticks.pop_front();
+            ticks.push((tick, actions));
+        }
+        ticks
+    }
+
+    /// Actions of a settled block that were committed, in block order;
+    /// none while any is committed but not delivered yet, and an empty tick
+    /// for a block that failed or is forgotten
+    fn tick_actions(&self, block: &Hash) -> Option<Vec<ActionId>> {
+        let Some(header) = self.blocks.get(block).filter(|_| !self.has_failed(block)) else {
+            return Some(Vec::new());
+        };
+        let mut committed = Vec::new();
+        for action_id in &header.actions {
+            if self.block_of.get(action_id) != Some(block) || self.is_rejected(action_id) {
+                continue;
+            }
+            let Some(action) = self.pending.iter().find(|action| &action.id() == action_id) else {
+                continue;
+            };
+            self.delivered_sequence(&action.game_id, action_id)?;
+            committed.push(*action_id);
+        }
+        Some(committed)
+    }
+
+    /// Count a committed block's latency and turnout, and wait on the
+    /// validators yet to vote on it
+    fn record_round(&mut self, round: u64, block: &Hash, proposed: Instant) {
+        let latency = Instant::now().saturating_duration_since(proposed);
+        let voters = self.voters(block);
+        let expected = self.validators().len();
+        self.metrics.round_latency.record(latency);
+        self.metrics.round_votes_received.add(voters.len() as u64);
+        self.metrics.round_votes_expected.add(expected as u64);
+        tracing::debug!(
+            round,
+            block = %short_id(block),
+            latency_ms = latency.as_millis() as u64,
+            votes = voters.len(),
+            expected,
+            "Round committed"
+        );
+        let missing: HashSet<PlayerId> = self
+            .validators()
+            .ids()
+            .filter(|validator| !voters.contains(*validator))
+            .copied()
+            .collect();
+        if !missing.is_empty() {
+            self.awaiting_votes.insert(*block, (proposed, missing));
+        }
+    }
+
+    /// Validators that voted on a block, or on any of its actions
+    fn voters(&self, block: &Hash) -> HashSet<PlayerId> {
+        let actions = self
+            .blocks
+            .get(block)
+            .map_or(&[][..], |header| header.actions.as_slice());
+        std::iter::once(block)
+            .chain(actions)
+            .flat_map(|id| self.votes(id))
+            .map(|vote| vote.voter)
+            .filter(|voter| self.validators().contains(voter))
+            .collect()
+    }
+
+    /// Count a validator's vote on a committed block, or on one of its
+    /// actions, as late
+    fn note_late(&mut self, voted: &Hash, voter: &PlayerId) {
+        let block = self.block_of.get(voted).copied().unwrap_or(*voted);
+        if let Some((_, missing)) = self.awaiting_votes.get_mut(&block)
+            && missing.remove(voter)
+        {
+            self.metrics.validators_late.inc(voter);
+            tracing::debug!(
+                voter = %short_id(voter),
+                block = %short_id(&block),
+                "Vote after the round committed"
+            );
+        }
+    }
+
+    /// Count the validators that did not vote on a committed block within
+    /// `timeout` of its proposal as silent on it
+    pub fn expire_votes(&mut self, now: Instant, timeout: Duration) {
+        let expired: Vec<Hash> = self
+            .awaiting_votes
+            .iter()
+            .filter(|(_, (proposed, _))| now.saturating_duration_since(*proposed) >= timeout)
+            .map(|(block, _)| *block)
+            .collect();
+        for block in expired {
+            let Some((_, missing)) = self.awaiting_votes.remove(&block) else {
+                continue;
+            };
+            for voter in missing {
+                self.metrics.validators_silent.inc(&voter);
+                tracing::debug!(
+                    voter = %short_id(&voter),
+                    block = %short_id(&block),
+                    "No vote within the consensus timeout"
+                );
+            }
+        }
+    }
+
+    /// Rounds whose block has waited `after` since its proposal, and as long
+    /// since we last asked, without the votes to settle it, with a bitmap of
+    /// the validators we have votes from and those we are missing
+    ///
+    /// Bit `i` of the bitmap is the `i`th validator in the rotation. Each
+    /// block returned counts as asked for now.
+    pub fn missing_votes(
+        &mut self,
+        now: Instant,
+        after: Duration,
+    ) -> Vec<(u64, u64, Vec<PlayerId>)> {
+        let unsettled = &self.unsettled;
+        self.asked
+            .retain(|block, _| unsettled.values().any(|unsettled| unsettled == block));
+        let waited = |since: &Instant| now.saturating_duration_since(*since) >= after;
+        let due: Vec<(u64, Hash)> = self
+            .unsettled
+            .iter()
+            .filter(|(_, block)| {
+                self.proposed_at.get(*block).is_some_and(waited)
+                    && self.asked.get(*block).is_none_or(waited)
+                    && !self.is_empty_block(block)
+                    && !self.has_failed(block)
+                    && self.tally(block).outcome() == Outcome::Pending
+            })
+            .map(|(round, block)| (*round, *block))
+            .collect();
+        let mut missing = Vec::new();
+        for (round, block) in due {
+            let voters: HashSet<PlayerId> =
+                self.votes(&block).iter().map(|vote| vote.voter).collect();
+            let absent: Vec<PlayerId> = self
+                .rotation
+                .order()
+                .iter()
+                .filter(|validator| !voters.contains(*validator))
+                .copied()
+                .collect();
+            if absent.is_empty() {
+                continue;
+            }
+            let have = self
+                .rotation
+                .order()
+                .iter()
+                .take(64)
+                .enumerate()
+                .filter(|(_, validator)| voters.contains(*validator))
+                .fold(0, |have, (i, _)| have | 1 << i);
+            self.asked.insert(block, now);
+            missing.push((round, have, absent));
+        }
+        missing
+    }
+
+    /// Votes we hold on the block of `round` and its actions, from
+    /// validators not marked in `have` as [`missing_votes`] marks them, up
+    /// to `MAX_VOTES_ANSWERED`
+    ///
+    /// [`missing_votes`]: Self::missing_votes
+    pub fn held_votes(&self, round: u64, have: u64) -> Vec<Vote> {
+        let order = self.rotation.order();
+        let wanted = |voter: &PlayerId| {
+            order
+                .iter()
+                .position(|validator| validator == voter)
+                .is_none_or(|i| i >= 64 || have & 1 << i == 0)
+        };
+        self.blocks
+            .iter()
+            .filter(|(_, header)| header.round == round)
+            .flat_map(|(block, header)| std::iter::once(block).chain(&header.actions))
+            .flat_map(|id| self.votes(id))
+            .filter(|vote| wanted(&vote.voter))
+            .take(vote::MAX_VOTES_ANSWERED)
+            .cloned()
+            .collect()
+    }
+
+    /// Whether we already hold `voter`'s vote on an action or block
+    pub fn has_vote(&self, action_id: &ActionId, voter: &PlayerId) -> bool {
+        self.votes(action_id)
+            .iter()
+            .any(|vote| &vote.voter == voter)
+    }
+
+    /// Latest block we know of that did not fail, for the next one to
+    /// follow
+    fn tip(&self) -> Option<Hash> {
+        self.blocks
+            .iter()
+            .filter(|(block, _)| !self.has_failed(block))
+            .max_by_key(|(_, header)| header.round)
+            .map(|(block, _)| *block)
+    }
+
+    /// Mark a block the votes rejected as failed, and discard the blocks
+    /// built on it, in round order
+    fn fail(&mut self, block: &Hash) {
+        if !self.failed.insert(*block) {
+            return;
+        }
+        self.unsettled.retain(|_, unsettled| unsettled != block);
+        self.proposed_at.remove(block);
+        let Some(round) = self.blocks.get(block).map(|header| header.round) else {
+            return;
+        };
+        self.metrics.rounds_failed_rejected.inc();
+        tracing::debug!(
+            round,
+            block = %short_id(block),
+            cause = "rejection",
+            "Block rejected"
+        );
+        let mut later: Vec<(u64, Hash, Option<Hash>)> = self
+            .blocks
+            .iter()
+            .filter(|(_, header)| header.round > round)
+            .map(|(later, header)| (header.round, *later, header.parent))
+            .collect();
+        later.sort();
+        let mut requeued = Vec::new();
+        for (_, later, parent) in later {
+            if parent.is_some_and(|parent| self.failed.contains(&parent)) {
+                requeued.extend(self.discard(&later));
+            }
+        }
+        self.requeue(requeued);
+    }
+
+    /// Drop a block following a failed one, returning its actions still
+    /// undecided, to be proposed again
+    fn discard(&mut self, block: &Hash) -> Vec<SignedAction> {
+        if !self.failed.insert(*block) {
+            return Vec::new();
+        }
+        self.unsettled.retain(|_, unsettled| unsettled != block);
+        self.proposed_at.remove(block);
+        let Some(header) = self.blocks.get(block).cloned() else {
+            return Vec::new();
+        };
+        let mut requeued = Vec::new();
+        for action_id in &header.actions {
+            if self.block_of.get(action_id) != Some(block) {
+                continue;
+            }
+            self.block_of.remove(action_id);
+            let Some(action) = self.pending.iter().find(|action| &action.id() == action_id) else {
+                continue;
+            };
+            if !self.is_decided(&action.game_id, action_id) && !self.unproposed.contains(action) {
+                requeued.push(action.clone());
+            }
+        }
+        tracing::debug!(
+            "Block {} of round {} discarded after its parent failed, {} actions to propose again",
+            short_id(block),
+            header.round,
+            requeued.len()
+        );
+        self.metrics.blocks_discarded.inc();
+        requeued
+    }
+
+    /// Put actions of discarded blocks back ahead of those not yet proposed,
+    /// their speculation to be rolled back
+    fn requeue(&mut self, actions: Vec<SignedAction>) {
+        if actions.is_empty() {
+            return;
+        }
+        self.unproposed_since.get_or_insert_with(Instant::now);
+        self.revoked.extend(actions.iter().cloned());
+        self.unproposed.splice(0..0, actions);
+    }
+
+    /// Actions of a block proposed in a recent round, in order
+    pub fn block(&self, block: &Hash) -> Option<&[ActionId]> {
+        self.blocks
+            .get(block)
+            .map(|header| header.actions.as_slice())
+    }
+
+    /// Blocks a quorum approved with actions still to be numbered, for a
+    /// new sequencer to take over
+    pub fn approved_blocks(&self) -> Vec<Hash> {
+        self.blocks
+            .iter()
+            .filter(|(block, header)| {
+                self.tally(block).outcome() == Outcome::Approved
+                    && !self.has_failed(block)
+                    && header
+                        .actions
+                        .iter()
+                        .any(|action_id| self.is_pending(action_id))
+            })
+            .map(|(block, _)| *block)
+            .collect()
+    }
+
+    /// Our votes on a block, with `invalid` the actions in it found
+    /// invalid and why, recorded here for gossiping in order
+    ///
+    /// With none invalid the block is approved. Otherwise, as
+    /// `invalid_in_block` says, the block is rejected for the first reason
+    /// given, or the invalid actions are rejected on their own ahead of
+    /// approving the block. Blocks we have not seen, and actions outside
+    /// the block, are refused.
+    pub fn vote_block(
+        &mut self,
+        keypair: &KeyPair,
+        block: &Hash,
+        invalid: &[(ActionId, RejectCode)],
+    ) -> Result<Vec<Vote>> {
+        let actions = &self
+            .blocks
+            .get(block)
+            .ok_or_else(|| {
+                SwarmhostError::validation(format!("Unknown block {}", short_id(block)))
+            })?
+            .actions;
+        if let Some((outside, _)) = invalid.iter().find(|(id, _)| !actions.contains(id)) {
+            return Err(SwarmhostError::validation(format!(
+                "Action {} is not in block {}",
+                short_id(outside),
+                short_id(block)
+            )));
+        }
+        let round = self.round();
+        let votes = match invalid.first() {
+            None => vec![Vote::new(keypair, *block, round, Decision::Approve)],
+            Some((_, reason)) if self.config.invalid_in_block == InvalidInBlock::RejectBlock => {
+                vec![Vote::rejecting(keypair, *block, round, *reason)]
+            }
+            Some(_) => invalid
+                .iter()
+                .map(|(action_id, reason)| Vote::rejecting(keypair, *action_id, round, *reason))
+                .chain([Vote::new(keypair, *block, round, Decision::Approve)])
+                .collect(),
+        };
+        votes
+            .into_iter()
+            .map(|vote| Ok(self.cast_vote(vote)?.0))
+            .collect()
+    }
+
+    /// What our checks see of a pending action before we approve it on its
+    /// own; none for an action we have not received
+    pub fn validation_context(&self, action_id: &ActionId) -> Option<ValidationContext> {
+        let action = self
+            .pending
+            .iter()
+            .find(|action| &action.id() == action_id)?;
+        Some(self.context(action))
+    }
+
+    /// What our checks see of each action of a block before we approve it
+    pub fn block_contexts(&self, block: &Hash) -> Result<Vec<ValidationContext>> {
+        let header = self.blocks.get(block).ok_or_else(|| {
+            SwarmhostError::validation(format!("Unknown block {}", short_id(block)))
+        })?;
+        Ok(self
+            .pending
+            .iter()
+            .filter(|action| header.actions.contains(&action.id()))
+            .map(|action| self.context(action))
+            .collect())
+    }
+
+    fn context(&self, action: &SignedAction) -> ValidationContext {
+        let action_id = action.id();
+        let of_actor = |other: &&SignedAction| other.actor == action.actor;
+        let actor_actions = match self
+            .block_of
+            .get(&action_id)
+            .and_then(|b| self.blocks.get(b))
+        {
+            Some(header) => self
+                .pending
+                .iter()
+                .filter(of_actor)
+                .filter(|other| header.actions.contains(&other.id()))
+                .count() as u32,
+            None => 1,
+        };
+        let committed = self
+            .logs
+            .get(&action.game_id)
+            .is_some_and(|log| log.is_sequenced(&action_id));
+        let replayed = self
+            .pending
+            .iter()
+            .take_while(|other| other.id() != action_id)
+            .filter(of_actor)
+            .any(|other| other.nonce == action.nonce && other.game_id == action.game_id);
+        let next = self
+            .logs
+            .get(&action.game_id)
+            .map_or(sequence::FIRST_SEQUENCE, CommitLog::next_deliver);
+        let refused_membership_change = ScheduledChange::of(action).is_some_and(|scheduled| {
+            scheduled
+                .and_then(|scheduled| {
+                    self.check_membership_change(&action.game_id, &action.actor, &scheduled, next)
+                })
+                .is_err()
+        });
+        let skew = self.config.expiry_clock_skew.as_millis() as u64;
+        let expired = action.is_expired(action::clock_ms(Instant::now()).saturating_sub(skew));
+        ValidationContext {
+            game_id: action.game_id.clone(),
+            action_id,
+            round: self.round(),
+            action: action.clone(),
+            actor_actions,
+            duplicate: committed || replayed,
+            refused_membership_change,
+            expired,
+            max_action_size: self.config.max_action_size,
+            max_actions_per_player_per_round: self.config.max_actions_per_player_per_round,
+        }
+    }
+
+    /// What our checks see of an action of a class delivered without a
+    /// sequence; none if we have it already
+    ///
+    /// It is refused first if too large or not signed by its actor.
+    pub fn unsequenced_context(&self, action: &SignedAction) -> Result<Option<ValidationContext>> {
+        if self.unsequenced.contains(&action.id()) {
+            return Ok(None);
+        }
+        self.check_size(action)?;
+        if let Err(e) = action.verify() {
+            self.metrics.actions_rejected_signature.inc();
+            self.reject(action, RejectionReason::InvalidSignature);
+            return Err(e);
+        }
+        Ok(Some(self.context(action)))
+    }
+
+    /// Deliver an action of `class` without a sequence, returning it with
+    /// the causal actions waiting on it, in order; a causal action whose
+    /// dependencies are not all delivered or committed waits itself
+    pub fn deliver_unsequenced(
+        &mut self,
+        action: SignedAction,
+        class: OrderingClass,
+    ) -> Vec<SignedAction> {
+        let logs = &self.logs;
+        self.unsequenced
+            .receive(action, class, |action_id| is_committed(logs, action_id))
+    }
+
+    /// Causal actions waiting on commits delivered since they arrived
+    pub fn release_unsequenced(&mut self) -> Vec<SignedAction> {
+        let logs = &self.logs;
+        self.unsequenced
+            .release(|action_id| is_committed(logs, action_id))
+    }
+
+    /// When the oldest action waiting for a block may go out in one that is
+    /// not full; in ticked rounds, when the next tick is due
+    pub fn batch_deadline(&self) -> Option<Instant> {
+        match self.config.round_mode {
+            RoundMode::FreeRunning => self
+                .unproposed_since
+                .map(|since| since + self.config.batch_interval),
+            RoundMode::Ticked { .. } => self.tick_at,
+        }
+    }
+
+    /// Remember the actions of a proposed round as a block in flight, and
+    /// announce it for validators to vote on
+    ///
+    /// A block following one that already failed is discarded instead.
+    fn record_block(&mut self, proposal: &RoundProposal, now: Instant) {
+        let header = proposal.header();
+        let block = header.hash();
+        for action_id in &header.actions {
+            self.block_of.insert(*action_id, block);
+        }
+        let actions = header.actions.clone();
+        let orphaned = header.parent.is_some_and(|parent| self.has_failed(&parent));
+        self.blocks.insert(block, header);
+        self.unsettled.insert(proposal.round, block);
+        self.proposed_at.insert(block, now);
+        self.metrics
+            .pipeline_occupancy
+            .set(self.unsettled.len() as u64);
+        if orphaned {
+            let requeued = self.discard(&block);
+            self.requeue(requeued);
+            return;
+        }
+        if actions.is_empty() {
+            // An empty tick settles as it is, with nothing to vote on
+            self.settle_blocks();
+            return;
+        }
+        let _ = self.events.send(NodeEvent::BlockProposed {
+            round: proposal.round,
+            block,
+            actions,
+        });
+    }
+
+    /// Take in a commit, returning the commits of its game now deliverable,
+    /// in order
+    ///
+    /// Commits numbered by anyone but the sequencer are refused as consensus
+    /// errors, except those of an earlier view's leader, which may still be
+    /// on their way; the sequencer is trusted to number only approved
+    /// actions. A second action at a sequence the sequencer already filled
+    /// convicts it of equivocation, and another action at one a checkpoint
+    /// fixed is refused as a consensus error.
+    pub fn receive_commit(&mut self, commit: Commit, now: Instant) -> Result<Vec<Commit>> {
+        self.check_ejected(&commit.sequencer)?;
+        if commit.action.action_type == GRACE_EPOCH_ACTION {
+            self.take_up_certified_grace(&commit);
+        }
+        if self.sequencer() != Some(commit.sequencer) {
+            let message = format!("{} is not the sequencer", short_id(&commit.sequencer));
+            let led_earlier = self
+                .rotation
+                .order()
+                .iter()
+                .position(|validator| validator == &commit.sequencer)
+                .is_some_and(|turn| (turn as u64) < self.view);
+            return Err(if led_earlier {
+                SwarmhostError::validation(message)
+            } else {
+                SwarmhostError::consensus(message)
+            });
+        }
+        commit.verify()?;
+        self.check_certificate(&commit, &[])?;
+        self.deliver(commit, now)
+    }
+
+    /// Take up the grace epoch a commit starts, on the approvals certifying
+    /// it, if we missed them; its sequencer is one of the validators it
+    /// leaves
+    fn take_up_certified_grace(&mut self, commit: &Commit) {
+        let action_id = commit.action.id();
+        for vote in &commit.certificate {
+            if vote.action_id == action_id && !self.has_vote(&action_id, &vote.voter) {
+                let _ = self.votes.record(vote.clone());
+            }
+        }
+        self.take_up_grace(&commit.action);
+    }
+
+    /// Take in commits a peer certified for us to catch up, returning those
+    /// now deliverable, in order
+    ///
+    /// Unlike gossiped commits, they need not be the current sequencer's:
+    /// the approvals of a quorum of our validators vouch for each. The
+    /// rounds of their blocks are over, so we move on past the latest, to
+    /// follow the rounds in progress rather than wait out each one.
+    pub fn receive_certified(
+        &mut self,
+        certified: CertifiedCommits,
+        now: Instant,
+    ) -> Result<Vec<Commit>> {
+        certified.verify(self.validators(), self.required_weight())?;
+        for commit in &certified.commits {
+            self.check_certificate(commit, &certified.blocks)?;
+        }
+        if let Some(round) = certified.blocks.iter().map(|header| header.round).max() {
+            self.end_round(round, &[], now);
+        }
+        let mut delivered = Vec::new();
+        for commit in certified.commits {
+            delivered.extend(self.deliver(commit, now)?);
+        }
+        Ok(delivered)
+    }
+
+    /// Delivered commits of a game from `from` on, with the approvals that
+    /// certify them, for a peer catching up
+    ///
+    /// An action approved with its block is certified by the block's votes.
+    pub fn certified(&self, game_id: &str, from: u64, count: u32) -> CertifiedCommits {
+        let approvals = |id: &Hash| -> Vec<Vote> {
+            self.votes(id)
+                .iter()
+                .filter(|vote| vote.approves())
+                .cloned()
+                .collect()
+        };
+        let required = self.required_weight();
+        let mut certified = CertifiedCommits::default();
+        for commit in self.commits(game_id, from, count) {
+            let action_id = commit.action.id();
+            let approved = approvals(&action_id);
+            let approving = self
+                .validators()
+                .weight(approved.iter().map(|vote| &vote.voter));
+            if approving >= required {
+                certified.votes.extend(approved);
+            } else if let Some(block) = self.block_of.get(&action_id)
+                && let Some(header) = self.blocks.get(block)
+                && !certified.blocks.contains(header)
+            {
+                certified.blocks.push(header.clone());
+                certified.votes.extend(approvals(block));
+            }
+            // The block its randomness is drawn from goes along too
+            if let Some(vote) = commit.certificate.first()
+                && let Some(header) = self.blocks.get(&vote.action_id)
+                && !certified.blocks.contains(header)
+            {
+                certified.blocks.push(header.clone());
+            }
+            certified.commits.push(commit);
+        }
+        certified
+    }
+
+    /// Go on in a game from just after `sequence`, every action up to which
+    /// a restored snapshot holds, returning the commits now deliverable
+    pub fn skip_to(&mut self, game_id: &str, sequence: u64, now: Instant) -> Vec<Commit> {
+        self.logs
+            .entry(game_id.to_string())
+            .or_default()
+            .skip_to(sequence + 1, now)
+    }
+
+    /// Sequence number of the last commit delivered in a game; 0 for none
+    pub fn committed(&self, game_id: &str) -> u64 {
+        self.logs
+            .get(game_id)
+            .map_or(0, |log| log.next_deliver() - sequence::FIRST_SEQUENCE)
+    }
+
+    /// Sequence an action of `game_id` was delivered at, if it is recent
+    pub fn delivered_sequence(&self, game_id: &str, action_id: &ActionId) -> Option<u64> {
+        self.logs
+            .get(game_id)
+            .and_then(|log| log.delivered_sequence(action_id))
+    }
+
+    /// Hold a game's commits up to `sequence` fixed, as a checkpoint a
+    /// quorum signed there has
+    pub fn finalize(&mut self, game_id: &str, sequence: u64) {
+        let finalized = self.finalized.entry(game_id.to_string()).or_default();
+        *finalized = (*finalized).max(sequence);
+    }
+
+    /// Take in a checked commit, convicting a sequencer that filled its
+    /// place with another action
+    ///
+    /// In a frozen game it is held until the fork is resolved, and in a
+    /// halted one dropped.
+    fn deliver(&mut self, commit: Commit, now: Instant) -> Result<Vec<Commit>> {
+        if let Some(fork) = self.forks.get_mut(&commit.action.game_id) {
+            if !fork.halted {
+                fork.held.push(commit);
+            }
+            return Ok(Vec::new());
+        }
+        let first = self
+            .logs
+            .get(&commit.action.game_id)
+            .and_then(|log| log.conflicting(&commit))
+            .cloned();
+        if let Some(first) = first {
+            let evidence = Equivocation::Commits(Box::new(first), Box::new(commit));
+            return Err(self.convict(evidence));
+        }
+        self.check_finalized(&commit)?;
+        let log = self.logs.entry(commit.action.game_id.clone()).or_default();
+        let delivered = log.insert(commit, now);
+        for commit in &delivered {
+            let action_id = commit.action.id();
+            self.resolved.remove(&action_id);
+            self.after.remove(&action_id);
+            self.schedule(commit);
+            self.activate(&commit.action.game_id, commit.sequence + 1);
+        }
+        self.settle_blocks();
+        Ok(delivered)
+    }
+
+    /// The commit delivered at `sequence` of a game, as far as history
+    /// reaches
+    pub fn delivered_at(&self, game_id: &str, sequence: u64) -> Option<&Commit> {
+        self.logs.get(game_id)?.delivered_at(sequence)
+    }
+
+    /// Freeze a game whose log a peer's differs from at `sequence`,
+    /// returning whether it was not frozen already
+    ///
+    /// Its commits are held back until [`resolve_fork`](Self::resolve_fork)
+    /// settles which branch holds.
+    pub fn freeze(&mut self, game_id: &str, sequence: u64) -> bool {
+        if self.forks.contains_key(game_id) {
+            return false;
+        }
+        self.forks.insert(game_id.to_string(), Fork::new(sequence));
+        true
+    }
+
+    /// Let a frozen game go on with our branch, when the fork could not be
+    /// looked into, returning the commits held meanwhile now delivered
+    ///
+    /// A halted game stays halted.
+    pub fn thaw(&mut self, game_id: &str, now: Instant) -> Vec<Commit> {
+        if self.forks.get(game_id).is_none_or(|fork| fork.halted) {
+            return Vec::new();
+        }
+        let held = self.forks.remove(game_id).unwrap_or_default().held;
+        let mut delivered = Vec::new();
+        for commit in held {
+            match self.deliver(commit, now) {
+                Ok(commits) => delivered.extend(commits),
+                Err(e) => tracing::debug!("Dropping a commit held during a fork: {}", e),
+            }
+        }
+        delivered
+    }
+
+    /// Settle a frozen game's fork against `theirs`, the peer's commit at
+    /// the fork with its certificate, by which branch a quorum of the
+    /// validators at the fork certified
+    ///
+    /// Taking up the peer's branch rewinds our log to the fork, unless a
+    /// checkpoint fixed it there, history does not reach back that far, or
+    /// a membership change was committed since; then the game is halted
+    /// instead. A halted game goes no further until it is left.
+    pub fn resolve_fork(
+        &mut self,
+        game_id: &str,
+        theirs: CertifiedCommits,
+        now: Instant,
+    ) -> Result<Resolution> {
+        let Some(fork) = self.forks.get(game_id) else {
+            return Err(SwarmhostError::consensus(format!(
+                "{} has no fork to resolve",
+                game_id
+            )));
+        };
+        if fork.halted {
+            return Ok(Resolution::Halted);
+        }
+        let sequence = fork.sequence;
+        let validators = self.validators_at(game_id, sequence);
+        let required = self.required_weight_at(game_id, sequence);
+        let certified = |branch: &CertifiedCommits| {
+            matches!(&branch.commits[..], [commit] if commit.sequence == sequence
+                && commit.action.game_id == game_id)
+                && branch.verify(validators, required).is_ok()
+        };
+        let ours = self.certified(game_id, sequence, 1);
+        let agreed = ours.commits.first().map(|commit| commit.action.id())
+            == theirs.commits.first().map(|commit| commit.action.id());
+        match (certified(&ours) || agreed, certified(&theirs) && !agreed) {
+            (true, false) => Ok(Resolution::Kept {
+                delivered: self.thaw(game_id, now),
+            }),
+            (false, true) if self.can_rewind(game_id, sequence) => {
+                let rolled_back = self
+                    .logs
+                    .get_mut(game_id)
+                    .map(|log| log.rewind(sequence))
+                    .unwrap_or_default();
+                self.forks.remove(game_id);
+                let mut delivered = Vec::new();
+                for commit in theirs.commits {
+                    match self.deliver(commit, now) {
+                        Ok(commits) => delivered.extend(commits),
+                        Err(e) => tracing::warn!("Could not take up the other branch: {}", e),
+                    }
+                }
+                Ok(Resolution::Adopted {
+                    rolled_back,
+                    delivered,
+                })
+            }
+            _ => {
+                self.halt(game_id);
+                Ok(Resolution::Halted)
+            }
+        }
+    }
+
+    /// Whether a game's log can be rewound to `sequence`: no checkpoint
+    /// fixed it there, history reaches back to it, and no commit from it on
+    /// changed the validators
+    fn can_rewind(&self, game_id: &str, sequence: u64) -> bool {
+        let finalized = self.finalized.get(game_id).copied().unwrap_or(0);
+        let next = self.committed(game_id) + sequence::FIRST_SEQUENCE;
+        sequence > finalized
+            && (sequence..next).all(|at| {
+                self.delivered_at(game_id, at)
+                    .is_some_and(|commit| ScheduledChange::of(&commit.action).is_none())
+            })
+    }
+
+    /// Stop delivering a game's commits for good, its fork found to be
+    /// beyond repair
+    fn halt(&mut self, game_id: &str) {
+        let fork = self.forks.entry(game_id.to_string()).or_default();
+        fork.halted = true;
+        fork.held.clear();
+        tracing::error!(
+            "Halting {}: its log forked at {} and no one branch is certified",
+            game_id,
+            fork.sequence
+        );
+    }
+
+    /// Whether a game's log forked and was halted
+    pub fn is_halted(&self, game_id: &str) -> bool {
+        self.forks.get(game_id).is_some_and(|fork| fork.halted)
+    }
+
+    /// Forget a game's fork, on leaving it
+    pub fn clear_fork(&mut self, game_id: &str) {
+        self.forks.remove(game_id);
+    }
+
+    /// Hold back the membership change a delivered commit carries, if any,
+    /// until its game's log reaches its activation
+    ///
+    /// Every validator delivers commits in the same order, so each
+    /// schedules the same changes at the same point, and a change is agreed
+    /// by the validators before it. One proposed by a non-validator, due
+    /// no later than its own commit, or refused by
+    /// [`with_membership_change`](Self::with_membership_change), is skipped
+    /// everywhere alike.
+    fn schedule(&mut self, commit: &Commit) {
+        let Some(scheduled) = ScheduledChange::of(&commit.action) else {
+            return;
+        };
+        let game_id = &commit.action.game_id;
+        let checked = scheduled.and_then(|scheduled| {
+            self.check_membership_change(
+                game_id,
+                &commit.action.actor,
+                &scheduled,
+                commit.sequence,
+            )?;
+            Ok(scheduled)
+        });
+        match checked {
+            Ok(scheduled) => {
+                tracing::info!(
+                    "Membership change for {} in {} takes effect at {}",
+                    short_id(scheduled.change.validator()),
+                    game_id,
+                    scheduled.activation
+                );
+                self.scheduled.push((game_id.clone(), scheduled));
+            }
+            Err(e) => {
+                tracing::warn!("Skipping membership change at {}: {}", commit.sequence, e)
+            }
+        }
+    }
+
+    /// Make the membership changes of a game due by `next`, the sequence
+    /// its log delivers next
+    fn activate(&mut self, game_id: &str, next: u64) {
+        let (due, waiting): (Vec<_>, Vec<_>) = std::mem::take(&mut self.scheduled)
+            .into_iter()
+            .partition(|(game, scheduled)| game == game_id && scheduled.activation <= next);
+        self.scheduled = waiting;
+        for (_, scheduled) in due {
+            let mut membership = self.validators().clone();
+            if let Err(e) = membership.apply(&scheduled.change) {
+                tracing::warn!("Skipping membership change at {}: {}", next, e);
+                continue;
+            }
+            let required = self.quorum_of(&membership);
+            if required > membership.total_weight() {
+                tracing::warn!(
+                    "Skipping membership change at {}: the quorum needs {} of {}",
+                    next,
+                    required,
+                    membership.total_weight()
+                );
+                continue;
+            }
+            let replaced = (self.validators().clone(), self.required_weight());
+            self.seat(membership, required);
+            self.quorum_floor = required;
+            self.superseded
+                .entry(game_id.to_string())
+                .or_default()
+                .insert(next, replaced);
+            tracing::info!(
+                "Validators of {} changed at {} for {}: {} weighing {}",
+                game_id,
+                next,
+                short_id(scheduled.change.validator()),
+                self.validators().len(),
+                self.validators().total_weight()
+            );
+            self.reweighed = true;
+        }
+    }
+
+    /// Ask to move past the view in progress once `view_change_rounds`
+    /// rounds in a row were skipped, and one view further after each such
+    /// run since; the request is counted here before it is returned
+    ///
+    /// It carries what we delivered in each game and every approved action
+    /// we have not delivered, so the next leader numbers it again.
+    pub fn view_change(&mut self, keypair: &KeyPair) -> Option<ViewChange> {
+        let rounds = self.config.view_change_rounds.max(1);
+        let view = self.view + (self.failed_rounds / rounds) as u64;
+        if view <= self.view.max(self.requested_view)
+            || !self.rotation.order().contains(&keypair.public_key())
+        {
+            return None;
+        }
+        self.requested_view = view;
+        let mut committed: Vec<(String, u64)> = self
+            .logs
+            .iter()
+            .map(|(game_id, log)| (game_id.clone(), log.next_deliver() - 1))
+            .collect();
+        committed.sort();
+        let change = ViewChange::new(keypair, view, committed, self.prepared());
+        self.receive_view_change(change.clone()).ok()?;
+        Some(change)
+    }
+
+    /// Take in a validator's request to change view
+    ///
+    /// Requests from outside the validator set, or whose prepared actions
+    /// lack a quorum's approvals, are refused as consensus errors; those
+    /// for views already reached or too far ahead, and repeats, are
+    /// ignored. Prepared actions given one number by the same sequencer
+    /// convict it of equivocation.
+    pub fn receive_view_change(&mut self, change: ViewChange) -> Result<()> {
+        self.check_ejected(&change.voter)?;
+        if !self.validators().contains(&change.voter) {
+            return Err(SwarmhostError::consensus(format!(
+                "{} is not a validator",
+                short_id(&change.voter)
+            )));
+        }
+        if change.view <= self.view
+            || change.view > self.view + view::MAX_VIEWS_AHEAD
+            || self
+                .view_changes
+                .get(&change.view)
+                .is_some_and(|changes| changes.contains_key(&change.voter))
+        {
+            return Ok(());
+        }
+        change.verify(self.validators(), self.required_weight())?;
+
+        let changes = self.view_changes.entry(change.view).or_default();
+        changes.insert(change.voter, change);
+        for evidence in view::equivocations(changes.values()) {
+            self.convict(evidence);
+        }
+        Ok(())
+    }
+
+    /// Start the latest view a quorum asked for, if we lead it, returning
+    /// it to gossip; its commits are to be applied here too
+    ///
+    /// Actions the quorum prepared keep the number they were given, past
+    /// what anyone delivered; the rest fill the numbers after that in order
+    /// of id. A number nobody prepared an action for stays a gap.
+    pub fn new_view(&mut self, keypair: &KeyPair) -> Option<NewView> {
+        let leader = keypair.public_key();
+        let required = self.required_weight();
+        let (&view, changes) = self.view_changes.iter().rev().find(|(view, changes)| {
+            self.validators().weight(changes.keys()) >= required
+                && self.rotation.proposer(**view) == Some(leader)
+        })?;
+        let changes: Vec<ViewChange> = changes.values().cloned().collect();
+
+        let mut games: Vec<_> = view::carryover(&changes).into_iter().collect();
+        games.sort_by(|(a, _), (b, _)| a.cmp(b));
+        let mut commits = Vec::new();
+        for (game_id, game) in games {
+            let log = self.logs.get(&game_id);
+            let delivered = |action: &SignedAction| {
+                log.is_some_and(|log| {
+                    log.is_sequenced(&action.id()) && log.waiting_commit(&action.id()).is_none()
+                })
+            };
+            let mut numbered = game.numbered;
+            let mut sequence = game.committed;
+            for action in game.unnumbered {
+                if delivered(&action) {
+                    continue;
+                }
+                sequence += 1;
+                while numbered.contains_key(&sequence) {
+                    sequence += 1;
+                }
+                numbered.insert(sequence, action);
+            }
+            commits.extend(numbered.into_iter().map(|(sequence, action)| {
+                let certificate = self.carried_certificate(&action.id(), &changes);
+                Commit::certified(keypair, sequence, action, certificate)
+            }));
+        }
+        self.enter_view(view, leader);
+        Some(NewView::new(keypair, view, changes, commits))
+    }
+
+    /// The approvals a prepared action is numbered on in a new view: its
+    /// old commit's, to draw the same randomness, or else ours, or else
+    /// those it was prepared with
+    fn carried_certificate(&self, action_id: &ActionId, changes: &[ViewChange]) -> Vec<Vote> {
+        let prepared: Vec<&Prepared> = changes
+            .iter()
+            .flat_map(|change| &change.prepared)
+            .filter(|prepared| &prepared.action.id() == action_id)
+            .collect();
+        if let Some(commit) = prepared
+            .iter()
+            .filter_map(|prepared| prepared.commit.as_ref())
+            .find(|commit| !commit.certificate.is_empty())
+        {
+            return commit.certificate.clone();
+        }
+        let ours = self.certificate(action_id);
+        if !ours.is_empty() {
+            return ours;
+        }
+        prepared.first().map_or_else(Vec::new, |prepared| {
+            beacon::certificate(&prepared.votes, self.validators(), self.required_weight())
+        })
+    }
+
+    /// Take in the start of a view, returning the commits its leader made
+    /// of prepared actions, to apply in order
+    ///
+    /// A new view from anyone but that view's leader, without a quorum of
+    /// valid view changes behind it, or renumbering what a checkpoint fixed,
+    /// is refused as a consensus error.
+    /// Views already reached are ignored.
+    pub fn receive_new_view(&mut self, new_view: NewView) -> Result<Vec<Commit>> {
+        self.check_ejected(&new_view.leader)?;
+        if self.rotation.proposer(new_view.view) != Some(new_view.leader) {
+            return Err(SwarmhostError::consensus(format!(
+                "{} does not lead view {}",
+                short_id(&new_view.leader),
+                new_view.view
+            )));
+        }
+        if new_view.view <= self.view {
+            return Ok(Vec::new());
+        }
+        new_view.verify(self.validators(), self.required_weight())?;
+
+        for commit in &new_view.commits {
+            self.check_finalized(commit)?;
+            self.check_certificate(commit, &[])?;
+        }
+        for evidence in view::equivocations(&new_view.view_changes) {
+            self.convict(evidence);
+        }
+        self.enter_view(new_view.view, new_view.leader);
+        Ok(new_view.commits)
+    }
+
+    /// Approved actions whose commit we have not delivered, with their
+    /// approvals, and their commit if it is waiting on a gap
+    fn prepared(&self) -> Vec<Prepared> {
+        self.pending
+            .iter()
+            .filter_map(|action| {
+                let action_id = action.id();
+                if self.tally(&action_id).outcome() != Outcome::Approved {
+                    return None;
+                }
+                let log = self.logs.get(&action.game_id);
+                let commit = log.and_then(|log| log.waiting_commit(&action_id)).cloned();
+                if commit.is_none() && log.is_some_and(|log| log.is_sequenced(&action_id)) {
+                    return None;
+                }
+                let votes = self
+                    .votes(&action_id)
+                    .iter()
+                    .filter(|vote| vote.approves())
+                    .cloned()
+                    .collect();
+                Some(Prepared {
+                    action: action.clone(),
+                    votes,
+                    commit,
+                })
+            })
+            .collect()
+    }
+
+    /// Take in evidence of equivocation a peer passed on, returning whether
+    /// it ejected a validator
+    ///
+    /// Evidence against a player who never validated is refused, and
+    /// evidence that does not verify is refused as a consensus or crypto
+    /// error.
+    pub fn receive_evidence(&mut self, evidence: &Equivocation) -> Result<bool> {
+        let offender = evidence.offender();
+        if self.ejected.contains(&offender) {
+            return Ok(false);
+        }
+        if !self.votes.validators().contains(&offender) {
+            return Err(SwarmhostError::validation(format!(
+                "{} is not a validator",
+                short_id(&offender)
+            )));
+        }
+        evidence.verify()?;
+        self.eject(evidence);
+        Ok(true)
+    }
+
+    /// Evidence found here since the last call, for peers to check
+    pub fn take_evidence(&mut self) -> Vec<Equivocation> {
+        std::mem::take(&mut self.evidence)
+    }
+
+    /// Let validators ejected for equivocating back in, on leaving their
+    /// game
+    pub fn clear_ejected(&mut self) {
+        self.ejected.clear();
+    }
+
+    /// Actions rejected for conflicts, or put back from a discarded block,
+    /// since the last call, for their speculation to be rolled back
+    pub fn take_revoked(&mut self) -> Vec<SignedAction> {
+        std::mem::take(&mut self.revoked)
+    }
+
+    /// Settle the conflicts among the actions of `proposal`, by the policy
+    /// of their game: losers are rejected, and actions ordered after others
+    /// are held back until those are decided
+    fn resolve_conflicts(&mut self, proposal: &RoundProposal) {
+        let touching = proposal
+            .actions
+            .iter()
+            .filter(|action| !action.conflict_keys.is_empty());
+        self.resolved.extend(touching.map(SignedAction::id));
+        let conflicts = conflict::detect(&proposal.actions);
+        if conflicts.is_empty() {
+            return;
+        }
+        let resolution = conflict::resolve(&conflicts, |game_id| {
+            self.config.conflict_policy_of(game_id)
+        });
+        let _ = self.events.send(NodeEvent::ConflictsDetected {
+            round: proposal.round,
+            conflicts,
+        });
+        self.after.extend(resolution.after);
+        for (loser, winner) in resolution.rejected {
+            self.resolved.remove(&loser);
+            let Some(at) = self.pending.iter().position(|action| action.id() == loser) else {
+                continue;
+            };
+            let action = self.pending.remove(at);
+            self.note_pending();
+            self.metrics.actions_rejected_conflict.inc();
+            self.reject(&action, RejectionReason::Conflict { with: winner });
+            self.revoked.push(action);
+        }
+    }
+
+    /// Refuse a message from a validator ejected for equivocating; honest
+    /// peers may still relay its messages until the evidence reaches them
+    /// Refuse, as a consensus error, a commit putting an action at a
+    /// sequence a checkpoint fixed other than the one delivered there
+    ///
+    /// Only what we delivered ourselves is held to the checkpoint; a node
+    /// behind it checks the rest against it when catching up.
+    fn check_finalized(&self, commit: &Commit) -> Result<()> {
+        let game_id = &commit.action.game_id;
+        let finalized = self
+            .finalized
+            .get(game_id)
+            .map_or(0, |&sequence| sequence.min(self.committed(game_id)));
+        if commit.sequence > finalized {
+            return Ok(());
+        }
+        let delivered = self
+            .logs
+            .get(game_id)
+            .and_then(|log| log.delivered_at(commit.sequence));
+        if delivered.is_some_and(|delivered| delivered.action.id() == commit.action.id()) {
+            return Ok(());
+        }
+        Err(SwarmhostError::consensus(format!(
+            "commit at {} of {} rewrites the log below its checkpoint at {}",
+            commit.sequence, game_id, finalized
+        )))
+    }
+
+    fn check_ejected(&self, player: &PlayerId) -> Result<()> {
+        if self.ejected.contains(player) {
+            return Err(SwarmhostError::validation(format!(
+                "{} was ejected for equivocating",
+                short_id(player)
+            )));
+        }
+        Ok(())
+    }
+
+    /// Eject the validator `evidence` convicts and keep the evidence for
+    /// peers, building the error that refuses the message proving it
+    fn convict(&mut self, evidence: Equivocation) -> SwarmhostError {
+        let error =
+            SwarmhostError::validation(format!("{} equivocated", short_id(&evidence.offender())));
+        if self.eject(&evidence) {
+            self.evidence.push(evidence);
+        }
+        error
+    }
+
+    /// Drop the offender from the validators and report it, unless it is
+    /// not one any more
+    fn eject(&mut self, evidence: &Equivocation) -> bool {
+        let offender = evidence.offender();
+        if !self.votes.validators().contains(&offender) {
+            return false;
+        }
+        self.ejected.insert(offender);
+        self.install(self.votes.validators().clone());
+        tracing::warn!(
+            "Ejecting validator {} for equivocating",
+            short_id(&offender)
+        );
+        let _ = self.events.send(NodeEvent::Misbehavior {
+            offender,
+            evidence: Box::new(evidence.clone()),
+        });
+        true
+    }
+
+    /// Move to `view`, led by `leader`, counting failed rounds afresh
+    fn enter_view(&mut self, view: u64, leader: PlayerId) {
+        self.metrics.rounds_failed_view_change.inc();
+        tracing::debug!(
+            view,
+            round = self.round(),
+            cause = "view change",
+            "Sequencer replaced"
+        );
+        self.view = view;
+        self.clear_holds();
+        self.failed_rounds = 0;
+        self.view_changes = self.view_changes.split_off(&(view + 1));
+        tracing::info!("Moved to view {}, led by {}", view, short_id(&leader));
+        let _ = self.events.send(NodeEvent::ViewChanged { view, leader });
+    }
+
+    /// Games with commits missing for `timeout`, with the first missing
+    /// sequence number and how many to fetch
+    pub fn gaps(&mut self, now: Instant, timeout: Duration) -> Vec<(String, u64, u32)> {
+        self.logs
+            .iter_mut()
+            .filter_map(|(game_id, log)| {
+                let (from, count) = log.gap(now, timeout)?;
+                Some((game_id.clone(), from, count))
+            })
+            .collect()
+    }
+
+    /// Delivered commits of a game from `from` on, to answer a fetch
+    pub fn commits(&self, game_id: &str, from: u64, count: u32) -> Vec<Commit> {
+        self.logs
+            .get(game_id)
+            .map(|log| log.delivered(from, count))
+            .unwrap_or_default()
+    }
+
+    /// Refuse an action larger than `max_action_size`
+    pub fn check_size(&self, action: &SignedAction) -> Result<()> {
+        if action.data.len() > self.config.max_action_size {
+            self.metrics.actions_rejected_oversized.inc();
+            return Err(self.reject(
+                action,
+                RejectionReason::Oversized {
+                    size: action.data.len(),
+                    max: self.config.max_action_size,
+                },
+            ));
+        }
+        Ok(())
+    }
+
+    /// Report a rejected action and build the matching error
+    fn reject(&self, action: &SignedAction, reason: RejectionReason) -> SwarmhostError {
+        let error = SwarmhostError::validation(format!(
+            "Action from {} rejected: {}",
+            short_id(&action.actor),
+            reason
+        ));
+        let _ = self.events.send(NodeEvent::ActionRejected {
+            action_id: action.id(),
+            actor: action.actor,
+            reason,
+        });
+        error
+    }
+}
+
+/// Whether an action was committed and delivered in any game's log
+fn is_committed(logs: &HashMap<String, CommitLog>, action_id: &ActionId) -> bool {
+    logs.values().any(|log| log.is_delivered(action_id))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::KeyPair;
+
+    fn manager(
+        config: ConsensusConfig,
+    ) -> (
+        ConsensusManager,
+        broadcast::Receiver<NodeEvent>,
+        Arc<NodeMetrics>,
+    ) {
+        let (events, rx) = broadcast::channel(16);
+        let metrics = Arc::new(NodeMetrics::default());
+        (
+            ConsensusManager::new(config, events, metrics.clone()),
+            rx,
+            metrics,
+        )
+    }
+
+    #[test]
+    fn test_oversized_proposal_rejected_before_signature_check() {
+        let config = ConsensusConfig {
+            max_action_size: 8,
+            ..Default::default()
+        };
+        let (mut consensus, mut events, metrics) = manager(config);
+
+        let mut action = SignedAction::new(&KeyPair::generate(), "game", 0, 1, vec![0; 9]);
+        action.signature = vec![0; 64];
+
+        let result = consensus.receive_proposal(action);
+        assert!(matches!(result, Err(SwarmhostError::Validation(_))));
+        assert_eq!(metrics.actions_rejected_oversized.get(), 1);
+        assert_eq!(metrics.actions_rejected_signature.get(), 0);
+        assert!(matches!(
+            events.try_recv().unwrap(),
+            NodeEvent::ActionRejected {
+                reason: RejectionReason::Oversized { size: 9, max: 8 },
+                ..
+            }
+        ));
+    }
+
+    #[test]
+    fn test_rate_limit_per_player_per_round() {
+        let config = ConsensusConfig {
+            max_actions_per_player_per_round: 2,
+            ..Default::default()
+        };
+        let (mut consensus, _events, metrics) = manager(config);
+        let spammer = KeyPair::generate();
+        let other = KeyPair::generate();
+
+        for nonce in 0..2 {
+            let action = SignedAction::new(&spammer, "game", nonce, 1, vec![]);
+            assert!(consensus.receive_proposal(action).is_ok());
+        }
+
+        // Third action this round is refused even with a bad signature
+        let mut action = SignedAction::new(&spammer, "game", 2, 1, vec![]);
+        action.signature = vec![0; 64];
+        assert!(consensus.receive_proposal(action).is_err());
+        assert_eq!(metrics.actions_rejected_rate_limited.get(), 1);
+        assert_eq!(metrics.actions_rejected_signature.get(), 0);
+
+        // Other players are unaffected
+        let action = SignedAction::new(&other, "game", 0, 1, vec![]);
+        assert!(consensus.receive_proposal(action).is_ok());
+
+        // The limit resets every round
+        consensus.advance_round();
+        let action = SignedAction::new(&spammer, "game", 3, 1, vec![]);
+        assert!(consensus.receive_proposal(action).is_ok());
+        assert_eq!(consensus.pending().len(), 4);
+    }
+
+    #[test]
+    fn test_full_intake_refuses_new_actions_as_overloaded() {
+        let config = ConsensusConfig {
+            max_pending_actions: 2,
+            ..Default::default()
+        };
+        let (mut consensus, _events, metrics) = manager(config);
+        let player = KeyPair::generate();
+        let actions: Vec<_> = (0..3)
+            .map(|nonce| SignedAction::new(&player, "game", nonce, 1, vec![]))
+            .collect();
+        consensus.submit_local(actions[0].clone()).unwrap();
+        consensus.receive_proposal(actions[1].clone()).unwrap();
+        assert!(consensus.is_full());
+        assert_eq!(metrics.pending_actions.get(), 2);
+
+        for refused in [
+            consensus.receive_proposal(actions[2].clone()),
+            consensus.submit_local(actions[2].clone()),
+        ] {
+            assert!(matches!(refused, Err(SwarmhostError::Overloaded(_))));
+        }
+        assert_eq!(metrics.actions_overloaded.get(), 2);
+        // Not a rejection: the actor may hand it to us again later
+        assert_eq!(metrics.actions_rejected_rate_limited.get(), 0);
+
+        // One we already have is no extra load
+        assert!(consensus.receive_proposal(actions[1].clone()).is_ok());
+        assert_eq!(consensus.undecided(), 2);
+    }
+
+    #[test]
+    fn test_bad_signature_rejected() {
+        let (mut consensus, _events, metrics) = manager(ConsensusConfig::default());
+        let mut action = SignedAction::new(&KeyPair::generate(), "game", 0, 1, vec![1]);
+        action.data = vec![2];
+
+        assert!(consensus.receive_proposal(action).is_err());
+        assert_eq!(metrics.actions_rejected_signature.get(), 1);
+        assert!(consensus.pending().is_empty());
+    }
+
+    #[test]
+    fn test_votes_counted_once_per_voter() {
+        let (mut consensus, _events, _metrics) = manager(ConsensusConfig::default());
+        let voter = KeyPair::generate();
+        let other = KeyPair::generate();
+        let action_id = [9; 32];
+        consensus.set_validators([voter.public_key(), other.public_key()].into());
+
+        assert!(
+            consensus
+                .receive_vote(Vote::new(&voter, action_id, 0, Decision::Approve))
+                .is_ok()
+        );
+        assert!(
+            consensus
+                .receive_vote(Vote::new(&voter, action_id, 0, Decision::Reject))
+                .is_err()
+        );
+
+        let mut forged = Vote::new(&other, action_id, 0, Decision::Approve);
+        forged.decision = Decision::Reject;
+        assert!(consensus.receive_vote(forged).is_err());
+
+        assert_eq!(consensus.votes(&action_id).len(), 1);
+        assert!(consensus.votes(&[0; 32]).is_empty());
+    }
+
+    #[test]
+    fn test_only_the_sequencer_numbers_approved_actions() {
+        let (mut consensus, _events, _metrics) = manager(ConsensusConfig::default());
+        let mut keys = [KeyPair::generate(), KeyPair::generate()];
+        keys.sort_by_key(KeyPair::public_key);
+        let [sequencer, other] = &keys;
+        consensus.set_validators(keys.iter().map(KeyPair::public_key).collect());
+        assert_eq!(consensus.sequencer(), Some(sequencer.public_key()));
+
+        let action = SignedAction::new(other, "game", 0, 1, vec![]);
+        let action_id = consensus.receive_proposal(action).unwrap();
+        assert!(
+            consensus
+                .sequence(&action_id, sequencer, Instant::now())
+                .is_empty()
+        );
+
+        for key in &keys {
+            let vote = Vote::new(key, action_id, 0, Decision::Approve);
+            consensus.receive_vote(vote).unwrap();
+        }
+        assert!(
+            consensus
+                .sequence(&action_id, other, Instant::now())
+                .is_empty()
+        );
+        let commit = consensus
+            .sequence(&action_id, sequencer, Instant::now())
+            .remove(0);
+        assert!(
+            consensus
+                .sequence(&action_id, sequencer, Instant::now())
+                .is_empty()
+        );
+
+        let delivered = consensus
+            .receive_commit(commit.clone(), Instant::now())
+            .unwrap();
+        assert_eq!(delivered, vec![commit.clone()]);
+        assert_eq!(consensus.commits("game", 1, 10), vec![commit.clone()]);
+
+        // A commit numbered by any other validator is refused
+        let forged = Commit::new(other, 2, commit.action);
+        assert!(matches!(
+            consensus.receive_commit(forged, Instant::now()),
+            Err(SwarmhostError::Consensus(_))
+        ));
+    }
+
+    #[test]
+    fn test_unanimous_approval_counts_as_a_fast_path_commit() {
+        let config = ConsensusConfig::default();
+        let timeout = config.fast_path_timeout;
+        let (mut consensus, _events, metrics) = manager(config);
+        let mut keys: Vec<KeyPair> = (0..4).map(|_| KeyPair::generate()).collect();
+        keys.sort_by_key(KeyPair::public_key);
+        consensus.set_validators(keys.iter().map(KeyPair::public_key).collect());
+        let sequencer = &keys[0];
+        let start = Instant::now();
+
+        // Everyone approves the first, a quorum of 3 the second
+        let mut ids = Vec::new();
+        for (nonce, voters) in [(0, 4), (1, 3)] {
+            let action = SignedAction::new(&keys[1], "game", nonce, 1, vec![]);
+            let action_id = consensus.receive_proposal(action).unwrap();
+            for key in &keys[..voters] {
+                let vote = Vote::new(key, action_id, 0, Decision::Approve);
+                consensus.receive_vote(vote).unwrap();
+            }
+            ids.push(action_id);
+        }
+        let mut commits = consensus.sequence(&ids[0], sequencer, start);
+        assert_eq!(commits.len(), 1);
+        assert_eq!(metrics.commits_fast_path.get(), 1);
+
+        // The quorum short of everyone waits on the last validator first
+        assert!(consensus.sequence(&ids[1], sequencer, start).is_empty());
+        assert_eq!(consensus.held_until(), Some(start + timeout));
+        let almost = start + timeout - Duration::from_millis(1);
+        assert!(consensus.release_holds(almost).is_empty());
+        assert_eq!(consensus.release_holds(start + timeout), vec![ids[1]]);
+        commits.extend(consensus.sequence(&ids[1], sequencer, start + timeout));
+        assert_eq!(commits.len(), 2);
+        assert_eq!(metrics.commits_fast_path.get(), 1);
+        assert_eq!(metrics.commits_normal_path.get(), 1);
+        assert_eq!(consensus.held_until(), None);
+
+        // The certificate is every approval on the fast path, a quorum's
+        // otherwise
+        let signers: Vec<usize> = commits
+            .iter()
+            .map(|commit| commit.certificate.len())
+            .collect();
+        assert_eq!(signers, vec![4, 3]);
+        for commit in commits {
+            consensus.receive_commit(commit, Instant::now()).unwrap();
+        }
+        let certified = consensus.certified("game", 1, 2);
+        assert_eq!(certified.votes.len(), 7);
+        assert!(
+            certified
+                .verify(consensus.validators(), consensus.required_weight())
+                .is_ok()
+        );
+    }
+
+    #[test]
+    fn test_holds_dropped_when_the_sequencer_changes() {
+        let (mut consensus, _events, _metrics) = manager(ConsensusConfig::default());
+        let mut keys: Vec<KeyPair> = (0..4).map(|_| KeyPair::generate()).collect();
+        keys.sort_by_key(KeyPair::public_key);
+        consensus.set_validators(keys.iter().map(KeyPair::public_key).collect());
+        let start = Instant::now();
+
+        let action = SignedAction::new(&keys[1], "game", 0, 1, vec![]);
+        let action_id = consensus.receive_proposal(action).unwrap();
+        for key in &keys[..3] {
+            let vote = Vote::new(key, action_id, 0, Decision::Approve);
+            consensus.receive_vote(vote).unwrap();
+        }
+        assert!(consensus.sequence(&action_id, &keys[0], start).is_empty());
+        assert!(consensus.held_until().is_some());
+
+        // The same validators in the same order keep the hold
+        consensus.set_validators(keys.iter().map(KeyPair::public_key).collect());
+        assert!(consensus.held_until().is_some());
+
+        // Without us the hold is no longer ours to release
+        consensus.set_validators(keys[1..].iter().map(KeyPair::public_key).collect());
+        assert_eq!(consensus.held_until(), None);
+        assert!(
+            consensus
+                .release_holds(start + Duration::from_secs(60))
+                .is_empty()
+        );
+    }
+
+    #[test]
+    fn test_removed_validator_counts_until_the_activation() {
+        let (mut consensus, _events, _metrics) = manager(ConsensusConfig::default());
+        let mut keys: Vec<KeyPair> = (0..4).map(|_| KeyPair::generate()).collect();
+        keys.sort_by_key(KeyPair::public_key);
+        consensus.set_validators(keys.iter().map(KeyPair::public_key).collect());
+        let (sequencer, leaving) = (&keys[0], &keys[3]);
+        let removal = |validator: &KeyPair, activation| ScheduledChange {
+            change: MembershipChange::RemoveValidator {
+                validator: validator.public_key(),
+            },
+            activation,
+        };
+
+        // Agreed by all four at 1, the removal holds from 3
+        let change = SignedAction::new(
+            &keys[1],
+            "game",
+            0,
+            MEMBERSHIP_CHANGE_ACTION,
+            removal(leaving, 3).encode(),
+        );
+        let mut actions = vec![change];
+        for nonce in 1..3 {
+            actions.push(SignedAction::new(&keys[1], "game", nonce, 1, vec![]));
+        }
+        for action in &actions[..2] {
+            let action_id = consensus.receive_proposal(action.clone()).unwrap();
+            for key in &keys {
+                let vote = Vote::new(key, action_id, 0, Decision::Approve);
+                consensus.receive_vote(vote).unwrap();
+            }
+            assert_eq!(consensus.votes(&action_id).len(), 4);
+            assert_eq!(consensus.validators().len(), 4);
+            for commit in consensus.sequence(&action_id, sequencer, Instant::now()) {
+                consensus.receive_commit(commit, Instant::now()).unwrap();
+            }
+        }
+
+        // From 3 on its votes are refused, and checkpoints below it still
+        // count it
+        assert!(!consensus.validators().contains(&leaving.public_key()));
+        assert_eq!(
+            consensus.take_reweighed(),
+            Some(consensus.validators().clone())
+        );
+        let action_id = consensus.receive_proposal(actions[2].clone()).unwrap();
+        let vote = Vote::new(leaving, action_id, 0, Decision::Approve);
+        assert!(consensus.receive_vote(vote).is_err());
+        assert!(consensus.votes(&action_id).is_empty());
+        assert_eq!(consensus.validators_at("game", 2).len(), 4);
+        assert_eq!(consensus.validators_at("game", 3).len(), 3);
+        assert_eq!(consensus.required_weight_at("game", 2), 3);
+
+        // Three is the fewest allowed, and a change must be due after its
+        // own commit
+        let too_few = SignedAction::new(
+            &keys[1],
+            "game",
+            3,
+            MEMBERSHIP_CHANGE_ACTION,
+            removal(&keys[2], 10).encode(),
+        );
+        let too_soon = SignedAction::new(
+            &keys[1],
+            "game",
+            4,
+            MEMBERSHIP_CHANGE_ACTION,
+            ScheduledChange {
+                change: MembershipChange::ChangeWeight {
+                    validator: keys[1].public_key(),
+                    weight: 2,
+                },
+                activation: 3,
+            }
+            .encode(),
+        );
+        for action in [too_few, too_soon] {
+            let action_id = consensus.receive_proposal(action).unwrap();
+            let ctx = consensus.validation_context(&action_id).unwrap();
+            assert!(ctx.refused_membership_change);
+        }
+        let ctx = consensus.validation_context(&action_id).unwrap();
+        assert!(!ctx.refused_membership_change);
+    }
+
+    #[test]
+    fn test_conflicting_actions_numbered_in_id_order() {
+        let (mut consensus, mut events, _metrics) = manager(ConsensusConfig::default());
+        let validator = KeyPair::generate();
+        consensus.set_validators([validator.public_key()].into());
+        let mut grabs: Vec<SignedAction> = (0..2)
+            .map(|nonce| SignedAction::touching(&validator, "game", nonce, 1, vec![], vec![7]))
+            .collect();
+        grabs.sort_by_key(SignedAction::id);
+        for grab in &grabs {
+            consensus.submit_local(grab.clone()).unwrap();
+        }
+        let [first, second] = [grabs[0].id(), grabs[1].id()];
+
+        // Not numbered before their round settles the conflict
+        consensus
+            .receive_vote(Vote::new(&validator, second, 0, Decision::Approve))
+            .unwrap();
+        assert!(
+            consensus
+                .sequence(&second, &validator, Instant::now())
+                .is_empty()
+        );
+        consensus.propose(&validator, Instant::now()).unwrap();
+        assert!(matches!(
+            events.try_recv().unwrap(),
+            NodeEvent::BlockProposed { round: 0, .. }
+        ));
+        assert!(matches!(
+            events.try_recv().unwrap(),
+            NodeEvent::ConflictsDetected { round: 0, .. }
+        ));
+
+        // The second waits for the first, then follows it
+        assert!(
+            consensus
+                .sequence(&second, &validator, Instant::now())
+                .is_empty()
+        );
+        consensus
+            .receive_vote(Vote::new(&validator, first, 0, Decision::Approve))
+            .unwrap();
+        let commits = consensus.sequence(&first, &validator, Instant::now());
+        let order: Vec<(u64, ActionId)> = commits
+            .iter()
+            .map(|commit| (commit.sequence, commit.action.id()))
+            .collect();
+        assert_eq!(order, vec![(1, first), (2, second)]);
+        assert_eq!(consensus.pending().len(), 2);
+    }
+
+    #[test]
+    fn test_actions_batched_until_block_full_or_interval_passed() {
+        let config = ConsensusConfig {
+            batch_interval: Duration::from_millis(50),
+            batch_max_actions: 3,
+            ..Default::default()
+        };
+        let (mut consensus, mut events, _metrics) = manager(config);
+        let validator = KeyPair::generate();
+        consensus.set_validators([validator.public_key()].into());
+        let submit = |consensus: &mut ConsensusManager, nonces: std::ops::Range<u64>| {
+            for nonce in nonces {
+                let action = SignedAction::new(&validator, "game", nonce, 1, vec![]);
+                consensus.submit_local(action).unwrap();
+            }
+        };
+
+        // Two actions wait for more until the interval passes
+        submit(&mut consensus, 0..2);
+        let now = Instant::now();
+        assert!(consensus.propose(&validator, now).is_none());
+        let block = consensus
+            .propose(&validator, now + Duration::from_millis(50))
+            .unwrap();
+        assert_eq!(block.actions.len(), 2);
+        let NodeEvent::BlockProposed {
+            round,
+            block: hash,
+            actions,
+        } = events.try_recv().unwrap()
+        else {
+            panic!("block not announced");
+        };
+        assert_eq!((round, hash), (0, block.hash()));
+        assert_eq!(consensus.block(&hash), Some(actions.as_slice()));
+
+        // A full block goes at once, and the rest waits for the next
+        submit(&mut consensus, 2..6);
+        let full = consensus.propose(&validator, Instant::now()).unwrap();
+        assert_eq!(full.actions.len(), 3);
+        assert!(consensus.propose(&validator, Instant::now()).is_none());
+        assert!(consensus.batch_deadline().is_some());
+    }
+
+    #[test]
+    fn test_ticked_rounds_go_out_on_the_beat_and_deliver_in_order() {
+        let tick = Duration::from_millis(50);
+        let config = ConsensusConfig {
+            round_mode: RoundMode::Ticked {
+                tick_interval: tick,
+            },
+            ..Default::default()
+        };
+        let (mut consensus, mut events, _metrics) = manager(config);
+        let validator = KeyPair::generate();
+        consensus.set_validators([validator.public_key()].into());
+
+        // The first tick is due an interval after the proposer is first asked
+        let start = Instant::now();
+        assert!(consensus.propose(&validator, start).is_none());
+        let action = SignedAction::new(&validator, "game", 0, 1, vec![]);
+        consensus.submit_local(action.clone()).unwrap();
+        assert!(consensus.propose(&validator, start + tick / 2).is_none());
+        let first = consensus.propose(&validator, start + tick).unwrap();
+        assert_eq!(first.action_ids(), vec![action.id()]);
+
+        // A tick with nothing to propose still goes out, keeping to the beat
+        // when we wake up late for it, and nobody is asked to vote on it
+        let empty = consensus
+            .propose(&validator, start + tick * 2 + Duration::from_millis(5))
+            .unwrap();
+        assert!(empty.actions.is_empty());
+        assert_eq!(empty.parent, Some(first.hash()));
+        assert_eq!(consensus.batch_deadline(), Some(start + tick * 3));
+        let mut announced = Vec::new();
+        while let Ok(event) = events.try_recv() {
+            if let NodeEvent::BlockProposed { round, .. } = event {
+                announced.push(round);
+            }
+        }
+        assert_eq!(announced, vec![0]);
+
+        // The empty tick waits for the commit of the one before it
+        assert!(consensus.take_ticks().is_empty());
+        consensus
+            .vote_block(&validator, &first.hash(), &[])
+            .unwrap();
+        for commit in consensus.sequence(&first.hash(), &validator, Instant::now()) {
+            consensus.receive_commit(commit, Instant::now()).unwrap();
+        }
+        assert_eq!(
+            consensus.take_ticks(),
+            vec![(0, vec![action.id()]), (1, Vec::new())]
+        );
+        assert!(consensus.take_ticks().is_empty());
+    }
+
+    #[test]
+    fn test_expired_actions_left_out_of_blocks_and_rejected_by_validators() {
+        let (mut consensus, mut events, _metrics) = manager(ConsensusConfig::default());
+        let validator = KeyPair::generate();
+        consensus.set_validators([validator.public_key()].into());
+        let now = Instant::now();
+        let clock = action::clock_ms(now);
+        let due = |nonce, deadline_ms| {
+            SignedAction::expiring(
+                &validator,
+                "game",
+                nonce,
+                1,
+                vec![],
+                vec![],
+                Some(deadline_ms),
+            )
+        };
+        let (fresh, late, stale) = (
+            due(0, clock + 10_000),
+            due(1, clock - 30),
+            due(2, clock - 200),
+        );
+        for action in [&fresh, &late, &stale] {
+            consensus.submit_local(action.clone()).unwrap();
+        }
+
+        // Validators allow for clocks up to `expiry_clock_skew` apart
+        let expired = |consensus: &ConsensusManager, action: &SignedAction| {
+            consensus.validation_context(&action.id()).unwrap().expired
+        };
+        assert!(!expired(&consensus, &fresh));
+        assert!(!expired(&consensus, &late));
+        assert!(expired(&consensus, &stale));
+
+        // The proposer does not
+        let block = consensus.propose(&validator, Instant::now()).unwrap();
+        assert_eq!(block.action_ids(), vec![fresh.id()]);
+        for action in [&late, &stale] {
+            assert_eq!(
+                events.try_recv().unwrap(),
+                NodeEvent::ActionRejected {
+                    action_id: action.id(),
+                    actor: validator.public_key(),
+                    reason: RejectionReason::Expired {
+                        deadline_ms: action.deadline_ms.unwrap()
+                    },
+                }
+            );
+        }
+        assert_eq!(consensus.take_revoked(), vec![late, stale]);
+        assert!(consensus.outstanding().is_empty());
+    }
+
+    #[test]
+    fn test_pipelined_blocks_commit_in_the_order_proposed() {
+        let config = ConsensusConfig {
+            pipeline_depth: 2,
+            ..Default::default()
+        };
+        let (mut consensus, _events, _metrics) = manager(config);
+        let validator = KeyPair::generate();
+        consensus.set_validators([validator.public_key()].into());
+        let mut submit = |nonce| {
+            let action = SignedAction::new(&validator, "game", nonce, 1, vec![]);
+            consensus.submit_local(action.clone()).unwrap();
+            let block = consensus.propose(&validator, Instant::now());
+            (action, block)
+        };
+        let (first, parent) = submit(0);
+        let (second, child) = submit(1);
+        let (parent, child) = (parent.unwrap(), child.unwrap());
+        assert_eq!((parent.parent, child.parent), (None, Some(parent.hash())));
+        // Two blocks in flight fill the pipeline
+        let (_, full) = submit(2);
+        assert!(full.is_none());
+
+        let approve = |consensus: &mut ConsensusManager, block: &RoundProposal| {
+            consensus
+                .vote_block(&validator, &block.hash(), &[])
+                .unwrap();
+            consensus.sequence(&block.hash(), &validator, Instant::now())
+        };
+        assert!(approve(&mut consensus, &child).is_empty());
+        let commits = approve(&mut consensus, &parent);
+        let order: Vec<(u64, ActionId)> = commits
+            .iter()
+            .map(|commit| (commit.sequence, commit.action.id()))
+            .collect();
+        assert_eq!(order, vec![(1, first.id()), (2, second.id())]);
+
+        // With both committed, the waiting action goes out
+        let next = consensus.propose(&validator, Instant::now()).unwrap();
+        assert_eq!(next.parent, Some(child.hash()));
+
+        let (mut serial, _events, _metrics) = manager(ConsensusConfig {
+            pipelining: false,
+            ..Default::default()
+        });
+        serial.set_validators([validator.public_key()].into());
+        for nonce in 0..2 {
+            let action = SignedAction::new(&validator, "game", nonce, 1, vec![]);
+            serial.submit_local(action).unwrap();
+        }
+        assert!(serial.propose(&validator, Instant::now()).is_some());
+        assert!(serial.propose(&validator, Instant::now()).is_none());
+    }
+
+    #[test]
+    fn test_votes_forgotten_with_the_blocks_they_decided() {
+        let tick = Duration::from_millis(50);
+        let config = ConsensusConfig {
+            round_mode: RoundMode::Ticked {
+                tick_interval: tick,
+            },
+            ..Default::default()
+        };
+        let (mut consensus, _events, _metrics) = manager(config);
+        let validator = KeyPair::generate();
+        consensus.set_validators([validator.public_key()].into());
+
+        // A few actions go out first, then empty ticks carry the game on
+        // past the block history
+        let start = Instant::now();
+        assert!(consensus.propose(&validator, start).is_none());
+        let actions: Vec<SignedAction> = (0..4)
+            .map(|nonce| SignedAction::new(&validator, "game", nonce, 1, vec![]))
+            .collect();
+        let rounds = rotation::BLOCK_HISTORY as u32 + 8;
+        for round in 0..rounds {
+            if let Some(action) = actions.get(round as usize) {
+                consensus.submit_local(action.clone()).unwrap();
+            }
+            let block = consensus
+                .propose(&validator, start + tick * (round + 1))
+                .unwrap();
+            consensus
+                .vote_block(&validator, &block.hash(), &[])
+                .unwrap();
+            for commit in consensus.sequence(&block.hash(), &validator, Instant::now()) {
+                consensus.receive_commit(commit, Instant::now()).unwrap();
+            }
+            consensus.take_ticks();
+        }
+        for action in &actions {
+            assert!(consensus.votes(&action.id()).is_empty());
+        }
+        assert!(consensus.pending().is_empty());
+        assert!(consensus.tallies().len() <= rotation::BLOCK_HISTORY as usize + 1);
+    }
+
+    #[test]
+    fn test_blocks_built_on_a_rejected_one_are_proposed_again() {
+        let (mut consensus, _events, metrics) = manager(ConsensusConfig::default());
+        let validator = KeyPair::generate();
+        consensus.set_validators([validator.public_key()].into());
+        let actions: Vec<SignedAction> = (0..3)
+            .map(|nonce| SignedAction::new(&validator, "game", nonce, 1, vec![]))
+            .collect();
+        let mut blocks = Vec::new();
+        for action in &actions {
+            consensus.submit_local(action.clone()).unwrap();
+            blocks.push(consensus.propose(&validator, Instant::now()).unwrap());
+        }
+        // The blocks after the first are approved before it fails
+        for block in &blocks[1..] {
+            consensus
+                .vote_block(&validator, &block.hash(), &[])
+                .unwrap();
+            assert!(
+                consensus
+                    .sequence(&block.hash(), &validator, Instant::now())
+                    .is_empty()
+            );
+        }
+        let failed = blocks[0].hash();
+        let rejection = Vote::rejecting(&validator, failed, 2, RejectCode::GAME);
+        consensus.receive_vote(rejection).unwrap();
+        assert!(
+            consensus
+                .sequence(&failed, &validator, Instant::now())
+                .is_empty()
+        );
+        assert_eq!(metrics.blocks_discarded.get(), 2);
+        assert_eq!(metrics.rounds_failed_rejected.get(), 1);
+        assert_eq!(metrics.pipeline_occupancy.get(), 0);
+        let revoked: Vec<ActionId> = consensus
+            .take_revoked()
+            .iter()
+            .map(SignedAction::id)
+            .collect();
+        assert_eq!(revoked, vec![actions[1].id(), actions[2].id()]);
+        // Their approvals approve nothing now
+        assert!(
+            consensus
+                .sequence(&blocks[2].hash(), &validator, Instant::now())
+                .is_empty()
+        );
+
+        let again = consensus.propose(&validator, Instant::now()).unwrap();
+        assert_eq!(again.action_ids(), revoked);
+        assert_eq!(again.parent, None);
+        consensus
+            .vote_block(&validator, &again.hash(), &[])
+            .unwrap();
+        let commits = consensus.sequence(&again.hash(), &validator, Instant::now());
+        let order: Vec<(u64, ActionId)> = commits
+            .iter()
+            .map(|commit| (commit.sequence, commit.action.id()))
+            .collect();
+        assert_eq!(order, vec![(1, revoked[0]), (2, revoked[1])]);
+    }
+
+    #[test]
+    fn test_block_votes_cover_its_actions_as_configured() {
+        for policy in [InvalidInBlock::RejectActions, InvalidInBlock::RejectBlock] {
+            let config = ConsensusConfig {
+                invalid_in_block: policy,
+                ..Default::default()
+            };
+            let (mut consensus, _events, _metrics) = manager(config);
+            let validator = KeyPair::generate();
+            consensus.set_validators([validator.public_key()].into());
+            let mut actions: Vec<SignedAction> = (0..3)
+                .map(|nonce| SignedAction::new(&validator, "game", nonce, 1, vec![]))
+                .collect();
+            actions.sort_by_key(SignedAction::id);
+            for action in &actions {
+                consensus.submit_local(action.clone()).unwrap();
+            }
+            let block = consensus
+                .propose(&validator, Instant::now())
+                .unwrap()
+                .hash();
+            let [first, bad, last] = [actions[0].id(), actions[1].id(), actions[2].id()];
+
+            assert!(
+                consensus
+                    .vote_block(&validator, &block, &[([9; 32], RejectCode::GAME)])
+                    .is_err()
+            );
+            let votes = consensus
+                .vote_block(&validator, &block, &[(bad, RejectCode::GAME)])
+                .unwrap();
+            assert!(
+                votes
+                    .iter()
+                    .all(|vote| vote.approves() || vote.reason == Some(RejectCode::GAME))
+            );
+            let commits = consensus.sequence(&block, &validator, Instant::now());
+            let order: Vec<(u64, ActionId)> = commits
+                .iter()
+                .map(|commit| (commit.sequence, commit.action.id()))
+                .collect();
+            match policy {
+                InvalidInBlock::RejectActions => {
+                    assert_eq!(votes.len(), 2);
+                    assert_eq!(order, vec![(1, first), (2, last)]);
+                }
+                InvalidInBlock::RejectBlock => {
+                    assert_eq!(votes.len(), 1);
+                    assert_eq!(consensus.tally(&block).outcome(), Outcome::Rejected);
+                    assert!(order.is_empty());
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_rounds_accepted_only_from_their_proposer() {
+        let (mut consensus, _events, _metrics) = manager(ConsensusConfig::default());
+        let mut keys = [KeyPair::generate(), KeyPair::generate()];
+        keys.sort_by_key(KeyPair::public_key);
+        let [first, second] = &keys;
+        consensus.set_validators(keys.iter().map(KeyPair::public_key).collect());
+        let now = Instant::now();
+
+        // Our action waits for round 0's proposer
+        let ours = SignedAction::new(second, "game", 0, 1, vec![]);
+        consensus.submit_local(ours.clone()).unwrap();
+        assert!(consensus.propose(second, now).is_none());
+        assert_eq!(consensus.outstanding(), std::slice::from_ref(&ours));
+
+        let theirs = SignedAction::new(first, "game", 0, 1, vec![]);
+        let out_of_turn = RoundProposal::new(second, 0, None, vec![theirs.clone()]);
+        assert!(matches!(
+            consensus.receive_round(out_of_turn, now),
+            Err(SwarmhostError::Consensus(_))
+        ));
+
+        let round = RoundProposal::new(first, 0, None, vec![ours, theirs.clone()]);
+        assert_eq!(
+            consensus.receive_round(round.clone(), now).unwrap(),
+            vec![theirs]
+        );
+        assert_eq!(consensus.round(), 1);
+        assert!(consensus.outstanding().is_empty());
+        assert_eq!(consensus.pending().len(), 2);
+        // A copy of a finished round changes nothing
+        assert!(consensus.receive_round(round, now).unwrap().is_empty());
+
+        // Round 1 is ours, with nothing to put forward
+        assert_eq!(consensus.proposer(), Some(second.public_key()));
+        assert!(consensus.propose(second, now).is_none());
+    }
+
+    #[test]
+    fn test_double_vote_ejects_the_voter_with_evidence() {
+        let (mut consensus, mut events, _metrics) = manager(ConsensusConfig::default());
+        let keys = [
+            KeyPair::generate(),
+            KeyPair::generate(),
+            KeyPair::generate(),
+        ];
+        let ids: HashSet<PlayerId> = keys.iter().map(KeyPair::public_key).collect();
+        consensus.set_validators(ids.clone());
+        let offender = &keys[0];
+
+        let approve = Vote::new(offender, [1; 32], 0, Decision::Approve);
+        let reject = Vote::new(offender, [1; 32], 0, Decision::Reject);
+        consensus.receive_vote(approve.clone()).unwrap();
+        assert!(matches!(
+            consensus.receive_vote(reject.clone()),
+            Err(SwarmhostError::Validation(_))
+        ));
+
+        let evidence = consensus.take_evidence();
+        assert_eq!(evidence, vec![Equivocation::Votes(approve, reject)]);
+        assert!(evidence[0].verify().is_ok());
+        assert!(consensus.take_evidence().is_empty());
+        assert_eq!(
+            events.try_recv().unwrap(),
+            NodeEvent::Misbehavior {
+                offender: offender.public_key(),
+                evidence: Box::new(evidence[0].clone()),
+            }
+        );
+
+        // Its later votes are ignored, and it stays out of the validators
+        assert!(!consensus.validators().contains(&offender.public_key()));
+        let later = Vote::new(offender, [2; 32], 0, Decision::Approve);
+        assert!(matches!(
+            consensus.receive_vote(later),
+            Err(SwarmhostError::Validation(_))
+        ));
+        assert!(consensus.votes(&[2; 32]).is_empty());
+        consensus.set_validators(ids);
+        assert_eq!(consensus.validators().len(), 2);
+        assert_eq!(consensus.tally(&[1; 32]).required, 2);
+
+        // The same evidence from a peer changes nothing more
+        assert!(!consensus.receive_evidence(&evidence[0]).unwrap());
+        assert!(events.try_recv().is_err());
+    }
+
+    #[test]
+    fn test_observer_votes_tallied_apart_from_the_quorum() {
+        let (mut consensus, mut events, _metrics) = manager(ConsensusConfig::default());
+        let keys = [KeyPair::generate(), KeyPair::generate()];
+        let ids: HashSet<PlayerId> = keys.iter().map(KeyPair::public_key).collect();
+        consensus.set_validators(ids);
+        let observer = KeyPair::generate();
+        let action = SignedAction::new(&observer, "game", 0, 1, vec![]);
+        let action_id = consensus.receive_proposal(action).unwrap();
+
+        // Observers rejecting change nothing of the validators' tally
+        let reject = ObserverVote::new(&observer, action_id, 0, Decision::Reject, None);
+        consensus.receive_observer_vote(reject.clone()).unwrap();
+        for key in &keys {
+            consensus
+                .receive_vote(Vote::new(key, action_id, 0, Decision::Approve))
+                .unwrap();
+        }
+        assert_eq!(consensus.tally(&action_id).outcome(), Outcome::Approved);
+        let tally = ObserverTally {
+            approvals: 0,
+            rejections: 1,
+        };
+        assert_eq!(consensus.observer_tallies()[&action_id], tally);
+        assert_eq!(
+            events.try_recv().unwrap(),
+            NodeEvent::ObserverVoted {
+                action_id,
+                observer: observer.public_key(),
+                decision: Decision::Reject,
+                tally,
+            }
+        );
+
+        // Voting both ways is refused without ejecting anyone, and neither
+        // validators nor votes on unknown actions count as observers
+        let approve = ObserverVote::new(&observer, action_id, 0, Decision::Approve, None);
+        assert!(consensus.receive_observer_vote(approve).is_err());
+        assert!(events.try_recv().is_err());
+        let validator = ObserverVote::new(&keys[0], action_id, 0, Decision::Reject, None);
+        assert!(consensus.receive_observer_vote(validator).is_err());
+        let unknown = ObserverVote::new(&KeyPair::generate(), [9; 32], 0, Decision::Reject, None);
+        assert!(consensus.receive_observer_vote(unknown).is_err());
+        assert_eq!(consensus.validators().len(), 2);
+        assert_eq!(consensus.observer_tallies()[&action_id], tally);
+
+        // Signed under its own domain, it cannot pass for a real vote
+        let replayed = Vote {
+            action_id,
+            round: 0,
+            voter: observer.public_key(),
+            decision: Decision::Reject,
+            reason: None,
+            signature: reject.signature,
+        };
+        assert!(replayed.verify().is_err());
+    }
+
+    /// Commit `action` with every validator's approval, numbered by the
+    /// first of `keys`, the sequencer
+    fn commit_approved(consensus: &mut ConsensusManager, keys: &[KeyPair], action: SignedAction) {
+        let action_id = consensus.receive_proposal(action).unwrap();
+        for key in keys {
+            if consensus.validators().contains(&key.public_key()) {
+                let vote = Vote::new(key, action_id, 0, Decision::Approve);
+                consensus.receive_vote(vote).unwrap();
+            }
+        }
+        for commit in consensus.sequence(&action_id, &keys[0], Instant::now()) {
+            consensus.receive_commit(commit, Instant::now()).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_quorum_shrinks_with_removals_only_when_adaptive() {
+        let mut keys: Vec<KeyPair> = (0..6).map(|_| KeyPair::generate()).collect();
+        keys.sort_by_key(KeyPair::public_key);
+        let removal = |nonce: u64| {
+            let scheduled = ScheduledChange {
+                change: MembershipChange::RemoveValidator {
+                    validator: keys[5 - nonce as usize].public_key(),
+                },
+                activation: nonce + 2,
+            };
+            SignedAction::new(
+                &keys[0],
+                "game",
+                nonce,
+                MEMBERSHIP_CHANGE_ACTION,
+                scheduled.encode(),
+            )
+        };
+
+        // Four of six stay needed with two validators gone, so a third
+        // leaving would stall the game
+        let (mut fixed, _events, _metrics) = manager(ConsensusConfig::default());
+        fixed.set_validators(keys.iter().map(KeyPair::public_key).collect());
+        for nonce in 0..2 {
+            commit_approved(&mut fixed, &keys, removal(nonce));
+        }
+        assert_eq!((fixed.validators().len(), fixed.required_weight()), (4, 4));
+        let third = MembershipChange::RemoveValidator {
+            validator: keys[3].public_key(),
+        };
+        assert!(fixed.with_membership_change(&third).is_err());
+
+        // Adaptive, the quorum is two thirds of whoever is left
+        let config = ConsensusConfig {
+            adaptive_quorum: true,
+            ..ConsensusConfig::default()
+        };
+        let (mut adaptive, _events, _metrics) = manager(config);
+        adaptive.set_validators(keys.iter().map(KeyPair::public_key).collect());
+        for nonce in 0..3 {
+            commit_approved(&mut adaptive, &keys, removal(nonce));
+        }
+        assert_eq!(
+            (adaptive.validators().len(), adaptive.required_weight()),
+            (3, 2)
+        );
+        assert_eq!(adaptive.required_weight_at("game", 1), 4);
+        assert_eq!(adaptive.required_weight_at("game", 3), 3);
+    }
+
+    #[test]
+    fn test_grace_epoch_needs_every_validator_it_names() {
+        let mut keys: Vec<KeyPair> = (0..6).map(|_| KeyPair::generate()).collect();
+        keys.sort_by_key(KeyPair::public_key);
+        let grace = |validators: &[KeyPair]| {
+            let grace = GraceEpoch {
+                validators: validators.iter().map(KeyPair::public_key).collect(),
+            };
+            SignedAction::new(&keys[0], "game", 0, GRACE_EPOCH_ACTION, grace.encode())
+        };
+        let config = ConsensusConfig {
+            quorum_grace: true,
+            ..ConsensusConfig::default()
+        };
+        let (mut consensus, mut events, _metrics) = manager(config.clone());
+        consensus.set_validators(keys.iter().map(KeyPair::public_key).collect());
+
+        // Four of six make a quorum anyway, and one validator is too few
+        for named in [&keys[..4], &keys[..1]] {
+            let action_id = consensus.receive_proposal(grace(named)).unwrap();
+            for key in named {
+                let vote = Vote::new(key, action_id, 0, Decision::Approve);
+                consensus.receive_vote(vote).unwrap();
+            }
+        }
+        assert_eq!(consensus.validators().len(), 6);
+
+        // Three go on alone once the last of them approves, all of them
+        // needed from then on
+        let action = grace(&keys[..3]);
+        let action_id = consensus.receive_proposal(action.clone()).unwrap();
+        for key in &keys[..2] {
+            let vote = Vote::new(key, action_id, 0, Decision::Approve);
+            assert_eq!(consensus.receive_vote(vote).unwrap(), None);
+        }
+        let vote = Vote::new(&keys[2], action_id, 0, Decision::Approve);
+        assert_eq!(
+            consensus.receive_vote(vote).unwrap(),
+            Some(Outcome::Approved)
+        );
+        assert_eq!(
+            (consensus.validators().len(), consensus.required_weight()),
+            (3, 3)
+        );
+        assert!(matches!(
+            events.try_recv().unwrap(),
+            NodeEvent::GraceEpochEntered { from: 1, ref validators, .. } if validators.len() == 3
+        ));
+        assert_eq!(consensus.validators_at("game", 0).len(), 6);
+        assert_eq!(consensus.required_weight_at("game", 0), 4);
+
+        // Committed, it starts the epoch on a node that missed the votes
+        let commits = consensus.sequence(&action_id, &keys[0], Instant::now());
+        assert_eq!(commits.len(), 1);
+        consensus
+            .receive_commit(commits[0].clone(), Instant::now())
+            .unwrap();
+        let (mut replaying, _events, _metrics) = manager(config);
+        replaying.set_validators(keys.iter().map(KeyPair::public_key).collect());
+        replaying
+            .receive_commit(commits[0].clone(), Instant::now())
+            .unwrap();
+        assert_eq!(replaying.validators(), consensus.validators());
+        assert_eq!(replaying.committed("game"), 1);
+    }
+
+    #[test]
+    fn test_restarted_validator_stands_by_its_recorded_votes() {
+        let keys = [
+            KeyPair::generate(),
+            KeyPair::generate(),
+            KeyPair::generate(),
+        ];
+        let ids: HashSet<PlayerId> = keys.iter().map(KeyPair::public_key).collect();
+        let local = &keys[0];
+        let (mut consensus, _events, _metrics) = manager(ConsensusConfig::default());
+        consensus.set_validators(ids.clone());
+        let approve = Vote::new(local, [1; 32], 0, Decision::Approve);
+        consensus.cast_vote(approve.clone()).unwrap();
+        for _ in 0..3 {
+            consensus.advance_round();
+        }
+        let record = consensus.safety_record();
+        assert_eq!(record.round, 3);
+        assert_eq!(record.votes, vec![approve.clone()]);
+
+        // After the crash, a fresh manager picks up where the record left it
+        let (mut restarted, mut events, _metrics) = manager(ConsensusConfig::default());
+        restarted.set_validators(ids);
+        restarted.recover(record.clone());
+        assert_eq!(restarted.safety_record(), record);
+
+        // Voting otherwise is refused before anything is sent
+        let reject = Vote::new(local, [1; 32], 3, Decision::Reject);
+        assert!(matches!(
+            restarted.cast_vote(reject),
+            Err(SwarmhostError::InvalidState(_))
+        ));
+        // and voting alike sends the very vote peers may already have
+        let again = Vote::new(local, [1; 32], 3, Decision::Approve);
+        let (sent, _) = restarted.cast_vote(again).unwrap();
+        assert_eq!(sent, approve);
+        assert!(restarted.take_evidence().is_empty());
+        assert!(events.try_recv().is_err());
+    }
+
+    #[test]
+    fn test_second_commit_at_a_sequence_convicts_the_sequencer() {
+        let (mut consensus, _events, _metrics) = manager(ConsensusConfig::default());
+        let sequencer = KeyPair::generate();
+        consensus.set_validators([sequencer.public_key()].into());
+        let first = SignedAction::new(&sequencer, "game", 0, 1, vec![1]);
+        let second = SignedAction::new(&sequencer, "game", 1, 1, vec![2]);
+        let now = Instant::now();
+
+        let commit = Commit::new(&sequencer, 1, first);
+        consensus.receive_commit(commit.clone(), now).unwrap();
+        // A copy is fine; another action at the same place is not
+        assert!(
+            consensus
+                .receive_commit(commit.clone(), now)
+                .unwrap()
+                .is_empty()
+        );
+        let conflicting = Commit::new(&sequencer, 1, second);
+        assert!(consensus.receive_commit(conflicting.clone(), now).is_err());
+        assert_eq!(
+            consensus.take_evidence(),
+            vec![Equivocation::Commits(
+                Box::new(commit),
+                Box::new(conflicting)
+            )]
+        );
+        assert!(consensus.validators().is_empty());
+    }
+
+    #[test]
+    fn test_commits_below_the_checkpoint_cannot_rewrite_it() {
+        let (mut consensus, _events, _metrics) = manager(ConsensusConfig::default());
+        let validators = [KeyPair::generate(), KeyPair::generate()];
+        consensus.set_validators(validators.iter().map(KeyPair::public_key).collect());
+        let sequencer = consensus.sequencer().unwrap();
+        let [sequencer, other] = if validators[0].public_key() == sequencer {
+            [&validators[0], &validators[1]]
+        } else {
+            [&validators[1], &validators[0]]
+        };
+        let now = Instant::now();
+        for nonce in 0..3 {
+            let action = SignedAction::new(sequencer, "game", nonce, 1, vec![]);
+            let commit = Commit::new(sequencer, nonce + 1, action);
+            consensus.receive_commit(commit, now).unwrap();
+        }
+
+        // Another validator's number for a new action at 2, certified by a
+        // quorum, as a view that lost track of the log would give it
+        let rewrite = SignedAction::new(other, "game", 0, 1, vec![]);
+        let certified = CertifiedCommits {
+            commits: vec![Commit::new(other, 2, rewrite.clone())],
+            blocks: Vec::new(),
+            votes: validators
+                .iter()
+                .map(|keypair| Vote::new(keypair, rewrite.id(), 0, Decision::Approve))
+                .collect(),
+        };
+        assert!(
+            consensus
+                .receive_certified(certified.clone(), now)
+                .unwrap()
+                .is_empty()
+        );
+
+        consensus.finalize("game", 2);
+        assert!(matches!(
+            consensus.receive_certified(certified, now),
+            Err(SwarmhostError::Consensus(_))
+        ));
+        // What the checkpoint holds still passes, as does what follows it
+        let kept = consensus.commits("game", 2, 1).remove(0);
+        assert!(consensus.receive_commit(kept, now).unwrap().is_empty());
+        let next = SignedAction::new(sequencer, "game", 3, 1, vec![]);
+        let next = Commit::new(sequencer, 4, next);
+        assert_eq!(consensus.receive_commit(next, now).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_fork_resolved_for_the_branch_a_quorum_certified() {
+        let (mut consensus, _events, _metrics) = manager(ConsensusConfig::default());
+        let mut validators = [KeyPair::generate(), KeyPair::generate()];
+        validators.sort_by_key(KeyPair::public_key);
+        consensus.set_validators(validators.iter().map(KeyPair::public_key).collect());
+        let [sequencer, other] = &validators;
+        assert_eq!(consensus.sequencer(), Some(sequencer.public_key()));
+        let approved = |action: &SignedAction| -> Vec<Vote> {
+            validators
+                .iter()
+                .map(|keypair| Vote::new(keypair, action.id(), 0, Decision::Approve))
+                .collect()
+        };
+        let now = Instant::now();
+        let ours: Vec<Commit> = (0..3)
+            .map(|nonce| {
+                let action = SignedAction::new(sequencer, "game", nonce, 1, vec![]);
+                Commit::new(sequencer, nonce + 1, action)
+            })
+            .collect();
+        for commit in &ours[..2] {
+            consensus.receive_commit(commit.clone(), now).unwrap();
+        }
+        for vote in approved(&ours[0].action) {
+            consensus.receive_vote(vote).unwrap();
+        }
+        let branch = |sequence, nonce, certified: bool| {
+            let action = SignedAction::new(other, "game", nonce, 1, vec![]);
+            CertifiedCommits {
+                votes: if certified {
+                    approved(&action)
+                } else {
+                    Vec::new()
+                },
+                commits: vec![Commit::new(other, sequence, action)],
+                blocks: Vec::new(),
+            }
+        };
+
+        // Ours is certified at 1 and theirs is not: what was held goes on
+        assert!(consensus.freeze("game", 1));
+        assert!(!consensus.freeze("game", 2));
+        assert!(
+            consensus
+                .receive_commit(ours[2].clone(), now)
+                .unwrap()
+                .is_empty()
+        );
+        assert_eq!(
+            consensus
+                .resolve_fork("game", branch(1, 0, false), now)
+                .unwrap(),
+            Resolution::Kept {
+                delivered: vec![ours[2].clone()]
+            }
+        );
+
+        // Theirs is at 2: ours is rolled back from there
+        let theirs = branch(2, 1, true);
+        assert!(consensus.freeze("game", 2));
+        assert_eq!(
+            consensus.resolve_fork("game", theirs.clone(), now).unwrap(),
+            Resolution::Adopted {
+                rolled_back: ours[1..].to_vec(),
+                delivered: theirs.commits.clone(),
+            }
+        );
+        assert_eq!(consensus.committed("game"), 2);
+        assert_eq!(consensus.delivered_at("game", 2), theirs.commits.first());
+
+        // Both certified at 1: nothing more is delivered until it is left
+        assert!(consensus.freeze("game", 1));
+        assert_eq!(
+            consensus
+                .resolve_fork("game", branch(1, 2, true), now)
+                .unwrap(),
+            Resolution::Halted
+        );
+        assert!(consensus.is_halted("game"));
+        assert!(consensus.thaw("game", now).is_empty());
+        let next = SignedAction::new(sequencer, "game", 9, 1, vec![]);
+        let next = Commit::new(sequencer, 3, next);
+        assert!(
+            consensus
+                .receive_commit(next.clone(), now)
+                .unwrap()
+                .is_empty()
+        );
+        consensus.clear_fork("game");
+        assert_eq!(consensus.receive_commit(next, now).unwrap().len(), 1);
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,177 @@
+// consensus/observer.rs - Verdicts of nodes that validate without a say,
+// counted apart from the quorum
+
+use super::action::ActionId;
+use super::validation::RejectCode;
+use super::vote::Decision;
+use crate::crypto::{self, KeyPair, PlayerId, short_id};
+use crate::error::{Result, SwarmhostError};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
+
+/// Actions and blocks whose observer votes are kept; past this those of the
+/// oldest are forgotten
+const OBSERVED_HISTORY: usize = 1024;
+
+/// Most observers whose votes on one action or block are counted
+const MAX_OBSERVERS: usize = 64;
+
+/// A non-validator's signed verdict on a proposed action or block
+///
+/// It mirrors a [`Vote`](super::Vote) but is signed under its own domain,
+/// so it can never be replayed as one, even if the observer later becomes
+/// a validator.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ObserverVote {
+    /// Action or block being judged
+    pub action_id: ActionId,
+
+    /// Consensus round the observer was in when voting
+    pub round: u64,
+
+    /// Player casting the vote
+    pub observer: PlayerId,
+
+    /// Whether the observer found it valid
+    pub decision: Decision,
+
+    /// Why the observer found it invalid, when it says
+    #[serde(default)]
+    pub reason: Option<RejectCode>,
+
+    /// Observer's signature over [`ObserverVote::signing_bytes`]
+    pub signature: Vec<u8>,
+}
+
+impl ObserverVote {
+    /// Build and sign an observer vote, with `reason` for a rejection
+    pub fn new(
+        keypair: &KeyPair,
+        action_id: ActionId,
+        round: u64,
+        decision: Decision,
+        reason: Option<RejectCode>,
+    ) -> Self {
+        let mut vote = Self {
+            action_id,
+            round,
+            observer: keypair.public_key(),
+            decision,
+            reason: reason.filter(|_| decision == Decision::Reject),
+            signature: Vec::new(),
+        };
+        vote.signature = keypair.sign(&vote.signing_bytes());
+        vote
+    }
+
+    /// Canonical bytes covered by the signature; a reason, when given,
+    /// follows the rest
+    pub fn signing_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(110);
+        bytes.extend_from_slice(b"swarmhost-observer-vote-v1");
+        bytes.extend_from_slice(&self.action_id);
+        bytes.extend_from_slice(&self.round.to_be_bytes());
+        bytes.extend_from_slice(&self.observer);
+        bytes.push(self.approves() as u8);
+        if let Some(RejectCode(code)) = self.reason {
+            bytes.extend_from_slice(&code.to_be_bytes());
+        }
+        bytes
+    }
+
+    pub fn approves(&self) -> bool {
+        self.decision == Decision::Approve
+    }
+
+    /// Check the signature against the observer's public key
+    pub fn verify(&self) -> Result<()> {
+        crypto::verify_signature(&self.observer, &self.signing_bytes(), &self.signature)
+    }
+}
+
+/// How many observers approved and rejected one action or block
+///
+/// Each observer counts once, whatever its weight would be as a validator;
+/// none of it counts toward the quorum.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct ObserverTally {
+    pub approvals: u32,
+    pub rejections: u32,
+}
+
+/// Observer votes on recent actions and blocks
+#[derive(Debug, Default)]
+pub struct Observed {
+    votes: HashMap<ActionId, Vec<ObserverVote>>,
+    /// What they judge, oldest first, to forget them in turn
+    order: VecDeque<ActionId>,
+}
+
+impl Observed {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Count an observer vote, returning the tally it leaves
+    ///
+    /// An observer counts once per action or block: a repeat is refused,
+    /// and so is one deciding otherwise, though that only proves the
+    /// observer misbehaved and costs it nothing here. The signature is
+    /// checked last.
+    pub fn record(&mut self, vote: ObserverVote) -> Result<ObserverTally> {
+        let votes = self.votes(&vote.action_id);
+        if votes.iter().any(|first| first.observer == vote.observer) {
+            return Err(SwarmhostError::validation(format!(
+                "Observer {} already voted on {}",
+                short_id(&vote.observer),
+                short_id(&vote.action_id)
+            )));
+        }
+        if votes.len() >= MAX_OBSERVERS {
+            return Err(SwarmhostError::validation(format!(
+                "{} observers already voted on {}",
+                MAX_OBSERVERS,
+                short_id(&vote.action_id)
+            )));
+        }
+        vote.verify()?;
+
+        let subject = vote.action_id;
+        if !self.votes.contains_key(&subject) {
+            if self.order.len() == OBSERVED_HISTORY
+                && let Some(oldest) = self.order.pop_front()
+            {
+                self.votes.remove(&oldest);
+            }
+            self.order.push_back(subject);
+        }
+        self.votes.entry(subject).or_default().push(vote);
+        Ok(self.tally(&subject))
+    }
+
+    /// Observer votes so far on an action or block
+    pub fn votes(&self, action_id: &ActionId) -> &[ObserverVote] {
+        self.votes
+            .get(action_id)
+            .map(Vec::as_slice)
+            .unwrap_or_default()
+    }
+
+    pub fn tally(&self, action_id: &ActionId) -> ObserverTally {
+        let votes = self.votes(action_id);
+        let approvals = votes.iter().filter(|vote| vote.approves()).count() as u32;
+        ObserverTally {
+            approvals,
+            rejections: votes.len() as u32 - approvals,
+        }
+    }
+
+    /// Tally of every action and block observers voted on that is still
+    /// remembered
+    pub fn tallies(&self) -> HashMap<ActionId, ObserverTally> {
+        self.votes
+            .keys()
+            .map(|action_id| (*action_id, self.tally(action_id)))
+            .collect()
+    }
+}
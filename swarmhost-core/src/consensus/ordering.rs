@@ -0,0 +1,219 @@
+// consensus/ordering.rs - How much ordering each kind of action needs, and
+// delivering those that need less than the log gives
+
+use super::action::{ActionId, SignedAction};
+use std::collections::{HashSet, VecDeque};
+
+/// Actions delivered outside the log remembered to drop copies of them
+const DELIVERED_HISTORY: usize = 4096;
+
+/// Actions held back for their dependencies; past this the oldest is
+/// dropped
+const MAX_WAITING: usize = 1024;
+
+/// The order an action type is delivered in
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum OrderingClass {
+    /// Agreed on by the validators and committed at a sequence of its
+    /// game's log, like every action is by default
+    #[default]
+    Strict,
+    /// Gossiped, and delivered once every action it depends on has been
+    /// delivered, or committed; concurrent ones arrive in any order
+    Causal,
+    /// Gossiped, and delivered as it arrives
+    Unordered,
+}
+
+/// Tells the ordering class of a game's action types
+///
+/// Set with
+/// [`SwarmhostNode::with_classifier`](crate::node::SwarmhostNode::with_classifier);
+/// every node of a game must classify its action types alike, or some
+/// commit what others deliver straight away. Any
+/// `Fn(u32) -> OrderingClass` is one.
+pub trait ActionClassifier: Send + Sync {
+    fn classify(&self, action_type: u32) -> OrderingClass;
+}
+
+impl<F> ActionClassifier for F
+where
+    F: Fn(u32) -> OrderingClass + Send + Sync,
+{
+    fn classify(&self, action_type: u32) -> OrderingClass {
+        self(action_type)
+    }
+}
+
+/// The default classifier: every action goes through consensus
+#[derive(Debug, Clone, Copy, Default)]
+pub struct AllStrict;
+
+impl ActionClassifier for AllStrict {
+    fn classify(&self, _action_type: u32) -> OrderingClass {
+        OrderingClass::Strict
+    }
+}
+
+/// Actions delivered without a sequence, and causal ones waiting for their
+/// dependencies
+#[derive(Debug, Default)]
+pub struct Unsequenced {
+    delivered: HashSet<ActionId>,
+    /// The same, oldest first, to forget them in turn
+    history: VecDeque<ActionId>,
+    /// Causal actions whose dependencies are not all delivered, oldest
+    /// first
+    waiting: VecDeque<SignedAction>,
+}
+
+impl Unsequenced {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Whether the action was delivered, or is waiting to be
+    pub fn contains(&self, action_id: &ActionId) -> bool {
+        self.delivered.contains(action_id)
+            || self.waiting.iter().any(|action| &action.id() == action_id)
+    }
+
+    pub fn is_delivered(&self, action_id: &ActionId) -> bool {
+        self.delivered.contains(action_id)
+    }
+
+    /// Take in an action, returning it with the waiting ones it lets go
+    /// when it may be delivered, in the order to deliver them
+    ///
+    /// `committed` tells whether an action was committed to the log, for
+    /// causal actions depending on one that was.
+    pub fn receive(
+        &mut self,
+        action: SignedAction,
+        class: OrderingClass,
+        committed: impl Fn(&ActionId) -> bool,
+    ) -> Vec<SignedAction> {
+        if self.contains(&action.id()) {
+            return Vec::new();
+        }
+        if class == OrderingClass::Causal && !self.ready(&action, &committed) {
+            if self.waiting.len() == MAX_WAITING {
+                self.waiting.pop_front();
+            }
+            self.waiting.push_back(action);
+            return Vec::new();
+        }
+        self.deliver(action.id());
+        let mut delivered = vec![action];
+        delivered.extend(self.release(committed));
+        delivered
+    }
+
+    /// Waiting actions whose dependencies have all been delivered since,
+    /// in the order to deliver them
+    pub fn release(&mut self, committed: impl Fn(&ActionId) -> bool) -> Vec<SignedAction> {
+        let mut released = Vec::new();
+        while let Some(at) = self
+            .waiting
+            .iter()
+            .position(|action| self.ready(action, &committed))
+        {
+            let action = self.waiting.remove(at).expect("position is in range");
+            self.deliver(action.id());
+            released.push(action);
+        }
+        released
+    }
+
+    fn ready(&self, action: &SignedAction, committed: &impl Fn(&ActionId) -> bool) -> bool {
+        action
+            .depends_on
+            .iter()
+            .all(|dependency| self.delivered.contains(dependency) || committed(dependency))
+    }
+
+    fn deliver(&mut self, action_id: ActionId) {
+        if self.history.len() == DELIVERED_HISTORY
+            && let Some(oldest) = self.history.pop_front()
+        {
+            self.delivered.remove(&oldest);
+        }
+        self.delivered.insert(action_id);
+        self.history.push_back(action_id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::KeyPair;
+
+    fn chat(keypair: &KeyPair, nonce: u64, depends_on: &[&SignedAction]) -> SignedAction {
+        let mut action = SignedAction::new(keypair, "game", nonce, 9, vec![nonce as u8]);
+        action.depends_on = depends_on.iter().map(|action| action.id()).collect();
+        action.sign(keypair);
+        action
+    }
+
+    fn ids(actions: &[SignedAction]) -> Vec<ActionId> {
+        actions.iter().map(SignedAction::id).collect()
+    }
+
+    #[test]
+    fn test_causal_actions_wait_for_every_dependency() {
+        let keypair = KeyPair::generate();
+        let question = chat(&keypair, 0, &[]);
+        let answer = chat(&keypair, 1, &[&question]);
+        let thanks = chat(&keypair, 2, &[&question, &answer]);
+        let mut unsequenced = Unsequenced::new();
+        let none = |_: &ActionId| false;
+
+        // Arriving last to first, each waits on the one before it
+        assert!(
+            unsequenced
+                .receive(thanks.clone(), OrderingClass::Causal, none)
+                .is_empty()
+        );
+        assert!(
+            unsequenced
+                .receive(answer.clone(), OrderingClass::Causal, none)
+                .is_empty()
+        );
+        assert!(unsequenced.contains(&thanks.id()));
+        let delivered = unsequenced.receive(question.clone(), OrderingClass::Causal, none);
+        assert_eq!(
+            ids(&delivered),
+            vec![question.id(), answer.id(), thanks.id()]
+        );
+
+        // Copies are dropped, however they are classed
+        assert!(
+            unsequenced
+                .receive(answer, OrderingClass::Unordered, none)
+                .is_empty()
+        );
+    }
+
+    #[test]
+    fn test_causal_action_on_a_committed_one_waits_for_its_commit() {
+        let keypair = KeyPair::generate();
+        let strike = SignedAction::new(&keypair, "game", 0, 1, vec![]);
+        let taunt = chat(&keypair, 1, &[&strike]);
+        let mut unsequenced = Unsequenced::new();
+
+        assert!(
+            unsequenced
+                .receive(taunt.clone(), OrderingClass::Causal, |_| false)
+                .is_empty()
+        );
+        assert!(unsequenced.release(|_| false).is_empty());
+        let released = unsequenced.release(|action_id| *action_id == strike.id());
+        assert_eq!(ids(&released), vec![taunt.id()]);
+        assert!(unsequenced.is_delivered(&taunt.id()));
+
+        // Unordered actions ignore what they depend on
+        let shrug = chat(&keypair, 2, &[&chat(&keypair, 3, &[])]);
+        let delivered = unsequenced.receive(shrug.clone(), OrderingClass::Unordered, |_| false);
+        assert_eq!(ids(&delivered), vec![shrug.id()]);
+    }
+}
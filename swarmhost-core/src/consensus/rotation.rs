@@ -0,0 +1,423 @@
+// consensus/rotation.rs - Validators taking turns to propose, and skipping a
+// proposer that stays silent
+
+use super::action::{ActionId, SignedAction};
+use super::membership::Membership;
+use crate::crypto::{self, Hash, KeyPair, PlayerId, short_id};
+use crate::error::{Result, SwarmhostError};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashSet};
+
+/// Most actions a proposer puts in one round; the rest wait for its next turn
+pub const MAX_ROUND_ACTIONS: usize = 64;
+
+/// Furthest ahead of our round a timeout vote is kept for
+pub const MAX_ROUNDS_AHEAD: u64 = 64;
+
+/// Rounds back whose blocks are kept, to certify their commits to peers
+/// catching up
+pub const BLOCK_HISTORY: u64 = 4096;
+
+/// The actions the proposer of `round` puts forward, signed by it
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RoundProposal {
+    pub round: u64,
+    pub proposer: PlayerId,
+    /// Block of the latest earlier round the proposer knew of, committed or
+    /// not; none if it knew of none
+    pub parent: Option<Hash>,
+    pub actions: Vec<SignedAction>,
+    /// Proposer's signature over [`RoundProposal::signing_bytes`]
+    pub signature: Vec<u8>,
+}
+
+impl RoundProposal {
+    /// Put `actions` forward in `round`, following the block `parent`, and
+    /// sign them
+    pub fn new(
+        keypair: &KeyPair,
+        round: u64,
+        parent: Option<Hash>,
+        actions: Vec<SignedAction>,
+    ) -> Self {
+        let mut proposal = Self {
+            round,
+            proposer: keypair.public_key(),
+            parent,
+            actions,
+            signature: Vec::new(),
+        };
+        proposal.signature = keypair.sign(&proposal.signing_bytes());
+        proposal
+    }
+
+    /// Canonical bytes covered by the signature
+    pub fn signing_bytes(&self) -> Vec<u8> {
+        round_bytes(
+            self.round,
+            &self.proposer,
+            self.parent.as_ref(),
+            self.action_ids(),
+        )
+    }
+
+    /// Hash of the signed bytes, which validators vote on to approve every
+    /// action of the round as one block
+    pub fn hash(&self) -> Hash {
+        crypto::hash(&self.signing_bytes())
+    }
+
+    /// The round without its actions, only their ids
+    pub fn header(&self) -> BlockHeader {
+        BlockHeader {
+            round: self.round,
+            proposer: self.proposer,
+            parent: self.parent,
+            actions: self.action_ids(),
+        }
+    }
+
+    /// Ids of the actions put forward, in order
+    pub fn action_ids(&self) -> Vec<ActionId> {
+        self.actions.iter().map(SignedAction::id).collect()
+    }
+
+    /// Check the proposer's signature and every actor's
+    pub fn verify(&self) -> Result<()> {
+        crypto::verify_signature(&self.proposer, &self.signing_bytes(), &self.signature)?;
+        self.actions.iter().try_for_each(SignedAction::verify)
+    }
+}
+
+/// What a block's hash covers: its round, proposer, parent and action ids,
+/// enough to tell which actions a vote on the block approved
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BlockHeader {
+    pub round: u64,
+    pub proposer: PlayerId,
+    pub parent: Option<Hash>,
+    pub actions: Vec<ActionId>,
+}
+
+impl BlockHeader {
+    /// Hash of the round the header is of, as [`RoundProposal::hash`]
+    pub fn hash(&self) -> Hash {
+        crypto::hash(&round_bytes(
+            self.round,
+            &self.proposer,
+            self.parent.as_ref(),
+            self.actions.iter().copied(),
+        ))
+    }
+}
+
+/// Bytes a round's proposer signs, and its block hash covers
+fn round_bytes(
+    round: u64,
+    proposer: &PlayerId,
+    parent: Option<&Hash>,
+    actions: impl IntoIterator<Item = ActionId>,
+) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(128);
+    bytes.extend_from_slice(b"swarmhost-round-v2");
+    bytes.extend_from_slice(&round.to_be_bytes());
+    bytes.extend_from_slice(proposer);
+    match parent {
+        Some(parent) => {
+            bytes.push(1);
+            bytes.extend_from_slice(parent);
+        }
+        None => bytes.push(0),
+    }
+    for action_id in actions {
+        bytes.extend_from_slice(&action_id);
+    }
+    bytes
+}
+
+/// A validator's statement that it gave up waiting on the proposer of
+/// `round`; a quorum of them skips the round
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TimeoutVote {
+    pub round: u64,
+    pub voter: PlayerId,
+    /// Voter's signature over [`TimeoutVote::signing_bytes`]
+    pub signature: Vec<u8>,
+}
+
+impl TimeoutVote {
+    /// Build and sign a vote to skip `round`
+    pub fn new(keypair: &KeyPair, round: u64) -> Self {
+        let mut vote = Self {
+            round,
+            voter: keypair.public_key(),
+            signature: Vec::new(),
+        };
+        vote.signature = keypair.sign(&vote.signing_bytes());
+        vote
+    }
+
+    /// Canonical bytes covered by the signature
+    pub fn signing_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(64);
+        bytes.extend_from_slice(b"swarmhost-timeout-v1");
+        bytes.extend_from_slice(&self.round.to_be_bytes());
+        bytes.extend_from_slice(&self.voter);
+        bytes
+    }
+
+    /// Check the signature against the voter's public key
+    pub fn verify(&self) -> Result<()> {
+        crypto::verify_signature(&self.voter, &self.signing_bytes(), &self.signature)
+    }
+}
+
+/// Whose turn it is to propose, and the votes so far to skip a turn
+///
+/// Validators take turns in order of id: the proposer of round `r` is the
+/// `r mod n`th, whatever their weights. A round ends when its proposal
+/// arrives, or when validators weighing a quorum vote to skip it.
+#[derive(Debug, Default)]
+pub struct Rotation {
+    validators: Membership,
+    order: Vec<PlayerId>,
+    round: u64,
+    /// Validators that voted to skip each round from ours on
+    timeouts: BTreeMap<u64, HashSet<PlayerId>>,
+}
+
+impl Rotation {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Replace the validators taking turns
+    ///
+    /// Timeout votes already counted from players no longer validating are
+    /// dropped.
+    pub fn set_validators(&mut self, validators: &Membership) {
+        self.validators = validators.clone();
+        self.order = validators.ids().copied().collect();
+        for voters in self.timeouts.values_mut() {
+            voters.retain(|voter| validators.contains(voter));
+        }
+    }
+
+    /// Validators in the order they take turns
+    pub fn order(&self) -> &[PlayerId] {
+        &self.order
+    }
+
+    /// Round in progress
+    pub fn round(&self) -> u64 {
+        self.round
+    }
+
+    /// Validator whose turn it is in `round`; none without validators
+    pub fn proposer(&self, round: u64) -> Option<PlayerId> {
+        let count = self.order.len() as u64;
+        (count > 0).then(|| self.order[(round % count) as usize])
+    }
+
+    /// Validator whose turn it is now
+    pub fn current(&self) -> Option<PlayerId> {
+        self.proposer(self.round)
+    }
+
+    /// End `round`, moving on to the next one; false if it already ended
+    pub fn finish(&mut self, round: u64) -> bool {
+        if round < self.round {
+            return false;
+        }
+        self.round = round + 1;
+        self.timeouts = self.timeouts.split_off(&self.round);
+        true
+    }
+
+    /// Whether `voter` already voted to skip `round`
+    pub fn voted(&self, round: u64, voter: &PlayerId) -> bool {
+        self.timeouts
+            .get(&round)
+            .is_some_and(|voters| voters.contains(voter))
+    }
+
+    /// Validators that voted to skip `round` so far
+    pub fn timeouts(&self, round: u64) -> usize {
+        self.timeouts.get(&round).map_or(0, HashSet::len)
+    }
+
+    /// Count a vote to skip a round, returning whether the voters so far
+    /// weigh `required` and so ended the round
+    ///
+    /// Votes from outside the validator set are refused as protocol
+    /// violations. Votes on rounds already over or too far ahead, and
+    /// repeats, are ignored. The signature is checked last.
+    pub fn record_timeout(&mut self, vote: &TimeoutVote, required: u64) -> Result<bool> {
+        if !self.validators.contains(&vote.voter) {
+            return Err(SwarmhostError::consensus(format!(
+                "{} is not a validator",
+                short_id(&vote.voter)
+            )));
+        }
+        if vote.round < self.round
+            || vote.round > self.round + MAX_ROUNDS_AHEAD
+            || self.voted(vote.round, &vote.voter)
+        {
+            return Ok(false);
+        }
+        vote.verify()?;
+
+        let voters = self.timeouts.entry(vote.round).or_default();
+        voters.insert(vote.voter);
+        if self.validators.weight(voters.iter()) < required {
+            return Ok(false);
+        }
+        Ok(self.finish(vote.round))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn validators(count: usize) -> Vec<KeyPair> {
+        let mut keys: Vec<KeyPair> = (0..count).map(|_| KeyPair::generate()).collect();
+        keys.sort_by_key(KeyPair::public_key);
+        keys
+    }
+
+    fn rotation(keys: &[KeyPair]) -> Rotation {
+        let mut rotation = Rotation::new();
+        rotation.set_validators(&Membership::equal(keys.iter().map(KeyPair::public_key)));
+        rotation
+    }
+
+    #[test]
+    fn test_validators_take_turns_in_id_order() {
+        let keys = validators(3);
+        let mut rotation = rotation(&keys);
+        for round in 0..7 {
+            assert_eq!(rotation.round(), round);
+            let expected = keys[round as usize % 3].public_key();
+            assert_eq!(rotation.current(), Some(expected));
+            assert!(rotation.finish(round));
+        }
+        // A proposal for a round already over ends nothing
+        assert!(!rotation.finish(3));
+        assert_eq!(rotation.round(), 7);
+        assert_eq!(Rotation::new().current(), None);
+    }
+
+    #[test]
+    fn test_quorum_of_timeouts_skips_the_round() {
+        let keys = validators(4);
+        let mut rotation = rotation(&keys);
+
+        assert!(
+            !rotation
+                .record_timeout(&TimeoutVote::new(&keys[1], 0), 3)
+                .unwrap()
+        );
+        // Repeats count once
+        assert!(
+            !rotation
+                .record_timeout(&TimeoutVote::new(&keys[1], 0), 3)
+                .unwrap()
+        );
+        assert!(
+            !rotation
+                .record_timeout(&TimeoutVote::new(&keys[2], 0), 3)
+                .unwrap()
+        );
+        assert_eq!(rotation.timeouts(0), 2);
+        assert!(
+            rotation
+                .record_timeout(&TimeoutVote::new(&keys[3], 0), 3)
+                .unwrap()
+        );
+        assert_eq!(rotation.round(), 1);
+        assert_eq!(rotation.current(), Some(keys[1].public_key()));
+
+        // Late votes on the skipped round are ignored
+        assert!(
+            !rotation
+                .record_timeout(&TimeoutVote::new(&keys[0], 0), 3)
+                .unwrap()
+        );
+        assert_eq!(rotation.timeouts(0), 0);
+    }
+
+    #[test]
+    fn test_timeouts_count_at_their_voters_weight() {
+        let keys = validators(3);
+        let mut rotation = Rotation::new();
+        let weights = [4, 1, 1];
+        rotation.set_validators(&Membership::weighted(
+            keys.iter().map(KeyPair::public_key).zip(weights),
+        ));
+        // Turns still go round in id order
+        assert_eq!(rotation.order().len(), 3);
+        assert_eq!(rotation.proposer(1), Some(keys[1].public_key()));
+
+        // 4 of 6 needed: both light validators make 2
+        for key in &keys[1..] {
+            assert!(
+                !rotation
+                    .record_timeout(&TimeoutVote::new(key, 0), 4)
+                    .unwrap()
+            );
+        }
+        assert!(
+            rotation
+                .record_timeout(&TimeoutVote::new(&keys[0], 0), 4)
+                .unwrap()
+        );
+        // The heavy validator alone skips the next round
+        assert!(
+            rotation
+                .record_timeout(&TimeoutVote::new(&keys[0], 1), 4)
+                .unwrap()
+        );
+        assert_eq!(rotation.round(), 2);
+    }
+
+    #[test]
+    fn test_bad_timeout_votes_refused() {
+        let keys = validators(2);
+        let mut rotation = rotation(&keys);
+
+        let outsider = TimeoutVote::new(&KeyPair::generate(), 0);
+        assert!(matches!(
+            rotation.record_timeout(&outsider, 1),
+            Err(SwarmhostError::Consensus(_))
+        ));
+
+        let mut replayed = TimeoutVote::new(&keys[0], 0);
+        replayed.round = 1;
+        assert!(rotation.record_timeout(&replayed, 1).is_err());
+        assert_eq!(rotation.round(), 0);
+    }
+
+    #[test]
+    fn test_tampered_round_proposal_fails_verification() {
+        let keys = validators(1);
+        let action = SignedAction::new(&keys[0], "game", 0, 1, vec![1]);
+        let proposal = RoundProposal::new(&keys[0], 3, None, vec![action]);
+        assert!(proposal.verify().is_ok());
+
+        let mut dropped = proposal.clone();
+        dropped.actions.clear();
+        assert!(dropped.verify().is_err());
+
+        // Moving the block onto another parent breaks the signature
+        let mut reparented = proposal.clone();
+        reparented.parent = Some([9; 32]);
+        assert!(reparented.verify().is_err());
+        assert_ne!(reparented.header().hash(), proposal.hash());
+        assert_eq!(proposal.header().hash(), proposal.hash());
+
+        let mut forged = proposal;
+        forged.actions[0].data = vec![2];
+        assert!(forged.verify().is_err());
+    }
+}
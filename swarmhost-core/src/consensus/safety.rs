@@ -0,0 +1,71 @@
+// consensus/safety.rs - What a validator writes down before speaking, so
+// that after a crash it never contradicts what it said before
+
+use super::vote::Vote;
+use crate::crypto::Hash;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
+
+/// Our own votes remembered; past this the oldest is forgotten, its action
+/// or block long settled and its votes expired everywhere
+const CAST_HISTORY: usize = 256;
+
+/// The least consensus state a validator must get back after a crash to
+/// stay true to what it sent before it
+///
+/// It is written ahead of every vote, proposal and view change, so what
+/// peers may have seen from us is never newer than what is on disk.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SafetyRecord {
+    /// Round in progress; we proposed in none from here on
+    pub round: u64,
+    /// View in progress
+    pub view: u64,
+    /// Highest view we asked to move to
+    pub requested_view: u64,
+    /// Our latest votes, oldest first; approvals of blocks among them are
+    /// the blocks we prepared
+    pub votes: Vec<Vote>,
+}
+
+/// Votes we cast, by the action or block they decide
+#[derive(Debug, Default)]
+pub struct Cast {
+    votes: HashMap<Hash, Vote>,
+    /// What they decide, oldest first, to forget them in turn
+    order: VecDeque<Hash>,
+}
+
+impl Cast {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Our vote on an action or block, if we cast one
+    pub fn get(&self, subject: &Hash) -> Option<&Vote> {
+        self.votes.get(subject)
+    }
+
+    /// Remember a vote we cast
+    pub fn insert(&mut self, vote: Vote) {
+        let subject = vote.action_id;
+        if self.votes.insert(subject, vote).is_some() {
+            return;
+        }
+        if self.order.len() == CAST_HISTORY
+            && let Some(oldest) = self.order.pop_front()
+        {
+            self.votes.remove(&oldest);
+        }
+        self.order.push_back(subject);
+    }
+
+    /// Every vote remembered, oldest first
+    pub fn to_vec(&self) -> Vec<Vote> {
+        self.order
+            .iter()
+            .filter_map(|subject| self.votes.get(subject))
+            .cloned()
+            .collect()
+    }
+}
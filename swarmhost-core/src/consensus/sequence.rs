@@ -0,0 +1,467 @@
+// consensus/sequence.rs - Total order of committed actions within a game
+
+use super::action::{ActionId, SignedAction};
+use super::beacon::Randomness;
+use super::vote::Vote;
+use crate::crypto::{self, KeyPair, PlayerId};
+use crate::error::Result;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashSet, VecDeque};
+use std::time::Duration;
+use tokio::time::Instant;
+
+/// Sequence number of the first commit in every game
+pub const FIRST_SEQUENCE: u64 = 1;
+
+/// Delivered commits kept per game to answer peers filling a gap
+pub const HISTORY: usize = 4096;
+
+/// Most commits sent in answer to one fetch
+pub const MAX_FETCH: u32 = 64;
+
+/// An approved action and its place in its game's order, signed by the
+/// validator that assigned it
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Commit {
+    pub sequence: u64,
+    pub action: SignedAction,
+    /// Validator that assigned the sequence number
+    pub sequencer: PlayerId,
+    /// Sequencer's signature over [`Commit::signing_bytes`]
+    pub signature: Vec<u8>,
+    /// Approvals the action was numbered on, its own or its block's, that
+    /// its round's randomness is drawn from
+    #[serde(default)]
+    pub certificate: Vec<Vote>,
+}
+
+impl Commit {
+    /// Place `action` at `sequence` and sign it
+    pub fn new(keypair: &KeyPair, sequence: u64, action: SignedAction) -> Self {
+        Self::certified(keypair, sequence, action, Vec::new())
+    }
+
+    /// Place `action` at `sequence` on the approvals in `certificate`, and
+    /// sign both
+    pub fn certified(
+        keypair: &KeyPair,
+        sequence: u64,
+        action: SignedAction,
+        certificate: Vec<Vote>,
+    ) -> Self {
+        let mut commit = Self {
+            sequence,
+            action,
+            sequencer: keypair.public_key(),
+            signature: Vec::new(),
+            certificate,
+        };
+        commit.signature = keypair.sign(&commit.signing_bytes());
+        commit
+    }
+
+    /// Canonical bytes covered by the signature; the randomness of a
+    /// certificate, when there is one, follows the rest
+    pub fn signing_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(128);
+        bytes.extend_from_slice(b"swarmhost-commit-v1");
+        bytes.extend_from_slice(&self.sequence.to_be_bytes());
+        bytes.extend_from_slice(&self.action.id());
+        bytes.extend_from_slice(&self.sequencer);
+        if let Some(randomness) = self.randomness() {
+            bytes.extend_from_slice(&randomness.0);
+        }
+        bytes
+    }
+
+    /// The randomness of the round the action was committed in, drawn
+    /// from its certificate; none for a commit made without one
+    pub fn randomness(&self) -> Option<Randomness> {
+        (!self.certificate.is_empty()).then(|| Randomness::of(&self.certificate))
+    }
+
+    /// Check the sequencer's signature and the actor's
+    pub fn verify(&self) -> Result<()> {
+        crypto::verify_signature(&self.sequencer, &self.signing_bytes(), &self.signature)?;
+        self.action.verify()
+    }
+}
+
+/// One game's commits: the numbers handed out, and those delivered in order
+///
+/// Commits may arrive in any order; each is held until every commit before
+/// it has been delivered.
+#[derive(Debug)]
+pub struct CommitLog {
+    next_assign: u64,
+    next_deliver: u64,
+    waiting: BTreeMap<u64, Commit>,
+    /// Recently delivered commits, oldest first
+    history: VecDeque<Commit>,
+    /// Actions given a number, whether by us or another sequencer
+    sequenced: HashSet<ActionId>,
+    /// When the oldest gap before a waiting commit was noticed
+    gap_since: Option<Instant>,
+}
+
+impl Default for CommitLog {
+    fn default() -> Self {
+        Self {
+            next_assign: FIRST_SEQUENCE,
+            next_deliver: FIRST_SEQUENCE,
+            waiting: BTreeMap::new(),
+            history: VecDeque::new(),
+            sequenced: HashSet::new(),
+            gap_since: None,
+        }
+    }
+}
+
+impl CommitLog {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Give `action` the next sequence number on the approvals in
+    /// `certificate`, unless it already has one
+    pub fn assign(
+        &mut self,
+        keypair: &KeyPair,
+        action: SignedAction,
+        certificate: Vec<Vote>,
+    ) -> Option<Commit> {
+        if !self.sequenced.insert(action.id()) {
+            return None;
+        }
+        let commit = Commit::certified(keypair, self.next_assign, action, certificate);
+        self.next_assign += 1;
+        Some(commit)
+    }
+
+    /// Take in a commit, returning those now deliverable in order
+    ///
+    /// Commits already delivered or waiting are ignored, as is a second
+    /// number for an action that has one.
+    pub fn insert(&mut self, commit: Commit, now: Instant) -> Vec<Commit> {
+        let sequence = commit.sequence;
+        if sequence < self.next_deliver || self.waiting.contains_key(&sequence) {
+            return Vec::new();
+        }
+        let action_id = commit.action.id();
+        if self.sequenced.contains(&action_id) && !self.assigned_here(&commit) {
+            return Vec::new();
+        }
+        self.sequenced.insert(action_id);
+        self.next_assign = self.next_assign.max(sequence + 1);
+        self.waiting.insert(sequence, commit);
+        self.deliver(now)
+    }
+
+    /// Go on from `next` with every commit before it delivered elsewhere,
+    /// as when restoring a snapshot, returning those now deliverable
+    ///
+    /// Nothing happens when `next` is not past the next commit to deliver.
+    pub fn skip_to(&mut self, next: u64, now: Instant) -> Vec<Commit> {
+        if next <= self.next_deliver {
+            return Vec::new();
+        }
+        self.waiting = self.waiting.split_off(&next);
+        // History is kept without gaps
+        self.history.clear();
+        self.next_deliver = next;
+        self.next_assign = self.next_assign.max(next);
+        self.deliver(now)
+    }
+
+    /// Forget the commits from `next` on, delivered or waiting, to take up
+    /// another branch of the log from there, returning those delivered
+    ///
+    /// Nothing happens unless history reaches back to `next`.
+    pub fn rewind(&mut self, next: u64) -> Vec<Commit> {
+        if self.delivered_at(next).is_none() {
+            return Vec::new();
+        }
+        let kept = (next - self.history[0].sequence) as usize;
+        let rewound: Vec<Commit> = self.history.drain(kept..).collect();
+        for commit in rewound.iter().chain(self.waiting.values()) {
+            self.sequenced.remove(&commit.action.id());
+        }
+        self.waiting.clear();
+        self.gap_since = None;
+        self.next_deliver = next;
+        self.next_assign = next;
+        rewound
+    }
+
+    /// Deliver waiting commits that follow on from those delivered
+    fn deliver(&mut self, now: Instant) -> Vec<Commit> {
+        let mut ready = Vec::new();
+        while let Some(commit) = self.waiting.remove(&self.next_deliver) {
+            self.next_deliver += 1;
+            if self.history.len() == HISTORY {
+                self.history.pop_front();
+            }
+            self.history.push_back(commit.clone());
+            ready.push(commit);
+        }
+
+        self.gap_since = match (self.waiting.is_empty(), ready.is_empty()) {
+            (true, _) => None,
+            (false, true) => self.gap_since.or(Some(now)),
+            (false, false) => Some(now),
+        };
+        ready
+    }
+
+    /// Whether `commit` is the one we numbered its action with ourselves,
+    /// on its way back to us
+    fn assigned_here(&self, commit: &Commit) -> bool {
+        commit.sequence < self.next_assign
+            && !self
+                .history
+                .iter()
+                .chain(self.waiting.values())
+                .any(|seen| seen.action.id() == commit.action.id())
+    }
+
+    /// The missing range before the first waiting commit, as its first
+    /// sequence and length, once it has been open for `timeout`
+    ///
+    /// Reported again only after another `timeout`, so a fetch in flight is
+    /// not repeated straight away.
+    pub fn gap(&mut self, now: Instant, timeout: Duration) -> Option<(u64, u32)> {
+        let since = self.gap_since?;
+        if now.duration_since(since) < timeout {
+            return None;
+        }
+        let (&first_waiting, _) = self.waiting.first_key_value()?;
+        self.gap_since = Some(now);
+        let missing = (first_waiting - self.next_deliver).min(MAX_FETCH as u64);
+        Some((self.next_deliver, missing as u32))
+    }
+
+    /// Up to `count` delivered commits from `from` on, as far as history
+    /// reaches
+    pub fn delivered(&self, from: u64, count: u32) -> Vec<Commit> {
+        self.history
+            .iter()
+            .skip_while(|commit| commit.sequence < from)
+            .take(count.min(MAX_FETCH) as usize)
+            .cloned()
+            .collect()
+    }
+
+    /// Sequence number the next delivered commit will have
+    pub fn next_deliver(&self) -> u64 {
+        self.next_deliver
+    }
+
+    /// Whether `action_id` was given a number and delivered
+    pub fn is_delivered(&self, action_id: &ActionId) -> bool {
+        self.sequenced.contains(action_id) && self.waiting_commit(action_id).is_none()
+    }
+
+    /// Whether `action_id` was given a number, delivered or not
+    pub fn is_sequenced(&self, action_id: &ActionId) -> bool {
+        self.sequenced.contains(action_id)
+    }
+
+    /// A commit we hold from `commit`'s sequencer at its sequence, but for
+    /// another action
+    pub fn conflicting(&self, commit: &Commit) -> Option<&Commit> {
+        self.waiting
+            .get(&commit.sequence)
+            .into_iter()
+            .chain(self.delivered_at(commit.sequence))
+            .find(|held| {
+                held.sequencer == commit.sequencer && held.action.id() != commit.action.id()
+            })
+    }
+
+    /// The commit delivered at `sequence`, as far as history reaches
+    pub fn delivered_at(&self, sequence: u64) -> Option<&Commit> {
+        let oldest = self.history.front()?;
+        let index = sequence.checked_sub(oldest.sequence)?;
+        self.history.get(usize::try_from(index).ok()?)
+    }
+
+    /// Sequence `action_id` was delivered at, as far as history reaches
+    pub fn delivered_sequence(&self, action_id: &ActionId) -> Option<u64> {
+        self.history
+            .iter()
+            .find(|commit| &commit.action.id() == action_id)
+            .map(|commit| commit.sequence)
+    }
+
+    /// The commit of `action_id`, if it is waiting on a gap
+    pub fn waiting_commit(&self, action_id: &ActionId) -> Option<&Commit> {
+        self.waiting
+            .values()
+            .find(|commit| &commit.action.id() == action_id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::consensus::Decision;
+
+    fn commits(keypair: &KeyPair, count: u64) -> Vec<Commit> {
+        let mut log = CommitLog::new();
+        (0..count)
+            .map(|nonce| {
+                let action = SignedAction::new(keypair, "game", nonce, 1, vec![]);
+                log.assign(keypair, action, Vec::new()).unwrap()
+            })
+            .collect()
+    }
+
+    fn sequences(commits: &[Commit]) -> Vec<u64> {
+        commits.iter().map(|commit| commit.sequence).collect()
+    }
+
+    #[test]
+    fn test_certificate_is_signed_into_the_commit() {
+        let keypair = KeyPair::generate();
+        let action = SignedAction::new(&keypair, "game", 0, 1, vec![]);
+        let approve = |voter: &KeyPair| Vote::new(voter, action.id(), 0, Decision::Approve);
+        let commit = Commit::certified(&keypair, 1, action.clone(), vec![approve(&keypair)]);
+        assert!(commit.verify().is_ok());
+        assert!(commit.randomness().is_some());
+        assert!(
+            Commit::new(&keypair, 1, action.clone())
+                .randomness()
+                .is_none()
+        );
+
+        // Swapping or dropping the certificate on the way changes the
+        // randomness, and breaks the signature
+        let mut swapped = commit.clone();
+        swapped.certificate = vec![approve(&KeyPair::generate())];
+        assert_ne!(swapped.randomness(), commit.randomness());
+        assert!(swapped.verify().is_err());
+        let mut stripped = commit;
+        stripped.certificate.clear();
+        assert!(stripped.verify().is_err());
+    }
+
+    #[test]
+    fn test_assigns_increasing_numbers_once_per_action() {
+        let keypair = KeyPair::generate();
+        let mut log = CommitLog::new();
+        let action = SignedAction::new(&keypair, "game", 0, 1, vec![]);
+
+        let first = log.assign(&keypair, action.clone(), Vec::new()).unwrap();
+        assert_eq!(first.sequence, FIRST_SEQUENCE);
+        assert!(first.verify().is_ok());
+        assert!(log.assign(&keypair, action, Vec::new()).is_none());
+
+        let second = SignedAction::new(&keypair, "game", 1, 1, vec![]);
+        assert_eq!(
+            log.assign(&keypair, second, Vec::new()).unwrap().sequence,
+            2
+        );
+
+        // Our own commits come back to us and are delivered
+        assert_eq!(sequences(&log.insert(first, Instant::now())), vec![1]);
+    }
+
+    #[test]
+    fn test_skipping_ahead_delivers_what_follows() {
+        let keypair = KeyPair::generate();
+        let now = Instant::now();
+        let all = commits(&keypair, 5);
+        let mut log = CommitLog::new();
+        assert!(log.insert(all[1].clone(), now).is_empty());
+        assert!(log.insert(all[4].clone(), now).is_empty());
+
+        // Up to the third is in a snapshot: the fourth is still missing
+        assert!(log.skip_to(4, now).is_empty());
+        assert_eq!(sequences(&log.insert(all[3].clone(), now)), vec![4, 5]);
+        assert!(log.skip_to(3, now).is_empty());
+        assert_eq!(log.next_deliver(), 6);
+    }
+
+    #[test]
+    fn test_rewinding_takes_up_another_branch() {
+        let keypair = KeyPair::generate();
+        let all = commits(&keypair, 4);
+        let mut log = CommitLog::new();
+        let now = Instant::now();
+        for commit in &all {
+            log.insert(commit.clone(), now);
+        }
+        assert!(log.rewind(9).is_empty());
+
+        assert_eq!(sequences(&log.rewind(2)), vec![2, 3, 4]);
+        assert_eq!(log.next_deliver(), 2);
+        assert!(!log.is_sequenced(&all[1].action.id()));
+        // The other branch puts another action at 2
+        let other = SignedAction::new(&keypair, "game", 9, 1, vec![]);
+        let branch = Commit::new(&keypair, 2, other);
+        assert_eq!(sequences(&log.insert(branch.clone(), now)), vec![2]);
+        assert_eq!(log.delivered_at(2), Some(&branch));
+    }
+
+    #[test]
+    fn test_tampered_commit_fails_verification() {
+        let keypair = KeyPair::generate();
+        let mut commit = commits(&keypair, 1).remove(0);
+        commit.sequence = 7;
+        assert!(commit.verify().is_err());
+    }
+
+    #[test]
+    fn test_out_of_order_commits_wait_for_the_gap() {
+        let keypair = KeyPair::generate();
+        let all = commits(&keypair, 4);
+        let mut log = CommitLog::new();
+        let now = Instant::now();
+
+        assert!(log.insert(all[2].clone(), now).is_empty());
+        assert!(log.insert(all[1].clone(), now).is_empty());
+        assert_eq!(sequences(&log.insert(all[0].clone(), now)), vec![1, 2, 3]);
+        assert_eq!(sequences(&log.insert(all[3].clone(), now)), vec![4]);
+
+        // Repeats of delivered commits are ignored
+        assert!(log.insert(all[1].clone(), now).is_empty());
+        assert_eq!(log.next_deliver(), 5);
+        assert_eq!(sequences(&log.delivered(2, 2)), vec![2, 3]);
+    }
+
+    #[test]
+    fn test_second_number_for_an_action_ignored() {
+        let keypair = KeyPair::generate();
+        let all = commits(&keypair, 1);
+        let mut log = CommitLog::new();
+        let now = Instant::now();
+        log.insert(all[0].clone(), now);
+
+        let again = Commit::new(&keypair, 2, all[0].action.clone());
+        assert!(log.insert(again, now).is_empty());
+        assert_eq!(log.next_deliver(), 2);
+    }
+
+    #[test]
+    fn test_gap_reported_after_timeout() {
+        let keypair = KeyPair::generate();
+        let all = commits(&keypair, 5);
+        let mut log = CommitLog::new();
+        let timeout = Duration::from_secs(2);
+        let start = Instant::now();
+
+        log.insert(all[0].clone(), start);
+        log.insert(all[3].clone(), start);
+        assert_eq!(log.gap(start + Duration::from_secs(1), timeout), None);
+        let later = start + timeout;
+        assert_eq!(log.gap(later, timeout), Some((2, 2)));
+        // Not again until another timeout has passed
+        assert_eq!(log.gap(later + Duration::from_secs(1), timeout), None);
+        assert_eq!(log.gap(later + timeout, timeout), Some((2, 2)));
+
+        log.insert(all[1].clone(), later);
+        log.insert(all[2].clone(), later);
+        assert_eq!(log.gap(later + timeout * 2, timeout), None);
+        assert_eq!(log.next_deliver(), 5);
+    }
+}
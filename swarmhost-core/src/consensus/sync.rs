@@ -0,0 +1,124 @@
+// consensus/sync.rs - Commits with the quorum certificates behind them, for
+// a node catching up on a game
+
+use super::membership::Membership;
+use super::rotation::BlockHeader;
+use super::sequence::Commit;
+use super::vote::Vote;
+use crate::crypto::{Hash, PlayerId, short_id};
+use crate::error::{Result, SwarmhostError};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+
+/// Delivered commits of a game, in order, with a quorum's approval of each:
+/// votes on its action, or on a block it went out in
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CertifiedCommits {
+    pub commits: Vec<Commit>,
+    /// Blocks the approvals are for, each once
+    pub blocks: Vec<BlockHeader>,
+    /// Approvals of the actions and blocks
+    pub votes: Vec<Vote>,
+}
+
+impl CertifiedCommits {
+    /// Check every signature, and that members of `validators` weighing
+    /// `required` approved each commit's action, on its own or with its
+    /// block
+    pub fn verify(&self, validators: &Membership, required: u64) -> Result<()> {
+        let mut approvers: HashMap<Hash, HashSet<PlayerId>> = HashMap::new();
+        for vote in &self.votes {
+            if !vote.approves() || !validators.contains(&vote.voter) {
+                return Err(SwarmhostError::consensus(format!(
+                    "vote by {} is not a validator's approval",
+                    short_id(&vote.voter)
+                )));
+            }
+            vote.verify()?;
+            approvers
+                .entry(vote.action_id)
+                .or_default()
+                .insert(vote.voter);
+        }
+        let approved = |id: &Hash| {
+            approvers
+                .get(id)
+                .is_some_and(|voters| validators.weight(voters) >= required)
+        };
+        let blocks: Vec<(Hash, &BlockHeader)> = self
+            .blocks
+            .iter()
+            .map(|header| (header.hash(), header))
+            .collect();
+
+        for commit in &self.commits {
+            if !validators.contains(&commit.sequencer) {
+                return Err(SwarmhostError::consensus(format!(
+                    "commit at {} by {}, not a validator",
+                    commit.sequence,
+                    short_id(&commit.sequencer)
+                )));
+            }
+            commit.verify()?;
+            let action_id = commit.action.id();
+            let certified = approved(&action_id)
+                || blocks
+                    .iter()
+                    .any(|(block, header)| header.actions.contains(&action_id) && approved(block));
+            if !certified {
+                return Err(SwarmhostError::consensus(format!(
+                    "commit at {} lacks a quorum's approval",
+                    commit.sequence
+                )));
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::consensus::{Decision, RoundProposal, SignedAction};
+    use crate::crypto::KeyPair;
+
+    #[test]
+    fn test_commits_need_a_quorum_on_them_or_their_block() {
+        let validators = [KeyPair::generate(), KeyPair::generate()];
+        let ids = Membership::equal(validators.iter().map(KeyPair::public_key));
+        let [sequencer, other] = &validators;
+        let actions: Vec<SignedAction> = (0..2)
+            .map(|nonce| SignedAction::new(sequencer, "game", nonce, 1, vec![]))
+            .collect();
+        let block = RoundProposal::new(sequencer, 0, None, actions.clone()).header();
+        let approve = |id| {
+            validators
+                .iter()
+                .map(|keypair| Vote::new(keypair, id, 0, Decision::Approve))
+                .collect::<Vec<_>>()
+        };
+        let commits: Vec<Commit> = actions
+            .iter()
+            .enumerate()
+            .map(|(i, action)| Commit::new(sequencer, i as u64 + 1, action.clone()))
+            .collect();
+
+        // The first approved on its own, the second with its block
+        let mut certified = CertifiedCommits {
+            commits: commits.clone(),
+            blocks: vec![block.clone()],
+            votes: [approve(actions[0].id()), approve(block.hash())].concat(),
+        };
+        assert!(certified.verify(&ids, 2).is_ok());
+        assert!(certified.verify(&ids, 3).is_err());
+
+        // Without the block the second is not certified
+        certified.blocks.clear();
+        assert!(certified.verify(&ids, 2).is_err());
+
+        // Nor with a rejection in place of an approval
+        certified.blocks.push(block.clone());
+        certified.votes[3] = Vote::new(other, block.hash(), 0, Decision::Reject);
+        assert!(certified.verify(&ids, 2).is_err());
+    }
+}
@@ -0,0 +1,372 @@
+// consensus/tally.rs - Counting validators' votes towards a quorum
+
+use super::action::ActionId;
+use super::membership::Membership;
+use super::vote::{Decision, Vote};
+use crate::crypto::short_id;
+use crate::error::{Result, SwarmhostError};
+use std::collections::HashMap;
+
+/// Where the votes on one action stand, by weight
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Tally {
+    pub approvals: u64,
+    pub rejections: u64,
+    /// Combined weight of the validators entitled to vote
+    pub validators: u64,
+    /// Approving weight needed to accept the action
+    pub required: u64,
+}
+
+/// What the votes so far settle
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Outcome {
+    /// Neither side can be ruled out yet
+    Pending,
+    /// A quorum approved
+    Approved,
+    /// Too many rejected for a quorum to approve, even if everyone left does
+    Rejected,
+}
+
+impl Tally {
+    /// Whether every validator, by weight, approved
+    pub fn is_unanimous(&self) -> bool {
+        self.validators > 0 && self.approvals >= self.validators
+    }
+
+    pub fn outcome(&self) -> Outcome {
+        if self.validators == 0 {
+            Outcome::Pending
+        } else if self.approvals >= self.required {
+            Outcome::Approved
+        } else if self.validators.saturating_sub(self.rejections) < self.required {
+            Outcome::Rejected
+        } else {
+            Outcome::Pending
+        }
+    }
+}
+
+/// Votes on each action, counted once per validator at its weight
+#[derive(Debug, Default)]
+pub struct VoteTracker {
+    validators: Membership,
+    required: u64,
+    votes: HashMap<ActionId, Vec<Vote>>,
+}
+
+impl VoteTracker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Replace the validator set; approvals weighing `required` accept an
+    /// action
+    ///
+    /// Votes already counted from players no longer validating stop
+    /// counting, and the rest count at their new weights.
+    pub fn set_validators(&mut self, validators: Membership, required: u64) {
+        self.validators = validators;
+        self.required = required;
+    }
+
+    pub fn validators(&self) -> &Membership {
+        &self.validators
+    }
+
+    /// Weight of the approvals that accept an action
+    pub fn required(&self) -> u64 {
+        self.required
+    }
+
+    /// Count a vote, returning the outcome if this vote settled it
+    ///
+    /// Votes from outside the validator set are refused as protocol
+    /// violations, and a validator's second vote on an action is refused
+    /// whatever it says, so a vote arriving twice or after the outcome is
+    /// settled still counts once. The signature is checked last.
+    pub fn record(&mut self, vote: Vote) -> Result<Option<Outcome>> {
+        if !self.validators.contains(&vote.voter) {
+            return Err(SwarmhostError::consensus(format!(
+                "{} is not a validator",
+                short_id(&vote.voter)
+            )));
+        }
+        if self
+            .votes(&vote.action_id)
+            .iter()
+            .any(|v| v.voter == vote.voter)
+        {
+            return Err(SwarmhostError::validation(format!(
+                "{} already voted on this action",
+                short_id(&vote.voter)
+            )));
+        }
+        vote.verify()?;
+
+        let before = self.tally(&vote.action_id).outcome();
+        let action_id = vote.action_id;
+        self.votes.entry(action_id).or_default().push(vote);
+        let after = self.tally(&action_id).outcome();
+        Ok((after != before).then_some(after))
+    }
+
+    /// Votes received so far for an action
+    pub fn votes(&self, action_id: &ActionId) -> &[Vote] {
+        self.votes
+            .get(action_id)
+            .map(Vec::as_slice)
+            .unwrap_or_default()
+    }
+
+    /// Current validators' votes on an action
+    pub fn tally(&self, action_id: &ActionId) -> Tally {
+        let mut tally = Tally {
+            validators: self.validators.total_weight(),
+            required: self.required,
+            ..Default::default()
+        };
+        for vote in self.votes(action_id) {
+            let weight = self.validators.weight_of(&vote.voter);
+            match vote.decision {
+                Decision::Approve => tally.approvals += weight,
+                Decision::Reject => tally.rejections += weight,
+            }
+        }
+        tally
+    }
+
+    /// Drop the votes on an action or block that is no longer kept
+    pub fn forget(&mut self, action_id: &ActionId) {
+        self.votes.remove(action_id);
+    }
+
+    /// Tallies of every action voted on
+    pub fn tallies(&self) -> HashMap<ActionId, Tally> {
+        self.votes
+            .keys()
+            .map(|action_id| (*action_id, self.tally(action_id)))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::KeyPair;
+    use crate::node::ConsensusConfig;
+
+    const ACTION: ActionId = [7; 32];
+
+    /// `count` validators needing the default two-thirds quorum
+    fn tracker(count: usize) -> (VoteTracker, Vec<KeyPair>) {
+        weighted(&vec![1; count])
+    }
+
+    /// Validators of the given weights needing the default two-thirds
+    /// quorum of their total
+    fn weighted(weights: &[u64]) -> (VoteTracker, Vec<KeyPair>) {
+        let keys: Vec<KeyPair> = weights.iter().map(|_| KeyPair::generate()).collect();
+        let membership = Membership::weighted(
+            keys.iter()
+                .map(KeyPair::public_key)
+                .zip(weights.iter().copied()),
+        );
+        let mut tracker = VoteTracker::new();
+        let required = ConsensusConfig::default().required_weight(membership.total_weight());
+        tracker.set_validators(membership, required);
+        (tracker, keys)
+    }
+
+    /// Record one vote per key in order, returning the outcome after each
+    fn cast(tracker: &mut VoteTracker, votes: &[(&KeyPair, Decision)]) -> Vec<Option<Outcome>> {
+        votes
+            .iter()
+            .map(|(key, decision)| {
+                tracker
+                    .record(Vote::new(key, ACTION, 0, *decision))
+                    .unwrap()
+            })
+            .collect()
+    }
+
+    /// Approvals needed, and rejections that make approval impossible, when
+    /// validators vote in order
+    fn thresholds(count: usize) -> (usize, usize) {
+        weighted_thresholds(&vec![1; count])
+    }
+
+    fn weighted_thresholds(weights: &[u64]) -> (usize, usize) {
+        let settled_at = |decision, expected| {
+            let (mut votes, keys) = weighted(weights);
+            let ballots: Vec<_> = keys.iter().map(|key| (key, decision)).collect();
+            let outcomes = cast(&mut votes, &ballots);
+            let at = outcomes.iter().position(Option::is_some).unwrap();
+            assert_eq!(outcomes[at], Some(expected));
+            at + 1
+        };
+        (
+            settled_at(Decision::Approve, Outcome::Approved),
+            settled_at(Decision::Reject, Outcome::Rejected),
+        )
+    }
+
+    #[test]
+    fn test_two_thirds_thresholds() {
+        // ceil(2/3 * n) approvals; rejected once n - rejections falls short
+        assert_eq!(thresholds(4), (3, 2));
+        assert_eq!(thresholds(5), (4, 2));
+        assert_eq!(thresholds(6), (4, 3));
+        assert_eq!(thresholds(7), (5, 3));
+        assert_eq!(thresholds(1), (1, 1));
+    }
+
+    #[test]
+    fn test_weighted_thresholds() {
+        // A server weighing 3 and two clients: 4 of 5 needs the server and
+        // a client, and the server alone can sink an action
+        assert_eq!(weighted_thresholds(&[3, 1, 1]), (2, 1));
+        // The clients go first: both are 2 short, and rejecting takes both
+        assert_eq!(weighted_thresholds(&[1, 1, 3]), (3, 2));
+        // 6 of 8: the two heavy validators suffice together, and either
+        // can sink an action, while the light ones need everyone
+        assert_eq!(weighted_thresholds(&[3, 3, 1, 1]), (2, 1));
+        assert_eq!(weighted_thresholds(&[1, 1, 3, 3]), (4, 3));
+        // 7 of 10 over four validators weighing 2 and two weighing 1
+        assert_eq!(weighted_thresholds(&[2, 2, 2, 2, 1, 1]), (4, 2));
+    }
+
+    #[test]
+    fn test_votes_count_at_their_weight() {
+        let (mut tracker, keys) = weighted(&[3, 1, 1]);
+        cast(
+            &mut tracker,
+            &[(&keys[1], Decision::Approve), (&keys[2], Decision::Reject)],
+        );
+        assert_eq!(
+            tracker.tally(&ACTION),
+            Tally {
+                approvals: 1,
+                rejections: 1,
+                validators: 5,
+                required: 4
+            }
+        );
+        assert_eq!(tracker.tally(&ACTION).outcome(), Outcome::Pending);
+
+        let settled = cast(&mut tracker, &[(&keys[0], Decision::Approve)]);
+        assert_eq!(settled, vec![Some(Outcome::Approved)]);
+        assert_eq!(tracker.tally(&ACTION).approvals, 4);
+
+        // Reweighing counts the same votes again at the new weights
+        let mut reweighed = tracker.validators().clone();
+        reweighed.set_weight(keys[0].public_key(), 1);
+        tracker.set_validators(reweighed, 2);
+        assert_eq!(tracker.tally(&ACTION).approvals, 2);
+        assert_eq!(tracker.tally(&ACTION).validators, 3);
+    }
+
+    #[test]
+    fn test_split_votes_stay_pending_until_settled() {
+        let (mut tracker, keys) = tracker(7);
+        let outcomes = cast(
+            &mut tracker,
+            &[
+                (&keys[0], Decision::Approve),
+                (&keys[1], Decision::Reject),
+                (&keys[2], Decision::Approve),
+                (&keys[3], Decision::Reject),
+                (&keys[4], Decision::Approve),
+                (&keys[5], Decision::Approve),
+            ],
+        );
+        // 4 approvals of the 5 needed, with one validator left to vote
+        assert!(outcomes.iter().all(Option::is_none));
+        assert_eq!(tracker.tally(&ACTION).outcome(), Outcome::Pending);
+
+        let last = cast(&mut tracker, &[(&keys[6], Decision::Approve)]);
+        assert_eq!(last, vec![Some(Outcome::Approved)]);
+        assert_eq!(
+            tracker.tally(&ACTION),
+            Tally {
+                approvals: 5,
+                rejections: 2,
+                validators: 7,
+                required: 5
+            }
+        );
+    }
+
+    #[test]
+    fn test_unanimity_takes_every_validators_weight() {
+        let (mut tracker, keys) = weighted(&[3, 1, 1]);
+        let settled = cast(
+            &mut tracker,
+            &[(&keys[0], Decision::Approve), (&keys[1], Decision::Approve)],
+        );
+        assert_eq!(settled[1], Some(Outcome::Approved));
+        assert!(!tracker.tally(&ACTION).is_unanimous());
+
+        cast(&mut tracker, &[(&keys[2], Decision::Approve)]);
+        assert!(tracker.tally(&ACTION).is_unanimous());
+        assert!(!Tally::default().is_unanimous());
+    }
+
+    #[test]
+    fn test_duplicate_and_late_votes_count_once() {
+        let (mut tracker, keys) = tracker(4);
+        cast(
+            &mut tracker,
+            &[(&keys[0], Decision::Approve), (&keys[1], Decision::Approve)],
+        );
+
+        // The same vote again, and a change of mind, are both refused
+        let again = Vote::new(&keys[1], ACTION, 0, Decision::Approve);
+        assert!(tracker.record(again).is_err());
+        let flipped = Vote::new(&keys[1], ACTION, 1, Decision::Reject);
+        assert!(tracker.record(flipped).is_err());
+        assert_eq!(tracker.tally(&ACTION).approvals, 2);
+
+        let settled = cast(&mut tracker, &[(&keys[2], Decision::Approve)]);
+        assert_eq!(settled, vec![Some(Outcome::Approved)]);
+
+        // A late vote is counted but settles nothing new
+        let late = cast(&mut tracker, &[(&keys[3], Decision::Reject)]);
+        assert_eq!(late, vec![None]);
+        assert_eq!(tracker.votes(&ACTION).len(), 4);
+    }
+
+    #[test]
+    fn test_outsiders_and_forgeries_refused() {
+        let (mut tracker, keys) = tracker(4);
+
+        let outsider = Vote::new(&KeyPair::generate(), ACTION, 0, Decision::Approve);
+        assert!(matches!(
+            tracker.record(outsider),
+            Err(SwarmhostError::Consensus(_))
+        ));
+
+        let mut forged = Vote::new(&keys[0], ACTION, 0, Decision::Approve);
+        forged.decision = Decision::Reject;
+        assert!(matches!(
+            tracker.record(forged),
+            Err(SwarmhostError::Crypto(_))
+        ));
+        assert!(tracker.votes(&ACTION).is_empty());
+    }
+
+    #[test]
+    fn test_removed_validator_stops_counting() {
+        let (mut tracker, keys) = tracker(4);
+        cast(
+            &mut tracker,
+            &[(&keys[0], Decision::Approve), (&keys[1], Decision::Approve)],
+        );
+
+        let remaining = Membership::equal(keys[1..].iter().map(KeyPair::public_key));
+        tracker.set_validators(remaining, 2);
+        assert_eq!(tracker.tally(&ACTION).approvals, 1);
+        assert!(tracker.tallies().contains_key(&ACTION));
+    }
+}
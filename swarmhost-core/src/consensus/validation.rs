@@ -0,0 +1,399 @@
+// consensus/validation.rs - Checks an action passes before a validator
+// approves it, from the built-in ones to a game's own
+
+use super::action::{ActionId, SignedAction};
+use crate::crypto::PlayerId;
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::Semaphore;
+
+/// Machine-readable reason a validator rejected an action, carried in its
+/// Reject vote
+///
+/// Codes below [`RejectCode::FIRST_CUSTOM`] are the built-in checks';
+/// validators a game registers use codes from there on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct RejectCode(pub u32);
+
+impl RejectCode {
+    pub const INVALID_SIGNATURE: Self = Self(1);
+    pub const OVERSIZED: Self = Self(2);
+    pub const RATE_LIMITED: Self = Self(3);
+    pub const DUPLICATE: Self = Self(4);
+    /// The checks did not finish within `validation_timeout`
+    pub const TIMEOUT: Self = Self(5);
+    /// The game itself found the action invalid
+    pub const GAME: Self = Self(6);
+    /// A membership change the validators may not make
+    pub const MEMBERSHIP: Self = Self(7);
+    /// The action's deadline passed before it could be committed
+    pub const EXPIRED: Self = Self(8);
+
+    /// First code free for a game's own validators
+    pub const FIRST_CUSTOM: u32 = 1000;
+
+    /// Whether the checks ran out of time rather than finding a fault
+    pub fn is_timeout(&self) -> bool {
+        *self == Self::TIMEOUT
+    }
+}
+
+impl fmt::Display for RejectCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            Self::INVALID_SIGNATURE => write!(f, "invalid signature"),
+            Self::OVERSIZED => write!(f, "oversized"),
+            Self::RATE_LIMITED => write!(f, "rate limited"),
+            Self::DUPLICATE => write!(f, "duplicate"),
+            Self::TIMEOUT => write!(f, "validation timed out"),
+            Self::GAME => write!(f, "invalid in the game"),
+            Self::MEMBERSHIP => write!(f, "invalid membership change"),
+            Self::EXPIRED => write!(f, "expired"),
+            Self(code) => write!(f, "rejected with code {}", code),
+        }
+    }
+}
+
+/// Verdict of one check, or of the whole pipeline
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ValidationResult {
+    Valid,
+    Invalid(RejectCode),
+}
+
+/// What a validator knows of an action it is about to vote on
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ValidationContext {
+    pub game_id: String,
+    pub action_id: ActionId,
+    /// Round the vote goes in
+    pub round: u64,
+    /// The action as its actor signed it
+    pub action: SignedAction,
+    /// Actions of the same actor in the block the action went out in,
+    /// counting this one
+    pub actor_actions: u32,
+    /// Whether the game's log already holds the action, or an earlier
+    /// pending action of its actor has its nonce
+    pub duplicate: bool,
+    /// Whether the action is a membership change that is malformed, comes
+    /// from a non-validator, is due too soon, or would leave the
+    /// validators too few or dominated by one
+    pub refused_membership_change: bool,
+    /// Whether the action's deadline passed, by more than
+    /// `expiry_clock_skew` on our clock
+    pub expired: bool,
+    pub max_action_size: usize,
+    pub max_actions_per_player_per_round: u32,
+}
+
+/// A check an action must pass for us to approve it
+///
+/// Register one with
+/// [`SwarmhostNode::with_validator`](crate::node::SwarmhostNode::with_validator);
+/// it runs after the built-in checks, in the order registered, and before
+/// the game's own verdict given to
+/// [`SwarmhostNode::vote`](crate::node::SwarmhostNode::vote).
+#[async_trait]
+pub trait ActionValidator: Send + Sync {
+    async fn validate(
+        &self,
+        ctx: &ValidationContext,
+        actor: &PlayerId,
+        action_type: u32,
+        data: &[u8],
+    ) -> ValidationResult;
+}
+
+/// The actor's signature must verify
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SignatureCheck;
+
+#[async_trait]
+impl ActionValidator for SignatureCheck {
+    async fn validate(
+        &self,
+        ctx: &ValidationContext,
+        _actor: &PlayerId,
+        _action_type: u32,
+        _data: &[u8],
+    ) -> ValidationResult {
+        match ctx.action.verify() {
+            Ok(()) => ValidationResult::Valid,
+            Err(_) => ValidationResult::Invalid(RejectCode::INVALID_SIGNATURE),
+        }
+    }
+}
+
+/// The payload must fit in `max_action_size`
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SizeCheck;
+
+#[async_trait]
+impl ActionValidator for SizeCheck {
+    async fn validate(
+        &self,
+        ctx: &ValidationContext,
+        _actor: &PlayerId,
+        _action_type: u32,
+        data: &[u8],
+    ) -> ValidationResult {
+        if data.len() > ctx.max_action_size {
+            return ValidationResult::Invalid(RejectCode::OVERSIZED);
+        }
+        ValidationResult::Valid
+    }
+}
+
+/// The actor must stay within `max_actions_per_player_per_round`
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RateCheck;
+
+#[async_trait]
+impl ActionValidator for RateCheck {
+    async fn validate(
+        &self,
+        ctx: &ValidationContext,
+        _actor: &PlayerId,
+        _action_type: u32,
+        _data: &[u8],
+    ) -> ValidationResult {
+        if ctx.actor_actions > ctx.max_actions_per_player_per_round {
+            return ValidationResult::Invalid(RejectCode::RATE_LIMITED);
+        }
+        ValidationResult::Valid
+    }
+}
+
+/// The action must not be committed already, nor replay an earlier one's
+/// nonce
+#[derive(Debug, Clone, Copy, Default)]
+pub struct DuplicateCheck;
+
+#[async_trait]
+impl ActionValidator for DuplicateCheck {
+    async fn validate(
+        &self,
+        ctx: &ValidationContext,
+        _actor: &PlayerId,
+        _action_type: u32,
+        _data: &[u8],
+    ) -> ValidationResult {
+        if ctx.duplicate {
+            return ValidationResult::Invalid(RejectCode::DUPLICATE);
+        }
+        ValidationResult::Valid
+    }
+}
+
+/// A membership change must be one the validators may make
+#[derive(Debug, Clone, Copy, Default)]
+pub struct MembershipCheck;
+
+#[async_trait]
+impl ActionValidator for MembershipCheck {
+    async fn validate(
+        &self,
+        ctx: &ValidationContext,
+        _actor: &PlayerId,
+        _action_type: u32,
+        _data: &[u8],
+    ) -> ValidationResult {
+        if ctx.refused_membership_change {
+            return ValidationResult::Invalid(RejectCode::MEMBERSHIP);
+        }
+        ValidationResult::Valid
+    }
+}
+
+/// The action's deadline must not have passed
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ExpiryCheck;
+
+#[async_trait]
+impl ActionValidator for ExpiryCheck {
+    async fn validate(
+        &self,
+        ctx: &ValidationContext,
+        _actor: &PlayerId,
+        _action_type: u32,
+        _data: &[u8],
+    ) -> ValidationResult {
+        if ctx.expired {
+            return ValidationResult::Invalid(RejectCode::EXPIRED);
+        }
+        ValidationResult::Valid
+    }
+}
+
+/// The checks run on each action before we approve it, in order, stopping
+/// at the first that rejects it
+///
+/// The built-in signature, size, rate, duplicate, membership and expiry
+/// checks come first. At most `max_concurrent_validations` actions are
+/// checked at once, and the wait for a turn counts against each action's
+/// budget.
+#[derive(Clone)]
+pub struct ValidationPipeline {
+    validators: Vec<Arc<dyn ActionValidator>>,
+    permits: Arc<Semaphore>,
+}
+
+impl ValidationPipeline {
+    pub fn new(max_concurrent_validations: usize) -> Self {
+        Self {
+            validators: vec![
+                Arc::new(SignatureCheck),
+                Arc::new(SizeCheck),
+                Arc::new(RateCheck),
+                Arc::new(DuplicateCheck),
+                Arc::new(MembershipCheck),
+                Arc::new(ExpiryCheck),
+            ],
+            permits: Arc::new(Semaphore::new(max_concurrent_validations.max(1))),
+        }
+    }
+
+    /// Add a check after those already registered
+    pub fn push(&mut self, validator: Arc<dyn ActionValidator>) {
+        self.validators.push(validator);
+    }
+
+    /// Run every check on the action, rejecting it with
+    /// [`RejectCode::TIMEOUT`] if they take longer than `budget` together
+    pub async fn run(&self, ctx: &ValidationContext, budget: Duration) -> ValidationResult {
+        let checks = async {
+            let Ok(_permit) = self.permits.acquire().await else {
+                return ValidationResult::Invalid(RejectCode::TIMEOUT);
+            };
+            let action = &ctx.action;
+            for validator in &self.validators {
+                let result = validator
+                    .validate(ctx, &action.actor, action.action_type, &action.data)
+                    .await;
+                if result != ValidationResult::Valid {
+                    return result;
+                }
+            }
+            ValidationResult::Valid
+        };
+        tokio::time::timeout(budget, checks)
+            .await
+            .unwrap_or(ValidationResult::Invalid(RejectCode::TIMEOUT))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::KeyPair;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    fn context(action: SignedAction) -> ValidationContext {
+        ValidationContext {
+            game_id: action.game_id.clone(),
+            action_id: action.id(),
+            round: 0,
+            action,
+            actor_actions: 1,
+            duplicate: false,
+            refused_membership_change: false,
+            expired: false,
+            max_action_size: 64,
+            max_actions_per_player_per_round: 10,
+        }
+    }
+
+    /// Rejects `action_type` 7 with the first custom code, counting calls
+    #[derive(Default)]
+    struct NoSevens(AtomicUsize);
+
+    #[async_trait]
+    impl ActionValidator for NoSevens {
+        async fn validate(
+            &self,
+            _ctx: &ValidationContext,
+            _actor: &PlayerId,
+            action_type: u32,
+            _data: &[u8],
+        ) -> ValidationResult {
+            self.0.fetch_add(1, Ordering::SeqCst);
+            if action_type == 7 {
+                return ValidationResult::Invalid(RejectCode(RejectCode::FIRST_CUSTOM));
+            }
+            ValidationResult::Valid
+        }
+    }
+
+    struct Slow;
+
+    #[async_trait]
+    impl ActionValidator for Slow {
+        async fn validate(
+            &self,
+            _ctx: &ValidationContext,
+            _actor: &PlayerId,
+            _action_type: u32,
+            _data: &[u8],
+        ) -> ValidationResult {
+            tokio::time::sleep(Duration::from_secs(3600)).await;
+            ValidationResult::Valid
+        }
+    }
+
+    #[tokio::test]
+    async fn test_first_rejection_stops_the_pipeline() {
+        let keypair = KeyPair::generate();
+        let counter = Arc::new(NoSevens::default());
+        let mut pipeline = ValidationPipeline::new(4);
+        pipeline.push(counter.clone());
+        let budget = Duration::from_secs(1);
+
+        let fine = context(SignedAction::new(&keypair, "game", 0, 1, vec![1]));
+        assert_eq!(pipeline.run(&fine, budget).await, ValidationResult::Valid);
+        let seven = context(SignedAction::new(&keypair, "game", 1, 7, vec![1]));
+        assert_eq!(
+            pipeline.run(&seven, budget).await,
+            ValidationResult::Invalid(RejectCode(RejectCode::FIRST_CUSTOM))
+        );
+        assert_eq!(counter.0.load(Ordering::SeqCst), 2);
+
+        // The built-in checks come first
+        let mut forged = SignedAction::new(&keypair, "game", 2, 7, vec![1]);
+        forged.signature = vec![0; 64];
+        assert_eq!(
+            pipeline.run(&context(forged), budget).await,
+            ValidationResult::Invalid(RejectCode::INVALID_SIGNATURE)
+        );
+        let mut replayed = context(SignedAction::new(&keypair, "game", 0, 1, vec![1]));
+        replayed.duplicate = true;
+        assert_eq!(
+            pipeline.run(&replayed, budget).await,
+            ValidationResult::Invalid(RejectCode::DUPLICATE)
+        );
+        let mut stale = context(SignedAction::new(&keypair, "game", 3, 1, vec![1]));
+        stale.expired = true;
+        assert_eq!(
+            pipeline.run(&stale, budget).await,
+            ValidationResult::Invalid(RejectCode::EXPIRED)
+        );
+        assert_eq!(counter.0.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_slow_validator_times_out_instead_of_hanging() {
+        let keypair = KeyPair::generate();
+        let mut pipeline = ValidationPipeline::new(1);
+        pipeline.push(Arc::new(Slow));
+        let ctx = context(SignedAction::new(&keypair, "game", 0, 1, vec![1]));
+
+        let started = tokio::time::Instant::now();
+        let result = pipeline.run(&ctx, Duration::from_millis(200)).await;
+        assert_eq!(result, ValidationResult::Invalid(RejectCode::TIMEOUT));
+        assert!(RejectCode::TIMEOUT.is_timeout());
+        assert_eq!(started.elapsed(), Duration::from_millis(200));
+    }
+}
@@ -0,0 +1,488 @@
+// consensus/view.rs - Handing sequencing to another validator when consensus
+// stalls
+
+use super::action::{ActionId, SignedAction};
+use super::evidence::Equivocation;
+use super::membership::Membership;
+use super::sequence::Commit;
+use super::vote::Vote;
+use crate::crypto::{self, KeyPair, PlayerId, short_id};
+use crate::error::{Result, SwarmhostError};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+/// Furthest ahead of our view a view change is kept for
+pub const MAX_VIEWS_AHEAD: u64 = 64;
+
+/// An action a quorum approved that has no delivered commit yet, with the
+/// approvals that show it, and the number it was given if we saw one
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Prepared {
+    pub action: SignedAction,
+    /// Approvals from validators weighing at least a quorum
+    pub votes: Vec<Vote>,
+    /// A sequencer's commit of the action, held back by a gap
+    pub commit: Option<Commit>,
+}
+
+impl Prepared {
+    /// Check the action's signature, that members of `validators` weighing
+    /// `required` approved it, and that its commit, if any, is a
+    /// validator's and for this action
+    pub fn verify(&self, validators: &Membership, required: u64) -> Result<()> {
+        self.action.verify()?;
+        let action_id = self.action.id();
+        let mut approvers = HashSet::new();
+        for vote in &self.votes {
+            if vote.action_id != action_id || !vote.approves() || !validators.contains(&vote.voter)
+            {
+                return Err(SwarmhostError::consensus(format!(
+                    "vote by {} does not approve {}",
+                    short_id(&vote.voter),
+                    short_id(&action_id)
+                )));
+            }
+            vote.verify()?;
+            approvers.insert(vote.voter);
+        }
+        let approving = validators.weight(&approvers);
+        if approving < required {
+            return Err(SwarmhostError::consensus(format!(
+                "{} prepared with approvals weighing {} of {}",
+                short_id(&action_id),
+                approving,
+                required
+            )));
+        }
+        if let Some(commit) = &self.commit {
+            if commit.action.id() != action_id || !validators.contains(&commit.sequencer) {
+                return Err(SwarmhostError::consensus(format!(
+                    "commit at {} is not {}'s",
+                    commit.sequence,
+                    short_id(&action_id)
+                )));
+            }
+            commit.verify()?;
+        }
+        Ok(())
+    }
+}
+
+/// A validator's request to move to `view`, with what it has committed and
+/// prepared so the new leader loses nothing
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ViewChange {
+    pub view: u64,
+    pub voter: PlayerId,
+    /// Highest sequence delivered in each game
+    pub committed: Vec<(String, u64)>,
+    pub prepared: Vec<Prepared>,
+    /// Voter's signature over [`ViewChange::signing_bytes`]
+    pub signature: Vec<u8>,
+}
+
+impl ViewChange {
+    /// Build and sign a request to move to `view`
+    pub fn new(
+        keypair: &KeyPair,
+        view: u64,
+        committed: Vec<(String, u64)>,
+        prepared: Vec<Prepared>,
+    ) -> Self {
+        let mut change = Self {
+            view,
+            voter: keypair.public_key(),
+            committed,
+            prepared,
+            signature: Vec::new(),
+        };
+        change.signature = keypair.sign(&change.signing_bytes());
+        change
+    }
+
+    /// Canonical bytes covered by the signature
+    pub fn signing_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(128);
+        bytes.extend_from_slice(b"swarmhost-view-change-v1");
+        bytes.extend_from_slice(&self.view.to_be_bytes());
+        bytes.extend_from_slice(&self.voter);
+        for (game_id, sequence) in &self.committed {
+            bytes.extend_from_slice(&(game_id.len() as u64).to_be_bytes());
+            bytes.extend_from_slice(game_id.as_bytes());
+            bytes.extend_from_slice(&sequence.to_be_bytes());
+        }
+        for prepared in &self.prepared {
+            bytes.extend_from_slice(&prepared.action.id());
+            bytes.extend_from_slice(&(prepared.votes.len() as u64).to_be_bytes());
+            for vote in &prepared.votes {
+                bytes.extend_from_slice(&vote.signature);
+            }
+            match &prepared.commit {
+                Some(commit) => bytes.extend_from_slice(&commit.signature),
+                None => bytes.push(0),
+            }
+        }
+        bytes
+    }
+
+    /// Check the voter's signature and every prepared action's evidence
+    pub fn verify(&self, validators: &Membership, required: u64) -> Result<()> {
+        crypto::verify_signature(&self.voter, &self.signing_bytes(), &self.signature)?;
+        self.prepared
+            .iter()
+            .try_for_each(|prepared| prepared.verify(validators, required))
+    }
+}
+
+/// The start of `view`: the quorum of view changes that asked for it, and
+/// the commits its leader made of what they had prepared
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct NewView {
+    pub view: u64,
+    pub leader: PlayerId,
+    pub view_changes: Vec<ViewChange>,
+    /// Prepared actions numbered by the leader
+    pub commits: Vec<Commit>,
+    /// Leader's signature over [`NewView::signing_bytes`]
+    pub signature: Vec<u8>,
+}
+
+impl NewView {
+    /// Start `view` from `view_changes` and sign it
+    pub fn new(
+        keypair: &KeyPair,
+        view: u64,
+        view_changes: Vec<ViewChange>,
+        commits: Vec<Commit>,
+    ) -> Self {
+        let mut new_view = Self {
+            view,
+            leader: keypair.public_key(),
+            view_changes,
+            commits,
+            signature: Vec::new(),
+        };
+        new_view.signature = keypair.sign(&new_view.signing_bytes());
+        new_view
+    }
+
+    /// Canonical bytes covered by the signature
+    pub fn signing_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(128);
+        bytes.extend_from_slice(b"swarmhost-new-view-v1");
+        bytes.extend_from_slice(&self.view.to_be_bytes());
+        bytes.extend_from_slice(&self.leader);
+        for change in &self.view_changes {
+            bytes.extend_from_slice(&change.signature);
+        }
+        for commit in &self.commits {
+            bytes.extend_from_slice(&commit.signing_bytes());
+            bytes.extend_from_slice(&commit.signature);
+        }
+        bytes
+    }
+
+    /// Check the leader's signature, that members of `validators` weighing
+    /// `required` asked for the view, and that the leader numbered only
+    /// prepared actions
+    pub fn verify(&self, validators: &Membership, required: u64) -> Result<()> {
+        crypto::verify_signature(&self.leader, &self.signing_bytes(), &self.signature)?;
+        let mut voters = HashSet::new();
+        for change in &self.view_changes {
+            if change.view != self.view || !validators.contains(&change.voter) {
+                return Err(SwarmhostError::consensus(format!(
+                    "view change by {} is not for view {}",
+                    short_id(&change.voter),
+                    self.view
+                )));
+            }
+            change.verify(validators, required)?;
+            voters.insert(change.voter);
+        }
+        let asking = validators.weight(&voters);
+        if asking < required {
+            return Err(SwarmhostError::consensus(format!(
+                "view {} asked for by validators weighing {} of {}",
+                self.view, asking, required
+            )));
+        }
+        let prepared: HashSet<ActionId> = self
+            .view_changes
+            .iter()
+            .flat_map(|change| &change.prepared)
+            .map(|prepared| prepared.action.id())
+            .collect();
+        for commit in &self.commits {
+            if commit.sequencer != self.leader || !prepared.contains(&commit.action.id()) {
+                return Err(SwarmhostError::consensus(format!(
+                    "commit at {} was not prepared",
+                    commit.sequence
+                )));
+            }
+            commit.verify()?;
+        }
+        Ok(())
+    }
+}
+
+/// Equivocations among the prepared commits of `changes`: one sequencer
+/// giving the same number in a game to two actions
+pub fn equivocations<'a>(changes: impl IntoIterator<Item = &'a ViewChange>) -> Vec<Equivocation> {
+    let mut numbered: HashMap<(PlayerId, &str, u64), &Commit> = HashMap::new();
+    let mut found = Vec::new();
+    for commit in changes
+        .into_iter()
+        .flat_map(|change| &change.prepared)
+        .filter_map(|prepared| prepared.commit.as_ref())
+    {
+        let key = (
+            commit.sequencer,
+            commit.action.game_id.as_str(),
+            commit.sequence,
+        );
+        match numbered.get(&key) {
+            Some(first) if first.action.id() != commit.action.id() => found.push(
+                Equivocation::Commits(Box::new((*first).clone()), Box::new(commit.clone())),
+            ),
+            Some(_) => {}
+            None => {
+                numbered.insert(key, commit);
+            }
+        }
+    }
+    found
+}
+
+/// What a new leader has to number in one game, from a quorum's view
+/// changes
+#[derive(Debug, Default)]
+pub struct Carryover {
+    /// Highest sequence anyone delivered
+    pub committed: u64,
+    /// Actions already numbered past that, at their number
+    pub numbered: BTreeMap<u64, SignedAction>,
+    /// Prepared actions with no number, in order of id
+    pub unnumbered: Vec<SignedAction>,
+}
+
+/// Gather what `changes` committed and prepared, by game
+///
+/// Actions numbered at or below what was committed are left out, as that
+/// place is already filled, by the action itself unless its sequencer
+/// equivocated. Where two actions share a number, the smaller id keeps it
+/// and the other is numbered afresh.
+pub fn carryover<'a>(
+    changes: impl IntoIterator<Item = &'a ViewChange> + Clone,
+) -> HashMap<String, Carryover> {
+    let mut games: HashMap<String, Carryover> = HashMap::new();
+    for change in changes.clone() {
+        for (game_id, sequence) in &change.committed {
+            let game = games.entry(game_id.clone()).or_default();
+            game.committed = game.committed.max(*sequence);
+        }
+    }
+    let mut prepared: Vec<&Prepared> = changes
+        .into_iter()
+        .flat_map(|change| &change.prepared)
+        .collect();
+    prepared.sort_by_key(|prepared| prepared.action.id());
+    let mut seen = HashSet::new();
+    for prepared in prepared {
+        let action = &prepared.action;
+        let game = games.entry(action.game_id.clone()).or_default();
+        let number = prepared.commit.as_ref().map(|commit| commit.sequence);
+        if number.is_some_and(|sequence| sequence <= game.committed) {
+            continue;
+        }
+        if !seen.insert(action.id()) {
+            continue;
+        }
+        match number.filter(|sequence| !game.numbered.contains_key(sequence)) {
+            Some(sequence) => {
+                game.numbered.insert(sequence, action.clone());
+            }
+            None => game.unnumbered.push(action.clone()),
+        }
+    }
+    games
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::consensus::Decision;
+
+    struct Validators {
+        keys: Vec<KeyPair>,
+        ids: Membership,
+    }
+
+    fn validators(count: usize) -> Validators {
+        weighted(&vec![1; count])
+    }
+
+    fn weighted(weights: &[u64]) -> Validators {
+        let keys: Vec<KeyPair> = weights.iter().map(|_| KeyPair::generate()).collect();
+        let ids = Membership::weighted(
+            keys.iter()
+                .map(KeyPair::public_key)
+                .zip(weights.iter().copied()),
+        );
+        Validators { keys, ids }
+    }
+
+    fn prepared(validators: &Validators, action: SignedAction, approvals: usize) -> Prepared {
+        let votes = validators.keys[..approvals]
+            .iter()
+            .map(|key| Vote::new(key, action.id(), 0, Decision::Approve))
+            .collect();
+        Prepared {
+            action,
+            votes,
+            commit: None,
+        }
+    }
+
+    #[test]
+    fn test_prepared_needs_a_quorum_of_approvals() {
+        let validators = validators(3);
+        let action = SignedAction::new(&validators.keys[0], "game", 0, 1, vec![]);
+        assert!(
+            prepared(&validators, action.clone(), 2)
+                .verify(&validators.ids, 2)
+                .is_ok()
+        );
+        assert!(matches!(
+            prepared(&validators, action.clone(), 1).verify(&validators.ids, 2),
+            Err(SwarmhostError::Consensus(_))
+        ));
+
+        // The same approval twice counts once
+        let mut repeated = prepared(&validators, action, 1);
+        repeated.votes.push(repeated.votes[0].clone());
+        assert!(repeated.verify(&validators.ids, 2).is_err());
+    }
+
+    #[test]
+    fn test_new_view_needs_a_quorum_of_view_changes() {
+        let validators = validators(3);
+        let leader = &validators.keys[1];
+        let changes: Vec<ViewChange> = validators.keys[..2]
+            .iter()
+            .map(|key| ViewChange::new(key, 1, vec![("game".into(), 4)], Vec::new()))
+            .collect();
+        let new_view = NewView::new(leader, 1, changes.clone(), Vec::new());
+        assert!(new_view.verify(&validators.ids, 2).is_ok());
+
+        let short = NewView::new(leader, 1, changes[..1].to_vec(), Vec::new());
+        assert!(short.verify(&validators.ids, 2).is_err());
+
+        let doubled = NewView::new(leader, 1, vec![changes[0].clone(); 2], Vec::new());
+        assert!(doubled.verify(&validators.ids, 2).is_err());
+
+        // The leader may only number actions someone prepared
+        let action = SignedAction::new(leader, "game", 0, 1, vec![]);
+        let unprepared = NewView::new(leader, 1, changes, vec![Commit::new(leader, 5, action)]);
+        assert!(unprepared.verify(&validators.ids, 2).is_err());
+    }
+
+    #[test]
+    fn test_view_evidence_counts_weight_not_heads() {
+        // 4 of 5: the heavy validator and either light one
+        let validators = weighted(&[3, 1, 1]);
+        let [heavy, light, other] = [0, 1, 2].map(|i| &validators.keys[i]);
+        let action = SignedAction::new(light, "game", 0, 1, vec![]);
+        let approved_by = |keys: &[&KeyPair]| Prepared {
+            action: action.clone(),
+            votes: keys
+                .iter()
+                .map(|key| Vote::new(key, action.id(), 0, Decision::Approve))
+                .collect(),
+            commit: None,
+        };
+        assert!(
+            approved_by(&[heavy, light])
+                .verify(&validators.ids, 4)
+                .is_ok()
+        );
+        assert!(
+            approved_by(&[light, other])
+                .verify(&validators.ids, 4)
+                .is_err()
+        );
+
+        let change = |key| ViewChange::new(key, 1, Vec::new(), Vec::new());
+        let lights = NewView::new(light, 1, vec![change(light), change(other)], Vec::new());
+        assert!(lights.verify(&validators.ids, 4).is_err());
+        let with_heavy = NewView::new(light, 1, vec![change(heavy), change(other)], Vec::new());
+        assert!(with_heavy.verify(&validators.ids, 4).is_ok());
+    }
+
+    #[test]
+    fn test_two_actions_at_one_sequence_are_equivocation() {
+        let validators = validators(3);
+        let sequencer = &validators.keys[0];
+        let first = SignedAction::new(sequencer, "game", 0, 1, vec![1]);
+        let second = SignedAction::new(sequencer, "game", 1, 1, vec![2]);
+        let change = |voter: &KeyPair, action: &SignedAction| {
+            let mut prepared = prepared(&validators, action.clone(), 2);
+            prepared.commit = Some(Commit::new(sequencer, 3, action.clone()));
+            ViewChange::new(voter, 1, Vec::new(), vec![prepared])
+        };
+        let changes = [
+            change(&validators.keys[1], &first),
+            change(&validators.keys[2], &second),
+        ];
+        assert!(changes.iter().all(|c| c.verify(&validators.ids, 2).is_ok()));
+
+        let found = equivocations(&changes);
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].offender(), sequencer.public_key());
+        assert!(found[0].verify().is_ok());
+        assert!(equivocations(&changes[..1]).is_empty());
+
+        // Only one of them keeps the number
+        let games = carryover(&changes);
+        let game = &games["game"];
+        assert_eq!(game.numbered.len(), 1);
+        assert_eq!(game.unnumbered.len(), 1);
+    }
+
+    #[test]
+    fn test_carryover_drops_numbers_already_taken() {
+        let validators = validators(2);
+        let key = &validators.keys[0];
+        let delivered = SignedAction::new(key, "game", 0, 1, vec![]);
+        let waiting = SignedAction::new(key, "game", 1, 1, vec![]);
+        let fresh = SignedAction::new(key, "game", 2, 1, vec![]);
+        let numbered = |action: &SignedAction, sequence| {
+            let mut prepared = prepared(&validators, action.clone(), 2);
+            prepared.commit = Some(Commit::new(key, sequence, action.clone()));
+            prepared
+        };
+        let changes = [
+            ViewChange::new(
+                key,
+                1,
+                vec![("game".into(), 1)],
+                vec![numbered(&delivered, 1), numbered(&waiting, 3)],
+            ),
+            ViewChange::new(
+                &validators.keys[1],
+                1,
+                vec![("game".into(), 2)],
+                vec![
+                    numbered(&waiting, 3),
+                    prepared(&validators, fresh.clone(), 2),
+                ],
+            ),
+        ];
+
+        let games = carryover(&changes);
+        let game = &games["game"];
+        assert_eq!(game.committed, 2);
+        assert_eq!(game.numbered.len(), 1);
+        assert_eq!(game.numbered[&3], waiting);
+        assert_eq!(game.unnumbered, vec![fresh]);
+    }
+}
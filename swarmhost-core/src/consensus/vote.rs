@@ -0,0 +1,127 @@
+// consensus/vote.rs - Signed votes on proposed actions
+
+use super::action::ActionId;
+use super::validation::RejectCode;
+use crate::crypto::{self, KeyPair, PlayerId};
+use crate::error::Result;
+use serde::{Deserialize, Serialize};
+
+/// Most votes sent in answer to a peer asking for those of a round
+pub const MAX_VOTES_ANSWERED: usize = 256;
+
+/// How a validator votes on an action
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Decision {
+    Approve,
+    Reject,
+}
+
+/// A validator's verdict on a proposed action
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Vote {
+    /// Action being voted on
+    pub action_id: ActionId,
+
+    /// Consensus round the voter was in when voting
+    pub round: u64,
+
+    /// Player casting the vote
+    pub voter: PlayerId,
+
+    /// Whether the voter accepts the action
+    pub decision: Decision,
+
+    /// Why the voter rejected it, when it says
+    #[serde(default)]
+    pub reason: Option<RejectCode>,
+
+    /// Voter's signature over [`Vote::signing_bytes`]
+    pub signature: Vec<u8>,
+}
+
+impl Vote {
+    /// Build and sign a vote
+    pub fn new(keypair: &KeyPair, action_id: ActionId, round: u64, decision: Decision) -> Self {
+        Self::signed(keypair, action_id, round, decision, None)
+    }
+
+    /// Build and sign a vote rejecting an action for `reason`
+    pub fn rejecting(
+        keypair: &KeyPair,
+        action_id: ActionId,
+        round: u64,
+        reason: RejectCode,
+    ) -> Self {
+        Self::signed(keypair, action_id, round, Decision::Reject, Some(reason))
+    }
+
+    fn signed(
+        keypair: &KeyPair,
+        action_id: ActionId,
+        round: u64,
+        decision: Decision,
+        reason: Option<RejectCode>,
+    ) -> Self {
+        let mut vote = Self {
+            action_id,
+            round,
+            voter: keypair.public_key(),
+            decision,
+            reason,
+            signature: Vec::new(),
+        };
+        vote.signature = keypair.sign(&vote.signing_bytes());
+        vote
+    }
+
+    /// Canonical bytes covered by the signature; a reason, when given,
+    /// follows the rest
+    pub fn signing_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(100);
+        bytes.extend_from_slice(b"swarmhost-vote-v2");
+        bytes.extend_from_slice(&self.action_id);
+        bytes.extend_from_slice(&self.round.to_be_bytes());
+        bytes.extend_from_slice(&self.voter);
+        bytes.push(self.approves() as u8);
+        if let Some(RejectCode(code)) = self.reason {
+            bytes.extend_from_slice(&code.to_be_bytes());
+        }
+        bytes
+    }
+
+    pub fn approves(&self) -> bool {
+        self.decision == Decision::Approve
+    }
+
+    /// Check the signature against the voter's public key
+    pub fn verify(&self) -> Result<()> {
+        crypto::verify_signature(&self.voter, &self.signing_bytes(), &self.signature)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_flipped_vote_fails_verification() {
+        let keypair = KeyPair::generate();
+        let vote = Vote::new(&keypair, [3; 32], 4, Decision::Approve);
+        assert!(vote.verify().is_ok());
+
+        let mut flipped = vote.clone();
+        flipped.decision = Decision::Reject;
+        assert!(flipped.verify().is_err());
+
+        let mut replayed = vote.clone();
+        replayed.round = 5;
+        assert!(replayed.verify().is_err());
+
+        // The reason for a rejection is signed with it
+        let rejected = Vote::rejecting(&keypair, [3; 32], 4, RejectCode::DUPLICATE);
+        assert!(rejected.verify().is_ok());
+        let mut reworded = rejected.clone();
+        reworded.reason = Some(RejectCode::GAME);
+        assert!(reworded.verify().is_err());
+    }
+}
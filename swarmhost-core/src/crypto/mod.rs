@@ -48,6 +48,13 @@ impl KeyPair {
         self.signing_key.to_bytes()
     }
 
+    /// X25519 secret for key agreement, derived from the signing key
+    ///
+    /// Its public half is [`x25519_public`] of our player id.
+    pub fn x25519_secret(&self) -> [u8; 32] {
+        self.signing_key.to_scalar_bytes()
+    }
+
     /// Sign a message
     pub fn sign(&self, message: &[u8]) -> Vec<u8> {
         self.signing_key.sign(message).to_bytes().to_vec()
@@ -77,6 +84,42 @@ pub fn verify_signature(public_key: &PlayerId, message: &[u8], signature: &[u8])
         .map_err(|e| SwarmhostError::crypto(format!("Verification failed: {}", e)))
 }
 
+/// X25519 public key matching a player's [`KeyPair::x25519_secret`]
+pub fn x25519_public(id: &PlayerId) -> Result<[u8; 32]> {
+    let key = VerifyingKey::from_bytes(id)
+        .map_err(|e| SwarmhostError::crypto(format!("Invalid public key: {}", e)))?;
+    Ok(key.to_montgomery().to_bytes())
+}
+
+/// Short hex prefix of a player id, for log messages
+pub fn short_id(id: &PlayerId) -> String {
+    id[..4].iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Full hex encoding of a player id, as used in config files
+pub fn player_id_hex(id: &PlayerId) -> String {
+    id.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Parse a player id from its 64-character hex encoding
+pub fn parse_player_id(s: &str) -> Result<PlayerId> {
+    let s = s.trim();
+    if s.len() != 64 || !s.is_ascii() {
+        return Err(SwarmhostError::validation(format!(
+            "Player id must be 64 hex characters, got '{}'",
+            s
+        )));
+    }
+
+    let mut id = [0u8; 32];
+    for (i, byte) in id.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&s[2 * i..2 * i + 2], 16).map_err(|_| {
+            SwarmhostError::validation(format!("Player id is not valid hex: '{}'", s))
+        })?;
+    }
+    Ok(id)
+}
+
 /// Hash data using Blake2s
 pub fn hash(data: &[u8]) -> Hash {
     let mut hasher = Blake2s256::new();
@@ -157,4 +200,17 @@ mod tests {
         let hash3 = hash_multiple(&[piece3, piece2, piece1]);
         assert_ne!(hash1, hash3);
     }
+
+    #[test]
+    fn test_player_id_hex_round_trip() {
+        let id = KeyPair::generate().public_key();
+        let hex = player_id_hex(&id);
+        assert_eq!(hex.len(), 64);
+        assert!(hex.starts_with(&short_id(&id)));
+        assert_eq!(parse_player_id(&hex).unwrap(), id);
+        assert_eq!(parse_player_id(&hex.to_uppercase()).unwrap(), id);
+
+        assert!(parse_player_id(&hex[..62]).is_err());
+        assert!(parse_player_id(&format!("zz{}", &hex[2..])).is_err());
+    }
 }
@@ -1,5 +1,14 @@
-// error.rs - Error types for Swarmhost
+// error.rs - Error types for Swarmhost, with the stable codes clients act on
 
+use crate::consensus::ActionId;
+use crate::crypto::{PlayerId, short_id};
+use crate::network::handshake::CloseCode;
+use crate::network::outbound::SendError;
+use crate::node::RejectionReason;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::io;
+use std::time::Duration;
 use thiserror::Error;
 
 pub type Result<T> = std::result::Result<T, SwarmhostError>;
@@ -33,8 +42,88 @@ pub enum SwarmhostError {
     #[error("Peer error: {0}")]
     Peer(String),
 
+    #[error("Handshake failed with {code}: {reason}")]
+    Handshake { code: CloseCode, reason: String },
+
     #[error("Configuration error: {0}")]
     Config(String),
+
+    #[error("{game_id} is pruned before {earliest}, the earliest sequence still held")]
+    Pruned { game_id: String, earliest: u64 },
+
+    #[error("Overloaded: {0}")]
+    Overloaded(String),
+
+    #[error("Node not running")]
+    NotRunning,
+
+    #[error("Node already running")]
+    AlreadyRunning,
+
+    #[error("No game joined")]
+    NoGame,
+
+    #[error("Still catching up on the game")]
+    CatchingUp,
+
+    #[error("Quorum lost: too few validators reachable")]
+    QuorumLost,
+
+    #[error("Too large: {0}")]
+    TooLarge(String),
+
+    #[error(
+        "Timeout: {} not committed within {after:?}{}",
+        short_id(.action_id),
+        awaiting(*.round, .missing_voters)
+    )]
+    CommitTimeout {
+        action_id: ActionId,
+        after: Duration,
+        /// Round of the block it was put forward in, if it was
+        round: Option<u64>,
+        /// Validators yet to vote on it or its block
+        missing_voters: Vec<PlayerId>,
+    },
+
+    #[error("{} rejected: {reason}", short_id(.action_id))]
+    Rejected {
+        action_id: ActionId,
+        reason: RejectionReason,
+    },
+
+    #[error("No answer from {} within {after:?}", short_id(.peer))]
+    NoAnswer { peer: PlayerId, after: Duration },
+
+    #[error("{} is not connected", short_id(.peer))]
+    NotConnected { peer: PlayerId },
+
+    #[error("{} disconnected", short_id(.peer))]
+    Disconnected { peer: PlayerId },
+
+    #[error("Sending to {} failed: {source}", short_id(.peer))]
+    SendFailed {
+        peer: PlayerId,
+        #[source]
+        source: SendError,
+    },
+
+    #[error("{} answered {code}: {message}", short_id(.peer))]
+    Remote {
+        peer: PlayerId,
+        code: ErrorCode,
+        message: String,
+    },
+}
+
+/// The round and voters a commit timeout was waiting on, as a suffix
+fn awaiting(round: Option<u64>, missing_voters: &[PlayerId]) -> String {
+    let mut suffix = round.map_or_else(String::new, |round| format!(" in round {}", round));
+    if !missing_voters.is_empty() {
+        let voters: Vec<String> = missing_voters.iter().map(short_id).collect();
+        suffix += &format!(", waiting on {}", voters.join(", "));
+    }
+    suffix
 }
 
 // Helper for creating errors
@@ -58,4 +147,551 @@ impl SwarmhostError {
     pub fn timeout(msg: impl Into<String>) -> Self {
         SwarmhostError::Timeout(msg.into())
     }
+
+    pub fn handshake(code: CloseCode, reason: impl Into<String>) -> Self {
+        SwarmhostError::Handshake {
+            code,
+            reason: reason.into(),
+        }
+    }
+
+    /// Stable code of the error, for a client to act on rather than its
+    /// message
+    pub fn code(&self) -> ErrorCode {
+        match self {
+            SwarmhostError::Network(e) => match e.kind() {
+                io::ErrorKind::ConnectionRefused
+                | io::ErrorKind::ConnectionReset
+                | io::ErrorKind::ConnectionAborted
+                | io::ErrorKind::NotConnected
+                | io::ErrorKind::BrokenPipe
+                | io::ErrorKind::TimedOut
+                | io::ErrorKind::Interrupted
+                | io::ErrorKind::WouldBlock
+                | io::ErrorKind::UnexpectedEof
+                | io::ErrorKind::AddrNotAvailable
+                | io::ErrorKind::NetworkUnreachable
+                | io::ErrorKind::HostUnreachable => ErrorCode::Disconnected,
+                _ => ErrorCode::Io,
+            },
+            SwarmhostError::Peer(_) | SwarmhostError::NotConnected { .. } => ErrorCode::PeerFailed,
+            SwarmhostError::Disconnected { .. } => ErrorCode::Disconnected,
+            SwarmhostError::SendFailed { source, .. } => match source {
+                SendError::Closed => ErrorCode::Disconnected,
+                SendError::Full => ErrorCode::PeerBusy,
+                SendError::ControlOverflow => ErrorCode::PeerFailed,
+            },
+            SwarmhostError::Timeout(_)
+            | SwarmhostError::NoAnswer { .. }
+            | SwarmhostError::CommitTimeout { .. } => ErrorCode::Timeout,
+            SwarmhostError::Handshake { code, .. } => match code {
+                CloseCode::Overloaded
+                | CloseCode::RateLimited
+                | CloseCode::Busy
+                | CloseCode::Evicted
+                | CloseCode::AlreadyConnected => ErrorCode::PeerBusy,
+                CloseCode::Kicked | CloseCode::NotInvited | CloseCode::Banned => {
+                    ErrorCode::PeerRefused
+                }
+                CloseCode::EncryptionRequired
+                | CloseCode::NoCommonCipher
+                | CloseCode::IncompatibleVersion
+                | CloseCode::NoCommonFormat => ErrorCode::Incompatible,
+                CloseCode::IdentityMismatch | CloseCode::BadIdentityProof => {
+                    ErrorCode::IdentityRejected
+                }
+                CloseCode::Normal
+                | CloseCode::Timeout
+                | CloseCode::ProtocolError
+                | CloseCode::Leaving
+                | CloseCode::Shutdown
+                | CloseCode::HandshakeTimeout => ErrorCode::HandshakeFailed,
+            },
+            SwarmhostError::Consensus(_) => ErrorCode::Consensus,
+            SwarmhostError::QuorumLost => ErrorCode::QuorumLost,
+            SwarmhostError::Overloaded(_) => ErrorCode::Overloaded,
+            SwarmhostError::Validation(_) | SwarmhostError::Rejected { .. } => {
+                ErrorCode::Validation
+            }
+            SwarmhostError::TooLarge(_) => ErrorCode::TooLarge,
+            SwarmhostError::Serialization(_) => ErrorCode::Malformed,
+            SwarmhostError::InvalidState(_) => ErrorCode::InvalidState,
+            SwarmhostError::Pruned { .. } => ErrorCode::Pruned,
+            SwarmhostError::NoGame => ErrorCode::NoGame,
+            SwarmhostError::CatchingUp => ErrorCode::CatchingUp,
+            SwarmhostError::Crypto(_) => ErrorCode::Crypto,
+            SwarmhostError::Config(_) => ErrorCode::Config,
+            SwarmhostError::Node(_) => ErrorCode::Internal,
+            SwarmhostError::NotRunning => ErrorCode::NotRunning,
+            SwarmhostError::AlreadyRunning => ErrorCode::AlreadyRunning,
+            SwarmhostError::Remote { code, .. } => *code,
+        }
+    }
+
+    /// Whether the same call may succeed if tried again later, unchanged
+    pub fn is_retryable(&self) -> bool {
+        self.code().is_retryable()
+    }
+
+    /// What part of the node the error comes from
+    pub fn category(&self) -> ErrorCategory {
+        self.code().category()
+    }
+
+    /// The action the error is about, if it is about one
+    pub fn action_id(&self) -> Option<ActionId> {
+        match self {
+            SwarmhostError::CommitTimeout { action_id, .. }
+            | SwarmhostError::Rejected { action_id, .. } => Some(*action_id),
+            _ => None,
+        }
+    }
+
+    /// The peer that failed or refused us, if one did
+    pub fn peer(&self) -> Option<PlayerId> {
+        match self {
+            SwarmhostError::NoAnswer { peer, .. }
+            | SwarmhostError::NotConnected { peer }
+            | SwarmhostError::Disconnected { peer }
+            | SwarmhostError::SendFailed { peer, .. }
+            | SwarmhostError::Remote { peer, .. } => Some(*peer),
+            _ => None,
+        }
+    }
+
+    /// The game the error is about, if it is about one
+    pub fn game_id(&self) -> Option<&str> {
+        match self {
+            SwarmhostError::Pruned { game_id, .. } => Some(game_id),
+            _ => None,
+        }
+    }
+
+    /// The consensus round the error is about, if it is about one
+    pub fn round(&self) -> Option<u64> {
+        match self {
+            SwarmhostError::CommitTimeout { round, .. } => *round,
+            _ => None,
+        }
+    }
+
+    /// Validators whose votes were still missing, for a commit timeout
+    pub fn missing_voters(&self) -> &[PlayerId] {
+        match self {
+            SwarmhostError::CommitTimeout { missing_voters, .. } => missing_voters,
+            _ => &[],
+        }
+    }
+
+    /// Why the action was rejected, if it was
+    pub fn rejection(&self) -> Option<&RejectionReason> {
+        match self {
+            SwarmhostError::Rejected { reason, .. } => Some(reason),
+            _ => None,
+        }
+    }
+}
+
+/// Stable code of a [`SwarmhostError`]
+///
+/// The numbers never change between releases and are never reused; new
+/// codes take new numbers in their category's hundred: 1xx network, 2xx
+/// consensus, 3xx validation, 4xx crypto, 5xx config and 9xx internal. A
+/// code is serialized as its number, so it crosses the wire and FFI
+/// boundaries as it is.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(into = "u16", try_from = "u16")]
+#[repr(u16)]
+pub enum ErrorCode {
+    /// An I/O error not down to the connection dropping
+    Io = 100,
+    /// The connection dropped or could not be made; retryable
+    Disconnected = 101,
+    /// A peer could not be reached, or failed us; retryable
+    PeerFailed = 102,
+    /// No answer in time; retryable
+    Timeout = 103,
+    /// A peer refused us for lack of room or sending too fast; retryable
+    PeerBusy = 110,
+    /// A peer banned, kicked or did not invite us
+    PeerRefused = 111,
+    /// We share no protocol, format or cipher with a peer
+    Incompatible = 112,
+    /// A handshake was cut short, or the peer went away; retryable
+    HandshakeFailed = 113,
+
+    /// A peer broke the consensus protocol
+    Consensus = 200,
+    /// Too few validators are reachable for a quorum; retryable
+    QuorumLost = 201,
+    /// Too many actions pending to take another; retryable
+    Overloaded = 202,
+
+    /// An action or message failed our checks
+    Validation = 300,
+    /// A message or action larger than the limits allow
+    TooLarge = 301,
+    /// Bytes that do not decode
+    Malformed = 302,
+    /// Asked of state we do not hold, or that does not add up
+    InvalidState = 303,
+    /// The sequence asked for is pruned
+    Pruned = 304,
+    /// No game joined; join one first
+    NoGame = 305,
+    /// Still catching up on the game; retryable
+    CatchingUp = 306,
+
+    /// A signature or key did not verify
+    Crypto = 400,
+    /// A peer is not who it claimed to be
+    IdentityRejected = 401,
+
+    /// The configuration is invalid
+    Config = 500,
+
+    /// Something went wrong inside the node
+    Internal = 900,
+    /// The node is not running; start it first
+    NotRunning = 901,
+    /// The node was started already
+    AlreadyRunning = 902,
+}
+
+impl ErrorCode {
+    /// Every code, in order of their numbers
+    pub const ALL: [ErrorCode; 24] = [
+        ErrorCode::Io,
+        ErrorCode::Disconnected,
+        ErrorCode::PeerFailed,
+        ErrorCode::Timeout,
+        ErrorCode::PeerBusy,
+        ErrorCode::PeerRefused,
+        ErrorCode::Incompatible,
+        ErrorCode::HandshakeFailed,
+        ErrorCode::Consensus,
+        ErrorCode::QuorumLost,
+        ErrorCode::Overloaded,
+        ErrorCode::Validation,
+        ErrorCode::TooLarge,
+        ErrorCode::Malformed,
+        ErrorCode::InvalidState,
+        ErrorCode::Pruned,
+        ErrorCode::NoGame,
+        ErrorCode::CatchingUp,
+        ErrorCode::Crypto,
+        ErrorCode::IdentityRejected,
+        ErrorCode::Config,
+        ErrorCode::Internal,
+        ErrorCode::NotRunning,
+        ErrorCode::AlreadyRunning,
+    ];
+
+    /// Numeric value of the code
+    pub fn as_u16(self) -> u16 {
+        self as u16
+    }
+
+    /// The code numbered `code`, if it is one we know
+    pub fn from_u16(code: u16) -> Option<ErrorCode> {
+        Self::ALL.into_iter().find(|known| known.as_u16() == code)
+    }
+
+    /// Whether an error of this code may go away by trying again later
+    pub fn is_retryable(self) -> bool {
+        matches!(
+            self,
+            ErrorCode::Disconnected
+                | ErrorCode::PeerFailed
+                | ErrorCode::Timeout
+                | ErrorCode::PeerBusy
+                | ErrorCode::HandshakeFailed
+                | ErrorCode::QuorumLost
+                | ErrorCode::Overloaded
+                | ErrorCode::CatchingUp
+        )
+    }
+
+    /// Category the code is numbered in
+    pub fn category(self) -> ErrorCategory {
+        ErrorCategory::from_u8((self.as_u16() / 100) as u8).unwrap_or(ErrorCategory::Internal)
+    }
+}
+
+impl From<ErrorCode> for u16 {
+    fn from(code: ErrorCode) -> u16 {
+        code.as_u16()
+    }
+}
+
+impl TryFrom<u16> for ErrorCode {
+    type Error = SwarmhostError;
+
+    fn try_from(code: u16) -> Result<ErrorCode> {
+        ErrorCode::from_u16(code)
+            .ok_or_else(|| SwarmhostError::Serialization(format!("unknown error code {}", code)))
+    }
+}
+
+impl fmt::Display for ErrorCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?} ({})", self, self.as_u16())
+    }
+}
+
+/// Broad kind of a [`SwarmhostError`], from its [`ErrorCode`]
+///
+/// Numbered as the hundreds of its codes, and serialized as that number.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(into = "u8", try_from = "u8")]
+#[repr(u8)]
+pub enum ErrorCategory {
+    Network = 1,
+    Consensus = 2,
+    Validation = 3,
+    Crypto = 4,
+    Config = 5,
+    Internal = 9,
+}
+
+impl ErrorCategory {
+    /// Number carried on the wire
+    pub fn as_u8(self) -> u8 {
+        self as u8
+    }
+
+    /// The category numbered `category`, if it is one we know
+    pub fn from_u8(category: u8) -> Option<ErrorCategory> {
+        [
+            ErrorCategory::Network,
+            ErrorCategory::Consensus,
+            ErrorCategory::Validation,
+            ErrorCategory::Crypto,
+            ErrorCategory::Config,
+            ErrorCategory::Internal,
+        ]
+        .into_iter()
+        .find(|known| known.as_u8() == category)
+    }
+}
+
+impl From<ErrorCategory> for u8 {
+    fn from(category: ErrorCategory) -> u8 {
+        category.as_u8()
+    }
+}
+
+impl TryFrom<u8> for ErrorCategory {
+    type Error = SwarmhostError;
+
+    fn try_from(category: u8) -> Result<ErrorCategory> {
+        ErrorCategory::from_u8(category).ok_or_else(|| {
+            SwarmhostError::Serialization(format!("unknown error category {}", category))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_error_codes_keep_their_numbers() {
+        // Clients store and compare these; never change one
+        let pinned = [
+            (ErrorCode::Io, 100),
+            (ErrorCode::Disconnected, 101),
+            (ErrorCode::PeerFailed, 102),
+            (ErrorCode::Timeout, 103),
+            (ErrorCode::PeerBusy, 110),
+            (ErrorCode::PeerRefused, 111),
+            (ErrorCode::Incompatible, 112),
+            (ErrorCode::HandshakeFailed, 113),
+            (ErrorCode::Consensus, 200),
+            (ErrorCode::QuorumLost, 201),
+            (ErrorCode::Overloaded, 202),
+            (ErrorCode::Validation, 300),
+            (ErrorCode::TooLarge, 301),
+            (ErrorCode::Malformed, 302),
+            (ErrorCode::InvalidState, 303),
+            (ErrorCode::Pruned, 304),
+            (ErrorCode::NoGame, 305),
+            (ErrorCode::CatchingUp, 306),
+            (ErrorCode::Crypto, 400),
+            (ErrorCode::IdentityRejected, 401),
+            (ErrorCode::Config, 500),
+            (ErrorCode::Internal, 900),
+            (ErrorCode::NotRunning, 901),
+            (ErrorCode::AlreadyRunning, 902),
+        ];
+        for (code, number) in pinned {
+            assert_eq!(code.as_u16(), number, "{:?}", code);
+            assert_eq!(ErrorCode::from_u16(number), Some(code));
+            // Serialized as the number, and back
+            assert_eq!(serde_json::to_string(&code).unwrap(), number.to_string());
+            let back: ErrorCode = serde_json::from_str(&number.to_string()).unwrap();
+            assert_eq!(back, code);
+        }
+        assert!(serde_json::from_str::<ErrorCode>("999").is_err());
+
+        let categories = [
+            (ErrorCategory::Network, 1),
+            (ErrorCategory::Consensus, 2),
+            (ErrorCategory::Validation, 3),
+            (ErrorCategory::Crypto, 4),
+            (ErrorCategory::Config, 5),
+            (ErrorCategory::Internal, 9),
+        ];
+        for (category, number) in categories {
+            assert_eq!(category.as_u8(), number, "{:?}", category);
+            assert_eq!(
+                serde_json::to_string(&category).unwrap(),
+                number.to_string()
+            );
+            let back: ErrorCategory = serde_json::from_str(&number.to_string()).unwrap();
+            assert_eq!(back, category);
+        }
+        assert!(serde_json::from_str::<ErrorCategory>("7").is_err());
+    }
+
+    #[test]
+    fn test_errors_are_classified_by_what_a_client_can_do() {
+        let refused = io::Error::from(io::ErrorKind::ConnectionRefused);
+        let missing = io::Error::from(io::ErrorKind::NotFound);
+        let cases = [
+            (SwarmhostError::from(refused), ErrorCode::Disconnected, true),
+            (SwarmhostError::from(missing), ErrorCode::Io, false),
+            (
+                SwarmhostError::handshake(CloseCode::Busy, "full"),
+                ErrorCode::PeerBusy,
+                true,
+            ),
+            (
+                SwarmhostError::handshake(CloseCode::Banned, "banned"),
+                ErrorCode::PeerRefused,
+                false,
+            ),
+            (
+                SwarmhostError::handshake(CloseCode::IncompatibleVersion, "v2"),
+                ErrorCode::Incompatible,
+                false,
+            ),
+            (SwarmhostError::QuorumLost, ErrorCode::QuorumLost, true),
+            (
+                SwarmhostError::Overloaded("full".to_string()),
+                ErrorCode::Overloaded,
+                true,
+            ),
+            (SwarmhostError::NoGame, ErrorCode::NoGame, false),
+            (SwarmhostError::NotRunning, ErrorCode::NotRunning, false),
+            // A peer's error is classified by the code it sent
+            (
+                SwarmhostError::Remote {
+                    peer: [1; 32],
+                    code: ErrorCode::CatchingUp,
+                    message: "Still catching up on the game".to_string(),
+                },
+                ErrorCode::CatchingUp,
+                true,
+            ),
+            (
+                SwarmhostError::crypto("bad signature"),
+                ErrorCode::Crypto,
+                false,
+            ),
+        ];
+        for (error, code, retryable) in cases {
+            assert_eq!(error.code(), code, "{}", error);
+            assert_eq!(error.is_retryable(), retryable, "{}", error);
+            assert_eq!(error.category(), code.category());
+        }
+        assert_eq!(
+            SwarmhostError::QuorumLost.category(),
+            ErrorCategory::Consensus
+        );
+        assert_eq!(
+            SwarmhostError::NotRunning.category(),
+            ErrorCategory::Internal
+        );
+    }
+
+    #[test]
+    fn test_context_is_shown_and_can_be_read_back() {
+        let action_id = [0xab; 32];
+        let (late, absent) = ([0x11; 32], [0x22; 32]);
+        let timeout = SwarmhostError::CommitTimeout {
+            action_id,
+            after: Duration::from_secs(2),
+            round: Some(7),
+            missing_voters: vec![late, absent],
+        };
+        assert_eq!(
+            timeout.to_string(),
+            "Timeout: abababab not committed within 2s in round 7, \
+             waiting on 11111111, 22222222"
+        );
+        assert_eq!(timeout.action_id(), Some(action_id));
+        assert_eq!(timeout.round(), Some(7));
+        assert_eq!(timeout.missing_voters(), [late, absent]);
+        assert_eq!(timeout.peer(), None);
+        assert_eq!(timeout.code(), ErrorCode::Timeout);
+
+        // Never put forward, so no round and nobody to wait on yet
+        let unproposed = SwarmhostError::CommitTimeout {
+            action_id,
+            after: Duration::from_secs(2),
+            round: None,
+            missing_voters: Vec::new(),
+        };
+        assert_eq!(
+            unproposed.to_string(),
+            "Timeout: abababab not committed within 2s"
+        );
+
+        let rejected = SwarmhostError::Rejected {
+            action_id,
+            reason: RejectionReason::InvalidSignature,
+        };
+        assert_eq!(rejected.to_string(), "abababab rejected: invalid signature");
+        assert_eq!(rejected.action_id(), Some(action_id));
+        assert_eq!(
+            rejected.rejection(),
+            Some(&RejectionReason::InvalidSignature)
+        );
+        assert_eq!(rejected.code(), ErrorCode::Validation);
+
+        let silent = SwarmhostError::NoAnswer {
+            peer: late,
+            after: Duration::from_millis(500),
+        };
+        assert_eq!(silent.to_string(), "No answer from 11111111 within 500ms");
+        assert_eq!(silent.peer(), Some(late));
+        assert!(silent.is_retryable());
+        assert_eq!(
+            SwarmhostError::Disconnected { peer: absent }.code(),
+            ErrorCode::Disconnected
+        );
+        assert_eq!(
+            SwarmhostError::NotConnected { peer: absent }.peer(),
+            Some(absent)
+        );
+        let unsent = SwarmhostError::SendFailed {
+            peer: absent,
+            source: SendError::Closed,
+        };
+        assert_eq!(
+            unsent.to_string(),
+            "Sending to 22222222 failed: connection closed"
+        );
+        assert_eq!(unsent.peer(), Some(absent));
+        assert_eq!(unsent.code(), ErrorCode::Disconnected);
+        assert!(std::error::Error::source(&unsent).is_some());
+
+        let pruned = SwarmhostError::Pruned {
+            game_id: "chess".to_string(),
+            earliest: 10,
+        };
+        assert_eq!(pruned.game_id(), Some("chess"));
+        assert_eq!(pruned.action_id(), None);
+    }
 }
@@ -0,0 +1,296 @@
+// logging.rs - Tracing subscriber setup driven by LogConfig
+
+use crate::error::{Result, SwarmhostError};
+use crate::node::{LogConfig, LogFormat};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, MutexGuard};
+use tracing::Subscriber;
+use tracing_subscriber::fmt::MakeWriter;
+use tracing_subscriber::fmt::format::FmtSpan;
+use tracing_subscriber::fmt::writer::BoxMakeWriter;
+use tracing_subscriber::registry::LookupSpan;
+use tracing_subscriber::{EnvFilter, Layer};
+
+/// Initialize logging with the defaults, honoring `RUST_LOG` if set
+///
+/// A `RUST_LOG` that does not parse is warned about and the default level
+/// used instead. Does nothing if a global subscriber is already installed.
+pub fn init_logging() {
+    let (config, invalid) = env_config(std::env::var("RUST_LOG").ok());
+    match init_logging_with(&config) {
+        Ok(()) => {}
+        // The subscriber already installed stays
+        Err(SwarmhostError::Node(_)) => return,
+        Err(e) => {
+            eprintln!("Could not initialize logging: {}", e);
+            return;
+        }
+    }
+    if let Some(filter) = invalid {
+        tracing::warn!(
+            "Ignoring RUST_LOG={:?}, which does not parse; logging at {}",
+            filter,
+            config.level
+        );
+    }
+}
+
+/// The default config at the level `rust_log` asks for, or at the default
+/// level along with `rust_log` if it does not parse
+fn env_config(rust_log: Option<String>) -> (LogConfig, Option<String>) {
+    let mut config = LogConfig::default();
+    match rust_log {
+        Some(filter) if EnvFilter::try_new(&filter).is_ok() => {
+            config.level = filter;
+            (config, None)
+        }
+        invalid => (config, invalid),
+    }
+}
+
+/// Install a global subscriber built from `config`
+///
+/// Fails instead of panicking if a global subscriber is already set.
+pub fn init_logging_with(config: &LogConfig) -> Result<()> {
+    use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
+
+    tracing_subscriber::registry()
+        .with(logging_layer(config)?)
+        .try_init()
+        .map_err(|e| SwarmhostError::node(format!("Logging already initialized: {}", e)))
+}
+
+/// Build the configured layer without installing it
+///
+/// For host applications that compose their own subscriber.
+pub fn logging_layer<S>(config: &LogConfig) -> Result<Box<dyn Layer<S> + Send + Sync>>
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    let filter = EnvFilter::try_new(&config.level).map_err(|e| {
+        SwarmhostError::Config(format!("Invalid log level '{}': {}", config.level, e))
+    })?;
+
+    let (writer, ansi) = match &config.file {
+        Some(path) => {
+            let file =
+                RotatingFile::open(path, config.max_file_size, config.max_files).map_err(|e| {
+                    SwarmhostError::Config(format!(
+                        "Cannot open log file {}: {}",
+                        path.display(),
+                        e
+                    ))
+                })?;
+            (BoxMakeWriter::new(file), false)
+        }
+        None => (BoxMakeWriter::new(io::stdout), true),
+    };
+
+    let span_events = if config.span_events {
+        FmtSpan::NEW | FmtSpan::CLOSE
+    } else {
+        FmtSpan::NONE
+    };
+
+    let base = tracing_subscriber::fmt::layer()
+        .with_writer(writer)
+        .with_ansi(ansi && config.format != LogFormat::Json)
+        .with_span_events(span_events);
+
+    Ok(match config.format {
+        LogFormat::Pretty => base.pretty().with_filter(filter).boxed(),
+        LogFormat::Compact => base.compact().with_filter(filter).boxed(),
+        LogFormat::Json => base.json().with_filter(filter).boxed(),
+    })
+}
+
+/// Log file that moves itself aside once it reaches a size limit
+///
+/// Rotated files are named `<path>.1` (newest) to `<path>.<max_files>`; the
+/// oldest is dropped. With `max_files == 0` the file is truncated instead.
+#[derive(Clone)]
+pub struct RotatingFile {
+    inner: Arc<Mutex<RotatingState>>,
+}
+
+struct RotatingState {
+    path: PathBuf,
+    file: File,
+    size: u64,
+    max_size: u64,
+    max_files: usize,
+}
+
+impl RotatingFile {
+    pub fn open(path: &Path, max_size: u64, max_files: usize) -> io::Result<Self> {
+        if let Some(parent) = path.parent().filter(|p| !p.as_os_str().is_empty()) {
+            fs::create_dir_all(parent)?;
+        }
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        let size = file.metadata()?.len();
+
+        Ok(Self {
+            inner: Arc::new(Mutex::new(RotatingState {
+                path: path.to_path_buf(),
+                file,
+                size,
+                max_size,
+                max_files,
+            })),
+        })
+    }
+
+    fn lock(&self) -> MutexGuard<'_, RotatingState> {
+        self.inner.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl RotatingState {
+    fn numbered(&self, n: usize) -> PathBuf {
+        let mut name = self.path.as_os_str().to_owned();
+        name.push(format!(".{}", n));
+        PathBuf::from(name)
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        self.file.flush()?;
+
+        if self.max_files == 0 {
+            self.file = File::create(&self.path)?;
+        } else {
+            for n in (1..self.max_files).rev() {
+                let from = self.numbered(n);
+                if from.exists() {
+                    fs::rename(from, self.numbered(n + 1))?;
+                }
+            }
+            fs::rename(&self.path, self.numbered(1))?;
+            self.file = OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(&self.path)?;
+        }
+
+        self.size = 0;
+        Ok(())
+    }
+}
+
+impl Write for RotatingFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut state = self.lock();
+        if state.size > 0 && state.size + buf.len() as u64 > state.max_size {
+            state.rotate()?;
+        }
+        state.file.write_all(buf)?;
+        state.size += buf.len() as u64;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.lock().file.flush()
+    }
+}
+
+impl<'a> MakeWriter<'a> for RotatingFile {
+    type Writer = RotatingFile;
+
+    fn make_writer(&'a self) -> Self::Writer {
+        self.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tracing_subscriber::layer::SubscriberExt;
+
+    #[test]
+    fn test_rust_log_that_does_not_parse_falls_back_to_the_default() {
+        let default = LogConfig::default().level;
+
+        let (config, invalid) = env_config(Some("swarmhost_core=trace".to_string()));
+        assert_eq!(config.level, "swarmhost_core=trace");
+        assert_eq!(invalid, None);
+
+        let (config, invalid) = env_config(Some("swarmhost_core=loud".to_string()));
+        assert_eq!(config.level, default);
+        assert_eq!(invalid.as_deref(), Some("swarmhost_core=loud"));
+
+        let (config, invalid) = env_config(None);
+        assert_eq!(config.level, default);
+        assert_eq!(invalid, None);
+    }
+
+    #[test]
+    fn test_json_output_fields() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("node.log");
+        let config = LogConfig {
+            level: "swarmhost_core=debug".to_string(),
+            format: LogFormat::Json,
+            file: Some(path.clone()),
+            span_events: true,
+            ..Default::default()
+        };
+
+        let subscriber = tracing_subscriber::registry().with(logging_layer(&config).unwrap());
+        tracing::subscriber::with_default(subscriber, || {
+            let span = tracing::info_span!("round", number = 7);
+            let _entered = span.enter();
+            tracing::info!(peer = "a1b2c3d4", "peer connected");
+            tracing::trace!("filtered out");
+        });
+
+        let output = fs::read_to_string(&path).unwrap();
+        let lines: Vec<serde_json::Value> = output
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+
+        let event = lines
+            .iter()
+            .find(|line| line["fields"]["message"] == "peer connected")
+            .expect("event line");
+        assert_eq!(event["level"], "INFO");
+        assert_eq!(event["fields"]["peer"], "a1b2c3d4");
+        assert_eq!(event["target"], "swarmhost_core::logging::tests");
+        assert_eq!(event["span"]["name"], "round");
+        assert_eq!(event["span"]["number"], 7);
+
+        assert!(lines.iter().any(|line| line["fields"]["message"] == "new"));
+        assert!(!output.contains("filtered out"));
+    }
+
+    #[test]
+    fn test_invalid_level_is_config_error() {
+        let config = LogConfig {
+            level: "swarmhost_core=loud".to_string(),
+            ..Default::default()
+        };
+        let layer = logging_layer::<tracing_subscriber::Registry>(&config);
+        assert!(matches!(layer, Err(SwarmhostError::Config(_))));
+    }
+
+    #[test]
+    fn test_rotation_by_size() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("node.log");
+        let mut file = RotatingFile::open(&path, 100, 2).unwrap();
+
+        let line = [b'x'; 40];
+        for _ in 0..10 {
+            file.write_all(&line).unwrap();
+        }
+
+        assert!(fs::metadata(&path).unwrap().len() <= 100);
+        assert_eq!(
+            fs::metadata(dir.path().join("node.log.1")).unwrap().len(),
+            80
+        );
+        assert!(dir.path().join("node.log.2").exists());
+        assert!(!dir.path().join("node.log.3").exists());
+    }
+}
@@ -0,0 +1,217 @@
+// network/batch.rs - Packing several small messages into one frame
+
+use super::codec::malformed;
+use crate::error::Result;
+use crate::node::BatchConfig;
+use bytes::Bytes;
+use std::time::Duration;
+use tokio::time::Instant;
+
+/// Room to leave in a frame for the [`PeerMessage::Batch`](super::PeerMessage::Batch)
+/// wrapped around a packed batch
+pub const ENVELOPE: usize = 16;
+
+/// Most messages one batch can hold
+pub const MAX_ITEMS: usize = u16::MAX as usize;
+
+const COUNT_LEN: usize = 2;
+const OFFSET_LEN: usize = 4;
+
+/// Size of `count` items totalling `bytes` once packed
+pub fn packed_len(count: usize, bytes: usize) -> usize {
+    COUNT_LEN + count * OFFSET_LEN + bytes
+}
+
+/// Pack encoded messages behind a header: a big-endian u16 count, then the
+/// u32 offset at which each item ends, then the items back to back
+pub fn pack(items: &[Bytes]) -> Vec<u8> {
+    let bytes = items.iter().map(Bytes::len).sum();
+    let mut packed = Vec::with_capacity(packed_len(items.len(), bytes));
+    packed.extend_from_slice(&(items.len() as u16).to_be_bytes());
+    let mut end = 0u32;
+    for item in items {
+        end += item.len() as u32;
+        packed.extend_from_slice(&end.to_be_bytes());
+    }
+    for item in items {
+        packed.extend_from_slice(item);
+    }
+    packed
+}
+
+/// The items of a packed batch, in the order they were packed
+pub fn unpack(packed: &[u8]) -> Result<Vec<&[u8]>> {
+    let (count, rest) = packed
+        .split_first_chunk::<COUNT_LEN>()
+        .ok_or_else(|| malformed("batch too short for its count"))?;
+    let count = u16::from_be_bytes(*count) as usize;
+    let header = count * OFFSET_LEN;
+    if rest.len() < header {
+        return Err(malformed(format!("batch too short for {} offsets", count)));
+    }
+    let (offsets, body) = rest.split_at(header);
+
+    let mut items = Vec::with_capacity(count);
+    let mut start = 0;
+    for offset in offsets.chunks_exact(OFFSET_LEN) {
+        let end = u32::from_be_bytes([offset[0], offset[1], offset[2], offset[3]]) as usize;
+        if end < start || end > body.len() {
+            return Err(malformed(format!(
+                "batch item ends at {} after {} in a {} byte body",
+                end,
+                start,
+                body.len()
+            )));
+        }
+        items.push(&body[start..end]);
+        start = end;
+    }
+    if start != body.len() {
+        return Err(malformed(format!(
+            "{} trailing bytes after batch items",
+            body.len() - start
+        )));
+    }
+    Ok(items)
+}
+
+/// Encoded messages waiting to share a frame
+///
+/// A batch is due `window` after its first message, or at once when an
+/// urgent one joins, and should be sent early once it reaches `max_bytes`.
+/// A zero window turns batching off.
+pub struct Batcher {
+    window: Duration,
+    max_bytes: usize,
+    items: Vec<Bytes>,
+    bytes: usize,
+    due: Option<Instant>,
+}
+
+impl Batcher {
+    pub fn new(config: &BatchConfig) -> Self {
+        Self {
+            window: config.window,
+            max_bytes: config.max_bytes,
+            items: Vec::new(),
+            bytes: 0,
+            due: None,
+        }
+    }
+
+    /// A batcher that never holds anything back
+    pub fn disabled() -> Self {
+        Self::new(&BatchConfig {
+            window: Duration::ZERO,
+            ..BatchConfig::default()
+        })
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        !self.window.is_zero()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.items.is_empty()
+    }
+
+    /// Whether a `len` byte message can join without the packed batch
+    /// going over `limit`
+    pub fn fits(&self, len: usize, limit: usize) -> bool {
+        self.items.len() < MAX_ITEMS && packed_len(self.items.len() + 1, self.bytes + len) <= limit
+    }
+
+    /// Add a message; `urgent` makes the batch due now
+    pub fn push(&mut self, item: Bytes, urgent: bool, now: Instant) {
+        let due = if urgent { now } else { now + self.window };
+        self.due = Some(self.due.map_or(due, |current| current.min(due)));
+        self.bytes += item.len();
+        self.items.push(item);
+    }
+
+    /// Reached `max_bytes` and should go out without waiting
+    pub fn is_full(&self) -> bool {
+        packed_len(self.items.len(), self.bytes) >= self.max_bytes
+    }
+
+    /// When the batch should be sent, if anything is waiting
+    pub fn due(&self) -> Option<Instant> {
+        self.due
+    }
+
+    /// Everything waiting, oldest first
+    pub fn take(&mut self) -> Vec<Bytes> {
+        self.bytes = 0;
+        self.due = None;
+        std::mem::take(&mut self.items)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn items() -> Vec<Bytes> {
+        vec![
+            Bytes::from_static(b"first"),
+            Bytes::new(),
+            Bytes::from_static(b"third and longest"),
+            Bytes::from_static(b"4"),
+        ]
+    }
+
+    #[test]
+    fn test_unpack_preserves_order() {
+        let packed = pack(&items());
+        assert_eq!(packed.len(), packed_len(4, 23));
+        let unpacked = unpack(&packed).unwrap();
+        assert_eq!(unpacked, items().iter().map(|b| &b[..]).collect::<Vec<_>>());
+        assert!(unpack(&pack(&[])).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_unpack_rejects_bad_headers() {
+        let packed = pack(&items());
+        assert!(unpack(&packed[..1]).is_err());
+        assert!(unpack(&packed[..COUNT_LEN + 3]).is_err());
+        assert!(unpack(&packed[..packed.len() - 1]).is_err());
+
+        let mut trailing = packed.clone();
+        trailing.push(0);
+        assert!(unpack(&trailing).is_err());
+
+        // Second item ending before the first
+        let mut backwards = packed;
+        backwards[COUNT_LEN + OFFSET_LEN..COUNT_LEN + 2 * OFFSET_LEN]
+            .copy_from_slice(&1u32.to_be_bytes());
+        assert!(unpack(&backwards).is_err());
+    }
+
+    #[test]
+    fn test_batch_due_after_window_or_when_urgent() {
+        let config = BatchConfig {
+            window: Duration::from_millis(2),
+            max_bytes: 64,
+        };
+        let now = Instant::now();
+        let mut batcher = Batcher::new(&config);
+        assert!(batcher.is_enabled() && batcher.due().is_none());
+
+        batcher.push(Bytes::from_static(b"vote"), false, now);
+        let later = now + Duration::from_millis(1);
+        batcher.push(Bytes::from_static(b"vote"), false, later);
+        assert_eq!(batcher.due(), Some(now + config.window));
+        batcher.push(Bytes::from_static(b"ping"), true, later);
+        assert_eq!(batcher.due(), Some(later));
+
+        assert!(!batcher.is_full());
+        assert!(batcher.fits(34, 64));
+        assert!(!batcher.fits(35, 64));
+        batcher.push(Bytes::from(vec![0; 34]), false, later);
+        assert!(batcher.is_full());
+
+        assert_eq!(batcher.take().len(), 4);
+        assert!(batcher.is_empty() && batcher.due().is_none());
+        assert!(!Batcher::disabled().is_enabled());
+    }
+}
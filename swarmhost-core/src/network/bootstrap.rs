@@ -0,0 +1,698 @@
+// network/bootstrap.rs - Bootstrap server selection and discovery protocol
+
+use super::frame::FramedStream;
+use super::proxy::Socks5Proxy;
+use crate::crypto::{self, KeyPair, PlayerId, short_id};
+use crate::error::{Result, SwarmhostError};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tokio::net::TcpStream;
+
+/// Largest bootstrap request or response
+pub const MAX_BOOTSTRAP_MESSAGE: usize = 256 * 1024;
+
+/// How long a registration stays listed without a refresh
+pub const REGISTRATION_TTL: Duration = Duration::from_secs(120);
+
+/// Ordered list of bootstrap servers with a sticky "active" entry
+///
+/// Connection attempts start at the active server and walk the list in order,
+/// wrapping around. The first server that answers becomes the active one and
+/// stays active until it fails.
+#[derive(Debug, Clone)]
+pub struct BootstrapList {
+    servers: Vec<String>,
+    active: usize,
+    proxy: Option<Socks5Proxy>,
+}
+
+impl BootstrapList {
+    pub fn new(servers: Vec<String>) -> Self {
+        Self {
+            servers,
+            active: 0,
+            proxy: None,
+        }
+    }
+
+    /// Reach the servers through `proxy`, which also resolves their names
+    pub fn with_proxy(mut self, proxy: Socks5Proxy) -> Self {
+        self.proxy = Some(proxy);
+        self
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.servers.is_empty()
+    }
+
+    /// The server currently in use (or next to be tried)
+    pub fn active(&self) -> Option<&str> {
+        self.servers.get(self.active).map(String::as_str)
+    }
+
+    /// Move on to the next server in the list
+    pub fn rotate(&mut self) {
+        if !self.servers.is_empty() {
+            self.active = (self.active + 1) % self.servers.len();
+        }
+    }
+
+    /// Connect to the first server that answers, trying each once
+    pub async fn connect(&mut self, attempt_timeout: Duration) -> Result<(String, TcpStream)> {
+        for _ in 0..self.servers.len() {
+            let server = self.servers[self.active].clone();
+
+            let connect = async {
+                match &self.proxy {
+                    Some(proxy) => proxy.connect(&server).await,
+                    None => Ok(TcpStream::connect(&server).await?),
+                }
+            };
+            match tokio::time::timeout(attempt_timeout, connect).await {
+                Ok(Ok(stream)) => {
+                    tracing::debug!("Connected to bootstrap server {}", server);
+                    return Ok((server, stream));
+                }
+                Ok(Err(e)) => {
+                    tracing::warn!("Bootstrap server {} unreachable: {}", server, e);
+                }
+                Err(_) => {
+                    tracing::warn!("Bootstrap server {} timed out", server);
+                }
+            }
+
+            self.rotate();
+        }
+
+        Err(SwarmhostError::Peer(
+            "No bootstrap server reachable".to_string(),
+        ))
+    }
+}
+
+/// A node announcing where it can be reached and which games it is in
+///
+/// `addrs` lists every address the node listens on, typically one per IP
+/// family. An unspecified IP asks the server to substitute the address it
+/// sees the node connecting from (useful behind NAT); the port is always
+/// taken as given.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Registration {
+    pub player_id: PlayerId,
+    pub addrs: Vec<SocketAddr>,
+    pub games: Vec<String>,
+    /// Milliseconds since the Unix epoch; a server refuses registrations
+    /// older than the newest it has seen from the same player
+    pub timestamp_ms: u64,
+}
+
+/// A node asking for the peers in one game
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PeerQuery {
+    pub player_id: PlayerId,
+    pub game_id: String,
+    pub timestamp_ms: u64,
+}
+
+/// A bootstrap message signed by the player it names
+///
+/// Servers and clients both check the signature, so a server cannot list a
+/// peer under an id it does not hold the key for.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Signed<T> {
+    pub body: T,
+    pub signature: Vec<u8>,
+}
+
+/// Message bodies that can be signed
+pub trait Signable: Serialize {
+    /// Domain separator so one kind of message cannot pass for another
+    const CONTEXT: &'static [u8];
+
+    fn signer(&self) -> &PlayerId;
+}
+
+impl Signable for Registration {
+    const CONTEXT: &'static [u8] = b"swarmhost-bootstrap-register-v2";
+
+    fn signer(&self) -> &PlayerId {
+        &self.player_id
+    }
+}
+
+impl Signable for PeerQuery {
+    const CONTEXT: &'static [u8] = b"swarmhost-bootstrap-query-v1";
+
+    fn signer(&self) -> &PlayerId {
+        &self.player_id
+    }
+}
+
+impl<T: Signable> Signed<T> {
+    pub fn sign(keypair: &KeyPair, body: T) -> Result<Self> {
+        let signature = keypair.sign(&signing_bytes(&body)?);
+        Ok(Self { body, signature })
+    }
+
+    /// Check the signature against the key named in the body
+    pub fn verify(&self) -> Result<&T> {
+        crypto::verify_signature(
+            self.body.signer(),
+            &signing_bytes(&self.body)?,
+            &self.signature,
+        )?;
+        Ok(&self.body)
+    }
+}
+
+fn signing_bytes<T: Signable>(body: &T) -> Result<Vec<u8>> {
+    let mut bytes = T::CONTEXT.to_vec();
+    bincode::serialize_into(&mut bytes, body)
+        .map_err(|e| SwarmhostError::Serialization(e.to_string()))?;
+    Ok(bytes)
+}
+
+/// Requests a node sends to a bootstrap server
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum BootstrapRequest {
+    Register(Signed<Registration>),
+    Query(Signed<PeerQuery>),
+}
+
+/// A bootstrap server's answer
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum BootstrapResponse {
+    /// Registration accepted; refresh before `ttl` runs out
+    Registered { ttl: Duration },
+    /// Registrations of the peers in the queried game
+    Peers(Vec<ListedPeer>),
+    /// The request was rejected (bad signature, stale timestamp, ...)
+    Refused(String),
+}
+
+/// A registration as listed by the server
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ListedPeer {
+    pub registration: Signed<Registration>,
+    /// Address the server saw the registration come from
+    pub observed_ip: IpAddr,
+}
+
+/// A peer and the addresses it can be dialed at
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PeerRecord {
+    pub player_id: PlayerId,
+    pub addrs: Vec<SocketAddr>,
+}
+
+impl PeerRecord {
+    /// A peer reachable at a single address
+    pub fn at(player_id: PlayerId, addr: SocketAddr) -> Self {
+        Self {
+            player_id,
+            addrs: vec![addr],
+        }
+    }
+}
+
+impl ListedPeer {
+    /// Check the signature and resolve the addresses to dial
+    ///
+    /// The observed IP only stands in for unspecified addresses of its own
+    /// family; those of the other family cannot be resolved and are dropped.
+    pub fn verify(&self) -> Result<PeerRecord> {
+        let registration = self.registration.verify()?;
+        let addrs = registration
+            .addrs
+            .iter()
+            .filter_map(|addr| match addr.ip() {
+                ip if !ip.is_unspecified() => Some(*addr),
+                ip if ip.is_ipv4() == self.observed_ip.is_ipv4() => {
+                    Some(SocketAddr::new(self.observed_ip, addr.port()))
+                }
+                _ => None,
+            })
+            .collect();
+        Ok(PeerRecord {
+            player_id: registration.player_id,
+            addrs,
+        })
+    }
+}
+
+/// Client side of the bootstrap protocol
+///
+/// Each request opens a fresh connection to the active server, failing over
+/// through the list as [`BootstrapList::connect`] does.
+#[derive(Debug)]
+pub struct BootstrapClient {
+    servers: BootstrapList,
+    keypair: KeyPair,
+}
+
+impl BootstrapClient {
+    pub fn new(servers: BootstrapList, keypair: KeyPair) -> Self {
+        Self { servers, keypair }
+    }
+
+    /// The server that answered last (or will be tried first)
+    pub fn active(&self) -> Option<&str> {
+        self.servers.active()
+    }
+
+    /// Announce ourselves at `addrs`, returning how long the registration
+    /// lasts
+    pub async fn register(
+        &mut self,
+        addrs: Vec<SocketAddr>,
+        games: Vec<String>,
+        timeout: Duration,
+    ) -> Result<Duration> {
+        let registration = Registration {
+            player_id: self.keypair.public_key(),
+            addrs,
+            games,
+            timestamp_ms: now_ms(),
+        };
+        let request = BootstrapRequest::Register(Signed::sign(&self.keypair, registration)?);
+
+        match self.exchange(&request, timeout).await? {
+            BootstrapResponse::Registered { ttl } => Ok(ttl),
+            other => Err(unexpected(other)),
+        }
+    }
+
+    /// Peers registered in `game_id`, excluding ourselves
+    ///
+    /// Entries with bad signatures or for other games are dropped.
+    pub async fn query(&mut self, game_id: &str, timeout: Duration) -> Result<Vec<PeerRecord>> {
+        let query = PeerQuery {
+            player_id: self.keypair.public_key(),
+            game_id: game_id.to_string(),
+            timestamp_ms: now_ms(),
+        };
+        let request = BootstrapRequest::Query(Signed::sign(&self.keypair, query)?);
+
+        let listed = match self.exchange(&request, timeout).await? {
+            BootstrapResponse::Peers(listed) => listed,
+            other => return Err(unexpected(other)),
+        };
+
+        let own_id = self.keypair.public_key();
+        Ok(listed
+            .iter()
+            .filter(|peer| peer.registration.body.games.iter().any(|g| g == game_id))
+            .filter_map(|peer| match peer.verify() {
+                Ok(record) => Some(record),
+                Err(e) => {
+                    tracing::warn!(
+                        "Bootstrap listed {} with a bad signature: {}",
+                        short_id(&peer.registration.body.player_id),
+                        e
+                    );
+                    None
+                }
+            })
+            .filter(|record| record.player_id != own_id)
+            .collect())
+    }
+
+    async fn exchange(
+        &mut self,
+        request: &BootstrapRequest,
+        timeout: Duration,
+    ) -> Result<BootstrapResponse> {
+        let (server, stream) = self.servers.connect(timeout).await?;
+        let mut framed = FramedStream::new(stream, MAX_BOOTSTRAP_MESSAGE);
+
+        let exchange = async {
+            framed.send(&encode(request)?).await?;
+            decode::<BootstrapResponse>(&framed.recv().await?)
+        };
+        match tokio::time::timeout(timeout, exchange).await {
+            Ok(Ok(response)) => Ok(response),
+            Ok(Err(e)) => {
+                self.servers.rotate();
+                Err(e)
+            }
+            Err(_) => {
+                self.servers.rotate();
+                Err(SwarmhostError::timeout(format!(
+                    "Bootstrap server {} did not answer within {:?}",
+                    server, timeout
+                )))
+            }
+        }
+    }
+}
+
+/// Server-side state of the bootstrap protocol, independent of any socket
+#[derive(Debug)]
+pub struct BootstrapRegistry {
+    ttl: Duration,
+    entries: HashMap<PlayerId, (ListedPeer, Instant)>,
+}
+
+impl Default for BootstrapRegistry {
+    fn default() -> Self {
+        Self::new(REGISTRATION_TTL)
+    }
+}
+
+impl BootstrapRegistry {
+    pub fn new(ttl: Duration) -> Self {
+        Self {
+            ttl,
+            entries: HashMap::new(),
+        }
+    }
+
+    /// Answer one request from a client connecting from `from`
+    pub fn handle(
+        &mut self,
+        request: BootstrapRequest,
+        from: SocketAddr,
+        now: Instant,
+    ) -> BootstrapResponse {
+        self.entries
+            .retain(|_, (_, registered)| now.duration_since(*registered) < self.ttl);
+
+        match request {
+            BootstrapRequest::Register(registration) => {
+                if let Err(e) = registration.verify() {
+                    return BootstrapResponse::Refused(format!("bad signature: {}", e));
+                }
+                let player_id = registration.body.player_id;
+                if let Some((existing, _)) = self.entries.get(&player_id)
+                    && existing.registration.body.timestamp_ms > registration.body.timestamp_ms
+                {
+                    return BootstrapResponse::Refused("stale registration".to_string());
+                }
+                let listed = ListedPeer {
+                    registration,
+                    observed_ip: from.ip(),
+                };
+                self.entries.insert(player_id, (listed, now));
+                BootstrapResponse::Registered { ttl: self.ttl }
+            }
+            BootstrapRequest::Query(query) => {
+                let query = match query.verify() {
+                    Ok(query) => query,
+                    Err(e) => return BootstrapResponse::Refused(format!("bad signature: {}", e)),
+                };
+                BootstrapResponse::Peers(
+                    self.entries
+                        .values()
+                        .map(|(listed, _)| listed)
+                        .filter(|listed| listed.registration.body.player_id != query.player_id)
+                        .filter(|listed| listed.registration.body.games.contains(&query.game_id))
+                        .cloned()
+                        .collect(),
+                )
+            }
+        }
+    }
+
+    /// Currently listed registrations
+    pub fn registrations(&self) -> Vec<Registration> {
+        self.entries
+            .values()
+            .map(|(listed, _)| listed.registration.body.clone())
+            .collect()
+    }
+}
+
+fn encode<T: Serialize>(message: &T) -> Result<Vec<u8>> {
+    bincode::serialize(message).map_err(|e| SwarmhostError::Serialization(e.to_string()))
+}
+
+fn decode<T: for<'de> Deserialize<'de>>(bytes: &[u8]) -> Result<T> {
+    bincode::deserialize(bytes).map_err(|e| SwarmhostError::Serialization(e.to_string()))
+}
+
+fn unexpected(response: BootstrapResponse) -> SwarmhostError {
+    match response {
+        BootstrapResponse::Refused(reason) => {
+            SwarmhostError::Peer(format!("Bootstrap server refused request: {}", reason))
+        }
+        other => SwarmhostError::Peer(format!("Unexpected bootstrap response: {:?}", other)),
+    }
+}
+
+fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+/// In-process bootstrap server for tests
+#[cfg(test)]
+pub(crate) mod mock {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+    use tokio::net::TcpListener;
+    use tokio::task::JoinHandle;
+
+    pub(crate) struct MockBootstrap {
+        pub addr: SocketAddr,
+        pub registry: Arc<Mutex<BootstrapRegistry>>,
+        task: JoinHandle<()>,
+    }
+
+    impl MockBootstrap {
+        pub async fn start() -> Self {
+            let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let addr = listener.local_addr().unwrap();
+            let registry = Arc::new(Mutex::new(BootstrapRegistry::default()));
+
+            let shared = registry.clone();
+            let task = tokio::spawn(async move {
+                while let Ok((stream, from)) = listener.accept().await {
+                    let registry = shared.clone();
+                    tokio::spawn(async move {
+                        let mut framed = FramedStream::new(stream, MAX_BOOTSTRAP_MESSAGE);
+                        let Ok(bytes) = framed.recv().await else {
+                            return;
+                        };
+                        let response = match decode::<BootstrapRequest>(&bytes) {
+                            Ok(request) => {
+                                registry
+                                    .lock()
+                                    .unwrap()
+                                    .handle(request, from, Instant::now())
+                            }
+                            Err(e) => BootstrapResponse::Refused(e.to_string()),
+                        };
+                        let _ = framed.send(&encode(&response).unwrap()).await;
+                    });
+                }
+            });
+
+            Self {
+                addr,
+                registry,
+                task,
+            }
+        }
+
+        pub fn registrations(&self) -> Vec<Registration> {
+            self.registry.lock().unwrap().registrations()
+        }
+    }
+
+    impl Drop for MockBootstrap {
+        fn drop(&mut self) {
+            self.task.abort();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::net::TcpListener;
+
+    async fn dead_address() -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        listener.local_addr().unwrap().to_string()
+    }
+
+    #[tokio::test]
+    async fn test_falls_back_to_second_server() {
+        let dead = dead_address().await;
+        let live = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let live_addr = live.local_addr().unwrap().to_string();
+
+        let mut list = BootstrapList::new(vec![dead, live_addr.clone()]);
+        let attempt_timeout = Duration::from_millis(500);
+
+        let (server, _stream) =
+            tokio::time::timeout(attempt_timeout * 2, list.connect(attempt_timeout))
+                .await
+                .expect("fallback took longer than two attempt timeouts")
+                .unwrap();
+
+        assert_eq!(server, live_addr);
+        assert_eq!(list.active(), Some(live_addr.as_str()));
+    }
+
+    #[tokio::test]
+    async fn test_all_dead_fails() {
+        let mut list = BootstrapList::new(vec![dead_address().await, dead_address().await]);
+        assert!(list.connect(Duration::from_millis(200)).await.is_err());
+    }
+
+    #[test]
+    fn test_rotate_wraps() {
+        let mut list = BootstrapList::new(vec!["a:1".into(), "b:1".into()]);
+        assert_eq!(list.active(), Some("a:1"));
+        list.rotate();
+        assert_eq!(list.active(), Some("b:1"));
+        list.rotate();
+        assert_eq!(list.active(), Some("a:1"));
+        assert_eq!(BootstrapList::new(Vec::new()).active(), None);
+    }
+
+    fn registration(
+        keypair: &KeyPair,
+        addrs: &[&str],
+        games: &[&str],
+        timestamp_ms: u64,
+    ) -> Signed<Registration> {
+        let body = Registration {
+            player_id: keypair.public_key(),
+            addrs: addrs.iter().map(|addr| addr.parse().unwrap()).collect(),
+            games: games.iter().map(|g| g.to_string()).collect(),
+            timestamp_ms,
+        };
+        Signed::sign(keypair, body).unwrap()
+    }
+
+    #[test]
+    fn test_registry_rejects_forged_and_stale_registrations() {
+        let alice = KeyPair::generate();
+        let mallory = KeyPair::generate();
+        let from: SocketAddr = "10.0.0.1:5000".parse().unwrap();
+        let now = Instant::now();
+        let mut registry = BootstrapRegistry::default();
+
+        // Mallory signs a registration claiming to be Alice
+        let mut forged = registration(&mallory, &["10.6.6.6:9000"], &["game"], 1);
+        forged.body.player_id = alice.public_key();
+        assert!(matches!(
+            registry.handle(BootstrapRequest::Register(forged), from, now),
+            BootstrapResponse::Refused(_)
+        ));
+
+        let fresh = registration(&alice, &["10.0.0.1:9000"], &["game"], 2);
+        assert_eq!(
+            registry.handle(BootstrapRequest::Register(fresh), from, now),
+            BootstrapResponse::Registered {
+                ttl: REGISTRATION_TTL
+            }
+        );
+        let stale = registration(&alice, &["10.0.0.1:9000"], &[], 1);
+        assert!(matches!(
+            registry.handle(BootstrapRequest::Register(stale), from, now),
+            BootstrapResponse::Refused(_)
+        ));
+        assert_eq!(registry.registrations().len(), 1);
+
+        // Entries expire without a refresh
+        let query = Signed::sign(
+            &mallory,
+            PeerQuery {
+                player_id: mallory.public_key(),
+                game_id: "game".to_string(),
+                timestamp_ms: 3,
+            },
+        )
+        .unwrap();
+        match registry.handle(BootstrapRequest::Query(query.clone()), from, now) {
+            BootstrapResponse::Peers(peers) => assert_eq!(peers.len(), 1),
+            other => panic!("unexpected {:?}", other),
+        }
+        match registry.handle(BootstrapRequest::Query(query), from, now + REGISTRATION_TTL) {
+            BootstrapResponse::Peers(peers) => assert!(peers.is_empty()),
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_listed_peer_verification() {
+        let alice = KeyPair::generate();
+        let listed = ListedPeer {
+            registration: registration(
+                &alice,
+                &["0.0.0.0:9000", "[::]:9000", "[2001:db8::7]:9001"],
+                &["game"],
+                1,
+            ),
+            observed_ip: "203.0.113.7".parse().unwrap(),
+        };
+        // The IPv6 wildcard cannot take an IPv4 observed address
+        assert_eq!(
+            listed.verify().unwrap(),
+            PeerRecord {
+                player_id: alice.public_key(),
+                addrs: vec![
+                    "203.0.113.7:9000".parse().unwrap(),
+                    "[2001:db8::7]:9001".parse().unwrap(),
+                ],
+            }
+        );
+
+        // A server rewriting an address breaks the signature
+        let mut rewritten = listed.clone();
+        rewritten.registration.body.addrs[2] = "[2001:db8::666]:9001".parse().unwrap();
+        assert!(rewritten.verify().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_register_and_query_mock_server() {
+        let server = mock::MockBootstrap::start().await;
+        let servers = || BootstrapList::new(vec![server.addr.to_string()]);
+        let timeout = Duration::from_secs(1);
+
+        let alice = KeyPair::generate();
+        let mut alice_client = BootstrapClient::new(servers(), alice.clone());
+        let ttl = alice_client
+            .register(
+                vec!["0.0.0.0:7001".parse().unwrap()],
+                vec!["game".into()],
+                timeout,
+            )
+            .await
+            .unwrap();
+        assert_eq!(ttl, REGISTRATION_TTL);
+
+        let mut bob_client = BootstrapClient::new(servers(), KeyPair::generate());
+        bob_client
+            .register(
+                vec!["127.0.0.1:7002".parse().unwrap()],
+                vec!["other".into()],
+                timeout,
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(
+            bob_client.query("game", timeout).await.unwrap(),
+            vec![PeerRecord::at(
+                alice.public_key(),
+                "127.0.0.1:7001".parse().unwrap()
+            )]
+        );
+        assert!(
+            alice_client
+                .query("game", timeout)
+                .await
+                .unwrap()
+                .is_empty()
+        );
+        assert_eq!(server.registrations().len(), 2);
+    }
+}
@@ -0,0 +1,778 @@
+// network/codec.rs - Encoding peer messages for the wire
+
+use super::handshake::{CloseCode, SUPPORTED_PROTOCOLS};
+use super::message::PeerMessage;
+use crate::error::{Result, SwarmhostError};
+use crate::node::WireFormat;
+use bytes::Bytes;
+use std::fmt;
+
+/// Turns peer messages into frames and back for one connection
+///
+/// A codec is built for the wire format and protocol version agreed in the
+/// handshake, so future versions can change encodings per connection.
+pub trait MessageCodec: Send + Sync {
+    fn format(&self) -> WireFormat;
+
+    fn encode(&self, message: &PeerMessage) -> Result<Bytes>;
+
+    /// Parse a frame, treating garbage as a protocol violation
+    fn decode(&self, bytes: &[u8]) -> Result<PeerMessage>;
+}
+
+/// Formats we are willing to speak, most preferred first
+///
+/// The configured format leads, followed by any other compiled in, so two
+/// peers with different preferences still agree on something.
+pub fn offered_formats(preferred: WireFormat) -> Vec<WireFormat> {
+    let mut formats = vec![preferred];
+    for format in [WireFormat::Bincode, WireFormat::Protobuf] {
+        if format != preferred && available(format) {
+            formats.push(format);
+        }
+    }
+    formats
+}
+
+/// First of the initiator's formats the responder also speaks
+pub fn negotiate(initiator: &[WireFormat], responder: &[WireFormat]) -> Option<WireFormat> {
+    initiator
+        .iter()
+        .find(|format| responder.contains(format))
+        .copied()
+}
+
+/// Codec for a connection that agreed on `format` and protocol `version`
+pub fn codec_for(format: WireFormat, version: u16) -> Result<Box<dyn MessageCodec>> {
+    if !SUPPORTED_PROTOCOLS.contains(version) {
+        return Err(SwarmhostError::Peer(format!(
+            "No message encoding for protocol {}",
+            version
+        )));
+    }
+    match format {
+        WireFormat::Bincode => Ok(Box::new(BincodeCodec)),
+        #[cfg(feature = "protobuf")]
+        WireFormat::Protobuf => Ok(Box::new(super::protobuf::ProtobufCodec)),
+        #[cfg(not(feature = "protobuf"))]
+        WireFormat::Protobuf => Err(SwarmhostError::Config(
+            "Protobuf wire format needs the 'protobuf' feature".to_string(),
+        )),
+    }
+}
+
+fn available(format: WireFormat) -> bool {
+    match format {
+        WireFormat::Bincode => true,
+        WireFormat::Protobuf => cfg!(feature = "protobuf"),
+    }
+}
+
+/// The error for a frame that does not decode
+pub(super) fn malformed(reason: impl fmt::Display) -> SwarmhostError {
+    SwarmhostError::handshake(
+        CloseCode::ProtocolError,
+        format!("bad peer message: {}", reason),
+    )
+}
+
+/// serde/bincode encoding of [`PeerMessage`]; the default
+pub struct BincodeCodec;
+
+impl MessageCodec for BincodeCodec {
+    fn format(&self) -> WireFormat {
+        WireFormat::Bincode
+    }
+
+    fn encode(&self, message: &PeerMessage) -> Result<Bytes> {
+        let encoded = bincode::serialize(message)
+            .map_err(|e| SwarmhostError::Serialization(e.to_string()))?;
+        Ok(Bytes::from(encoded))
+    }
+
+    fn decode(&self, bytes: &[u8]) -> Result<PeerMessage> {
+        bincode::deserialize(bytes).map_err(malformed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::consensus::{
+        BlockHeader, CertifiedCommits, Commit, Decision, Equivocation, NewView, ObserverVote,
+        Prepared, RejectCode, RoundProposal, SignedAction, TimeoutVote, ViewChange, Vote,
+    };
+    use crate::error::ErrorCode;
+    use crate::network::bootstrap::PeerRecord;
+    use crate::network::fragment::Fragment;
+    use crate::network::gossip::{GossipMessage, GossipPayload};
+    use crate::network::message::CommitMark;
+    use crate::network::mux::Stream;
+    use crate::network::outbound::Priority;
+    use crate::network::pex::{PexEntry, PexSample};
+    use crate::network::punch::PunchSignal;
+    use crate::network::relay::RelayOffer;
+    use crate::network::remote::RemoteError;
+    use crate::network::resume::ResumptionToken;
+    use crate::network::trace::TraceContext;
+    use crate::state::{
+        Checkpoint, CheckpointSignature, CheckpointVote, SnapshotAttestation, SnapshotBase,
+        SnapshotChunk, SyncPlan,
+    };
+    use proptest::prelude::*;
+    use std::net::{IpAddr, SocketAddr};
+
+    fn bytes() -> impl Strategy<Value = Vec<u8>> {
+        prop::collection::vec(any::<u8>(), 0..64)
+    }
+
+    fn addr() -> impl Strategy<Value = SocketAddr> {
+        (any::<IpAddr>(), any::<u16>()).prop_map(SocketAddr::from)
+    }
+
+    fn addrs() -> impl Strategy<Value = Vec<SocketAddr>> {
+        prop::collection::vec(addr(), 0..3)
+    }
+
+    fn contacts() -> impl Strategy<Value = Vec<PeerRecord>> {
+        let contact = (any::<[u8; 32]>(), addrs())
+            .prop_map(|(player_id, addrs)| PeerRecord { player_id, addrs });
+        prop::collection::vec(contact, 0..8)
+    }
+
+    fn close_code() -> impl Strategy<Value = CloseCode> {
+        prop_oneof![
+            Just(CloseCode::Leaving),
+            Just(CloseCode::Shutdown),
+            Just(CloseCode::Kicked),
+            Just(CloseCode::ProtocolError),
+        ]
+    }
+
+    fn remote_error() -> impl Strategy<Value = RemoteError> {
+        (
+            prop::sample::select(ErrorCode::ALL.to_vec()),
+            any::<String>(),
+            prop::option::of(any::<[u8; 32]>()),
+            prop::option::of(any::<String>()),
+        )
+            .prop_map(|(code, message, action_id, game_id)| RemoteError {
+                code,
+                category: code.category(),
+                message,
+                action_id,
+                game_id,
+            })
+    }
+
+    fn pex_sample() -> impl Strategy<Value = PexSample> {
+        let entry =
+            (any::<[u8; 32]>(), addrs(), any::<u64>()).prop_map(|(player_id, addrs, age_ms)| {
+                PexEntry {
+                    player_id,
+                    addrs,
+                    age_ms,
+                }
+            });
+        (
+            any::<[u8; 32]>(),
+            addrs(),
+            prop::collection::vec(entry, 0..8),
+            bytes(),
+        )
+            .prop_map(|(from, addrs, entries, signature)| PexSample {
+                from,
+                addrs,
+                entries,
+                signature,
+            })
+    }
+
+    fn class() -> impl Strategy<Value = Priority> {
+        prop::sample::select(Priority::ALL.to_vec())
+    }
+
+    fn punch_signal() -> impl Strategy<Value = PunchSignal> {
+        prop_oneof![
+            (any::<u64>(), addr()).prop_map(|(nonce, addr)| PunchSignal::Offer { nonce, addr }),
+            (any::<u64>(), addr()).prop_map(|(nonce, addr)| PunchSignal::Answer { nonce, addr }),
+            any::<u64>().prop_map(|nonce| PunchSignal::Unreachable { nonce }),
+        ]
+    }
+
+    fn action() -> impl Strategy<Value = SignedAction> {
+        (
+            any::<String>(),
+            any::<[u8; 32]>(),
+            any::<u64>(),
+            any::<u32>(),
+            bytes(),
+            prop::collection::vec(any::<u64>(), 0..4),
+            any::<Option<u64>>(),
+            prop::collection::vec(any::<[u8; 32]>(), 0..3),
+            bytes(),
+        )
+            .prop_map(
+                |(
+                    game_id,
+                    actor,
+                    nonce,
+                    action_type,
+                    data,
+                    conflict_keys,
+                    deadline_ms,
+                    depends_on,
+                    signature,
+                )| {
+                    SignedAction {
+                        game_id,
+                        actor,
+                        nonce,
+                        action_type,
+                        data,
+                        conflict_keys,
+                        deadline_ms,
+                        depends_on,
+                        signature,
+                    }
+                },
+            )
+    }
+
+    fn commit() -> impl Strategy<Value = Commit> {
+        (
+            any::<u64>(),
+            action(),
+            any::<[u8; 32]>(),
+            bytes(),
+            prop::collection::vec(vote(), 0..3),
+        )
+            .prop_map(
+                |(sequence, action, sequencer, signature, certificate)| Commit {
+                    sequence,
+                    action,
+                    sequencer,
+                    signature,
+                    certificate,
+                },
+            )
+    }
+
+    fn vote() -> impl Strategy<Value = Vote> {
+        let decision = prop_oneof![
+            Just((Decision::Approve, None)),
+            Just((Decision::Reject, None)),
+            (1..=u32::MAX).prop_map(|code| (Decision::Reject, Some(RejectCode(code)))),
+        ];
+        (
+            any::<[u8; 32]>(),
+            any::<u64>(),
+            any::<[u8; 32]>(),
+            decision,
+            bytes(),
+        )
+            .prop_map(
+                |(action_id, round, voter, (decision, reason), signature)| Vote {
+                    action_id,
+                    round,
+                    voter,
+                    decision,
+                    reason,
+                    signature,
+                },
+            )
+    }
+
+    fn round() -> impl Strategy<Value = RoundProposal> {
+        (
+            any::<u64>(),
+            any::<[u8; 32]>(),
+            prop::option::of(any::<[u8; 32]>()),
+            prop::collection::vec(action(), 0..4),
+            bytes(),
+        )
+            .prop_map(
+                |(round, proposer, parent, actions, signature)| RoundProposal {
+                    round,
+                    proposer,
+                    parent,
+                    actions,
+                    signature,
+                },
+            )
+    }
+
+    fn snapshot_chunk() -> impl Strategy<Value = SnapshotChunk> {
+        (
+            any::<String>(),
+            any::<u64>(),
+            any::<[u8; 32]>(),
+            any::<u32>(),
+            any::<u64>(),
+            any::<[u8; 32]>(),
+            bytes(),
+        )
+            .prop_map(
+                |(game_id, sequence, state_hash, index, offset, checksum, data)| SnapshotChunk {
+                    game_id,
+                    sequence,
+                    state_hash,
+                    index,
+                    offset,
+                    checksum,
+                    data,
+                },
+            )
+    }
+
+    fn sync_plan() -> impl Strategy<Value = SyncPlan> {
+        (
+            any::<String>(),
+            any::<u64>(),
+            any::<[u8; 32]>(),
+            any::<u64>(),
+            any::<u32>(),
+            prop::collection::vec(any::<[u8; 32]>(), 0..4),
+            prop::option::of(attestation()),
+            prop::option::of(any::<u64>()),
+        )
+            .prop_map(
+                |(
+                    game_id,
+                    sequence,
+                    state_hash,
+                    size,
+                    chunk_size,
+                    checksums,
+                    attestation,
+                    delta_from,
+                )| {
+                    SyncPlan {
+                        game_id,
+                        sequence,
+                        state_hash,
+                        size,
+                        chunk_size,
+                        checksums,
+                        attestation,
+                        delta_from,
+                    }
+                },
+            )
+    }
+
+    fn snapshot_base() -> impl Strategy<Value = SnapshotBase> {
+        (any::<u64>(), any::<[u8; 32]>())
+            .prop_map(|(sequence, checksum)| SnapshotBase { sequence, checksum })
+    }
+
+    fn mark() -> impl Strategy<Value = CommitMark> {
+        (
+            any::<String>(),
+            any::<u64>(),
+            any::<[u8; 32]>(),
+            prop::option::of(any::<[u8; 32]>()),
+        )
+            .prop_map(|(game_id, sequence, action_id, state_hash)| CommitMark {
+                game_id,
+                sequence,
+                action_id,
+                state_hash,
+            })
+    }
+
+    fn checkpoint() -> impl Strategy<Value = Checkpoint> {
+        let signed = (any::<[u8; 32]>(), bytes())
+            .prop_map(|(signer, signature)| CheckpointSignature { signer, signature });
+        (
+            any::<String>(),
+            any::<u64>(),
+            any::<[u8; 32]>(),
+            any::<[u8; 32]>(),
+            prop::collection::vec(signed, 0..3),
+        )
+            .prop_map(
+                |(game_id, sequence, state_hash, log_root, signatures)| Checkpoint {
+                    game_id,
+                    sequence,
+                    state_hash,
+                    log_root,
+                    signatures,
+                },
+            )
+    }
+
+    fn attestation() -> impl Strategy<Value = SnapshotAttestation> {
+        (checkpoint(), any::<[u8; 32]>(), bytes()).prop_map(|(checkpoint, producer, signature)| {
+            SnapshotAttestation {
+                checkpoint,
+                producer,
+                signature,
+            }
+        })
+    }
+
+    fn certified() -> impl Strategy<Value = CertifiedCommits> {
+        let block = (
+            any::<u64>(),
+            any::<[u8; 32]>(),
+            prop::option::of(any::<[u8; 32]>()),
+            prop::collection::vec(any::<[u8; 32]>(), 0..3),
+        )
+            .prop_map(|(round, proposer, parent, actions)| BlockHeader {
+                round,
+                proposer,
+                parent,
+                actions,
+            });
+        (
+            prop::collection::vec(commit(), 0..3),
+            prop::collection::vec(block, 0..2),
+            prop::collection::vec(vote(), 0..3),
+        )
+            .prop_map(|(commits, blocks, votes)| CertifiedCommits {
+                commits,
+                blocks,
+                votes,
+            })
+    }
+
+    fn view_change() -> impl Strategy<Value = ViewChange> {
+        let prepared = (
+            action(),
+            prop::collection::vec(vote(), 0..3),
+            prop::option::of(commit()),
+        )
+            .prop_map(|(action, votes, commit)| Prepared {
+                action,
+                votes,
+                commit,
+            });
+        (
+            any::<u64>(),
+            any::<[u8; 32]>(),
+            prop::collection::vec(("[a-z]{0,8}", any::<u64>()), 0..3),
+            prop::collection::vec(prepared, 0..3),
+            bytes(),
+        )
+            .prop_map(|(view, voter, committed, prepared, signature)| ViewChange {
+                view,
+                voter,
+                committed,
+                prepared,
+                signature,
+            })
+    }
+
+    fn gossip() -> impl Strategy<Value = GossipMessage> {
+        // Built first, before the names of its parts are shadowed
+        let evidence = prop_oneof![
+            (vote(), vote()).prop_map(|(first, second)| Equivocation::Votes(first, second)),
+            (round(), round()).prop_map(|(first, second)| Equivocation::Rounds(first, second)),
+            (commit(), commit()).prop_map(|(first, second)| {
+                Equivocation::Commits(Box::new(first), Box::new(second))
+            }),
+        ]
+        .prop_map(|evidence| GossipPayload::Evidence(Box::new(evidence)));
+        let proposal = action().prop_map(GossipPayload::Proposal);
+        let unsequenced = action().prop_map(GossipPayload::Unsequenced);
+        let observer_vote = vote().prop_map(|vote| {
+            GossipPayload::ObserverVote(ObserverVote {
+                action_id: vote.action_id,
+                round: vote.round,
+                observer: vote.voter,
+                decision: vote.decision,
+                reason: vote.reason,
+                signature: vote.signature,
+            })
+        });
+        let vote = vote().prop_map(GossipPayload::Vote);
+        let change = view_change().prop_map(GossipPayload::ViewChange);
+        let new_view = (
+            any::<u64>(),
+            any::<[u8; 32]>(),
+            prop::collection::vec(view_change(), 0..3),
+            prop::collection::vec(commit(), 0..3),
+            bytes(),
+        )
+            .prop_map(|(view, leader, view_changes, commits, signature)| {
+                GossipPayload::NewView(NewView {
+                    view,
+                    leader,
+                    view_changes,
+                    commits,
+                    signature,
+                })
+            });
+        let checkpoint_vote = (
+            any::<String>(),
+            any::<u64>(),
+            any::<[u8; 32]>(),
+            any::<[u8; 32]>(),
+            any::<[u8; 32]>(),
+            bytes(),
+        )
+            .prop_map(
+                |(game_id, sequence, state_hash, log_root, signer, signature)| {
+                    GossipPayload::CheckpointVote(CheckpointVote {
+                        game_id,
+                        sequence,
+                        state_hash,
+                        log_root,
+                        signer,
+                        signature,
+                    })
+                },
+            );
+        let checkpoint = checkpoint().prop_map(GossipPayload::Checkpoint);
+        let commit = commit().prop_map(GossipPayload::Commit);
+        let round = round().prop_map(GossipPayload::Round);
+        let timeout =
+            (any::<u64>(), any::<[u8; 32]>(), bytes()).prop_map(|(round, voter, signature)| {
+                GossipPayload::Timeout(TimeoutVote {
+                    round,
+                    voter,
+                    signature,
+                })
+            });
+        (
+            any::<u8>(),
+            prop_oneof![
+                proposal,
+                vote,
+                commit,
+                round,
+                timeout,
+                change,
+                new_view,
+                evidence,
+                checkpoint_vote,
+                checkpoint,
+                unsequenced,
+                observer_vote
+            ],
+        )
+            .prop_map(|(hops_left, payload)| GossipMessage { hops_left, payload })
+    }
+
+    /// Every message variant, with arbitrary contents
+    fn message() -> impl Strategy<Value = PeerMessage> {
+        prop_oneof![
+            (any::<u64>(), any::<u64>(), prop::option::of(mark())).prop_map(
+                |(nonce, sent_at_ms, mark)| PeerMessage::Ping {
+                    nonce,
+                    sent_at_ms,
+                    mark
+                }
+            ),
+            (any::<u64>(), any::<u64>(), prop::option::of(mark())).prop_map(
+                |(nonce, sent_at_ms, mark)| PeerMessage::Pong {
+                    nonce,
+                    sent_at_ms,
+                    mark
+                }
+            ),
+            gossip().prop_map(PeerMessage::Gossip),
+            (any::<[u8; 32]>(), punch_signal())
+                .prop_map(|(to, signal)| PeerMessage::Signal { to, signal }),
+            (any::<[u8; 32]>(), punch_signal())
+                .prop_map(|(from, signal)| PeerMessage::Signaled { from, signal }),
+            (any::<u32>(), any::<u64>()).prop_map(|(max_sessions, session_bandwidth)| {
+                PeerMessage::RelayOffer(RelayOffer {
+                    max_sessions,
+                    session_bandwidth,
+                })
+            }),
+            (any::<u64>(), any::<[u8; 32]>())
+                .prop_map(|(session, to)| PeerMessage::RelayOpen { session, to }),
+            (any::<u64>(), any::<[u8; 32]>())
+                .prop_map(|(session, from)| PeerMessage::RelayIncoming { session, from }),
+            (any::<u64>(), bytes())
+                .prop_map(|(session, payload)| PeerMessage::RelayData { session, payload }),
+            any::<u64>().prop_map(|session| PeerMessage::RelayClose { session }),
+            (
+                any::<u64>(),
+                any::<u32>(),
+                any::<u32>(),
+                any::<u32>(),
+                bytes()
+            )
+                .prop_map(|(transfer, index, total, checksum, data)| {
+                    PeerMessage::Fragment(Fragment {
+                        transfer,
+                        index,
+                        total,
+                        checksum,
+                        data,
+                    })
+                }),
+            (any::<u64>(), prop::collection::vec(any::<u32>(), 0..16))
+                .prop_map(|(transfer, missing)| PeerMessage::FragmentRequest { transfer, missing }),
+            (prop::option::of(any::<String>()), any::<u64>())
+                .prop_map(|(game_id, committed)| PeerMessage::Playing { game_id, committed }),
+            (class(), bytes()).prop_map(|(class, payload)| PeerMessage::Direct { class, payload }),
+            (any::<String>(), class(), bytes()).prop_map(|(game_id, class, payload)| {
+                PeerMessage::Broadcast {
+                    game_id,
+                    class,
+                    payload,
+                }
+            }),
+            (any::<u64>(), bytes()).prop_map(|(id, payload)| PeerMessage::Request { id, payload }),
+            (any::<u64>(), bytes()).prop_map(|(id, payload)| PeerMessage::Response { id, payload }),
+            addr().prop_map(|addr| PeerMessage::DhtHello { addr }),
+            (any::<u64>(), any::<[u8; 32]>())
+                .prop_map(|(id, target)| PeerMessage::DhtFindNode { id, target }),
+            (any::<u64>(), any::<[u8; 32]>())
+                .prop_map(|(id, key)| PeerMessage::DhtFindProviders { id, key }),
+            (any::<u64>(), contacts(), contacts()).prop_map(|(id, closer, providers)| {
+                PeerMessage::DhtFound {
+                    id,
+                    closer,
+                    providers,
+                }
+            }),
+            (any::<[u8; 32]>(), addr())
+                .prop_map(|(key, addr)| PeerMessage::DhtProvide { key, addr }),
+            bytes().prop_map(PeerMessage::Batch),
+            pex_sample().prop_map(PeerMessage::Pex),
+            close_code().prop_map(|code| PeerMessage::GoAway { code }),
+            Just(PeerMessage::GoAwayAck),
+            (any::<[u8; 16]>(), any::<[u8; 32]>())
+                .prop_map(|(id, mac)| PeerMessage::Resume(ResumptionToken { id, mac })),
+            (prop::sample::select(Stream::ALL.to_vec()), any::<u32>())
+                .prop_map(|(stream, bytes)| PeerMessage::Credit { stream, bytes }),
+            (
+                any::<u64>(),
+                prop::option::of(any::<[u8; 32]>()),
+                class(),
+                bytes()
+            )
+                .prop_map(|(correlation, action, class, payload)| {
+                    PeerMessage::Traced {
+                        trace: TraceContext {
+                            correlation,
+                            action,
+                        },
+                        message: Box::new(PeerMessage::Direct { class, payload }),
+                    }
+                }),
+            (any::<String>(), any::<u64>(), any::<u32>()).prop_map(|(game_id, from, count)| {
+                PeerMessage::FetchCommits {
+                    game_id,
+                    from,
+                    count,
+                }
+            }),
+            prop::collection::vec(commit(), 0..4).prop_map(PeerMessage::Commits),
+            action().prop_map(PeerMessage::Forward),
+            (
+                any::<u64>(),
+                any::<String>(),
+                prop::option::of(snapshot_base())
+            )
+                .prop_map(|(id, game_id, base)| PeerMessage::FetchSyncPlan {
+                    id,
+                    game_id,
+                    base
+                }),
+            (any::<u64>(), sync_plan()).prop_map(|(id, plan)| PeerMessage::SyncPlan { id, plan }),
+            (
+                any::<u64>(),
+                any::<String>(),
+                any::<u64>(),
+                prop::option::of(any::<u64>()),
+                any::<u32>()
+            )
+                .prop_map(|(id, game_id, sequence, delta_from, chunk)| {
+                    PeerMessage::FetchSnapshot {
+                        id,
+                        game_id,
+                        sequence,
+                        delta_from,
+                        chunk,
+                    }
+                }),
+            (any::<u64>(), prop::option::of(snapshot_chunk()))
+                .prop_map(|(id, chunk)| PeerMessage::Snapshot { id, chunk }),
+            (any::<u64>(), any::<String>(), any::<u64>(), any::<u32>()).prop_map(
+                |(id, game_id, from, count)| PeerMessage::FetchCertified {
+                    id,
+                    game_id,
+                    from,
+                    count,
+                }
+            ),
+            (any::<u64>(), certified())
+                .prop_map(|(id, commits)| PeerMessage::Certified { id, commits }),
+            (any::<u64>(), any::<u64>())
+                .prop_map(|(round, have)| PeerMessage::GetVotes { round, have }),
+            prop::collection::vec(vote(), 0..4).prop_map(PeerMessage::Votes),
+            (any::<u64>(), any::<String>(), any::<u64>()).prop_map(|(id, game_id, sequence)| {
+                PeerMessage::FetchStateHash {
+                    id,
+                    game_id,
+                    sequence,
+                }
+            }),
+            (any::<u64>(), prop::option::of(any::<[u8; 32]>()))
+                .prop_map(|(id, state_hash)| PeerMessage::StateHash { id, state_hash }),
+            any::<u32>().prop_map(|retry_after_ms| PeerMessage::Busy { retry_after_ms }),
+            (prop::option::of(any::<u64>()), remote_error())
+                .prop_map(|(id, error)| PeerMessage::Error { id, error }),
+        ]
+    }
+
+    fn codec(format: WireFormat) -> Box<dyn MessageCodec> {
+        codec_for(format, SUPPORTED_PROTOCOLS.max).unwrap()
+    }
+
+    proptest! {
+        #[test]
+        fn test_bincode_round_trips_every_message(message in message()) {
+            let codec = codec(WireFormat::Bincode);
+            let encoded = codec.encode(&message).unwrap();
+            prop_assert_eq!(codec.decode(&encoded).unwrap(), message);
+        }
+
+        #[cfg(feature = "protobuf")]
+        #[test]
+        fn test_protobuf_round_trips_every_message(message in message()) {
+            let codec = codec(WireFormat::Protobuf);
+            let encoded = codec.encode(&message).unwrap();
+            prop_assert_eq!(codec.decode(&encoded).unwrap(), message);
+        }
+    }
+
+    #[test]
+    fn test_negotiation_prefers_initiator_order() {
+        use WireFormat::*;
+        assert_eq!(
+            negotiate(&[Protobuf, Bincode], &[Bincode, Protobuf]),
+            Some(Protobuf)
+        );
+        assert_eq!(
+            negotiate(&[Bincode, Protobuf], &[Protobuf, Bincode]),
+            Some(Bincode)
+        );
+        assert_eq!(negotiate(&[Protobuf, Bincode], &[Bincode]), Some(Bincode));
+        assert_eq!(negotiate(&[Protobuf], &[Bincode]), None);
+
+        assert_eq!(offered_formats(Bincode)[0], Bincode);
+        assert_eq!(
+            offered_formats(Bincode).contains(&Protobuf),
+            cfg!(feature = "protobuf")
+        );
+    }
+
+    #[test]
+    fn test_garbage_is_a_protocol_error() {
+        match codec(WireFormat::Bincode).decode(&[0xff; 3]) {
+            Err(SwarmhostError::Handshake { code, .. }) => {
+                assert_eq!(code, CloseCode::ProtocolError)
+            }
+            other => panic!("expected protocol error, got {:?}", other),
+        }
+        assert!(codec_for(WireFormat::Bincode, SUPPORTED_PROTOCOLS.max + 1).is_err());
+    }
+}
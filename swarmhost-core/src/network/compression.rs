@@ -0,0 +1,239 @@
+// network/compression.rs - Per-message compression
+
+use crate::error::{Result, SwarmhostError};
+use crate::node::{CompressionAlgorithm, CompressionConfig, NodeMetrics};
+use serde::{Deserialize, Serialize};
+
+/// Bytes the compression header adds in front of every message
+pub const HEADER_LEN: usize = 1;
+
+const FLAG_RAW: u8 = 0;
+const FLAG_COMPRESSED: u8 = 1;
+
+/// Default zstd level when the peer picked zstd but we are configured for lz4
+const DEFAULT_ZSTD_LEVEL: i32 = 3;
+
+/// Compression codec as announced in the handshake
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Codec {
+    Lz4,
+    Zstd,
+}
+
+/// Codecs we are willing to use, most preferred first
+///
+/// The configured algorithm leads; the other built-in codec follows so that
+/// two peers with different preferences still agree on something. Disabling
+/// compression offers nothing.
+pub fn offered_codecs(config: &CompressionConfig) -> Vec<Codec> {
+    match config.algorithm {
+        CompressionAlgorithm::None => Vec::new(),
+        CompressionAlgorithm::Lz4 => vec![Codec::Lz4, Codec::Zstd],
+        CompressionAlgorithm::Zstd { .. } => vec![Codec::Zstd, Codec::Lz4],
+    }
+}
+
+/// First of the initiator's codecs the responder also supports
+pub fn negotiate(initiator: &[Codec], responder: &[Codec]) -> Option<Codec> {
+    initiator
+        .iter()
+        .find(|codec| responder.contains(codec))
+        .copied()
+}
+
+/// Adds or strips the compression header on each message of a connection
+pub struct Compressor {
+    codec: Option<Codec>,
+    zstd_level: i32,
+    min_size: usize,
+}
+
+impl Compressor {
+    pub fn new(codec: Option<Codec>, config: &CompressionConfig) -> Self {
+        let zstd_level = match config.algorithm {
+            CompressionAlgorithm::Zstd { level } => level,
+            _ => DEFAULT_ZSTD_LEVEL,
+        };
+
+        Self {
+            codec,
+            zstd_level,
+            min_size: config.min_size,
+        }
+    }
+
+    /// Codec agreed for this connection, or `None` if compression is off
+    pub fn codec(&self) -> Option<Codec> {
+        self.codec
+    }
+
+    /// Prefix `payload` with its header, compressing it when worthwhile
+    ///
+    /// Messages under `min_size` are never compressed, and neither are those
+    /// that compression would not shrink.
+    pub fn encode(&self, payload: &[u8], metrics: Option<&NodeMetrics>) -> Result<Vec<u8>> {
+        let compressed = match self.codec {
+            Some(codec) if payload.len() >= self.min_size => {
+                Some(self.compress(codec, payload)?).filter(|c| c.len() < payload.len())
+            }
+            _ => None,
+        };
+
+        let mut out = Vec::with_capacity(HEADER_LEN + payload.len());
+        match compressed {
+            Some(body) => {
+                if let Some(metrics) = metrics {
+                    metrics.compression_input_bytes.add(payload.len() as u64);
+                    metrics.compression_output_bytes.add(body.len() as u64);
+                }
+                out.push(FLAG_COMPRESSED);
+                out.extend_from_slice(&body);
+            }
+            None => {
+                if let Some(metrics) = metrics {
+                    metrics.uncompressed_bytes.add(payload.len() as u64);
+                }
+                out.push(FLAG_RAW);
+                out.extend_from_slice(payload);
+            }
+        }
+        Ok(out)
+    }
+
+    /// Strip the header and decompress, refusing output over `max_size`
+    pub fn decode(&self, message: &[u8], max_size: usize) -> Result<Vec<u8>> {
+        let (&flag, body) = message
+            .split_first()
+            .ok_or_else(|| compression_error("empty message"))?;
+
+        match (flag, self.codec) {
+            (FLAG_RAW, _) => Ok(body.to_vec()),
+            (FLAG_COMPRESSED, Some(codec)) => decompress(codec, body, max_size),
+            (FLAG_COMPRESSED, None) => Err(compression_error(
+                "compressed message but no codec was negotiated",
+            )),
+            (flag, _) => Err(compression_error(format!("unknown flag {}", flag))),
+        }
+    }
+
+    fn compress(&self, codec: Codec, payload: &[u8]) -> Result<Vec<u8>> {
+        match codec {
+            Codec::Lz4 => Ok(lz4_flex::compress_prepend_size(payload)),
+            Codec::Zstd => zstd::bulk::compress(payload, self.zstd_level)
+                .map_err(|e| compression_error(format!("zstd: {}", e))),
+        }
+    }
+}
+
+fn decompress(codec: Codec, body: &[u8], max_size: usize) -> Result<Vec<u8>> {
+    match codec {
+        Codec::Lz4 => {
+            // Check the size prefix before lz4 allocates the output buffer
+            let declared = body
+                .get(..4)
+                .map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]) as usize)
+                .ok_or_else(|| compression_error("lz4 body too short"))?;
+            if declared > max_size {
+                return Err(compression_error(format!(
+                    "decompressed size {} exceeds max_message_size {}",
+                    declared, max_size
+                )));
+            }
+            lz4_flex::decompress_size_prepended(body)
+                .map_err(|e| compression_error(format!("lz4: {}", e)))
+        }
+        Codec::Zstd => zstd::bulk::decompress(body, max_size)
+            .map_err(|e| compression_error(format!("zstd: {}", e))),
+    }
+}
+
+fn compression_error(msg: impl std::fmt::Display) -> SwarmhostError {
+    SwarmhostError::Peer(format!("Invalid compressed message: {}", msg))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(algorithm: CompressionAlgorithm, min_size: usize) -> CompressionConfig {
+        CompressionConfig {
+            algorithm,
+            min_size,
+        }
+    }
+
+    #[test]
+    fn test_negotiate_mismatch() {
+        let lz4 = offered_codecs(&config(CompressionAlgorithm::Lz4, 0));
+        let zstd = offered_codecs(&config(CompressionAlgorithm::Zstd { level: 3 }, 0));
+        let none = offered_codecs(&config(CompressionAlgorithm::None, 0));
+
+        assert_eq!(negotiate(&lz4, &zstd), Some(Codec::Lz4));
+        assert_eq!(negotiate(&zstd, &lz4), Some(Codec::Zstd));
+        assert_eq!(negotiate(&lz4, &[Codec::Zstd]), Some(Codec::Zstd));
+        assert_eq!(negotiate(&[Codec::Lz4], &[Codec::Zstd]), None);
+        assert_eq!(negotiate(&zstd, &none), None);
+        assert_eq!(negotiate(&none, &lz4), None);
+    }
+
+    #[test]
+    fn test_min_size_boundary() {
+        let metrics = NodeMetrics::default();
+        let min_size = 64;
+        let compressor = Compressor::new(
+            Some(Codec::Lz4),
+            &config(CompressionAlgorithm::Lz4, min_size),
+        );
+
+        let below = vec![b'a'; min_size - 1];
+        let encoded = compressor.encode(&below, Some(&metrics)).unwrap();
+        assert_eq!(encoded[0], FLAG_RAW);
+        assert_eq!(&encoded[HEADER_LEN..], &below[..]);
+        assert_eq!(metrics.uncompressed_bytes.get(), below.len() as u64);
+        assert_eq!(metrics.compression_input_bytes.get(), 0);
+
+        let at = vec![b'a'; min_size];
+        let encoded = compressor.encode(&at, Some(&metrics)).unwrap();
+        assert_eq!(encoded[0], FLAG_COMPRESSED);
+        assert!(encoded.len() < at.len());
+        assert_eq!(metrics.compression_input_bytes.get(), at.len() as u64);
+        assert_eq!(
+            metrics.compression_output_bytes.get(),
+            (encoded.len() - HEADER_LEN) as u64
+        );
+        assert_eq!(compressor.decode(&encoded, 1024).unwrap(), at);
+    }
+
+    #[test]
+    fn test_round_trip_each_codec() {
+        let payload: Vec<u8> = b"move piece e2 e4; ".repeat(100);
+        for (codec, algorithm) in [
+            (Codec::Lz4, CompressionAlgorithm::Lz4),
+            (Codec::Zstd, CompressionAlgorithm::Zstd { level: 5 }),
+        ] {
+            let compressor = Compressor::new(Some(codec), &config(algorithm, 0));
+            let encoded = compressor.encode(&payload, None).unwrap();
+            assert_eq!(encoded[0], FLAG_COMPRESSED);
+            assert_eq!(compressor.decode(&encoded, payload.len()).unwrap(), payload);
+            assert!(compressor.decode(&encoded, payload.len() - 1).is_err());
+        }
+    }
+
+    #[test]
+    fn test_incompressible_sent_raw() {
+        let compressor = Compressor::new(Some(Codec::Lz4), &config(CompressionAlgorithm::Lz4, 0));
+        let noise: Vec<u8> = (0..256).map(|_| rand::random()).collect();
+        let encoded = compressor.encode(&noise, None).unwrap();
+        assert_eq!(encoded[0], FLAG_RAW);
+        assert_eq!(compressor.decode(&encoded, 1024).unwrap(), noise);
+    }
+
+    #[test]
+    fn test_compressed_without_codec_rejected() {
+        let sender = Compressor::new(Some(Codec::Lz4), &config(CompressionAlgorithm::Lz4, 0));
+        let receiver = Compressor::new(None, &config(CompressionAlgorithm::None, 0));
+        let encoded = sender.encode(&[0u8; 128], None).unwrap();
+        assert!(receiver.decode(&encoded, 1024).is_err());
+        assert!(receiver.decode(&[], 1024).is_err());
+    }
+}
@@ -0,0 +1,190 @@
+// network/dedup.rs - Suppressing messages that arrive more than once
+
+use super::gossip::{GossipPayload, MessageId};
+use super::message::PeerMessage;
+use crate::crypto;
+use crate::node::DedupConfig;
+use std::collections::{HashMap, VecDeque};
+use std::time::Duration;
+use tokio::time::Instant;
+
+/// Id under which `message` is deduplicated, or `None` if it bypasses the
+/// cache
+///
+/// Only gossip reaches us along several paths. Everything else is point to
+/// point, and heartbeats in particular repeat on purpose, so none of it is
+/// suppressed. The id hashes the canonical encoding of the payload (without
+/// the hop count, which differs between copies) scoped to its game, so the
+/// same bytes in two games are distinct messages.
+pub fn message_id(message: &PeerMessage) -> Option<MessageId> {
+    let PeerMessage::Gossip(gossip) = message else {
+        return None;
+    };
+    // Votes name an action id, which is already unique to its game, and
+    // rounds, views and evidence belong to the session rather than a game
+    let scope = match &gossip.payload {
+        GossipPayload::Proposal(action) | GossipPayload::Unsequenced(action) => {
+            action.game_id.as_str()
+        }
+        GossipPayload::Commit(commit) => commit.action.game_id.as_str(),
+        GossipPayload::CheckpointVote(vote) => vote.game_id.as_str(),
+        GossipPayload::Checkpoint(checkpoint) => checkpoint.game_id.as_str(),
+        GossipPayload::Vote(_)
+        | GossipPayload::ObserverVote(_)
+        | GossipPayload::Round(_)
+        | GossipPayload::Timeout(_)
+        | GossipPayload::ViewChange(_)
+        | GossipPayload::NewView(_)
+        | GossipPayload::Evidence(_) => "",
+    };
+    let canonical = bincode::serialize(&gossip.payload).ok()?;
+    Some(crypto::hash_multiple(&[
+        &(scope.len() as u64).to_le_bytes(),
+        scope.as_bytes(),
+        &canonical,
+    ]))
+}
+
+/// Message ids seen recently, forgotten after `ttl` or once `capacity`
+/// newer ones have been seen
+///
+/// Ids expire relative to when they were first seen, so a message that keeps
+/// circulating is delivered again once its ttl has passed.
+pub struct DedupCache {
+    capacity: usize,
+    ttl: Duration,
+    first_seen: HashMap<MessageId, Instant>,
+    /// Ids in the order they were first seen, which is also expiry order
+    order: VecDeque<(Instant, MessageId)>,
+}
+
+impl DedupCache {
+    pub fn new(config: &DedupConfig) -> Self {
+        Self {
+            capacity: config.capacity.max(1),
+            ttl: config.ttl,
+            first_seen: HashMap::new(),
+            order: VecDeque::new(),
+        }
+    }
+
+    /// Record `id`, returning true if it was not seen within the ttl
+    pub fn insert(&mut self, id: MessageId, now: Instant) -> bool {
+        self.expire(now);
+        if self.first_seen.contains_key(&id) {
+            return false;
+        }
+
+        self.first_seen.insert(id, now);
+        self.order.push_back((now, id));
+        while self.order.len() > self.capacity {
+            if let Some((_, oldest)) = self.order.pop_front() {
+                self.first_seen.remove(&oldest);
+            }
+        }
+        true
+    }
+
+    pub fn len(&self) -> usize {
+        self.first_seen.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.first_seen.is_empty()
+    }
+
+    /// Bytes the cache holds, roughly: each id twice, with when it was seen
+    pub fn memory_bytes(&self) -> u64 {
+        let each = 2 * (std::mem::size_of::<MessageId>() + std::mem::size_of::<Instant>());
+        (self.len() * each) as u64
+    }
+
+    /// Forget all but the `keep` ids seen last
+    pub fn shrink(&mut self, keep: usize) {
+        while self.order.len() > keep {
+            if let Some((_, oldest)) = self.order.pop_front() {
+                self.first_seen.remove(&oldest);
+            }
+        }
+    }
+
+    fn expire(&mut self, now: Instant) {
+        while let Some((seen, id)) = self.order.front().copied() {
+            if now.saturating_duration_since(seen) < self.ttl {
+                break;
+            }
+            self.order.pop_front();
+            self.first_seen.remove(&id);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::consensus::SignedAction;
+    use crate::crypto::KeyPair;
+    use crate::network::GossipMessage;
+
+    fn config() -> DedupConfig {
+        DedupConfig {
+            capacity: 2,
+            ttl: Duration::from_secs(10),
+        }
+    }
+
+    fn proposal(key: &KeyPair, game_id: &str, hops_left: u8) -> PeerMessage {
+        PeerMessage::Gossip(GossipMessage {
+            hops_left,
+            payload: GossipPayload::Proposal(SignedAction::new(
+                key,
+                game_id,
+                0,
+                1,
+                b"move".to_vec(),
+            )),
+        })
+    }
+
+    #[test]
+    fn test_ids_ignore_hops_but_not_game() {
+        let key = KeyPair::generate();
+        let id = message_id(&proposal(&key, "a", 3)).unwrap();
+        assert_eq!(message_id(&proposal(&key, "a", 1)), Some(id));
+        assert_ne!(message_id(&proposal(&key, "b", 3)), Some(id));
+
+        let ping = PeerMessage::Ping {
+            nonce: 1,
+            sent_at_ms: 0,
+            mark: None,
+        };
+        assert_eq!(message_id(&ping), None);
+    }
+
+    #[test]
+    fn test_duplicates_suppressed_until_ttl() {
+        let start = Instant::now();
+        let mut cache = DedupCache::new(&config());
+        let id = crypto::hash(b"message");
+
+        assert!(cache.insert(id, start));
+        assert!(!cache.insert(id, start + Duration::from_secs(9)));
+        // Seeing it again does not extend its life
+        assert!(cache.insert(id, start + Duration::from_secs(10)));
+        assert_eq!(cache.len(), 1);
+    }
+
+    #[test]
+    fn test_capacity_evicts_oldest() {
+        let now = Instant::now();
+        let mut cache = DedupCache::new(&config());
+        let ids: Vec<_> = (0u8..3).map(|i| crypto::hash(&[i])).collect();
+
+        assert!(cache.insert(ids[0], now));
+        assert!(cache.insert(ids[1], now));
+        assert!(cache.insert(ids[2], now));
+        assert_eq!(cache.len(), 2);
+        assert!(!cache.insert(ids[2], now));
+        assert!(cache.insert(ids[0], now));
+    }
+}
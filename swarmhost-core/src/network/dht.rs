@@ -0,0 +1,577 @@
+// network/dht.rs - Kademlia-style routing table, provider records and lookups
+
+use super::bootstrap::PeerRecord;
+use crate::crypto::{self, Hash, PlayerId};
+use crate::node::DhtConfig;
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::net::SocketAddr;
+use std::time::Duration;
+use tokio::time::Instant;
+
+/// Position in the DHT's id space
+pub type Key = Hash;
+
+/// Bits in a key, and so buckets in a routing table
+const KEY_BITS: usize = 256;
+
+/// Providers remembered per key; later ones are refused until some expire
+pub const MAX_PROVIDERS_PER_KEY: usize = 64;
+
+/// Where a player sits in the id space
+pub fn node_key(player_id: &PlayerId) -> Key {
+    crypto::hash_multiple(&[b"swarmhost dht node", player_id])
+}
+
+/// Where a game's provider records are stored
+pub fn game_key(game_id: &str) -> Key {
+    crypto::hash_multiple(&[b"swarmhost dht game", game_id.as_bytes()])
+}
+
+/// XOR distance between two keys; compare results as big-endian numbers
+pub fn distance(a: &Key, b: &Key) -> Key {
+    std::array::from_fn(|i| a[i] ^ b[i])
+}
+
+/// Leading bits `a` and `b` share, or `None` if they are equal
+fn shared_prefix(a: &Key, b: &Key) -> Option<usize> {
+    let distance = distance(a, b);
+    let byte = distance.iter().position(|&byte| byte != 0)?;
+    Some(byte * 8 + distance[byte].leading_zeros() as usize)
+}
+
+/// Result of offering a contact to the routing table
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Insert {
+    /// The contact is new and had room in its bucket
+    Added,
+    /// The contact was known; it is now the most recently seen
+    Updated,
+    /// The bucket is full, so the contact waits as a replacement. Check
+    /// `oldest`: [`RoutingTable::remove`] it if it is gone, making room for
+    /// the replacement, or insert it again if it is alive
+    Full { oldest: PeerRecord },
+}
+
+/// Up to k contacts sharing a prefix length with us, least recently seen
+/// first, and the newest contacts waiting for one of them to go
+struct KBucket {
+    entries: VecDeque<PeerRecord>,
+    replacements: VecDeque<PeerRecord>,
+    /// Last lookup for a key in the bucket's range
+    refreshed: Instant,
+}
+
+/// Contacts by distance from us, as in Kademlia
+///
+/// Long-lived contacts are preferred: a full bucket keeps its oldest entry
+/// as long as it answers, and new contacts only take its place once it is
+/// removed.
+pub struct RoutingTable {
+    local: Key,
+    local_id: PlayerId,
+    k: usize,
+    buckets: Vec<KBucket>,
+}
+
+impl RoutingTable {
+    pub fn new(local_id: PlayerId, config: &DhtConfig, now: Instant) -> Self {
+        Self {
+            local: node_key(&local_id),
+            local_id,
+            k: config.bucket_size.max(1),
+            buckets: (0..KEY_BITS)
+                .map(|_| KBucket {
+                    entries: VecDeque::new(),
+                    replacements: VecDeque::new(),
+                    refreshed: now,
+                })
+                .collect(),
+        }
+    }
+
+    /// Record that `contact` was seen
+    pub fn insert(&mut self, contact: PeerRecord) -> Insert {
+        // Nothing to do for our own id
+        let Some(index) = self.bucket_of(&contact.player_id) else {
+            return Insert::Updated;
+        };
+        let k = self.k;
+        let bucket = &mut self.buckets[index];
+
+        let known = bucket
+            .entries
+            .iter()
+            .position(|entry| entry.player_id == contact.player_id);
+        if let Some(position) = known {
+            bucket.entries.remove(position);
+            bucket.entries.push_back(contact);
+            return Insert::Updated;
+        }
+
+        bucket
+            .replacements
+            .retain(|entry| entry.player_id != contact.player_id);
+        if bucket.entries.len() < k {
+            bucket.entries.push_back(contact);
+            return Insert::Added;
+        }
+        bucket.replacements.push_back(contact);
+        if bucket.replacements.len() > k {
+            bucket.replacements.pop_front();
+        }
+        Insert::Full {
+            oldest: bucket.entries[0].clone(),
+        }
+    }
+
+    /// Forget a contact that stopped answering, promoting the newest
+    /// replacement in its place; returns whether it was in the table
+    pub fn remove(&mut self, player_id: &PlayerId) -> bool {
+        let Some(index) = self.bucket_of(player_id) else {
+            return false;
+        };
+        let bucket = &mut self.buckets[index];
+        bucket
+            .replacements
+            .retain(|entry| entry.player_id != *player_id);
+        let before = bucket.entries.len();
+        bucket.entries.retain(|entry| entry.player_id != *player_id);
+        if bucket.entries.len() == before {
+            return false;
+        }
+        if let Some(replacement) = bucket.replacements.pop_back() {
+            bucket.entries.push_back(replacement);
+        }
+        true
+    }
+
+    /// Up to `count` contacts closest to `target`, nearest first
+    pub fn closest(&self, target: &Key, count: usize) -> Vec<PeerRecord> {
+        let mut contacts: Vec<_> = self
+            .buckets
+            .iter()
+            .flat_map(|bucket| bucket.entries.iter().cloned())
+            .collect();
+        contacts.sort_by_key(|contact| distance(&node_key(&contact.player_id), target));
+        contacts.truncate(count);
+        contacts
+    }
+
+    pub fn contains(&self, player_id: &PlayerId) -> bool {
+        self.bucket_of(player_id).is_some_and(|index| {
+            self.buckets[index]
+                .entries
+                .iter()
+                .any(|entry| entry.player_id == *player_id)
+        })
+    }
+
+    pub fn len(&self) -> usize {
+        self.buckets.iter().map(|bucket| bucket.entries.len()).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Note a lookup for `target`, which keeps its bucket fresh
+    pub fn touch(&mut self, target: &Key, now: Instant) {
+        if let Some(index) = shared_prefix(&self.local, target) {
+            self.buckets[index].refreshed = now;
+        }
+    }
+
+    /// A random key in each bucket not looked up within `interval`, for the
+    /// caller to look up
+    ///
+    /// Only buckets up to the nearest one holding contacts are refreshed;
+    /// nearer ones cover too little of the id space to hold anyone.
+    pub fn stale(&mut self, interval: Duration, now: Instant) -> Vec<Key> {
+        let Some(nearest) = self
+            .buckets
+            .iter()
+            .rposition(|bucket| !bucket.entries.is_empty())
+        else {
+            return Vec::new();
+        };
+        let mut keys = Vec::new();
+        for (index, bucket) in self.buckets[..=nearest].iter_mut().enumerate() {
+            if now.duration_since(bucket.refreshed) >= interval {
+                bucket.refreshed = now;
+                keys.push(random_key_in(&self.local, index));
+            }
+        }
+        keys
+    }
+
+    fn bucket_of(&self, player_id: &PlayerId) -> Option<usize> {
+        if *player_id == self.local_id {
+            return None;
+        }
+        shared_prefix(&self.local, &node_key(player_id))
+    }
+}
+
+/// A random key sharing exactly `prefix` leading bits with `local`
+fn random_key_in(local: &Key, prefix: usize) -> Key {
+    let mut key: Key = rand::random();
+    for bit in 0..=prefix {
+        let (byte, mask) = (bit / 8, 0x80 >> (bit % 8));
+        let wanted = if bit == prefix {
+            !local[byte] & mask
+        } else {
+            local[byte] & mask
+        };
+        key[byte] = (key[byte] & !mask) | wanted;
+    }
+    key
+}
+
+/// Players who announced they can be found for a key (e.g. a game), each
+/// forgotten after `ttl` unless announced again
+pub struct ProviderStore {
+    ttl: Duration,
+    records: HashMap<Key, HashMap<PlayerId, (Vec<SocketAddr>, Instant)>>,
+}
+
+impl ProviderStore {
+    pub fn new(config: &DhtConfig) -> Self {
+        Self {
+            ttl: config.provider_ttl,
+            records: HashMap::new(),
+        }
+    }
+
+    /// Store or refresh a provider; returns false if the key already has
+    /// [`MAX_PROVIDERS_PER_KEY`] others
+    pub fn add(&mut self, key: Key, provider: PeerRecord, now: Instant) -> bool {
+        self.expire(now);
+        let providers = self.records.entry(key).or_default();
+        if providers.len() >= MAX_PROVIDERS_PER_KEY && !providers.contains_key(&provider.player_id)
+        {
+            return false;
+        }
+        providers.insert(provider.player_id, (provider.addrs, now + self.ttl));
+        true
+    }
+
+    /// Unexpired providers of `key`
+    pub fn get(&self, key: &Key, now: Instant) -> Vec<PeerRecord> {
+        let Some(providers) = self.records.get(key) else {
+            return Vec::new();
+        };
+        providers
+            .iter()
+            .filter(|(_, (_, expires))| *expires > now)
+            .map(|(&player_id, (addrs, _))| PeerRecord {
+                player_id,
+                addrs: addrs.clone(),
+            })
+            .collect()
+    }
+
+    pub fn expire(&mut self, now: Instant) {
+        self.records.retain(|_, providers| {
+            providers.retain(|_, (_, expires)| *expires > now);
+            !providers.is_empty()
+        });
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Progress {
+    Waiting,
+    Asked,
+    Answered,
+    Failed,
+}
+
+/// An iterative search for the k contacts closest to a key
+///
+/// Sans-IO: [`next`](Self::next) says whom to ask, at most `alpha` at a time,
+/// and the caller reports each answer with [`respond`](Self::respond) or
+/// [`fail`](Self::fail). The search ends once the k closest contacts that
+/// did not fail have all answered.
+pub struct Lookup {
+    target: Key,
+    local_id: PlayerId,
+    k: usize,
+    alpha: usize,
+    /// Everyone heard of, by distance from the target
+    candidates: BTreeMap<Key, (PeerRecord, Progress)>,
+}
+
+impl Lookup {
+    /// Start from the closest contacts we already know
+    pub fn new(
+        target: Key,
+        local_id: PlayerId,
+        seeds: Vec<PeerRecord>,
+        config: &DhtConfig,
+    ) -> Self {
+        let mut lookup = Self {
+            target,
+            local_id,
+            k: config.bucket_size.max(1),
+            alpha: config.alpha.max(1),
+            candidates: BTreeMap::new(),
+        };
+        lookup.learn(seeds);
+        lookup
+    }
+
+    pub fn target(&self) -> &Key {
+        &self.target
+    }
+
+    /// Contacts to ask now
+    pub fn ask_next(&mut self) -> Vec<PeerRecord> {
+        let mut asked = self.asked();
+        let alpha = self.alpha;
+        let mut ask = Vec::new();
+        for (contact, progress) in self.nearest_mut() {
+            if asked >= alpha {
+                break;
+            }
+            if *progress == Progress::Waiting {
+                *progress = Progress::Asked;
+                ask.push(contact.clone());
+                asked += 1;
+            }
+        }
+        ask
+    }
+
+    /// `from` answered with the contacts it knows closest to the target
+    pub fn respond(&mut self, from: &PlayerId, closer: Vec<PeerRecord>) {
+        self.set(from, Progress::Answered);
+        self.learn(closer.into_iter().take(self.k));
+    }
+
+    /// `from` could not be reached or did not answer in time
+    pub fn fail(&mut self, from: &PlayerId) {
+        self.set(from, Progress::Failed);
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.candidates
+            .values()
+            .filter(|(_, progress)| *progress != Progress::Failed)
+            .take(self.k)
+            .all(|(_, progress)| *progress == Progress::Answered)
+    }
+
+    /// The closest contacts that answered, nearest first
+    pub fn closest(&self) -> Vec<PeerRecord> {
+        self.candidates
+            .values()
+            .filter(|(_, progress)| *progress == Progress::Answered)
+            .take(self.k)
+            .map(|(contact, _)| contact.clone())
+            .collect()
+    }
+
+    fn learn(&mut self, contacts: impl IntoIterator<Item = PeerRecord>) {
+        for contact in contacts {
+            if contact.player_id == self.local_id {
+                continue;
+            }
+            let distance = distance(&node_key(&contact.player_id), &self.target);
+            self.candidates
+                .entry(distance)
+                .or_insert((contact, Progress::Waiting));
+        }
+    }
+
+    fn set(&mut self, player_id: &PlayerId, progress: Progress) {
+        let distance = distance(&node_key(player_id), &self.target);
+        if let Some(candidate) = self.candidates.get_mut(&distance) {
+            candidate.1 = progress;
+        }
+    }
+
+    fn nearest_mut(&mut self) -> impl Iterator<Item = &mut (PeerRecord, Progress)> {
+        self.candidates
+            .values_mut()
+            .filter(|(_, progress)| *progress != Progress::Failed)
+            .take(self.k)
+    }
+
+    fn asked(&self) -> usize {
+        self.candidates
+            .values()
+            .filter(|(_, progress)| *progress == Progress::Asked)
+            .count()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::{IpAddr, Ipv4Addr};
+
+    fn contact(n: u8) -> PeerRecord {
+        PeerRecord::at(
+            [n; 32],
+            SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), 9000 + n as u16),
+        )
+    }
+
+    fn config(bucket_size: usize) -> DhtConfig {
+        DhtConfig {
+            bucket_size,
+            alpha: 2,
+            ..DhtConfig::default()
+        }
+    }
+
+    /// Contacts that all land in the bucket of the farthest half from `local`
+    fn far_contacts(local: &PlayerId, count: usize) -> Vec<PeerRecord> {
+        let top = node_key(local)[0] & 0x80;
+        (1..=u8::MAX)
+            .map(contact)
+            .filter(|contact| node_key(&contact.player_id)[0] & 0x80 != top)
+            .take(count)
+            .collect()
+    }
+
+    #[test]
+    fn test_full_bucket_keeps_oldest_until_removed() {
+        let local = [0; 32];
+        let now = Instant::now();
+        let mut table = RoutingTable::new(local, &config(2), now);
+        let far = far_contacts(&local, 3);
+
+        assert_eq!(table.insert(far[0].clone()), Insert::Added);
+        assert_eq!(table.insert(far[1].clone()), Insert::Added);
+        assert_eq!(
+            table.insert(far[2].clone()),
+            Insert::Full {
+                oldest: far[0].clone()
+            }
+        );
+        assert!(!table.contains(&far[2].player_id));
+
+        // Seen again, the oldest moves to the back and stays
+        assert_eq!(table.insert(far[0].clone()), Insert::Updated);
+        assert_eq!(
+            table.insert(far[2].clone()),
+            Insert::Full {
+                oldest: far[1].clone()
+            }
+        );
+
+        assert!(table.remove(&far[1].player_id));
+        assert!(table.contains(&far[2].player_id));
+        assert_eq!(table.len(), 2);
+        let us = PeerRecord {
+            player_id: local,
+            ..contact(0)
+        };
+        assert_eq!(table.insert(us), Insert::Updated);
+        assert_eq!(table.len(), 2);
+    }
+
+    #[test]
+    fn test_closest_sorted_by_xor_distance() {
+        let now = Instant::now();
+        let mut table = RoutingTable::new([0; 32], &config(20), now);
+        for n in 1..=40 {
+            table.insert(contact(n));
+        }
+
+        let target = node_key(&contact(7).player_id);
+        let closest = table.closest(&target, 5);
+        assert_eq!(closest.len(), 5);
+        assert_eq!(closest[0], contact(7));
+        let distances: Vec<_> = closest
+            .iter()
+            .map(|contact| distance(&node_key(&contact.player_id), &target))
+            .collect();
+        assert!(distances.windows(2).all(|pair| pair[0] < pair[1]));
+    }
+
+    #[test]
+    fn test_stale_buckets_get_keys_in_their_range() {
+        let local = [0; 32];
+        let now = Instant::now();
+        let mut table = RoutingTable::new(local, &config(20), now);
+        table.insert(far_contacts(&local, 1).remove(0));
+
+        assert!(table.stale(Duration::from_secs(60), now).is_empty());
+        let later = now + Duration::from_secs(60);
+        let keys = table.stale(Duration::from_secs(60), later);
+        assert_eq!(keys.len(), 1);
+        assert_eq!(shared_prefix(&node_key(&local), &keys[0]), Some(0));
+        assert!(table.stale(Duration::from_secs(60), later).is_empty());
+
+        for prefix in [0, 7, 8, 100, 255] {
+            let key = random_key_in(&node_key(&local), prefix);
+            assert_eq!(shared_prefix(&node_key(&local), &key), Some(prefix));
+        }
+    }
+
+    #[test]
+    fn test_provider_records_expire() {
+        let config = DhtConfig {
+            provider_ttl: Duration::from_secs(10),
+            ..DhtConfig::default()
+        };
+        let now = Instant::now();
+        let mut store = ProviderStore::new(&config);
+        let key = game_key("chess");
+
+        assert!(store.add(key, contact(1), now));
+        assert_eq!(store.get(&key, now), vec![contact(1)]);
+        assert!(store.get(&game_key("go"), now).is_empty());
+        assert!(store.get(&key, now + Duration::from_secs(10)).is_empty());
+
+        for n in 0..MAX_PROVIDERS_PER_KEY as u8 {
+            store.add(key, contact(n), now);
+        }
+        assert!(!store.add(key, contact(200), now));
+        assert!(store.add(key, contact(3), now));
+    }
+
+    #[test]
+    fn test_lookup_converges_on_closest() {
+        // Everyone knows everyone; the lookup must walk from one far seed to
+        // the k contacts nearest the target
+        let everyone: Vec<_> = (1..=50).map(contact).collect();
+        let target = game_key("far away");
+        let mut expected = everyone.clone();
+        expected.sort_by_key(|contact| distance(&node_key(&contact.player_id), &target));
+        expected.truncate(4);
+
+        let farthest = everyone
+            .iter()
+            .max_by_key(|contact| distance(&node_key(&contact.player_id), &target))
+            .unwrap()
+            .clone();
+        let mut lookup = Lookup::new(target, [0; 32], vec![farthest], &config(4));
+        let mut asked = 0;
+        while !lookup.is_finished() {
+            let ask = lookup.ask_next();
+            assert!(!ask.is_empty() && ask.len() <= 2);
+            for peer in ask {
+                asked += 1;
+                if peer == expected[1] {
+                    lookup.fail(&peer.player_id);
+                    continue;
+                }
+                let mut known = everyone.clone();
+                known.retain(|contact| *contact != peer);
+                known.sort_by_key(|contact| distance(&node_key(&contact.player_id), &target));
+                known.truncate(4);
+                lookup.respond(&peer.player_id, known);
+            }
+        }
+
+        let mut without_failed = everyone.clone();
+        without_failed.retain(|contact| *contact != expected[1]);
+        without_failed.sort_by_key(|contact| distance(&node_key(&contact.player_id), &target));
+        without_failed.truncate(4);
+        assert_eq!(lookup.closest(), without_failed);
+        assert!(asked < everyone.len());
+    }
+}
@@ -0,0 +1,111 @@
+// network/discovery/loopback.rs - In-process stand-in for multicast discovery
+
+use super::{LocalDiscovery, LocalPeer};
+use crate::error::Result;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, MutexGuard, OnceLock};
+use tokio::sync::mpsc;
+
+/// Announcements shared by every [`LoopbackDiscovery`] handle on one bus
+#[derive(Default)]
+struct Bus {
+    advertised: HashMap<u64, LocalPeer>,
+    browsers: Vec<mpsc::UnboundedSender<LocalPeer>>,
+    next_handle: u64,
+}
+
+/// [`LocalDiscovery`] over an in-process bus, mimicking multicast on a LAN
+///
+/// Every handle created from the same bus sees the others' advertisements.
+#[derive(Clone, Default)]
+pub struct LoopbackDiscovery {
+    bus: Arc<Mutex<Bus>>,
+    handle: u64,
+}
+
+impl LoopbackDiscovery {
+    /// A bus isolated from every other
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Bus shared by every node in the process using the memory transport
+    pub fn global() -> Self {
+        static GLOBAL: OnceLock<LoopbackDiscovery> = OnceLock::new();
+        GLOBAL.get_or_init(LoopbackDiscovery::new).handle()
+    }
+
+    /// Another participant on the same bus
+    pub fn handle(&self) -> Self {
+        let id = {
+            let mut bus = self.lock();
+            bus.next_handle += 1;
+            bus.next_handle
+        };
+        Self {
+            bus: self.bus.clone(),
+            handle: id,
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Bus> {
+        self.bus.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl LocalDiscovery for LoopbackDiscovery {
+    fn advertise(&self, peer: &LocalPeer) -> Result<()> {
+        let mut bus = self.lock();
+        bus.advertised.insert(self.handle, peer.clone());
+        bus.browsers
+            .retain(|browser| browser.send(peer.clone()).is_ok());
+        Ok(())
+    }
+
+    fn withdraw(&self) {
+        self.lock().advertised.remove(&self.handle);
+    }
+
+    fn browse(&self) -> Result<mpsc::UnboundedReceiver<LocalPeer>> {
+        let (tx, rx) = mpsc::unbounded_channel();
+        let mut bus = self.lock();
+        for peer in bus.advertised.values() {
+            let _ = tx.send(peer.clone());
+        }
+        bus.browsers.push(tx);
+        Ok(rx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::SocketAddr;
+
+    #[tokio::test]
+    async fn test_browsers_see_existing_and_new_adverts() {
+        let bus = LoopbackDiscovery::new();
+        let (a, b) = (bus.handle(), bus.handle());
+        let peer = |id: u8| LocalPeer {
+            player_id: [id; 32],
+            addr: SocketAddr::from(([127, 0, 0, 1], 9000 + id as u16)),
+            games: vec!["game".to_string()],
+        };
+
+        a.advertise(&peer(1)).unwrap();
+        let mut seen = b.browse().unwrap();
+        assert_eq!(seen.recv().await, Some(peer(1)));
+
+        b.advertise(&peer(2)).unwrap();
+        assert_eq!(seen.recv().await, Some(peer(2)));
+
+        // Isolated buses do not leak into each other
+        let mut other = LoopbackDiscovery::new().browse().unwrap();
+        assert!(other.try_recv().is_err());
+
+        a.withdraw();
+        let mut fresh = b.browse().unwrap();
+        assert_eq!(fresh.recv().await, Some(peer(2)));
+        assert!(fresh.try_recv().is_err());
+    }
+}
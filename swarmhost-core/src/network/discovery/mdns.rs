@@ -0,0 +1,156 @@
+// network/discovery/mdns.rs - LAN discovery over multicast DNS-SD
+
+use super::{LocalDiscovery, LocalPeer};
+use crate::crypto::{parse_player_id, player_id_hex};
+use crate::error::{Result, SwarmhostError};
+use mdns_sd::{ServiceDaemon, ServiceEvent, ServiceInfo};
+use std::net::SocketAddr;
+use std::sync::Mutex;
+use tokio::sync::mpsc;
+
+/// DNS-SD service type every node advertises under
+pub const SERVICE_TYPE: &str = "_swarmhost._udp.local.";
+
+/// TXT key holding the hex-encoded PlayerId
+const ID_KEY: &str = "id";
+
+/// TXT key holding the comma-separated game ids
+const GAMES_KEY: &str = "games";
+
+fn mdns_error(e: mdns_sd::Error) -> SwarmhostError {
+    SwarmhostError::Network(std::io::Error::other(e.to_string()))
+}
+
+/// [`LocalDiscovery`] using multicast DNS service discovery
+pub struct MdnsDiscovery {
+    daemon: ServiceDaemon,
+    /// Full name of our current advertisement, if any
+    registered: Mutex<Option<String>>,
+}
+
+impl MdnsDiscovery {
+    pub fn new() -> Result<Self> {
+        Ok(Self {
+            daemon: ServiceDaemon::new().map_err(mdns_error)?,
+            registered: Mutex::new(None),
+        })
+    }
+
+    fn service_info(peer: &LocalPeer) -> Result<ServiceInfo> {
+        let id = player_id_hex(&peer.player_id);
+        // DNS labels stop at 63 bytes, so the instance name is a prefix of the
+        // id and the full id travels in the TXT record
+        let instance = &id[..32];
+        let host = format!("{}.local.", instance);
+        let games = peer.games.join(",");
+        let properties = [(ID_KEY, id.as_str()), (GAMES_KEY, games.as_str())];
+
+        let info = if peer.addr.ip().is_unspecified() {
+            ServiceInfo::new(
+                SERVICE_TYPE,
+                instance,
+                &host,
+                "",
+                peer.addr.port(),
+                &properties[..],
+            )
+            .map(ServiceInfo::enable_addr_auto)
+        } else {
+            ServiceInfo::new(
+                SERVICE_TYPE,
+                instance,
+                &host,
+                peer.addr.ip(),
+                peer.addr.port(),
+                &properties[..],
+            )
+        };
+        info.map_err(mdns_error)
+    }
+}
+
+impl LocalDiscovery for MdnsDiscovery {
+    fn advertise(&self, peer: &LocalPeer) -> Result<()> {
+        let info = Self::service_info(peer)?;
+        let fullname = info.get_fullname().to_string();
+        self.daemon.register(info).map_err(mdns_error)?;
+        *self.registered.lock().unwrap_or_else(|e| e.into_inner()) = Some(fullname);
+        Ok(())
+    }
+
+    fn withdraw(&self) {
+        let registered = self
+            .registered
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .take();
+        if let Some(fullname) = registered {
+            let _ = self.daemon.unregister(&fullname);
+        }
+    }
+
+    fn browse(&self) -> Result<mpsc::UnboundedReceiver<LocalPeer>> {
+        let events = self.daemon.browse(SERVICE_TYPE).map_err(mdns_error)?;
+        let (tx, rx) = mpsc::unbounded_channel();
+
+        tokio::spawn(async move {
+            while let Ok(event) = events.recv_async().await {
+                let ServiceEvent::ServiceResolved(info) = event else {
+                    continue;
+                };
+                let Some(peer) = parse_service(&info) else {
+                    tracing::debug!("Ignoring malformed mDNS record {}", info.get_fullname());
+                    continue;
+                };
+                if tx.send(peer).is_err() {
+                    break;
+                }
+            }
+        });
+
+        Ok(rx)
+    }
+}
+
+impl Drop for MdnsDiscovery {
+    fn drop(&mut self) {
+        self.withdraw();
+        let _ = self.daemon.shutdown();
+    }
+}
+
+/// Read a peer back out of a resolved service record
+fn parse_service(info: &ServiceInfo) -> Option<LocalPeer> {
+    let player_id = parse_player_id(info.get_property_val_str(ID_KEY)?).ok()?;
+    let ip = info.get_addresses().iter().next().copied()?;
+    let games = info
+        .get_property_val_str(GAMES_KEY)
+        .unwrap_or_default()
+        .split(',')
+        .filter(|g| !g.is_empty())
+        .map(str::to_string)
+        .collect();
+
+    Some(LocalPeer {
+        player_id,
+        addr: SocketAddr::new(ip, info.get_port()),
+        games,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_service_record_round_trip() {
+        let peer = LocalPeer {
+            player_id: [0xab; 32],
+            addr: SocketAddr::from(([192, 168, 1, 20], 7777)),
+            games: vec!["kart".to_string(), "chess".to_string()],
+        };
+
+        let info = MdnsDiscovery::service_info(&peer).unwrap();
+        assert_eq!(parse_service(&info), Some(peer));
+    }
+}
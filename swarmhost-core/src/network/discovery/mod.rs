@@ -0,0 +1,133 @@
+// network/discovery/mod.rs - Local network peer discovery
+
+pub mod loopback;
+#[cfg(feature = "mdns")]
+pub mod mdns;
+
+pub use loopback::LoopbackDiscovery;
+
+use super::bootstrap::PeerRecord;
+use crate::crypto::PlayerId;
+use crate::error::Result;
+use crate::node::{NetworkConfig, TransportKind};
+use std::collections::BTreeMap;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use tokio::sync::mpsc;
+
+/// A node as advertised on the local network
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LocalPeer {
+    pub player_id: PlayerId,
+    /// Where to dial; an unspecified IP is filled in by the mechanism
+    pub addr: SocketAddr,
+    /// Games the node has joined
+    pub games: Vec<String>,
+}
+
+impl From<&LocalPeer> for PeerRecord {
+    fn from(peer: &LocalPeer) -> Self {
+        PeerRecord::at(peer.player_id, peer.addr)
+    }
+}
+
+/// A game being played on the local network and the nodes in it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GameAnnouncement {
+    pub game_id: String,
+    pub peers: Vec<PeerRecord>,
+}
+
+/// A way of finding nodes on the local network without a bootstrap server
+pub trait LocalDiscovery: Send + Sync {
+    /// Start advertising `peer`, replacing any previous advertisement
+    fn advertise(&self, peer: &LocalPeer) -> Result<()>;
+
+    /// Stop advertising
+    fn withdraw(&self);
+
+    /// Peers seen on the network, including ones already advertising when
+    /// browsing starts; the same peer may be reported more than once
+    fn browse(&self) -> Result<mpsc::UnboundedReceiver<LocalPeer>>;
+}
+
+/// The local discovery mechanism for a config, if `enable_mdns` is set
+///
+/// Nodes on the in-memory transport use the in-process
+/// [`LoopbackDiscovery`] so simulations behave like a LAN. Other transports
+/// need the `mdns` feature; without it the setting is ignored with a warning.
+pub fn for_config(config: &NetworkConfig) -> Result<Option<Arc<dyn LocalDiscovery>>> {
+    if !config.enable_mdns {
+        return Ok(None);
+    }
+    if config.transport == TransportKind::Memory {
+        return Ok(Some(Arc::new(LoopbackDiscovery::global())));
+    }
+
+    #[cfg(feature = "mdns")]
+    {
+        Ok(Some(Arc::new(mdns::MdnsDiscovery::new()?)))
+    }
+    #[cfg(not(feature = "mdns"))]
+    {
+        tracing::warn!("enable_mdns is set but the 'mdns' feature is off; skipping discovery");
+        Ok(None)
+    }
+}
+
+/// Group peers by the games they advertise
+pub fn games_of(peers: impl IntoIterator<Item = LocalPeer>) -> Vec<GameAnnouncement> {
+    let mut games: BTreeMap<String, Vec<PeerRecord>> = BTreeMap::new();
+    for peer in peers {
+        for game_id in &peer.games {
+            let listed = games.entry(game_id.clone()).or_default();
+            if !listed.iter().any(|p| p.player_id == peer.player_id) {
+                listed.push(PeerRecord::from(&peer));
+            }
+        }
+    }
+
+    games
+        .into_iter()
+        .map(|(game_id, peers)| GameAnnouncement { game_id, peers })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_games_of_groups_and_dedups() {
+        let peer = |id: u8, games: &[&str]| LocalPeer {
+            player_id: [id; 32],
+            addr: SocketAddr::from(([192, 168, 1, id], 9000)),
+            games: games.iter().map(|g| g.to_string()).collect(),
+        };
+
+        let games = games_of([
+            peer(1, &["kart", "chess"]),
+            peer(2, &["kart"]),
+            peer(1, &["kart", "chess"]),
+        ]);
+
+        assert_eq!(games.len(), 2);
+        assert_eq!(games[0].game_id, "chess");
+        assert_eq!(games[0].peers.len(), 1);
+        assert_eq!(games[1].game_id, "kart");
+        assert_eq!(games[1].peers.len(), 2);
+    }
+
+    #[test]
+    fn test_mdns_needs_feature_outside_memory_transport() {
+        let mut config = NetworkConfig::default();
+        assert!(for_config(&config).unwrap().is_none());
+
+        config.enable_mdns = true;
+        let found = for_config(&config).map(|d| d.is_some()).unwrap_or(false);
+        assert_eq!(found, cfg!(feature = "mdns"));
+
+        config.transport = TransportKind::Memory;
+        assert!(for_config(&config).unwrap().is_some());
+    }
+}
@@ -0,0 +1,510 @@
+// network/fragment.rs - Splitting bulk messages above max_message_size
+
+use super::message::PeerMessage;
+use crate::crypto;
+use crate::error::{Result, SwarmhostError};
+use crate::node::FragmentConfig;
+use bytes::Bytes;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use tokio::time::Instant;
+
+/// Room left in each fragment for the message wrapping it
+pub const FRAGMENT_OVERHEAD: usize = 64;
+
+/// Most fragments asked for again in one [`PeerMessage::FragmentRequest`]
+pub const MAX_REREQUESTED: usize = 1024;
+
+/// One numbered piece of an encoded message
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Fragment {
+    /// Sender-chosen id shared by every fragment of one message
+    pub transfer: u64,
+    pub index: u32,
+    pub total: u32,
+    /// Truncated Blake2s of `data`
+    pub checksum: u32,
+    pub data: Vec<u8>,
+}
+
+impl Fragment {
+    fn new(transfer: u64, index: u32, total: u32, data: &[u8]) -> Self {
+        Self {
+            transfer,
+            index,
+            total,
+            checksum: checksum(data),
+            data: data.to_vec(),
+        }
+    }
+}
+
+fn checksum(data: &[u8]) -> u32 {
+    let hash = crypto::hash(data);
+    u32::from_le_bytes([hash[0], hash[1], hash[2], hash[3]])
+}
+
+/// A transfer the receiver gave up waiting on
+#[derive(Debug)]
+pub enum Stalled {
+    /// Ask the sender once more for the `missing` fragments of `transfer`,
+    /// at most [`MAX_REREQUESTED`] of them, with a
+    /// [`PeerMessage::FragmentRequest`]
+    Rerequest { transfer: u64, missing: Vec<u32> },
+    /// Already re-requested; the transfer is dropped
+    Abandoned(SwarmhostError),
+}
+
+/// A message we sent in fragments, kept to answer one re-request
+struct Sent {
+    transfer: u64,
+    sent_at: Instant,
+    fragment_size: usize,
+    encoded: Bytes,
+}
+
+/// A message being put back together
+struct Reassembly {
+    total: u32,
+    /// Payload of every fragment but the last, once one of them is in
+    fragment_size: Option<usize>,
+    received: BTreeMap<u32, Vec<u8>>,
+    bytes: usize,
+    deadline: Instant,
+    rerequested: bool,
+}
+
+impl Reassembly {
+    /// The first [`MAX_REREQUESTED`] fragments not yet received
+    fn missing(&self) -> Vec<u32> {
+        (0..self.total)
+            .filter(|index| !self.received.contains_key(index))
+            .take(MAX_REREQUESTED)
+            .collect()
+    }
+}
+
+/// Fragmentation state for one connection, in both directions
+///
+/// Oversized messages are split into numbered fragments that may arrive out
+/// of order or more than once. A transfer that stalls for
+/// `reassembly_timeout` is re-requested once, then abandoned.
+pub struct Fragmenter {
+    config: FragmentConfig,
+    next_transfer: u64,
+    sent: VecDeque<Sent>,
+    incoming: HashMap<u64, Reassembly>,
+}
+
+impl Fragmenter {
+    pub fn new(config: &FragmentConfig) -> Self {
+        Self {
+            config: config.clone(),
+            next_transfer: rand::random(),
+            sent: VecDeque::new(),
+            incoming: HashMap::new(),
+        }
+    }
+
+    /// Split an encoded message into fragments that each fit in
+    /// `max_payload`
+    pub fn split(
+        &mut self,
+        encoded: Bytes,
+        max_payload: usize,
+        now: Instant,
+    ) -> Result<Vec<PeerMessage>> {
+        if encoded.len() > self.config.max_transfer_size {
+            return Err(SwarmhostError::TooLarge(format!(
+                "Message of {} bytes exceeds max_transfer_size ({})",
+                encoded.len(),
+                self.config.max_transfer_size
+            )));
+        }
+        let fragment_size = max_payload.saturating_sub(FRAGMENT_OVERHEAD);
+        if fragment_size == 0 {
+            return Err(SwarmhostError::Config(
+                "max_message_size leaves no room for fragments".to_string(),
+            ));
+        }
+
+        let transfer = self.next_transfer;
+        self.next_transfer = self.next_transfer.wrapping_add(1);
+        let total = encoded.len().div_ceil(fragment_size) as u32;
+        let fragments = encoded
+            .chunks(fragment_size)
+            .zip(0..)
+            .map(|(data, index)| PeerMessage::Fragment(Fragment::new(transfer, index, total, data)))
+            .collect();
+
+        // Kept until the receiver would have given up on it
+        let retention = self.config.reassembly_timeout * 2;
+        self.sent
+            .retain(|sent| now.saturating_duration_since(sent.sent_at) < retention);
+        if self.sent.len() >= self.config.max_reassemblies {
+            self.sent.pop_front();
+        }
+        self.sent.push_back(Sent {
+            transfer,
+            sent_at: now,
+            fragment_size,
+            encoded,
+        });
+        Ok(fragments)
+    }
+
+    /// Fragments the receiver asked for again, if we still have them
+    pub fn resend(&self, transfer: u64, missing: &[u32]) -> Vec<PeerMessage> {
+        let Some(sent) = self.sent.iter().find(|sent| sent.transfer == transfer) else {
+            return Vec::new();
+        };
+        let chunks: Vec<_> = sent.encoded.chunks(sent.fragment_size).collect();
+        let total = chunks.len() as u32;
+        missing
+            .iter()
+            .filter_map(|&index| {
+                let data = chunks.get(index as usize)?;
+                Some(PeerMessage::Fragment(Fragment::new(
+                    transfer, index, total, data,
+                )))
+            })
+            .collect()
+    }
+
+    /// Add a received fragment, returning the encoded message once every
+    /// fragment is in
+    ///
+    /// Duplicates and fragments failing their checksum are ignored; the
+    /// latter are fetched again by the re-request.
+    ///
+    /// Every fragment but the last carries the sender's full fragment size,
+    /// which the last may not exceed and which bounds how many fragments a
+    /// transfer within `max_transfer_size` can have; a transfer breaking
+    /// either is refused, in whichever order its fragments arrive.
+    pub fn receive(&mut self, fragment: Fragment, now: Instant) -> Result<Option<Vec<u8>>> {
+        let limit = self.config.max_transfer_size;
+        let last = fragment.total.checked_sub(1) == Some(fragment.index);
+        let most = |fragment_size: usize| limit.div_ceil(fragment_size);
+        if fragment.total == 0
+            || fragment.index >= fragment.total
+            || fragment.total as usize > limit
+            || (!last && fragment.data.is_empty())
+            || (!last && fragment.total as usize > most(fragment.data.len()))
+        {
+            self.incoming.remove(&fragment.transfer);
+            return Err(SwarmhostError::Peer(format!(
+                "Fragment {}/{} of transfer {} is out of range",
+                fragment.index, fragment.total, fragment.transfer
+            )));
+        }
+        if checksum(&fragment.data) != fragment.checksum {
+            tracing::debug!(
+                "Fragment {} of transfer {} failed its checksum",
+                fragment.index,
+                fragment.transfer
+            );
+            return Ok(None);
+        }
+
+        if !self.incoming.contains_key(&fragment.transfer)
+            && self.incoming.len() >= self.config.max_reassemblies
+        {
+            return Err(SwarmhostError::Peer(format!(
+                "Refusing transfer {}: {} reassemblies already in progress",
+                fragment.transfer,
+                self.incoming.len()
+            )));
+        }
+        let timeout = self.config.reassembly_timeout;
+        let reassembly = self
+            .incoming
+            .entry(fragment.transfer)
+            .or_insert_with(|| Reassembly {
+                total: fragment.total,
+                fragment_size: None,
+                received: BTreeMap::new(),
+                bytes: 0,
+                deadline: now + timeout,
+                rerequested: false,
+            });
+        if reassembly.total != fragment.total {
+            let transfer = fragment.transfer;
+            self.incoming.remove(&transfer);
+            return Err(SwarmhostError::Peer(format!(
+                "Fragments of transfer {} disagree on their count",
+                transfer
+            )));
+        }
+        let size = fragment.data.len();
+        let sized = match (last, reassembly.fragment_size) {
+            (true, full) => full.is_none_or(|full| size <= full),
+            (false, Some(full)) => size == full,
+            (false, None) => {
+                reassembly.fragment_size = Some(size);
+                // The last fragment may have come first, before the size
+                // it must not exceed was known
+                let tail = reassembly.received.get(&(reassembly.total - 1));
+                tail.is_none_or(|tail| tail.len() <= size)
+            }
+        };
+        if !sized {
+            let transfer = fragment.transfer;
+            self.incoming.remove(&transfer);
+            return Err(SwarmhostError::Peer(format!(
+                "Fragments of transfer {} disagree on their size",
+                transfer
+            )));
+        }
+        if reassembly.received.contains_key(&fragment.index) {
+            return Ok(None);
+        }
+
+        reassembly.bytes += fragment.data.len();
+        if reassembly.bytes > limit {
+            let transfer = fragment.transfer;
+            self.incoming.remove(&transfer);
+            return Err(SwarmhostError::TooLarge(format!(
+                "Transfer {} exceeds max_transfer_size ({})",
+                transfer, limit
+            )));
+        }
+        reassembly.received.insert(fragment.index, fragment.data);
+        reassembly.deadline = now + timeout;
+        if reassembly.received.len() < reassembly.total as usize {
+            return Ok(None);
+        }
+
+        let reassembly = self.incoming.remove(&fragment.transfer).unwrap();
+        let mut encoded = Vec::with_capacity(reassembly.bytes);
+        for data in reassembly.received.into_values() {
+            encoded.extend_from_slice(&data);
+        }
+        Ok(Some(encoded))
+    }
+
+    /// Bytes of fragments received towards transfers not yet whole
+    pub fn buffered(&self) -> u64 {
+        self.incoming.values().map(|r| r.bytes as u64).sum()
+    }
+
+    /// Whether fragments of `transfer` are being put together
+    pub fn receiving(&self, transfer: u64) -> bool {
+        self.incoming.contains_key(&transfer)
+    }
+
+    /// When the next incomplete transfer stalls, if any are in progress
+    pub fn next_deadline(&self) -> Option<Instant> {
+        self.incoming.values().map(|r| r.deadline).min()
+    }
+
+    /// Re-request or abandon every transfer that has stalled by `now`
+    pub fn stalled(&mut self, now: Instant) -> Vec<Stalled> {
+        let timeout = self.config.reassembly_timeout;
+        let mut stalled = Vec::new();
+        self.incoming.retain(|&transfer, reassembly| {
+            if reassembly.deadline > now {
+                return true;
+            }
+            if reassembly.rerequested {
+                stalled.push(Stalled::Abandoned(SwarmhostError::Peer(format!(
+                    "Transfer {} abandoned with {} of {} fragments missing",
+                    transfer,
+                    reassembly.total as usize - reassembly.received.len(),
+                    reassembly.total
+                ))));
+                return false;
+            }
+            reassembly.rerequested = true;
+            reassembly.deadline = now + timeout;
+            let missing = reassembly.missing();
+            stalled.push(Stalled::Rerequest { transfer, missing });
+            true
+        });
+        stalled
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn config() -> FragmentConfig {
+        FragmentConfig {
+            max_transfer_size: 1024 * 1024,
+            reassembly_timeout: Duration::from_secs(5),
+            max_reassemblies: 2,
+        }
+    }
+
+    fn fragments(messages: Vec<PeerMessage>) -> Vec<Fragment> {
+        messages
+            .into_iter()
+            .map(|message| match message {
+                PeerMessage::Fragment(fragment) => fragment,
+                other => panic!("not a fragment: {:?}", other),
+            })
+            .collect()
+    }
+
+    fn payload(len: usize) -> Vec<u8> {
+        (0..len).map(|i| (i * 7) as u8).collect()
+    }
+
+    #[test]
+    fn test_out_of_order_and_duplicate_fragments_reassemble() {
+        let now = Instant::now();
+        let mut sender = Fragmenter::new(&config());
+        let mut receiver = Fragmenter::new(&config());
+        let encoded = payload(1000);
+
+        let mut pieces = fragments(sender.split(encoded.clone().into(), 164, now).unwrap());
+        assert_eq!(pieces.len(), 10);
+        assert!(pieces.iter().all(|f| f.data.len() <= 100));
+        pieces.reverse();
+        pieces.insert(3, pieces[0].clone());
+
+        let mut result = None;
+        for fragment in pieces {
+            assert!(result.is_none());
+            result = receiver.receive(fragment, now).unwrap();
+        }
+        assert_eq!(result, Some(encoded));
+        assert_eq!(receiver.next_deadline(), None);
+    }
+
+    #[test]
+    fn test_dropped_fragment_rerequested_once() {
+        let start = Instant::now();
+        let mut sender = Fragmenter::new(&config());
+        let mut receiver = Fragmenter::new(&config());
+        let encoded = payload(1000);
+
+        let mut pieces = fragments(sender.split(encoded.clone().into(), 164, start).unwrap());
+        let dropped = pieces.remove(4);
+        for fragment in pieces {
+            assert_eq!(receiver.receive(fragment, start).unwrap(), None);
+        }
+
+        let stalled_at = start + Duration::from_secs(5);
+        assert_eq!(receiver.next_deadline(), Some(stalled_at));
+        assert!(
+            receiver
+                .stalled(stalled_at - Duration::from_millis(1))
+                .is_empty()
+        );
+        let stalled = receiver.stalled(stalled_at);
+        let [Stalled::Rerequest { transfer, missing }] = &stalled[..] else {
+            panic!("expected one re-request, got {:?}", stalled);
+        };
+        assert_eq!(
+            (*transfer, missing.as_slice()),
+            (dropped.transfer, &[4][..])
+        );
+        assert!(receiver.stalled(stalled_at).is_empty());
+
+        let resent = fragments(sender.resend(*transfer, missing));
+        assert_eq!(resent, vec![dropped]);
+        let result = receiver.receive(resent[0].clone(), stalled_at).unwrap();
+        assert_eq!(result, Some(encoded));
+    }
+
+    #[test]
+    fn test_transfer_abandoned_after_rerequest() {
+        let start = Instant::now();
+        let mut sender = Fragmenter::new(&config());
+        let mut receiver = Fragmenter::new(&config());
+
+        let first = fragments(sender.split(payload(300).into(), 164, start).unwrap()).remove(0);
+        receiver.receive(first, start).unwrap();
+
+        let timeout = Duration::from_secs(5);
+        assert!(matches!(
+            receiver.stalled(start + timeout)[..],
+            [Stalled::Rerequest { .. }]
+        ));
+        assert!(matches!(
+            receiver.stalled(start + timeout * 2)[..],
+            [Stalled::Abandoned(SwarmhostError::Peer(_))]
+        ));
+        assert_eq!(receiver.next_deadline(), None);
+    }
+
+    #[test]
+    fn test_fragment_count_bounded_by_the_fragment_size() {
+        let start = Instant::now();
+        let mut receiver = Fragmenter::new(&config());
+        let claimed = 1024 * 1024;
+
+        // A million 16 byte fragments make far more than the megabyte a
+        // transfer may be
+        let tiny = Fragment::new(1, 0, claimed, &[1; 16]);
+        assert!(matches!(
+            receiver.receive(tiny, start),
+            Err(SwarmhostError::Peer(_))
+        ));
+        assert!(!receiver.receiving(1));
+
+        // The last fragment alone does not tell; what is asked for again is
+        // capped all the same
+        let last = Fragment::new(2, claimed - 1, claimed, &[1]);
+        assert_eq!(receiver.receive(last, start).unwrap(), None);
+        let stalled = receiver.stalled(start + Duration::from_secs(5));
+        let [Stalled::Rerequest { missing, .. }] = &stalled[..] else {
+            panic!("expected one re-request, got {:?}", stalled);
+        };
+        assert_eq!(missing.len(), MAX_REREQUESTED);
+
+        // A full fragment then shows the count is out of range too
+        let full = Fragment::new(2, 0, claimed, &[1; 16]);
+        assert!(matches!(
+            receiver.receive(full, start),
+            Err(SwarmhostError::Peer(_))
+        ));
+        assert!(!receiver.receiving(2));
+
+        // A last fragment that came first is held to the size learned later
+        let oversized = Fragment::new(3, 2, 3, &[1; 32]);
+        assert_eq!(receiver.receive(oversized, start).unwrap(), None);
+        let full = Fragment::new(3, 0, 3, &[1; 16]);
+        assert!(matches!(
+            receiver.receive(full, start),
+            Err(SwarmhostError::Peer(_))
+        ));
+        assert!(!receiver.receiving(3));
+    }
+
+    #[test]
+    fn test_reassemblies_capped_per_peer() {
+        let now = Instant::now();
+        let mut sender = Fragmenter::new(&config());
+        let mut receiver = Fragmenter::new(&config());
+
+        for _ in 0..2 {
+            let first = fragments(sender.split(payload(300).into(), 164, now).unwrap()).remove(0);
+            receiver.receive(first, now).unwrap();
+        }
+        let third = fragments(sender.split(payload(300).into(), 164, now).unwrap()).remove(0);
+        assert!(matches!(
+            receiver.receive(third, now),
+            Err(SwarmhostError::Peer(_))
+        ));
+    }
+
+    #[test]
+    fn test_corrupted_fragment_ignored() {
+        let now = Instant::now();
+        let mut sender = Fragmenter::new(&config());
+        let mut receiver = Fragmenter::new(&config());
+        let encoded = payload(150);
+
+        let pieces = fragments(sender.split(encoded.clone().into(), 164, now).unwrap());
+        let mut corrupted = pieces[1].clone();
+        corrupted.data[0] ^= 1;
+
+        assert_eq!(receiver.receive(pieces[0].clone(), now).unwrap(), None);
+        assert_eq!(receiver.receive(corrupted, now).unwrap(), None);
+        let result = receiver.receive(pieces[1].clone(), now).unwrap();
+        assert_eq!(result, Some(encoded));
+    }
+}
@@ -0,0 +1,261 @@
+// network/frame.rs - Length-prefixed message framing
+
+use crate::error::{Result, SwarmhostError};
+use bytes::{Buf, Bytes, BytesMut};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+/// Size of the big-endian length prefix in front of every frame
+pub const LENGTH_PREFIX_LEN: usize = 4;
+
+/// Room reserved in `max_message_size` for everything wrapped around an
+/// action payload: length prefix, encryption tag and message envelope
+pub const MAX_FRAME_OVERHEAD: usize = 512;
+
+/// Write one frame: a 4-byte big-endian length followed by the payload
+///
+/// Oversized payloads are rejected before anything is written.
+pub async fn write_frame<W>(writer: &mut W, payload: &[u8], max_size: usize) -> Result<()>
+where
+    W: AsyncWrite + Unpin,
+{
+    if payload.len() > max_size {
+        return Err(SwarmhostError::TooLarge(format!(
+            "Outgoing message of {} bytes exceeds max_message_size {}",
+            payload.len(),
+            max_size
+        )));
+    }
+
+    let mut buf = Vec::with_capacity(LENGTH_PREFIX_LEN + payload.len());
+    buf.extend_from_slice(&(payload.len() as u32).to_be_bytes());
+    buf.extend_from_slice(payload);
+
+    writer.write_all(&buf).await?;
+    writer.flush().await?;
+    Ok(())
+}
+
+/// Read one frame, rejecting any whose declared length exceeds `max_size`
+pub async fn read_frame<R>(reader: &mut R, max_size: usize) -> Result<Vec<u8>>
+where
+    R: AsyncRead + Unpin,
+{
+    let mut len_buf = [0u8; LENGTH_PREFIX_LEN];
+    reader.read_exact(&mut len_buf).await?;
+
+    let len = u32::from_be_bytes(len_buf) as usize;
+    if len > max_size {
+        return Err(SwarmhostError::TooLarge(format!(
+            "Incoming message of {} bytes exceeds max_message_size {}",
+            len, max_size
+        )));
+    }
+
+    let mut payload = vec![0u8; len];
+    reader.read_exact(&mut payload).await?;
+    Ok(payload)
+}
+
+/// Message-oriented wrapper over a byte stream using length-prefixed frames
+///
+/// Both directions are cancel-safe: a `recv` dropped mid-frame keeps the
+/// bytes read so far, and a `send` dropped mid-write leaves the rest of the
+/// frame buffered to be finished by the next `send`, `flush` or `close`.
+pub struct FramedStream<S> {
+    stream: S,
+    max_frame_size: usize,
+    read_buf: BytesMut,
+    write_buf: Vec<u8>,
+    written: usize,
+}
+
+impl<S> FramedStream<S>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    pub fn new(stream: S, max_frame_size: usize) -> Self {
+        Self {
+            stream,
+            max_frame_size,
+            read_buf: BytesMut::with_capacity(8 * 1024),
+            write_buf: Vec::new(),
+            written: 0,
+        }
+    }
+
+    /// Largest payload accepted in either direction
+    pub fn max_frame_size(&self) -> usize {
+        self.max_frame_size
+    }
+
+    /// Send one frame
+    ///
+    /// Oversized payloads are rejected before anything is written.
+    pub async fn send(&mut self, payload: &[u8]) -> Result<()> {
+        self.flush().await?;
+
+        if payload.len() > self.max_frame_size {
+            return Err(SwarmhostError::TooLarge(format!(
+                "Outgoing message of {} bytes exceeds max_message_size {}",
+                payload.len(),
+                self.max_frame_size
+            )));
+        }
+
+        self.write_buf.clear();
+        self.written = 0;
+        self.write_buf
+            .extend_from_slice(&(payload.len() as u32).to_be_bytes());
+        self.write_buf.extend_from_slice(payload);
+        self.flush().await
+    }
+
+    /// Finish writing any partially sent frame
+    pub async fn flush(&mut self) -> Result<()> {
+        while self.written < self.write_buf.len() {
+            let n = self.stream.write(&self.write_buf[self.written..]).await?;
+            if n == 0 {
+                return Err(std::io::Error::from(std::io::ErrorKind::WriteZero).into());
+            }
+            self.written += n;
+        }
+        self.write_buf.clear();
+        self.written = 0;
+        self.stream.flush().await?;
+        Ok(())
+    }
+
+    /// Receive one frame
+    ///
+    /// A frame declaring more than the maximum size shuts the stream down and
+    /// fails with [`SwarmhostError::TooLarge`], code
+    /// [`ErrorCode::TooLarge`](crate::error::ErrorCode::TooLarge), which is
+    /// not retryable.
+    pub async fn recv(&mut self) -> Result<Bytes> {
+        loop {
+            if self.read_buf.len() >= LENGTH_PREFIX_LEN {
+                let len = u32::from_be_bytes([
+                    self.read_buf[0],
+                    self.read_buf[1],
+                    self.read_buf[2],
+                    self.read_buf[3],
+                ]) as usize;
+
+                if len > self.max_frame_size {
+                    let _ = self.stream.shutdown().await;
+                    return Err(SwarmhostError::TooLarge(format!(
+                        "Incoming message of {} bytes exceeds max_message_size {}",
+                        len, self.max_frame_size
+                    )));
+                }
+
+                if self.read_buf.len() >= LENGTH_PREFIX_LEN + len {
+                    self.read_buf.advance(LENGTH_PREFIX_LEN);
+                    return Ok(self.read_buf.split_to(len).freeze());
+                }
+                self.read_buf
+                    .reserve(LENGTH_PREFIX_LEN + len - self.read_buf.len());
+            }
+
+            if self.stream.read_buf(&mut self.read_buf).await? == 0 {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::UnexpectedEof,
+                    "connection closed",
+                )
+                .into());
+            }
+        }
+    }
+
+    /// Flush pending output and shut down the write side
+    pub async fn close(&mut self) -> Result<()> {
+        self.flush().await?;
+        self.stream.shutdown().await?;
+        Ok(())
+    }
+
+    pub fn get_ref(&self) -> &S {
+        &self.stream
+    }
+
+    /// Give back the underlying stream, discarding buffered data
+    pub fn into_inner(self) -> S {
+        self.stream
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_exact_max_and_one_over() {
+        let (a, b) = tokio::io::duplex(64);
+        let mut sender = FramedStream::new(a, 100);
+        let mut receiver = FramedStream::new(b, 100);
+
+        let exact = vec![7u8; 100];
+        let (sent, received) = tokio::join!(sender.send(&exact), receiver.recv());
+        sent.unwrap();
+        assert_eq!(&received.unwrap()[..], &exact[..]);
+
+        let err = sender.send(&[0u8; 101]).await.unwrap_err();
+        assert!(err.to_string().contains("101"));
+    }
+
+    #[tokio::test]
+    async fn test_oversized_incoming_closes() {
+        let (mut a, b) = tokio::io::duplex(64);
+        let mut receiver = FramedStream::new(b, 100);
+
+        a.write_all(&101u32.to_be_bytes()).await.unwrap();
+        match receiver.recv().await {
+            Err(SwarmhostError::TooLarge(msg)) => assert!(msg.contains("101")),
+            other => panic!("expected too-large error, got {:?}", other),
+        }
+
+        let mut buf = [0u8; 1];
+        assert_eq!(a.read(&mut buf).await.unwrap(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_frames_split_across_reads() {
+        let (mut a, b) = tokio::io::duplex(1024);
+        let mut receiver = FramedStream::new(b, 1024);
+
+        let mut wire = Vec::new();
+        for payload in [&b"first"[..], &b""[..], &b"third frame"[..]] {
+            wire.extend_from_slice(&(payload.len() as u32).to_be_bytes());
+            wire.extend_from_slice(payload);
+        }
+
+        let writer = tokio::spawn(async move {
+            for chunk in wire.chunks(3) {
+                a.write_all(chunk).await.unwrap();
+                a.flush().await.unwrap();
+                tokio::task::yield_now().await;
+            }
+            a
+        });
+
+        assert_eq!(&receiver.recv().await.unwrap()[..], b"first");
+        assert_eq!(&receiver.recv().await.unwrap()[..], b"");
+        assert_eq!(&receiver.recv().await.unwrap()[..], b"third frame");
+        drop(writer.await.unwrap());
+        assert!(receiver.recv().await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_cancelled_recv_loses_nothing() {
+        let (mut a, b) = tokio::io::duplex(1024);
+        let mut receiver = FramedStream::new(b, 1024);
+
+        a.write_all(&[0, 0, 0, 5, b'h', b'e']).await.unwrap();
+        let cancelled =
+            tokio::time::timeout(std::time::Duration::from_millis(20), receiver.recv()).await;
+        assert!(cancelled.is_err());
+
+        a.write_all(b"llo").await.unwrap();
+        assert_eq!(&receiver.recv().await.unwrap()[..], b"hello");
+    }
+}
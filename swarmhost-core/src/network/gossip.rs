@@ -0,0 +1,373 @@
+// network/gossip.rs - Epidemic dissemination of proposals, votes, commits,
+// observers' verdicts, the rounds that carry them, the views that order them, checkpoints that
+// fix them and evidence against validators that equivocate
+
+use crate::consensus::{
+    Commit, Equivocation, NewView, ObserverVote, RoundProposal, SignedAction, TimeoutVote,
+    ViewChange, Vote,
+};
+use crate::crypto::{self, Hash, PlayerId};
+use crate::node::GossipConfig;
+use crate::state::{Checkpoint, CheckpointVote};
+use rand::SeedableRng;
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+
+/// Identifier of a gossiped message: the hash of its payload
+pub type MessageId = Hash;
+
+/// What travels over the gossip layer
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum GossipPayload {
+    Proposal(SignedAction),
+    Vote(Vote),
+    Commit(Commit),
+    /// The actions put forward in a round
+    Round(RoundProposal),
+    /// A vote to skip a round
+    Timeout(TimeoutVote),
+    /// A request to move to a later view
+    ViewChange(ViewChange),
+    /// The start of a view
+    NewView(NewView),
+    /// Proof that a validator signed two conflicting messages
+    Evidence(Box<Equivocation>),
+    /// A validator's signature over a game's log at a checkpoint sequence
+    CheckpointVote(CheckpointVote),
+    /// A quorum's signatures over a game's log at a checkpoint sequence
+    Checkpoint(Checkpoint),
+    /// An action of a type delivered without consensus
+    Unsequenced(SignedAction),
+    /// A non-validator's verdict on an action or block, tallied apart
+    ObserverVote(ObserverVote),
+}
+
+/// A payload plus how much further it may travel
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct GossipMessage {
+    /// Hops left, including the one to the receiver
+    pub hops_left: u8,
+    pub payload: GossipPayload,
+}
+
+impl GossipMessage {
+    /// Id of the payload; the hop count is excluded so every copy matches
+    ///
+    /// Signatures are covered too, so a copy with a forged signature cannot
+    /// shadow the genuine message in the seen-cache.
+    pub fn id(&self) -> MessageId {
+        match &self.payload {
+            GossipPayload::Proposal(action) => {
+                crypto::hash_multiple(&[b"proposal", &action.signing_bytes(), &action.signature])
+            }
+            GossipPayload::Vote(vote) => {
+                crypto::hash_multiple(&[b"vote", &vote.signing_bytes(), &vote.signature])
+            }
+            GossipPayload::Commit(commit) => crypto::hash_multiple(&[
+                b"commit",
+                &commit.signing_bytes(),
+                &commit.signature,
+                &commit.action.signature,
+            ]),
+            GossipPayload::Round(proposal) => {
+                crypto::hash_multiple(&[b"round", &proposal.signing_bytes(), &proposal.signature])
+            }
+            GossipPayload::Timeout(vote) => {
+                crypto::hash_multiple(&[b"timeout", &vote.signing_bytes(), &vote.signature])
+            }
+            GossipPayload::ViewChange(change) => {
+                crypto::hash_multiple(&[b"view-change", &change.signing_bytes(), &change.signature])
+            }
+            GossipPayload::NewView(new_view) => crypto::hash_multiple(&[
+                b"new-view",
+                &new_view.signing_bytes(),
+                &new_view.signature,
+            ]),
+            // Both messages, signatures and all
+            GossipPayload::Evidence(evidence) => crypto::hash_multiple(&[
+                b"evidence",
+                &bincode::serialize(evidence).unwrap_or_default(),
+            ]),
+            GossipPayload::CheckpointVote(vote) => crypto::hash_multiple(&[
+                b"checkpoint-vote",
+                &vote.signing_bytes(),
+                &vote.signer,
+                &vote.signature,
+            ]),
+            // Every signature, so a copy with a forged one cannot shadow it
+            GossipPayload::Checkpoint(checkpoint) => crypto::hash_multiple(&[
+                b"checkpoint",
+                &bincode::serialize(checkpoint).unwrap_or_default(),
+            ]),
+            GossipPayload::Unsequenced(action) => {
+                crypto::hash_multiple(&[b"unsequenced", &action.signing_bytes(), &action.signature])
+            }
+            GossipPayload::ObserverVote(vote) => {
+                crypto::hash_multiple(&[b"observer-vote", &vote.signing_bytes(), &vote.signature])
+            }
+        }
+    }
+}
+
+/// Least-recently-seen eviction over a fixed number of message ids
+pub struct SeenCache {
+    capacity: usize,
+    stamps: HashMap<MessageId, u64>,
+    by_age: BTreeMap<u64, MessageId>,
+    clock: u64,
+}
+
+impl SeenCache {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity: capacity.max(1),
+            stamps: HashMap::new(),
+            by_age: BTreeMap::new(),
+            clock: 0,
+        }
+    }
+
+    /// Record `id`, returning true the first time it is seen
+    ///
+    /// Seeing an id again makes it the most recent, so messages still
+    /// circulating are the last to be forgotten.
+    pub fn insert(&mut self, id: MessageId) -> bool {
+        self.clock += 1;
+        let fresh = match self.stamps.insert(id, self.clock) {
+            Some(previous) => {
+                self.by_age.remove(&previous);
+                false
+            }
+            None => true,
+        };
+        self.by_age.insert(self.clock, id);
+
+        while self.stamps.len() > self.capacity {
+            if let Some((_, oldest)) = self.by_age.pop_first() {
+                self.stamps.remove(&oldest);
+            }
+        }
+        fresh
+    }
+
+    pub fn contains(&self, id: &MessageId) -> bool {
+        self.stamps.contains_key(id)
+    }
+
+    pub fn len(&self) -> usize {
+        self.stamps.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.stamps.is_empty()
+    }
+}
+
+/// Per-node gossip state: which messages were seen and who to pass them to
+///
+/// This only decides; sending is up to the caller.
+pub struct Gossip {
+    seen: SeenCache,
+    rng: StdRng,
+}
+
+impl Gossip {
+    pub fn new(config: &GossipConfig) -> Self {
+        Self::with_rng(config, StdRng::from_entropy())
+    }
+
+    /// Use a specific random source for peer selection (e.g. seeded in tests)
+    pub fn with_rng(config: &GossipConfig, rng: StdRng) -> Self {
+        Self {
+            seen: SeenCache::new(config.seen_cache_size),
+            rng,
+        }
+    }
+
+    /// Start spreading a locally created payload
+    ///
+    /// Returns the message and the peers to send it to.
+    pub fn publish(
+        &mut self,
+        payload: GossipPayload,
+        peers: &[PlayerId],
+        config: &GossipConfig,
+    ) -> (GossipMessage, Vec<PlayerId>) {
+        let message = GossipMessage {
+            hops_left: config.max_hops,
+            payload,
+        };
+        self.seen.insert(message.id());
+        let targets = self.pick(peers, None, config.fanout);
+        (message, targets)
+    }
+
+    /// Mark a received message as seen, returning false for duplicates
+    ///
+    /// Only messages seen for the first time should reach the application.
+    pub fn observe(&mut self, message: &GossipMessage) -> bool {
+        self.seen.insert(message.id())
+    }
+
+    /// Where to pass on a message received from `from`, if anywhere
+    ///
+    /// The hop count is capped at our own limit so a peer cannot make a
+    /// message live longer than the network allows.
+    pub fn relay(
+        &mut self,
+        mut message: GossipMessage,
+        from: PlayerId,
+        peers: &[PlayerId],
+        config: &GossipConfig,
+    ) -> Option<(GossipMessage, Vec<PlayerId>)> {
+        let hops_left = message.hops_left.min(config.max_hops);
+        if hops_left <= 1 {
+            return None;
+        }
+        message.hops_left = hops_left - 1;
+
+        let targets = self.pick(peers, Some(from), config.fanout);
+        (!targets.is_empty()).then_some((message, targets))
+    }
+
+    /// Up to `fanout` random peers, never `exclude`
+    fn pick(
+        &mut self,
+        peers: &[PlayerId],
+        exclude: Option<PlayerId>,
+        fanout: usize,
+    ) -> Vec<PlayerId> {
+        let candidates: Vec<PlayerId> = peers
+            .iter()
+            .copied()
+            .filter(|peer| Some(*peer) != exclude)
+            .collect();
+        candidates
+            .choose_multiple(&mut self.rng, fanout)
+            .copied()
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::KeyPair;
+    use std::collections::VecDeque;
+
+    fn proposal() -> GossipPayload {
+        GossipPayload::Proposal(SignedAction::new(
+            &KeyPair::generate(),
+            "game",
+            0,
+            1,
+            b"move".to_vec(),
+        ))
+    }
+
+    #[test]
+    fn test_seen_cache_evicts_least_recent() {
+        let mut cache = SeenCache::new(2);
+        assert!(cache.insert([1; 32]));
+        assert!(cache.insert([2; 32]));
+        assert!(!cache.insert([1; 32]));
+
+        // [2] is now the least recently seen
+        assert!(cache.insert([3; 32]));
+        assert_eq!(cache.len(), 2);
+        assert!(cache.contains(&[1; 32]));
+        assert!(!cache.contains(&[2; 32]));
+    }
+
+    #[test]
+    fn test_forged_copy_has_its_own_id() {
+        let genuine = GossipMessage {
+            hops_left: 3,
+            payload: proposal(),
+        };
+        let mut forged = genuine.clone();
+        if let GossipPayload::Proposal(action) = &mut forged.payload {
+            action.signature = vec![0; 64];
+        }
+
+        let mut relayed = genuine.clone();
+        relayed.hops_left = 1;
+        assert_eq!(genuine.id(), relayed.id());
+        assert_ne!(genuine.id(), forged.id());
+    }
+
+    #[test]
+    fn test_relay_respects_hops_and_sender() {
+        let config = GossipConfig {
+            fanout: 8,
+            seen_cache_size: 16,
+            max_hops: 3,
+        };
+        let mut gossip = Gossip::with_rng(&config, StdRng::seed_from_u64(1));
+        let peers = [[1; 32], [2; 32], [3; 32]];
+
+        // An inflated hop count is capped at our limit
+        let message = GossipMessage {
+            hops_left: 200,
+            payload: proposal(),
+        };
+        let (relayed, targets) = gossip.relay(message, [1; 32], &peers, &config).unwrap();
+        assert_eq!(relayed.hops_left, 2);
+        assert_eq!(targets.len(), 2);
+        assert!(!targets.contains(&[1; 32]));
+
+        let (last, _) = gossip.relay(relayed, [2; 32], &peers, &config).unwrap();
+        assert_eq!(last.hops_left, 1);
+        assert!(gossip.relay(last, [3; 32], &peers, &config).is_none());
+    }
+
+    /// Ten fully connected nodes; the originator only talks to `fanout` of
+    /// them and the rest hear about the action from each other
+    #[test]
+    fn test_ten_node_spread_delivers_once_each() {
+        const NODES: usize = 10;
+        let config = GossipConfig {
+            fanout: 5,
+            seen_cache_size: 64,
+            max_hops: 6,
+        };
+        let ids: Vec<PlayerId> = (0..NODES as u8).map(|i| [i; 32]).collect();
+        let mut nodes: Vec<Gossip> = (0..NODES as u64)
+            .map(|i| Gossip::with_rng(&config, StdRng::seed_from_u64(i)))
+            .collect();
+        let peers_of = |i: usize| -> Vec<PlayerId> {
+            ids.iter().copied().filter(|id| *id != ids[i]).collect()
+        };
+
+        let (message, targets) = nodes[0].publish(proposal(), &peers_of(0), &config);
+        assert_eq!(targets.len(), config.fanout);
+
+        let mut in_flight: VecDeque<(PlayerId, PlayerId, GossipMessage)> = targets
+            .into_iter()
+            .map(|to| (ids[0], to, message.clone()))
+            .collect();
+        let mut delivered = vec![0; NODES];
+        let mut copies = 0;
+
+        while let Some((from, to, message)) = in_flight.pop_front() {
+            copies += 1;
+            let node = to[0] as usize;
+            if !nodes[node].observe(&message) {
+                continue;
+            }
+            delivered[node] += 1;
+            if let Some((message, targets)) =
+                nodes[node].relay(message, from, &peers_of(node), &config)
+            {
+                in_flight.extend(targets.into_iter().map(|next| (to, next, message.clone())));
+            }
+        }
+
+        // The originator already had it; everyone else got it exactly once
+        assert_eq!(delivered[0], 0);
+        assert!(delivered[1..].iter().all(|&n| n == 1), "{:?}", delivered);
+        assert!(copies > NODES, "duplicates were never exercised");
+    }
+}
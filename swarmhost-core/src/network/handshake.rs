@@ -0,0 +1,298 @@
+// network/handshake.rs - Connection handshake primitives
+
+use crate::crypto::PlayerId;
+use crate::error::{Result, SwarmhostError};
+use crate::node::NetworkConfig;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+/// Wire protocol versions this build speaks
+///
+/// Bumped whenever the meaning of frames changes, independently of the crate
+/// version.
+pub const SUPPORTED_PROTOCOLS: VersionRange = VersionRange { min: 1, max: 1 };
+
+/// Which end of a connection we are
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Role {
+    /// We dialed the peer
+    Initiator,
+    /// The peer dialed us
+    Responder,
+}
+
+/// Reason a connection was refused or closed, sent to the remote side
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[repr(u16)]
+pub enum CloseCode {
+    /// Orderly close
+    Normal = 1000,
+    /// Nothing heard from the peer within `peer_timeout`
+    Timeout = 1001,
+    /// Peer sent something we could not interpret
+    ProtocolError = 1002,
+    /// Peer stopped draining our control traffic
+    Overloaded = 1003,
+    /// Peer kept sending faster than our inbound limits allow
+    RateLimited = 1004,
+    /// The peer left the game we were playing together
+    Leaving = 1005,
+    /// The peer's node is shutting down
+    Shutdown = 1006,
+    /// The peer removed us: it banned or evicted us, or we misbehaved
+    Kicked = 1007,
+    /// Handshake did not complete in time
+    HandshakeTimeout = 1010,
+    /// One side requires encryption and the other refuses it
+    EncryptionRequired = 1011,
+    /// Both sides want encryption but share no cipher suite
+    NoCommonCipher = 1012,
+    /// The peer claimed a player id other than the one its transport proved
+    IdentityMismatch = 1013,
+    /// The peers share no wire protocol version
+    IncompatibleVersion = 1014,
+    /// The peers share no wire format
+    NoCommonFormat = 1015,
+    /// The peer's signature over the handshake did not match the player id
+    /// it claimed
+    BadIdentityProof = 1016,
+    /// An allowlist is configured and the peer is not on it
+    NotInvited = 1020,
+    /// The peer is on the denylist
+    Banned = 1021,
+    /// At `max_peers`, with no connection worth dropping for the peer
+    Busy = 1022,
+    /// Dropped at `max_peers` to make room for a more valuable peer
+    Evicted = 1023,
+    /// The peer's player id is already connected on another live connection
+    AlreadyConnected = 1024,
+}
+
+impl CloseCode {
+    /// Numeric value carried on the wire
+    pub fn as_u16(self) -> u16 {
+        self as u16
+    }
+
+    /// The code carried as `code` on the wire, if it is one we know
+    pub fn from_u16(code: u16) -> Option<CloseCode> {
+        use CloseCode::*;
+        [
+            Normal,
+            Timeout,
+            ProtocolError,
+            Overloaded,
+            RateLimited,
+            Leaving,
+            Shutdown,
+            Kicked,
+            HandshakeTimeout,
+            EncryptionRequired,
+            NoCommonCipher,
+            IdentityMismatch,
+            IncompatibleVersion,
+            NoCommonFormat,
+            BadIdentityProof,
+            NotInvited,
+            Banned,
+            Busy,
+            Evicted,
+            AlreadyConnected,
+        ]
+        .into_iter()
+        .find(|known| known.as_u16() == code)
+    }
+
+    /// How the peer reports it when we close a connection for this reason
+    ///
+    /// Reasons that are about the peer (a ban, eviction, its traffic) mean it
+    /// was kicked; the rest mean the same on both sides.
+    pub fn as_seen_by_peer(self) -> CloseCode {
+        match self {
+            CloseCode::Overloaded
+            | CloseCode::RateLimited
+            | CloseCode::NotInvited
+            | CloseCode::Banned
+            | CloseCode::Busy
+            | CloseCode::Evicted
+            | CloseCode::AlreadyConnected => CloseCode::Kicked,
+            other => other,
+        }
+    }
+}
+
+/// An inclusive range of wire protocol versions
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct VersionRange {
+    pub min: u16,
+    pub max: u16,
+}
+
+impl VersionRange {
+    pub fn contains(self, version: u16) -> bool {
+        (self.min..=self.max).contains(&version)
+    }
+
+    /// The highest version both sides speak
+    ///
+    /// Fails with [`CloseCode::IncompatibleVersion`] naming both ranges when
+    /// they do not overlap, and with [`CloseCode::ProtocolError`] when the
+    /// peer's range is empty.
+    pub fn negotiate(self, remote: VersionRange) -> Result<u16> {
+        if remote.min > remote.max {
+            return Err(SwarmhostError::handshake(
+                CloseCode::ProtocolError,
+                format!("peer sent an empty protocol range {}", remote),
+            ));
+        }
+        if remote.min > self.max {
+            return Err(SwarmhostError::handshake(
+                CloseCode::IncompatibleVersion,
+                format!(
+                    "peer requires protocol {}, we support up to {} (peer {}, us {})",
+                    remote.min, self.max, remote, self
+                ),
+            ));
+        }
+        if remote.max < self.min {
+            return Err(SwarmhostError::handshake(
+                CloseCode::IncompatibleVersion,
+                format!(
+                    "peer supports up to protocol {}, we require {} (peer {}, us {})",
+                    remote.max, self.min, remote, self
+                ),
+            ));
+        }
+        Ok(self.max.min(remote.max))
+    }
+}
+
+impl fmt::Display for VersionRange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.min == self.max {
+            write!(f, "v{}", self.min)
+        } else {
+            write!(f, "v{}-v{}", self.min, self.max)
+        }
+    }
+}
+
+/// Whether the peer lists in `config` let `peer` connect
+///
+/// The denylist wins over the allowlist.
+pub fn check_admission(
+    config: &NetworkConfig,
+    peer: &PlayerId,
+) -> std::result::Result<(), CloseCode> {
+    if config.denylist.contains(peer) {
+        return Err(CloseCode::Banned);
+    }
+    match &config.allowlist {
+        Some(allowlist) if !allowlist.contains(peer) => Err(CloseCode::NotInvited),
+        _ => Ok(()),
+    }
+}
+
+impl fmt::Display for CloseCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?} ({})", self, self.as_u16())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_check_admission() {
+        let (alice, bob, carol) = ([1u8; 32], [2u8; 32], [3u8; 32]);
+        let mut config = NetworkConfig::default();
+        assert_eq!(check_admission(&config, &alice), Ok(()));
+
+        config.denylist = vec![bob];
+        assert_eq!(check_admission(&config, &bob), Err(CloseCode::Banned));
+        assert_eq!(check_admission(&config, &alice), Ok(()));
+
+        config.allowlist = Some(vec![alice, bob]);
+        assert_eq!(check_admission(&config, &alice), Ok(()));
+        assert_eq!(check_admission(&config, &bob), Err(CloseCode::Banned));
+        assert_eq!(check_admission(&config, &carol), Err(CloseCode::NotInvited));
+    }
+
+    #[test]
+    fn test_close_codes_round_trip_and_map_for_the_peer() {
+        for code in [
+            CloseCode::Leaving,
+            CloseCode::Kicked,
+            CloseCode::AlreadyConnected,
+        ] {
+            assert_eq!(CloseCode::from_u16(code.as_u16()), Some(code));
+        }
+        assert_eq!(CloseCode::from_u16(1999), None);
+
+        assert_eq!(CloseCode::Banned.as_seen_by_peer(), CloseCode::Kicked);
+        assert_eq!(CloseCode::Evicted.as_seen_by_peer(), CloseCode::Kicked);
+        assert_eq!(CloseCode::Shutdown.as_seen_by_peer(), CloseCode::Shutdown);
+        assert_eq!(
+            CloseCode::ProtocolError.as_seen_by_peer(),
+            CloseCode::ProtocolError
+        );
+    }
+
+    fn range(min: u16, max: u16) -> VersionRange {
+        VersionRange { min, max }
+    }
+
+    fn close_code(result: Result<u16>) -> CloseCode {
+        match result {
+            Err(SwarmhostError::Handshake { code, .. }) => code,
+            other => panic!("expected handshake error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_overlapping_ranges_pick_highest_common() {
+        assert_eq!(range(1, 3).negotiate(range(2, 5)).unwrap(), 3);
+        assert_eq!(range(2, 5).negotiate(range(1, 3)).unwrap(), 3);
+        assert_eq!(range(1, 1).negotiate(range(1, 1)).unwrap(), 1);
+        assert_eq!(range(1, 4).negotiate(range(2, 2)).unwrap(), 2);
+    }
+
+    #[test]
+    fn test_disjoint_ranges_name_both_sides() {
+        let newer = range(1, 2).negotiate(range(3, 4));
+        assert_eq!(close_code(newer), CloseCode::IncompatibleVersion);
+        let message = range(1, 2).negotiate(range(3, 4)).unwrap_err().to_string();
+        assert!(
+            message.contains("peer requires protocol 3, we support up to 2"),
+            "{}",
+            message
+        );
+        assert!(
+            message.contains("v3-v4") && message.contains("v1-v2"),
+            "{}",
+            message
+        );
+
+        let older = range(3, 4).negotiate(range(1, 2)).unwrap_err().to_string();
+        assert!(
+            older.contains("peer supports up to protocol 2, we require 3"),
+            "{}",
+            older
+        );
+    }
+
+    #[test]
+    fn test_absurd_ranges_from_peer() {
+        // Claiming every version just lands on our newest
+        assert_eq!(range(1, 2).negotiate(range(0, u16::MAX)).unwrap(), 2);
+        assert_eq!(
+            close_code(range(1, 2).negotiate(range(u16::MAX, 0))),
+            CloseCode::ProtocolError
+        );
+        assert_eq!(
+            close_code(range(1, 2).negotiate(range(u16::MAX, u16::MAX))),
+            CloseCode::IncompatibleVersion
+        );
+    }
+}
@@ -0,0 +1,182 @@
+// network/happy_eyeballs.rs - Racing connections to a peer's addresses
+
+use super::transport::{Connection, Transport};
+use crate::error::{Result, SwarmhostError};
+use std::future::Future;
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::task::Poll;
+use std::time::Duration;
+
+/// How long each attempt runs alone before the next one starts
+pub const HEAD_START: Duration = Duration::from_millis(250);
+
+/// Which IP version a connection runs over
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum AddressFamily {
+    Ipv4,
+    Ipv6,
+}
+
+impl AddressFamily {
+    pub fn of(addr: &SocketAddr) -> Self {
+        match addr {
+            SocketAddr::V4(_) => Self::Ipv4,
+            SocketAddr::V6(_) => Self::Ipv6,
+        }
+    }
+}
+
+/// The order to try `addrs` in: alternating families, IPv6 first, each
+/// family keeping its own order; duplicates are dropped
+pub fn interleave(addrs: &[SocketAddr]) -> Vec<SocketAddr> {
+    let mut unique: Vec<SocketAddr> = Vec::with_capacity(addrs.len());
+    for addr in addrs {
+        if !unique.contains(addr) {
+            unique.push(*addr);
+        }
+    }
+    let (v6, v4): (Vec<_>, Vec<_>) = unique.into_iter().partition(SocketAddr::is_ipv6);
+
+    let mut ordered = Vec::with_capacity(v6.len() + v4.len());
+    let (mut v6, mut v4) = (v6.into_iter(), v4.into_iter());
+    loop {
+        match (v6.next(), v4.next()) {
+            (None, None) => return ordered,
+            (first, second) => ordered.extend(first.into_iter().chain(second)),
+        }
+    }
+}
+
+type Attempt<'a> =
+    Pin<Box<dyn Future<Output = (SocketAddr, Result<Box<dyn Connection>>)> + Send + 'a>>;
+
+/// Connect to whichever of `addrs` answers first
+///
+/// Attempts start in [`interleave`] order, each `head_start` after the one
+/// before or as soon as it fails. The first connection up wins and the
+/// attempts still running are dropped, so at most one connection results.
+pub async fn connect(
+    transport: &dyn Transport,
+    addrs: &[SocketAddr],
+    head_start: Duration,
+) -> Result<(SocketAddr, Box<dyn Connection>)> {
+    let mut queue = interleave(addrs).into_iter();
+    let mut attempts: Vec<Attempt<'_>> = Vec::new();
+    let mut failure = None;
+
+    loop {
+        if attempts.is_empty() {
+            let Some(addr) = queue.next() else {
+                return Err(failure
+                    .unwrap_or_else(|| SwarmhostError::Peer("No address to dial".to_string())));
+            };
+            attempts.push(attempt(transport, addr));
+        }
+
+        let waiting = !queue.as_slice().is_empty();
+        let finished = tokio::select! {
+            finished = first(&mut attempts) => Some(finished),
+            _ = tokio::time::sleep(head_start), if waiting => None,
+        };
+        match finished {
+            Some((addr, Ok(conn))) => return Ok((addr, conn)),
+            Some((addr, Err(e))) => {
+                tracing::debug!("Connecting to {} failed: {}", addr, e);
+                failure = Some(e);
+                if let Some(next) = queue.next() {
+                    attempts.push(attempt(transport, next));
+                }
+            }
+            None => {
+                if let Some(next) = queue.next() {
+                    attempts.push(attempt(transport, next));
+                }
+            }
+        }
+    }
+}
+
+fn attempt(transport: &dyn Transport, addr: SocketAddr) -> Attempt<'_> {
+    Box::pin(async move { (addr, transport.dial(addr).await) })
+}
+
+/// The first attempt to finish, which is removed from `attempts`
+async fn first(attempts: &mut Vec<Attempt<'_>>) -> (SocketAddr, Result<Box<dyn Connection>>) {
+    std::future::poll_fn(|cx| {
+        for i in 0..attempts.len() {
+            if let Poll::Ready(finished) = attempts[i].as_mut().poll(cx) {
+                drop(attempts.swap_remove(i));
+                return Poll::Ready(finished);
+            }
+        }
+        Poll::Pending
+    })
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::network::sim::{NetworkConditions, SimNetwork};
+    use crate::node::NetworkConfig;
+    use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+    use tokio::time::Instant;
+
+    fn v4(port: u16) -> SocketAddr {
+        SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), port)
+    }
+
+    fn v6(port: u16) -> SocketAddr {
+        SocketAddr::new(IpAddr::V6(Ipv6Addr::LOCALHOST), port)
+    }
+
+    #[test]
+    fn test_interleave_starts_with_ipv6() {
+        assert_eq!(
+            interleave(&[v4(1), v4(2), v6(3), v4(1)]),
+            vec![v6(3), v4(1), v4(2)]
+        );
+        assert_eq!(interleave(&[v4(1)]), vec![v4(1)]);
+        assert!(interleave(&[]).is_empty());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_falls_back_to_ipv4_after_head_start() {
+        let sim = SimNetwork::new(1);
+        let config = NetworkConfig::default();
+        let server = sim.transport(&config);
+        let listener = server.listen(v4(0)).await.unwrap();
+        let port = listener.local_addr().port();
+        let _v6 = server.listen(v6(port)).await.unwrap();
+
+        let client = sim.transport(&config);
+        let home = client.listen(v4(0)).await.unwrap().local_addr();
+        sim.set_conditions(home, v6(port), NetworkConditions::perfect().with_loss(1.0));
+
+        let started = Instant::now();
+        let (addr, _conn) = connect(&client, &[v4(port), v6(port)], HEAD_START)
+            .await
+            .unwrap();
+        assert_eq!(addr, v4(port));
+        assert_eq!(started.elapsed(), HEAD_START);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_failed_attempt_starts_the_next_at_once() {
+        let sim = SimNetwork::new(1);
+        let config = NetworkConfig::default();
+        let server = sim.transport(&config);
+        let listener = server.listen(v4(0)).await.unwrap();
+        let port = listener.local_addr().port();
+
+        let client = sim.transport(&config);
+        let started = Instant::now();
+        // Nothing listens on IPv6, so that attempt is refused straight away
+        let (addr, _conn) = connect(&client, &[v6(port), v4(port)], HEAD_START)
+            .await
+            .unwrap();
+        assert_eq!(addr, v4(port));
+        assert!(started.elapsed() < HEAD_START);
+    }
+}
@@ -0,0 +1,295 @@
+// network/heartbeat.rs - Ping/pong liveness checks and RTT estimation
+
+use super::message::{CommitMark, PeerMessage};
+use std::collections::VecDeque;
+use std::time::Duration;
+use tokio::time::Instant;
+
+/// Each new RTT sample moves the average 1/RTT_SMOOTHING of the way (as in
+/// TCP's SRTT)
+pub const RTT_SMOOTHING: u32 = 8;
+
+/// Pings remembered while waiting for their pongs
+const MAX_IN_FLIGHT: usize = 8;
+
+/// What a connection should do when its heartbeat deadline passes
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Tick {
+    /// Nothing due yet
+    Wait,
+    /// Send this ping, once the caller has marked it with its latest
+    /// commit
+    Ping(Box<PeerMessage>),
+    /// Nothing heard from the peer within the timeout
+    TimedOut,
+}
+
+/// Heartbeat bookkeeping for one connection
+///
+/// The caller owns the clock: every method takes `now`, so tests can drive it
+/// with arbitrary instants. Once a round trip is measured, any outgoing
+/// traffic postpones the next ping; any incoming traffic resets the timeout.
+#[derive(Debug)]
+pub struct Heartbeat {
+    started: Instant,
+    last_sent: Instant,
+    /// When we last pinged, which traffic does not postpone until a pong
+    /// gives us the RTT, so that busy connections measure it too
+    last_pinged: Instant,
+    last_received: Instant,
+    next_nonce: u64,
+    in_flight: VecDeque<(u64, Instant)>,
+    rtt: Option<Duration>,
+}
+
+impl Heartbeat {
+    pub fn new(now: Instant) -> Self {
+        Self {
+            started: now,
+            last_sent: now,
+            last_pinged: now,
+            last_received: now,
+            next_nonce: 0,
+            in_flight: VecDeque::new(),
+            rtt: None,
+        }
+    }
+
+    /// When `tick` next has something to do
+    pub fn deadline(&self, interval: Duration, timeout: Duration) -> Instant {
+        (self.quiet_since() + interval).min(self.last_received + timeout)
+    }
+
+    /// Since when no ping, nor once the RTT is known any traffic, was sent
+    fn quiet_since(&self) -> Instant {
+        match self.rtt {
+            Some(_) => self.last_sent,
+            None => self.last_pinged,
+        }
+    }
+
+    /// Decide whether to ping or give up on the peer
+    pub fn tick(&mut self, now: Instant, interval: Duration, timeout: Duration) -> Tick {
+        if now >= self.last_received + timeout {
+            return Tick::TimedOut;
+        }
+        if now < self.quiet_since() + interval {
+            return Tick::Wait;
+        }
+
+        let nonce = self.next_nonce;
+        self.next_nonce += 1;
+        if self.in_flight.len() == MAX_IN_FLIGHT {
+            self.in_flight.pop_front();
+        }
+        self.in_flight.push_back((nonce, now));
+        self.record_sent(now);
+        self.last_pinged = now;
+
+        Tick::Ping(Box::new(PeerMessage::Ping {
+            nonce,
+            sent_at_ms: now.duration_since(self.started).as_millis() as u64,
+            mark: None,
+        }))
+    }
+
+    /// Note outgoing traffic, which makes a ping redundant
+    pub fn record_sent(&mut self, now: Instant) {
+        self.last_sent = now;
+    }
+
+    /// Note incoming traffic of any kind
+    pub fn record_received(&mut self, now: Instant) {
+        self.last_received = now;
+    }
+
+    /// Fold the pong for one of our pings into the RTT estimate
+    ///
+    /// Returns the updated estimate, or `None` for an unknown nonce.
+    pub fn record_pong(&mut self, nonce: u64, now: Instant) -> Option<Duration> {
+        let index = self.in_flight.iter().position(|(n, _)| *n == nonce)?;
+        let (_, sent) = self.in_flight.remove(index)?;
+        // Pings older than this one will never be answered
+        self.in_flight.retain(|(n, _)| *n > nonce);
+
+        let sample = now.duration_since(sent);
+        let rtt = match self.rtt {
+            Some(rtt) => ewma(rtt, sample),
+            None => sample,
+        };
+        self.rtt = Some(rtt);
+        Some(rtt)
+    }
+
+    /// Smoothed round-trip time, once a pong has arrived
+    pub fn rtt(&self) -> Option<Duration> {
+        self.rtt
+    }
+}
+
+/// The pong answering `ping` with our commit `mark`, or `None` if it is
+/// not a ping
+pub fn pong_for(ping: &PeerMessage, mark: Option<CommitMark>) -> Option<PeerMessage> {
+    match *ping {
+        PeerMessage::Ping {
+            nonce, sent_at_ms, ..
+        } => Some(PeerMessage::Pong {
+            nonce,
+            sent_at_ms,
+            mark,
+        }),
+        _ => None,
+    }
+}
+
+fn ewma(average: Duration, sample: Duration) -> Duration {
+    (average * (RTT_SMOOTHING - 1) + sample) / RTT_SMOOTHING
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const INTERVAL: Duration = Duration::from_secs(3);
+    const TIMEOUT: Duration = Duration::from_secs(30);
+
+    fn ping_nonce(tick: Tick) -> u64 {
+        match tick {
+            Tick::Ping(ping) => match *ping {
+                PeerMessage::Ping { nonce, .. } => nonce,
+                other => panic!("expected ping, got {:?}", other),
+            },
+            other => panic!("expected ping, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_pings_every_interval_unless_traffic_was_sent() {
+        let start = Instant::now();
+        let mut hb = Heartbeat::new(start);
+
+        assert_eq!(hb.deadline(INTERVAL, TIMEOUT), start + INTERVAL);
+        assert_eq!(hb.tick(start + INTERVAL / 2, INTERVAL, TIMEOUT), Tick::Wait);
+        let first = ping_nonce(hb.tick(start + INTERVAL, INTERVAL, TIMEOUT));
+        assert_eq!(first, 0);
+        hb.record_pong(first, start + INTERVAL + Duration::from_millis(40));
+
+        // Application traffic at 4s postpones the next ping to 7s
+        hb.record_sent(start + Duration::from_secs(4));
+        assert_eq!(
+            hb.tick(start + Duration::from_secs(6), INTERVAL, TIMEOUT),
+            Tick::Wait
+        );
+        assert_eq!(
+            ping_nonce(hb.tick(start + Duration::from_secs(7), INTERVAL, TIMEOUT)),
+            1
+        );
+    }
+
+    #[test]
+    fn test_pings_through_traffic_until_a_round_trip_is_measured() {
+        let start = Instant::now();
+        let mut hb = Heartbeat::new(start);
+
+        // Traffic never lets up, but a ping goes out each interval all the
+        // same while none is answered
+        hb.record_sent(start + INTERVAL / 2);
+        assert_eq!(hb.deadline(INTERVAL, TIMEOUT), start + INTERVAL);
+        assert_eq!(ping_nonce(hb.tick(start + INTERVAL, INTERVAL, TIMEOUT)), 0);
+        hb.record_sent(start + INTERVAL * 3 / 2);
+        let second = ping_nonce(hb.tick(start + INTERVAL * 2, INTERVAL, TIMEOUT));
+        assert_eq!(second, 1);
+
+        let answered = start + INTERVAL * 2 + Duration::from_millis(40);
+        hb.record_pong(second, answered);
+        hb.record_sent(answered);
+        assert_eq!(hb.deadline(INTERVAL, TIMEOUT), answered + INTERVAL);
+    }
+
+    #[test]
+    fn test_timely_pongs_keep_peer_alive() {
+        let start = Instant::now();
+        let mut hb = Heartbeat::new(start);
+
+        let mut now = start;
+        for _ in 0..20 {
+            now += INTERVAL;
+            let nonce = ping_nonce(hb.tick(now, INTERVAL, TIMEOUT));
+            let answered = now + Duration::from_millis(40);
+            hb.record_received(answered);
+            assert!(hb.record_pong(nonce, answered).is_some());
+        }
+
+        assert!(now - start > TIMEOUT);
+        assert_eq!(hb.rtt(), Some(Duration::from_millis(40)));
+    }
+
+    #[test]
+    fn test_silent_peer_times_out_exactly() {
+        let start = Instant::now();
+        let mut hb = Heartbeat::new(start);
+
+        let mut now = start;
+        while now + INTERVAL < start + TIMEOUT {
+            now += INTERVAL;
+            ping_nonce(hb.tick(now, INTERVAL, TIMEOUT));
+        }
+
+        assert_eq!(hb.deadline(INTERVAL, TIMEOUT), start + TIMEOUT);
+        assert_ne!(
+            hb.tick(start + TIMEOUT - Duration::from_nanos(1), INTERVAL, TIMEOUT),
+            Tick::TimedOut
+        );
+        assert_eq!(hb.tick(start + TIMEOUT, INTERVAL, TIMEOUT), Tick::TimedOut);
+    }
+
+    #[test]
+    fn test_rtt_moving_average() {
+        let start = Instant::now();
+        let mut hb = Heartbeat::new(start);
+        assert_eq!(hb.rtt(), None);
+
+        let first = ping_nonce(hb.tick(start + INTERVAL, INTERVAL, TIMEOUT));
+        let sent = start + INTERVAL;
+        assert_eq!(
+            hb.record_pong(first, sent + Duration::from_millis(100)),
+            Some(Duration::from_millis(100))
+        );
+
+        // 7/8 * 100ms + 1/8 * 20ms = 90ms
+        let second = ping_nonce(hb.tick(sent + INTERVAL, INTERVAL, TIMEOUT));
+        assert_eq!(
+            hb.record_pong(second, sent + INTERVAL + Duration::from_millis(20)),
+            Some(Duration::from_millis(90))
+        );
+
+        // Duplicate and unknown pongs are ignored
+        assert_eq!(hb.record_pong(second, sent + TIMEOUT), None);
+        assert_eq!(hb.record_pong(99, sent + TIMEOUT), None);
+        assert_eq!(hb.rtt(), Some(Duration::from_millis(90)));
+    }
+
+    #[test]
+    fn test_pong_echoes_ping() {
+        let mark = |sequence| CommitMark {
+            game_id: "game".into(),
+            sequence,
+            action_id: [sequence as u8; 32],
+            state_hash: None,
+        };
+        let ping = PeerMessage::Ping {
+            nonce: 7,
+            sent_at_ms: 1234,
+            mark: Some(mark(5)),
+        };
+        assert_eq!(
+            pong_for(&ping, Some(mark(3))),
+            Some(PeerMessage::Pong {
+                nonce: 7,
+                sent_at_ms: 1234,
+                mark: Some(mark(3)),
+            })
+        );
+        assert_eq!(pong_for(&pong_for(&ping, None).unwrap(), None), None);
+    }
+}
@@ -0,0 +1,133 @@
+// network/inbound.rs - Per-connection inbound rate limits
+
+use super::throttle::Bandwidth;
+use crate::node::InboundConfig;
+use std::time::Duration;
+use tokio::time::Instant;
+
+/// What to do with a frame just read from a peer
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Verdict {
+    /// Within the limits: handle it
+    Accept,
+    /// Over the limits again soon after a strike: drop it unread
+    Drop,
+    /// Over the limits for the first time: drop it and penalize the peer
+    Warn,
+    /// Over the limits again after a warning: drop it and stop reading from
+    /// the peer until `until`
+    Pause { until: Instant },
+    /// Over the limits again after a pause: close the connection
+    Disconnect,
+}
+
+/// Message and byte budgets for frames from one peer, checked before a
+/// frame is decoded
+///
+/// Each time the peer goes over a budget it gets a strike, and strikes
+/// escalate from a warning to a read pause to a disconnect. Frames over
+/// budget within `pause` of a strike count towards it rather than adding
+/// another, and strikes are forgiven after `forgive_after` within budget.
+pub struct InboundLimiter {
+    messages: Option<Bandwidth>,
+    bytes: Option<Bandwidth>,
+    pause: Duration,
+    forgive_after: Duration,
+    strikes: u32,
+    last_strike: Option<Instant>,
+}
+
+impl InboundLimiter {
+    pub fn new(config: &InboundConfig, now: Instant) -> Self {
+        Self {
+            messages: config
+                .max_messages_per_sec
+                .map(|rate| Bandwidth::new(rate, now)),
+            bytes: config
+                .max_bytes_per_sec
+                .map(|rate| Bandwidth::new(rate, now)),
+            pause: config.pause,
+            forgive_after: config.forgive_after,
+            strikes: 0,
+            last_strike: None,
+        }
+    }
+
+    /// Charge a `len` byte frame to the budgets and decide what to do
+    pub fn check(&mut self, len: usize, now: Instant) -> Verdict {
+        let mut over = false;
+        for (bucket, cost) in [(&mut self.messages, 1), (&mut self.bytes, len)] {
+            if let Some(bucket) = bucket {
+                over |= !bucket.reserve(cost, now).is_zero();
+            }
+        }
+
+        if !over {
+            let forgiven = self
+                .last_strike
+                .is_some_and(|at| now.saturating_duration_since(at) >= self.forgive_after);
+            if forgiven {
+                self.strikes = 0;
+                self.last_strike = None;
+            }
+            return Verdict::Accept;
+        }
+        if self.last_strike.is_some_and(|at| now < at + self.pause) {
+            return Verdict::Drop;
+        }
+        self.strikes += 1;
+        self.last_strike = Some(now);
+        match self.strikes {
+            1 => Verdict::Warn,
+            2 => Verdict::Pause {
+                until: now + self.pause,
+            },
+            _ => Verdict::Disconnect,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config() -> InboundConfig {
+        InboundConfig {
+            max_messages_per_sec: Some(10),
+            max_bytes_per_sec: Some(1000),
+            pause: Duration::from_millis(100),
+            forgive_after: Duration::from_secs(5),
+            ..InboundConfig::default()
+        }
+    }
+
+    #[test]
+    fn test_flood_escalates_from_warning_to_disconnect() {
+        let start = Instant::now();
+        let mut limiter = InboundLimiter::new(&config(), start);
+        for _ in 0..10 {
+            assert_eq!(limiter.check(10, start), Verdict::Accept);
+        }
+        assert_eq!(limiter.check(10, start), Verdict::Warn);
+        assert_eq!(limiter.check(10, start), Verdict::Drop);
+
+        let later = start + Duration::from_millis(100);
+        let until = later + Duration::from_millis(100);
+        assert_eq!(limiter.check(10, later), Verdict::Pause { until });
+        assert_eq!(limiter.check(10, later), Verdict::Drop);
+        assert_eq!(limiter.check(10, until), Verdict::Disconnect);
+    }
+
+    #[test]
+    fn test_large_frames_count_against_bytes_and_strikes_are_forgiven() {
+        let start = Instant::now();
+        let mut limiter = InboundLimiter::new(&config(), start);
+        assert_eq!(limiter.check(1000, start), Verdict::Accept);
+        assert_eq!(limiter.check(1, start), Verdict::Warn);
+
+        // Back within budget long enough to be forgiven
+        let quiet = start + Duration::from_secs(6);
+        assert_eq!(limiter.check(1, quiet), Verdict::Accept);
+        assert_eq!(limiter.check(1000, quiet), Verdict::Warn);
+    }
+}
@@ -0,0 +1,534 @@
+// network/memory.rs - In-process transport for tests and simulations
+
+use super::sim::{Arrivals, Impairments, Shaper};
+use super::transport::{Connection, Listener, StreamConnection, Transport};
+use crate::error::{Result, SwarmhostError};
+use crate::node::NetworkConfig;
+use async_trait::async_trait;
+use bytes::{Buf, Bytes};
+use std::collections::HashMap;
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::sync::{Arc, Mutex, MutexGuard, OnceLock};
+use std::time::Duration;
+use tokio::io::DuplexStream;
+use tokio::sync::{mpsc, watch};
+use tokio::time::Instant;
+
+/// Bytes buffered in each direction of an in-memory connection
+const PIPE_CAPACITY: usize = 64 * 1024;
+
+/// First port handed out when listening on port 0 or dialing
+const FIRST_EPHEMERAL_PORT: u16 = 49152;
+
+/// Arrival time in front of every frame, as nanoseconds since the
+/// connection's epoch
+const ARRIVAL_LEN: usize = 8;
+
+/// A dialed pipe waiting to be accepted
+struct Pending {
+    stream: DuplexStream,
+    peer_addr: SocketAddr,
+    /// Where the dialer listens, naming its end of the link
+    from: SocketAddr,
+    /// The listener's cut count when the pipe was dialed
+    cuts: u64,
+    epoch: Instant,
+    seed: u64,
+}
+
+type Incoming = mpsc::UnboundedSender<Pending>;
+
+/// Whether a listener can be reached, and how many times it was cut off
+#[derive(Debug, Clone, Copy, Default)]
+struct Link {
+    down: bool,
+    cuts: u64,
+}
+
+/// Address registry connecting in-memory listeners and dialers
+///
+/// Transports sharing a network can reach each other; separate networks are
+/// isolated.
+#[derive(Clone, Default)]
+pub struct MemoryNetwork {
+    registry: Arc<Mutex<Registry>>,
+    impairments: Impairments,
+}
+
+#[derive(Default)]
+struct Registry {
+    listeners: HashMap<SocketAddr, Incoming>,
+    links: HashMap<SocketAddr, watch::Sender<Link>>,
+    last_port: u16,
+}
+
+impl Registry {
+    fn allocate_port(&mut self, ip: IpAddr) -> io::Result<u16> {
+        for _ in FIRST_EPHEMERAL_PORT..=u16::MAX {
+            self.last_port = match self.last_port {
+                port if port < FIRST_EPHEMERAL_PORT || port == u16::MAX => FIRST_EPHEMERAL_PORT,
+                port => port + 1,
+            };
+            if !self
+                .listeners
+                .contains_key(&SocketAddr::new(ip, self.last_port))
+            {
+                return Ok(self.last_port);
+            }
+        }
+        Err(io::Error::new(
+            io::ErrorKind::AddrNotAvailable,
+            "no free in-memory ports",
+        ))
+    }
+
+    /// Address of the listener for `addr`, falling back to one bound to the
+    /// unspecified address
+    fn lookup(&self, addr: SocketAddr) -> Option<SocketAddr> {
+        let unspecified = match addr.ip() {
+            IpAddr::V4(_) => IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+            IpAddr::V6(_) => IpAddr::V6(Ipv6Addr::UNSPECIFIED),
+        };
+        [addr, SocketAddr::new(unspecified, addr.port())]
+            .into_iter()
+            .find(|addr| self.listeners.contains_key(addr))
+    }
+}
+
+impl MemoryNetwork {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Network shared by every node in the process using the memory transport
+    pub fn global() -> Self {
+        static GLOBAL: OnceLock<MemoryNetwork> = OnceLock::new();
+        GLOBAL.get_or_init(MemoryNetwork::new).clone()
+    }
+
+    /// A transport on this network using the config's message size limit
+    pub fn transport(&self, config: &NetworkConfig) -> MemoryTransport {
+        MemoryTransport {
+            network: self.clone(),
+            max_message_size: config.max_message_size,
+            home: Arc::new(OnceLock::new()),
+        }
+    }
+
+    /// Sever every connection to the listener at `addr` and refuse new ones
+    /// until [`restore`](Self::restore), as if its link had gone down
+    pub fn cut(&self, addr: SocketAddr) {
+        let registry = self.lock();
+        if let Some(link) = registry
+            .lookup(addr)
+            .and_then(|addr| registry.links.get(&addr))
+        {
+            link.send_modify(|link| {
+                link.down = true;
+                link.cuts += 1;
+            });
+        }
+    }
+
+    /// Let the listener at `addr` be dialed again after a [`cut`](Self::cut)
+    pub fn restore(&self, addr: SocketAddr) {
+        let registry = self.lock();
+        if let Some(link) = registry
+            .lookup(addr)
+            .and_then(|addr| registry.links.get(&addr))
+        {
+            link.send_modify(|link| link.down = false);
+        }
+    }
+
+    /// Address of the listener `addr` reaches, or `addr` if none does
+    pub(super) fn resolve(&self, addr: SocketAddr) -> SocketAddr {
+        self.lock().lookup(addr).unwrap_or(addr)
+    }
+
+    pub(super) fn impairments(&self) -> &Impairments {
+        &self.impairments
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Registry> {
+        self.registry.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// [`Transport`] whose connections are in-process pipes
+#[derive(Clone)]
+pub struct MemoryTransport {
+    network: MemoryNetwork,
+    max_message_size: usize,
+    /// Where this transport first listened, naming its end of every link
+    home: Arc<OnceLock<SocketAddr>>,
+}
+
+#[async_trait]
+impl Transport for MemoryTransport {
+    async fn listen(&self, addr: SocketAddr) -> Result<Box<dyn Listener>> {
+        let mut registry = self.network.lock();
+        let addr = match addr.port() {
+            0 => SocketAddr::new(addr.ip(), registry.allocate_port(addr.ip())?),
+            _ if registry.listeners.contains_key(&addr) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::AddrInUse,
+                    format!("{} is already in use", addr),
+                )
+                .into());
+            }
+            _ => addr,
+        };
+
+        let (tx, rx) = mpsc::unbounded_channel();
+        registry.listeners.insert(addr, tx);
+        let _ = self.home.set(addr);
+        let (link, _) = watch::channel(Link::default());
+        let watching = link.subscribe();
+        registry.links.insert(addr, link);
+
+        Ok(Box::new(MemoryListener {
+            addr,
+            incoming: tokio::sync::Mutex::new(rx),
+            link: watching,
+            network: self.network.clone(),
+            max_message_size: self.max_message_size,
+        }))
+    }
+
+    async fn dial(&self, addr: SocketAddr) -> Result<Box<dyn Connection>> {
+        // Connecting takes a round trip, and never finishes over a link that
+        // loses everything, like a TCP dial into a black hole
+        if let Some(from) = self.home.get().copied() {
+            let listener = self.network.resolve(addr);
+            if let Some(conditions) = self.network.impairments.get(from, listener) {
+                if conditions.loss >= 1.0 {
+                    std::future::pending::<()>().await;
+                }
+                tokio::time::sleep(conditions.latency * 2).await;
+            }
+        }
+
+        let mut registry = self.network.lock();
+        let local_ip = match addr.ip() {
+            IpAddr::V4(_) => IpAddr::V4(Ipv4Addr::LOCALHOST),
+            IpAddr::V6(_) => IpAddr::V6(Ipv6Addr::LOCALHOST),
+        };
+        let local_addr = SocketAddr::new(local_ip, registry.allocate_port(local_ip)?);
+
+        let refused = || io::Error::new(io::ErrorKind::ConnectionRefused, addr.to_string());
+        let listener = registry.lookup(addr).ok_or_else(refused)?;
+        let link = registry.links[&listener].subscribe();
+        let state = *link.borrow();
+        if state.down {
+            return Err(refused().into());
+        }
+
+        let (ours, theirs) = tokio::io::duplex(PIPE_CAPACITY);
+        let from = self.home.get().copied().unwrap_or(local_addr);
+        let epoch = Instant::now();
+        let impairments = &self.network.impairments;
+        registry.listeners[&listener]
+            .send(Pending {
+                stream: theirs,
+                peer_addr: local_addr,
+                from,
+                cuts: state.cuts,
+                epoch,
+                seed: impairments.next_seed(),
+            })
+            .map_err(|_| refused())?;
+
+        let shaper = Shaper::new(
+            impairments.clone(),
+            from,
+            listener,
+            impairments.next_seed(),
+            epoch,
+        );
+        Ok(Box::new(MemoryConnection::new(
+            ours,
+            addr,
+            self.max_message_size,
+            link,
+            state.cuts,
+            shaper,
+        )))
+    }
+}
+
+/// An in-memory pipe that fails once its listener is [cut](MemoryNetwork::cut)
+/// and delays frames as its link's [conditions](super::sim::NetworkConditions) say
+struct MemoryConnection {
+    inner: StreamConnection<DuplexStream>,
+    max_message_size: usize,
+    link: watch::Receiver<Link>,
+    /// The listener was dropped, which leaves the pipe up
+    orphaned: bool,
+    /// Why the pipe stopped, held until the frames still in flight are out
+    ended: Option<SwarmhostError>,
+    /// The listener's cut count when the pipe was dialed
+    cuts: u64,
+    shaper: Shaper,
+    arrivals: Arrivals,
+}
+
+impl MemoryConnection {
+    fn new(
+        stream: DuplexStream,
+        peer_addr: SocketAddr,
+        max_message_size: usize,
+        link: watch::Receiver<Link>,
+        cuts: u64,
+        shaper: Shaper,
+    ) -> Self {
+        let arrivals = Arrivals::new(shaper.epoch(), PIPE_CAPACITY);
+        Self {
+            inner: StreamConnection::new(stream, max_message_size + ARRIVAL_LEN, peer_addr),
+            max_message_size,
+            link,
+            orphaned: false,
+            ended: None,
+            cuts,
+            shaper,
+            arrivals,
+        }
+    }
+
+    /// Send `payload` once per copy the link delivers, each stamped with
+    /// when it arrives
+    async fn transmit(&mut self, payload: Bytes, reliable: bool) -> Result<()> {
+        self.check()?;
+        if payload.len() > self.max_message_size {
+            return Err(SwarmhostError::TooLarge(format!(
+                "Outgoing message of {} bytes exceeds max_message_size {}",
+                payload.len(),
+                self.max_message_size
+            )));
+        }
+
+        for arrival in self.shaper.transmit(payload.len(), reliable).await {
+            let mut frame = Vec::with_capacity(ARRIVAL_LEN + payload.len());
+            frame.extend_from_slice(&(arrival.as_nanos() as u64).to_be_bytes());
+            frame.extend_from_slice(&payload);
+            self.inner.send(Bytes::from(frame)).await?;
+        }
+        Ok(())
+    }
+
+    fn check(&self) -> io::Result<()> {
+        if self.link.borrow().cuts == self.cuts {
+            Ok(())
+        } else {
+            Err(io::Error::new(io::ErrorKind::ConnectionReset, "link cut"))
+        }
+    }
+}
+
+#[async_trait]
+impl Connection for MemoryConnection {
+    async fn send(&mut self, payload: Bytes) -> Result<()> {
+        self.transmit(payload, true).await
+    }
+
+    async fn send_unreliable(&mut self, payload: Bytes) -> Result<()> {
+        self.transmit(payload, false).await
+    }
+
+    async fn recv(&mut self) -> Result<Bytes> {
+        loop {
+            self.check()?;
+            let due = self.arrivals.next();
+            if let Some(e) = self.ended.take_if(|_| due.is_none()) {
+                return Err(e);
+            }
+            let reading = self.ended.is_none() && self.arrivals.has_room();
+            // Frames already sent are taken in first, so one arriving earlier
+            // overtakes those sent before it even when the reader is late
+            tokio::select! {
+                biased;
+                received = self.inner.recv(), if reading => match received {
+                    Ok(mut frame) if frame.len() >= ARRIVAL_LEN => {
+                        let payload = frame.split_off(ARRIVAL_LEN);
+                        let arrival = Duration::from_nanos(frame.get_u64());
+                        self.arrivals.push(arrival, payload);
+                    }
+                    Ok(_) => {
+                        return Err(SwarmhostError::Peer("Truncated in-memory frame".to_string()));
+                    }
+                    Err(e) => self.ended = Some(e),
+                },
+                _ = tokio::time::sleep_until(due.unwrap_or_else(Instant::now)), if due.is_some() => {
+                    if let Some(frame) = self.arrivals.pop(Instant::now()) {
+                        return Ok(frame);
+                    }
+                }
+                changed = self.link.changed(), if !self.orphaned => {
+                    // A dropped listener leaves its connections up
+                    self.orphaned = changed.is_err();
+                }
+            }
+        }
+    }
+
+    async fn close(&mut self) -> Result<()> {
+        self.inner.close().await
+    }
+
+    fn peer_addr(&self) -> SocketAddr {
+        self.inner.peer_addr()
+    }
+
+    fn max_message_size(&self) -> usize {
+        self.max_message_size
+    }
+}
+
+struct MemoryListener {
+    addr: SocketAddr,
+    incoming: tokio::sync::Mutex<mpsc::UnboundedReceiver<Pending>>,
+    link: watch::Receiver<Link>,
+    network: MemoryNetwork,
+    max_message_size: usize,
+}
+
+#[async_trait]
+impl Listener for MemoryListener {
+    async fn accept(&self) -> Result<Box<dyn Connection>> {
+        let pending = self
+            .incoming
+            .lock()
+            .await
+            .recv()
+            .await
+            .ok_or_else(|| io::Error::from(io::ErrorKind::NotConnected))?;
+        let shaper = Shaper::new(
+            self.network.impairments.clone(),
+            self.addr,
+            pending.from,
+            pending.seed,
+            pending.epoch,
+        );
+        Ok(Box::new(MemoryConnection::new(
+            pending.stream,
+            pending.peer_addr,
+            self.max_message_size,
+            self.link.clone(),
+            pending.cuts,
+            shaper,
+        )))
+    }
+
+    fn local_addr(&self) -> SocketAddr {
+        self.addr
+    }
+}
+
+impl Drop for MemoryListener {
+    fn drop(&mut self) {
+        let mut registry = self.network.lock();
+        registry.listeners.remove(&self.addr);
+        registry.links.remove(&self.addr);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::SwarmhostError;
+    use bytes::Bytes;
+
+    fn transport(network: &MemoryNetwork) -> MemoryTransport {
+        network.transport(&NetworkConfig {
+            max_message_size: 1024,
+            ..Default::default()
+        })
+    }
+
+    #[tokio::test]
+    async fn test_dial_and_exchange() {
+        let network = MemoryNetwork::new();
+        let transport = transport(&network);
+        let listener = transport
+            .listen("0.0.0.0:0".parse().unwrap())
+            .await
+            .unwrap();
+        let port = listener.local_addr().port();
+        assert!(port >= FIRST_EPHEMERAL_PORT);
+
+        let mut dialed = transport
+            .dial(SocketAddr::new("127.0.0.1".parse().unwrap(), port))
+            .await
+            .unwrap();
+        let mut accepted = listener.accept().await.unwrap();
+        assert_eq!(accepted.peer_addr().ip(), Ipv4Addr::LOCALHOST);
+
+        dialed.send(Bytes::from_static(b"hello")).await.unwrap();
+        assert_eq!(&accepted.recv().await.unwrap()[..], b"hello");
+        accepted.send(Bytes::from(vec![1; 1024])).await.unwrap();
+        assert_eq!(dialed.recv().await.unwrap().len(), 1024);
+        assert!(matches!(
+            dialed.send(Bytes::from(vec![1; 1025])).await,
+            Err(SwarmhostError::TooLarge(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_refused_and_isolated() {
+        let network = MemoryNetwork::new();
+        let listener = transport(&network)
+            .listen("127.0.0.1:7000".parse().unwrap())
+            .await
+            .unwrap();
+
+        assert!(
+            transport(&network)
+                .listen("127.0.0.1:7000".parse().unwrap())
+                .await
+                .is_err()
+        );
+        assert!(
+            transport(&MemoryNetwork::new())
+                .dial(listener.local_addr())
+                .await
+                .is_err()
+        );
+
+        drop(listener);
+        assert!(
+            transport(&network)
+                .dial("127.0.0.1:7000".parse().unwrap())
+                .await
+                .is_err()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_cut_severs_and_refuses_until_restored() {
+        let network = MemoryNetwork::new();
+        let transport = transport(&network);
+        let listener = transport
+            .listen("127.0.0.1:0".parse().unwrap())
+            .await
+            .unwrap();
+        let addr = listener.local_addr();
+        let mut dialed = transport.dial(addr).await.unwrap();
+        let mut accepted = listener.accept().await.unwrap();
+
+        let waiting = tokio::spawn(async move { accepted.recv().await });
+        tokio::task::yield_now().await;
+        network.cut(addr);
+        assert!(waiting.await.unwrap().is_err());
+        assert!(dialed.send(Bytes::from_static(b"lost")).await.is_err());
+        assert!(transport.dial(addr).await.is_err());
+
+        // Restoring lets new connections through but does not revive old ones
+        network.restore(addr);
+        assert!(dialed.recv().await.is_err());
+        let mut redialed = transport.dial(addr).await.unwrap();
+        let mut accepted = listener.accept().await.unwrap();
+        redialed.send(Bytes::from_static(b"back")).await.unwrap();
+        assert_eq!(&accepted.recv().await.unwrap()[..], b"back");
+    }
+}
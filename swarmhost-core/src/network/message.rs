@@ -0,0 +1,250 @@
+// network/message.rs - Messages exchanged between connected peers
+
+use super::bootstrap::PeerRecord;
+use super::fragment::Fragment;
+use super::gossip::{GossipMessage, GossipPayload};
+use super::handshake::CloseCode;
+use super::mux::Stream;
+use super::outbound::Priority;
+use super::pex::PexSample;
+use super::punch::PunchSignal;
+use super::relay::RelayOffer;
+use super::remote::RemoteError;
+use super::resume::ResumptionToken;
+use super::trace::TraceContext;
+use crate::consensus::{ActionId, CertifiedCommits, Commit, SignedAction, Vote};
+use crate::crypto::{Hash, PlayerId};
+use crate::state::{SnapshotBase, SnapshotChunk, SyncPlan};
+use serde::{Deserialize, Serialize};
+use std::net::SocketAddr;
+
+/// A message sent over an established [`SecureChannel`](super::SecureChannel)
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum PeerMessage {
+    /// Liveness probe; `sent_at_ms` is the sender's clock, echoed back.
+    /// `mark` is the sender's latest commit in the game it plays
+    Ping {
+        nonce: u64,
+        sent_at_ms: u64,
+        mark: Option<CommitMark>,
+    },
+    /// Answer to a ping with the same nonce; `mark` is the sender's commit
+    /// at the sequence the ping's was at, or its latest if it is behind
+    Pong {
+        nonce: u64,
+        sent_at_ms: u64,
+        mark: Option<CommitMark>,
+    },
+    /// A proposal, vote or commit being spread through the network
+    Gossip(GossipMessage),
+    /// Ask the receiver to pass hole punching signaling on to `to`
+    Signal { to: PlayerId, signal: PunchSignal },
+    /// Hole punching signaling from `from`, passed on by the sender
+    Signaled { from: PlayerId, signal: PunchSignal },
+    /// The sender will relay sessions for us
+    RelayOffer(RelayOffer),
+    /// Ask the receiver to relay a new session to `to`
+    RelayOpen { session: u64, to: PlayerId },
+    /// `from` opened a session to us through the sender, which relays it
+    RelayIncoming { session: u64, from: PlayerId },
+    /// One frame of a relayed session, to or from the relay
+    RelayData { session: u64, payload: Vec<u8> },
+    /// A relayed session was refused or has ended
+    RelayClose { session: u64 },
+    /// One piece of a bulk message too large to send whole
+    Fragment(Fragment),
+    /// Ask the sender again for fragments that never arrived
+    FragmentRequest { transfer: u64, missing: Vec<u32> },
+    /// The game the sender is playing and the last sequence it delivered
+    /// in it, sent on connect and when it joins one
+    Playing {
+        game_id: Option<String>,
+        committed: u64,
+    },
+    /// Application payload for the receiver alone
+    Direct { class: Priority, payload: Vec<u8> },
+    /// Application payload for every peer playing `game_id`
+    Broadcast {
+        game_id: String,
+        class: Priority,
+        payload: Vec<u8>,
+    },
+    /// Application request, answered by a response with the same id
+    Request { id: u64, payload: Vec<u8> },
+    /// Answer to the sender's request `id`
+    Response { id: u64, payload: Vec<u8> },
+    /// The sender takes part in the DHT and can be dialed at `addr`
+    DhtHello { addr: SocketAddr },
+    /// DHT query for the contacts the receiver knows closest to `target`
+    DhtFindNode { id: u64, target: [u8; 32] },
+    /// DHT query for the providers of `key` the receiver stores, and the
+    /// contacts it knows closest to it
+    DhtFindProviders { id: u64, key: [u8; 32] },
+    /// Answer to the sender's DHT query `id`
+    DhtFound {
+        id: u64,
+        closer: Vec<PeerRecord>,
+        providers: Vec<PeerRecord>,
+    },
+    /// The sender can be dialed at `addr` by anyone looking for `key`
+    DhtProvide { key: [u8; 32], addr: SocketAddr },
+    /// Several encoded messages sharing one frame, packed as
+    /// [`batch::pack`](super::batch::pack) describes
+    Batch(Vec<u8>),
+    /// Peers the sender is connected to, and where to dial the sender
+    Pex(PexSample),
+    /// The sender is closing the connection for `code`; everything it had
+    /// queued for us was sent before this
+    GoAway { code: CloseCode },
+    /// Answer to a GoAway: the receiver has it and is closing too
+    GoAwayAck,
+    /// Present this in the next handshake to pick up where we left off
+    Resume(ResumptionToken),
+    /// The sender consumed `bytes` more of what we sent on `stream`
+    Credit { stream: Stream, bytes: u32 },
+    /// An application message with the correlation ids it is traced under
+    Traced {
+        trace: TraceContext,
+        message: Box<PeerMessage>,
+    },
+    /// Ask for up to `count` commits of `game_id` from sequence `from` on,
+    /// to fill a gap
+    FetchCommits {
+        game_id: String,
+        from: u64,
+        count: u32,
+    },
+    /// Answer to FetchCommits: the commits the sender still has, in order
+    Commits(Vec<Commit>),
+    /// An action for the receiver to put forward in its turn as proposer
+    Forward(SignedAction),
+    /// Ask for the plan of the newest snapshot of `game_id` the receiver
+    /// would hand a peer catching up on it, as a delta from `base` if the
+    /// receiver holds the same
+    FetchSyncPlan {
+        id: u64,
+        game_id: String,
+        base: Option<SnapshotBase>,
+    },
+    /// Answer to FetchSyncPlan `id`
+    SyncPlan { id: u64, plan: SyncPlan },
+    /// Ask for chunk `chunk` of the snapshot of `game_id` at `sequence`,
+    /// or of the delta to it from the one at `delta_from`, as its plan lays
+    /// it out
+    FetchSnapshot {
+        id: u64,
+        game_id: String,
+        sequence: u64,
+        delta_from: Option<u64>,
+        chunk: u32,
+    },
+    /// Answer to FetchSnapshot `id`; no chunk if the sender no longer
+    /// holds the snapshot
+    Snapshot {
+        id: u64,
+        chunk: Option<SnapshotChunk>,
+    },
+    /// Ask for up to `count` commits of `game_id` from sequence `from` on,
+    /// with the votes that certify them, to catch up on it
+    FetchCertified {
+        id: u64,
+        game_id: String,
+        from: u64,
+        count: u32,
+    },
+    /// Answer to FetchCertified `id`
+    Certified { id: u64, commits: CertifiedCommits },
+    /// Ask for the votes on round `round`'s block and its actions the
+    /// receiver holds, from validators whose bit in `have` is clear; bit
+    /// `i` is the `i`th validator in the rotation, and those past the 64th
+    /// are always asked for
+    GetVotes { round: u64, have: u64 },
+    /// Answer to GetVotes: signed votes the sender holds
+    Votes(Vec<Vote>),
+    /// Ask for the receiver's state hash of `game_id` at `sequence`, to
+    /// find where its state and ours first differ
+    FetchStateHash {
+        id: u64,
+        game_id: String,
+        sequence: u64,
+    },
+    /// Answer to FetchStateHash `id`; none if the sender does not hold
+    /// the log that far back, or that far on
+    StateHash { id: u64, state_hash: Option<Hash> },
+    /// The sender has too many actions pending to take the one we forwarded;
+    /// hold off sending it more for `retry_after_ms`
+    Busy { retry_after_ms: u32 },
+    /// Why the sender could not answer fetch `id`, or, with none, refused
+    /// the action we forwarded
+    Error { id: Option<u64>, error: RemoteError },
+}
+
+/// The action a node committed at one sequence of a game, carried on
+/// heartbeats for peers to check their log agrees
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CommitMark {
+    pub game_id: String,
+    pub sequence: u64,
+    pub action_id: ActionId,
+    /// The node's state hash at `sequence`, for peers to check their state
+    /// agrees too; none if it no longer holds it
+    pub state_hash: Option<Hash>,
+}
+
+impl PeerMessage {
+    /// Outbound queue class; relayed frames, fragments, request/response
+    /// payloads, snapshot syncs and fetched commits can be large and bursty, so they yield
+    /// to everything else.
+    /// Application messages use the class they were sent with, and actions
+    /// gossiped without consensus, like observers' votes, go as game
+    /// actions, behind it. Only bulk
+    /// messages may be sent in fragments
+    pub fn priority(&self) -> Priority {
+        match self {
+            PeerMessage::Traced { message, .. } => message.priority(),
+            PeerMessage::Direct { class, .. } | PeerMessage::Broadcast { class, .. } => *class,
+            PeerMessage::Gossip(GossipMessage {
+                payload: GossipPayload::Unsequenced(_) | GossipPayload::ObserverVote(_),
+                ..
+            }) => Priority::GameAction,
+            PeerMessage::RelayData { .. }
+            | PeerMessage::Fragment(_)
+            | PeerMessage::Request { .. }
+            | PeerMessage::Response { .. }
+            | PeerMessage::Commits(_)
+            | PeerMessage::FetchSyncPlan { .. }
+            | PeerMessage::SyncPlan { .. }
+            | PeerMessage::FetchSnapshot { .. }
+            | PeerMessage::Snapshot { .. }
+            | PeerMessage::Certified { .. } => Priority::Bulk,
+            _ => Priority::Control,
+        }
+    }
+
+    /// Send under `trace`, if there is one
+    pub fn traced(self, trace: Option<TraceContext>) -> PeerMessage {
+        match trace {
+            Some(trace) => PeerMessage::Traced {
+                trace,
+                message: Box::new(self),
+            },
+            None => self,
+        }
+    }
+
+    /// The trace the message is sent under
+    pub fn trace(&self) -> Option<&TraceContext> {
+        match self {
+            PeerMessage::Traced { trace, .. } => Some(trace),
+            _ => None,
+        }
+    }
+
+    /// Take the message out of its trace header
+    pub fn untraced(self) -> (Option<TraceContext>, PeerMessage) {
+        match self {
+            PeerMessage::Traced { trace, message } => (Some(trace), *message),
+            message => (None, message),
+        }
+    }
+}
@@ -1,10 +1,142 @@
-// network/mod.rs - Networking layer (placeholder)
+// network/mod.rs - Networking layer
+//
+// The per-connection protocol state here (heartbeats, scores, throttles,
+// dedup, batching, fragmentation, retransmission, resumption, the DHT's
+// routing table) does no I/O and never reads the clock: the caller passes
+// `now` in and sends what comes out.
+
+pub mod batch;
+pub mod bootstrap;
+pub mod codec;
+pub mod compression;
+pub mod dedup;
+pub mod dht;
+pub mod discovery;
+pub mod fragment;
+pub mod frame;
+pub mod gossip;
+pub mod handshake;
+pub mod happy_eyeballs;
+pub mod heartbeat;
+pub mod inbound;
+pub mod memory;
+pub mod message;
+pub mod mux;
+pub mod nat;
+pub mod outbound;
+pub mod pex;
+pub mod portmap;
+#[cfg(feature = "protobuf")]
+pub mod protobuf;
+pub mod proxy;
+pub mod punch;
+pub mod quic;
+pub mod relay;
+pub mod reliable;
+pub mod remote;
+pub mod resume;
+pub mod score;
+pub mod secure;
+pub mod security;
+pub mod sim;
+pub mod stun;
+pub mod tcp;
+pub mod throttle;
+pub mod trace;
+pub mod transport;
+#[cfg(feature = "websocket")]
+pub mod websocket;
+
+pub use bootstrap::{BootstrapClient, BootstrapList, PeerRecord};
+pub use codec::MessageCodec;
+pub use dedup::DedupCache;
+pub use discovery::{GameAnnouncement, LocalDiscovery, LocalPeer};
+pub use fragment::{Fragment, Fragmenter};
+pub use frame::FramedStream;
+pub use gossip::{Gossip, GossipMessage, GossipPayload};
+pub use handshake::{CloseCode, Role, check_admission};
+pub use happy_eyeballs::AddressFamily;
+pub use heartbeat::Heartbeat;
+pub use inbound::{InboundLimiter, Verdict};
+pub use memory::{MemoryNetwork, MemoryTransport};
+pub use message::{CommitMark, PeerMessage};
+pub use mux::Mux;
+pub use outbound::{OutboundSender, Priority, QueueDepths};
+pub use pex::{PeerStore, PexEntry, PexSample};
+pub use portmap::{MappingMethod, PortMapper, PortMapping, PortProtocol};
+pub use proxy::Socks5Proxy;
+pub use quic::{QuicConnection, QuicListener, QuicTransport};
+pub use relay::{RelayOffer, RelayUsage, RelayedConnection};
+pub use reliable::Reliable;
+pub use remote::RemoteError;
+pub use resume::{Resumption, ResumptionToken};
+pub use score::{Offense, PeerScore};
+pub use security::SecureChannel;
+pub use sim::{Jitter, LinkPreset, NetworkConditions, SimNetwork};
+pub use tcp::{TcpConnection, TcpTransport};
+pub use throttle::{Bandwidth, Throttle};
+pub use trace::{TraceContext, Tracer};
+pub use transport::{Connection, Listener, StreamConnection, Transport};
+#[cfg(feature = "websocket")]
+pub use websocket::{WebSocketConnection, WebSocketTransport};
+
+use crate::error::{Result, SwarmhostError};
+use crate::node::{NetworkConfig, NodeConfig, TransportKind};
+use std::net::{IpAddr, Ipv6Addr, SocketAddr};
+use std::sync::Arc;
 
 #[derive(Default)]
 pub struct NetworkManager;
 
 impl NetworkManager {
     pub fn new() -> Self {
-        Self  // Remove ::default()
+        Self // Remove ::default()
+    }
+}
+
+/// The transport selected by `config.network.transport`
+pub fn transport_for(config: &NodeConfig) -> Result<Arc<dyn Transport>> {
+    let network = &config.network;
+    Ok(match network.transport {
+        TransportKind::Tcp => Arc::new(TcpTransport::new(network)),
+        TransportKind::Quic => {
+            let keypair = config.keypair.as_ref().ok_or_else(|| {
+                SwarmhostError::Config("QUIC transport needs a keypair".to_string())
+            })?;
+            Arc::new(QuicTransport::new(network, keypair)?)
+        }
+        #[cfg(feature = "websocket")]
+        TransportKind::WebSocket => Arc::new(WebSocketTransport::new(network)),
+        #[cfg(not(feature = "websocket"))]
+        TransportKind::WebSocket => {
+            return Err(SwarmhostError::Config(
+                "WebSocket transport needs the 'websocket' feature".to_string(),
+            ));
+        }
+        TransportKind::Memory => Arc::new(MemoryNetwork::global().transport(network)),
+    })
+}
+
+/// Open the listeners described by the network config
+///
+/// The first listener is always `bind_addr:port`. With `dual_stack` set and an
+/// IPv4 `bind_addr`, a second listener is opened on `[::]` using the same port
+/// the first one received.
+pub async fn listen_all(
+    transport: &dyn Transport,
+    config: &NetworkConfig,
+    port: u16,
+) -> Result<Vec<Box<dyn Listener>>> {
+    let first = transport
+        .listen(SocketAddr::new(config.bind_addr, port))
+        .await?;
+    let bound_port = first.local_addr().port();
+
+    let mut listeners = vec![first];
+    if config.dual_stack && config.bind_addr.is_ipv4() {
+        let v6 = SocketAddr::new(IpAddr::V6(Ipv6Addr::UNSPECIFIED), bound_port);
+        listeners.push(transport.listen(v6).await?);
     }
-}
\ No newline at end of file
+
+    Ok(listeners)
+}
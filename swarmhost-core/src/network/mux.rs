@@ -0,0 +1,256 @@
+// network/mux.rs - Per-class logical streams with flow control credits
+
+use super::message::PeerMessage;
+use super::outbound::Priority;
+use crate::node::MuxConfig;
+use serde::{Deserialize, Serialize};
+
+/// A logical stream within one connection, one per traffic class
+///
+/// Votes and proposals get a stream of their own, apart from the other
+/// control traffic they share an outbound queue with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Stream {
+    Control = 0,
+    Consensus = 1,
+    GameAction = 2,
+    Bulk = 3,
+}
+
+const STREAMS: usize = 4;
+
+/// Credit each side starts with on every stream, before the peer grants the
+/// rest of its window; no window may be smaller
+pub const MIN_WINDOW: u32 = 64 * 1024;
+
+impl Stream {
+    /// Every stream, in id order
+    pub const ALL: [Stream; STREAMS] = [
+        Stream::Control,
+        Stream::Consensus,
+        Stream::GameAction,
+        Stream::Bulk,
+    ];
+
+    /// The stream `message` travels on
+    pub fn of(message: &PeerMessage) -> Stream {
+        match message {
+            PeerMessage::Gossip(_) => Stream::Consensus,
+            PeerMessage::Traced { message, .. } => Stream::of(message),
+            message => match message.priority() {
+                Priority::Control => Stream::Control,
+                Priority::GameAction => Stream::GameAction,
+                Priority::Bulk => Stream::Bulk,
+            },
+        }
+    }
+
+    pub fn id(self) -> u8 {
+        self as u8
+    }
+
+    pub fn from_id(id: u8) -> Option<Stream> {
+        Stream::ALL.get(id as usize).copied()
+    }
+
+    /// The outbound queue class the stream's messages wait in
+    pub fn priority(self) -> Priority {
+        match self {
+            Stream::Control | Stream::Consensus => Priority::Control,
+            Stream::GameAction => Priority::GameAction,
+            Stream::Bulk => Priority::Bulk,
+        }
+    }
+}
+
+/// Flow control for the streams of one connection, yamux-style
+///
+/// Each side may have at most a window of unconsumed bytes in flight on a
+/// stream. Sending spends credit; the receiver grants it back with
+/// [`PeerMessage::Credit`] as it consumes messages, once half a window has
+/// built up. A stream out of credit holds its messages back while the
+/// others carry on, so a snapshot in flight never has more than a window
+/// of bytes ahead of a vote.
+///
+/// Messages are never split across credit, so a stream with any credit left
+/// may overdraw it by one message. Each side starts with [`MIN_WINDOW`] of
+/// credit and the peer grants the rest of its own window straight away, so
+/// the two ends need not be configured alike.
+///
+/// Over QUIC the streams share the connection's one QUIC stream, since the
+/// channel seals frames in order.
+#[derive(Debug)]
+pub struct Mux {
+    windows: [u64; STREAMS],
+    /// Bytes we may still send on each stream
+    credit: [i64; STREAMS],
+    /// Bytes consumed on each stream that the peer has not been credited for
+    consumed: [u64; STREAMS],
+    bulk_chunk: usize,
+}
+
+impl Mux {
+    pub fn new(config: &MuxConfig) -> Self {
+        let windows = [
+            config.control_window,
+            config.consensus_window,
+            config.game_action_window,
+            config.bulk_window,
+        ]
+        .map(|window| window.max(1) as u64);
+        Self {
+            windows,
+            credit: [MIN_WINDOW as i64; STREAMS],
+            consumed: [0; STREAMS],
+            bulk_chunk: config.bulk_chunk,
+        }
+    }
+
+    /// Grants for the part of each window beyond [`MIN_WINDOW`], to send
+    /// as the connection opens
+    pub fn opening_credits(&self) -> Vec<PeerMessage> {
+        Stream::ALL
+            .iter()
+            .filter_map(|&stream| {
+                let extra = self.windows[stream as usize].saturating_sub(MIN_WINDOW as u64);
+                (extra > 0).then(|| PeerMessage::Credit {
+                    stream,
+                    bytes: extra.min(u32::MAX as u64) as u32,
+                })
+            })
+            .collect()
+    }
+
+    /// Whether `stream` has credit to send
+    pub fn is_open(&self, stream: Stream) -> bool {
+        self.credit[stream as usize] > 0
+    }
+
+    /// Whether every stream waiting in the `priority` queue has credit
+    pub fn lane_open(&self, priority: Priority) -> bool {
+        Stream::ALL
+            .iter()
+            .filter(|stream| stream.priority() == priority)
+            .all(|&stream| self.is_open(stream))
+    }
+
+    /// Largest message sent whole on `stream`; bigger bulk messages go in
+    /// fragments, so nothing waits long behind one
+    pub fn max_message(&self, stream: Stream, max_payload: usize) -> usize {
+        match stream {
+            Stream::Bulk => max_payload.min(self.bulk_chunk),
+            _ => max_payload,
+        }
+    }
+
+    /// Spend credit for `len` bytes sent on `stream`
+    pub fn sent(&mut self, stream: Stream, len: usize) {
+        self.credit[stream as usize] -= len as i64;
+    }
+
+    /// The peer consumed `bytes` more of what we sent on `stream`
+    pub fn credited(&mut self, stream: Stream, bytes: u32) {
+        let credit = &mut self.credit[stream as usize];
+        *credit = credit.saturating_add(bytes as i64);
+    }
+
+    /// Record that we consumed `len` bytes the peer sent on `stream`,
+    /// returning the credit to grant it once half a window has built up
+    pub fn consumed(&mut self, stream: Stream, len: usize) -> Option<PeerMessage> {
+        let consumed = &mut self.consumed[stream as usize];
+        *consumed += len as u64;
+        if *consumed < self.windows[stream as usize] / 2 {
+            return None;
+        }
+        let bytes = (*consumed).min(u32::MAX as u64) as u32;
+        *consumed -= bytes as u64;
+        Some(PeerMessage::Credit { stream, bytes })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::consensus::{Decision, Vote};
+    use crate::crypto::KeyPair;
+    use crate::network::GossipMessage;
+    use crate::network::gossip::GossipPayload;
+
+    fn config() -> MuxConfig {
+        MuxConfig {
+            control_window: MIN_WINDOW,
+            consensus_window: MIN_WINDOW,
+            game_action_window: MIN_WINDOW,
+            bulk_window: 2 * MIN_WINDOW,
+            ..MuxConfig::default()
+        }
+    }
+
+    #[test]
+    fn test_stream_of_message() {
+        let vote = PeerMessage::Gossip(GossipMessage {
+            hops_left: 1,
+            payload: GossipPayload::Vote(Vote::new(
+                &KeyPair::generate(),
+                [0; 32],
+                0,
+                Decision::Approve,
+            )),
+        });
+        assert_eq!(Stream::of(&vote), Stream::Consensus);
+        let ping = PeerMessage::Ping {
+            nonce: 0,
+            sent_at_ms: 0,
+            mark: None,
+        };
+        assert_eq!(Stream::of(&ping), Stream::Control);
+        let bulk = PeerMessage::Direct {
+            class: Priority::Bulk,
+            payload: Vec::new(),
+        };
+        assert_eq!(Stream::of(&bulk), Stream::Bulk);
+        for stream in Stream::ALL {
+            assert_eq!(Stream::from_id(stream.id()), Some(stream));
+        }
+        assert_eq!(Stream::from_id(4), None);
+    }
+
+    #[test]
+    fn test_stream_without_credit_holds_only_its_lane() {
+        let window = MIN_WINDOW as usize;
+        let mut mux = Mux::new(&config());
+        mux.sent(Stream::Bulk, window - 1);
+        assert!(mux.lane_open(Priority::Bulk));
+        // The message that overdraws it still goes
+        mux.sent(Stream::Bulk, window);
+        assert!(!mux.lane_open(Priority::Bulk));
+        assert!(mux.lane_open(Priority::Control));
+        assert!(mux.lane_open(Priority::GameAction));
+
+        mux.credited(Stream::Bulk, MIN_WINDOW);
+        assert!(mux.is_open(Stream::Bulk));
+    }
+
+    #[test]
+    fn test_peer_grants_rest_of_window_then_half_windows() {
+        let mut mux = Mux::new(&config());
+        assert_eq!(
+            mux.opening_credits(),
+            vec![PeerMessage::Credit {
+                stream: Stream::Bulk,
+                bytes: MIN_WINDOW
+            }]
+        );
+
+        let half = MIN_WINDOW as usize;
+        assert_eq!(mux.consumed(Stream::Bulk, half - 1), None);
+        assert_eq!(
+            mux.consumed(Stream::Bulk, 1),
+            Some(PeerMessage::Credit {
+                stream: Stream::Bulk,
+                bytes: MIN_WINDOW
+            })
+        );
+        assert_eq!(mux.consumed(Stream::Bulk, 100), None);
+    }
+}
@@ -0,0 +1,106 @@
+// network/nat.rs - Public address detection for nodes behind NAT
+
+use super::stun;
+use crate::node::NatConfig;
+use std::net::{IpAddr, SocketAddr};
+use tokio::net::UdpSocket;
+
+/// Work out the address peers should use to reach us
+///
+/// A manually configured `advertised_addr` always wins. Otherwise each STUN
+/// server is queried in turn from a UDP socket on the listen port, and the
+/// first mapped address returned is used. `None` means no source answered;
+/// the caller may still learn an observed address from the bootstrap server.
+pub async fn detect_public_addr(
+    nat: &NatConfig,
+    bind_addr: IpAddr,
+    port: u16,
+) -> Option<SocketAddr> {
+    if let Some(addr) = nat.advertised_addr {
+        return Some(addr);
+    }
+
+    if nat.stun_servers.is_empty() {
+        return None;
+    }
+
+    let socket = match UdpSocket::bind((bind_addr, port)).await {
+        Ok(socket) => socket,
+        Err(_) => UdpSocket::bind((bind_addr, 0)).await.ok()?,
+    };
+
+    for server in &nat.stun_servers {
+        match stun::query(&socket, server, nat.stun_timeout).await {
+            Ok(addr) => {
+                tracing::info!("STUN server {} reports public address {}", server, addr);
+                return Some(addr);
+            }
+            Err(e) => tracing::warn!("STUN query to {} failed: {}", server, e),
+        }
+    }
+
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    async fn mock_stun_server(public: SocketAddr) -> String {
+        let server = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let addr = server.local_addr().unwrap().to_string();
+
+        tokio::spawn(async move {
+            let mut buf = [0u8; 576];
+            while let Ok((len, from)) = server.recv_from(&mut buf).await {
+                if len < 20 {
+                    continue;
+                }
+                let mut txid = [0u8; 12];
+                txid.copy_from_slice(&buf[8..20]);
+                let _ = server
+                    .send_to(&stun::binding_response(&txid, public), from)
+                    .await;
+            }
+        });
+
+        addr
+    }
+
+    #[tokio::test]
+    async fn test_manual_address_wins() {
+        let manual: SocketAddr = "198.51.100.1:7000".parse().unwrap();
+        let nat = NatConfig {
+            advertised_addr: Some(manual),
+            stun_servers: vec![mock_stun_server("203.0.113.1:1".parse().unwrap()).await],
+            ..Default::default()
+        };
+
+        let addr = detect_public_addr(&nat, "127.0.0.1".parse().unwrap(), 0).await;
+        assert_eq!(addr, Some(manual));
+    }
+
+    #[tokio::test]
+    async fn test_stun_detection_skips_dead_server() {
+        let public: SocketAddr = "203.0.113.9:40001".parse().unwrap();
+        let dead = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let dead_addr = dead.local_addr().unwrap().to_string();
+
+        let nat = NatConfig {
+            stun_servers: vec![dead_addr, mock_stun_server(public).await],
+            stun_timeout: Duration::from_millis(200),
+            ..Default::default()
+        };
+
+        let addr = detect_public_addr(&nat, "127.0.0.1".parse().unwrap(), 0).await;
+        assert_eq!(addr, Some(public));
+        drop(dead);
+    }
+
+    #[tokio::test]
+    async fn test_no_sources() {
+        let addr = detect_public_addr(&NatConfig::default(), "127.0.0.1".parse().unwrap(), 0).await;
+        assert_eq!(addr, None);
+    }
+}
@@ -0,0 +1,399 @@
+// network/outbound.rs - Per-peer outbound queues drained by priority
+
+use super::message::PeerMessage;
+use crate::node::{NodeMetrics, OutboundConfig};
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use tokio::sync::Notify;
+
+/// Traffic classes, drained strictly in this order
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub enum Priority {
+    /// Heartbeats, signaling and consensus; overflowing it means the peer is
+    /// broken, so the connection is closed
+    Control,
+    /// Game actions; when full the oldest is dropped, as a newer one
+    /// supersedes it
+    GameAction,
+    /// Snapshots, state sync and relayed sessions; when full the sender
+    /// waits
+    Bulk,
+}
+
+const LANES: usize = 3;
+
+impl Priority {
+    /// Every class, most urgent first
+    pub const ALL: [Priority; LANES] = [Priority::Control, Priority::GameAction, Priority::Bulk];
+}
+
+/// Messages waiting in each class
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct QueueDepths {
+    pub control: usize,
+    pub game_action: usize,
+    pub bulk: usize,
+}
+
+impl QueueDepths {
+    /// Messages waiting in `priority`
+    pub fn of(&self, priority: Priority) -> usize {
+        match priority {
+            Priority::Control => self.control,
+            Priority::GameAction => self.game_action,
+            Priority::Bulk => self.bulk,
+        }
+    }
+}
+
+/// Why a message could not be queued
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SendError {
+    /// The control queue is full; the connection should be closed
+    ControlOverflow,
+    /// The bulk queue is full (only from `try_send`)
+    Full,
+    /// The connection task has gone away
+    Closed,
+}
+
+impl fmt::Display for SendError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SendError::ControlOverflow => write!(f, "control queue overflowed"),
+            SendError::Full => write!(f, "bulk queue is full"),
+            SendError::Closed => write!(f, "connection closed"),
+        }
+    }
+}
+
+impl std::error::Error for SendError {}
+
+struct Lanes {
+    queued: [VecDeque<PeerMessage>; LANES],
+    capacity: [usize; LANES],
+    closed: bool,
+}
+
+struct Shared {
+    lanes: Mutex<Lanes>,
+    /// Wakes the receiver when something is queued
+    readable: Notify,
+    /// Wakes bulk senders when the bulk queue has room
+    writable: Notify,
+    metrics: Arc<NodeMetrics>,
+}
+
+impl Shared {
+    fn depths(&self) -> QueueDepths {
+        let lanes = self.lanes.lock().unwrap();
+        QueueDepths {
+            control: lanes.queued[Priority::Control as usize].len(),
+            game_action: lanes.queued[Priority::GameAction as usize].len(),
+            bulk: lanes.queued[Priority::Bulk as usize].len(),
+        }
+    }
+}
+
+/// Queue messages for one connection; cheap to clone
+#[derive(Clone)]
+pub struct OutboundSender {
+    shared: Arc<Shared>,
+}
+
+/// The connection task's end, yielding the most urgent message first
+pub struct OutboundReceiver {
+    shared: Arc<Shared>,
+}
+
+/// A prioritized queue with the per-class capacities in `config`
+pub fn queue(
+    config: &OutboundConfig,
+    metrics: Arc<NodeMetrics>,
+) -> (OutboundSender, OutboundReceiver) {
+    let shared = Arc::new(Shared {
+        lanes: Mutex::new(Lanes {
+            queued: Default::default(),
+            capacity: [
+                config.control_capacity,
+                config.game_action_capacity,
+                config.bulk_capacity,
+            ],
+            closed: false,
+        }),
+        readable: Notify::new(),
+        writable: Notify::new(),
+        metrics,
+    });
+    (
+        OutboundSender {
+            shared: shared.clone(),
+        },
+        OutboundReceiver { shared },
+    )
+}
+
+impl OutboundSender {
+    /// Queue a message without waiting, applying its class's overflow policy
+    pub fn try_send(&self, message: PeerMessage) -> Result<(), SendError> {
+        self.push(message.priority(), message).map_err(|(e, _)| e)
+    }
+
+    /// Queue a message, waiting for room if it is bulk traffic
+    pub async fn send(&self, mut message: PeerMessage) -> Result<(), SendError> {
+        loop {
+            let writable = self.shared.writable.notified();
+            tokio::pin!(writable);
+            // Register before checking so a wakeup in between is not lost
+            writable.as_mut().enable();
+
+            match self.push(message.priority(), message) {
+                Ok(()) => return Ok(()),
+                Err((SendError::Full, returned)) => message = returned,
+                Err((e, _)) => return Err(e),
+            }
+            writable.await;
+        }
+    }
+
+    /// Queue a message, handing it back if it did not fit
+    #[allow(clippy::result_large_err)]
+    fn push(
+        &self,
+        priority: Priority,
+        message: PeerMessage,
+    ) -> Result<(), (SendError, PeerMessage)> {
+        let lane = priority as usize;
+        {
+            let mut lanes = self.shared.lanes.lock().unwrap();
+            if lanes.closed {
+                return Err((SendError::Closed, message));
+            }
+            if lanes.queued[lane].len() >= lanes.capacity[lane] {
+                match priority {
+                    Priority::Control => return Err((SendError::ControlOverflow, message)),
+                    Priority::GameAction => {
+                        lanes.queued[lane].pop_front();
+                        self.shared.metrics.game_actions_dropped.inc();
+                    }
+                    Priority::Bulk => return Err((SendError::Full, message)),
+                }
+            }
+            lanes.queued[lane].push_back(message);
+        }
+        self.shared.readable.notify_one();
+        Ok(())
+    }
+
+    /// Messages waiting in each class
+    pub fn depths(&self) -> QueueDepths {
+        self.shared.depths()
+    }
+}
+
+impl OutboundReceiver {
+    /// The next message, most urgent class first
+    pub async fn recv(&mut self) -> PeerMessage {
+        self.recv_where(|_| true).await
+    }
+
+    /// The next message from the classes `open` allows, most urgent first
+    ///
+    /// Messages in other classes stay queued, applying backpressure to their
+    /// senders.
+    pub async fn recv_where(&mut self, open: impl Fn(Priority) -> bool) -> PeerMessage {
+        let shared = self.shared.clone();
+        loop {
+            let readable = shared.readable.notified();
+            tokio::pin!(readable);
+            readable.as_mut().enable();
+
+            if let Some(message) = self.pop(&open) {
+                return message;
+            }
+            readable.await;
+        }
+    }
+
+    /// The next message if one is queued, most urgent class first
+    pub fn try_recv(&mut self) -> Option<PeerMessage> {
+        self.pop(|_| true)
+    }
+
+    fn pop(&mut self, open: impl Fn(Priority) -> bool) -> Option<PeerMessage> {
+        let (lane, message) = {
+            let mut lanes = self.shared.lanes.lock().unwrap();
+            lanes
+                .queued
+                .iter_mut()
+                .enumerate()
+                .filter(|(lane, _)| open(Priority::ALL[*lane]))
+                .find_map(|(lane, queued)| queued.pop_front().map(|m| (lane, m)))?
+        };
+        if lane == Priority::Bulk as usize {
+            self.shared.writable.notify_waiters();
+        }
+        Some(message)
+    }
+
+    /// Refuse further messages, keeping those already queued to be drained
+    pub fn close(&mut self) {
+        self.shared.lanes.lock().unwrap().closed = true;
+        self.shared.writable.notify_waiters();
+    }
+
+    /// Messages waiting in each class
+    pub fn depths(&self) -> QueueDepths {
+        self.shared.depths()
+    }
+
+    /// Put messages back at the head of their classes, in order, ahead of
+    /// anything queued and regardless of capacity
+    ///
+    /// Used for the fragments of a message that was already admitted.
+    pub fn push_front(&mut self, messages: Vec<PeerMessage>) {
+        let mut lanes = self.shared.lanes.lock().unwrap();
+        for message in messages.into_iter().rev() {
+            lanes.queued[message.priority() as usize].push_front(message);
+        }
+    }
+}
+
+impl Drop for OutboundReceiver {
+    fn drop(&mut self) {
+        let mut lanes = self.shared.lanes.lock().unwrap();
+        lanes.closed = true;
+        lanes.queued.iter_mut().for_each(VecDeque::clear);
+        drop(lanes);
+        self.shared.writable.notify_waiters();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn config() -> OutboundConfig {
+        OutboundConfig {
+            control_capacity: 2,
+            game_action_capacity: 2,
+            bulk_capacity: 2,
+        }
+    }
+
+    fn ping(nonce: u64) -> PeerMessage {
+        PeerMessage::Ping {
+            nonce,
+            sent_at_ms: 0,
+            mark: None,
+        }
+    }
+
+    fn bulk(session: u64) -> PeerMessage {
+        PeerMessage::RelayData {
+            session,
+            payload: vec![0; 16],
+        }
+    }
+
+    #[tokio::test]
+    async fn test_control_overtakes_queued_bulk() {
+        let (tx, mut rx) = queue(&config(), Arc::default());
+        tx.try_send(bulk(1)).unwrap();
+        tx.try_send(bulk(2)).unwrap();
+        tx.try_send(ping(3)).unwrap();
+
+        assert_eq!(
+            tx.depths(),
+            QueueDepths {
+                control: 1,
+                game_action: 0,
+                bulk: 2
+            }
+        );
+        assert_eq!(rx.recv().await, ping(3));
+        assert_eq!(rx.recv().await, bulk(1));
+        assert_eq!(rx.recv().await, bulk(2));
+    }
+
+    #[tokio::test]
+    async fn test_bulk_sender_waits_for_room() {
+        let (tx, mut rx) = queue(&config(), Arc::default());
+        tx.send(bulk(1)).await.unwrap();
+        tx.send(bulk(2)).await.unwrap();
+        assert_eq!(tx.try_send(bulk(3)), Err(SendError::Full));
+
+        let blocked = tx.clone();
+        let third = tokio::spawn(async move { blocked.send(bulk(3)).await });
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        assert!(!third.is_finished());
+
+        assert_eq!(rx.recv().await, bulk(1));
+        third.await.unwrap().unwrap();
+        assert_eq!(tx.depths().bulk, 2);
+    }
+
+    #[tokio::test]
+    async fn test_closed_classes_stay_queued() {
+        let (tx, mut rx) = queue(&config(), Arc::default());
+        tx.try_send(bulk(1)).unwrap();
+
+        let held = tokio::time::timeout(
+            Duration::from_millis(20),
+            rx.recv_where(|priority| priority != Priority::Bulk),
+        );
+        assert!(held.await.is_err());
+        assert_eq!(rx.depths().of(Priority::Bulk), 1);
+
+        tx.try_send(ping(2)).unwrap();
+        assert_eq!(
+            rx.recv_where(|priority| priority != Priority::Bulk).await,
+            ping(2)
+        );
+        assert_eq!(rx.recv().await, bulk(1));
+    }
+
+    #[tokio::test]
+    async fn test_game_action_overflow_drops_oldest() {
+        let metrics = Arc::new(NodeMetrics::default());
+        let (tx, mut rx) = queue(&config(), metrics.clone());
+        for nonce in 1..=3 {
+            tx.push(Priority::GameAction, ping(nonce)).unwrap();
+        }
+
+        assert_eq!(metrics.game_actions_dropped.get(), 1);
+        assert_eq!(rx.recv().await, ping(2));
+        assert_eq!(rx.recv().await, ping(3));
+    }
+
+    #[tokio::test]
+    async fn test_control_overflow_and_closed_queue() {
+        let (tx, rx) = queue(&config(), Arc::default());
+        tx.try_send(ping(1)).unwrap();
+        tx.try_send(ping(2)).unwrap();
+        assert_eq!(tx.try_send(ping(3)), Err(SendError::ControlOverflow));
+
+        drop(rx);
+        assert_eq!(tx.send(bulk(1)).await, Err(SendError::Closed));
+    }
+
+    #[tokio::test]
+    async fn test_closed_queue_still_drains() {
+        let (tx, mut rx) = queue(&config(), Arc::default());
+        tx.send(bulk(1)).await.unwrap();
+        tx.send(bulk(2)).await.unwrap();
+        let blocked = tx.clone();
+        let third = tokio::spawn(async move { blocked.send(bulk(3)).await });
+        tokio::time::sleep(Duration::from_millis(20)).await;
+
+        rx.close();
+        assert_eq!(third.await.unwrap(), Err(SendError::Closed));
+        assert_eq!(tx.try_send(ping(4)), Err(SendError::Closed));
+        assert_eq!(rx.try_recv(), Some(bulk(1)));
+        assert_eq!(rx.try_recv(), Some(bulk(2)));
+        assert_eq!(rx.try_recv(), None);
+    }
+}
@@ -0,0 +1,350 @@
+// network/pex.rs - Peer exchange: signed samples of who else is out there
+
+use super::bootstrap::PeerRecord;
+use crate::crypto::{self, KeyPair, PlayerId};
+use crate::error::Result;
+use crate::node::PexConfig;
+use rand::SeedableRng;
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::time::Duration;
+use tokio::time::Instant;
+
+/// A peer the sender is connected to, and how long ago it last heard from it
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PexEntry {
+    pub player_id: PlayerId,
+    pub addrs: Vec<SocketAddr>,
+    pub age_ms: u64,
+}
+
+/// Peers the sender vouches for, plus where to dial the sender itself
+/// (nowhere if `addrs` is empty), signed by the sender
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PexSample {
+    pub from: PlayerId,
+    pub addrs: Vec<SocketAddr>,
+    pub entries: Vec<PexEntry>,
+    pub signature: Vec<u8>,
+}
+
+impl PexSample {
+    pub fn new(keypair: &KeyPair, addrs: Vec<SocketAddr>, entries: Vec<PexEntry>) -> Self {
+        let mut sample = Self {
+            from: keypair.public_key(),
+            addrs,
+            entries,
+            signature: Vec::new(),
+        };
+        sample.signature = keypair.sign(&sample.signing_bytes());
+        sample
+    }
+
+    /// Canonical bytes covered by the signature
+    pub fn signing_bytes(&self) -> Vec<u8> {
+        fn push_addrs(bytes: &mut Vec<u8>, addrs: &[SocketAddr]) {
+            bytes.push(addrs.len() as u8);
+            for addr in addrs {
+                let addr = addr.to_string();
+                bytes.push(addr.len() as u8);
+                bytes.extend_from_slice(addr.as_bytes());
+            }
+        }
+
+        let mut bytes = Vec::with_capacity(64 + self.entries.len() * 128);
+        bytes.extend_from_slice(b"swarmhost-pex-v2");
+        bytes.extend_from_slice(&self.from);
+        push_addrs(&mut bytes, &self.addrs);
+        bytes.extend_from_slice(&(self.entries.len() as u32).to_be_bytes());
+        for entry in &self.entries {
+            bytes.extend_from_slice(&entry.player_id);
+            push_addrs(&mut bytes, &entry.addrs);
+            bytes.extend_from_slice(&entry.age_ms.to_be_bytes());
+        }
+        bytes
+    }
+
+    /// Check the signature against the sender's public key
+    pub fn verify(&self) -> Result<()> {
+        crypto::verify_signature(&self.from, &self.signing_bytes(), &self.signature)
+    }
+}
+
+struct Known {
+    addrs: Vec<SocketAddr>,
+    last_seen: Instant,
+    /// We are connected to it and it told us where to dial it
+    confirmed: bool,
+}
+
+/// Peers we know of, from our own connections and from others' samples
+///
+/// Only confirmed peers go into our samples, so a peer poisoned with made-up
+/// entries cannot get us to spread them. Heard-of peers older than
+/// `max_age` are forgotten; at capacity the stalest one makes room.
+pub struct PeerStore {
+    known: HashMap<PlayerId, Known>,
+    capacity: usize,
+    max_age: Duration,
+    rng: StdRng,
+}
+
+impl PeerStore {
+    pub fn new(config: &PexConfig) -> Self {
+        Self::with_rng(config, StdRng::from_entropy())
+    }
+
+    pub fn with_rng(config: &PexConfig, rng: StdRng) -> Self {
+        Self {
+            known: HashMap::new(),
+            capacity: config.store_capacity,
+            max_age: config.max_age,
+            rng,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.known.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.known.is_empty()
+    }
+
+    pub fn contains(&self, player_id: &PlayerId) -> bool {
+        self.known.contains_key(player_id)
+    }
+
+    /// Every peer we know of, confirmed or not
+    pub fn records(&self) -> Vec<PeerRecord> {
+        self.known
+            .iter()
+            .map(|(player_id, known)| PeerRecord {
+                player_id: *player_id,
+                addrs: known.addrs.clone(),
+            })
+            .collect()
+    }
+
+    /// We are connected to `peer` and heard from it now
+    pub fn confirm(&mut self, peer: PeerRecord, now: Instant) {
+        self.known.insert(
+            peer.player_id,
+            Known {
+                addrs: peer.addrs,
+                last_seen: now,
+                confirmed: true,
+            },
+        );
+    }
+
+    /// Mark a connected peer as seen now; returns whether it is confirmed
+    pub fn touch(&mut self, player_id: &PlayerId, now: Instant) -> bool {
+        match self.known.get_mut(player_id) {
+            Some(known) if known.confirmed => {
+                known.last_seen = now;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Our connection to `player_id` ended; it is only heard-of from now on
+    pub fn unconfirm(&mut self, player_id: &PlayerId) {
+        if let Some(known) = self.known.get_mut(player_id) {
+            known.confirmed = false;
+        }
+    }
+
+    /// Take in an entry from someone's sample, returning whether it told us
+    /// anything new
+    ///
+    /// Entries older than `max_age` are ignored, as is anything about a
+    /// confirmed peer: our own view of it wins.
+    pub fn learn(&mut self, entry: &PexEntry, now: Instant) -> bool {
+        let age = Duration::from_millis(entry.age_ms);
+        if age > self.max_age {
+            return false;
+        }
+        let seen = now.checked_sub(age).unwrap_or(now);
+        match self.known.get(&entry.player_id) {
+            Some(known) if known.confirmed || known.last_seen >= seen => return false,
+            Some(_) => {}
+            None if self.known.len() >= self.capacity => {
+                let Some(stalest) = self.stalest_unconfirmed().filter(|(_, at)| *at < seen) else {
+                    return false;
+                };
+                self.known.remove(&stalest.0);
+            }
+            None => {}
+        }
+        self.known.insert(
+            entry.player_id,
+            Known {
+                addrs: entry.addrs.clone(),
+                last_seen: seen,
+                confirmed: false,
+            },
+        );
+        true
+    }
+
+    fn stalest_unconfirmed(&self) -> Option<(PlayerId, Instant)> {
+        self.known
+            .iter()
+            .filter(|(_, known)| !known.confirmed)
+            .map(|(player_id, known)| (*player_id, known.last_seen))
+            .min_by_key(|(_, last_seen)| *last_seen)
+    }
+
+    /// Up to `count` random confirmed peers seen within `max_age`, for a
+    /// sample
+    pub fn sample(&mut self, count: usize, now: Instant) -> Vec<PexEntry> {
+        let fresh: Vec<PexEntry> = self
+            .known
+            .iter()
+            .filter(|(_, known)| known.confirmed)
+            .map(|(player_id, known)| PexEntry {
+                player_id: *player_id,
+                addrs: known.addrs.clone(),
+                age_ms: now.duration_since(known.last_seen).as_millis() as u64,
+            })
+            .filter(|entry| Duration::from_millis(entry.age_ms) <= self.max_age)
+            .collect();
+        fresh
+            .choose_multiple(&mut self.rng, count)
+            .cloned()
+            .collect()
+    }
+
+    /// Heard-of peers worth dialing, most recently seen first
+    pub fn candidates(&self) -> Vec<PeerRecord> {
+        let mut candidates: Vec<_> = self
+            .known
+            .iter()
+            .filter(|(_, known)| !known.confirmed)
+            .collect();
+        candidates.sort_by_key(|(_, known)| std::cmp::Reverse(known.last_seen));
+        candidates
+            .into_iter()
+            .map(|(player_id, known)| PeerRecord {
+                player_id: *player_id,
+                addrs: known.addrs.clone(),
+            })
+            .collect()
+    }
+
+    /// Forget heard-of peers not seen within `max_age`
+    pub fn expire(&mut self, now: Instant) {
+        let max_age = self.max_age;
+        self.known
+            .retain(|_, known| known.confirmed || now.duration_since(known.last_seen) <= max_age);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn store(capacity: usize) -> PeerStore {
+        let config = PexConfig {
+            store_capacity: capacity,
+            max_age: Duration::from_secs(60),
+            ..PexConfig::default()
+        };
+        PeerStore::with_rng(&config, StdRng::seed_from_u64(1))
+    }
+
+    fn record(n: u8) -> PeerRecord {
+        PeerRecord {
+            player_id: [n; 32],
+            addrs: vec![
+                SocketAddr::from(([0x2001, 0xdb8, 0, 0, 0, 0, 0, n as u16], 7000)),
+                SocketAddr::from(([10, 0, 0, n], 7000)),
+            ],
+        }
+    }
+
+    fn entry(n: u8, age_secs: u64) -> PexEntry {
+        PexEntry {
+            player_id: [n; 32],
+            addrs: record(n).addrs,
+            age_ms: age_secs * 1000,
+        }
+    }
+
+    #[test]
+    fn test_tampered_sample_fails_verification() {
+        let keypair = KeyPair::generate();
+        let addrs = record(1).addrs;
+        let sample = PexSample::new(&keypair, addrs, vec![entry(2, 5), entry(3, 0)]);
+        assert!(sample.verify().is_ok());
+
+        let mut aged = sample.clone();
+        aged.entries[0].age_ms = 0;
+        assert!(aged.verify().is_err());
+        let mut moved = sample.clone();
+        moved.addrs = record(9).addrs;
+        assert!(moved.verify().is_err());
+        let mut dropped = sample;
+        dropped.entries[1].addrs.pop();
+        assert!(dropped.verify().is_err());
+    }
+
+    #[test]
+    fn test_learn_ignores_stale_entries_and_confirmed_peers() {
+        let now = Instant::now() + Duration::from_secs(3600);
+        let mut store = store(8);
+        assert!(!store.learn(&entry(1, 61), now));
+        assert!(store.learn(&entry(1, 30), now));
+        // An older report of the same peer changes nothing; a newer one does
+        assert!(!store.learn(&entry(1, 40), now));
+        assert!(store.learn(&entry(1, 10), now));
+
+        store.confirm(record(2), now);
+        let elsewhere = PexEntry {
+            addrs: record(9).addrs,
+            ..entry(2, 0)
+        };
+        assert!(!store.learn(&elsewhere, now));
+        assert_eq!(
+            store
+                .candidates()
+                .iter()
+                .map(|r| r.player_id)
+                .collect::<Vec<_>>(),
+            vec![[1; 32]]
+        );
+
+        store.expire(now + Duration::from_secs(51));
+        assert!(!store.contains(&[1; 32]));
+        assert!(store.contains(&[2; 32]));
+    }
+
+    #[test]
+    fn test_samples_hold_only_confirmed_peers() {
+        let now = Instant::now() + Duration::from_secs(3600);
+        let mut store = store(3);
+        store.confirm(record(1), now);
+        store.confirm(record(2), now);
+        store.learn(&entry(3, 0), now);
+        // Full: a fresher heard-of peer displaces the stalest, older ones do not
+        assert!(store.learn(&entry(4, 0), now + Duration::from_secs(1)));
+        assert!(!store.contains(&[3; 32]));
+        assert!(!store.learn(&entry(5, 30), now));
+
+        let sample = store.sample(8, now + Duration::from_secs(2));
+        let mut ids: Vec<_> = sample.iter().map(|e| e.player_id).collect();
+        ids.sort();
+        assert_eq!(ids, vec![[1; 32], [2; 32]]);
+        assert!(sample.iter().all(|e| e.age_ms == 2000));
+        assert_eq!(store.sample(1, now).len(), 1);
+
+        store.unconfirm(&[1; 32]);
+        assert!(!store.touch(&[1; 32], now));
+        assert_eq!(store.sample(8, now).len(), 1);
+    }
+}
@@ -0,0 +1,1014 @@
+// network/portmap.rs - Router port mapping via UPnP IGD or NAT-PMP
+
+use crate::error::{Result, SwarmhostError};
+use std::future::Future;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr, SocketAddrV4};
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpStream, UdpSocket};
+
+/// Where SSDP searches for UPnP devices are sent
+pub const SSDP_MULTICAST: SocketAddr =
+    SocketAddr::V4(SocketAddrV4::new(Ipv4Addr::new(239, 255, 255, 250), 1900));
+
+/// Port NAT-PMP gateways listen on
+pub const NAT_PMP_PORT: u16 = 5351;
+
+/// How long each mapping method gets before it is given up on
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(3);
+
+const SEARCH_TARGET: &str = "urn:schemas-upnp-org:device:InternetGatewayDevice:1";
+const MAX_HTTP_RESPONSE: usize = 64 * 1024;
+const DESCRIPTION: &str = "swarmhost";
+
+/// Services that can forward ports, most preferred first
+const WAN_SERVICES: [&str; 3] = [
+    "urn:schemas-upnp-org:service:WANIPConnection:2",
+    "urn:schemas-upnp-org:service:WANIPConnection:1",
+    "urn:schemas-upnp-org:service:WANPPPConnection:1",
+];
+
+/// Transport protocol of a mapped port
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PortProtocol {
+    Tcp,
+    Udp,
+}
+
+impl PortProtocol {
+    fn upnp_name(self) -> &'static str {
+        match self {
+            Self::Tcp => "TCP",
+            Self::Udp => "UDP",
+        }
+    }
+
+    fn nat_pmp_opcode(self) -> u8 {
+        match self {
+            Self::Udp => 1,
+            Self::Tcp => 2,
+        }
+    }
+}
+
+/// Which protocol the router was asked through
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MappingMethod {
+    Upnp,
+    NatPmp,
+}
+
+impl std::fmt::Display for MappingMethod {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Upnp => write!(f, "UPnP"),
+            Self::NatPmp => write!(f, "NAT-PMP"),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+enum Gateway {
+    Upnp {
+        control_url: String,
+        service: &'static str,
+        internal: IpAddr,
+    },
+    NatPmp(SocketAddr),
+}
+
+/// A port forwarded by the router, held until removed or its lease runs out
+#[derive(Debug, Clone)]
+pub struct PortMapping {
+    pub protocol: PortProtocol,
+    pub internal_port: u16,
+    /// Address peers outside the NAT reach the port on
+    pub external: SocketAddr,
+    /// How long the router keeps the mapping without a renewal
+    pub lease: Duration,
+    gateway: Gateway,
+}
+
+impl PortMapping {
+    pub fn method(&self) -> MappingMethod {
+        match self.gateway {
+            Gateway::Upnp { .. } => MappingMethod::Upnp,
+            Gateway::NatPmp(_) => MappingMethod::NatPmp,
+        }
+    }
+
+    /// Ask the router for a fresh lease on the same external port
+    pub async fn renew(&mut self, timeout: Duration) -> Result<()> {
+        match &self.gateway {
+            Gateway::Upnp {
+                control_url,
+                service,
+                internal,
+            } => {
+                let add = upnp_add(
+                    control_url,
+                    service,
+                    *internal,
+                    self.protocol,
+                    self.internal_port,
+                    self.external.port(),
+                    self.lease,
+                );
+                within(timeout, "UPnP renewal", add).await
+            }
+            Gateway::NatPmp(gateway) => {
+                let (port, lifetime) = nat_pmp_map(
+                    *gateway,
+                    self.protocol,
+                    self.internal_port,
+                    self.external.port(),
+                    lease_secs(self.lease),
+                    timeout,
+                )
+                .await?;
+                self.external.set_port(port);
+                self.lease = Duration::from_secs(lifetime as u64);
+                Ok(())
+            }
+        }
+    }
+
+    /// Take the mapping off the router
+    pub async fn remove(&self, timeout: Duration) -> Result<()> {
+        match &self.gateway {
+            Gateway::Upnp {
+                control_url,
+                service,
+                ..
+            } => {
+                let port = self.external.port().to_string();
+                let args = [
+                    ("NewRemoteHost", ""),
+                    ("NewExternalPort", port.as_str()),
+                    ("NewProtocol", self.protocol.upnp_name()),
+                ];
+                let delete = soap(control_url, service, "DeletePortMapping", &args);
+                within(timeout, "UPnP removal", delete).await.map(|_| ())
+            }
+            Gateway::NatPmp(gateway) => {
+                nat_pmp_map(*gateway, self.protocol, self.internal_port, 0, 0, timeout)
+                    .await
+                    .map(|_| ())
+            }
+        }
+    }
+}
+
+/// Asks the local router to forward a port, trying UPnP IGD then NAT-PMP
+#[derive(Debug, Clone)]
+pub struct PortMapper {
+    ssdp: SocketAddr,
+    nat_pmp: Option<SocketAddr>,
+    timeout: Duration,
+}
+
+impl Default for PortMapper {
+    fn default() -> Self {
+        Self {
+            ssdp: SSDP_MULTICAST,
+            nat_pmp: None,
+            timeout: DEFAULT_TIMEOUT,
+        }
+    }
+}
+
+impl PortMapper {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Send UPnP searches here instead of the SSDP multicast group
+    pub fn with_ssdp(mut self, addr: SocketAddr) -> Self {
+        self.ssdp = addr;
+        self
+    }
+
+    /// Use this NAT-PMP gateway instead of the default route's
+    pub fn with_nat_pmp(mut self, addr: SocketAddr) -> Self {
+        self.nat_pmp = Some(addr);
+        self
+    }
+
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    pub fn timeout(&self) -> Duration {
+        self.timeout
+    }
+
+    /// Forward `port` on the router to this host for `lease`
+    ///
+    /// The same external port is asked for. UPnP is tried first; NAT-PMP is
+    /// only used when no UPnP gateway answers or it refuses the mapping.
+    pub async fn map(
+        &self,
+        protocol: PortProtocol,
+        port: u16,
+        lease: Duration,
+    ) -> Result<PortMapping> {
+        let upnp = match self.map_upnp(protocol, port, lease).await {
+            Ok(mapping) => return Ok(mapping),
+            Err(e) => e,
+        };
+        tracing::debug!("UPnP port mapping failed: {}", upnp);
+
+        let gateway = match self.nat_pmp {
+            Some(gateway) => Ok(gateway),
+            None => default_gateway().map(|ip| SocketAddr::new(IpAddr::V4(ip), NAT_PMP_PORT)),
+        };
+        let nat_pmp = match gateway {
+            Ok(gateway) => match map_nat_pmp(gateway, protocol, port, lease, self.timeout).await {
+                Ok(mapping) => return Ok(mapping),
+                Err(e) => e,
+            },
+            Err(e) => e,
+        };
+
+        Err(SwarmhostError::Peer(format!(
+            "No port mapping (UPnP: {}; NAT-PMP: {})",
+            upnp, nat_pmp
+        )))
+    }
+
+    async fn map_upnp(
+        &self,
+        protocol: PortProtocol,
+        port: u16,
+        lease: Duration,
+    ) -> Result<PortMapping> {
+        let (control_url, service, internal) =
+            within(self.timeout, "UPnP discovery", self.discover()).await?;
+
+        let mapped = async {
+            upnp_add(&control_url, service, internal, protocol, port, port, lease).await?;
+            let reply = soap(&control_url, service, "GetExternalIPAddress", &[]).await?;
+            tag(&reply, "NewExternalIPAddress")
+                .and_then(|ip| ip.trim().parse::<IpAddr>().ok())
+                .ok_or_else(|| portmap_error("gateway did not report its external address"))
+        };
+        let external = within(self.timeout, "UPnP mapping", mapped).await?;
+
+        tracing::info!(
+            "UPnP gateway {} maps {}:{} to {}:{}",
+            control_url,
+            internal,
+            port,
+            external,
+            port
+        );
+        Ok(PortMapping {
+            protocol,
+            internal_port: port,
+            external: SocketAddr::new(external, port),
+            lease,
+            gateway: Gateway::Upnp {
+                control_url,
+                service,
+                internal,
+            },
+        })
+    }
+
+    /// Search for an IGD and return its control URL, service type and the
+    /// local address it sees us on
+    async fn discover(&self) -> Result<(String, &'static str, IpAddr)> {
+        let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0)).await?;
+        let search = format!(
+            "M-SEARCH * HTTP/1.1\r\nHOST: {}\r\nMAN: \"ssdp:discover\"\r\nMX: 2\r\nST: {}\r\n\r\n",
+            SSDP_MULTICAST, SEARCH_TARGET
+        );
+        socket.send_to(search.as_bytes(), self.ssdp).await?;
+
+        let mut buf = [0u8; 2048];
+        loop {
+            let (len, from) = socket.recv_from(&mut buf).await?;
+            let reply = String::from_utf8_lossy(&buf[..len]);
+            let Some(location) = header(&reply, "location") else {
+                continue;
+            };
+            match describe(location).await {
+                Ok(found) => return Ok(found),
+                Err(e) => tracing::debug!("Skipping UPnP device at {}: {}", from, e),
+            }
+        }
+    }
+}
+
+async fn describe(location: &str) -> Result<(String, &'static str, IpAddr)> {
+    let (authority, path) = split_url(location)?;
+    let (status, body) = http_request(&authority, "GET", &path, &[], "").await?;
+    if status != 200 {
+        return Err(portmap_error(format!("description returned {}", status)));
+    }
+    let (control, service) =
+        wan_service(&body).ok_or_else(|| portmap_error("device has no WAN connection service"))?;
+    let control_url = resolve_url(location, control);
+
+    let (authority, _) = split_url(&control_url)?;
+    let gateway = tokio::net::lookup_host(&authority)
+        .await?
+        .next()
+        .ok_or_else(|| portmap_error(format!("cannot resolve {}", authority)))?;
+    let probe = UdpSocket::bind(SocketAddr::new(unspecified(gateway.ip()), 0)).await?;
+    probe.connect(gateway).await?;
+    let internal = probe.local_addr()?.ip();
+
+    Ok((control_url, service, internal))
+}
+
+async fn upnp_add(
+    control_url: &str,
+    service: &str,
+    internal: IpAddr,
+    protocol: PortProtocol,
+    internal_port: u16,
+    external_port: u16,
+    lease: Duration,
+) -> Result<()> {
+    let external_port = external_port.to_string();
+    let internal_port = internal_port.to_string();
+    let internal = internal.to_string();
+    let lease = lease_secs(lease).to_string();
+    soap(
+        control_url,
+        service,
+        "AddPortMapping",
+        &[
+            ("NewRemoteHost", ""),
+            ("NewExternalPort", &external_port),
+            ("NewProtocol", protocol.upnp_name()),
+            ("NewInternalPort", &internal_port),
+            ("NewInternalClient", &internal),
+            ("NewEnabled", "1"),
+            ("NewPortMappingDescription", DESCRIPTION),
+            ("NewLeaseDuration", &lease),
+        ],
+    )
+    .await
+    .map(|_| ())
+}
+
+/// Invoke `action` on a UPnP service and return the response body
+async fn soap(
+    control_url: &str,
+    service: &str,
+    action: &str,
+    args: &[(&str, &str)],
+) -> Result<String> {
+    let mut body = format!(
+        "<?xml version=\"1.0\"?><s:Envelope xmlns:s=\"http://schemas.xmlsoap.org/soap/envelope/\" \
+         s:encodingStyle=\"http://schemas.xmlsoap.org/soap/encoding/\"><s:Body><u:{} xmlns:u=\"{}\">",
+        action, service
+    );
+    for (name, value) in args {
+        body.push_str(&format!("<{}>{}</{}>", name, value, name));
+    }
+    body.push_str(&format!("</u:{}></s:Body></s:Envelope>", action));
+
+    let (authority, path) = split_url(control_url)?;
+    let soap_action = format!("\"{}#{}\"", service, action);
+    let headers = [
+        ("Content-Type", "text/xml; charset=\"utf-8\""),
+        ("SOAPAction", soap_action.as_str()),
+    ];
+    let (status, reply) = http_request(&authority, "POST", &path, &headers, &body).await?;
+    if status != 200 {
+        let reason = tag(&reply, "errorDescription").unwrap_or("no description");
+        return Err(portmap_error(format!(
+            "{} returned {}: {}",
+            action, status, reason
+        )));
+    }
+    Ok(reply)
+}
+
+/// A one-shot HTTP/1.1 exchange, returning the status and decoded body
+async fn http_request(
+    authority: &str,
+    method: &str,
+    path: &str,
+    headers: &[(&str, &str)],
+    body: &str,
+) -> Result<(u16, String)> {
+    let mut request = format!(
+        "{} {} HTTP/1.1\r\nHost: {}\r\nConnection: close\r\nContent-Length: {}\r\n",
+        method,
+        path,
+        authority,
+        body.len()
+    );
+    for (name, value) in headers {
+        request.push_str(&format!("{}: {}\r\n", name, value));
+    }
+    request.push_str("\r\n");
+    request.push_str(body);
+
+    let mut stream = TcpStream::connect(authority).await?;
+    stream.write_all(request.as_bytes()).await?;
+    let mut response = Vec::new();
+    (&mut stream)
+        .take(MAX_HTTP_RESPONSE as u64 + 1)
+        .read_to_end(&mut response)
+        .await?;
+    if response.len() > MAX_HTTP_RESPONSE {
+        return Err(portmap_error("HTTP response too large"));
+    }
+
+    let response = String::from_utf8_lossy(&response);
+    let (head, body) = response
+        .split_once("\r\n\r\n")
+        .ok_or_else(|| portmap_error("truncated HTTP response"))?;
+    let status = head
+        .split_whitespace()
+        .nth(1)
+        .and_then(|status| status.parse().ok())
+        .ok_or_else(|| portmap_error("bad HTTP status line"))?;
+    let body = match header(head, "transfer-encoding") {
+        Some(encoding) if encoding.eq_ignore_ascii_case("chunked") => dechunk(body)?,
+        _ => body.to_string(),
+    };
+    Ok((status, body))
+}
+
+fn dechunk(mut rest: &str) -> Result<String> {
+    let mut body = String::new();
+    loop {
+        let (size, after) = rest
+            .split_once("\r\n")
+            .ok_or_else(|| portmap_error("truncated chunk"))?;
+        let size = size.split(';').next().unwrap_or_default().trim();
+        let size = usize::from_str_radix(size, 16)
+            .map_err(|_| portmap_error(format!("bad chunk size {:?}", size)))?;
+        if size == 0 {
+            return Ok(body);
+        }
+        let chunk = after
+            .get(..size)
+            .ok_or_else(|| portmap_error("truncated chunk"))?;
+        body.push_str(chunk);
+        rest = after[size..]
+            .strip_prefix("\r\n")
+            .ok_or_else(|| portmap_error("unterminated chunk"))?;
+    }
+}
+
+/// Value of the first header called `name`, ignoring case
+fn header<'a>(head: &'a str, name: &str) -> Option<&'a str> {
+    head.lines().find_map(|line| {
+        let (key, value) = line.split_once(':')?;
+        key.trim()
+            .eq_ignore_ascii_case(name)
+            .then_some(value.trim())
+    })
+}
+
+/// Text inside the first unprefixed `<name>` element
+fn tag<'a>(xml: &'a str, name: &str) -> Option<&'a str> {
+    let open = format!("<{}>", name);
+    let close = format!("</{}>", name);
+    let start = xml.find(&open)? + open.len();
+    let len = xml[start..].find(&close)?;
+    Some(&xml[start..start + len])
+}
+
+/// Control URL and type of the most preferred WAN service in a description
+fn wan_service(description: &str) -> Option<(&str, &'static str)> {
+    let services: Vec<&str> = description
+        .split("<service>")
+        .skip(1)
+        .map(|block| block.split("</service>").next().unwrap_or_default())
+        .collect();
+    WAN_SERVICES.iter().find_map(|wanted| {
+        services.iter().find_map(|&block| {
+            if tag(block, "serviceType")?.trim() != *wanted {
+                return None;
+            }
+            Some((tag(block, "controlURL")?.trim(), *wanted))
+        })
+    })
+}
+
+/// Split an `http://` URL into `host:port` and path
+fn split_url(url: &str) -> Result<(String, String)> {
+    let rest = url
+        .strip_prefix("http://")
+        .ok_or_else(|| portmap_error(format!("unsupported URL {}", url)))?;
+    let (authority, path) = match rest.find('/') {
+        Some(i) => (&rest[..i], &rest[i..]),
+        None => (rest, "/"),
+    };
+    let has_port = match authority.rfind(':') {
+        Some(i) => !authority[i..].contains(']'),
+        None => false,
+    };
+    let authority = if has_port {
+        authority.to_string()
+    } else {
+        format!("{}:80", authority)
+    };
+    Ok((authority, path.to_string()))
+}
+
+/// `url` made absolute against the device at `location`
+fn resolve_url(location: &str, url: &str) -> String {
+    if url.starts_with("http://") {
+        return url.to_string();
+    }
+    let base = location
+        .strip_prefix("http://")
+        .and_then(|rest| rest.split('/').next())
+        .unwrap_or_default();
+    if url.starts_with('/') {
+        format!("http://{}{}", base, url)
+    } else {
+        format!("http://{}/{}", base, url)
+    }
+}
+
+async fn map_nat_pmp(
+    gateway: SocketAddr,
+    protocol: PortProtocol,
+    port: u16,
+    lease: Duration,
+    timeout: Duration,
+) -> Result<PortMapping> {
+    let reply = nat_pmp_request(gateway, &[0, 0], 128, timeout).await?;
+    let ip = parse_nat_pmp_address(&reply)?;
+    let (external_port, lifetime) =
+        nat_pmp_map(gateway, protocol, port, port, lease_secs(lease), timeout).await?;
+
+    tracing::info!(
+        "NAT-PMP gateway {} maps port {} to {}:{}",
+        gateway,
+        port,
+        ip,
+        external_port
+    );
+    Ok(PortMapping {
+        protocol,
+        internal_port: port,
+        external: SocketAddr::new(IpAddr::V4(ip), external_port),
+        lease: Duration::from_secs(lifetime as u64),
+        gateway: Gateway::NatPmp(gateway),
+    })
+}
+
+/// Request a mapping; returns the external port and lifetime granted
+async fn nat_pmp_map(
+    gateway: SocketAddr,
+    protocol: PortProtocol,
+    internal: u16,
+    external: u16,
+    lifetime: u32,
+    timeout: Duration,
+) -> Result<(u16, u32)> {
+    let request = nat_pmp_map_request(protocol, internal, external, lifetime);
+    let opcode = 128 + protocol.nat_pmp_opcode();
+    let reply = nat_pmp_request(gateway, &request, opcode, timeout).await?;
+    parse_nat_pmp_map(&reply)
+}
+
+/// Send `request` until a successful response with `opcode` arrives,
+/// doubling the wait between sends as RFC 6886 asks
+async fn nat_pmp_request(
+    gateway: SocketAddr,
+    request: &[u8],
+    opcode: u8,
+    timeout: Duration,
+) -> Result<Vec<u8>> {
+    let socket = UdpSocket::bind(SocketAddr::new(unspecified(gateway.ip()), 0)).await?;
+    socket.connect(gateway).await?;
+
+    let exchange = async {
+        let mut wait = Duration::from_millis(250);
+        let mut buf = [0u8; 16];
+        loop {
+            socket.send(request).await?;
+            if let Ok(received) = tokio::time::timeout(wait, socket.recv(&mut buf)).await {
+                let len = received?;
+                if len >= 4 && buf[0] == 0 && buf[1] == opcode {
+                    let result = u16::from_be_bytes([buf[2], buf[3]]);
+                    if result != 0 {
+                        return Err(portmap_error(format!("NAT-PMP result code {}", result)));
+                    }
+                    return Ok(buf[..len].to_vec());
+                }
+            }
+            wait *= 2;
+        }
+    };
+    within(timeout, "NAT-PMP request", exchange).await
+}
+
+fn nat_pmp_map_request(
+    protocol: PortProtocol,
+    internal: u16,
+    external: u16,
+    lifetime: u32,
+) -> Vec<u8> {
+    let mut buf = vec![0, protocol.nat_pmp_opcode(), 0, 0];
+    buf.extend_from_slice(&internal.to_be_bytes());
+    buf.extend_from_slice(&external.to_be_bytes());
+    buf.extend_from_slice(&lifetime.to_be_bytes());
+    buf
+}
+
+fn parse_nat_pmp_address(buf: &[u8]) -> Result<Ipv4Addr> {
+    if buf.len() < 12 {
+        return Err(portmap_error("short NAT-PMP address response"));
+    }
+    Ok(Ipv4Addr::new(buf[8], buf[9], buf[10], buf[11]))
+}
+
+fn parse_nat_pmp_map(buf: &[u8]) -> Result<(u16, u32)> {
+    if buf.len() < 16 {
+        return Err(portmap_error("short NAT-PMP mapping response"));
+    }
+    let external = u16::from_be_bytes([buf[10], buf[11]]);
+    let lifetime = u32::from_be_bytes([buf[12], buf[13], buf[14], buf[15]]);
+    Ok((external, lifetime))
+}
+
+/// The IPv4 default gateway from the kernel routing table
+fn default_gateway() -> Result<Ipv4Addr> {
+    let table = std::fs::read_to_string("/proc/net/route")
+        .map_err(|e| portmap_error(format!("cannot read the routing table: {}", e)))?;
+    parse_route_table(&table).ok_or_else(|| portmap_error("no default route"))
+}
+
+fn parse_route_table(table: &str) -> Option<Ipv4Addr> {
+    table.lines().skip(1).find_map(|line| {
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        if fields.len() < 3 || fields[1] != "00000000" {
+            return None;
+        }
+        // Addresses are printed as a host-order integer
+        let raw = u32::from_str_radix(fields[2], 16).ok()?;
+        Some(Ipv4Addr::from(raw.to_ne_bytes()))
+    })
+}
+
+fn unspecified(like: IpAddr) -> IpAddr {
+    match like {
+        IpAddr::V4(_) => IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+        IpAddr::V6(_) => IpAddr::V6(std::net::Ipv6Addr::UNSPECIFIED),
+    }
+}
+
+fn lease_secs(lease: Duration) -> u32 {
+    lease.as_secs().min(u32::MAX as u64) as u32
+}
+
+async fn within<T>(
+    timeout: Duration,
+    what: &str,
+    fut: impl Future<Output = Result<T>>,
+) -> Result<T> {
+    tokio::time::timeout(timeout, fut)
+        .await
+        .map_err(|_| SwarmhostError::timeout(format!("{} timed out", what)))?
+}
+
+fn portmap_error(msg: impl std::fmt::Display) -> SwarmhostError {
+    SwarmhostError::Peer(format!("Port mapping failed: {}", msg))
+}
+
+#[cfg(test)]
+pub(crate) mod mock {
+    use super::*;
+    use std::collections::HashMap;
+    use std::sync::{Arc, Mutex};
+    use tokio::net::TcpListener;
+    use tokio::task::JoinHandle;
+
+    const CONTROL_PATH: &str = "/ctl/IPConn";
+
+    #[derive(Debug, Default)]
+    pub(crate) struct IgdState {
+        /// External port to internal client and lease
+        pub mappings: HashMap<u16, (SocketAddr, Duration)>,
+        /// AddPortMapping calls seen, renewals included
+        pub adds: usize,
+    }
+
+    /// An Internet Gateway Device answering SSDP searches and SOAP calls
+    pub(crate) struct MockIgd {
+        pub ssdp: SocketAddr,
+        pub state: Arc<Mutex<IgdState>>,
+        tasks: Vec<JoinHandle<()>>,
+    }
+
+    impl MockIgd {
+        pub async fn start(external_ip: IpAddr) -> Self {
+            let http = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let location = format!("http://{}/rootDesc.xml", http.local_addr().unwrap());
+            let ssdp = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+            let ssdp_addr = ssdp.local_addr().unwrap();
+            let state = Arc::new(Mutex::new(IgdState::default()));
+
+            let search = tokio::spawn(async move {
+                let mut buf = [0u8; 1024];
+                while let Ok((len, from)) = ssdp.recv_from(&mut buf).await {
+                    if !buf[..len].starts_with(b"M-SEARCH") {
+                        continue;
+                    }
+                    let reply = format!(
+                        "HTTP/1.1 200 OK\r\nST: {}\r\nLOCATION: {}\r\n\r\n",
+                        SEARCH_TARGET, location
+                    );
+                    let _ = ssdp.send_to(reply.as_bytes(), from).await;
+                }
+            });
+
+            let shared = state.clone();
+            let control = tokio::spawn(async move {
+                while let Ok((stream, _)) = http.accept().await {
+                    let state = shared.clone();
+                    tokio::spawn(async move {
+                        let _ = serve(stream, state, external_ip).await;
+                    });
+                }
+            });
+
+            Self {
+                ssdp: ssdp_addr,
+                state,
+                tasks: vec![search, control],
+            }
+        }
+
+        /// A mapper that finds this device
+        pub fn mapper(&self) -> PortMapper {
+            PortMapper::new()
+                .with_ssdp(self.ssdp)
+                .with_timeout(Duration::from_secs(1))
+        }
+    }
+
+    impl Drop for MockIgd {
+        fn drop(&mut self) {
+            for task in &self.tasks {
+                task.abort();
+            }
+        }
+    }
+
+    async fn serve(
+        mut stream: TcpStream,
+        state: Arc<Mutex<IgdState>>,
+        external_ip: IpAddr,
+    ) -> std::io::Result<()> {
+        let mut request = Vec::new();
+        let mut buf = [0u8; 4096];
+        let (head, body) = loop {
+            let n = stream.read(&mut buf).await?;
+            if n == 0 {
+                return Ok(());
+            }
+            request.extend_from_slice(&buf[..n]);
+            let text = String::from_utf8_lossy(&request);
+            if let Some((head, body)) = text.split_once("\r\n\r\n") {
+                let length = header(head, "content-length")
+                    .and_then(|length| length.parse().ok())
+                    .unwrap_or(0);
+                if body.len() >= length {
+                    break (head.to_string(), body.to_string());
+                }
+            }
+        };
+
+        let response = if head.starts_with("GET /rootDesc.xml") {
+            // Sent chunked, as miniupnpd does
+            let description = description();
+            let (first, second) = description.split_at(description.len() / 2);
+            format!(
+                "HTTP/1.1 200 OK\r\nContent-Type: text/xml\r\nTransfer-Encoding: chunked\r\n\r\n\
+                 {:x}\r\n{}\r\n{:x}\r\n{}\r\n0\r\n\r\n",
+                first.len(),
+                first,
+                second.len(),
+                second
+            )
+        } else {
+            let action = header(&head, "soapaction")
+                .and_then(|action| action.trim_matches('"').rsplit('#').next())
+                .unwrap_or_default();
+            let (status, reply) = control(action, &body, &state, external_ip);
+            let xml = format!(
+                "<?xml version=\"1.0\"?><s:Envelope xmlns:s=\"http://schemas.xmlsoap.org/soap/envelope/\">\
+                 <s:Body>{}</s:Body></s:Envelope>",
+                reply
+            );
+            format!(
+                "HTTP/1.1 {}\r\nContent-Type: text/xml\r\nContent-Length: {}\r\n\r\n{}",
+                status,
+                xml.len(),
+                xml
+            )
+        };
+        stream.write_all(response.as_bytes()).await?;
+        stream.shutdown().await
+    }
+
+    fn description() -> String {
+        format!(
+            "<?xml version=\"1.0\"?><root><device><deviceType>{}</deviceType><serviceList>\
+             <service><serviceType>urn:schemas-upnp-org:service:Layer3Forwarding:1</serviceType>\
+             <controlURL>/ctl/L3F</controlURL></service></serviceList><deviceList><device>\
+             <serviceList><service><serviceType>{}</serviceType><controlURL>{}</controlURL>\
+             </service></serviceList></device></deviceList></device></root>",
+            SEARCH_TARGET, WAN_SERVICES[1], CONTROL_PATH
+        )
+    }
+
+    fn control(
+        action: &str,
+        body: &str,
+        state: &Mutex<IgdState>,
+        external_ip: IpAddr,
+    ) -> (&'static str, String) {
+        let arg = |name| tag(body, name).unwrap_or_default();
+        let port: u16 = arg("NewExternalPort").parse().unwrap_or(0);
+        let mut state = state.lock().unwrap();
+        match action {
+            "AddPortMapping" => {
+                let client = SocketAddr::new(
+                    arg("NewInternalClient").parse().unwrap(),
+                    arg("NewInternalPort").parse().unwrap(),
+                );
+                let lease = Duration::from_secs(arg("NewLeaseDuration").parse().unwrap());
+                state.mappings.insert(port, (client, lease));
+                state.adds += 1;
+                ("200 OK", response(action, ""))
+            }
+            "GetExternalIPAddress" => {
+                let ip = format!(
+                    "<NewExternalIPAddress>{}</NewExternalIPAddress>",
+                    external_ip
+                );
+                ("200 OK", response(action, &ip))
+            }
+            "DeletePortMapping" => match state.mappings.remove(&port) {
+                Some(_) => ("200 OK", response(action, "")),
+                None => fault(714, "NoSuchEntryInArray"),
+            },
+            _ => fault(401, "Invalid Action"),
+        }
+    }
+
+    fn response(action: &str, args: &str) -> String {
+        format!(
+            "<u:{}Response xmlns:u=\"{}\">{}</u:{}Response>",
+            action, WAN_SERVICES[1], args, action
+        )
+    }
+
+    fn fault(code: u16, description: &str) -> (&'static str, String) {
+        let fault = format!(
+            "<s:Fault><faultcode>s:Client</faultcode><faultstring>UPnPError</faultstring>\
+             <detail><UPnPError><errorCode>{}</errorCode><errorDescription>{}</errorDescription>\
+             </UPnPError></detail></s:Fault>",
+            code, description
+        );
+        ("500 Internal Server Error", fault)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::mock::MockIgd;
+    use super::*;
+
+    const EXTERNAL: IpAddr = IpAddr::V4(Ipv4Addr::new(203, 0, 113, 7));
+
+    /// A NAT-PMP gateway granting every request as asked
+    async fn mock_nat_pmp() -> SocketAddr {
+        let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let addr = socket.local_addr().unwrap();
+        tokio::spawn(async move {
+            let mut buf = [0u8; 16];
+            while let Ok((len, from)) = socket.recv_from(&mut buf).await {
+                let opcode = buf[1];
+                let body = match opcode {
+                    0 if len == 2 => vec![203, 0, 113, 7],
+                    1 | 2 if len == 12 => buf[4..12].to_vec(),
+                    _ => continue,
+                };
+                let mut reply = vec![0, 128 + opcode, 0, 0, 0, 0, 0, 42];
+                reply.extend_from_slice(&body);
+                let _ = socket.send_to(&reply, from).await;
+            }
+        });
+        addr
+    }
+
+    #[test]
+    fn test_nat_pmp_codec() {
+        let request = nat_pmp_map_request(PortProtocol::Tcp, 7000, 7001, 3600);
+        assert_eq!(
+            request,
+            [0, 2, 0, 0, 0x1b, 0x58, 0x1b, 0x59, 0, 0, 0x0e, 0x10]
+        );
+
+        let mut reply = vec![0, 130, 0, 0, 0, 0, 0, 1];
+        reply.extend_from_slice(&request[4..]);
+        assert_eq!(parse_nat_pmp_map(&reply).unwrap(), (7001, 3600));
+        assert!(parse_nat_pmp_map(&reply[..12]).is_err());
+
+        let address = [0, 128, 0, 0, 0, 0, 0, 1, 198, 51, 100, 1];
+        assert_eq!(
+            parse_nat_pmp_address(&address).unwrap(),
+            Ipv4Addr::new(198, 51, 100, 1)
+        );
+    }
+
+    #[test]
+    fn test_parse_route_table() {
+        let table = "Iface\tDestination\tGateway \tFlags\n\
+                     eth0\t0010A8C0\t00000000\t0001\n\
+                     eth0\t00000000\t0100A8C0\t0003\n";
+        let expected = Ipv4Addr::from(u32::from_str_radix("0100A8C0", 16).unwrap().to_ne_bytes());
+        assert_eq!(parse_route_table(table), Some(expected));
+        assert_eq!(parse_route_table("Iface\n"), None);
+    }
+
+    #[test]
+    fn test_http_helpers() {
+        assert_eq!(
+            dechunk("4\r\nWiki\r\n5;x=y\r\npedia\r\n0\r\n\r\n").unwrap(),
+            "Wikipedia"
+        );
+        assert!(dechunk("9\r\nshort\r\n").is_err());
+
+        assert_eq!(
+            split_url("http://10.0.0.1/desc.xml").unwrap(),
+            ("10.0.0.1:80".to_string(), "/desc.xml".to_string())
+        );
+        assert_eq!(
+            split_url("http://[::1]:5000").unwrap(),
+            ("[::1]:5000".to_string(), "/".to_string())
+        );
+        assert!(split_url("https://10.0.0.1/").is_err());
+        assert_eq!(
+            resolve_url("http://10.0.0.1:5000/desc.xml", "ctl/IPConn"),
+            "http://10.0.0.1:5000/ctl/IPConn"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_upnp_map_renew_remove() {
+        let igd = MockIgd::start(EXTERNAL).await;
+        let lease = Duration::from_secs(600);
+
+        let mut mapping = igd
+            .mapper()
+            .map(PortProtocol::Udp, 7000, lease)
+            .await
+            .unwrap();
+        assert_eq!(mapping.method(), MappingMethod::Upnp);
+        assert_eq!(mapping.external, SocketAddr::new(EXTERNAL, 7000));
+        {
+            let state = igd.state.lock().unwrap();
+            let (client, granted) = state.mappings[&7000];
+            assert_eq!(client.port(), 7000);
+            assert!(client.ip().is_loopback());
+            assert_eq!(granted, lease);
+        }
+
+        mapping.renew(Duration::from_secs(1)).await.unwrap();
+        assert_eq!(igd.state.lock().unwrap().adds, 2);
+
+        mapping.remove(Duration::from_secs(1)).await.unwrap();
+        assert!(igd.state.lock().unwrap().mappings.is_empty());
+        // The device reports a second removal as an error
+        assert!(mapping.remove(Duration::from_secs(1)).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_falls_back_to_nat_pmp() {
+        // Swallows searches without answering
+        let silent = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let mapper = PortMapper::new()
+            .with_ssdp(silent.local_addr().unwrap())
+            .with_nat_pmp(mock_nat_pmp().await)
+            .with_timeout(Duration::from_millis(200));
+
+        let mut mapping = mapper
+            .map(PortProtocol::Tcp, 7000, Duration::from_secs(60))
+            .await
+            .unwrap();
+        assert_eq!(mapping.method(), MappingMethod::NatPmp);
+        assert_eq!(mapping.external, SocketAddr::new(EXTERNAL, 7000));
+        assert_eq!(mapping.lease, Duration::from_secs(60));
+
+        mapping.renew(mapper.timeout()).await.unwrap();
+        mapping.remove(mapper.timeout()).await.unwrap();
+    }
+}
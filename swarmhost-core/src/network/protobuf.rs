@@ -0,0 +1,1151 @@
+// network/protobuf.rs - Protobuf encoding of peer messages
+
+use super::bootstrap::PeerRecord;
+use super::codec::{MessageCodec, malformed};
+use super::fragment::Fragment;
+use super::gossip::{GossipMessage, GossipPayload};
+use super::handshake::CloseCode;
+use super::message::{CommitMark, PeerMessage};
+use super::mux::Stream;
+use super::outbound::Priority;
+use super::pex::{PexEntry, PexSample};
+use super::punch::PunchSignal;
+use super::relay::RelayOffer;
+use super::remote::RemoteError;
+use super::resume::ResumptionToken;
+use super::trace::TraceContext;
+use crate::consensus::{
+    BlockHeader, CertifiedCommits, Commit, Decision, Equivocation, NewView, ObserverVote, Prepared,
+    RejectCode, RoundProposal, SignedAction, TimeoutVote, ViewChange, Vote,
+};
+use crate::crypto::Hash;
+use crate::error::{ErrorCategory, ErrorCode, Result, SwarmhostError};
+use crate::node::WireFormat;
+use crate::state::{
+    Checkpoint, CheckpointSignature, CheckpointVote, SnapshotAttestation, SnapshotBase,
+    SnapshotChunk, SyncPlan,
+};
+use bytes::Bytes;
+use prost::Message;
+use proto::peer_message::Message as Kind;
+
+/// Types generated from `proto/swarmhost.proto`
+#[allow(clippy::all)]
+pub mod proto {
+    include!(concat!(env!("OUT_DIR"), "/swarmhost.v1.rs"));
+}
+
+/// Encoding described by `proto/swarmhost.proto`, for peers not written in
+/// Rust
+pub struct ProtobufCodec;
+
+impl MessageCodec for ProtobufCodec {
+    fn format(&self) -> WireFormat {
+        WireFormat::Protobuf
+    }
+
+    fn encode(&self, message: &PeerMessage) -> Result<Bytes> {
+        Ok(Bytes::from(to_proto(message).encode_to_vec()))
+    }
+
+    fn decode(&self, bytes: &[u8]) -> Result<PeerMessage> {
+        let message = proto::PeerMessage::decode(bytes).map_err(malformed)?;
+        from_proto(message).map_err(malformed)
+    }
+}
+
+fn to_proto(message: &PeerMessage) -> proto::PeerMessage {
+    let kind = match message.clone() {
+        PeerMessage::Ping {
+            nonce,
+            sent_at_ms,
+            mark,
+        } => Kind::Ping(proto::Heartbeat {
+            nonce,
+            sent_at_ms,
+            mark: mark.map(mark_to_proto),
+        }),
+        PeerMessage::Pong {
+            nonce,
+            sent_at_ms,
+            mark,
+        } => Kind::Pong(proto::Heartbeat {
+            nonce,
+            sent_at_ms,
+            mark: mark.map(mark_to_proto),
+        }),
+        PeerMessage::Gossip(gossip) => Kind::Gossip(gossip_to_proto(gossip)),
+        PeerMessage::Signal { to, signal } => Kind::Signal(proto::Signal {
+            peer: to.to_vec(),
+            signal: Some(signal_to_proto(signal)),
+        }),
+        PeerMessage::Signaled { from, signal } => Kind::Signaled(proto::Signal {
+            peer: from.to_vec(),
+            signal: Some(signal_to_proto(signal)),
+        }),
+        PeerMessage::RelayOffer(offer) => Kind::RelayOffer(proto::RelayOffer {
+            max_sessions: offer.max_sessions,
+            session_bandwidth: offer.session_bandwidth,
+        }),
+        PeerMessage::RelayOpen { session, to } => Kind::RelayOpen(proto::RelaySession {
+            session,
+            peer: to.to_vec(),
+        }),
+        PeerMessage::RelayIncoming { session, from } => Kind::RelayIncoming(proto::RelaySession {
+            session,
+            peer: from.to_vec(),
+        }),
+        PeerMessage::RelayData { session, payload } => {
+            Kind::RelayData(proto::RelayData { session, payload })
+        }
+        PeerMessage::RelayClose { session } => Kind::RelayClose(proto::RelayClose { session }),
+        PeerMessage::Fragment(fragment) => Kind::Fragment(proto::Fragment {
+            transfer: fragment.transfer,
+            index: fragment.index,
+            total: fragment.total,
+            checksum: fragment.checksum,
+            data: fragment.data,
+        }),
+        PeerMessage::FragmentRequest { transfer, missing } => {
+            Kind::FragmentRequest(proto::FragmentRequest { transfer, missing })
+        }
+        PeerMessage::Playing { game_id, committed } => {
+            Kind::Playing(proto::Playing { game_id, committed })
+        }
+        PeerMessage::Direct { class, payload } => Kind::Direct(proto::Direct {
+            class: class_to_proto(class).into(),
+            payload,
+        }),
+        PeerMessage::Broadcast {
+            game_id,
+            class,
+            payload,
+        } => Kind::Broadcast(proto::Broadcast {
+            game_id,
+            class: class_to_proto(class).into(),
+            payload,
+        }),
+        PeerMessage::Request { id, payload } => Kind::Request(proto::Exchange { id, payload }),
+        PeerMessage::Response { id, payload } => Kind::Response(proto::Exchange { id, payload }),
+        PeerMessage::DhtHello { addr } => Kind::DhtHello(proto::DhtHello {
+            addr: addr.to_string(),
+        }),
+        PeerMessage::DhtFindNode { id, target } => Kind::DhtFindNode(proto::DhtQuery {
+            id,
+            key: target.to_vec(),
+        }),
+        PeerMessage::DhtFindProviders { id, key } => Kind::DhtFindProviders(proto::DhtQuery {
+            id,
+            key: key.to_vec(),
+        }),
+        PeerMessage::DhtFound {
+            id,
+            closer,
+            providers,
+        } => Kind::DhtFound(proto::DhtFound {
+            id,
+            closer: closer.into_iter().map(contact_to_proto).collect(),
+            providers: providers.into_iter().map(contact_to_proto).collect(),
+        }),
+        PeerMessage::DhtProvide { key, addr } => Kind::DhtProvide(proto::DhtProvide {
+            key: key.to_vec(),
+            addr: addr.to_string(),
+        }),
+        PeerMessage::Batch(packed) => Kind::Batch(proto::Batch { packed }),
+        PeerMessage::Pex(sample) => Kind::Pex(proto::PexSample {
+            from: sample.from.to_vec(),
+            addrs: addrs_to_proto(&sample.addrs),
+            entries: sample
+                .entries
+                .into_iter()
+                .map(|entry| proto::PexEntry {
+                    player_id: entry.player_id.to_vec(),
+                    addrs: addrs_to_proto(&entry.addrs),
+                    age_ms: entry.age_ms,
+                })
+                .collect(),
+            signature: sample.signature,
+        }),
+        PeerMessage::GoAway { code } => Kind::GoAway(proto::GoAway {
+            code: code.as_u16() as u32,
+        }),
+        PeerMessage::GoAwayAck => Kind::GoAwayAck(proto::GoAwayAck {}),
+        PeerMessage::Resume(token) => Kind::Resume(proto::Resume {
+            id: token.id.to_vec(),
+            mac: token.mac.to_vec(),
+        }),
+        PeerMessage::Credit { stream, bytes } => Kind::Credit(proto::Credit {
+            stream: stream.id() as u32,
+            bytes,
+        }),
+        PeerMessage::Traced { trace, message } => Kind::Traced(proto::Traced {
+            correlation: trace.correlation,
+            action: trace
+                .action
+                .map(|action| action.to_vec())
+                .unwrap_or_default(),
+            message: to_proto(&message).encode_to_vec(),
+        }),
+        PeerMessage::FetchCommits {
+            game_id,
+            from,
+            count,
+        } => Kind::FetchCommits(proto::FetchCommits {
+            game_id,
+            from,
+            count,
+        }),
+        PeerMessage::Commits(commits) => Kind::Commits(proto::Commits {
+            commits: commits.into_iter().map(commit_to_proto).collect(),
+        }),
+        PeerMessage::Forward(action) => Kind::Forward(action_to_proto(action)),
+        PeerMessage::FetchSyncPlan { id, game_id, base } => {
+            Kind::FetchSyncPlan(proto::FetchSyncPlan {
+                id,
+                game_id,
+                base: base.map(|base| proto::SnapshotBase {
+                    sequence: base.sequence,
+                    checksum: base.checksum.to_vec(),
+                }),
+            })
+        }
+        PeerMessage::SyncPlan { id, plan } => Kind::SyncPlan(proto::SyncPlan {
+            id,
+            game_id: plan.game_id,
+            sequence: plan.sequence,
+            state_hash: plan.state_hash.to_vec(),
+            size: plan.size,
+            chunk_size: plan.chunk_size,
+            checksums: plan.checksums.iter().map(|hash| hash.to_vec()).collect(),
+            delta_from: plan.delta_from,
+            attestation: plan.attestation.map(attestation_to_proto),
+        }),
+        PeerMessage::FetchSnapshot {
+            id,
+            game_id,
+            sequence,
+            delta_from,
+            chunk,
+        } => Kind::FetchSnapshot(proto::FetchSnapshot {
+            id,
+            game_id,
+            chunk,
+            sequence,
+            delta_from,
+        }),
+        PeerMessage::Snapshot { id, chunk } => Kind::Snapshot(proto::Snapshot {
+            id,
+            chunk: chunk.map(|chunk| proto::SnapshotChunk {
+                game_id: chunk.game_id,
+                sequence: chunk.sequence,
+                state_hash: chunk.state_hash.to_vec(),
+                index: chunk.index,
+                offset: chunk.offset,
+                checksum: chunk.checksum.to_vec(),
+                data: chunk.data,
+            }),
+        }),
+        PeerMessage::FetchCertified {
+            id,
+            game_id,
+            from,
+            count,
+        } => Kind::FetchCertified(proto::FetchCertified {
+            id,
+            game_id,
+            from,
+            count,
+        }),
+        PeerMessage::Certified { id, commits } => Kind::Certified(proto::Certified {
+            id,
+            commits: commits.commits.into_iter().map(commit_to_proto).collect(),
+            blocks: commits
+                .blocks
+                .into_iter()
+                .map(|block| proto::BlockHeader {
+                    round: block.round,
+                    proposer: block.proposer.to_vec(),
+                    actions: block.actions.iter().map(|id| id.to_vec()).collect(),
+                    parent: parent_to_proto(block.parent),
+                })
+                .collect(),
+            votes: commits.votes.into_iter().map(vote_to_proto).collect(),
+        }),
+        PeerMessage::GetVotes { round, have } => Kind::GetVotes(proto::GetVotes { round, have }),
+        PeerMessage::Votes(votes) => Kind::Votes(proto::Votes {
+            votes: votes.into_iter().map(vote_to_proto).collect(),
+        }),
+        PeerMessage::FetchStateHash {
+            id,
+            game_id,
+            sequence,
+        } => Kind::FetchStateHash(proto::FetchStateHash {
+            id,
+            game_id,
+            sequence,
+        }),
+        PeerMessage::StateHash { id, state_hash } => Kind::StateHash(proto::StateHash {
+            id,
+            state_hash: state_hash.map(|hash| hash.to_vec()),
+        }),
+        PeerMessage::Busy { retry_after_ms } => Kind::Busy(proto::Busy { retry_after_ms }),
+        PeerMessage::Error { id, error } => Kind::Error(proto::Error {
+            id,
+            error: Some(proto::RemoteError {
+                code: error.code.as_u16().into(),
+                category: error.category.as_u8().into(),
+                message: error.message,
+                action_id: error.action_id.map(|id| id.to_vec()),
+                game_id: error.game_id,
+            }),
+        }),
+    };
+    proto::PeerMessage {
+        message: Some(kind),
+    }
+}
+
+fn gossip_to_proto(gossip: GossipMessage) -> proto::Gossip {
+    let payload = match gossip.payload {
+        GossipPayload::Proposal(action) => {
+            proto::gossip::Payload::Proposal(action_to_proto(action))
+        }
+        GossipPayload::Vote(vote) => proto::gossip::Payload::Vote(vote_to_proto(vote)),
+        GossipPayload::Commit(commit) => proto::gossip::Payload::Commit(commit_to_proto(commit)),
+        GossipPayload::Round(proposal) => proto::gossip::Payload::Round(round_to_proto(proposal)),
+        GossipPayload::Timeout(vote) => proto::gossip::Payload::Timeout(proto::TimeoutVote {
+            round: vote.round,
+            voter: vote.voter.to_vec(),
+            signature: vote.signature,
+        }),
+        GossipPayload::ViewChange(change) => {
+            proto::gossip::Payload::ViewChange(view_change_to_proto(change))
+        }
+        GossipPayload::NewView(new_view) => proto::gossip::Payload::NewView(proto::NewView {
+            view: new_view.view,
+            leader: new_view.leader.to_vec(),
+            view_changes: new_view
+                .view_changes
+                .into_iter()
+                .map(view_change_to_proto)
+                .collect(),
+            commits: new_view.commits.into_iter().map(commit_to_proto).collect(),
+            signature: new_view.signature,
+        }),
+        GossipPayload::Evidence(evidence) => {
+            use proto::equivocation::{Commits, Kind, Rounds, Votes};
+            let kind = match *evidence {
+                Equivocation::Votes(first, second) => Kind::Votes(Votes {
+                    first: Some(vote_to_proto(first)),
+                    second: Some(vote_to_proto(second)),
+                }),
+                Equivocation::Rounds(first, second) => Kind::Rounds(Rounds {
+                    first: Some(round_to_proto(first)),
+                    second: Some(round_to_proto(second)),
+                }),
+                Equivocation::Commits(first, second) => Kind::Commits(Commits {
+                    first: Some(commit_to_proto(*first)),
+                    second: Some(commit_to_proto(*second)),
+                }),
+            };
+            proto::gossip::Payload::Evidence(proto::Equivocation { kind: Some(kind) })
+        }
+        GossipPayload::CheckpointVote(vote) => {
+            proto::gossip::Payload::CheckpointVote(proto::CheckpointVote {
+                game_id: vote.game_id,
+                sequence: vote.sequence,
+                state_hash: vote.state_hash.to_vec(),
+                log_root: vote.log_root.to_vec(),
+                signer: vote.signer.to_vec(),
+                signature: vote.signature,
+            })
+        }
+        GossipPayload::Checkpoint(checkpoint) => {
+            proto::gossip::Payload::Checkpoint(checkpoint_to_proto(checkpoint))
+        }
+        GossipPayload::Unsequenced(action) => {
+            proto::gossip::Payload::Unsequenced(action_to_proto(action))
+        }
+        GossipPayload::ObserverVote(vote) => proto::gossip::Payload::ObserverVote(proto::Vote {
+            action_id: vote.action_id.to_vec(),
+            voter: vote.observer.to_vec(),
+            approve: vote.approves(),
+            signature: vote.signature,
+            round: vote.round,
+            reject_code: vote.reason.map_or(0, |RejectCode(code)| code),
+        }),
+    };
+    proto::Gossip {
+        hops_left: gossip.hops_left.into(),
+        payload: Some(payload),
+    }
+}
+
+fn mark_to_proto(mark: CommitMark) -> proto::CommitMark {
+    proto::CommitMark {
+        game_id: mark.game_id,
+        sequence: mark.sequence,
+        action_id: mark.action_id.to_vec(),
+        state_hash: mark.state_hash.map(|hash| hash.to_vec()),
+    }
+}
+
+fn checkpoint_to_proto(checkpoint: Checkpoint) -> proto::Checkpoint {
+    proto::Checkpoint {
+        game_id: checkpoint.game_id,
+        sequence: checkpoint.sequence,
+        state_hash: checkpoint.state_hash.to_vec(),
+        log_root: checkpoint.log_root.to_vec(),
+        signatures: checkpoint
+            .signatures
+            .into_iter()
+            .map(|signed| proto::CheckpointSignature {
+                signer: signed.signer.to_vec(),
+                signature: signed.signature,
+            })
+            .collect(),
+    }
+}
+
+fn attestation_to_proto(attestation: SnapshotAttestation) -> proto::SnapshotAttestation {
+    proto::SnapshotAttestation {
+        checkpoint: Some(checkpoint_to_proto(attestation.checkpoint)),
+        producer: attestation.producer.to_vec(),
+        signature: attestation.signature,
+    }
+}
+
+fn action_to_proto(action: SignedAction) -> proto::ActionProposal {
+    proto::ActionProposal {
+        game_id: action.game_id,
+        actor: action.actor.to_vec(),
+        nonce: action.nonce,
+        action_type: action.action_type,
+        data: action.data,
+        signature: action.signature,
+        conflict_keys: action.conflict_keys,
+        deadline_ms: action.deadline_ms,
+        depends_on: action.depends_on.iter().map(|id| id.to_vec()).collect(),
+    }
+}
+
+fn vote_to_proto(vote: Vote) -> proto::Vote {
+    proto::Vote {
+        action_id: vote.action_id.to_vec(),
+        voter: vote.voter.to_vec(),
+        approve: vote.approves(),
+        signature: vote.signature,
+        round: vote.round,
+        reject_code: vote.reason.map_or(0, |RejectCode(code)| code),
+    }
+}
+
+fn round_to_proto(proposal: RoundProposal) -> proto::RoundProposal {
+    proto::RoundProposal {
+        round: proposal.round,
+        proposer: proposal.proposer.to_vec(),
+        actions: proposal.actions.into_iter().map(action_to_proto).collect(),
+        signature: proposal.signature,
+        parent: parent_to_proto(proposal.parent),
+    }
+}
+
+/// A block's parent hash, empty for none
+fn parent_to_proto(parent: Option<Hash>) -> Vec<u8> {
+    parent.map(|parent| parent.to_vec()).unwrap_or_default()
+}
+
+fn view_change_to_proto(change: ViewChange) -> proto::ViewChange {
+    proto::ViewChange {
+        view: change.view,
+        voter: change.voter.to_vec(),
+        committed: change
+            .committed
+            .into_iter()
+            .map(|(game_id, sequence)| proto::Committed { game_id, sequence })
+            .collect(),
+        prepared: change
+            .prepared
+            .into_iter()
+            .map(|prepared| proto::Prepared {
+                action: Some(action_to_proto(prepared.action)),
+                votes: prepared.votes.into_iter().map(vote_to_proto).collect(),
+                commit: prepared.commit.map(commit_to_proto),
+            })
+            .collect(),
+        signature: change.signature,
+    }
+}
+
+fn commit_to_proto(commit: Commit) -> proto::Commit {
+    proto::Commit {
+        sequence: commit.sequence,
+        action: Some(action_to_proto(commit.action)),
+        sequencer: commit.sequencer.to_vec(),
+        signature: commit.signature,
+        certificate: commit.certificate.into_iter().map(vote_to_proto).collect(),
+    }
+}
+
+fn signal_to_proto(signal: PunchSignal) -> proto::PunchSignal {
+    use proto::punch_signal::{Kind, Probe, Unreachable};
+
+    let kind = match signal {
+        PunchSignal::Offer { nonce, addr } => Kind::Offer(Probe {
+            nonce,
+            addr: addr.to_string(),
+        }),
+        PunchSignal::Answer { nonce, addr } => Kind::Answer(Probe {
+            nonce,
+            addr: addr.to_string(),
+        }),
+        PunchSignal::Unreachable { nonce } => Kind::Unreachable(Unreachable { nonce }),
+    };
+    proto::PunchSignal { kind: Some(kind) }
+}
+
+fn contact_to_proto(contact: PeerRecord) -> proto::Contact {
+    proto::Contact {
+        player_id: contact.player_id.to_vec(),
+        addrs: addrs_to_proto(&contact.addrs),
+    }
+}
+
+fn addrs_to_proto(addrs: &[std::net::SocketAddr]) -> Vec<String> {
+    addrs.iter().map(|addr| addr.to_string()).collect()
+}
+
+fn class_to_proto(class: Priority) -> proto::TrafficClass {
+    match class {
+        Priority::Control => proto::TrafficClass::Control,
+        Priority::GameAction => proto::TrafficClass::GameAction,
+        Priority::Bulk => proto::TrafficClass::Bulk,
+    }
+}
+
+fn from_proto(message: proto::PeerMessage) -> Result<PeerMessage> {
+    Ok(match required(message.message, "message")? {
+        Kind::Ping(ping) => PeerMessage::Ping {
+            nonce: ping.nonce,
+            sent_at_ms: ping.sent_at_ms,
+            mark: ping.mark.map(mark_from_proto).transpose()?,
+        },
+        Kind::Pong(pong) => PeerMessage::Pong {
+            nonce: pong.nonce,
+            sent_at_ms: pong.sent_at_ms,
+            mark: pong.mark.map(mark_from_proto).transpose()?,
+        },
+        Kind::Gossip(gossip) => PeerMessage::Gossip(gossip_from_proto(gossip)?),
+        Kind::Signal(signal) => PeerMessage::Signal {
+            to: id(&signal.peer, "peer")?,
+            signal: signal_from_proto(required(signal.signal, "signal")?)?,
+        },
+        Kind::Signaled(signal) => PeerMessage::Signaled {
+            from: id(&signal.peer, "peer")?,
+            signal: signal_from_proto(required(signal.signal, "signal")?)?,
+        },
+        Kind::RelayOffer(offer) => PeerMessage::RelayOffer(RelayOffer {
+            max_sessions: offer.max_sessions,
+            session_bandwidth: offer.session_bandwidth,
+        }),
+        Kind::RelayOpen(open) => PeerMessage::RelayOpen {
+            session: open.session,
+            to: id(&open.peer, "peer")?,
+        },
+        Kind::RelayIncoming(incoming) => PeerMessage::RelayIncoming {
+            session: incoming.session,
+            from: id(&incoming.peer, "peer")?,
+        },
+        Kind::RelayData(data) => PeerMessage::RelayData {
+            session: data.session,
+            payload: data.payload,
+        },
+        Kind::RelayClose(close) => PeerMessage::RelayClose {
+            session: close.session,
+        },
+        Kind::Fragment(fragment) => PeerMessage::Fragment(Fragment {
+            transfer: fragment.transfer,
+            index: fragment.index,
+            total: fragment.total,
+            checksum: fragment.checksum,
+            data: fragment.data,
+        }),
+        Kind::FragmentRequest(request) => PeerMessage::FragmentRequest {
+            transfer: request.transfer,
+            missing: request.missing,
+        },
+        Kind::Playing(playing) => PeerMessage::Playing {
+            game_id: playing.game_id,
+            committed: playing.committed,
+        },
+        Kind::Direct(direct) => PeerMessage::Direct {
+            class: class_from_proto(direct.class)?,
+            payload: direct.payload,
+        },
+        Kind::Broadcast(broadcast) => PeerMessage::Broadcast {
+            game_id: broadcast.game_id,
+            class: class_from_proto(broadcast.class)?,
+            payload: broadcast.payload,
+        },
+        Kind::Request(request) => PeerMessage::Request {
+            id: request.id,
+            payload: request.payload,
+        },
+        Kind::Response(response) => PeerMessage::Response {
+            id: response.id,
+            payload: response.payload,
+        },
+        Kind::DhtHello(hello) => PeerMessage::DhtHello {
+            addr: parse_addr(&hello.addr)?,
+        },
+        Kind::DhtFindNode(query) => PeerMessage::DhtFindNode {
+            id: query.id,
+            target: id(&query.key, "key")?,
+        },
+        Kind::DhtFindProviders(query) => PeerMessage::DhtFindProviders {
+            id: query.id,
+            key: id(&query.key, "key")?,
+        },
+        Kind::DhtFound(found) => PeerMessage::DhtFound {
+            id: found.id,
+            closer: contacts_from_proto(found.closer)?,
+            providers: contacts_from_proto(found.providers)?,
+        },
+        Kind::DhtProvide(provide) => PeerMessage::DhtProvide {
+            key: id(&provide.key, "key")?,
+            addr: parse_addr(&provide.addr)?,
+        },
+        Kind::Batch(batch) => PeerMessage::Batch(batch.packed),
+        Kind::Pex(sample) => PeerMessage::Pex(PexSample {
+            from: id(&sample.from, "from")?,
+            addrs: parse_addrs(&sample.addrs)?,
+            entries: sample
+                .entries
+                .into_iter()
+                .map(|entry| {
+                    Ok(PexEntry {
+                        player_id: id(&entry.player_id, "player_id")?,
+                        addrs: parse_addrs(&entry.addrs)?,
+                        age_ms: entry.age_ms,
+                    })
+                })
+                .collect::<Result<_>>()?,
+            signature: sample.signature,
+        }),
+        Kind::GoAway(go_away) => PeerMessage::GoAway {
+            code: u16::try_from(go_away.code)
+                .ok()
+                .and_then(CloseCode::from_u16)
+                .ok_or_else(|| invalid(format!("unknown close code {}", go_away.code)))?,
+        },
+        Kind::GoAwayAck(_) => PeerMessage::GoAwayAck,
+        Kind::Resume(resume) => {
+            PeerMessage::Resume(ResumptionToken {
+                id: resume.id.as_slice().try_into().map_err(|_| {
+                    invalid(format!("id must be 16 bytes, got {}", resume.id.len()))
+                })?,
+                mac: id(&resume.mac, "mac")?,
+            })
+        }
+        Kind::Credit(credit) => PeerMessage::Credit {
+            stream: u8::try_from(credit.stream)
+                .ok()
+                .and_then(Stream::from_id)
+                .ok_or_else(|| invalid(format!("unknown stream {}", credit.stream)))?,
+            bytes: credit.bytes,
+        },
+        Kind::Traced(traced) => {
+            let message = proto::PeerMessage::decode(traced.message.as_slice())
+                .map_err(|e| invalid(format!("traced message: {}", e)))?;
+            PeerMessage::Traced {
+                trace: TraceContext {
+                    correlation: traced.correlation,
+                    action: match traced.action.as_slice() {
+                        [] => None,
+                        action => Some(id(action, "action")?),
+                    },
+                },
+                message: Box::new(from_proto(message)?),
+            }
+        }
+        Kind::FetchCommits(fetch) => PeerMessage::FetchCommits {
+            game_id: fetch.game_id,
+            from: fetch.from,
+            count: fetch.count,
+        },
+        Kind::Commits(commits) => PeerMessage::Commits(
+            commits
+                .commits
+                .into_iter()
+                .map(commit_from_proto)
+                .collect::<Result<_>>()?,
+        ),
+        Kind::Forward(action) => PeerMessage::Forward(action_from_proto(action)?),
+        Kind::FetchSyncPlan(fetch) => PeerMessage::FetchSyncPlan {
+            id: fetch.id,
+            game_id: fetch.game_id,
+            base: fetch.base.map(base_from_proto).transpose()?,
+        },
+        Kind::SyncPlan(plan) => PeerMessage::SyncPlan {
+            id: plan.id,
+            plan: SyncPlan {
+                game_id: plan.game_id,
+                sequence: plan.sequence,
+                state_hash: id(&plan.state_hash, "state_hash")?,
+                size: plan.size,
+                chunk_size: plan.chunk_size,
+                checksums: plan
+                    .checksums
+                    .iter()
+                    .map(|checksum| id(checksum, "checksum"))
+                    .collect::<Result<_>>()?,
+                attestation: plan.attestation.map(attestation_from_proto).transpose()?,
+                delta_from: plan.delta_from,
+            },
+        },
+        Kind::FetchSnapshot(fetch) => PeerMessage::FetchSnapshot {
+            id: fetch.id,
+            game_id: fetch.game_id,
+            sequence: fetch.sequence,
+            delta_from: fetch.delta_from,
+            chunk: fetch.chunk,
+        },
+        Kind::Snapshot(answer) => PeerMessage::Snapshot {
+            id: answer.id,
+            chunk: answer.chunk.map(chunk_from_proto).transpose()?,
+        },
+        Kind::FetchCertified(fetch) => PeerMessage::FetchCertified {
+            id: fetch.id,
+            game_id: fetch.game_id,
+            from: fetch.from,
+            count: fetch.count,
+        },
+        Kind::Certified(certified) => PeerMessage::Certified {
+            id: certified.id,
+            commits: CertifiedCommits {
+                commits: certified
+                    .commits
+                    .into_iter()
+                    .map(commit_from_proto)
+                    .collect::<Result<_>>()?,
+                blocks: certified
+                    .blocks
+                    .into_iter()
+                    .map(|block| {
+                        Ok(BlockHeader {
+                            round: block.round,
+                            proposer: id(&block.proposer, "proposer")?,
+                            parent: parent_from_proto(&block.parent)?,
+                            actions: block
+                                .actions
+                                .iter()
+                                .map(|action| id(action, "action"))
+                                .collect::<Result<_>>()?,
+                        })
+                    })
+                    .collect::<Result<_>>()?,
+                votes: certified
+                    .votes
+                    .into_iter()
+                    .map(vote_from_proto)
+                    .collect::<Result<_>>()?,
+            },
+        },
+        Kind::GetVotes(get) => PeerMessage::GetVotes {
+            round: get.round,
+            have: get.have,
+        },
+        Kind::Votes(votes) => PeerMessage::Votes(
+            votes
+                .votes
+                .into_iter()
+                .map(vote_from_proto)
+                .collect::<Result<_>>()?,
+        ),
+        Kind::FetchStateHash(fetch) => PeerMessage::FetchStateHash {
+            id: fetch.id,
+            game_id: fetch.game_id,
+            sequence: fetch.sequence,
+        },
+        Kind::StateHash(answer) => PeerMessage::StateHash {
+            id: answer.id,
+            state_hash: answer
+                .state_hash
+                .map(|hash| id(&hash, "state_hash"))
+                .transpose()?,
+        },
+        Kind::Busy(busy) => PeerMessage::Busy {
+            retry_after_ms: busy.retry_after_ms,
+        },
+        Kind::Error(failed) => {
+            let error = required(failed.error, "error")?;
+            PeerMessage::Error {
+                id: failed.id,
+                error: RemoteError {
+                    code: u16::try_from(error.code)
+                        .ok()
+                        .and_then(ErrorCode::from_u16)
+                        .ok_or_else(|| invalid(format!("unknown error code {}", error.code)))?,
+                    category: u8::try_from(error.category)
+                        .ok()
+                        .and_then(ErrorCategory::from_u8)
+                        .ok_or_else(|| {
+                            invalid(format!("unknown error category {}", error.category))
+                        })?,
+                    message: error.message,
+                    action_id: error
+                        .action_id
+                        .map(|action_id| id(&action_id, "action_id"))
+                        .transpose()?,
+                    game_id: error.game_id,
+                },
+            }
+        }
+    })
+}
+
+/// Unknown enum values decode as plain integers; refuse them
+fn class_from_proto(class: i32) -> Result<Priority> {
+    match proto::TrafficClass::try_from(class) {
+        Ok(proto::TrafficClass::Control) => Ok(Priority::Control),
+        Ok(proto::TrafficClass::GameAction) => Ok(Priority::GameAction),
+        Ok(proto::TrafficClass::Bulk) => Ok(Priority::Bulk),
+        Err(_) => Err(invalid(format!("unknown traffic class {}", class))),
+    }
+}
+
+fn gossip_from_proto(gossip: proto::Gossip) -> Result<GossipMessage> {
+    let hops_left = u8::try_from(gossip.hops_left)
+        .map_err(|_| invalid(format!("hops_left {} out of range", gossip.hops_left)))?;
+    let payload = match required(gossip.payload, "payload")? {
+        proto::gossip::Payload::Proposal(action) => {
+            GossipPayload::Proposal(action_from_proto(action)?)
+        }
+        proto::gossip::Payload::Vote(vote) => GossipPayload::Vote(vote_from_proto(vote)?),
+        proto::gossip::Payload::Commit(commit) => GossipPayload::Commit(commit_from_proto(commit)?),
+        proto::gossip::Payload::Round(proposal) => {
+            GossipPayload::Round(round_from_proto(proposal)?)
+        }
+        proto::gossip::Payload::Timeout(vote) => GossipPayload::Timeout(TimeoutVote {
+            round: vote.round,
+            voter: id(&vote.voter, "voter")?,
+            signature: vote.signature,
+        }),
+        proto::gossip::Payload::ViewChange(change) => {
+            GossipPayload::ViewChange(view_change_from_proto(change)?)
+        }
+        proto::gossip::Payload::NewView(new_view) => GossipPayload::NewView(NewView {
+            view: new_view.view,
+            leader: id(&new_view.leader, "leader")?,
+            view_changes: new_view
+                .view_changes
+                .into_iter()
+                .map(view_change_from_proto)
+                .collect::<Result<_>>()?,
+            commits: new_view
+                .commits
+                .into_iter()
+                .map(commit_from_proto)
+                .collect::<Result<_>>()?,
+            signature: new_view.signature,
+        }),
+        proto::gossip::Payload::Evidence(evidence) => {
+            use proto::equivocation::Kind;
+            GossipPayload::Evidence(Box::new(match required(evidence.kind, "kind")? {
+                Kind::Votes(votes) => Equivocation::Votes(
+                    vote_from_proto(required(votes.first, "first")?)?,
+                    vote_from_proto(required(votes.second, "second")?)?,
+                ),
+                Kind::Rounds(rounds) => Equivocation::Rounds(
+                    round_from_proto(required(rounds.first, "first")?)?,
+                    round_from_proto(required(rounds.second, "second")?)?,
+                ),
+                Kind::Commits(commits) => Equivocation::Commits(
+                    Box::new(commit_from_proto(required(commits.first, "first")?)?),
+                    Box::new(commit_from_proto(required(commits.second, "second")?)?),
+                ),
+            }))
+        }
+        proto::gossip::Payload::CheckpointVote(vote) => {
+            GossipPayload::CheckpointVote(CheckpointVote {
+                game_id: vote.game_id,
+                sequence: vote.sequence,
+                state_hash: id(&vote.state_hash, "state_hash")?,
+                log_root: id(&vote.log_root, "log_root")?,
+                signer: id(&vote.signer, "signer")?,
+                signature: vote.signature,
+            })
+        }
+        proto::gossip::Payload::Checkpoint(checkpoint) => {
+            GossipPayload::Checkpoint(checkpoint_from_proto(checkpoint)?)
+        }
+        proto::gossip::Payload::Unsequenced(action) => {
+            GossipPayload::Unsequenced(action_from_proto(action)?)
+        }
+        proto::gossip::Payload::ObserverVote(vote) => {
+            let vote = vote_from_proto(vote)?;
+            GossipPayload::ObserverVote(ObserverVote {
+                action_id: vote.action_id,
+                round: vote.round,
+                observer: vote.voter,
+                decision: vote.decision,
+                reason: vote.reason,
+                signature: vote.signature,
+            })
+        }
+    };
+    Ok(GossipMessage { hops_left, payload })
+}
+
+fn mark_from_proto(mark: proto::CommitMark) -> Result<CommitMark> {
+    Ok(CommitMark {
+        game_id: mark.game_id,
+        sequence: mark.sequence,
+        action_id: id(&mark.action_id, "action_id")?,
+        state_hash: mark
+            .state_hash
+            .map(|hash| id(&hash, "state_hash"))
+            .transpose()?,
+    })
+}
+
+fn chunk_from_proto(chunk: proto::SnapshotChunk) -> Result<SnapshotChunk> {
+    Ok(SnapshotChunk {
+        game_id: chunk.game_id,
+        sequence: chunk.sequence,
+        state_hash: id(&chunk.state_hash, "state_hash")?,
+        index: chunk.index,
+        offset: chunk.offset,
+        checksum: id(&chunk.checksum, "checksum")?,
+        data: chunk.data,
+    })
+}
+
+fn base_from_proto(base: proto::SnapshotBase) -> Result<SnapshotBase> {
+    Ok(SnapshotBase {
+        sequence: base.sequence,
+        checksum: id(&base.checksum, "checksum")?,
+    })
+}
+
+fn checkpoint_from_proto(checkpoint: proto::Checkpoint) -> Result<Checkpoint> {
+    Ok(Checkpoint {
+        game_id: checkpoint.game_id,
+        sequence: checkpoint.sequence,
+        state_hash: id(&checkpoint.state_hash, "state_hash")?,
+        log_root: id(&checkpoint.log_root, "log_root")?,
+        signatures: checkpoint
+            .signatures
+            .into_iter()
+            .map(|signed| {
+                Ok(CheckpointSignature {
+                    signer: id(&signed.signer, "signer")?,
+                    signature: signed.signature,
+                })
+            })
+            .collect::<Result<_>>()?,
+    })
+}
+
+fn attestation_from_proto(attestation: proto::SnapshotAttestation) -> Result<SnapshotAttestation> {
+    Ok(SnapshotAttestation {
+        checkpoint: checkpoint_from_proto(required(attestation.checkpoint, "checkpoint")?)?,
+        producer: id(&attestation.producer, "producer")?,
+        signature: attestation.signature,
+    })
+}
+
+fn action_from_proto(action: proto::ActionProposal) -> Result<SignedAction> {
+    Ok(SignedAction {
+        game_id: action.game_id,
+        actor: id(&action.actor, "actor")?,
+        nonce: action.nonce,
+        action_type: action.action_type,
+        data: action.data,
+        conflict_keys: action.conflict_keys,
+        deadline_ms: action.deadline_ms,
+        depends_on: action
+            .depends_on
+            .iter()
+            .map(|dependency| id(dependency, "depends_on"))
+            .collect::<Result<_>>()?,
+        signature: action.signature,
+    })
+}
+
+fn vote_from_proto(vote: proto::Vote) -> Result<Vote> {
+    Ok(Vote {
+        action_id: id(&vote.action_id, "action_id")?,
+        round: vote.round,
+        voter: id(&vote.voter, "voter")?,
+        decision: if vote.approve {
+            Decision::Approve
+        } else {
+            Decision::Reject
+        },
+        reason: reject_code(vote.approve, vote.reject_code)?,
+        signature: vote.signature,
+    })
+}
+
+fn round_from_proto(proposal: proto::RoundProposal) -> Result<RoundProposal> {
+    Ok(RoundProposal {
+        round: proposal.round,
+        proposer: id(&proposal.proposer, "proposer")?,
+        parent: parent_from_proto(&proposal.parent)?,
+        actions: proposal
+            .actions
+            .into_iter()
+            .map(action_from_proto)
+            .collect::<Result<_>>()?,
+        signature: proposal.signature,
+    })
+}
+
+fn view_change_from_proto(change: proto::ViewChange) -> Result<ViewChange> {
+    Ok(ViewChange {
+        view: change.view,
+        voter: id(&change.voter, "voter")?,
+        committed: change
+            .committed
+            .into_iter()
+            .map(|committed| (committed.game_id, committed.sequence))
+            .collect(),
+        prepared: change
+            .prepared
+            .into_iter()
+            .map(|prepared| {
+                Ok(Prepared {
+                    action: action_from_proto(required(prepared.action, "action")?)?,
+                    votes: prepared
+                        .votes
+                        .into_iter()
+                        .map(vote_from_proto)
+                        .collect::<Result<_>>()?,
+                    commit: prepared.commit.map(commit_from_proto).transpose()?,
+                })
+            })
+            .collect::<Result<_>>()?,
+        signature: change.signature,
+    })
+}
+
+fn commit_from_proto(commit: proto::Commit) -> Result<Commit> {
+    Ok(Commit {
+        sequence: commit.sequence,
+        action: action_from_proto(required(commit.action, "action")?)?,
+        sequencer: id(&commit.sequencer, "sequencer")?,
+        signature: commit.signature,
+        certificate: commit
+            .certificate
+            .into_iter()
+            .map(vote_from_proto)
+            .collect::<Result<_>>()?,
+    })
+}
+
+fn signal_from_proto(signal: proto::PunchSignal) -> Result<PunchSignal> {
+    use proto::punch_signal::Kind;
+
+    Ok(match required(signal.kind, "kind")? {
+        Kind::Offer(probe) => PunchSignal::Offer {
+            nonce: probe.nonce,
+            addr: parse_addr(&probe.addr)?,
+        },
+        Kind::Answer(probe) => PunchSignal::Answer {
+            nonce: probe.nonce,
+            addr: parse_addr(&probe.addr)?,
+        },
+        Kind::Unreachable(unreachable) => PunchSignal::Unreachable {
+            nonce: unreachable.nonce,
+        },
+    })
+}
+
+fn contacts_from_proto(contacts: Vec<proto::Contact>) -> Result<Vec<PeerRecord>> {
+    contacts
+        .into_iter()
+        .map(|contact| {
+            Ok(PeerRecord {
+                player_id: id(&contact.player_id, "player_id")?,
+                addrs: parse_addrs(&contact.addrs)?,
+            })
+        })
+        .collect()
+}
+
+/// A player id, action id or hash, which must be exactly 32 bytes
+fn id(bytes: &[u8], field: &str) -> Result<[u8; 32]> {
+    bytes
+        .try_into()
+        .map_err(|_| invalid(format!("{} must be 32 bytes, got {}", field, bytes.len())))
+}
+
+/// A block's parent hash, which is empty for none or 32 bytes
+fn parent_from_proto(parent: &[u8]) -> Result<Option<Hash>> {
+    match parent {
+        [] => Ok(None),
+        parent => Ok(Some(id(parent, "parent")?)),
+    }
+}
+
+/// A vote's reason for rejecting, 0 for none; an approval gives none
+fn reject_code(approve: bool, code: u32) -> Result<Option<RejectCode>> {
+    match code {
+        0 => Ok(None),
+        _ if approve => Err(invalid(format!("approval with reject code {}", code))),
+        _ => Ok(Some(RejectCode(code))),
+    }
+}
+
+fn parse_addr(addr: &str) -> Result<std::net::SocketAddr> {
+    addr.parse()
+        .map_err(|_| invalid(format!("bad address {:?}", addr)))
+}
+
+fn parse_addrs(addrs: &[String]) -> Result<Vec<std::net::SocketAddr>> {
+    addrs.iter().map(|addr| parse_addr(addr)).collect()
+}
+
+/// proto3 makes every message field optional; ours are not
+fn required<T>(field: Option<T>, name: &str) -> Result<T> {
+    field.ok_or_else(|| invalid(format!("missing {}", name)))
+}
+
+fn invalid(message: String) -> SwarmhostError {
+    SwarmhostError::Serialization(message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rejects_what_protobuf_allows_but_we_do_not() {
+        let short_id = proto::PeerMessage {
+            message: Some(Kind::RelayOpen(proto::RelaySession {
+                session: 1,
+                peer: vec![0; 31],
+            })),
+        };
+        assert!(ProtobufCodec.decode(&short_id.encode_to_vec()).is_err());
+
+        let empty = proto::PeerMessage { message: None };
+        assert!(ProtobufCodec.decode(&empty.encode_to_vec()).is_err());
+
+        let far = proto::PeerMessage {
+            message: Some(Kind::Gossip(proto::Gossip {
+                hops_left: 256,
+                payload: Some(proto::gossip::Payload::Vote(proto::Vote {
+                    action_id: vec![0; 32],
+                    voter: vec![0; 32],
+                    approve: true,
+                    signature: vec![],
+                    round: 0,
+                    reject_code: 0,
+                })),
+            })),
+        };
+        assert!(ProtobufCodec.decode(&far.encode_to_vec()).is_err());
+    }
+}
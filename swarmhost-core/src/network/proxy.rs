@@ -0,0 +1,410 @@
+// network/proxy.rs - SOCKS5 client for outbound connections
+
+use crate::error::{Result, SwarmhostError};
+use crate::node::{ProxyConfig, parse_host_port};
+use std::fmt;
+use std::net::{IpAddr, SocketAddr};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+
+const VERSION: u8 = 5;
+const NO_AUTH: u8 = 0x00;
+const USER_PASS: u8 = 0x02;
+const NO_ACCEPTABLE: u8 = 0xff;
+const CONNECT: u8 = 0x01;
+const ATYP_V4: u8 = 0x01;
+const ATYP_DOMAIN: u8 = 0x03;
+const ATYP_V6: u8 = 0x04;
+
+/// Opens TCP streams through a SOCKS5 proxy (RFC 1928), optionally with
+/// username/password auth (RFC 1929)
+///
+/// Hostnames are passed to the proxy as-is for it to resolve. Errors name
+/// the proxy when it cannot be reached or refuses us, and the destination
+/// when the proxy cannot reach it.
+#[derive(Clone)]
+pub struct Socks5Proxy {
+    addr: String,
+    credentials: Option<(String, String)>,
+}
+
+impl Socks5Proxy {
+    pub fn new(config: &ProxyConfig) -> Self {
+        Self {
+            addr: config.addr.clone(),
+            credentials: config.username.clone().zip(config.password.clone()),
+        }
+    }
+
+    /// Address of the proxy itself
+    pub fn addr(&self) -> &str {
+        &self.addr
+    }
+
+    /// Open a stream to `addr` through the proxy
+    pub async fn connect_addr(&self, addr: SocketAddr) -> Result<TcpStream> {
+        self.connect(&addr.to_string()).await
+    }
+
+    /// Open a stream to `target` (host:port) through the proxy, leaving any
+    /// hostname for the proxy to resolve
+    pub async fn connect(&self, target: &str) -> Result<TcpStream> {
+        let (host, port) = parse_host_port(target).map_err(|e| {
+            SwarmhostError::Peer(format!("Invalid proxy target '{}': {}", target, e))
+        })?;
+
+        let mut stream = TcpStream::connect(&self.addr).await.map_err(|e| {
+            SwarmhostError::Peer(format!("SOCKS5 proxy {} unreachable: {}", self.addr, e))
+        })?;
+        self.negotiate(&mut stream, target, &host, port)
+            .await
+            .map_err(|e| match e {
+                SwarmhostError::Network(e) => SwarmhostError::Peer(format!(
+                    "SOCKS5 proxy {} failed during handshake: {}",
+                    self.addr, e
+                )),
+                other => other,
+            })?;
+        tracing::trace!("Connected to {} through proxy {}", target, self.addr);
+        Ok(stream)
+    }
+
+    async fn negotiate(
+        &self,
+        stream: &mut TcpStream,
+        target: &str,
+        host: &str,
+        port: u16,
+    ) -> Result<()> {
+        let methods: &[u8] = match self.credentials {
+            Some(_) => &[NO_AUTH, USER_PASS],
+            None => &[NO_AUTH],
+        };
+        let mut greeting = vec![VERSION, methods.len() as u8];
+        greeting.extend_from_slice(methods);
+        stream.write_all(&greeting).await?;
+
+        let mut choice = [0u8; 2];
+        stream.read_exact(&mut choice).await?;
+        if choice[0] != VERSION {
+            return Err(self.refused(format!("answered with version {}", choice[0])));
+        }
+        match (choice[1], &self.credentials) {
+            (NO_AUTH, _) => {}
+            (USER_PASS, Some((username, password))) => {
+                self.authenticate(stream, username, password).await?
+            }
+            (NO_ACCEPTABLE, _) => {
+                return Err(self.refused("accepts none of our auth methods".to_string()));
+            }
+            (method, _) => {
+                return Err(self.refused(format!("chose auth method {} we did not offer", method)));
+            }
+        }
+
+        let mut request = vec![VERSION, CONNECT, 0];
+        match host.parse::<IpAddr>() {
+            Ok(IpAddr::V4(ip)) => {
+                request.push(ATYP_V4);
+                request.extend_from_slice(&ip.octets());
+            }
+            Ok(IpAddr::V6(ip)) => {
+                request.push(ATYP_V6);
+                request.extend_from_slice(&ip.octets());
+            }
+            Err(_) => {
+                let name = host.as_bytes();
+                if name.len() > 255 {
+                    return Err(SwarmhostError::Peer(format!(
+                        "Hostname '{}' is too long for SOCKS5",
+                        host
+                    )));
+                }
+                request.push(ATYP_DOMAIN);
+                request.push(name.len() as u8);
+                request.extend_from_slice(name);
+            }
+        }
+        request.extend_from_slice(&port.to_be_bytes());
+        stream.write_all(&request).await?;
+
+        let mut reply = [0u8; 4];
+        stream.read_exact(&mut reply).await?;
+        if reply[0] != VERSION {
+            return Err(self.refused(format!("answered with version {}", reply[0])));
+        }
+        if reply[1] != 0 {
+            return Err(SwarmhostError::Peer(format!(
+                "SOCKS5 proxy {} could not reach {}: {}",
+                self.addr,
+                target,
+                reply_reason(reply[1])
+            )));
+        }
+        // Skip the address the proxy bound for us; we have no use for it
+        let bound = match reply[3] {
+            ATYP_V4 => 4,
+            ATYP_V6 => 16,
+            ATYP_DOMAIN => stream.read_u8().await? as usize,
+            atyp => return Err(self.refused(format!("replied with address type {}", atyp))),
+        };
+        let mut skip = vec![0u8; bound + 2];
+        stream.read_exact(&mut skip).await?;
+        Ok(())
+    }
+
+    async fn authenticate(
+        &self,
+        stream: &mut TcpStream,
+        username: &str,
+        password: &str,
+    ) -> Result<()> {
+        let mut request = vec![1, username.len() as u8];
+        request.extend_from_slice(username.as_bytes());
+        request.push(password.len() as u8);
+        request.extend_from_slice(password.as_bytes());
+        stream.write_all(&request).await?;
+
+        let mut status = [0u8; 2];
+        stream.read_exact(&mut status).await?;
+        if status[1] != 0 {
+            return Err(self.refused("rejected the username/password".to_string()));
+        }
+        Ok(())
+    }
+
+    fn refused(&self, why: String) -> SwarmhostError {
+        SwarmhostError::Peer(format!("SOCKS5 proxy {} {}", self.addr, why))
+    }
+}
+
+impl fmt::Debug for Socks5Proxy {
+    // Keep the password out of logs
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Socks5Proxy")
+            .field("addr", &self.addr)
+            .field("auth", &self.credentials.is_some())
+            .finish()
+    }
+}
+
+/// Open a TCP stream to `addr`, through `proxy` if there is one
+pub(super) async fn connect_tcp(
+    proxy: Option<&Socks5Proxy>,
+    addr: SocketAddr,
+) -> Result<TcpStream> {
+    match proxy {
+        Some(proxy) => proxy.connect_addr(addr).await,
+        None => Ok(TcpStream::connect(addr).await?),
+    }
+}
+
+fn reply_reason(code: u8) -> &'static str {
+    match code {
+        1 => "general failure",
+        2 => "not allowed by ruleset",
+        3 => "network unreachable",
+        4 => "host unreachable",
+        5 => "connection refused",
+        6 => "TTL expired",
+        7 => "command not supported",
+        8 => "address type not supported",
+        _ => "unknown error",
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod mock {
+    //! A minimal SOCKS5 server, just enough to relay CONNECT requests
+
+    use super::*;
+    use tokio::net::TcpListener;
+    use tokio::sync::mpsc;
+
+    pub struct MockSocks5 {
+        pub addr: SocketAddr,
+        /// Destinations requested, as the client sent them
+        pub requests: mpsc::UnboundedReceiver<String>,
+    }
+
+    impl MockSocks5 {
+        /// Start a server that demands `credentials` if given
+        pub async fn start(credentials: Option<(&'static str, &'static str)>) -> Self {
+            let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let addr = listener.local_addr().unwrap();
+            let (tx, requests) = mpsc::unbounded_channel();
+            tokio::spawn(async move {
+                while let Ok((stream, _)) = listener.accept().await {
+                    tokio::spawn(serve(stream, credentials, tx.clone()));
+                }
+            });
+            Self { addr, requests }
+        }
+    }
+
+    async fn serve(
+        mut client: TcpStream,
+        credentials: Option<(&'static str, &'static str)>,
+        requests: mpsc::UnboundedSender<String>,
+    ) -> std::io::Result<()> {
+        let mut header = [0u8; 2];
+        client.read_exact(&mut header).await?;
+        let mut methods = vec![0u8; header[1] as usize];
+        client.read_exact(&mut methods).await?;
+
+        let wanted = if credentials.is_some() {
+            USER_PASS
+        } else {
+            NO_AUTH
+        };
+        if !methods.contains(&wanted) {
+            return client.write_all(&[VERSION, NO_ACCEPTABLE]).await;
+        }
+        client.write_all(&[VERSION, wanted]).await?;
+        if let Some((username, password)) = credentials {
+            let mut len = [0u8; 2];
+            client.read_exact(&mut len).await?;
+            let mut user = vec![0u8; len[1] as usize];
+            client.read_exact(&mut user).await?;
+            let mut pass = vec![0u8; client.read_u8().await? as usize];
+            client.read_exact(&mut pass).await?;
+            let ok = user == username.as_bytes() && pass == password.as_bytes();
+            client.write_all(&[1, if ok { 0 } else { 1 }]).await?;
+            if !ok {
+                return Ok(());
+            }
+        }
+
+        let mut request = [0u8; 4];
+        client.read_exact(&mut request).await?;
+        let host = match request[3] {
+            ATYP_V4 => {
+                let mut ip = [0u8; 4];
+                client.read_exact(&mut ip).await?;
+                IpAddr::from(ip).to_string()
+            }
+            ATYP_V6 => {
+                let mut ip = [0u8; 16];
+                client.read_exact(&mut ip).await?;
+                format!("[{}]", IpAddr::from(ip))
+            }
+            _ => {
+                let mut name = vec![0u8; client.read_u8().await? as usize];
+                client.read_exact(&mut name).await?;
+                String::from_utf8_lossy(&name).into_owned()
+            }
+        };
+        let port = client.read_u16().await?;
+        let target = format!("{}:{}", host, port);
+        let _ = requests.send(target.clone());
+
+        let mut upstream = match TcpStream::connect(&target).await {
+            Ok(upstream) => upstream,
+            Err(_) => {
+                return client
+                    .write_all(&[VERSION, 5, 0, ATYP_V4, 0, 0, 0, 0, 0, 0])
+                    .await;
+            }
+        };
+        client
+            .write_all(&[VERSION, 0, 0, ATYP_V4, 127, 0, 0, 1, 0, 0])
+            .await?;
+        tokio::io::copy_bidirectional(&mut client, &mut upstream).await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::mock::MockSocks5;
+    use super::*;
+    use tokio::net::TcpListener;
+
+    fn proxy(addr: SocketAddr, credentials: Option<(&str, &str)>) -> Socks5Proxy {
+        Socks5Proxy::new(&ProxyConfig {
+            addr: addr.to_string(),
+            username: credentials.map(|(user, _)| user.to_string()),
+            password: credentials.map(|(_, pass)| pass.to_string()),
+        })
+    }
+
+    /// Echo the first message back and return how many bytes were echoed
+    async fn echo_once(listener: TcpListener) -> usize {
+        let (mut stream, _) = listener.accept().await.unwrap();
+        let mut buf = [0u8; 64];
+        let n = stream.read(&mut buf).await.unwrap();
+        stream.write_all(&buf[..n]).await.unwrap();
+        n
+    }
+
+    #[tokio::test]
+    async fn test_relays_with_auth_and_leaves_hostnames_to_the_proxy() {
+        let mut server = MockSocks5::start(Some(("player", "secret"))).await;
+        let target = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = target.local_addr().unwrap().port();
+        let echo = tokio::spawn(echo_once(target));
+
+        let proxy = proxy(server.addr, Some(("player", "secret")));
+        let mut stream = proxy.connect(&format!("localhost:{}", port)).await.unwrap();
+        assert_eq!(
+            server.requests.recv().await.unwrap(),
+            format!("localhost:{}", port)
+        );
+
+        stream.write_all(b"through the proxy").await.unwrap();
+        let mut back = [0u8; 17];
+        stream.read_exact(&mut back).await.unwrap();
+        assert_eq!(&back, b"through the proxy");
+        assert_eq!(echo.await.unwrap(), 17);
+    }
+
+    #[tokio::test]
+    async fn test_wrong_password_refused() {
+        let server = MockSocks5::start(Some(("player", "secret"))).await;
+        let err = proxy(server.addr, Some(("player", "guess")))
+            .connect("127.0.0.1:9")
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("rejected the username/password"));
+
+        let err = proxy(server.addr, None)
+            .connect("127.0.0.1:9")
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("accepts none of our auth methods"));
+    }
+
+    #[tokio::test]
+    async fn test_proxy_and_destination_failures_told_apart() {
+        // Bind and drop to find ports nobody listens on
+        let dead = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let dead_addr = dead.local_addr().unwrap();
+        drop(dead);
+
+        let err = proxy(dead_addr, None)
+            .connect_addr(dead_addr)
+            .await
+            .unwrap_err()
+            .to_string();
+        assert!(
+            err.contains(&format!("proxy {} unreachable", dead_addr)),
+            "{}",
+            err
+        );
+
+        let server = MockSocks5::start(None).await;
+        let err = proxy(server.addr, None)
+            .connect_addr(dead_addr)
+            .await
+            .unwrap_err()
+            .to_string();
+        assert!(
+            err.contains(&format!(
+                "could not reach {}: connection refused",
+                dead_addr
+            )),
+            "{}",
+            err
+        );
+    }
+}
@@ -0,0 +1,276 @@
+// network/punch.rs - Coordinated UDP hole punching
+
+use super::stun;
+use crate::error::{Result, SwarmhostError};
+use crate::node::NatConfig;
+use serde::{Deserialize, Serialize};
+use std::net::{IpAddr, SocketAddr};
+use std::time::Duration;
+use tokio::net::UdpSocket;
+use tokio::time::Instant;
+
+/// Marks a datagram as a punch probe rather than QUIC traffic
+const PROBE_MAGIC: &[u8; 8] = b"swhpunch";
+
+const PROBE_LEN: usize = PROBE_MAGIC.len() + 8 + 1;
+
+/// Probes sent back to back in each burst, in case some are dropped
+const BURST: usize = 3;
+
+/// Longest pause between bursts once backoff has grown
+const MAX_BURST_GAP: Duration = Duration::from_secs(1);
+
+/// Signaling for a hole punch, passed between the two peers by one both
+/// are already connected to
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum PunchSignal {
+    /// The sender wants a direct path and is probing from `addr`
+    Offer { nonce: u64, addr: SocketAddr },
+    /// The receiver of an offer agreed and is probing from `addr`
+    Answer { nonce: u64, addr: SocketAddr },
+    /// The target is not connected to the go-between, or declined
+    Unreachable { nonce: u64 },
+}
+
+impl PunchSignal {
+    pub fn nonce(&self) -> u64 {
+        match *self {
+            PunchSignal::Offer { nonce, .. }
+            | PunchSignal::Answer { nonce, .. }
+            | PunchSignal::Unreachable { nonce } => nonce,
+        }
+    }
+}
+
+/// A fresh UDP socket to punch from
+pub async fn bind(ip: IpAddr) -> Result<UdpSocket> {
+    Ok(UdpSocket::bind((ip, 0)).await?)
+}
+
+/// The address the other peer should probe to reach `socket`
+///
+/// Asks the configured STUN servers from the socket itself, so the NAT
+/// mapping they report is the one the probes will use. Without STUN the
+/// socket's own address is used, which only works when it is routable.
+pub async fn external_addr(socket: &UdpSocket, nat: &NatConfig) -> Result<SocketAddr> {
+    for server in &nat.stun_servers {
+        match stun::query(socket, server, nat.stun_timeout).await {
+            Ok(addr) => return Ok(addr),
+            Err(e) => tracing::debug!("STUN query to {} failed: {}", server, e),
+        }
+    }
+
+    let local = socket.local_addr()?;
+    if local.ip().is_unspecified() {
+        return Err(SwarmhostError::Config(
+            "Hole punching from an unspecified address needs nat.stun_servers".to_string(),
+        ));
+    }
+    Ok(local)
+}
+
+/// Probe `peer` from `socket` until the two of us hear each other
+///
+/// Both sides call this at roughly the same time, each aiming at the other's
+/// external address; the outgoing probes open our NAT's mapping so the
+/// peer's probes get through. Probes go out in bursts whose spacing doubles
+/// from `nat.punch_interval`, for at most `nat.punch_window`. A probe from
+/// the peer is answered with an ack, and we are done once an ack arrives,
+/// since that proves the path works both ways. Returns where the peer's
+/// packets came from, which is the address to connect to.
+pub async fn punch(
+    socket: &UdpSocket,
+    peer: SocketAddr,
+    nonce: u64,
+    nat: &NatConfig,
+) -> Result<SocketAddr> {
+    let deadline = Instant::now() + nat.punch_window;
+    let mut gap = nat.punch_interval;
+    let mut next_burst = Instant::now();
+    let mut target = peer;
+    let mut heard = false;
+    let mut buf = [0u8; 64];
+
+    loop {
+        tokio::select! {
+            _ = tokio::time::sleep_until(next_burst) => {
+                if Instant::now() >= deadline {
+                    return Err(SwarmhostError::timeout(format!(
+                        "No answer to hole punching probes from {}",
+                        peer
+                    )));
+                }
+                send_burst(socket, target, nonce, heard).await;
+                next_burst = (Instant::now() + gap).min(deadline);
+                gap = (gap * 2).min(MAX_BURST_GAP);
+            }
+            received = socket.recv_from(&mut buf) => {
+                let (len, from) = received?;
+                let Some(ack) = parse_probe(&buf[..len], nonce) else {
+                    continue;
+                };
+                // A NAT may map the peer to another port than it saw for
+                // itself; answer wherever the probe really came from
+                target = from;
+                heard = true;
+                send_burst(socket, target, nonce, true).await;
+                if ack {
+                    return Ok(from);
+                }
+            }
+        }
+    }
+}
+
+async fn send_burst(socket: &UdpSocket, to: SocketAddr, nonce: u64, ack: bool) {
+    let probe = probe(nonce, ack);
+    for _ in 0..BURST {
+        // Sends fail until routes or mappings exist; the next burst retries
+        if let Err(e) = socket.send_to(&probe, to).await {
+            tracing::trace!("Punch probe to {} failed: {}", to, e);
+        }
+    }
+}
+
+fn probe(nonce: u64, ack: bool) -> [u8; PROBE_LEN] {
+    let mut probe = [0u8; PROBE_LEN];
+    probe[..8].copy_from_slice(PROBE_MAGIC);
+    probe[8..16].copy_from_slice(&nonce.to_be_bytes());
+    probe[16] = ack as u8;
+    probe
+}
+
+/// Whether a datagram is our probe, and if so whether it is an ack
+fn parse_probe(datagram: &[u8], nonce: u64) -> Option<bool> {
+    if datagram.len() != PROBE_LEN
+        || &datagram[..8] != PROBE_MAGIC
+        || datagram[8..16] != nonce.to_be_bytes()
+    {
+        return None;
+    }
+    Some(datagram[16] != 0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::KeyPair;
+    use crate::network::{Connection, QuicTransport};
+    use crate::node::NetworkConfig;
+    use bytes::Bytes;
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicBool, Ordering};
+
+    /// A port-translating NAT in front of one host
+    ///
+    /// The host sends everything to `inside`; it leaves from `outside`,
+    /// which is the host's public address. Packets reaching `outside` are
+    /// dropped until the host has sent to their source, like a
+    /// port-restricted cone NAT.
+    struct Nat {
+        inside: SocketAddr,
+        outside: SocketAddr,
+    }
+
+    async fn nat(host: SocketAddr, remote: SocketAddr) -> Nat {
+        let inside = Arc::new(UdpSocket::bind("127.0.0.1:0").await.unwrap());
+        let outside = Arc::new(UdpSocket::bind("127.0.0.1:0").await.unwrap());
+        let addrs = Nat {
+            inside: inside.local_addr().unwrap(),
+            outside: outside.local_addr().unwrap(),
+        };
+        let mapped = Arc::new(AtomicBool::new(false));
+
+        let (from, to, open) = (inside.clone(), outside.clone(), mapped.clone());
+        tokio::spawn(async move {
+            let mut buf = [0u8; 2048];
+            while let Ok((len, _)) = from.recv_from(&mut buf).await {
+                open.store(true, Ordering::SeqCst);
+                let _ = to.send_to(&buf[..len], remote).await;
+            }
+        });
+        tokio::spawn(async move {
+            let mut buf = [0u8; 2048];
+            while let Ok((len, source)) = outside.recv_from(&mut buf).await {
+                if source == remote && mapped.load(Ordering::SeqCst) {
+                    let _ = inside.send_to(&buf[..len], host).await;
+                }
+            }
+        });
+
+        addrs
+    }
+
+    fn fast() -> NatConfig {
+        NatConfig {
+            punch_window: Duration::from_secs(5),
+            punch_interval: Duration::from_millis(10),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_probe_round_trip() {
+        assert_eq!(parse_probe(&probe(42, false), 42), Some(false));
+        assert_eq!(parse_probe(&probe(42, true), 42), Some(true));
+        assert_eq!(parse_probe(&probe(42, true), 43), None);
+        assert_eq!(parse_probe(b"not a probe at all", 42), None);
+    }
+
+    #[tokio::test]
+    async fn test_punch_through_nat_gives_direct_quic_connection() {
+        let public = bind("127.0.0.1".parse().unwrap()).await.unwrap();
+        let natted = bind("127.0.0.1".parse().unwrap()).await.unwrap();
+        let public_addr = public.local_addr().unwrap();
+        let nat = nat(natted.local_addr().unwrap(), public_addr).await;
+
+        // Before the NATed host sends anything, the NAT drops inbound traffic
+        public.send_to(b"hello?", nat.outside).await.unwrap();
+        let mut buf = [0u8; 16];
+        let blocked =
+            tokio::time::timeout(Duration::from_millis(100), natted.recv_from(&mut buf)).await;
+        assert!(blocked.is_err());
+
+        // Each side aims at the address signaling gave it for the other
+        let config = fast();
+        let (a, b) = tokio::join!(
+            punch(&public, nat.outside, 7, &config),
+            punch(&natted, nat.inside, 7, &config),
+        );
+        assert_eq!(a.unwrap(), nat.outside);
+        assert_eq!(b.unwrap(), nat.inside);
+
+        // Upgrade the punched path to QUIC
+        let (alice, bob) = (KeyPair::generate(), KeyPair::generate());
+        let network = NetworkConfig::default();
+        let dialer = QuicTransport::new(&network, &alice).unwrap();
+        let acceptor = QuicTransport::new(&network, &bob).unwrap();
+        let (dialed, accepted) = tokio::join!(
+            dialer.connect_from(public.into_std().unwrap(), nat.outside),
+            acceptor.accept_on(natted.into_std().unwrap()),
+        );
+        let (mut dialed, mut accepted) = (dialed.unwrap(), accepted.unwrap());
+
+        assert_eq!(dialed.peer_identity(), Some(bob.public_key()));
+        assert_eq!(accepted.peer_identity(), Some(alice.public_key()));
+        dialed.send(Bytes::from_static(b"through")).await.unwrap();
+        assert_eq!(&accepted.recv().await.unwrap()[..], b"through");
+        accepted.send(Bytes::from_static(b"the nat")).await.unwrap();
+        assert_eq!(&dialed.recv().await.unwrap()[..], b"the nat");
+    }
+
+    #[tokio::test]
+    async fn test_punch_gives_up_after_window() {
+        let socket = bind("127.0.0.1".parse().unwrap()).await.unwrap();
+        let silent = bind("127.0.0.1".parse().unwrap()).await.unwrap();
+        let config = NatConfig {
+            punch_window: Duration::from_millis(200),
+            ..fast()
+        };
+
+        let started = Instant::now();
+        let result = punch(&socket, silent.local_addr().unwrap(), 1, &config).await;
+        assert!(matches!(result, Err(SwarmhostError::Timeout(_))));
+        assert!(started.elapsed() < Duration::from_secs(2));
+    }
+}
@@ -0,0 +1,582 @@
+// network/quic.rs - QUIC transport with player-id-bound certificates
+
+use super::frame::FramedStream;
+use super::transport::{Connection, Listener, Transport};
+use crate::crypto::{KeyPair, PlayerId};
+use crate::error::{Result, SwarmhostError};
+use crate::node::NetworkConfig;
+use async_trait::async_trait;
+use bytes::Bytes;
+use quinn::{Endpoint, EndpointConfig, IdleTimeout, RecvStream, SendStream, TokioRuntime};
+use rustls::client::{ServerCertVerified, ServerCertVerifier};
+use rustls::server::{ClientCertVerified, ClientCertVerifier};
+use rustls::{Certificate, CertificateError, DistinguishedName, PrivateKey, ServerName};
+use socket2::{Domain, Protocol, Socket, Type};
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, UdpSocket};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+use tokio::io::{AsyncReadExt, Join};
+use tokio::sync::{Mutex, mpsc};
+use tokio::task::JoinHandle;
+
+/// Name presented in TLS; identity comes from the certificate key instead
+const SERVER_NAME: &str = "swarmhost";
+
+const ALPN: &[u8] = b"swarmhost";
+
+/// First byte the dialer writes on the control stream, so the listener
+/// learns about the stream before the dialer has anything else to say
+const CONTROL_STREAM: u8 = 0;
+
+/// Connections that finished the QUIC handshake but were not yet accepted
+const ACCEPT_BACKLOG: usize = 128;
+
+/// DER prefix of an Ed25519 SubjectPublicKeyInfo; the 32-byte key follows
+const ED25519_SPKI_PREFIX: [u8; 12] = [
+    0x30, 0x2a, 0x30, 0x05, 0x06, 0x03, 0x2b, 0x65, 0x70, 0x03, 0x21, 0x00,
+];
+
+type ControlStream = Join<RecvStream, SendStream>;
+
+/// [`Transport`] over QUIC
+///
+/// Control and consensus traffic uses one bidirectional stream per
+/// connection; [`Connection::send_unreliable`] uses QUIC datagrams. Each node
+/// presents a self-signed certificate for its own Ed25519 key, so the key
+/// proven in TLS is the player id, and the secure handshake checks it against
+/// the id the peer claims.
+#[derive(Clone)]
+pub struct QuicTransport {
+    server_config: quinn::ServerConfig,
+    client_config: quinn::ClientConfig,
+    max_message_size: usize,
+    accept_timeout: Duration,
+    accept_v4_mapped: bool,
+}
+
+impl QuicTransport {
+    pub fn new(config: &NetworkConfig, keypair: &KeyPair) -> Result<Self> {
+        let (cert, key) = self_signed_cert(keypair)?;
+        let verifier = Arc::new(PlayerIdVerifier);
+
+        let mut server_crypto = rustls::ServerConfig::builder()
+            .with_safe_defaults()
+            .with_client_cert_verifier(verifier.clone())
+            .with_single_cert(vec![cert.clone()], key.clone())
+            .map_err(tls_error)?;
+        server_crypto.alpn_protocols = vec![ALPN.to_vec()];
+
+        let mut client_crypto = rustls::ClientConfig::builder()
+            .with_safe_defaults()
+            .with_custom_certificate_verifier(verifier)
+            .with_client_auth_cert(vec![cert], key)
+            .map_err(tls_error)?;
+        client_crypto.alpn_protocols = vec![ALPN.to_vec()];
+
+        let transport_config = Arc::new(transport_config(config)?);
+        let mut server_config = quinn::ServerConfig::with_crypto(Arc::new(server_crypto));
+        server_config.transport_config(transport_config.clone());
+        let mut client_config = quinn::ClientConfig::new(Arc::new(client_crypto));
+        client_config.transport_config(transport_config);
+
+        Ok(Self {
+            server_config,
+            client_config,
+            max_message_size: config.max_message_size,
+            accept_timeout: config.security.handshake_timeout,
+            accept_v4_mapped: config.dual_stack && config.bind_addr.is_ipv6(),
+        })
+    }
+
+    /// Open a connection to `addr` from a fresh local endpoint
+    pub async fn connect(&self, addr: SocketAddr) -> Result<QuicConnection> {
+        let local = match addr.ip() {
+            IpAddr::V4(_) => SocketAddr::new(IpAddr::V4(Ipv4Addr::UNSPECIFIED), 0),
+            IpAddr::V6(_) => SocketAddr::new(IpAddr::V6(Ipv6Addr::UNSPECIFIED), 0),
+        };
+        self.connect_from(UdpSocket::bind(local)?, addr).await
+    }
+
+    /// Open a connection to `addr` from an existing socket, e.g. one a NAT
+    /// hole was punched from
+    pub async fn connect_from(
+        &self,
+        socket: UdpSocket,
+        addr: SocketAddr,
+    ) -> Result<QuicConnection> {
+        let endpoint = Endpoint::new(
+            EndpointConfig::default(),
+            None,
+            socket,
+            Arc::new(TokioRuntime),
+        )?;
+        let connection = endpoint
+            .connect_with(self.client_config.clone(), addr, SERVER_NAME)
+            .map_err(quic_error)?
+            .await
+            .map_err(quic_error)?;
+
+        let (mut send, recv) = connection.open_bi().await.map_err(quic_error)?;
+        send.write_all(&[CONTROL_STREAM])
+            .await
+            .map_err(quic_error)?;
+
+        QuicConnection::new(
+            connection,
+            Some(endpoint),
+            send,
+            recv,
+            self.max_message_size,
+        )
+    }
+
+    /// Accept the first connection to arrive on an existing socket, e.g. the
+    /// far end of a punched NAT hole
+    pub async fn accept_on(&self, socket: UdpSocket) -> Result<QuicConnection> {
+        let endpoint = Endpoint::new(
+            EndpointConfig::default(),
+            Some(self.server_config.clone()),
+            socket,
+            Arc::new(TokioRuntime),
+        )?;
+        let accept = async {
+            let connecting = endpoint
+                .accept()
+                .await
+                .ok_or_else(|| io::Error::from(io::ErrorKind::NotConnected))?;
+            // One peer per punched socket
+            endpoint.set_server_config(None);
+            finish_accept(connecting, Some(endpoint.clone()), self.max_message_size).await
+        };
+        tokio::time::timeout(self.accept_timeout, accept)
+            .await
+            .map_err(|_| SwarmhostError::timeout("QUIC accept on punched socket"))?
+    }
+
+    /// Start accepting connections on `addr`
+    pub fn bind(&self, addr: SocketAddr) -> Result<QuicListener> {
+        let socket = bind_udp(addr, !self.accept_v4_mapped)?;
+        let endpoint = Endpoint::new(
+            EndpointConfig::default(),
+            Some(self.server_config.clone()),
+            socket,
+            Arc::new(TokioRuntime),
+        )?;
+
+        let (tx, rx) = mpsc::channel(ACCEPT_BACKLOG);
+        let driver = tokio::spawn(drive_accepts(
+            endpoint.clone(),
+            tx,
+            self.max_message_size,
+            self.accept_timeout,
+        ));
+
+        Ok(QuicListener {
+            local_addr: endpoint.local_addr()?,
+            endpoint,
+            incoming: Mutex::new(rx),
+            driver,
+        })
+    }
+}
+
+#[async_trait]
+impl Transport for QuicTransport {
+    async fn listen(&self, addr: SocketAddr) -> Result<Box<dyn Listener>> {
+        Ok(Box::new(self.bind(addr)?))
+    }
+
+    async fn dial(&self, addr: SocketAddr) -> Result<Box<dyn Connection>> {
+        Ok(Box::new(self.connect(addr).await?))
+    }
+}
+
+/// Finish QUIC handshakes in the background so a slow peer cannot hold up
+/// the accept loop
+async fn drive_accepts(
+    endpoint: Endpoint,
+    ready: mpsc::Sender<QuicConnection>,
+    max_message_size: usize,
+    timeout: Duration,
+) {
+    while let Some(connecting) = endpoint.accept().await {
+        let ready = ready.clone();
+        tokio::spawn(async move {
+            let remote = connecting.remote_address();
+            let accept = finish_accept(connecting, None, max_message_size);
+            match tokio::time::timeout(timeout, accept).await {
+                Ok(Ok(conn)) => {
+                    let _ = ready.send(conn).await;
+                }
+                Ok(Err(e)) => tracing::debug!("QUIC connection from {} failed: {}", remote, e),
+                Err(_) => tracing::debug!("QUIC connection from {} timed out", remote),
+            }
+        });
+    }
+}
+
+/// Complete an incoming connection up to its control stream
+async fn finish_accept(
+    connecting: quinn::Connecting,
+    endpoint: Option<Endpoint>,
+    max_message_size: usize,
+) -> Result<QuicConnection> {
+    let connection = connecting.await.map_err(quic_error)?;
+    let (send, mut recv) = connection.accept_bi().await.map_err(quic_error)?;
+    if recv.read_u8().await? != CONTROL_STREAM {
+        return Err(SwarmhostError::Peer(
+            "Unexpected QUIC stream type".to_string(),
+        ));
+    }
+    QuicConnection::new(connection, endpoint, send, recv, max_message_size)
+}
+
+/// Accepts QUIC connections on one UDP socket
+pub struct QuicListener {
+    endpoint: Endpoint,
+    local_addr: SocketAddr,
+    incoming: Mutex<mpsc::Receiver<QuicConnection>>,
+    driver: JoinHandle<()>,
+}
+
+impl QuicListener {
+    /// Wait for the next connection that completed its QUIC handshake
+    pub async fn next(&self) -> Result<QuicConnection> {
+        let conn = self
+            .incoming
+            .lock()
+            .await
+            .recv()
+            .await
+            .ok_or_else(|| io::Error::from(io::ErrorKind::NotConnected))?;
+        Ok(conn)
+    }
+}
+
+#[async_trait]
+impl Listener for QuicListener {
+    async fn accept(&self) -> Result<Box<dyn Connection>> {
+        Ok(Box::new(self.next().await?))
+    }
+
+    fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+}
+
+impl Drop for QuicListener {
+    fn drop(&mut self) {
+        // Refuse new peers but leave established connections to close
+        // gracefully
+        self.endpoint.set_server_config(None);
+        self.driver.abort();
+    }
+}
+
+/// A QUIC connection to one peer
+pub struct QuicConnection {
+    connection: quinn::Connection,
+    framed: FramedStream<ControlStream>,
+    peer_id: PlayerId,
+    /// The endpoint a dialed or punched connection owns; accepted ones
+    /// share the listener's
+    endpoint: Option<Endpoint>,
+}
+
+impl QuicConnection {
+    fn new(
+        connection: quinn::Connection,
+        endpoint: Option<Endpoint>,
+        send: SendStream,
+        recv: RecvStream,
+        max_message_size: usize,
+    ) -> Result<Self> {
+        let peer_id = connection
+            .peer_identity()
+            .and_then(|identity| identity.downcast::<Vec<Certificate>>().ok())
+            .and_then(|certs| certs.first().and_then(|cert| cert_player_id(&cert.0)))
+            .ok_or_else(|| SwarmhostError::Peer("Peer presented no usable certificate".into()))?;
+
+        Ok(Self {
+            connection,
+            framed: FramedStream::new(tokio::io::join(recv, send), max_message_size),
+            peer_id,
+            endpoint,
+        })
+    }
+
+    /// Current address of the peer, which changes if it migrates
+    pub fn remote_address(&self) -> SocketAddr {
+        self.connection.remote_address()
+    }
+
+    /// Move a dialed connection to a new local socket, e.g. after the
+    /// client's network changed; the session carries on over the new path
+    pub fn rebind(&self, socket: UdpSocket) -> Result<()> {
+        let endpoint = self.endpoint.as_ref().ok_or_else(|| {
+            SwarmhostError::Peer("Only dialed QUIC connections can rebind".to_string())
+        })?;
+        Ok(endpoint.rebind(socket)?)
+    }
+}
+
+#[async_trait]
+impl Connection for QuicConnection {
+    async fn send(&mut self, payload: Bytes) -> Result<()> {
+        self.framed.send(&payload).await
+    }
+
+    async fn send_unreliable(&mut self, payload: Bytes) -> Result<()> {
+        let max = self.framed.max_frame_size();
+        if payload.len() > max {
+            return Err(SwarmhostError::TooLarge(format!(
+                "Outgoing message of {} bytes exceeds max_message_size ({})",
+                payload.len(),
+                max
+            )));
+        }
+
+        match self.connection.max_datagram_size() {
+            Some(limit) if payload.len() <= limit => {
+                self.connection.send_datagram(payload).map_err(quic_error)
+            }
+            // Too big for one packet, or the peer disabled datagrams
+            _ => self.framed.send(&payload).await,
+        }
+    }
+
+    async fn recv(&mut self) -> Result<Bytes> {
+        tokio::select! {
+            frame = self.framed.recv() => frame,
+            datagram = self.connection.read_datagram() => datagram.map_err(quic_error),
+        }
+    }
+
+    async fn close(&mut self) -> Result<()> {
+        self.framed.close().await
+    }
+
+    fn peer_addr(&self) -> SocketAddr {
+        self.connection.remote_address()
+    }
+
+    fn max_message_size(&self) -> usize {
+        self.framed.max_frame_size()
+    }
+
+    fn peer_identity(&self) -> Option<PlayerId> {
+        Some(self.peer_id)
+    }
+}
+
+/// Accepts any single self-signed certificate for an Ed25519 key
+///
+/// rustls still checks the TLS handshake signature against that key, so a
+/// peer can only present its own player id.
+struct PlayerIdVerifier;
+
+impl PlayerIdVerifier {
+    fn check(
+        end_entity: &Certificate,
+        intermediates: &[Certificate],
+    ) -> std::result::Result<(), rustls::Error> {
+        if !intermediates.is_empty() || cert_player_id(&end_entity.0).is_none() {
+            return Err(rustls::Error::InvalidCertificate(
+                CertificateError::BadEncoding,
+            ));
+        }
+        Ok(())
+    }
+}
+
+impl ServerCertVerifier for PlayerIdVerifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &Certificate,
+        intermediates: &[Certificate],
+        _server_name: &ServerName,
+        _scts: &mut dyn Iterator<Item = &[u8]>,
+        _ocsp_response: &[u8],
+        _now: SystemTime,
+    ) -> std::result::Result<ServerCertVerified, rustls::Error> {
+        Self::check(end_entity, intermediates)?;
+        Ok(ServerCertVerified::assertion())
+    }
+}
+
+impl ClientCertVerifier for PlayerIdVerifier {
+    fn client_auth_root_subjects(&self) -> &[DistinguishedName] {
+        &[]
+    }
+
+    fn verify_client_cert(
+        &self,
+        end_entity: &Certificate,
+        intermediates: &[Certificate],
+        _now: SystemTime,
+    ) -> std::result::Result<ClientCertVerified, rustls::Error> {
+        Self::check(end_entity, intermediates)?;
+        Ok(ClientCertVerified::assertion())
+    }
+}
+
+/// Self-signed certificate whose key is the node's signing key
+fn self_signed_cert(keypair: &KeyPair) -> Result<(Certificate, PrivateKey)> {
+    let pkcs8 = ed25519_pkcs8(keypair);
+    let key_pair = rcgen::KeyPair::from_der(&pkcs8).map_err(cert_error)?;
+
+    let mut params = rcgen::CertificateParams::new(vec![SERVER_NAME.to_string()]);
+    params.alg = &rcgen::PKCS_ED25519;
+    params.key_pair = Some(key_pair);
+    let cert = rcgen::Certificate::from_params(params).map_err(cert_error)?;
+
+    Ok((
+        Certificate(cert.serialize_der().map_err(cert_error)?),
+        PrivateKey(pkcs8),
+    ))
+}
+
+/// PKCS#8 v2 encoding of an Ed25519 key (RFC 8410)
+fn ed25519_pkcs8(keypair: &KeyPair) -> Vec<u8> {
+    let mut der = vec![
+        0x30, 0x53, 0x02, 0x01, 0x01, 0x30, 0x05, 0x06, 0x03, 0x2b, 0x65, 0x70, 0x04, 0x22, 0x04,
+        0x20,
+    ];
+    der.extend_from_slice(&keypair.private_key());
+    der.extend_from_slice(&[0xa1, 0x23, 0x03, 0x21, 0x00]);
+    der.extend_from_slice(&keypair.public_key());
+    der
+}
+
+/// The Ed25519 public key in a DER certificate, i.e. the player id
+fn cert_player_id(der: &[u8]) -> Option<PlayerId> {
+    let start = der
+        .windows(ED25519_SPKI_PREFIX.len())
+        .position(|window| window == ED25519_SPKI_PREFIX)?
+        + ED25519_SPKI_PREFIX.len();
+    der.get(start..start + 32)?.try_into().ok()
+}
+
+fn transport_config(config: &NetworkConfig) -> Result<quinn::TransportConfig> {
+    let idle = IdleTimeout::try_from(config.peer_timeout)
+        .map_err(|e| SwarmhostError::Config(format!("peer_timeout is too long for QUIC: {}", e)))?;
+
+    let mut transport = quinn::TransportConfig::default();
+    transport
+        .keep_alive_interval(Some(config.heartbeat_interval))
+        .max_idle_timeout(Some(idle));
+    Ok(transport)
+}
+
+fn bind_udp(addr: SocketAddr, only_v6: bool) -> io::Result<UdpSocket> {
+    let socket = Socket::new(Domain::for_address(addr), Type::DGRAM, Some(Protocol::UDP))?;
+    if addr.is_ipv6() {
+        socket.set_only_v6(only_v6)?;
+    }
+    socket.bind(&addr.into())?;
+    Ok(socket.into())
+}
+
+fn quic_error(e: impl std::error::Error + Send + Sync + 'static) -> SwarmhostError {
+    SwarmhostError::Network(io::Error::other(e))
+}
+
+fn tls_error(e: rustls::Error) -> SwarmhostError {
+    SwarmhostError::crypto(format!("TLS setup failed: {}", e))
+}
+
+fn cert_error(e: rcgen::RcgenError) -> SwarmhostError {
+    SwarmhostError::crypto(format!("Certificate generation failed: {}", e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::network::{CloseCode, Role, SecureChannel};
+
+    async fn pair(
+        dialer: &KeyPair,
+        listener: &KeyPair,
+    ) -> (QuicConnection, QuicConnection, QuicListener) {
+        let config = NetworkConfig::default();
+        let server = QuicTransport::new(&config, listener).unwrap();
+        let client = QuicTransport::new(&config, dialer).unwrap();
+
+        let listening = server.bind("127.0.0.1:0".parse().unwrap()).unwrap();
+        let dialed = client.connect(listening.local_addr()).await.unwrap();
+        let accepted = listening.next().await.unwrap();
+        (dialed, accepted, listening)
+    }
+
+    #[tokio::test]
+    async fn test_reliable_and_unreliable_messages() {
+        let (alice, bob) = (KeyPair::generate(), KeyPair::generate());
+        let (mut dialed, mut accepted, _listener) = pair(&alice, &bob).await;
+
+        dialed.send(Bytes::from_static(b"consensus")).await.unwrap();
+        assert_eq!(&accepted.recv().await.unwrap()[..], b"consensus");
+
+        accepted
+            .send_unreliable(Bytes::from_static(b"position"))
+            .await
+            .unwrap();
+        assert_eq!(&dialed.recv().await.unwrap()[..], b"position");
+
+        // Larger than a datagram, so it falls back to the stream
+        let big = Bytes::from(vec![7u8; 16 * 1024]);
+        dialed.send_unreliable(big.clone()).await.unwrap();
+        assert_eq!(accepted.recv().await.unwrap(), big);
+    }
+
+    #[tokio::test]
+    async fn test_certificates_prove_player_ids() {
+        let (alice, bob) = (KeyPair::generate(), KeyPair::generate());
+        let (dialed, accepted, _listener) = pair(&alice, &bob).await;
+
+        assert_eq!(dialed.peer_identity(), Some(bob.public_key()));
+        assert_eq!(accepted.peer_identity(), Some(alice.public_key()));
+    }
+
+    #[tokio::test]
+    async fn test_claimed_id_must_match_certificate() {
+        let (alice, bob) = (KeyPair::generate(), KeyPair::generate());
+        let (dialed, accepted, _listener) = pair(&alice, &bob).await;
+        let config = NetworkConfig::default();
+
+        let impostor = KeyPair::generate();
+        let (a, b) = tokio::join!(
+            SecureChannel::establish(Box::new(dialed), &config, &impostor, Role::Initiator),
+            SecureChannel::establish(Box::new(accepted), &config, &bob, Role::Responder),
+        );
+        assert!(a.is_err());
+        match b {
+            Err(SwarmhostError::Handshake { code, .. }) => {
+                assert_eq!(code, CloseCode::IdentityMismatch)
+            }
+            other => panic!("expected identity mismatch, got {:?}", other.err()),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_session_survives_client_address_change() {
+        let (alice, bob) = (KeyPair::generate(), KeyPair::generate());
+        let (mut dialed, mut accepted, _listener) = pair(&alice, &bob).await;
+
+        dialed.send(Bytes::from_static(b"before")).await.unwrap();
+        assert_eq!(&accepted.recv().await.unwrap()[..], b"before");
+        let old_addr = accepted.remote_address();
+
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let new_addr = socket.local_addr().unwrap();
+        dialed.rebind(socket).unwrap();
+
+        dialed.send(Bytes::from_static(b"after")).await.unwrap();
+        assert_eq!(&accepted.recv().await.unwrap()[..], b"after");
+        accepted.send(Bytes::from_static(b"reply")).await.unwrap();
+        assert_eq!(&dialed.recv().await.unwrap()[..], b"reply");
+
+        assert_ne!(old_addr, new_addr);
+        assert_eq!(accepted.remote_address(), new_addr);
+    }
+}
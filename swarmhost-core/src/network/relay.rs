@@ -0,0 +1,175 @@
+// network/relay.rs - Connections spliced through a third peer
+
+use super::message::PeerMessage;
+use super::outbound::OutboundSender;
+use super::transport::Connection;
+use crate::crypto::PlayerId;
+use crate::error::{Result, SwarmhostError};
+use async_trait::async_trait;
+use bytes::Bytes;
+use serde::{Deserialize, Serialize};
+use std::io;
+use std::net::SocketAddr;
+use std::time::Duration;
+use tokio::sync::mpsc;
+
+/// Room left in each relayed frame for the message wrapping it on the way to
+/// the relay
+pub const RELAY_OVERHEAD: usize = 64;
+
+/// What a peer willing to relay offers, sent when a connection opens
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RelayOffer {
+    /// Sessions it serves at once
+    pub max_sessions: u32,
+    /// Bytes per second it forwards for each session
+    pub session_bandwidth: u64,
+}
+
+/// Traffic a relay has forwarded for one session
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RelayUsage {
+    pub session: u64,
+    /// The peer that opened the session and the one it reached
+    pub between: (PlayerId, PlayerId),
+    pub bytes: u64,
+    pub frames: u64,
+    /// Total time frames waited for the bandwidth cap
+    pub throttled: Duration,
+}
+
+/// One end of a session through a relay, carried inside the connection to
+/// the relay
+///
+/// Frames are whatever the end-to-end [`SecureChannel`](super::SecureChannel)
+/// produces, so the relay only ever sees ciphertext.
+pub struct RelayedConnection {
+    session: u64,
+    relay_addr: SocketAddr,
+    to_relay: OutboundSender,
+    from_relay: mpsc::Receiver<Bytes>,
+    max_message_size: usize,
+    closed: bool,
+}
+
+impl RelayedConnection {
+    /// `to_relay` queues messages on the connection to the relay, and
+    /// `from_relay` yields the session's frames as they arrive on it
+    pub fn new(
+        session: u64,
+        relay_addr: SocketAddr,
+        to_relay: OutboundSender,
+        from_relay: mpsc::Receiver<Bytes>,
+        max_message_size: usize,
+    ) -> Self {
+        Self {
+            session,
+            relay_addr,
+            to_relay,
+            from_relay,
+            max_message_size: max_message_size.saturating_sub(RELAY_OVERHEAD),
+            closed: false,
+        }
+    }
+
+    pub fn session(&self) -> u64 {
+        self.session
+    }
+}
+
+#[async_trait]
+impl Connection for RelayedConnection {
+    async fn send(&mut self, payload: Bytes) -> Result<()> {
+        if payload.len() > self.max_message_size {
+            return Err(SwarmhostError::TooLarge(format!(
+                "Outgoing message of {} bytes exceeds relayed limit ({})",
+                payload.len(),
+                self.max_message_size
+            )));
+        }
+        let message = PeerMessage::RelayData {
+            session: self.session,
+            payload: payload.to_vec(),
+        };
+        self.to_relay
+            .send(message)
+            .await
+            .map_err(|_| io::Error::from(io::ErrorKind::ConnectionAborted))?;
+        Ok(())
+    }
+
+    async fn recv(&mut self) -> Result<Bytes> {
+        let frame = self
+            .from_relay
+            .recv()
+            .await
+            .ok_or_else(|| io::Error::from(io::ErrorKind::ConnectionAborted))?;
+        Ok(frame)
+    }
+
+    async fn close(&mut self) -> Result<()> {
+        if !self.closed {
+            self.closed = true;
+            let close = PeerMessage::RelayClose {
+                session: self.session,
+            };
+            let _ = self.to_relay.send(close).await;
+        }
+        Ok(())
+    }
+
+    fn peer_addr(&self) -> SocketAddr {
+        self.relay_addr
+    }
+
+    fn max_message_size(&self) -> usize {
+        self.max_message_size
+    }
+}
+
+impl Drop for RelayedConnection {
+    fn drop(&mut self) {
+        if !self.closed {
+            let _ = self.to_relay.try_send(PeerMessage::RelayClose {
+                session: self.session,
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::network::outbound;
+    use crate::node::OutboundConfig;
+    use std::sync::Arc;
+
+    #[tokio::test]
+    async fn test_relayed_connection_wraps_frames() {
+        let (to_relay, mut relay_rx) = outbound::queue(&OutboundConfig::default(), Arc::default());
+        let (relay_tx, from_relay) = mpsc::channel(4);
+        let addr = "127.0.0.1:9000".parse().unwrap();
+        let mut conn = RelayedConnection::new(7, addr, to_relay, from_relay, 1024);
+
+        conn.send(Bytes::from_static(b"ciphertext")).await.unwrap();
+        assert_eq!(
+            relay_rx.recv().await,
+            PeerMessage::RelayData {
+                session: 7,
+                payload: b"ciphertext".to_vec()
+            }
+        );
+        assert!(conn.send(Bytes::from(vec![0u8; 1024])).await.is_err());
+
+        relay_tx.send(Bytes::from_static(b"reply")).await.unwrap();
+        assert_eq!(&conn.recv().await.unwrap()[..], b"reply");
+
+        // Dropping the connection tells the relay the session is over
+        drop(conn);
+        assert_eq!(
+            relay_rx.recv().await,
+            PeerMessage::RelayClose { session: 7 }
+        );
+        drop(relay_tx);
+    }
+}
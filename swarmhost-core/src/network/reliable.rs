@@ -0,0 +1,507 @@
+// network/reliable.rs - Acknowledged delivery over unreliable datagrams
+
+use crate::node::ReliableConfig;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet, HashMap, VecDeque};
+use std::time::Duration;
+use tokio::time::Instant;
+
+/// Independent sequence space within one connection, so a lost vote does
+/// not hold up acknowledgement of anything else
+pub type Channel = u8;
+
+/// Sequence numbers past the cumulative ack covered by the selective bits
+const SELECTIVE_BITS: u64 = 64;
+
+/// What one side has received on a channel
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Ack {
+    pub channel: Channel,
+    /// Every sequence number below this has arrived
+    pub next: u64,
+    /// Bit `i` set: `next + 1 + i` has arrived too
+    pub selective: u64,
+}
+
+/// One message, numbered within its channel
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Data {
+    pub channel: Channel,
+    pub seq: u64,
+    /// Oldest sequence number the sender still retransmits; anything below
+    /// it was acknowledged or given up on
+    pub floor: u64,
+    pub payload: Vec<u8>,
+}
+
+/// A datagram of the reliable layer: data, acks, or data with acks
+/// piggybacked on it
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Packet {
+    pub acks: Vec<Ack>,
+    pub data: Option<Data>,
+}
+
+/// Something the caller must act on, from [`Reliable::poll`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Output {
+    /// Send this as one datagram
+    Transmit(Packet),
+    /// Never acknowledged within `max_transmissions`; the receiver may or
+    /// may not have it
+    Undeliverable { channel: Channel, seq: u64 },
+}
+
+struct InFlight {
+    payload: Vec<u8>,
+    transmissions: u32,
+    deadline: Instant,
+}
+
+#[derive(Default)]
+struct Outgoing {
+    next_seq: u64,
+    /// Numbered but not yet sent, for want of window
+    waiting: VecDeque<(u64, Vec<u8>)>,
+    in_flight: BTreeMap<u64, InFlight>,
+}
+
+impl Outgoing {
+    fn floor(&self) -> u64 {
+        self.in_flight
+            .keys()
+            .next()
+            .or_else(|| self.waiting.front().map(|(seq, _)| seq))
+            .copied()
+            .unwrap_or(self.next_seq)
+    }
+}
+
+#[derive(Default)]
+struct Incoming {
+    next: u64,
+    /// Arrived past `next`, kept so copies are recognised
+    ahead: BTreeSet<u64>,
+    ack_due: Option<Instant>,
+}
+
+impl Incoming {
+    fn ack(&self, channel: Channel) -> Ack {
+        let selective = self
+            .ahead
+            .range(self.next + 1..self.next + 1 + SELECTIVE_BITS)
+            .fold(0, |bits, seq| bits | 1 << (seq - self.next - 1));
+        Ack {
+            channel,
+            next: self.next,
+            selective,
+        }
+    }
+
+    fn advance_to(&mut self, floor: u64) {
+        if floor > self.next {
+            self.next = floor;
+            self.ahead = self.ahead.split_off(&floor);
+        }
+        while self.ahead.remove(&self.next) {
+            self.next += 1;
+        }
+    }
+}
+
+/// Reliable delivery state for one peer, in both directions
+///
+/// Messages are numbered per channel and retransmitted until acknowledged,
+/// with the timeout derived from the connection's RTT and doubling on each
+/// attempt. The receiver acknowledges cumulatively plus the next
+/// [`SELECTIVE_BITS`] individually, on outgoing data or alone after
+/// `ack_delay`, and delivers each message exactly once, in arrival order.
+/// The caller transmits what [`poll`](Self::poll) hands out.
+pub struct Reliable {
+    config: ReliableConfig,
+    rtt: Option<Duration>,
+    outgoing: HashMap<Channel, Outgoing>,
+    incoming: HashMap<Channel, Incoming>,
+}
+
+impl Reliable {
+    pub fn new(config: &ReliableConfig) -> Self {
+        Self {
+            config: config.clone(),
+            rtt: None,
+            outgoing: HashMap::new(),
+            incoming: HashMap::new(),
+        }
+    }
+
+    /// Use the heartbeat's RTT estimate for retransmission timeouts
+    pub fn set_rtt(&mut self, rtt: Duration) {
+        self.rtt = Some(rtt);
+    }
+
+    /// Queue a message, returning its sequence number; it goes out from the
+    /// next [`poll`](Self::poll)
+    pub fn send(&mut self, channel: Channel, payload: Vec<u8>) -> u64 {
+        let outgoing = self.outgoing.entry(channel).or_default();
+        let seq = outgoing.next_seq;
+        outgoing.next_seq += 1;
+        outgoing.waiting.push_back((seq, payload));
+        seq
+    }
+
+    /// Take in a datagram, returning the message it carried unless that is a
+    /// copy of one already delivered
+    pub fn receive(&mut self, packet: Packet, now: Instant) -> Option<(Channel, Vec<u8>)> {
+        for ack in packet.acks {
+            self.acknowledged(ack);
+        }
+
+        let data = packet.data?;
+        let window = self.config.window as u64;
+        let ack_delay = self.config.ack_delay;
+        let incoming = self.incoming.entry(data.channel).or_default();
+        incoming.advance_to(data.floor);
+        // Acknowledge copies too, in case our last ack was lost
+        incoming.ack_due.get_or_insert(now + ack_delay);
+
+        let fresh = data.seq >= incoming.next
+            && data.seq < incoming.next + window
+            && incoming.ahead.insert(data.seq);
+        if !fresh {
+            return None;
+        }
+        incoming.advance_to(incoming.next);
+        Some((data.channel, data.payload))
+    }
+
+    /// Datagrams due now (new messages, retransmissions and acks) and
+    /// messages given up on
+    pub fn poll(&mut self, now: Instant) -> Vec<Output> {
+        let mut outputs = Vec::new();
+        let mut data = Vec::new();
+        let rto = self.rto();
+
+        for (&channel, outgoing) in &mut self.outgoing {
+            let expired: Vec<u64> = outgoing
+                .in_flight
+                .iter()
+                .filter(|(_, sent)| sent.deadline <= now)
+                .map(|(&seq, _)| seq)
+                .collect();
+            for seq in expired {
+                let sent = outgoing.in_flight.get_mut(&seq).unwrap();
+                if sent.transmissions >= self.config.max_transmissions {
+                    outgoing.in_flight.remove(&seq);
+                    outputs.push(Output::Undeliverable { channel, seq });
+                    continue;
+                }
+                sent.transmissions += 1;
+                sent.deadline = now + backoff(rto, sent.transmissions, self.config.max_rto);
+                data.push((channel, seq, sent.payload.clone()));
+            }
+
+            // The receiver only keeps track of `window` sequence numbers
+            while let Some(&(seq, _)) = outgoing.waiting.front() {
+                if seq >= outgoing.floor() + self.config.window as u64 {
+                    break;
+                }
+                let (seq, payload) = outgoing.waiting.pop_front().unwrap();
+                data.push((channel, seq, payload.clone()));
+                outgoing.in_flight.insert(
+                    seq,
+                    InFlight {
+                        payload,
+                        transmissions: 1,
+                        deadline: now + rto,
+                    },
+                );
+            }
+        }
+
+        // Pending acks ride on the first datagram out, or go alone once due
+        let sending = !data.is_empty();
+        let mut acks: Vec<Ack> = self
+            .incoming
+            .iter_mut()
+            .filter(|(_, incoming)| incoming.ack_due.is_some_and(|due| sending || due <= now))
+            .map(|(&channel, incoming)| {
+                incoming.ack_due = None;
+                incoming.ack(channel)
+            })
+            .collect();
+
+        for (channel, seq, payload) in data {
+            let floor = self.outgoing[&channel].floor();
+            outputs.push(Output::Transmit(Packet {
+                acks: std::mem::take(&mut acks),
+                data: Some(Data {
+                    channel,
+                    seq,
+                    floor,
+                    payload,
+                }),
+            }));
+        }
+        if !acks.is_empty() {
+            outputs.push(Output::Transmit(Packet { acks, data: None }));
+        }
+        outputs
+    }
+
+    /// When [`poll`](Self::poll) next has something to do, if ever
+    pub fn next_deadline(&self) -> Option<Instant> {
+        let retransmits = self
+            .outgoing
+            .values()
+            .flat_map(|outgoing| outgoing.in_flight.values())
+            .map(|sent| sent.deadline);
+        let acks = self
+            .incoming
+            .values()
+            .filter_map(|incoming| incoming.ack_due);
+        retransmits.chain(acks).min()
+    }
+
+    /// Messages sent or waiting that are not yet acknowledged
+    pub fn unacknowledged(&self) -> usize {
+        self.outgoing
+            .values()
+            .map(|outgoing| outgoing.in_flight.len() + outgoing.waiting.len())
+            .sum()
+    }
+
+    fn acknowledged(&mut self, ack: Ack) {
+        let Some(outgoing) = self.outgoing.get_mut(&ack.channel) else {
+            return;
+        };
+        outgoing.in_flight = outgoing.in_flight.split_off(&ack.next);
+        for bit in 0..SELECTIVE_BITS {
+            if ack.selective & 1 << bit != 0 {
+                outgoing.in_flight.remove(&(ack.next + 1 + bit));
+            }
+        }
+    }
+
+    /// First retransmission timeout: twice the RTT plus the peer's ack delay
+    fn rto(&self) -> Duration {
+        match self.rtt {
+            Some(rtt) => {
+                (rtt * 2 + self.config.ack_delay).clamp(self.config.min_rto, self.config.max_rto)
+            }
+            None => self.config.initial_rto,
+        }
+    }
+}
+
+/// Timeout before transmission `transmissions + 1`, doubling each time
+fn backoff(rto: Duration, transmissions: u32, max: Duration) -> Duration {
+    let doublings = transmissions.saturating_sub(1).min(16);
+    (rto * (1 << doublings)).min(max)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+
+    const LATENCY: Duration = Duration::from_millis(10);
+
+    fn config() -> ReliableConfig {
+        ReliableConfig {
+            max_transmissions: 16,
+            ..Default::default()
+        }
+    }
+
+    /// Two endpoints joined by a link that drops each datagram with
+    /// probability `loss`
+    struct Link {
+        ends: [Reliable; 2],
+        /// Datagrams on the wire: arrival time, destination, packet
+        wire: Vec<(Instant, usize, Packet)>,
+        rng: StdRng,
+        loss: f64,
+        now: Instant,
+        delivered: [Vec<Vec<u8>>; 2],
+        undeliverable: Vec<u64>,
+    }
+
+    impl Link {
+        fn new(config: &ReliableConfig, loss: f64) -> Self {
+            let mut ends = [Reliable::new(config), Reliable::new(config)];
+            for end in &mut ends {
+                end.set_rtt(LATENCY * 2);
+            }
+            Self {
+                ends,
+                wire: Vec::new(),
+                rng: StdRng::seed_from_u64(339),
+                loss,
+                now: Instant::now(),
+                delivered: Default::default(),
+                undeliverable: Vec::new(),
+            }
+        }
+
+        fn poll(&mut self) {
+            for from in 0..2 {
+                for output in self.ends[from].poll(self.now) {
+                    match output {
+                        Output::Transmit(packet) => {
+                            if !self.rng.gen_bool(self.loss) {
+                                self.wire.push((self.now + LATENCY, 1 - from, packet));
+                            }
+                        }
+                        Output::Undeliverable { seq, .. } => self.undeliverable.push(seq),
+                    }
+                }
+            }
+        }
+
+        /// Move the clock to the next event and handle it; false once idle
+        fn step(&mut self) -> bool {
+            self.poll();
+            let arrival = self.wire.iter().map(|(at, _, _)| *at).min();
+            let deadlines = self.ends.iter().filter_map(Reliable::next_deadline);
+            let Some(next) = arrival.into_iter().chain(deadlines).min() else {
+                return false;
+            };
+            self.now = self.now.max(next);
+
+            let now = self.now;
+            let (arrived, in_flight) = std::mem::take(&mut self.wire)
+                .into_iter()
+                .partition(|(at, _, _)| *at <= now);
+            self.wire = in_flight;
+            for (_, to, packet) in arrived {
+                if let Some((_, payload)) = self.ends[to].receive(packet, now) {
+                    self.delivered[to].push(payload);
+                }
+            }
+            true
+        }
+    }
+
+    #[test]
+    fn test_every_message_delivered_once_despite_loss() {
+        let mut link = Link::new(&config(), 0.3);
+        for i in 0u32..1000 {
+            link.ends[0].send((i % 3) as Channel, i.to_le_bytes().to_vec());
+        }
+        for i in 0u32..100 {
+            link.ends[1].send(0, i.to_le_bytes().to_vec());
+        }
+
+        let deadline = link.now + Duration::from_secs(600);
+        while link.step() {
+            assert!(link.now < deadline, "never settled");
+        }
+
+        assert!(link.undeliverable.is_empty());
+        for (end, count) in [(1, 1000u32), (0, 100)] {
+            let mut received: Vec<u32> = link.delivered[end]
+                .iter()
+                .map(|bytes| u32::from_le_bytes(bytes[..].try_into().unwrap()))
+                .collect();
+            received.sort_unstable();
+            assert_eq!(received, (0..count).collect::<Vec<_>>());
+        }
+        assert_eq!(link.ends[0].unacknowledged(), 0);
+    }
+
+    #[test]
+    fn test_copies_are_acknowledged_but_not_redelivered() {
+        let now = Instant::now();
+        let mut sender = Reliable::new(&config());
+        let mut receiver = Reliable::new(&config());
+        sender.send(0, b"vote".to_vec());
+        let Some(Output::Transmit(packet)) = sender.poll(now).pop() else {
+            panic!("nothing sent");
+        };
+
+        assert_eq!(
+            receiver.receive(packet.clone(), now),
+            Some((0, b"vote".to_vec()))
+        );
+        assert_eq!(receiver.receive(packet, now), None);
+
+        let later = now + config().ack_delay;
+        assert_eq!(receiver.next_deadline(), Some(later));
+        let outputs = receiver.poll(later);
+        let [Output::Transmit(Packet { acks, data: None })] = &outputs[..] else {
+            panic!("expected a standalone ack, got {:?}", outputs);
+        };
+        assert_eq!(
+            acks[..],
+            [Ack {
+                channel: 0,
+                next: 1,
+                selective: 0
+            }]
+        );
+    }
+
+    #[test]
+    fn test_gives_up_after_max_transmissions() {
+        let config = ReliableConfig {
+            max_transmissions: 3,
+            ..config()
+        };
+        let mut link = Link::new(&config, 1.0);
+        let seq = link.ends[0].send(0, b"lost".to_vec());
+        link.ends[0].send(0, b"lost too".to_vec());
+        while link.step() {}
+        assert_eq!(link.undeliverable, [seq, seq + 1]);
+        assert_eq!(link.ends[0].unacknowledged(), 0);
+
+        // The receiver skips what was given up on and takes what follows
+        link.loss = 0.0;
+        link.ends[0].send(0, b"after".to_vec());
+        while link.step() {}
+        assert_eq!(link.delivered[1], [b"after".to_vec()]);
+    }
+
+    #[test]
+    fn test_selective_ack_spares_what_arrived() {
+        let now = Instant::now();
+        let mut sender = Reliable::new(&config());
+        let mut receiver = Reliable::new(&config());
+        for i in 0u8..3 {
+            sender.send(0, vec![i]);
+        }
+        let packets: Vec<Packet> = sender
+            .poll(now)
+            .into_iter()
+            .filter_map(|output| match output {
+                Output::Transmit(packet) => Some(packet),
+                _ => None,
+            })
+            .collect();
+        // The first is lost
+        receiver.receive(packets[1].clone(), now);
+        receiver.receive(packets[2].clone(), now);
+
+        let ack = receiver.incoming[&0].ack(0);
+        assert_eq!((ack.next, ack.selective), (0, 0b11));
+        sender.receive(
+            Packet {
+                acks: vec![ack],
+                data: None,
+            },
+            now,
+        );
+
+        let resent = sender.poll(now + config().initial_rto);
+        let seqs: Vec<u64> = resent
+            .iter()
+            .filter_map(|output| match output {
+                Output::Transmit(Packet {
+                    data: Some(data), ..
+                }) => Some(data.seq),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(seqs, [0]);
+    }
+}
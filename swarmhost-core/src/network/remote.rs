@@ -0,0 +1,237 @@
+// network/remote.rs - Errors sent to peers: why we refused what they sent or
+// could not answer what they asked, scrubbed of what is ours alone
+
+use crate::consensus::ActionId;
+use crate::crypto::PlayerId;
+use crate::error::{ErrorCategory, ErrorCode, SwarmhostError};
+use serde::{Deserialize, Serialize};
+
+/// Longest message kept, in bytes; longer ones are cut at a character
+const MAX_MESSAGE: usize = 256;
+
+/// Stands in for a filesystem path in a message
+const PATH: &str = "<path>";
+
+/// An error as it crosses the wire to the peer it concerns
+///
+/// Built with [`from_error`](Self::from_error), which keeps the code and
+/// category as they are and scrubs the message; the receiver turns it into
+/// [`SwarmhostError::Remote`] with [`into_error`](Self::into_error).
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RemoteError {
+    pub code: ErrorCode,
+    pub category: ErrorCategory,
+    pub message: String,
+    /// The action the error is about, if it is about one
+    pub action_id: Option<ActionId>,
+    /// The game the error is about, if it is about one
+    pub game_id: Option<String>,
+}
+
+impl RemoteError {
+    /// Describe `error` for a peer, without what is no business of theirs
+    ///
+    /// Internal, configuration and I/O errors keep only their code: their
+    /// messages are about this machine. Other messages lose filesystem
+    /// paths and control characters, and are cut to `MAX_MESSAGE` bytes.
+    pub fn from_error(error: &SwarmhostError) -> Self {
+        let code = error.code();
+        let message = if withheld(code) {
+            "details withheld".to_string()
+        } else {
+            scrub(&error.to_string())
+        };
+        RemoteError {
+            code,
+            category: code.category(),
+            message,
+            action_id: error.action_id(),
+            game_id: error.game_id().map(str::to_string),
+        }
+    }
+
+    /// The error as the caller who sent `peer` what it refused sees it
+    ///
+    /// The message is the peer's, so it is scrubbed again here; a peer
+    /// need not follow our rules.
+    pub fn into_error(self, peer: PlayerId) -> SwarmhostError {
+        SwarmhostError::Remote {
+            peer,
+            code: self.code,
+            message: scrub(&self.message),
+        }
+    }
+}
+
+/// Whether a message with `code` says more about us than about the peer's
+/// request
+fn withheld(code: ErrorCode) -> bool {
+    code == ErrorCode::Io
+        || matches!(
+            code.category(),
+            ErrorCategory::Internal | ErrorCategory::Config
+        )
+}
+
+/// `message` with paths replaced by `PATH`, control characters by spaces,
+/// and cut to `MAX_MESSAGE` bytes
+fn scrub(message: &str) -> String {
+    // Control characters first, so a path after a line break is a word too
+    let message: String = message
+        .chars()
+        .map(|c| if c.is_control() { ' ' } else { c })
+        .collect();
+    let mut scrubbed = String::with_capacity(message.len().min(MAX_MESSAGE));
+    for (i, word) in message.split(' ').enumerate() {
+        if i > 0 {
+            scrubbed.push(' ');
+        }
+        let core = word
+            .trim_start_matches(['\'', '"', '(', '[', '<', '`'])
+            .trim_end_matches(['\'', '"', ')', ']', '>', '`', ',', ';', ':', '.']);
+        if is_path(core) {
+            scrubbed.push_str(&word.replacen(core, PATH, 1));
+        } else {
+            scrubbed.push_str(word);
+        }
+    }
+    if scrubbed.len() > MAX_MESSAGE {
+        let mut end = MAX_MESSAGE;
+        while !scrubbed.is_char_boundary(end) {
+            end -= 1;
+        }
+        scrubbed.truncate(end);
+    }
+    scrubbed
+}
+
+/// Whether `word` looks like a filesystem path: absolute, home or relative
+/// to the current directory on Unix, or a drive or share on Windows
+fn is_path(word: &str) -> bool {
+    let bytes = word.as_bytes();
+    let drive = bytes.len() > 2
+        && bytes[0].is_ascii_alphabetic()
+        && bytes[1] == b':'
+        && matches!(bytes[2], b'\\' | b'/');
+    (word.starts_with('/') && word.len() > 1)
+        || word.starts_with("~/")
+        || word.starts_with("./")
+        || word.starts_with("../")
+        || word.starts_with('\\')
+        || drive
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::network::MessageCodec;
+    use crate::network::PeerMessage;
+    use crate::network::codec::BincodeCodec;
+    use std::io;
+
+    #[test]
+    fn test_scrubbing_leaves_no_paths_or_internals() {
+        // Paths go wherever they are in the message, whatever wraps them
+        let loading = SwarmhostError::validation(
+            "snapshot '/var/lib/swarmhost/chess/0042.snap' does not match ~/keys/node.key, \
+             nor C:\\Users\\ada\\state.db: (./data/log)",
+        );
+        let sent = RemoteError::from_error(&loading);
+        assert_eq!(
+            sent.message,
+            "Validation error: snapshot '<path>' does not match <path>, \
+             nor <path>: (<path>)"
+        );
+        assert_eq!(sent.code, ErrorCode::Validation);
+
+        // Words with slashes that are not paths stay
+        let ratio = SwarmhostError::validation("3/4 of the validators and/or a quorum");
+        assert_eq!(
+            RemoteError::from_error(&ratio).message,
+            "Validation error: 3/4 of the validators and/or a quorum"
+        );
+
+        // Messages about this machine keep only their code
+        let missing = io::Error::new(io::ErrorKind::NotFound, "/etc/swarmhost/node.toml");
+        let withheld = [
+            SwarmhostError::from(missing),
+            SwarmhostError::node("state_manager poisoned at src/node/mod.rs:812"),
+            SwarmhostError::Config("data_dir /srv/swarmhost is read-only".to_string()),
+        ];
+        for error in withheld {
+            let sent = RemoteError::from_error(&error);
+            assert_eq!(sent.message, "details withheld", "{}", error);
+            assert_eq!(sent.code, error.code());
+        }
+
+        // Control characters cannot forge log lines, and length is bounded
+        let forged = SwarmhostError::validation(format!("bad\nINFO forged{}", "x".repeat(500)));
+        let sent = RemoteError::from_error(&forged);
+        assert!(!sent.message.contains('\n'));
+        assert!(sent.message.len() <= MAX_MESSAGE);
+        let multibyte = SwarmhostError::validation("é".repeat(300));
+        assert!(RemoteError::from_error(&multibyte).message.len() <= MAX_MESSAGE);
+    }
+
+    #[test]
+    fn test_a_peer_unscrubbed_message_is_scrubbed_on_receipt() {
+        let careless = RemoteError {
+            code: ErrorCode::Validation,
+            category: ErrorCategory::Validation,
+            message: "cannot open\n/home/host/game.db\r\n".to_string(),
+            action_id: None,
+            game_id: None,
+        };
+        let peer = [7; 32];
+        match careless.into_error(peer) {
+            SwarmhostError::Remote {
+                peer: from,
+                code,
+                message,
+            } => {
+                assert_eq!(from, peer);
+                assert_eq!(code, ErrorCode::Validation);
+                assert_eq!(message, "cannot open <path>  ");
+            }
+            other => panic!("expected a remote error, got {}", other),
+        }
+    }
+
+    #[test]
+    fn test_remote_errors_keep_their_code_and_category_on_the_wire() {
+        let codec = BincodeCodec;
+        for code in ErrorCode::ALL {
+            let error = RemoteError {
+                code,
+                category: code.category(),
+                message: format!("{}", code),
+                action_id: Some([3; 32]),
+                game_id: Some("chess".to_string()),
+            };
+            let message = PeerMessage::Error {
+                id: Some(9),
+                error: error.clone(),
+            };
+            let decoded = codec.decode(&codec.encode(&message).unwrap()).unwrap();
+            assert_eq!(decoded, message);
+
+            let received = error.into_error([1; 32]);
+            assert_eq!(received.code(), code);
+            assert_eq!(received.category(), code.category());
+            assert_eq!(received.is_retryable(), code.is_retryable());
+        }
+    }
+
+    #[test]
+    fn test_context_is_carried_from_the_error() {
+        let pruned = SwarmhostError::Pruned {
+            game_id: "chess".to_string(),
+            earliest: 40,
+        };
+        let sent = RemoteError::from_error(&pruned);
+        assert_eq!(sent.code, ErrorCode::Pruned);
+        assert_eq!(sent.category, ErrorCategory::Validation);
+        assert_eq!(sent.game_id.as_deref(), Some("chess"));
+        assert_eq!(sent.action_id, None);
+    }
+}
@@ -0,0 +1,181 @@
+// network/resume.rs - Session resumption tokens
+
+use crate::crypto::{Hash, PlayerId, hash_multiple, short_id};
+use crate::error::{Result, SwarmhostError};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt;
+use std::time::Duration;
+use tokio::time::Instant;
+
+/// Domain separator for token MACs
+const TOKEN_MAC_CONTEXT: &[u8] = b"swarmhost resumption token v1";
+
+/// Lets a peer pick up a dropped session where it left off
+///
+/// Given out once the handshake is done and presented in the next one; the
+/// issuer alone can check it, since the MAC is keyed by a secret that never
+/// leaves the issuing node.
+#[derive(Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ResumptionToken {
+    pub id: [u8; 16],
+    pub mac: Hash,
+}
+
+impl fmt::Debug for ResumptionToken {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ResumptionToken")
+            .field("id", &format_args!("{:02x?}", &self.id[..4]))
+            .finish_non_exhaustive()
+    }
+}
+
+struct Issued {
+    peer: PlayerId,
+    secret: [u8; 32],
+    expires: Instant,
+}
+
+/// Tokens we issued and that have not been used yet
+///
+/// Each connection gets a fresh secret, and a peer holds at most one token:
+/// issuing another revokes the last. A token is spent by the first attempt
+/// to redeem it, valid or not.
+#[derive(Default)]
+pub struct Resumption {
+    issued: HashMap<[u8; 16], Issued>,
+}
+
+impl Resumption {
+    /// A new token for `peer`, valid for `lifetime`
+    pub fn issue(&mut self, peer: PlayerId, lifetime: Duration, now: Instant) -> ResumptionToken {
+        self.expire(now);
+        self.revoke(&peer);
+        let id: [u8; 16] = rand::random();
+        let secret: [u8; 32] = rand::random();
+        self.issued.insert(
+            id,
+            Issued {
+                peer,
+                secret,
+                expires: now + lifetime,
+            },
+        );
+        ResumptionToken {
+            id,
+            mac: mac(&secret, &id, &peer),
+        }
+    }
+
+    /// Whether `peer` holds a token that has not expired
+    pub fn is_issued(&self, peer: &PlayerId, now: Instant) -> bool {
+        self.issued
+            .values()
+            .any(|issued| issued.peer == *peer && issued.expires > now)
+    }
+
+    /// Spend `token`, failing unless we issued it to `peer` and it is
+    /// still valid
+    pub fn redeem(&mut self, peer: &PlayerId, token: &ResumptionToken, now: Instant) -> Result<()> {
+        self.expire(now);
+        let Some(issued) = self.issued.remove(&token.id) else {
+            return Err(SwarmhostError::Peer(
+                "Unknown, expired or already used resumption token".to_string(),
+            ));
+        };
+        if issued.peer != *peer {
+            return Err(SwarmhostError::Peer(format!(
+                "Resumption token was issued to {}, not {}",
+                short_id(&issued.peer),
+                short_id(peer)
+            )));
+        }
+        if !equal(&mac(&issued.secret, &token.id, peer), &token.mac) {
+            return Err(SwarmhostError::Peer(
+                "Resumption token has a bad MAC".to_string(),
+            ));
+        }
+        Ok(())
+    }
+
+    /// Forget any token issued to `peer`
+    pub fn revoke(&mut self, peer: &PlayerId) {
+        self.issued.retain(|_, issued| issued.peer != *peer);
+    }
+
+    pub fn len(&self) -> usize {
+        self.issued.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.issued.is_empty()
+    }
+
+    fn expire(&mut self, now: Instant) {
+        self.issued.retain(|_, issued| issued.expires > now);
+    }
+}
+
+fn mac(secret: &[u8; 32], id: &[u8; 16], peer: &PlayerId) -> Hash {
+    hash_multiple(&[TOKEN_MAC_CONTEXT, secret, id, peer])
+}
+
+/// Compare without stopping at the first difference
+fn equal(a: &Hash, b: &Hash) -> bool {
+    a.iter().zip(b).fold(0, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::KeyPair;
+
+    const LIFETIME: Duration = Duration::from_secs(60);
+
+    #[test]
+    fn test_token_is_single_use() {
+        let peer = KeyPair::generate().public_key();
+        let now = Instant::now();
+        let mut resumption = Resumption::default();
+        let token = resumption.issue(peer, LIFETIME, now);
+        assert!(resumption.is_issued(&peer, now));
+
+        assert!(resumption.redeem(&peer, &token, now).is_ok());
+        assert!(resumption.redeem(&peer, &token, now).is_err());
+        assert!(!resumption.is_issued(&peer, now));
+    }
+
+    #[test]
+    fn test_token_expires() {
+        let peer = KeyPair::generate().public_key();
+        let now = Instant::now();
+        let mut resumption = Resumption::default();
+        let token = resumption.issue(peer, LIFETIME, now);
+
+        let later = now + LIFETIME;
+        assert!(!resumption.is_issued(&peer, later));
+        assert!(resumption.redeem(&peer, &token, later).is_err());
+        assert!(resumption.is_empty());
+    }
+
+    #[test]
+    fn test_token_is_bound_to_peer_and_secret() {
+        let peer = KeyPair::generate().public_key();
+        let other = KeyPair::generate().public_key();
+        let now = Instant::now();
+        let mut resumption = Resumption::default();
+
+        let token = resumption.issue(peer, LIFETIME, now);
+        assert!(resumption.redeem(&other, &token, now).is_err());
+
+        let mut forged = resumption.issue(peer, LIFETIME, now);
+        forged.mac[0] ^= 1;
+        assert!(resumption.redeem(&peer, &forged, now).is_err());
+
+        // A new token revokes the last
+        let first = resumption.issue(peer, LIFETIME, now);
+        let second = resumption.issue(peer, LIFETIME, now);
+        assert!(resumption.redeem(&peer, &first, now).is_err());
+        assert!(resumption.redeem(&peer, &second, now).is_ok());
+    }
+}
@@ -0,0 +1,136 @@
+// network/score.rs - Peer reputation from decaying demerits
+
+use std::fmt;
+use std::time::Duration;
+use tokio::time::Instant;
+
+/// Misbehaviour that costs a peer reputation
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Offense {
+    /// A message that does not decode
+    MalformedMessage,
+    /// A proposal or vote whose signature does not verify
+    InvalidSignature,
+    /// No vote within the consensus timeout
+    VoteTimeout,
+    /// Round-trip time above `max_rtt`; only counted when
+    /// `penalize_latency` is set
+    HighLatency,
+    /// Frames beyond the inbound limits
+    Flooding,
+    /// Something the sender has no standing to send, e.g. a vote from
+    /// outside the validator set
+    ProtocolViolation,
+}
+
+impl Offense {
+    /// Demerits added per occurrence, by severity
+    pub fn weight(self) -> f64 {
+        match self {
+            Offense::MalformedMessage => 25.0,
+            Offense::InvalidSignature => 40.0,
+            Offense::VoteTimeout => 10.0,
+            Offense::HighLatency => 5.0,
+            Offense::Flooding => 20.0,
+            Offense::ProtocolViolation => 30.0,
+        }
+    }
+}
+
+impl fmt::Display for Offense {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Offense::MalformedMessage => "malformed message",
+            Offense::InvalidSignature => "invalid signature",
+            Offense::VoteTimeout => "vote timeout",
+            Offense::HighLatency => "high latency",
+            Offense::Flooding => "flooding",
+            Offense::ProtocolViolation => "protocol violation",
+        };
+        f.write_str(name)
+    }
+}
+
+/// Demerits held against one connected peer
+///
+/// Demerits decay exponentially, halving every `half_life`, so occasional
+/// slips are forgiven while sustained misbehaviour crosses the ban
+/// threshold.
+#[derive(Debug, Clone)]
+pub struct PeerScore {
+    demerits: f64,
+    updated: Instant,
+}
+
+impl PeerScore {
+    pub fn new(now: Instant) -> Self {
+        Self {
+            demerits: 0.0,
+            updated: now,
+        }
+    }
+
+    /// Demerits after decaying to `now`
+    pub fn demerits(&self, now: Instant, half_life: Duration) -> f64 {
+        let elapsed = now.saturating_duration_since(self.updated);
+        self.demerits * 0.5f64.powf(elapsed.as_secs_f64() / half_life.as_secs_f64())
+    }
+
+    /// Add an offense, returning the decayed total including it
+    pub fn penalize(&mut self, offense: Offense, now: Instant, half_life: Duration) -> f64 {
+        self.demerits = self.demerits(now, half_life) + offense.weight();
+        self.updated = now;
+        self.demerits
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HALF_LIFE: Duration = Duration::from_secs(60);
+
+    fn close(a: f64, b: f64) -> bool {
+        (a - b).abs() < 1e-9
+    }
+
+    #[test]
+    fn test_demerits_halve_every_half_life() {
+        let start = Instant::now();
+        let mut score = PeerScore::new(start);
+        assert_eq!(score.demerits(start, HALF_LIFE), 0.0);
+
+        assert!(close(
+            score.penalize(Offense::InvalidSignature, start, HALF_LIFE),
+            40.0
+        ));
+        assert!(close(score.demerits(start + HALF_LIFE, HALF_LIFE), 20.0));
+        assert!(close(score.demerits(start + HALF_LIFE * 3, HALF_LIFE), 5.0));
+        assert!(close(
+            score.demerits(start + HALF_LIFE / 2, HALF_LIFE),
+            40.0 / 2f64.sqrt()
+        ));
+    }
+
+    #[test]
+    fn test_penalties_accumulate_on_decayed_total() {
+        let start = Instant::now();
+        let mut score = PeerScore::new(start);
+
+        score.penalize(Offense::MalformedMessage, start, HALF_LIFE);
+        score.penalize(Offense::MalformedMessage, start, HALF_LIFE);
+        // 50 decays to 25 before the next 25 is added
+        let total = score.penalize(Offense::MalformedMessage, start + HALF_LIFE, HALF_LIFE);
+        assert!(close(total, 50.0));
+
+        // Reading the score does not change it
+        assert!(close(score.demerits(start + HALF_LIFE, HALF_LIFE), 50.0));
+    }
+
+    #[test]
+    fn test_offenses_weighted_by_severity() {
+        assert!(Offense::InvalidSignature.weight() > Offense::MalformedMessage.weight());
+        assert!(Offense::MalformedMessage.weight() > Offense::VoteTimeout.weight());
+        assert!(Offense::VoteTimeout.weight() > Offense::HighLatency.weight());
+    }
+}
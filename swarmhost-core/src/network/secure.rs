@@ -0,0 +1,491 @@
+// network/secure.rs - Noise handshake and rekeying AEAD sessions
+
+use super::handshake::Role;
+use super::transport::Connection;
+use crate::crypto::{self, KeyPair, PlayerId, hash_multiple, short_id};
+use crate::error::{Result, SwarmhostError};
+use crate::node::{CipherSuite, SecurityConfig};
+use aes_gcm::Aes256Gcm;
+use bytes::Bytes;
+use chacha20poly1305::ChaCha20Poly1305;
+use chacha20poly1305::aead::{Aead, KeyInit};
+use serde::{Deserialize, Serialize};
+
+/// Largest Noise handshake message
+const NOISE_MAX_MESSAGE: usize = 65535;
+
+/// Domain separator for the signature binding a Noise static key to a player
+const IDENTITY_CONTEXT: &[u8] = b"swarmhost-noise-identity-v1";
+
+/// Domain separator for deriving the next key when rekeying
+const REKEY_CONTEXT: &[u8] = b"swarmhost-rekey-v1";
+
+/// Which Noise handshake pattern to run
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Pattern {
+    /// Both static keys are exchanged during the handshake
+    XX,
+    /// The initiator already knows who it is dialing (e.g. from discovery),
+    /// saving a round trip
+    IK { responder: PlayerId },
+}
+
+impl Pattern {
+    fn noise_params(self, suite: CipherSuite) -> String {
+        let pattern = match self {
+            Pattern::XX => "XX",
+            Pattern::IK { .. } => "IK",
+        };
+        let cipher = match suite {
+            CipherSuite::ChaCha20Poly1305 => "ChaChaPoly",
+            CipherSuite::Aes256Gcm => "AESGCM",
+        };
+        format!("Noise_{}_25519_{}_BLAKE2s", pattern, cipher)
+    }
+}
+
+/// Sent by each side once the handshake is done, proving that the player id
+/// owns the Noise static key used in this particular handshake
+#[derive(Serialize, Deserialize)]
+struct IdentityProof {
+    player_id: PlayerId,
+    signature: Vec<u8>,
+}
+
+/// Bytes signed in an [`IdentityProof`]; the role stops a proof being
+/// reflected back at its sender
+fn proof_bytes(role: Role, handshake_hash: &[u8], noise_static: &[u8]) -> Vec<u8> {
+    let role = match role {
+        Role::Initiator => b'i',
+        Role::Responder => b'r',
+    };
+    let mut bytes = Vec::with_capacity(IDENTITY_CONTEXT.len() + 1 + 64);
+    bytes.extend_from_slice(IDENTITY_CONTEXT);
+    bytes.push(role);
+    bytes.extend_from_slice(handshake_hash);
+    bytes.extend_from_slice(noise_static);
+    bytes
+}
+
+fn noise_error(stage: &str, e: snow::Error) -> SwarmhostError {
+    let reason = match e {
+        snow::Error::Decrypt => {
+            "decryption failed: the negotiation was altered in transit (downgrade attempt?), \
+             the transcript was replayed, or the peer holds a different key"
+                .to_string()
+        }
+        other => other.to_string(),
+    };
+    SwarmhostError::Peer(format!("Noise handshake failed {}: {}", stage, reason))
+}
+
+/// Run a Noise handshake over `conn` and authenticate the peer's player id
+///
+/// `prologue` must be identical on both sides; passing the transcript of
+/// whatever was negotiated in the clear makes any tampering with it fail
+/// the handshake. Every failure is a [`SwarmhostError::Peer`] with the reason.
+pub async fn handshake(
+    conn: &mut dyn Connection,
+    keypair: &KeyPair,
+    role: Role,
+    suite: CipherSuite,
+    pattern: Pattern,
+    prologue: &[u8],
+    config: &SecurityConfig,
+) -> Result<(SecureSession, PlayerId)> {
+    let params: snow::params::NoiseParams = pattern
+        .noise_params(suite)
+        .parse()
+        .map_err(|e| noise_error("to start", e))?;
+    let secret = keypair.x25519_secret();
+    let mut builder = snow::Builder::new(params)
+        .local_private_key(&secret)
+        .prologue(prologue);
+
+    let expected_static;
+    if let (Role::Initiator, Pattern::IK { responder }) = (role, pattern) {
+        expected_static = crypto::x25519_public(&responder).map_err(|e| {
+            SwarmhostError::Peer(format!("Cannot dial {}: {}", short_id(&responder), e))
+        })?;
+        builder = builder.remote_public_key(&expected_static);
+    }
+
+    let mut noise = match role {
+        Role::Initiator => builder.build_initiator(),
+        Role::Responder => builder.build_responder(),
+    }
+    .map_err(|e| noise_error("to start", e))?;
+
+    let mut buf = vec![0u8; NOISE_MAX_MESSAGE];
+    while !noise.is_handshake_finished() {
+        if noise.is_my_turn() {
+            let len = noise
+                .write_message(&[], &mut buf)
+                .map_err(|e| noise_error("writing", e))?;
+            conn.send(Bytes::copy_from_slice(&buf[..len]))
+                .await
+                .map_err(|e| SwarmhostError::Peer(format!("Noise handshake interrupted: {}", e)))?;
+        } else {
+            let message = conn.recv().await.map_err(|e| {
+                SwarmhostError::Peer(format!("Peer left during the Noise handshake: {}", e))
+            })?;
+            noise
+                .read_message(&message, &mut buf)
+                .map_err(|e| noise_error("reading", e))?;
+        }
+    }
+
+    let handshake_hash = noise.get_handshake_hash().to_vec();
+    let remote_static = noise
+        .get_remote_static()
+        .ok_or_else(|| SwarmhostError::Peer("Peer sent no Noise static key".to_string()))?
+        .to_vec();
+    let (i2r, r2i) = noise.dangerously_get_raw_split();
+    let mut session = SecureSession::new(suite, role, i2r, r2i, config);
+
+    let local_static = crypto::x25519_public(&keypair.public_key())?;
+    let proof = IdentityProof {
+        player_id: keypair.public_key(),
+        signature: keypair.sign(&proof_bytes(role, &handshake_hash, &local_static)),
+    };
+    let proof =
+        bincode::serialize(&proof).map_err(|e| SwarmhostError::Serialization(e.to_string()))?;
+    conn.send(Bytes::from(session.seal(&proof)?))
+        .await
+        .map_err(|e| SwarmhostError::Peer(format!("Noise handshake interrupted: {}", e)))?;
+
+    let sealed = conn.recv().await.map_err(|e| {
+        SwarmhostError::Peer(format!("Peer left before proving its identity: {}", e))
+    })?;
+    let proof = session.open(&sealed).map_err(|_| {
+        SwarmhostError::Peer(
+            "Identity proof did not decrypt: handshake transcript replayed".to_string(),
+        )
+    })?;
+    let proof: IdentityProof = bincode::deserialize(&proof)
+        .map_err(|e| SwarmhostError::Peer(format!("Malformed identity proof: {}", e)))?;
+
+    let remote_role = match role {
+        Role::Initiator => Role::Responder,
+        Role::Responder => Role::Initiator,
+    };
+    crypto::verify_signature(
+        &proof.player_id,
+        &proof_bytes(remote_role, &handshake_hash, &remote_static),
+        &proof.signature,
+    )
+    .map_err(|_| {
+        SwarmhostError::Peer(format!(
+            "Wrong key: {} did not sign this handshake",
+            short_id(&proof.player_id)
+        ))
+    })?;
+    if crypto::x25519_public(&proof.player_id)? != remote_static[..] {
+        return Err(SwarmhostError::Peer(format!(
+            "Wrong key: Noise static key does not belong to {}",
+            short_id(&proof.player_id)
+        )));
+    }
+
+    Ok((session, proof.player_id))
+}
+
+enum AeadCipher {
+    ChaCha20Poly1305(Box<ChaCha20Poly1305>),
+    Aes256Gcm(Box<Aes256Gcm>),
+}
+
+impl AeadCipher {
+    fn new(suite: CipherSuite, key: &[u8; 32]) -> Self {
+        match suite {
+            CipherSuite::ChaCha20Poly1305 => {
+                AeadCipher::ChaCha20Poly1305(Box::new(ChaCha20Poly1305::new(key.into())))
+            }
+            CipherSuite::Aes256Gcm => AeadCipher::Aes256Gcm(Box::new(Aes256Gcm::new(key.into()))),
+        }
+    }
+
+    fn seal(&self, nonce: &[u8; 12], plaintext: &[u8]) -> Result<Vec<u8>> {
+        let result = match self {
+            AeadCipher::ChaCha20Poly1305(cipher) => cipher.encrypt(nonce.into(), plaintext),
+            AeadCipher::Aes256Gcm(cipher) => cipher.encrypt(nonce.into(), plaintext),
+        };
+        result.map_err(|_| SwarmhostError::crypto("Encryption failed"))
+    }
+
+    fn open(&self, nonce: &[u8; 12], ciphertext: &[u8]) -> Result<Vec<u8>> {
+        let result = match self {
+            AeadCipher::ChaCha20Poly1305(cipher) => cipher.decrypt(nonce.into(), ciphertext),
+            AeadCipher::Aes256Gcm(cipher) => cipher.decrypt(nonce.into(), ciphertext),
+        };
+        result.map_err(|_| {
+            SwarmhostError::crypto("Decryption failed: frame tampered or out of order")
+        })
+    }
+}
+
+/// Key, cipher and counters for one direction of a session
+struct Direction {
+    suite: CipherSuite,
+    key: [u8; 32],
+    cipher: AeadCipher,
+    messages: u64,
+    bytes: u64,
+    generation: u64,
+}
+
+impl Direction {
+    fn new(suite: CipherSuite, key: [u8; 32]) -> Self {
+        Self {
+            suite,
+            cipher: AeadCipher::new(suite, &key),
+            key,
+            messages: 0,
+            bytes: 0,
+            generation: 0,
+        }
+    }
+
+    /// Count a frame, moving to the next key once either limit is reached
+    fn advance(&mut self, len: usize, limits: (u64, u64)) {
+        self.messages += 1;
+        self.bytes += len as u64;
+        if self.messages >= limits.0 || self.bytes >= limits.1 {
+            self.key = hash_multiple(&[REKEY_CONTEXT, &self.key]);
+            self.cipher = AeadCipher::new(self.suite, &self.key);
+            self.messages = 0;
+            self.bytes = 0;
+            self.generation += 1;
+        }
+    }
+}
+
+/// Directional AEAD keys and nonce counters for one connection
+///
+/// Each direction derives a fresh key after the configured number of
+/// messages or bytes. Both ends count the same frames, so they switch keys
+/// in step without any extra messages.
+pub struct SecureSession {
+    suite: CipherSuite,
+    send: Direction,
+    recv: Direction,
+    limits: (u64, u64),
+}
+
+impl SecureSession {
+    /// Session from the initiator-to-responder and responder-to-initiator keys
+    pub fn new(
+        suite: CipherSuite,
+        role: Role,
+        i2r: [u8; 32],
+        r2i: [u8; 32],
+        config: &SecurityConfig,
+    ) -> Self {
+        let (send_key, recv_key) = match role {
+            Role::Initiator => (i2r, r2i),
+            Role::Responder => (r2i, i2r),
+        };
+
+        Self {
+            suite,
+            send: Direction::new(suite, send_key),
+            recv: Direction::new(suite, recv_key),
+            limits: (config.rekey_after_messages, config.rekey_after_bytes),
+        }
+    }
+
+    pub fn suite(&self) -> CipherSuite {
+        self.suite
+    }
+
+    /// Times each direction has rekeyed, as (send, recv)
+    pub fn rekeys(&self) -> (u64, u64) {
+        (self.send.generation, self.recv.generation)
+    }
+
+    /// Encrypt the next outgoing frame
+    pub fn seal(&mut self, plaintext: &[u8]) -> Result<Vec<u8>> {
+        let nonce = nonce_for(self.send.messages);
+        let sealed = self.send.cipher.seal(&nonce, plaintext)?;
+        self.send.advance(plaintext.len(), self.limits);
+        Ok(sealed)
+    }
+
+    /// Decrypt the next incoming frame
+    pub fn open(&mut self, ciphertext: &[u8]) -> Result<Vec<u8>> {
+        let nonce = nonce_for(self.recv.messages);
+        let plaintext = self.recv.cipher.open(&nonce, ciphertext)?;
+        self.recv.advance(plaintext.len(), self.limits);
+        Ok(plaintext)
+    }
+}
+
+fn nonce_for(counter: u64) -> [u8; 12] {
+    let mut nonce = [0u8; 12];
+    nonce[4..].copy_from_slice(&counter.to_be_bytes());
+    nonce
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::network::{MemoryNetwork, TcpTransport, Transport};
+    use crate::node::NetworkConfig;
+
+    type Side = Result<(SecureSession, PlayerId, Box<dyn Connection>)>;
+
+    fn limits(messages: u64, bytes: u64) -> SecurityConfig {
+        SecurityConfig {
+            rekey_after_messages: messages,
+            rekey_after_bytes: bytes,
+            ..Default::default()
+        }
+    }
+
+    /// One end of a handshake; the connection is dropped if it fails so the
+    /// other end is not left waiting
+    async fn side(
+        mut conn: Box<dyn Connection>,
+        keypair: &KeyPair,
+        role: Role,
+        pattern: Pattern,
+        prologue: &[u8],
+    ) -> Side {
+        let config = SecurityConfig::default();
+        let suite = CipherSuite::ChaCha20Poly1305;
+        let (session, peer) = handshake(
+            conn.as_mut(),
+            keypair,
+            role,
+            suite,
+            pattern,
+            prologue,
+            &config,
+        )
+        .await?;
+        Ok((session, peer, conn))
+    }
+
+    async fn connected(transport: &dyn Transport) -> (Box<dyn Connection>, Box<dyn Connection>) {
+        let listener = transport
+            .listen("127.0.0.1:0".parse().unwrap())
+            .await
+            .unwrap();
+        let (dialed, accepted) =
+            tokio::join!(transport.dial(listener.local_addr()), listener.accept());
+        (dialed.unwrap(), accepted.unwrap())
+    }
+
+    /// XX and IK over a real transport, then traffic both ways
+    async fn interop(transport: &dyn Transport) {
+        let (alice, bob) = (KeyPair::generate(), KeyPair::generate());
+
+        for pattern in [
+            Pattern::XX,
+            Pattern::IK {
+                responder: bob.public_key(),
+            },
+        ] {
+            let (a, b) = connected(transport).await;
+            let (ours, theirs) = tokio::join!(
+                side(a, &alice, Role::Initiator, pattern, b"prologue"),
+                side(b, &bob, Role::Responder, pattern, b"prologue"),
+            );
+            let (mut ours, bob_id, mut a) = ours.unwrap();
+            let (mut theirs, alice_id, mut b) = theirs.unwrap();
+            assert_eq!(bob_id, bob.public_key());
+            assert_eq!(alice_id, alice.public_key());
+
+            let sealed = ours.seal(b"hello bob").unwrap();
+            a.send(Bytes::from(sealed)).await.unwrap();
+            assert_eq!(theirs.open(&b.recv().await.unwrap()).unwrap(), b"hello bob");
+
+            let sealed = theirs.seal(b"hi alice").unwrap();
+            b.send(Bytes::from(sealed)).await.unwrap();
+            assert_eq!(ours.open(&a.recv().await.unwrap()).unwrap(), b"hi alice");
+        }
+    }
+
+    #[tokio::test]
+    async fn test_interop_over_memory() {
+        interop(&MemoryNetwork::new().transport(&NetworkConfig::default())).await;
+    }
+
+    #[tokio::test]
+    async fn test_interop_over_tcp() {
+        interop(&TcpTransport::new(&NetworkConfig::default())).await;
+    }
+
+    #[tokio::test]
+    async fn test_altered_negotiation_detected() {
+        let transport = MemoryNetwork::new().transport(&NetworkConfig::default());
+        let (a, b) = connected(&transport).await;
+        let (alice, bob) = (KeyPair::generate(), KeyPair::generate());
+
+        // What each side believes was negotiated differs
+        let (ours, theirs) = tokio::join!(
+            side(a, &alice, Role::Initiator, Pattern::XX, b"mode=Required"),
+            side(b, &bob, Role::Responder, Pattern::XX, b"mode=Plaintext"),
+        );
+        match ours {
+            Err(SwarmhostError::Peer(reason)) => {
+                assert!(reason.contains("downgrade"), "{}", reason)
+            }
+            _ => panic!("expected a peer error"),
+        }
+        assert!(matches!(theirs, Err(SwarmhostError::Peer(_))));
+    }
+
+    #[tokio::test]
+    async fn test_ik_to_wrong_key_fails() {
+        let transport = MemoryNetwork::new().transport(&NetworkConfig::default());
+        let (a, b) = connected(&transport).await;
+        let (alice, bob) = (KeyPair::generate(), KeyPair::generate());
+        let expected = Pattern::IK {
+            responder: KeyPair::generate().public_key(),
+        };
+
+        let (ours, theirs) = tokio::join!(
+            side(a, &alice, Role::Initiator, expected, b"prologue"),
+            side(b, &bob, Role::Responder, expected, b"prologue"),
+        );
+        assert!(matches!(ours, Err(SwarmhostError::Peer(_))));
+        assert!(matches!(theirs, Err(SwarmhostError::Peer(_))));
+    }
+
+    #[test]
+    fn test_rekey_in_step() {
+        let config = limits(3, u64::MAX);
+        let suite = CipherSuite::Aes256Gcm;
+        let mut a = SecureSession::new(suite, Role::Initiator, [1; 32], [2; 32], &config);
+        let mut b = SecureSession::new(suite, Role::Responder, [1; 32], [2; 32], &config);
+
+        for i in 0..10u8 {
+            let sealed = a.seal(&[i; 40]).unwrap();
+            assert_eq!(b.open(&sealed).unwrap(), [i; 40]);
+        }
+        assert_eq!(a.rekeys(), (3, 0));
+        assert_eq!(b.rekeys(), (0, 3));
+
+        // A peer that never rekeys cannot read past the first key
+        let never = limits(u64::MAX, u64::MAX);
+        let mut stale = SecureSession::new(suite, Role::Responder, [1; 32], [2; 32], &never);
+        let mut fresh = SecureSession::new(suite, Role::Initiator, [1; 32], [2; 32], &config);
+        for _ in 0..3 {
+            stale.open(&fresh.seal(b"x").unwrap()).unwrap();
+        }
+        assert!(stale.open(&fresh.seal(b"x").unwrap()).is_err());
+    }
+
+    #[test]
+    fn test_rekey_after_bytes() {
+        let config = limits(u64::MAX, 100);
+        let suite = CipherSuite::ChaCha20Poly1305;
+        let mut a = SecureSession::new(suite, Role::Initiator, [1; 32], [2; 32], &config);
+
+        a.seal(&[0; 60]).unwrap();
+        assert_eq!(a.rekeys(), (0, 0));
+        a.seal(&[0; 60]).unwrap();
+        assert_eq!(a.rekeys(), (1, 0));
+    }
+}
@@ -0,0 +1,1165 @@
+// network/security.rs - Encryption negotiation and encrypted framing
+
+use super::batch::{self, Batcher};
+use super::codec::{self as message_codec, MessageCodec};
+use super::compression::{self, Codec, Compressor};
+use super::handshake::{self, CloseCode, Role, SUPPORTED_PROTOCOLS, VersionRange};
+use super::message::PeerMessage;
+use super::mux::Mux;
+use super::resume::ResumptionToken;
+use super::secure::{self, Pattern, SecureSession};
+use super::transport::Connection;
+use crate::crypto::{self, Hash, KeyPair, PlayerId, hash_multiple, short_id};
+use crate::error::{Result, SwarmhostError};
+use crate::node::{CipherSuite, NetworkConfig, NodeMetrics, SecurityMode, WireFormat};
+use bytes::Bytes;
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use tokio::time::Instant;
+
+/// Bytes added to every encrypted frame by the AEAD tag
+pub const TAG_LEN: usize = 16;
+
+/// Domain separator for identity proofs, so they never verify as anything
+/// else a player signs
+const IDENTITY_PROOF_CONTEXT: &[u8] = b"swarmhost identity proof v1";
+
+/// What each side announces before deciding whether to encrypt and compress
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SecurityOffer {
+    /// Wire protocol versions spoken; first so any version can read it
+    pub protocol: VersionRange,
+    /// Wire formats spoken, most preferred first
+    pub formats: Vec<WireFormat>,
+    pub mode: SecurityMode,
+    pub ciphers: Vec<CipherSuite>,
+    pub compression: Vec<Codec>,
+    pub player_id: PlayerId,
+    /// Initiator only: the player it expects to reach, so the Noise
+    /// handshake can use the IK pattern
+    pub known_responder: Option<PlayerId>,
+    /// Fresh for every connection, so an identity proof cannot be replayed
+    /// on another
+    pub nonce: [u8; 32],
+    /// A token the peer gave us on an earlier connection, to resume that
+    /// session; it is bound to our id, so it is no use to anyone who sees it
+    pub resume: Option<ResumptionToken>,
+}
+
+/// Extra admission check on a peer's proven id, for limits the peer lists
+/// cannot express (e.g. `max_peers`)
+pub type Admit<'a> = &'a (dyn Fn(&PlayerId) -> std::result::Result<(), CloseCode> + Sync);
+
+/// Each side's answer once it has seen the peer's offer
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+enum Admission {
+    Accept,
+    Refuse(CloseCode),
+}
+
+/// What a side signs to prove it holds the key of the id it claimed: both
+/// offers with their nonces, the agreed protocol version and the id of the
+/// player it believes it is talking to
+fn identity_proof(transcript: &Hash, protocol_version: u16, peer: &PlayerId) -> Hash {
+    hash_multiple(&[
+        IDENTITY_PROOF_CONTEXT,
+        transcript,
+        &protocol_version.to_be_bytes(),
+        peer,
+    ])
+}
+
+/// Read a handshake frame, refusing one larger than an unauthenticated peer
+/// may send
+async fn recv_handshake(conn: &mut dyn Connection, config: &NetworkConfig) -> Result<Bytes> {
+    let frame = conn.recv().await?;
+    let limit = config.inbound.handshake_max_bytes;
+    if frame.len() > limit {
+        return Err(SwarmhostError::handshake(
+            CloseCode::ProtocolError,
+            format!(
+                "{} byte handshake frame exceeds the {} byte limit",
+                frame.len(),
+                limit
+            ),
+        ));
+    }
+    Ok(frame)
+}
+
+/// Decide whether a connection is encrypted, and with which cipher
+///
+/// Encryption is used when neither side is `Plaintext` and they share a
+/// cipher; the initiator's preference order wins. A `Required` side refuses
+/// to fall back to plaintext.
+pub fn negotiate(
+    initiator: &SecurityOffer,
+    responder: &SecurityOffer,
+) -> std::result::Result<Option<CipherSuite>, CloseCode> {
+    let modes = [initiator.mode, responder.mode];
+    let required = modes.contains(&SecurityMode::Required);
+
+    if modes.contains(&SecurityMode::Plaintext) {
+        return if required {
+            Err(CloseCode::EncryptionRequired)
+        } else {
+            Ok(None)
+        };
+    }
+
+    let common = initiator
+        .ciphers
+        .iter()
+        .find(|cipher| responder.ciphers.contains(cipher))
+        .copied();
+
+    match common {
+        Some(cipher) => Ok(Some(cipher)),
+        None if required => Err(CloseCode::NoCommonCipher),
+        None => Ok(None),
+    }
+}
+
+/// A connection that encrypts every frame when the handshake agreed to
+///
+/// Encryption keys come from a Noise handshake (see [`secure`]) whose
+/// prologue is the clear-text negotiation, so the peer's player id is
+/// proven rather than merely claimed and a tampered negotiation is caught.
+///
+/// Each message carries a one-byte compression header inside the (possibly
+/// encrypted) frame saying whether the negotiated codec was applied. The
+/// header and tag count against the transport's frame limit, so the largest
+/// application message is [`max_payload`](Self::max_payload).
+///
+/// Messages given to [`send_batched`](Self::send_batched) may share a frame
+/// as a [`PeerMessage::Batch`], compressed and sealed as a whole; anything
+/// sent directly goes after them.
+///
+/// The channel also keeps the flow control credits of the connection's
+/// per-class streams (see [`Mux`]); the caller spends and grants them.
+pub struct SecureChannel {
+    conn: Box<dyn Connection>,
+    peer_id: PlayerId,
+    protocol_version: u16,
+    message_codec: Box<dyn MessageCodec>,
+    session: Option<SecureSession>,
+    compressor: Compressor,
+    batcher: Batcher,
+    mux: Mux,
+    metrics: Option<Arc<NodeMetrics>>,
+    /// The token the peer presented, if any
+    resume: Option<ResumptionToken>,
+}
+
+impl SecureChannel {
+    /// Exchange offers, check the peer lists and set up encryption and
+    /// compression as agreed
+    ///
+    /// Fails with [`SwarmhostError::Handshake`] carrying the close code when
+    /// the peers' protocol versions, wire formats or requirements are
+    /// incompatible, either side refuses the other, or the peer is too slow, and with [`SwarmhostError::Peer`] when
+    /// the Noise handshake fails.
+    pub async fn establish(
+        conn: Box<dyn Connection>,
+        config: &NetworkConfig,
+        keypair: &KeyPair,
+        role: Role,
+    ) -> Result<Self> {
+        Self::establish_with(conn, config, keypair, role, None, None, &|_| Ok(())).await
+    }
+
+    /// Dial side of [`establish`](Self::establish) when the peer's id is
+    /// already known (e.g. from discovery)
+    ///
+    /// Uses the shorter Noise IK handshake, and fails with a "wrong key"
+    /// [`SwarmhostError::Peer`] if someone else answers.
+    pub async fn establish_to(
+        conn: Box<dyn Connection>,
+        config: &NetworkConfig,
+        keypair: &KeyPair,
+        peer: PlayerId,
+    ) -> Result<Self> {
+        Self::establish_with(
+            conn,
+            config,
+            keypair,
+            Role::Initiator,
+            Some(peer),
+            None,
+            &|_| Ok(()),
+        )
+        .await
+    }
+
+    /// [`establish`](Self::establish) or [`establish_to`](Self::establish_to)
+    /// with a further admission check
+    ///
+    /// `admit` runs once the peer's identity is proven and the peer lists
+    /// have let it in; a refusal is sent to the peer like a denylist one.
+    /// `resume` is presented to the peer, which finds it in
+    /// [`resume_token`](Self::resume_token).
+    pub async fn establish_admitting(
+        conn: Box<dyn Connection>,
+        config: &NetworkConfig,
+        keypair: &KeyPair,
+        role: Role,
+        expected: Option<PlayerId>,
+        resume: Option<ResumptionToken>,
+        admit: Admit<'_>,
+    ) -> Result<Self> {
+        Self::establish_with(conn, config, keypair, role, expected, resume, admit).await
+    }
+
+    async fn establish_with(
+        conn: Box<dyn Connection>,
+        config: &NetworkConfig,
+        keypair: &KeyPair,
+        role: Role,
+        expected: Option<PlayerId>,
+        resume: Option<ResumptionToken>,
+        admit: Admit<'_>,
+    ) -> Result<Self> {
+        let timeout = config.security.handshake_timeout;
+        let handshake = Self::handshake(conn, config, keypair, role, expected, resume, admit);
+        match tokio::time::timeout(timeout, handshake).await {
+            Ok(result) => result,
+            Err(_) => Err(SwarmhostError::handshake(
+                CloseCode::HandshakeTimeout,
+                format!("no security offer within {:?}", timeout),
+            )),
+        }
+    }
+
+    async fn handshake(
+        mut conn: Box<dyn Connection>,
+        config: &NetworkConfig,
+        keypair: &KeyPair,
+        role: Role,
+        expected: Option<PlayerId>,
+        resume: Option<ResumptionToken>,
+        admit: Admit<'_>,
+    ) -> Result<Self> {
+        let local_id = keypair.public_key();
+        let local = SecurityOffer {
+            protocol: SUPPORTED_PROTOCOLS,
+            formats: message_codec::offered_formats(config.wire_format),
+            mode: config.security.mode,
+            ciphers: config.security.ciphers.clone(),
+            compression: compression::offered_codecs(&config.compression),
+            player_id: local_id,
+            known_responder: expected,
+            nonce: rand::random(),
+            resume,
+        };
+
+        let local_bytes = Bytes::from(
+            bincode::serialize(&local).map_err(|e| SwarmhostError::Serialization(e.to_string()))?,
+        );
+        conn.send(local_bytes.clone()).await?;
+
+        let remote_bytes = recv_handshake(conn.as_mut(), config).await?;
+        let remote: SecurityOffer = bincode::deserialize(&remote_bytes).map_err(|e| {
+            SwarmhostError::handshake(
+                CloseCode::ProtocolError,
+                format!("bad security offer: {}", e),
+            )
+        })?;
+        let protocol_version = SUPPORTED_PROTOCOLS.negotiate(remote.protocol)?;
+
+        if let Some(proven) = conn.peer_identity()
+            && proven != remote.player_id
+        {
+            return Err(SwarmhostError::handshake(
+                CloseCode::IdentityMismatch,
+                format!(
+                    "peer claimed {} but authenticated as {}",
+                    short_id(&remote.player_id),
+                    short_id(&proven)
+                ),
+            ));
+        }
+
+        if let Some(expected) = expected.filter(|id| *id != remote.player_id) {
+            return Err(SwarmhostError::Peer(format!(
+                "Wrong key: dialed {} but reached {}",
+                short_id(&expected),
+                short_id(&remote.player_id)
+            )));
+        }
+        if let Some(wanted) = remote.known_responder.filter(|id| *id != local_id) {
+            return Err(SwarmhostError::Peer(format!(
+                "Wrong key: peer dialed {} but reached us",
+                short_id(&wanted)
+            )));
+        }
+
+        let (initiator, responder, transcript) = match role {
+            Role::Initiator => (
+                &local,
+                &remote,
+                hash_multiple(&[&local_bytes, &remote_bytes]),
+            ),
+            Role::Responder => (
+                &remote,
+                &local,
+                hash_multiple(&[&remote_bytes, &local_bytes]),
+            ),
+        };
+
+        let format =
+            message_codec::negotiate(&initiator.formats, &responder.formats).ok_or_else(|| {
+                SwarmhostError::handshake(
+                    CloseCode::NoCommonFormat,
+                    format!(
+                        "no common wire format (peer {:?}, us {:?})",
+                        remote.formats, local.formats
+                    ),
+                )
+            })?;
+
+        let session = match negotiate(initiator, responder) {
+            Ok(Some(suite)) => {
+                let pattern = match initiator.known_responder {
+                    Some(responder) => Pattern::IK { responder },
+                    None => Pattern::XX,
+                };
+                let (session, proven) = secure::handshake(
+                    conn.as_mut(),
+                    keypair,
+                    role,
+                    suite,
+                    pattern,
+                    &transcript,
+                    &config.security,
+                )
+                .await?;
+                if proven != remote.player_id {
+                    return Err(SwarmhostError::handshake(
+                        CloseCode::IdentityMismatch,
+                        format!(
+                            "peer claimed {} but proved {}",
+                            short_id(&remote.player_id),
+                            short_id(&proven)
+                        ),
+                    ));
+                }
+                Some(session)
+            }
+            Ok(None) => None,
+            Err(code) => {
+                return Err(SwarmhostError::handshake(
+                    code,
+                    format!(
+                        "local mode {:?} is incompatible with peer mode {:?}",
+                        local.mode, remote.mode
+                    ),
+                ));
+            }
+        };
+
+        // Bind the connection to both ids whether or not Noise already did
+        let proof = identity_proof(&transcript, protocol_version, &remote.player_id);
+        conn.send(Bytes::from(keypair.sign(&proof))).await?;
+        let remote_proof = recv_handshake(conn.as_mut(), config).await?;
+        let expected_proof = identity_proof(&transcript, protocol_version, &local_id);
+        if crypto::verify_signature(&remote.player_id, &expected_proof, &remote_proof).is_err() {
+            return Err(SwarmhostError::handshake(
+                CloseCode::BadIdentityProof,
+                format!(
+                    "peer claiming {} could not prove it",
+                    short_id(&remote.player_id)
+                ),
+            ));
+        }
+
+        let admission = handshake::check_admission(config, &remote.player_id)
+            .and_then(|()| admit(&remote.player_id));
+        let local_admission = match admission {
+            Ok(()) => Admission::Accept,
+            Err(code) => Admission::Refuse(code),
+        };
+        let admission_bytes = bincode::serialize(&local_admission)
+            .map_err(|e| SwarmhostError::Serialization(e.to_string()))?;
+        conn.send(Bytes::from(admission_bytes)).await?;
+
+        let remote_bytes = recv_handshake(conn.as_mut(), config).await?;
+        let remote_admission: Admission = bincode::deserialize(&remote_bytes).map_err(|e| {
+            SwarmhostError::handshake(CloseCode::ProtocolError, format!("bad admission: {}", e))
+        })?;
+
+        if let Admission::Refuse(code) = local_admission {
+            return Err(SwarmhostError::handshake(
+                code,
+                format!("refused peer {}", short_id(&remote.player_id)),
+            ));
+        }
+        if let Admission::Refuse(code) = remote_admission {
+            return Err(SwarmhostError::handshake(
+                code,
+                format!("peer {} refused us", short_id(&remote.player_id)),
+            ));
+        }
+
+        let codec = compression::negotiate(&initiator.compression, &responder.compression);
+
+        Ok(Self {
+            conn,
+            peer_id: remote.player_id,
+            protocol_version,
+            message_codec: message_codec::codec_for(format, protocol_version)?,
+            session,
+            compressor: Compressor::new(codec, &config.compression),
+            batcher: Batcher::new(&config.batching),
+            mux: Mux::new(&config.mux),
+            metrics: None,
+            resume: remote.resume,
+        })
+    }
+
+    /// Record compression and framing statistics in `metrics`
+    pub fn with_metrics(mut self, metrics: Arc<NodeMetrics>) -> Self {
+        self.metrics = Some(metrics);
+        self
+    }
+
+    /// Player id of the peer, proven by its signature over the handshake
+    /// (and by Noise too on encrypted connections)
+    ///
+    /// The only id anything received on this channel may be attributed to.
+    pub fn peer_id(&self) -> PlayerId {
+        self.peer_id
+    }
+
+    /// Flow control credits of the connection's streams
+    pub fn mux(&self) -> &Mux {
+        &self.mux
+    }
+
+    pub fn mux_mut(&mut self) -> &mut Mux {
+        &mut self.mux
+    }
+
+    /// Token the peer presented to resume an earlier session
+    pub fn resume_token(&self) -> Option<&ResumptionToken> {
+        self.resume.as_ref()
+    }
+
+    /// Wire protocol version agreed in the handshake, which decides how
+    /// messages are encoded on this connection
+    pub fn protocol_version(&self) -> u16 {
+        self.protocol_version
+    }
+
+    /// Encoding of peer messages agreed in the handshake
+    pub fn message_codec(&self) -> &dyn MessageCodec {
+        self.message_codec.as_ref()
+    }
+
+    pub fn wire_format(&self) -> WireFormat {
+        self.message_codec.format()
+    }
+
+    pub fn is_encrypted(&self) -> bool {
+        self.session.is_some()
+    }
+
+    /// Cipher suite in use, or `None` for plaintext
+    pub fn cipher(&self) -> Option<CipherSuite> {
+        self.session.as_ref().map(SecureSession::suite)
+    }
+
+    /// Compression codec in use, or `None` if messages are never compressed
+    pub fn compression(&self) -> Option<Codec> {
+        self.compressor.codec()
+    }
+
+    /// Largest message `send` accepts and `recv` produces
+    pub fn max_payload(&self) -> usize {
+        let tag = if self.session.is_some() { TAG_LEN } else { 0 };
+        self.conn
+            .max_message_size()
+            .saturating_sub(compression::HEADER_LEN + tag)
+    }
+
+    /// Send one message, after any batched ahead of it
+    pub async fn send(&mut self, payload: &[u8]) -> Result<()> {
+        self.flush().await?;
+        self.send_frame(payload).await
+    }
+
+    /// Send an encoded message, perhaps in one frame with others
+    ///
+    /// It is held until the batch is due (see [`flush_due`](Self::flush_due))
+    /// unless that fills the batch; `urgent` makes the batch due at once.
+    pub async fn send_batched(&mut self, payload: Bytes, urgent: bool) -> Result<()> {
+        if !self.batcher.is_enabled() {
+            return self.send_frame(&payload).await;
+        }
+        let limit = self.max_payload().saturating_sub(batch::ENVELOPE);
+        if !self.batcher.fits(payload.len(), limit) {
+            self.flush().await?;
+            if !self.batcher.fits(payload.len(), limit) {
+                return self.send_frame(&payload).await;
+            }
+        }
+        self.batcher.push(payload, urgent, Instant::now());
+        if self.batcher.is_full() {
+            self.flush().await?;
+        }
+        Ok(())
+    }
+
+    /// When the batched messages should be sent with [`flush`](Self::flush),
+    /// if there are any
+    pub fn flush_due(&self) -> Option<Instant> {
+        self.batcher.due()
+    }
+
+    /// Send the batched messages now
+    ///
+    /// A lone message goes as itself rather than as a batch of one.
+    pub async fn flush(&mut self) -> Result<()> {
+        let items = self.batcher.take();
+        match items.as_slice() {
+            [] => Ok(()),
+            [item] => self.send_frame(item).await,
+            _ => {
+                let packed = PeerMessage::Batch(batch::pack(&items));
+                let encoded = self.message_codec.encode(&packed)?;
+                if let Some(metrics) = &self.metrics {
+                    metrics.messages_batched.add(items.len() as u64);
+                }
+                self.send_frame(&encoded).await
+            }
+        }
+    }
+
+    async fn send_frame(&mut self, payload: &[u8]) -> Result<()> {
+        let max_payload = self.max_payload();
+        if payload.len() > max_payload {
+            return Err(SwarmhostError::TooLarge(format!(
+                "Outgoing message of {} bytes exceeds the {} bytes left by max_message_size",
+                payload.len(),
+                max_payload
+            )));
+        }
+
+        let message = self.compressor.encode(payload, self.metrics.as_deref())?;
+        if let Some(metrics) = &self.metrics {
+            metrics.frames_sent.inc();
+        }
+        match &mut self.session {
+            Some(session) => {
+                let sealed = session.seal(&message)?;
+                self.conn.send(Bytes::from(sealed)).await
+            }
+            None => self.conn.send(Bytes::from(message)).await,
+        }
+    }
+
+    /// Receive one message
+    pub async fn recv(&mut self) -> Result<Vec<u8>> {
+        let max_payload = self.max_payload();
+        let frame = self.conn.recv().await?;
+        let message = match &mut self.session {
+            Some(session) => session.open(&frame)?,
+            None => frame.to_vec(),
+        };
+        self.compressor.decode(&message, max_payload)
+    }
+
+    /// Flush pending output and shut down the write side
+    pub async fn close(&mut self) -> Result<()> {
+        let flushed = self.flush().await;
+        let closed = self.conn.close().await;
+        flushed.and(closed)
+    }
+
+    pub fn connection(&self) -> &dyn Connection {
+        self.conn.as_ref()
+    }
+
+    /// Give back the underlying connection
+    pub fn into_connection(self) -> Box<dyn Connection> {
+        self.conn
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::network::transport::StreamConnection;
+    use std::sync::{Arc, Mutex, OnceLock};
+    use tokio::io::{AsyncReadExt, AsyncWriteExt, DuplexStream};
+
+    const MAX: usize = 64 * 1024;
+
+    fn dialer_key() -> &'static KeyPair {
+        static KEY: OnceLock<KeyPair> = OnceLock::new();
+        KEY.get_or_init(|| KeyPair::from_bytes(&[1; 32]).unwrap())
+    }
+
+    fn listener_key() -> &'static KeyPair {
+        static KEY: OnceLock<KeyPair> = OnceLock::new();
+        KEY.get_or_init(|| KeyPair::from_bytes(&[2; 32]).unwrap())
+    }
+
+    fn framed(stream: DuplexStream) -> Box<dyn Connection> {
+        Box::new(StreamConnection::new(
+            stream,
+            MAX,
+            "127.0.0.1:9000".parse().unwrap(),
+        ))
+    }
+
+    fn offer(mode: SecurityMode, ciphers: &[CipherSuite]) -> SecurityOffer {
+        SecurityOffer {
+            protocol: SUPPORTED_PROTOCOLS,
+            formats: vec![WireFormat::Bincode],
+            mode,
+            ciphers: ciphers.to_vec(),
+            compression: Vec::new(),
+            player_id: [0; 32],
+            known_responder: None,
+            nonce: [0; 32],
+            resume: None,
+        }
+    }
+
+    fn config(mode: SecurityMode) -> NetworkConfig {
+        let mut config = NetworkConfig {
+            max_message_size: MAX,
+            ..Default::default()
+        };
+        config.security.mode = mode;
+        config
+    }
+
+    /// Two in-memory endpoints joined through a tap recording a -> b bytes
+    fn tapped_pair() -> (DuplexStream, DuplexStream, Arc<Mutex<Vec<u8>>>) {
+        let (a, mut tap_a) = tokio::io::duplex(MAX);
+        let (mut tap_b, b) = tokio::io::duplex(MAX);
+        let captured = Arc::new(Mutex::new(Vec::new()));
+
+        let recorder = captured.clone();
+        tokio::spawn(async move {
+            let mut forward = vec![0u8; 4096];
+            let mut backward = vec![0u8; 4096];
+            loop {
+                tokio::select! {
+                    read = tap_a.read(&mut forward) => match read {
+                        Ok(0) | Err(_) => break,
+                        Ok(n) => {
+                            recorder.lock().unwrap().extend_from_slice(&forward[..n]);
+                            if tap_b.write_all(&forward[..n]).await.is_err() {
+                                break;
+                            }
+                        }
+                    },
+                    read = tap_b.read(&mut backward) => match read {
+                        Ok(0) | Err(_) => break,
+                        Ok(n) => {
+                            if tap_a.write_all(&backward[..n]).await.is_err() {
+                                break;
+                            }
+                        }
+                    },
+                }
+            }
+        });
+
+        (a, b, captured)
+    }
+
+    #[test]
+    fn test_negotiate_matrix() {
+        use CipherSuite::*;
+        use SecurityMode::*;
+        let both = [ChaCha20Poly1305, Aes256Gcm];
+
+        assert_eq!(
+            negotiate(&offer(Plaintext, &both), &offer(Plaintext, &both)),
+            Ok(None)
+        );
+        assert_eq!(
+            negotiate(&offer(Plaintext, &both), &offer(Encrypted, &both)),
+            Ok(None)
+        );
+        assert_eq!(
+            negotiate(&offer(Plaintext, &both), &offer(Required, &both)),
+            Err(CloseCode::EncryptionRequired)
+        );
+        assert_eq!(
+            negotiate(&offer(Encrypted, &both), &offer(Required, &both)),
+            Ok(Some(ChaCha20Poly1305))
+        );
+        assert_eq!(
+            negotiate(
+                &offer(Encrypted, &[Aes256Gcm, ChaCha20Poly1305]),
+                &offer(Encrypted, &both)
+            ),
+            Ok(Some(Aes256Gcm))
+        );
+        assert_eq!(
+            negotiate(
+                &offer(Encrypted, &[Aes256Gcm]),
+                &offer(Encrypted, &[ChaCha20Poly1305])
+            ),
+            Ok(None)
+        );
+        assert_eq!(
+            negotiate(
+                &offer(Required, &[Aes256Gcm]),
+                &offer(Encrypted, &[ChaCha20Poly1305])
+            ),
+            Err(CloseCode::NoCommonCipher)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_required_vs_plaintext_fails_with_code() {
+        let (a, b) = tokio::io::duplex(MAX);
+        let required = config(SecurityMode::Required);
+        let plaintext = config(SecurityMode::Plaintext);
+
+        let (dialer, listener) = tokio::join!(
+            SecureChannel::establish(framed(a), &required, dialer_key(), Role::Initiator),
+            SecureChannel::establish(framed(b), &plaintext, listener_key(), Role::Responder),
+        );
+
+        for result in [dialer.err(), listener.err()] {
+            match result {
+                Some(SwarmhostError::Handshake { code, .. }) => {
+                    assert_eq!(code, CloseCode::EncryptionRequired)
+                }
+                other => panic!("expected handshake error, got {:?}", other),
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_encrypted_traffic_unreadable_by_tap() {
+        let (a, b, captured) = tapped_pair();
+        let encrypted = config(SecurityMode::Encrypted);
+
+        let (dialer, listener) = tokio::join!(
+            SecureChannel::establish(framed(a), &encrypted, dialer_key(), Role::Initiator),
+            SecureChannel::establish(framed(b), &encrypted, listener_key(), Role::Responder),
+        );
+        let mut dialer = dialer.unwrap();
+        let mut listener = listener.unwrap();
+        assert!(dialer.is_encrypted() && listener.is_encrypted());
+        assert_eq!(dialer.cipher(), listener.cipher());
+
+        let secret = b"the treasure is buried under the third palm tree";
+        dialer.send(secret).await.unwrap();
+        dialer.send(secret).await.unwrap();
+        assert_eq!(listener.recv().await.unwrap(), secret);
+        assert_eq!(listener.recv().await.unwrap(), secret);
+
+        let wire = captured.lock().unwrap().clone();
+        assert!(wire.len() > secret.len());
+        assert!(!wire.windows(secret.len()).any(|window| window == secret));
+    }
+
+    #[tokio::test]
+    async fn test_plaintext_traffic_visible_to_tap() {
+        let (a, b, captured) = tapped_pair();
+        let plaintext = config(SecurityMode::Plaintext);
+
+        let (dialer, listener) = tokio::join!(
+            SecureChannel::establish(framed(a), &plaintext, dialer_key(), Role::Initiator),
+            SecureChannel::establish(framed(b), &plaintext, listener_key(), Role::Responder),
+        );
+        let mut dialer = dialer.unwrap();
+        let mut listener = listener.unwrap();
+        assert!(!dialer.is_encrypted());
+
+        dialer.send(b"hello").await.unwrap();
+        assert_eq!(listener.recv().await.unwrap(), b"hello");
+
+        let wire = captured.lock().unwrap().clone();
+        assert!(wire.windows(5).any(|window| window == b"hello"));
+    }
+
+    #[tokio::test]
+    async fn test_protocol_version_negotiated_or_refused() {
+        let encrypted = config(SecurityMode::Encrypted);
+        let (a, b) = tokio::io::duplex(MAX);
+        let (dialer, listener) = tokio::join!(
+            SecureChannel::establish(framed(a), &encrypted, dialer_key(), Role::Initiator),
+            SecureChannel::establish(framed(b), &encrypted, listener_key(), Role::Responder),
+        );
+        assert_eq!(dialer.unwrap().protocol_version(), SUPPORTED_PROTOCOLS.max);
+        assert_eq!(
+            listener.unwrap().protocol_version(),
+            SUPPORTED_PROTOCOLS.max
+        );
+
+        // A peer from the future offers only versions we do not speak
+        let (a, b) = tokio::io::duplex(MAX);
+        let mut future = framed(a);
+        let newer = SUPPORTED_PROTOCOLS.max + 1;
+        let mut offer = offer(SecurityMode::Encrypted, &[CipherSuite::ChaCha20Poly1305]);
+        offer.protocol = VersionRange {
+            min: newer,
+            max: newer + 2,
+        };
+        future
+            .send(Bytes::from(bincode::serialize(&offer).unwrap()))
+            .await
+            .unwrap();
+
+        match SecureChannel::establish(framed(b), &encrypted, listener_key(), Role::Responder).await
+        {
+            Err(SwarmhostError::Handshake { code, reason }) => {
+                assert_eq!(code, CloseCode::IncompatibleVersion);
+                let expected = format!(
+                    "peer requires protocol {}, we support up to {}",
+                    newer, SUPPORTED_PROTOCOLS.max
+                );
+                assert!(reason.contains(&expected), "{}", reason);
+            }
+            other => panic!("expected incompatible version, got {:?}", other.err()),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_wire_format_negotiated_or_refused() {
+        let encrypted = config(SecurityMode::Encrypted);
+        let (a, b) = tokio::io::duplex(MAX);
+        let (dialer, listener) = tokio::join!(
+            SecureChannel::establish(framed(a), &encrypted, dialer_key(), Role::Initiator),
+            SecureChannel::establish(framed(b), &encrypted, listener_key(), Role::Responder),
+        );
+        assert_eq!(dialer.unwrap().wire_format(), WireFormat::Bincode);
+        assert_eq!(listener.unwrap().wire_format(), WireFormat::Bincode);
+
+        let (a, b) = tokio::io::duplex(MAX);
+        let mut mute = framed(a);
+        let mut offer = offer(SecurityMode::Encrypted, &[CipherSuite::ChaCha20Poly1305]);
+        offer.formats.clear();
+        mute.send(Bytes::from(bincode::serialize(&offer).unwrap()))
+            .await
+            .unwrap();
+
+        match SecureChannel::establish(framed(b), &encrypted, listener_key(), Role::Responder).await
+        {
+            Err(SwarmhostError::Handshake { code, .. }) => {
+                assert_eq!(code, CloseCode::NoCommonFormat)
+            }
+            other => panic!("expected no common format, got {:?}", other.err()),
+        }
+    }
+
+    #[cfg(feature = "protobuf")]
+    #[tokio::test]
+    async fn test_peers_preferring_different_formats_agree() {
+        use crate::network::PeerMessage;
+
+        let mut protobuf = config(SecurityMode::Encrypted);
+        protobuf.wire_format = WireFormat::Protobuf;
+        let bincode = config(SecurityMode::Encrypted);
+
+        let (a, b) = tokio::io::duplex(MAX);
+        let (dialer, listener) = tokio::join!(
+            SecureChannel::establish(framed(a), &protobuf, dialer_key(), Role::Initiator),
+            SecureChannel::establish(framed(b), &bincode, listener_key(), Role::Responder),
+        );
+        let mut dialer = dialer.unwrap();
+        let mut listener = listener.unwrap();
+        // The dialer's preference wins
+        assert_eq!(dialer.wire_format(), WireFormat::Protobuf);
+        assert_eq!(listener.wire_format(), WireFormat::Protobuf);
+
+        let ping = PeerMessage::Ping {
+            nonce: 7,
+            sent_at_ms: 1,
+            mark: None,
+        };
+        let encoded = dialer.message_codec().encode(&ping).unwrap();
+        dialer.send(&encoded).await.unwrap();
+        let received = listener.recv().await.unwrap();
+        assert_eq!(listener.message_codec().decode(&received).unwrap(), ping);
+    }
+
+    #[tokio::test]
+    async fn test_handshake_timeout() {
+        let (a, _b) = tokio::io::duplex(MAX);
+        let mut slow = config(SecurityMode::Encrypted);
+        slow.security.handshake_timeout = std::time::Duration::from_millis(50);
+
+        match SecureChannel::establish(framed(a), &slow, dialer_key(), Role::Initiator).await {
+            Err(SwarmhostError::Handshake { code, .. }) => {
+                assert_eq!(code, CloseCode::HandshakeTimeout)
+            }
+            _ => panic!("expected handshake timeout"),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_oversized_handshake_frame_refused() {
+        let (a, b) = tokio::io::duplex(MAX);
+        let config = config(SecurityMode::Encrypted);
+        let mut flooder = framed(b);
+        let junk = Bytes::from(vec![0; config.inbound.handshake_max_bytes + 1]);
+        let (result, _) = tokio::join!(
+            SecureChannel::establish(framed(a), &config, listener_key(), Role::Responder),
+            flooder.send(junk),
+        );
+        match result {
+            Err(SwarmhostError::Handshake { code, reason }) => {
+                assert_eq!(code, CloseCode::ProtocolError);
+                assert!(reason.contains("handshake frame"));
+            }
+            _ => panic!("expected the frame to be refused"),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_compression_negotiated_in_handshake() {
+        use crate::node::CompressionAlgorithm;
+
+        let (a, b) = tokio::io::duplex(MAX);
+        let mut zstd = config(SecurityMode::Encrypted);
+        zstd.compression.algorithm = CompressionAlgorithm::Zstd { level: 3 };
+        let lz4 = config(SecurityMode::Encrypted);
+        let metrics = Arc::new(NodeMetrics::default());
+
+        let (dialer, listener) = tokio::join!(
+            SecureChannel::establish(framed(a), &zstd, dialer_key(), Role::Initiator),
+            SecureChannel::establish(framed(b), &lz4, listener_key(), Role::Responder),
+        );
+        let mut dialer = dialer.unwrap().with_metrics(metrics.clone());
+        let mut listener = listener.unwrap();
+        assert_eq!(dialer.compression(), Some(Codec::Zstd));
+        assert_eq!(listener.compression(), Some(Codec::Zstd));
+
+        let big = b"state update ".repeat(200);
+        dialer.send(&big).await.unwrap();
+        dialer.send(b"tiny").await.unwrap();
+        assert_eq!(listener.recv().await.unwrap(), big);
+        assert_eq!(listener.recv().await.unwrap(), b"tiny");
+
+        assert_eq!(metrics.compression_input_bytes.get(), big.len() as u64);
+        assert!(metrics.compression_output_bytes.get() < big.len() as u64);
+        assert_eq!(metrics.uncompressed_bytes.get(), 4);
+    }
+
+    #[tokio::test]
+    async fn test_batched_messages_share_a_frame_in_order() {
+        let (a, b) = tokio::io::duplex(MAX);
+        let config = config(SecurityMode::Encrypted);
+        let metrics = Arc::new(NodeMetrics::default());
+
+        let (dialer, listener) = tokio::join!(
+            SecureChannel::establish(framed(a), &config, dialer_key(), Role::Initiator),
+            SecureChannel::establish(framed(b), &config, listener_key(), Role::Responder),
+        );
+        let mut dialer = dialer.unwrap().with_metrics(metrics.clone());
+        let mut listener = listener.unwrap();
+
+        let codec = message_codec::codec_for(WireFormat::Bincode, SUPPORTED_PROTOCOLS.max).unwrap();
+        let items: Vec<Bytes> = (0..3u8).map(|i| Bytes::from(vec![i; 100])).collect();
+        for item in &items {
+            dialer.send_batched(item.clone(), false).await.unwrap();
+        }
+        assert!(dialer.flush_due().is_some());
+        // A direct send goes after everything batched before it
+        dialer.send(b"direct").await.unwrap();
+        assert!(dialer.flush_due().is_none());
+
+        let frame = listener.recv().await.unwrap();
+        match codec.decode(&frame).unwrap() {
+            PeerMessage::Batch(packed) => {
+                assert_eq!(
+                    batch::unpack(&packed).unwrap(),
+                    items.iter().map(|i| &i[..]).collect::<Vec<_>>()
+                );
+            }
+            other => panic!("expected a batch, got {:?}", other),
+        }
+        assert_eq!(listener.recv().await.unwrap(), b"direct");
+        assert_eq!(metrics.frames_sent.get(), 2);
+        assert_eq!(metrics.messages_batched.get(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_peer_lists_refuse_with_distinct_codes() {
+        let open = config(SecurityMode::Encrypted);
+        let mut banning = open.clone();
+        banning.denylist = vec![dialer_key().public_key()];
+        let mut private = open.clone();
+        private.allowlist = Some(vec![[9; 32]]);
+
+        for (listener_config, expected) in [
+            (&banning, CloseCode::Banned),
+            (&private, CloseCode::NotInvited),
+        ] {
+            let (a, b) = tokio::io::duplex(MAX);
+            let (dialer, listener) = tokio::join!(
+                SecureChannel::establish(framed(a), &open, dialer_key(), Role::Initiator),
+                SecureChannel::establish(
+                    framed(b),
+                    listener_config,
+                    listener_key(),
+                    Role::Responder
+                ),
+            );
+
+            for result in [dialer.err(), listener.err()] {
+                match result {
+                    Some(SwarmhostError::Handshake { code, .. }) => assert_eq!(code, expected),
+                    other => panic!("expected handshake error, got {:?}", other),
+                }
+            }
+        }
+
+        let (a, b) = tokio::io::duplex(MAX);
+        let mut invited = private.clone();
+        invited.allowlist = Some(vec![dialer_key().public_key()]);
+        let (dialer, listener) = tokio::join!(
+            SecureChannel::establish(framed(a), &open, dialer_key(), Role::Initiator),
+            SecureChannel::establish(framed(b), &invited, listener_key(), Role::Responder),
+        );
+        assert_eq!(dialer.unwrap().peer_id(), listener_key().public_key());
+        assert_eq!(listener.unwrap().peer_id(), dialer_key().public_key());
+    }
+
+    #[tokio::test]
+    async fn test_admit_refuses_like_peer_lists() {
+        let open = config(SecurityMode::Encrypted);
+        let busy = |_: &PlayerId| Err(CloseCode::Busy);
+        let (a, b) = tokio::io::duplex(MAX);
+        let (dialer, listener) = tokio::join!(
+            SecureChannel::establish(framed(a), &open, dialer_key(), Role::Initiator),
+            SecureChannel::establish_admitting(
+                framed(b),
+                &open,
+                listener_key(),
+                Role::Responder,
+                None,
+                None,
+                &busy
+            ),
+        );
+        for result in [dialer.err(), listener.err()] {
+            match result {
+                Some(SwarmhostError::Handshake { code, .. }) => assert_eq!(code, CloseCode::Busy),
+                other => panic!("expected handshake error, got {:?}", other),
+            }
+        }
+
+        // The denylist is checked first, so its code wins
+        let mut banning = open.clone();
+        banning.denylist = vec![dialer_key().public_key()];
+        let (a, b) = tokio::io::duplex(MAX);
+        let (_, listener) = tokio::join!(
+            SecureChannel::establish(framed(a), &open, dialer_key(), Role::Initiator),
+            SecureChannel::establish_admitting(
+                framed(b),
+                &banning,
+                listener_key(),
+                Role::Responder,
+                None,
+                None,
+                &busy
+            ),
+        );
+        match listener {
+            Err(SwarmhostError::Handshake { code, .. }) => assert_eq!(code, CloseCode::Banned),
+            other => panic!("expected handshake error, got {:?}", other.err()),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_replayed_transcript_rejected() {
+        let (a, b, captured) = tapped_pair();
+        let encrypted = config(SecurityMode::Encrypted);
+
+        let (dialer, listener) = tokio::join!(
+            SecureChannel::establish(framed(a), &encrypted, dialer_key(), Role::Initiator),
+            SecureChannel::establish(framed(b), &encrypted, listener_key(), Role::Responder),
+        );
+        dialer.unwrap().send(b"move").await.unwrap();
+        assert_eq!(listener.unwrap().recv().await.unwrap(), b"move");
+
+        // Play the dialer's recorded bytes to a fresh listener
+        let (mut attacker, victim) = tokio::io::duplex(MAX);
+        let recording = captured.lock().unwrap().clone();
+        attacker.write_all(&recording).await.unwrap();
+
+        let replayed =
+            SecureChannel::establish(framed(victim), &encrypted, listener_key(), Role::Responder)
+                .await;
+        match replayed {
+            Err(SwarmhostError::Peer(reason)) => assert!(reason.contains("Noise"), "{}", reason),
+            other => panic!("expected replay to fail, got {:?}", other.err()),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_dialing_known_peer_checks_key() {
+        let encrypted = config(SecurityMode::Encrypted);
+
+        let (a, b) = tokio::io::duplex(MAX);
+        let (dialer, listener) = tokio::join!(
+            SecureChannel::establish_to(
+                framed(a),
+                &encrypted,
+                dialer_key(),
+                listener_key().public_key()
+            ),
+            SecureChannel::establish(framed(b), &encrypted, listener_key(), Role::Responder),
+        );
+        assert_eq!(dialer.unwrap().peer_id(), listener_key().public_key());
+        assert_eq!(listener.unwrap().peer_id(), dialer_key().public_key());
+
+        let (a, b) = tokio::io::duplex(MAX);
+        let someone_else = KeyPair::generate().public_key();
+        let (dialer, listener) = tokio::join!(
+            SecureChannel::establish_to(framed(a), &encrypted, dialer_key(), someone_else),
+            SecureChannel::establish(framed(b), &encrypted, listener_key(), Role::Responder),
+        );
+        for result in [dialer.err(), listener.err()] {
+            match result {
+                Some(SwarmhostError::Peer(reason)) => assert!(reason.contains("Wrong key")),
+                other => panic!("expected wrong key, got {:?}", other),
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_impersonation_fails_identity_proof() {
+        let plaintext = config(SecurityMode::Plaintext);
+        let (a, b) = tokio::io::duplex(MAX);
+        let listening = tokio::spawn(async move {
+            SecureChannel::establish(framed(b), &plaintext, listener_key(), Role::Responder).await
+        });
+
+        // Mallory claims the dialer's id but only holds its own key
+        let mut mallory = framed(a);
+        let mut claim = offer(SecurityMode::Plaintext, &[]);
+        claim.player_id = dialer_key().public_key();
+        claim.nonce = [7; 32];
+        let claim_bytes = Bytes::from(bincode::serialize(&claim).unwrap());
+        mallory.send(claim_bytes.clone()).await.unwrap();
+        let listener_offer = mallory.recv().await.unwrap();
+
+        let transcript = hash_multiple(&[&claim_bytes, &listener_offer]);
+        let proof = identity_proof(
+            &transcript,
+            SUPPORTED_PROTOCOLS.max,
+            &listener_key().public_key(),
+        );
+        let forged = KeyPair::from_bytes(&[3; 32]).unwrap().sign(&proof);
+        mallory.send(Bytes::from(forged)).await.unwrap();
+
+        match listening.await.unwrap() {
+            Err(SwarmhostError::Handshake { code, .. }) => {
+                assert_eq!(code, CloseCode::BadIdentityProof)
+            }
+            other => panic!("expected a bad identity proof, got {:?}", other.err()),
+        }
+    }
+}
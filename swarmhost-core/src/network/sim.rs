@@ -0,0 +1,520 @@
+// network/sim.rs - Impaired links for the in-memory transport
+
+use super::memory::{MemoryNetwork, MemoryTransport};
+use crate::node::NetworkConfig;
+use bytes::Bytes;
+use rand::rngs::StdRng;
+use rand::{Rng, RngCore, SeedableRng};
+use std::collections::{BTreeMap, HashMap};
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::Duration;
+use tokio::time::Instant;
+
+/// Most times one reliable frame is resent before the connection gives up
+const MAX_RESENDS: u32 = 8;
+
+/// Shortest wait before a lost reliable frame is resent
+const MIN_RESEND_DELAY: Duration = Duration::from_millis(10);
+
+/// Exponential jitter is cut off at this many times its mean
+const MAX_JITTER_MEANS: f64 = 10.0;
+
+/// How much each frame is delayed beyond the fixed latency
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Jitter {
+    #[default]
+    None,
+    /// Anywhere from zero to this, evenly
+    Uniform(Duration),
+    /// Usually little, now and then a lot, averaging this
+    Exponential(Duration),
+}
+
+impl Jitter {
+    fn sample(&self, rng: &mut StdRng) -> Duration {
+        match *self {
+            Jitter::None => Duration::ZERO,
+            Jitter::Uniform(max) => max.mul_f64(rng.gen_range(0.0..1.0)),
+            Jitter::Exponential(mean) => {
+                let draw = -(1.0 - rng.gen_range(0.0..1.0f64)).ln();
+                mean.mul_f64(draw.min(MAX_JITTER_MEANS))
+            }
+        }
+    }
+}
+
+/// Typical links, as starting points for [`NetworkConditions`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LinkPreset {
+    /// A few milliseconds, rare loss, plenty of bandwidth
+    Wifi,
+    /// About 100ms with a long tail, some loss, under 1 Mbit/s
+    Mobile3G,
+    /// Close to unusable: high latency, heavy loss, reordering and duplicates
+    Terrible,
+}
+
+/// What one link does to the frames crossing it, in each direction
+///
+/// Every frame (each [`Connection::send`](super::Connection::send) call,
+/// so each fragment of a split message) is impaired on its own. Reliable
+/// frames behave like TCP segments: a lost one costs a resend delay, and
+/// they arrive once and in order. Frames sent with
+/// [`send_unreliable`](super::Connection::send_unreliable) are really lost,
+/// reordered and duplicated.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct NetworkConditions {
+    /// Fixed one-way delay
+    pub latency: Duration,
+    pub jitter: Jitter,
+    /// Chance of each transmission being lost, from 0 to 1
+    pub loss: f64,
+    /// Bytes per second in each direction; unlimited if unset
+    pub bandwidth: Option<u64>,
+    /// Chance of an unreliable frame being held back so later ones overtake it
+    pub reorder: f64,
+    /// Chance of an unreliable frame arriving twice
+    pub duplicate: f64,
+}
+
+impl From<LinkPreset> for NetworkConditions {
+    fn from(preset: LinkPreset) -> Self {
+        match preset {
+            LinkPreset::Wifi => Self {
+                latency: Duration::from_millis(4),
+                jitter: Jitter::Exponential(Duration::from_millis(2)),
+                loss: 0.005,
+                bandwidth: Some(6_000_000),
+                reorder: 0.001,
+                duplicate: 0.0,
+            },
+            LinkPreset::Mobile3G => Self {
+                latency: Duration::from_millis(100),
+                jitter: Jitter::Exponential(Duration::from_millis(30)),
+                loss: 0.02,
+                bandwidth: Some(96_000),
+                reorder: 0.01,
+                duplicate: 0.005,
+            },
+            LinkPreset::Terrible => Self {
+                latency: Duration::from_millis(400),
+                jitter: Jitter::Exponential(Duration::from_millis(200)),
+                loss: 0.15,
+                bandwidth: Some(16_000),
+                reorder: 0.1,
+                duplicate: 0.05,
+            },
+        }
+    }
+}
+
+impl NetworkConditions {
+    /// A link that delivers everything at once
+    pub fn perfect() -> Self {
+        Self::default()
+    }
+
+    pub fn with_latency(mut self, latency: Duration, jitter: Jitter) -> Self {
+        self.latency = latency;
+        self.jitter = jitter;
+        self
+    }
+
+    pub fn with_loss(mut self, loss: f64) -> Self {
+        self.loss = loss;
+        self
+    }
+
+    pub fn with_bandwidth(mut self, bytes_per_sec: Option<u64>) -> Self {
+        self.bandwidth = bytes_per_sec;
+        self
+    }
+
+    pub fn with_reorder(mut self, reorder: f64) -> Self {
+        self.reorder = reorder;
+        self
+    }
+
+    pub fn with_duplicate(mut self, duplicate: f64) -> Self {
+        self.duplicate = duplicate;
+        self
+    }
+
+    /// Time `len` bytes occupy the link
+    fn transmit_time(&self, len: usize) -> Duration {
+        match self.bandwidth {
+            Some(rate) if rate > 0 => Duration::from_secs_f64(len as f64 / rate as f64),
+            _ => Duration::ZERO,
+        }
+    }
+}
+
+/// Conditions of every impaired link on a memory network, and where each
+/// connection's randomness comes from
+#[derive(Clone)]
+pub(super) struct Impairments {
+    inner: Arc<Mutex<ImpairmentTable>>,
+}
+
+struct ImpairmentTable {
+    /// By the two ends' listening addresses, lowest first
+    links: HashMap<(SocketAddr, SocketAddr), NetworkConditions>,
+    seeds: StdRng,
+}
+
+impl Default for Impairments {
+    fn default() -> Self {
+        Self {
+            inner: Arc::new(Mutex::new(ImpairmentTable {
+                links: HashMap::new(),
+                seeds: StdRng::seed_from_u64(0),
+            })),
+        }
+    }
+}
+
+fn link_key(a: SocketAddr, b: SocketAddr) -> (SocketAddr, SocketAddr) {
+    if a <= b { (a, b) } else { (b, a) }
+}
+
+impl Impairments {
+    fn lock(&self) -> MutexGuard<'_, ImpairmentTable> {
+        self.inner.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn reseed(&self, seed: u64) {
+        self.lock().seeds = StdRng::seed_from_u64(seed);
+    }
+
+    fn set(&self, a: SocketAddr, b: SocketAddr, conditions: NetworkConditions) {
+        let mut table = self.lock();
+        if conditions == NetworkConditions::perfect() {
+            table.links.remove(&link_key(a, b));
+        } else {
+            table.links.insert(link_key(a, b), conditions);
+        }
+    }
+
+    pub(super) fn get(&self, a: SocketAddr, b: SocketAddr) -> Option<NetworkConditions> {
+        self.lock().links.get(&link_key(a, b)).cloned()
+    }
+
+    /// Seed for the next connection's randomness
+    pub fn next_seed(&self) -> u64 {
+        self.lock().seeds.next_u64()
+    }
+}
+
+/// Decides when each frame one end of a connection sends arrives
+pub(super) struct Shaper {
+    impairments: Impairments,
+    from: SocketAddr,
+    to: SocketAddr,
+    rng: StdRng,
+    /// Arrival times are sent as offsets from this, shared by both ends
+    epoch: Instant,
+    /// When the link finishes transmitting what was already sent
+    busy_until: Instant,
+    /// Arrival of the last reliable frame, which later ones may not overtake
+    last_arrival: Instant,
+    /// A reliable frame was lost on every resend, so nothing after it
+    /// arrives either, as on a TCP connection that has given up
+    stalled: bool,
+}
+
+impl Shaper {
+    pub fn new(
+        impairments: Impairments,
+        from: SocketAddr,
+        to: SocketAddr,
+        seed: u64,
+        epoch: Instant,
+    ) -> Self {
+        Self {
+            impairments,
+            from,
+            to,
+            rng: StdRng::seed_from_u64(seed),
+            epoch,
+            busy_until: epoch,
+            last_arrival: epoch,
+            stalled: false,
+        }
+    }
+
+    pub fn epoch(&self) -> Instant {
+        self.epoch
+    }
+
+    /// Wait for the link to be free, then put a frame of `len` bytes on it
+    ///
+    /// Returns when each copy of the frame arrives, as offsets from the
+    /// epoch; none if it was lost. Cancel-safe: nothing changes before the
+    /// wait ends.
+    pub async fn transmit(&mut self, len: usize, reliable: bool) -> Vec<Duration> {
+        // A perfect link still lets frames sent before it was fixed go first
+        let conditions = self.impairments.get(self.from, self.to).unwrap_or_default();
+        tokio::time::sleep_until(self.busy_until).await;
+
+        let sent = Instant::now() + conditions.transmit_time(len);
+        self.busy_until = sent;
+        let arrive = |rng: &mut StdRng| sent + conditions.latency + conditions.jitter.sample(rng);
+
+        let arrivals = if reliable {
+            if self.stalled {
+                return Vec::new();
+            }
+            // Lost segments are resent about a round trip later
+            let resend = (conditions.latency * 2).max(MIN_RESEND_DELAY);
+            let mut arrival = arrive(&mut self.rng);
+            let mut sends = 0;
+            while chance(&mut self.rng, conditions.loss) {
+                if sends == MAX_RESENDS {
+                    self.stalled = true;
+                    return Vec::new();
+                }
+                sends += 1;
+                arrival += resend;
+            }
+            let arrival = arrival.max(self.last_arrival);
+            self.last_arrival = arrival;
+            vec![arrival]
+        } else if chance(&mut self.rng, conditions.loss) {
+            Vec::new()
+        } else {
+            let mut arrivals = vec![arrive(&mut self.rng)];
+            if chance(&mut self.rng, conditions.reorder) {
+                arrivals[0] += conditions.latency.max(Duration::from_millis(1));
+            }
+            if chance(&mut self.rng, conditions.duplicate) {
+                arrivals.push(arrive(&mut self.rng));
+            }
+            arrivals
+        };
+        arrivals
+            .into_iter()
+            .map(|arrival| arrival - self.epoch)
+            .collect()
+    }
+}
+
+/// True with probability `p`, treating anything outside 0..=1 as never or
+/// always
+fn chance(rng: &mut StdRng, p: f64) -> bool {
+    rng.gen_range(0.0..1.0) < p
+}
+
+/// Frames received ahead of their arrival time, in arrival order
+pub(super) struct Arrivals {
+    epoch: Instant,
+    waiting: BTreeMap<(Instant, u64), Bytes>,
+    received: u64,
+    bytes: usize,
+    capacity: usize,
+}
+
+impl Arrivals {
+    /// Holding back at most `capacity` bytes before the sender has to wait
+    pub fn new(epoch: Instant, capacity: usize) -> Self {
+        Self {
+            epoch,
+            waiting: BTreeMap::new(),
+            received: 0,
+            bytes: 0,
+            capacity,
+        }
+    }
+
+    pub fn push(&mut self, offset: Duration, frame: Bytes) {
+        self.bytes += frame.len();
+        self.waiting
+            .insert((self.epoch + offset, self.received), frame);
+        self.received += 1;
+    }
+
+    /// The earliest frame due by `now`
+    pub fn pop(&mut self, now: Instant) -> Option<Bytes> {
+        let entry = self.waiting.first_entry()?;
+        if entry.key().0 > now {
+            return None;
+        }
+        let frame = entry.remove();
+        self.bytes -= frame.len();
+        Some(frame)
+    }
+
+    /// When the earliest frame is due
+    pub fn next(&self) -> Option<Instant> {
+        self.waiting.keys().next().map(|(due, _)| *due)
+    }
+
+    pub fn has_room(&self) -> bool {
+        self.bytes < self.capacity
+    }
+}
+
+/// A [`MemoryNetwork`] whose links can be impaired
+///
+/// Links are named by their ends' listening addresses, so a node's
+/// connections count as coming from where it listens whichever side
+/// dialed. Conditions can change at any time and apply from the next frame.
+/// A dial takes a round trip and hangs over a link that loses everything.
+/// All randomness comes from the seed, so with paused time a run repeats
+/// exactly.
+#[derive(Clone)]
+pub struct SimNetwork {
+    network: MemoryNetwork,
+}
+
+impl SimNetwork {
+    /// A network of its own, every link perfect until set otherwise
+    pub fn new(seed: u64) -> Self {
+        let network = MemoryNetwork::new();
+        network.impairments().reseed(seed);
+        Self { network }
+    }
+
+    pub fn network(&self) -> &MemoryNetwork {
+        &self.network
+    }
+
+    /// A transport on this network, for
+    /// [`SwarmhostNode::with_transport`](crate::node::SwarmhostNode::with_transport)
+    pub fn transport(&self, config: &NetworkConfig) -> MemoryTransport {
+        self.network.transport(config)
+    }
+
+    /// Impair the link between the nodes listening at `a` and `b`, both ways
+    pub fn set_conditions(
+        &self,
+        a: SocketAddr,
+        b: SocketAddr,
+        conditions: impl Into<NetworkConditions>,
+    ) {
+        let (a, b) = (self.network.resolve(a), self.network.resolve(b));
+        self.network.impairments().set(a, b, conditions.into());
+    }
+
+    /// Impair every link between the nodes listening at `addrs`
+    pub fn set_all_conditions(
+        &self,
+        addrs: &[SocketAddr],
+        conditions: impl Into<NetworkConditions>,
+    ) {
+        let conditions = conditions.into();
+        for (i, &a) in addrs.iter().enumerate() {
+            for &b in &addrs[i + 1..] {
+                self.set_conditions(a, b, conditions.clone());
+            }
+        }
+    }
+
+    /// Conditions on the link between `a` and `b`
+    pub fn conditions(&self, a: SocketAddr, b: SocketAddr) -> NetworkConditions {
+        let (a, b) = (self.network.resolve(a), self.network.resolve(b));
+        self.network.impairments().get(a, b).unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::network::{Connection, Transport};
+
+    type Pair = (
+        Box<dyn Connection>,
+        Box<dyn Connection>,
+        SocketAddr,
+        SocketAddr,
+    );
+
+    /// A connection between two listening ends, and their addresses
+    async fn pair(sim: &SimNetwork) -> Pair {
+        let config = NetworkConfig::default();
+        let (left, right) = (sim.transport(&config), sim.transport(&config));
+        let left_listener = left.listen("127.0.0.1:0".parse().unwrap()).await.unwrap();
+        let right_listener = right.listen("127.0.0.1:0".parse().unwrap()).await.unwrap();
+        let (a, b) = (left_listener.local_addr(), right_listener.local_addr());
+        let dialed = left.dial(b).await.unwrap();
+        let accepted = right_listener.accept().await.unwrap();
+        (dialed, accepted, a, b)
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_latency_and_bandwidth_per_frame() {
+        let sim = SimNetwork::new(7);
+        let (mut dialed, mut accepted, a, b) = pair(&sim).await;
+        let conditions = NetworkConditions::perfect()
+            .with_latency(Duration::from_millis(50), Jitter::None)
+            .with_bandwidth(Some(1000));
+        sim.set_conditions(b, a, conditions.clone());
+        assert_eq!(sim.conditions(a, b), conditions);
+
+        // Two 500-byte frames take a second on the wire, then the latency
+        let start = Instant::now();
+        dialed.send(Bytes::from(vec![1; 500])).await.unwrap();
+        dialed.send(Bytes::from(vec![2; 500])).await.unwrap();
+        assert_eq!(accepted.recv().await.unwrap()[0], 1);
+        assert_eq!(accepted.recv().await.unwrap()[0], 2);
+        assert_eq!(start.elapsed(), Duration::from_millis(1050));
+
+        // Back to perfect from the next frame
+        sim.set_conditions(a, b, NetworkConditions::perfect());
+        let start = Instant::now();
+        accepted.send(Bytes::from_static(b"now")).await.unwrap();
+        assert_eq!(&dialed.recv().await.unwrap()[..], b"now");
+        assert_eq!(start.elapsed(), Duration::ZERO);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_reliable_frames_arrive_once_in_order_despite_loss() {
+        let sim = SimNetwork::new(1);
+        let (mut dialed, mut accepted, a, b) = pair(&sim).await;
+        sim.set_conditions(a, b, LinkPreset::Terrible);
+
+        for i in 0..200u8 {
+            dialed.send(Bytes::from(vec![i])).await.unwrap();
+        }
+        for i in 0..200u8 {
+            assert_eq!(accepted.recv().await.unwrap()[0], i);
+        }
+    }
+
+    async fn datagrams(seed: u64) -> Vec<u8> {
+        let sim = SimNetwork::new(seed);
+        let (mut dialed, mut accepted, a, b) = pair(&sim).await;
+        let conditions = NetworkConditions::from(LinkPreset::Terrible)
+            .with_loss(0.3)
+            .with_reorder(0.3)
+            .with_duplicate(0.3);
+        sim.set_conditions(a, b, conditions);
+
+        for i in 0..100u8 {
+            dialed.send_unreliable(Bytes::from(vec![i])).await.unwrap();
+        }
+        // A reliable marker after the datagrams, held back past all of them
+        tokio::time::sleep(Duration::from_secs(30)).await;
+        dialed.send(Bytes::from_static(&[u8::MAX])).await.unwrap();
+        let mut received = Vec::new();
+        loop {
+            match accepted.recv().await.unwrap()[0] {
+                u8::MAX => return received,
+                i => received.push(i),
+            }
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_datagrams_lost_reordered_and_duplicated_reproducibly() {
+        let received = datagrams(42).await;
+        let mut distinct = received.clone();
+        distinct.sort_unstable();
+        distinct.dedup();
+
+        assert!(distinct.len() < 100, "some were lost");
+        assert!(received.len() > distinct.len(), "some came twice");
+        assert!(received.windows(2).any(|w| w[0] > w[1]), "some overtook");
+        assert_eq!(datagrams(42).await, received);
+    }
+}
@@ -0,0 +1,283 @@
+// network/stun.rs - Minimal STUN (RFC 5389) binding client
+
+use crate::error::{Result, SwarmhostError};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::time::Duration;
+use tokio::net::UdpSocket;
+
+/// Fixed value in every STUN header since RFC 5389
+pub const MAGIC_COOKIE: u32 = 0x2112_A442;
+
+const BINDING_REQUEST: u16 = 0x0001;
+const BINDING_SUCCESS: u16 = 0x0101;
+const ATTR_MAPPED_ADDRESS: u16 = 0x0001;
+const ATTR_XOR_MAPPED_ADDRESS: u16 = 0x0020;
+const HEADER_LEN: usize = 20;
+const FAMILY_IPV4: u8 = 0x01;
+const FAMILY_IPV6: u8 = 0x02;
+
+pub type TransactionId = [u8; 12];
+
+/// Encode a Binding request with no attributes
+pub fn binding_request(transaction_id: &TransactionId) -> Vec<u8> {
+    let mut buf = Vec::with_capacity(HEADER_LEN);
+    write_header(&mut buf, BINDING_REQUEST, 0, transaction_id);
+    buf
+}
+
+/// Encode a Binding success response carrying an XOR-MAPPED-ADDRESS
+pub fn binding_response(transaction_id: &TransactionId, addr: SocketAddr) -> Vec<u8> {
+    let value = encode_address(addr, transaction_id);
+
+    let mut buf = Vec::with_capacity(HEADER_LEN + 4 + value.len());
+    write_header(
+        &mut buf,
+        BINDING_SUCCESS,
+        (4 + value.len()) as u16,
+        transaction_id,
+    );
+    buf.extend_from_slice(&ATTR_XOR_MAPPED_ADDRESS.to_be_bytes());
+    buf.extend_from_slice(&(value.len() as u16).to_be_bytes());
+    buf.extend_from_slice(&value);
+    buf
+}
+
+/// Parse a Binding success response and return the mapped address
+///
+/// XOR-MAPPED-ADDRESS is preferred; plain MAPPED-ADDRESS is accepted from
+/// servers that predate RFC 5389.
+pub fn parse_binding_response(buf: &[u8], transaction_id: &TransactionId) -> Result<SocketAddr> {
+    if buf.len() < HEADER_LEN {
+        return Err(stun_error("message shorter than header"));
+    }
+
+    let msg_type = u16::from_be_bytes([buf[0], buf[1]]);
+    let length = u16::from_be_bytes([buf[2], buf[3]]) as usize;
+    let cookie = u32::from_be_bytes([buf[4], buf[5], buf[6], buf[7]]);
+
+    if cookie != MAGIC_COOKIE {
+        return Err(stun_error("bad magic cookie"));
+    }
+    if &buf[8..HEADER_LEN] != transaction_id {
+        return Err(stun_error("transaction id mismatch"));
+    }
+    if msg_type != BINDING_SUCCESS {
+        return Err(stun_error(format!(
+            "unexpected message type {:#06x}",
+            msg_type
+        )));
+    }
+    if buf.len() < HEADER_LEN + length {
+        return Err(stun_error("truncated attributes"));
+    }
+
+    let mut attrs = &buf[HEADER_LEN..HEADER_LEN + length];
+    let mut mapped = None;
+
+    while attrs.len() >= 4 {
+        let attr_type = u16::from_be_bytes([attrs[0], attrs[1]]);
+        let attr_len = u16::from_be_bytes([attrs[2], attrs[3]]) as usize;
+        if attrs.len() < 4 + attr_len {
+            return Err(stun_error("truncated attribute"));
+        }
+
+        let value = &attrs[4..4 + attr_len];
+        match attr_type {
+            ATTR_XOR_MAPPED_ADDRESS => return decode_address(value, Some(transaction_id)),
+            ATTR_MAPPED_ADDRESS => mapped = Some(decode_address(value, None)?),
+            _ => {}
+        }
+
+        // Attribute values are padded to a multiple of four bytes
+        let padded = attr_len.div_ceil(4) * 4;
+        attrs = &attrs[(4 + padded).min(attrs.len())..];
+    }
+
+    mapped.ok_or_else(|| stun_error("no mapped address attribute"))
+}
+
+/// Ask a STUN server for the address `socket` appears as from outside
+pub async fn query(socket: &UdpSocket, server: &str, timeout: Duration) -> Result<SocketAddr> {
+    let transaction_id: TransactionId = rand::random();
+    socket
+        .send_to(&binding_request(&transaction_id), server)
+        .await?;
+
+    tokio::time::timeout(timeout, recv_response(socket, &transaction_id))
+        .await
+        .map_err(|_| SwarmhostError::timeout(format!("STUN server {} did not answer", server)))?
+}
+
+async fn recv_response(socket: &UdpSocket, transaction_id: &TransactionId) -> Result<SocketAddr> {
+    let mut buf = [0u8; 576];
+    loop {
+        let (len, from) = socket.recv_from(&mut buf).await?;
+        match parse_binding_response(&buf[..len], transaction_id) {
+            Ok(addr) => return Ok(addr),
+            Err(e) => tracing::debug!("Ignoring packet from {}: {}", from, e),
+        }
+    }
+}
+
+fn write_header(buf: &mut Vec<u8>, msg_type: u16, length: u16, transaction_id: &TransactionId) {
+    buf.extend_from_slice(&msg_type.to_be_bytes());
+    buf.extend_from_slice(&length.to_be_bytes());
+    buf.extend_from_slice(&MAGIC_COOKIE.to_be_bytes());
+    buf.extend_from_slice(transaction_id);
+}
+
+fn xor_key(transaction_id: &TransactionId) -> [u8; 16] {
+    let mut key = [0u8; 16];
+    key[..4].copy_from_slice(&MAGIC_COOKIE.to_be_bytes());
+    key[4..].copy_from_slice(transaction_id);
+    key
+}
+
+fn encode_address(addr: SocketAddr, transaction_id: &TransactionId) -> Vec<u8> {
+    let key = xor_key(transaction_id);
+    let port = addr.port() ^ (MAGIC_COOKIE >> 16) as u16;
+
+    let mut value = vec![0];
+    match addr.ip() {
+        IpAddr::V4(ip) => {
+            value.push(FAMILY_IPV4);
+            value.extend_from_slice(&port.to_be_bytes());
+            value.extend(ip.octets().iter().zip(&key).map(|(o, k)| o ^ k));
+        }
+        IpAddr::V6(ip) => {
+            value.push(FAMILY_IPV6);
+            value.extend_from_slice(&port.to_be_bytes());
+            value.extend(ip.octets().iter().zip(&key).map(|(o, k)| o ^ k));
+        }
+    }
+    value
+}
+
+fn decode_address(value: &[u8], xor: Option<&TransactionId>) -> Result<SocketAddr> {
+    if value.len() < 4 {
+        return Err(stun_error("address attribute too short"));
+    }
+
+    let key = xor.map(xor_key).unwrap_or([0u8; 16]);
+    let port_key = if xor.is_some() {
+        (MAGIC_COOKIE >> 16) as u16
+    } else {
+        0
+    };
+    let port = u16::from_be_bytes([value[2], value[3]]) ^ port_key;
+
+    match value[1] {
+        FAMILY_IPV4 if value.len() >= 8 => {
+            let mut octets = [0u8; 4];
+            for (i, octet) in octets.iter_mut().enumerate() {
+                *octet = value[4 + i] ^ key[i];
+            }
+            Ok(SocketAddr::new(IpAddr::V4(Ipv4Addr::from(octets)), port))
+        }
+        FAMILY_IPV6 if value.len() >= 20 => {
+            let mut octets = [0u8; 16];
+            for (i, octet) in octets.iter_mut().enumerate() {
+                *octet = value[4 + i] ^ key[i];
+            }
+            Ok(SocketAddr::new(IpAddr::V6(Ipv6Addr::from(octets)), port))
+        }
+        family => Err(stun_error(format!(
+            "bad address family {} or length {}",
+            family,
+            value.len()
+        ))),
+    }
+}
+
+fn stun_error(msg: impl std::fmt::Display) -> SwarmhostError {
+    SwarmhostError::Peer(format!("Invalid STUN response: {}", msg))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TXID: TransactionId = [
+        0xb7, 0xe7, 0xa7, 0x01, 0xbc, 0x34, 0xd6, 0x86, 0xfa, 0x87, 0xdf, 0xae,
+    ];
+
+    #[test]
+    fn test_request_header() {
+        let request = binding_request(&TXID);
+        assert_eq!(request.len(), 20);
+        assert_eq!(&request[..4], &[0x00, 0x01, 0x00, 0x00]);
+        assert_eq!(&request[4..8], &MAGIC_COOKIE.to_be_bytes());
+        assert_eq!(&request[8..], &TXID);
+    }
+
+    #[test]
+    fn test_parse_rfc5769_ipv4_vector() {
+        // XOR-MAPPED-ADDRESS portion of the RFC 5769 IPv4 response vector
+        let mut response = Vec::new();
+        write_header(&mut response, BINDING_SUCCESS, 12, &TXID);
+        response.extend_from_slice(&[
+            0x00, 0x20, 0x00, 0x08, 0x00, 0x01, 0xa1, 0x47, 0xe1, 0x12, 0xa6, 0x43,
+        ]);
+
+        let addr = parse_binding_response(&response, &TXID).unwrap();
+        assert_eq!(addr, "192.0.2.1:32853".parse().unwrap());
+    }
+
+    #[test]
+    fn test_round_trip_ipv6() {
+        let addr: SocketAddr = "[2001:db8:1234:5678:11:2233:4455:6677]:32853"
+            .parse()
+            .unwrap();
+        let response = binding_response(&TXID, addr);
+        assert_eq!(parse_binding_response(&response, &TXID).unwrap(), addr);
+    }
+
+    #[test]
+    fn test_plain_mapped_address_fallback() {
+        let mut response = Vec::new();
+        write_header(&mut response, BINDING_SUCCESS, 12, &TXID);
+        response.extend_from_slice(&[0x00, 0x01, 0x00, 0x08, 0x00, 0x01]);
+        response.extend_from_slice(&9000u16.to_be_bytes());
+        response.extend_from_slice(&[198, 51, 100, 7]);
+
+        let addr = parse_binding_response(&response, &TXID).unwrap();
+        assert_eq!(addr, "198.51.100.7:9000".parse().unwrap());
+    }
+
+    #[test]
+    fn test_rejects_bad_responses() {
+        let response = binding_response(&TXID, "192.0.2.1:1".parse().unwrap());
+
+        let mut other_txid = TXID;
+        other_txid[0] ^= 1;
+        assert!(parse_binding_response(&response, &other_txid).is_err());
+        assert!(parse_binding_response(&response[..10], &TXID).is_err());
+        assert!(parse_binding_response(&response[..response.len() - 2], &TXID).is_err());
+        assert!(parse_binding_response(&binding_request(&TXID), &TXID).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_query_mock_server() {
+        let server = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let server_addr = server.local_addr().unwrap().to_string();
+        let public: SocketAddr = "203.0.113.7:40000".parse().unwrap();
+
+        tokio::spawn(async move {
+            let mut buf = [0u8; 576];
+            let (len, from) = server.recv_from(&mut buf).await.unwrap();
+            assert!(len >= HEADER_LEN);
+            let mut txid = [0u8; 12];
+            txid.copy_from_slice(&buf[8..HEADER_LEN]);
+            server
+                .send_to(&binding_response(&txid, public), from)
+                .await
+                .unwrap();
+        });
+
+        let client = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let addr = query(&client, &server_addr, Duration::from_secs(2))
+            .await
+            .unwrap();
+        assert_eq!(addr, public);
+    }
+}
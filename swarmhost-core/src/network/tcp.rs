@@ -0,0 +1,196 @@
+// network/tcp.rs - TCP transport
+
+use super::proxy::{self, Socks5Proxy};
+use super::transport::{Connection, Listener, StreamConnection, Transport};
+use crate::error::Result;
+use crate::node::NetworkConfig;
+use async_trait::async_trait;
+use socket2::{Domain, Protocol, Socket, Type};
+use std::io;
+use std::net::SocketAddr;
+use tokio::net::{TcpListener, TcpStream};
+
+/// A framed TCP connection
+pub type TcpConnection = StreamConnection<TcpStream>;
+
+/// Dials and accepts TCP connections carrying length-prefixed frames
+#[derive(Debug, Clone)]
+pub struct TcpTransport {
+    max_message_size: usize,
+    accept_v4_mapped: bool,
+    proxy: Option<Socks5Proxy>,
+}
+
+impl TcpTransport {
+    pub fn new(config: &NetworkConfig) -> Self {
+        Self {
+            max_message_size: config.max_message_size,
+            accept_v4_mapped: config.dual_stack && config.bind_addr.is_ipv6(),
+            proxy: config.outbound_proxy.as_ref().map(Socks5Proxy::new),
+        }
+    }
+
+    /// Open a connection to `addr`, through the outbound proxy if one is set
+    pub async fn connect(&self, addr: SocketAddr) -> Result<TcpConnection> {
+        let stream = proxy::connect_tcp(self.proxy.as_ref(), addr).await?;
+        Ok(self.wrap(stream, addr))
+    }
+
+    /// Wait for the next inbound connection on `listener`
+    pub async fn accept(&self, listener: &TcpListener) -> Result<TcpConnection> {
+        let (stream, addr) = listener.accept().await?;
+        Ok(self.wrap(stream, addr))
+    }
+
+    fn wrap(&self, stream: TcpStream, peer_addr: SocketAddr) -> TcpConnection {
+        // Frames are small and latency matters more than packet count
+        let _ = stream.set_nodelay(true);
+        StreamConnection::new(stream, self.max_message_size, peer_addr)
+    }
+}
+
+#[async_trait]
+impl Transport for TcpTransport {
+    async fn listen(&self, addr: SocketAddr) -> Result<Box<dyn Listener>> {
+        let listener = bind_tcp(addr, !self.accept_v4_mapped)?;
+        Ok(Box::new(TcpTransportListener {
+            local_addr: listener.local_addr()?,
+            listener,
+            transport: self.clone(),
+        }))
+    }
+
+    async fn dial(&self, addr: SocketAddr) -> Result<Box<dyn Connection>> {
+        Ok(Box::new(self.connect(addr).await?))
+    }
+}
+
+struct TcpTransportListener {
+    listener: TcpListener,
+    local_addr: SocketAddr,
+    transport: TcpTransport,
+}
+
+#[async_trait]
+impl Listener for TcpTransportListener {
+    async fn accept(&self) -> Result<Box<dyn Connection>> {
+        Ok(Box::new(self.transport.accept(&self.listener).await?))
+    }
+
+    fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+}
+
+pub(super) fn bind_tcp(addr: SocketAddr, only_v6: bool) -> io::Result<TcpListener> {
+    let socket = Socket::new(Domain::for_address(addr), Type::STREAM, Some(Protocol::TCP))?;
+    if addr.is_ipv6() {
+        socket.set_only_v6(only_v6)?;
+    }
+    socket.set_reuse_address(true)?;
+    socket.set_nonblocking(true)?;
+    socket.bind(&addr.into())?;
+    socket.listen(1024)?;
+
+    TcpListener::from_std(socket.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::SwarmhostError;
+    use bytes::Bytes;
+    use tokio::io::AsyncWriteExt;
+
+    const MAX: usize = 4096;
+
+    async fn pair() -> (TcpConnection, TcpConnection) {
+        let transport = TcpTransport::new(&NetworkConfig {
+            max_message_size: MAX,
+            ..Default::default()
+        });
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let (dialed, accepted) = tokio::join!(transport.connect(addr), transport.accept(&listener));
+        let (dialed, accepted) = (dialed.unwrap(), accepted.unwrap());
+        assert_eq!(accepted.peer_addr(), dialed.get_ref().local_addr().unwrap());
+        assert_eq!(dialed.peer_addr(), addr);
+        (dialed, accepted)
+    }
+
+    #[tokio::test]
+    async fn test_exactly_max_size_message() {
+        let (mut a, mut b) = pair().await;
+        let payload = Bytes::from(vec![0x5a; MAX]);
+
+        let (sent, received) = tokio::join!(a.send(payload.clone()), b.recv());
+        sent.unwrap();
+        assert_eq!(received.unwrap(), payload);
+    }
+
+    #[tokio::test]
+    async fn test_one_byte_over_rejected_both_ways() {
+        let (mut a, mut b) = pair().await;
+
+        match a.send(Bytes::from(vec![0; MAX + 1])).await {
+            Err(SwarmhostError::TooLarge(msg)) => assert!(msg.contains(&(MAX + 1).to_string())),
+            other => panic!("expected too-large error, got {:?}", other),
+        }
+
+        // Nothing was written, so the connection is still usable
+        a.send(Bytes::from_static(b"still fine")).await.unwrap();
+        assert_eq!(&b.recv().await.unwrap()[..], b"still fine");
+
+        // A peer that ignores the limit gets disconnected
+        let mut raw = a.into_inner();
+        raw.write_all(&((MAX + 1) as u32).to_be_bytes())
+            .await
+            .unwrap();
+        match b.recv().await {
+            Err(SwarmhostError::TooLarge(msg)) => assert!(msg.contains(&(MAX + 1).to_string())),
+            other => panic!("expected too-large error, got {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_reassembles_across_segments() {
+        let (a, mut b) = pair().await;
+        let mut raw = a.into_inner();
+
+        let payload: Vec<u8> = (0..2000u32).map(|i| i as u8).collect();
+        let mut wire = (payload.len() as u32).to_be_bytes().to_vec();
+        wire.extend_from_slice(&payload);
+
+        let writer = tokio::spawn(async move {
+            for chunk in wire.chunks(97) {
+                raw.write_all(chunk).await.unwrap();
+                raw.flush().await.unwrap();
+                tokio::time::sleep(std::time::Duration::from_millis(1)).await;
+            }
+            raw
+        });
+
+        assert_eq!(&b.recv().await.unwrap()[..], &payload[..]);
+        writer.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_transport_trait_round_trip() {
+        let transport = TcpTransport::new(&NetworkConfig::default());
+        let listener = transport
+            .listen("127.0.0.1:0".parse().unwrap())
+            .await
+            .unwrap();
+        let addr = listener.local_addr();
+        assert_ne!(addr.port(), 0);
+
+        let (dialed, accepted) = tokio::join!(transport.dial(addr), listener.accept());
+        let (mut dialed, mut accepted) = (dialed.unwrap(), accepted.unwrap());
+        dialed.send(Bytes::from_static(b"ping")).await.unwrap();
+        assert_eq!(&accepted.recv().await.unwrap()[..], b"ping");
+
+        dialed.close().await.unwrap();
+        assert!(accepted.recv().await.is_err());
+    }
+}
@@ -0,0 +1,277 @@
+// network/throttle.rs - Upload rate limits
+
+use super::outbound::Priority;
+use crate::node::UploadConfig;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::time::Instant;
+
+/// A rate shared by every connection, e.g. the node-wide upload cap
+pub type SharedBandwidth = Arc<Mutex<Bandwidth>>;
+
+/// Token bucket holding traffic to a byte rate
+///
+/// Up to one second of traffic may burst unless set otherwise with
+/// [`with_burst`](Self::with_burst); beyond that each frame is delayed until
+/// the rate allows it.
+#[derive(Debug)]
+pub struct Bandwidth {
+    rate: u64,
+    /// Most bytes that may build up while idle
+    burst: f64,
+    /// Bytes that may be sent now; negative when frames are already waiting
+    tokens: f64,
+    last: Instant,
+}
+
+impl Bandwidth {
+    pub fn new(rate: u64, now: Instant) -> Self {
+        Self {
+            rate,
+            burst: rate as f64,
+            tokens: rate as f64,
+            last: now,
+        }
+    }
+
+    /// Let at most `burst` worth of traffic through at once
+    pub fn with_burst(mut self, burst: Duration) -> Self {
+        self.burst = self.rate as f64 * burst.as_secs_f64();
+        self.tokens = self.tokens.min(self.burst);
+        self
+    }
+
+    /// Take `len` bytes from the bucket, returning how long the frame must
+    /// wait before it goes out
+    pub fn reserve(&mut self, len: usize, now: Instant) -> Duration {
+        self.take(len, now);
+        self.wait(now)
+    }
+
+    /// Take `len` bytes for a frame that goes out now, waiting or not
+    pub fn take(&mut self, len: usize, now: Instant) {
+        self.refill(now);
+        self.tokens -= len as f64;
+    }
+
+    /// How long until the bucket is out of debt
+    pub fn wait(&mut self, now: Instant) -> Duration {
+        self.refill(now);
+        if self.tokens >= 0.0 {
+            Duration::ZERO
+        } else {
+            Duration::from_secs_f64(-self.tokens / self.rate as f64)
+        }
+    }
+
+    fn refill(&mut self, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.last).as_secs_f64();
+        self.last = now;
+        self.tokens = (self.tokens + elapsed * self.rate as f64).min(self.burst);
+    }
+}
+
+/// The upload limits for one connection
+///
+/// A frame may go out once every bucket it is charged to is out of debt: the
+/// peer's, its class's and the node-wide one. Control frames are charged to
+/// a small reserve instead while it lasts, so heartbeats and consensus are
+/// not held up behind bulk traffic.
+pub struct Throttle {
+    peer: Option<Bandwidth>,
+    /// Per-class limits, indexed by priority
+    classes: [Option<Bandwidth>; 3],
+    reserve: Bandwidth,
+    global: Option<SharedBandwidth>,
+    held_since: Option<Instant>,
+}
+
+impl Throttle {
+    pub fn new(config: &UploadConfig, global: Option<SharedBandwidth>, now: Instant) -> Self {
+        let bucket =
+            |rate: Option<u64>| rate.map(|rate| Bandwidth::new(rate, now).with_burst(config.burst));
+        Self {
+            peer: bucket(config.max_upload_bytes_per_sec_per_peer),
+            classes: [
+                None,
+                bucket(config.game_action_bytes_per_sec),
+                bucket(config.bulk_bytes_per_sec),
+            ],
+            reserve: Bandwidth::new(config.control_reserve_bytes_per_sec.max(1), now),
+            global,
+            held_since: None,
+        }
+    }
+
+    /// The node-wide upload cap in `config`, to share between connections
+    pub fn global(config: &UploadConfig, now: Instant) -> Option<SharedBandwidth> {
+        config.max_upload_bytes_per_sec.map(|rate| {
+            Arc::new(Mutex::new(
+                Bandwidth::new(rate, now).with_burst(config.burst),
+            ))
+        })
+    }
+
+    /// How long before a frame of `priority` may go out; zero if now
+    pub fn delay(&mut self, priority: Priority, now: Instant) -> Duration {
+        if self.uses_reserve(priority, now) {
+            return Duration::ZERO;
+        }
+        let mut delay = Duration::ZERO;
+        for bucket in self.buckets(priority) {
+            delay = delay.max(bucket.wait(now));
+        }
+        if let Some(global) = &self.global {
+            delay = delay.max(global.lock().unwrap().wait(now));
+        }
+        delay
+    }
+
+    /// Charge a frame of `len` bytes that is going out now
+    ///
+    /// Frames sent without asking [`delay`](Self::delay) first, like pings,
+    /// are charged too, pushing back the traffic that does wait.
+    pub fn record(&mut self, priority: Priority, len: usize, now: Instant) {
+        if self.uses_reserve(priority, now) {
+            self.reserve.take(len, now);
+            return;
+        }
+        for bucket in self.buckets(priority) {
+            bucket.take(len, now);
+        }
+        if let Some(global) = &self.global {
+            global.lock().unwrap().take(len, now);
+        }
+    }
+
+    /// Note whether queued messages are being held back, returning how long
+    /// they were once they no longer are
+    pub fn hold(&mut self, held: bool, now: Instant) -> Duration {
+        match (held, self.held_since) {
+            (true, None) => {
+                self.held_since = Some(now);
+                Duration::ZERO
+            }
+            (false, Some(since)) => {
+                self.held_since = None;
+                now.saturating_duration_since(since)
+            }
+            _ => Duration::ZERO,
+        }
+    }
+
+    fn uses_reserve(&mut self, priority: Priority, now: Instant) -> bool {
+        priority == Priority::Control && self.reserve.wait(now).is_zero()
+    }
+
+    fn buckets(&mut self, priority: Priority) -> impl Iterator<Item = &mut Bandwidth> {
+        self.peer
+            .iter_mut()
+            .chain(self.classes[priority as usize].iter_mut())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config() -> UploadConfig {
+        UploadConfig {
+            max_upload_bytes_per_sec_per_peer: Some(1000),
+            bulk_bytes_per_sec: Some(500),
+            control_reserve_bytes_per_sec: 100,
+            burst: Duration::from_secs(1),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_bandwidth_allows_burst_then_paces() {
+        let start = Instant::now();
+        let mut bucket = Bandwidth::new(1000, start);
+
+        assert_eq!(bucket.reserve(600, start), Duration::ZERO);
+        assert_eq!(bucket.reserve(400, start), Duration::ZERO);
+        // The bucket is empty, so 500 more bytes take half a second
+        assert_eq!(bucket.reserve(500, start), Duration::from_millis(500));
+        // ...and the next 500 queue up behind them
+        assert_eq!(bucket.reserve(500, start), Duration::from_secs(1));
+
+        // Idle time refills, but never past one second's worth
+        let later = start + Duration::from_secs(10);
+        assert_eq!(bucket.reserve(1000, later), Duration::ZERO);
+        assert_eq!(bucket.reserve(100, later), Duration::from_millis(100));
+
+        let mut short = Bandwidth::new(1000, start).with_burst(Duration::from_millis(100));
+        assert_eq!(short.reserve(300, start), Duration::from_millis(200));
+    }
+
+    #[test]
+    fn test_bulk_held_back_before_other_classes() {
+        let now = Instant::now();
+        let mut throttle = Throttle::new(&config(), None, now);
+
+        throttle.record(Priority::Bulk, 1000, now);
+        // Bulk is over its own, lower rate while the peer limit has room
+        assert_eq!(throttle.delay(Priority::Bulk, now), Duration::from_secs(1));
+        assert_eq!(throttle.delay(Priority::GameAction, now), Duration::ZERO);
+
+        throttle.record(Priority::GameAction, 500, now);
+        assert_eq!(
+            throttle.delay(Priority::GameAction, now),
+            Duration::from_millis(500)
+        );
+        assert_eq!(throttle.delay(Priority::Bulk, now), Duration::from_secs(1));
+    }
+
+    #[test]
+    fn test_control_reserve_bypasses_limits() {
+        let now = Instant::now();
+        let mut throttle = Throttle::new(&config(), None, now);
+        throttle.record(Priority::Bulk, 5000, now);
+
+        // Within the reserve, control goes out at once
+        assert_eq!(throttle.delay(Priority::Control, now), Duration::ZERO);
+        throttle.record(Priority::Control, 150, now);
+        // Past it, control waits on the peer limit like everything else
+        assert!(!throttle.delay(Priority::Control, now).is_zero());
+        let refilled = now + Duration::from_millis(500);
+        assert_eq!(throttle.delay(Priority::Control, refilled), Duration::ZERO);
+    }
+
+    #[test]
+    fn test_global_limit_shared_between_connections() {
+        let now = Instant::now();
+        let config = UploadConfig {
+            max_upload_bytes_per_sec: Some(1000),
+            ..Default::default()
+        };
+        let global = Throttle::global(&config, now);
+        let mut first = Throttle::new(&config, global.clone(), now);
+        let mut second = Throttle::new(&config, global, now);
+
+        first.record(Priority::Bulk, 1250, now);
+        assert_eq!(second.delay(Priority::Bulk, now), Duration::from_secs(1));
+        assert!(Throttle::global(&UploadConfig::default(), now).is_none());
+    }
+
+    #[test]
+    fn test_hold_measures_time_held_back() {
+        let start = Instant::now();
+        let mut throttle = Throttle::new(&config(), None, start);
+
+        assert_eq!(throttle.hold(true, start), Duration::ZERO);
+        assert_eq!(
+            throttle.hold(true, start + Duration::from_secs(1)),
+            Duration::ZERO
+        );
+        assert_eq!(
+            throttle.hold(false, start + Duration::from_secs(3)),
+            Duration::from_secs(3)
+        );
+        assert_eq!(
+            throttle.hold(false, start + Duration::from_secs(4)),
+            Duration::ZERO
+        );
+    }
+}
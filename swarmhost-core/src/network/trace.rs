@@ -0,0 +1,187 @@
+// network/trace.rs - Correlation ids that follow messages across peers
+
+use crate::consensus::ActionId;
+use crate::crypto::{PlayerId, short_id};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
+use std::hash::Hash;
+use tracing::Span;
+
+/// Most correlation ids remembered of each kind before the oldest go
+const MAX_REMEMBERED: usize = 4096;
+
+/// Header carried in front of a traced message
+///
+/// Everything sent about one action (its proposal, the votes on it and
+/// every relayed copy) shares the action's correlation id, and a response
+/// shares its request's, so one grep over every node's logs finds the path.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TraceContext {
+    pub correlation: u64,
+    /// The action the message is about, if any
+    pub action: Option<ActionId>,
+}
+
+impl TraceContext {
+    /// A fresh correlation id
+    pub fn new(action: Option<ActionId>) -> Self {
+        Self {
+            correlation: rand::random(),
+            action,
+        }
+    }
+}
+
+/// A step of a message's path that gets its own span
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Step {
+    Propose,
+    Send,
+    Receive,
+    Validate,
+    Vote,
+    Commit,
+}
+
+/// Span for `step` at `node`, recording the trace's ids; disabled for
+/// untraced messages
+pub fn span(step: Step, node: &PlayerId, trace: Option<&TraceContext>) -> Span {
+    let Some(trace) = trace else {
+        return Span::none();
+    };
+    let node = short_id(node);
+    let action = trace.action.as_ref().map(short_id);
+    // Span names must be literals
+    macro_rules! step_span {
+        ($name:literal) => {
+            tracing::info_span!(
+                $name,
+                node = %node,
+                correlation = trace.correlation,
+                action = ?action
+            )
+        };
+    }
+    match step {
+        Step::Propose => step_span!("propose"),
+        Step::Send => step_span!("send"),
+        Step::Receive => step_span!("receive"),
+        Step::Validate => step_span!("validate"),
+        Step::Vote => step_span!("vote"),
+        Step::Commit => step_span!("commit"),
+    }
+}
+
+/// Correlation ids this node gave out or adopted, so that what it sends
+/// later about the same action or request carries them on
+///
+/// The first id seen for an action wins; the oldest are forgotten past a
+/// few thousand of each kind.
+#[derive(Default)]
+pub struct Tracer {
+    actions: Recent<ActionId>,
+    requests: Recent<(PlayerId, u64)>,
+}
+
+impl Tracer {
+    /// The trace of `action`, starting one if we have none
+    pub fn action(&mut self, action: ActionId) -> TraceContext {
+        let correlation = self
+            .actions
+            .get_or_insert(action, || TraceContext::new(None).correlation);
+        TraceContext {
+            correlation,
+            action: Some(action),
+        }
+    }
+
+    /// Carry on with `trace` for its action, unless we already have one
+    pub fn adopt(&mut self, trace: &TraceContext) {
+        if let Some(action) = trace.action {
+            self.actions.get_or_insert(action, || trace.correlation);
+        }
+    }
+
+    /// Remember the trace of request `id` from `peer` for the response
+    pub fn requested(&mut self, peer: PlayerId, id: u64, trace: &TraceContext) {
+        self.requests
+            .get_or_insert((peer, id), || trace.correlation);
+    }
+
+    /// The trace for our response to request `id` from `peer`
+    pub fn response(&mut self, peer: PlayerId, id: u64) -> TraceContext {
+        match self.requests.remove(&(peer, id)) {
+            Some(correlation) => TraceContext {
+                correlation,
+                action: None,
+            },
+            None => TraceContext::new(None),
+        }
+    }
+}
+
+/// Bounded map that forgets in insertion order
+struct Recent<K> {
+    ids: HashMap<K, u64>,
+    order: VecDeque<K>,
+}
+
+impl<K> Default for Recent<K> {
+    fn default() -> Self {
+        Self {
+            ids: HashMap::new(),
+            order: VecDeque::new(),
+        }
+    }
+}
+
+impl<K: Hash + Eq + Copy> Recent<K> {
+    fn get_or_insert(&mut self, key: K, id: impl FnOnce() -> u64) -> u64 {
+        if let Some(&existing) = self.ids.get(&key) {
+            return existing;
+        }
+        let id = id();
+        self.ids.insert(key, id);
+        self.order.push_back(key);
+        while self.order.len() > MAX_REMEMBERED {
+            if let Some(oldest) = self.order.pop_front() {
+                self.ids.remove(&oldest);
+            }
+        }
+        id
+    }
+
+    fn remove(&mut self, key: &K) -> Option<u64> {
+        let id = self.ids.remove(key)?;
+        self.order.retain(|k| k != key);
+        Some(id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_action_keeps_first_correlation() {
+        let action: ActionId = [7; 32];
+        let mut tracer = Tracer::default();
+        let theirs = TraceContext::new(Some(action));
+        tracer.adopt(&theirs);
+        tracer.adopt(&TraceContext::new(Some(action)));
+        assert_eq!(tracer.action(action), theirs);
+
+        let ours = tracer.action([8; 32]);
+        assert_eq!(tracer.action([8; 32]), ours);
+    }
+
+    #[test]
+    fn test_response_reuses_request_correlation() {
+        let peer: PlayerId = [1; 32];
+        let mut tracer = Tracer::default();
+        let request = TraceContext::new(None);
+        tracer.requested(peer, 3, &request);
+        assert_eq!(tracer.response(peer, 3), request);
+        assert_ne!(tracer.response(peer, 3).correlation, request.correlation);
+    }
+}
@@ -0,0 +1,117 @@
+// network/transport.rs - Transport abstraction shared by TCP and in-memory connections
+
+use super::frame::FramedStream;
+use crate::crypto::PlayerId;
+use crate::error::Result;
+use async_trait::async_trait;
+use bytes::Bytes;
+use std::net::SocketAddr;
+use tokio::io::{AsyncRead, AsyncWrite};
+
+/// A way of reaching peers: TCP, in-memory, ...
+#[async_trait]
+pub trait Transport: Send + Sync {
+    /// Start accepting connections on `addr` (port 0 picks a free port)
+    async fn listen(&self, addr: SocketAddr) -> Result<Box<dyn Listener>>;
+
+    /// Open a connection to `addr`
+    async fn dial(&self, addr: SocketAddr) -> Result<Box<dyn Connection>>;
+}
+
+/// Accepts inbound connections for a transport
+#[async_trait]
+pub trait Listener: Send + Sync {
+    /// Wait for the next inbound connection
+    async fn accept(&self) -> Result<Box<dyn Connection>>;
+
+    /// Address the listener is bound to
+    fn local_addr(&self) -> SocketAddr;
+}
+
+/// A message-oriented connection to one peer
+///
+/// `send` and `recv` must be cancel-safe: dropping either mid-way must not
+/// lose or corrupt frames.
+#[async_trait]
+pub trait Connection: Send {
+    /// Send one message, failing before writing if it exceeds the limit
+    async fn send(&mut self, payload: Bytes) -> Result<()>;
+
+    /// Send one message that may be lost or reordered (e.g. position updates)
+    ///
+    /// Transports without an unreliable channel send it reliably. Either way
+    /// the peer receives it from `recv`.
+    async fn send_unreliable(&mut self, payload: Bytes) -> Result<()> {
+        self.send(payload).await
+    }
+
+    /// Receive one message
+    async fn recv(&mut self) -> Result<Bytes>;
+
+    /// Flush anything queued and close the write side
+    async fn close(&mut self) -> Result<()>;
+
+    /// Address of the remote end
+    fn peer_addr(&self) -> SocketAddr;
+
+    /// Largest message accepted in either direction
+    fn max_message_size(&self) -> usize;
+
+    /// Player id the transport itself authenticated, if it does so
+    fn peer_identity(&self) -> Option<PlayerId> {
+        None
+    }
+}
+
+/// [`Connection`] over any byte stream, using length-prefixed frames
+pub struct StreamConnection<S> {
+    framed: FramedStream<S>,
+    peer_addr: SocketAddr,
+}
+
+impl<S> StreamConnection<S>
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send,
+{
+    pub fn new(stream: S, max_message_size: usize, peer_addr: SocketAddr) -> Self {
+        Self {
+            framed: FramedStream::new(stream, max_message_size),
+            peer_addr,
+        }
+    }
+
+    pub fn get_ref(&self) -> &S {
+        self.framed.get_ref()
+    }
+
+    /// Give back the underlying stream, discarding buffered data
+    pub fn into_inner(self) -> S {
+        self.framed.into_inner()
+    }
+}
+
+#[async_trait]
+impl<S> Connection for StreamConnection<S>
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send,
+{
+    async fn send(&mut self, payload: Bytes) -> Result<()> {
+        self.framed.send(&payload).await
+    }
+
+    async fn recv(&mut self) -> Result<Bytes> {
+        self.framed.recv().await
+    }
+
+    async fn close(&mut self) -> Result<()> {
+        self.framed.close().await
+    }
+
+    fn peer_addr(&self) -> SocketAddr {
+        self.peer_addr
+    }
+
+    fn max_message_size(&self) -> usize {
+        self.framed.max_frame_size()
+    }
+}
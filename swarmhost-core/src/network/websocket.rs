@@ -0,0 +1,356 @@
+// network/websocket.rs - WebSocket transport for browser peers
+
+use super::proxy::{self, Socks5Proxy};
+use super::tcp::bind_tcp;
+use super::transport::{Connection, Listener, StreamConnection, Transport};
+use crate::error::{Result, SwarmhostError};
+use crate::node::NetworkConfig;
+use async_trait::async_trait;
+use futures_util::{Sink, Stream};
+use std::io;
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::task::{Context, Poll, ready};
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::{Mutex, mpsc};
+use tokio::task::JoinHandle;
+use tokio_tungstenite::WebSocketStream;
+use tokio_tungstenite::tungstenite::handshake::server::{ErrorResponse, Request, Response};
+use tokio_tungstenite::tungstenite::http::StatusCode;
+use tokio_tungstenite::tungstenite::protocol::WebSocketConfig;
+use tokio_tungstenite::tungstenite::{self, Message};
+
+/// A framed WebSocket connection
+pub type WebSocketConnection = StreamConnection<WsStream<TcpStream>>;
+
+/// Connections that finished the upgrade but were not yet accepted
+const ACCEPT_BACKLOG: usize = 128;
+
+/// [`Transport`] over WebSocket, for peers that cannot open raw sockets
+///
+/// Every binary message carries bytes of the same length-prefixed frames TCP
+/// uses, so message limits and errors match the TCP transport exactly.
+#[derive(Debug, Clone)]
+pub struct WebSocketTransport {
+    max_message_size: usize,
+    path: String,
+    accept_timeout: Duration,
+    accept_v4_mapped: bool,
+    proxy: Option<Socks5Proxy>,
+}
+
+impl WebSocketTransport {
+    pub fn new(config: &NetworkConfig) -> Self {
+        Self {
+            max_message_size: config.max_message_size,
+            path: config.websocket_path.clone(),
+            accept_timeout: config.security.handshake_timeout,
+            accept_v4_mapped: config.dual_stack && config.bind_addr.is_ipv6(),
+            proxy: config.outbound_proxy.as_ref().map(Socks5Proxy::new),
+        }
+    }
+
+    /// Open a connection to `ws://addr/<path>`, through the outbound proxy if
+    /// one is set
+    pub async fn connect(&self, addr: SocketAddr) -> Result<WebSocketConnection> {
+        let stream = proxy::connect_tcp(self.proxy.as_ref(), addr).await?;
+        let _ = stream.set_nodelay(true);
+
+        let url = format!("ws://{}{}", addr, self.path);
+        let (ws, _) = tokio_tungstenite::client_async_with_config(
+            url,
+            stream,
+            Some(ws_config(self.max_message_size)),
+        )
+        .await
+        .map_err(ws_error)?;
+
+        Ok(StreamConnection::new(
+            WsStream::new(ws),
+            self.max_message_size,
+            addr,
+        ))
+    }
+
+    /// Accept the upgrade on a freshly accepted TCP stream, refusing other
+    /// paths with 404
+    async fn upgrade(&self, stream: TcpStream, addr: SocketAddr) -> Result<WebSocketConnection> {
+        let _ = stream.set_nodelay(true);
+
+        let path = self.path.clone();
+        // The error type is fixed by tungstenite's handshake callback
+        #[allow(clippy::result_large_err)]
+        let check_path = move |request: &Request, response: Response| {
+            if request.uri().path() == path {
+                Ok(response)
+            } else {
+                let mut refusal = ErrorResponse::new(Some("unknown path".to_string()));
+                *refusal.status_mut() = StatusCode::NOT_FOUND;
+                Err(refusal)
+            }
+        };
+        let ws = tokio_tungstenite::accept_hdr_async_with_config(
+            stream,
+            check_path,
+            Some(ws_config(self.max_message_size)),
+        )
+        .await
+        .map_err(ws_error)?;
+
+        Ok(StreamConnection::new(
+            WsStream::new(ws),
+            self.max_message_size,
+            addr,
+        ))
+    }
+}
+
+#[async_trait]
+impl Transport for WebSocketTransport {
+    async fn listen(&self, addr: SocketAddr) -> Result<Box<dyn Listener>> {
+        let listener = bind_tcp(addr, !self.accept_v4_mapped)?;
+        let local_addr = listener.local_addr()?;
+
+        let (tx, rx) = mpsc::channel(ACCEPT_BACKLOG);
+        let driver = tokio::spawn(drive_accepts(listener, self.clone(), tx));
+
+        Ok(Box::new(WebSocketListener {
+            local_addr,
+            incoming: Mutex::new(rx),
+            driver,
+        }))
+    }
+
+    async fn dial(&self, addr: SocketAddr) -> Result<Box<dyn Connection>> {
+        Ok(Box::new(self.connect(addr).await?))
+    }
+}
+
+/// Run upgrades in the background so a slow client cannot hold up the
+/// accept loop
+async fn drive_accepts(
+    listener: TcpListener,
+    transport: WebSocketTransport,
+    ready: mpsc::Sender<Result<WebSocketConnection>>,
+) {
+    loop {
+        let (stream, addr) = match listener.accept().await {
+            Ok(accepted) => accepted,
+            Err(e) => {
+                if ready.send(Err(e.into())).await.is_err() {
+                    return;
+                }
+                continue;
+            }
+        };
+
+        let ready = ready.clone();
+        let transport = transport.clone();
+        tokio::spawn(async move {
+            match tokio::time::timeout(transport.accept_timeout, transport.upgrade(stream, addr))
+                .await
+            {
+                Ok(Ok(conn)) => {
+                    let _ = ready.send(Ok(conn)).await;
+                }
+                Ok(Err(e)) => tracing::debug!("WebSocket upgrade from {} failed: {}", addr, e),
+                Err(_) => tracing::debug!("WebSocket upgrade from {} timed out", addr),
+            }
+        });
+    }
+}
+
+struct WebSocketListener {
+    local_addr: SocketAddr,
+    incoming: Mutex<mpsc::Receiver<Result<WebSocketConnection>>>,
+    driver: JoinHandle<()>,
+}
+
+#[async_trait]
+impl Listener for WebSocketListener {
+    async fn accept(&self) -> Result<Box<dyn Connection>> {
+        let conn = self
+            .incoming
+            .lock()
+            .await
+            .recv()
+            .await
+            .ok_or_else(|| io::Error::from(io::ErrorKind::NotConnected))??;
+        Ok(Box::new(conn))
+    }
+
+    fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+}
+
+impl Drop for WebSocketListener {
+    fn drop(&mut self) {
+        self.driver.abort();
+    }
+}
+
+/// Byte stream over the binary messages of a WebSocket
+///
+/// Each write becomes one binary message; reads drain messages in order.
+/// Text messages are a protocol error, and a close message reads as EOF.
+pub struct WsStream<S> {
+    ws: WebSocketStream<S>,
+    pending: Vec<u8>,
+    offset: usize,
+}
+
+impl<S> WsStream<S> {
+    fn new(ws: WebSocketStream<S>) -> Self {
+        Self {
+            ws,
+            pending: Vec::new(),
+            offset: 0,
+        }
+    }
+}
+
+impl<S> AsyncRead for WsStream<S>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        while self.offset == self.pending.len() {
+            match ready!(Pin::new(&mut self.ws).poll_next(cx)) {
+                Some(Ok(Message::Binary(data))) => {
+                    self.pending = data;
+                    self.offset = 0;
+                }
+                Some(Ok(Message::Text(_))) => {
+                    return Poll::Ready(Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        "unexpected text message",
+                    )));
+                }
+                Some(Ok(Message::Close(_))) | None => return Poll::Ready(Ok(())),
+                Some(Ok(_)) => {} // ping/pong are answered by tungstenite
+                Some(Err(e)) => return Poll::Ready(Err(into_io(e))),
+            }
+        }
+
+        let n = buf.remaining().min(self.pending.len() - self.offset);
+        let start = self.offset;
+        buf.put_slice(&self.pending[start..start + n]);
+        self.offset += n;
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl<S> AsyncWrite for WsStream<S>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        ready!(Pin::new(&mut self.ws).poll_ready(cx)).map_err(into_io)?;
+        Pin::new(&mut self.ws)
+            .start_send(Message::Binary(buf.to_vec()))
+            .map_err(into_io)?;
+        Poll::Ready(Ok(buf.len()))
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.ws).poll_flush(cx).map_err(into_io)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match ready!(Pin::new(&mut self.ws).poll_close(cx)) {
+            Ok(()) | Err(tungstenite::Error::ConnectionClosed) => Poll::Ready(Ok(())),
+            Err(e) => Poll::Ready(Err(into_io(e))),
+        }
+    }
+}
+
+fn ws_config(max_message_size: usize) -> WebSocketConfig {
+    // Room for one frame plus its length prefix; the frame layer reports
+    // oversized messages itself
+    let limit = max_message_size + super::frame::LENGTH_PREFIX_LEN;
+    WebSocketConfig {
+        max_message_size: Some(limit),
+        max_frame_size: Some(limit),
+        ..Default::default()
+    }
+}
+
+fn into_io(e: tungstenite::Error) -> io::Error {
+    match e {
+        tungstenite::Error::Io(e) => e,
+        other => io::Error::other(other),
+    }
+}
+
+fn ws_error(e: tungstenite::Error) -> SwarmhostError {
+    SwarmhostError::Network(into_io(e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bytes::Bytes;
+
+    const MAX: usize = 4096;
+
+    fn transport(path: &str) -> WebSocketTransport {
+        WebSocketTransport::new(&NetworkConfig {
+            max_message_size: MAX,
+            websocket_path: path.to_string(),
+            ..Default::default()
+        })
+    }
+
+    async fn pair() -> (Box<dyn Connection>, Box<dyn Connection>, Box<dyn Listener>) {
+        let transport = transport("/swarmhost");
+        let listener = transport
+            .listen("127.0.0.1:0".parse().unwrap())
+            .await
+            .unwrap();
+        let dialed = transport.dial(listener.local_addr()).await.unwrap();
+        let accepted = listener.accept().await.unwrap();
+        (dialed, accepted, listener)
+    }
+
+    #[tokio::test]
+    async fn test_messages_and_size_limit_match_tcp() {
+        let (mut a, mut b, _listener) = pair().await;
+
+        let exact = Bytes::from(vec![3u8; MAX]);
+        a.send(exact.clone()).await.unwrap();
+        assert_eq!(b.recv().await.unwrap(), exact);
+
+        match a.send(Bytes::from(vec![0u8; MAX + 1])).await {
+            Err(SwarmhostError::TooLarge(msg)) => assert!(msg.contains(&(MAX + 1).to_string())),
+            other => panic!("expected too-large error, got {:?}", other),
+        }
+
+        b.send(Bytes::from_static(b"still fine")).await.unwrap();
+        assert_eq!(&a.recv().await.unwrap()[..], b"still fine");
+
+        a.close().await.unwrap();
+        assert!(b.recv().await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_wrong_path_refused() {
+        let listener = transport("/swarmhost")
+            .listen("127.0.0.1:0".parse().unwrap())
+            .await
+            .unwrap();
+
+        let result = transport("/other").dial(listener.local_addr()).await;
+        assert!(matches!(result, Err(SwarmhostError::Network(_))));
+    }
+}
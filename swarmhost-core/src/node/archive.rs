@@ -0,0 +1,146 @@
+// node/archive.rs - Exporting a game to an archive and importing one, to
+// move it between hosts or look into it elsewhere
+
+use super::peers::PeerContext;
+use super::sequence;
+use crate::crypto::{Hash, short_id};
+use crate::error::{Result, SwarmhostError};
+use crate::state::{ArchiveConfig, ArchiveTail, GameArchive, ReplayMode};
+use tokio::time::Instant;
+
+/// What an exported or imported archive held
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExportSummary {
+    pub game_id: String,
+    /// Sequence of the last commit held
+    pub sequence: u64,
+    pub state_hash: Hash,
+    /// Sequence the snapshot was taken at
+    pub snapshot_sequence: u64,
+    /// Commits held after the snapshot
+    pub commits: usize,
+    /// Size of the archive
+    pub bytes: u64,
+}
+
+impl ExportSummary {
+    pub(super) fn of(archive: &GameArchive, bytes: u64) -> Self {
+        Self {
+            game_id: archive.header.game_id.clone(),
+            sequence: archive.header.sequence,
+            state_hash: archive.header.state_hash,
+            snapshot_sequence: archive.snapshot.sequence,
+            commits: archive.tail.commits.len(),
+            bytes,
+        }
+    }
+}
+
+/// Archive a game as we hold it: the snapshot we would sync a peer from
+/// and our delivered commits after it, or, when history no longer reaches
+/// back to it, a snapshot of the log as it stands
+pub(super) async fn export(
+    game_id: &str,
+    snapshot_interval: u32,
+    ctx: &PeerContext,
+) -> Result<GameArchive> {
+    let consensus = ctx.consensus.lock().await;
+    let state_manager = ctx.state_manager.lock().await;
+    let Some(log) = state_manager.log(game_id) else {
+        return Err(SwarmhostError::InvalidState(format!(
+            "No log of {} to export",
+            game_id
+        )));
+    };
+    let (snapshot, attestation) = state_manager.sync_snapshot(game_id)?;
+    let after = log.sequence().checked_sub(snapshot.sequence);
+    let certified = after.map(|count| {
+        let count = u32::try_from(count).unwrap_or(u32::MAX);
+        consensus.certified(game_id, snapshot.sequence + 1, count)
+    });
+    let (snapshot, attestation, tail) = match certified {
+        Some(certified) if Some(certified.commits.len() as u64) == after => {
+            let tail = ArchiveTail {
+                commits: certified.commits,
+                blocks: certified.blocks,
+            };
+            (snapshot, attestation, tail)
+        }
+        _ => {
+            let Some(snapshot) = state_manager.current_snapshot(game_id) else {
+                return Err(SwarmhostError::InvalidState(format!(
+                    "No log of {} to export",
+                    game_id
+                )));
+            };
+            (snapshot, None, ArchiveTail::default())
+        }
+    };
+    let config = ArchiveConfig {
+        validators: consensus
+            .validators()
+            .ids()
+            .map(|id| (*id, consensus.validators().weight_of(id)))
+            .collect(),
+        required: consensus.required_weight(),
+        checkpoint_interval: ctx.consensus_config.borrow().checkpoint_interval,
+        snapshot_interval,
+    };
+    let checkpoint = state_manager.latest_checkpoint(game_id).cloned();
+    GameArchive::new(
+        ctx.local_id,
+        config,
+        checkpoint,
+        snapshot,
+        attestation,
+        tail,
+    )
+}
+
+/// Replay an archive and take its game up where the replay ends, ready to
+/// join and go on with; refused if we already hold the game that far
+///
+/// The archive is checked against our validators, or against those it
+/// names when we have none yet, in which case it is only as trustworthy
+/// as where it came from. A snapshot without an attestation is refused
+/// unless `allow_unverified`.
+pub(super) async fn import(
+    archive: &GameArchive,
+    allow_unverified: bool,
+    ctx: &PeerContext,
+) -> Result<()> {
+    if archive.attestation.is_none() && !allow_unverified {
+        return Err(SwarmhostError::validation(format!(
+            "Archive of {}: its snapshot at {} is not attested",
+            archive.header.game_id, archive.snapshot.sequence
+        )));
+    }
+    let mut consensus = ctx.consensus.lock().await;
+    let outcome = if consensus.validators().is_empty() {
+        let validators = archive.config.membership();
+        archive.replay(&validators, archive.config.required, ReplayMode::Strict)?
+    } else {
+        let required = consensus.required_weight();
+        archive.replay(consensus.validators(), required, ReplayMode::Strict)?
+    };
+    let game_id = &archive.header.game_id;
+    let committed = consensus.committed(game_id);
+    if committed >= outcome.sequence {
+        return Err(SwarmhostError::InvalidState(format!(
+            "Already hold {} up to {}, as far as the archive's {}",
+            game_id, committed, outcome.sequence
+        )));
+    }
+    let delivered = {
+        let mut state_manager = ctx.state_manager.lock().await;
+        state_manager.import(archive, outcome.log)?;
+        tracing::info!(
+            "Imported {} at {} from an archive by {}",
+            game_id,
+            outcome.sequence,
+            short_id(&archive.header.exporter)
+        );
+        consensus.skip_to(game_id, outcome.sequence, Instant::now())
+    };
+    sequence::deliver(delivered, ctx).await
+}
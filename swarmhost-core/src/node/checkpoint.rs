@@ -0,0 +1,79 @@
+// node/checkpoint.rs - Signing each game's log every `checkpoint_interval`
+// commits, and gathering a quorum's signatures into checkpoints, pruning
+// the log behind them
+
+use super::peers::{self, PeerContext};
+use super::{NodeEvent, snapshot};
+use crate::error::Result;
+use crate::network::GossipPayload;
+use crate::state::{Checkpoint, CheckpointVote};
+
+/// Count and gossip the checkpoint signatures made here while applying
+/// commits; those made while not a validator are dropped
+pub(super) async fn publish(ctx: &PeerContext) {
+    let signed = ctx.state_manager.lock().await.take_signed();
+    for vote in signed {
+        if let Err(e) = receive_vote(vote.clone(), ctx).await {
+            tracing::debug!("Not sending our checkpoint signature: {}", e);
+            continue;
+        }
+        peers::publish(GossipPayload::CheckpointVote(vote), None, ctx).await;
+    }
+}
+
+/// Count a validator's checkpoint signature, and once a quorum of the
+/// validators at its sequence signed the same log, hold the game to the
+/// checkpoint they make and gossip it
+pub(super) async fn receive_vote(vote: CheckpointVote, ctx: &PeerContext) -> Result<()> {
+    let formed = {
+        let mut consensus = ctx.consensus.lock().await;
+        let (game_id, sequence) = (vote.game_id.clone(), vote.sequence);
+        let formed = ctx.state_manager.lock().await.receive_checkpoint_vote(
+            vote,
+            consensus.validators_at(&game_id, sequence),
+            consensus.required_weight_at(&game_id, sequence),
+        )?;
+        if let Some(checkpoint) = &formed {
+            consensus.finalize(&checkpoint.game_id, checkpoint.sequence);
+        }
+        formed
+    };
+    if let Some(checkpoint) = formed {
+        announce(&checkpoint, ctx);
+        snapshot::settle(&checkpoint.game_id, ctx).await;
+        peers::publish(GossipPayload::Checkpoint(checkpoint), None, ctx).await;
+    }
+    Ok(())
+}
+
+/// Take in a checkpoint a peer passed on, holding the game to it if it is
+/// newer than ours
+pub(super) async fn receive(checkpoint: Checkpoint, ctx: &PeerContext) -> Result<()> {
+    let mut consensus = ctx.consensus.lock().await;
+    let newer = ctx.state_manager.lock().await.receive_checkpoint(
+        checkpoint.clone(),
+        consensus.validators_at(&checkpoint.game_id, checkpoint.sequence),
+        consensus.required_weight_at(&checkpoint.game_id, checkpoint.sequence),
+    )?;
+    if newer {
+        consensus.finalize(&checkpoint.game_id, checkpoint.sequence);
+        announce(&checkpoint, ctx);
+        drop(consensus);
+        snapshot::settle(&checkpoint.game_id, ctx).await;
+    }
+    Ok(())
+}
+
+/// Tell the game a checkpoint now holds
+pub(super) fn announce(checkpoint: &Checkpoint, ctx: &PeerContext) {
+    tracing::info!(
+        "Checkpoint of {} at {}, signed by {}",
+        checkpoint.game_id,
+        checkpoint.sequence,
+        checkpoint.signatures.len()
+    );
+    let _ = ctx.events.send(NodeEvent::Checkpointed {
+        game_id: checkpoint.game_id.clone(),
+        sequence: checkpoint.sequence,
+    });
+}
@@ -1,18 +1,35 @@
 // node/config.rs - Configuration for Swarmhost nodes
 
-use crate::crypto::{KeyPair, PlayerId};
+use super::migrations::{self, CONFIG_VERSION};
+use crate::consensus::rotation::MAX_ROUND_ACTIONS;
+use crate::crypto::{KeyPair, PlayerId, short_id};
+use crate::error::SwarmhostError;
+use crate::network::fragment::FRAGMENT_OVERHEAD;
+use crate::network::frame::MAX_FRAME_OVERHEAD;
+use crate::network::mux::MIN_WINDOW;
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::path::{Path, PathBuf};
 use std::time::Duration;
 
+/// Largest quorum denominator accepted by validation
+pub const MAX_QUORUM_DENOMINATOR: u32 = 1000;
+
 /// Configuration for a Swarmhost node
-#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct NodeConfig {
+    /// On-disk format version, upgraded by `from_file` when older
+    #[serde(default = "current_config_version")]
+    pub config_version: u32,
+
     /// Player's keypair (for signing actions)
     #[serde(skip)]
     pub keypair: Option<KeyPair>,
 
-    /// Bootstrap server address for peer discovery
-    pub bootstrap_server: Option<String>,
+    /// Bootstrap server addresses for peer discovery, tried in order
+    #[serde(default)]
+    pub bootstrap_servers: Vec<String>,
 
     /// Port to listen on for incoming connections
     pub listen_port: u16,
@@ -25,6 +42,36 @@ pub struct NodeConfig {
 
     /// State management configuration
     pub state: StateConfig,
+
+    /// Logging configuration
+    #[serde(default)]
+    pub log: LogConfig,
+
+    /// Preset this config was built from, if any
+    #[serde(default)]
+    pub preset: Option<ConfigPreset>,
+}
+
+/// Named starting points for common deployment shapes
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ConfigPreset {
+    /// Same local network: short timeouts, no compression, mDNS discovery
+    Lan,
+    /// Peers across the internet: longer timeouts, compression, relay allowed
+    Internet,
+    /// Slow-paced games: long consensus timeout, frequent snapshots, rare heartbeats
+    TurnBased,
+}
+
+impl ConfigPreset {
+    /// Stable name of the preset
+    pub fn name(&self) -> &'static str {
+        match self {
+            ConfigPreset::Lan => "lan",
+            ConfigPreset::Internet => "internet",
+            ConfigPreset::TurnBased => "turn_based",
+        }
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -33,19 +80,241 @@ pub struct ConsensusConfig {
     pub quorum_numerator: u32,
     pub quorum_denominator: u32,
 
-    /// Enable optimistic execution?
+    /// Allow quorum fractions at or below 1/2 (lets disjoint halves both commit)
+    #[serde(default)]
+    pub allow_weak_quorum: bool,
+
+    /// Allow one validator to weigh enough to make a quorum on its own
+    /// (server-authoritative games)
+    #[serde(default)]
+    pub allow_dictatorship: bool,
+
+    /// Fewest validators a membership change may leave; a removal going
+    /// below it is rejected
+    #[serde(default = "default_min_validators")]
+    pub min_validators: usize,
+
+    /// Let a quorum shrink with the validators a committed membership
+    /// change removes; without it the quorum never falls below the one the
+    /// validators were set with, and a removal it would outweigh is refused
+    #[serde(default)]
+    pub adaptive_quorum: bool,
+
+    /// Let the validators still reachable, when too few for a quorum, go on
+    /// alone once all of them agree after `grace_period` degraded; the
+    /// epoch this starts is committed to the log like any action
+    #[serde(default)]
+    pub quorum_grace: bool,
+
+    /// Fewest reachable validators the grace rule lets go on
+    #[serde(default = "default_grace_min_validators")]
+    pub grace_min_validators: usize,
+
+    /// How long the session is degraded before the grace rule applies
+    #[serde(with = "serde_duration", default = "default_grace_period")]
+    pub grace_period: Duration,
+
+    /// Apply actions to a speculative copy of the game state as soon as
+    /// they are proposed, rolling back if consensus decides otherwise
     pub optimistic_execution: bool,
 
+    /// Actions that may be speculated on ahead of the confirmed state; past
+    /// this, further actions wait for their commit
+    #[serde(default = "default_max_speculation_depth")]
+    pub max_speculation_depth: usize,
+
+    /// Stop speculating on a game, rolling back what was speculated, once
+    /// our state of it is found to differ from a peer's, so that no more
+    /// is built on a state that may be wrong
+    #[serde(default)]
+    pub pause_speculation_on_divergence: bool,
+
     /// Timeout for reaching consensus on an action
     #[serde(with = "serde_duration")]
     pub consensus_timeout: Duration,
 
     /// Maximum concurrent actions being validated
     pub max_concurrent_validations: usize,
+
+    /// How long the checks on an action may take, waiting for one of the
+    /// `max_concurrent_validations` slots included, before we reject it as
+    /// timed out
+    #[serde(with = "serde_duration", default = "default_validation_timeout")]
+    pub validation_timeout: Duration,
+
+    /// How far apart nodes' clocks may be when checking action deadlines:
+    /// validators approve an action until this long past its deadline, and
+    /// its submitter gives it up as expired once twice this has passed
+    #[serde(with = "serde_duration", default = "default_expiry_clock_skew")]
+    pub expiry_clock_skew: Duration,
+
+    /// Largest action payload accepted, in bytes
+    #[serde(default = "default_max_action_size")]
+    pub max_action_size: usize,
+
+    /// Actions a single player may propose per consensus round
+    #[serde(default = "default_max_actions_per_player_per_round")]
+    pub max_actions_per_player_per_round: u32,
+
+    /// Most actions taken in and not yet decided at once; past it, local
+    /// submits wait for room and forwarded actions are answered busy
+    #[serde(default = "default_max_pending_actions")]
+    pub max_pending_actions: usize,
+
+    /// How long a local submit waits for room among the pending actions, or
+    /// for a busy proposer, before failing as overloaded
+    #[serde(with = "serde_duration", default = "default_submit_wait")]
+    pub submit_wait: Duration,
+
+    /// Consensus timeouts the reachable validators may fall short of a
+    /// quorum before the session is degraded
+    #[serde(default = "default_quorum_loss_timeouts")]
+    pub quorum_loss_timeouts: u32,
+
+    /// What happens to local actions submitted while degraded
+    #[serde(default)]
+    pub when_degraded: DegradedActions,
+
+    /// How long a gap in a game's commits may stay open before the missing
+    /// ones are fetched from a peer
+    #[serde(with = "serde_duration", default = "default_gap_timeout")]
+    pub gap_timeout: Duration,
+
+    /// How long validators wait on the proposer of a round before voting
+    /// to skip it
+    #[serde(with = "serde_duration", default = "default_proposer_timeout")]
+    pub proposer_timeout: Duration,
+
+    /// How long the sequencer waits on the validators yet to vote once a
+    /// quorum approved an action or block, before numbering it without
+    /// them; zero numbers it on the quorum straight away
+    #[serde(with = "serde_duration", default = "default_fast_path_timeout")]
+    pub fast_path_timeout: Duration,
+
+    /// Rounds in a row that may be skipped before validators ask to hand
+    /// sequencing to the next one
+    #[serde(default = "default_view_change_rounds")]
+    pub view_change_rounds: u32,
+
+    /// How actions in one round touching the same entity are resolved, in
+    /// games without a policy of their own
+    #[serde(default)]
+    pub conflict_policy: ConflictPolicy,
+
+    /// Conflict policy of particular games, by game id; every validator of
+    /// a game must agree on it
+    #[serde(default)]
+    pub game_conflict_policies: HashMap<String, ConflictPolicy>,
+
+    /// How long the proposer lets actions gather into one block before
+    /// proposing it; zero proposes as soon as an action waits
+    #[serde(with = "serde_duration", default)]
+    pub batch_interval: Duration,
+
+    /// Most actions in one block; a full block is proposed straight away
+    #[serde(default = "default_batch_max_actions")]
+    pub batch_max_actions: usize,
+
+    /// Most bytes of actions in one block, which never outgrows
+    /// `max_action_size` so it fits in a message; a full block is proposed
+    /// straight away
+    #[serde(default = "default_max_action_size")]
+    pub batch_max_bytes: usize,
+
+    /// Let a proposer put a block forward while blocks of earlier rounds
+    /// are still uncommitted; off, each waits for the one before it
+    #[serde(default = "default_pipelining")]
+    pub pipelining: bool,
+
+    /// Most blocks proposed and not yet committed at once while pipelining;
+    /// with optimistic execution, no more than `max_speculation_depth`
+    #[serde(default = "default_pipeline_depth")]
+    pub pipeline_depth: usize,
+
+    /// How we vote on a block holding actions the game finds invalid
+    #[serde(default)]
+    pub invalid_in_block: InvalidInBlock,
+
+    /// How long a peer may take to answer each fetch while we catch up on
+    /// a game in progress
+    #[serde(with = "serde_duration", default = "default_sync_timeout")]
+    pub sync_timeout: Duration,
+
+    /// Snapshot chunks asked of each peer at once while catching up
+    #[serde(default = "default_sync_chunks_per_peer")]
+    pub sync_chunks_per_peer: usize,
+
+    /// Commits between checkpoints, where validators sign a game's log so
+    /// no later view may reorder it; 0 for none
+    #[serde(default = "default_checkpoint_interval")]
+    pub checkpoint_interval: u64,
+
+    /// Whether rounds follow one another as actions come, or one opens at
+    /// every tick of a lockstep game; every validator must agree on it
+    #[serde(default)]
+    pub round_mode: RoundMode,
+}
+
+/// Handling of local actions while too few validators are reachable
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Default)]
+pub enum DegradedActions {
+    /// Refuse them
+    #[default]
+    Reject,
+    /// Hold them, and send them in order once a quorum is reachable again
+    Queue,
+}
+
+/// Vote on a block some of whose actions are invalid
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Default)]
+pub enum InvalidInBlock {
+    /// Reject the whole block
+    RejectBlock,
+    /// Reject the invalid actions alone, and approve the block, so the rest
+    /// are committed
+    #[default]
+    RejectActions,
+}
+
+/// Pace of consensus rounds
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Default)]
+pub enum RoundMode {
+    /// A block is proposed once actions wait for one, as `batch_interval`
+    /// and the batch limits allow
+    #[default]
+    FreeRunning,
+    /// A block is proposed at every tick, empty if no action came since
+    /// the last, and the game advances one tick per block
+    Ticked {
+        #[serde(with = "serde_duration")]
+        tick_interval: Duration,
+    },
+}
+
+/// Resolution of actions in one round sharing a conflict key; conflicting
+/// actions are ordered by action id
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Default)]
+pub enum ConflictPolicy {
+    /// The first stands, and those conflicting with it are rejected
+    FirstWins,
+    /// All stand, committed in order
+    #[default]
+    AllCommitOrdered,
+    /// None of them stand
+    RejectAll,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct NetworkConfig {
+    /// Address to bind listeners to (defaults to 0.0.0.0)
+    #[serde(default = "default_bind_addr")]
+    pub bind_addr: IpAddr,
+
+    /// Also listen on `[::]` when `bind_addr` is IPv4, or accept IPv4-mapped
+    /// connections when `bind_addr` is IPv6
+    #[serde(default)]
+    pub dual_stack: bool,
+
     /// Maximum number of peers to maintain connections with
     pub max_peers: usize,
 
@@ -57,185 +326,2720 @@ pub struct NetworkConfig {
     #[serde(with = "serde_duration")]
     pub peer_timeout: Duration,
 
+    /// Timeout for each attempt to reach a bootstrap server
+    #[serde(with = "serde_duration", default = "default_bootstrap_timeout")]
+    pub bootstrap_timeout: Duration,
+
+    /// Time a closing connection gets to send what is queued and hear the
+    /// peer acknowledge the close
+    #[serde(with = "serde_duration", default = "default_drain_timeout")]
+    pub drain_timeout: Duration,
+
     /// Maximum message size in bytes
     pub max_message_size: usize,
 
-    /// Enable message compression?
-    pub enable_compression: bool,
+    /// How connections to peers are carried
+    #[serde(default)]
+    pub transport: TransportKind,
+
+    /// HTTP path WebSocket peers connect to
+    #[serde(default = "default_websocket_path")]
+    pub websocket_path: String,
+
+    /// Message compression settings
+    #[serde(default)]
+    pub compression: CompressionConfig,
+
+    /// Preferred encoding for peer messages; the one used is negotiated
+    #[serde(default)]
+    pub wire_format: WireFormat,
+
+    /// Discover peers on the local network via mDNS?
+    #[serde(default)]
+    pub enable_mdns: bool,
+
+    /// Find peers through a Kademlia-style DHT run by connected peers?
+    #[serde(default)]
+    pub enable_dht: bool,
+
+    /// Swap samples of connected peers with neighbours, and dial the peers
+    /// learned that way while below `max_peers`?
+    #[serde(default)]
+    pub enable_pex: bool,
+
+    /// Allow connections to be relayed through a third party?
+    #[serde(default)]
+    pub allow_relay: bool,
+
+    /// Only these players may connect, if set
+    #[serde(default, with = "serde_opt_player_ids")]
+    pub allowlist: Option<Vec<PlayerId>>,
+
+    /// Players refused at handshake time, even if allowlisted
+    #[serde(default, with = "serde_player_ids")]
+    pub denylist: Vec<PlayerId>,
+
+    /// SOCKS5 proxy that outbound TCP and WebSocket dials, bootstrap
+    /// included, go through; listening is unaffected. STUN and hole
+    /// punching, which it cannot carry, are skipped while it is set, so
+    /// peers not dialed through it are reached by relay only
+    #[serde(default)]
+    pub outbound_proxy: Option<ProxyConfig>,
+
+    /// NAT traversal and public address settings
+    #[serde(default)]
+    pub nat: NatConfig,
+
+    /// How proposals and votes spread between peers
+    #[serde(default)]
+    pub gossip: GossipConfig,
+
+    /// Relaying sessions for peers that cannot reach each other
+    #[serde(default)]
+    pub relay: RelayConfig,
+
+    /// When misbehaving peers are dropped and banned
+    #[serde(default)]
+    pub reputation: ReputationConfig,
+
+    /// Per-peer outbound queue capacities
+    #[serde(default)]
+    pub outbound: OutboundConfig,
+
+    /// Packing small messages to a peer into shared frames
+    #[serde(default)]
+    pub batching: BatchConfig,
+
+    /// Flow control windows of each class's stream within a connection
+    #[serde(default)]
+    pub mux: MuxConfig,
+
+    /// Suppression of messages that arrive along several paths
+    #[serde(default)]
+    pub dedup: DedupConfig,
+
+    /// Splitting bulk messages larger than `max_message_size`
+    #[serde(default)]
+    pub fragmentation: FragmentConfig,
+
+    /// Limits on how fast we send to peers
+    #[serde(default)]
+    pub upload: UploadConfig,
+
+    /// Limits on how fast peers may send to us
+    #[serde(default)]
+    pub inbound: InboundConfig,
+
+    /// Acknowledgement and retransmission of datagrams that must arrive
+    #[serde(default)]
+    pub reliable: ReliableConfig,
+
+    /// Re-dialing validators whose connection dropped
+    #[serde(default)]
+    pub reconnect: ReconnectConfig,
+
+    /// Tokens that let a dropped peer resume its session
+    #[serde(default)]
+    pub resumption: ResumptionConfig,
+
+    /// Routing table and lookup settings, used with `enable_dht`
+    #[serde(default)]
+    pub dht: DhtConfig,
+
+    /// Peer exchange settings, used with `enable_pex`
+    #[serde(default)]
+    pub pex: PexConfig,
+
+    /// Transport encryption settings
+    #[serde(default)]
+    pub security: SecurityConfig,
+}
+
+/// Whether connections are encrypted
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SecurityMode {
+    /// Never encrypt (only allowed on loopback by default)
+    Plaintext,
+    /// Encrypt when the peer supports it, otherwise fall back to plaintext
+    Encrypted,
+    /// Refuse peers that cannot encrypt
+    Required,
+}
+
+/// AEAD cipher suites available for encrypted connections
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum CipherSuite {
+    ChaCha20Poly1305,
+    Aes256Gcm,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct StateConfig {
-    /// How often to create state snapshots (in number of actions)
-    pub snapshot_interval: u32,
+#[serde(default)]
+pub struct SecurityConfig {
+    /// Encryption policy for connections
+    pub mode: SecurityMode,
 
-    /// Maximum number of snapshots to keep in memory
-    pub max_snapshots_in_memory: usize,
+    /// Supported cipher suites, most preferred first
+    pub ciphers: Vec<CipherSuite>,
 
-    /// Maximum action log size before requiring a snapshot
-    pub max_action_log_size: usize,
+    /// Time allowed for the peer to complete the security handshake
+    #[serde(with = "serde_duration")]
+    pub handshake_timeout: Duration,
+
+    /// Allow `Plaintext` mode on a non-loopback bind address
+    pub allow_insecure_on_public: bool,
+
+    /// Derive fresh keys after this many encrypted messages in one direction
+    pub rekey_after_messages: u64,
+
+    /// Derive fresh keys after this many encrypted bytes in one direction
+    pub rekey_after_bytes: u64,
 }
 
-// Helper module for Duration serialization
-mod serde_duration {
-    use serde::{Deserialize, Deserializer, Serializer};
-    use std::time::Duration;
+/// A SOCKS5 proxy for outbound connections
+///
+/// Destinations are handed to the proxy unresolved, so hostnames are looked
+/// up on its side (SOCKS5h) rather than leaking through local DNS.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ProxyConfig {
+    /// Proxy address (host:port), e.g. `127.0.0.1:9050` for a local Tor
+    pub addr: String,
 
-    pub fn serialize<S>(duration: &Duration, serializer: S) -> Result<S::Ok, S::Error>
-    where
-        S: Serializer,
-    {
-        serializer.serialize_u64(duration.as_secs())
-    }
+    /// Username for username/password auth; without one only no-auth is
+    /// offered
+    #[serde(default)]
+    pub username: Option<String>,
 
-    pub fn deserialize<'de, D>(deserializer: D) -> Result<Duration, D::Error>
-    where
-        D: Deserializer<'de>,
-    {
-        let secs = u64::deserialize(deserializer)?;
-        Ok(Duration::from_secs(secs))
-    }
+    /// Password to go with `username`
+    #[serde(default)]
+    pub password: Option<String>,
 }
 
-impl Default for ConsensusConfig {
-    fn default() -> Self {
-        Self {
-            quorum_numerator: 2,
-            quorum_denominator: 3,
-            optimistic_execution: true,
-            consensus_timeout: Duration::from_secs(5),
-            max_concurrent_validations: 100,
-        }
-    }
+/// Transport used for peer connections
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Default)]
+pub enum TransportKind {
+    #[default]
+    Tcp,
+    /// UDP-based, without head-of-line blocking between messages; also
+    /// carries unreliable datagrams
+    Quic,
+    /// WebSocket, so browser builds can connect (needs the `websocket`
+    /// feature)
+    WebSocket,
+    /// In-process connections between nodes in the same process (tests,
+    /// simulations)
+    Memory,
 }
 
-impl Default for NetworkConfig {
-    fn default() -> Self {
-        Self {
-            max_peers: 50,
-            heartbeat_interval: Duration::from_secs(10),
-            peer_timeout: Duration::from_secs(30),
-            max_message_size: 1024 * 1024,
-            enable_compression: true,
-        }
-    }
+/// Compression algorithm preferred for outgoing messages
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Default)]
+pub enum CompressionAlgorithm {
+    /// Never compress
+    None,
+    /// Fast, modest ratio; suits latency-sensitive traffic
+    #[default]
+    Lz4,
+    /// Better ratio at a configurable CPU cost (levels 1-22)
+    Zstd { level: i32 },
 }
 
-impl Default for StateConfig {
-    fn default() -> Self {
-        Self {
-            snapshot_interval: 100,
-            max_snapshots_in_memory: 10,
-            max_action_log_size: 1000,
-        }
-    }
+/// Encoding of peer messages on the wire
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize, Default)]
+pub enum WireFormat {
+    /// Compact serde encoding; only Rust peers speak it
+    #[default]
+    Bincode,
+    /// Protocol buffers, for services in other languages (needs the
+    /// `protobuf` feature)
+    Protobuf,
 }
 
-impl NodeConfig {
-    /// Create a new config with a randomly generated keypair
-    pub fn new() -> Self {
-        Self {
-            keypair: Some(KeyPair::generate()),
-            ..Default::default()
-        }
-    }
+/// Message compression settings
+///
+/// The algorithm actually used on a connection is negotiated with the peer.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CompressionConfig {
+    /// Preferred algorithm
+    pub algorithm: CompressionAlgorithm,
 
-    /// Create config with an existing keypair
-    pub fn with_keypair(keypair: KeyPair) -> Self {
-        Self {
-            keypair: Some(keypair),
-            ..Default::default()
-        }
-    }
+    /// Messages smaller than this many bytes are sent uncompressed
+    pub min_size: usize,
+}
 
-    /// Get the player ID (public key)
-    pub fn player_id(&self) -> Option<PlayerId> {
-        self.keypair.as_ref().map(|kp| kp.public_key())
-    }
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct NatConfig {
+    /// STUN servers (host:port) queried for our public address, in order
+    pub stun_servers: Vec<String>,
 
-    /// Set bootstrap server
-    pub fn with_bootstrap(mut self, server: impl Into<String>) -> Self {
-        self.bootstrap_server = Some(server.into());
-        self
-    }
+    /// Ask the router to forward the listen port, via UPnP IGD or else
+    /// NAT-PMP?
+    pub enable_upnp: bool,
 
-    /// Set listen port
-    pub fn with_port(mut self, port: u16) -> Self {
-        self.listen_port = port;
-        self
-    }
+    /// Lease asked for on the port mapping; it is renewed halfway through
+    #[serde(with = "serde_duration")]
+    pub upnp_lease: Duration,
 
-    /// Enable/disable optimistic execution
-    pub fn with_optimistic_execution(mut self, enabled: bool) -> Self {
-        self.consensus.optimistic_execution = enabled;
-        self
-    }
+    /// Address to advertise to peers, overriding detection
+    pub advertised_addr: Option<SocketAddr>,
 
-    /// Validate the configuration
-    pub fn validate(&self) -> Result<(), String> {
-        if self.keypair.is_none() {
-            return Err("Keypair must be set".to_string());
-        }
+    /// Timeout for each STUN query
+    #[serde(with = "serde_duration")]
+    pub stun_timeout: Duration,
 
-        if self.consensus.quorum_numerator == 0 || self.consensus.quorum_denominator == 0 {
-            return Err("Quorum fraction cannot have zero denominator/numerator".to_string());
-        }
+    /// Try UDP hole punching when a peer cannot be dialed directly?
+    pub hole_punching: bool,
 
-        if self.consensus.quorum_numerator > self.consensus.quorum_denominator {
-            return Err("Quorum numerator cannot exceed denominator".to_string());
-        }
+    /// How long to keep probing a peer before giving up on punching
+    #[serde(with = "serde_duration")]
+    pub punch_window: Duration,
 
-        if self.network.max_message_size == 0 {
-            return Err("Max message size must be > 0".to_string());
-        }
+    /// Pause after the first probe burst; it doubles after each burst
+    #[serde(with = "serde_duration")]
+    pub punch_interval: Duration,
+}
 
-        Ok(())
-    }
+/// Gossip dissemination settings
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct GossipConfig {
+    /// Peers each node forwards a new message to
+    pub fanout: usize,
+
+    /// Message ids remembered to suppress duplicates
+    pub seen_cache_size: usize,
+
+    /// Hops a message may travel before it stops being forwarded
+    pub max_hops: u8,
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+/// Settings for serving as a relay
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RelayConfig {
+    /// Offer to relay for connected peers? Only useful with a public address
+    pub enabled: bool,
 
-    #[test]
-    fn test_default_config() {
-        let config = NodeConfig::default();
-        assert_eq!(config.consensus.quorum_numerator, 2);
-        assert_eq!(config.consensus.quorum_denominator, 3);
-        assert!(config.consensus.optimistic_execution);
-    }
+    /// Sessions relayed at once
+    pub max_sessions: usize,
 
-    #[test]
-    fn test_new_config_generates_keypair() {
-        let config = NodeConfig::new();
-        assert!(config.keypair.is_some());
-        assert!(config.player_id().is_some());
-    }
+    /// Bytes per second forwarded for each session, both directions together
+    pub session_bandwidth: u64,
+}
 
-    #[test]
-    fn test_config_builder() {
-        let config = NodeConfig::new()
-            .with_bootstrap("localhost:8080")
-            .with_port(9000)
-            .with_optimistic_execution(false);
+/// Peer scoring and automatic eviction
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ReputationConfig {
+    /// Demerits at which a peer is disconnected and banned
+    pub ban_threshold: u32,
 
-        assert_eq!(config.bootstrap_server, Some("localhost:8080".to_string()));
-        assert_eq!(config.listen_port, 9000);
-        assert!(!config.consensus.optimistic_execution);
-    }
+    /// Time for a peer's demerits to halve
+    #[serde(with = "serde_duration")]
+    pub decay_half_life: Duration,
 
-    #[test]
-    fn test_validate_config() {
-        let mut config = NodeConfig::new();
-        assert!(config.validate().is_ok());
+    /// How long a banned peer is refused
+    #[serde(with = "serde_duration")]
+    pub ban_duration: Duration,
 
-        // Invalid: no keypair
-        config.keypair = None;
-        assert!(config.validate().is_err());
+    /// Penalize peers for high latency alone? Off so slow but honest
+    /// players are never evicted for their connection
+    pub penalize_latency: bool,
 
-        // Invalid: bad quorum
-        config.keypair = Some(KeyPair::generate());
-        config.consensus.quorum_numerator = 0;
-        assert!(config.validate().is_err());
+    /// Round-trip time that counts as an offense with `penalize_latency`
+    #[serde(with = "serde_duration")]
+    pub max_rtt: Duration,
+}
+
+/// Messages each peer connection buffers per traffic class
+///
+/// Control overflowing closes the connection, game actions drop the oldest,
+/// and bulk senders wait for room.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct OutboundConfig {
+    /// Heartbeats, signaling and consensus
+    pub control_capacity: usize,
+
+    /// Game actions
+    pub game_action_capacity: usize,
+
+    /// Snapshots, state sync and relayed sessions
+    pub bulk_capacity: usize,
+}
+
+/// Logical streams within a connection, one per traffic class
+///
+/// Each window is how many bytes the peer may have sent on the stream that
+/// we have not consumed yet. Small windows keep a vote from queueing behind
+/// much of a snapshot on the wire; large ones let bulk transfers fill a slow
+/// round trip.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct MuxConfig {
+    /// Heartbeats, signaling and other control traffic; every window is at
+    /// least 64 KiB
+    pub control_window: u32,
+
+    /// Proposals and votes
+    pub consensus_window: u32,
+
+    /// Game actions
+    pub game_action_window: u32,
+
+    /// Snapshots, state sync and relayed sessions
+    pub bulk_window: u32,
+
+    /// Bulk messages larger than this are sent in fragments of at most this
+    /// size, so other streams never wait behind a long frame
+    pub bulk_chunk: usize,
+}
+
+/// Coalescing of outgoing messages
+///
+/// Messages to a peer wait up to `window` to share one frame, which goes out
+/// early once `max_bytes` have gathered. Control messages never wait: they
+/// go as soon as nothing else is ready to send, taking whatever was already
+/// gathered with them.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct BatchConfig {
+    /// Longest a message waits for company; zero sends every message in its
+    /// own frame
+    #[serde(with = "serde_duration")]
+    pub window: Duration,
+
+    /// Batch size that is sent without waiting out the window
+    pub max_bytes: usize,
+}
+
+/// Duplicate message suppression
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DedupConfig {
+    /// Message ids remembered at once
+    pub capacity: usize,
+
+    /// How long after a message is first seen copies are still dropped
+    #[serde(with = "serde_duration")]
+    pub ttl: Duration,
+}
+
+/// Fragmentation of oversized bulk messages
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct FragmentConfig {
+    /// Largest message that may be sent in fragments
+    pub max_transfer_size: usize,
+
+    /// How long a transfer may go without a new fragment before the missing
+    /// ones are re-requested, and again before it is abandoned
+    #[serde(with = "serde_duration")]
+    pub reassembly_timeout: Duration,
+
+    /// Transfers each peer may have in progress at once
+    pub max_reassemblies: usize,
+}
+
+/// Inbound limits, so no peer can make us spend unbounded work on its
+/// frames
+///
+/// A connected peer going over a budget has its frames dropped unread and
+/// is first penalized, then not read from for `pause`, then disconnected.
+/// Peers still in the handshake are unauthenticated, so each of their frames
+/// is capped at `handshake_max_bytes` and only `max_half_open` handshakes run
+/// at once.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct InboundConfig {
+    /// Frames per second from one peer; unlimited if unset
+    pub max_messages_per_sec: Option<u64>,
+
+    /// Bytes per second from one peer; unlimited if unset
+    pub max_bytes_per_sec: Option<u64>,
+
+    /// How long an offending peer goes unread, and how long after a strike
+    /// further excess counts towards it
+    #[serde(with = "serde_duration")]
+    pub pause: Duration,
+
+    /// Time within the limits after which strikes are forgiven
+    #[serde(with = "serde_duration")]
+    pub forgive_after: Duration,
+
+    /// Largest frame accepted before the peer's identity is proven
+    pub handshake_max_bytes: usize,
+
+    /// Inbound handshakes in progress at once across the node; connections
+    /// beyond this are dropped. Read at start
+    pub max_half_open: usize,
+}
+
+/// Upload rate limits, so no peer can make us send more than our link
+/// allows
+///
+/// Bulk traffic is held back first: it is drained last and may have its own
+/// lower rate. Control frames up to `control_reserve_bytes_per_sec` are never
+/// held back, so heartbeats keep a throttled peer from timing out.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct UploadConfig {
+    /// Bytes per second sent to any one peer; unlimited if unset
+    pub max_upload_bytes_per_sec_per_peer: Option<u64>,
+
+    /// Bytes per second sent to all peers together; unlimited if unset
+    pub max_upload_bytes_per_sec: Option<u64>,
+
+    /// Bytes per second of game actions to one peer, within the peer limit
+    pub game_action_bytes_per_sec: Option<u64>,
+
+    /// Bytes per second of bulk traffic to one peer, within the peer limit
+    pub bulk_bytes_per_sec: Option<u64>,
+
+    /// Control bytes per second sent to each peer regardless of the limits
+    pub control_reserve_bytes_per_sec: u64,
+
+    /// How far traffic may run ahead of a limit, as time at that rate
+    #[serde(with = "serde_duration")]
+    pub burst: Duration,
+}
+
+/// Reliable delivery over unreliable datagrams
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ReliableConfig {
+    /// How long the receiver waits for outgoing data to carry an ack before
+    /// sending it alone
+    #[serde(with = "serde_duration")]
+    pub ack_delay: Duration,
+
+    /// Retransmission timeout before the connection has an RTT estimate
+    #[serde(with = "serde_duration")]
+    pub initial_rto: Duration,
+
+    /// Bounds on the retransmission timeout derived from the RTT
+    #[serde(with = "serde_duration")]
+    pub min_rto: Duration,
+    #[serde(with = "serde_duration")]
+    pub max_rto: Duration,
+
+    /// Times a message is sent before it is reported undeliverable
+    pub max_transmissions: u32,
+
+    /// Sequence numbers per channel that may be outstanding at once
+    pub window: usize,
+}
+
+/// Reconnecting to fellow validators of the current game after their
+/// connection drops
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ReconnectConfig {
+    /// How long a dropped validator keeps its place while we try to get it
+    /// back; zero disables reconnection
+    #[serde(with = "serde_duration")]
+    pub window: Duration,
+
+    /// Wait before the first re-dial, doubled after each failure
+    #[serde(with = "serde_duration")]
+    pub initial_backoff: Duration,
+
+    /// Longest wait between re-dials
+    #[serde(with = "serde_duration")]
+    pub max_backoff: Duration,
+}
+
+/// Resuming a session after a brief disconnect
+///
+/// Once connected, each side gives the other a single-use token. A peer
+/// whose connection drops keeps its place, queue and reputation for `grace`;
+/// if it dials back with the token, it carries on where it left off.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ResumptionConfig {
+    /// How long a dropped peer holding a token keeps its place; zero
+    /// disables resumption
+    #[serde(with = "serde_duration")]
+    pub grace: Duration,
+
+    /// How long a token stays valid after it is issued
+    #[serde(with = "serde_duration")]
+    pub lifetime: Duration,
+}
+
+/// Kademlia-style peer discovery
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DhtConfig {
+    /// Contacts per routing table bucket, and how many nodes a lookup
+    /// converges on (Kademlia's k)
+    pub bucket_size: usize,
+
+    /// Queries a lookup keeps in flight at once
+    pub alpha: usize,
+
+    /// How long to wait for a contact to answer a query, dial included
+    #[serde(with = "serde_duration")]
+    pub request_timeout: Duration,
+
+    /// Buckets without a lookup for this long are refreshed with one
+    #[serde(with = "serde_duration")]
+    pub refresh_interval: Duration,
+
+    /// How long a provider record is kept; we re-announce our game at half
+    /// this
+    #[serde(with = "serde_duration")]
+    pub provider_ttl: Duration,
+}
+
+/// Peer exchange (PEX)
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PexConfig {
+    /// Time between rounds of sending samples and dialing learned peers
+    #[serde(with = "serde_duration")]
+    pub interval: Duration,
+
+    /// Neighbours sent a sample each round, and most peers dialed per round
+    pub fanout: usize,
+
+    /// Most entries in a sample; extra entries received are ignored
+    pub max_entries: usize,
+
+    /// Entries last seen longer ago than this are ignored, and learned peers
+    /// are forgotten
+    #[serde(with = "serde_duration")]
+    pub max_age: Duration,
+
+    /// Most peers remembered, connected or not
+    pub store_capacity: usize,
+}
+
+/// Log line format
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Default)]
+pub enum LogFormat {
+    /// Multi-line, human friendly
+    Pretty,
+    /// One line per event
+    #[default]
+    Compact,
+    /// One JSON object per line
+    Json,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct LogConfig {
+    /// Filter directives in `RUST_LOG` syntax (e.g. `swarmhost_core=debug`)
+    pub level: String,
+
+    /// Output format
+    pub format: LogFormat,
+
+    /// Write to this file instead of stdout
+    pub file: Option<PathBuf>,
+
+    /// Rotate the log file once it reaches this many bytes
+    pub max_file_size: u64,
+
+    /// Rotated files to keep alongside the active one
+    pub max_files: usize,
+
+    /// Also log when spans open and close
+    pub span_events: bool,
+
+    /// Put a correlation id on every application message and record send,
+    /// receive, validate, vote and commit spans carrying it
+    pub trace_messages: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StateConfig {
+    /// How often to create state snapshots (in number of actions)
+    pub snapshot_interval: u32,
+
+    /// Maximum number of snapshots to keep in memory
+    pub max_snapshots_in_memory: usize,
+
+    /// Snapshots to keep per game with a lasting backend, besides
+    /// those at a checkpoint, which are all kept
+    #[serde(default = "default_max_snapshots_on_disk")]
+    pub max_snapshots_on_disk: usize,
+
+    /// Committed actions a game's log holds in memory before those behind
+    /// its newest snapshot or checkpoint are pruned
+    pub max_action_log_size: usize,
+
+    /// Archive pruned log entries with a lasting backend rather than
+    /// let them go
+    #[serde(default)]
+    pub archive_pruned: bool,
+
+    /// Most actions replayed to rebuild a game's state at a past sequence,
+    /// unless the caller allows more
+    #[serde(default = "default_max_replay_actions")]
+    pub max_replay_actions: u64,
+
+    /// Bytes the node may hold in snapshots, action logs, reassembly
+    /// buffers, past states and its duplicate cache before shedding what it
+    /// can; 0 for no limit
+    #[serde(default)]
+    pub max_memory_bytes: u64,
+
+    /// Where snapshots, and everything else kept across restarts, are
+    /// stored
+    #[serde(default)]
+    pub persistence: PersistenceBackend,
+}
+
+/// Storage backend for snapshots, the commits since, the consensus safety
+/// record and known peers
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, Default)]
+pub enum PersistenceBackend {
+    /// Keep everything in memory only
+    #[default]
+    InMemory,
+    /// Write each value as a file under `path`, optionally fsyncing each one
+    Directory { path: PathBuf, fsync: bool },
+    /// Keep everything in a sled database at `path`, optionally flushing
+    /// after each write; needs the `sled` feature
+    Sled { path: PathBuf, fsync: bool },
+}
+
+// Helper module for Duration serialization
+//
+// Durations are written as a floating-point number of seconds with millisecond
+// precision (e.g. `0.25` for 250ms). Bare integers are still accepted and read
+// as whole seconds, which keeps config files from older versions loading.
+mod serde_duration {
+    use serde::de::{self, Visitor};
+    use serde::{Deserializer, Serializer};
+    use std::fmt;
+    use std::time::Duration;
+
+    pub fn serialize<S>(duration: &Duration, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.serialize_f64(duration.as_millis() as f64 / 1000.0)
+    }
+
+    pub fn deserialize<'de, D>(deserializer: D) -> Result<Duration, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        deserializer.deserialize_any(DurationVisitor)
+    }
+
+    struct DurationVisitor;
+
+    impl Visitor<'_> for DurationVisitor {
+        type Value = Duration;
+
+        fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            f.write_str("a non-negative number of seconds")
+        }
+
+        fn visit_u64<E: de::Error>(self, secs: u64) -> Result<Duration, E> {
+            Ok(Duration::from_secs(secs))
+        }
+
+        fn visit_i64<E: de::Error>(self, secs: i64) -> Result<Duration, E> {
+            u64::try_from(secs)
+                .map(Duration::from_secs)
+                .map_err(|_| E::custom(format!("duration cannot be negative: {}", secs)))
+        }
+
+        fn visit_f64<E: de::Error>(self, secs: f64) -> Result<Duration, E> {
+            if !secs.is_finite() || secs < 0.0 {
+                return Err(E::custom(format!("invalid duration: {}", secs)));
+            }
+            Ok(Duration::from_millis((secs * 1000.0).round() as u64))
+        }
+    }
+}
+
+fn default_max_action_size() -> usize {
+    64 * 1024
+}
+
+fn default_batch_max_actions() -> usize {
+    MAX_ROUND_ACTIONS
+}
+
+fn default_max_actions_per_player_per_round() -> u32 {
+    10
+}
+
+fn default_max_pending_actions() -> usize {
+    4096
+}
+
+fn default_submit_wait() -> Duration {
+    Duration::from_secs(1)
+}
+
+fn default_quorum_loss_timeouts() -> u32 {
+    3
+}
+
+fn default_max_speculation_depth() -> usize {
+    64
+}
+
+fn default_pipelining() -> bool {
+    true
+}
+
+fn default_pipeline_depth() -> usize {
+    4
+}
+
+fn default_gap_timeout() -> Duration {
+    Duration::from_secs(2)
+}
+
+fn default_sync_timeout() -> Duration {
+    Duration::from_secs(5)
+}
+
+fn default_sync_chunks_per_peer() -> usize {
+    4
+}
+
+fn default_validation_timeout() -> Duration {
+    Duration::from_millis(500)
+}
+
+fn default_expiry_clock_skew() -> Duration {
+    Duration::from_millis(50)
+}
+
+fn default_checkpoint_interval() -> u64 {
+    1000
+}
+
+fn default_max_snapshots_on_disk() -> usize {
+    20
+}
+
+fn default_max_replay_actions() -> u64 {
+    10_000
+}
+
+fn default_proposer_timeout() -> Duration {
+    Duration::from_secs(1)
+}
+
+fn default_fast_path_timeout() -> Duration {
+    Duration::from_millis(50)
+}
+
+fn default_view_change_rounds() -> u32 {
+    3
+}
+
+fn default_min_validators() -> usize {
+    3
+}
+
+fn default_grace_min_validators() -> usize {
+    2
+}
+
+fn default_grace_period() -> Duration {
+    Duration::from_secs(60)
+}
+
+impl Default for ConsensusConfig {
+    fn default() -> Self {
+        Self {
+            quorum_numerator: 2,
+            quorum_denominator: 3,
+            allow_weak_quorum: false,
+            allow_dictatorship: false,
+            min_validators: default_min_validators(),
+            adaptive_quorum: false,
+            quorum_grace: false,
+            grace_min_validators: default_grace_min_validators(),
+            grace_period: default_grace_period(),
+            optimistic_execution: true,
+            max_speculation_depth: default_max_speculation_depth(),
+            pause_speculation_on_divergence: false,
+            consensus_timeout: Duration::from_secs(5),
+            max_concurrent_validations: 100,
+            validation_timeout: default_validation_timeout(),
+            expiry_clock_skew: default_expiry_clock_skew(),
+            max_action_size: default_max_action_size(),
+            max_actions_per_player_per_round: default_max_actions_per_player_per_round(),
+            max_pending_actions: default_max_pending_actions(),
+            submit_wait: default_submit_wait(),
+            quorum_loss_timeouts: default_quorum_loss_timeouts(),
+            when_degraded: DegradedActions::Reject,
+            gap_timeout: default_gap_timeout(),
+            proposer_timeout: default_proposer_timeout(),
+            fast_path_timeout: default_fast_path_timeout(),
+            view_change_rounds: default_view_change_rounds(),
+            conflict_policy: ConflictPolicy::default(),
+            game_conflict_policies: HashMap::new(),
+            batch_interval: Duration::ZERO,
+            batch_max_actions: default_batch_max_actions(),
+            batch_max_bytes: default_max_action_size(),
+            pipelining: default_pipelining(),
+            pipeline_depth: default_pipeline_depth(),
+            invalid_in_block: InvalidInBlock::default(),
+            sync_timeout: default_sync_timeout(),
+            sync_chunks_per_peer: default_sync_chunks_per_peer(),
+            checkpoint_interval: default_checkpoint_interval(),
+            round_mode: RoundMode::default(),
+        }
+    }
+}
+
+impl ConsensusConfig {
+    /// Vote weight needed for a quorum among validators weighing
+    /// `total_weight` together
+    ///
+    /// This is `ceil(total_weight * numerator / denominator)`; with every
+    /// validator weighing 1 it is a head count.
+    pub fn required_weight(&self, total_weight: u64) -> u64 {
+        let numerator = self.quorum_numerator as u64;
+        let denominator = self.quorum_denominator.max(1) as u64;
+        total_weight.saturating_mul(numerator).div_ceil(denominator)
+    }
+
+    /// Most blocks that may be in flight, proposed but not yet committed:
+    /// `pipeline_depth` while pipelining, and one if not
+    pub fn blocks_in_flight(&self) -> usize {
+        if self.pipelining {
+            self.pipeline_depth.max(1)
+        } else {
+            1
+        }
+    }
+
+    /// Time between ticks, in ticked rounds
+    pub fn tick_interval(&self) -> Option<Duration> {
+        match self.round_mode {
+            RoundMode::FreeRunning => None,
+            RoundMode::Ticked { tick_interval } => Some(tick_interval),
+        }
+    }
+
+    /// Conflict policy of `game_id`
+    pub fn conflict_policy_of(&self, game_id: &str) -> ConflictPolicy {
+        self.game_conflict_policies
+            .get(game_id)
+            .copied()
+            .unwrap_or(self.conflict_policy)
+    }
+}
+
+// Player id lists as hex strings
+mod serde_player_ids {
+    use crate::crypto::{PlayerId, parse_player_id, player_id_hex};
+    use serde::de::Error;
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S>(ids: &[PlayerId], serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.collect_seq(ids.iter().map(player_id_hex))
+    }
+
+    pub fn deserialize<'de, D>(deserializer: D) -> Result<Vec<PlayerId>, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        Vec::<String>::deserialize(deserializer)?
+            .iter()
+            .map(|s| parse_player_id(s).map_err(D::Error::custom))
+            .collect()
+    }
+}
+
+mod serde_opt_player_ids {
+    use crate::crypto::{PlayerId, parse_player_id, player_id_hex};
+    use serde::de::Error;
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S>(ids: &Option<Vec<PlayerId>>, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        match ids {
+            Some(ids) => {
+                serializer.serialize_some(&ids.iter().map(player_id_hex).collect::<Vec<_>>())
+            }
+            None => serializer.serialize_none(),
+        }
+    }
+
+    pub fn deserialize<'de, D>(deserializer: D) -> Result<Option<Vec<PlayerId>>, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        Option::<Vec<String>>::deserialize(deserializer)?
+            .map(|ids| {
+                ids.iter()
+                    .map(|s| parse_player_id(s).map_err(D::Error::custom))
+                    .collect()
+            })
+            .transpose()
+    }
+}
+
+fn current_config_version() -> u32 {
+    CONFIG_VERSION
+}
+
+fn default_bootstrap_timeout() -> Duration {
+    Duration::from_secs(5)
+}
+
+fn default_drain_timeout() -> Duration {
+    Duration::from_secs(2)
+}
+
+fn default_websocket_path() -> String {
+    "/swarmhost".to_string()
+}
+
+fn default_bind_addr() -> IpAddr {
+    IpAddr::V4(Ipv4Addr::UNSPECIFIED)
+}
+
+impl Default for NodeConfig {
+    fn default() -> Self {
+        Self {
+            config_version: CONFIG_VERSION,
+            keypair: None,
+            bootstrap_servers: Vec::new(),
+            listen_port: 0,
+            consensus: ConsensusConfig::default(),
+            network: NetworkConfig::default(),
+            state: StateConfig::default(),
+            log: LogConfig::default(),
+            preset: None,
+        }
+    }
+}
+
+impl Default for LogConfig {
+    fn default() -> Self {
+        Self {
+            level: "swarmhost_core=info".to_string(),
+            format: LogFormat::Compact,
+            file: None,
+            max_file_size: 10 * 1024 * 1024,
+            max_files: 5,
+            span_events: false,
+            trace_messages: false,
+        }
+    }
+}
+
+impl Default for NetworkConfig {
+    fn default() -> Self {
+        Self {
+            bind_addr: default_bind_addr(),
+            dual_stack: false,
+            max_peers: 50,
+            heartbeat_interval: Duration::from_secs(3),
+            peer_timeout: Duration::from_secs(30),
+            bootstrap_timeout: default_bootstrap_timeout(),
+            drain_timeout: default_drain_timeout(),
+            max_message_size: 1024 * 1024,
+            transport: TransportKind::default(),
+            websocket_path: default_websocket_path(),
+            compression: CompressionConfig::default(),
+            wire_format: WireFormat::default(),
+            enable_mdns: false,
+            enable_dht: false,
+            enable_pex: false,
+            allow_relay: false,
+            allowlist: None,
+            denylist: Vec::new(),
+            outbound_proxy: None,
+            nat: NatConfig::default(),
+            gossip: GossipConfig::default(),
+            relay: RelayConfig::default(),
+            reputation: ReputationConfig::default(),
+            outbound: OutboundConfig::default(),
+            batching: BatchConfig::default(),
+            mux: MuxConfig::default(),
+            dedup: DedupConfig::default(),
+            fragmentation: FragmentConfig::default(),
+            upload: UploadConfig::default(),
+            inbound: InboundConfig::default(),
+            reliable: ReliableConfig::default(),
+            reconnect: ReconnectConfig::default(),
+            resumption: ResumptionConfig::default(),
+            dht: DhtConfig::default(),
+            pex: PexConfig::default(),
+            security: SecurityConfig::default(),
+        }
+    }
+}
+
+impl Default for CompressionConfig {
+    fn default() -> Self {
+        Self {
+            algorithm: CompressionAlgorithm::Lz4,
+            min_size: 256,
+        }
+    }
+}
+
+impl Default for NatConfig {
+    fn default() -> Self {
+        Self {
+            stun_servers: Vec::new(),
+            enable_upnp: false,
+            upnp_lease: Duration::from_secs(3600),
+            advertised_addr: None,
+            stun_timeout: Duration::from_secs(3),
+            hole_punching: true,
+            punch_window: Duration::from_secs(10),
+            punch_interval: Duration::from_millis(50),
+        }
+    }
+}
+
+impl Default for GossipConfig {
+    fn default() -> Self {
+        Self {
+            fanout: 6,
+            seen_cache_size: 4096,
+            max_hops: 6,
+        }
+    }
+}
+
+impl Default for RelayConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            max_sessions: 16,
+            session_bandwidth: 256 * 1024,
+        }
+    }
+}
+
+impl Default for ReputationConfig {
+    fn default() -> Self {
+        Self {
+            ban_threshold: 100,
+            decay_half_life: Duration::from_secs(300),
+            ban_duration: Duration::from_secs(600),
+            penalize_latency: false,
+            max_rtt: Duration::from_millis(500),
+        }
+    }
+}
+
+impl Default for OutboundConfig {
+    fn default() -> Self {
+        Self {
+            control_capacity: 256,
+            game_action_capacity: 256,
+            bulk_capacity: 32,
+        }
+    }
+}
+
+impl Default for MuxConfig {
+    fn default() -> Self {
+        Self {
+            control_window: 256 * 1024,
+            consensus_window: 256 * 1024,
+            game_action_window: 256 * 1024,
+            bulk_window: 256 * 1024,
+            bulk_chunk: 16 * 1024,
+        }
+    }
+}
+
+impl Default for BatchConfig {
+    fn default() -> Self {
+        Self {
+            window: Duration::from_millis(2),
+            max_bytes: 16 * 1024,
+        }
+    }
+}
+
+impl Default for DedupConfig {
+    fn default() -> Self {
+        Self {
+            capacity: 8192,
+            ttl: Duration::from_secs(120),
+        }
+    }
+}
+
+impl Default for FragmentConfig {
+    fn default() -> Self {
+        Self {
+            max_transfer_size: 64 * 1024 * 1024,
+            reassembly_timeout: Duration::from_secs(10),
+            max_reassemblies: 4,
+        }
+    }
+}
+
+impl Default for UploadConfig {
+    fn default() -> Self {
+        Self {
+            max_upload_bytes_per_sec_per_peer: None,
+            max_upload_bytes_per_sec: None,
+            game_action_bytes_per_sec: None,
+            bulk_bytes_per_sec: None,
+            control_reserve_bytes_per_sec: 8 * 1024,
+            burst: Duration::from_millis(250),
+        }
+    }
+}
+
+impl Default for InboundConfig {
+    fn default() -> Self {
+        Self {
+            max_messages_per_sec: Some(5000),
+            max_bytes_per_sec: None,
+            pause: Duration::from_secs(1),
+            forgive_after: Duration::from_secs(30),
+            handshake_max_bytes: 16 * 1024,
+            max_half_open: 64,
+        }
+    }
+}
+
+impl Default for ReliableConfig {
+    fn default() -> Self {
+        Self {
+            ack_delay: Duration::from_millis(20),
+            initial_rto: Duration::from_secs(1),
+            min_rto: Duration::from_millis(50),
+            max_rto: Duration::from_secs(10),
+            max_transmissions: 8,
+            window: 256,
+        }
+    }
+}
+
+impl Default for ReconnectConfig {
+    fn default() -> Self {
+        Self {
+            window: Duration::from_secs(30),
+            initial_backoff: Duration::from_millis(250),
+            max_backoff: Duration::from_secs(5),
+        }
+    }
+}
+
+impl Default for ResumptionConfig {
+    fn default() -> Self {
+        Self {
+            grace: Duration::ZERO,
+            lifetime: Duration::from_secs(300),
+        }
+    }
+}
+
+impl Default for DhtConfig {
+    fn default() -> Self {
+        Self {
+            bucket_size: 20,
+            alpha: 3,
+            request_timeout: Duration::from_secs(5),
+            refresh_interval: Duration::from_secs(600),
+            provider_ttl: Duration::from_secs(1800),
+        }
+    }
+}
+
+impl Default for PexConfig {
+    fn default() -> Self {
+        Self {
+            interval: Duration::from_secs(30),
+            fanout: 3,
+            max_entries: 16,
+            max_age: Duration::from_secs(300),
+            store_capacity: 1024,
+        }
+    }
+}
+
+impl Default for SecurityConfig {
+    fn default() -> Self {
+        Self {
+            mode: SecurityMode::Encrypted,
+            ciphers: vec![CipherSuite::ChaCha20Poly1305, CipherSuite::Aes256Gcm],
+            handshake_timeout: Duration::from_secs(5),
+            allow_insecure_on_public: false,
+            rekey_after_messages: 1 << 20,
+            rekey_after_bytes: 1 << 30,
+        }
+    }
+}
+
+impl Default for StateConfig {
+    fn default() -> Self {
+        Self {
+            snapshot_interval: 100,
+            max_snapshots_in_memory: 10,
+            max_snapshots_on_disk: default_max_snapshots_on_disk(),
+            max_action_log_size: 1000,
+            archive_pruned: false,
+            max_replay_actions: default_max_replay_actions(),
+            max_memory_bytes: 0,
+            persistence: PersistenceBackend::InMemory,
+        }
+    }
+}
+
+impl NodeConfig {
+    /// Create a new config with a randomly generated keypair
+    pub fn new() -> Self {
+        Self {
+            keypair: Some(KeyPair::generate()),
+            ..Default::default()
+        }
+    }
+
+    /// Create config with an existing keypair
+    pub fn with_keypair(keypair: KeyPair) -> Self {
+        Self {
+            keypair: Some(keypair),
+            ..Default::default()
+        }
+    }
+
+    /// Preset for peers on the same local network
+    ///
+    /// Short heartbeat/peer/consensus timeouts, compression off (bandwidth is
+    /// cheap, CPU time is not) and mDNS discovery on.
+    pub fn lan() -> Self {
+        let mut config = Self::new();
+        config.preset = Some(ConfigPreset::Lan);
+        config.network.heartbeat_interval = Duration::from_millis(500);
+        config.network.peer_timeout = Duration::from_secs(3);
+        config.network.compression.algorithm = CompressionAlgorithm::None;
+        config.network.enable_mdns = true;
+        config.consensus.consensus_timeout = Duration::from_secs(1);
+        config
+    }
+
+    /// Preset for peers spread across the internet
+    ///
+    /// Longer heartbeat/peer/consensus timeouts to ride out latency spikes,
+    /// compression on and relaying allowed for peers behind strict NATs.
+    pub fn internet() -> Self {
+        let mut config = Self::new();
+        config.preset = Some(ConfigPreset::Internet);
+        config.network.heartbeat_interval = Duration::from_secs(5);
+        config.network.peer_timeout = Duration::from_secs(45);
+        config.network.compression.algorithm = CompressionAlgorithm::Zstd { level: 3 };
+        config.network.allow_relay = true;
+        config.network.nat.stun_servers = vec!["stun.l.google.com:19302".to_string()];
+        config.consensus.consensus_timeout = Duration::from_secs(10);
+        config
+    }
+
+    /// Preset for turn-based games
+    ///
+    /// A long consensus timeout (players think between moves), a snapshot
+    /// every few actions since each one matters, and a low heartbeat rate.
+    pub fn turn_based() -> Self {
+        let mut config = Self::new();
+        config.preset = Some(ConfigPreset::TurnBased);
+        config.network.heartbeat_interval = Duration::from_secs(30);
+        config.network.peer_timeout = Duration::from_secs(90);
+        config.consensus.consensus_timeout = Duration::from_secs(60);
+        config.state.snapshot_interval = 10;
+        config
+    }
+
+    /// Persist snapshots under `path`
+    ///
+    /// The turn-based preset fsyncs every snapshot since each one covers a few
+    /// deliberate moves; other configs leave flushing to the OS.
+    pub fn with_data_dir(mut self, path: impl Into<PathBuf>) -> Self {
+        self.state.persistence = PersistenceBackend::Directory {
+            path: path.into(),
+            fsync: self.preset == Some(ConfigPreset::TurnBased),
+        };
+        self
+    }
+
+    /// Load a JSON config file, migrating older formats in memory
+    ///
+    /// The keypair is never stored in the file; set `keypair` before use.
+    pub fn from_file(path: impl AsRef<Path>) -> crate::Result<Self> {
+        Ok(Self::load(path.as_ref())?.0)
+    }
+
+    /// Load a JSON config file and rewrite it in the current format if it was
+    /// migrated
+    pub fn upgrade_file(path: impl AsRef<Path>) -> crate::Result<Self> {
+        let path = path.as_ref();
+        let (config, from) = Self::load(path)?;
+        if from < CONFIG_VERSION {
+            tracing::info!(
+                "Upgrading {} from config_version {} to {}",
+                path.display(),
+                from,
+                CONFIG_VERSION
+            );
+            config.to_file(path)?;
+        }
+        Ok(config)
+    }
+
+    /// Write this config to a file as pretty-printed JSON
+    pub fn to_file(&self, path: impl AsRef<Path>) -> crate::Result<()> {
+        let json = serde_json::to_string_pretty(self)
+            .map_err(|e| SwarmhostError::Serialization(e.to_string()))?;
+        std::fs::write(path, json)?;
+        Ok(())
+    }
+
+    fn load(path: &Path) -> crate::Result<(Self, u32)> {
+        let text = std::fs::read_to_string(path).map_err(|e| {
+            SwarmhostError::Config(format!("cannot read {}: {}", path.display(), e))
+        })?;
+        let mut value: serde_json::Value = serde_json::from_str(&text)
+            .map_err(|e| SwarmhostError::Config(format!("{}: {}", path.display(), e)))?;
+
+        let from = migrations::migrate(&mut value)?;
+        let config = serde_json::from_value(value)
+            .map_err(|e| SwarmhostError::Config(format!("{}: {}", path.display(), e)))?;
+        Ok((config, from))
+    }
+
+    /// Name of the preset this config was built from, if any
+    pub fn preset_name(&self) -> Option<&'static str> {
+        self.preset.map(|preset| preset.name())
+    }
+
+    /// Get the player ID (public key)
+    pub fn player_id(&self) -> Option<PlayerId> {
+        self.keypair.as_ref().map(|kp| kp.public_key())
+    }
+
+    /// Add a bootstrap server (tried after any already configured)
+    pub fn with_bootstrap(mut self, server: impl Into<String>) -> Self {
+        self.bootstrap_servers.push(server.into());
+        self
+    }
+
+    /// Replace the bootstrap server list
+    pub fn with_bootstrap_servers<I, S>(mut self, servers: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.bootstrap_servers = servers.into_iter().map(Into::into).collect();
+        self
+    }
+
+    /// Set bootstrap server
+    #[deprecated(note = "use `with_bootstrap` or `bootstrap_servers`")]
+    pub fn set_bootstrap_server(&mut self, server: impl Into<String>) {
+        self.bootstrap_servers.push(server.into());
+    }
+
+    /// Set listen port
+    pub fn with_port(mut self, port: u16) -> Self {
+        self.listen_port = port;
+        self
+    }
+
+    /// Set the quorum fraction (e.g. 2/3)
+    pub fn with_quorum(mut self, numerator: u32, denominator: u32) -> Self {
+        self.consensus.quorum_numerator = numerator;
+        self.consensus.quorum_denominator = denominator;
+        self
+    }
+
+    /// Enable/disable optimistic execution
+    pub fn with_optimistic_execution(mut self, enabled: bool) -> Self {
+        self.consensus.optimistic_execution = enabled;
+        self
+    }
+
+    /// Validate the configuration
+    ///
+    /// All violations are reported at once, joined with "; ".
+    pub fn validate(&self) -> Result<(), String> {
+        let errors = self.validation_errors();
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors.join("; "))
+        }
+    }
+
+    /// Every validation rule the configuration violates
+    pub fn validation_errors(&self) -> Vec<String> {
+        let mut errors = Vec::new();
+
+        if self.keypair.is_none() {
+            errors.push("Keypair must be set".to_string());
+        }
+
+        let numerator = self.consensus.quorum_numerator;
+        let denominator = self.consensus.quorum_denominator;
+        if numerator == 0 || denominator == 0 {
+            errors.push("Quorum fraction cannot have zero denominator/numerator".to_string());
+        } else if numerator > denominator {
+            errors.push("Quorum numerator cannot exceed denominator".to_string());
+        } else if denominator > MAX_QUORUM_DENOMINATOR {
+            errors.push(format!(
+                "Quorum denominator cannot exceed {}",
+                MAX_QUORUM_DENOMINATOR
+            ));
+        } else if numerator * 2 <= denominator && !self.consensus.allow_weak_quorum {
+            errors.push(format!(
+                "Quorum {}/{} must be greater than 1/2 (set allow_weak_quorum to override)",
+                numerator, denominator
+            ));
+        }
+
+        let durations = [
+            ("heartbeat_interval", self.network.heartbeat_interval),
+            ("peer_timeout", self.network.peer_timeout),
+            ("bootstrap_timeout", self.network.bootstrap_timeout),
+            ("drain_timeout", self.network.drain_timeout),
+            ("nat.stun_timeout", self.network.nat.stun_timeout),
+            ("nat.upnp_lease", self.network.nat.upnp_lease),
+            ("nat.punch_window", self.network.nat.punch_window),
+            ("nat.punch_interval", self.network.nat.punch_interval),
+            (
+                "reputation.decay_half_life",
+                self.network.reputation.decay_half_life,
+            ),
+            (
+                "reputation.ban_duration",
+                self.network.reputation.ban_duration,
+            ),
+            ("reputation.max_rtt", self.network.reputation.max_rtt),
+            ("dedup.ttl", self.network.dedup.ttl),
+            ("upload.burst", self.network.upload.burst),
+            ("inbound.pause", self.network.inbound.pause),
+            ("inbound.forgive_after", self.network.inbound.forgive_after),
+            ("reliable.ack_delay", self.network.reliable.ack_delay),
+            ("reliable.initial_rto", self.network.reliable.initial_rto),
+            ("reliable.min_rto", self.network.reliable.min_rto),
+            ("reliable.max_rto", self.network.reliable.max_rto),
+            (
+                "reconnect.initial_backoff",
+                self.network.reconnect.initial_backoff,
+            ),
+            ("reconnect.max_backoff", self.network.reconnect.max_backoff),
+            ("resumption.lifetime", self.network.resumption.lifetime),
+            ("dht.request_timeout", self.network.dht.request_timeout),
+            ("dht.refresh_interval", self.network.dht.refresh_interval),
+            ("dht.provider_ttl", self.network.dht.provider_ttl),
+            ("pex.interval", self.network.pex.interval),
+            ("pex.max_age", self.network.pex.max_age),
+            (
+                "fragmentation.reassembly_timeout",
+                self.network.fragmentation.reassembly_timeout,
+            ),
+            (
+                "security.handshake_timeout",
+                self.network.security.handshake_timeout,
+            ),
+            ("consensus_timeout", self.consensus.consensus_timeout),
+            ("consensus.gap_timeout", self.consensus.gap_timeout),
+            (
+                "consensus.proposer_timeout",
+                self.consensus.proposer_timeout,
+            ),
+            ("consensus.sync_timeout", self.consensus.sync_timeout),
+            (
+                "consensus.validation_timeout",
+                self.consensus.validation_timeout,
+            ),
+            ("consensus.grace_period", self.consensus.grace_period),
+        ];
+        for (name, value) in durations {
+            if value.is_zero() {
+                errors.push(format!("{} must be > 0", name));
+            }
+        }
+
+        let heartbeat = self.network.heartbeat_interval;
+        if heartbeat
+            .checked_mul(2)
+            .is_none_or(|twice| self.network.peer_timeout < twice)
+        {
+            errors.push(format!(
+                "peer_timeout ({:?}) must be at least 2x heartbeat_interval ({:?})",
+                self.network.peer_timeout, heartbeat
+            ));
+        }
+
+        if self.consensus.consensus_timeout <= heartbeat {
+            errors.push(format!(
+                "consensus_timeout ({:?}) must be greater than heartbeat_interval ({:?})",
+                self.consensus.consensus_timeout, heartbeat
+            ));
+        }
+
+        if self.consensus.quorum_loss_timeouts == 0 {
+            errors.push("consensus.quorum_loss_timeouts must be at least 1".to_string());
+        }
+
+        if self.consensus.view_change_rounds == 0 {
+            errors.push("consensus.view_change_rounds must be at least 1".to_string());
+        }
+
+        if let Some(tick) = self.consensus.tick_interval() {
+            if tick.is_zero() {
+                errors.push("consensus.round_mode tick_interval must be > 0".to_string());
+            } else if tick >= self.consensus.proposer_timeout {
+                errors.push(format!(
+                    "tick_interval ({:?}) must be less than proposer_timeout ({:?})",
+                    tick, self.consensus.proposer_timeout
+                ));
+            }
+        }
+
+        for server in &self.bootstrap_servers {
+            if let Err(e) = parse_host_port(server) {
+                errors.push(format!("Invalid bootstrap server '{}': {}", server, e));
+            }
+        }
+
+        for server in &self.network.nat.stun_servers {
+            if let Err(e) = parse_host_port(server) {
+                errors.push(format!("Invalid STUN server '{}': {}", server, e));
+            }
+        }
+
+        if let Some(proxy) = &self.network.outbound_proxy {
+            if let Err(e) = parse_host_port(&proxy.addr) {
+                errors.push(format!("Invalid outbound proxy '{}': {}", proxy.addr, e));
+            }
+            match (&proxy.username, &proxy.password) {
+                (Some(username), Some(password)) => {
+                    let lengths = [username.len(), password.len()];
+                    if lengths.iter().any(|len| !(1..=255).contains(len)) {
+                        errors.push(
+                            "outbound_proxy username and password must be 1-255 bytes".to_string(),
+                        );
+                    }
+                }
+                (None, None) => {}
+                _ => errors
+                    .push("outbound_proxy username and password must be set together".to_string()),
+            }
+            if self.network.transport == TransportKind::Quic {
+                errors.push("outbound_proxy cannot carry the QUIC transport".to_string());
+            }
+        }
+
+        let bind_addr = self.network.bind_addr;
+        let is_broadcast = matches!(bind_addr, IpAddr::V4(v4) if v4.is_broadcast());
+        if bind_addr.is_multicast() || is_broadcast {
+            errors.push(format!(
+                "Bind address {} must be a unicast address",
+                bind_addr
+            ));
+        }
+
+        let security = &self.network.security;
+        if security.mode == SecurityMode::Plaintext
+            && !bind_addr.is_loopback()
+            && !security.allow_insecure_on_public
+        {
+            errors.push(format!(
+                "Plaintext security mode requires a loopback bind address (got {}) unless allow_insecure_on_public is set",
+                bind_addr
+            ));
+        }
+
+        if security.mode != SecurityMode::Plaintext && security.ciphers.is_empty() {
+            errors.push("security.ciphers cannot be empty unless mode is Plaintext".to_string());
+        }
+
+        if security.rekey_after_messages == 0 || security.rekey_after_bytes == 0 {
+            errors.push(
+                "security.rekey_after_messages and rekey_after_bytes must be > 0".to_string(),
+            );
+        }
+
+        if self.network.transport == TransportKind::WebSocket && !cfg!(feature = "websocket") {
+            errors.push("WebSocket transport needs the 'websocket' feature".to_string());
+        }
+        if self.network.wire_format == WireFormat::Protobuf && !cfg!(feature = "protobuf") {
+            errors.push("Protobuf wire format needs the 'protobuf' feature".to_string());
+        }
+
+        if !self.network.websocket_path.starts_with('/') {
+            errors.push(format!(
+                "websocket_path must start with '/', got '{}'",
+                self.network.websocket_path
+            ));
+        }
+
+        if let CompressionAlgorithm::Zstd { level } = self.network.compression.algorithm
+            && !(1..=22).contains(&level)
+        {
+            errors.push(format!(
+                "zstd compression level must be 1-22, got {}",
+                level
+            ));
+        }
+
+        if let Some(allowlist) = &self.network.allowlist {
+            for id in self
+                .network
+                .denylist
+                .iter()
+                .filter(|id| allowlist.contains(id))
+            {
+                errors.push(format!(
+                    "Player {} is in both allowlist and denylist",
+                    short_id(id)
+                ));
+            }
+        }
+
+        if self.network.max_message_size == 0 {
+            errors.push("Max message size must be > 0".to_string());
+        }
+
+        let gossip = &self.network.gossip;
+        if gossip.fanout == 0 {
+            errors.push("gossip.fanout must be > 0".to_string());
+        }
+        if gossip.seen_cache_size == 0 {
+            errors.push("gossip.seen_cache_size must be > 0".to_string());
+        }
+        if gossip.max_hops == 0 {
+            errors.push("gossip.max_hops must be > 0".to_string());
+        }
+
+        if self.network.reputation.ban_threshold == 0 {
+            errors.push("reputation.ban_threshold must be > 0".to_string());
+        }
+
+        let relay = &self.network.relay;
+        if relay.enabled && relay.max_sessions == 0 {
+            errors.push("relay.max_sessions must be > 0".to_string());
+        }
+        if relay.enabled && relay.session_bandwidth == 0 {
+            errors.push("relay.session_bandwidth must be > 0".to_string());
+        }
+
+        if self.network.batching.max_bytes == 0 {
+            errors.push("batching.max_bytes must be > 0".to_string());
+        }
+        let mux = &self.network.mux;
+        for (name, window) in [
+            ("mux.control_window", mux.control_window),
+            ("mux.consensus_window", mux.consensus_window),
+            ("mux.game_action_window", mux.game_action_window),
+            ("mux.bulk_window", mux.bulk_window),
+        ] {
+            if window < MIN_WINDOW {
+                errors.push(format!(
+                    "{} ({}) must be at least {} bytes",
+                    name, window, MIN_WINDOW
+                ));
+            }
+        }
+        if mux.bulk_chunk <= FRAGMENT_OVERHEAD {
+            errors.push(format!(
+                "mux.bulk_chunk ({}) must exceed the {} byte fragment overhead",
+                mux.bulk_chunk, FRAGMENT_OVERHEAD
+            ));
+        }
+        if self.network.dedup.capacity == 0 {
+            errors.push("dedup.capacity must be > 0".to_string());
+        }
+        if self.network.fragmentation.max_reassemblies == 0 {
+            errors.push("fragmentation.max_reassemblies must be > 0".to_string());
+        }
+
+        let reliable = &self.network.reliable;
+        if reliable.min_rto > reliable.max_rto {
+            errors.push(format!(
+                "reliable.min_rto ({:?}) must not exceed reliable.max_rto ({:?})",
+                reliable.min_rto, reliable.max_rto
+            ));
+        }
+        if reliable.max_transmissions == 0 {
+            errors.push("reliable.max_transmissions must be > 0".to_string());
+        }
+        if reliable.window == 0 {
+            errors.push("reliable.window must be > 0".to_string());
+        }
+
+        let reconnect = &self.network.reconnect;
+        if reconnect.initial_backoff > reconnect.max_backoff {
+            errors.push(format!(
+                "reconnect.initial_backoff ({:?}) must not exceed reconnect.max_backoff ({:?})",
+                reconnect.initial_backoff, reconnect.max_backoff
+            ));
+        }
+
+        let resumption = &self.network.resumption;
+        if resumption.lifetime < resumption.grace {
+            errors.push(format!(
+                "resumption.lifetime ({:?}) must be at least resumption.grace ({:?})",
+                resumption.lifetime, resumption.grace
+            ));
+        }
+
+        let dht = &self.network.dht;
+        if dht.bucket_size == 0 {
+            errors.push("dht.bucket_size must be > 0".to_string());
+        }
+        if dht.alpha == 0 {
+            errors.push("dht.alpha must be > 0".to_string());
+        }
+
+        let pex = &self.network.pex;
+        for (name, value) in [
+            ("fanout", pex.fanout),
+            ("max_entries", pex.max_entries),
+            ("store_capacity", pex.store_capacity),
+        ] {
+            if value == 0 {
+                errors.push(format!("pex.{} must be > 0", name));
+            }
+        }
+
+        let upload = &self.network.upload;
+        for (name, rate) in [
+            (
+                "upload.max_upload_bytes_per_sec_per_peer",
+                upload.max_upload_bytes_per_sec_per_peer,
+            ),
+            (
+                "upload.max_upload_bytes_per_sec",
+                upload.max_upload_bytes_per_sec,
+            ),
+            (
+                "upload.game_action_bytes_per_sec",
+                upload.game_action_bytes_per_sec,
+            ),
+            ("upload.bulk_bytes_per_sec", upload.bulk_bytes_per_sec),
+            (
+                "inbound.max_messages_per_sec",
+                self.network.inbound.max_messages_per_sec,
+            ),
+            (
+                "inbound.max_bytes_per_sec",
+                self.network.inbound.max_bytes_per_sec,
+            ),
+        ] {
+            if rate == Some(0) {
+                errors.push(format!("{} must be > 0 when set", name));
+            }
+        }
+
+        let inbound = &self.network.inbound;
+        if inbound.handshake_max_bytes == 0 {
+            errors.push("inbound.handshake_max_bytes must be > 0".to_string());
+        }
+        if inbound.max_half_open == 0 {
+            errors.push("inbound.max_half_open must be > 0".to_string());
+        }
+
+        let outbound = &self.network.outbound;
+        for (name, capacity) in [
+            ("outbound.control_capacity", outbound.control_capacity),
+            (
+                "outbound.game_action_capacity",
+                outbound.game_action_capacity,
+            ),
+            ("outbound.bulk_capacity", outbound.bulk_capacity),
+        ] {
+            if capacity == 0 {
+                errors.push(format!("{} must be > 0", name));
+            }
+        }
+
+        let max_action_size = self.consensus.max_action_size;
+        if max_action_size == 0 {
+            errors.push("max_action_size must be > 0".to_string());
+        } else if max_action_size + MAX_FRAME_OVERHEAD > self.network.max_message_size {
+            errors.push(format!(
+                "max_action_size ({}) must leave {} bytes of framing room in max_message_size ({})",
+                max_action_size, MAX_FRAME_OVERHEAD, self.network.max_message_size
+            ));
+        }
+
+        let batch_max_actions = self.consensus.batch_max_actions;
+        if batch_max_actions == 0 || batch_max_actions > MAX_ROUND_ACTIONS {
+            errors.push(format!(
+                "consensus.batch_max_actions must be between 1 and {}",
+                MAX_ROUND_ACTIONS
+            ));
+        }
+        if self.consensus.batch_max_bytes == 0 {
+            errors.push("consensus.batch_max_bytes must be > 0".to_string());
+        }
+        if self.consensus.batch_interval >= self.consensus.proposer_timeout {
+            errors.push(
+                "consensus.batch_interval must be shorter than consensus.proposer_timeout"
+                    .to_string(),
+            );
+        }
+        if self.consensus.fast_path_timeout >= self.consensus.proposer_timeout {
+            errors.push(format!(
+                "consensus.fast_path_timeout ({:?}) must be shorter than consensus.proposer_timeout ({:?})",
+                self.consensus.fast_path_timeout, self.consensus.proposer_timeout
+            ));
+        }
+
+        if self.consensus.min_validators == 0 {
+            errors.push("consensus.min_validators must be > 0".to_string());
+        }
+        if self.consensus.grace_min_validators < 2 && !self.consensus.allow_dictatorship {
+            errors.push(
+                "consensus.grace_min_validators must be at least 2 unless allow_dictatorship is set"
+                    .to_string(),
+            );
+        }
+
+        if self.consensus.sync_chunks_per_peer == 0 {
+            errors.push("consensus.sync_chunks_per_peer must be > 0".to_string());
+        }
+
+        let pipeline_depth = self.consensus.pipeline_depth;
+        if pipeline_depth == 0 {
+            errors.push("consensus.pipeline_depth must be > 0".to_string());
+        } else if self.consensus.pipelining
+            && self.consensus.optimistic_execution
+            && pipeline_depth > self.consensus.max_speculation_depth
+        {
+            errors.push(format!(
+                "consensus.pipeline_depth ({}) must not exceed max_speculation_depth ({})",
+                pipeline_depth, self.consensus.max_speculation_depth
+            ));
+        }
+
+        if self.consensus.max_actions_per_player_per_round == 0 {
+            errors.push("max_actions_per_player_per_round must be > 0".to_string());
+        }
+
+        if self.consensus.max_pending_actions == 0 {
+            errors.push("max_pending_actions must be > 0".to_string());
+        }
+
+        if self.state.max_action_log_size < self.state.snapshot_interval as usize {
+            errors.push(format!(
+                "max_action_log_size ({}) must be >= snapshot_interval ({})",
+                self.state.max_action_log_size, self.state.snapshot_interval
+            ));
+        }
+
+        if let PersistenceBackend::Directory { path, .. } | PersistenceBackend::Sled { path, .. } =
+            &self.state.persistence
+            && let Err(e) = check_writable_dir(path)
+        {
+            errors.push(format!(
+                "State directory {} is not writable: {}",
+                path.display(),
+                e
+            ));
+        }
+        if matches!(self.state.persistence, PersistenceBackend::Sled { .. })
+            && !cfg!(feature = "sled")
+        {
+            errors.push("state.persistence is Sled but the 'sled' feature is off".to_string());
+        }
+
+        if let Err(e) = tracing_subscriber::EnvFilter::try_new(&self.log.level) {
+            errors.push(format!("Invalid log level '{}': {}", self.log.level, e));
+        }
+
+        if self.log.file.is_some() && self.log.max_file_size == 0 {
+            errors.push("log.max_file_size must be greater than zero".to_string());
+        }
+
+        if self.state.max_snapshots_in_memory == 0 {
+            errors.push(format!(
+                "max_snapshots_in_memory ({}) must be >= 1",
+                self.state.max_snapshots_in_memory
+            ));
+        }
+
+        if self.state.max_snapshots_on_disk == 0 {
+            errors.push(format!(
+                "max_snapshots_on_disk ({}) must be >= 1",
+                self.state.max_snapshots_on_disk
+            ));
+        }
+
+        errors
+    }
+}
+
+/// Create `path` if needed and confirm we can write files in it
+fn check_writable_dir(path: &Path) -> std::io::Result<()> {
+    std::fs::create_dir_all(path)?;
+    let probe = path.join(".swarmhost-write-test");
+    std::fs::write(&probe, b"ok")?;
+    std::fs::remove_file(&probe)
+}
+
+/// Split a `host:port` string, accepting bracketed IPv6 hosts (`[::1]:9000`)
+pub fn parse_host_port(addr: &str) -> Result<(String, u16), String> {
+    if let Ok(socket_addr) = addr.parse::<std::net::SocketAddr>() {
+        return Ok((socket_addr.ip().to_string(), socket_addr.port()));
+    }
+
+    let (host, port) = addr
+        .rsplit_once(':')
+        .ok_or_else(|| "expected host:port".to_string())?;
+
+    if host.is_empty() || host.contains(':') || host.contains('[') {
+        return Err(format!("invalid host '{}'", host));
+    }
+
+    let port: u16 = port
+        .parse()
+        .map_err(|_| format!("invalid port '{}'", port))?;
+    if port == 0 {
+        return Err("port must be > 0".to_string());
+    }
+
+    Ok((host.to_string(), port))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_default_config() {
+        let config = NodeConfig::default();
+        assert_eq!(config.consensus.quorum_numerator, 2);
+        assert_eq!(config.consensus.quorum_denominator, 3);
+        assert!(config.consensus.optimistic_execution);
+    }
+
+    #[test]
+    fn test_new_config_generates_keypair() {
+        let config = NodeConfig::new();
+        assert!(config.keypair.is_some());
+        assert!(config.player_id().is_some());
+    }
+
+    #[test]
+    fn test_config_builder() {
+        let config = NodeConfig::new()
+            .with_bootstrap("localhost:8080")
+            .with_port(9000)
+            .with_optimistic_execution(false);
+
+        assert_eq!(config.bootstrap_servers, vec!["localhost:8080".to_string()]);
+        assert_eq!(config.listen_port, 9000);
+        assert!(!config.consensus.optimistic_execution);
+    }
+
+    #[test]
+    fn test_validate_config() {
+        let mut config = NodeConfig::new();
+        assert!(config.validate().is_ok());
+
+        // Invalid: no keypair
+        config.keypair = None;
+        assert!(config.validate().is_err());
+
+        // Invalid: bad quorum
+        config.keypair = Some(KeyPair::generate());
+        config.consensus.quorum_numerator = 0;
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn test_duration_millisecond_round_trip() {
+        let mut config = NodeConfig::new();
+        config.network.heartbeat_interval = Duration::from_millis(250);
+
+        let json = serde_json::to_string(&config).unwrap();
+        let restored: NodeConfig = serde_json::from_str(&json).unwrap();
+
+        assert_eq!(
+            restored.network.heartbeat_interval,
+            Duration::from_millis(250)
+        );
+        assert_eq!(restored.network.peer_timeout, config.network.peer_timeout);
+    }
+
+    #[test]
+    fn test_duration_legacy_integer_seconds() {
+        let mut value = serde_json::to_value(NodeConfig::default()).unwrap();
+        value["network"]["heartbeat_interval"] = serde_json::json!(7);
+        value["consensus"]["consensus_timeout"] = serde_json::json!(12);
+
+        let config: NodeConfig = serde_json::from_value(value).unwrap();
+        assert_eq!(config.network.heartbeat_interval, Duration::from_secs(7));
+        assert_eq!(config.consensus.consensus_timeout, Duration::from_secs(12));
+    }
+
+    #[test]
+    fn test_validate_rejects_zero_durations() {
+        let mut config = NodeConfig::new();
+        config.network.heartbeat_interval = Duration::ZERO;
+        assert!(
+            config
+                .validate()
+                .unwrap_err()
+                .contains("heartbeat_interval")
+        );
+
+        let mut config = NodeConfig::new();
+        config.network.peer_timeout = Duration::ZERO;
+        assert!(config.validate().unwrap_err().contains("peer_timeout"));
+
+        let mut config = NodeConfig::new();
+        config.consensus.consensus_timeout = Duration::ZERO;
+        assert!(config.validate().unwrap_err().contains("consensus_timeout"));
+    }
+
+    #[test]
+    fn test_presets_are_valid() {
+        for config in [
+            NodeConfig::lan(),
+            NodeConfig::internet(),
+            NodeConfig::turn_based(),
+        ] {
+            assert!(config.validate().is_ok(), "{:?}", config.preset_name());
+            assert!(config.keypair.is_some());
+        }
+    }
+
+    #[test]
+    fn test_presets_differ_from_default() {
+        let default = NodeConfig::default();
+
+        let lan = NodeConfig::lan();
+        assert_eq!(lan.preset_name(), Some("lan"));
+        assert!(lan.network.heartbeat_interval < default.network.heartbeat_interval);
+        assert!(lan.network.peer_timeout < default.network.peer_timeout);
+        assert!(lan.consensus.consensus_timeout < default.consensus.consensus_timeout);
+        assert_eq!(
+            lan.network.compression.algorithm,
+            CompressionAlgorithm::None
+        );
+        assert!(lan.network.enable_mdns && !default.network.enable_mdns);
+
+        let internet = NodeConfig::internet();
+        assert_eq!(internet.preset_name(), Some("internet"));
+        assert!(internet.network.peer_timeout > default.network.peer_timeout);
+        assert!(internet.consensus.consensus_timeout > default.consensus.consensus_timeout);
+        assert!(matches!(
+            internet.network.compression.algorithm,
+            CompressionAlgorithm::Zstd { .. }
+        ));
+        assert!(internet.network.allow_relay && !default.network.allow_relay);
+
+        let turn_based = NodeConfig::turn_based();
+        assert_eq!(turn_based.preset_name(), Some("turn_based"));
+        assert!(turn_based.consensus.consensus_timeout > default.consensus.consensus_timeout);
+        assert!(turn_based.state.snapshot_interval < default.state.snapshot_interval);
+        assert!(turn_based.network.heartbeat_interval > default.network.heartbeat_interval);
+
+        assert_eq!(default.preset_name(), None);
+    }
+
+    #[test]
+    fn test_builders_apply_after_preset() {
+        let config = NodeConfig::lan().with_port(9000);
+        assert_eq!(config.listen_port, 9000);
+        assert_eq!(config.preset_name(), Some("lan"));
+        assert!(config.network.enable_mdns);
+    }
+
+    #[test]
+    fn test_with_quorum() {
+        let config = NodeConfig::new().with_quorum(3, 4);
+        assert_eq!(config.consensus.quorum_numerator, 3);
+        assert_eq!(config.consensus.quorum_denominator, 4);
+        assert!(config.validate().is_ok());
+    }
+
+    #[test]
+    fn test_validate_rejects_weak_quorum() {
+        let config = NodeConfig::new().with_quorum(1, 100);
+        assert!(config.validate().is_err());
+
+        let config = NodeConfig::new().with_quorum(1, 2);
+        assert!(config.validate().is_err());
+
+        let mut config = NodeConfig::new().with_quorum(1, 2);
+        config.consensus.allow_weak_quorum = true;
+        assert!(config.validate().is_ok());
+    }
+
+    #[test]
+    fn test_validate_quorum_loss_timeouts() {
+        let mut config = NodeConfig::new();
+        config.consensus.quorum_loss_timeouts = 0;
+        assert!(
+            config
+                .validate()
+                .unwrap_err()
+                .contains("quorum_loss_timeouts")
+        );
+    }
+
+    #[test]
+    fn test_validate_min_validators() {
+        let mut config = NodeConfig::new();
+        assert_eq!(config.consensus.min_validators, 3);
+        config.consensus.min_validators = 0;
+        assert!(config.validate().unwrap_err().contains("min_validators"));
+    }
+
+    #[test]
+    fn test_validate_grace_min_validators() {
+        let mut config = NodeConfig::new();
+        assert!(!config.consensus.quorum_grace);
+        config.consensus.grace_min_validators = 1;
+        assert!(
+            config
+                .validate()
+                .unwrap_err()
+                .contains("grace_min_validators")
+        );
+        config.consensus.allow_dictatorship = true;
+        assert!(config.validate().is_ok());
+    }
+
+    #[test]
+    fn test_validate_pipeline_depth() {
+        let mut config = NodeConfig::new();
+        assert_eq!(config.consensus.blocks_in_flight(), 4);
+        config.consensus.pipeline_depth = 0;
+        assert!(config.validate().unwrap_err().contains("pipeline_depth"));
+
+        // Deeper than speculation reaches only without speculating
+        config.consensus.pipeline_depth = 8;
+        config.consensus.max_speculation_depth = 4;
+        assert!(config.validate().unwrap_err().contains("pipeline_depth"));
+        config.consensus.optimistic_execution = false;
+        assert!(config.validate().is_ok());
+
+        config.consensus.pipelining = false;
+        assert_eq!(config.consensus.blocks_in_flight(), 1);
+    }
+
+    #[test]
+    fn test_validate_tick_interval() {
+        let mut config = NodeConfig::new();
+        assert_eq!(config.consensus.tick_interval(), None);
+        config.consensus.round_mode = RoundMode::Ticked {
+            tick_interval: Duration::from_millis(50),
+        };
+        assert!(config.validate().is_ok());
+        let json = serde_json::to_string(&config).unwrap();
+        let restored: NodeConfig = serde_json::from_str(&json).unwrap();
+        assert_eq!(
+            restored.consensus.tick_interval(),
+            Some(Duration::from_millis(50))
+        );
+
+        // A tick must come round before the proposer is given up on
+        config.consensus.round_mode = RoundMode::Ticked {
+            tick_interval: config.consensus.proposer_timeout,
+        };
+        assert!(config.validate().unwrap_err().contains("tick_interval"));
+        config.consensus.round_mode = RoundMode::Ticked {
+            tick_interval: Duration::ZERO,
+        };
+        assert!(config.validate().unwrap_err().contains("tick_interval"));
+    }
+
+    #[test]
+    fn test_validate_rejects_huge_denominator() {
+        let config = NodeConfig::new().with_quorum(2000, MAX_QUORUM_DENOMINATOR + 1);
+        assert!(config.validate().is_err());
+
+        let config = NodeConfig::new().with_quorum(999, MAX_QUORUM_DENOMINATOR);
+        assert!(config.validate().is_ok());
+    }
+
+    #[test]
+    fn test_required_weight_two_thirds() {
+        let consensus = ConsensusConfig::default();
+        assert_eq!(consensus.required_weight(4), 3);
+        assert_eq!(consensus.required_weight(5), 4);
+        assert_eq!(consensus.required_weight(6), 4);
+        assert_eq!(consensus.required_weight(7), 5);
+        assert_eq!(consensus.required_weight(0), 0);
+        assert_eq!(consensus.required_weight(100), 67);
+    }
+
+    #[test]
+    fn test_validate_rejects_multicast_and_broadcast_bind() {
+        let mut config = NodeConfig::new();
+        config.network.bind_addr = "224.0.0.1".parse().unwrap();
+        assert!(config.validate().unwrap_err().contains("224.0.0.1"));
+
+        config.network.bind_addr = "255.255.255.255".parse().unwrap();
+        assert!(config.validate().is_err());
+
+        config.network.bind_addr = "ff02::1".parse().unwrap();
+        assert!(config.validate().is_err());
+
+        config.network.bind_addr = "::1".parse().unwrap();
+        assert!(config.validate().is_ok());
+    }
+
+    #[test]
+    #[allow(deprecated)]
+    fn test_bootstrap_servers_builders() {
+        let mut config = NodeConfig::new()
+            .with_bootstrap("a.example.com:8080")
+            .with_bootstrap("b.example.com:8080");
+        config.set_bootstrap_server("c.example.com:8080");
+        assert_eq!(
+            config.bootstrap_servers,
+            vec![
+                "a.example.com:8080",
+                "b.example.com:8080",
+                "c.example.com:8080"
+            ]
+        );
+
+        let config = config.with_bootstrap_servers(["[::1]:9000"]);
+        assert_eq!(config.bootstrap_servers, vec!["[::1]:9000"]);
+        assert!(config.validate().is_ok());
+    }
+
+    #[test]
+    fn test_validate_rejects_malformed_bootstrap() {
+        for bad in [
+            "localhost",
+            "localhost:",
+            ":8080",
+            "host:notaport",
+            "host:0",
+            "::1:80",
+        ] {
+            let config = NodeConfig::new().with_bootstrap(bad);
+            assert!(config.validate().is_err(), "{} should be rejected", bad);
+        }
+
+        let config = NodeConfig::new()
+            .with_bootstrap("127.0.0.1:8080")
+            .with_bootstrap("bootstrap.example.com:443");
+        assert!(config.validate().is_ok());
+    }
+
+    #[test]
+    fn test_validate_fast_path_timeout() {
+        let mut config = NodeConfig::new();
+        config.consensus.fast_path_timeout = Duration::ZERO;
+        assert!(config.validate().is_ok());
+
+        // Stragglers are given up on before the proposer would be
+        config.consensus.fast_path_timeout = config.consensus.proposer_timeout;
+        let err = config.validate().unwrap_err();
+        assert!(err.contains("fast_path_timeout"), "{}", err);
+        let shown = format!("({:?})", config.consensus.proposer_timeout);
+        assert_eq!(err.matches(&shown).count(), 2, "{}", err);
+    }
+
+    #[test]
+    fn test_validate_cross_field_rules() {
+        let mut config = NodeConfig::new();
+        config.network.heartbeat_interval = Duration::from_secs(30);
+        config.network.peer_timeout = Duration::from_secs(10);
+        config.consensus.consensus_timeout = Duration::from_secs(5);
+        config.state.snapshot_interval = 500;
+        config.state.max_action_log_size = 100;
+        config.state.max_snapshots_in_memory = 0;
+
+        let errors = config.validation_errors();
+        assert_eq!(errors.len(), 4, "{:?}", errors);
+        assert!(errors[0].contains("peer_timeout (10s)"));
+        assert!(errors[0].contains("heartbeat_interval (30s)"));
+        assert!(errors[1].contains("consensus_timeout (5s)"));
+        assert!(errors[2].contains("max_action_log_size (100)"));
+        assert!(errors[2].contains("snapshot_interval (500)"));
+        assert!(errors[3].contains("max_snapshots_in_memory"));
+
+        let message = config.validate().unwrap_err();
+        for error in &errors {
+            assert!(message.contains(error.as_str()));
+        }
+    }
+
+    #[test]
+    fn test_validate_cross_field_boundaries() {
+        let mut config = NodeConfig::new();
+        config.network.heartbeat_interval = Duration::from_secs(2);
+        config.network.peer_timeout = Duration::from_secs(4);
+        config.consensus.consensus_timeout = Duration::from_millis(2001);
+        config.state.snapshot_interval = 50;
+        config.state.max_action_log_size = 50;
+        config.state.max_snapshots_in_memory = 1;
+        assert!(config.validate().is_ok());
+
+        config.consensus.consensus_timeout = Duration::from_secs(2);
+        assert!(config.validate().is_err());
+
+        // A heartbeat too long to double is reported, not overflowed on
+        config.network.heartbeat_interval = Duration::MAX;
+        let errors = config.validation_errors();
+        assert!(errors.iter().any(|error| error.contains("peer_timeout")));
+    }
+
+    #[test]
+    fn test_validate_rejects_malformed_stun_server() {
+        let mut config = NodeConfig::new();
+        config.network.nat.stun_servers = vec!["stun.example.com".to_string()];
+        assert!(config.validate().unwrap_err().contains("STUN"));
+
+        config.network.nat.stun_servers = vec!["stun.example.com:3478".to_string()];
+        assert!(config.validate().is_ok());
+    }
+
+    #[test]
+    fn test_validate_websocket_settings() {
+        let mut config = NodeConfig::new();
+        assert_eq!(config.network.websocket_path, "/swarmhost");
+
+        config.network.websocket_path = "swarmhost".to_string();
+        assert!(config.validate().unwrap_err().contains("websocket_path"));
+
+        config.network.websocket_path = "/play".to_string();
+        config.network.transport = TransportKind::WebSocket;
+        assert_eq!(config.validate().is_ok(), cfg!(feature = "websocket"));
+    }
+
+    #[test]
+    fn test_validate_gossip_settings() {
+        let mut config = NodeConfig::new();
+        config.network.gossip.fanout = 0;
+        config.network.gossip.max_hops = 0;
+
+        let err = config.validate().unwrap_err();
+        assert!(err.contains("gossip.fanout"));
+        assert!(err.contains("gossip.max_hops"));
+        assert!(!err.contains("seen_cache_size"));
+    }
+
+    #[test]
+    fn test_validate_relay_settings_when_enabled() {
+        let mut config = NodeConfig::new();
+        config.network.relay.max_sessions = 0;
+        config.network.relay.session_bandwidth = 0;
+        assert!(config.validate().is_ok());
+
+        config.network.relay.enabled = true;
+        let err = config.validate().unwrap_err();
+        assert!(err.contains("relay.max_sessions"));
+        assert!(err.contains("relay.session_bandwidth"));
+    }
+
+    #[test]
+    fn test_validate_dht_settings() {
+        let mut config = NodeConfig::new();
+        config.network.dht.bucket_size = 0;
+        config.network.dht.provider_ttl = Duration::ZERO;
+        let err = config.validate().unwrap_err();
+        assert!(err.contains("dht.bucket_size"));
+        assert!(err.contains("dht.provider_ttl"));
+        assert!(!err.contains("dht.alpha"));
+    }
+
+    #[test]
+    fn test_validate_pex_settings() {
+        let mut config = NodeConfig::new();
+        config.network.pex.max_entries = 0;
+        config.network.pex.interval = Duration::ZERO;
+        let err = config.validate().unwrap_err();
+        assert!(err.contains("pex.max_entries"));
+        assert!(err.contains("pex.interval"));
+        assert!(!err.contains("pex.fanout"));
+    }
+
+    #[test]
+    fn test_validate_outbound_proxy() {
+        let mut config = NodeConfig::new();
+        config.network.outbound_proxy = Some(ProxyConfig {
+            addr: "localhost:9050".to_string(),
+            username: Some("player".to_string()),
+            password: Some("secret".to_string()),
+        });
+        assert!(config.validate().is_ok());
+
+        config.network.outbound_proxy = Some(ProxyConfig {
+            addr: "localhost".to_string(),
+            username: Some("player".to_string()),
+            password: None,
+        });
+        config.network.transport = TransportKind::Quic;
+        let err = config.validate().unwrap_err();
+        assert!(err.contains("Invalid outbound proxy 'localhost'"));
+        assert!(err.contains("must be set together"));
+        assert!(err.contains("QUIC"));
+    }
+
+    #[test]
+    fn test_validate_outbound_capacities() {
+        let mut config = NodeConfig::new();
+        config.network.outbound.game_action_capacity = 0;
+        let err = config.validate().unwrap_err();
+        assert!(err.contains("outbound.game_action_capacity"));
+        assert!(!err.contains("outbound.bulk_capacity"));
+    }
+
+    #[test]
+    fn test_validate_mux_windows() {
+        let mut config = NodeConfig::new();
+        config.network.mux.bulk_window = 0;
+        config.network.mux.bulk_chunk = FRAGMENT_OVERHEAD;
+        let err = config.validate().unwrap_err();
+        assert!(err.contains("mux.bulk_window"));
+        assert!(err.contains("mux.bulk_chunk"));
+        assert!(!err.contains("mux.control_window"));
+    }
+
+    #[test]
+    fn test_validate_upload_rates() {
+        let mut config = NodeConfig::new();
+        config.network.upload.max_upload_bytes_per_sec_per_peer = Some(100 * 1024);
+        assert!(config.validate().is_ok());
+
+        config.network.upload.bulk_bytes_per_sec = Some(0);
+        let err = config.validate().unwrap_err();
+        assert!(err.contains("upload.bulk_bytes_per_sec"));
+        assert!(!err.contains("upload.max_upload_bytes_per_sec_per_peer"));
+    }
+
+    #[test]
+    fn test_validate_reliable_timeouts() {
+        let mut config = NodeConfig::new();
+        config.network.reliable.min_rto = Duration::from_secs(20);
+        config.network.reliable.max_transmissions = 0;
+        let err = config.validate().unwrap_err();
+        assert!(err.contains("reliable.min_rto"));
+        assert!(err.contains("reliable.max_transmissions"));
+    }
+
+    #[test]
+    fn test_validate_reconnect_backoff() {
+        let mut config = NodeConfig::new();
+        config.network.reconnect.window = Duration::ZERO;
+        assert!(config.validate().is_ok());
+
+        config.network.reconnect.initial_backoff = Duration::from_secs(10);
+        let err = config.validate().unwrap_err();
+        assert!(err.contains("reconnect.initial_backoff"));
+        config.network.reconnect.initial_backoff = Duration::ZERO;
+        assert!(
+            config
+                .validate()
+                .unwrap_err()
+                .contains("reconnect.initial_backoff")
+        );
+    }
+
+    #[test]
+    fn test_validate_resumption() {
+        let mut config = NodeConfig::new();
+        config.network.resumption.grace = Duration::from_secs(10);
+        assert!(config.validate().is_ok());
+
+        config.network.resumption.lifetime = Duration::from_secs(5);
+        let err = config.validate().unwrap_err();
+        assert!(err.contains("resumption.lifetime"));
+        config.network.resumption.lifetime = Duration::ZERO;
+        assert!(
+            config
+                .validate()
+                .unwrap_err()
+                .contains("resumption.lifetime")
+        );
+    }
+
+    #[test]
+    fn test_validate_plaintext_requires_loopback() {
+        let mut config = NodeConfig::new();
+        config.network.security.mode = SecurityMode::Plaintext;
+        assert!(config.validate().unwrap_err().contains("Plaintext"));
+
+        config.network.bind_addr = "127.0.0.1".parse().unwrap();
+        assert!(config.validate().is_ok());
+
+        config.network.bind_addr = "0.0.0.0".parse().unwrap();
+        config.network.security.allow_insecure_on_public = true;
+        assert!(config.validate().is_ok());
+    }
+
+    #[test]
+    fn test_validate_requires_ciphers_when_encrypting() {
+        let mut config = NodeConfig::new();
+        config.network.security.ciphers.clear();
+        assert!(config.validate().is_err());
+
+        config.network.security.mode = SecurityMode::Required;
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn test_validate_action_size_against_message_size() {
+        let mut config = NodeConfig::new();
+        config.network.max_message_size = 4096;
+        config.consensus.max_action_size = 4096;
+        assert!(config.validate().unwrap_err().contains("max_action_size"));
+
+        config.consensus.max_action_size = 4096 - MAX_FRAME_OVERHEAD;
+        assert!(config.validate().is_ok());
+
+        config.consensus.max_actions_per_player_per_round = 0;
+        assert!(config.validate().is_err());
+
+        config.consensus.max_actions_per_player_per_round = 10;
+        config.consensus.max_pending_actions = 0;
+        assert!(
+            config
+                .validate()
+                .unwrap_err()
+                .contains("max_pending_actions")
+        );
+    }
+
+    #[test]
+    fn test_with_data_dir_selects_directory_backend() {
+        let dir = tempfile::tempdir().unwrap();
+
+        let config = NodeConfig::turn_based().with_data_dir(dir.path());
+        assert_eq!(
+            config.state.persistence,
+            PersistenceBackend::Directory {
+                path: dir.path().to_path_buf(),
+                fsync: true
+            }
+        );
+        assert!(config.validate().is_ok());
+
+        let config = NodeConfig::lan().with_data_dir(dir.path());
+        assert!(matches!(
+            config.state.persistence,
+            PersistenceBackend::Directory { fsync: false, .. }
+        ));
+        assert_eq!(
+            NodeConfig::new().state.persistence,
+            PersistenceBackend::InMemory
+        );
+    }
+
+    #[test]
+    fn test_validate_rejects_unwritable_directory() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("not-a-dir");
+        std::fs::write(&file, b"").unwrap();
+
+        let config = NodeConfig::new().with_data_dir(file.join("snapshots"));
+        assert!(config.validate().unwrap_err().contains("not writable"));
+
+        let config = NodeConfig::new().with_data_dir(dir.path().join("created/on/validate"));
+        assert!(config.validate().is_ok());
+        assert!(dir.path().join("created/on/validate").is_dir());
+    }
+
+    #[test]
+    fn test_validate_zstd_level() {
+        let mut config = NodeConfig::new();
+        config.network.compression.algorithm = CompressionAlgorithm::Zstd { level: 0 };
+        assert!(config.validate().unwrap_err().contains("zstd"));
+
+        config.network.compression.algorithm = CompressionAlgorithm::Zstd { level: 19 };
+        assert!(config.validate().is_ok());
+    }
+
+    #[test]
+    fn test_from_file_migrates_without_writing() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("node.json");
+        let original = r#"{"bootstrap_server": "seed:9000", "listen_port": 1,
+            "consensus": {"quorum_numerator": 2, "quorum_denominator": 3,
+                "optimistic_execution": true, "consensus_timeout": 5,
+                "max_concurrent_validations": 10},
+            "network": {"max_peers": 5, "heartbeat_interval": 1, "peer_timeout": 10,
+                "max_message_size": 65536, "enable_compression": true},
+            "state": {"snapshot_interval": 10, "max_snapshots_in_memory": 2,
+                "max_action_log_size": 100}}"#;
+        std::fs::write(&path, original).unwrap();
+
+        let config = NodeConfig::from_file(&path).unwrap();
+        assert_eq!(config.config_version, CONFIG_VERSION);
+        assert_eq!(config.bootstrap_servers, vec!["seed:9000"]);
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), original);
+
+        let upgraded = NodeConfig::upgrade_file(&path).unwrap();
+        assert_eq!(upgraded.bootstrap_servers, config.bootstrap_servers);
+        let rewritten = std::fs::read_to_string(&path).unwrap();
+        assert!(rewritten.contains("\"config_version\""));
+        assert!(!rewritten.contains("bootstrap_server\""));
+
+        let reloaded = NodeConfig::from_file(&path).unwrap();
+        assert_eq!(reloaded.network.max_peers, 5);
+    }
+
+    #[test]
+    fn test_from_file_rejects_newer_version() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("node.json");
+        let mut config = NodeConfig::new();
+        config.config_version = CONFIG_VERSION + 1;
+        config.to_file(&path).unwrap();
+
+        match NodeConfig::from_file(&path) {
+            Err(SwarmhostError::Config(msg)) => assert!(msg.contains("newer")),
+            other => panic!("expected config error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_validate_log_config() {
+        let mut config = NodeConfig::new();
+        config.log.level = "swarmhost_core=loud".to_string();
+        assert!(config.validate().unwrap_err().contains("log level"));
+
+        config.log.level = "warn,swarmhost_core::network=trace".to_string();
+        config.log.file = Some(PathBuf::from("node.log"));
+        config.log.max_file_size = 0;
+        assert!(config.validate().unwrap_err().contains("max_file_size"));
+    }
+
+    #[test]
+    fn test_peer_lists_serde_and_validation() {
+        let (alice, bob) = ([0xaa; 32], [0xbb; 32]);
+        let mut config = NodeConfig::new();
+        config.network.allowlist = Some(vec![alice]);
+        config.network.denylist = vec![bob];
+        assert!(config.validate().is_ok());
+
+        let json = serde_json::to_value(&config).unwrap();
+        assert_eq!(json["network"]["denylist"][0], "bb".repeat(32));
+        let parsed: NodeConfig = serde_json::from_value(json).unwrap();
+        assert_eq!(parsed.network.allowlist, Some(vec![alice]));
+        assert_eq!(parsed.network.denylist, vec![bob]);
+
+        config.network.denylist.push(alice);
+        let err = config.validate().unwrap_err();
+        assert!(err.contains("both allowlist and denylist"));
+        assert!(err.contains(&short_id(&alice)));
+
+        let bad = r#"{"network": {"max_peers": 1, "heartbeat_interval": 1, "peer_timeout": 2,
+            "max_message_size": 1024, "denylist": ["nothex"]}}"#;
+        assert!(serde_json::from_str::<NodeConfig>(bad).is_err());
     }
 }
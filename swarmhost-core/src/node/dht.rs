@@ -0,0 +1,441 @@
+// node/dht.rs - Finding peers and games through the DHT
+
+use super::peers::{self, PeerContext};
+use super::{DhtConfig, NodeState};
+use crate::crypto::{PlayerId, short_id};
+use crate::error::{Result, SwarmhostError};
+use crate::network::dht::{
+    self, Insert, Key, Lookup, MAX_PROVIDERS_PER_KEY, ProviderStore, RoutingTable,
+};
+use crate::network::{PeerMessage, PeerRecord};
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use tokio::sync::oneshot;
+use tokio::task::JoinSet;
+use tokio::time::Instant;
+
+/// Our part of the DHT: who we know and whose announcements we keep
+pub(super) struct Dht {
+    table: RoutingTable,
+    providers: ProviderStore,
+}
+
+impl Dht {
+    pub fn new(local_id: PlayerId, config: &DhtConfig, now: Instant) -> Self {
+        Self {
+            table: RoutingTable::new(local_id, config, now),
+            providers: ProviderStore::new(config),
+        }
+    }
+}
+
+/// What a DHT query asks for
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) enum Query {
+    /// The contacts closest to a key
+    Nodes,
+    /// Providers of a key, along with the contacts closest to it
+    Providers,
+}
+
+impl Query {
+    fn message(self, id: u64, key: Key) -> PeerMessage {
+        match self {
+            Query::Nodes => PeerMessage::DhtFindNode { id, target: key },
+            Query::Providers => PeerMessage::DhtFindProviders { id, key },
+        }
+    }
+}
+
+/// An answer to one of our queries
+pub(super) struct Found {
+    closer: Vec<PeerRecord>,
+    providers: Vec<PeerRecord>,
+}
+
+/// Where others can dial us, to tell new peers when the DHT is on
+pub(super) fn hello(state: &NodeState, ctx: &PeerContext) -> Option<PeerMessage> {
+    ctx.dht.as_ref()?;
+    own_addr(state).map(|addr| PeerMessage::DhtHello { addr })
+}
+
+pub(super) fn own_addr(state: &NodeState) -> Option<SocketAddr> {
+    let listener = state.listeners.first()?;
+    Some(
+        state
+            .advertised_addr
+            .unwrap_or_else(|| listener.local_addr()),
+    )
+}
+
+/// Every address we can be dialed at, one per listener, with the
+/// advertised address standing in for the listeners of its family
+pub(super) fn own_addrs(state: &NodeState) -> Vec<SocketAddr> {
+    let mut addrs = Vec::new();
+    for listener in &state.listeners {
+        let addr = match state.advertised_addr {
+            Some(advertised) if advertised.is_ipv4() == listener.local_addr().is_ipv4() => {
+                advertised
+            }
+            _ => listener.local_addr(),
+        };
+        if !addrs.contains(&addr) {
+            addrs.push(addr);
+        }
+    }
+    addrs
+}
+
+/// An address a peer gave for itself; an unspecified IP (a listener on all
+/// interfaces) is replaced with the one its connection comes from
+pub(super) fn reachable(addr: SocketAddr, seen: SocketAddr) -> SocketAddr {
+    if addr.ip().is_unspecified() {
+        SocketAddr::new(seen.ip(), addr.port())
+    } else {
+        addr
+    }
+}
+
+/// [`reachable`] for each address a peer gave; unspecified ones of the
+/// other family than its connection cannot be resolved and are dropped
+pub(super) fn reachable_all(addrs: &[SocketAddr], seen: SocketAddr) -> Vec<SocketAddr> {
+    addrs
+        .iter()
+        .filter(|addr| !addr.ip().is_unspecified() || addr.is_ipv4() == seen.is_ipv4())
+        .map(|addr| reachable(*addr, seen))
+        .collect()
+}
+
+/// A connected peer takes part in the DHT
+///
+/// The first such peer is our way in: we look ourselves up through it to
+/// fill the routing table. A peer landing in a full bucket waits as a
+/// replacement while the bucket's oldest contact is checked.
+pub(super) async fn on_hello(peer: PlayerId, addr: SocketAddr, ctx: &PeerContext) {
+    let Some(dht) = ctx.dht.clone() else {
+        return;
+    };
+    let Some(seen) = ctx
+        .state
+        .read()
+        .await
+        .connections
+        .get(&peer)
+        .map(|handle| handle.info.addr)
+    else {
+        return;
+    };
+    let contact = PeerRecord::at(peer, reachable(addr, seen));
+
+    let (was_empty, inserted) = {
+        let mut dht = dht.lock().await;
+        (dht.table.is_empty(), dht.table.insert(contact))
+    };
+    match inserted {
+        Insert::Added if was_empty => {
+            tracing::debug!("Joining the DHT through {}", short_id(&peer));
+            let ctx = ctx.clone();
+            tokio::spawn(async move {
+                let (closest, _) = lookup(dht::node_key(&ctx.local_id), Query::Nodes, &ctx).await;
+                tracing::debug!("DHT bootstrap found {} contacts", closest.len());
+            });
+        }
+        Insert::Full { oldest } => {
+            let ctx = ctx.clone();
+            tokio::spawn(async move { check_oldest(oldest, &ctx).await });
+        }
+        Insert::Added | Insert::Updated => {}
+    }
+}
+
+/// Keep a bucket's oldest contact if it still answers, or drop it so the
+/// newest replacement takes its place
+async fn check_oldest(oldest: PeerRecord, ctx: &PeerContext) {
+    let Some(dht) = &ctx.dht else {
+        return;
+    };
+    let connected = ctx
+        .state
+        .read()
+        .await
+        .connected_peers
+        .contains(&oldest.player_id);
+    let alive = connected
+        || ask(&oldest, Query::Nodes, dht::node_key(&ctx.local_id), ctx)
+            .await
+            .is_ok();
+
+    let mut dht = dht.lock().await;
+    if alive {
+        dht.table.insert(oldest);
+    } else {
+        tracing::debug!("Dropping DHT contact {}", short_id(&oldest.player_id));
+        dht.table.remove(&oldest.player_id);
+    }
+}
+
+/// Answer a peer's query from our routing table and provider records
+pub(super) async fn on_query(peer: PlayerId, id: u64, key: Key, query: Query, ctx: &PeerContext) {
+    let Some(dht) = &ctx.dht else {
+        return;
+    };
+    let k = ctx.network.borrow().dht.bucket_size;
+    let (mut closer, providers) = {
+        let dht = dht.lock().await;
+        let providers = match query {
+            Query::Nodes => Vec::new(),
+            Query::Providers => dht.providers.get(&key, Instant::now()),
+        };
+        // One extra in case the asker is among them
+        (dht.table.closest(&key, k + 1), providers)
+    };
+    closer.retain(|contact| contact.player_id != peer);
+    closer.truncate(k);
+
+    let found = PeerMessage::DhtFound {
+        id,
+        closer,
+        providers,
+    };
+    peers::send_to(&*ctx.state.read().await, &[peer], found);
+}
+
+/// Hand an answer to the query waiting for it
+pub(super) async fn on_found(
+    peer: PlayerId,
+    id: u64,
+    closer: Vec<PeerRecord>,
+    providers: Vec<PeerRecord>,
+    ctx: &PeerContext,
+) {
+    match ctx.state.write().await.dht_queries.remove(&(peer, id)) {
+        Some(waiting) => {
+            let _ = waiting.send(Found { closer, providers });
+        }
+        None => tracing::debug!(
+            "Ignoring DHT answer {} from {}: timed out or never asked",
+            id,
+            short_id(&peer)
+        ),
+    }
+}
+
+/// Store a peer's announcement that it can be found for `key`
+///
+/// The provider is always the sender, whose id the handshake proved, so no
+/// one can announce on someone else's behalf.
+pub(super) async fn on_provide(peer: PlayerId, key: Key, addr: SocketAddr, ctx: &PeerContext) {
+    let Some(dht) = &ctx.dht else {
+        return;
+    };
+    let Some(seen) = ctx
+        .state
+        .read()
+        .await
+        .connections
+        .get(&peer)
+        .map(|handle| handle.info.addr)
+    else {
+        return;
+    };
+    let provider = PeerRecord::at(peer, reachable(addr, seen));
+    if !dht
+        .lock()
+        .await
+        .providers
+        .add(key, provider, Instant::now())
+    {
+        tracing::debug!("Too many providers to store {}'s", short_id(&peer));
+    }
+}
+
+/// Announce that we play `game_id` to the nodes closest to it, returning how
+/// many were told
+pub(super) async fn provide(game_id: &str, ctx: &PeerContext) -> usize {
+    let Some(dht) = &ctx.dht else {
+        return 0;
+    };
+    let Some(addr) = own_addr(&*ctx.state.read().await) else {
+        return 0;
+    };
+    let key = dht::game_key(game_id);
+    let us = PeerRecord::at(ctx.local_id, addr);
+    dht.lock().await.providers.add(key, us, Instant::now());
+
+    let (closest, _) = lookup(key, Query::Nodes, ctx).await;
+    let targets: Vec<_> = closest.iter().map(|contact| contact.player_id).collect();
+    let provide = PeerMessage::DhtProvide { key, addr };
+    peers::send_to(&*ctx.state.read().await, &targets, provide)
+}
+
+/// Everyone who announced `game_id`, other than us
+pub(super) async fn find_providers(game_id: &str, ctx: &PeerContext) -> Vec<PeerRecord> {
+    let Some(dht) = &ctx.dht else {
+        return Vec::new();
+    };
+    let key = dht::game_key(game_id);
+    let stored = dht.lock().await.providers.get(&key, Instant::now());
+    let (_, found) = lookup(key, Query::Providers, ctx).await;
+
+    let mut providers = HashMap::new();
+    for provider in stored.into_iter().chain(found) {
+        if provider.player_id != ctx.local_id {
+            providers.insert(provider.player_id, provider);
+        }
+    }
+    providers.into_values().collect()
+}
+
+/// Announce that we play `game_id` and, with `search` set, dial the other
+/// players who did; returns how many new peers were connected
+pub(super) async fn join(game_id: &str, search: bool, ctx: &PeerContext) -> usize {
+    if ctx.dht.is_none() {
+        return 0;
+    }
+    let told = provide(game_id, ctx).await;
+    tracing::debug!("Announced {} to {} DHT nodes", game_id, told);
+    if !search {
+        return 0;
+    }
+
+    let found = find_providers(game_id, ctx).await;
+    tracing::info!("Found {} players of {} in the DHT", found.len(), game_id);
+    let dialed = peers::dial_all(ctx.transport.clone(), found, ctx).await;
+    dialed.connected.len()
+}
+
+/// Refresh idle buckets, expire provider records and re-announce our game
+/// until the task is aborted
+pub(super) async fn maintain(ctx: PeerContext) {
+    let Some(dht) = ctx.dht.clone() else {
+        return;
+    };
+    loop {
+        let config = ctx.network.borrow().dht.clone();
+        tokio::time::sleep(config.refresh_interval.min(config.provider_ttl / 2)).await;
+
+        let stale = {
+            let mut dht = dht.lock().await;
+            let now = Instant::now();
+            dht.providers.expire(now);
+            dht.table.stale(config.refresh_interval, now)
+        };
+        for key in stale {
+            lookup(key, Query::Nodes, &ctx).await;
+        }
+
+        let game = ctx.state.read().await.current_game.clone();
+        if let Some(game_id) = game {
+            provide(&game_id, &ctx).await;
+        }
+    }
+}
+
+/// Walk the DHT towards `key`, returning the closest contacts that answered
+/// and, for [`Query::Providers`], the providers they returned
+///
+/// Contacts we are not connected to are dialed; those that fail or time out
+/// are dropped from the routing table.
+async fn lookup(key: Key, query: Query, ctx: &PeerContext) -> (Vec<PeerRecord>, Vec<PeerRecord>) {
+    let Some(dht) = &ctx.dht else {
+        return (Vec::new(), Vec::new());
+    };
+    let config = ctx.network.borrow().dht.clone();
+    let seeds = {
+        let mut dht = dht.lock().await;
+        dht.table.touch(&key, Instant::now());
+        dht.table.closest(&key, config.bucket_size)
+    };
+    let mut search = Lookup::new(key, ctx.local_id, seeds, &config);
+
+    let mut providers = HashMap::new();
+    let mut queries = JoinSet::new();
+    loop {
+        for contact in search.ask_next() {
+            let ctx = ctx.clone();
+            queries.spawn(async move {
+                let answer = ask(&contact, query, key, &ctx).await;
+                (contact, answer)
+            });
+        }
+        let Some(done) = queries.join_next().await else {
+            break;
+        };
+        let Ok((contact, answer)) = done else {
+            continue;
+        };
+        match answer {
+            Ok(found) => {
+                for provider in found.providers.into_iter().take(MAX_PROVIDERS_PER_KEY) {
+                    providers.insert(provider.player_id, provider);
+                }
+                search.respond(&contact.player_id, found.closer);
+                dht.lock().await.table.insert(contact);
+            }
+            Err(e) => {
+                tracing::debug!(
+                    "DHT query to {} failed: {}",
+                    short_id(&contact.player_id),
+                    e
+                );
+                search.fail(&contact.player_id);
+                dht.lock().await.table.remove(&contact.player_id);
+            }
+        }
+        if search.is_finished() {
+            break;
+        }
+    }
+    // Queries still out clean up after themselves when they time out
+    queries.detach_all();
+
+    (search.closest(), providers.into_values().collect())
+}
+
+/// Send one query to `contact`, dialing it first if need be, and wait for
+/// the answer
+async fn ask(contact: &PeerRecord, query: Query, key: Key, ctx: &PeerContext) -> Result<Found> {
+    let peer = contact.player_id;
+    let timeout = ctx.network.borrow().dht.request_timeout;
+    let deadline = Instant::now() + timeout;
+    let timed_out = || SwarmhostError::NoAnswer {
+        peer,
+        after: timeout,
+    };
+
+    let connected = |state: &NodeState| state.connected_peers.contains(&peer);
+    if !connected(&*ctx.state.read().await) {
+        let dial = peers::dial(ctx.transport.as_ref(), &contact.addrs, Some(peer), ctx);
+        let dialed = tokio::time::timeout_at(deadline, dial)
+            .await
+            .map_err(|_| timed_out())?;
+        // Losing a race with the contact dialing us still leaves a connection
+        if let Err(e) = dialed
+            && !connected(&*ctx.state.read().await)
+        {
+            return Err(e);
+        }
+    }
+
+    let (tx, rx) = oneshot::channel();
+    let id = {
+        let mut state = ctx.state.write().await;
+        let id = state.next_request;
+        state.next_request += 1;
+        state.dht_queries.insert((peer, id), tx);
+        if peers::send_to(&state, &[peer], query.message(id, key)) == 0 {
+            state.dht_queries.remove(&(peer, id));
+            return Err(SwarmhostError::NotConnected { peer });
+        }
+        id
+    };
+
+    match tokio::time::timeout_at(deadline, rx).await {
+        Ok(Ok(found)) => Ok(found),
+        Ok(Err(_)) => Err(SwarmhostError::Disconnected { peer }),
+        Err(_) => {
+            ctx.state.write().await.dht_queries.remove(&(peer, id));
+            Err(timed_out())
+        }
+    }
+}
@@ -0,0 +1,223 @@
+// node/divergence.rs - Checking our state hash against each peer's on
+// heartbeats, and finding where the two first differ
+
+use super::peers::{self, PeerContext};
+use super::sync::{self, Answer};
+use super::{NodeEvent, NodeState, sequence};
+use crate::consensus::ActionId;
+use crate::crypto::{Hash, PlayerId, short_id};
+use crate::error::Result;
+use crate::network::{CommitMark, PeerMessage};
+
+/// Action ids up to a divergence kept in its report
+pub const DIVERGENCE_REPORT_ACTIONS: usize = 32;
+
+/// What was known when our state of a game was found to differ from a
+/// peer's, to debug it with
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DivergenceReport {
+    pub game_id: String,
+    pub peer: PlayerId,
+    /// Sequence the hashes were compared at
+    pub sequence: u64,
+    pub ours: Hash,
+    pub theirs: Hash,
+    /// The last `DIVERGENCE_REPORT_ACTIONS` actions we applied up to
+    /// `sequence`, oldest first
+    pub recent: Vec<ActionId>,
+    /// First sequence the states differ at, once found
+    pub first_divergent: Option<u64>,
+}
+
+/// Compare the state hash on a peer's mark, which committed the same
+/// action there as we did, with ours at the same sequence
+///
+/// When they differ, the divergence is reported and a task spawned to
+/// find where it starts, and with `pause_speculation_on_divergence` we
+/// stop speculating on the game. One divergence per game is reported.
+pub(super) async fn check(
+    state: &mut NodeState,
+    theirs: &CommitMark,
+    peer: PlayerId,
+    ctx: &PeerContext,
+) {
+    let Some(their_hash) = theirs.state_hash else {
+        return;
+    };
+    if state.divergences.contains_key(&theirs.game_id) {
+        return;
+    }
+    let pause = ctx
+        .consensus_config
+        .borrow()
+        .pause_speculation_on_divergence;
+    let (ours, recent, reverted) = {
+        let mut state_manager = ctx.state_manager.lock().await;
+        let Some(log) = state_manager.log(&theirs.game_id) else {
+            return;
+        };
+        let Some(ours) = log.hash_through(theirs.sequence) else {
+            return;
+        };
+        if ours == their_hash {
+            return;
+        }
+        let first = theirs
+            .sequence
+            .saturating_sub(DIVERGENCE_REPORT_ACTIONS as u64 - 1);
+        let recent = log.range(first, theirs.sequence).to_vec();
+        let reverted = if pause {
+            state_manager.pause_speculation(&theirs.game_id)
+        } else {
+            Vec::new()
+        };
+        (ours, recent, reverted)
+    };
+    sequence::revert(reverted, ctx);
+    tracing::error!(
+        "State of {} at {} is {} at {}, where ours is {}",
+        theirs.game_id,
+        theirs.sequence,
+        short_id(&their_hash),
+        short_id(&peer),
+        short_id(&ours)
+    );
+    let _ = ctx.events.send(NodeEvent::StateDivergence {
+        game_id: theirs.game_id.clone(),
+        peer,
+        sequence: theirs.sequence,
+        ours,
+        theirs: their_hash,
+    });
+    state.divergences.insert(
+        theirs.game_id.clone(),
+        DivergenceReport {
+            game_id: theirs.game_id.clone(),
+            peer,
+            sequence: theirs.sequence,
+            ours,
+            theirs: their_hash,
+            recent,
+            first_divergent: None,
+        },
+    );
+    let task = tokio::spawn(localize(
+        peer,
+        theirs.game_id.clone(),
+        theirs.sequence,
+        ctx.clone(),
+    ));
+    state.tasks.push(task);
+}
+
+/// Find the first sequence our state of a game differs from `peer`'s at,
+/// record it in the game's report and announce it
+async fn localize(peer: PlayerId, game_id: String, sequence: u64, ctx: PeerContext) {
+    match first_divergent(peer, &game_id, sequence, &ctx).await {
+        Ok(Some(first)) => {
+            tracing::warn!(
+                "State of {} first differs from {}'s at {}",
+                game_id,
+                short_id(&peer),
+                first
+            );
+            if let Some(report) = ctx.state.write().await.divergences.get_mut(&game_id) {
+                report.first_divergent = Some(first);
+            }
+            let _ = ctx.events.send(NodeEvent::DivergenceLocalized {
+                game_id,
+                peer,
+                sequence: first,
+            });
+        }
+        Ok(None) => tracing::warn!(
+            "State of {} differs from {}'s before the oldest hash we can compare",
+            game_id,
+            short_id(&peer)
+        ),
+        Err(e) => tracing::warn!(
+            "Could not find where the state of {} differs from {}'s: {}",
+            game_id,
+            short_id(&peer),
+            e
+        ),
+    }
+}
+
+/// Binary search for the first sequence up to `differing` where our state
+/// hash and `peer`'s differ, asking for its hash at each midpoint
+///
+/// State hashes chain, so once they differ they differ from then on. The
+/// search starts from the oldest hash we hold; none if the peer's differs
+/// there already, or it cannot tell us its own.
+pub(super) async fn first_divergent(
+    peer: PlayerId,
+    game_id: &str,
+    differing: u64,
+    ctx: &PeerContext,
+) -> Result<Option<u64>> {
+    let ours = |sequence: u64| async move {
+        let state_manager = ctx.state_manager.lock().await;
+        state_manager
+            .log(game_id)
+            .and_then(|log| log.hash_through(sequence))
+    };
+    let oldest = {
+        let state_manager = ctx.state_manager.lock().await;
+        state_manager.log(game_id).map_or(0, |log| log.pruned())
+    };
+    let (mut agreed, mut differs) = (oldest, differing);
+    if agreed >= differs {
+        return Ok(None);
+    }
+    match theirs(peer, game_id, agreed, ctx).await? {
+        Some(theirs) if Some(theirs) == ours(agreed).await => {}
+        _ => return Ok(None),
+    }
+    while differs - agreed > 1 {
+        let middle = agreed + (differs - agreed) / 2;
+        match theirs(peer, game_id, middle, ctx).await? {
+            Some(theirs) if Some(theirs) == ours(middle).await => agreed = middle,
+            Some(_) => differs = middle,
+            None => return Ok(None),
+        }
+    }
+    Ok(Some(differs))
+}
+
+/// `peer`'s state hash of a game at `sequence`
+async fn theirs(
+    peer: PlayerId,
+    game_id: &str,
+    sequence: u64,
+    ctx: &PeerContext,
+) -> Result<Option<Hash>> {
+    let fetch = |id| PeerMessage::FetchStateHash {
+        id,
+        game_id: game_id.to_string(),
+        sequence,
+    };
+    match sync::ask(peer, fetch, ctx).await? {
+        Answer::StateHash(state_hash) => Ok(state_hash),
+        _ => Err(sync::unexpected(peer)),
+    }
+}
+
+/// Answer a peer looking for where our states differ with our state hash
+/// at the sequence it names
+pub(super) async fn on_fetch_state_hash(
+    peer: PlayerId,
+    id: u64,
+    game_id: String,
+    sequence: u64,
+    ctx: &PeerContext,
+) {
+    let state_hash = ctx
+        .state_manager
+        .lock()
+        .await
+        .log(&game_id)
+        .and_then(|log| log.hash_through(sequence));
+    let state = ctx.state.read().await;
+    peers::send_to(&state, &[peer], PeerMessage::StateHash { id, state_hash });
+}
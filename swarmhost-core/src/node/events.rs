@@ -0,0 +1,320 @@
+// node/events.rs - Events emitted by a running node
+
+use crate::consensus::{
+    ActionId, Conflict, Decision, Equivocation, ObserverTally, Randomness, RejectCode,
+};
+use crate::crypto::{Hash, PlayerId, short_id};
+use crate::network::{CloseCode, Offense, Priority, RemoteError};
+use bytes::Bytes;
+use std::fmt;
+use std::net::SocketAddr;
+
+/// Notifications published on the node's event channel
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum NodeEvent {
+    /// An action was refused before reaching consensus
+    ActionRejected {
+        action_id: ActionId,
+        actor: PlayerId,
+        reason: RejectionReason,
+    },
+
+    /// A validator refused an action of ours we forwarded it, for the
+    /// reason it sent back
+    ActionRefused {
+        action_id: ActionId,
+        by: PlayerId,
+        error: RemoteError,
+    },
+
+    /// An action was committed at `sequence` in its game's order and applied
+    /// to the action log; every node sees the same actions at the same
+    /// sequence numbers, one event each, in order
+    ActionCommitted {
+        game_id: String,
+        sequence: u64,
+        action_id: ActionId,
+        actor: PlayerId,
+        /// Randomness of the round it was committed in, the same on every
+        /// node and known to none before the round's votes; none for a
+        /// commit made without a certificate
+        round_randomness: Option<Randomness>,
+    },
+
+    /// An action of a [`Causal`](crate::consensus::OrderingClass::Causal) or
+    /// [`Unordered`](crate::consensus::OrderingClass::Unordered) type passed
+    /// our checks and was delivered without a sequence; a causal one after
+    /// every action it depends on
+    ActionDelivered {
+        game_id: String,
+        action_id: ActionId,
+        actor: PlayerId,
+    },
+
+    /// One of our own actions was committed at `sequence`; follows its
+    /// [`ActionCommitted`](Self::ActionCommitted)
+    ActionReceipt { action_id: ActionId, sequence: u64 },
+
+    /// With optimistic execution, a proposed action was applied to the
+    /// speculative state ahead of its commit
+    ActionSpeculated {
+        game_id: String,
+        action_id: ActionId,
+        actor: PlayerId,
+    },
+
+    /// A speculatively applied action was rolled back, because consensus
+    /// rejected it or ordered another action first; undo its effects. It
+    /// is re-applied straight away unless it was rejected
+    SpeculationReverted { action_id: ActionId },
+
+    /// A peer completed the handshake
+    PeerConnected { peer: PlayerId, addr: SocketAddr },
+
+    /// A connected peer was dropped; [`CloseCode::Evicted`] when it made
+    /// room at `max_peers`. When the peer closed the connection itself, the
+    /// reason it gave: [`CloseCode::Leaving`], [`CloseCode::Shutdown`],
+    /// [`CloseCode::Kicked`] and so on
+    ///
+    /// Fellow validators, and peers holding a resumption token, whose
+    /// connection fails are not reported here: they keep their place for
+    /// `reconnect.window` or `resumption.grace`, ending in
+    /// [`PeerReconnected`](Self::PeerReconnected) or [`PeerLost`](Self::PeerLost).
+    PeerDisconnected { peer: PlayerId, reason: CloseCode },
+
+    /// A peer whose connection dropped is back, with the messages queued
+    /// for it in the meantime
+    PeerReconnected { peer: PlayerId, addr: SocketAddr },
+
+    /// A peer whose connection dropped did not come back in time, or came
+    /// back without a valid resumption token, and was removed
+    PeerLost { peer: PlayerId },
+
+    /// A connecting peer was refused for lack of room: [`CloseCode::Busy`]
+    /// at `max_peers`
+    PeerRejected { peer: PlayerId, reason: CloseCode },
+
+    /// A peer was penalized; `demerits` is its decayed total, rounded
+    PeerScoreChanged {
+        peer: PlayerId,
+        offense: Offense,
+        demerits: u32,
+    },
+
+    /// A peer sent us an application message; `game_id` is set when it was
+    /// broadcast to a game we are playing
+    Message {
+        from: PlayerId,
+        game_id: Option<String>,
+        class: Priority,
+        payload: Bytes,
+    },
+
+    /// A peer made a request; answer it with
+    /// [`NetworkHandle::respond`](super::NetworkHandle::respond)
+    Request {
+        from: PlayerId,
+        id: u64,
+        payload: Bytes,
+    },
+
+    /// Validators weighing less than a quorum (counting ourselves) have
+    /// been reachable for `quorum_loss_timeouts` consensus timeouts; the
+    /// session is degraded and local actions are refused or held until
+    /// [`QuorumRestored`](Self::QuorumRestored)
+    QuorumLost { reachable: u64, required: u64 },
+
+    /// A quorum of validators is reachable again; held actions are sent
+    QuorumRestored { reachable: u64, required: u64 },
+
+    /// The validators still reachable in `game_id` went on alone under the
+    /// grace rule from sequence `from`, every one of them needed for a
+    /// quorum until the validators change again
+    GraceEpochEntered {
+        game_id: String,
+        from: u64,
+        validators: Vec<PlayerId>,
+    },
+
+    /// Catching up on `game_id`, joined in progress: commits up to `have`
+    /// of the `need` a peer reported are applied here
+    SyncProgress {
+        game_id: String,
+        have: u64,
+        need: u64,
+    },
+
+    /// Catching up on `game_id`: `received` of the `size` bytes of the
+    /// snapshot at `sequence` are fetched and checked
+    SnapshotProgress {
+        game_id: String,
+        sequence: u64,
+        received: u64,
+        size: u64,
+    },
+
+    /// Caught up on `game_id` through `sequence`; from now on the node
+    /// votes
+    SessionReady { game_id: String, sequence: u64 },
+
+    /// The log of `game_id` up to `sequence` was snapshotted, `size` bytes
+    /// of it, every `snapshot_interval` commits
+    SnapshotCreated {
+        game_id: String,
+        sequence: u64,
+        size: usize,
+    },
+
+    /// A quorum of validators signed the log of `game_id` up to `sequence`,
+    /// which no later view may reorder; see
+    /// [`SwarmhostNode::latest_checkpoint`](super::SwarmhostNode::latest_checkpoint)
+    Checkpointed { game_id: String, sequence: u64 },
+
+    /// Rounds kept failing and a quorum of validators moved to `view`;
+    /// `leader` numbers committed actions from now on
+    ViewChanged { view: u64, leader: PlayerId },
+
+    /// The proposer of `round` put `actions` forward as one block; approve
+    /// them all at once with
+    /// [`SwarmhostNode::vote_block`](super::SwarmhostNode::vote_block)
+    BlockProposed {
+        round: u64,
+        block: Hash,
+        actions: Vec<ActionId>,
+    },
+
+    /// In [ticked](super::RoundMode::Ticked) rounds, the round of `tick`
+    /// is over and `actions` were committed in it, in order, each after its
+    /// [`ActionCommitted`](Self::ActionCommitted); none when the tick was
+    /// empty. Every node sees the same ticks in turn, so the game advances
+    /// its simulation one tick for each
+    TickCommitted { tick: u64, actions: Vec<ActionId> },
+
+    /// Actions in the proposal of `round` touched the same entities; sent
+    /// before any of them is rejected or committed, so a game validating
+    /// them knows which conflicts its policy resolved
+    ConflictsDetected {
+        round: u64,
+        conflicts: Vec<Conflict>,
+    },
+
+    /// `observer`, which is not a validator, voted `decision` on an action
+    /// or block; `tally` is where observers stand on it now. It has no
+    /// bearing on the quorum, only on whether neutral observers agree with it
+    ObserverVoted {
+        action_id: ActionId,
+        observer: PlayerId,
+        decision: Decision,
+        tally: ObserverTally,
+    },
+
+    /// `offender` signed two conflicting messages and was ejected from the
+    /// validators of the game; `evidence` proves it to anyone
+    Misbehavior {
+        offender: PlayerId,
+        evidence: Box<Equivocation>,
+    },
+
+    /// `peer` committed `theirs` at `sequence` of `game_id`, where we
+    /// committed `ours`; no more commits of the game are applied until the
+    /// fork is resolved
+    ForkDetected {
+        game_id: String,
+        sequence: u64,
+        ours: ActionId,
+        theirs: ActionId,
+        peer: PlayerId,
+    },
+
+    /// The log of `game_id` was rolled back from sequence `from` to `to`,
+    /// the last commit kept, to take up another branch after it
+    StateRolledBack { game_id: String, from: u64, to: u64 },
+
+    /// The fork at `sequence` of `game_id` was resolved for the branch a
+    /// quorum certified. When that was the peer's, the actions in
+    /// `rolled_back` were taken out of the log, from `sequence` on; undo
+    /// their effects, as the other branch's are committed in their place
+    ForkResolved {
+        game_id: String,
+        sequence: u64,
+        rolled_back: Vec<ActionId>,
+    },
+
+    /// Both branches of the fork at `sequence` of `game_id` were certified,
+    /// proving two quorums that share no honest validator, or neither was;
+    /// the session is halted and applies no more commits until an operator
+    /// has the node leave the game
+    SessionHalted { game_id: String, sequence: u64 },
+
+    /// `peer` committed the same action at `sequence` of `game_id` as we
+    /// did, but its state hash there is `theirs` where ours is `ours`: one
+    /// of us applied the same actions differently. A
+    /// [report](super::SwarmhostNode::divergence_report) is kept, and the
+    /// first sequence the states differ at looked for
+    StateDivergence {
+        game_id: String,
+        peer: PlayerId,
+        sequence: u64,
+        ours: Hash,
+        theirs: Hash,
+    },
+
+    /// The state of `game_id` first differs from `peer`'s at `sequence`
+    DivergenceLocalized {
+        game_id: String,
+        peer: PlayerId,
+        sequence: u64,
+    },
+}
+
+/// How waiting for one of our actions to be committed ended
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CommitOutcome {
+    /// Committed at `sequence` in its game's log
+    Committed { sequence: u64 },
+    /// Its deadline passed first, and it will not be committed
+    Expired,
+}
+
+/// Why an action was refused
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RejectionReason {
+    /// Payload larger than `max_action_size`
+    Oversized { size: usize, max: usize },
+    /// Actor exceeded `max_actions_per_player_per_round`
+    RateLimited { limit: u32 },
+    /// Signature did not verify against the actor's key
+    InvalidSignature,
+    /// Another action in the same round touched the same entity, and the
+    /// game's conflict policy let it stand over this one
+    Conflict { with: ActionId },
+    /// Its deadline passed before it could be committed
+    Expired { deadline_ms: u64 },
+    /// One of our checks failed on an action delivered without a sequence
+    Invalid { code: RejectCode },
+}
+
+impl fmt::Display for RejectionReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RejectionReason::Oversized { size, max } => {
+                write!(
+                    f,
+                    "action of {} bytes exceeds max_action_size {}",
+                    size, max
+                )
+            }
+            RejectionReason::RateLimited { limit } => {
+                write!(f, "more than {} actions this round", limit)
+            }
+            RejectionReason::InvalidSignature => write!(f, "invalid signature"),
+            RejectionReason::Conflict { with } => {
+                write!(f, "conflicts with {}", short_id(with))
+            }
+            RejectionReason::Expired { deadline_ms } => {
+                write!(f, "deadline {} ms passed", deadline_ms)
+            }
+            RejectionReason::Invalid { code } => write!(f, "{}", code),
+        }
+    }
+}
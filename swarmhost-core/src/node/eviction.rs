@@ -0,0 +1,161 @@
+// node/eviction.rs - Choosing which connection to drop at max_peers
+
+use super::NodeState;
+use crate::crypto::PlayerId;
+use std::cmp::Reverse;
+use std::collections::HashSet;
+use std::time::Duration;
+
+/// What a connection is worth to us, least first
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum PeerRole {
+    /// Not playing our game, e.g. a lobby peer or a relay
+    Spectator,
+    /// Playing our game without validating it
+    Player,
+    /// In the validator set of our game
+    Validator,
+}
+
+/// A connected or connecting peer as the eviction policy sees it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PeerStanding {
+    pub player_id: PlayerId,
+    pub role: PeerRole,
+    /// Smoothed round-trip time; `None` for a peer still connecting or not
+    /// yet measured
+    pub rtt: Option<Duration>,
+}
+
+/// Decides who gets a connection slot once `max_peers` is reached
+///
+/// Set with [`SwarmhostNode::with_eviction_policy`](super::SwarmhostNode::with_eviction_policy).
+/// A connecting peer's role is known from the validator set alone, as it has
+/// not yet said which game it plays.
+pub trait EvictionPolicy: Send + Sync {
+    /// The connection to drop to make room for `candidate`, or `None` to
+    /// refuse it as busy
+    fn select(&self, candidate: &PeerStanding, connected: &[PeerStanding]) -> Option<PlayerId>;
+}
+
+/// The default policy: only validators displace anyone, and they displace
+/// spectators first, then the non-validator with the highest latency
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ValidatorsFirst;
+
+impl EvictionPolicy for ValidatorsFirst {
+    fn select(&self, candidate: &PeerStanding, connected: &[PeerStanding]) -> Option<PlayerId> {
+        if candidate.role != PeerRole::Validator {
+            return None;
+        }
+        connected
+            .iter()
+            .filter(|peer| peer.role != PeerRole::Validator)
+            // Unmeasured peers count as slowest: they have shown us nothing
+            .min_by_key(|peer| (peer.role, Reverse(peer.rtt.unwrap_or(Duration::MAX))))
+            .map(|peer| peer.player_id)
+    }
+}
+
+/// Everyone holding a slot, taken when `max_peers` is reached
+pub(super) struct Crowd {
+    validators: HashSet<PlayerId>,
+    connected: Vec<PeerStanding>,
+}
+
+impl Crowd {
+    pub fn of(state: &NodeState) -> Self {
+        let game = state.current_game.as_deref();
+        let connected = state
+            .connected_peers
+            .iter()
+            .filter_map(|peer| state.connections.get(peer))
+            .map(|handle| {
+                let role = if state.validators.contains(&handle.info.player_id) {
+                    PeerRole::Validator
+                } else if game.is_some() && handle.info.game.as_deref() == game {
+                    PeerRole::Player
+                } else {
+                    PeerRole::Spectator
+                };
+                PeerStanding {
+                    player_id: handle.info.player_id,
+                    role,
+                    rtt: handle.info.rtt,
+                }
+            })
+            .collect();
+        Self {
+            validators: state.validators.players(),
+            connected,
+        }
+    }
+
+    /// The connection `policy` would drop for `peer`
+    pub fn victim(&self, policy: &dyn EvictionPolicy, peer: &PlayerId) -> Option<PlayerId> {
+        let role = if self.validators.contains(peer) {
+            PeerRole::Validator
+        } else {
+            PeerRole::Spectator
+        };
+        let candidate = PeerStanding {
+            player_id: *peer,
+            role,
+            rtt: None,
+        };
+        policy
+            .select(&candidate, &self.connected)
+            .filter(|victim| self.connected.iter().any(|p| p.player_id == *victim))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn standing(id: u8, role: PeerRole, rtt_ms: Option<u64>) -> PeerStanding {
+        PeerStanding {
+            player_id: [id; 32],
+            role,
+            rtt: rtt_ms.map(Duration::from_millis),
+        }
+    }
+
+    #[test]
+    fn test_validators_displace_spectators_then_slowest_player() {
+        let validator = standing(9, PeerRole::Validator, None);
+        let mut connected = vec![
+            standing(1, PeerRole::Player, Some(300)),
+            standing(2, PeerRole::Spectator, Some(10)),
+            standing(3, PeerRole::Player, Some(40)),
+            standing(4, PeerRole::Validator, Some(900)),
+        ];
+        assert_eq!(
+            ValidatorsFirst.select(&validator, &connected),
+            Some([2; 32])
+        );
+
+        connected.remove(1);
+        assert_eq!(
+            ValidatorsFirst.select(&validator, &connected),
+            Some([1; 32])
+        );
+        connected.push(standing(5, PeerRole::Player, None));
+        assert_eq!(
+            ValidatorsFirst.select(&validator, &connected),
+            Some([5; 32])
+        );
+
+        connected.retain(|peer| peer.role == PeerRole::Validator);
+        assert_eq!(ValidatorsFirst.select(&validator, &connected), None);
+    }
+
+    #[test]
+    fn test_non_validators_never_displace_anyone() {
+        let connected = vec![standing(1, PeerRole::Spectator, Some(500))];
+        for role in [PeerRole::Spectator, PeerRole::Player] {
+            let candidate = standing(9, role, None);
+            assert_eq!(ValidatorsFirst.select(&candidate, &connected), None);
+        }
+    }
+}
@@ -0,0 +1,25 @@
+// node/evidence.rs - Spreading proof that a validator equivocated, and
+// ejecting it from the game's validators
+
+use super::peers::{self, PeerContext};
+use crate::network::GossipPayload;
+
+/// Gossip the evidence consensus found while taking in messages, so honest
+/// peers eject the offenders too
+pub(super) async fn publish(ctx: &PeerContext) {
+    let found = ctx.consensus.lock().await.take_evidence();
+    if found.is_empty() {
+        return;
+    }
+    eject(ctx).await;
+    for evidence in found {
+        peers::publish(GossipPayload::Evidence(Box::new(evidence)), None, ctx).await;
+    }
+}
+
+/// Stop counting ejected validators among those of the game, as consensus
+/// already has
+pub(super) async fn eject(ctx: &PeerContext) {
+    let validators = ctx.consensus.lock().await.validators().clone();
+    ctx.state.write().await.validators = validators;
+}
@@ -0,0 +1,213 @@
+// node/fork.rs - Checking our log against each peer's on heartbeats, and
+// resolving a fork for the branch a quorum certified
+
+use super::peers::PeerContext;
+use super::sync::{self, Answer};
+use super::{NodeEvent, checkpoint, divergence, ordering, rotation, sequence};
+use crate::consensus::{ConsensusManager, Resolution};
+use crate::crypto::{PlayerId, short_id};
+use crate::error::Result;
+use crate::network::{CommitMark, PeerMessage};
+use crate::state::{ActionLog, StateManager};
+use tokio::time::Instant;
+
+/// Mark a ping with our latest commit in the game we play
+pub(super) async fn stamp(ping: &mut PeerMessage, ctx: &PeerContext) {
+    let PeerMessage::Ping { mark, .. } = ping else {
+        return;
+    };
+    let Some(game_id) = ctx.state.read().await.current_game.clone() else {
+        return;
+    };
+    let consensus = ctx.consensus.lock().await;
+    let sequence = consensus.committed(&game_id);
+    let state_manager = ctx.state_manager.lock().await;
+    *mark = mark_at(&consensus, &state_manager, game_id, sequence);
+}
+
+/// Check the commit a peer's ping is marked with against ours, returning
+/// ours at the same sequence, or our latest if we are behind, to mark the
+/// pong with
+pub(super) async fn answer(
+    ping: &PeerMessage,
+    peer: PlayerId,
+    ctx: &PeerContext,
+) -> Option<CommitMark> {
+    let PeerMessage::Ping {
+        mark: Some(theirs), ..
+    } = ping
+    else {
+        return None;
+    };
+    if !check(theirs, peer, ctx).await {
+        return None;
+    }
+    let consensus = ctx.consensus.lock().await;
+    let sequence = consensus.committed(&theirs.game_id).min(theirs.sequence);
+    let state_manager = ctx.state_manager.lock().await;
+    mark_at(&consensus, &state_manager, theirs.game_id.clone(), sequence)
+}
+
+/// Compare a peer's commit with ours at the same sequence, returning
+/// whether it is of the game we play
+///
+/// When they differ, the game is frozen, the fork reported, and a task
+/// spawned to resolve it; one fork per game is looked into at a time.
+/// When they agree, the state hashes at that sequence are compared too.
+pub(super) async fn check(theirs: &CommitMark, peer: PlayerId, ctx: &PeerContext) -> bool {
+    let mut state = ctx.state.write().await;
+    if state.current_game.as_deref() != Some(theirs.game_id.as_str()) {
+        return false;
+    }
+    let ours = {
+        let mut consensus = ctx.consensus.lock().await;
+        let Some(ours) = consensus
+            .delivered_at(&theirs.game_id, theirs.sequence)
+            .map(|commit| commit.action.id())
+        else {
+            return true;
+        };
+        if ours == theirs.action_id {
+            drop(consensus);
+            divergence::check(&mut state, theirs, peer, ctx).await;
+            return true;
+        }
+        if !consensus.freeze(&theirs.game_id, theirs.sequence) {
+            return true;
+        }
+        ours
+    };
+    tracing::warn!(
+        "{} committed {} at {} of {}, where we committed {}",
+        short_id(&peer),
+        short_id(&theirs.action_id),
+        theirs.sequence,
+        theirs.game_id,
+        short_id(&ours)
+    );
+    let _ = ctx.events.send(NodeEvent::ForkDetected {
+        game_id: theirs.game_id.clone(),
+        sequence: theirs.sequence,
+        ours,
+        theirs: theirs.action_id,
+        peer,
+    });
+    let task = tokio::spawn(resolve(
+        peer,
+        theirs.game_id.clone(),
+        theirs.sequence,
+        ctx.clone(),
+    ));
+    state.tasks.push(task);
+    true
+}
+
+/// Our commit at `sequence` of a game, if history reaches it
+fn mark_at(
+    consensus: &ConsensusManager,
+    state_manager: &StateManager,
+    game_id: String,
+    sequence: u64,
+) -> Option<CommitMark> {
+    let action_id = consensus.delivered_at(&game_id, sequence)?.action.id();
+    let state_hash = state_manager
+        .log(&game_id)
+        .and_then(|log| log.hash_through(sequence));
+    Some(CommitMark {
+        game_id,
+        sequence,
+        action_id,
+        state_hash,
+    })
+}
+
+/// Settle the fork of `game_id` at `sequence` against `peer`'s branch
+///
+/// If the peer cannot tell us its branch, we go on with ours; the next
+/// heartbeat finds the fork again if it is still there.
+async fn resolve(peer: PlayerId, game_id: String, sequence: u64, ctx: PeerContext) {
+    if let Err(e) = settle(peer, &game_id, sequence, &ctx).await {
+        tracing::warn!(
+            "Could not resolve the fork of {} at {} with {}: {}",
+            game_id,
+            sequence,
+            short_id(&peer),
+            e
+        );
+        let mut consensus = ctx.consensus.lock().await;
+        let delivered = consensus.thaw(&game_id, Instant::now());
+        if let Err(e) = sequence::deliver(delivered, &ctx).await {
+            tracing::warn!("Could not apply the commits held during the fork: {}", e);
+        }
+    }
+    ordering::release(&ctx).await;
+    checkpoint::publish(&ctx).await;
+    sequence::reweigh(&ctx).await;
+    rotation::propose(&ctx).await;
+}
+
+/// Fetch the peer's commit at the fork with its certificate, and keep the
+/// branch a quorum certified, or halt the game if that is not one of them
+async fn settle(peer: PlayerId, game_id: &str, sequence: u64, ctx: &PeerContext) -> Result<()> {
+    let fetch = |id| PeerMessage::FetchCertified {
+        id,
+        game_id: game_id.to_string(),
+        from: sequence,
+        count: 1,
+    };
+    let Answer::Commits(theirs) = sync::ask(peer, fetch, ctx).await? else {
+        return Err(sync::unexpected(peer));
+    };
+    let resolved = |rolled_back| {
+        let _ = ctx.events.send(NodeEvent::ForkResolved {
+            game_id: game_id.to_string(),
+            sequence,
+            rolled_back,
+        });
+    };
+    let mut consensus = ctx.consensus.lock().await;
+    match consensus.resolve_fork(game_id, theirs, Instant::now())? {
+        Resolution::Kept { delivered } => {
+            tracing::info!("Keeping our branch of {} at {}", game_id, sequence);
+            resolved(Vec::new());
+            sequence::deliver(delivered, ctx).await
+        }
+        Resolution::Adopted {
+            rolled_back,
+            delivered,
+        } => {
+            tracing::warn!(
+                "Taking up the branch of {} at {} from {}, rolling back {} commits",
+                game_id,
+                sequence,
+                short_id(&peer),
+                rolled_back.len()
+            );
+            let (from, reverted) = {
+                let mut state_manager = ctx.state_manager.lock().await;
+                let from = state_manager.log(game_id).map_or(0, ActionLog::sequence);
+                (from, state_manager.rollback_to(game_id, sequence - 1)?)
+            };
+            sequence::revert(reverted, ctx);
+            let _ = ctx.events.send(NodeEvent::StateRolledBack {
+                game_id: game_id.to_string(),
+                from,
+                to: sequence - 1,
+            });
+            resolved(
+                rolled_back
+                    .iter()
+                    .map(|commit| commit.action.id())
+                    .collect(),
+            );
+            sequence::deliver(delivered, ctx).await
+        }
+        Resolution::Halted => {
+            let _ = ctx.events.send(NodeEvent::SessionHalted {
+                game_id: game_id.to_string(),
+                sequence,
+            });
+            Ok(())
+        }
+    }
+}
@@ -0,0 +1,113 @@
+// node/grace.rs - Letting the validators still reachable go on alone once a
+// quorum has been out of reach for the grace period
+
+use super::peers::{self, PeerContext};
+use super::{ConsensusConfig, partition, recovery, sequence};
+use crate::consensus::{ActionId, Decision, GRACE_EPOCH_ACTION, GraceEpoch, SignedAction, Vote};
+use crate::crypto::short_id;
+use crate::network::GossipPayload;
+use tokio::time::Instant;
+
+/// Approve the grace epoch naming the validators reachable right now, once
+/// the session has been degraded for `grace_period` and at least
+/// `grace_min_validators` of them, counting us, are left; the first of them
+/// in order of id puts it forward
+///
+/// A validator that sees other validators reachable names other ones, and
+/// its approval counts for nothing until they agree. The epoch takes effect
+/// with the last approval, and is committed like any action.
+pub(super) async fn check(config: &ConsensusConfig, ctx: &PeerContext) {
+    if !config.quorum_grace {
+        return;
+    }
+    let (game_id, grace) = {
+        let state = ctx.state.read().await;
+        let waited = state
+            .partition
+            .degraded_for(Instant::now())
+            .is_some_and(|degraded| degraded >= config.grace_period);
+        let Some(game_id) = state.current_game.clone().filter(|_| waited) else {
+            return;
+        };
+        let live = partition::reachable_validators(&state);
+        if live.len() < config.grace_min_validators || !live.contains(&ctx.local_id) {
+            return;
+        }
+        (game_id, GraceEpoch { validators: live })
+    };
+    let proposed = ctx
+        .consensus
+        .lock()
+        .await
+        .pending()
+        .iter()
+        .find(|action| {
+            action.game_id == game_id
+                && GraceEpoch::of(action).and_then(Result::ok).as_ref() == Some(&grace)
+        })
+        .map(SignedAction::id);
+    let action_id = match proposed {
+        Some(action_id) => action_id,
+        None if grace.validators[0] == ctx.local_id => put_forward(game_id, &grace, ctx).await,
+        None => return,
+    };
+    approve(action_id, ctx).await;
+}
+
+/// Sign a grace epoch action with our next nonce and gossip it
+async fn put_forward(game_id: String, grace: &GraceEpoch, ctx: &PeerContext) -> ActionId {
+    let nonce = {
+        let mut state = ctx.state.write().await;
+        let nonce = state.next_nonce;
+        state.next_nonce += 1;
+        nonce
+    };
+    let action = SignedAction::new(
+        &ctx.keypair,
+        game_id,
+        nonce,
+        GRACE_EPOCH_ACTION,
+        grace.encode(),
+    );
+    let action_id = action.id();
+    tracing::warn!(
+        "Quorum out of reach for the grace period, proposing that {} validators go on alone",
+        grace.validators.len()
+    );
+    if let Err(e) = ctx.consensus.lock().await.submit_local(action.clone()) {
+        tracing::warn!("Could not put the grace epoch forward: {}", e);
+    }
+    peers::publish(GossipPayload::Proposal(action), None, ctx).await;
+    action_id
+}
+
+/// Approve a grace epoch action unless we already did, persisting the vote
+/// before gossiping it
+async fn approve(action_id: ActionId, ctx: &PeerContext) {
+    let vote = {
+        let mut consensus = ctx.consensus.lock().await;
+        if consensus.has_vote(&action_id, &ctx.local_id) {
+            return;
+        }
+        let vote = Vote::new(
+            &ctx.keypair,
+            action_id,
+            consensus.round(),
+            Decision::Approve,
+        );
+        let vote = match consensus.cast_vote(vote) {
+            Ok((vote, _)) => vote,
+            Err(e) => {
+                tracing::debug!("Not approving grace epoch {}: {}", short_id(&action_id), e);
+                return;
+            }
+        };
+        if let Err(e) = recovery::write_ahead(&consensus, &ctx.state_manager).await {
+            tracing::error!("Could not persist our safety record, not approving: {}", e);
+            return;
+        }
+        vote
+    };
+    peers::publish(GossipPayload::Vote(vote), None, ctx).await;
+    sequence::settle(action_id, None, ctx).await;
+}
@@ -0,0 +1,147 @@
+// node/handle.rs - Sending application messages to peers
+
+use super::NodeState;
+use super::peers;
+use crate::crypto::{PlayerId, short_id};
+use crate::error::{Result, SwarmhostError};
+use crate::network::outbound::SendError;
+use crate::network::{CloseCode, PeerMessage, Priority, TraceContext};
+use bytes::Bytes;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::{RwLock, oneshot};
+
+/// Sends application payloads to connected peers
+///
+/// With `trace_messages` set, each message gets a correlation id of its own,
+/// and a response its request's. Cheap to clone; obtained from [`SwarmhostNode::network`](super::SwarmhostNode::network).
+/// Messages from peers arrive as [`NodeEvent::Message`](super::NodeEvent::Message)
+/// and [`NodeEvent::Request`](super::NodeEvent::Request).
+#[derive(Clone)]
+pub struct NetworkHandle {
+    state: Arc<RwLock<NodeState>>,
+}
+
+impl NetworkHandle {
+    pub(super) fn new(state: Arc<RwLock<NodeState>>) -> Self {
+        Self { state }
+    }
+
+    /// Queue `payload` for every connected peer playing `game_id`, returning
+    /// how many it was queued for
+    ///
+    /// Peers whose queue for `class` is full are skipped, like gossip.
+    pub async fn broadcast(&self, game_id: &str, class: Priority, payload: Bytes) -> usize {
+        let state = self.state.read().await;
+        let targets: Vec<_> = state
+            .connected_peers
+            .iter()
+            .filter(|peer| {
+                state
+                    .connections
+                    .get(*peer)
+                    .is_some_and(|handle| handle.info.game.as_deref() == Some(game_id))
+            })
+            .copied()
+            .collect();
+        let message = PeerMessage::Broadcast {
+            game_id: game_id.to_string(),
+            class,
+            payload: payload.to_vec(),
+        };
+        let trace = state.traces.is_some().then(|| TraceContext::new(None));
+        peers::send_to(&state, &targets, message.traced(trace))
+    }
+
+    /// Queue `payload` for one connected peer
+    ///
+    /// Bulk payloads wait for room in the peer's queue; fails if the peer is
+    /// not connected.
+    pub async fn send_to(&self, peer: PlayerId, class: Priority, payload: Bytes) -> Result<()> {
+        let message = PeerMessage::Direct {
+            class,
+            payload: payload.to_vec(),
+        };
+        self.enqueue(peer, message).await
+    }
+
+    /// Send `payload` as a request and wait up to `timeout` for the answer
+    ///
+    /// Each request gets its own id, so concurrent requests to one peer are
+    /// never confused. A response arriving after the timeout is dropped.
+    pub async fn request(
+        &self,
+        peer: PlayerId,
+        payload: Bytes,
+        timeout: Duration,
+    ) -> Result<Bytes> {
+        let (tx, rx) = oneshot::channel();
+        let id = {
+            let mut state = self.state.write().await;
+            let id = state.next_request;
+            state.next_request += 1;
+            state.requests.insert((peer, id), tx);
+            id
+        };
+
+        let message = PeerMessage::Request {
+            id,
+            payload: payload.to_vec(),
+        };
+        let result = match self.enqueue(peer, message).await {
+            Ok(()) => match tokio::time::timeout(timeout, rx).await {
+                Ok(Ok(response)) => Ok(response),
+                Ok(Err(_)) => Err(SwarmhostError::Disconnected { peer }),
+                Err(_) => Err(SwarmhostError::NoAnswer {
+                    peer,
+                    after: timeout,
+                }),
+            },
+            Err(e) => Err(e),
+        };
+        if result.is_err() {
+            self.state.write().await.requests.remove(&(peer, id));
+        }
+        result
+    }
+
+    /// Answer request `id` from `peer`
+    pub async fn respond(&self, peer: PlayerId, id: u64, payload: Bytes) -> Result<()> {
+        let message = PeerMessage::Response {
+            id,
+            payload: payload.to_vec(),
+        };
+        let trace = {
+            let mut state = self.state.write().await;
+            state
+                .traces
+                .as_mut()
+                .map(|traces| traces.response(peer, id))
+        };
+        self.enqueue(peer, message.traced(trace)).await
+    }
+
+    /// Queue `message` for `peer`, under a fresh trace unless it has one
+    async fn enqueue(&self, peer: PlayerId, mut message: PeerMessage) -> Result<()> {
+        let outbound = {
+            let state = self.state.read().await;
+            if state.traces.is_some() && message.trace().is_none() {
+                message = message.traced(Some(TraceContext::new(None)));
+            }
+            match state.connections.get(&peer) {
+                Some(handle) => handle.outbound.clone(),
+                None => {
+                    return Err(SwarmhostError::NotConnected { peer });
+                }
+            }
+        };
+        let sent = outbound.send(message).await;
+        if sent == Err(SendError::ControlOverflow) {
+            tracing::warn!("{} is not draining control traffic", short_id(&peer));
+            if let Some(handle) = self.state.read().await.connections.get(&peer) {
+                let _ = handle.close.send(Some(CloseCode::Overloaded));
+            }
+        }
+        sent.map_err(|source| SwarmhostError::SendFailed { peer, source })
+    }
+}
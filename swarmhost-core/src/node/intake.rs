@@ -0,0 +1,100 @@
+// node/intake.rs - Keeping the actions taken in bounded: local submits wait
+// for room, and a full proposer answers forwards busy so their senders hold
+// off
+
+use super::NodeState;
+use super::peers::{self, PeerContext};
+use crate::consensus::SignedAction;
+use crate::crypto::{PlayerId, short_id};
+use crate::error::{Result, SwarmhostError};
+use crate::network::PeerMessage;
+use std::time::Duration;
+use tokio::sync::broadcast;
+use tokio::time::Instant;
+
+/// How long a full proposer asks forwarders to hold off
+pub(super) const BUSY_RETRY_AFTER: Duration = Duration::from_millis(100);
+
+/// Longest a busy answer makes us hold off, whatever it asks
+const MAX_BUSY_BACKOFF: Duration = Duration::from_secs(5);
+
+/// Whether the proposer answered busy and we are still holding off
+pub(super) fn is_paced(state: &NodeState, now: Instant) -> bool {
+    state.paced_until.is_some_and(|until| until > now)
+}
+
+/// Wait until another local action may be taken in: the proposer is not
+/// holding us off, and fewer than `max_pending_actions` are pending, or
+/// held while degraded
+///
+/// Any decision may make room, so each event wakes us to look again; once
+/// `submit_wait` has passed without room, the action is refused as
+/// overloaded.
+pub(super) async fn wait_for_room(ctx: &PeerContext) -> Result<()> {
+    let mut events = ctx.events.subscribe();
+    let (max_pending, submit_wait) = {
+        let config = ctx.consensus_config.borrow();
+        (config.max_pending_actions, config.submit_wait)
+    };
+    let give_up = Instant::now() + submit_wait;
+    loop {
+        let now = Instant::now();
+        let (full, paced_until) = {
+            let state = ctx.state.read().await;
+            let full =
+                state.held_actions.len() >= max_pending || ctx.consensus.lock().await.is_full();
+            (full, state.paced_until.filter(|until| *until > now))
+        };
+        if !full && paced_until.is_none() {
+            return Ok(());
+        }
+        if now >= give_up {
+            ctx.metrics.actions_overloaded.inc();
+            return Err(SwarmhostError::Overloaded(match paced_until {
+                Some(_) => format!("proposer still busy after {:?}", submit_wait),
+                None => format!(
+                    "no room among {} pending actions within {:?}",
+                    max_pending, submit_wait
+                ),
+            }));
+        }
+        let wake = paced_until.map_or(give_up, |until| until.min(give_up));
+        tokio::select! {
+            event = events.recv() => {
+                if let Err(broadcast::error::RecvError::Closed) = event {
+                    return Err(SwarmhostError::NotRunning);
+                }
+            }
+            _ = tokio::time::sleep_until(wake) => {}
+        }
+    }
+}
+
+/// Answer a forward we had no room for busy, so its sender holds off
+/// instead of handing it to us again straight away
+pub(super) async fn refuse(peer: PlayerId, action: &SignedAction, ctx: &PeerContext) {
+    tracing::debug!(
+        "Too many actions pending for {} forwarded by {}",
+        short_id(&action.id()),
+        short_id(&peer)
+    );
+    ctx.metrics.busy_sent.inc();
+    let busy = PeerMessage::Busy {
+        retry_after_ms: BUSY_RETRY_AFTER.as_millis() as u32,
+    };
+    peers::send_to(&*ctx.state.read().await, &[peer], busy);
+}
+
+/// Hold off forwarding to `peer` for as long as it asks, up to
+/// `MAX_BUSY_BACKOFF`, if it is the proposer we forward to
+pub(super) async fn on_busy(peer: PlayerId, retry_after_ms: u32, ctx: &PeerContext) {
+    let mut state = ctx.state.write().await;
+    if ctx.consensus.lock().await.proposer() != Some(peer) {
+        return;
+    }
+    ctx.metrics.busy_received.inc();
+    let backoff = Duration::from_millis(retry_after_ms.into()).min(MAX_BUSY_BACKOFF);
+    let until = Instant::now() + backoff;
+    tracing::debug!("{} is busy, holding off for {:?}", short_id(&peer), backoff);
+    state.paced_until = Some(state.paced_until.map_or(until, |paced| paced.max(until)));
+}
@@ -0,0 +1,133 @@
+// node/memory.rs - Keeping what the node holds within `max_memory_bytes`,
+// by shedding what can be rebuilt or fetched again once over it
+
+use super::MemoryUsage;
+use super::peers::PeerContext;
+use crate::error::Result;
+use crate::network::fragment::Fragmenter;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// What is shed to get back under budget, in the order it is
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Shed {
+    /// Past states kept rebuilt
+    History,
+    /// Snapshots not needed to go on
+    Snapshots,
+    /// The older half of the duplicate cache
+    Dedup,
+    /// Log entries behind each game's newest snapshot or checkpoint
+    Log,
+}
+
+const SHED_ORDER: [Shed; 4] = [Shed::History, Shed::Snapshots, Shed::Dedup, Shed::Log];
+
+/// The node's memory budget, and whether it is still over it after
+/// shedding all it could
+pub(super) struct MemoryBudget {
+    /// Bytes allowed; 0 for no limit
+    limit: u64,
+    over: AtomicBool,
+}
+
+impl MemoryBudget {
+    pub fn new(limit: u64) -> Self {
+        Self {
+            limit,
+            over: AtomicBool::new(false),
+        }
+    }
+
+    /// Whether to refuse what the node can do without until it is back
+    /// under budget
+    pub fn over(&self) -> bool {
+        self.over.load(Ordering::Relaxed)
+    }
+}
+
+/// Account the memory the node holds, and when it is over budget shed
+/// what it can, in [`SHED_ORDER`], until it is back under
+///
+/// If it is still over with everything shed, new fragmented transfers are
+/// refused until it is not. Pruning the log this way passes over peers
+/// lagging behind, so a fork found against one may no longer roll back.
+pub(super) async fn enforce(ctx: &PeerContext) {
+    let mut usage = account(ctx).await;
+    let limit = ctx.memory.limit;
+    if limit == 0 {
+        return;
+    }
+    if usage.total() > limit {
+        ctx.metrics.memory_sheds.inc();
+        for step in SHED_ORDER {
+            if let Err(e) = shed(step, ctx).await {
+                tracing::warn!("Could not shed {:?} to save memory: {}", step, e);
+            }
+            usage = account(ctx).await;
+            if usage.total() <= limit {
+                break;
+            }
+        }
+    }
+    let over = usage.total() > limit;
+    if ctx.memory.over.swap(over, Ordering::Relaxed) != over {
+        if over {
+            tracing::warn!(
+                "Holding {} bytes with all shed, over max_memory_bytes of {}: {:?}",
+                usage.total(),
+                limit,
+                usage
+            );
+        } else {
+            tracing::info!("Back under max_memory_bytes of {}", limit);
+        }
+    }
+}
+
+/// Measure what the major consumers hold, updating the metrics with it
+async fn account(ctx: &PeerContext) -> MemoryUsage {
+    let metrics = &ctx.metrics;
+    {
+        let state_manager = ctx.state_manager.lock().await;
+        metrics.memory_snapshots.set(state_manager.snapshot_bytes());
+        metrics.memory_action_log.set(state_manager.log_bytes());
+        metrics.memory_history.set(state_manager.history_bytes());
+    }
+    metrics
+        .memory_dedup
+        .set(ctx.dedup.lock().await.memory_bytes());
+    metrics.memory_usage()
+}
+
+async fn shed(step: Shed, ctx: &PeerContext) -> Result<()> {
+    match step {
+        Shed::History => ctx.state_manager.lock().await.clear_history(),
+        Shed::Snapshots => ctx.state_manager.lock().await.shed_snapshots()?,
+        Shed::Dedup => {
+            let mut dedup = ctx.dedup.lock().await;
+            let keep = dedup.len() / 2;
+            dedup.shrink(keep);
+        }
+        Shed::Log => {
+            let mut state_manager = ctx.state_manager.lock().await;
+            let games: Vec<String> = state_manager.games().cloned().collect();
+            for game_id in games {
+                let pruned = state_manager.shed_log(&game_id)?;
+                if pruned > 0 {
+                    tracing::debug!("Pruned {} entries of {} to save memory", pruned, game_id);
+                    ctx.metrics.log_entries_pruned.add(pruned as u64);
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Account what a connection's `fragments` hold now against `buffered`,
+/// what they held when last accounted
+pub(super) fn reassembling(buffered: &mut u64, fragments: &Fragmenter, ctx: &PeerContext) {
+    let now = fragments.buffered();
+    ctx.metrics.memory_reassembly.add(now);
+    ctx.metrics.memory_reassembly.sub(*buffered);
+    *buffered = now;
+}
@@ -0,0 +1,365 @@
+// node/metrics.rs - Runtime counters exposed by the node
+
+use crate::crypto::PlayerId;
+use std::collections::{HashMap, VecDeque};
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+/// Upper bounds of the latency histogram's buckets, in milliseconds; one
+/// last bucket takes anything slower
+pub const LATENCY_BUCKETS_MS: [u64; 10] = [5, 10, 25, 50, 100, 250, 500, 1000, 2500, 5000];
+
+/// Latest samples a latency histogram keeps for its percentiles
+pub const LATENCY_WINDOW: usize = 1024;
+
+/// Monotonic counter safe to bump from any task
+#[derive(Debug, Default)]
+pub struct Counter(AtomicU64);
+
+impl Counter {
+    pub fn inc(&self) {
+        self.add(1);
+    }
+
+    pub fn add(&self, n: u64) {
+        self.0.fetch_add(n, Ordering::Relaxed);
+    }
+
+    pub fn get(&self) -> u64 {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// Current value of something that goes up and down, safe to set from any
+/// task
+#[derive(Debug, Default)]
+pub struct Gauge(AtomicU64);
+
+impl Gauge {
+    pub fn set(&self, value: u64) {
+        self.0.store(value, Ordering::Relaxed);
+    }
+
+    pub fn add(&self, n: u64) {
+        self.0.fetch_add(n, Ordering::Relaxed);
+    }
+
+    pub fn sub(&self, n: u64) {
+        self.0.fetch_sub(n, Ordering::Relaxed);
+    }
+
+    pub fn get(&self) -> u64 {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// A counter per player
+#[derive(Debug, Default)]
+pub struct PlayerCounter(Mutex<HashMap<PlayerId, u64>>);
+
+impl PlayerCounter {
+    pub fn inc(&self, player: &PlayerId) {
+        *self
+            .0
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .entry(*player)
+            .or_default() += 1;
+    }
+
+    pub fn get(&self, player: &PlayerId) -> u64 {
+        let counts = self.0.lock().unwrap_or_else(|e| e.into_inner());
+        counts.get(player).copied().unwrap_or(0)
+    }
+
+    /// Every player counted so far, with its count
+    pub fn snapshot(&self) -> HashMap<PlayerId, u64> {
+        self.0.lock().unwrap_or_else(|e| e.into_inner()).clone()
+    }
+}
+
+/// Durations counted into the buckets of [`LATENCY_BUCKETS_MS`], with the
+/// latest [`LATENCY_WINDOW`] of them kept for percentiles
+#[derive(Debug, Default)]
+pub struct LatencyHistogram {
+    buckets: [Counter; LATENCY_BUCKETS_MS.len() + 1],
+    window: Mutex<VecDeque<Duration>>,
+}
+
+impl LatencyHistogram {
+    pub fn record(&self, latency: Duration) {
+        let millis = latency.as_millis();
+        let bucket = LATENCY_BUCKETS_MS
+            .iter()
+            .position(|bound| millis <= *bound as u128)
+            .unwrap_or(LATENCY_BUCKETS_MS.len());
+        self.buckets[bucket].inc();
+        let mut window = self.window.lock().unwrap_or_else(|e| e.into_inner());
+        if window.len() == LATENCY_WINDOW {
+            window.pop_front();
+        }
+        window.push_back(latency);
+    }
+
+    /// Samples in each bucket, in the order of [`LATENCY_BUCKETS_MS`] and
+    /// the slowest last
+    pub fn buckets(&self) -> Vec<u64> {
+        self.buckets.iter().map(Counter::get).collect()
+    }
+
+    /// Samples recorded since the node was created
+    pub fn count(&self) -> u64 {
+        self.buckets.iter().map(Counter::get).sum()
+    }
+
+    /// Latency below which `quantile`, from 0 to 1, of the samples in the
+    /// window fall, by nearest rank; none without samples
+    pub fn percentile(&self, quantile: f64) -> Option<Duration> {
+        let mut samples: Vec<Duration> = self
+            .window
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .iter()
+            .copied()
+            .collect();
+        if samples.is_empty() {
+            return None;
+        }
+        samples.sort_unstable();
+        let rank = (quantile * samples.len() as f64).ceil() as usize;
+        Some(samples[rank.clamp(1, samples.len()) - 1])
+    }
+
+    /// The median, 95th and 99th percentiles over the window
+    pub fn summary(&self) -> Option<LatencySummary> {
+        Some(LatencySummary {
+            p50: self.percentile(0.50)?,
+            p95: self.percentile(0.95)?,
+            p99: self.percentile(0.99)?,
+        })
+    }
+}
+
+/// Percentiles of the latencies in a histogram's window
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LatencySummary {
+    pub p50: Duration,
+    pub p95: Duration,
+    pub p99: Duration,
+}
+
+/// Counters describing what the node has done since it was created
+#[derive(Debug, Default)]
+pub struct NodeMetrics {
+    /// Actions refused for exceeding `max_action_size`
+    pub actions_rejected_oversized: Counter,
+
+    /// Remote actions refused for exceeding the per-player round rate
+    pub actions_rejected_rate_limited: Counter,
+
+    /// Actions refused, local or remote, for `max_pending_actions` already
+    /// pending
+    pub actions_overloaded: Counter,
+
+    /// Forwarded actions we answered busy
+    pub busy_sent: Counter,
+
+    /// Busy answers to actions we forwarded
+    pub busy_received: Counter,
+
+    /// Remote actions refused because their signature did not verify
+    pub actions_rejected_signature: Counter,
+
+    /// Actions rejected for losing a conflict with another in their round
+    pub actions_rejected_conflict: Counter,
+
+    /// Actions we numbered as sequencer once every validator, by weight,
+    /// had approved them
+    pub commits_fast_path: Counter,
+
+    /// Actions we numbered as sequencer on a quorum short of every validator
+    pub commits_normal_path: Counter,
+
+    /// Blocks dropped for following a rejected block in the pipeline, their
+    /// actions put back to be proposed again
+    pub blocks_discarded: Counter,
+
+    /// Time from a block's proposal reaching us to its last action being
+    /// committed
+    pub round_latency: LatencyHistogram,
+
+    /// Votes on committed blocks in by the time they were committed,
+    /// summed over blocks
+    pub round_votes_received: Counter,
+
+    /// Votes on committed blocks there would have been had every
+    /// validator voted, summed over blocks
+    pub round_votes_expected: Counter,
+
+    /// Votes each validator cast on a block after it was committed, within
+    /// `consensus_timeout` of its proposal
+    pub validators_late: PlayerCounter,
+
+    /// Blocks each validator had not voted on `consensus_timeout` after
+    /// their proposal
+    pub validators_silent: PlayerCounter,
+
+    /// Rounds we asked peers for votes on, waiting on them near the round
+    /// deadline
+    pub vote_requests: Counter,
+
+    /// Votes we only got by asking peers for them
+    pub votes_recovered: Counter,
+
+    /// Rounds skipped for a silent proposer
+    pub rounds_failed_timeout: Counter,
+
+    /// Blocks the votes rejected
+    pub rounds_failed_rejected: Counter,
+
+    /// Views given up on for a sequencer that let rounds fail
+    pub rounds_failed_view_change: Counter,
+
+    /// Blocks proposed and not yet settled
+    pub pipeline_occupancy: Gauge,
+
+    /// Actions taken in and not yet decided
+    pub pending_actions: Gauge,
+
+    /// Local actions held while degraded
+    pub held_actions: Gauge,
+
+    /// Committed actions the log of the game last committed to holds in
+    /// memory
+    pub action_log_length: Gauge,
+
+    /// Log entries pruned behind snapshots and checkpoints
+    pub log_entries_pruned: Counter,
+
+    /// Snapshot chunks sent to peers catching up
+    pub snapshot_chunks_served: Counter,
+
+    /// Original size of outgoing messages that were compressed
+    pub compression_input_bytes: Counter,
+
+    /// Size of those messages after compression
+    pub compression_output_bytes: Counter,
+
+    /// Outgoing message bytes sent without compression
+    pub uncompressed_bytes: Counter,
+
+    /// Frames handed to transports after the handshake
+    pub frames_sent: Counter,
+
+    /// Messages that shared a frame with others
+    pub messages_batched: Counter,
+
+    /// Demerits given to peers for misbehaviour
+    pub peer_demerits: Counter,
+
+    /// Peers disconnected and banned for reaching the demerit threshold
+    pub peers_banned: Counter,
+
+    /// Connections refused at `max_peers`
+    pub peers_rejected_busy: Counter,
+
+    /// Connections dropped at `max_peers` to make room for another peer
+    pub peers_evicted: Counter,
+
+    /// Queued game actions discarded because a newer one overflowed the queue
+    pub game_actions_dropped: Counter,
+
+    /// Messages dropped because a copy had already been delivered
+    pub duplicates_dropped: Counter,
+
+    /// Microseconds queued messages were held back by upload limits, summed
+    /// over peers
+    pub upload_throttled_micros: Counter,
+
+    /// Frames dropped unread for going over the inbound limits
+    pub inbound_dropped: Counter,
+
+    /// Peers disconnected for going over the inbound limits
+    pub peers_rate_limited: Counter,
+
+    /// Inbound connections dropped at `max_half_open`
+    pub handshakes_refused: Counter,
+
+    /// Bytes of snapshots held in memory, as last accounted
+    pub memory_snapshots: Gauge,
+
+    /// Bytes the action logs hold, as last accounted
+    pub memory_action_log: Gauge,
+
+    /// Bytes of fragments waiting for the rest of their message
+    pub memory_reassembly: Gauge,
+
+    /// Bytes of past states kept rebuilt, as last accounted
+    pub memory_history: Gauge,
+
+    /// Bytes the duplicate cache holds, as last accounted
+    pub memory_dedup: Gauge,
+
+    /// Times memory was shed for going over `max_memory_bytes`
+    pub memory_sheds: Counter,
+
+    /// Fragments of new transfers refused while over `max_memory_bytes`
+    pub fragments_refused_over_budget: Counter,
+}
+
+/// Bytes held by the node's major consumers of memory
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct MemoryUsage {
+    pub snapshots: u64,
+    pub action_log: u64,
+    pub reassembly: u64,
+    pub history: u64,
+    pub dedup: u64,
+}
+
+impl MemoryUsage {
+    pub fn total(&self) -> u64 {
+        self.snapshots + self.action_log + self.reassembly + self.history + self.dedup
+    }
+}
+
+impl NodeMetrics {
+    /// Memory held per consumer, as last accounted
+    pub fn memory_usage(&self) -> MemoryUsage {
+        MemoryUsage {
+            snapshots: self.memory_snapshots.get(),
+            action_log: self.memory_action_log.get(),
+            reassembly: self.memory_reassembly.get(),
+            history: self.memory_history.get(),
+            dedup: self.memory_dedup.get(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_latency_percentiles_over_the_window() {
+        let histogram = LatencyHistogram::default();
+        assert_eq!(histogram.summary(), None);
+        for millis in 1..=100 {
+            histogram.record(Duration::from_millis(millis));
+        }
+        let summary = histogram.summary().unwrap();
+        assert_eq!(summary.p50, Duration::from_millis(50));
+        assert_eq!(summary.p95, Duration::from_millis(95));
+        assert_eq!(summary.p99, Duration::from_millis(99));
+        assert_eq!(histogram.count(), 100);
+        assert_eq!(histogram.buckets()[..5], [5, 5, 15, 25, 50]);
+
+        // Only the latest samples count towards the percentiles
+        for _ in 0..LATENCY_WINDOW {
+            histogram.record(Duration::from_secs(10));
+        }
+        assert_eq!(histogram.percentile(0.0), Some(Duration::from_secs(10)));
+        assert_eq!(histogram.buckets()[LATENCY_BUCKETS_MS.len()], 1024);
+    }
+}
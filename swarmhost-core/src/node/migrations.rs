@@ -0,0 +1,163 @@
+// node/migrations.rs - Upgrades for older on-disk config formats
+
+use crate::error::{Result, SwarmhostError};
+use serde_json::{Map, Value, json};
+
+/// Config format version written by this library
+pub const CONFIG_VERSION: u32 = 1;
+
+/// `MIGRATIONS[n]` upgrades a version `n` config to version `n + 1`
+const MIGRATIONS: &[fn(&mut Map<String, Value>)] = &[v0_to_v1];
+
+/// Format version of a raw config; files without `config_version` are version 0
+pub fn detect_version(value: &Value) -> Result<u32> {
+    match value.get("config_version") {
+        None => Ok(0),
+        Some(version) => version
+            .as_u64()
+            .and_then(|v| u32::try_from(v).ok())
+            .ok_or_else(|| {
+                SwarmhostError::Config(format!(
+                    "config_version must be a non-negative integer, got {}",
+                    version
+                ))
+            }),
+    }
+}
+
+/// Upgrade a raw config to [`CONFIG_VERSION`] one step at a time
+///
+/// Returns the version the config started at.
+pub fn migrate(value: &mut Value) -> Result<u32> {
+    let from = detect_version(value)?;
+    if from > CONFIG_VERSION {
+        return Err(SwarmhostError::Config(format!(
+            "config_version {} is newer than the newest version this library supports ({})",
+            from, CONFIG_VERSION
+        )));
+    }
+
+    let config = value
+        .as_object_mut()
+        .ok_or_else(|| SwarmhostError::Config("config must be a JSON object".to_string()))?;
+
+    for (version, migration) in MIGRATIONS.iter().enumerate().skip(from as usize) {
+        migration(config);
+        config.insert("config_version".to_string(), json!(version + 1));
+    }
+
+    Ok(from)
+}
+
+/// v0 -> v1: `bootstrap_server` becomes the `bootstrap_servers` list and
+/// `network.enable_compression` becomes the `network.compression` section
+fn v0_to_v1(config: &mut Map<String, Value>) {
+    if let Some(server) = config.remove("bootstrap_server") {
+        let servers = match server {
+            Value::String(server) => vec![Value::String(server)],
+            _ => Vec::new(),
+        };
+        config
+            .entry("bootstrap_servers")
+            .or_insert(Value::Array(servers));
+    }
+
+    if let Some(network) = config.get_mut("network").and_then(Value::as_object_mut)
+        && network.remove("enable_compression") == Some(Value::Bool(false))
+    {
+        network
+            .entry("compression")
+            .or_insert_with(|| json!({ "algorithm": "None" }));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::node::{CompressionAlgorithm, NodeConfig, SecurityMode};
+    use std::time::Duration;
+
+    /// One fixture per historical format, indexed by version
+    const FIXTURES: &[&str] = &[
+        include_str!("../../tests/fixtures/config/v0.json"),
+        include_str!("../../tests/fixtures/config/v1.json"),
+    ];
+
+    fn load_fixture(version: u32) -> (NodeConfig, u32) {
+        let mut value: Value = serde_json::from_str(FIXTURES[version as usize]).unwrap();
+        let from = migrate(&mut value).unwrap();
+        (serde_json::from_value(value).unwrap(), from)
+    }
+
+    #[test]
+    fn test_every_version_has_a_fixture() {
+        assert_eq!(FIXTURES.len(), CONFIG_VERSION as usize + 1);
+        for (version, fixture) in FIXTURES.iter().enumerate() {
+            let value: Value = serde_json::from_str(fixture).unwrap();
+            assert_eq!(detect_version(&value).unwrap(), version as u32);
+
+            let (config, from) = load_fixture(version as u32);
+            assert_eq!(from, version as u32);
+            assert_eq!(config.config_version, CONFIG_VERSION);
+        }
+    }
+
+    #[test]
+    fn test_v0_fixture() {
+        let (config, _) = load_fixture(0);
+        assert_eq!(config.bootstrap_servers, vec!["bootstrap.example.com:9000"]);
+        assert_eq!(config.listen_port, 7000);
+        assert_eq!(config.consensus.quorum_numerator, 3);
+        assert_eq!(config.consensus.consensus_timeout, Duration::from_secs(15));
+        assert_eq!(config.network.heartbeat_interval, Duration::from_secs(5));
+        assert_eq!(config.network.max_peers, 20);
+        assert_eq!(
+            config.network.compression.algorithm,
+            CompressionAlgorithm::None
+        );
+    }
+
+    #[test]
+    fn test_v0_compression_enabled_keeps_default() {
+        let mut value =
+            json!({ "network": { "enable_compression": true }, "bootstrap_server": null });
+        migrate(&mut value).unwrap();
+        assert_eq!(value["network"], json!({}));
+        assert_eq!(value["bootstrap_servers"], json!([]));
+    }
+
+    #[test]
+    fn test_v1_fixture() {
+        let (config, _) = load_fixture(1);
+        assert_eq!(config.bootstrap_servers.len(), 2);
+        assert_eq!(
+            config.network.heartbeat_interval,
+            Duration::from_millis(500)
+        );
+        assert_eq!(
+            config.network.compression.algorithm,
+            CompressionAlgorithm::Zstd { level: 5 }
+        );
+        assert_eq!(config.network.security.mode, SecurityMode::Required);
+        assert_eq!(config.consensus.max_action_size, 32768);
+        assert_eq!(config.preset_name(), Some("internet"));
+    }
+
+    #[test]
+    fn test_newer_version_rejected() {
+        let mut value = json!({ "config_version": CONFIG_VERSION + 1 });
+        match migrate(&mut value) {
+            Err(SwarmhostError::Config(msg)) => {
+                assert!(msg.contains(&(CONFIG_VERSION + 1).to_string()));
+                assert!(msg.contains(&format!("({})", CONFIG_VERSION)));
+            }
+            other => panic!("expected config error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_bad_version_rejected() {
+        assert!(detect_version(&json!({ "config_version": "two" })).is_err());
+        assert!(migrate(&mut json!([1, 2])).is_err());
+    }
+}
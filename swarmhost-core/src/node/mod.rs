@@ -1,25 +1,197 @@
 // node/mod.rs - Main node implementation
 
+mod archive;
+mod checkpoint;
 mod config;
+mod dht;
+mod divergence;
+mod events;
+mod eviction;
+mod evidence;
+mod fork;
+mod grace;
+mod handle;
+mod intake;
+mod memory;
+mod metrics;
+mod migrations;
+mod observer;
+mod ordering;
+mod partition;
+mod peers;
+mod pex;
+mod portmap;
+mod pull;
+mod reconnect;
+mod recovery;
+mod relay;
+mod reload;
+mod rotation;
+mod sequence;
+mod snapshot;
+mod status;
+mod sync;
+mod tick;
+mod traversal;
+mod view;
 
-pub use config::{ConsensusConfig, NetworkConfig, NodeConfig, StateConfig};
+pub use archive::ExportSummary;
+pub(crate) use config::parse_host_port;
+pub use config::{
+    BatchConfig, CipherSuite, CompressionAlgorithm, CompressionConfig, ConfigPreset,
+    ConflictPolicy, ConsensusConfig, DedupConfig, DegradedActions, DhtConfig, FragmentConfig,
+    GossipConfig, InboundConfig, InvalidInBlock, LogConfig, LogFormat, MuxConfig, NatConfig,
+    NetworkConfig, NodeConfig, OutboundConfig, PersistenceBackend, PexConfig, ProxyConfig,
+    ReconnectConfig, RelayConfig, ReliableConfig, ReputationConfig, ResumptionConfig, RoundMode,
+    SecurityConfig, SecurityMode, StateConfig, TransportKind, UploadConfig, WireFormat,
+};
+pub use divergence::DivergenceReport;
+pub use events::{CommitOutcome, NodeEvent, RejectionReason};
+pub use eviction::{EvictionPolicy, PeerRole, PeerStanding, ValidatorsFirst};
+pub use handle::NetworkHandle;
+pub use metrics::{
+    Counter, Gauge, LatencyHistogram, LatencySummary, MemoryUsage, NodeMetrics, PlayerCounter,
+};
+pub use migrations::CONFIG_VERSION;
+pub use peers::{ConnectionPath, PeerInfo};
+pub use reload::{ConfigDiff, TUNABLE_FIELDS};
+pub use status::{ConsensusInfo, NatStatus, NodeStatus};
 
-use crate::crypto::PlayerId;
+use reload::ConfigWatch;
+
+use crate::consensus::{
+    ActionClassifier, ActionId, ActionValidator, AllStrict, ConsensusManager, Decision,
+    MEMBERSHIP_CHANGE_ACTION, Membership, MembershipChange, ObserverVote, OrderingClass,
+    Randomness, RejectCode, ScheduledChange, SignedAction, ValidationPipeline, ValidationResult,
+    Vote, action,
+};
+use crate::crypto::{Hash, KeyPair, PlayerId, short_id};
 use crate::error::{Result, SwarmhostError};
+use crate::network::punch::PunchSignal;
+use crate::network::throttle::{Bandwidth, SharedBandwidth, Throttle};
+use crate::network::trace::{self, Step};
+use crate::network::{
+    self, BootstrapClient, BootstrapList, CloseCode, DedupCache, GameAnnouncement, Gossip,
+    GossipPayload, Listener, LocalDiscovery, LocalPeer, Offense, PeerRecord, PeerStore, PortMapper,
+    PortMapping, PortProtocol, RelayUsage, Socks5Proxy, Transport,
+};
+use crate::state::{
+    ActionLog, Checkpoint, ConfirmedStates, GameArchive, Snapshot, StateManager, StateView,
+};
+use bytes::Bytes;
+use peers::{PeerContext, PeerHandle};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::io::{Read, Write};
+use std::net::SocketAddr;
 use std::sync::Arc;
-use tokio::sync::RwLock;
+use std::time::Duration;
+use tokio::sync::{Mutex, Notify, RwLock, Semaphore, broadcast, mpsc, oneshot, watch};
+use tokio::task::{JoinHandle, JoinSet};
+use tokio::time::Instant;
+use tracing::Instrument;
 
 /// The main Swarmhost node
 pub struct SwarmhostNode {
     config: NodeConfig,
+    keypair: Arc<KeyPair>,
+    tunables: ConfigWatch,
+    transport: Arc<dyn Transport>,
+    bootstrap: Option<Arc<Mutex<BootstrapClient>>>,
+    /// Wakes the bootstrap task to re-register early
+    bootstrap_refresh: Arc<Notify>,
+    /// LAN discovery, when `enable_mdns` is set
+    local_discovery: Option<Arc<dyn LocalDiscovery>>,
+    /// Asks the router to forward the listen port, when `enable_upnp` is set
+    port_mapper: PortMapper,
     state: Arc<RwLock<NodeState>>,
+    consensus: Arc<Mutex<ConsensusManager>>,
+    gossip: Arc<Mutex<Gossip>>,
+    dedup: Arc<Mutex<DedupCache>>,
+    memory: Arc<memory::MemoryBudget>,
+    /// The node-wide upload limit shared by every connection, if set
+    upload: Option<SharedBandwidth>,
+    /// Who keeps a slot once `max_peers` is reached
+    eviction: Arc<dyn EvictionPolicy>,
+    /// Routing table and provider records, when `enable_dht` is set
+    dht: Option<Arc<Mutex<dht::Dht>>>,
+    /// Peers known through PEX, when `enable_pex` is set
+    pex: Option<Arc<Mutex<PeerStore>>>,
+    /// Limits inbound handshakes in progress to `max_half_open`
+    handshakes: Arc<Semaphore>,
+    state_manager: Arc<Mutex<StateManager>>,
+    /// Views of each game's confirmed state, read without the lock above
+    confirmed: Arc<ConfirmedStates>,
+    /// Checks an action passes before we approve it
+    validation: ValidationPipeline,
+    /// Which action types are delivered without consensus
+    classifier: Arc<dyn ActionClassifier>,
+    events: broadcast::Sender<NodeEvent>,
+    metrics: Arc<NodeMetrics>,
 }
 
+/// Capacity of the node event channel before slow subscribers lag
+const EVENT_CHANNEL_CAPACITY: usize = 1024;
+
 /// Internal node state
 struct NodeState {
     player_id: PlayerId,
     is_running: bool,
     connected_peers: Vec<PlayerId>,
+    /// Close signal for each connected peer's connection task
+    connections: HashMap<PlayerId, PeerHandle>,
+    current_game: Option<String>,
+    /// Validators of the current game and their weights, as set by the
+    /// host and reweighed by committed weight changes
+    validators: Membership,
+    next_nonce: u64,
+    listeners: Vec<Arc<dyn Listener>>,
+    active_bootstrap: Option<String>,
+    advertised_addr: Option<SocketAddr>,
+    nat_status: NatStatus,
+    /// The listen port as forwarded by the router, removed on stop
+    port_mapping: Option<PortMapping>,
+    /// Latest advertisement seen from each node on the local network
+    local_peers: HashMap<PlayerId, LocalPeer>,
+    /// Hole punches we offered, waiting for the other side's answer
+    punches: HashMap<u64, oneshot::Sender<PunchSignal>>,
+    /// Sessions we relay between two of our peers
+    relay_sessions: HashMap<u64, relay::RelaySession>,
+    /// Frames for our end of each session relayed to us, by relay and session
+    relayed: HashMap<(PlayerId, u64), mpsc::Sender<Bytes>>,
+    /// Peers evicted for misbehaviour, refused until the given time
+    bans: HashMap<PlayerId, tokio::time::Instant>,
+    /// Resumption tokens we issued to our peers
+    resumption: network::Resumption,
+    /// The latest resumption token each peer issued to us
+    resume_tokens: HashMap<PlayerId, network::ResumptionToken>,
+    /// Our requests waiting for an answer, by peer and request id
+    requests: HashMap<(PlayerId, u64), oneshot::Sender<Bytes>>,
+    /// Our DHT queries waiting for an answer, numbered like requests
+    dht_queries: HashMap<(PlayerId, u64), oneshot::Sender<dht::Found>>,
+    /// Our catch-up fetches waiting for an answer, numbered like requests
+    syncs: HashMap<(PlayerId, u64), oneshot::Sender<sync::Answer>>,
+    /// Whether we are catching up on the current game, and so not voting
+    catching_up: bool,
+    /// Snapshot chunks fetched so far, for a catch-up cut short to resume
+    fetched: Option<sync::Fetched>,
+    /// Divergence found between our state of each game and a peer's
+    divergences: HashMap<String, divergence::DivergenceReport>,
+    /// Whether we restarted from a safety record and have yet to catch up
+    /// on what became of what we had in flight, not voting meanwhile
+    recovering: bool,
+    next_request: u64,
+    /// How often each peer may still ask us for votes
+    vote_requests: HashMap<PlayerId, Bandwidth>,
+    /// Correlation ids to carry on, when `trace_messages` is set
+    traces: Option<network::Tracer>,
+    /// Whether a quorum of validators is reachable
+    partition: partition::Partition,
+    /// Local actions held while degraded, oldest first
+    held_actions: VecDeque<SignedAction>,
+    /// Until when the proposer asked us to hold off, having answered one of
+    /// our forwards busy
+    paced_until: Option<tokio::time::Instant>,
+    tasks: Vec<JoinHandle<()>>,
 }
 
 impl SwarmhostNode {
@@ -27,17 +199,390 @@ impl SwarmhostNode {
     pub fn new(config: NodeConfig) -> Result<Self> {
         config.validate().map_err(SwarmhostError::Config)?;
 
-        let player_id = config
-            .player_id()
+        let keypair = config
+            .keypair
+            .clone()
+            .map(Arc::new)
             .ok_or_else(|| SwarmhostError::Config("No keypair set".to_string()))?;
+        let player_id = keypair.public_key();
 
         let state = Arc::new(RwLock::new(NodeState {
             player_id,
             is_running: false,
             connected_peers: Vec::new(),
+            connections: HashMap::new(),
+            current_game: None,
+            validators: Membership::new(),
+            next_nonce: 0,
+            listeners: Vec::new(),
+            active_bootstrap: None,
+            advertised_addr: None,
+            nat_status: NatStatus::Disabled,
+            port_mapping: None,
+            local_peers: HashMap::new(),
+            punches: HashMap::new(),
+            relay_sessions: HashMap::new(),
+            relayed: HashMap::new(),
+            bans: HashMap::new(),
+            resumption: network::Resumption::default(),
+            resume_tokens: HashMap::new(),
+            requests: HashMap::new(),
+            dht_queries: HashMap::new(),
+            syncs: HashMap::new(),
+            catching_up: false,
+            fetched: None,
+            divergences: HashMap::new(),
+            recovering: false,
+            next_request: 0,
+            vote_requests: HashMap::new(),
+            traces: config.log.trace_messages.then(network::Tracer::default),
+            partition: partition::Partition::default(),
+            held_actions: VecDeque::new(),
+            paced_until: None,
+            tasks: Vec::new(),
         }));
 
-        Ok(Self { config, state })
+        let tunables = ConfigWatch::new(&config);
+        let transport = network::transport_for(&config)?;
+        let local_discovery = network::discovery::for_config(&config.network)?;
+        let (events, _) = broadcast::channel(EVENT_CHANNEL_CAPACITY);
+        let metrics = Arc::new(NodeMetrics::default());
+        let consensus = Arc::new(Mutex::new(ConsensusManager::new(
+            config.consensus.clone(),
+            events.clone(),
+            metrics.clone(),
+        )));
+
+        let mut state_manager = StateManager::new(&config.state)?;
+        if config.consensus.optimistic_execution {
+            state_manager = state_manager.with_speculation(config.consensus.max_speculation_depth);
+        }
+        let confirmed = state_manager.confirmed();
+        let state_manager = Arc::new(Mutex::new(state_manager));
+        let dht = config.network.enable_dht.then(|| {
+            let now = tokio::time::Instant::now();
+            Arc::new(Mutex::new(dht::Dht::new(
+                player_id,
+                &config.network.dht,
+                now,
+            )))
+        });
+
+        let pex = config
+            .network
+            .enable_pex
+            .then(|| Arc::new(Mutex::new(PeerStore::new(&config.network.pex))));
+
+        let bootstrap = match (&config.keypair, config.bootstrap_servers.is_empty()) {
+            (Some(keypair), false) => {
+                let mut servers = BootstrapList::new(config.bootstrap_servers.clone());
+                if let Some(proxy) = &config.network.outbound_proxy {
+                    servers = servers.with_proxy(Socks5Proxy::new(proxy));
+                }
+                Some(Arc::new(Mutex::new(BootstrapClient::new(
+                    servers,
+                    keypair.clone(),
+                ))))
+            }
+            _ => None,
+        };
+
+        let gossip = Arc::new(Mutex::new(Gossip::new(&config.network.gossip)));
+        let dedup = Arc::new(Mutex::new(DedupCache::new(&config.network.dedup)));
+        let memory = Arc::new(memory::MemoryBudget::new(config.state.max_memory_bytes));
+        let upload = Throttle::global(&config.network.upload, tokio::time::Instant::now());
+        let handshakes = Arc::new(Semaphore::new(config.network.inbound.max_half_open));
+        let validation = ValidationPipeline::new(config.consensus.max_concurrent_validations);
+
+        Ok(Self {
+            config,
+            keypair,
+            tunables,
+            transport,
+            bootstrap,
+            bootstrap_refresh: Arc::new(Notify::new()),
+            local_discovery,
+            port_mapper: PortMapper::default(),
+            state,
+            consensus,
+            gossip,
+            dedup,
+            memory,
+            upload,
+            eviction: Arc::new(ValidatorsFirst),
+            dht,
+            pex,
+            handshakes,
+            state_manager,
+            confirmed,
+            validation,
+            classifier: Arc::new(AllStrict),
+            events,
+            metrics,
+        })
+    }
+
+    /// Decide who keeps a slot at `max_peers` with `policy` instead of
+    /// [`ValidatorsFirst`]
+    pub fn with_eviction_policy(mut self, policy: impl EvictionPolicy + 'static) -> Self {
+        self.eviction = Arc::new(policy);
+        self
+    }
+
+    /// Check actions with `validator` before approving them, after the
+    /// built-in checks and any validators added before it
+    ///
+    /// An action it rejects gets a Reject vote with its reason code, however
+    /// the game votes on it.
+    pub fn with_validator(mut self, validator: impl ActionValidator + 'static) -> Self {
+        self.validation.push(Arc::new(validator));
+        self
+    }
+
+    /// Order each action type as `classifier` says instead of committing
+    /// every action through consensus
+    ///
+    /// [`Causal`](OrderingClass::Causal) and
+    /// [`Unordered`](OrderingClass::Unordered) actions are gossiped and
+    /// delivered as [`NodeEvent::ActionDelivered`] once they pass the
+    /// checks, without proposer, votes or a sequence; every node of a game
+    /// must classify alike.
+    pub fn with_classifier(mut self, classifier: impl ActionClassifier + 'static) -> Self {
+        self.classifier = Arc::new(classifier);
+        self
+    }
+
+    /// Reach peers through `transport` instead of the one `network.transport`
+    /// names, e.g. a [`SimNetwork`](network::SimNetwork)'s
+    pub fn with_transport(mut self, transport: impl Transport + 'static) -> Self {
+        self.transport = Arc::new(transport);
+        self
+    }
+
+    /// Map the listen port through `mapper` instead of one searching the
+    /// local network for a router
+    pub fn with_port_mapper(mut self, mapper: PortMapper) -> Self {
+        self.port_mapper = mapper;
+        self
+    }
+
+    /// Newest stored snapshot for a game (e.g. to restore after a restart)
+    pub async fn latest_snapshot(&self, game_id: &str) -> Result<Option<Snapshot>> {
+        self.state_manager.lock().await.latest_snapshot(game_id)
+    }
+
+    /// Actions committed to a game so far, in sequence order; those pruned
+    /// are not held
+    pub async fn action_log(&self, game_id: &str) -> Option<ActionLog> {
+        self.state_manager.lock().await.log(game_id).cloned()
+    }
+
+    /// Prune a game's log now, if it holds more than `max_action_log_size`
+    /// entries, up to its newest snapshot or checkpoint, returning the
+    /// entries pruned
+    ///
+    /// Entries a connected peer playing the game has not shown us it holds
+    /// are kept unless `force` is set.
+    pub async fn prune_action_log(&self, game_id: &str, force: bool) -> Result<usize> {
+        snapshot::prune(game_id, force, &self.peer_context()).await
+    }
+
+    /// State hash of a game at `sequence`, or at its last commit with none,
+    /// as [`ActionLog::hash`] chains it; none for a sequence not committed
+    /// yet or older than the [`STATE_HASH_HISTORY`](crate::state::log::STATE_HASH_HISTORY)
+    /// kept
+    pub async fn state_hash(&self, game_id: &str, sequence: Option<u64>) -> Option<Hash> {
+        let state_manager = self.state_manager.lock().await;
+        let log = state_manager.log(game_id)?;
+        log.hash_at(sequence.unwrap_or(log.sequence()))
+    }
+
+    /// A game's state as it stood at `sequence`, for replays and disputes,
+    /// rebuilt from the nearest snapshot without touching the live state;
+    /// see [`StateManager::state_at`](crate::state::StateManager::state_at)
+    /// for the cost guard `allow_expensive` lifts
+    pub async fn state_at(
+        &self,
+        game_id: &str,
+        sequence: u64,
+        allow_expensive: bool,
+    ) -> Result<Snapshot> {
+        self.state_manager
+            .lock()
+            .await
+            .state_at(game_id, sequence, allow_expensive)
+    }
+
+    /// Randomness of the round the commit at `sequence` of a game was made
+    /// in, as its [`ActionCommitted`](NodeEvent::ActionCommitted) carried,
+    /// while history reaches it
+    pub async fn round_randomness(&self, game_id: &str, sequence: u64) -> Option<Randomness> {
+        self.consensus
+            .lock()
+            .await
+            .delivered_at(game_id, sequence)?
+            .randomness()
+    }
+
+    /// A game's confirmed state as of the last batch of commits applied,
+    /// read without waiting on the node, as often as a render loop likes;
+    /// see [`ConfirmedStates`] for how far behind it may be
+    pub fn confirmed_state(&self, game_id: &str) -> Option<Arc<StateView>> {
+        self.confirmed.get(game_id)
+    }
+
+    /// Actions applied to a game so far, including those speculated on
+    /// ahead of their commit with optimistic execution
+    pub async fn speculative_log(&self, game_id: &str) -> Option<ActionLog> {
+        self.state_manager
+            .lock()
+            .await
+            .speculative_log(game_id)
+            .cloned()
+    }
+
+    /// Latest checkpoint of a game: its log up to a sequence, signed by a
+    /// quorum of validators, for anyone to audit with
+    /// [`Checkpoint::verify`]
+    pub async fn latest_checkpoint(&self, game_id: &str) -> Option<Checkpoint> {
+        self.state_manager
+            .lock()
+            .await
+            .latest_checkpoint(game_id)
+            .cloned()
+    }
+
+    /// What was known when our state of a game was found to differ from a
+    /// peer's, once heartbeats showed it; see
+    /// [`StateDivergence`](NodeEvent::StateDivergence)
+    pub async fn divergence_report(&self, game_id: &str) -> Option<DivergenceReport> {
+        self.state.read().await.divergences.get(game_id).cloned()
+    }
+
+    /// Export a game to `writer` as a [`GameArchive`](crate::state::GameArchive):
+    /// its latest checkpoint, the snapshot we would sync a peer from, and
+    /// our commits after it, returning what was written
+    pub async fn export_game(
+        &self,
+        game_id: &str,
+        mut writer: impl Write,
+    ) -> Result<ExportSummary> {
+        let snapshot_interval = self.config().state.snapshot_interval;
+        let archive = archive::export(game_id, snapshot_interval, &self.peer_context()).await?;
+        let bytes = archive.write_to(&mut writer)?;
+        Ok(ExportSummary::of(&archive, bytes))
+    }
+
+    /// Import a game exported by [`export_game`](Self::export_game),
+    /// returning what the archive held
+    ///
+    /// The archive's checksums, signatures and certificates are checked and
+    /// its commits replayed on top of its snapshot, against our validators
+    /// or, with none set yet, those it names. The game is then taken up
+    /// where the replay ends, for [`join_game`](Self::join_game) to go on
+    /// from; to look into an archive without taking it up, replay it with
+    /// [`GameArchive::replay`](crate::state::GameArchive::replay) instead.
+    /// An archive of a newer format, or of a game we already hold as far,
+    /// is refused, as is one whose snapshot carries no attestation, as
+    /// before its game's first checkpoint, unless `allow_unverified`; that
+    /// is for local tooling, handling archives it made itself.
+    pub async fn import_game(
+        &self,
+        mut reader: impl Read,
+        allow_unverified: bool,
+    ) -> Result<ExportSummary> {
+        let archive = GameArchive::read_from(&mut reader)?;
+        archive::import(&archive, allow_unverified, &self.peer_context()).await?;
+        let bytes = archive.write_to(&mut std::io::sink())?;
+        Ok(ExportSummary::of(&archive, bytes))
+    }
+
+    /// Subscribe to node events
+    pub fn subscribe(&self) -> broadcast::Receiver<NodeEvent> {
+        self.events.subscribe()
+    }
+
+    /// Handle for sending application messages and requests to peers
+    pub fn network(&self) -> NetworkHandle {
+        NetworkHandle::new(self.state.clone())
+    }
+
+    /// Runtime counters
+    pub fn metrics(&self) -> &NodeMetrics {
+        &self.metrics
+    }
+
+    /// The node's configuration, including any reloaded tunables
+    pub fn config(&self) -> NodeConfig {
+        self.tunables.current(&self.config)
+    }
+
+    /// Apply a new configuration to the running node
+    ///
+    /// Only fields listed in [`TUNABLE_FIELDS`] are applied; running tasks pick
+    /// them up without reconnecting peers. Changes to any other field are
+    /// reported in [`ConfigDiff::rejected`] and do not abort the reload.
+    pub fn reload_config(&self, new: NodeConfig) -> Result<ConfigDiff> {
+        new.validate().map_err(SwarmhostError::Config)?;
+
+        let diff = self.tunables.update(&self.config, |current| {
+            let (merged, diff) = reload::reconcile(&current, &new)?;
+            merged.validate().map_err(SwarmhostError::Config)?;
+            Ok((merged, diff))
+        })?;
+
+        if !diff.applied.is_empty() {
+            tracing::info!("Reloaded config fields: {}", diff.applied.join(", "));
+        }
+        if !diff.rejected.is_empty() {
+            tracing::warn!(
+                "Ignored changes to immutable config fields: {}",
+                diff.rejected.join(", ")
+            );
+        }
+
+        Ok(diff)
+    }
+
+    /// Replace the allowlist and denylist at runtime
+    ///
+    /// New handshakes use the lists immediately. Connected peers the new lists
+    /// refuse are disconnected and returned.
+    pub async fn update_peer_lists(
+        &self,
+        allowlist: Option<Vec<PlayerId>>,
+        denylist: Vec<PlayerId>,
+    ) -> Result<Vec<PlayerId>> {
+        let updated = self.tunables.update(&self.config, |mut candidate| {
+            candidate.network.allowlist = allowlist;
+            candidate.network.denylist = denylist;
+            candidate.validate().map_err(SwarmhostError::Config)?;
+            let updated = candidate.network.clone();
+            Ok((candidate, updated))
+        })?;
+
+        let mut guard = self.state.write().await;
+        let state = &mut *guard;
+        let mut disconnected = Vec::new();
+        state
+            .connected_peers
+            .retain(|peer| match network::check_admission(&updated, peer) {
+                Ok(()) => true,
+                Err(reason) => {
+                    tracing::info!("Disconnecting {}: {}", short_id(peer), reason);
+                    if let Some(handle) = state.connections.remove(peer) {
+                        let _ = handle.close.send(Some(reason));
+                    }
+                    let _ = self.events.send(NodeEvent::PeerDisconnected {
+                        peer: *peer,
+                        reason,
+                    });
+                    disconnected.push(*peer);
+                    false
+                }
+            });
+
+        Ok(disconnected)
     }
 
     /// Get the player ID for this node
@@ -47,23 +592,150 @@ impl SwarmhostNode {
     }
 
     /// Start the node
+    ///
+    /// If an earlier run persisted a consensus safety record, we pick up
+    /// from it, standing by the votes it holds, and do not vote until we
+    /// have caught up on what became of them. Each game we hold snapshots
+    /// of is restored from the newest one that loads, damaged ones skipped.
     pub async fn start(&self) -> Result<()> {
         let mut state = self.state.write().await;
 
         if state.is_running {
-            return Err(SwarmhostError::Node("Node already running".to_string()));
+            return Err(SwarmhostError::AlreadyRunning);
         }
+        let recovering = recovery::recover(&self.peer_context()).await?;
 
         tracing::info!(
             "Starting Swarmhost node on port {}",
             self.config.listen_port
         );
 
+        let listeners = network::listen_all(
+            self.transport.as_ref(),
+            &self.config.network,
+            self.config.listen_port,
+        )
+        .await?;
+        for listener in &listeners {
+            tracing::info!("Listening on {}", listener.local_addr());
+        }
+
+        let listen_port = listeners[0].local_addr().port();
+        state.listeners = listeners.into_iter().map(Arc::from).collect();
+        state.recovering = recovering;
         state.is_running = true;
 
+        let ctx = self.peer_context();
+        for listener in state.listeners.clone() {
+            let task = tokio::spawn(peers::accept_loop(listener, ctx.clone()));
+            state.tasks.push(task);
+        }
+
+        let nat = self.config.network.nat.clone();
+        let bind_addr = self.config.network.bind_addr;
+        if let Some(addr) = nat.advertised_addr {
+            state.advertised_addr = Some(addr);
+        }
+        if nat.enable_upnp {
+            let protocol = match self.config.network.transport {
+                TransportKind::Quic => PortProtocol::Udp,
+                _ => PortProtocol::Tcp,
+            };
+            state.nat_status = NatStatus::Pending;
+            state.tasks.push(tokio::spawn(portmap::maintain(
+                self.port_mapper.clone(),
+                protocol,
+                listen_port,
+                nat,
+                bind_addr,
+                self.state.clone(),
+            )));
+        } else if nat.advertised_addr.is_none()
+            && !nat.stun_servers.is_empty()
+            // STUN would go around the proxy
+            && self.config.network.outbound_proxy.is_none()
+        {
+            let node_state = self.state.clone();
+            state.tasks.push(tokio::spawn(async move {
+                if let Some(addr) =
+                    network::nat::detect_public_addr(&nat, bind_addr, listen_port).await
+                {
+                    node_state.write().await.advertised_addr = Some(addr);
+                }
+            }));
+        }
+
+        if let Some(client) = &self.bootstrap {
+            let task = tokio::spawn(bootstrap_task(
+                client.clone(),
+                self.bootstrap_refresh.clone(),
+                self.tunables.network.subscribe(),
+                self.state.clone(),
+            ));
+            state.tasks.push(task);
+        }
+
+        if let Some(discovery) = &self.local_discovery {
+            self.advertise_locally(discovery.as_ref(), &state)?;
+            let task = tokio::spawn(local_discovery_task(
+                discovery.browse()?,
+                self.transport.clone(),
+                self.peer_context(),
+            ));
+            state.tasks.push(task);
+        }
+
+        if self.dht.is_some() {
+            let task = tokio::spawn(dht::maintain(self.peer_context()));
+            state.tasks.push(task);
+        }
+        if self.pex.is_some() {
+            let task = tokio::spawn(pex::maintain(self.peer_context()));
+            state.tasks.push(task);
+        }
+        let task = tokio::spawn(partition::watch(
+            self.tunables.consensus.subscribe(),
+            self.peer_context(),
+        ));
+        state.tasks.push(task);
+        let task = tokio::spawn(sequence::watch(
+            self.tunables.consensus.subscribe(),
+            self.peer_context(),
+        ));
+        state.tasks.push(task);
+        let task = tokio::spawn(sequence::release(
+            self.tunables.consensus.subscribe(),
+            self.peer_context(),
+        ));
+        state.tasks.push(task);
+        let task = tokio::spawn(rotation::watch(
+            self.tunables.consensus.subscribe(),
+            self.peer_context(),
+        ));
+        state.tasks.push(task);
+        let task = tokio::spawn(rotation::batch(
+            self.tunables.consensus.subscribe(),
+            self.peer_context(),
+        ));
+        state.tasks.push(task);
+        drop(state);
+
+        recovery::restore_stored(&self.peer_context()).await;
         Ok(())
     }
 
+    /// Advertise our first listener and current game on the local network
+    fn advertise_locally(&self, discovery: &dyn LocalDiscovery, state: &NodeState) -> Result<()> {
+        let Some(listener) = state.listeners.first() else {
+            return Ok(());
+        };
+        discovery.advertise(&LocalPeer {
+            player_id: state.player_id,
+            addr: listener.local_addr(),
+            games: state.current_game.iter().cloned().collect(),
+        })
+    }
+
     /// Stop the node
     pub async fn stop(&self) -> Result<()> {
         let mut state = self.state.write().await;
@@ -76,50 +748,1108 @@ impl SwarmhostNode {
 
         state.is_running = false;
         state.connected_peers.clear();
+        state.listeners.clear();
+        state.active_bootstrap = None;
+        state.advertised_addr = None;
+        state.nat_status = NatStatus::Disabled;
+        let mapping = state.port_mapping.take();
+        state.local_peers.clear();
+        state.punches.clear();
+        state.relay_sessions.clear();
+        state.relayed.clear();
+        state.requests.clear();
+        state.dht_queries.clear();
+        state.partition = partition::Partition::default();
+        state.held_actions.clear();
+        state.paced_until = None;
+        self.metrics.held_actions.set(0);
+        if let Some(discovery) = &self.local_discovery {
+            discovery.withdraw();
+        }
+        for task in state.tasks.drain(..) {
+            task.abort();
+        }
+
+        let connections: Vec<_> = state
+            .connections
+            .drain()
+            .map(|(_, handle)| handle.close)
+            .collect();
+        drop(state);
+
+        self.close_gracefully(connections, CloseCode::Shutdown)
+            .await;
+        if let Some(mapping) = mapping
+            && let Err(e) = mapping.remove(self.port_mapper.timeout()).await
+        {
+            tracing::warn!("Could not remove the port mapping: {}", e);
+        }
+        Ok(())
+    }
+
+    /// Ask each connection to close for `reason` and wait for them to finish
+    ///
+    /// Each one delivers what it has queued and tells its peer why before its
+    /// socket closes, within `drain_timeout`.
+    async fn close_gracefully(
+        &self,
+        connections: Vec<watch::Sender<Option<CloseCode>>>,
+        reason: CloseCode,
+    ) {
+        let grace = self.config().network.drain_timeout + peers::CLOSE_GRACE;
+        for close in &connections {
+            let _ = close.send(Some(reason));
+        }
+        for close in connections {
+            if tokio::time::timeout(grace, close.closed()).await.is_err() {
+                tracing::warn!("Connection did not close within {:?}", grace);
+            }
+        }
+    }
+
+    /// Dial a peer and complete the handshake, returning its player id
+    pub async fn connect(&self, addr: SocketAddr) -> Result<PlayerId> {
+        if !self.is_running().await {
+            return Err(SwarmhostError::NotRunning);
+        }
+
+        peers::dial(self.transport.as_ref(), &[addr], None, &self.peer_context()).await
+    }
+
+    /// Connect to `peer` over a UDP hole punched with the help of `via`, a
+    /// peer both of us are already connected to
+    ///
+    /// For players that cannot be dialed because both sides are behind NAT.
+    pub async fn connect_via(&self, peer: PlayerId, via: PlayerId) -> Result<PlayerId> {
+        if !self.is_running().await {
+            return Err(SwarmhostError::NotRunning);
+        }
+
+        traversal::connect_punched(peer, via, &self.peer_context()).await
+    }
+
+    /// Connect to `peer` through `via`, which relays the session without
+    /// being able to read it
+    pub async fn connect_relayed(&self, peer: PlayerId, via: PlayerId) -> Result<PlayerId> {
+        if !self.is_running().await {
+            return Err(SwarmhostError::NotRunning);
+        }
+
+        relay::connect_relayed(peer, via, &self.peer_context()).await
+    }
+
+    /// Set the validators of the current game
+    ///
+    /// Only their votes count, a quorum of them deciding each action. At
+    /// `max_peers`, a connecting validator may displace a connection that is
+    /// not one. Validators ejected from the game for equivocating are left
+    /// out.
+    pub async fn set_validators(&self, validators: impl IntoIterator<Item = PlayerId>) {
+        let validators: HashSet<PlayerId> = validators.into_iter().collect();
+        let mut state = self.state.write().await;
+        let mut consensus = self.consensus.lock().await;
+        consensus.set_validators(validators);
+        state.validators = consensus.validators().clone();
+    }
 
+    /// Set the validators of the current game with the weight of each
+    /// one's vote, like [`set_validators`](Self::set_validators)
+    ///
+    /// A quorum is then a share of their total weight rather than of their
+    /// number, e.g. a server weighing as much as its clients together.
+    /// Weights letting one validator make a quorum alone are refused as a
+    /// config error unless `allow_dictatorship` is set.
+    pub async fn set_validator_weights(
+        &self,
+        weights: impl IntoIterator<Item = (PlayerId, u64)>,
+    ) -> Result<()> {
+        let mut state = self.state.write().await;
+        let mut consensus = self.consensus.lock().await;
+        consensus.set_membership(Membership::weighted(weights))?;
+        state.validators = consensus.validators().clone();
+        Ok(())
+    }
+
+    /// Propose adding, removing or reweighing a validator of the current
+    /// game from `activation` on in its log
+    ///
+    /// The change is an action like any other, agreed on by the validators
+    /// it replaces, and every node switches to the new ones once the
+    /// commit before `activation` is delivered; pick one far enough ahead
+    /// for the change itself to be committed first. Fails unless we are a
+    /// validator, if a removal would leave fewer than `min_validators`, or
+    /// if the change would let one validator make a quorum alone without
+    /// `allow_dictatorship`.
+    pub async fn submit_membership_change(
+        &self,
+        change: MembershipChange,
+        activation: u64,
+    ) -> Result<()> {
+        {
+            let consensus = self.consensus.lock().await;
+            if !consensus.validators().contains(&self.keypair.public_key()) {
+                return Err(SwarmhostError::validation(
+                    "Only validators may change the validators",
+                ));
+            }
+            consensus.with_membership_change(&change)?;
+        }
+        let scheduled = ScheduledChange { change, activation };
+        self.submit_action(MEMBERSHIP_CHANGE_ACTION, &scheduled.encode())
+            .await?;
         Ok(())
     }
 
+    /// Penalize a connected peer for misbehaviour seen outside the network
+    /// layer, e.g. a missed vote; enough demerits get it banned
+    pub async fn report_peer(&self, peer: PlayerId, offense: Offense) {
+        peers::penalize(peer, offense, &self.peer_context()).await
+    }
+
+    /// Traffic forwarded so far for each session this node relays
+    pub async fn relay_usage(&self) -> Vec<RelayUsage> {
+        let state = self.state.read().await;
+        state
+            .relay_sessions
+            .values()
+            .map(relay::RelaySession::usage)
+            .collect()
+    }
+
+    /// Stop relaying a session, e.g. one using more than its share; returns
+    /// whether it existed
+    pub async fn close_relay_session(&self, session: u64) -> bool {
+        relay::close_session(&mut *self.state.write().await, session)
+    }
+
+    fn peer_context(&self) -> PeerContext {
+        PeerContext {
+            local_id: self.keypair.public_key(),
+            keypair: self.keypair.clone(),
+            transport: self.transport.clone(),
+            network: self.tunables.network.subscribe(),
+            state: self.state.clone(),
+            events: self.events.clone(),
+            metrics: self.metrics.clone(),
+            upload: self.upload.clone(),
+            eviction: self.eviction.clone(),
+            dht: self.dht.clone(),
+            pex: self.pex.clone(),
+            handshakes: self.handshakes.clone(),
+            gossip: self.gossip.clone(),
+            dedup: self.dedup.clone(),
+            memory: self.memory.clone(),
+            consensus: self.consensus.clone(),
+            state_manager: self.state_manager.clone(),
+            consensus_config: self.tunables.consensus.subscribe(),
+            classifier: self.classifier.clone(),
+            validation: self.validation.clone(),
+            trace_messages: self.config.log.trace_messages,
+        }
+    }
+
     /// Check if the node is running
     pub async fn is_running(&self) -> bool {
         let state = self.state.read().await;
         state.is_running
     }
 
+    /// All addresses the node is listening on (empty when stopped)
+    pub async fn local_addr(&self) -> Vec<SocketAddr> {
+        let state = self.state.read().await;
+        state
+            .listeners
+            .iter()
+            .map(|listener| listener.local_addr())
+            .collect()
+    }
+
+    /// Connected peers with their addresses and measured RTT
+    pub async fn peers(&self) -> Vec<PeerInfo> {
+        let state = self.state.read().await;
+        state
+            .connected_peers
+            .iter()
+            .filter_map(|peer| state.connections.get(peer))
+            .map(|handle| PeerInfo {
+                queues: handle.outbound.depths(),
+                throttled: Duration::from_micros(handle.throttled.get()),
+                reconnecting: handle.parked.is_some(),
+                ..handle.info.clone()
+            })
+            .collect()
+    }
+
     /// Get the number of connected peers
     pub async fn peer_count(&self) -> usize {
         let state = self.state.read().await;
         state.connected_peers.len()
     }
 
-    /// Join a game session
-    pub async fn join_game(&self, _game_id: &str) -> Result<()> {
+    /// Get a snapshot of the node's runtime status
+    pub async fn status(&self) -> NodeStatus {
+        let state = self.state.read().await;
+        let consensus = self.consensus.lock().await;
+        NodeStatus {
+            is_running: state.is_running,
+            peer_count: state.connected_peers.len(),
+            local_addrs: state
+                .listeners
+                .iter()
+                .map(|listener| listener.local_addr())
+                .collect(),
+            active_bootstrap: state.active_bootstrap.clone(),
+            advertised_addr: state.advertised_addr,
+            nat_status: state.nat_status.clone(),
+            degraded: state.partition.is_degraded(),
+            pending_actions: consensus.undecided(),
+            held_actions: state.held_actions.len(),
+            overloaded: consensus.is_full() || intake::is_paced(&state, Instant::now()),
+        }
+    }
+
+    /// Get a snapshot of the consensus session, including whether it is
+    /// degraded for lack of reachable validators
+    pub async fn consensus_info(&self) -> ConsensusInfo {
         let state = self.state.read().await;
+        let consensus = self.consensus.lock().await;
+        let (reachable, required) = partition::reachability(&state, consensus.required_weight());
+        ConsensusInfo {
+            round: consensus.round(),
+            proposer: consensus.proposer(),
+            view: consensus.view(),
+            pending: consensus.pending().len(),
+            queued: state.held_actions.len(),
+            reachable,
+            required,
+            degraded: state.partition.is_degraded(),
+            halted: state
+                .current_game
+                .as_deref()
+                .is_some_and(|game_id| consensus.is_halted(game_id)),
+            tallies: consensus.tallies(),
+            observer_approval: consensus.observer_tallies(),
+        }
+    }
 
-        if !state.is_running {
-            return Err(SwarmhostError::Node("Node not running".to_string()));
+    /// Games being played on the local network, found by browsing for
+    /// `timeout`
+    ///
+    /// Empty when LAN discovery is disabled. Games we have not joined are
+    /// included.
+    pub async fn discover_local_games(&self, timeout: Duration) -> Vec<GameAnnouncement> {
+        let Some(discovery) = &self.local_discovery else {
+            return Vec::new();
+        };
+        let mut found = match discovery.browse() {
+            Ok(found) => found,
+            Err(e) => {
+                tracing::warn!("Local discovery failed: {}", e);
+                return Vec::new();
+            }
+        };
+
+        let local_id = self.player_id().await;
+        let mut seen: HashMap<PlayerId, LocalPeer> = HashMap::new();
+        let deadline = tokio::time::sleep(timeout);
+        tokio::pin!(deadline);
+        loop {
+            tokio::select! {
+                peer = found.recv() => match peer {
+                    Some(peer) if peer.player_id != local_id => {
+                        seen.insert(peer.player_id, peer);
+                    }
+                    Some(_) => {}
+                    None => break,
+                },
+                _ = &mut deadline => break,
+            }
+        }
+
+        network::discovery::games_of(seen.into_values())
+    }
+
+    /// Players who announced `game_id` in the DHT, found by asking the nodes
+    /// closest to it
+    ///
+    /// Fails when `enable_dht` is off.
+    pub async fn find_providers(&self, game_id: &str) -> Result<Vec<PeerRecord>> {
+        if !self.is_running().await {
+            return Err(SwarmhostError::NotRunning);
+        }
+        if self.dht.is_none() {
+            return Err(SwarmhostError::Config("enable_dht is off".to_string()));
+        }
+
+        Ok(dht::find_providers(game_id, &self.peer_context()).await)
+    }
+
+    /// Peers we know of through PEX, connected or not; empty when
+    /// `enable_pex` is off
+    pub async fn known_peers(&self) -> Vec<PeerRecord> {
+        match &self.pex {
+            Some(store) => store.lock().await.records(),
+            None => Vec::new(),
+        }
+    }
+
+    /// Join a game session
+    ///
+    /// With a bootstrap server configured, asks it for the game's peers and
+    /// dials them, and re-registers so they can find us. If the query fails
+    /// while we already have direct peers, the join still succeeds and the
+    /// query is retried in the background. If none of the peers it lists
+    /// connect and we have no others, the join fails with why: a host's own
+    /// refusal, such as [`CloseCode::Busy`] from a full one, ahead of peers
+    /// that could not be reached. With LAN discovery on, the game is
+    /// advertised locally and nodes already seen in it are dialed. With the
+    /// DHT on, the game is announced there, and when no bootstrap server
+    /// answered its other players are looked up there and dialed. Our newest
+    /// stored snapshot of the game, if any, is taken up first.
+    pub async fn join_game(&self, game_id: &str) -> Result<()> {
+        if !self.is_running().await {
+            return Err(SwarmhostError::NotRunning);
+        }
+
+        tracing::info!("Joining game: {}", game_id);
+        recovery::restore(game_id, &self.peer_context()).await?;
+
+        let Some(client) = &self.bootstrap else {
+            let committed = self.consensus.lock().await.committed(game_id);
+            peers::set_game(&mut *self.state.write().await, game_id, committed);
+            self.join_local_game().await?;
+            self.join_dht_game(game_id, true).await;
+            return Ok(());
+        };
+
+        let timeout = self.config().network.bootstrap_timeout;
+        let result = client.lock().await.query(game_id, timeout).await;
+        let found = match result {
+            Ok(found) => Some(found),
+            Err(e) if self.peer_count().await > 0 => {
+                tracing::warn!(
+                    "Bootstrap query for {} failed, retrying in the background: {}",
+                    game_id,
+                    e
+                );
+                None
+            }
+            Err(e) => return Err(e),
+        };
+
+        {
+            let committed = self.consensus.lock().await.committed(game_id);
+            let mut state = self.state.write().await;
+            peers::set_game(&mut state, game_id, committed);
+            if found.is_none() {
+                state.tasks.push(tokio::spawn(retry_query(
+                    client.clone(),
+                    game_id.to_string(),
+                    self.transport.clone(),
+                    self.peer_context(),
+                )));
+            }
+        }
+        self.bootstrap_refresh.notify_one();
+        self.join_local_game().await?;
+        self.join_dht_game(game_id, found.is_none()).await;
+
+        if let Some(found) = found {
+            let dialed = peers::dial_all(self.transport.clone(), found, &self.peer_context()).await;
+            tracing::info!(
+                "Connected to {} peers in {}",
+                dialed.connected.len(),
+                game_id
+            );
+            // Every host listed refused us or could not be reached, and no
+            // one else connected us to the game
+            if dialed.connected.is_empty()
+                && self.peer_count().await == 0
+                && let Some(e) = dialed.into_error()
+            {
+                self.leave_game().await?;
+                return Err(e);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Re-advertise with the current game and dial local nodes already in it
+    async fn join_local_game(&self) -> Result<()> {
+        let Some(discovery) = &self.local_discovery else {
+            return Ok(());
+        };
+
+        let targets = {
+            let state = self.state.read().await;
+            self.advertise_locally(discovery.as_ref(), &state)?;
+            state
+                .local_peers
+                .values()
+                .filter(|peer| should_dial_local(&state, peer))
+                .map(PeerRecord::from)
+                .collect::<Vec<_>>()
+        };
+
+        if !targets.is_empty() {
+            let dialed =
+                peers::dial_all(self.transport.clone(), targets, &self.peer_context()).await;
+            tracing::info!("Connected to {} local peers", dialed.connected.len());
+        }
+        Ok(())
+    }
+
+    /// Announce the game in the DHT and, with `search`, dial its players
+    async fn join_dht_game(&self, game_id: &str, search: bool) {
+        if self.dht.is_none() {
+            return;
+        }
+        let connected = dht::join(game_id, search, &self.peer_context()).await;
+        if search {
+            tracing::info!("Connected to {} DHT peers in {}", connected, game_id);
         }
+    }
+
+    /// Leave the current game
+    ///
+    /// Peers playing it are disconnected with [`CloseCode::Leaving`] once
+    /// what is queued for them is delivered; other peers stay connected and
+    /// are told we no longer play. Bootstrap servers and the local network
+    /// stop hearing about the game.
+    pub async fn leave_game(&self) -> Result<()> {
+        let connections = {
+            let mut state = self.state.write().await;
+            let Some(game_id) = state.current_game.take() else {
+                return Ok(());
+            };
+            tracing::info!("Leaving game: {}", game_id);
+            state.partition = partition::Partition::default();
+            state.held_actions.clear();
+            state.paced_until = None;
+            self.metrics.held_actions.set(0);
+            state.fetched = None;
+            {
+                let mut consensus = self.consensus.lock().await;
+                consensus.clear_ejected();
+                consensus.clear_fork(&game_id);
+            }
 
-        tracing::info!("Joining game: {}", _game_id);
+            let players: Vec<PlayerId> = state
+                .connections
+                .iter()
+                .filter(|(_, handle)| handle.info.game.as_deref() == Some(game_id.as_str()))
+                .map(|(peer, _)| *peer)
+                .collect();
+            let mut connections = Vec::new();
+            for peer in players {
+                state.connected_peers.retain(|p| p != &peer);
+                let Some(handle) = state.connections.remove(&peer) else {
+                    continue;
+                };
+                // A parked validator has no connection left to close
+                if handle.parked.is_none() {
+                    connections.push(handle.close);
+                    let _ = self.events.send(NodeEvent::PeerDisconnected {
+                        peer,
+                        reason: CloseCode::Leaving,
+                    });
+                }
+            }
+            peers::send_to(
+                &state,
+                &state.connected_peers,
+                network::PeerMessage::Playing {
+                    game_id: None,
+                    committed: 0,
+                },
+            );
+            if let Some(discovery) = &self.local_discovery {
+                self.advertise_locally(discovery.as_ref(), &state)?;
+            }
+            connections
+        };
+        self.bootstrap_refresh.notify_one();
 
+        self.close_gracefully(connections, CloseCode::Leaving).await;
         Ok(())
     }
 
     /// Submit an action to the network
-    pub async fn submit_action(&self, _action_type: u32, _action_data: &[u8]) -> Result<()> {
-        let state = self.state.read().await;
+    ///
+    /// With validators set, the action goes to the validator whose turn it
+    /// is to propose, or is proposed here if the turn is ours; without, it
+    /// is gossiped: sent to a few peers, who pass it on. While
+    /// the session is degraded it is refused, or held and sent once a quorum
+    /// is reachable again, as `when_degraded` says. An action of a type
+    /// [classified](Self::with_classifier) to skip consensus is checked and
+    /// delivered here, then gossiped, degraded or not.
+    ///
+    /// While `max_pending_actions` are pending, or held, or the proposer
+    /// answered one of our forwards busy, it waits up to `submit_wait` for
+    /// room, then fails as [`Overloaded`](SwarmhostError::Overloaded).
+    pub async fn submit_action(&self, action_type: u32, action_data: &[u8]) -> Result<ActionId> {
+        self.submit(action_type, action_data, &[], None, &[]).await
+    }
+
+    /// Submit an action touching the game entities in `conflict_keys`, like
+    /// [`submit_action`](Self::submit_action)
+    ///
+    /// Actions in the same round sharing a key conflict, and the game's
+    /// [`ConflictPolicy`] settles which are committed.
+    pub async fn submit_action_touching(
+        &self,
+        action_type: u32,
+        action_data: &[u8],
+        conflict_keys: &[u64],
+    ) -> Result<ActionId> {
+        self.submit(action_type, action_data, conflict_keys, None, &[])
+            .await
+    }
+
+    /// Submit an action that is only worth committing within `ttl`, like
+    /// [`submit_action`](Self::submit_action)
+    ///
+    /// The deadline is signed with the action. Proposers leave it out of
+    /// their blocks once it has passed, and validators reject it, allowing
+    /// for clocks `expiry_clock_skew` apart;
+    /// [`wait_for_commit`](Self::wait_for_commit) tells when it expired.
+    pub async fn submit_action_expiring(
+        &self,
+        action_type: u32,
+        action_data: &[u8],
+        ttl: Duration,
+    ) -> Result<ActionId> {
+        let deadline_ms = action::clock_ms(Instant::now()) + ttl.as_millis() as u64;
+        self.submit(action_type, action_data, &[], Some(deadline_ms), &[])
+            .await
+    }
+
+    /// Submit an action to be delivered only after those in `depends_on`,
+    /// like [`submit_action`](Self::submit_action)
+    ///
+    /// The dependencies are signed with the action. They hold it back only
+    /// for an action type the classifier set with
+    /// [`with_classifier`](Self::with_classifier) calls
+    /// [`Causal`](OrderingClass::Causal); it is delivered once each of them
+    /// has been delivered, or committed.
+    pub async fn submit_action_after(
+        &self,
+        action_type: u32,
+        action_data: &[u8],
+        depends_on: &[ActionId],
+    ) -> Result<ActionId> {
+        self.submit(action_type, action_data, &[], None, depends_on)
+            .await
+    }
+
+    /// Wait up to `timeout` for one of our actions to be committed
+    ///
+    /// An action with a deadline comes back [`CommitOutcome::Expired`] once
+    /// the deadline is `expiry_clock_skew` twice over behind us, when no
+    /// validator whose clock is within the skew of ours approves it any
+    /// more; it is then dropped here too. An action refused for another
+    /// reason fails with [`SwarmhostError::Rejected`], or, refused by the
+    /// validator it was forwarded to, with [`SwarmhostError::Remote`] and
+    /// that validator's code. One undecided after `timeout` fails with
+    /// [`SwarmhostError::CommitTimeout`], naming the round it was put
+    /// forward in and the validators yet to vote on it.
+    pub async fn wait_for_commit(
+        &self,
+        action_id: ActionId,
+        timeout: Duration,
+    ) -> Result<CommitOutcome> {
+        let mut events = self.events.subscribe();
+        let (game_id, held) = {
+            let state = self.state.read().await;
+            let held = state
+                .held_actions
+                .iter()
+                .find(|action| action.id() == action_id)
+                .and_then(|action| action.deadline_ms);
+            (state.current_game.clone(), held)
+        };
+        let deadline_ms = {
+            let consensus = self.consensus.lock().await;
+            let committed = game_id
+                .as_deref()
+                .and_then(|game_id| consensus.delivered_sequence(game_id, &action_id));
+            if let Some(sequence) = committed {
+                return Ok(CommitOutcome::Committed { sequence });
+            }
+            consensus.deadline_of(&action_id).or(held)
+        };
+        // By then no validator within the skew of our clock approves it
+        let skew = self.tunables.consensus.borrow().expiry_clock_skew;
+        let expiry_at = |deadline_ms: u64| {
+            let now = Instant::now();
+            let left = deadline_ms.saturating_sub(action::clock_ms(now));
+            now + Duration::from_millis(left) + skew * 2
+        };
+        let mut expiry = deadline_ms.map(expiry_at);
+        let give_up = Instant::now() + timeout;
+        loop {
+            let event = tokio::select! {
+                event = events.recv() => event,
+                _ = tokio::time::sleep_until(expiry.unwrap_or(give_up)), if expiry.is_some() => {
+                    self.consensus.lock().await.expire(&action_id);
+                    sequence::roll_back(&self.peer_context()).await;
+                    return Ok(CommitOutcome::Expired);
+                }
+                _ = tokio::time::sleep_until(give_up) => {
+                    let (round, missing_voters) =
+                        self.consensus.lock().await.awaiting_votes_on(&action_id);
+                    return Err(SwarmhostError::CommitTimeout {
+                        action_id,
+                        after: timeout,
+                        round,
+                        missing_voters,
+                    });
+                }
+            };
+            match event {
+                Ok(NodeEvent::ActionReceipt {
+                    action_id: committed,
+                    sequence,
+                }) if committed == action_id => {
+                    return Ok(CommitOutcome::Committed { sequence });
+                }
+                // Dropped here, but a proposer may have put it forward
+                // before; wait until it is too late for that too
+                Ok(NodeEvent::ActionRejected {
+                    action_id: rejected,
+                    reason: RejectionReason::Expired { deadline_ms },
+                    ..
+                }) if rejected == action_id => {
+                    expiry = Some(expiry_at(deadline_ms));
+                }
+                Ok(NodeEvent::ActionRejected {
+                    action_id: rejected,
+                    reason,
+                    ..
+                }) if rejected == action_id => {
+                    return Err(SwarmhostError::Rejected { action_id, reason });
+                }
+                Ok(NodeEvent::ActionRefused {
+                    action_id: refused,
+                    by,
+                    error,
+                }) if refused == action_id => {
+                    return Err(error.into_error(by));
+                }
+                Ok(_) | Err(broadcast::error::RecvError::Lagged(_)) => {}
+                Err(broadcast::error::RecvError::Closed) => {
+                    return Err(SwarmhostError::NotRunning);
+                }
+            }
+        }
+    }
+
+    /// Sign an action with the next nonce, once there is room for it, and
+    /// send it on its way, or hold it while degraded; one skipping consensus
+    /// is delivered here first
+    async fn submit(
+        &self,
+        action_type: u32,
+        action_data: &[u8],
+        conflict_keys: &[u64],
+        deadline_ms: Option<u64>,
+        depends_on: &[ActionId],
+    ) -> Result<ActionId> {
+        // Those skipping consensus are never pending
+        let class = ordering::class_of(action_type, self.classifier.as_ref());
+        if class == OrderingClass::Strict {
+            intake::wait_for_room(&self.peer_context()).await?;
+        }
+        let mut state = self.state.write().await;
 
         if !state.is_running {
-            return Err(SwarmhostError::Node("Node not running".to_string()));
+            return Err(SwarmhostError::NotRunning);
+        }
+
+        let game_id = state.current_game.clone().ok_or(SwarmhostError::NoGame)?;
+
+        let keypair = self
+            .config
+            .keypair
+            .as_ref()
+            .ok_or_else(|| SwarmhostError::Config("No keypair set".to_string()))?;
+
+        // Actions held earlier are still going out, and must go first; those
+        // skipping consensus need no quorum
+        let degraded = class == OrderingClass::Strict
+            && (state.partition.is_degraded() || !state.held_actions.is_empty());
+        let when_degraded = self.tunables.consensus.borrow().when_degraded;
+        if degraded && when_degraded == DegradedActions::Reject {
+            return Err(SwarmhostError::QuorumLost);
+        }
+
+        let nonce = state.next_nonce;
+        state.next_nonce += 1;
+        let mut action = SignedAction {
+            game_id,
+            actor: keypair.public_key(),
+            nonce,
+            action_type,
+            data: action_data.to_vec(),
+            conflict_keys: conflict_keys.to_vec(),
+            deadline_ms,
+            depends_on: depends_on.to_vec(),
+            signature: Vec::new(),
+        };
+        action.sign(keypair);
+        let action_id = action.id();
+        if class != OrderingClass::Strict {
+            drop(state);
+            ordering::submit(action, class, &self.peer_context()).await?;
+            return Ok(action_id);
+        }
+        if degraded {
+            self.consensus.lock().await.check_size(&action)?;
+            state.held_actions.push_back(action);
+            self.metrics
+                .held_actions
+                .set(state.held_actions.len() as u64);
+            return Ok(action_id);
         }
+        let trace = state
+            .traces
+            .as_mut()
+            .map(|traces| traces.action(action.id()));
+        let span = trace::span(Step::Propose, &state.player_id, trace.as_ref());
 
+        self.consensus.lock().await.submit_local(action.clone())?;
+        drop(state);
+
+        let ctx = self.peer_context();
+        sequence::speculate(&action, &ctx).await;
+        rotation::submit(action, trace, &ctx).instrument(span).await;
+        Ok(action_id)
+    }
+
+    /// Vote on a proposed action and gossip the vote
+    ///
+    /// Fails unless we are one of the validators. An approval stands only
+    /// if the action passes the built-in checks and those added with
+    /// [`with_validator`](Self::with_validator), the game's verdict coming
+    /// last; otherwise we reject it with the reason code of the check that
+    /// failed. If the vote settles the action, it is committed (when we are
+    /// the sequencer) or its speculation rolled back.
+    ///
+    /// The vote is persisted before it is sent. After a restart, a vote cast
+    /// on the action before it stands: the same vote is sent again, and
+    /// voting otherwise fails.
+    pub async fn vote(&self, action_id: ActionId, decision: Decision) -> Result<()> {
+        if !self.is_running().await {
+            return Err(SwarmhostError::NotRunning);
+        }
+        self.check_caught_up().await?;
+
+        let keypair = self
+            .config
+            .keypair
+            .as_ref()
+            .ok_or_else(|| SwarmhostError::Config("No keypair set".to_string()))?;
+
+        let trace = {
+            let mut state = self.state.write().await;
+            state.traces.as_mut().map(|traces| traces.action(action_id))
+        };
+        let player_id = keypair.public_key();
+        let span = trace::span(Step::Vote, &player_id, trace.as_ref());
+        let reason = match decision {
+            Decision::Approve => self.validate(&action_id).await,
+            Decision::Reject => Some(RejectCode::GAME),
+        };
+
+        let vote = {
+            let mut consensus = self.consensus.lock().await;
+            let _vote = span.enter();
+            let round = consensus.round();
+            let vote = match reason {
+                Some(reason) => Vote::rejecting(keypair, action_id, round, reason),
+                None => Vote::new(keypair, action_id, round, decision),
+            };
+            let (vote, outcome) = consensus.cast_vote(vote)?;
+            recovery::write_ahead(&consensus, &self.state_manager).await?;
+            peers::trace_commit(outcome, &action_id, trace.as_ref(), &player_id);
+            vote
+        };
+
+        let ctx = self.peer_context();
+        peers::publish(GossipPayload::Vote(vote), trace, &ctx)
+            .instrument(span)
+            .await;
+        sequence::settle(action_id, trace, &ctx).await;
+        Ok(())
+    }
+
+    /// Give our verdict on a proposed action as a neutral observer, and
+    /// gossip it
+    ///
+    /// Fails if we are one of the validators. Like a vote, an approval
+    /// stands only if the action passes our checks, and is sent as a
+    /// rejection with the failing check's reason code otherwise. Every node
+    /// tallies observer votes apart, in
+    /// [`ConsensusInfo::observer_approval`] and
+    /// [`NodeEvent::ObserverVoted`]; they never count toward the quorum.
+    pub async fn observe(&self, action_id: ActionId, decision: Decision) -> Result<()> {
+        if !self.is_running().await {
+            return Err(SwarmhostError::NotRunning);
+        }
+        self.check_caught_up().await?;
+
+        let keypair = self
+            .config
+            .keypair
+            .as_ref()
+            .ok_or_else(|| SwarmhostError::Config("No keypair set".to_string()))?;
+        let reason = match decision {
+            Decision::Approve => self.validate(&action_id).await,
+            Decision::Reject => Some(RejectCode::GAME),
+        };
+
+        let vote = {
+            let mut consensus = self.consensus.lock().await;
+            if consensus.validators().contains(&keypair.public_key()) {
+                return Err(SwarmhostError::InvalidState(
+                    "Validators vote, they do not observe".to_string(),
+                ));
+            }
+            let decision = match reason {
+                Some(_) => Decision::Reject,
+                None => decision,
+            };
+            let vote = ObserverVote::new(keypair, action_id, consensus.round(), decision, reason);
+            consensus.receive_observer_vote(vote.clone())?;
+            vote
+        };
+
+        let ctx = self.peer_context();
+        peers::publish(GossipPayload::ObserverVote(vote), None, &ctx).await;
+        Ok(())
+    }
+
+    /// Run the validation pipeline on a pending action, returning why it
+    /// failed; an action we have not received is left to the game
+    async fn validate(&self, action_id: &ActionId) -> Option<RejectCode> {
+        let ctx = self.consensus.lock().await.validation_context(action_id)?;
+        let budget = self.tunables.consensus.borrow().validation_timeout;
+        match self.validation.run(&ctx, budget).await {
+            ValidationResult::Valid => None,
+            ValidationResult::Invalid(reason) => {
+                tracing::debug!("Rejecting {}: {}", short_id(action_id), reason);
+                Some(reason)
+            }
+        }
+    }
+
+    /// Refuse to vote while catching up on the game, until
+    /// [`NodeEvent::SessionReady`]
+    async fn check_caught_up(&self) -> Result<()> {
+        let state = self.state.read().await;
+        if state.catching_up || state.recovering {
+            return Err(SwarmhostError::CatchingUp);
+        }
+        Ok(())
+    }
+
+    /// Vote on a block of actions proposed for a round, as announced by
+    /// [`NodeEvent::BlockProposed`], with `invalid` the actions in it the
+    /// game found invalid
+    ///
+    /// One vote covers every valid action in the block. The rest of its
+    /// actions go through the validation pipeline at once, up to
+    /// `max_concurrent_validations`, and those failing count as invalid
+    /// too. What becomes of the block when some are invalid is up to
+    /// `invalid_in_block`.
+    pub async fn vote_block(&self, block: Hash, invalid: &[ActionId]) -> Result<()> {
+        if !self.is_running().await {
+            return Err(SwarmhostError::NotRunning);
+        }
+        self.check_caught_up().await?;
+
+        let keypair = self
+            .config
+            .keypair
+            .as_ref()
+            .ok_or_else(|| SwarmhostError::Config("No keypair set".to_string()))?;
+
+        let contexts = self.consensus.lock().await.block_contexts(&block)?;
+        let budget = self.tunables.consensus.borrow().validation_timeout;
+        let mut checks = JoinSet::new();
+        for (index, ctx) in contexts.into_iter().enumerate() {
+            if invalid.contains(&ctx.action_id) {
+                continue;
+            }
+            let validation = self.validation.clone();
+            checks.spawn(async move {
+                let result = validation.run(&ctx, budget).await;
+                (index, ctx.action_id, result)
+            });
+        }
+        let mut failed = Vec::new();
+        while let Some(checked) = checks.join_next().await {
+            let (index, action_id, result) = checked
+                .map_err(|e| SwarmhostError::Node(format!("Validating an action failed: {}", e)))?;
+            if let ValidationResult::Invalid(reason) = result {
+                tracing::debug!("Rejecting {}: {}", short_id(&action_id), reason);
+                failed.push((index, action_id, reason));
+            }
+        }
+        failed.sort_by_key(|(index, _, _)| *index);
+        let invalid: Vec<(ActionId, RejectCode)> = invalid
+            .iter()
+            .map(|action_id| (*action_id, RejectCode::GAME))
+            .chain(
+                failed
+                    .into_iter()
+                    .map(|(_, action_id, reason)| (action_id, reason)),
+            )
+            .collect();
+
+        let votes = {
+            let mut consensus = self.consensus.lock().await;
+            let votes = consensus.vote_block(keypair, &block, &invalid)?;
+            recovery::write_ahead(&consensus, &self.state_manager).await?;
+            votes
+        };
+
+        let ctx = self.peer_context();
+        for vote in votes {
+            let action_id = vote.action_id;
+            peers::publish(GossipPayload::Vote(vote), None, &ctx).await;
+            sequence::settle(action_id, None, &ctx).await;
+        }
         Ok(())
     }
 }
 
+/// Keep our bootstrap registration fresh, re-registering early when
+/// `refresh` is notified (e.g. after joining a game)
+async fn bootstrap_task(
+    client: Arc<Mutex<BootstrapClient>>,
+    refresh: Arc<Notify>,
+    network: watch::Receiver<NetworkConfig>,
+    state: Arc<RwLock<NodeState>>,
+) {
+    loop {
+        let attempt_timeout = network.borrow().bootstrap_timeout;
+        let registration = {
+            let state = state.read().await;
+            let addrs = dht::own_addrs(&state);
+            (!addrs.is_empty()).then(|| (addrs, state.current_game.iter().cloned().collect()))
+        };
+        let Some((addrs, games)) = registration else {
+            return;
+        };
+
+        let mut client = client.lock().await;
+        let wait = match client.register(addrs, games, attempt_timeout).await {
+            Ok(ttl) => {
+                let server = client.active().map(str::to_string);
+                let mut state = state.write().await;
+                if state.active_bootstrap != server {
+                    tracing::info!(
+                        "Using bootstrap server {}",
+                        server.as_deref().unwrap_or("?")
+                    );
+                }
+                state.active_bootstrap = server;
+                ttl / 2
+            }
+            Err(e) => {
+                tracing::warn!("Bootstrap registration failed: {}", e);
+                attempt_timeout
+            }
+        };
+        drop(client);
+
+        tokio::select! {
+            _ = tokio::time::sleep(wait) => {}
+            _ = refresh.notified() => {}
+        }
+    }
+}
+
+/// Remember nodes seen on the local network and dial those in our game
+async fn local_discovery_task(
+    mut found: mpsc::UnboundedReceiver<LocalPeer>,
+    transport: Arc<dyn Transport>,
+    ctx: PeerContext,
+) {
+    while let Some(peer) = found.recv().await {
+        if peer.player_id == ctx.local_id {
+            continue;
+        }
+
+        let dial = {
+            let mut state = ctx.state.write().await;
+            let dial = should_dial_local(&state, &peer);
+            state.local_peers.insert(peer.player_id, peer.clone());
+            dial
+        };
+        if dial {
+            tracing::debug!("Found {} on the local network", short_id(&peer.player_id));
+            peers::dial_all(transport.clone(), vec![PeerRecord::from(&peer)], &ctx).await;
+        }
+    }
+}
+
+/// Whether to dial a node found on the local network
+///
+/// Both sides see each other's advertisements, so only the lower player id
+/// dials; otherwise simultaneous dials would race and both could be refused.
+fn should_dial_local(state: &NodeState, peer: &LocalPeer) -> bool {
+    state.player_id < peer.player_id
+        && !state.connections.contains_key(&peer.player_id)
+        && state
+            .current_game
+            .as_ref()
+            .is_some_and(|game| peer.games.contains(game))
+}
+
+/// Retry a failed bootstrap query until it succeeds or we leave the game
+async fn retry_query(
+    client: Arc<Mutex<BootstrapClient>>,
+    game_id: String,
+    transport: Arc<dyn Transport>,
+    ctx: PeerContext,
+) {
+    loop {
+        let timeout = ctx.network.borrow().bootstrap_timeout;
+        tokio::time::sleep(timeout).await;
+        if ctx.state.read().await.current_game.as_deref() != Some(game_id.as_str()) {
+            return;
+        }
+
+        let result = client.lock().await.query(&game_id, timeout).await;
+        match result {
+            Ok(found) => {
+                let dialed = peers::dial_all(transport, found, &ctx).await;
+                tracing::info!(
+                    "Connected to {} peers in {}",
+                    dialed.connected.len(),
+                    game_id
+                );
+                return;
+            }
+            Err(e) => tracing::warn!("Bootstrap query for {} failed: {}", game_id, e),
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::consensus::{Commit, Conflict, GRACE_EPOCH_ACTION, ObserverTally};
+    use crate::network::MappingMethod;
+    use crate::network::bootstrap::mock::MockBootstrap;
+    use crate::network::frame::MAX_FRAME_OVERHEAD;
+    use crate::network::portmap::mock::MockIgd;
+    use crate::network::proxy::mock::MockSocks5;
+    use rand::rngs::StdRng;
+    use rand::{RngCore, SeedableRng};
 
     #[tokio::test]
     async fn test_node_creation() {
@@ -160,4 +1890,5961 @@ mod tests {
         let result = node.submit_action(1, b"test").await;
         assert!(result.is_err());
     }
+
+    #[tokio::test]
+    async fn test_start_binds_configured_address() {
+        let mut config = NodeConfig::new();
+        config.network.bind_addr = "127.0.0.1".parse().unwrap();
+        let node = SwarmhostNode::new(config).unwrap();
+
+        node.start().await.unwrap();
+        let addrs = node.local_addr().await;
+        assert_eq!(addrs.len(), 1);
+        assert!(addrs[0].ip().is_loopback());
+        let port = addrs[0].port();
+
+        assert!(tokio::net::TcpStream::connect(("::1", port)).await.is_err());
+        assert!(
+            tokio::net::TcpStream::connect(("127.0.0.1", port))
+                .await
+                .is_ok()
+        );
+
+        node.stop().await.unwrap();
+        assert!(node.local_addr().await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_invalid_bind_address_is_config_error() {
+        let mut config = NodeConfig::new();
+        config.network.bind_addr = "239.1.2.3".parse().unwrap();
+
+        let result = SwarmhostNode::new(config);
+        assert!(matches!(result, Err(SwarmhostError::Config(_))));
+    }
+
+    #[tokio::test]
+    async fn test_status_reports_active_bootstrap() {
+        let dead = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let dead_addr = dead.local_addr().unwrap().to_string();
+        drop(dead);
+        let live = MockBootstrap::start().await;
+        let live_addr = live.addr.to_string();
+
+        let mut config = NodeConfig::new()
+            .with_bootstrap(dead_addr)
+            .with_bootstrap(live_addr.clone());
+        config.network.bootstrap_timeout = std::time::Duration::from_millis(500);
+        let node = SwarmhostNode::new(config).unwrap();
+        node.start().await.unwrap();
+
+        let deadline = tokio::time::Instant::now() + std::time::Duration::from_secs(1);
+        while node.status().await.active_bootstrap.is_none() {
+            assert!(tokio::time::Instant::now() < deadline);
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        }
+        assert_eq!(node.status().await.active_bootstrap, Some(live_addr));
+
+        node.stop().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_reload_config() {
+        let config = NodeConfig::new();
+        let node = SwarmhostNode::new(config.clone()).unwrap();
+        let mut network = node.tunables.network.subscribe();
+
+        let mut new = config.clone();
+        new.network.heartbeat_interval = std::time::Duration::from_secs(1);
+        new.network.max_peers = 10;
+        new.listen_port = 12345;
+
+        let diff = node.reload_config(new).unwrap();
+        assert!(
+            diff.applied
+                .contains(&"network.heartbeat_interval".to_string())
+        );
+        assert!(diff.applied.contains(&"network.max_peers".to_string()));
+        assert_eq!(diff.rejected, vec!["listen_port"]);
+
+        assert!(network.has_changed().unwrap());
+        assert_eq!(network.borrow_and_update().max_peers, 10);
+        assert_eq!(
+            node.config().network.heartbeat_interval,
+            std::time::Duration::from_secs(1)
+        );
+        assert_eq!(node.config().listen_port, config.listen_port);
+    }
+
+    #[tokio::test]
+    async fn test_reload_rejects_invalid_config() {
+        let config = NodeConfig::new();
+        let node = SwarmhostNode::new(config.clone()).unwrap();
+
+        let mut new = config;
+        new.network.peer_timeout = std::time::Duration::ZERO;
+        assert!(matches!(
+            node.reload_config(new),
+            Err(SwarmhostError::Config(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_status_reports_advertised_addr() {
+        let advertised: SocketAddr = "198.51.100.20:9000".parse().unwrap();
+        let mut config = NodeConfig::new();
+        config.network.bind_addr = "127.0.0.1".parse().unwrap();
+        config.network.nat.advertised_addr = Some(advertised);
+        let node = SwarmhostNode::new(config).unwrap();
+
+        node.start().await.unwrap();
+        let status = node.status().await;
+        assert_eq!(status.advertised_addr, Some(advertised));
+        assert_eq!(status.local_addrs.len(), 1);
+        assert!(status.local_addrs[0].ip().is_loopback());
+    }
+
+    async fn wait_for_nat_status(node: &SwarmhostNode, done: impl Fn(&NatStatus) -> bool) {
+        for _ in 0..100 {
+            if done(&node.status().await.nat_status) {
+                return;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        panic!("NAT status stuck at {:?}", node.status().await.nat_status);
+    }
+
+    #[tokio::test]
+    async fn test_upnp_maps_renews_and_removes_listen_port() {
+        let external_ip = "203.0.113.9".parse().unwrap();
+        let igd = MockIgd::start(external_ip).await;
+        let mut config = NodeConfig::new();
+        config.network.bind_addr = "127.0.0.1".parse().unwrap();
+        config.network.nat.enable_upnp = true;
+        config.network.nat.upnp_lease = Duration::from_secs(2);
+        let node = SwarmhostNode::new(config)
+            .unwrap()
+            .with_port_mapper(igd.mapper());
+
+        node.start().await.unwrap();
+        let port = node.status().await.local_addrs[0].port();
+        let external = SocketAddr::new(external_ip, port);
+        wait_for_nat_status(&node, |status| *status != NatStatus::Pending).await;
+        let status = node.status().await;
+        assert_eq!(
+            status.nat_status,
+            NatStatus::Mapped {
+                external,
+                method: MappingMethod::Upnp
+            }
+        );
+        assert_eq!(status.advertised_addr, Some(external));
+        assert!(igd.state.lock().unwrap().mappings.contains_key(&port));
+
+        // Renewed once half the lease has passed
+        for _ in 0..100 {
+            if igd.state.lock().unwrap().adds >= 2 {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+        assert!(igd.state.lock().unwrap().adds >= 2);
+
+        node.stop().await.unwrap();
+        assert!(igd.state.lock().unwrap().mappings.is_empty());
+        assert_eq!(node.status().await.nat_status, NatStatus::Disabled);
+    }
+
+    #[tokio::test]
+    async fn test_port_mapping_failure_is_not_fatal() {
+        // Neither gateway ever answers
+        let ssdp = tokio::net::UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let nat_pmp = tokio::net::UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let mapper = PortMapper::new()
+            .with_ssdp(ssdp.local_addr().unwrap())
+            .with_nat_pmp(nat_pmp.local_addr().unwrap())
+            .with_timeout(Duration::from_millis(100));
+        let mut config = NodeConfig::new();
+        config.network.bind_addr = "127.0.0.1".parse().unwrap();
+        config.network.nat.enable_upnp = true;
+        let node = SwarmhostNode::new(config).unwrap().with_port_mapper(mapper);
+
+        node.start().await.unwrap();
+        assert_eq!(node.status().await.nat_status, NatStatus::Pending);
+        wait_for_nat_status(&node, |status| matches!(status, NatStatus::Failed { .. })).await;
+        assert!(node.is_running().await);
+        assert_eq!(node.status().await.advertised_addr, None);
+    }
+
+    #[tokio::test]
+    async fn test_submit_oversized_action_rejected() {
+        let mut config = NodeConfig::new();
+        config.consensus.max_action_size = 16;
+        let node = SwarmhostNode::new(config).unwrap();
+        let mut events = node.subscribe();
+
+        node.start().await.unwrap();
+        node.join_game("game").await.unwrap();
+
+        assert!(node.submit_action(1, &[0; 16]).await.is_ok());
+
+        let result = node.submit_action(1, &[0; 17]).await;
+        assert!(matches!(result, Err(SwarmhostError::Validation(_))));
+        assert_eq!(node.metrics().actions_rejected_oversized.get(), 1);
+        // The accepted action was applied speculatively first
+        let reason = next_event(&mut events, |event| match event {
+            NodeEvent::ActionRejected { reason, .. } => Some(reason),
+            _ => None,
+        })
+        .await;
+        assert_eq!(reason, RejectionReason::Oversized { size: 17, max: 16 });
+    }
+
+    #[tokio::test]
+    async fn test_snapshot_restored_by_fresh_node() {
+        let dir = tempfile::tempdir().unwrap();
+        let snapshot = Snapshot::new("game", 100, [7; 32], b"board".to_vec());
+
+        {
+            let node = SwarmhostNode::new(NodeConfig::new().with_data_dir(dir.path())).unwrap();
+            node.state_manager
+                .lock()
+                .await
+                .save_snapshot(&snapshot)
+                .unwrap();
+        }
+
+        let node = SwarmhostNode::new(NodeConfig::new().with_data_dir(dir.path())).unwrap();
+        assert_eq!(node.latest_snapshot("game").await.unwrap(), Some(snapshot));
+        assert_eq!(node.latest_snapshot("other").await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn test_start_restores_the_newest_snapshot_that_loads() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = NodeConfig::new().with_data_dir(dir.path());
+        let actions: Vec<ActionId> = (0..30u32)
+            .map(|i| crate::crypto::hash(&i.to_be_bytes()))
+            .collect();
+        let snapshot_at = |sequence: usize| {
+            let log = ActionLog::from_entries(&actions[..sequence]).unwrap();
+            let data = bincode::serialize(&log.image()).unwrap();
+            Snapshot::new("game", log.sequence(), log.hash(), data)
+        };
+        let paths = {
+            let node = SwarmhostNode::new(config.clone()).unwrap();
+            let mut state_manager = node.state_manager.lock().await;
+            for sequence in [10, 20, 30] {
+                state_manager.save_snapshot(&snapshot_at(sequence)).unwrap();
+            }
+            let mut paths: Vec<_> = std::fs::read_dir(dir.path().join("games"))
+                .unwrap()
+                .flat_map(|game| std::fs::read_dir(game.unwrap().path()).unwrap())
+                .map(|file| file.unwrap().path())
+                .filter(|path| path.extension().is_some_and(|ext| ext == "snap"))
+                .collect();
+            paths.sort();
+            paths
+        };
+        let mut damaged = std::fs::read(&paths[2]).unwrap();
+        let last = damaged.len() - 1;
+        damaged[last] ^= 1;
+        std::fs::write(&paths[2], damaged).unwrap();
+
+        let node = SwarmhostNode::new(config).unwrap();
+        node.start().await.unwrap();
+        assert_eq!(node.consensus.lock().await.committed("game"), 20);
+        assert_eq!(
+            node.action_log("game").await,
+            Some(ActionLog::from_entries(&actions[..20]).unwrap())
+        );
+        node.stop().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_update_peer_lists_disconnects_denied() {
+        let node = SwarmhostNode::new(NodeConfig::new()).unwrap();
+        let mut events = node.subscribe();
+        let (alice, bob, carol) = ([1u8; 32], [2u8; 32], [3u8; 32]);
+        node.state
+            .write()
+            .await
+            .connected_peers
+            .extend([alice, bob, carol]);
+
+        let dropped = node
+            .update_peer_lists(Some(vec![alice]), vec![bob])
+            .await
+            .unwrap();
+        assert_eq!(dropped, vec![bob, carol]);
+        assert_eq!(node.peer_count().await, 1);
+        assert_eq!(node.config().network.denylist, vec![bob]);
+
+        assert_eq!(
+            events.recv().await.unwrap(),
+            NodeEvent::PeerDisconnected {
+                peer: bob,
+                reason: network::CloseCode::Banned
+            }
+        );
+        assert_eq!(
+            events.recv().await.unwrap(),
+            NodeEvent::PeerDisconnected {
+                peer: carol,
+                reason: network::CloseCode::NotInvited
+            }
+        );
+
+        let conflict = node.update_peer_lists(Some(vec![alice]), vec![alice]).await;
+        assert!(matches!(conflict, Err(SwarmhostError::Config(_))));
+        assert_eq!(node.config().network.denylist, vec![bob]);
+
+        // A reload edits the config the lists went into, not the one it
+        // started from
+        let mut reloaded = node.config();
+        reloaded.network.denylist = Vec::new();
+        reloaded.network.max_peers += 1;
+        node.reload_config(reloaded).unwrap();
+        assert_eq!(node.config().network.denylist, vec![bob]);
+        assert_eq!(node.config().network.allowlist, Some(vec![alice]));
+    }
+
+    /// Name, node and correlation id of every span opened with one
+    #[derive(Clone, Default)]
+    struct SpanLog(Arc<std::sync::Mutex<Vec<SpanRecord>>>);
+
+    type SpanRecord = (&'static str, String, u64);
+
+    #[derive(Default)]
+    struct SpanFields {
+        node: String,
+        correlation: Option<u64>,
+    }
+
+    impl tracing::field::Visit for SpanFields {
+        fn record_u64(&mut self, field: &tracing::field::Field, value: u64) {
+            if field.name() == "correlation" {
+                self.correlation = Some(value);
+            }
+        }
+
+        fn record_debug(&mut self, field: &tracing::field::Field, value: &dyn std::fmt::Debug) {
+            if field.name() == "node" {
+                self.node = format!("{:?}", value);
+            }
+        }
+    }
+
+    impl<S: tracing::Subscriber> tracing_subscriber::Layer<S> for SpanLog {
+        fn on_new_span(
+            &self,
+            attrs: &tracing::span::Attributes<'_>,
+            _: &tracing::span::Id,
+            _: tracing_subscriber::layer::Context<'_, S>,
+        ) {
+            let mut fields = SpanFields::default();
+            attrs.record(&mut fields);
+            if let Some(correlation) = fields.correlation {
+                let name = attrs.metadata().name();
+                self.0
+                    .lock()
+                    .unwrap()
+                    .push((name, fields.node, correlation));
+            }
+        }
+    }
+
+    impl SpanLog {
+        fn find(&self, name: &str, node: &PlayerId) -> Option<u64> {
+            let node = short_id(node);
+            self.0
+                .lock()
+                .unwrap()
+                .iter()
+                .find(|(n, at, _)| *n == name && *at == node)
+                .map(|&(_, _, correlation)| correlation)
+        }
+    }
+
+    #[tokio::test]
+    async fn test_commit_span_carries_proposal_correlation() {
+        use tracing_subscriber::layer::SubscriberExt;
+
+        let spans = SpanLog::default();
+        let _subscriber =
+            tracing::subscriber::set_default(tracing_subscriber::registry().with(spans.clone()));
+
+        let traced = || {
+            let mut config = loopback_config(TransportKind::Memory);
+            config.log.trace_messages = true;
+            let node = SwarmhostNode::new(config).unwrap();
+            async move {
+                node.start().await.unwrap();
+                node
+            }
+        };
+        let (a, b) = (traced().await, traced().await);
+        a.connect(b.local_addr().await[0]).await.unwrap();
+        wait_for_peers(&b, 1).await;
+        let (a_id, b_id) = (a.player_id().await, b.player_id().await);
+        for node in [&a, &b] {
+            node.join_game("game").await.unwrap();
+            node.set_validators([a_id, b_id]).await;
+        }
+
+        a.submit_action(1, b"move").await.unwrap();
+        let action_id = a.consensus.lock().await.pending()[0].id();
+        let deadline = tokio::time::Instant::now() + Duration::from_secs(2);
+        while b.consensus.lock().await.pending().is_empty() {
+            assert!(
+                tokio::time::Instant::now() < deadline,
+                "action never arrived"
+            );
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        b.vote(action_id, Decision::Approve).await.unwrap();
+        a.vote(action_id, Decision::Approve).await.unwrap();
+
+        let proposed = spans.find("propose", &a_id).expect("proposal span");
+        let committed = loop {
+            if let Some(committed) = spans.find("commit", &b_id) {
+                break committed;
+            }
+            assert!(tokio::time::Instant::now() < deadline, "never committed");
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        };
+        assert_eq!(committed, proposed);
+        for step in ["receive", "validate", "vote"] {
+            assert_eq!(spans.find(step, &b_id), Some(proposed), "{} span", step);
+        }
+    }
+
+    fn loopback_config(transport: TransportKind) -> NodeConfig {
+        let mut config = NodeConfig::new();
+        config.network.bind_addr = "127.0.0.1".parse().unwrap();
+        config.network.transport = transport;
+        config
+    }
+
+    async fn wait_for_peers(node: &SwarmhostNode, count: usize) {
+        for _ in 0..100 {
+            if node.peer_count().await == count {
+                return;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+        }
+        panic!("expected {} peers, have {}", count, node.peer_count().await);
+    }
+
+    /// Same scenario for every transport; only the config differs
+    async fn connect_and_disconnect(transport: TransportKind) {
+        let node_a = SwarmhostNode::new(loopback_config(transport)).unwrap();
+        let node_b = SwarmhostNode::new(loopback_config(transport)).unwrap();
+        node_a.start().await.unwrap();
+        node_b.start().await.unwrap();
+        let mut events_a = node_a.subscribe();
+        let mut events_b = node_b.subscribe();
+
+        let addr = node_a.local_addr().await[0];
+        let peer = node_b.connect(addr).await.unwrap();
+        assert_eq!(peer, node_a.player_id().await);
+        wait_for_peers(&node_a, 1).await;
+        assert_eq!(node_b.peer_count().await, 1);
+
+        match events_a.recv().await.unwrap() {
+            NodeEvent::PeerConnected { peer, .. } => assert_eq!(peer, node_b.player_id().await),
+            other => panic!("unexpected event {:?}", other),
+        }
+        assert!(matches!(
+            events_b.recv().await.unwrap(),
+            NodeEvent::PeerConnected { .. }
+        ));
+
+        assert!(node_b.connect(addr).await.is_err());
+        assert_eq!(node_b.peer_count().await, 1);
+
+        node_a.stop().await.unwrap();
+        wait_for_peers(&node_b, 0).await;
+        assert_eq!(
+            events_b.recv().await.unwrap(),
+            NodeEvent::PeerDisconnected {
+                peer,
+                reason: CloseCode::Shutdown
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn test_nodes_connect_over_tcp() {
+        connect_and_disconnect(TransportKind::Tcp).await;
+    }
+
+    #[tokio::test]
+    async fn test_nodes_connect_over_quic() {
+        connect_and_disconnect(TransportKind::Quic).await;
+    }
+
+    #[cfg(feature = "websocket")]
+    #[tokio::test]
+    async fn test_nodes_connect_over_websocket() {
+        connect_and_disconnect(TransportKind::WebSocket).await;
+    }
+
+    #[tokio::test]
+    async fn test_nodes_connect_over_memory() {
+        connect_and_disconnect(TransportKind::Memory).await;
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_heartbeats_keep_peers_connected() {
+        let node_a = SwarmhostNode::new(loopback_config(TransportKind::Memory)).unwrap();
+        let node_b = SwarmhostNode::new(loopback_config(TransportKind::Memory)).unwrap();
+        node_a.start().await.unwrap();
+        node_b.start().await.unwrap();
+
+        node_b.connect(node_a.local_addr().await[0]).await.unwrap();
+        let timeout = node_a.config().network.peer_timeout;
+        tokio::time::sleep(timeout * 3).await;
+
+        for node in [&node_a, &node_b] {
+            let peers = node.peers().await;
+            assert_eq!(peers.len(), 1);
+            assert!(peers[0].rtt.is_some());
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_silent_peer_dropped_at_timeout() {
+        let node = SwarmhostNode::new(loopback_config(TransportKind::Memory)).unwrap();
+        node.start().await.unwrap();
+        let mut events = node.subscribe();
+        let config = node.config().network;
+
+        // A peer that completes the handshake and then never answers
+        let silent_key = crate::crypto::KeyPair::generate();
+        let silent = silent_key.public_key();
+        let conn = network::MemoryNetwork::global()
+            .transport(&config)
+            .dial(node.local_addr().await[0])
+            .await
+            .unwrap();
+        let _channel =
+            network::SecureChannel::establish(conn, &config, &silent_key, network::Role::Initiator)
+                .await
+                .unwrap();
+
+        assert!(matches!(
+            events.recv().await.unwrap(),
+            NodeEvent::PeerConnected { .. }
+        ));
+        let connected_at = tokio::time::Instant::now();
+
+        assert_eq!(
+            events.recv().await.unwrap(),
+            NodeEvent::PeerDisconnected {
+                peer: silent,
+                reason: CloseCode::Timeout
+            }
+        );
+        assert_eq!(connected_at.elapsed(), config.peer_timeout);
+        assert_eq!(node.peer_count().await, 0);
+    }
+
+    fn bootstrapped_config(server: &MockBootstrap) -> NodeConfig {
+        let mut config =
+            loopback_config(TransportKind::Tcp).with_bootstrap(server.addr.to_string());
+        config.network.bootstrap_timeout = std::time::Duration::from_millis(500);
+        config
+    }
+
+    #[tokio::test]
+    async fn test_join_game_discovers_peers_via_bootstrap() {
+        let server = MockBootstrap::start().await;
+        let node_a = SwarmhostNode::new(bootstrapped_config(&server)).unwrap();
+        let node_b = SwarmhostNode::new(bootstrapped_config(&server)).unwrap();
+        node_a.start().await.unwrap();
+        node_b.start().await.unwrap();
+
+        node_a.join_game("game").await.unwrap();
+        let a_id = node_a.player_id().await;
+        let deadline = tokio::time::Instant::now() + std::time::Duration::from_secs(2);
+        while !server
+            .registrations()
+            .iter()
+            .any(|r| r.player_id == a_id && r.games == ["game"])
+        {
+            assert!(tokio::time::Instant::now() < deadline);
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        }
+
+        node_b.join_game("game").await.unwrap();
+        assert_eq!(node_b.peer_count().await, 1);
+        wait_for_peers(&node_a, 1).await;
+    }
+
+    #[tokio::test]
+    async fn test_join_game_through_socks5_proxy() {
+        let server = MockBootstrap::start().await;
+        let mut proxy = MockSocks5::start(Some(("player", "secret"))).await;
+        let node_a = SwarmhostNode::new(bootstrapped_config(&server)).unwrap();
+        node_a.start().await.unwrap();
+        node_a.join_game("game").await.unwrap();
+        let a_addr = node_a.local_addr().await[0];
+
+        // Named by hostname, which only the proxy resolves
+        let bootstrap = format!("localhost:{}", server.addr.port());
+        let mut config = loopback_config(TransportKind::Tcp).with_bootstrap(bootstrap.clone());
+        config.network.bootstrap_timeout = std::time::Duration::from_millis(500);
+        config.network.outbound_proxy = Some(ProxyConfig {
+            addr: proxy.addr.to_string(),
+            username: Some("player".to_string()),
+            password: Some("secret".to_string()),
+        });
+        let node_b = SwarmhostNode::new(config).unwrap();
+        node_b.start().await.unwrap();
+
+        let deadline = tokio::time::Instant::now() + std::time::Duration::from_secs(2);
+        while !server.registrations().iter().any(|r| r.games == ["game"]) {
+            assert!(tokio::time::Instant::now() < deadline);
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        }
+        node_b.join_game("game").await.unwrap();
+        assert_eq!(node_b.peer_count().await, 1);
+        wait_for_peers(&node_a, 1).await;
+
+        let mut requested = Vec::new();
+        while let Ok(target) = proxy.requests.try_recv() {
+            requested.push(target);
+        }
+        assert!(requested.contains(&bootstrap), "{:?}", requested);
+        let a_port = format!(":{}", a_addr.port());
+        assert!(
+            requested.iter().any(|target| target.ends_with(&a_port)),
+            "{:?}",
+            requested
+        );
+    }
+
+    #[tokio::test]
+    async fn test_join_game_tolerates_bootstrap_failure_with_direct_peers() {
+        let dead = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let dead_addr = dead.local_addr().unwrap().to_string();
+        drop(dead);
+
+        let mut config = loopback_config(TransportKind::Tcp).with_bootstrap(dead_addr);
+        config.network.bootstrap_timeout = std::time::Duration::from_millis(100);
+        let node = SwarmhostNode::new(config).unwrap();
+        node.start().await.unwrap();
+
+        // No peers yet, so the failed query fails the join
+        assert!(node.join_game("game").await.is_err());
+
+        let direct = SwarmhostNode::new(loopback_config(TransportKind::Tcp)).unwrap();
+        direct.start().await.unwrap();
+        node.connect(direct.local_addr().await[0]).await.unwrap();
+
+        node.join_game("game").await.unwrap();
+        assert_eq!(node.peer_count().await, 1);
+    }
+
+    #[tokio::test]
+    async fn test_join_game_fails_with_the_refusal_of_a_full_host() {
+        let server = MockBootstrap::start().await;
+        let mut config = bootstrapped_config(&server);
+        config.network.max_peers = 1;
+        let host = SwarmhostNode::new(config).unwrap();
+        host.start().await.unwrap();
+        host.join_game("game").await.unwrap();
+        let filler = SwarmhostNode::new(loopback_config(TransportKind::Tcp)).unwrap();
+        filler.start().await.unwrap();
+        filler.connect(host.local_addr().await[0]).await.unwrap();
+        wait_for_peers(&host, 1).await;
+
+        let host_id = host.player_id().await;
+        let deadline = tokio::time::Instant::now() + std::time::Duration::from_secs(2);
+        while !server
+            .registrations()
+            .iter()
+            .any(|r| r.player_id == host_id)
+        {
+            assert!(tokio::time::Instant::now() < deadline);
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        }
+
+        let joiner = SwarmhostNode::new(bootstrapped_config(&server)).unwrap();
+        joiner.start().await.unwrap();
+        let err = joiner.join_game("game").await.unwrap_err();
+        match &err {
+            SwarmhostError::Handshake { code, .. } => assert_eq!(*code, CloseCode::Busy),
+            other => panic!("expected the host's refusal, got {:?}", other),
+        }
+        assert_eq!(err.code(), crate::error::ErrorCode::PeerBusy);
+        assert!(err.is_retryable());
+        assert_eq!(joiner.state.read().await.current_game, None);
+        assert_eq!(host.peer_count().await, 1);
+    }
+
+    fn lan_config() -> NodeConfig {
+        let mut config = loopback_config(TransportKind::Memory);
+        config.network.enable_mdns = true;
+        config
+    }
+
+    #[tokio::test]
+    async fn test_local_nodes_find_each_other_without_bootstrap() {
+        let node_a = SwarmhostNode::new(lan_config()).unwrap();
+        let node_b = SwarmhostNode::new(lan_config()).unwrap();
+        node_a.start().await.unwrap();
+        node_b.start().await.unwrap();
+
+        node_a.join_game("lan-couch").await.unwrap();
+        node_b.join_game("lan-couch").await.unwrap();
+        wait_for_peers(&node_a, 1).await;
+        wait_for_peers(&node_b, 1).await;
+
+        // Re-advertising must not open a second connection to the same node
+        node_a.join_game("lan-couch").await.unwrap();
+        tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+        assert_eq!(node_a.peer_count().await, 1);
+        assert_eq!(node_b.peer_count().await, 1);
+
+        node_a.stop().await.unwrap();
+        node_b.stop().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_discover_local_games_lists_unjoined_games() {
+        let host = SwarmhostNode::new(lan_config()).unwrap();
+        let browser = SwarmhostNode::new(lan_config()).unwrap();
+        host.start().await.unwrap();
+        browser.start().await.unwrap();
+        host.join_game("lan-lobby").await.unwrap();
+
+        let games = browser
+            .discover_local_games(std::time::Duration::from_millis(50))
+            .await;
+        let lobby = games
+            .iter()
+            .find(|game| game.game_id == "lan-lobby")
+            .expect("lobby advertised");
+        assert_eq!(lobby.peers.len(), 1);
+        assert_eq!(lobby.peers[0].player_id, host.player_id().await);
+        assert_eq!(browser.peer_count().await, 0);
+
+        let disabled = SwarmhostNode::new(loopback_config(TransportKind::Memory)).unwrap();
+        assert!(
+            disabled
+                .discover_local_games(std::time::Duration::from_millis(10))
+                .await
+                .is_empty()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_actions_and_votes_gossip_across_hops() {
+        let nodes: Vec<SwarmhostNode> = (0..3)
+            .map(|_| SwarmhostNode::new(loopback_config(TransportKind::Memory)).unwrap())
+            .collect();
+        for node in &nodes {
+            node.start().await.unwrap();
+            node.join_game("gossip").await.unwrap();
+        }
+
+        // A line: 0 - 1 - 2, so 2 only hears from 0 through 1
+        let middle = nodes[1].local_addr().await[0];
+        nodes[0].connect(middle).await.unwrap();
+        nodes[2].connect(middle).await.unwrap();
+        wait_for_peers(&nodes[1], 2).await;
+        let mut ids = Vec::new();
+        for node in &nodes {
+            ids.push(node.player_id().await);
+        }
+        for node in &nodes {
+            node.set_validators(ids.clone()).await;
+        }
+
+        nodes[0].submit_action(1, b"jump").await.unwrap();
+        let action_id = nodes[0].consensus.lock().await.pending()[0].id();
+
+        let deadline = tokio::time::Instant::now() + std::time::Duration::from_secs(2);
+        while nodes[2].consensus.lock().await.pending().is_empty() {
+            assert!(
+                tokio::time::Instant::now() < deadline,
+                "action never arrived"
+            );
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        }
+        assert_eq!(nodes[2].consensus.lock().await.pending()[0].id(), action_id);
+
+        nodes[2].vote(action_id, Decision::Approve).await.unwrap();
+        while nodes[0].consensus.lock().await.votes(&action_id).is_empty() {
+            assert!(tokio::time::Instant::now() < deadline, "vote never arrived");
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        }
+
+        // Echoes of our own action never come back as a second copy
+        tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+        assert_eq!(nodes[0].consensus.lock().await.pending().len(), 1);
+        assert_eq!(nodes[1].consensus.lock().await.pending().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_connect_via_mutual_peer_punches_direct_path() {
+        let nodes: Vec<SwarmhostNode> = (0..3)
+            .map(|_| SwarmhostNode::new(loopback_config(TransportKind::Memory)).unwrap())
+            .collect();
+        for node in &nodes {
+            node.start().await.unwrap();
+        }
+        let middle = nodes[1].local_addr().await[0];
+        nodes[0].connect(middle).await.unwrap();
+        nodes[2].connect(middle).await.unwrap();
+        wait_for_peers(&nodes[1], 2).await;
+
+        let (first, hub, last) = (
+            nodes[0].player_id().await,
+            nodes[1].player_id().await,
+            nodes[2].player_id().await,
+        );
+        assert_eq!(nodes[0].connect_via(last, hub).await.unwrap(), last);
+        wait_for_peers(&nodes[2], 2).await;
+
+        let path_to = |peers: Vec<PeerInfo>, id| {
+            peers
+                .into_iter()
+                .find(|info| info.player_id == id)
+                .map(|info| info.path)
+        };
+        assert_eq!(
+            path_to(nodes[0].peers().await, last),
+            Some(ConnectionPath::HolePunched)
+        );
+        assert_eq!(
+            path_to(nodes[2].peers().await, first),
+            Some(ConnectionPath::HolePunched)
+        );
+        assert_eq!(
+            path_to(nodes[0].peers().await, hub),
+            Some(ConnectionPath::Direct)
+        );
+
+        // A go-between that does not know the target says so quickly
+        let stranger = KeyPair::generate().public_key();
+        let result = tokio::time::timeout(
+            std::time::Duration::from_secs(1),
+            nodes[0].connect_via(stranger, hub),
+        )
+        .await
+        .expect("refusal arrives before the punch window");
+        assert!(result.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_proxied_node_never_punches_or_asks_stun() {
+        let stun = tokio::net::UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let mut proxied = loopback_config(TransportKind::Memory);
+        proxied.network.outbound_proxy = Some(ProxyConfig {
+            addr: "127.0.0.1:1".to_string(),
+            username: None,
+            password: None,
+        });
+        proxied.network.nat.stun_servers = vec![stun.local_addr().unwrap().to_string()];
+        let configs = [
+            proxied,
+            loopback_config(TransportKind::Memory),
+            loopback_config(TransportKind::Memory),
+        ];
+        let nodes: Vec<SwarmhostNode> = configs
+            .into_iter()
+            .map(|config| SwarmhostNode::new(config).unwrap())
+            .collect();
+        for node in &nodes {
+            node.start().await.unwrap();
+        }
+        let middle = nodes[1].local_addr().await[0];
+        nodes[0].connect(middle).await.unwrap();
+        nodes[2].connect(middle).await.unwrap();
+        wait_for_peers(&nodes[1], 2).await;
+        let (first, hub, last) = (
+            nodes[0].player_id().await,
+            nodes[1].player_id().await,
+            nodes[2].player_id().await,
+        );
+
+        // Not when asked to, nor when offered a punch
+        let err = nodes[0].connect_via(last, hub).await.unwrap_err();
+        assert!(err.to_string().contains("outbound_proxy"), "{}", err);
+        let declined = tokio::time::timeout(
+            std::time::Duration::from_secs(1),
+            nodes[2].connect_via(first, hub),
+        )
+        .await
+        .expect("the proxied node declines before the punch window");
+        assert!(declined.is_err());
+
+        // A peer it cannot dial is left to the relays
+        let unreachable = PeerRecord::at(last, "127.0.0.1:1".parse().unwrap());
+        let dialed = peers::dial_all(
+            nodes[0].transport.clone(),
+            vec![unreachable],
+            &nodes[0].peer_context(),
+        )
+        .await;
+        let [(peer, err)] = &dialed.failed[..] else {
+            panic!("expected one failure, got {:?}", dialed);
+        };
+        assert_eq!(*peer, last);
+        assert!(err.to_string().contains("outbound_proxy"), "{}", err);
+        assert!(nodes[0].state.read().await.punches.is_empty());
+        assert_eq!(nodes[0].peer_count().await, 1);
+
+        let mut query = [0; 64];
+        let asked = tokio::time::timeout(
+            std::time::Duration::from_millis(100),
+            stun.recv_from(&mut query),
+        )
+        .await;
+        assert!(asked.is_err(), "STUN went around the proxy");
+    }
+
+    #[tokio::test]
+    async fn test_unreachable_peers_reach_quorum_through_relay() {
+        let mut relay_config = loopback_config(TransportKind::Memory);
+        relay_config.network.relay.enabled = true;
+        // Each player gets its own key
+        let player_config = || {
+            let mut config = loopback_config(TransportKind::Memory);
+            config.network.allow_relay = true;
+            config.network.nat.hole_punching = false;
+            config
+        };
+
+        let relay = SwarmhostNode::new(relay_config).unwrap();
+        let a = SwarmhostNode::new(player_config()).unwrap();
+        let b = SwarmhostNode::new(player_config()).unwrap();
+        for node in [&relay, &a, &b] {
+            node.start().await.unwrap();
+            node.join_game("relayed").await.unwrap();
+        }
+        let relay_addr = relay.local_addr().await[0];
+        a.connect(relay_addr).await.unwrap();
+        b.connect(relay_addr).await.unwrap();
+        wait_for_peers(&relay, 2).await;
+
+        let (relay_id, b_id) = (relay.player_id().await, b.player_id().await);
+        for _ in 0..100 {
+            if a.peers().await.iter().any(|info| info.relay.is_some()) {
+                break;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        }
+
+        // B's address goes nowhere, so only the relay can connect us
+        let unreachable = PeerRecord::at(b_id, "127.0.0.1:1".parse().unwrap());
+        let dialed =
+            peers::dial_all(a.transport.clone(), vec![unreachable], &a.peer_context()).await;
+        assert_eq!(dialed.connected, [b_id]);
+        wait_for_peers(&b, 2).await;
+
+        let path_to = |peers: Vec<PeerInfo>, id| {
+            peers
+                .into_iter()
+                .find(|info| info.player_id == id)
+                .map(|info| info.path)
+        };
+        let relayed = Some(ConnectionPath::Relayed { via: relay_id });
+        assert_eq!(path_to(a.peers().await, b_id), relayed);
+        assert_eq!(path_to(b.peers().await, a.player_id().await), relayed);
+        let sessions = relay.relay_usage().await;
+        assert_eq!(sessions.len(), 1);
+        let before = sessions[0].bytes;
+        assert!(before > 0, "handshake went through the relay");
+        let validators = [a.player_id().await, relay_id, b_id];
+        for node in [&relay, &a, &b] {
+            node.set_validators(validators).await;
+        }
+
+        a.submit_action(1, b"relayed move").await.unwrap();
+        let action_id = a.consensus.lock().await.pending()[0].id();
+        let deadline = tokio::time::Instant::now() + std::time::Duration::from_secs(2);
+        for voter in [&relay, &b] {
+            while voter.consensus.lock().await.pending().is_empty() {
+                assert!(
+                    tokio::time::Instant::now() < deadline,
+                    "action never arrived"
+                );
+                tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+            }
+            voter.vote(action_id, Decision::Approve).await.unwrap();
+        }
+
+        let quorum = a.config.consensus.required_weight(3) as usize;
+        while a.consensus.lock().await.votes(&action_id).len() < quorum {
+            assert!(tokio::time::Instant::now() < deadline, "no quorum");
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        }
+        assert!(relay.relay_usage().await[0].bytes > before);
+
+        // The relay can cut the session off, which drops the link between A
+        // and B; as validators they keep each other's place to reconnect
+        assert!(relay.close_relay_session(sessions[0].session).await);
+        let a_id = a.player_id().await;
+        for (node, peer) in [(&a, b_id), (&b, a_id)] {
+            let reconnecting = || async {
+                node.peers()
+                    .await
+                    .iter()
+                    .any(|info| info.player_id == peer && info.reconnecting)
+            };
+            while !reconnecting().await {
+                assert!(tokio::time::Instant::now() < deadline, "link not dropped");
+                tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_consensus_overtakes_saturating_bulk_traffic() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        use std::time::Duration;
+
+        // Uncompressed, so the transport only buffers a few bulk frames
+        let uncompressed = || {
+            let mut config = loopback_config(TransportKind::Memory);
+            config.network.compression.algorithm = CompressionAlgorithm::None;
+            SwarmhostNode::new(config).unwrap()
+        };
+        let (a, b) = (uncompressed(), uncompressed());
+        for node in [&a, &b] {
+            node.start().await.unwrap();
+            node.join_game("queues").await.unwrap();
+        }
+        let b_id = a.connect(b.local_addr().await[0]).await.unwrap();
+        let a_id = a.player_id().await;
+        wait_for_peers(&b, 1).await;
+
+        // B counts bulk frames as they arrive, and stalls on consensus as
+        // soon as it reads a proposal, so the count stops where it arrived
+        let (frames, mut arriving) = mpsc::channel(64);
+        b.state.write().await.relayed.insert((a_id, 7), frames);
+        let received = Arc::new(AtomicUsize::new(0));
+        let counter = received.clone();
+        tokio::spawn(async move {
+            while arriving.recv().await.is_some() {
+                counter.fetch_add(1, Ordering::SeqCst);
+            }
+        });
+        let stalled = b.consensus.lock().await;
+
+        let outbound = a.state.read().await.connections[&b_id].outbound.clone();
+        let producer = tokio::spawn(async move {
+            for _ in 0..2000 {
+                let frame = network::PeerMessage::RelayData {
+                    session: 7,
+                    payload: vec![0; 16 * 1024],
+                };
+                if outbound.send(frame).await.is_err() {
+                    break;
+                }
+            }
+        });
+
+        let bulk_capacity = a.config().network.outbound.bulk_capacity;
+        let deadline = tokio::time::Instant::now() + Duration::from_secs(5);
+        while a.peers().await[0].queues.bulk < bulk_capacity {
+            assert!(
+                tokio::time::Instant::now() < deadline,
+                "bulk queue never filled"
+            );
+            tokio::task::yield_now().await;
+        }
+
+        let before = received.load(Ordering::SeqCst);
+        a.submit_action(1, b"urgent").await.unwrap();
+        loop {
+            let seen = received.load(Ordering::SeqCst);
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            if received.load(Ordering::SeqCst) == seen {
+                break;
+            }
+            assert!(tokio::time::Instant::now() < deadline, "B never stalled");
+        }
+
+        // Only frames already written to the transport got ahead of the
+        // proposal, not the backlog queued behind them
+        let overtaken = received.load(Ordering::SeqCst) - before;
+        assert!(
+            overtaken < bulk_capacity,
+            "{} bulk frames overtook the proposal",
+            overtaken
+        );
+        assert!(!producer.is_finished());
+
+        drop(stalled);
+        while b.consensus.lock().await.pending().is_empty() {
+            assert!(
+                tokio::time::Instant::now() < deadline,
+                "proposal never applied"
+            );
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        producer.abort();
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_bulk_message_above_frame_limit_sent_in_fragments() {
+        let small_frames = || {
+            let mut config = loopback_config(TransportKind::Memory);
+            config.network.max_message_size = 64 * 1024;
+            config.consensus.max_action_size = 64 * 1024 - MAX_FRAME_OVERHEAD;
+            SwarmhostNode::new(config).unwrap()
+        };
+        let (a, b) = (small_frames(), small_frames());
+        a.start().await.unwrap();
+        b.start().await.unwrap();
+        let b_id = a.connect(b.local_addr().await[0]).await.unwrap();
+        let a_id = a.player_id().await;
+        wait_for_peers(&b, 1).await;
+
+        let (frames, mut arriving) = mpsc::channel(1);
+        b.state.write().await.relayed.insert((a_id, 7), frames);
+
+        // Seeded, so the run is repeatable, but with no pattern that a
+        // fragment reassembled out of place could match
+        let mut payload = vec![0; 5 << 20];
+        StdRng::seed_from_u64(335).fill_bytes(&mut payload);
+        let outbound = a.state.read().await.connections[&b_id].outbound.clone();
+        let message = network::PeerMessage::RelayData {
+            session: 7,
+            payload: payload.clone(),
+        };
+        outbound.send(message).await.unwrap();
+
+        let received = tokio::time::timeout(std::time::Duration::from_secs(10), arriving.recv())
+            .await
+            .expect("payload never arrived")
+            .unwrap();
+        assert!(received[..] == payload[..], "payload was corrupted");
+        assert_eq!(b.peer_count().await, 1);
+    }
+
+    /// Wait for the first event `matches` picks out
+    async fn next_event<T>(
+        events: &mut broadcast::Receiver<NodeEvent>,
+        matches: impl Fn(NodeEvent) -> Option<T>,
+    ) -> T {
+        loop {
+            if let Some(found) = matches(events.recv().await.unwrap()) {
+                return found;
+            }
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_partitioned_node_degrades_and_sends_held_actions_after_healing() {
+        let sim = network::SimNetwork::new(5);
+        let mut config = loopback_config(TransportKind::Memory);
+        config.network.reconnect.window = Duration::from_secs(60);
+        config.consensus.when_degraded = DegradedActions::Queue;
+        let mut nodes = Vec::new();
+        for _ in 0..3 {
+            let mut config = config.clone();
+            config.keypair = Some(KeyPair::generate());
+            let transport = sim.transport(&config.network);
+            let node = SwarmhostNode::new(config)
+                .unwrap()
+                .with_transport(transport);
+            node.start().await.unwrap();
+            node.join_game("game").await.unwrap();
+            nodes.push(node);
+        }
+        let mut addrs = Vec::new();
+        let mut ids = Vec::new();
+        for node in &nodes {
+            addrs.push(node.local_addr().await[0]);
+            ids.push(node.player_id().await);
+        }
+        for node in &nodes {
+            node.set_validators(ids.clone()).await;
+        }
+        // A dials both, so it is the one that re-dials after healing
+        let (a, b) = (&nodes[0], &nodes[1]);
+        a.connect(addrs[1]).await.unwrap();
+        a.connect(addrs[2]).await.unwrap();
+        b.connect(addrs[2]).await.unwrap();
+        for node in &nodes {
+            wait_for_peers(node, 2).await;
+        }
+        let mut events = a.subscribe();
+
+        // Cut A off from the other two, who still make a quorum of three
+        let cut = network::NetworkConditions::perfect().with_loss(1.0);
+        sim.set_conditions(addrs[0], addrs[1], cut.clone());
+        sim.set_conditions(addrs[0], addrs[2], cut);
+        let partitioned = tokio::time::Instant::now();
+
+        let lost = next_event(&mut events, |event| match event {
+            NodeEvent::QuorumLost {
+                reachable,
+                required,
+            } => Some((reachable, required)),
+            _ => None,
+        })
+        .await;
+        assert_eq!(lost, (1, 2));
+        // Noticing the drop takes up to a peer timeout, then the grace
+        // period runs, checked once a heartbeat
+        let network = &config.network;
+        let grace = config.consensus.consensus_timeout * config.consensus.quorum_loss_timeouts;
+        let elapsed = partitioned.elapsed();
+        assert!(elapsed >= grace, "degraded after {:?}", elapsed);
+        assert!(
+            elapsed <= network.peer_timeout + network.heartbeat_interval * 2 + grace,
+            "degraded after {:?}",
+            elapsed
+        );
+        assert!(a.status().await.degraded);
+        assert!(!b.status().await.degraded);
+
+        for i in 0..3u8 {
+            a.submit_action(1, &[i]).await.unwrap();
+        }
+        let info = a.consensus_info().await;
+        assert!(info.degraded);
+        assert_eq!((info.queued, info.pending), (3, 0));
+
+        sim.set_conditions(addrs[0], addrs[1], network::NetworkConditions::perfect());
+        sim.set_conditions(addrs[0], addrs[2], network::NetworkConditions::perfect());
+        next_event(&mut events, |event| {
+            matches!(event, NodeEvent::QuorumRestored { .. }).then_some(())
+        })
+        .await;
+        assert!(!a.status().await.degraded);
+
+        // Every held action reaches the others in the order it was submitted
+        let a_id = ids[0];
+        let quorum = config.consensus.required_weight(3) as usize;
+        let deadline = tokio::time::Instant::now() + Duration::from_secs(10);
+        let mut voted = vec![HashSet::new(); nodes.len()];
+        loop {
+            let mut committed = a.consensus_info().await.queued == 0;
+            for (node, voted) in nodes.iter().zip(&mut voted).skip(1) {
+                let held: Vec<SignedAction> = {
+                    let consensus = node.consensus.lock().await;
+                    consensus
+                        .pending()
+                        .iter()
+                        .filter(|action| action.actor == a_id)
+                        .cloned()
+                        .collect()
+                };
+                let order: Vec<u8> = held.iter().map(|action| action.data[0]).collect();
+                assert!(order.is_sorted(), "reordered: {:?}", order);
+                for action in &held {
+                    if voted.insert(action.id()) {
+                        node.vote(action.id(), Decision::Approve).await.unwrap();
+                    }
+                }
+                committed &= held.len() == 3;
+            }
+            {
+                let consensus = a.consensus.lock().await;
+                committed &= consensus.pending().len() == 3
+                    && consensus
+                        .pending()
+                        .iter()
+                        .all(|action| consensus.votes(&action.id()).len() >= quorum);
+            }
+            if committed {
+                break;
+            }
+            assert!(
+                tokio::time::Instant::now() < deadline,
+                "held actions never committed"
+            );
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+    }
+
+    /// Two started dual-stack nodes on `sim`
+    async fn dual_stack_pair(sim: &network::SimNetwork) -> (SwarmhostNode, SwarmhostNode) {
+        let mut nodes = Vec::new();
+        for _ in 0..2 {
+            let mut config = loopback_config(TransportKind::Memory);
+            config.network.dual_stack = true;
+            let transport = sim.transport(&config.network);
+            let node = SwarmhostNode::new(config)
+                .unwrap()
+                .with_transport(transport);
+            node.start().await.unwrap();
+            nodes.push(node);
+        }
+        let b = nodes.pop().unwrap();
+        (nodes.pop().unwrap(), b)
+    }
+
+    /// `node` as listed with both its listeners, IPv4 first
+    async fn listed_dual_stack(node: &SwarmhostNode) -> PeerRecord {
+        let addrs = node.local_addr().await;
+        PeerRecord {
+            player_id: node.player_id().await,
+            addrs: vec![
+                addrs[0],
+                SocketAddr::new("::1".parse().unwrap(), addrs[1].port()),
+            ],
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_dial_falls_back_to_ipv4_when_ipv6_is_blackholed() {
+        let sim = network::SimNetwork::new(12);
+        let (a, b) = dual_stack_pair(&sim).await;
+        let listed = listed_dual_stack(&b).await;
+        let (v4, v6) = (listed.addrs[0], listed.addrs[1]);
+        let a_addr = a.local_addr().await[0];
+        sim.set_conditions(
+            a_addr,
+            v6,
+            network::NetworkConditions::perfect().with_loss(1.0),
+        );
+
+        let started = tokio::time::Instant::now();
+        let dialed = peers::dial_all(a.transport.clone(), vec![listed], &a.peer_context()).await;
+        assert_eq!(dialed.connected.len(), 1);
+        let elapsed = started.elapsed();
+        assert!(
+            elapsed >= network::happy_eyeballs::HEAD_START
+                && elapsed < network::happy_eyeballs::HEAD_START + Duration::from_millis(50),
+            "connected after {:?}",
+            elapsed
+        );
+
+        let peers = a.peers().await;
+        assert_eq!(peers.len(), 1);
+        assert_eq!(peers[0].family, network::AddressFamily::Ipv4);
+        assert_eq!(peers[0].addr, v4);
+        wait_for_peers(&b, 1).await;
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_dial_keeps_one_connection_when_both_families_connect() {
+        let sim = network::SimNetwork::new(13);
+        let (a, b) = dual_stack_pair(&sim).await;
+        let listed = listed_dual_stack(&b).await;
+        let (v4, v6) = (listed.addrs[0], listed.addrs[1]);
+        let a_addr = a.local_addr().await[0];
+        let mut events = b.subscribe();
+        // IPv6 takes just past the head start to connect, so IPv4, started
+        // at the head start, connects at the same moment
+        let latency = |ms| {
+            network::NetworkConditions::perfect()
+                .with_latency(Duration::from_millis(ms), network::Jitter::None)
+        };
+        sim.set_conditions(a_addr, v6, latency(126));
+        sim.set_conditions(a_addr, v4, latency(1));
+
+        let dialed = peers::dial_all(a.transport.clone(), vec![listed], &a.peer_context()).await;
+        assert_eq!(dialed.connected.len(), 1);
+        tokio::time::sleep(Duration::from_secs(1)).await;
+
+        assert_eq!(a.peer_count().await, 1);
+        assert_eq!(b.peer_count().await, 1);
+        let a_id = a.player_id().await;
+        let mut opened = 0;
+        while let Ok(event) = events.try_recv() {
+            match event {
+                NodeEvent::PeerConnected { peer, .. } if peer == a_id => opened += 1,
+                NodeEvent::PeerDisconnected { peer, .. } if peer == a_id => {
+                    panic!("a second connection displaced the first")
+                }
+                _ => {}
+            }
+        }
+        assert_eq!(opened, 1);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_vote_overtakes_half_sent_bulk_transfer() {
+        let sim = network::SimNetwork::new(11);
+        let mut nodes = Vec::new();
+        for _ in 0..2 {
+            let config = loopback_config(TransportKind::Memory);
+            let transport = sim.transport(&config.network);
+            let node = SwarmhostNode::new(config)
+                .unwrap()
+                .with_transport(transport);
+            node.start().await.unwrap();
+            nodes.push(node);
+        }
+        let (a, b) = (&nodes[0], &nodes[1]);
+        let b_addr = b.local_addr().await[0];
+        // 1 MB/s, so 4 MiB take over four seconds
+        let link = network::NetworkConditions::perfect()
+            .with_latency(Duration::from_millis(10), network::Jitter::None)
+            .with_bandwidth(Some(1_000_000));
+        sim.set_conditions(a.local_addr().await[0], b_addr, link);
+        let b_id = a.connect(b_addr).await.unwrap();
+        wait_for_peers(b, 1).await;
+        let a_id = a.player_id().await;
+        a.set_validators([a_id]).await;
+        b.set_validators([a_id]).await;
+        let mut events = b.subscribe();
+
+        // Random, so compression cannot shrink it
+        let snapshot: Vec<u8> = (0..4 * 1024 * 1024).map(|_| rand::random()).collect();
+        let started = tokio::time::Instant::now();
+        a.network()
+            .send_to(b_id, network::Priority::Bulk, Bytes::from(snapshot))
+            .await
+            .unwrap();
+        tokio::time::sleep(Duration::from_secs(2)).await;
+
+        let action_id: ActionId = rand::random();
+        a.vote(action_id, Decision::Approve).await.unwrap();
+        let voted = tokio::time::Instant::now();
+        while b.consensus.lock().await.votes(&action_id).is_empty() {
+            assert!(
+                voted.elapsed() < Duration::from_millis(500),
+                "vote stuck behind the snapshot"
+            );
+            tokio::time::sleep(Duration::from_millis(5)).await;
+        }
+        while let Ok(event) = events.try_recv() {
+            assert!(
+                !matches!(event, NodeEvent::Message { .. }),
+                "snapshot finished before the vote"
+            );
+        }
+
+        loop {
+            if let NodeEvent::Message { class, payload, .. } = events.recv().await.unwrap() {
+                assert_eq!(class, network::Priority::Bulk);
+                assert_eq!(payload.len(), 4 * 1024 * 1024);
+                break;
+            }
+        }
+        assert!(started.elapsed() >= Duration::from_secs(4));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_upload_limit_paces_bulk_but_not_heartbeats() {
+        let limited = || {
+            let mut config = loopback_config(TransportKind::Memory);
+            config.network.max_message_size = 16 * 1024;
+            config.consensus.max_action_size = 16 * 1024 - MAX_FRAME_OVERHEAD;
+            config.network.heartbeat_interval = Duration::from_millis(500);
+            config.network.peer_timeout = Duration::from_secs(2);
+            config.network.upload.max_upload_bytes_per_sec_per_peer = Some(100 * 1024);
+            SwarmhostNode::new(config).unwrap()
+        };
+        let (a, b) = (limited(), limited());
+        a.start().await.unwrap();
+        b.start().await.unwrap();
+        let b_id = a.connect(b.local_addr().await[0]).await.unwrap();
+        let a_id = a.player_id().await;
+        wait_for_peers(&b, 1).await;
+
+        let (frames, mut arriving) = mpsc::channel(1);
+        b.state.write().await.relayed.insert((a_id, 7), frames);
+
+        // Random, so compression cannot shrink it under the limit
+        let payload: Vec<u8> = (0..1024 * 1024).map(|_| rand::random()).collect();
+        let outbound = a.state.read().await.connections[&b_id].outbound.clone();
+        let started = tokio::time::Instant::now();
+        outbound
+            .send(network::PeerMessage::RelayData {
+                session: 7,
+                payload: payload.clone(),
+            })
+            .await
+            .unwrap();
+
+        let received = arriving.recv().await.unwrap();
+        let elapsed = started.elapsed();
+        assert!(received[..] == payload[..], "payload was corrupted");
+        assert!(
+            elapsed >= Duration::from_secs(9) && elapsed <= Duration::from_secs(11),
+            "1MB at 100KB/s took {:?}",
+            elapsed
+        );
+
+        // b kept pinging a every half second, and a's pongs never queued
+        // behind the transfer, so neither side timed out
+        let b_view = b.peers().await;
+        assert_eq!(b_view.len(), 1);
+        let rtt = b_view[0].rtt.expect("no pong during the transfer");
+        assert!(rtt < Duration::from_millis(50), "pong delayed {:?}", rtt);
+
+        let a_view = a.peers().await;
+        assert!(a_view[0].throttled >= Duration::from_secs(8));
+        assert!(a.metrics().upload_throttled_micros.get() >= 8_000_000);
+    }
+
+    #[tokio::test]
+    async fn test_proposal_from_three_peers_dispatched_once() {
+        let receiver = SwarmhostNode::new(loopback_config(TransportKind::Memory)).unwrap();
+        receiver.start().await.unwrap();
+        receiver.join_game("dedup").await.unwrap();
+        let addr = receiver.local_addr().await[0];
+
+        let mut senders = Vec::new();
+        for _ in 0..3 {
+            let sender = SwarmhostNode::new(loopback_config(TransportKind::Memory)).unwrap();
+            sender.start().await.unwrap();
+            sender.connect(addr).await.unwrap();
+            senders.push(sender);
+        }
+        wait_for_peers(&receiver, 3).await;
+        let receiver_id = receiver.player_id().await;
+
+        // The same proposal, as if it had reached each sender by a
+        // different route; one hop left so the receiver keeps it to itself
+        let action = SignedAction::new(&senders[0].keypair, "dedup", 0, 1, b"move".to_vec());
+        for sender in &senders {
+            let copy = network::PeerMessage::Gossip(network::GossipMessage {
+                hops_left: 1,
+                payload: GossipPayload::Proposal(action.clone()),
+            });
+            peers::send_to(&*sender.state.read().await, &[receiver_id], copy);
+        }
+
+        let deadline = tokio::time::Instant::now() + std::time::Duration::from_secs(2);
+        while receiver.metrics().duplicates_dropped.get() < 2 {
+            assert!(
+                tokio::time::Instant::now() < deadline,
+                "duplicates never arrived"
+            );
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        }
+        assert_eq!(receiver.consensus.lock().await.pending().len(), 1);
+        assert_eq!(receiver.metrics().duplicates_dropped.get(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_vote_from_non_validator_is_penalized() {
+        let validator = SwarmhostNode::new(loopback_config(TransportKind::Memory)).unwrap();
+        let outsider_config = loopback_config(TransportKind::Memory);
+        let outsider_key = outsider_config.keypair.clone().unwrap();
+        let outsider = SwarmhostNode::new(outsider_config).unwrap();
+        validator.start().await.unwrap();
+        outsider.start().await.unwrap();
+        let mut events = validator.subscribe();
+
+        outsider
+            .connect(validator.local_addr().await[0])
+            .await
+            .unwrap();
+        let (validator_id, outsider_id) = (validator.player_id().await, outsider.player_id().await);
+        validator.set_validators([validator_id]).await;
+        outsider.set_validators([validator_id]).await;
+
+        let action_id: ActionId = rand::random();
+        assert!(outsider.vote(action_id, Decision::Approve).await.is_err());
+        let vote = Vote::new(&outsider_key, action_id, 0, Decision::Approve);
+        let gossip = network::PeerMessage::Gossip(network::GossipMessage {
+            hops_left: 1,
+            payload: GossipPayload::Vote(vote),
+        });
+        peers::send_to(&*outsider.state.read().await, &[validator_id], gossip);
+
+        let offense = next_event(&mut events, |event| match event {
+            NodeEvent::PeerScoreChanged { peer, offense, .. } if peer == outsider_id => {
+                Some(offense)
+            }
+            _ => None,
+        })
+        .await;
+        assert_eq!(offense, Offense::ProtocolViolation);
+        assert!(
+            validator
+                .consensus
+                .lock()
+                .await
+                .votes(&action_id)
+                .is_empty()
+        );
+
+        validator.vote(action_id, Decision::Approve).await.unwrap();
+        let tally = validator.consensus_info().await.tallies[&action_id];
+        assert_eq!(
+            tally,
+            crate::consensus::Tally {
+                approvals: 1,
+                rejections: 0,
+                validators: 1,
+                required: 1
+            }
+        );
+        assert_eq!(tally.outcome(), crate::consensus::Outcome::Approved);
+    }
+
+    #[tokio::test]
+    async fn test_peer_sending_bad_signatures_is_banned() {
+        let good = SwarmhostNode::new(loopback_config(TransportKind::Memory)).unwrap();
+        let bad_config = loopback_config(TransportKind::Memory);
+        let bad_key = bad_config.keypair.clone().unwrap();
+        let bad = SwarmhostNode::new(bad_config).unwrap();
+        good.start().await.unwrap();
+        bad.start().await.unwrap();
+        let mut events = good.subscribe();
+
+        let good_addr = good.local_addr().await[0];
+        bad.connect(good_addr).await.unwrap();
+        let (good_id, bad_id) = (good.player_id().await, bad.player_id().await);
+
+        for nonce in 0..3 {
+            let mut action = SignedAction::new(&bad_key, "game", nonce, 1, b"cheat".to_vec());
+            action.signature[0] ^= 1;
+            let forged = network::PeerMessage::Gossip(network::GossipMessage {
+                hops_left: 1,
+                payload: GossipPayload::Proposal(action),
+            });
+            peers::send_to(&*bad.state.read().await, &[good_id], forged);
+        }
+
+        let mut demerits = Vec::new();
+        let reason = loop {
+            match events.recv().await.unwrap() {
+                NodeEvent::PeerScoreChanged {
+                    peer,
+                    offense,
+                    demerits: total,
+                } => {
+                    assert_eq!((peer, offense), (bad_id, Offense::InvalidSignature));
+                    demerits.push(total);
+                }
+                NodeEvent::PeerDisconnected { peer, reason } if peer == bad_id => break reason,
+                _ => {}
+            }
+        };
+        assert_eq!(reason, network::CloseCode::Banned);
+        assert_eq!(demerits.len(), 3);
+        assert!(demerits[1] < 100 && demerits[2] >= 100);
+        assert_eq!(good.metrics().peers_banned.get(), 1);
+        assert_eq!(good.peer_count().await, 0);
+
+        // The ban outlives the connection
+        wait_for_peers(&bad, 0).await;
+        assert!(bad.connect(good_addr).await.is_err());
+        assert_eq!(good.peer_count().await, 0);
+    }
+
+    #[tokio::test]
+    async fn test_latency_alone_is_not_penalized_by_default() {
+        let node = SwarmhostNode::new(loopback_config(TransportKind::Memory)).unwrap();
+        let other = SwarmhostNode::new(loopback_config(TransportKind::Memory)).unwrap();
+        node.start().await.unwrap();
+        other.start().await.unwrap();
+        node.connect(other.local_addr().await[0]).await.unwrap();
+        let other_id = other.player_id().await;
+
+        for _ in 0..50 {
+            node.report_peer(other_id, Offense::HighLatency).await;
+        }
+        assert_eq!(node.peer_count().await, 1);
+        assert_eq!(node.metrics().peer_demerits.get(), 0);
+
+        let mut config = node.config();
+        config.network.reputation.penalize_latency = true;
+        node.reload_config(config).unwrap();
+        for _ in 0..25 {
+            node.report_peer(other_id, Offense::HighLatency).await;
+        }
+        assert_eq!(node.peer_count().await, 0);
+    }
+
+    /// Two started nodes over the memory transport, the second dialed by the
+    /// first; returns the second's id
+    async fn connected_pair() -> (SwarmhostNode, SwarmhostNode, PlayerId) {
+        let a = SwarmhostNode::new(loopback_config(TransportKind::Memory)).unwrap();
+        let b = SwarmhostNode::new(loopback_config(TransportKind::Memory)).unwrap();
+        a.start().await.unwrap();
+        b.start().await.unwrap();
+        let b_id = a.connect(b.local_addr().await[0]).await.unwrap();
+        wait_for_peers(&b, 1).await;
+        (a, b, b_id)
+    }
+
+    async fn next_request(events: &mut broadcast::Receiver<NodeEvent>) -> (PlayerId, u64, Bytes) {
+        loop {
+            if let NodeEvent::Request { from, id, payload } = events.recv().await.unwrap() {
+                return (from, id, payload);
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_broadcast_reaches_peers_in_the_game() {
+        let hub = SwarmhostNode::new(loopback_config(TransportKind::Memory)).unwrap();
+        hub.start().await.unwrap();
+        let addr = hub.local_addr().await[0];
+        let mut players = Vec::new();
+        for game in ["arena", "lobby"] {
+            let player = SwarmhostNode::new(loopback_config(TransportKind::Memory)).unwrap();
+            player.start().await.unwrap();
+            player.connect(addr).await.unwrap();
+            player.join_game(game).await.unwrap();
+            players.push(player);
+        }
+        let (arena, lobby) = (&players[0], &players[1]);
+        let mut arena_events = arena.subscribe();
+        let mut lobby_events = lobby.subscribe();
+
+        let deadline = tokio::time::Instant::now() + Duration::from_secs(5);
+        while hub
+            .peers()
+            .await
+            .iter()
+            .filter(|p| p.game.is_some())
+            .count()
+            < 2
+        {
+            assert!(
+                tokio::time::Instant::now() < deadline,
+                "games never announced"
+            );
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+
+        let network = hub.network();
+        let hub_id = hub.player_id().await;
+        let sent = network
+            .broadcast("arena", network::Priority::GameAction, Bytes::from("go"))
+            .await;
+        assert_eq!(sent, 1);
+        assert_eq!(
+            arena_events.recv().await.unwrap(),
+            NodeEvent::Message {
+                from: hub_id,
+                game_id: Some("arena".to_string()),
+                class: network::Priority::GameAction,
+                payload: Bytes::from("go"),
+            }
+        );
+
+        let lobby_id = lobby.player_id().await;
+        network
+            .send_to(lobby_id, network::Priority::Bulk, Bytes::from("chunk"))
+            .await
+            .unwrap();
+        assert_eq!(
+            lobby_events.recv().await.unwrap(),
+            NodeEvent::Message {
+                from: hub_id,
+                game_id: None,
+                class: network::Priority::Bulk,
+                payload: Bytes::from("chunk"),
+            }
+        );
+
+        let stranger = KeyPair::generate().public_key();
+        let result = network
+            .send_to(stranger, network::Priority::Bulk, Bytes::from("?"))
+            .await;
+        assert_eq!(result.unwrap_err().peer(), Some(stranger));
+
+        // A connection that stopped taking messages names the peer too
+        let (closed, mut receiver) = network::outbound::queue(
+            &hub.config.network.outbound,
+            Arc::new(NodeMetrics::default()),
+        );
+        receiver.close();
+        hub.state
+            .write()
+            .await
+            .connections
+            .get_mut(&lobby_id)
+            .unwrap()
+            .outbound = closed;
+        let err = network
+            .send_to(lobby_id, network::Priority::Bulk, Bytes::from("?"))
+            .await
+            .unwrap_err();
+        assert_eq!(err.peer(), Some(lobby_id));
+        assert_eq!(err.code(), crate::error::ErrorCode::Disconnected);
+    }
+
+    #[tokio::test]
+    async fn test_flooding_peer_disconnected_while_neighbour_unaffected() {
+        let mut strict = loopback_config(TransportKind::Memory);
+        strict.network.inbound.max_messages_per_sec = Some(200);
+        strict.network.inbound.pause = Duration::from_millis(200);
+        let victim = SwarmhostNode::new(strict).unwrap();
+        // Every message its own frame, as fast as the transport takes them
+        let mut unbatched = loopback_config(TransportKind::Memory);
+        unbatched.network.batching.window = Duration::ZERO;
+        let flooder = SwarmhostNode::new(unbatched).unwrap();
+        let neighbour = SwarmhostNode::new(loopback_config(TransportKind::Memory)).unwrap();
+        for node in [&victim, &flooder, &neighbour] {
+            node.start().await.unwrap();
+        }
+        let addr = victim.local_addr().await[0];
+        let victim_id = flooder.connect(addr).await.unwrap();
+        neighbour.connect(addr).await.unwrap();
+        wait_for_peers(&victim, 2).await;
+        let flooder_id = flooder.player_id().await;
+        let neighbour_id = neighbour.player_id().await;
+        let mut events = victim.subscribe();
+
+        let outbound = flooder.state.read().await.connections[&victim_id]
+            .outbound
+            .clone();
+        let flood = tokio::spawn(async move {
+            loop {
+                let junk = network::PeerMessage::Direct {
+                    class: network::Priority::Bulk,
+                    payload: vec![0; 8],
+                };
+                if outbound.send(junk).await.is_err() {
+                    break;
+                }
+            }
+        });
+
+        let deadline = tokio::time::Instant::now() + Duration::from_secs(10);
+        let reason = loop {
+            let event = tokio::time::timeout_at(deadline, events.recv())
+                .await
+                .expect("flooder never disconnected");
+            match event {
+                Ok(NodeEvent::PeerDisconnected { peer, reason }) => {
+                    assert_eq!(peer, flooder_id);
+                    break reason;
+                }
+                Ok(_) | Err(broadcast::error::RecvError::Lagged(_)) => {}
+                Err(e) => panic!("event channel failed: {}", e),
+            }
+        };
+        flood.abort();
+        assert_eq!(reason, CloseCode::RateLimited);
+        assert_eq!(victim.metrics().peers_rate_limited.get(), 1);
+        assert!(victim.metrics().inbound_dropped.get() > 0);
+
+        let peers: Vec<_> = victim.peers().await.iter().map(|p| p.player_id).collect();
+        assert_eq!(peers, vec![neighbour_id]);
+        let mut events = victim.subscribe();
+        neighbour
+            .network()
+            .send_to(victim_id, network::Priority::GameAction, Bytes::from("hi"))
+            .await
+            .unwrap();
+        loop {
+            if let NodeEvent::Message { from, payload, .. } = events.recv().await.unwrap() {
+                assert_eq!((from, payload), (neighbour_id, Bytes::from("hi")));
+                break;
+            }
+        }
+    }
+
+    /// Frames A hands its transport while gossiping 1000 votes to B
+    async fn frames_for_vote_burst(batching: BatchConfig) -> u64 {
+        let pair = || {
+            let mut config = loopback_config(TransportKind::Memory);
+            config.network.batching = batching.clone();
+            config.network.outbound.control_capacity = 2048;
+            SwarmhostNode::new(config).unwrap()
+        };
+        let (a, b) = (pair(), pair());
+        a.start().await.unwrap();
+        b.start().await.unwrap();
+        a.connect(b.local_addr().await[0]).await.unwrap();
+        wait_for_peers(&b, 1).await;
+        let a_id = a.player_id().await;
+        a.set_validators([a_id]).await;
+        b.set_validators([a_id]).await;
+
+        let before = a.metrics().frames_sent.get();
+        let actions: Vec<ActionId> = (0..1000).map(|_| rand::random()).collect();
+        for action_id in &actions {
+            a.vote(*action_id, Decision::Approve).await.unwrap();
+        }
+
+        let deadline = tokio::time::Instant::now() + Duration::from_secs(5);
+        loop {
+            let consensus = b.consensus.lock().await;
+            let arrived = actions
+                .iter()
+                .filter(|id| !consensus.votes(id).is_empty())
+                .count();
+            if arrived == actions.len() {
+                break;
+            }
+            drop(consensus);
+            assert!(
+                tokio::time::Instant::now() < deadline,
+                "{} of 1000 votes arrived",
+                arrived
+            );
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        a.metrics().frames_sent.get() - before
+    }
+
+    #[tokio::test]
+    async fn test_vote_burst_batched_into_fewer_frames() {
+        let unbatched = frames_for_vote_burst(BatchConfig {
+            window: Duration::ZERO,
+            ..BatchConfig::default()
+        })
+        .await;
+        let batched = frames_for_vote_burst(BatchConfig::default()).await;
+        assert!(unbatched >= 1000);
+        assert!(
+            batched < unbatched / 2,
+            "{} frames batched vs {} unbatched",
+            batched,
+            unbatched
+        );
+    }
+
+    #[tokio::test]
+    async fn test_batched_messages_arrive_in_order() {
+        let (a, b, b_id) = connected_pair().await;
+        let mut events = b.subscribe();
+        let network = a.network();
+        // Fewer than the game action queue holds, so none are dropped
+        for i in 0..200u32 {
+            let payload = Bytes::copy_from_slice(&i.to_be_bytes());
+            network
+                .send_to(b_id, network::Priority::GameAction, payload)
+                .await
+                .unwrap();
+        }
+
+        let mut next = 0u32;
+        while next < 200 {
+            if let NodeEvent::Message { payload, .. } = events.recv().await.unwrap() {
+                assert_eq!(payload, Bytes::copy_from_slice(&next.to_be_bytes()));
+                next += 1;
+            }
+        }
+        assert!(a.metrics().messages_batched.get() > 0);
+    }
+
+    #[tokio::test]
+    async fn test_stop_delivers_queued_messages_and_says_why() {
+        let (a, b, b_id) = connected_pair().await;
+        let a_id = a.player_id().await;
+        let mut events = b.subscribe();
+        let network = a.network();
+        for i in 0..200u32 {
+            let payload = Bytes::copy_from_slice(&i.to_be_bytes());
+            network
+                .send_to(b_id, network::Priority::Bulk, payload)
+                .await
+                .unwrap();
+        }
+        a.stop().await.unwrap();
+
+        let mut next = 0u32;
+        let reason = loop {
+            match events.recv().await.unwrap() {
+                NodeEvent::Message { payload, .. } => {
+                    assert_eq!(payload, Bytes::copy_from_slice(&next.to_be_bytes()));
+                    next += 1;
+                }
+                NodeEvent::PeerDisconnected { peer, reason } => {
+                    assert_eq!(peer, a_id);
+                    break reason;
+                }
+                _ => {}
+            }
+        };
+        assert_eq!(next, 200);
+        assert_eq!(reason, CloseCode::Shutdown);
+        assert!(
+            network
+                .send_to(b_id, network::Priority::Bulk, Bytes::new())
+                .await
+                .is_err()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_leave_game_closes_only_game_peers() {
+        let (a, b, b_id) = connected_pair().await;
+        let a_id = a.player_id().await;
+        let bystander = SwarmhostNode::new(loopback_config(TransportKind::Memory)).unwrap();
+        bystander.start().await.unwrap();
+        bystander.connect(b.local_addr().await[0]).await.unwrap();
+        wait_for_peers(&b, 2).await;
+
+        a.join_game("arena").await.unwrap();
+        b.join_game("arena").await.unwrap();
+        for _ in 0..100 {
+            let peers = b.peers().await;
+            if peers
+                .iter()
+                .any(|p| p.player_id == a_id && p.game.as_deref() == Some("arena"))
+            {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+
+        let mut a_events = a.subscribe();
+        let mut b_events = b.subscribe();
+        b.network()
+            .send_to(
+                a_id,
+                network::Priority::GameAction,
+                Bytes::from_static(b"gg"),
+            )
+            .await
+            .unwrap();
+        b.leave_game().await.unwrap();
+
+        assert_eq!(
+            b_events.recv().await.unwrap(),
+            NodeEvent::PeerDisconnected {
+                peer: a_id,
+                reason: CloseCode::Leaving
+            }
+        );
+        match a_events.recv().await.unwrap() {
+            NodeEvent::Message { from, payload, .. } => {
+                assert_eq!((from, &payload[..]), (b_id, &b"gg"[..]))
+            }
+            other => panic!("expected the last message first, got {:?}", other),
+        }
+        assert_eq!(
+            a_events.recv().await.unwrap(),
+            NodeEvent::PeerDisconnected {
+                peer: b_id,
+                reason: CloseCode::Leaving
+            }
+        );
+        assert_eq!(b.peer_count().await, 1);
+        assert_eq!(b.peers().await[0].player_id, bystander.player_id().await);
+        assert!(b.submit_action(1, b"late").await.is_err());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_response_after_timeout_is_ignored() {
+        let (a, b, b_id) = connected_pair().await;
+        let mut requests = b.subscribe();
+        let network = a.network();
+
+        let timeout = Duration::from_millis(100);
+        let slow = tokio::spawn({
+            let network = network.clone();
+            async move { network.request(b_id, Bytes::from("slow"), timeout).await }
+        });
+        let (from, id, _) = next_request(&mut requests).await;
+        let result = slow.await.unwrap();
+        assert!(matches!(
+            result,
+            Err(SwarmhostError::NoAnswer { peer, after }) if peer == b_id && after == timeout
+        ));
+        assert!(a.state.read().await.requests.is_empty());
+
+        // The late answer arrives before the next one and matches nothing
+        b.network()
+            .respond(from, id, Bytes::from("late"))
+            .await
+            .unwrap();
+        let fast = tokio::spawn({
+            let network = network.clone();
+            async move { network.request(b_id, Bytes::from("fast"), timeout).await }
+        });
+        let (from, id, payload) = next_request(&mut requests).await;
+        assert_eq!(payload, Bytes::from("fast"));
+        b.network()
+            .respond(from, id, Bytes::from("answer"))
+            .await
+            .unwrap();
+        assert_eq!(fast.await.unwrap().unwrap(), Bytes::from("answer"));
+        assert!(a.state.read().await.requests.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_requests_do_not_cross_match() {
+        let (a, b, b_id) = connected_pair().await;
+        let mut requests = b.subscribe();
+        let network = a.network();
+
+        let pending: Vec<_> = (0..10u8)
+            .map(|i| {
+                let network = network.clone();
+                tokio::spawn(async move {
+                    let response = network
+                        .request(b_id, Bytes::from(vec![i]), Duration::from_secs(5))
+                        .await;
+                    (i, response)
+                })
+            })
+            .collect();
+
+        // Answer in the reverse of the order the requests arrived
+        let mut received = Vec::new();
+        for _ in 0..10 {
+            received.push(next_request(&mut requests).await);
+        }
+        let responder = b.network();
+        for (from, id, payload) in received.into_iter().rev() {
+            let answer = Bytes::from(vec![payload[0], payload[0]]);
+            responder.respond(from, id, answer).await.unwrap();
+        }
+
+        for task in pending {
+            let (i, response) = task.await.unwrap();
+            assert_eq!(response.unwrap(), Bytes::from(vec![i, i]));
+        }
+        assert!(a.state.read().await.requests.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_full_node_refuses_peers_but_validators_displace_spectators() {
+        let mut config = loopback_config(TransportKind::Memory);
+        config.network.max_peers = 2;
+        let hub = SwarmhostNode::new(config).unwrap();
+        hub.start().await.unwrap();
+        hub.join_game("table").await.unwrap();
+        let addr = hub.local_addr().await[0];
+        let mut events = hub.subscribe();
+
+        let mut peers = Vec::new();
+        for _ in 0..4 {
+            let peer = SwarmhostNode::new(loopback_config(TransportKind::Memory)).unwrap();
+            peer.start().await.unwrap();
+            peers.push(peer);
+        }
+        for spectator in &peers[..2] {
+            spectator.connect(addr).await.unwrap();
+        }
+        wait_for_peers(&hub, 2).await;
+        let validator = peers[3].player_id().await;
+        hub.set_validators([validator]).await;
+
+        let latecomer = peers[2].player_id().await;
+        match peers[2].connect(addr).await {
+            Err(SwarmhostError::Handshake { code, .. }) => assert_eq!(code, CloseCode::Busy),
+            other => panic!("expected a busy refusal, got {:?}", other),
+        }
+        assert_eq!(hub.peer_count().await, 2);
+        assert_eq!(hub.metrics().peers_rejected_busy.get(), 1);
+
+        peers[3].connect(addr).await.unwrap();
+        // The hub registers the validator just after the dialer is done
+        let mut connected = Vec::new();
+        for _ in 0..100 {
+            connected = hub.peers().await.iter().map(|p| p.player_id).collect();
+            if connected.contains(&validator) {
+                break;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+        }
+        assert_eq!(connected.len(), 2);
+        assert!(connected.contains(&validator));
+        assert_eq!(hub.metrics().peers_evicted.get(), 1);
+
+        let mut seen = Vec::new();
+        while seen.len() < 2 {
+            if let event @ (NodeEvent::PeerRejected { .. } | NodeEvent::PeerDisconnected { .. }) =
+                events.recv().await.unwrap()
+            {
+                seen.push(event)
+            }
+        }
+        assert_eq!(
+            seen[0],
+            NodeEvent::PeerRejected {
+                peer: latecomer,
+                reason: CloseCode::Busy
+            }
+        );
+        match &seen[1] {
+            NodeEvent::PeerDisconnected { peer, reason } => {
+                assert_eq!(*reason, CloseCode::Evicted);
+                assert!(!connected.contains(peer));
+            }
+            other => panic!("expected an eviction, got {:?}", other),
+        }
+    }
+
+    /// Two validators of the same game, the first dialing the second;
+    /// returns the second's listen address
+    async fn validator_pair(window: Duration) -> (SwarmhostNode, SwarmhostNode, SocketAddr) {
+        let node = || {
+            let mut config = loopback_config(TransportKind::Memory);
+            config.network.reconnect.window = window;
+            SwarmhostNode::new(config).unwrap()
+        };
+        let (a, b) = (node(), node());
+        let ids = [a.player_id().await, b.player_id().await];
+        for node in [&a, &b] {
+            node.start().await.unwrap();
+            node.join_game("table").await.unwrap();
+            node.set_validators(ids).await;
+        }
+        let addr = b.local_addr().await[0];
+        a.connect(addr).await.unwrap();
+        wait_for_peers(&b, 1).await;
+        (a, b, addr)
+    }
+
+    async fn wait_for_event(
+        events: &mut broadcast::Receiver<NodeEvent>,
+        wanted: impl Fn(&NodeEvent) -> bool,
+    ) -> NodeEvent {
+        loop {
+            let event = events.recv().await.unwrap();
+            if wanted(&event) {
+                return event;
+            }
+            assert!(
+                !matches!(event, NodeEvent::PeerDisconnected { .. }),
+                "unexpected {:?}",
+                event
+            );
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_validator_reconnects_after_link_outage_without_losing_actions() {
+        let (a, b, addr) = validator_pair(Duration::from_secs(30)).await;
+        let (a_id, b_id) = (a.player_id().await, b.player_id().await);
+        let mut a_events = a.subscribe();
+        let mut b_events = b.subscribe();
+
+        a.submit_action(1, b"before").await.unwrap();
+        while b.consensus.lock().await.pending().is_empty() {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        let link = network::MemoryNetwork::global();
+        link.cut(addr);
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert!(a.peers().await[0].reconnecting);
+        for _ in 0..3 {
+            a.submit_action(1, b"during").await.unwrap();
+        }
+        tokio::time::sleep(Duration::from_secs(5)).await;
+        link.restore(addr);
+
+        for (events, peer) in [(&mut a_events, b_id), (&mut b_events, a_id)] {
+            let event = wait_for_event(events, |event| {
+                matches!(event, NodeEvent::PeerReconnected { .. })
+            })
+            .await;
+            assert!(matches!(event, NodeEvent::PeerReconnected { peer: p, .. } if p == peer));
+        }
+        assert_eq!(a.peer_count().await, 1);
+        assert!(!a.peers().await[0].reconnecting);
+
+        a.submit_action(1, b"after").await.unwrap();
+        let deadline = tokio::time::Instant::now() + Duration::from_secs(5);
+        while b.consensus.lock().await.pending().len() < 5 {
+            assert!(tokio::time::Instant::now() < deadline, "actions were lost");
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_validator_lost_after_reconnect_window() {
+        let window = Duration::from_secs(10);
+        let (a, b, addr) = validator_pair(window).await;
+        let b_id = b.player_id().await;
+        let mut a_events = a.subscribe();
+
+        network::MemoryNetwork::global().cut(addr);
+        let started = tokio::time::Instant::now();
+        let lost = wait_for_event(&mut a_events, |event| {
+            matches!(event, NodeEvent::PeerLost { .. })
+        })
+        .await;
+        assert_eq!(lost, NodeEvent::PeerLost { peer: b_id });
+        assert!(started.elapsed() >= window);
+        assert_eq!(a.peer_count().await, 0);
+        wait_for_peers(&b, 0).await;
+    }
+
+    /// Two players of the same game holding each other's resumption tokens,
+    /// the first dialing the second; returns the second's listen address
+    async fn resumable_pair() -> (SwarmhostNode, SwarmhostNode, SocketAddr) {
+        let node = || {
+            let mut config = loopback_config(TransportKind::Memory);
+            config.network.resumption.grace = Duration::from_secs(30);
+            SwarmhostNode::new(config).unwrap()
+        };
+        let (a, b) = (node(), node());
+        for node in [&a, &b] {
+            node.start().await.unwrap();
+            node.join_game("table").await.unwrap();
+        }
+        let addr = b.local_addr().await[0];
+        a.connect(addr).await.unwrap();
+        wait_for_peers(&b, 1).await;
+        let b_id = b.player_id().await;
+        while !a.state.read().await.resume_tokens.contains_key(&b_id) {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        (a, b, addr)
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_resumption_token_resumes_mid_game_with_missed_actions() {
+        let (a, b, addr) = resumable_pair().await;
+        let (a_id, b_id) = (a.player_id().await, b.player_id().await);
+        let mut a_events = a.subscribe();
+        let mut b_events = b.subscribe();
+
+        a.submit_action(1, b"before").await.unwrap();
+        while b.consensus.lock().await.pending().is_empty() {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        let link = network::MemoryNetwork::global();
+        link.cut(addr);
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert!(a.peers().await[0].reconnecting);
+        assert!(b.peers().await[0].reconnecting);
+        for _ in 0..3 {
+            a.submit_action(1, b"during").await.unwrap();
+        }
+        tokio::time::sleep(Duration::from_secs(5)).await;
+        link.restore(addr);
+
+        for (events, peer) in [(&mut a_events, b_id), (&mut b_events, a_id)] {
+            let event = wait_for_event(events, |event| {
+                matches!(event, NodeEvent::PeerReconnected { .. })
+            })
+            .await;
+            assert!(matches!(event, NodeEvent::PeerReconnected { peer: p, .. } if p == peer));
+        }
+        let info = &b.peers().await[0];
+        assert_eq!(info.game.as_deref(), Some("table"));
+        assert!(!info.reconnecting);
+
+        a.submit_action(1, b"after").await.unwrap();
+        let deadline = tokio::time::Instant::now() + Duration::from_secs(5);
+        while b.consensus.lock().await.pending().len() < 5 {
+            assert!(tokio::time::Instant::now() < deadline, "actions were lost");
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_replayed_resumption_token_is_rejected() {
+        let (a, b, addr) = resumable_pair().await;
+        let (a_id, b_id) = (a.player_id().await, b.player_id().await);
+        let mut b_events = b.subscribe();
+        let spent = a.state.read().await.resume_tokens[&b_id];
+
+        let link = network::MemoryNetwork::global();
+        link.cut(addr);
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        link.restore(addr);
+        let event = wait_for_event(&mut b_events, |event| {
+            matches!(event, NodeEvent::PeerReconnected { .. })
+        })
+        .await;
+        assert!(matches!(event, NodeEvent::PeerReconnected { peer, .. } if peer == a_id));
+
+        // Present the spent token again instead of the fresh one
+        while a.state.read().await.resume_tokens[&b_id] == spent {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        a.state.write().await.resume_tokens.insert(b_id, spent);
+        link.cut(addr);
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        link.restore(addr);
+
+        let event = wait_for_event(&mut b_events, |event| {
+            matches!(
+                event,
+                NodeEvent::PeerLost { .. }
+                    | NodeEvent::PeerConnected { .. }
+                    | NodeEvent::PeerReconnected { .. }
+            )
+        })
+        .await;
+        assert_eq!(event, NodeEvent::PeerLost { peer: a_id });
+        let event = wait_for_event(&mut b_events, |event| {
+            matches!(
+                event,
+                NodeEvent::PeerConnected { .. } | NodeEvent::PeerReconnected { .. }
+            )
+        })
+        .await;
+        assert!(matches!(event, NodeEvent::PeerConnected { peer, .. } if peer == a_id));
+        assert_eq!(b.peer_count().await, 1);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_five_nodes_commit_100_actions_over_mobile_3g() {
+        let sim = network::SimNetwork::new(3);
+        let mut config = loopback_config(TransportKind::Memory);
+        config.consensus.max_actions_per_player_per_round = 100;
+        let mut nodes = Vec::new();
+        for _ in 0..5 {
+            let mut config = config.clone();
+            config.keypair = Some(KeyPair::generate());
+            let transport = sim.transport(&config.network);
+            let node = SwarmhostNode::new(config)
+                .unwrap()
+                .with_transport(transport);
+            node.start().await.unwrap();
+            node.join_game("mobile").await.unwrap();
+            nodes.push(node);
+        }
+
+        let mut addrs = Vec::new();
+        for node in &nodes {
+            addrs.push(node.local_addr().await[0]);
+        }
+        sim.set_all_conditions(&addrs, network::LinkPreset::Mobile3G);
+        for (i, node) in nodes.iter().enumerate() {
+            for addr in &addrs[i + 1..] {
+                node.connect(*addr).await.unwrap();
+            }
+        }
+        for node in &nodes {
+            wait_for_peers(node, 4).await;
+        }
+        let mut ids = Vec::new();
+        for node in &nodes {
+            ids.push(node.player_id().await);
+        }
+        for node in &nodes {
+            node.set_validators(ids.clone()).await;
+        }
+
+        for i in 0..100u8 {
+            nodes[i as usize % 5].submit_action(1, &[i]).await.unwrap();
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+
+        // Everyone approves whatever reaches them, until every node has seen
+        // a quorum for every action
+        let quorum = config.consensus.required_weight(5) as usize;
+        let mut voted = vec![HashSet::new(); nodes.len()];
+        let deadline = tokio::time::Instant::now() + Duration::from_secs(120);
+        loop {
+            let mut committed = true;
+            for (node, voted) in nodes.iter().zip(&mut voted) {
+                let pending: Vec<ActionId> = {
+                    let consensus = node.consensus.lock().await;
+                    consensus.pending().iter().map(SignedAction::id).collect()
+                };
+                for &action_id in &pending {
+                    if voted.insert(action_id) {
+                        node.vote(action_id, Decision::Approve).await.unwrap();
+                    }
+                }
+                let consensus = node.consensus.lock().await;
+                committed &= pending.len() == 100
+                    && pending
+                        .iter()
+                        .all(|action_id| consensus.votes(action_id).len() >= quorum);
+            }
+            if committed {
+                break;
+            }
+            assert!(
+                tokio::time::Instant::now() < deadline,
+                "not every action was committed everywhere"
+            );
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+        for node in &nodes {
+            assert_eq!(node.peer_count().await, 4);
+        }
+    }
+
+    /// Fully connected validators of one game over simulated Wi-Fi, one per
+    /// config
+    async fn validator_mesh(
+        sim: &network::SimNetwork,
+        configs: Vec<NodeConfig>,
+    ) -> Vec<SwarmhostNode> {
+        validator_mesh_with(sim, configs, |node| node).await
+    }
+
+    /// A [`validator_mesh`] whose nodes `build` finishes building
+    async fn validator_mesh_with(
+        sim: &network::SimNetwork,
+        configs: Vec<NodeConfig>,
+        build: impl Fn(SwarmhostNode) -> SwarmhostNode,
+    ) -> Vec<SwarmhostNode> {
+        let count = configs.len();
+        let mut nodes = Vec::new();
+        for mut config in configs {
+            config.keypair = Some(KeyPair::generate());
+            let transport = sim.transport(&config.network);
+            let node = build(
+                SwarmhostNode::new(config)
+                    .unwrap()
+                    .with_transport(transport),
+            );
+            node.start().await.unwrap();
+            node.join_game("ordered").await.unwrap();
+            nodes.push(node);
+        }
+        let mut addrs = Vec::new();
+        for node in &nodes {
+            addrs.push(node.local_addr().await[0]);
+        }
+        sim.set_all_conditions(&addrs, network::LinkPreset::Wifi);
+        for (i, node) in nodes.iter().enumerate() {
+            for addr in &addrs[i + 1..] {
+                node.connect(*addr).await.unwrap();
+            }
+        }
+        let mut ids = Vec::new();
+        for node in &nodes {
+            wait_for_peers(node, count - 1).await;
+            ids.push(node.player_id().await);
+        }
+        for node in &nodes {
+            node.set_validators(ids.clone()).await;
+        }
+        nodes
+    }
+
+    /// Wait until `count` actions of the mesh's game are committed on `node`
+    async fn committed(node: &SwarmhostNode, count: usize) -> ActionLog {
+        loop {
+            if let Some(log) = node.action_log("ordered").await
+                && log.sequence() >= count as u64
+            {
+                return log;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    }
+
+    /// Approve `action_id` on every node once its proposal has arrived
+    async fn approve_everywhere(nodes: &[SwarmhostNode], action_id: ActionId) {
+        for node in nodes {
+            loop {
+                let arrived = node
+                    .consensus
+                    .lock()
+                    .await
+                    .pending()
+                    .iter()
+                    .any(|action| action.id() == action_id);
+                if arrived {
+                    break;
+                }
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+            node.vote(action_id, Decision::Approve).await.unwrap();
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_concurrent_submissions_commit_in_one_order_everywhere() {
+        let sim = network::SimNetwork::new(11);
+        let nodes = validator_mesh(&sim, vec![loopback_config(TransportKind::Memory); 3]).await;
+        let mut events = nodes[0].subscribe();
+
+        async fn submit(node: &SwarmhostNode, tag: u8) {
+            for i in 0..5u8 {
+                node.submit_action(1, &[tag, i]).await.unwrap();
+                tokio::time::sleep(Duration::from_millis(3)).await;
+            }
+        }
+        tokio::join!(
+            submit(&nodes[0], 0),
+            submit(&nodes[1], 1),
+            submit(&nodes[2], 2)
+        );
+
+        // Everyone approves whatever reaches them until every log is full
+        let mut voted = vec![HashSet::new(); nodes.len()];
+        let deadline = tokio::time::Instant::now() + Duration::from_secs(30);
+        loop {
+            let mut committed = true;
+            for (node, voted) in nodes.iter().zip(&mut voted) {
+                let pending: Vec<ActionId> = {
+                    let consensus = node.consensus.lock().await;
+                    consensus.pending().iter().map(SignedAction::id).collect()
+                };
+                for &action_id in &pending {
+                    if voted.insert(action_id) {
+                        node.vote(action_id, Decision::Approve).await.unwrap();
+                    }
+                }
+                let log = node.action_log("ordered").await;
+                committed &= log.is_some_and(|log| log.entries().len() == 15);
+            }
+            if committed {
+                break;
+            }
+            assert!(
+                tokio::time::Instant::now() < deadline,
+                "not every action was committed everywhere"
+            );
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+
+        let first = nodes[0].action_log("ordered").await.unwrap();
+        for node in &nodes[1..] {
+            let log = node.action_log("ordered").await.unwrap();
+            assert_eq!(log.hash(), first.hash());
+            assert_eq!(log.entries(), first.entries());
+        }
+
+        let mut sequences = Vec::new();
+        let mut receipts = 0;
+        while sequences.len() < 15 || receipts < 5 {
+            match events.recv().await.unwrap() {
+                NodeEvent::ActionCommitted {
+                    sequence,
+                    action_id,
+                    ..
+                } => {
+                    assert_eq!(first.entries()[sequences.len()], action_id);
+                    sequences.push(sequence);
+                }
+                NodeEvent::ActionReceipt { .. } => receipts += 1,
+                _ => {}
+            }
+        }
+        assert_eq!(sequences, (1..=15).collect::<Vec<u64>>());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_missing_commit_fetched_after_gap_timeout() {
+        let sim = network::SimNetwork::new(12);
+        let nodes = validator_mesh(&sim, vec![loopback_config(TransportKind::Memory); 3]).await;
+        for i in 0..3u8 {
+            nodes[0].submit_action(1, &[i]).await.unwrap();
+        }
+        let actions: Vec<ActionId> = nodes[0]
+            .consensus
+            .lock()
+            .await
+            .pending()
+            .iter()
+            .map(SignedAction::id)
+            .collect();
+        for node in &nodes {
+            while node.consensus.lock().await.pending().len() < 3 {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+            for &action_id in &actions {
+                node.vote(action_id, Decision::Approve).await.unwrap();
+            }
+        }
+        let full = committed(&nodes[0], 3).await;
+
+        // A late joiner hears of the last two commits but not the first
+        let mut config = loopback_config(TransportKind::Memory);
+        config.keypair = Some(KeyPair::generate());
+        let transport = sim.transport(&config.network);
+        let late = SwarmhostNode::new(config)
+            .unwrap()
+            .with_transport(transport);
+        late.start().await.unwrap();
+        late.join_game("ordered").await.unwrap();
+        late.connect(nodes[0].local_addr().await[0]).await.unwrap();
+        let mut ids = Vec::new();
+        for node in &nodes {
+            ids.push(node.player_id().await);
+        }
+        late.set_validators(ids).await;
+        let commits = nodes[0].consensus.lock().await.commits("ordered", 2, 2);
+        let mut events = late.subscribe();
+        for commit in commits {
+            sequence::receive(commit, &late.peer_context())
+                .await
+                .unwrap();
+        }
+        assert!(late.action_log("ordered").await.is_none());
+
+        let fetched = next_event(&mut events, |event| match event {
+            NodeEvent::ActionCommitted { sequence, .. } => Some(sequence),
+            _ => None,
+        })
+        .await;
+        assert_eq!(fetched, 1);
+        let log = committed(&late, 3).await;
+        assert_eq!(log.hash(), full.hash());
+    }
+
+    /// The id of the latest action `node` submitted
+    async fn last_submitted(node: &SwarmhostNode) -> ActionId {
+        let player_id = node.player_id().await;
+        let consensus = node.consensus.lock().await;
+        let mut mine = consensus
+            .pending()
+            .iter()
+            .filter(|action| action.actor == player_id);
+        mine.next_back().unwrap().id()
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_speculation_matching_final_order_is_kept() {
+        let sim = network::SimNetwork::new(13);
+        let nodes = validator_mesh(&sim, vec![loopback_config(TransportKind::Memory); 3]).await;
+        let mut events = nodes[0].subscribe();
+
+        let mut actions = Vec::new();
+        for i in 0..2u8 {
+            nodes[0].submit_action(1, &[i]).await.unwrap();
+            actions.push(last_submitted(&nodes[0]).await);
+        }
+        let ahead = nodes[0].speculative_log("ordered").await.unwrap();
+        assert_eq!(ahead.entries(), &actions[..]);
+        assert!(nodes[0].action_log("ordered").await.is_none());
+
+        for &action_id in &actions {
+            approve_everywhere(&nodes, action_id).await;
+        }
+        let log = committed(&nodes[0], 2).await;
+        assert_eq!(log.hash(), ahead.hash());
+        for node in &nodes {
+            let log = committed(node, 2).await;
+            assert_eq!(node.speculative_log("ordered").await, Some(log));
+        }
+
+        let mut speculated = 0;
+        while let Ok(event) = events.try_recv() {
+            match event {
+                NodeEvent::ActionSpeculated { .. } => speculated += 1,
+                NodeEvent::SpeculationReverted { .. } => panic!("speculation was right"),
+                _ => {}
+            }
+        }
+        assert_eq!(speculated, 2);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_competing_action_reverts_speculation_to_pessimistic_state() {
+        let sim = network::SimNetwork::new(14);
+        // Blocks commit in the order proposed, so both go out in one for
+        // the later one to be committed first
+        let mut optimistic = loopback_config(TransportKind::Memory);
+        optimistic.consensus.batch_interval = Duration::from_millis(200);
+        let pessimistic = optimistic.clone().with_optimistic_execution(false);
+        let nodes = validator_mesh(&sim, vec![optimistic.clone(), optimistic, pessimistic]).await;
+        let mut events = nodes[0].subscribe();
+
+        // Node 0 speculates on its own action, then on node 1's
+        nodes[0].submit_action(1, b"mine").await.unwrap();
+        let mine = last_submitted(&nodes[0]).await;
+        nodes[1].submit_action(1, b"theirs").await.unwrap();
+        let theirs = last_submitted(&nodes[1]).await;
+        loop {
+            let log = nodes[0].speculative_log("ordered").await.unwrap();
+            if log.entries() == [mine, theirs] {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+
+        // but node 1's is approved, and so committed, first
+        approve_everywhere(&nodes, theirs).await;
+        committed(&nodes[0], 1).await;
+        let reverted = next_event(&mut events, |event| match event {
+            NodeEvent::SpeculationReverted { action_id } => Some(action_id),
+            _ => None,
+        })
+        .await;
+        assert_eq!(reverted, mine);
+        let replayed = nodes[0].speculative_log("ordered").await.unwrap();
+        assert_eq!(replayed.entries(), &[theirs, mine]);
+
+        approve_everywhere(&nodes, mine).await;
+        let pessimistic = committed(&nodes[2], 2).await;
+        committed(&nodes[0], 2).await;
+        let speculative = nodes[0].speculative_log("ordered").await.unwrap();
+        assert_eq!(speculative.hash(), pessimistic.hash());
+        assert_eq!(speculative.hash(), replayed.hash());
+        assert_eq!(nodes[2].speculative_log("ordered").await, Some(pessimistic));
+    }
+
+    /// Wait until `node` has moved on to `round`
+    async fn reached_round(node: &SwarmhostNode, round: u64) {
+        let deadline = tokio::time::Instant::now() + Duration::from_secs(10);
+        while node.consensus.lock().await.round() < round {
+            assert!(
+                tokio::time::Instant::now() < deadline,
+                "round {} never reached",
+                round
+            );
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_proposer_turn_rotates_through_validators_in_id_order() {
+        let sim = network::SimNetwork::new(14);
+        // Nothing is voted on, so every block stays in flight
+        let mut config = loopback_config(TransportKind::Memory);
+        config.consensus.pipeline_depth = 10;
+        let nodes = validator_mesh(&sim, vec![config; 3]).await;
+        let mut order = Vec::new();
+        for node in &nodes {
+            order.push(node.player_id().await);
+        }
+        order.sort();
+
+        for round in 0..10u64 {
+            let proposer = order[round as usize % order.len()];
+            for node in &nodes {
+                let info = node.consensus_info().await;
+                assert_eq!((info.round, info.proposer), (round, Some(proposer)));
+            }
+            // Submitters are proposers in some rounds and forward in others
+            let submitter = &nodes[round as usize % nodes.len()];
+            submitter.submit_action(1, &[round as u8]).await.unwrap();
+            let action_id = last_submitted(submitter).await;
+            for node in &nodes {
+                reached_round(node, round + 1).await;
+                let consensus = node.consensus.lock().await;
+                assert!(consensus.pending().iter().any(|a| a.id() == action_id));
+            }
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_stopped_proposer_skipped_without_losing_forwarded_actions() {
+        let sim = network::SimNetwork::new(15);
+        let nodes = validator_mesh(&sim, vec![loopback_config(TransportKind::Memory); 3]).await;
+        let timeout = nodes[0].config.consensus.proposer_timeout;
+        let proposer = nodes[0].consensus_info().await.proposer.unwrap();
+        let mut live = Vec::new();
+        for node in &nodes {
+            if node.player_id().await == proposer {
+                node.stop().await.unwrap();
+            } else {
+                live.push(node);
+            }
+        }
+        for node in &live {
+            wait_for_peers(node, 1).await;
+        }
+
+        let started = tokio::time::Instant::now();
+        live[0].submit_action(1, b"waiting").await.unwrap();
+        let action_id = last_submitted(live[0]).await;
+
+        // Round 0 is skipped by timeout votes, and the action goes in round 1
+        for node in &live {
+            reached_round(node, 2).await;
+        }
+        let elapsed = started.elapsed();
+        assert!(
+            elapsed >= timeout && elapsed < timeout * 3 / 2,
+            "skipped after {:?}",
+            elapsed
+        );
+        assert!(live[0].consensus.lock().await.outstanding().is_empty());
+        for node in &live {
+            assert_eq!(node.metrics().rounds_failed_timeout.get(), 1);
+        }
+        let consensus = live[1].consensus.lock().await;
+        assert!(consensus.pending().iter().any(|a| a.id() == action_id));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_stalled_sequencer_replaced_keeping_its_prepared_action() {
+        let sim = network::SimNetwork::new(16);
+        let mut config = loopback_config(TransportKind::Memory);
+        config.consensus.view_change_rounds = 1;
+        let mut by_id = Vec::new();
+        for node in validator_mesh(&sim, vec![config; 3]).await {
+            by_id.push((node.player_id().await, node));
+        }
+        by_id.sort_by_key(|(id, _)| *id);
+        let (order, nodes): (Vec<PlayerId>, Vec<SwarmhostNode>) = by_id.into_iter().unzip();
+        // In id order: the sequencer of view 0, the leader of view 1, and the other
+        let [stalled, leader, other] = &nodes[..] else {
+            unreachable!()
+        };
+        let mut events = other.subscribe();
+
+        // The action goes in round 0 and a quorum approves it, but its
+        // sequencer stops before numbering it
+        leader.submit_action(1, b"prepared").await.unwrap();
+        let prepared = last_submitted(leader).await;
+        for node in &nodes {
+            reached_round(node, 1).await;
+        }
+        stalled.stop().await.unwrap();
+        for node in [leader, other] {
+            wait_for_peers(node, 1).await;
+            node.vote(prepared, Decision::Approve).await.unwrap();
+        }
+
+        // Rounds 1 and 2 go ahead; round 3 is the stalled node's turn
+        leader.submit_action(1, b"one").await.unwrap();
+        reached_round(other, 2).await;
+        other.submit_action(1, b"two").await.unwrap();
+        reached_round(leader, 3).await;
+        leader.submit_action(1, b"three").await.unwrap();
+
+        let (view, new_leader) = next_event(&mut events, |event| match event {
+            NodeEvent::ViewChanged { view, leader } => Some((view, leader)),
+            _ => None,
+        })
+        .await;
+        assert_eq!((view, new_leader), (1, order[1]));
+        for node in [leader, other] {
+            let log = committed(node, 1).await;
+            assert_eq!(log.entries(), &[prepared]);
+            assert_eq!(node.consensus_info().await.view, 1);
+            assert_eq!(node.consensus.lock().await.sequencer(), Some(order[1]));
+            assert_eq!(node.metrics().rounds_failed_view_change.get(), 1);
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_double_vote_evidence_reaches_peers_that_saw_one_vote() {
+        let sim = network::SimNetwork::new(17);
+        let nodes = validator_mesh(&sim, vec![loopback_config(TransportKind::Memory); 3]).await;
+        let offender = nodes[0].config.keypair.clone().unwrap();
+        let offender_id = offender.public_key();
+        let mut events = nodes[2].subscribe();
+
+        nodes[1].submit_action(1, b"contested").await.unwrap();
+        let action_id = last_submitted(&nodes[1]).await;
+        for node in &nodes[1..] {
+            reached_round(node, 1).await;
+        }
+        nodes[0].vote(action_id, Decision::Approve).await.unwrap();
+        for node in &nodes[1..] {
+            while node.consensus.lock().await.votes(&action_id).is_empty() {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        }
+
+        // Only node 1 hears the offender take its vote back
+        let reject = Vote::new(&offender, action_id, 0, Decision::Reject);
+        {
+            let state = nodes[0].state.read().await;
+            let gossip = network::PeerMessage::Gossip(network::GossipMessage {
+                hops_left: 1,
+                payload: GossipPayload::Vote(reject),
+            });
+            let node_1 = nodes[1].player_id().await;
+            peers::send_to(&state, &[node_1], gossip);
+        }
+
+        // Node 2 learns of it from the evidence node 1 gossips
+        let (offender_seen, evidence) = next_event(&mut events, |event| match event {
+            NodeEvent::Misbehavior { offender, evidence } => Some((offender, evidence)),
+            _ => None,
+        })
+        .await;
+        assert_eq!(offender_seen, offender_id);
+        assert!(evidence.verify().is_ok());
+        assert!(matches!(
+            *evidence,
+            crate::consensus::Equivocation::Votes(..)
+        ));
+        for node in &nodes[1..] {
+            assert!(
+                !node
+                    .consensus
+                    .lock()
+                    .await
+                    .validators()
+                    .contains(&offender_id)
+            );
+            assert!(!node.state.read().await.validators.contains(&offender_id));
+        }
+
+        // and ignores what the offender votes from then on, though its own
+        // node refuses to vote for it any more
+        nodes[1].submit_action(1, b"later").await.unwrap();
+        let later = last_submitted(&nodes[1]).await;
+        reached_round(&nodes[0], 2).await;
+        assert!(nodes[0].vote(later, Decision::Approve).await.is_err());
+        {
+            let state = nodes[0].state.read().await;
+            let vote = Vote::new(&offender, later, 2, Decision::Approve);
+            let gossip = network::PeerMessage::Gossip(network::GossipMessage {
+                hops_left: 1,
+                payload: GossipPayload::Vote(vote),
+            });
+            peers::send_to(&state, &state.connected_peers, gossip);
+        }
+        tokio::time::sleep(Duration::from_secs(1)).await;
+        for node in &nodes[1..] {
+            assert!(node.consensus.lock().await.votes(&later).is_empty());
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_conflicting_submissions_resolve_alike_everywhere() {
+        let sim = network::SimNetwork::new(18);
+        let mut config = loopback_config(TransportKind::Memory);
+        config
+            .consensus
+            .game_conflict_policies
+            .insert("ordered".to_string(), ConflictPolicy::FirstWins);
+        let nodes = validator_mesh(&sim, vec![config; 3]).await;
+        let mut events = Vec::new();
+        for node in &nodes {
+            events.push(node.subscribe());
+        }
+        let mut by_id = Vec::new();
+        for node in &nodes {
+            by_id.push((node.player_id().await, node));
+        }
+        by_id.sort_by_key(|(id, _)| *id);
+        let [(_, first), (next_id, next), (_, third)] = by_id[..] else {
+            unreachable!()
+        };
+
+        // Two players grab item 7 at once; both actions reach the proposer
+        // of round 1 before its turn comes
+        let mut grabs = Vec::new();
+        for actor in [first, third] {
+            let keypair = actor.config.keypair.as_ref().unwrap();
+            let grab =
+                SignedAction::touching(keypair, "ordered", 100, 1, b"grab".to_vec(), vec![7]);
+            let state = actor.state.read().await;
+            peers::send_to(
+                &state,
+                &[next_id],
+                network::PeerMessage::Forward(grab.clone()),
+            );
+            grabs.push(grab.id());
+        }
+        while next.consensus.lock().await.pending().len() < 2 {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        first.submit_action(1, b"unrelated").await.unwrap();
+        let unrelated = last_submitted(first).await;
+        grabs.sort();
+        let [winner, loser] = grabs[..] else {
+            unreachable!()
+        };
+
+        approve_everywhere(&nodes, unrelated).await;
+        approve_everywhere(&nodes, winner).await;
+        let expected = committed(&nodes[0], 2).await;
+        assert_eq!(expected.entries(), &[unrelated, winner]);
+        for (node, events) in nodes.iter().zip(&mut events) {
+            assert_eq!(committed(node, 2).await.entries(), expected.entries());
+            let conflicts = next_event(events, |event| match event {
+                NodeEvent::ConflictsDetected { round, conflicts } => Some((round, conflicts)),
+                _ => None,
+            })
+            .await;
+            assert_eq!(
+                conflicts,
+                (
+                    1,
+                    vec![Conflict {
+                        game_id: "ordered".to_string(),
+                        key: 7,
+                        actions: vec![winner, loser],
+                    }]
+                )
+            );
+            let rejected = next_event(events, |event| match event {
+                NodeEvent::ActionRejected {
+                    action_id, reason, ..
+                } => Some((action_id, reason)),
+                _ => None,
+            })
+            .await;
+            assert_eq!(
+                rejected,
+                (loser, RejectionReason::Conflict { with: winner })
+            );
+            let consensus = node.consensus.lock().await;
+            assert!(
+                consensus
+                    .pending()
+                    .iter()
+                    .all(|action| action.id() != loser)
+            );
+        }
+    }
+
+    /// Have every node of a fresh mesh submit ten actions at once, and
+    /// approve what they are proposed, by block when batching and action by
+    /// action when not, until all are committed everywhere; returns the
+    /// nodes, the frames they sent meanwhile, and the blocks proposed
+    async fn commit_workload(
+        seed: u64,
+        consensus: ConsensusConfig,
+    ) -> (Vec<SwarmhostNode>, u64, Vec<Vec<ActionId>>) {
+        let sim = network::SimNetwork::new(seed);
+        let batch_interval = consensus.batch_interval;
+        let mut config = loopback_config(TransportKind::Memory);
+        config.consensus = consensus;
+        let nodes = validator_mesh(&sim, vec![config; 3]).await;
+        let mut events: Vec<_> = nodes.iter().map(SwarmhostNode::subscribe).collect();
+        let frames = |nodes: &[SwarmhostNode]| -> u64 {
+            nodes
+                .iter()
+                .map(|node| node.metrics().frames_sent.get())
+                .sum()
+        };
+        let before = frames(&nodes);
+
+        async fn submit(node: &SwarmhostNode, tag: u8) {
+            for i in 0..10u8 {
+                node.submit_action(1, &[tag, i]).await.unwrap();
+                tokio::time::sleep(Duration::from_millis(2)).await;
+            }
+        }
+        tokio::join!(
+            submit(&nodes[0], 0),
+            submit(&nodes[1], 1),
+            submit(&nodes[2], 2)
+        );
+
+        let mut blocks = Vec::new();
+        let mut voted = vec![HashSet::new(); nodes.len()];
+        let deadline = tokio::time::Instant::now() + Duration::from_secs(30);
+        loop {
+            let mut committed = true;
+            for (i, node) in nodes.iter().enumerate() {
+                loop {
+                    let (block, actions) = match events[i].try_recv() {
+                        Ok(NodeEvent::BlockProposed { block, actions, .. }) => (block, actions),
+                        Ok(_) | Err(broadcast::error::TryRecvError::Lagged(_)) => continue,
+                        Err(_) => break,
+                    };
+                    if i == 0 {
+                        blocks.push(actions);
+                    }
+                    if !batch_interval.is_zero() {
+                        node.vote_block(block, &[]).await.unwrap();
+                    }
+                }
+                if batch_interval.is_zero() {
+                    let pending: Vec<ActionId> = {
+                        let consensus = node.consensus.lock().await;
+                        consensus.pending().iter().map(SignedAction::id).collect()
+                    };
+                    for action_id in pending {
+                        if voted[i].insert(action_id) {
+                            node.vote(action_id, Decision::Approve).await.unwrap();
+                        }
+                    }
+                }
+                let log = node.action_log("ordered").await;
+                committed &= log.is_some_and(|log| log.entries().len() == 30);
+            }
+            if committed {
+                break;
+            }
+            assert!(
+                tokio::time::Instant::now() < deadline,
+                "not every action was committed everywhere"
+            );
+            tokio::time::sleep(Duration::from_millis(5)).await;
+        }
+        let sent = frames(&nodes) - before;
+        (nodes, sent, blocks)
+    }
+
+    fn batching() -> ConsensusConfig {
+        ConsensusConfig {
+            batch_interval: Duration::from_millis(20),
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_batching_cuts_consensus_traffic() {
+        // A round per action, as before batching
+        let singly = ConsensusConfig {
+            batch_max_actions: 1,
+            ..Default::default()
+        };
+        let (_nodes, unbatched, _rounds) = commit_workload(19, singly).await;
+        let (_nodes, batched, blocks) = commit_workload(19, batching()).await;
+
+        assert!(blocks.len() < 10, "{} blocks for 30 actions", blocks.len());
+        assert!(
+            batched * 4 < unbatched * 3,
+            "{batched} frames sent batching, {unbatched} without"
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_block_actions_numbered_together_alike_everywhere() {
+        let (nodes, _elapsed, blocks) = commit_workload(20, batching()).await;
+
+        let mut orders = Vec::new();
+        for node in &nodes {
+            let consensus = node.consensus.lock().await;
+            let order: Vec<(u64, ActionId)> = consensus
+                .commits("ordered", 1, 30)
+                .iter()
+                .map(|commit| (commit.sequence, commit.action.id()))
+                .collect();
+            orders.push(order);
+        }
+        assert_eq!(orders[0].len(), 30);
+        assert!(orders.iter().all(|order| *order == orders[0]));
+
+        // Each block's actions hold consecutive numbers, in block order
+        let numbered: Vec<ActionId> = orders[0].iter().map(|(_, id)| *id).collect();
+        for block in &blocks {
+            let at = numbered.iter().position(|id| *id == block[0]).unwrap();
+            assert_eq!(&numbered[at..at + block.len()], block.as_slice());
+        }
+    }
+    /// Approve every block `nodes` are proposed until `count` actions are
+    /// committed on each
+    async fn approve_blocks(
+        nodes: &[SwarmhostNode],
+        events: &mut [broadcast::Receiver<NodeEvent>],
+        count: usize,
+    ) {
+        let deadline = tokio::time::Instant::now() + Duration::from_secs(60);
+        loop {
+            let mut committed = true;
+            for (node, events) in nodes.iter().zip(events.iter_mut()) {
+                loop {
+                    match events.try_recv() {
+                        Ok(NodeEvent::BlockProposed { block, .. }) => {
+                            node.vote_block(block, &[]).await.unwrap()
+                        }
+                        Ok(_) | Err(broadcast::error::TryRecvError::Lagged(_)) => continue,
+                        Err(_) => break,
+                    }
+                }
+                let log = node.action_log("ordered").await;
+                committed &= log.is_some_and(|log| log.entries().len() >= count);
+            }
+            if committed {
+                return;
+            }
+            assert!(
+                tokio::time::Instant::now() < deadline,
+                "not every action was committed everywhere"
+            );
+            tokio::time::sleep(Duration::from_millis(5)).await;
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_late_joiner_catches_up_before_voting() {
+        let sim = network::SimNetwork::new(21);
+        let mut config = loopback_config(TransportKind::Memory);
+        config.consensus = ConsensusConfig {
+            max_actions_per_player_per_round: 1000,
+            ..batching()
+        };
+        config.state.snapshot_interval = 128;
+        let nodes = validator_mesh(&sim, vec![config.clone(); 3]).await;
+        let mut events: Vec<_> = nodes.iter().map(SwarmhostNode::subscribe).collect();
+        let submit = async {
+            for i in 0..500u32 {
+                nodes[i as usize % 3]
+                    .submit_action(1, &i.to_be_bytes())
+                    .await
+                    .unwrap();
+                tokio::time::sleep(Duration::from_millis(2)).await;
+            }
+        };
+        tokio::join!(submit, approve_blocks(&nodes, &mut events, 500));
+
+        // A fourth node joins the game 500 commits in
+        let mut ids = Vec::new();
+        for node in &nodes {
+            ids.push(node.player_id().await);
+        }
+        config.keypair = Some(KeyPair::generate());
+        let transport = sim.transport(&config.network);
+        let joiner = SwarmhostNode::new(config)
+            .unwrap()
+            .with_transport(transport);
+        joiner.start().await.unwrap();
+        joiner.set_validators(ids.clone()).await;
+        joiner.join_game("ordered").await.unwrap();
+        let mut joined = joiner.subscribe();
+        for node in &nodes {
+            joiner.connect(node.local_addr().await[0]).await.unwrap();
+        }
+
+        let mut progressed = false;
+        let ready = tokio::time::timeout(Duration::from_secs(30), async {
+            loop {
+                match joined.recv().await.unwrap() {
+                    NodeEvent::SyncProgress { have, need, .. } => {
+                        assert!(have <= need);
+                        progressed = true;
+                    }
+                    NodeEvent::SessionReady { sequence, .. } => return sequence,
+                    _ => {}
+                }
+            }
+        })
+        .await
+        .expect("the joiner never caught up");
+        assert!(progressed);
+        assert_eq!(ready, 500);
+        let log = joiner.action_log("ordered").await.unwrap();
+        let expected = nodes[0].action_log("ordered").await.unwrap();
+        assert_eq!(log.entries().len(), 500);
+        assert_eq!(log.hash(), expected.hash());
+
+        // Once caught up it votes as a validator like the rest
+        ids.push(joiner.player_id().await);
+        let mut nodes = nodes;
+        nodes.push(joiner);
+        events.push(joined);
+        for node in &nodes {
+            node.set_validators(ids.clone()).await;
+        }
+        nodes[0].submit_action(1, b"late").await.unwrap();
+        approve_blocks(&nodes, &mut events, 501).await;
+        let log = nodes[3].action_log("ordered").await.unwrap();
+        assert_eq!(
+            log.hash(),
+            nodes[0].action_log("ordered").await.unwrap().hash()
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_checkpoints_form_on_schedule_and_seed_late_joiners() {
+        let sim = network::SimNetwork::new(22);
+        let mut config = loopback_config(TransportKind::Memory);
+        config.consensus = ConsensusConfig {
+            max_actions_per_player_per_round: 1000,
+            checkpoint_interval: 50,
+            ..batching()
+        };
+        // Checkpoints are the only snapshots taken
+        config.state.snapshot_interval = 0;
+        let nodes = validator_mesh(&sim, vec![config.clone(); 3]).await;
+        let mut events: Vec<_> = nodes.iter().map(SwarmhostNode::subscribe).collect();
+        let mut checkpointed = nodes[0].subscribe();
+        let submit = async {
+            for i in 0..130u32 {
+                nodes[i as usize % 3]
+                    .submit_action(1, &i.to_be_bytes())
+                    .await
+                    .unwrap();
+                tokio::time::sleep(Duration::from_millis(2)).await;
+            }
+        };
+        // Watched as they come, as the events of the workload overrun them
+        let watch = tokio::time::timeout(Duration::from_secs(60), async {
+            let mut sequences = Vec::new();
+            while sequences.last() != Some(&100) {
+                match checkpointed.recv().await {
+                    Ok(NodeEvent::Checkpointed { sequence, .. }) => sequences.push(sequence),
+                    Ok(_) | Err(broadcast::error::RecvError::Lagged(_)) => {}
+                    Err(e) => panic!("events closed: {e}"),
+                }
+            }
+            sequences
+        });
+        let ((), (), sequences) =
+            tokio::join!(submit, approve_blocks(&nodes, &mut events, 130), watch);
+        assert_eq!(sequences.expect("no checkpoint at 100"), [50, 100]);
+
+        let mut ids = Vec::new();
+        for node in &nodes {
+            ids.push(node.player_id().await);
+        }
+        let validators = Membership::equal(ids.iter().copied());
+        let quorum = config.consensus.required_weight(3);
+        let log = nodes[0].action_log("ordered").await.unwrap();
+        let signed = ActionLog::from_entries(&log.entries()[..100]).unwrap();
+        let checkpoint = loop {
+            if let Some(checkpoint) = nodes[0].latest_checkpoint("ordered").await
+                && checkpoint.sequence == 100
+            {
+                break checkpoint;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        };
+        assert!(checkpoint.verify(&validators, quorum).is_ok());
+        assert_eq!(checkpoint.state_hash, signed.hash());
+        assert_eq!(checkpoint.log_root, signed.merkle_root());
+
+        // A node joining late starts from the checkpoint, not the beginning
+        config.keypair = Some(KeyPair::generate());
+        let transport = sim.transport(&config.network);
+        let joiner = SwarmhostNode::new(config)
+            .unwrap()
+            .with_transport(transport);
+        joiner.start().await.unwrap();
+        joiner.set_validators(ids).await;
+        joiner.join_game("ordered").await.unwrap();
+        let mut joined = joiner.subscribe();
+        joiner
+            .connect(nodes[0].local_addr().await[0])
+            .await
+            .unwrap();
+        let mut restored = None;
+        let ready = tokio::time::timeout(Duration::from_secs(30), async {
+            loop {
+                match joined.recv().await.unwrap() {
+                    NodeEvent::Checkpointed { sequence, .. } => restored = Some(sequence),
+                    NodeEvent::SessionReady { sequence, .. } => return sequence,
+                    _ => {}
+                }
+            }
+        })
+        .await
+        .expect("the joiner never caught up");
+        assert_eq!(restored, Some(100));
+        assert_eq!(ready, 130);
+        assert_eq!(joiner.latest_checkpoint("ordered").await, Some(checkpoint));
+        assert_eq!(joiner.action_log("ordered").await.unwrap(), log);
+    }
+    /// Snapshot of a little over 20MB, made once for every test using it
+    fn large_snapshot() -> &'static Snapshot {
+        static LARGE: std::sync::OnceLock<Snapshot> = std::sync::OnceLock::new();
+        LARGE.get_or_init(|| made_up_snapshot(660_000))
+    }
+
+    /// Snapshot of a log of `entries` made-up actions of "ordered"
+    fn made_up_snapshot(entries: u32) -> Snapshot {
+        let actions: Vec<ActionId> = (0..entries)
+            .map(|i| crate::crypto::hash(&i.to_be_bytes()))
+            .collect();
+        let log = ActionLog::from_entries(&actions).unwrap();
+        let data = bincode::serialize(&log.image()).unwrap();
+        Snapshot::new("ordered", log.sequence(), log.hash(), data)
+    }
+
+    /// A node of the simulated network sending snapshot chunks, which are
+    /// hashes, uncompressed
+    fn sync_node(sim: &network::SimNetwork) -> SwarmhostNode {
+        let mut config = loopback_config(TransportKind::Memory);
+        config.keypair = Some(KeyPair::generate());
+        config.network.compression.algorithm = CompressionAlgorithm::None;
+        SwarmhostNode::new(config.clone())
+            .unwrap()
+            .with_transport(sim.transport(&config.network))
+    }
+
+    /// A node playing "ordered" that holds `snapshot` of it, as though it
+    /// had committed up to it, over a Wifi link to `joiner`
+    async fn provider(
+        sim: &network::SimNetwork,
+        joiner: &SwarmhostNode,
+        snapshot: &Snapshot,
+    ) -> SwarmhostNode {
+        let node = sync_node(sim);
+        node.start().await.unwrap();
+        node.join_game("ordered").await.unwrap();
+        let addr = node.local_addr().await[0];
+        sim.set_conditions(
+            joiner.local_addr().await[0],
+            addr,
+            network::LinkPreset::Wifi,
+        );
+        let peer = joiner.connect(addr).await.unwrap();
+        loop {
+            let peers = joiner.peers().await;
+            let playing = peers
+                .iter()
+                .any(|info| info.player_id == peer && info.game.as_deref() == Some("ordered"));
+            if playing {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        node.state_manager
+            .lock()
+            .await
+            .save_snapshot(snapshot)
+            .unwrap();
+        let now = tokio::time::Instant::now();
+        node.consensus
+            .lock()
+            .await
+            .skip_to("ordered", snapshot.sequence, now);
+        node
+    }
+
+    /// Tell the joiner a provider holds the game up to `sequence`, as it
+    /// does on taking a snapshot
+    async fn announce_snapshot(provider: &SwarmhostNode, sequence: u64) {
+        let state = provider.state.read().await;
+        let playing = network::PeerMessage::Playing {
+            game_id: Some("ordered".to_string()),
+            committed: sequence,
+        };
+        peers::send_to(&state, &state.connected_peers, playing);
+    }
+
+    /// Wait for the joiner to be caught up, handing `progress` the bytes of
+    /// the snapshot in each time more are
+    async fn caught_up(
+        events: &mut broadcast::Receiver<NodeEvent>,
+        mut progress: impl FnMut(u64, u64),
+    ) -> u64 {
+        let ready = tokio::time::timeout(Duration::from_secs(120), async {
+            loop {
+                match events.recv().await {
+                    Ok(NodeEvent::SnapshotProgress { received, size, .. }) => {
+                        progress(received, size)
+                    }
+                    Ok(NodeEvent::SessionReady { sequence, .. }) => return sequence,
+                    Ok(_) | Err(broadcast::error::RecvError::Lagged(_)) => {}
+                    Err(e) => panic!("events closed: {e}"),
+                }
+            }
+        });
+        ready.await.expect("the joiner never caught up")
+    }
+
+    /// Wait for more than `bytes` of the snapshot to be in
+    async fn fetched_past(events: &mut broadcast::Receiver<NodeEvent>, bytes: u64) {
+        loop {
+            match events.recv().await {
+                Ok(NodeEvent::SnapshotProgress { received, .. }) if received > bytes => return,
+                Ok(_) | Err(broadcast::error::RecvError::Lagged(_)) => {}
+                Err(e) => panic!("events closed: {e}"),
+            }
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_large_snapshot_syncs_from_two_providers_at_once() {
+        let sim = network::SimNetwork::new(46);
+        let snapshot = large_snapshot();
+        assert!(snapshot.data.len() > 20 << 20);
+        let joiner = sync_node(&sim);
+        joiner.start().await.unwrap();
+        joiner.join_game("ordered").await.unwrap();
+        let mut events = joiner.subscribe();
+        let providers = [
+            provider(&sim, &joiner, snapshot).await,
+            provider(&sim, &joiner, snapshot).await,
+        ];
+
+        let started = tokio::time::Instant::now();
+        announce_snapshot(&providers[0], snapshot.sequence).await;
+        let mut last = 0;
+        let ready = caught_up(&mut events, |received, size| {
+            assert!(received > last && received <= size);
+            last = received;
+        })
+        .await;
+        assert_eq!(ready, snapshot.sequence);
+        assert_eq!(last, snapshot.data.len() as u64);
+        let log = joiner.action_log("ordered").await.unwrap();
+        assert_eq!(log.hash(), snapshot.state_hash);
+
+        // Both sent their share, in less time than either link alone takes
+        let chunks = snapshot
+            .data
+            .len()
+            .div_ceil(crate::state::snapshot::CHUNK_SIZE) as u64;
+        let served = providers
+            .each_ref()
+            .map(|node| node.metrics().snapshot_chunks_served.get());
+        assert_eq!(served[0] + served[1], chunks);
+        assert!(
+            served.iter().all(|&served| served > chunks / 4),
+            "{:?}",
+            served
+        );
+        let alone = Duration::from_secs_f64(snapshot.data.len() as f64 / 6_000_000.0);
+        assert!(started.elapsed() < alone, "{:?}", started.elapsed());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_snapshot_sync_outlives_a_provider_and_resumes_after_losing_all() {
+        let sim = network::SimNetwork::new(47);
+        let snapshot = large_snapshot();
+        let size = snapshot.data.len() as u64;
+        let joiner = sync_node(&sim);
+        joiner.start().await.unwrap();
+        joiner.join_game("ordered").await.unwrap();
+        let mut events = joiner.subscribe();
+        let providers = [
+            provider(&sim, &joiner, snapshot).await,
+            provider(&sim, &joiner, snapshot).await,
+        ];
+
+        // One provider goes away a third of the way in, and the other sends
+        // the rest
+        announce_snapshot(&providers[0], snapshot.sequence).await;
+        fetched_past(&mut events, size / 3).await;
+        providers[1].stop().await.unwrap();
+        assert_eq!(caught_up(&mut events, |_, _| {}).await, snapshot.sequence);
+        let log = joiner.action_log("ordered").await.unwrap();
+        assert_eq!(log.hash(), snapshot.state_hash);
+
+        // A joiner left with no provider halfway keeps what it has, and
+        // fetches only the rest from the next to turn up
+        let snapshot = &made_up_snapshot(100_000);
+        let chunks = snapshot
+            .data
+            .len()
+            .div_ceil(crate::state::snapshot::CHUNK_SIZE) as u64;
+        let late = sync_node(&sim);
+        late.start().await.unwrap();
+        late.join_game("ordered").await.unwrap();
+        let mut events = late.subscribe();
+        let first = provider(&sim, &late, snapshot).await;
+        announce_snapshot(&first, snapshot.sequence).await;
+        fetched_past(&mut events, snapshot.data.len() as u64 / 2).await;
+        first.stop().await.unwrap();
+        while late.state.read().await.catching_up {
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+        let kept = late
+            .state
+            .read()
+            .await
+            .fetched
+            .as_ref()
+            .unwrap()
+            .chunks
+            .len() as u64;
+        assert!(kept > chunks / 2 && kept < chunks, "{} of {}", kept, chunks);
+
+        let second = provider(&sim, &late, snapshot).await;
+        announce_snapshot(&second, snapshot.sequence).await;
+        assert_eq!(caught_up(&mut events, |_, _| {}).await, snapshot.sequence);
+        assert_eq!(second.metrics().snapshot_chunks_served.get(), chunks - kept);
+        let log = late.action_log("ordered").await.unwrap();
+        assert_eq!(log.hash(), snapshot.state_hash);
+    }
+
+    /// A node of the simulated network playing "ordered" from `snapshot`
+    /// of it, which it holds as it would one of its own
+    async fn sync_node_from(sim: &network::SimNetwork, snapshot: &Snapshot) -> SwarmhostNode {
+        let node = sync_node(sim);
+        node.start().await.unwrap();
+        node.join_game("ordered").await.unwrap();
+        {
+            let mut state_manager = node.state_manager.lock().await;
+            state_manager.save_snapshot(snapshot).unwrap();
+            state_manager.restore(snapshot).unwrap();
+        }
+        let now = tokio::time::Instant::now();
+        node.consensus
+            .lock()
+            .await
+            .skip_to("ordered", snapshot.sequence, now);
+        node
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_joiner_holding_an_older_snapshot_is_sent_a_delta_or_else_it_whole() {
+        let sim = network::SimNetwork::new(48);
+        let (old, new) = (made_up_snapshot(10_000), made_up_snapshot(10_200));
+        let joiner = sync_node_from(&sim, &old).await;
+        let mut events = joiner.subscribe();
+        let holding_both = provider(&sim, &joiner, &new).await;
+        holding_both
+            .state_manager
+            .lock()
+            .await
+            .save_snapshot(&old)
+            .unwrap();
+
+        // The provider holds the joiner's snapshot too, so sends only what
+        // the newer one adds
+        announce_snapshot(&holding_both, new.sequence).await;
+        let mut size = 0;
+        let ready = caught_up(&mut events, |_, planned| size = planned).await;
+        assert_eq!(ready, new.sequence);
+        assert!(size > 0 && size < new.data.len() as u64 / 10, "{}", size);
+        let log = joiner.action_log("ordered").await.unwrap();
+        assert_eq!(log.hash(), new.state_hash);
+        joiner.stop().await.unwrap();
+        holding_both.stop().await.unwrap();
+
+        // One that no longer does sends the newer whole
+        let late = sync_node_from(&sim, &old).await;
+        let mut events = late.subscribe();
+        let holding_new = provider(&sim, &late, &new).await;
+        announce_snapshot(&holding_new, new.sequence).await;
+        let ready = caught_up(&mut events, |_, planned| size = planned).await;
+        assert_eq!(ready, new.sequence);
+        assert_eq!(size, new.data.len() as u64);
+        let log = late.action_log("ordered").await.unwrap();
+        assert_eq!(log.hash(), new.state_hash);
+    }
+
+    /// Hold `provider` to a checkpoint it alone signed at `snapshot`, with
+    /// the snapshot attested by `producer`, as it would serve it
+    async fn attested_by(
+        provider: &SwarmhostNode,
+        producer: &KeyPair,
+        snapshot: &Snapshot,
+    ) -> crate::state::SnapshotAttestation {
+        let log = ActionLog::from_image(bincode::deserialize(&snapshot.data).unwrap()).unwrap();
+        let vote = crate::state::CheckpointVote::new(
+            &provider.keypair,
+            "ordered",
+            snapshot.sequence,
+            log.hash(),
+            log.merkle_root(),
+        );
+        let checkpoint = Checkpoint::from_votes(&[vote]).unwrap();
+        let attestation =
+            crate::state::SnapshotAttestation::new(producer, snapshot, checkpoint.clone());
+        let mut state_manager = provider.state_manager.lock().await;
+        let signer = Membership::equal([provider.keypair.public_key()]);
+        assert!(
+            state_manager
+                .receive_checkpoint(checkpoint, &signer, 1)
+                .unwrap()
+        );
+        state_manager.save_attestation(&attestation).unwrap();
+        attestation
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_joiner_takes_up_an_attested_snapshot_only_if_it_verifies() {
+        let sim = network::SimNetwork::new(53);
+        let snapshot = made_up_snapshot(300);
+
+        // Its data changed after it was attested
+        let joiner = sync_node(&sim);
+        joiner.start().await.unwrap();
+        joiner.join_game("ordered").await.unwrap();
+        let tampered = provider(&sim, &joiner, &snapshot).await;
+        joiner.set_validators([tampered.keypair.public_key()]).await;
+        attested_by(&tampered, &tampered.keypair, &snapshot).await;
+        let mut changed = snapshot.clone();
+        let last = changed.data.len() - 1;
+        changed.data[last] ^= 1;
+        tampered
+            .state_manager
+            .lock()
+            .await
+            .save_snapshot(&changed)
+            .unwrap();
+        announce_snapshot(&tampered, snapshot.sequence).await;
+        tokio::time::sleep(Duration::from_secs(30)).await;
+        assert_eq!(joiner.consensus.lock().await.committed("ordered"), 0);
+        assert_eq!(joiner.latest_checkpoint("ordered").await, None);
+        joiner.stop().await.unwrap();
+        tampered.stop().await.unwrap();
+
+        // Produced by a node that is no validator
+        let joiner = sync_node(&sim);
+        joiner.start().await.unwrap();
+        joiner.join_game("ordered").await.unwrap();
+        let outsider = provider(&sim, &joiner, &snapshot).await;
+        joiner.set_validators([outsider.keypair.public_key()]).await;
+        attested_by(&outsider, &KeyPair::generate(), &snapshot).await;
+        announce_snapshot(&outsider, snapshot.sequence).await;
+        tokio::time::sleep(Duration::from_secs(30)).await;
+        assert_eq!(joiner.consensus.lock().await.committed("ordered"), 0);
+        joiner.stop().await.unwrap();
+        outsider.stop().await.unwrap();
+
+        // Attested by the validator, it is taken up with its checkpoint and
+        // kept with its attestation to serve on
+        let joiner = sync_node(&sim);
+        joiner.start().await.unwrap();
+        joiner.join_game("ordered").await.unwrap();
+        let mut events = joiner.subscribe();
+        let honest = provider(&sim, &joiner, &snapshot).await;
+        joiner.set_validators([honest.keypair.public_key()]).await;
+        let attestation = attested_by(&honest, &honest.keypair, &snapshot).await;
+        announce_snapshot(&honest, snapshot.sequence).await;
+        assert_eq!(caught_up(&mut events, |_, _| {}).await, snapshot.sequence);
+        assert_eq!(
+            joiner.latest_checkpoint("ordered").await,
+            Some(attestation.checkpoint.clone())
+        );
+        let state_manager = joiner.state_manager.lock().await;
+        let (served, kept) = state_manager.sync_snapshot("ordered").unwrap();
+        assert_eq!(served.data, snapshot.data);
+        assert_eq!(kept, Some(attestation));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_game_exported_from_one_node_goes_on_in_a_new_one_importing_it() {
+        let sim = network::SimNetwork::new(49);
+        let mut config = loopback_config(TransportKind::Memory);
+        config.state.snapshot_interval = 5;
+        let nodes = validator_mesh(&sim, vec![config; 3]).await;
+        for tag in 0..12u8 {
+            let action_id = nodes[0].submit_action(1, &[tag]).await.unwrap();
+            approve_everywhere(&nodes, action_id).await;
+        }
+        let played = committed(&nodes[0], 12).await;
+        let mut archive = Vec::new();
+        let exported = nodes[0].export_game("ordered", &mut archive).await.unwrap();
+        assert_eq!(
+            (exported.sequence, exported.state_hash),
+            (12, played.hash())
+        );
+        assert_eq!((exported.snapshot_sequence, exported.commits), (10, 2));
+        assert_eq!(exported.bytes, archive.len() as u64);
+
+        // A brand new node takes it up as it was, and only once; with no
+        // checkpoint yet its snapshot is unattested, so only when allowed
+        let newcomer = sync_node(&sim);
+        assert!(matches!(
+            newcomer.import_game(archive.as_slice(), false).await,
+            Err(SwarmhostError::Validation(_))
+        ));
+        let imported = newcomer
+            .import_game(archive.as_slice(), true)
+            .await
+            .unwrap();
+        assert_eq!(imported, exported);
+        assert_eq!(
+            newcomer.state_hash("ordered", None).await,
+            Some(played.hash())
+        );
+        assert!(matches!(
+            newcomer.import_game(archive.as_slice(), true).await,
+            Err(SwarmhostError::InvalidState(_))
+        ));
+
+        // And goes on committing with the original players
+        newcomer.start().await.unwrap();
+        newcomer.join_game("ordered").await.unwrap();
+        let mut addrs = vec![newcomer.local_addr().await[0]];
+        let mut ids = Vec::new();
+        for node in &nodes {
+            addrs.push(node.local_addr().await[0]);
+            ids.push(node.player_id().await);
+        }
+        newcomer.set_validators(ids).await;
+        sim.set_all_conditions(&addrs, network::LinkPreset::Wifi);
+        for addr in &addrs[1..] {
+            newcomer.connect(*addr).await.unwrap();
+        }
+        wait_for_peers(&newcomer, nodes.len()).await;
+        let action_id = newcomer.submit_action(1, b"imported").await.unwrap();
+        approve_everywhere(&nodes, action_id).await;
+        let expected = committed(&nodes[0], 13).await;
+        assert_eq!(expected.entries().last(), Some(&action_id));
+        assert_eq!(committed(&newcomer, 13).await.hash(), expected.hash());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_replaying_the_certified_commits_matches_the_live_log() {
+        let sim = network::SimNetwork::new(43);
+        let mut config = loopback_config(TransportKind::Memory);
+        config.consensus.checkpoint_interval = 10;
+        let nodes = validator_mesh(&sim, vec![config.clone(); 3]).await;
+
+        let commits = async {
+            for i in 0..25u32 {
+                let action_id = nodes[0].submit_action(1, &i.to_be_bytes()).await.unwrap();
+                nodes[0]
+                    .wait_for_commit(action_id, Duration::from_secs(10))
+                    .await
+                    .unwrap();
+            }
+            loop {
+                match nodes[0].latest_checkpoint("ordered").await {
+                    Some(checkpoint) if checkpoint.sequence == 20 => break checkpoint,
+                    _ => tokio::time::sleep(Duration::from_millis(10)).await,
+                }
+            }
+        };
+        let checkpoint = tokio::select! {
+            biased;
+            _ = async {
+                tokio::join!(
+                    approve_blocks_as_they_come(&nodes[0]),
+                    approve_blocks_as_they_come(&nodes[1]),
+                    approve_blocks_as_they_come(&nodes[2]),
+                )
+            } => unreachable!("the voters never stop"),
+            checkpoint = commits => checkpoint,
+        };
+
+        let mut ids = Vec::new();
+        for node in &nodes {
+            ids.push(node.player_id().await);
+        }
+        let validators = Membership::equal(ids);
+        let certified = nodes[0].consensus.lock().await.certified("ordered", 1, 25);
+        assert_eq!(certified.commits.len(), 25);
+        assert!(
+            certified
+                .commits
+                .iter()
+                .all(|commit| !commit.certificate.is_empty())
+        );
+        let checkpoints = [checkpoint];
+        let check = crate::state::ReplayCheck {
+            validators: &validators,
+            required: config.consensus.required_weight(3),
+            blocks: &certified.blocks,
+            checkpoints: &checkpoints,
+            mode: crate::state::ReplayMode::Strict,
+        };
+        let live = nodes[0].action_log("ordered").await.unwrap();
+        let empty = Snapshot::new(
+            "ordered",
+            0,
+            ActionLog::new().hash(),
+            bincode::serialize(&ActionLog::new().image()).unwrap(),
+        );
+        let (at_checkpoint, _) = nodes[0]
+            .state_manager
+            .lock()
+            .await
+            .sync_snapshot("ordered")
+            .unwrap();
+        assert_eq!(at_checkpoint.sequence, 20);
+        for snapshot in [empty, at_checkpoint] {
+            let outcome = crate::state::replay(&snapshot, &certified.commits, &check).unwrap();
+            assert_eq!(outcome.sequence, 25);
+            assert_eq!(outcome.state_hash, live.hash());
+            assert_eq!(outcome.log, live);
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_state_hashes_agree_at_every_sequence_until_the_state_diverges() {
+        let sim = network::SimNetwork::new(44);
+        let config = loopback_config(TransportKind::Memory);
+        let nodes = &validator_mesh(&sim, vec![config; 2]).await;
+        let commit = |range: std::ops::Range<u32>| async move {
+            let end = range.end as usize;
+            for i in range {
+                let action_id = nodes[0].submit_action(1, &i.to_be_bytes()).await.unwrap();
+                nodes[0]
+                    .wait_for_commit(action_id, Duration::from_secs(10))
+                    .await
+                    .unwrap();
+            }
+            committed(&nodes[1], end).await;
+        };
+        let hashes = |sequences: std::ops::RangeInclusive<u64>| async {
+            let mut hashes = Vec::new();
+            for sequence in sequences {
+                let a = nodes[0]
+                    .state_hash("ordered", Some(sequence))
+                    .await
+                    .unwrap();
+                let b = nodes[1]
+                    .state_hash("ordered", Some(sequence))
+                    .await
+                    .unwrap();
+                hashes.push(a == b);
+            }
+            hashes
+        };
+        let scenario = async {
+            commit(0..20).await;
+            assert_eq!(hashes(0..=20).await, [true; 21]);
+            assert_eq!(
+                nodes[1].state_hash("ordered", None).await,
+                nodes[1].state_hash("ordered", Some(20)).await
+            );
+            assert_eq!(nodes[0].state_hash("ordered", Some(21)).await, None);
+
+            nodes[1].state_manager.lock().await.flip("ordered", 12);
+            commit(20..25).await;
+            let agreed = hashes(0..=25).await;
+            assert_eq!(agreed[..12], [true; 12]);
+            assert_eq!(agreed[12..], [false; 14]);
+        };
+        tokio::select! {
+            biased;
+            _ = async {
+                tokio::join!(
+                    approve_blocks_as_they_come(&nodes[0]),
+                    approve_blocks_as_they_come(&nodes[1]),
+                )
+            } => unreachable!("the voters never stop"),
+            () = scenario => {}
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_heartbeats_report_a_diverged_state_and_find_where_it_starts() {
+        let sim = network::SimNetwork::new(50);
+        let config = loopback_config(TransportKind::Memory);
+        let mut pausing = config.clone();
+        pausing.consensus.pause_speculation_on_divergence = true;
+        let nodes = &validator_mesh(&sim, vec![config, pausing]).await;
+        let mut events = nodes[1].subscribe();
+        let scenario = async {
+            for i in 0..20u32 {
+                let action_id = nodes[0].submit_action(1, &i.to_be_bytes()).await.unwrap();
+                nodes[0]
+                    .wait_for_commit(action_id, Duration::from_secs(10))
+                    .await
+                    .unwrap();
+            }
+            committed(&nodes[1], 20).await;
+            assert_eq!(nodes[1].divergence_report("ordered").await, None);
+
+            nodes[1].state_manager.lock().await.flip("ordered", 12);
+            let (peer, sequence) = next_event(&mut events, |event| match event {
+                NodeEvent::StateDivergence { peer, sequence, .. } => Some((peer, sequence)),
+                _ => None,
+            })
+            .await;
+            assert_eq!(peer, nodes[0].player_id().await);
+            let localized = next_event(&mut events, |event| match event {
+                NodeEvent::DivergenceLocalized { sequence, .. } => Some(sequence),
+                _ => None,
+            })
+            .await;
+            assert_eq!(localized, 12);
+
+            let report = nodes[1].divergence_report("ordered").await.unwrap();
+            assert_eq!(
+                (report.sequence, report.first_divergent),
+                (sequence, Some(12))
+            );
+            assert_ne!(report.ours, report.theirs);
+            assert_eq!(report.recent.len() as u64, sequence);
+            // The node with the pause set speculates no more
+            let state_manager = nodes[1].state_manager.lock().await;
+            assert_eq!(
+                state_manager.speculative_log("ordered"),
+                state_manager.log("ordered")
+            );
+        };
+        tokio::select! {
+            biased;
+            _ = async {
+                tokio::join!(
+                    approve_blocks_as_they_come(&nodes[0]),
+                    approve_blocks_as_they_come(&nodes[1]),
+                )
+            } => unreachable!("the voters never stop"),
+            () = scenario => {}
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_a_tiny_memory_budget_is_kept_while_the_game_goes_on() {
+        const BUDGET: u64 = 32 * 1024;
+        let sim = network::SimNetwork::new(51);
+        let mut config = loopback_config(TransportKind::Memory);
+        config.state.snapshot_interval = 20;
+        config.state.max_memory_bytes = BUDGET;
+        let nodes = &validator_mesh(&sim, vec![config; 2]).await;
+        let scenario = async {
+            for i in 0..300u32 {
+                let action_id = nodes[0].submit_action(1, &i.to_be_bytes()).await.unwrap();
+                nodes[0]
+                    .wait_for_commit(action_id, Duration::from_secs(10))
+                    .await
+                    .unwrap();
+                for node in nodes {
+                    let usage = node.metrics().memory_usage();
+                    assert!(usage.total() <= BUDGET, "{:?} after {}", usage, i);
+                }
+            }
+            committed(&nodes[1], 300).await;
+            for node in nodes {
+                assert!(node.metrics().memory_sheds.get() > 0);
+                assert!(node.metrics().memory_action_log.get() > 0);
+            }
+        };
+        tokio::select! {
+            biased;
+            _ = async {
+                tokio::join!(
+                    approve_blocks_as_they_come(&nodes[0]),
+                    approve_blocks_as_they_come(&nodes[1]),
+                )
+            } => unreachable!("the voters never stop"),
+            () = scenario => {}
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_confirmed_state_is_read_whole_from_another_thread_while_commits_go_on() {
+        let sim = network::SimNetwork::new(52);
+        let config = loopback_config(TransportKind::Memory);
+        let nodes = &validator_mesh(&sim, vec![config; 2]).await;
+        assert_eq!(nodes[1].confirmed_state("ordered"), None);
+        let done = Arc::new(std::sync::atomic::AtomicBool::new(false));
+        let reader = {
+            let (confirmed, done) = (nodes[1].confirmed.clone(), done.clone());
+            std::thread::spawn(move || {
+                let mut seen = Vec::new();
+                while !done.load(std::sync::atomic::Ordering::Relaxed) {
+                    let Some(view) = confirmed.get("ordered") else {
+                        continue;
+                    };
+                    assert_eq!(view.log.sequence(), view.sequence);
+                    assert_eq!(view.log.hash(), view.state_hash);
+                    if seen.last() != Some(&view.sequence) {
+                        seen.push(view.sequence);
+                    }
+                }
+                seen
+            })
+        };
+        let scenario = async {
+            for i in 0..100u32 {
+                let action_id = nodes[0].submit_action(1, &i.to_be_bytes()).await.unwrap();
+                nodes[0]
+                    .wait_for_commit(action_id, Duration::from_secs(10))
+                    .await
+                    .unwrap();
+            }
+            committed(&nodes[1], 100).await;
+        };
+        tokio::select! {
+            biased;
+            _ = async {
+                tokio::join!(
+                    approve_blocks_as_they_come(&nodes[0]),
+                    approve_blocks_as_they_come(&nodes[1]),
+                )
+            } => unreachable!("the voters never stop"),
+            () = scenario => {}
+        }
+        done.store(true, std::sync::atomic::Ordering::Relaxed);
+        let seen = reader.join().unwrap();
+        assert!(seen.is_sorted(), "{:?}", seen);
+
+        let view = nodes[1].confirmed_state("ordered").unwrap();
+        assert_eq!(view.sequence, 100);
+        assert_eq!(
+            Some(view.state_hash),
+            nodes[1].state_hash("ordered", None).await
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_a_lagging_peer_holds_pruning_back_and_still_catches_up() {
+        let sim = network::SimNetwork::new(45);
+        let mut config = loopback_config(TransportKind::Memory);
+        config.state.snapshot_interval = 10;
+        config.state.max_action_log_size = 20;
+        let nodes = &validator_mesh(&sim, vec![config.clone(); 3]).await;
+        let mut ids = Vec::new();
+        let mut addrs = Vec::new();
+        for node in nodes {
+            ids.push(node.player_id().await);
+            addrs.push(node.local_addr().await[0]);
+        }
+
+        // A spectator follows the game, until its links are cut
+        config.keypair = Some(KeyPair::generate());
+        let spectator = &SwarmhostNode::new(config.clone())
+            .unwrap()
+            .with_transport(sim.transport(&config.network));
+        spectator.start().await.unwrap();
+        spectator.join_game("ordered").await.unwrap();
+        for addr in &addrs {
+            spectator.connect(*addr).await.unwrap();
+        }
+        wait_for_peers(spectator, nodes.len()).await;
+        spectator.set_validators(ids).await;
+        let lagging = spectator.local_addr().await[0];
+        let cut = network::NetworkConditions::perfect().with_loss(1.0);
+        for addr in &addrs {
+            sim.set_conditions(lagging, *addr, cut.clone());
+        }
+
+        let commit = |range: std::ops::Range<u32>| async move {
+            for i in range {
+                let action_id = nodes[0].submit_action(1, &i.to_be_bytes()).await.unwrap();
+                nodes[0]
+                    .wait_for_commit(action_id, Duration::from_secs(10))
+                    .await
+                    .unwrap();
+            }
+        };
+        let scenario = async {
+            commit(0..60).await;
+            for node in nodes {
+                committed(node, 60).await;
+            }
+            // Well past the log's limit, nothing the spectator lacks goes
+            assert_eq!(spectator.consensus.lock().await.committed("ordered"), 0);
+            let held = nodes[0].action_log("ordered").await.unwrap();
+            assert_eq!((held.pruned(), held.entries().len()), (0, 60));
+            // Unless forced
+            assert!(nodes[1].prune_action_log("ordered", true).await.unwrap() > 0);
+
+            // Its links are given up on after a peer timeout; healed, it
+            // redials and catches up, and once it says it holds them they go
+            let network = &config.network;
+            tokio::time::sleep(network.peer_timeout + network.heartbeat_interval * 2).await;
+            for addr in &addrs {
+                sim.set_conditions(lagging, *addr, network::LinkPreset::Wifi);
+            }
+            commit(60..80).await;
+            assert_eq!(
+                committed(spectator, 80).await.hash(),
+                committed(&nodes[0], 80).await.hash()
+            );
+            loop {
+                let log = nodes[0].action_log("ordered").await.unwrap();
+                if log.pruned() >= 60 {
+                    let metrics = nodes[0].metrics();
+                    assert_eq!(metrics.log_entries_pruned.get(), log.pruned());
+                    assert!(log.entries().len() <= 20);
+                    break;
+                }
+                tokio::time::sleep(Duration::from_millis(100)).await;
+            }
+        };
+        tokio::select! {
+            biased;
+            _ = async {
+                tokio::join!(
+                    approve_blocks_as_they_come(&nodes[0]),
+                    approve_blocks_as_they_come(&nodes[1]),
+                    approve_blocks_as_they_come(&nodes[2]),
+                )
+            } => unreachable!("the voters never stop"),
+            () = scenario => {}
+        }
+    }
+
+    /// Rejects every action of type 7, whatever the game makes of it
+    struct NoSevens;
+
+    #[async_trait::async_trait]
+    impl ActionValidator for NoSevens {
+        async fn validate(
+            &self,
+            _ctx: &crate::consensus::ValidationContext,
+            _actor: &PlayerId,
+            action_type: u32,
+            _data: &[u8],
+        ) -> ValidationResult {
+            if action_type == 7 {
+                return ValidationResult::Invalid(RejectCode(RejectCode::FIRST_CUSTOM));
+            }
+            ValidationResult::Valid
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_custom_validator_turns_the_quorum_against_an_action() {
+        let sim = network::SimNetwork::new(23);
+        let config = loopback_config(TransportKind::Memory);
+        let nodes =
+            validator_mesh_with(&sim, vec![config; 3], |node| node.with_validator(NoSevens)).await;
+
+        nodes[0].submit_action(7, b"seven").await.unwrap();
+        nodes[0].submit_action(1, b"one").await.unwrap();
+        let pending = nodes[0].consensus.lock().await.pending().to_vec();
+        let of_type = |action_type| {
+            pending
+                .iter()
+                .find(|action| action.action_type == action_type)
+                .unwrap()
+                .id()
+        };
+        let (seven, one) = (of_type(7), of_type(1));
+        // The game approves both
+        approve_everywhere(&nodes, seven).await;
+        approve_everywhere(&nodes, one).await;
+
+        let log = committed(&nodes[0], 1).await;
+        assert_eq!(log.entries(), [one]);
+        for node in &nodes {
+            let consensus = node.consensus.lock().await;
+            assert_eq!(
+                consensus.tally(&seven).outcome(),
+                crate::consensus::Outcome::Rejected
+            );
+            let votes = consensus.votes(&seven);
+            assert_eq!(votes.len(), 3);
+            assert!(votes.iter().all(|vote| {
+                !vote.approves() && vote.reason == Some(RejectCode(RejectCode::FIRST_CUSTOM))
+            }));
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_commit_timeout_names_the_action_round_and_missing_voters() {
+        let sim = network::SimNetwork::new(55);
+        let nodes = validator_mesh(&sim, vec![loopback_config(TransportKind::Memory); 3]).await;
+        let mut withholding = vec![nodes[1].player_id().await, nodes[2].player_id().await];
+        withholding.sort();
+
+        // Only the submitter approves; the other two never vote
+        let stuck = async {
+            let action_id = nodes[0].submit_action(1, b"stuck").await.unwrap();
+            let result = nodes[0]
+                .wait_for_commit(action_id, Duration::from_secs(2))
+                .await;
+            (action_id, result.unwrap_err())
+        };
+        let (action_id, error) = tokio::select! {
+            biased;
+            _ = approve_blocks_as_they_come(&nodes[0]) => unreachable!("the voter never stops"),
+            stuck = stuck => stuck,
+        };
+
+        assert!(
+            matches!(error, SwarmhostError::CommitTimeout { .. }),
+            "{}",
+            error
+        );
+        assert_eq!(error.code(), crate::error::ErrorCode::Timeout);
+        assert_eq!(error.action_id(), Some(action_id));
+        assert!(error.round().is_some());
+        let mut missing = error.missing_voters().to_vec();
+        missing.sort();
+        assert_eq!(missing, withholding);
+        let shown = error.to_string();
+        assert!(shown.contains(&short_id(&action_id)), "{}", shown);
+        assert!(shown.contains(&short_id(&withholding[0])), "{}", shown);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_a_refused_forward_fails_the_wait_with_the_validators_code() {
+        let sim = network::SimNetwork::new(56);
+        let mut config = loopback_config(TransportKind::Memory);
+        config.consensus.max_action_size = 64;
+        let nodes = validator_mesh(&sim, vec![config; 3]).await;
+
+        // A player allowing larger actions than the validators do
+        let player = sync_node(&sim);
+        player.start().await.unwrap();
+        player.join_game("ordered").await.unwrap();
+        let mut addrs = vec![player.local_addr().await[0]];
+        let mut ids = Vec::new();
+        for node in &nodes {
+            addrs.push(node.local_addr().await[0]);
+            ids.push(node.player_id().await);
+        }
+        player.set_validators(ids.clone()).await;
+        sim.set_all_conditions(&addrs, network::LinkPreset::Wifi);
+        for addr in &addrs[1..] {
+            player.connect(*addr).await.unwrap();
+        }
+        wait_for_peers(&player, nodes.len()).await;
+
+        let action_id = player.submit_action(1, &[7; 128]).await.unwrap();
+        let error = player
+            .wait_for_commit(action_id, Duration::from_secs(5))
+            .await
+            .unwrap_err();
+        match &error {
+            SwarmhostError::Remote {
+                peer,
+                code,
+                message,
+            } => {
+                assert!(ids.contains(peer));
+                assert_eq!(*code, crate::error::ErrorCode::Validation);
+                assert!(message.contains("max_action_size 64"), "{}", message);
+            }
+            other => panic!("expected the validator's error, got {}", other),
+        }
+        assert!(!error.is_retryable());
+    }
+
+    /// Approve every block proposed to `node` the moment it arrives
+    async fn approve_blocks_as_they_come(node: &SwarmhostNode) {
+        let mut events = node.subscribe();
+        loop {
+            match events.recv().await {
+                Ok(NodeEvent::BlockProposed { block, .. }) => {
+                    node.vote_block(block, &[]).await.unwrap()
+                }
+                Ok(_) | Err(broadcast::error::RecvError::Lagged(_)) => {}
+                Err(broadcast::error::RecvError::Closed) => return,
+            }
+        }
+    }
+
+    /// Approve every block proposed to `node`, taking `delay` over each,
+    /// like a game slow to apply them
+    async fn approve_blocks_slowly(node: &SwarmhostNode, delay: Duration) {
+        let mut events = node.subscribe();
+        loop {
+            match events.recv().await {
+                Ok(NodeEvent::BlockProposed { block, .. }) => {
+                    tokio::time::sleep(delay).await;
+                    let _ = node.vote_block(block, &[]).await;
+                }
+                Ok(_) | Err(broadcast::error::RecvError::Lagged(_)) => {}
+                Err(broadcast::error::RecvError::Closed) => return,
+            }
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_intake_stays_bounded_when_submitting_faster_than_blocks_are_approved() {
+        const MAX_PENDING: usize = 8;
+        // Approving a block takes the voters this long
+        const APPLY: Duration = Duration::from_millis(50);
+        let sim = network::SimNetwork::new(54);
+        let mut config = loopback_config(TransportKind::Memory);
+        config.consensus.max_pending_actions = MAX_PENDING;
+        config.consensus.max_actions_per_player_per_round = 1000;
+        config.consensus.submit_wait = Duration::from_millis(20);
+        let nodes = validator_mesh(&sim, vec![config; 3]).await;
+
+        let scenario = async {
+            let (mut accepted, mut overloaded, mut most_pending) = (0, 0, 0);
+            for i in 0..300u32 {
+                match nodes[i as usize % 3]
+                    .submit_action(1, &i.to_be_bytes())
+                    .await
+                {
+                    Ok(_) => accepted += 1,
+                    Err(SwarmhostError::Overloaded(_)) => overloaded += 1,
+                    Err(e) => panic!("{}", e),
+                }
+                for node in &nodes {
+                    most_pending = most_pending.max(node.status().await.pending_actions);
+                }
+            }
+            let caught_up = nodes[0].consensus.lock().await.committed("ordered");
+            committed(&nodes[0], accepted as usize).await;
+            (accepted, overloaded, most_pending, caught_up)
+        };
+        let (accepted, overloaded, most_pending, committed) = tokio::select! {
+            biased;
+            _ = async {
+                tokio::join!(
+                    approve_blocks_slowly(&nodes[0], APPLY),
+                    approve_blocks_slowly(&nodes[1], APPLY),
+                    approve_blocks_slowly(&nodes[2], APPLY),
+                )
+            } => unreachable!("the voters never stop"),
+            outcome = scenario => outcome,
+        };
+
+        // Each node takes in no more than its limit, besides what the
+        // others put in their rounds
+        assert!(
+            most_pending <= MAX_PENDING * nodes.len(),
+            "{} pending",
+            most_pending
+        );
+        // Submitting ran no further ahead of the blocks approved than that
+        assert!(overloaded > 0);
+        assert!(
+            accepted - committed <= (MAX_PENDING * nodes.len()) as u64,
+            "{} accepted, {} committed",
+            accepted,
+            committed
+        );
+        assert!(nodes[0].metrics().actions_overloaded.get() > 0);
+        assert_eq!(nodes[0].status().await.held_actions, 0);
+        // Forwards a full proposer had no room for were answered busy, and
+        // held their senders off
+        let busy = |count: fn(&NodeMetrics) -> u64| {
+            nodes.iter().map(|node| count(node.metrics())).sum::<u64>()
+        };
+        assert!(busy(|metrics| metrics.busy_sent.get()) > 0);
+        assert!(busy(|metrics| metrics.busy_received.get()) > 0);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_commit_takes_about_one_round_trip_when_everyone_approves() {
+        let sim = network::SimNetwork::new(25);
+        let nodes = validator_mesh(&sim, vec![loopback_config(TransportKind::Memory); 5]).await;
+        let mut addrs = Vec::new();
+        for node in &nodes {
+            addrs.push(node.local_addr().await[0]);
+        }
+        let one_way = Duration::from_millis(20);
+        let lossless =
+            network::NetworkConditions::perfect().with_latency(one_way, network::Jitter::None);
+        sim.set_all_conditions(&addrs, lossless);
+
+        let mut sequencer = 0;
+        for (i, node) in nodes.iter().enumerate() {
+            let id = node.player_id().await;
+            if node.consensus.lock().await.sequencer() == Some(id) {
+                sequencer = i;
+            }
+        }
+        let mut events = nodes[sequencer].subscribe();
+        let committed = async {
+            let started = tokio::time::Instant::now();
+            nodes[sequencer].submit_action(1, b"move").await.unwrap();
+            loop {
+                if let Ok(NodeEvent::ActionCommitted {
+                    game_id, sequence, ..
+                }) = events.recv().await
+                {
+                    return (started.elapsed(), game_id, sequence);
+                }
+            }
+        };
+        // Voters first, so each subscribes before the action goes out
+        let (elapsed, game_id, sequence) = tokio::select! {
+            biased;
+            _ = async {
+                tokio::join!(
+                    approve_blocks_as_they_come(&nodes[0]),
+                    approve_blocks_as_they_come(&nodes[1]),
+                    approve_blocks_as_they_come(&nodes[2]),
+                    approve_blocks_as_they_come(&nodes[3]),
+                    approve_blocks_as_they_come(&nodes[4]),
+                )
+            } => unreachable!("the voters never stop"),
+            committed = committed => committed,
+        };
+
+        // The block goes out and the approvals come back: one round trip,
+        // validating taking no time on the simulated clock
+        let round_trip = one_way * 2;
+        assert!(elapsed >= round_trip, "{:?}", elapsed);
+        assert!(
+            elapsed <= round_trip + Duration::from_millis(5),
+            "{:?}",
+            elapsed
+        );
+        let metrics = nodes[sequencer].metrics();
+        assert_eq!(metrics.commits_fast_path.get(), 1);
+        assert_eq!(metrics.commits_normal_path.get(), 0);
+
+        // Certified by every validator, not just a quorum of them
+        let consensus = nodes[sequencer].consensus.lock().await;
+        let commit = consensus.delivered_at(&game_id, sequence).unwrap();
+        let signers: HashSet<PlayerId> = commit.certificate.iter().map(|vote| vote.voter).collect();
+        assert_eq!(signers, consensus.validators().ids().copied().collect());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_commit_waits_the_fast_path_timeout_for_a_silent_validator() {
+        let sim = network::SimNetwork::new(26);
+        let nodes = validator_mesh(&sim, vec![loopback_config(TransportKind::Memory); 5]).await;
+        let mut addrs = Vec::new();
+        for node in &nodes {
+            addrs.push(node.local_addr().await[0]);
+        }
+        let one_way = Duration::from_millis(20);
+        let lossless =
+            network::NetworkConditions::perfect().with_latency(one_way, network::Jitter::None);
+        sim.set_all_conditions(&addrs, lossless);
+
+        let mut sequencer = 0;
+        for (i, node) in nodes.iter().enumerate() {
+            let id = node.player_id().await;
+            if node.consensus.lock().await.sequencer() == Some(id) {
+                sequencer = i;
+            }
+        }
+        // Every validator but one approves; it never votes
+        let voters: Vec<&SwarmhostNode> = nodes
+            .iter()
+            .enumerate()
+            .filter(|(i, _)| *i != (sequencer + 1) % nodes.len())
+            .map(|(_, node)| node)
+            .collect();
+        let mut events = nodes[sequencer].subscribe();
+        let committed = async {
+            let started = tokio::time::Instant::now();
+            nodes[sequencer].submit_action(1, b"move").await.unwrap();
+            loop {
+                if let Ok(NodeEvent::ActionCommitted { .. }) = events.recv().await {
+                    return started.elapsed();
+                }
+            }
+        };
+        let elapsed = tokio::select! {
+            biased;
+            _ = async {
+                tokio::join!(
+                    approve_blocks_as_they_come(voters[0]),
+                    approve_blocks_as_they_come(voters[1]),
+                    approve_blocks_as_they_come(voters[2]),
+                    approve_blocks_as_they_come(voters[3]),
+                )
+            } => unreachable!("the voters never stop"),
+            elapsed = committed => elapsed,
+        };
+
+        // The quorum is in after one round trip, and the sequencer waits
+        // on the last validator no longer than the timeout
+        let due = one_way * 2 + nodes[sequencer].config().consensus.fast_path_timeout;
+        assert!(elapsed >= due, "{:?}", elapsed);
+        assert!(elapsed <= due + Duration::from_millis(5), "{:?}", elapsed);
+        let metrics = nodes[sequencer].metrics();
+        assert_eq!(metrics.commits_fast_path.get(), 0);
+        assert_eq!(metrics.commits_normal_path.get(), 1);
+    }
+
+    /// Sim time for 4 validators on 50ms links to commit 16 actions one
+    /// node submits at once, a block each
+    async fn pipelined_workload(seed: u64, consensus: ConsensusConfig) -> Duration {
+        let sim = network::SimNetwork::new(seed);
+        let mut config = loopback_config(TransportKind::Memory);
+        config.consensus = ConsensusConfig {
+            batch_max_actions: 1,
+            ..consensus
+        };
+        let nodes = validator_mesh(&sim, vec![config; 4]).await;
+        let mut addrs = Vec::new();
+        for node in &nodes {
+            addrs.push(node.local_addr().await[0]);
+        }
+        let lossless = network::NetworkConditions::perfect()
+            .with_latency(Duration::from_millis(50), network::Jitter::None);
+        sim.set_all_conditions(&addrs, lossless);
+
+        let workload = async {
+            let started = tokio::time::Instant::now();
+            for i in 0..16u8 {
+                nodes[0].submit_action(1, &[i]).await.unwrap();
+            }
+            committed(&nodes[0], 16).await;
+            started.elapsed()
+        };
+        let elapsed = tokio::select! {
+            biased;
+            _ = async {
+                tokio::join!(
+                    approve_blocks_as_they_come(&nodes[0]),
+                    approve_blocks_as_they_come(&nodes[1]),
+                    approve_blocks_as_they_come(&nodes[2]),
+                    approve_blocks_as_they_come(&nodes[3]),
+                )
+            } => unreachable!("the voters never stop"),
+            elapsed = workload => elapsed,
+        };
+        let order = committed(&nodes[0], 16).await;
+        for node in &nodes[1..] {
+            assert_eq!(committed(node, 16).await.entries(), order.entries());
+        }
+        elapsed
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_pipelining_raises_throughput_over_slow_links() {
+        let serial = pipelined_workload(
+            26,
+            ConsensusConfig {
+                pipelining: false,
+                ..Default::default()
+            },
+        )
+        .await;
+        let pipelined = pipelined_workload(26, ConsensusConfig::default()).await;
+        assert!(
+            pipelined * 2 < serial,
+            "{:?} pipelined, {:?} one block at a time",
+            pipelined,
+            serial
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_round_metrics_count_latency_turnout_and_stragglers() {
+        let sim = network::SimNetwork::new(27);
+        // Rounds commit on the quorum, without waiting on the straggler
+        let mut config = loopback_config(TransportKind::Memory);
+        config.consensus.fast_path_timeout = Duration::ZERO;
+        let nodes = validator_mesh(&sim, vec![config; 4]).await;
+        let timeout = nodes[0].config.consensus.consensus_timeout;
+        let mut addrs = Vec::new();
+        for node in &nodes {
+            addrs.push(node.local_addr().await[0]);
+        }
+        let one_way = Duration::from_millis(20);
+        let lossless =
+            network::NetworkConditions::perfect().with_latency(one_way, network::Jitter::None);
+        sim.set_all_conditions(&addrs, lossless);
+        let straggler = nodes[3].player_id().await;
+
+        // Three of four validators make a quorum without the straggler,
+        // which votes on the first block 200ms in and never on the second
+        let mut straggling = nodes[3].subscribe();
+        let scenario = async {
+            nodes[0].submit_action(1, b"first").await.unwrap();
+            let block = next_event(&mut straggling, |event| match event {
+                NodeEvent::BlockProposed { block, .. } => Some(block),
+                _ => None,
+            })
+            .await;
+            committed(&nodes[0], 1).await;
+            tokio::time::sleep(Duration::from_millis(200)).await;
+            nodes[3].vote_block(block, &[]).await.unwrap();
+            nodes[0].submit_action(1, b"second").await.unwrap();
+            committed(&nodes[0], 2).await;
+            // Past the consensus timeout, and a gap check
+            tokio::time::sleep(timeout * 2).await;
+        };
+        tokio::select! {
+            biased;
+            _ = async {
+                tokio::join!(
+                    approve_blocks_as_they_come(&nodes[0]),
+                    approve_blocks_as_they_come(&nodes[1]),
+                    approve_blocks_as_they_come(&nodes[2]),
+                )
+            } => unreachable!("the voters never stop"),
+            () = scenario => {}
+        }
+
+        let metrics = nodes[0].metrics();
+        assert_eq!(metrics.round_latency.count(), 2);
+        let summary = metrics.round_latency.summary().unwrap();
+        // From the block reaching us, a hop or two for the votes and the
+        // commit to come in
+        assert!(summary.p50 >= one_way, "{:?}", summary);
+        assert!(summary.p99 <= one_way * 3, "{:?}", summary);
+        assert_eq!(metrics.round_votes_received.get(), 6);
+        assert_eq!(metrics.round_votes_expected.get(), 8);
+        assert_eq!(metrics.validators_late.snapshot(), [(straggler, 1)].into());
+        assert_eq!(
+            metrics.validators_silent.snapshot(),
+            [(straggler, 1)].into()
+        );
+        assert_eq!(metrics.pipeline_occupancy.get(), 0);
+        assert_eq!(metrics.rounds_failed_timeout.get(), 0);
+        assert_eq!(metrics.rounds_failed_rejected.get(), 0);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_snapshots_taken_every_interval_without_holding_up_commits() {
+        let sim = network::SimNetwork::new(42);
+        let mut config = loopback_config(TransportKind::Memory);
+        config.state.snapshot_interval = 100;
+        config.state.max_snapshots_in_memory = 2;
+        let nodes = validator_mesh(&sim, vec![config; 3]).await;
+        let mut events = nodes[0].subscribe();
+
+        let commits = async {
+            let mut latencies = Vec::new();
+            for i in 0..350u32 {
+                let submitted = tokio::time::Instant::now();
+                let action_id = nodes[0].submit_action(1, &i.to_be_bytes()).await.unwrap();
+                let outcome = nodes[0]
+                    .wait_for_commit(action_id, Duration::from_secs(10))
+                    .await
+                    .unwrap();
+                assert_eq!(
+                    outcome,
+                    CommitOutcome::Committed {
+                        sequence: i as u64 + 1
+                    }
+                );
+                latencies.push(submitted.elapsed());
+            }
+            latencies
+        };
+        let snapshots = async {
+            let mut created = Vec::new();
+            while created.len() < 3 {
+                if let NodeEvent::SnapshotCreated { sequence, size, .. } =
+                    events.recv().await.unwrap()
+                {
+                    created.push((sequence, size));
+                }
+            }
+            created
+        };
+        let (latencies, created) = tokio::select! {
+            biased;
+            _ = async {
+                tokio::join!(
+                    approve_blocks_as_they_come(&nodes[0]),
+                    approve_blocks_as_they_come(&nodes[1]),
+                    approve_blocks_as_they_come(&nodes[2]),
+                )
+            } => unreachable!("the voters never stop"),
+            done = async { tokio::join!(commits, snapshots) } => done,
+        };
+
+        // Each holds the ids of the actions up to it, after the pruned count,
+        // the base hash, no peaks and their count
+        let sizes = |sequence: u64| (sequence, 56 + 32 * sequence as usize);
+        assert_eq!(created, [sizes(100), sizes(200), sizes(300)]);
+        let latest = nodes[0].latest_snapshot("ordered").await.unwrap().unwrap();
+        assert_eq!(latest.sequence, 300);
+
+        // The commits making a snapshot due take no longer than the rest
+        let mut sorted = latencies.clone();
+        sorted.sort();
+        let p90 = sorted[sorted.len() * 9 / 10];
+        for sequence in [100, 200, 300] {
+            let latency = latencies[sequence - 1];
+            assert!(
+                latency <= p90 + Duration::from_millis(5),
+                "commit {} took {:?}, p90 {:?}",
+                sequence,
+                latency,
+                p90
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_dictating_weights_need_allow_dictatorship() {
+        let server = [1; 32];
+        let clients = [[2; 32], [3; 32]];
+        let weights = [(server, 4), (clients[0], 1), (clients[1], 1)];
+
+        let node = SwarmhostNode::new(loopback_config(TransportKind::Memory)).unwrap();
+        assert!(matches!(
+            node.set_validator_weights(weights).await,
+            Err(SwarmhostError::Config(_))
+        ));
+        // 4 of 6 needs the server and a client
+        node.set_validator_weights([(server, 3), (clients[0], 2), (clients[1], 1)])
+            .await
+            .unwrap();
+        assert_eq!(node.consensus.lock().await.required_weight(), 4);
+
+        let mut config = loopback_config(TransportKind::Memory);
+        config.consensus.allow_dictatorship = true;
+        let authoritative = SwarmhostNode::new(config).unwrap();
+        authoritative.set_validator_weights(weights).await.unwrap();
+        let consensus = authoritative.consensus.lock().await;
+        assert_eq!(consensus.validators().weight_of(&server), 4);
+        assert_eq!(consensus.required_weight(), 4);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_committed_weight_change_reweighs_every_node() {
+        let sim = network::SimNetwork::new(24);
+        let nodes = validator_mesh(&sim, vec![loopback_config(TransportKind::Memory); 3]).await;
+        let heavy = nodes[0].player_id().await;
+        let reweigh = |weight| MembershipChange::ChangeWeight {
+            validator: heavy,
+            weight,
+        };
+
+        // Weighing 5 of 7, one validator would make a quorum alone, and
+        // three validators are the fewest allowed
+        assert!(matches!(
+            nodes[0].submit_membership_change(reweigh(5), 2).await,
+            Err(SwarmhostError::Config(_))
+        ));
+        let removal = MembershipChange::RemoveValidator { validator: heavy };
+        assert!(matches!(
+            nodes[0].submit_membership_change(removal, 2).await,
+            Err(SwarmhostError::Validation(_))
+        ));
+        nodes[0]
+            .submit_membership_change(reweigh(2), 2)
+            .await
+            .unwrap();
+        let change = nodes[0].consensus.lock().await.pending()[0].id();
+        approve_everywhere(&nodes, change).await;
+
+        // Agreed by the old weights, it takes effect right after its own
+        // commit: 3 of 4
+        let deadline = tokio::time::Instant::now() + Duration::from_secs(10);
+        for node in &nodes {
+            assert_eq!(committed(node, 1).await.entries(), [change]);
+            loop {
+                let info = node.consensus_info().await;
+                if (info.reachable, info.required) == (4, 3) {
+                    break;
+                }
+                assert!(tokio::time::Instant::now() < deadline, "not reweighed");
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+            let consensus = node.consensus.lock().await;
+            assert_eq!(consensus.validators().weight_of(&heavy), 2);
+            assert_eq!(consensus.required_weight(), 3);
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_added_validator_takes_its_turn_from_the_activation() {
+        let sim = network::SimNetwork::new(28);
+        let nodes = validator_mesh(&sim, vec![loopback_config(TransportKind::Memory); 4]).await;
+        let mut ids = Vec::new();
+        for node in &nodes {
+            ids.push(node.player_id().await);
+        }
+        let newcomer = ids[3];
+        for node in &nodes {
+            node.set_validators(ids[..3].to_vec()).await;
+        }
+
+        // Agreed by the first three at 1, the newcomer joins from 3
+        let joining = MembershipChange::AddValidator {
+            validator: newcomer,
+            weight: 1,
+        };
+        nodes[0].submit_membership_change(joining, 3).await.unwrap();
+        let change = last_submitted(&nodes[0]).await;
+        approve_everywhere(&nodes[..3], change).await;
+        nodes[1].submit_action(1, b"before").await.unwrap();
+        let before = last_submitted(&nodes[1]).await;
+        for node in &nodes {
+            committed(node, 1).await;
+            assert!(!node.consensus.lock().await.validators().contains(&newcomer));
+        }
+        approve_everywhere(&nodes[..3], before).await;
+        for node in &nodes {
+            committed(node, 2).await;
+            assert_eq!(node.consensus.lock().await.validators().len(), 4);
+        }
+
+        // Rounds go on until its turn, where it proposes the next block
+        let mut events = nodes[0].subscribe();
+        let mut turns = Vec::new();
+        for count in 3..3 + nodes.len() {
+            let info = nodes[0].consensus_info().await;
+            nodes[0].submit_action(1, &[count as u8]).await.unwrap();
+            let action_id = last_submitted(&nodes[0]).await;
+            let round = next_event(&mut events, |event| match event {
+                NodeEvent::BlockProposed { round, actions, .. } if actions.contains(&action_id) => {
+                    Some(round)
+                }
+                _ => None,
+            })
+            .await;
+            approve_everywhere(&nodes, action_id).await;
+            for node in &nodes {
+                committed(node, count).await;
+            }
+            turns.push(info.proposer);
+            if info.proposer == Some(newcomer) {
+                assert_eq!(round, info.round);
+                break;
+            }
+        }
+        assert_eq!(turns.last(), Some(&Some(newcomer)), "turns: {:?}", turns);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_adaptive_quorum_keeps_committing_as_validators_are_removed() {
+        let sim = network::SimNetwork::new(39);
+        let mut config = loopback_config(TransportKind::Memory);
+        config.consensus.adaptive_quorum = true;
+        let nodes = validator_mesh(&sim, vec![config; 6]).await;
+
+        // Three players leave one after the other, each removal agreed by
+        // those still in and in effect right after its own commit
+        for (removed, leaving) in nodes[3..].iter().rev().enumerate() {
+            let staying = &nodes[..nodes.len() - removed];
+            let removal = MembershipChange::RemoveValidator {
+                validator: leaving.player_id().await,
+            };
+            nodes[0]
+                .submit_membership_change(removal, removed as u64 + 2)
+                .await
+                .unwrap();
+            let change = last_submitted(&nodes[0]).await;
+            approve_everywhere(staying, change).await;
+            for node in &staying[..staying.len() - 1] {
+                committed(node, removed + 1).await;
+                loop {
+                    let consensus = node.consensus.lock().await;
+                    if consensus.validators().len() == staying.len() - 1 {
+                        break;
+                    }
+                    drop(consensus);
+                    tokio::time::sleep(Duration::from_millis(10)).await;
+                }
+            }
+        }
+
+        // Gone for good, they are not missed: two of the three left agree
+        let mut addrs = Vec::new();
+        for node in &nodes {
+            addrs.push(node.local_addr().await[0]);
+        }
+        let cut = network::NetworkConditions::perfect().with_loss(1.0);
+        for gone in &addrs[3..] {
+            for addr in &addrs {
+                if addr != gone {
+                    sim.set_conditions(*addr, *gone, cut.clone());
+                }
+            }
+        }
+        nodes[1].submit_action(1, b"after").await.unwrap();
+        let after = last_submitted(&nodes[1]).await;
+        approve_everywhere(&nodes[..2], after).await;
+        for node in &nodes[..3] {
+            assert_eq!(committed(node, 4).await.entries()[3], after);
+            assert_eq!(node.consensus.lock().await.required_weight(), 2);
+        }
+    }
+
+    /// Six validators of "ordered" with three that go silent without
+    /// leaving, once the other three noticed; the session is degraded when
+    /// this returns, with the reachable validators approving every block
+    /// in the background
+    async fn three_of_six_gone_silent(
+        sim: &network::SimNetwork,
+        quorum_grace: bool,
+    ) -> Vec<SwarmhostNode> {
+        let mut config = loopback_config(TransportKind::Memory);
+        config.network.heartbeat_interval = Duration::from_millis(500);
+        config.network.peer_timeout = Duration::from_secs(2);
+        config.network.reconnect.window = Duration::from_secs(60);
+        config.consensus.when_degraded = DegradedActions::Queue;
+        config.consensus.quorum_loss_timeouts = 1;
+        config.consensus.quorum_grace = quorum_grace;
+        config.consensus.grace_period = Duration::from_secs(10);
+        let nodes = validator_mesh(sim, vec![config; 6]).await;
+        let mut addrs = Vec::new();
+        for node in &nodes {
+            addrs.push(node.local_addr().await[0]);
+        }
+        let cut = network::NetworkConditions::perfect().with_loss(1.0);
+        for gone in &addrs[3..] {
+            for addr in &addrs {
+                if addr != gone {
+                    sim.set_conditions(*addr, *gone, cut.clone());
+                }
+            }
+        }
+        let mut events = nodes[0].subscribe();
+        let lost = next_event(&mut events, |event| match event {
+            NodeEvent::QuorumLost {
+                reachable,
+                required,
+            } => Some((reachable, required)),
+            _ => None,
+        })
+        .await;
+        assert_eq!(lost, (3, 4));
+        nodes
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_silent_validators_stall_the_game_without_the_grace_rule() {
+        let sim = network::SimNetwork::new(40);
+        let nodes = three_of_six_gone_silent(&sim, false).await;
+        let stalled = async {
+            nodes[0].submit_action(1, b"stuck").await.unwrap();
+            tokio::time::sleep(Duration::from_secs(30)).await;
+        };
+        tokio::select! {
+            biased;
+            _ = async {
+                tokio::join!(
+                    approve_blocks_as_they_come(&nodes[0]),
+                    approve_blocks_as_they_come(&nodes[1]),
+                    approve_blocks_as_they_come(&nodes[2]),
+                )
+            } => unreachable!("the voters never stop"),
+            () = stalled => {}
+        }
+
+        for node in &nodes[..3] {
+            assert!(node.action_log("ordered").await.is_none());
+            let info = node.consensus_info().await;
+            assert!(info.degraded);
+            assert_eq!((info.reachable, info.required), (3, 4));
+            assert_eq!(node.consensus.lock().await.validators().len(), 6);
+        }
+        assert_eq!(nodes[0].consensus_info().await.queued, 1);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_grace_rule_lets_the_reachable_validators_go_on_alone() {
+        let sim = network::SimNetwork::new(41);
+        let nodes = three_of_six_gone_silent(&sim, true).await;
+        let degraded = tokio::time::Instant::now();
+        let mut events = nodes[0].subscribe();
+        let scenario = async {
+            let action_id = nodes[0].submit_action(1, b"held").await.unwrap();
+            let validators = next_event(&mut events, |event| match event {
+                NodeEvent::GraceEpochEntered { validators, .. } => Some(validators),
+                _ => None,
+            })
+            .await;
+            for node in &nodes[..3] {
+                committed(node, 2).await;
+            }
+            (action_id, validators)
+        };
+        let (action_id, validators) = tokio::select! {
+            biased;
+            _ = async {
+                tokio::join!(
+                    approve_blocks_as_they_come(&nodes[0]),
+                    approve_blocks_as_they_come(&nodes[1]),
+                    approve_blocks_as_they_come(&nodes[2]),
+                )
+            } => unreachable!("the voters never stop"),
+            found = scenario => found,
+        };
+
+        // Not before the grace period was up, and the epoch is in the log
+        // ahead of the action held meanwhile
+        assert!(degraded.elapsed() >= Duration::from_secs(10));
+        let mut live = Vec::new();
+        for node in &nodes[..3] {
+            live.push(node.player_id().await);
+        }
+        live.sort();
+        assert_eq!(validators, live);
+        for node in &nodes[..3] {
+            let log = committed(node, 2).await;
+            let consensus = node.consensus.lock().await;
+            let grace = consensus
+                .pending()
+                .iter()
+                .find(|action| action.id() == log.entries()[0])
+                .unwrap();
+            assert_eq!(grace.action_type, GRACE_EPOCH_ACTION);
+            assert_eq!(log.entries()[1], action_id);
+            assert_eq!(
+                consensus.validators().players(),
+                live.iter().copied().collect()
+            );
+            assert_eq!(consensus.required_weight(), 3);
+        }
+        assert!(!nodes[0].consensus_info().await.degraded);
+    }
+
+    /// Three validators of "ordered" in id order, the first two connected
+    /// and the last on its own with a commit at 1 forged by the first, the
+    /// sequencer, where the others committed an action of their own; the
+    /// forged action is returned with the one committed in its place
+    async fn forked_validators(
+        sim: &network::SimNetwork,
+    ) -> (Vec<SwarmhostNode>, SignedAction, ActionId) {
+        let mut keypairs: Vec<KeyPair> = (0..3).map(|_| KeyPair::generate()).collect();
+        keypairs.sort_by_key(KeyPair::public_key);
+        let ids: Vec<PlayerId> = keypairs.iter().map(KeyPair::public_key).collect();
+        let mut nodes = Vec::new();
+        for keypair in &keypairs {
+            let mut config = loopback_config(TransportKind::Memory);
+            config.keypair = Some(keypair.clone());
+            let transport = sim.transport(&config.network);
+            let node = SwarmhostNode::new(config)
+                .unwrap()
+                .with_transport(transport);
+            node.start().await.unwrap();
+            node.join_game("ordered").await.unwrap();
+            node.set_validators(ids.clone()).await;
+            nodes.push(node);
+        }
+        nodes[0]
+            .connect(nodes[1].local_addr().await[0])
+            .await
+            .unwrap();
+        wait_for_peers(&nodes[0], 1).await;
+
+        // Two of three make a quorum without the last
+        nodes[0].submit_action(1, b"agreed").await.unwrap();
+        let agreed = last_submitted(&nodes[0]).await;
+        approve_everywhere(&nodes[..2], agreed).await;
+        committed(&nodes[0], 1).await;
+        committed(&nodes[1], 1).await;
+
+        let forged = SignedAction::new(&keypairs[2], "ordered", 0, 1, b"forged".to_vec());
+        let commit = Commit::new(&keypairs[0], 1, forged.clone());
+        sequence::receive(commit, &nodes[2].peer_context())
+            .await
+            .unwrap();
+        assert_eq!(committed(&nodes[2], 1).await.entries(), &[forged.id()]);
+        (nodes, forged, agreed)
+    }
+
+    /// Connect the last of [`forked_validators`] to the others
+    async fn heal(nodes: &[SwarmhostNode]) {
+        for node in &nodes[..2] {
+            nodes[2].connect(node.local_addr().await[0]).await.unwrap();
+        }
+        wait_for_peers(&nodes[2], 2).await;
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_forged_commit_detected_and_replaced_by_the_certified_one() {
+        let sim = network::SimNetwork::new(29);
+        let (nodes, forged, agreed) = forked_validators(&sim).await;
+        let mut events: Vec<_> = nodes.iter().map(SwarmhostNode::subscribe).collect();
+        heal(&nodes).await;
+
+        // Heartbeats carry each side's commit at 1 to the other
+        let detected = next_event(&mut events[2], |event| match event {
+            NodeEvent::ForkDetected {
+                sequence,
+                ours,
+                theirs,
+                ..
+            } => Some((sequence, ours, theirs)),
+            _ => None,
+        })
+        .await;
+        assert_eq!(detected, (1, forged.id(), agreed));
+        let rolled_back_to = next_event(&mut events[2], |event| match event {
+            NodeEvent::StateRolledBack { from, to, .. } => Some((from, to)),
+            _ => None,
+        })
+        .await;
+        assert_eq!(rolled_back_to, (1, 0));
+        let rolled_back = next_event(&mut events[2], |event| match event {
+            NodeEvent::ForkResolved { rolled_back, .. } => Some(rolled_back),
+            _ => None,
+        })
+        .await;
+        assert_eq!(rolled_back, vec![forged.id()]);
+        let recommitted = next_event(&mut events[2], |event| match event {
+            NodeEvent::ActionCommitted {
+                sequence,
+                action_id,
+                ..
+            } => Some((sequence, action_id)),
+            _ => None,
+        })
+        .await;
+        assert_eq!(recommitted, (1, agreed));
+        assert_eq!(
+            nodes[2].action_log("ordered").await,
+            nodes[0].action_log("ordered").await
+        );
+
+        // The sequencer keeps its branch, and commits go on everywhere
+        let kept = next_event(&mut events[0], |event| match event {
+            NodeEvent::ForkResolved { rolled_back, .. } => Some(rolled_back),
+            _ => None,
+        })
+        .await;
+        assert!(kept.is_empty());
+        nodes[1].submit_action(1, b"after").await.unwrap();
+        let after = last_submitted(&nodes[1]).await;
+        approve_everywhere(&nodes, after).await;
+        for node in &nodes {
+            assert_eq!(committed(node, 2).await.entries(), &[agreed, after]);
+            assert!(!node.consensus_info().await.halted);
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_fork_with_both_branches_certified_halts_the_session() {
+        let sim = network::SimNetwork::new(30);
+        let (nodes, forged, agreed) = forked_validators(&sim).await;
+        // The first two approving the forged action too makes a second
+        // quorum, sharing the two with the first
+        for node in &nodes[..2] {
+            let keypair = node.config.keypair.as_ref().unwrap();
+            let vote = Vote::new(keypair, forged.id(), 0, Decision::Approve);
+            nodes[2].consensus.lock().await.receive_vote(vote).unwrap();
+        }
+        let mut events: Vec<_> = nodes.iter().map(SwarmhostNode::subscribe).collect();
+        heal(&nodes).await;
+
+        for (node, events) in nodes.iter().zip(&mut events) {
+            let halted = next_event(events, |event| match event {
+                NodeEvent::SessionHalted { sequence, .. } => Some(sequence),
+                NodeEvent::ForkResolved { .. } => panic!("a certified branch was given up"),
+                _ => None,
+            })
+            .await;
+            assert_eq!(halted, 1);
+            assert!(node.consensus_info().await.halted);
+        }
+        assert_eq!(committed(&nodes[0], 1).await.entries(), &[agreed]);
+        assert_eq!(committed(&nodes[2], 1).await.entries(), &[forged.id()]);
+
+        // No more commits until the game is left
+        nodes[0].submit_action(1, b"after").await.unwrap();
+        let after = last_submitted(&nodes[0]).await;
+        approve_everywhere(&nodes, after).await;
+        tokio::time::sleep(Duration::from_secs(5)).await;
+        for node in &nodes {
+            assert_eq!(node.action_log("ordered").await.unwrap().entries().len(), 1);
+        }
+        nodes[0].leave_game().await.unwrap();
+        assert!(!nodes[0].consensus_info().await.halted);
+    }
+
+    #[tokio::test]
+    async fn test_second_connection_for_a_proven_id_refused() {
+        let keypair = KeyPair::generate();
+        let original = SwarmhostNode::new(NodeConfig {
+            keypair: Some(keypair.clone()),
+            ..loopback_config(TransportKind::Memory)
+        })
+        .unwrap();
+        let twin = SwarmhostNode::new(NodeConfig {
+            keypair: Some(keypair.clone()),
+            ..loopback_config(TransportKind::Memory)
+        })
+        .unwrap();
+        let host = SwarmhostNode::new(loopback_config(TransportKind::Memory)).unwrap();
+        for node in [&original, &twin, &host] {
+            node.start().await.unwrap();
+        }
+        let addr = host.local_addr().await[0];
+
+        original.connect(addr).await.unwrap();
+        wait_for_peers(&host, 1).await;
+        let peers = host.peers().await;
+        assert_eq!(peers[0].player_id, keypair.public_key());
+        assert_eq!(peers[0].player_id, original.player_id().await);
+
+        match twin.connect(addr).await {
+            Err(SwarmhostError::Handshake { code, .. }) => {
+                assert_eq!(code, CloseCode::AlreadyConnected)
+            }
+            other => panic!("expected a duplicate refusal, got {:?}", other),
+        }
+        assert_eq!(host.peer_count().await, 1);
+        assert_eq!(original.peer_count().await, 1);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_dht_finds_game_across_the_id_space() {
+        use network::dht::{distance, game_key, node_key};
+
+        let sim = network::SimNetwork::new(7);
+        let mut config = loopback_config(TransportKind::Memory);
+        config.network.enable_dht = true;
+        // Small buckets, so no routing table can hold all 20 nodes
+        config.network.dht.bucket_size = 3;
+        let mut nodes = Vec::new();
+        for _ in 0..20 {
+            let mut config = config.clone();
+            config.keypair = Some(KeyPair::generate());
+            let transport = sim.transport(&config.network);
+            let node = SwarmhostNode::new(config)
+                .unwrap()
+                .with_transport(transport);
+            node.start().await.unwrap();
+            nodes.push(node);
+        }
+        let searcher = nodes.remove(0);
+        let home = node_key(&searcher.player_id().await);
+        let mut others = Vec::new();
+        for node in nodes {
+            let key = node_key(&node.player_id().await);
+            others.push((distance(&key, &home), node));
+        }
+        others.sort_by_key(|(distance, _)| *distance);
+
+        // The other 19 only know their neighbours in a chain
+        for pair in others.windows(2) {
+            let addr = pair[0].1.local_addr().await[0];
+            pair[1].1.connect(addr).await.unwrap();
+        }
+        tokio::time::sleep(Duration::from_secs(5)).await;
+
+        // A game in the half of the id space away from the searcher, played
+        // by the node farthest from it
+        let game = (0..)
+            .map(|n| format!("game-{}", n))
+            .find(|game| (game_key(game)[0] ^ home[0]) & 0x80 != 0)
+            .unwrap();
+        let (_, announcer) = others.last().unwrap();
+        let announcer_id = announcer.player_id().await;
+        assert_ne!(node_key(&announcer_id)[0] & 0x80, home[0] & 0x80);
+        announcer.join_game(&game).await.unwrap();
+
+        // The searcher knows only its nearest neighbour
+        let (_, nearest) = &others[0];
+        searcher
+            .connect(nearest.local_addr().await[0])
+            .await
+            .unwrap();
+        // Let the neighbour's hello land, making it our first DHT contact
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        let providers = searcher.find_providers(&game).await.unwrap();
+        assert_eq!(
+            providers.iter().map(|p| p.player_id).collect::<Vec<_>>(),
+            vec![announcer_id]
+        );
+
+        searcher.join_game(&game).await.unwrap();
+        assert!(
+            searcher
+                .peers()
+                .await
+                .iter()
+                .any(|peer| peer.player_id == announcer_id)
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_pex_densifies_a_line_of_nodes() {
+        let sim = network::SimNetwork::new(11);
+        let mut config = loopback_config(TransportKind::Memory);
+        config.network.enable_pex = true;
+        config.network.pex.interval = Duration::from_secs(1);
+        let mut nodes = Vec::new();
+        for _ in 0..8 {
+            let mut config = config.clone();
+            config.keypair = Some(KeyPair::generate());
+            let transport = sim.transport(&config.network);
+            let node = SwarmhostNode::new(config)
+                .unwrap()
+                .with_transport(transport);
+            node.start().await.unwrap();
+            nodes.push(node);
+        }
+        for pair in nodes.windows(2) {
+            pair[1]
+                .connect(pair[0].local_addr().await[0])
+                .await
+                .unwrap();
+        }
+
+        tokio::time::sleep(Duration::from_secs(5)).await;
+        for node in &nodes {
+            let own_id = node.player_id().await;
+            let known = node.known_peers().await;
+            assert!(known.iter().all(|peer| peer.player_id != own_id));
+            assert!(known.len() >= 5, "knows only {} peers", known.len());
+            assert!(node.peer_count().await > 2);
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_action_expiring_in_a_partition_is_never_committed() {
+        let sim = network::SimNetwork::new(31);
+        let config = loopback_config(TransportKind::Memory);
+        let nodes = validator_mesh(&sim, vec![config.clone(); 4]).await;
+        let proposer = nodes[0].consensus.lock().await.proposer().unwrap();
+        let mut submitter = 0;
+        while nodes[submitter].player_id().await == proposer {
+            submitter += 1;
+        }
+        let node = &nodes[submitter];
+        let addr = node.local_addr().await[0];
+        let mut others = Vec::new();
+        for other in &nodes {
+            let other_addr = other.local_addr().await[0];
+            if other_addr != addr {
+                others.push(other_addr);
+            }
+        }
+
+        // The other three still make a quorum, but never hear of the dodge
+        let cut = network::NetworkConditions::perfect().with_loss(1.0);
+        for &other in &others {
+            sim.set_conditions(addr, other, cut.clone());
+        }
+        let partitioned = tokio::time::Instant::now();
+        let dodge = node
+            .submit_action_expiring(1, b"dodge", Duration::from_millis(100))
+            .await
+            .unwrap();
+        let outcome = node
+            .wait_for_commit(dodge, Duration::from_secs(5))
+            .await
+            .unwrap();
+        assert_eq!(outcome, CommitOutcome::Expired);
+        let elapsed = partitioned.elapsed();
+        let expiry = Duration::from_millis(100) + config.consensus.expiry_clock_skew * 2;
+        assert!(
+            elapsed >= expiry && elapsed < expiry + Duration::from_millis(10),
+            "expired after {:?}",
+            elapsed
+        );
+        assert!(node.consensus.lock().await.pending().is_empty());
+
+        // Once healed, a forward of it still on the way is left out of
+        // every block, and the log goes on without it
+        tokio::time::sleep_until(partitioned + Duration::from_millis(500)).await;
+        for &other in &others {
+            sim.set_conditions(addr, other, network::NetworkConditions::perfect());
+        }
+        let step = node.submit_action(1, b"step").await.unwrap();
+        approve_everywhere(&nodes, step).await;
+        assert_eq!(
+            node.wait_for_commit(step, Duration::from_secs(5))
+                .await
+                .unwrap(),
+            CommitOutcome::Committed { sequence: 1 }
+        );
+        tokio::time::sleep(Duration::from_secs(1)).await;
+        for node in &nodes {
+            assert_eq!(committed(node, 1).await.entries(), &[step]);
+        }
+    }
+
+    /// Chat needs no place in the log, and a reply waits for what it
+    /// answers
+    fn chat_classes(action_type: u32) -> OrderingClass {
+        match action_type {
+            9 => OrderingClass::Unordered,
+            10 => OrderingClass::Causal,
+            _ => OrderingClass::Strict,
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_chat_flood_does_not_slow_strict_commits() {
+        let sim = network::SimNetwork::new(32);
+        let configs = vec![loopback_config(TransportKind::Memory); 3];
+        let nodes =
+            validator_mesh_with(&sim, configs, |node| node.with_classifier(chat_classes)).await;
+        let mut events = nodes[0].subscribe();
+
+        // Each node approves the move as soon as it arrives, so the
+        // latency is the network's rather than the voters'
+        async fn approve(node: &SwarmhostNode, action_id: ActionId) {
+            while !node
+                .consensus
+                .lock()
+                .await
+                .pending()
+                .iter()
+                .any(|action| action.id() == action_id)
+            {
+                tokio::time::sleep(Duration::from_millis(1)).await;
+            }
+            node.vote(action_id, Decision::Approve).await.unwrap();
+        }
+        async fn commit_moves(nodes: &[SwarmhostNode], tags: std::ops::Range<u8>) -> Duration {
+            let started = Instant::now();
+            for tag in tags {
+                let action_id = nodes[0].submit_action(1, &[tag]).await.unwrap();
+                tokio::join!(
+                    approve(&nodes[0], action_id),
+                    approve(&nodes[1], action_id),
+                    approve(&nodes[2], action_id)
+                );
+                let outcome = nodes[0]
+                    .wait_for_commit(action_id, Duration::from_secs(5))
+                    .await
+                    .unwrap();
+                assert!(matches!(outcome, CommitOutcome::Committed { .. }));
+            }
+            started.elapsed()
+        }
+        let quiet = commit_moves(&nodes, 0..10).await;
+
+        // The other two chat away, 500 lines a second between them, while
+        // the next moves are voted on
+        let flood = async {
+            let mut lines = HashSet::new();
+            for i in 0..60u8 {
+                for node in &nodes[1..] {
+                    lines.insert(node.submit_action(9, &[i]).await.unwrap());
+                }
+                tokio::time::sleep(Duration::from_millis(4)).await;
+            }
+            lines
+        };
+        let (busy, mut lines) = tokio::join!(commit_moves(&nodes, 10..20), flood);
+        // Only a line already on the wire goes out ahead of a move
+        assert!(
+            busy <= quiet + quiet / 5,
+            "{:?} to commit in the flood, {:?} without",
+            busy,
+            quiet
+        );
+
+        // Every line got through without a sequence
+        while !lines.is_empty() {
+            let delivered = next_event(&mut events, |event| match event {
+                NodeEvent::ActionDelivered { action_id, .. } => Some(action_id),
+                _ => None,
+            })
+            .await;
+            lines.remove(&delivered);
+        }
+        for node in &nodes {
+            assert_eq!(committed(node, 20).await.entries().len(), 20);
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_causal_chat_is_delivered_after_what_it_answers() {
+        let sim = network::SimNetwork::new(33);
+        let configs = vec![loopback_config(TransportKind::Memory); 3];
+        let nodes =
+            validator_mesh_with(&sim, configs, |node| node.with_classifier(chat_classes)).await;
+        let mut watchers: Vec<_> = nodes.iter().map(SwarmhostNode::subscribe).collect();
+
+        // A move still being voted on, and a question not yet asked, that
+        // the answer to both goes out ahead of
+        let strike = nodes[0].submit_action(1, b"strike").await.unwrap();
+        let keypair = &nodes[1].keypair;
+        let question = SignedAction::new(keypair, "ordered", 1000, 9, b"who?".to_vec());
+        let mut answer = SignedAction::new(keypair, "ordered", 1001, 10, b"me".to_vec());
+        answer.depends_on = vec![strike, question.id()];
+        answer.sign(keypair);
+        let ctx = nodes[1].peer_context();
+        ordering::submit(answer.clone(), OrderingClass::Causal, &ctx)
+            .await
+            .unwrap();
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        ordering::submit(question.clone(), OrderingClass::Unordered, &ctx)
+            .await
+            .unwrap();
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        approve_everywhere(&nodes, strike).await;
+
+        // Each node sees the answer last, whatever order the others came in
+        for events in &mut watchers {
+            let mut seen = Vec::new();
+            while seen.last() != Some(&answer.id()) {
+                let step = next_event(events, |event| match event {
+                    NodeEvent::ActionDelivered { action_id, .. }
+                    | NodeEvent::ActionCommitted { action_id, .. } => Some(action_id),
+                    _ => None,
+                })
+                .await;
+                seen.push(step);
+            }
+            assert_eq!(seen.len(), 3, "{:?}", seen);
+            assert!(seen.contains(&strike) && seen.contains(&question.id()));
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_every_node_draws_the_same_round_randomness() {
+        let sim = network::SimNetwork::new(34);
+        let nodes = validator_mesh(&sim, vec![loopback_config(TransportKind::Memory); 3]).await;
+        let mut watchers: Vec<_> = nodes.iter().map(SwarmhostNode::subscribe).collect();
+
+        let mut beacons = Vec::new();
+        for tag in 0..2u8 {
+            let action_id = nodes[0].submit_action(1, &[tag]).await.unwrap();
+            approve_everywhere(&nodes, action_id).await;
+            let mut drawn = Vec::new();
+            for events in &mut watchers {
+                drawn.push(
+                    next_event(events, |event| match event {
+                        NodeEvent::ActionCommitted {
+                            round_randomness, ..
+                        } => Some(round_randomness),
+                        _ => None,
+                    })
+                    .await
+                    .unwrap(),
+                );
+            }
+            assert!(drawn.iter().all(|beacon| *beacon == drawn[0]));
+            for node in &nodes {
+                let sequence = u64::from(tag) + 1;
+                assert_eq!(
+                    node.round_randomness("ordered", sequence).await,
+                    Some(drawn[0])
+                );
+            }
+            beacons.push(drawn[0]);
+        }
+        assert_ne!(beacons[0], beacons[1]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_restarted_validator_rejoins_without_equivocating() {
+        let sim = network::SimNetwork::new(35);
+        let dirs: Vec<_> = (0..3).map(|_| tempfile::tempdir().unwrap()).collect();
+        let mut config = loopback_config(TransportKind::Memory);
+        config.state.snapshot_interval = 4;
+        let configs = dirs
+            .iter()
+            .map(|dir| config.clone().with_data_dir(dir.path()))
+            .collect();
+        let mut nodes = validator_mesh(&sim, configs).await;
+        let mut ids = Vec::new();
+        for node in &nodes {
+            ids.push(node.player_id().await);
+        }
+        for tag in 0..10u8 {
+            let action_id = nodes[0].submit_action(1, &[tag]).await.unwrap();
+            approve_everywhere(&nodes, action_id).await;
+        }
+        for node in &nodes {
+            committed(node, 10).await;
+        }
+
+        // A validator other than the sequencer approves an action, and
+        // crashes before anyone else votes on it
+        let sequencer = nodes[0].consensus.lock().await.sequencer().unwrap();
+        let victim = ids.iter().rposition(|id| *id != sequencer).unwrap();
+        let victim = nodes.remove(victim);
+        let in_flight = nodes[0].submit_action(1, b"in flight").await.unwrap();
+        approve_everywhere(std::slice::from_ref(&victim), in_flight).await;
+        let voted_in = victim.consensus.lock().await.votes(&in_flight)[0].round;
+        let before = victim.action_log("ordered").await.unwrap();
+        let config = victim.config.clone();
+        victim.stop().await.unwrap();
+        drop(victim);
+        let mut watchers: Vec<_> = nodes.iter().map(SwarmhostNode::subscribe).collect();
+
+        // Restarted from the same directory, it is back in the round it
+        // voted in, with its last snapshot and the commits stored after it,
+        // and does not vote until it has caught up
+        let restarted = SwarmhostNode::new(config.clone())
+            .unwrap()
+            .with_transport(sim.transport(&config.network));
+        restarted.start().await.unwrap();
+        assert!(restarted.consensus.lock().await.round() >= voted_in);
+        restarted.set_validators(ids.clone()).await;
+        restarted.join_game("ordered").await.unwrap();
+        let restored = restarted.action_log("ordered").await.unwrap();
+        assert_eq!(restored.entries(), before.entries());
+        assert!(matches!(
+            restarted.vote(in_flight, Decision::Approve).await,
+            Err(SwarmhostError::CatchingUp)
+        ));
+
+        let mut ready = restarted.subscribe();
+        let mut addrs = vec![restarted.local_addr().await[0]];
+        for node in &nodes {
+            addrs.push(node.local_addr().await[0]);
+        }
+        sim.set_all_conditions(&addrs, network::LinkPreset::Wifi);
+        for addr in &addrs[1..] {
+            restarted.connect(*addr).await.unwrap();
+        }
+        next_event(&mut ready, |event| match event {
+            NodeEvent::SessionReady { sequence, .. } => Some(sequence),
+            _ => None,
+        })
+        .await;
+        assert_eq!(
+            restarted.action_log("ordered").await.unwrap().entries(),
+            before.entries()
+        );
+
+        // Voting otherwise on what it approved before the crash is refused,
+        // and the approval it sent then still counts towards the quorum
+        assert!(matches!(
+            restarted.vote(in_flight, Decision::Reject).await,
+            Err(SwarmhostError::InvalidState(_))
+        ));
+        approve_everywhere(&nodes[..1], in_flight).await;
+        nodes.push(restarted);
+        for node in &nodes {
+            assert_eq!(committed(node, 11).await.entries()[10], in_flight);
+        }
+
+        // It votes like the rest from then on
+        let after = nodes[0].submit_action(1, b"after").await.unwrap();
+        approve_everywhere(&nodes, after).await;
+        let expected = committed(&nodes[0], 12).await.hash();
+        for node in &nodes {
+            assert_eq!(committed(node, 12).await.hash(), expected);
+        }
+        for (node, events) in nodes.iter().zip(&mut watchers) {
+            while let Ok(event) = events.try_recv() {
+                assert!(!matches!(event, NodeEvent::Misbehavior { .. }));
+            }
+            let validators = node.consensus.lock().await.validators().clone();
+            assert!(ids.iter().all(|id| validators.contains(id)));
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_votes_lost_on_a_lossy_link_are_asked_for_before_the_round_fails() {
+        let sim = network::SimNetwork::new(36);
+        let nodes = validator_mesh(&sim, vec![loopback_config(TransportKind::Memory); 3]).await;
+        let mut addrs = Vec::new();
+        for node in &nodes {
+            addrs.push(node.local_addr().await[0]);
+        }
+
+        // One validator other than the sequencer is on lossy links, and
+        // with another abstaining its vote is needed for every quorum
+        let sequencer = nodes[0].consensus.lock().await.sequencer().unwrap();
+        let mut ids = Vec::new();
+        for node in &nodes {
+            ids.push(node.player_id().await);
+        }
+        let sequencer = ids.iter().position(|id| *id == sequencer).unwrap();
+        let others: Vec<usize> = (0..nodes.len()).filter(|i| *i != sequencer).collect();
+        let (voter, lossy) = (&nodes[others[0]], &nodes[others[1]]);
+        let lossy_addr = addrs[others[1]];
+        for addr in &addrs {
+            if *addr != lossy_addr {
+                sim.set_conditions(
+                    lossy_addr,
+                    *addr,
+                    network::NetworkConditions::from(network::LinkPreset::Wifi).with_loss(0.4),
+                );
+            }
+        }
+
+        // Its approvals are recorded but go missing with the frames it
+        // loses, so they are only ever sent when asked for
+        let vote_unheard = async {
+            let mut events = lossy.subscribe();
+            loop {
+                let block = next_event(&mut events, |event| match event {
+                    NodeEvent::BlockProposed { block, .. } => Some(block),
+                    _ => None,
+                })
+                .await;
+                let keypair = lossy.config.keypair.as_ref().unwrap();
+                let mut consensus = lossy.consensus.lock().await;
+                let round = consensus.round();
+                consensus
+                    .cast_vote(Vote::new(keypair, block, round, Decision::Approve))
+                    .unwrap();
+                recovery::write_ahead(&consensus, &lossy.state_manager)
+                    .await
+                    .unwrap();
+            }
+        };
+        let scenario = async {
+            for tag in 0..20u8 {
+                nodes[sequencer].submit_action(1, &[tag]).await.unwrap();
+                tokio::time::sleep(Duration::from_millis(100)).await;
+            }
+            let expected = committed(&nodes[sequencer], 20).await.hash();
+            for node in &nodes {
+                assert_eq!(committed(node, 20).await.hash(), expected);
+            }
+        };
+        tokio::select! {
+            biased;
+            _ = async {
+                tokio::join!(approve_blocks_as_they_come(voter), vote_unheard)
+            } => unreachable!("the voters never stop"),
+            () = scenario => {}
+        }
+
+        assert!(nodes[sequencer].metrics().vote_requests.get() > 0);
+        assert!(nodes[sequencer].metrics().votes_recovered.get() > 0);
+        for node in &nodes {
+            let metrics = node.metrics();
+            assert_eq!(metrics.rounds_failed_timeout.get(), 0);
+            assert_eq!(metrics.rounds_failed_view_change.get(), 0);
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_observer_disagreeing_with_the_quorum_is_reported_without_changing_the_commit() {
+        let sim = network::SimNetwork::new(37);
+        let nodes = validator_mesh(&sim, vec![loopback_config(TransportKind::Memory); 3]).await;
+        let mut ids = Vec::new();
+        for node in &nodes {
+            ids.push(node.player_id().await);
+        }
+
+        // A spectator follows the game without a say in it
+        let mut config = loopback_config(TransportKind::Memory);
+        config.keypair = Some(KeyPair::generate());
+        let spectator = SwarmhostNode::new(config.clone())
+            .unwrap()
+            .with_transport(sim.transport(&config.network));
+        spectator.start().await.unwrap();
+        spectator.join_game("ordered").await.unwrap();
+        for node in &nodes {
+            spectator.connect(node.local_addr().await[0]).await.unwrap();
+        }
+        wait_for_peers(&spectator, nodes.len()).await;
+        spectator.set_validators(ids).await;
+        let observer = spectator.player_id().await;
+        let mut watchers: Vec<_> = nodes.iter().map(SwarmhostNode::subscribe).collect();
+
+        // It rejects what every validator approves
+        let action_id = nodes[0].submit_action(1, b"contested").await.unwrap();
+        loop {
+            let arrived = spectator
+                .consensus
+                .lock()
+                .await
+                .pending()
+                .iter()
+                .any(|action| action.id() == action_id);
+            if arrived {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        spectator
+            .observe(action_id, Decision::Reject)
+            .await
+            .unwrap();
+        assert!(matches!(
+            nodes[0].observe(action_id, Decision::Approve).await,
+            Err(SwarmhostError::InvalidState(_))
+        ));
+        approve_everywhere(&nodes, action_id).await;
+
+        // The action is committed all the same, and every validator reports
+        // the dissent apart from its tally
+        let dissent = ObserverTally {
+            approvals: 0,
+            rejections: 1,
+        };
+        for (node, events) in nodes.iter().zip(&mut watchers) {
+            assert_eq!(committed(node, 1).await.entries(), &[action_id]);
+            let tally = next_event(events, |event| match event {
+                NodeEvent::ObserverVoted {
+                    action_id: voted,
+                    observer: voter,
+                    decision: Decision::Reject,
+                    tally,
+                } if voted == action_id && voter == observer => Some(tally),
+                _ => None,
+            })
+            .await;
+            assert_eq!(tally, dissent);
+            let info = node.consensus_info().await;
+            assert_eq!(info.observer_approval[&action_id], dissent);
+        }
+        assert_eq!(committed(&spectator, 1).await.entries(), &[action_id]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_ticked_rounds_commit_the_same_actions_in_the_same_ticks_everywhere() {
+        let sim = network::SimNetwork::new(38);
+        let mut config = loopback_config(TransportKind::Memory);
+        config.consensus.round_mode = RoundMode::Ticked {
+            tick_interval: Duration::from_millis(50),
+        };
+        // Round trips measured early on, for the tick timers to go by
+        config.network.heartbeat_interval = Duration::from_millis(500);
+        let nodes = validator_mesh(&sim, vec![config; 4]).await;
+        let mut watchers: Vec<_> = nodes.iter().map(SwarmhostNode::subscribe).collect();
+        let mut addrs = Vec::new();
+        for node in &nodes {
+            addrs.push(node.local_addr().await[0]);
+        }
+        // Some links longer than others, for the tick timers to make up for
+        for (i, a) in addrs.iter().enumerate() {
+            for (j, b) in addrs.iter().enumerate().skip(i + 1) {
+                let one_way = Duration::from_millis(4 + 2 * (i + j) as u64);
+                let conditions = network::NetworkConditions::perfect()
+                    .with_latency(one_way, network::Jitter::None);
+                sim.set_conditions(*a, *b, conditions);
+            }
+        }
+
+        // Every node submits in turn, out of step with the ticks
+        let submitted = async {
+            let mut submitted = Vec::new();
+            for i in 0..40 {
+                tokio::time::sleep(Duration::from_millis(70)).await;
+                let node = &nodes[i % nodes.len()];
+                submitted.push(node.submit_action(1, &[i as u8]).await.unwrap());
+            }
+            submitted
+        };
+        let ticks = async {
+            let mut seen = Vec::new();
+            for events in &mut watchers {
+                let mut ticks = Vec::new();
+                while ticks.len() < 100 {
+                    let tick = next_event(events, |event| match event {
+                        NodeEvent::TickCommitted { tick, actions } => Some((tick, actions)),
+                        _ => None,
+                    })
+                    .await;
+                    ticks.push(tick);
+                }
+                seen.push(ticks);
+            }
+            seen
+        };
+        let started = tokio::time::Instant::now();
+        let (submitted, seen) = tokio::select! {
+            biased;
+            _ = async {
+                tokio::join!(
+                    approve_blocks_as_they_come(&nodes[0]),
+                    approve_blocks_as_they_come(&nodes[1]),
+                    approve_blocks_as_they_come(&nodes[2]),
+                    approve_blocks_as_they_come(&nodes[3]),
+                )
+            } => unreachable!("the voters never stop"),
+            done = async { tokio::join!(submitted, ticks) } => done,
+        };
+
+        // One tick after another, the same everywhere, each action in one
+        assert!(seen.iter().all(|ticks| *ticks == seen[0]));
+        let first = seen[0][0].0;
+        for (at, (tick, _)) in seen[0].iter().enumerate() {
+            assert_eq!(*tick, first + at as u64);
+        }
+        let mut committed: Vec<ActionId> = seen[0]
+            .iter()
+            .flat_map(|(_, actions)| actions.iter().copied())
+            .collect();
+        assert!(seen[0].iter().any(|(_, actions)| actions.is_empty()));
+        committed.sort();
+        let mut expected = submitted;
+        expected.sort();
+        assert_eq!(committed, expected);
+
+        // On the beat, with no proposer given up on
+        let elapsed = started.elapsed();
+        assert!(elapsed < Duration::from_millis(50 * 104), "{:?}", elapsed);
+        for node in &nodes {
+            assert_eq!(node.metrics().rounds_failed_timeout.get(), 0);
+        }
+    }
 }
@@ -0,0 +1,37 @@
+// node/observer.rs - Taking in the verdicts of nodes that validate without
+// a say in consensus
+
+use super::peers::{self, PeerContext};
+use crate::consensus::ObserverVote;
+use crate::crypto::short_id;
+use crate::error::{Result, SwarmhostError};
+use crate::network::Offense;
+
+/// Count an observer's vote apart from the quorum
+///
+/// An observer that votes both ways is not ejected from anything, having
+/// no say, but we penalize it as a peer if it is connected to us; whoever
+/// only relayed its votes is not to blame.
+pub(super) async fn receive(vote: ObserverVote, ctx: &PeerContext) -> Result<()> {
+    let observer = vote.observer;
+    {
+        let mut consensus = ctx.consensus.lock().await;
+        let contradicts = consensus
+            .observer_vote(&vote.action_id, &observer)
+            .is_some_and(|first| first.decision != vote.decision);
+        if !contradicts {
+            return consensus.receive_observer_vote(vote);
+        }
+        vote.verify()?;
+    }
+    tracing::debug!(
+        "Observer {} voted both ways on {}",
+        short_id(&observer),
+        short_id(&vote.action_id)
+    );
+    peers::penalize(observer, Offense::ProtocolViolation, ctx).await;
+    Err(SwarmhostError::validation(format!(
+        "Observer {} voted both ways",
+        short_id(&observer)
+    )))
+}
@@ -0,0 +1,99 @@
+// node/ordering.rs - Delivering actions that need no place in the log
+// without a round of consensus
+
+use super::peers::{self, PeerContext};
+use super::{NodeEvent, RejectionReason};
+use crate::consensus::{
+    ActionClassifier, GRACE_EPOCH_ACTION, MEMBERSHIP_CHANGE_ACTION, OrderingClass, SignedAction,
+    ValidationResult,
+};
+use crate::crypto::short_id;
+use crate::error::{Result, SwarmhostError};
+use crate::network::GossipPayload;
+
+/// How actions of `action_type` are ordered; membership changes and grace
+/// epochs always go through consensus
+pub(super) fn class_of(action_type: u32, classifier: &dyn ActionClassifier) -> OrderingClass {
+    if action_type == MEMBERSHIP_CHANGE_ACTION || action_type == GRACE_EPOCH_ACTION {
+        return OrderingClass::Strict;
+    }
+    classifier.classify(action_type)
+}
+
+/// Deliver one of our own actions of `class` here, and gossip it
+pub(super) async fn submit(
+    action: SignedAction,
+    class: OrderingClass,
+    ctx: &PeerContext,
+) -> Result<()> {
+    receive(action.clone(), class, ctx).await?;
+    peers::publish(GossipPayload::Unsequenced(action), None, ctx).await;
+    Ok(())
+}
+
+/// Take in an action a peer gossiped as needing no consensus
+///
+/// We class it ourselves, so a peer cannot slip a move past the validators
+/// by sending it this way.
+pub(super) async fn receive_gossiped(action: SignedAction, ctx: &PeerContext) -> Result<()> {
+    match class_of(action.action_type, ctx.classifier.as_ref()) {
+        OrderingClass::Strict => Err(SwarmhostError::validation(format!(
+            "{} from {} is of a type committed by consensus",
+            short_id(&action.id()),
+            short_id(&action.actor)
+        ))),
+        class => receive(action, class, ctx).await,
+    }
+}
+
+/// Take in an action of a class delivered without a sequence
+///
+/// It goes through the checks a validator runs before voting, then is
+/// delivered, letting go any causal actions waiting on it. One failing
+/// them is refused as a validation error, and not passed on.
+pub(super) async fn receive(
+    action: SignedAction,
+    class: OrderingClass,
+    ctx: &PeerContext,
+) -> Result<()> {
+    let Some(check) = ctx.consensus.lock().await.unsequenced_context(&action)? else {
+        return Ok(());
+    };
+    let budget = ctx.consensus_config.borrow().validation_timeout;
+    if let ValidationResult::Invalid(code) = ctx.validation.run(&check, budget).await {
+        let _ = ctx.events.send(NodeEvent::ActionRejected {
+            action_id: check.action_id,
+            actor: action.actor,
+            reason: RejectionReason::Invalid { code },
+        });
+        return Err(SwarmhostError::validation(format!(
+            "{} from {} failed our checks: {}",
+            short_id(&check.action_id),
+            short_id(&action.actor),
+            code
+        )));
+    }
+    let delivered = ctx
+        .consensus
+        .lock()
+        .await
+        .deliver_unsequenced(action, class);
+    announce(delivered, ctx);
+    Ok(())
+}
+
+/// Deliver the causal actions that were waiting on commits applied since
+pub(super) async fn release(ctx: &PeerContext) {
+    let released = ctx.consensus.lock().await.release_unsequenced();
+    announce(released, ctx);
+}
+
+fn announce(delivered: Vec<SignedAction>, ctx: &PeerContext) {
+    for action in delivered {
+        let _ = ctx.events.send(NodeEvent::ActionDelivered {
+            action_id: action.id(),
+            game_id: action.game_id,
+            actor: action.actor,
+        });
+    }
+}
@@ -0,0 +1,227 @@
+// node/partition.rs - Noticing when too few validators are reachable for a quorum
+
+use super::peers::PeerContext;
+use super::{ConsensusConfig, NodeEvent, NodeState};
+use super::{grace, rotation, sequence};
+use crate::crypto::{PlayerId, short_id};
+use crate::error::SwarmhostError;
+use std::time::Duration;
+use tokio::sync::watch;
+use tokio::time::Instant;
+
+/// A change in whether a quorum of validators is reachable
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) enum Change {
+    Lost,
+    Restored,
+}
+
+/// How long the reachable validators have been short of a quorum
+///
+/// The session is degraded once they have been short for the whole grace
+/// period, and recovers as soon as a quorum is reachable again.
+#[derive(Debug, Default)]
+pub(super) struct Partition {
+    short_since: Option<Instant>,
+    /// Since when the session has been degraded
+    degraded: Option<Instant>,
+}
+
+impl Partition {
+    /// Record that validators weighing `reachable` can be reached where
+    /// `required` makes a quorum, returning the change this makes, if any
+    pub fn observe(
+        &mut self,
+        reachable: u64,
+        required: u64,
+        grace: Duration,
+        now: Instant,
+    ) -> Option<Change> {
+        if reachable >= required {
+            self.short_since = None;
+            return self.degraded.take().map(|_| Change::Restored);
+        }
+        let since = *self.short_since.get_or_insert(now);
+        if self.degraded.is_some() || now.duration_since(since) < grace {
+            return None;
+        }
+        self.degraded = Some(now);
+        Some(Change::Lost)
+    }
+
+    pub fn is_degraded(&self) -> bool {
+        self.degraded.is_some()
+    }
+
+    /// How long the session has been degraded by `now`; none if it is not
+    pub fn degraded_for(&self, now: Instant) -> Option<Duration> {
+        self.degraded.map(|since| now.duration_since(since))
+    }
+}
+
+/// Weight of the validators of our game reachable right now, counting us,
+/// and `required`, the weight consensus needs for a quorum; both zero
+/// outside a game
+pub(super) fn reachability(state: &NodeState, required: u64) -> (u64, u64) {
+    if state.current_game.is_none() {
+        return (0, 0);
+    }
+    let reachable = state.validators.weight(&reachable_validators(state));
+    (reachable, required)
+}
+
+/// Validators of our game reachable right now, counting us, in order of id
+///
+/// A validator whose connection dropped is unreachable while it is parked.
+pub(super) fn reachable_validators(state: &NodeState) -> Vec<PlayerId> {
+    state
+        .validators
+        .ids()
+        .filter(|validator| {
+            **validator == state.player_id
+                || state
+                    .connections
+                    .get(*validator)
+                    .is_some_and(|handle| handle.parked.is_none())
+        })
+        .copied()
+        .collect()
+}
+
+/// Check reachability every heartbeat until the task is aborted
+pub(super) async fn watch(consensus: watch::Receiver<ConsensusConfig>, ctx: PeerContext) {
+    loop {
+        let interval = ctx.network.borrow().heartbeat_interval;
+        tokio::time::sleep(interval).await;
+        let config = consensus.borrow().clone();
+        check(&config, &ctx).await;
+    }
+}
+
+/// Degrade the session once a quorum has been out of reach for
+/// `quorum_loss_timeouts` consensus timeouts, and restore it, sending the
+/// held actions, once one is back; while degraded, the grace rule may let
+/// the validators left go on alone
+async fn check(config: &ConsensusConfig, ctx: &PeerContext) {
+    let grace = config.consensus_timeout * config.quorum_loss_timeouts;
+    let mut state = ctx.state.write().await;
+    let required = ctx.consensus.lock().await.required_weight();
+    let (reachable, required) = reachability(&state, required);
+    match state
+        .partition
+        .observe(reachable, required, grace, Instant::now())
+    {
+        Some(Change::Lost) => {
+            tracing::warn!(
+                "Only validators weighing {} of the {} a quorum needs are reachable",
+                reachable,
+                required
+            );
+            let _ = ctx.events.send(NodeEvent::QuorumLost {
+                reachable,
+                required,
+            });
+        }
+        Some(Change::Restored) => {
+            tracing::info!(
+                "Quorum reachable again with validators weighing {}",
+                reachable
+            );
+            drop(state);
+            let _ = ctx.events.send(NodeEvent::QuorumRestored {
+                reachable,
+                required,
+            });
+            flush(ctx).await;
+        }
+        None => {
+            let held = !state.held_actions.is_empty();
+            drop(state);
+            grace::check(config, ctx).await;
+            if held {
+                flush(ctx).await;
+            }
+        }
+    }
+}
+
+/// Send the actions held while degraded, oldest first
+///
+/// Each stays queued until it has been sent, so actions submitted meanwhile
+/// queue up behind it instead of overtaking it; with too many pending, the
+/// rest wait for a later heartbeat.
+async fn flush(ctx: &PeerContext) {
+    loop {
+        let (action, trace) = {
+            let mut state = ctx.state.write().await;
+            if state.partition.is_degraded() {
+                return;
+            }
+            let Some(action) = state.held_actions.front().cloned() else {
+                return;
+            };
+            let trace = state
+                .traces
+                .as_mut()
+                .map(|traces| traces.action(action.id()));
+            (action, trace)
+        };
+        let submitted = ctx.consensus.lock().await.submit_local(action.clone());
+        match submitted {
+            Ok(_) => {
+                sequence::speculate(&action, ctx).await;
+                rotation::submit(action, trace, ctx).await;
+            }
+            // Still held, to go once pending actions make room
+            Err(SwarmhostError::Overloaded(_)) => return,
+            Err(e) => tracing::debug!("Dropping held action {}: {}", short_id(&action.id()), e),
+        }
+        let mut state = ctx.state.write().await;
+        state.held_actions.pop_front();
+        ctx.metrics
+            .held_actions
+            .set(state.held_actions.len() as u64);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const GRACE: Duration = Duration::from_secs(15);
+
+    #[test]
+    fn test_degraded_only_after_grace() {
+        let now = Instant::now();
+        let mut partition = Partition::default();
+        assert_eq!(partition.observe(3, 3, GRACE, now), None);
+        assert_eq!(partition.observe(2, 3, GRACE, now), None);
+        assert_eq!(partition.observe(2, 3, GRACE, now + GRACE / 2), None);
+        assert!(!partition.is_degraded());
+
+        assert_eq!(
+            partition.observe(1, 3, GRACE, now + GRACE),
+            Some(Change::Lost)
+        );
+        assert_eq!(partition.observe(1, 3, GRACE, now + GRACE * 2), None);
+        assert!(partition.is_degraded());
+        assert_eq!(partition.degraded_for(now + GRACE * 2), Some(GRACE));
+
+        assert_eq!(
+            partition.observe(3, 3, GRACE, now + GRACE * 3),
+            Some(Change::Restored)
+        );
+        assert_eq!(partition.observe(3, 3, GRACE, now + GRACE * 4), None);
+        assert_eq!(partition.degraded_for(now + GRACE * 4), None);
+    }
+
+    #[test]
+    fn test_brief_shortfall_starts_over() {
+        let now = Instant::now();
+        let mut partition = Partition::default();
+        partition.observe(1, 3, GRACE, now);
+        assert_eq!(partition.observe(3, 3, GRACE, now + GRACE / 2), None);
+        assert_eq!(partition.observe(1, 3, GRACE, now + GRACE), None);
+        assert!(!partition.is_degraded());
+    }
+}
@@ -0,0 +1,1603 @@
+// node/peers.rs - Accepting, dialing and serving peer connections
+
+use super::eviction::{Crowd, EvictionPolicy};
+use super::reconnect::{self, Parked};
+use super::{
+    ConsensusConfig, Counter, NetworkConfig, NodeEvent, NodeMetrics, NodeState, SecurityMode,
+    checkpoint, dht, divergence, evidence, fork, intake, memory, observer, ordering, pex, pull,
+    relay, rotation, sequence, sync, tick, traversal, view,
+};
+use crate::consensus::{
+    ActionClassifier, ActionId, ConsensusManager, Outcome, SignedAction, ValidationPipeline,
+};
+use crate::crypto::{KeyPair, PlayerId, short_id};
+use crate::error::{Result, SwarmhostError};
+use crate::network::batch;
+use crate::network::dedup::{self, DedupCache};
+use crate::network::fragment::{Fragment, Fragmenter, Stalled};
+use crate::network::happy_eyeballs::{self, AddressFamily};
+use crate::network::heartbeat::{self, Heartbeat, Tick};
+use crate::network::inbound::{InboundLimiter, Verdict};
+use crate::network::mux::Stream;
+use crate::network::outbound::{
+    self, OutboundReceiver, OutboundSender, Priority, QueueDepths, SendError,
+};
+use crate::network::punch::PunchSignal;
+use crate::network::relay::RelayOffer;
+use crate::network::score::{Offense, PeerScore};
+use crate::network::throttle::{SharedBandwidth, Throttle};
+use crate::network::trace::{self, Step, TraceContext};
+use crate::network::{
+    CloseCode, Connection, Gossip, GossipMessage, GossipPayload, Listener, MessageCodec,
+    PeerMessage, PeerRecord, PeerStore, Role, SecureChannel, Transport,
+};
+use crate::state::StateManager;
+use std::future::Future;
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::{Mutex, RwLock, Semaphore, broadcast, watch};
+use tokio::task::JoinSet;
+use tokio::time::Instant;
+use tracing::Instrument;
+
+/// How long a connection gets to close its socket once it has said goodbye
+pub(super) const CLOSE_GRACE: Duration = Duration::from_secs(1);
+
+/// Pause after a failed accept (e.g. out of file descriptors)
+const ACCEPT_BACKOFF: Duration = Duration::from_millis(100);
+
+/// How a connection to a peer was established, in order of preference
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConnectionPath {
+    /// One side dialed the other's address
+    Direct,
+    /// Both sides punched through their NATs over UDP
+    HolePunched,
+    /// Frames are spliced through another peer
+    Relayed { via: PlayerId },
+}
+
+/// A connected peer as seen by this node
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PeerInfo {
+    pub player_id: PlayerId,
+    pub addr: SocketAddr,
+    /// The IP version of `addr`; for a peer we dialed, the one that won
+    pub family: AddressFamily,
+    /// Smoothed round-trip time, once a heartbeat has been answered
+    pub rtt: Option<Duration>,
+    /// Whether the connection was dialed, punched or relayed
+    pub path: ConnectionPath,
+    /// What the peer offers if we ask it to relay
+    pub relay: Option<RelayOffer>,
+    /// Messages waiting to be sent to the peer, by class
+    pub queues: QueueDepths,
+    /// Total time messages to the peer were held back by upload limits
+    pub throttled: Duration,
+    /// The game the peer says it is playing
+    pub game: Option<String>,
+    /// Last commit of that game the peer has shown us it holds
+    pub synced: Option<u64>,
+    /// The connection dropped and we are waiting for the peer to come back
+    pub reconnecting: bool,
+}
+
+/// The node's handle on a connection task
+pub(super) struct PeerHandle {
+    pub close: watch::Sender<Option<CloseCode>>,
+    /// Messages for the connection task to send
+    pub outbound: OutboundSender,
+    pub info: PeerInfo,
+    pub score: PeerScore,
+    /// Microseconds the connection task held messages back for upload limits
+    pub throttled: Arc<Counter>,
+    /// Where to re-dial the peer if the connection drops; only known for
+    /// peers we dialed directly
+    pub redial: Option<SocketAddr>,
+    /// Set while the connection is down and the peer keeps its place
+    pub parked: Option<Parked>,
+}
+
+/// Everything a connection task needs from the node
+#[derive(Clone)]
+pub(super) struct PeerContext {
+    pub local_id: PlayerId,
+    pub keypair: Arc<KeyPair>,
+    pub transport: Arc<dyn Transport>,
+    pub network: watch::Receiver<NetworkConfig>,
+    pub state: Arc<RwLock<NodeState>>,
+    pub events: broadcast::Sender<NodeEvent>,
+    pub metrics: Arc<NodeMetrics>,
+    /// The node-wide upload limit, if set
+    pub upload: Option<SharedBandwidth>,
+    pub eviction: Arc<dyn EvictionPolicy>,
+    /// Our part of the DHT, when `enable_dht` is set
+    pub dht: Option<Arc<Mutex<dht::Dht>>>,
+    /// Peers known through PEX, when `enable_pex` is set
+    pub pex: Option<Arc<Mutex<PeerStore>>>,
+    /// One permit per inbound handshake allowed in progress
+    pub handshakes: Arc<Semaphore>,
+    pub gossip: Arc<Mutex<Gossip>>,
+    pub dedup: Arc<Mutex<DedupCache>>,
+    /// Whether we are over `max_memory_bytes`
+    pub memory: Arc<memory::MemoryBudget>,
+    pub consensus: Arc<Mutex<ConsensusManager>>,
+    pub state_manager: Arc<Mutex<StateManager>>,
+    pub consensus_config: watch::Receiver<ConsensusConfig>,
+    /// Tells which action types skip consensus
+    pub classifier: Arc<dyn ActionClassifier>,
+    /// Checks an action passes before we approve or deliver it
+    pub validation: ValidationPipeline,
+    /// Record spans for traced messages
+    pub trace_messages: bool,
+}
+
+/// Accept inbound connections until the task is aborted
+///
+/// Connections arriving while `max_half_open` handshakes are already in
+/// progress are dropped unanswered.
+pub(super) async fn accept_loop(listener: Arc<dyn Listener>, ctx: PeerContext) {
+    loop {
+        match listener.accept().await {
+            Ok(conn) => {
+                let addr = conn.peer_addr();
+                let Ok(permit) = ctx.handshakes.clone().try_acquire_owned() else {
+                    tracing::debug!("Dropping {}: too many handshakes in progress", addr);
+                    ctx.metrics.handshakes_refused.inc();
+                    continue;
+                };
+                let ctx = ctx.clone();
+                tokio::spawn(async move {
+                    let opened = open(conn, Role::Responder, None, ConnectionPath::Direct, &ctx);
+                    let result = opened.await;
+                    drop(permit);
+                    if let Err(e) = result {
+                        tracing::debug!("Inbound connection from {} failed: {}", addr, e);
+                    }
+                });
+            }
+            Err(e) => {
+                tracing::warn!("Accept failed: {}", e);
+                tokio::time::sleep(ACCEPT_BACKOFF).await;
+            }
+        }
+    }
+}
+
+/// Dial whichever of `addrs` connects first and bring the connection up as
+/// an outbound peer
+///
+/// IPv6 gets a head start before IPv4 is tried, happy-eyeballs style, and
+/// only the winning connection is kept. With `expected` set, the connection
+/// fails unless that player answers.
+pub(super) async fn dial(
+    transport: &dyn Transport,
+    addrs: &[SocketAddr],
+    expected: Option<PlayerId>,
+    ctx: &PeerContext,
+) -> Result<PlayerId> {
+    let (addr, conn) =
+        happy_eyeballs::connect(transport, addrs, happy_eyeballs::HEAD_START).await?;
+    tracing::debug!("Connected to {} over {:?}", addr, AddressFamily::of(&addr));
+    open(conn, Role::Initiator, expected, ConnectionPath::Direct, ctx).await
+}
+
+/// What came of [`dial_all`]: the peers it connected and why each other
+/// one failed
+#[derive(Debug, Default)]
+pub(super) struct Dialed {
+    pub connected: Vec<PlayerId>,
+    pub failed: Vec<(PlayerId, SwarmhostError)>,
+}
+
+impl Dialed {
+    /// Why nothing connected, preferring a peer's own refusal over a path
+    /// that never reached it
+    pub fn into_error(self) -> Option<SwarmhostError> {
+        let refused = self
+            .failed
+            .iter()
+            .position(|(_, e)| matches!(e, SwarmhostError::Handshake { .. }));
+        let mut failed = self.failed;
+        match refused {
+            Some(i) => Some(failed.swap_remove(i).1),
+            None => failed.into_iter().next().map(|(_, e)| e),
+        }
+    }
+}
+
+/// Dial every listed peer we are not yet connected to, in parallel
+///
+/// Peers that cannot be dialed directly are tried through a punched NAT
+/// hole, then through a relay; a peer that answers and refuses us is not
+/// tried again another way.
+pub(super) async fn dial_all(
+    transport: Arc<dyn Transport>,
+    peers: Vec<PeerRecord>,
+    ctx: &PeerContext,
+) -> Dialed {
+    let timeout = ctx.network.borrow().security.handshake_timeout;
+    let connected = ctx.state.read().await.connected_peers.clone();
+
+    let mut dials = JoinSet::new();
+    for peer in peers {
+        if connected.contains(&peer.player_id) {
+            continue;
+        }
+        let transport = transport.clone();
+        let ctx = ctx.clone();
+        dials.spawn(async move {
+            match tokio::time::timeout(
+                timeout,
+                dial(transport.as_ref(), &peer.addrs, Some(peer.player_id), &ctx),
+            )
+            .await
+            {
+                Ok(Ok(_)) => return (peer.player_id, Ok(())),
+                Ok(Err(e @ SwarmhostError::Handshake { .. })) => {
+                    tracing::debug!("{} refused us: {}", short_id(&peer.player_id), e);
+                    return (peer.player_id, Err(e));
+                }
+                Ok(Err(e)) => tracing::debug!(
+                    "Dialing {} at {:?} failed: {}",
+                    short_id(&peer.player_id),
+                    peer.addrs,
+                    e
+                ),
+                Err(_) => tracing::debug!(
+                    "Dialing {} at {:?} timed out",
+                    short_id(&peer.player_id),
+                    peer.addrs
+                ),
+            }
+
+            let result = traversal::connect_indirect(peer.player_id, &ctx).await;
+            if let Err(e) = &result {
+                tracing::debug!("No path to {}: {}", short_id(&peer.player_id), e);
+            }
+            (peer.player_id, result.map(|_| ()))
+        });
+    }
+
+    let mut dialed = Dialed::default();
+    while let Some(result) = dials.join_next().await {
+        match result {
+            Ok((peer, Ok(()))) => dialed.connected.push(peer),
+            Ok((peer, Err(e))) => dialed.failed.push((peer, e)),
+            Err(e) => tracing::warn!("Dial task failed: {}", e),
+        }
+    }
+    dialed
+}
+
+/// Handshake on a new connection, register the peer and serve it
+///
+/// Boxed because serving a peer can dial others, which opens connections in
+/// turn; the compiler cannot see through that cycle that the future is `Send`.
+pub(super) fn open<'a>(
+    conn: Box<dyn Connection>,
+    role: Role,
+    expected: Option<PlayerId>,
+    path: ConnectionPath,
+    ctx: &'a PeerContext,
+) -> Pin<Box<dyn Future<Output = Result<PlayerId>> + Send + 'a>> {
+    Box::pin(open_connection(conn, role, expected, path, ctx))
+}
+
+async fn open_connection(
+    conn: Box<dyn Connection>,
+    role: Role,
+    expected: Option<PlayerId>,
+    path: ConnectionPath,
+    ctx: &PeerContext,
+) -> Result<PlayerId> {
+    let addr = conn.peer_addr();
+    let mut config = ctx.network.borrow().clone();
+    if matches!(path, ConnectionPath::Relayed { .. }) {
+        // The relay must only ever see ciphertext
+        config.security.mode = SecurityMode::Required;
+    }
+    let (crowd, parked, live, resume) = {
+        // Evicted peers are refused like denylisted ones until the ban ends
+        let mut state = ctx.state.write().await;
+        let now = Instant::now();
+        state.bans.retain(|_, until| *until > now);
+        config.denylist.extend(state.bans.keys().copied());
+        let holding = |parked: bool| -> Vec<PlayerId> {
+            state
+                .connections
+                .values()
+                .filter(|handle| handle.parked.is_some() == parked)
+                .map(|handle| handle.info.player_id)
+                .collect()
+        };
+        let (parked, live) = (holding(true), holding(false));
+        let crowd = (state.connected_peers.len() >= config.max_peers).then(|| Crowd::of(&state));
+        // Kept until the peer replaces it, in case this attempt fails
+        let resume = expected.and_then(|peer| state.resume_tokens.get(&peer).copied());
+        (crowd, parked, live, resume)
+    };
+    // Refuse in the handshake, so the peer learns why, a second connection
+    // for a proven id or one that would go over max_peers with no one to make
+    // room; both are checked again once it is in. Peers coming back still
+    // hold their place
+    let admit = |peer: &PlayerId| match &crowd {
+        _ if live.contains(peer) => Err(CloseCode::AlreadyConnected),
+        Some(crowd)
+            if !parked.contains(peer) && crowd.victim(ctx.eviction.as_ref(), peer).is_none() =>
+        {
+            tracing::debug!("Refusing {}: at max_peers", short_id(peer));
+            ctx.metrics.peers_rejected_busy.inc();
+            let _ = ctx.events.send(NodeEvent::PeerRejected {
+                peer: *peer,
+                reason: CloseCode::Busy,
+            });
+            Err(CloseCode::Busy)
+        }
+        _ => Ok(()),
+    };
+    let channel = SecureChannel::establish_admitting(
+        conn,
+        &config,
+        &ctx.keypair,
+        role,
+        expected,
+        resume,
+        &admit,
+    )
+    .await?
+    .with_metrics(ctx.metrics.clone());
+    let peer = channel.peer_id();
+    let (close_tx, close_rx) = watch::channel(None);
+    let (outbound_tx, mut outbound_rx) = outbound::queue(&config.outbound, ctx.metrics.clone());
+    let mut throttled = Arc::new(Counter::default());
+    let redial = (role == Role::Initiator && path == ConnectionPath::Direct).then_some(addr);
+    let mut resumed = false;
+    let mut lost = false;
+
+    {
+        let mut state = ctx.state.write().await;
+        if !state.is_running {
+            return Err(SwarmhostError::NotRunning);
+        }
+        if peer == ctx.local_id {
+            return Err(SwarmhostError::Peer("Connected to ourselves".to_string()));
+        }
+        // A peer held for its token must present it when it dials us; one we
+        // dial back ourselves is the peer we held
+        let needs_token = state
+            .connections
+            .get(&peer)
+            .and_then(|handle| handle.parked.as_ref())
+            .is_some_and(|parked| parked.needs_token);
+        if needs_token && role == Role::Responder {
+            let redeemed = match channel.resume_token() {
+                Some(token) => state.resumption.redeem(&peer, token, Instant::now()),
+                None => Err(SwarmhostError::Peer("No resumption token".to_string())),
+            };
+            if let Err(e) = redeemed {
+                tracing::debug!("Not resuming {}: {}", short_id(&peer), e);
+                state.connected_peers.retain(|p| p != &peer);
+                state.connections.remove(&peer);
+                state.requests.retain(|(to, _), _| *to != peer);
+                state.dht_queries.retain(|(to, _), _| *to != peer);
+                lost = true;
+            }
+        }
+        let coming_back = state
+            .connections
+            .get_mut(&peer)
+            .filter(|handle| handle.parked.is_some());
+        if let Some(handle) = coming_back {
+            // Pick up the old queue, with whatever was sent while it was gone
+            if let Some(parked) = handle.parked.take() {
+                outbound_rx = parked.outbound;
+            }
+            handle.close = close_tx;
+            handle.info.addr = addr;
+            handle.info.family = AddressFamily::of(&addr);
+            handle.info.path = path;
+            handle.info.relay = None;
+            handle.redial = redial.or(handle.redial);
+            throttled = handle.throttled.clone();
+            resumed = true;
+        } else {
+            if state.connected_peers.contains(&peer) {
+                return Err(SwarmhostError::handshake(
+                    CloseCode::AlreadyConnected,
+                    format!("{} is already connected", short_id(&peer)),
+                ));
+            }
+            if state.connected_peers.len() >= config.max_peers {
+                match Crowd::of(&state).victim(ctx.eviction.as_ref(), &peer) {
+                    Some(victim) => evict(&mut state, victim, ctx),
+                    None => {
+                        return Err(SwarmhostError::handshake(
+                            CloseCode::Busy,
+                            format!("already at max_peers ({})", config.max_peers),
+                        ));
+                    }
+                }
+            }
+            state.connected_peers.push(peer);
+            state.connections.insert(
+                peer,
+                PeerHandle {
+                    close: close_tx,
+                    outbound: outbound_tx,
+                    info: PeerInfo {
+                        player_id: peer,
+                        addr,
+                        family: AddressFamily::of(&addr),
+                        rtt: None,
+                        path,
+                        relay: None,
+                        queues: QueueDepths::default(),
+                        throttled: Duration::ZERO,
+                        game: None,
+                        synced: None,
+                        reconnecting: false,
+                    },
+                    score: PeerScore::new(Instant::now()),
+                    throttled: throttled.clone(),
+                    redial,
+                    parked: None,
+                },
+            );
+        }
+        if !config.resumption.grace.is_zero() {
+            let lifetime = config.resumption.lifetime;
+            let token = state.resumption.issue(peer, lifetime, Instant::now());
+            send_to(&state, &[peer], PeerMessage::Resume(token));
+        }
+        if let Some(offer) = relay::offer(ctx) {
+            send_to(&state, &[peer], PeerMessage::RelayOffer(offer));
+        }
+        // A peer coming back already knows where to find us
+        if let Some(hello) = dht::hello(&state, ctx).filter(|_| !resumed) {
+            send_to(&state, &[peer], hello);
+        }
+        if let Some(game_id) = &state.current_game {
+            let playing = PeerMessage::Playing {
+                game_id: Some(game_id.clone()),
+                committed: ctx.consensus.lock().await.committed(game_id),
+            };
+            send_to(&state, &[peer], playing);
+        }
+    }
+
+    if lost {
+        let _ = ctx.events.send(NodeEvent::PeerLost { peer });
+    }
+    if resumed {
+        tracing::info!("Reconnected to {} at {}", short_id(&peer), addr);
+        let _ = ctx.events.send(NodeEvent::PeerReconnected { peer, addr });
+    } else {
+        tracing::info!("Connected to {} at {}", short_id(&peer), addr);
+        let _ = ctx.events.send(NodeEvent::PeerConnected { peer, addr });
+    }
+    pex::on_open(peer, redial, ctx).await;
+    let throttle = Throttle::new(&config.upload, ctx.upload.clone(), Instant::now());
+    tokio::spawn(serve(
+        channel,
+        peer,
+        close_rx,
+        outbound_rx,
+        throttle,
+        throttled,
+        ctx.clone(),
+    ));
+
+    Ok(peer)
+}
+
+/// How a connection task's loop ended
+enum Ending {
+    /// We are closing the connection and should tell the peer why
+    Close(CloseCode),
+    /// The peer said goodbye, or the connection failed or timed out
+    Gone(CloseCode),
+}
+
+/// Read from a connection until it fails, times out, the peer says goodbye
+/// or the node asks it to close, answering and sending heartbeats and queued
+/// messages along the way
+async fn serve(
+    mut channel: SecureChannel,
+    peer: PlayerId,
+    mut close: watch::Receiver<Option<CloseCode>>,
+    mut outbound: OutboundReceiver,
+    mut throttle: Throttle,
+    throttled: Arc<Counter>,
+    ctx: PeerContext,
+) {
+    let mut heartbeat = Heartbeat::new(Instant::now());
+    let mut fragments = Fragmenter::new(&ctx.network.borrow().fragmentation);
+    // What the fragments held when last accounted towards the memory budget
+    let mut buffered = 0;
+    let mut limiter = InboundLimiter::new(&ctx.network.borrow().inbound, Instant::now());
+    // Set while a flooding peer goes unread
+    let mut paused: Option<Instant> = None;
+    for credit in channel.mux().opening_credits() {
+        if let Err(e) = send(&mut channel, &mut throttle, &credit).await {
+            tracing::debug!("Granting {} credit failed: {}", short_id(&peer), e);
+        }
+    }
+
+    let ending = loop {
+        // Read each time round so reloaded intervals apply immediately
+        let (interval, timeout) = {
+            let network = ctx.network.borrow();
+            (network.heartbeat_interval, network.peer_timeout)
+        };
+        let reassembly = fragments.next_deadline();
+        memory::reassembling(&mut buffered, &fragments, &ctx);
+
+        // Classes over their upload limits stay queued until the limits allow
+        let now = Instant::now();
+        let delays = Priority::ALL.map(|priority| throttle.delay(priority, now));
+        let queued = outbound.depths();
+        let held = Priority::ALL
+            .iter()
+            .any(|&priority| !delays[priority as usize].is_zero() && queued.of(priority) > 0);
+        let released = throttle.hold(held, now);
+        if !released.is_zero() {
+            let micros = released.as_micros() as u64;
+            throttled.add(micros);
+            ctx.metrics.upload_throttled_micros.add(micros);
+        }
+        let unthrottled = delays
+            .iter()
+            .filter(|delay| !delay.is_zero())
+            .min()
+            .map(|delay| now + *delay);
+        // Classes whose streams are out of credit stay queued too, until the
+        // peer has consumed some of what is in flight
+        let open = Priority::ALL.map(|priority| {
+            delays[priority as usize].is_zero() && channel.mux().lane_open(priority)
+        });
+        // Batched messages wait for their window, but never behind an empty
+        // queue: whatever is gathered goes once nothing else is ready
+        let ready = Priority::ALL
+            .iter()
+            .any(|&priority| open[priority as usize] && queued.of(priority) > 0);
+        let flush_at = channel.flush_due().filter(|_| !ready);
+
+        tokio::select! {
+            message = channel.recv(), if paused.is_none() => {
+                let now = Instant::now();
+                heartbeat.record_received(now);
+                let result = match message {
+                    Ok(message) => match limiter.check(message.len(), now) {
+                        Verdict::Accept => {
+                            handle(
+                                &mut channel,
+                                &mut heartbeat,
+                                &mut fragments,
+                                &mut throttle,
+                                peer,
+                                &message,
+                                &ctx,
+                            )
+                            .await
+                        }
+                        verdict => {
+                            flooded(verdict, &mut paused, peer, &ctx).await.map(|()| None)
+                        }
+                    },
+                    Err(e) => Err(e),
+                };
+                match result {
+                    Ok(None) => {}
+                    Ok(Some(code)) => {
+                        let reason = code.as_seen_by_peer();
+                        tracing::debug!("{} closed the connection: {}", short_id(&peer), reason);
+                        let ack = send(&mut channel, &mut throttle, &PeerMessage::GoAwayAck);
+                        if let Err(e) = ack.await {
+                            tracing::debug!("Acknowledging {} failed: {}", short_id(&peer), e);
+                        }
+                        let _ = tokio::time::timeout(CLOSE_GRACE, channel.close()).await;
+                        break Ending::Gone(reason);
+                    }
+                    Err(e) => {
+                        tracing::debug!("Connection to {} ended: {}", short_id(&peer), e);
+                        break match e {
+                            SwarmhostError::Network(_) => Ending::Gone(CloseCode::Normal),
+                            SwarmhostError::Handshake { code, .. } => Ending::Close(code),
+                            _ => Ending::Close(CloseCode::ProtocolError),
+                        };
+                    }
+                }
+            },
+            _ = tokio::time::sleep_until(paused.unwrap_or(now)), if paused.is_some() => {
+                tracing::debug!("Reading from {} again", short_id(&peer));
+                paused = None;
+            },
+            message = outbound.recv_where(|priority| open[priority as usize]) => {
+                let sent = send_queued(
+                    &mut channel,
+                    &mut outbound,
+                    &mut fragments,
+                    &mut throttle,
+                    message,
+                    &ctx,
+                );
+                if let Err(e) = sent.await {
+                    tracing::debug!("Sending to {} failed: {}", short_id(&peer), e);
+                    break Ending::Gone(CloseCode::Normal);
+                }
+                heartbeat.record_sent(Instant::now());
+            },
+            _ = tokio::time::sleep_until(unthrottled.unwrap_or(now)), if unthrottled.is_some() => {},
+            _ = tokio::time::sleep_until(flush_at.unwrap_or(now)), if flush_at.is_some() => {
+                if let Err(e) = channel.flush().await {
+                    tracing::debug!("Sending to {} failed: {}", short_id(&peer), e);
+                    break Ending::Gone(CloseCode::Normal);
+                }
+            },
+            _ = tokio::time::sleep_until(reassembly.unwrap_or_else(Instant::now)), if reassembly.is_some() => {
+                if let Err(e) = chase_stalled(&mut channel, &mut fragments, &mut throttle, peer).await {
+                    tracing::debug!("Re-requesting from {} failed: {}", short_id(&peer), e);
+                    break Ending::Gone(CloseCode::Normal);
+                }
+            },
+            _ = tokio::time::sleep_until(heartbeat.deadline(interval, timeout)) => {
+                match heartbeat.tick(Instant::now(), interval, timeout) {
+                    Tick::Wait => {}
+                    Tick::Ping(mut ping) => {
+                        fork::stamp(&mut ping, &ctx).await;
+                        // A peer that stopped reading must not stall the timeout
+                        let sent = send(&mut channel, &mut throttle, &ping);
+                        match tokio::time::timeout(timeout, sent).await {
+                            Ok(Ok(())) => {}
+                            Ok(Err(e)) => {
+                                tracing::debug!("Ping to {} failed: {}", short_id(&peer), e);
+                                break Ending::Gone(CloseCode::Normal);
+                            }
+                            Err(_) => break Ending::Gone(CloseCode::Timeout),
+                        }
+                    }
+                    Tick::TimedOut => {
+                        tracing::info!("{} timed out after {:?}", short_id(&peer), timeout);
+                        let _ = tokio::time::timeout(CLOSE_GRACE, channel.close()).await;
+                        break Ending::Gone(CloseCode::Timeout);
+                    }
+                }
+            },
+            _ = close.changed() => {
+                break Ending::Close(close.borrow().unwrap_or(CloseCode::Normal));
+            }
+        }
+    };
+
+    let reason = match ending {
+        Ending::Gone(reason) => reason,
+        Ending::Close(reason) => {
+            let said = go_away(
+                &mut channel,
+                &mut outbound,
+                &mut fragments,
+                &mut throttle,
+                peer,
+                reason,
+                &ctx,
+            );
+            if let Err(e) = said.await {
+                tracing::debug!("Closing {} uncleanly: {}", short_id(&peer), e);
+            }
+            reason
+        }
+    };
+
+    ctx.metrics.memory_reassembly.sub(buffered);
+    pex::on_closed(peer, &ctx).await;
+    let mut state = ctx.state.write().await;
+    let was_connected = state.connected_peers.contains(&peer);
+    if let Some(hold) = reconnect::hold(&state, &peer, reason, &ctx).filter(|_| was_connected) {
+        relay::peer_gone(&mut state, peer);
+        reconnect::park(&mut state, peer, outbound, hold, &ctx);
+        return;
+    }
+    state.connected_peers.retain(|p| p != &peer);
+    state.connections.remove(&peer);
+    state.resumption.revoke(&peer);
+    state.resume_tokens.remove(&peer);
+    // Fails the peer's outstanding requests instead of leaving them to time out
+    state.requests.retain(|(to, _), _| *to != peer);
+    state.dht_queries.retain(|(to, _), _| *to != peer);
+    relay::peer_gone(&mut state, peer);
+    drop(state);
+
+    // Peers removed by the node itself were already reported
+    if was_connected {
+        let _ = ctx
+            .events
+            .send(NodeEvent::PeerDisconnected { peer, reason });
+    }
+}
+
+/// Act on a frame that went over the inbound limits; fails once the peer
+/// should be disconnected
+async fn flooded(
+    verdict: Verdict,
+    paused: &mut Option<Instant>,
+    peer: PlayerId,
+    ctx: &PeerContext,
+) -> Result<()> {
+    ctx.metrics.inbound_dropped.inc();
+    match verdict {
+        Verdict::Accept | Verdict::Drop => {}
+        Verdict::Warn => {
+            tracing::debug!("{} is over the inbound limits", short_id(&peer));
+            penalize(peer, Offense::Flooding, ctx).await;
+        }
+        Verdict::Pause { until } => {
+            tracing::debug!("Not reading from {} until {:?}", short_id(&peer), until);
+            *paused = Some(until);
+        }
+        Verdict::Disconnect => {
+            ctx.metrics.peers_rate_limited.inc();
+            return Err(SwarmhostError::handshake(
+                CloseCode::RateLimited,
+                "still over the inbound limits after a pause",
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// React to one message from a peer, returning the close code if the peer
+/// said goodbye
+async fn handle(
+    channel: &mut SecureChannel,
+    heartbeat: &mut Heartbeat,
+    fragments: &mut Fragmenter,
+    throttle: &mut Throttle,
+    peer: PlayerId,
+    message: &[u8],
+    ctx: &PeerContext,
+) -> Result<Option<CloseCode>> {
+    let len = message.len();
+    let codec = channel.message_codec();
+    let Some(message) = decode(codec, message, peer, ctx).await else {
+        return Ok(None);
+    };
+    let packed = match message {
+        PeerMessage::Batch(packed) => packed,
+        PeerMessage::GoAway { code } => return Ok(Some(code)),
+        message => {
+            let stream = Stream::of(&message);
+            receive(channel, heartbeat, fragments, throttle, peer, message, ctx).await?;
+            consumed(channel, throttle, stream, len).await?;
+            return Ok(None);
+        }
+    };
+    let items = match batch::unpack(&packed) {
+        Ok(items) => items,
+        Err(e) => {
+            tracing::debug!("Garbage batch from {}: {}", short_id(&peer), e);
+            penalize(peer, Offense::MalformedMessage, ctx).await;
+            return Ok(None);
+        }
+    };
+    for item in items {
+        let len = item.len();
+        let codec = channel.message_codec();
+        let Some(message) = decode(codec, item, peer, ctx).await else {
+            continue;
+        };
+        match message {
+            PeerMessage::Batch(_) => {
+                return Err(SwarmhostError::handshake(
+                    CloseCode::ProtocolError,
+                    "batch inside a batch",
+                ));
+            }
+            PeerMessage::GoAway { code } => return Ok(Some(code)),
+            message => {
+                let stream = Stream::of(&message);
+                receive(channel, heartbeat, fragments, throttle, peer, message, ctx).await?;
+                consumed(channel, throttle, stream, len).await?;
+            }
+        }
+    }
+    Ok(None)
+}
+
+/// Close a connection politely: deliver what is queued, tell the peer why
+/// and wait for its acknowledgement, all within `drain_timeout`
+///
+/// The queue refuses new messages from here on. GoAway goes after it, so the
+/// peer has everything we queued by the time it learns we are closing;
+/// whatever it sends us meanwhile is dropped.
+async fn go_away(
+    channel: &mut SecureChannel,
+    outbound: &mut OutboundReceiver,
+    fragments: &mut Fragmenter,
+    throttle: &mut Throttle,
+    peer: PlayerId,
+    reason: CloseCode,
+    ctx: &PeerContext,
+) -> Result<()> {
+    outbound.close();
+    let drain_timeout = ctx.network.borrow().drain_timeout;
+    let goodbye = async {
+        while let Some(message) = outbound.try_recv() {
+            send_queued(channel, outbound, fragments, throttle, message, ctx).await?;
+        }
+        send(channel, throttle, &PeerMessage::GoAway { code: reason }).await?;
+        loop {
+            let frame = channel.recv().await?;
+            if acknowledges(channel.message_codec(), &frame) {
+                return Ok(());
+            }
+        }
+    };
+    let said = match tokio::time::timeout(drain_timeout, goodbye).await {
+        Ok(said) => said,
+        Err(_) => Err(SwarmhostError::timeout(format!(
+            "{} did not acknowledge closing within {:?}",
+            short_id(&peer),
+            drain_timeout
+        ))),
+    };
+    let closed = match tokio::time::timeout(CLOSE_GRACE, channel.close()).await {
+        Ok(closed) => closed,
+        Err(_) => Err(SwarmhostError::timeout("socket did not close in time")),
+    };
+    said.and(closed)
+}
+
+/// Whether a frame answers our GoAway: an ack, or the peer's own GoAway if
+/// both sides closed at once
+fn acknowledges(codec: &dyn MessageCodec, frame: &[u8]) -> bool {
+    match codec.decode(frame) {
+        Ok(PeerMessage::GoAwayAck | PeerMessage::GoAway { .. }) => true,
+        Ok(PeerMessage::Batch(packed)) => batch::unpack(&packed)
+            .is_ok_and(|items| items.iter().any(|item| acknowledges(codec, item))),
+        _ => false,
+    }
+}
+
+/// Dispatch one message, putting fragments together first
+async fn receive(
+    channel: &mut SecureChannel,
+    heartbeat: &mut Heartbeat,
+    fragments: &mut Fragmenter,
+    throttle: &mut Throttle,
+    peer: PlayerId,
+    mut message: PeerMessage,
+    ctx: &PeerContext,
+) -> Result<()> {
+    if let PeerMessage::Fragment(fragment) = message {
+        let codec = channel.message_codec();
+        match reassemble(fragments, fragment, codec, peer, ctx).await? {
+            Some(whole) => message = whole,
+            None => return Ok(()),
+        }
+    }
+    let trace = message.trace().filter(|_| ctx.trace_messages);
+    let span = trace::span(Step::Receive, &ctx.local_id, trace);
+    dispatch(channel, heartbeat, fragments, throttle, peer, message, ctx)
+        .instrument(span)
+        .await
+}
+
+/// Add a fragment to its transfer, returning the message once it is whole
+///
+/// Only bulk messages may arrive in fragments; anything else is a protocol
+/// violation.
+async fn reassemble(
+    fragments: &mut Fragmenter,
+    fragment: Fragment,
+    codec: &dyn MessageCodec,
+    peer: PlayerId,
+    ctx: &PeerContext,
+) -> Result<Option<PeerMessage>> {
+    if ctx.memory.over() && !fragments.receiving(fragment.transfer) {
+        tracing::debug!(
+            "Refusing transfer {} from {}: over max_memory_bytes",
+            fragment.transfer,
+            short_id(&peer)
+        );
+        ctx.metrics.fragments_refused_over_budget.inc();
+        return Ok(None);
+    }
+    let encoded = match fragments.receive(fragment, Instant::now()) {
+        Ok(Some(encoded)) => encoded,
+        Ok(None) => return Ok(None),
+        Err(e) => {
+            tracing::debug!("Dropping fragment from {}: {}", short_id(&peer), e);
+            return Ok(None);
+        }
+    };
+    let Some(message) = decode(codec, &encoded, peer, ctx).await else {
+        return Ok(None);
+    };
+    if message.priority() != Priority::Bulk || matches!(message, PeerMessage::Fragment(_)) {
+        return Err(SwarmhostError::handshake(
+            CloseCode::ProtocolError,
+            format!("{:?} message sent in fragments", message.priority()),
+        ));
+    }
+    Ok(Some(message))
+}
+
+/// Parse a message, penalizing the peer for garbage
+async fn decode(
+    codec: &dyn MessageCodec,
+    message: &[u8],
+    peer: PlayerId,
+    ctx: &PeerContext,
+) -> Option<PeerMessage> {
+    match codec.decode(message) {
+        Ok(message) => Some(message),
+        Err(e) => {
+            tracing::debug!("Garbage from {}: {}", short_id(&peer), e);
+            penalize(peer, Offense::MalformedMessage, ctx).await;
+            None
+        }
+    }
+}
+
+/// React to one decoded message, unless a copy was already handled
+///
+/// A traced message is handled like the one inside, and the trace carried
+/// on by whatever it causes us to send.
+async fn dispatch(
+    channel: &mut SecureChannel,
+    heartbeat: &mut Heartbeat,
+    fragments: &mut Fragmenter,
+    throttle: &mut Throttle,
+    peer: PlayerId,
+    message: PeerMessage,
+    ctx: &PeerContext,
+) -> Result<()> {
+    let (trace, message) = message.untraced();
+    if let Some(id) = dedup::message_id(&message)
+        && !ctx.dedup.lock().await.insert(id, Instant::now())
+    {
+        ctx.metrics.duplicates_dropped.inc();
+        return Ok(());
+    }
+    match message {
+        ping @ PeerMessage::Ping { .. } => {
+            if let PeerMessage::Ping {
+                mark: Some(theirs), ..
+            } = &ping
+            {
+                synced(peer, &theirs.game_id, theirs.sequence, ctx).await;
+            }
+            let mark = fork::answer(&ping, peer, ctx).await;
+            if let Some(pong) = heartbeat::pong_for(&ping, mark) {
+                send(channel, throttle, &pong).await?;
+                heartbeat.record_sent(Instant::now());
+            }
+        }
+        PeerMessage::Pong { nonce, mark, .. } => {
+            if let Some(mark) = mark {
+                fork::check(&mark, peer, ctx).await;
+            }
+            if let Some(rtt) = heartbeat.record_pong(nonce, Instant::now()) {
+                tracing::trace!("RTT to {} is {:?}", short_id(&peer), rtt);
+                if let Some(handle) = ctx.state.write().await.connections.get_mut(&peer) {
+                    handle.info.rtt = Some(rtt);
+                }
+                if rtt > ctx.network.borrow().reputation.max_rtt {
+                    penalize(peer, Offense::HighLatency, ctx).await;
+                }
+            }
+        }
+        PeerMessage::Gossip(message) => receive_gossip(message, peer, trace, ctx).await,
+        PeerMessage::Signal { to, signal } => {
+            // Introduce the sender to `to`, or tell it we cannot
+            let state = ctx.state.read().await;
+            let forwarded = PeerMessage::Signaled { from: peer, signal };
+            if to == peer || send_to(&state, &[to], forwarded) == 0 {
+                let nonce = signal.nonce();
+                let refusal = PeerMessage::Signaled {
+                    from: to,
+                    signal: PunchSignal::Unreachable { nonce },
+                };
+                send_to(&state, &[peer], refusal);
+            }
+        }
+        PeerMessage::Signaled { from, signal } => {
+            traversal::on_signal(from, signal, peer, ctx).await
+        }
+        PeerMessage::RelayOffer(offer) => {
+            if let Some(handle) = ctx.state.write().await.connections.get_mut(&peer) {
+                handle.info.relay = Some(offer);
+            }
+        }
+        PeerMessage::RelayOpen { session, to } => relay::on_open(session, to, peer, ctx).await,
+        PeerMessage::RelayIncoming { session, from } => {
+            relay::on_incoming(session, from, peer, ctx).await
+        }
+        PeerMessage::RelayData { session, payload } => {
+            relay::on_data(session, payload, peer, ctx).await
+        }
+        PeerMessage::RelayClose { session } => relay::on_close(session, peer, ctx).await,
+        // Fragments are put together, and batches and goodbyes handled,
+        // before this; an ack only matters while we are closing
+        PeerMessage::Fragment(_)
+        | PeerMessage::Batch(_)
+        | PeerMessage::GoAway { .. }
+        | PeerMessage::GoAwayAck => {}
+        PeerMessage::Traced { .. } => {
+            return Err(SwarmhostError::handshake(
+                CloseCode::ProtocolError,
+                "traced message inside a traced message",
+            ));
+        }
+        PeerMessage::FragmentRequest { transfer, missing } => {
+            for fragment in fragments.resend(transfer, &missing) {
+                send(channel, throttle, &fragment).await?;
+            }
+        }
+        PeerMessage::Credit { stream, bytes } => channel.mux_mut().credited(stream, bytes),
+        PeerMessage::Resume(token) => {
+            ctx.state.write().await.resume_tokens.insert(peer, token);
+        }
+        PeerMessage::Playing { game_id, committed } => {
+            if let Some(handle) = ctx.state.write().await.connections.get_mut(&peer) {
+                handle.info.game = game_id.clone();
+                handle.info.synced = Some(committed);
+            }
+            sync::on_playing(peer, game_id, committed, ctx).await;
+        }
+        PeerMessage::Direct { class, payload } => {
+            let _ = ctx.events.send(NodeEvent::Message {
+                from: peer,
+                game_id: None,
+                class,
+                payload: payload.into(),
+            });
+        }
+        PeerMessage::Broadcast {
+            game_id,
+            class,
+            payload,
+        } => {
+            // Sent before we left the game, or by a peer with a stale view
+            if ctx.state.read().await.current_game.as_deref() != Some(game_id.as_str()) {
+                tracing::debug!(
+                    "Dropping broadcast for {} from {}",
+                    game_id,
+                    short_id(&peer)
+                );
+                return Ok(());
+            }
+            let _ = ctx.events.send(NodeEvent::Message {
+                from: peer,
+                game_id: Some(game_id),
+                class,
+                payload: payload.into(),
+            });
+        }
+        PeerMessage::Request { id, payload } => {
+            if let Some(trace) = &trace
+                && let Some(traces) = ctx.state.write().await.traces.as_mut()
+            {
+                traces.requested(peer, id, trace);
+            }
+            let _ = ctx.events.send(NodeEvent::Request {
+                from: peer,
+                id,
+                payload: payload.into(),
+            });
+        }
+        PeerMessage::Response { id, payload } => {
+            match ctx.state.write().await.requests.remove(&(peer, id)) {
+                Some(waiting) => {
+                    let _ = waiting.send(payload.into());
+                }
+                None => tracing::debug!(
+                    "Ignoring response {} from {}: timed out or never asked",
+                    id,
+                    short_id(&peer)
+                ),
+            }
+        }
+        PeerMessage::DhtHello { addr } => dht::on_hello(peer, addr, ctx).await,
+        PeerMessage::DhtFindNode { id, target } => {
+            dht::on_query(peer, id, target, dht::Query::Nodes, ctx).await
+        }
+        PeerMessage::DhtFindProviders { id, key } => {
+            dht::on_query(peer, id, key, dht::Query::Providers, ctx).await
+        }
+        PeerMessage::DhtFound {
+            id,
+            closer,
+            providers,
+        } => dht::on_found(peer, id, closer, providers, ctx).await,
+        PeerMessage::DhtProvide { key, addr } => dht::on_provide(peer, key, addr, ctx).await,
+        PeerMessage::Pex(sample) => pex::on_sample(peer, sample, ctx).await,
+        PeerMessage::FetchCommits {
+            game_id,
+            from,
+            count,
+        } => sequence::on_fetch(peer, game_id, from, count, ctx).await,
+        PeerMessage::Commits(commits) => sequence::on_commits(peer, commits, ctx).await,
+        PeerMessage::Forward(action) => rotation::on_forward(peer, action, trace, ctx).await,
+        PeerMessage::FetchSyncPlan { id, game_id, base } => {
+            sync::on_fetch_plan(peer, id, game_id, base, ctx).await
+        }
+        PeerMessage::SyncPlan { id, plan } => {
+            sync::on_answer(peer, id, sync::Answer::Plan(plan), ctx).await
+        }
+        PeerMessage::FetchSnapshot {
+            id,
+            game_id,
+            sequence,
+            delta_from,
+            chunk,
+        } => sync::on_fetch_snapshot(peer, id, game_id, sequence, delta_from, chunk, ctx).await,
+        PeerMessage::Snapshot { id, chunk } => {
+            sync::on_answer(peer, id, sync::Answer::Chunk(chunk), ctx).await
+        }
+        PeerMessage::FetchCertified {
+            id,
+            game_id,
+            from,
+            count,
+        } => sync::on_fetch_certified(peer, id, game_id, from, count, ctx).await,
+        PeerMessage::Certified { id, commits } => {
+            sync::on_answer(peer, id, sync::Answer::Commits(commits), ctx).await
+        }
+        PeerMessage::FetchStateHash {
+            id,
+            game_id,
+            sequence,
+        } => divergence::on_fetch_state_hash(peer, id, game_id, sequence, ctx).await,
+        PeerMessage::StateHash { id, state_hash } => {
+            sync::on_answer(peer, id, sync::Answer::StateHash(state_hash), ctx).await
+        }
+        PeerMessage::GetVotes { round, have } => pull::on_get_votes(peer, round, have, ctx).await,
+        PeerMessage::Votes(votes) => pull::on_votes(peer, votes, ctx).await,
+        PeerMessage::Busy { retry_after_ms } => intake::on_busy(peer, retry_after_ms, ctx).await,
+        PeerMessage::Error {
+            id: Some(id),
+            error,
+        } => sync::on_answer(peer, id, sync::Answer::Failed(error), ctx).await,
+        PeerMessage::Error { id: None, error } => rotation::on_refused(peer, error, ctx).await,
+    }
+    Ok(())
+}
+
+/// Note that `peer` holds the commits of `game_id` up to `sequence`, as its
+/// heartbeats and fetches show, if that is the game it plays
+pub(super) async fn synced(peer: PlayerId, game_id: &str, sequence: u64, ctx: &PeerContext) {
+    if let Some(handle) = ctx.state.write().await.connections.get_mut(&peer)
+        && handle.info.game.as_deref() == Some(game_id)
+    {
+        handle.info.synced = Some(sequence);
+    }
+}
+
+/// Last commit of `game_id` every connected peer playing it holds, as far
+/// as they have shown us; `u64::MAX` with no such peer
+pub(super) fn synced_floor(state: &NodeState, game_id: &str) -> u64 {
+    state
+        .connections
+        .values()
+        .filter(|handle| !handle.info.reconnecting && handle.info.game.as_deref() == Some(game_id))
+        .map(|handle| handle.info.synced.unwrap_or(0))
+        .min()
+        .unwrap_or(u64::MAX)
+}
+
+/// Record the game we are playing and tell every connected peer, with the
+/// last sequence we delivered in it
+pub(super) fn set_game(state: &mut NodeState, game_id: &str, committed: u64) {
+    state.current_game = Some(game_id.to_string());
+    let playing = PeerMessage::Playing {
+        game_id: Some(game_id.to_string()),
+        committed,
+    };
+    send_to(state, &state.connected_peers, playing);
+}
+
+/// Hand a gossiped message to consensus the first time it arrives and pass
+/// it on to a few other peers
+///
+/// Messages consensus rejects (bad signature, rate limited) are not
+/// forwarded. Copies pass on the trace they arrived under. Proposals are
+/// applied speculatively with optimistic execution, and a proposal or vote
+/// that settles an action gets it committed, if we are the sequencer, or
+/// its speculation rolled back. A round that ends hands the turn on, and a
+/// proposal reaching the proposer is put forward. A round skipped after
+/// too many others asks for a new view, and a view change may let us start
+/// the view we lead. Evidence of equivocation found on the way is gossiped,
+/// and the offender ejected.
+async fn receive_gossip(
+    message: GossipMessage,
+    from: PlayerId,
+    trace: Option<TraceContext>,
+    ctx: &PeerContext,
+) {
+    if !ctx.gossip.lock().await.observe(&message) {
+        return;
+    }
+
+    // Adopted before validating, so that anything we send about the action
+    // once consensus has it goes under the same trace
+    let traced = trace.as_ref().filter(|_| ctx.trace_messages);
+    if let Some(trace) = traced {
+        let mut state = ctx.state.write().await;
+        if let Some(traces) = state.traces.as_mut() {
+            traces.adopt(trace);
+        }
+    }
+    let validate = trace::span(Step::Validate, &ctx.local_id, traced);
+    let mut round_ended = false;
+    let mut ejected = false;
+    let accepted = match &message.payload {
+        GossipPayload::Proposal(action) => {
+            let mut consensus = ctx.consensus.lock().await;
+            let _validate = validate.entered();
+            consensus
+                .receive_proposal(action.clone())
+                .map(|id| vec![id])
+        }
+        GossipPayload::Vote(vote) => {
+            let mut consensus = ctx.consensus.lock().await;
+            let _validate = validate.entered();
+            consensus.receive_vote(vote.clone()).map(|outcome| {
+                trace_commit(outcome, &vote.action_id, traced, &ctx.local_id);
+                vec![vote.action_id]
+            })
+        }
+        GossipPayload::Commit(commit) => sequence::receive(commit.clone(), ctx)
+            .instrument(validate)
+            .await
+            .map(|()| Vec::new()),
+        GossipPayload::Round(proposal) => {
+            let sent = tick::sent_at(&proposal.proposer, ctx).await;
+            let mut consensus = ctx.consensus.lock().await;
+            let _validate = validate.entered();
+            let round = consensus.round();
+            let received = consensus.receive_round(proposal.clone(), sent);
+            round_ended = consensus.round() != round;
+            received.map(|fresh| fresh.iter().map(SignedAction::id).collect())
+        }
+        GossipPayload::Timeout(vote) => {
+            let mut consensus = ctx.consensus.lock().await;
+            let _validate = validate.entered();
+            consensus
+                .receive_timeout(vote, Instant::now())
+                .map(|skipped| {
+                    round_ended = skipped;
+                    Vec::new()
+                })
+        }
+        GossipPayload::ViewChange(change) => {
+            let mut consensus = ctx.consensus.lock().await;
+            let _validate = validate.entered();
+            consensus
+                .receive_view_change(change.clone())
+                .map(|()| Vec::new())
+        }
+        GossipPayload::NewView(new_view) => view::receive(new_view.clone(), ctx)
+            .instrument(validate)
+            .await
+            .map(|()| Vec::new()),
+        GossipPayload::Evidence(found) => {
+            let mut consensus = ctx.consensus.lock().await;
+            let _validate = validate.entered();
+            consensus.receive_evidence(found).map(|convicted| {
+                ejected = convicted;
+                Vec::new()
+            })
+        }
+        GossipPayload::CheckpointVote(vote) => checkpoint::receive_vote(vote.clone(), ctx)
+            .instrument(validate)
+            .await
+            .map(|()| Vec::new()),
+        GossipPayload::Checkpoint(found) => checkpoint::receive(found.clone(), ctx)
+            .instrument(validate)
+            .await
+            .map(|()| Vec::new()),
+        GossipPayload::Unsequenced(action) => ordering::receive_gossiped(action.clone(), ctx)
+            .instrument(validate)
+            .await
+            .map(|()| Vec::new()),
+        GossipPayload::ObserverVote(vote) => observer::receive(vote.clone(), ctx)
+            .instrument(validate)
+            .await
+            .map(|()| Vec::new()),
+    };
+    let candidates = match accepted {
+        Ok(action_ids) => action_ids,
+        Err(e) => {
+            tracing::debug!("Not relaying gossip from {}: {}", short_id(&from), e);
+            // Honest peers check signatures, voters and sequencers before
+            // relaying, so a bad one is on whoever sent it to us
+            if let Some(offense) = offense(&e) {
+                penalize(from, offense, ctx).await;
+            }
+            evidence::publish(ctx).await;
+            return;
+        }
+    };
+    // Actions approved before their round reached us wait for its
+    // conflicts to be settled, and may be committed now
+    let mut resolved = Vec::new();
+    match &message.payload {
+        GossipPayload::Proposal(action) => sequence::speculate(action, ctx).await,
+        GossipPayload::Round(proposal) => {
+            sequence::roll_back(ctx).await;
+            for action in &proposal.actions {
+                if candidates.contains(&action.id()) {
+                    sequence::speculate(action, ctx).await;
+                } else if !action.conflict_keys.is_empty() {
+                    resolved.push(action.id());
+                }
+            }
+        }
+        _ => {}
+    }
+    let proposed = matches!(message.payload, GossipPayload::Proposal(_));
+    let round = matches!(message.payload, GossipPayload::Round(_));
+    let timeout = matches!(message.payload, GossipPayload::Timeout(_));
+    let view_change = matches!(message.payload, GossipPayload::ViewChange(_));
+
+    {
+        let config = ctx.network.borrow().gossip.clone();
+        let state = ctx.state.read().await;
+        let relay = ctx
+            .gossip
+            .lock()
+            .await
+            .relay(message, from, &state.connected_peers, &config);
+        if let Some((message, targets)) = relay {
+            send_to(&state, &targets, PeerMessage::Gossip(message).traced(trace));
+        }
+    }
+    for action_id in candidates.into_iter().chain(resolved) {
+        sequence::settle(action_id, trace, ctx).await;
+    }
+    if ejected {
+        evidence::eject(ctx).await;
+    }
+    evidence::publish(ctx).await;
+    if round {
+        tick::deliver(ctx).await;
+    }
+    if view_change {
+        view::lead(ctx).await;
+    } else if round_ended {
+        if timeout {
+            view::request(ctx).await;
+        }
+        rotation::advance(ctx).await;
+    } else if timeout {
+        rotation::on_timeout(ctx).await;
+    } else if proposed {
+        rotation::propose(ctx).await;
+    }
+}
+
+/// Offense to charge the sender of consensus traffic refused with `error`,
+/// if it is one an honest peer would not have relayed
+pub(super) fn offense(error: &SwarmhostError) -> Option<Offense> {
+    match error {
+        SwarmhostError::Crypto(_) => Some(Offense::InvalidSignature),
+        SwarmhostError::Consensus(_) => Some(Offense::ProtocolViolation),
+        _ => None,
+    }
+}
+
+/// Log the commit of `action_id` under its trace when a vote has just
+/// settled it as approved
+pub(super) fn trace_commit(
+    outcome: Option<Outcome>,
+    action_id: &ActionId,
+    trace: Option<&TraceContext>,
+    node: &PlayerId,
+) {
+    if trace.is_some() && outcome == Some(Outcome::Approved) {
+        trace::span(Step::Commit, node, trace)
+            .in_scope(|| tracing::info!("Action {} committed", short_id(action_id)));
+    }
+}
+
+/// Add demerits to a connected peer, and disconnect and ban it once they
+/// reach the threshold
+///
+/// Latency only counts when `penalize_latency` is set.
+pub(super) async fn penalize(peer: PlayerId, offense: Offense, ctx: &PeerContext) {
+    let reputation = ctx.network.borrow().reputation.clone();
+    if offense == Offense::HighLatency && !reputation.penalize_latency {
+        return;
+    }
+
+    let now = Instant::now();
+    let mut state = ctx.state.write().await;
+    let Some(handle) = state.connections.get_mut(&peer) else {
+        return;
+    };
+    let demerits = handle
+        .score
+        .penalize(offense, now, reputation.decay_half_life);
+    ctx.metrics.peer_demerits.add(offense.weight() as u64);
+    tracing::debug!(
+        "{} penalized for {}: {:.1} demerits",
+        short_id(&peer),
+        offense,
+        demerits
+    );
+    let _ = ctx.events.send(NodeEvent::PeerScoreChanged {
+        peer,
+        offense,
+        demerits: demerits.round() as u32,
+    });
+
+    if demerits < reputation.ban_threshold as f64 {
+        return;
+    }
+    tracing::warn!(
+        "Banning {} for {:?} after {}",
+        short_id(&peer),
+        reputation.ban_duration,
+        offense
+    );
+    state.bans.insert(peer, now + reputation.ban_duration);
+    state.connected_peers.retain(|p| p != &peer);
+    if let Some(handle) = state.connections.remove(&peer) {
+        let _ = handle.close.send(Some(CloseCode::Banned));
+    }
+    ctx.metrics.peers_banned.inc();
+    let _ = ctx.events.send(NodeEvent::PeerDisconnected {
+        peer,
+        reason: CloseCode::Banned,
+    });
+}
+
+/// Disconnect `peer` to make room for another
+fn evict(state: &mut NodeState, peer: PlayerId, ctx: &PeerContext) {
+    tracing::info!("Evicting {} to stay within max_peers", short_id(&peer));
+    state.connected_peers.retain(|p| p != &peer);
+    if let Some(handle) = state.connections.remove(&peer) {
+        let _ = handle.close.send(Some(CloseCode::Evicted));
+    }
+    ctx.metrics.peers_evicted.inc();
+    let _ = ctx.events.send(NodeEvent::PeerDisconnected {
+        peer,
+        reason: CloseCode::Evicted,
+    });
+}
+
+/// Start spreading a locally created proposal or vote, under `trace` if set
+///
+/// Returns how many peers it was sent to directly.
+pub(super) async fn publish(
+    payload: GossipPayload,
+    trace: Option<TraceContext>,
+    ctx: &PeerContext,
+) -> usize {
+    let config = ctx.network.borrow().gossip.clone();
+    let state = ctx.state.read().await;
+    let (message, targets) =
+        ctx.gossip
+            .lock()
+            .await
+            .publish(payload, &state.connected_peers, &config);
+    send_to(&state, &targets, PeerMessage::Gossip(message).traced(trace))
+}
+
+/// Queue a message for each target without waiting; returns how many peers
+/// it was queued for
+///
+/// Bulk messages are dropped for peers whose queue is full (senders that can
+/// wait should use [`OutboundSender::send`]), and a peer whose control queue
+/// overflows is disconnected.
+pub(super) fn send_to(state: &NodeState, targets: &[PlayerId], message: PeerMessage) -> usize {
+    let mut queued = 0;
+    for target in targets {
+        let Some(handle) = state.connections.get(target) else {
+            continue;
+        };
+        match handle.outbound.try_send(message.clone()) {
+            Ok(()) => queued += 1,
+            Err(SendError::ControlOverflow) => {
+                tracing::warn!("{} is not draining control traffic", short_id(target));
+                let _ = handle.close.send(Some(CloseCode::Overloaded));
+            }
+            Err(e) => tracing::debug!("Dropping message for {}: {}", short_id(target), e),
+        }
+    }
+    queued
+}
+
+/// Send a queued message, splitting a bulk message too large for one frame
+/// into fragments that go back to the head of the bulk queue
+///
+/// Messages that fit may be batched with others; control messages make the
+/// batch due at once.
+///
+/// Oversized messages of other classes are dropped: they are a bug on our
+/// side, not a reason to lose the peer.
+async fn send_queued(
+    channel: &mut SecureChannel,
+    outbound: &mut OutboundReceiver,
+    fragments: &mut Fragmenter,
+    throttle: &mut Throttle,
+    message: PeerMessage,
+    ctx: &PeerContext,
+) -> Result<()> {
+    let encoded = channel.message_codec().encode(&message)?;
+    let stream = Stream::of(&message);
+    let max_message = channel.mux().max_message(stream, channel.max_payload());
+    if encoded.len() <= max_message {
+        throttle.record(message.priority(), encoded.len(), Instant::now());
+        channel.mux_mut().sent(stream, encoded.len());
+        let urgent = message.priority() == Priority::Control;
+        let trace = message.trace().filter(|_| ctx.trace_messages);
+        let span = trace::span(Step::Send, &ctx.local_id, trace);
+        return channel.send_batched(encoded, urgent).instrument(span).await;
+    }
+    if message.priority() != Priority::Bulk {
+        tracing::warn!(
+            "Dropping {} byte {:?} message: only bulk messages are fragmented",
+            encoded.len(),
+            message.priority()
+        );
+        return Ok(());
+    }
+    match fragments.split(encoded, max_message, Instant::now()) {
+        Ok(pieces) => outbound.push_front(pieces),
+        Err(e) => tracing::warn!("Dropping oversized message: {}", e),
+    }
+    Ok(())
+}
+
+/// Re-request the missing fragments of stalled transfers, or give up on
+/// those already re-requested
+async fn chase_stalled(
+    channel: &mut SecureChannel,
+    fragments: &mut Fragmenter,
+    throttle: &mut Throttle,
+    peer: PlayerId,
+) -> Result<()> {
+    for stalled in fragments.stalled(Instant::now()) {
+        match stalled {
+            Stalled::Rerequest { transfer, missing } => {
+                let request = PeerMessage::FragmentRequest { transfer, missing };
+                send(channel, throttle, &request).await?
+            }
+            Stalled::Abandoned(e) => {
+                tracing::debug!("Transfer from {} failed: {}", short_id(&peer), e)
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Send a message straight away, charging it to the upload limits
+async fn send(
+    channel: &mut SecureChannel,
+    throttle: &mut Throttle,
+    message: &PeerMessage,
+) -> Result<()> {
+    let encoded = channel.message_codec().encode(message)?;
+    throttle.record(message.priority(), encoded.len(), Instant::now());
+    channel.mux_mut().sent(Stream::of(message), encoded.len());
+    channel.send(&encoded).await
+}
+
+/// Count `len` bytes the peer sent on `stream` as consumed, granting it
+/// more credit once enough have been
+async fn consumed(
+    channel: &mut SecureChannel,
+    throttle: &mut Throttle,
+    stream: Stream,
+    len: usize,
+) -> Result<()> {
+    match channel.mux_mut().consumed(stream, len) {
+        Some(credit) => send(channel, throttle, &credit).await,
+        None => Ok(()),
+    }
+}
@@ -0,0 +1,177 @@
+// node/pex.rs - Swapping samples of connected peers with neighbours
+
+use super::dht;
+use super::peers::{self, PeerContext};
+use crate::crypto::{PlayerId, short_id};
+use crate::network::pex::{PexEntry, PexSample};
+use crate::network::{Offense, PeerMessage, PeerRecord, check_admission};
+use rand::seq::SliceRandom;
+use std::net::SocketAddr;
+use tokio::time::Instant;
+
+/// A new connection: confirm the peer if we dialed it, and tell it who we
+/// are connected to
+pub(super) async fn on_open(peer: PlayerId, redial: Option<SocketAddr>, ctx: &PeerContext) {
+    let Some(store) = &ctx.pex else {
+        return;
+    };
+    if let Some(addr) = redial {
+        let record = PeerRecord::at(peer, addr);
+        store.lock().await.confirm(record, Instant::now());
+    }
+    send_samples(&[peer], ctx).await;
+}
+
+/// A connection ended, so we no longer vouch for the peer
+pub(super) async fn on_closed(peer: PlayerId, ctx: &PeerContext) {
+    if let Some(store) = &ctx.pex {
+        store.lock().await.unconfirm(&peer);
+    }
+}
+
+/// Send each target a signed sample of our confirmed peers, leaving the
+/// target out of its own; returns how many were queued
+async fn send_samples(targets: &[PlayerId], ctx: &PeerContext) -> usize {
+    let Some(store) = &ctx.pex else {
+        return 0;
+    };
+    let max_entries = ctx.network.borrow().pex.max_entries;
+    let state = ctx.state.read().await;
+    let addrs = dht::own_addrs(&state);
+    let now = Instant::now();
+
+    let mut sent = 0;
+    for target in targets {
+        let mut entries = store.lock().await.sample(max_entries + 1, now);
+        entries.retain(|entry| entry.player_id != *target);
+        entries.truncate(max_entries);
+        let sample = PexSample::new(&ctx.keypair, addrs.clone(), entries);
+        sent += peers::send_to(&state, &[*target], PeerMessage::Pex(sample));
+    }
+    sent
+}
+
+/// Take in a neighbour's sample
+///
+/// The sample must be the sender's own and correctly signed. The sender is
+/// confirmed at the addresses it gave; up to `max_entries` of its entries are
+/// learned, unless the peer lists or a ban would refuse them.
+pub(super) async fn on_sample(peer: PlayerId, sample: PexSample, ctx: &PeerContext) {
+    let Some(store) = &ctx.pex else {
+        return;
+    };
+    if sample.from != peer || sample.verify().is_err() {
+        tracing::debug!("Bad PEX sample from {}", short_id(&peer));
+        peers::penalize(peer, Offense::InvalidSignature, ctx).await;
+        return;
+    }
+    let config = ctx.network.borrow().clone();
+    let now = Instant::now();
+    let (seen, banned) = {
+        let state = ctx.state.read().await;
+        let Some(handle) = state.connections.get(&peer) else {
+            return;
+        };
+        let banned: Vec<PlayerId> = state
+            .bans
+            .iter()
+            .filter(|(_, until)| **until > now)
+            .map(|(player_id, _)| *player_id)
+            .collect();
+        (handle.info.addr, banned)
+    };
+
+    let mut store = store.lock().await;
+    if !sample.addrs.is_empty() {
+        let record = PeerRecord {
+            player_id: peer,
+            addrs: dht::reachable_all(&sample.addrs, seen),
+        };
+        store.confirm(record, now);
+    }
+    let mut learned = 0;
+    for entry in sample.entries.iter().take(config.pex.max_entries) {
+        let refused = entry.player_id == ctx.local_id
+            || banned.contains(&entry.player_id)
+            || check_admission(&config, &entry.player_id).is_err();
+        if !refused && store.learn(entry, now) {
+            learned += 1;
+        }
+    }
+    tracing::trace!("Learned {} peers from {}", learned, short_id(&peer));
+}
+
+/// Every `interval`, send samples to `fanout` random neighbours and dial up
+/// to `fanout` learned peers while below `max_peers`, until the task is
+/// aborted
+///
+/// The peers known are persisted each time round, and those persisted by
+/// an earlier run are taken in as heard-of first, to dial after a restart.
+pub(super) async fn maintain(ctx: PeerContext) {
+    let Some(store) = ctx.pex.clone() else {
+        return;
+    };
+    let stored = ctx.state_manager.lock().await.load_peers();
+    match stored {
+        Ok(records) => {
+            let mut store = store.lock().await;
+            let now = Instant::now();
+            for record in records {
+                let entry = PexEntry {
+                    player_id: record.player_id,
+                    addrs: record.addrs,
+                    age_ms: 0,
+                };
+                store.learn(&entry, now);
+            }
+        }
+        Err(e) => tracing::warn!("Could not load the peers we knew of: {}", e),
+    }
+    loop {
+        let config = ctx.network.borrow().clone();
+        tokio::time::sleep(config.pex.interval).await;
+
+        let (neighbours, connected) = {
+            let state = ctx.state.read().await;
+            let live: Vec<PlayerId> = state
+                .connections
+                .values()
+                .filter(|handle| handle.parked.is_none())
+                .map(|handle| handle.info.player_id)
+                .collect();
+            (live, state.connected_peers.len())
+        };
+        let (candidates, known) = {
+            let mut store = store.lock().await;
+            let now = Instant::now();
+            store.expire(now);
+            for peer in &neighbours {
+                store.touch(peer, now);
+            }
+            (store.candidates(), store.records())
+        };
+        if let Err(e) = ctx.state_manager.lock().await.save_peers(&known) {
+            tracing::warn!("Could not persist the peers we know of: {}", e);
+        }
+
+        let targets: Vec<PlayerId> = neighbours
+            .choose_multiple(&mut rand::thread_rng(), config.pex.fanout)
+            .copied()
+            .collect();
+        send_samples(&targets, &ctx).await;
+
+        let room = config.max_peers.saturating_sub(connected);
+        let dials: Vec<PeerRecord> = candidates
+            .into_iter()
+            .filter(|candidate| !neighbours.contains(&candidate.player_id))
+            .take(room.min(config.pex.fanout))
+            .collect();
+        if !dials.is_empty() {
+            let dialed = peers::dial_all(ctx.transport.clone(), dials, &ctx).await;
+            tracing::debug!(
+                "Connected to {} peers learned through PEX",
+                dialed.connected.len()
+            );
+        }
+    }
+}
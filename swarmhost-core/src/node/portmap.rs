@@ -0,0 +1,67 @@
+// node/portmap.rs - Keeping the listen port forwarded by the router
+
+use super::{NatConfig, NatStatus, NodeState};
+use crate::network::{self, PortMapper, PortProtocol};
+use std::net::IpAddr;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::RwLock;
+
+/// Shortest wait between renewals, however short a lease the router grants
+const MIN_RENEWAL: Duration = Duration::from_secs(1);
+
+/// Map `port` on the router and renew it halfway through each lease
+///
+/// The external address is advertised unless `advertised_addr` is set. When
+/// no router maps the port the node carries on: STUN detection runs if
+/// servers are configured, and peers can still be reached through relays.
+pub(super) async fn maintain(
+    mapper: PortMapper,
+    protocol: PortProtocol,
+    port: u16,
+    nat: NatConfig,
+    bind_addr: IpAddr,
+    state: Arc<RwLock<NodeState>>,
+) {
+    let mut mapping = match mapper.map(protocol, port, nat.upnp_lease).await {
+        Ok(mapping) => mapping,
+        Err(e) => {
+            tracing::warn!("Could not map port {} on the router: {}", port, e);
+            state.write().await.nat_status = NatStatus::Failed {
+                reason: e.to_string(),
+            };
+            if !nat.stun_servers.is_empty()
+                && let Some(addr) = network::nat::detect_public_addr(&nat, bind_addr, port).await
+            {
+                state.write().await.advertised_addr = Some(addr);
+            }
+            return;
+        }
+    };
+
+    loop {
+        {
+            let mut state = state.write().await;
+            state.nat_status = NatStatus::Mapped {
+                external: mapping.external,
+                method: mapping.method(),
+            };
+            if nat.advertised_addr.is_none() {
+                state.advertised_addr = Some(mapping.external);
+            }
+            state.port_mapping = Some(mapping.clone());
+        }
+
+        // Renew halfway through the lease; after a failure, halfway through
+        // what is left of it
+        let mut wait = (mapping.lease / 2).max(MIN_RENEWAL);
+        loop {
+            tokio::time::sleep(wait).await;
+            match mapping.renew(mapper.timeout()).await {
+                Ok(()) => break,
+                Err(e) => tracing::warn!("Could not renew the port mapping: {}", e),
+            }
+            wait = (wait / 2).max(MIN_RENEWAL);
+        }
+    }
+}
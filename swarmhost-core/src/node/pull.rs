@@ -0,0 +1,113 @@
+// node/pull.rs - Asking peers for votes that never reached us, before a
+// round is given up on
+
+use super::peers::{self, PeerContext};
+use super::{evidence, sequence};
+use crate::consensus::Vote;
+use crate::crypto::{PlayerId, short_id};
+use crate::network::PeerMessage;
+use crate::network::throttle::Bandwidth;
+use std::time::Duration;
+use tokio::time::Instant;
+
+/// Requests for votes a peer may make a second, and in one burst; we ignore
+/// those past it, so that asking cannot be used to flood us or others
+const VOTE_REQUEST_RATE: u64 = 8;
+
+/// Ask for the votes blocks in flight have been missing `after` their
+/// proposal, at most once per `after` for each
+///
+/// The validators we have no vote from are asked if we are connected to
+/// them, and another peer besides: votes are signed, so whoever holds one
+/// can pass it on, even when the link to its voter is the one losing it.
+pub(super) async fn request(after: Duration, ctx: &PeerContext) {
+    let missing = ctx
+        .consensus
+        .lock()
+        .await
+        .missing_votes(Instant::now(), after);
+    if missing.is_empty() {
+        return;
+    }
+    let state = ctx.state.read().await;
+    for (round, have, absent) in missing {
+        let mut targets: Vec<PlayerId> = absent
+            .into_iter()
+            .filter(|validator| state.connected_peers.contains(validator))
+            .collect();
+        if let Some(other) = state
+            .connected_peers
+            .iter()
+            .find(|peer| !targets.contains(peer))
+        {
+            targets.push(*other);
+        }
+        tracing::debug!(
+            "Round {} still missing votes, asking {} peers",
+            round,
+            targets.len()
+        );
+        ctx.metrics.vote_requests.inc();
+        peers::send_to(&state, &targets, PeerMessage::GetVotes { round, have });
+    }
+}
+
+/// Answer a peer asking for the votes of a round with those we hold, unless
+/// it asks more often than `VOTE_REQUEST_RATE` allows
+pub(super) async fn on_get_votes(peer: PlayerId, round: u64, have: u64, ctx: &PeerContext) {
+    {
+        let now = Instant::now();
+        let mut state = ctx.state.write().await;
+        let state = &mut *state;
+        let connected = &state.connected_peers;
+        state
+            .vote_requests
+            .retain(|asker, _| connected.contains(asker));
+        let bucket = state
+            .vote_requests
+            .entry(peer)
+            .or_insert_with(|| Bandwidth::new(VOTE_REQUEST_RATE, now));
+        if !bucket.reserve(1, now).is_zero() {
+            tracing::debug!(
+                "Ignoring vote request from {}: asking too often",
+                short_id(&peer)
+            );
+            return;
+        }
+    }
+    let votes = ctx.consensus.lock().await.held_votes(round, have);
+    if votes.is_empty() {
+        return;
+    }
+    let state = ctx.state.read().await;
+    peers::send_to(&state, &[peer], PeerMessage::Votes(votes));
+}
+
+/// Take in votes a peer sent at our asking, penalizing a peer that sends
+/// bad ones
+///
+/// Votes that reached us some other way in the meantime are passed over;
+/// the rest count as they would gossiped, without being passed on, since
+/// whoever is missing them asks too.
+pub(super) async fn on_votes(peer: PlayerId, votes: Vec<Vote>, ctx: &PeerContext) {
+    for vote in votes {
+        let action_id = vote.action_id;
+        let received = {
+            let mut consensus = ctx.consensus.lock().await;
+            if consensus.has_vote(&action_id, &vote.voter) {
+                continue;
+            }
+            consensus.receive_vote(vote)
+        };
+        if let Err(e) = received {
+            tracing::debug!("Bad vote from {}: {}", short_id(&peer), e);
+            if let Some(offense) = peers::offense(&e) {
+                peers::penalize(peer, offense, ctx).await;
+            }
+            break;
+        }
+        ctx.metrics.votes_recovered.inc();
+        sequence::settle(action_id, None, ctx).await;
+    }
+    evidence::publish(ctx).await;
+}
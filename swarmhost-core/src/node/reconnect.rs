@@ -0,0 +1,146 @@
+// node/reconnect.rs - Getting dropped peers back
+
+use super::peers::{self, PeerContext};
+use super::{NodeEvent, NodeState};
+use crate::crypto::{PlayerId, short_id};
+use crate::network::CloseCode;
+use crate::network::outbound::OutboundReceiver;
+use rand::Rng;
+use std::net::SocketAddr;
+use std::time::Duration;
+use tokio::sync::oneshot::{self, error::TryRecvError};
+use tokio::time::Instant;
+
+/// What is left of a dropped peer's connection while we wait for it
+///
+/// Its queue is kept, so messages sent in the meantime go out once it is
+/// back. Dropping this (on resuming, or when the node removes the peer)
+/// stops the reconnect task.
+pub(super) struct Parked {
+    pub outbound: OutboundReceiver,
+    /// Held for its resumption token rather than as a validator, so it only
+    /// gets its place back by presenting the token
+    pub needs_token: bool,
+    _waiting: oneshot::Sender<()>,
+}
+
+/// How long a dropped peer keeps its place, and on what terms
+pub(super) struct Hold {
+    window: Duration,
+    needs_token: bool,
+}
+
+/// Whether a connection that ended with `reason` should be kept for the
+/// peer to come back to, and for how long
+///
+/// Fellow validators of the game we are playing are kept for
+/// `reconnect.window`; any other peer holding a resumption token from us for
+/// `resumption.grace`. Either only after failures that might pass.
+pub(super) fn hold(
+    state: &NodeState,
+    peer: &PlayerId,
+    reason: CloseCode,
+    ctx: &PeerContext,
+) -> Option<Hold> {
+    if !matches!(reason, CloseCode::Normal | CloseCode::Timeout) || !state.is_running {
+        return None;
+    }
+    let config = ctx.network.borrow();
+    if state.current_game.is_some() && state.validators.contains(peer) {
+        return Some(Hold {
+            window: config.reconnect.window,
+            needs_token: false,
+        });
+    }
+    let grace = config.resumption.grace;
+    (!grace.is_zero() && state.resumption.is_issued(peer, Instant::now())).then_some(Hold {
+        window: grace,
+        needs_token: true,
+    })
+}
+
+/// Keep `peer`'s place and queue, and try to get it back in the background
+///
+/// Peers we dialed directly are re-dialed; the others are waited for. Past
+/// the hold's window the peer is removed and [`NodeEvent::PeerLost`] sent.
+pub(super) fn park(
+    state: &mut NodeState,
+    peer: PlayerId,
+    outbound: OutboundReceiver,
+    hold: Hold,
+    ctx: &PeerContext,
+) {
+    let Some(handle) = state.connections.get_mut(&peer) else {
+        return;
+    };
+    let (waiting, resumed) = oneshot::channel();
+    handle.parked = Some(Parked {
+        outbound,
+        needs_token: hold.needs_token,
+        _waiting: waiting,
+    });
+    handle.info.rtt = None;
+    let redial = handle.redial;
+    tokio::spawn(reconnect(peer, redial, hold.window, resumed, ctx.clone()));
+}
+
+async fn reconnect(
+    peer: PlayerId,
+    redial: Option<SocketAddr>,
+    window: Duration,
+    mut resumed: oneshot::Receiver<()>,
+    ctx: PeerContext,
+) {
+    let config = ctx.network.borrow().reconnect.clone();
+    let deadline = Instant::now() + window;
+    tracing::info!(
+        "Lost {}, holding its place for {:?}",
+        short_id(&peer),
+        window
+    );
+
+    let mut backoff = config.initial_backoff;
+    loop {
+        let wait = jitter(backoff).min(deadline.saturating_duration_since(Instant::now()));
+        tokio::select! {
+            _ = &mut resumed => return,
+            _ = tokio::time::sleep(wait) => {}
+        }
+        if Instant::now() >= deadline {
+            break;
+        }
+        backoff = (backoff * 2).min(config.max_backoff);
+
+        let Some(addr) = redial else {
+            continue;
+        };
+        let timeout = ctx.network.borrow().security.handshake_timeout;
+        let addrs = [addr];
+        let dial = peers::dial(ctx.transport.as_ref(), &addrs, Some(peer), &ctx);
+        match tokio::time::timeout(timeout, dial).await {
+            Ok(Ok(_)) => return,
+            Ok(Err(e)) => tracing::debug!("Re-dialing {} failed: {}", short_id(&peer), e),
+            Err(_) => tracing::debug!("Re-dialing {} timed out", short_id(&peer)),
+        }
+    }
+
+    let mut state = ctx.state.write().await;
+    // It may have come back while we were waiting for the lock
+    if !matches!(resumed.try_recv(), Err(TryRecvError::Empty)) {
+        return;
+    }
+    tracing::info!("Giving up on {} after {:?}", short_id(&peer), window);
+    state.connected_peers.retain(|p| p != &peer);
+    state.connections.remove(&peer);
+    state.requests.retain(|(to, _), _| *to != peer);
+    state.resumption.revoke(&peer);
+    state.resume_tokens.remove(&peer);
+    drop(state);
+    let _ = ctx.events.send(NodeEvent::PeerLost { peer });
+}
+
+/// `backoff` scaled down by up to half, so peers that dropped together do
+/// not all re-dial at once
+fn jitter(backoff: Duration) -> Duration {
+    backoff.mul_f64(rand::thread_rng().gen_range(0.5..=1.0))
+}
@@ -0,0 +1,74 @@
+// node/recovery.rs - Writing down what consensus must not forget before
+// sending it, and picking up from it after a crash
+
+use super::peers::PeerContext;
+use super::sequence;
+use crate::consensus::ConsensusManager;
+use crate::error::Result;
+use crate::state::StateManager;
+use tokio::sync::Mutex;
+use tokio::time::Instant;
+
+/// Persist our safety record ahead of sending a vote, proposal or view
+/// change it covers
+///
+/// Called with the consensus lock held, so the record is never older than
+/// what goes out.
+pub(super) async fn write_ahead(
+    consensus: &ConsensusManager,
+    state_manager: &Mutex<StateManager>,
+) -> Result<()> {
+    let record = consensus.safety_record();
+    state_manager.lock().await.save_safety(&record)
+}
+
+/// Pick up from the safety record an earlier run left, returning whether
+/// there was one
+pub(super) async fn recover(ctx: &PeerContext) -> Result<bool> {
+    let Some(record) = ctx.state_manager.lock().await.load_safety()? else {
+        return Ok(false);
+    };
+    tracing::info!(
+        "Recovering in round {} of view {}, standing by {} earlier votes",
+        record.round,
+        record.view,
+        record.votes.len()
+    );
+    ctx.consensus.lock().await.recover(record);
+    Ok(true)
+}
+
+/// Take up a game's log from our newest stored snapshot of it and the
+/// commits stored after that, if that holds more than we have
+pub(super) async fn restore(game_id: &str, ctx: &PeerContext) -> Result<()> {
+    let mut consensus = ctx.consensus.lock().await;
+    let delivered = {
+        let mut state_manager = ctx.state_manager.lock().await;
+        let committed = consensus.committed(game_id);
+        let Some(sequence) = state_manager.restore_stored(game_id, committed)? else {
+            return Ok(());
+        };
+        tracing::info!("Restored {} at {} from our own store", game_id, sequence);
+        consensus.skip_to(game_id, sequence, Instant::now())
+    };
+    sequence::deliver(delivered, ctx).await
+}
+
+/// Take up every game we hold snapshots or commits of, as a restarted node
+/// finds them in its store
+///
+/// A game that cannot be restored is left for peers to catch us up on.
+pub(super) async fn restore_stored(ctx: &PeerContext) {
+    let games = match ctx.state_manager.lock().await.stored_games() {
+        Ok(games) => games,
+        Err(e) => {
+            tracing::warn!("Could not look for stored games: {}", e);
+            return;
+        }
+    };
+    for game_id in games {
+        if let Err(e) = restore(&game_id, ctx).await {
+            tracing::warn!("Could not restore {} from our store: {}", game_id, e);
+        }
+    }
+}
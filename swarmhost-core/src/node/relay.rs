@@ -0,0 +1,328 @@
+// node/relay.rs - Relaying for peers that cannot reach each other, and using relays
+
+use super::NodeState;
+use super::peers::{self, ConnectionPath, PeerContext};
+use crate::crypto::{PlayerId, short_id};
+use crate::error::{Result, SwarmhostError};
+use crate::network::relay::{RelayOffer, RelayUsage, RelayedConnection};
+use crate::network::throttle::Bandwidth;
+use crate::network::{PeerMessage, Role};
+use bytes::Bytes;
+use std::sync::{Arc, Mutex};
+use tokio::sync::mpsc;
+use tokio::time::Instant;
+
+/// Frames buffered for a relayed session, in either role, before it is
+/// considered stuck and closed
+const RELAY_QUEUE: usize = 256;
+
+/// A session this node relays between two of its peers
+pub(super) struct RelaySession {
+    between: (PlayerId, PlayerId),
+    /// Frames waiting for the bandwidth cap, with the peer each is for
+    queue: mpsc::Sender<(PlayerId, Vec<u8>)>,
+    usage: Arc<Mutex<RelayUsage>>,
+}
+
+impl RelaySession {
+    pub fn usage(&self) -> RelayUsage {
+        self.usage.lock().unwrap().clone()
+    }
+
+    fn other(&self, peer: PlayerId) -> Option<PlayerId> {
+        match self.between {
+            (a, b) if a == peer => Some(b),
+            (a, b) if b == peer => Some(a),
+            _ => None,
+        }
+    }
+}
+
+/// What we advertise to new peers, if we relay at all
+pub(super) fn offer(ctx: &PeerContext) -> Option<RelayOffer> {
+    let config = ctx.network.borrow();
+    config.relay.enabled.then(|| RelayOffer {
+        max_sessions: config.relay.max_sessions as u32,
+        session_bandwidth: config.relay.session_bandwidth,
+    })
+}
+
+/// Reach `target` through `via`, which splices our frames onto its own
+/// connection to `target`
+///
+/// The session still runs the full secure handshake end to end, so `via`
+/// forwards only ciphertext.
+pub(super) async fn connect_relayed(
+    target: PlayerId,
+    via: PlayerId,
+    ctx: &PeerContext,
+) -> Result<PlayerId> {
+    let session = rand::random();
+    let (frames, from_relay) = mpsc::channel(RELAY_QUEUE);
+    let (relay_addr, to_relay) = {
+        let mut state = ctx.state.write().await;
+        let handle = state
+            .connections
+            .get(&via)
+            .ok_or(SwarmhostError::NotConnected { peer: via })?;
+        let route = (handle.info.addr, handle.outbound.clone());
+        state.relayed.insert((via, session), frames);
+        route
+    };
+
+    let open = PeerMessage::RelayOpen {
+        session,
+        to: target,
+    };
+    let max_message_size = ctx.network.borrow().max_message_size;
+    let conn = RelayedConnection::new(
+        session,
+        relay_addr,
+        to_relay.clone(),
+        from_relay,
+        max_message_size,
+    );
+    let result = match to_relay.send(open).await {
+        Ok(()) => {
+            let path = ConnectionPath::Relayed { via };
+            peers::open(Box::new(conn), Role::Initiator, Some(target), path, ctx).await
+        }
+        Err(_) => Err(SwarmhostError::Disconnected { peer: via }),
+    };
+    if result.is_err() {
+        ctx.state.write().await.relayed.remove(&(via, session));
+    }
+    result
+}
+
+/// Try each connected peer that offered to relay until one reaches `target`
+pub(super) async fn connect_any_relay(target: PlayerId, ctx: &PeerContext) -> Result<PlayerId> {
+    if !ctx.network.borrow().allow_relay {
+        return Err(SwarmhostError::Peer("Relaying is disabled".to_string()));
+    }
+
+    let relays: Vec<_> = {
+        let state = ctx.state.read().await;
+        state
+            .connections
+            .values()
+            .filter(|handle| handle.info.relay.is_some() && handle.info.player_id != target)
+            .map(|handle| handle.info.player_id)
+            .collect()
+    };
+
+    let mut last = SwarmhostError::Peer("No connected peer offers to relay".to_string());
+    for via in relays {
+        match connect_relayed(target, via, ctx).await {
+            Ok(peer) => return Ok(peer),
+            Err(e) => {
+                tracing::debug!(
+                    "Relaying to {} via {} failed: {}",
+                    short_id(&target),
+                    short_id(&via),
+                    e
+                );
+                last = e;
+            }
+        }
+    }
+    Err(last)
+}
+
+/// `from` asks us to relay a session to `to`
+pub(super) async fn on_open(session: u64, to: PlayerId, from: PlayerId, ctx: &PeerContext) {
+    let (enabled, max_sessions, bandwidth) = {
+        let config = ctx.network.borrow();
+        (
+            config.relay.enabled,
+            config.relay.max_sessions,
+            config.relay.session_bandwidth,
+        )
+    };
+
+    let mut state = ctx.state.write().await;
+    let refusal = if !enabled {
+        Some("relaying is disabled")
+    } else if state.relay_sessions.len() >= max_sessions {
+        Some("relay is full")
+    } else if state.relay_sessions.contains_key(&session) {
+        Some("session id in use")
+    } else if to == from || !state.connections.contains_key(&to) {
+        Some("target is not connected")
+    } else {
+        None
+    };
+    if let Some(reason) = refusal {
+        tracing::debug!(
+            "Refusing to relay {} to {}: {}",
+            short_id(&from),
+            short_id(&to),
+            reason
+        );
+        peers::send_to(&state, &[from], PeerMessage::RelayClose { session });
+        return;
+    }
+
+    let usage = Arc::new(Mutex::new(RelayUsage {
+        session,
+        between: (from, to),
+        bytes: 0,
+        frames: 0,
+        throttled: Default::default(),
+    }));
+    let (queue, frames) = mpsc::channel(RELAY_QUEUE);
+    tokio::spawn(forward(
+        session,
+        frames,
+        bandwidth,
+        usage.clone(),
+        ctx.clone(),
+    ));
+    state.relay_sessions.insert(
+        session,
+        RelaySession {
+            between: (from, to),
+            queue,
+            usage,
+        },
+    );
+    peers::send_to(&state, &[to], PeerMessage::RelayIncoming { session, from });
+    tracing::info!("Relaying {} to {}", short_id(&from), short_id(&to));
+}
+
+/// Deliver a session's frames in order, no faster than its bandwidth cap
+///
+/// Ends when the session is removed and its queue closes.
+async fn forward(
+    session: u64,
+    mut frames: mpsc::Receiver<(PlayerId, Vec<u8>)>,
+    bandwidth: u64,
+    usage: Arc<Mutex<RelayUsage>>,
+    ctx: PeerContext,
+) {
+    let mut bucket = Bandwidth::new(bandwidth, Instant::now());
+    while let Some((to, payload)) = frames.recv().await {
+        let delay = bucket.reserve(payload.len(), Instant::now());
+        if !delay.is_zero() {
+            tokio::time::sleep(delay).await;
+        }
+        {
+            let mut usage = usage.lock().unwrap();
+            usage.bytes += payload.len() as u64;
+            usage.frames += 1;
+            usage.throttled += delay;
+        }
+
+        let outbound = match ctx.state.read().await.connections.get(&to) {
+            Some(handle) => handle.outbound.clone(),
+            None => break,
+        };
+        if outbound
+            .send(PeerMessage::RelayData { session, payload })
+            .await
+            .is_err()
+        {
+            break;
+        }
+    }
+}
+
+/// A relay we use tells us `from` opened a session to us through it
+pub(super) async fn on_incoming(session: u64, from: PlayerId, via: PlayerId, ctx: &PeerContext) {
+    let (allowed, max_message_size) = {
+        let config = ctx.network.borrow();
+        (config.allow_relay, config.max_message_size)
+    };
+
+    let mut state = ctx.state.write().await;
+    let route = state
+        .connections
+        .get(&via)
+        .map(|handle| (handle.info.addr, handle.outbound.clone()));
+    let Some((relay_addr, to_relay)) = route.filter(|_| allowed && state.is_running) else {
+        peers::send_to(&state, &[via], PeerMessage::RelayClose { session });
+        return;
+    };
+
+    // Registered before returning so the frames right behind this message
+    // find the session
+    let (frames, from_relay) = mpsc::channel(RELAY_QUEUE);
+    state.relayed.insert((via, session), frames);
+    drop(state);
+
+    let conn = RelayedConnection::new(session, relay_addr, to_relay, from_relay, max_message_size);
+    let ctx = ctx.clone();
+    tokio::spawn(async move {
+        let path = ConnectionPath::Relayed { via };
+        if let Err(e) = peers::open(Box::new(conn), Role::Responder, None, path, &ctx).await {
+            tracing::debug!("Relayed connection from {} failed: {}", short_id(&from), e);
+            ctx.state.write().await.relayed.remove(&(via, session));
+        }
+    });
+}
+
+/// A relayed frame arrived from `from`, either for a session we are an end
+/// of or for one we relay
+pub(super) async fn on_data(session: u64, payload: Vec<u8>, from: PlayerId, ctx: &PeerContext) {
+    let mut state = ctx.state.write().await;
+
+    if let Some(frames) = state.relayed.get(&(from, session)) {
+        if frames.try_send(Bytes::from(payload)).is_err() {
+            tracing::debug!("Relayed session {} is stuck; closing it", session);
+            state.relayed.remove(&(from, session));
+            peers::send_to(&state, &[from], PeerMessage::RelayClose { session });
+        }
+        return;
+    }
+
+    let Some(relay) = state.relay_sessions.get(&session) else {
+        return;
+    };
+    let Some(to) = relay.other(from) else {
+        return;
+    };
+    if relay.queue.try_send((to, payload)).is_err() {
+        tracing::debug!("Relay session {} exceeded its queue; closing it", session);
+        close_session(&mut state, session);
+    }
+}
+
+/// `from` ended a session, as its relay or as one of its ends
+pub(super) async fn on_close(session: u64, from: PlayerId, ctx: &PeerContext) {
+    let mut state = ctx.state.write().await;
+    if state.relayed.remove(&(from, session)).is_some() {
+        return;
+    }
+    let involved = state
+        .relay_sessions
+        .get(&session)
+        .is_some_and(|relay| relay.other(from).is_some());
+    if involved {
+        close_session(&mut state, session);
+    }
+}
+
+/// Stop relaying a session and tell both ends
+pub(super) fn close_session(state: &mut NodeState, session: u64) -> bool {
+    let Some(relay) = state.relay_sessions.remove(&session) else {
+        return false;
+    };
+    let (a, b) = relay.between;
+    peers::send_to(state, &[a, b], PeerMessage::RelayClose { session });
+    true
+}
+
+/// Tear down relaying that depended on a peer that just disconnected
+pub(super) fn peer_gone(state: &mut NodeState, peer: PlayerId) {
+    state.relayed.retain(|(via, _), _| *via != peer);
+
+    let sessions: Vec<_> = state
+        .relay_sessions
+        .iter()
+        .filter(|(_, relay)| relay.other(peer).is_some())
+        .map(|(session, _)| *session)
+        .collect();
+    for session in sessions {
+        close_session(state, session);
+    }
+}
@@ -0,0 +1,236 @@
+// node/reload.rs - Runtime reconfiguration of tunable fields
+
+use super::config::{ConsensusConfig, NetworkConfig, NodeConfig, StateConfig};
+use crate::error::{Result, SwarmhostError};
+use serde_json::Value;
+use std::sync::Mutex;
+use tokio::sync::watch;
+
+/// Fields that may change while the node is running, as dotted paths
+pub const TUNABLE_FIELDS: &[&str] = &[
+    "network.heartbeat_interval",
+    "network.peer_timeout",
+    "network.bootstrap_timeout",
+    "network.max_peers",
+    "network.compression.min_size",
+    "network.gossip.fanout",
+    "network.gossip.max_hops",
+    "network.reputation.ban_threshold",
+    "network.reputation.penalize_latency",
+    "consensus.consensus_timeout",
+    "consensus.gap_timeout",
+    "consensus.proposer_timeout",
+    "state.snapshot_interval",
+];
+
+/// Outcome of a config reload
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ConfigDiff {
+    /// Changed fields that were applied
+    pub applied: Vec<String>,
+
+    /// Changed fields that cannot change at runtime and were left as they were
+    pub rejected: Vec<String>,
+}
+
+impl ConfigDiff {
+    /// True if the new config was identical to the running one
+    pub fn is_empty(&self) -> bool {
+        self.applied.is_empty() && self.rejected.is_empty()
+    }
+}
+
+/// Watch channels carrying the live value of each tunable config section
+///
+/// Long-running tasks hold receivers and re-read them on every iteration, so a
+/// reload takes effect without restarting tasks or reconnecting peers.
+pub(crate) struct ConfigWatch {
+    pub network: watch::Sender<NetworkConfig>,
+    pub consensus: watch::Sender<ConsensusConfig>,
+    pub state: watch::Sender<StateConfig>,
+    /// Held across each [`update`](Self::update), so that one edit never
+    /// publishes over another it did not see
+    editing: Mutex<()>,
+}
+
+impl ConfigWatch {
+    pub fn new(config: &NodeConfig) -> Self {
+        Self {
+            network: watch::channel(config.network.clone()).0,
+            consensus: watch::channel(config.consensus.clone()).0,
+            state: watch::channel(config.state.clone()).0,
+            editing: Mutex::new(()),
+        }
+    }
+
+    /// Overlay the live section values onto `base`
+    pub fn current(&self, base: &NodeConfig) -> NodeConfig {
+        let mut config = base.clone();
+        config.network = self.network.borrow().clone();
+        config.consensus = self.consensus.borrow().clone();
+        config.state = self.state.borrow().clone();
+        config
+    }
+
+    /// Edit the live config over `base` and publish the result, one edit at
+    /// a time
+    ///
+    /// `edit` returns the config to publish along with what the caller
+    /// wants back, or an error to leave the live config as it was.
+    pub fn update<T>(
+        &self,
+        base: &NodeConfig,
+        edit: impl FnOnce(NodeConfig) -> Result<(NodeConfig, T)>,
+    ) -> Result<T> {
+        let _editing = self.editing.lock().unwrap();
+        let (updated, result) = edit(self.current(base))?;
+        self.publish(&updated);
+        Ok(result)
+    }
+
+    /// Publish new section values to every subscriber
+    fn publish(&self, config: &NodeConfig) {
+        self.network.send_replace(config.network.clone());
+        self.consensus.send_replace(config.consensus.clone());
+        self.state.send_replace(config.state.clone());
+    }
+}
+
+/// Merge the tunable fields of `new` into `current`
+///
+/// Returns the merged config along with which changed fields were applied and
+/// which were rejected as immutable.
+pub(crate) fn reconcile(
+    current: &NodeConfig,
+    new: &NodeConfig,
+) -> Result<(NodeConfig, ConfigDiff)> {
+    let current_value = to_value(current)?;
+    let new_value = to_value(new)?;
+
+    let mut changed = Vec::new();
+    changed_paths("", &current_value, &new_value, &mut changed);
+
+    let mut diff = ConfigDiff::default();
+    if current.player_id() != new.player_id() {
+        diff.rejected.push("keypair".to_string());
+    }
+
+    let mut merged_value = current_value;
+    for path in changed {
+        if !TUNABLE_FIELDS.contains(&path.as_str()) {
+            diff.rejected.push(path);
+            continue;
+        }
+
+        let pointer = format!("/{}", path.replace('.', "/"));
+        if let (Some(slot), Some(value)) = (
+            merged_value.pointer_mut(&pointer),
+            new_value.pointer(&pointer),
+        ) {
+            *slot = value.clone();
+        }
+        diff.applied.push(path);
+    }
+
+    let mut merged: NodeConfig = serde_json::from_value(merged_value)
+        .map_err(|e| SwarmhostError::Serialization(e.to_string()))?;
+    merged.keypair = current.keypair.clone();
+
+    Ok((merged, diff))
+}
+
+fn to_value(config: &NodeConfig) -> Result<Value> {
+    serde_json::to_value(config).map_err(|e| SwarmhostError::Serialization(e.to_string()))
+}
+
+/// Collect the dotted paths of every leaf that differs between two values
+fn changed_paths(prefix: &str, old: &Value, new: &Value, out: &mut Vec<String>) {
+    let join = |key: &str| {
+        if prefix.is_empty() {
+            key.to_string()
+        } else {
+            format!("{}.{}", prefix, key)
+        }
+    };
+
+    match (old, new) {
+        (Value::Object(old_map), Value::Object(new_map)) => {
+            for (key, old_value) in old_map {
+                match new_map.get(key) {
+                    Some(new_value) => changed_paths(&join(key), old_value, new_value, out),
+                    None => out.push(join(key)),
+                }
+            }
+            for key in new_map.keys() {
+                if !old_map.contains_key(key) {
+                    out.push(join(key));
+                }
+            }
+        }
+        _ if old != new => out.push(prefix.to_string()),
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn test_reconcile_applies_tunables_and_rejects_immutables() {
+        let current = NodeConfig::new();
+        let mut new = current.clone();
+        new.network.heartbeat_interval = Duration::from_millis(1500);
+        new.network.max_peers = 8;
+        new.state.snapshot_interval = 20;
+        new.listen_port = 4242;
+        new.consensus.quorum_numerator = 3;
+        new.consensus.quorum_denominator = 4;
+
+        let (merged, diff) = reconcile(&current, &new).unwrap();
+
+        assert_eq!(
+            diff.applied,
+            vec![
+                "network.heartbeat_interval",
+                "network.max_peers",
+                "state.snapshot_interval"
+            ]
+        );
+        assert_eq!(
+            diff.rejected,
+            vec![
+                "consensus.quorum_denominator",
+                "consensus.quorum_numerator",
+                "listen_port"
+            ]
+        );
+        assert_eq!(
+            merged.network.heartbeat_interval,
+            Duration::from_millis(1500)
+        );
+        assert_eq!(merged.network.max_peers, 8);
+        assert_eq!(merged.listen_port, current.listen_port);
+        assert_eq!(merged.consensus.quorum_numerator, 2);
+        assert_eq!(merged.player_id(), current.player_id());
+    }
+
+    #[test]
+    fn test_reconcile_rejects_new_keypair() {
+        let current = NodeConfig::new();
+        let new = NodeConfig::new();
+
+        let (merged, diff) = reconcile(&current, &new).unwrap();
+        assert_eq!(diff.rejected, vec!["keypair"]);
+        assert!(diff.applied.is_empty());
+        assert_eq!(merged.player_id(), current.player_id());
+    }
+
+    #[test]
+    fn test_reconcile_identical_is_empty() {
+        let current = NodeConfig::new();
+        let (_, diff) = reconcile(&current, &current.clone()).unwrap();
+        assert!(diff.is_empty());
+    }
+}
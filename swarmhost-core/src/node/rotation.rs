@@ -0,0 +1,286 @@
+// node/rotation.rs - Getting local actions to the validator whose turn it is
+// to propose, and skipping a proposer that stays silent
+
+use super::peers::{self, PeerContext};
+use super::{ConsensusConfig, NodeEvent, NodeState, intake, pull, recovery, sequence, tick, view};
+use crate::consensus::SignedAction;
+use crate::crypto::{PlayerId, short_id};
+use crate::error::SwarmhostError;
+use crate::network::trace::{self, Step, TraceContext};
+use crate::network::{GossipPayload, PeerMessage, RemoteError};
+use tokio::sync::watch;
+use tokio::time::Instant;
+use tracing::Instrument;
+
+/// Send a local action, already accepted by consensus, on its way
+///
+/// Without validators it is gossiped as it is. With them, we propose it if
+/// it is our turn, and forward it to the proposer if not.
+pub(super) async fn submit(action: SignedAction, trace: Option<TraceContext>, ctx: &PeerContext) {
+    let proposer = ctx.consensus.lock().await.proposer();
+    match proposer {
+        None => {
+            peers::publish(GossipPayload::Proposal(action), trace, ctx).await;
+        }
+        Some(proposer) if proposer != ctx.local_id => forward(action, proposer, trace, ctx).await,
+        Some(_) => advance(ctx).await,
+    }
+}
+
+/// Take in an action a peer forwarded for us to propose, penalizing a peer
+/// that forwards a bad one
+///
+/// It is accepted even when it is not our turn, to go in our next round;
+/// its actor hands it to every new proposer until one puts it forward.
+/// With too many actions pending, we answer busy instead.
+pub(super) async fn on_forward(
+    peer: PlayerId,
+    action: SignedAction,
+    trace: Option<TraceContext>,
+    ctx: &PeerContext,
+) {
+    let traced = trace.as_ref().filter(|_| ctx.trace_messages);
+    if let Some(trace) = traced {
+        let mut state = ctx.state.write().await;
+        if let Some(traces) = state.traces.as_mut() {
+            traces.adopt(trace);
+        }
+    }
+    let validate = trace::span(Step::Validate, &ctx.local_id, traced);
+    let accepted = {
+        let mut consensus = ctx.consensus.lock().await;
+        let _validate = validate.entered();
+        consensus.receive_proposal(action.clone())
+    };
+    let action_id = match accepted {
+        Ok(action_id) => action_id,
+        Err(SwarmhostError::Overloaded(_)) => return intake::refuse(peer, &action, ctx).await,
+        Err(e) => {
+            tracing::debug!("Bad forward from {}: {}", short_id(&peer), e);
+            let mut error = RemoteError::from_error(&e);
+            error.action_id = Some(action.id());
+            let refusal = PeerMessage::Error { id: None, error };
+            peers::send_to(&*ctx.state.read().await, &[peer], refusal);
+            if let Some(offense) = peers::offense(&e) {
+                peers::penalize(peer, offense, ctx).await;
+            }
+            return;
+        }
+    };
+    sequence::speculate(&action, ctx).await;
+    propose(ctx).await;
+    sequence::settle(action_id, trace, ctx).await;
+}
+
+/// Tell whoever waits on an action of ours that the validator we forwarded
+/// it to refused it
+///
+/// Only an action still waiting for a proposal can have been refused, and
+/// only a validator may refuse it.
+pub(super) async fn on_refused(peer: PlayerId, error: RemoteError, ctx: &PeerContext) {
+    let Some(action_id) = error.action_id else {
+        return;
+    };
+    {
+        let consensus = ctx.consensus.lock().await;
+        let ours = consensus
+            .outstanding()
+            .iter()
+            .any(|action| action.id() == action_id);
+        if !ours || !consensus.validators().contains(&peer) {
+            return;
+        }
+    }
+    tracing::debug!(
+        "{} refused {}: {}",
+        short_id(&peer),
+        short_id(&action_id),
+        error.message
+    );
+    let _ = ctx.events.send(NodeEvent::ActionRefused {
+        action_id,
+        by: peer,
+        error,
+    });
+}
+
+/// Put the actions waiting for a round forward, if it is our turn
+pub(super) async fn propose(ctx: &PeerContext) {
+    if ctx.consensus.lock().await.proposer() == Some(ctx.local_id) {
+        advance(ctx).await;
+    }
+}
+
+/// Follow the rounds from the one in progress: propose while the turn is
+/// ours and actions are waiting, then hand our own outstanding actions to
+/// the proposer
+///
+/// Called whenever a round ends, so an action whose proposer went quiet
+/// moves on with the turn.
+pub(super) async fn advance(ctx: &PeerContext) {
+    loop {
+        let (proposal, proposer, outstanding) = {
+            let mut consensus = ctx.consensus.lock().await;
+            let proposal = consensus.propose(&ctx.keypair, Instant::now());
+            if proposal.is_some()
+                && let Err(e) = recovery::write_ahead(&consensus, &ctx.state_manager).await
+            {
+                // Better the round times out than we propose in it twice
+                tracing::error!("Could not persist our safety record, not proposing: {}", e);
+                return;
+            }
+            let outstanding = consensus.outstanding().to_vec();
+            (proposal, consensus.proposer(), outstanding)
+        };
+        let Some(proposal) = proposal else {
+            let Some(proposer) = proposer.filter(|proposer| *proposer != ctx.local_id) else {
+                return;
+            };
+            for action in outstanding {
+                let trace = action_trace(&action, ctx).await;
+                forward(action, proposer, trace, ctx).await;
+            }
+            return;
+        };
+        tracing::debug!(
+            "Proposing {} actions in round {}",
+            proposal.actions.len(),
+            proposal.round
+        );
+        sequence::roll_back(ctx).await;
+        // The round goes under its first action's trace; an empty tick has
+        // none
+        let trace = match proposal.actions.first() {
+            Some(action) => action_trace(action, ctx).await,
+            None => None,
+        };
+        let span = trace::span(Step::Propose, &ctx.local_id, trace.as_ref());
+        peers::publish(GossipPayload::Round(proposal), trace, ctx)
+            .instrument(span)
+            .await;
+        tick::deliver(ctx).await;
+    }
+}
+
+/// The trace of `action`, when tracing messages
+async fn action_trace(action: &SignedAction, ctx: &PeerContext) -> Option<TraceContext> {
+    let mut state = ctx.state.write().await;
+    state
+        .traces
+        .as_mut()
+        .map(|traces| traces.action(action.id()))
+}
+
+/// Hand an action to `proposer`, or gossip it when we have no working
+/// connection to it, so that it reaches the proposer through other peers
+async fn forward(
+    action: SignedAction,
+    proposer: PlayerId,
+    trace: Option<TraceContext>,
+    ctx: &PeerContext,
+) {
+    {
+        let state = ctx.state.read().await;
+        let forward = PeerMessage::Forward(action.clone()).traced(trace);
+        if reachable(&state, &proposer) && peers::send_to(&state, &[proposer], forward) > 0 {
+            return;
+        }
+    }
+    tracing::debug!(
+        "Proposer {} not connected, gossiping {}",
+        short_id(&proposer),
+        short_id(&action.id())
+    );
+    peers::publish(GossipPayload::Proposal(action), trace, ctx).await;
+}
+
+/// Whether we have a connection to `peer` that is up
+fn reachable(state: &NodeState, peer: &PlayerId) -> bool {
+    state
+        .connections
+        .get(peer)
+        .is_some_and(|handle| handle.parked.is_none())
+}
+
+/// Vote to skip the round in progress, and move on if that made a quorum
+async fn skip(ctx: &PeerContext) {
+    let (vote, skipped) = {
+        let mut consensus = ctx.consensus.lock().await;
+        let round = consensus.round();
+        let vote = consensus.skip(&ctx.keypair, Instant::now());
+        (vote, consensus.round() != round)
+    };
+    let Some(vote) = vote else {
+        return;
+    };
+    tracing::info!(
+        "Voting to skip round {}: its proposer is silent",
+        vote.round
+    );
+    peers::publish(GossipPayload::Timeout(vote), None, ctx).await;
+    if skipped {
+        view::request(ctx).await;
+        advance(ctx).await;
+    }
+}
+
+/// After a peer voted to skip a round that goes on, join in straight away
+/// if we cannot reach its proposer either; otherwise our own timer decides
+pub(super) async fn on_timeout(ctx: &PeerContext) {
+    let proposer = ctx.consensus.lock().await.proposer();
+    let Some(proposer) = proposer.filter(|proposer| *proposer != ctx.local_id) else {
+        return;
+    };
+    if !reachable(&*ctx.state.read().await, &proposer) {
+        skip(ctx).await;
+    }
+}
+
+/// Vote to skip rounds whose proposer kept us waiting `proposer_timeout`,
+/// until the task is aborted
+///
+/// Blocks still short of votes half that long after their proposal have
+/// them [asked for](pull::request) first, so that a vote lost on the way
+/// does not cost the round.
+pub(super) async fn watch(consensus: watch::Receiver<ConsensusConfig>, ctx: PeerContext) {
+    loop {
+        let timeout = consensus.borrow().proposer_timeout;
+        tokio::time::sleep(timeout / 4).await;
+        pull::request(timeout / 2, &ctx).await;
+        let timed_out =
+            ctx.consensus
+                .lock()
+                .await
+                .timed_out(&ctx.local_id, Instant::now(), timeout);
+        if timed_out {
+            skip(&ctx).await;
+        }
+    }
+}
+
+/// Put a block that is not full forward once its oldest action waited
+/// `batch_interval`, or at every tick in ticked rounds, until the task is
+/// aborted
+///
+/// Full blocks go out as soon as they fill up; without an interval every
+/// action is proposed as it comes.
+pub(super) async fn batch(mut consensus: watch::Receiver<ConsensusConfig>, ctx: PeerContext) {
+    loop {
+        let interval = {
+            let config = consensus.borrow();
+            config.tick_interval().unwrap_or(config.batch_interval)
+        };
+        if interval.is_zero() {
+            if consensus.changed().await.is_err() {
+                return;
+            }
+            continue;
+        }
+        let deadline = ctx.consensus.lock().await.batch_deadline();
+        match deadline.filter(|deadline| *deadline > Instant::now()) {
+            Some(deadline) => tokio::time::sleep_until(deadline).await,
+            None => tokio::time::sleep(interval).await,
+        }
+        propose(&ctx).await;
+    }
+}
@@ -0,0 +1,299 @@
+// node/sequence.rs - Committing approved actions in one order on every node,
+// and applying them speculatively before that
+
+use super::peers::{self, PeerContext};
+use super::{
+    ConsensusConfig, NodeEvent, checkpoint, evidence, memory, ordering, rotation, snapshot, tick,
+};
+use crate::consensus::{ActionId, Commit, Outcome, SignedAction};
+use crate::crypto::{PlayerId, short_id};
+use crate::error::Result;
+use crate::network::trace::TraceContext;
+use crate::network::{GossipPayload, PeerMessage};
+use tokio::sync::watch;
+use tokio::time::Instant;
+
+/// Act on the votes on `action_id`, an action or a block, once they settle
+/// it: if a quorum approved it and we are the sequencer, number it, apply
+/// the commits here and gossip them; if they rejected it, roll back its
+/// speculation, and that of the actions of blocks built on it, which go back
+/// to be proposed again
+///
+/// Either way, conflicting actions held back behind it may be committed, and
+/// with a block settled the next may be proposed.
+pub(super) async fn settle(action_id: ActionId, trace: Option<TraceContext>, ctx: &PeerContext) {
+    let commits = {
+        let mut consensus = ctx.consensus.lock().await;
+        if consensus.tally(&action_id).outcome() == Outcome::Rejected {
+            let rejected = consensus
+                .block(&action_id)
+                .map_or_else(|| vec![action_id], <[ActionId]>::to_vec);
+            let games: Vec<(String, ActionId)> = consensus
+                .pending()
+                .iter()
+                .filter(|action| rejected.contains(&action.id()))
+                .map(|action| (action.game_id.clone(), action.id()))
+                .collect();
+            let mut state_manager = ctx.state_manager.lock().await;
+            for (game_id, rejected) in games {
+                let reverted = state_manager.reject(&game_id, &rejected);
+                revert(reverted, ctx);
+            }
+        }
+        consensus.sequence(&action_id, &ctx.keypair, Instant::now())
+    };
+    roll_back(ctx).await;
+    for commit in commits {
+        let committed = commit.action.id();
+        tracing::debug!(
+            "Committing {} at sequence {}",
+            short_id(&committed),
+            commit.sequence
+        );
+        if let Err(e) = receive(commit.clone(), ctx).await {
+            tracing::warn!("Could not apply our own commit: {}", e);
+            return;
+        }
+        let trace = trace.filter(|_| committed == action_id);
+        peers::publish(GossipPayload::Commit(commit), trace, ctx).await;
+    }
+    rotation::propose(ctx).await;
+}
+
+/// Roll back the speculation on actions rejected for conflicting with
+/// others in their round, and on those of blocks discarded behind a failed
+/// one
+pub(super) async fn roll_back(ctx: &PeerContext) {
+    let revoked = ctx.consensus.lock().await.take_revoked();
+    if revoked.is_empty() {
+        return;
+    }
+    let mut state_manager = ctx.state_manager.lock().await;
+    for action in revoked {
+        let reverted = state_manager.reject(&action.game_id, &action.id());
+        revert(reverted, ctx);
+    }
+}
+
+/// Apply a proposed action ahead of its commit, when optimistic execution
+/// is on and not too far ahead already
+pub(super) async fn speculate(action: &SignedAction, ctx: &PeerContext) {
+    let action_id = action.id();
+    let mut state_manager = ctx.state_manager.lock().await;
+    if state_manager.speculate(&action.game_id, &action_id) {
+        let _ = ctx.events.send(NodeEvent::ActionSpeculated {
+            game_id: action.game_id.clone(),
+            action_id,
+            actor: action.actor,
+        });
+    }
+}
+
+/// Tell the game which speculated actions were rolled back
+pub(super) fn revert(reverted: Vec<ActionId>, ctx: &PeerContext) {
+    for action_id in reverted {
+        tracing::debug!("Speculation on {} reverted", short_id(&action_id));
+        let _ = ctx
+            .events
+            .send(NodeEvent::SpeculationReverted { action_id });
+    }
+}
+
+/// Take in a commit, applying it and any it unblocks to the action log in
+/// sequence order
+///
+/// The consensus lock is held while applying, so commits delivered to two
+/// connections at once still reach the log, and the event channel, in order.
+pub(super) async fn receive(commit: Commit, ctx: &PeerContext) -> Result<()> {
+    {
+        let mut consensus = ctx.consensus.lock().await;
+        let delivered = consensus.receive_commit(commit, Instant::now())?;
+        deliver(delivered, ctx).await?;
+    }
+    tick::deliver(ctx).await;
+    ordering::release(ctx).await;
+    checkpoint::publish(ctx).await;
+    reweigh(ctx).await;
+    rotation::propose(ctx).await;
+    Ok(())
+}
+
+/// Take up the validators' new weights once a committed weight change
+/// reweighed them, as consensus already has
+pub(super) async fn reweigh(ctx: &PeerContext) {
+    let reweighed = ctx.consensus.lock().await.take_reweighed();
+    if let Some(validators) = reweighed {
+        ctx.state.write().await.validators = validators;
+    }
+}
+
+/// Apply commits consensus delivered to the action log, in order, and tell
+/// the game; the caller holds the consensus lock
+///
+/// The log is signed at every `checkpoint_interval`th commit, for the
+/// caller to [publish](checkpoint::publish) once the lock is released;
+/// snapshots falling due are encoded in the background.
+pub(super) async fn deliver(delivered: Vec<Commit>, ctx: &PeerContext) -> Result<()> {
+    if delivered.is_empty() {
+        return Ok(());
+    }
+    let interval = ctx.consensus_config.borrow().checkpoint_interval;
+    let mut state_manager = ctx.state_manager.lock().await;
+    let mut games = Vec::new();
+    for commit in delivered {
+        let round_randomness = commit.randomness();
+        let reverted = state_manager.apply_commit(&commit)?;
+        let action = commit.action;
+        let action_id = action.id();
+        revert(reverted, ctx);
+        if !games.contains(&action.game_id) {
+            games.push(action.game_id.clone());
+        }
+        if let Some(log) = state_manager.log(&action.game_id) {
+            ctx.metrics
+                .action_log_length
+                .set(log.entries().len() as u64);
+        }
+        if interval > 0 && commit.sequence.is_multiple_of(interval) {
+            state_manager.sign_checkpoint(&action.game_id, &ctx.keypair)?;
+        }
+        let _ = ctx.events.send(NodeEvent::ActionCommitted {
+            game_id: action.game_id,
+            sequence: commit.sequence,
+            action_id,
+            actor: action.actor,
+            round_randomness,
+        });
+        if action.actor == ctx.local_id {
+            let _ = ctx.events.send(NodeEvent::ActionReceipt {
+                action_id,
+                sequence: commit.sequence,
+            });
+        }
+    }
+    for game_id in &games {
+        state_manager.publish(game_id);
+    }
+    snapshot::encode(state_manager.take_due_snapshots(), ctx);
+    drop(state_manager);
+    memory::enforce(ctx).await;
+    Ok(())
+}
+
+/// Answer a peer filling a gap with the commits we still have
+pub(super) async fn on_fetch(
+    peer: PlayerId,
+    game_id: String,
+    from: u64,
+    count: u32,
+    ctx: &PeerContext,
+) {
+    let commits = ctx.consensus.lock().await.commits(&game_id, from, count);
+    if commits.is_empty() {
+        return;
+    }
+    let state = ctx.state.read().await;
+    peers::send_to(&state, &[peer], PeerMessage::Commits(commits));
+}
+
+/// Take in fetched commits, penalizing a peer that sends bad ones
+pub(super) async fn on_commits(peer: PlayerId, commits: Vec<Commit>, ctx: &PeerContext) {
+    for commit in commits {
+        if let Err(e) = receive(commit, ctx).await {
+            tracing::debug!("Bad commit from {}: {}", short_id(&peer), e);
+            if let Some(offense) = peers::offense(&e) {
+                peers::penalize(peer, offense, ctx).await;
+            }
+            break;
+        }
+    }
+    evidence::publish(ctx).await;
+}
+
+/// Number the actions and blocks held for the rest of their votes once
+/// `fast_path_timeout` has passed without them, until the task is aborted
+///
+/// Holds that end with the last vote are settled as that vote comes in.
+/// With nothing held, the task sleeps until something is.
+pub(super) async fn release(mut consensus: watch::Receiver<ConsensusConfig>, ctx: PeerContext) {
+    let holding = ctx.consensus.lock().await.holding();
+    loop {
+        let timeout = consensus.borrow().fast_path_timeout;
+        if timeout.is_zero() {
+            if consensus.changed().await.is_err() {
+                return;
+            }
+            continue;
+        }
+        let until = ctx.consensus.lock().await.held_until();
+        match until {
+            // Already past due if the wait ended while we were settling
+            Some(until) => tokio::time::sleep_until(until).await,
+            None => {
+                tokio::select! {
+                    _ = holding.notified() => {}
+                    changed = consensus.changed() => {
+                        if changed.is_err() {
+                            return;
+                        }
+                    }
+                }
+                continue;
+            }
+        }
+        let expired = ctx.consensus.lock().await.release_holds(Instant::now());
+        for action_id in expired {
+            settle(action_id, None, &ctx).await;
+        }
+    }
+}
+
+/// Fetch commits missing for `gap_timeout`, until the task is aborted; on
+/// the way, validators yet to vote on a block `consensus_timeout` after its
+/// proposal are counted as silent
+///
+/// The sequencer is asked if we are connected to it, any other peer if not.
+pub(super) async fn watch(consensus: watch::Receiver<ConsensusConfig>, ctx: PeerContext) {
+    loop {
+        let (timeout, vote_timeout) = {
+            let config = consensus.borrow();
+            (config.gap_timeout, config.consensus_timeout)
+        };
+        tokio::time::sleep(timeout / 2).await;
+        let (gaps, sequencer) = {
+            let mut consensus = ctx.consensus.lock().await;
+            let now = Instant::now();
+            consensus.expire_votes(now, vote_timeout);
+            (consensus.gaps(now, timeout), consensus.sequencer())
+        };
+        if gaps.is_empty() {
+            continue;
+        }
+        let state = ctx.state.read().await;
+        // Catching up fetches what we miss anyway
+        if state.catching_up {
+            continue;
+        }
+        let target = sequencer
+            .filter(|sequencer| state.connected_peers.contains(sequencer))
+            .or_else(|| state.connected_peers.first().copied());
+        let Some(target) = target else {
+            continue;
+        };
+        for (game_id, from, count) in gaps {
+            tracing::debug!(
+                "Commits {}..{} of {} missing, asking {}",
+                from,
+                from + count as u64,
+                game_id,
+                short_id(&target)
+            );
+            let fetch = PeerMessage::FetchCommits {
+                game_id,
+                from,
+                count,
+            };
+            peers::send_to(&state, &[target], fetch);
+        }
+    }
+}
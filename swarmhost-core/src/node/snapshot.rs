@@ -0,0 +1,131 @@
+// node/snapshot.rs - Encoding the snapshots taken every `snapshot_interval`
+// commits without holding up the commits, and pruning the log behind them
+
+use super::peers::{self, PeerContext};
+use super::{NodeEvent, memory};
+use crate::error::Result;
+use crate::network::PeerMessage;
+use crate::state::SnapshotDue;
+
+/// Encode each snapshot that fell due on a blocking task of its own, then
+/// store it, announce it, and prune the log behind it; the commits that
+/// made them due go on meanwhile
+pub(super) fn encode(due: Vec<SnapshotDue>, ctx: &PeerContext) {
+    for due in due {
+        let ctx = ctx.clone();
+        tokio::spawn(async move {
+            let encoded = match tokio::task::spawn_blocking(move || due.encode()).await {
+                Ok(encoded) => encoded,
+                Err(e) => {
+                    tracing::warn!("Could not encode a snapshot: {}", e);
+                    return;
+                }
+            };
+            let snapshot = &encoded.snapshot;
+            let (game_id, sequence, size) = (
+                snapshot.game_id.clone(),
+                snapshot.sequence,
+                snapshot.data.len(),
+            );
+            let interval = ctx.consensus_config.borrow().checkpoint_interval;
+            let stored = ctx
+                .state_manager
+                .lock()
+                .await
+                .store_snapshot(encoded, interval);
+            match stored {
+                Ok(true) => {
+                    tracing::debug!("Snapshot of {} at {}: {} bytes", game_id, sequence, size);
+                    let _ = ctx.events.send(NodeEvent::SnapshotCreated {
+                        game_id: game_id.clone(),
+                        sequence,
+                        size,
+                    });
+                    announce(&game_id, sequence, &ctx).await;
+                    settle(&game_id, &ctx).await;
+                    memory::enforce(&ctx).await;
+                }
+                Ok(false) => {
+                    tracing::debug!("Snapshot of {} at {} rewound meanwhile", game_id, sequence)
+                }
+                Err(e) => tracing::warn!("Could not store snapshot of {}: {}", game_id, e),
+            }
+        });
+    }
+}
+
+/// Tell the peers we hold a game's commits up to a snapshot just stored,
+/// so that they may prune their logs up to it
+async fn announce(game_id: &str, sequence: u64, ctx: &PeerContext) {
+    let state = ctx.state.read().await;
+    if state.current_game.as_deref() != Some(game_id) {
+        return;
+    }
+    let playing = PeerMessage::Playing {
+        game_id: Some(game_id.to_string()),
+        committed: sequence,
+    };
+    peers::send_to(&state, &state.connected_peers, playing);
+}
+
+/// Prune a game's log behind its snapshots and checkpoint once it outgrows
+/// `max_action_log_size`, returning the entries pruned
+///
+/// Unless `force`d, entries the slowest connected peer playing the game
+/// has not shown us it holds are kept, so that a fork found against it can
+/// still be rolled back.
+pub(super) async fn prune(game_id: &str, force: bool, ctx: &PeerContext) -> Result<usize> {
+    let floor = if force {
+        u64::MAX
+    } else {
+        peers::synced_floor(&*ctx.state.read().await, game_id)
+    };
+    let mut state_manager = ctx.state_manager.lock().await;
+    let pruned = state_manager.prune(game_id, floor)?;
+    if pruned > 0 {
+        tracing::debug!("Pruned {} entries of the log of {}", pruned, game_id);
+        ctx.metrics.log_entries_pruned.add(pruned as u64);
+        if let Some(log) = state_manager.log(game_id) {
+            ctx.metrics
+                .action_log_length
+                .set(log.entries().len() as u64);
+        }
+    }
+    Ok(pruned)
+}
+
+/// [Attest](crate::state::StateManager::attest) a game's snapshot at its checkpoint as
+/// a validator, and [prune](prune) its log, once something new lets us, as
+/// a stored snapshot or a checkpoint does, warning of a failure
+pub(super) async fn settle(game_id: &str, ctx: &PeerContext) {
+    if let Err(e) = attest(game_id, ctx).await {
+        tracing::warn!("Could not attest the snapshot of {}: {}", game_id, e);
+    }
+    if let Err(e) = prune(game_id, false, ctx).await {
+        tracing::warn!("Could not prune the log of {}: {}", game_id, e);
+    }
+}
+
+/// Sign the snapshot we hold at a game's latest checkpoint, if we were a
+/// validator there; a producer that was not would not be trusted
+async fn attest(game_id: &str, ctx: &PeerContext) -> Result<()> {
+    let consensus = ctx.consensus.lock().await;
+    let mut state_manager = ctx.state_manager.lock().await;
+    let Some(checkpoint) = state_manager.latest_checkpoint(game_id) else {
+        return Ok(());
+    };
+    if !consensus
+        .validators_at(game_id, checkpoint.sequence)
+        .contains(&ctx.local_id)
+    {
+        return Ok(());
+    }
+    if let Some(attestation) = state_manager.attest(game_id, &ctx.keypair)? {
+        tracing::debug!(
+            "Attested the snapshot of {} at {}",
+            game_id,
+            attestation.checkpoint.sequence
+        );
+    }
+    Ok(())
+}
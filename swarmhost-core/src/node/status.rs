@@ -0,0 +1,100 @@
+// node/status.rs - Point-in-time node status report
+
+use crate::consensus::{ActionId, ObserverTally, Tally};
+use crate::crypto::PlayerId;
+use crate::network::MappingMethod;
+use std::collections::HashMap;
+use std::net::SocketAddr;
+
+/// Snapshot of a node's runtime status
+#[derive(Debug, Clone, Default)]
+pub struct NodeStatus {
+    /// Whether the node has been started
+    pub is_running: bool,
+
+    /// Number of connected peers
+    pub peer_count: usize,
+
+    /// Addresses the node is listening on
+    pub local_addrs: Vec<SocketAddr>,
+
+    /// Bootstrap server currently in use, if one has answered
+    pub active_bootstrap: Option<String>,
+
+    /// Address advertised to peers (manual override, mapped on the router or
+    /// detected via STUN)
+    pub advertised_addr: Option<SocketAddr>,
+
+    /// Whether the router forwards the listen port to us
+    pub nat_status: NatStatus,
+
+    /// Too few validators are reachable to reach a quorum
+    pub degraded: bool,
+
+    /// Actions taken in and not yet decided
+    pub pending_actions: usize,
+
+    /// Local actions held while degraded
+    pub held_actions: usize,
+
+    /// `max_pending_actions` are pending, or the proposer answered busy,
+    /// so local submits wait
+    pub overloaded: bool,
+}
+
+/// Outcome of asking the router to forward the listen port
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum NatStatus {
+    /// Port mapping is off, or the node is stopped
+    #[default]
+    Disabled,
+    /// Waiting for the router to answer
+    Pending,
+    /// The router forwards `external` to our listen port
+    Mapped {
+        external: SocketAddr,
+        method: MappingMethod,
+    },
+    /// No router mapped the port; STUN and relays are relied on instead
+    Failed { reason: String },
+}
+
+/// Snapshot of the node's consensus session
+#[derive(Debug, Clone, Default)]
+pub struct ConsensusInfo {
+    /// Current consensus round
+    pub round: u64,
+
+    /// Validator whose turn it is to propose; none without validators
+    pub proposer: Option<PlayerId>,
+
+    /// View in progress; its leader numbers committed actions
+    pub view: u64,
+
+    /// Actions accepted but not yet decided
+    pub pending: usize,
+
+    /// Local actions held until a quorum is reachable again
+    pub queued: usize,
+
+    /// Weight of the validators of the current game reachable from here,
+    /// counting us
+    pub reachable: u64,
+
+    /// Validator weight needed for a quorum
+    pub required: u64,
+
+    /// Too few validators are reachable to reach a quorum
+    pub degraded: bool,
+
+    /// The game's log forked and neither branch alone was certified; no
+    /// more commits are applied until the game is left
+    pub halted: bool,
+
+    /// Votes so far on each pending or voted-on action
+    pub tallies: HashMap<ActionId, Tally>,
+
+    /// Non-validators' verdicts on each recent action or block they voted
+    /// on, which never count toward the quorum
+    pub observer_approval: HashMap<ActionId, ObserverTally>,
+}
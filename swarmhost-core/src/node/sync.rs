@@ -0,0 +1,557 @@
+// node/sync.rs - Catching up on a game joined in progress: the newest
+// snapshot the peers playing it have, or a delta to it from ours, fetched in
+// chunks from all of those holding it, then the certified commits since,
+// before voting
+
+use super::peers::{self, PeerContext};
+use super::{NodeEvent, checkpoint, ordering, sequence};
+use crate::consensus::CertifiedCommits;
+use crate::consensus::sequence::MAX_FETCH;
+use crate::crypto::{Hash, PlayerId, short_id};
+use crate::error::{Result, SwarmhostError};
+use crate::network::{PeerMessage, RemoteError};
+use crate::state::{Snapshot, SnapshotBase, SnapshotChunk, StateDelta, SyncPlan, apply_delta};
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use tokio::sync::oneshot;
+use tokio::task::JoinSet;
+use tokio::time::Instant;
+
+/// A peer's answer to one of our catch-up fetches
+pub(super) enum Answer {
+    Plan(SyncPlan),
+    Chunk(Option<SnapshotChunk>),
+    Commits(CertifiedCommits),
+    StateHash(Option<Hash>),
+    /// The peer could not answer, and said why
+    Failed(RemoteError),
+}
+
+/// Chunks of a snapshot fetched and checked against its plan, kept when a
+/// catch-up fails so that the next one of the same snapshot resumes there
+pub(super) struct Fetched {
+    plan: SyncPlan,
+    pub(super) chunks: BTreeMap<u32, Vec<u8>>,
+}
+
+/// Start catching up when a peer playing our game has delivered more
+/// commits than filling gaps would fetch, or, recovering from a crash, as
+/// many as we have, to learn what became of what we had in flight
+///
+/// Only one catch-up runs at a time; the node does not vote until it ends.
+pub(super) async fn on_playing(
+    peer: PlayerId,
+    game_id: Option<String>,
+    committed: u64,
+    ctx: &PeerContext,
+) {
+    let Some(game_id) = game_id else {
+        return;
+    };
+    let ours = ctx.consensus.lock().await.committed(&game_id);
+    let mut state = ctx.state.write().await;
+    let behind = if state.recovering {
+        committed >= ours
+    } else {
+        committed > ours + MAX_FETCH as u64
+    };
+    if !behind || state.current_game.as_deref() != Some(game_id.as_str()) || state.catching_up {
+        return;
+    }
+    tracing::info!(
+        "{} is at {} in {}, catching up from {}",
+        short_id(&peer),
+        committed,
+        game_id,
+        ours
+    );
+    state.catching_up = true;
+    let task = tokio::spawn(catch_up(peer, game_id, committed, ctx.clone()));
+    state.tasks.push(task);
+}
+
+/// Catch up on `game_id` from `peer`, which has delivered up to `head`,
+/// then declare the session ready
+async fn catch_up(peer: PlayerId, game_id: String, head: u64, ctx: PeerContext) {
+    let synced = sync(peer, &game_id, head, &ctx).await;
+    let mut state = ctx.state.write().await;
+    state.catching_up = false;
+    match synced {
+        Ok(sequence) => {
+            tracing::info!("Caught up on {} at {}", game_id, sequence);
+            state.recovering = false;
+            let _ = ctx
+                .events
+                .send(NodeEvent::SessionReady { game_id, sequence });
+        }
+        Err(e) => {
+            tracing::warn!(
+                "Catching up on {} from {} failed: {}",
+                game_id,
+                short_id(&peer),
+                e
+            );
+            drop(state);
+            if let Some(offense) = peers::offense(&e) {
+                peers::penalize(peer, offense, &ctx).await;
+            }
+        }
+    }
+}
+
+/// Restore the newest snapshot the peers playing the game hold if it is
+/// ahead of us, then apply the commits after it up to `head` from `peer`,
+/// returning where we got to
+///
+/// A snapshot taken at a checkpoint must be attested by a validator and
+/// hold the log the checkpoint's signers signed; it holds us to the
+/// checkpoint from then on, and is kept with its attestation to serve on. Holding a
+/// snapshot of our own, we ask for a delta from it and take one if offered.
+async fn sync(peer: PlayerId, game_id: &str, head: u64, ctx: &PeerContext) -> Result<u64> {
+    progress(game_id, head, ctx).await;
+    let base = ctx
+        .state_manager
+        .lock()
+        .await
+        .latest_snapshot(game_id)?
+        .filter(|snapshot| snapshot.sequence > 0)
+        .map(|snapshot| SnapshotBase::of(&snapshot));
+    let (plan, providers) = plan(peer, game_id, base, ctx).await?;
+    if plan.sequence > ctx.consensus.lock().await.committed(game_id) {
+        let snapshot = snapshot(&plan, providers, ctx).await?;
+        let mut consensus = ctx.consensus.lock().await;
+        if snapshot.sequence > consensus.committed(game_id) {
+            {
+                let mut state_manager = ctx.state_manager.lock().await;
+                match &plan.attestation {
+                    Some(attestation) => {
+                        let checkpoint = &attestation.checkpoint;
+                        attestation.verify(
+                            &snapshot,
+                            consensus.validators_at(game_id, checkpoint.sequence),
+                            consensus.required_weight_at(game_id, checkpoint.sequence),
+                        )?;
+                        state_manager.restore_checkpoint(&snapshot, checkpoint)?;
+                        state_manager.save_snapshot(&snapshot)?;
+                        state_manager.save_attestation(attestation)?;
+                        consensus.finalize(game_id, checkpoint.sequence);
+                        checkpoint::announce(checkpoint, ctx);
+                    }
+                    None => state_manager.restore(&snapshot)?,
+                }
+            }
+            let delivered = consensus.skip_to(game_id, snapshot.sequence, Instant::now());
+            sequence::deliver(delivered, ctx).await?;
+        }
+    }
+    loop {
+        let have = progress(game_id, head, ctx).await;
+        if have >= head {
+            return Ok(have);
+        }
+        let fetch = |id| PeerMessage::FetchCertified {
+            id,
+            game_id: game_id.to_string(),
+            from: have + 1,
+            count: MAX_FETCH,
+        };
+        let Answer::Commits(certified) = ask(peer, fetch, ctx).await? else {
+            return Err(unexpected(peer));
+        };
+        let mut consensus = ctx.consensus.lock().await;
+        let delivered = consensus.receive_certified(certified, Instant::now())?;
+        if delivered.is_empty() && consensus.committed(game_id) == have {
+            return Err(SwarmhostError::validation(format!(
+                "{} sent commits of {} not following on from {}",
+                short_id(&peer),
+                game_id,
+                have
+            )));
+        }
+        sequence::deliver(delivered, ctx).await?;
+        drop(consensus);
+        ordering::release(ctx).await;
+        checkpoint::publish(ctx).await;
+        sequence::reweigh(ctx).await;
+    }
+}
+
+/// Tell the game how far we got, returning the last commit delivered
+async fn progress(game_id: &str, head: u64, ctx: &PeerContext) -> u64 {
+    let have = ctx.consensus.lock().await.committed(game_id);
+    let _ = ctx.events.send(NodeEvent::SyncProgress {
+        game_id: game_id.to_string(),
+        have,
+        need: head,
+    });
+    have
+}
+
+/// Fetch the snapshot `plan` lays out from the `providers` holding it,
+/// or the delta to it from our own if the plan is of one
+///
+/// Chunks are asked of every provider at once, up to
+/// `sync_chunks_per_peer` each; one that fails to answer with the plan's
+/// chunk is asked for no more, and what it was to send is asked of the
+/// rest. Chunks already fetched towards the same plan are not asked for
+/// again.
+async fn snapshot(
+    plan: &SyncPlan,
+    mut providers: Vec<PlayerId>,
+    ctx: &PeerContext,
+) -> Result<Snapshot> {
+    let game_id = plan.game_id.as_str();
+    let mut missing: VecDeque<u32> = {
+        let mut state = ctx.state.write().await;
+        let fetched = state
+            .fetched
+            .take()
+            .filter(|fetched| fetched.plan == *plan)
+            .unwrap_or_else(|| Fetched {
+                plan: plan.clone(),
+                chunks: BTreeMap::new(),
+            });
+        let missing = (0..plan.chunks())
+            .filter(|index| !fetched.chunks.contains_key(index))
+            .collect();
+        state.fetched = Some(fetched);
+        missing
+    };
+    if missing.len() < plan.chunks() as usize {
+        tracing::info!(
+            "Resuming the snapshot of {} at {} with {} of {} chunks to go",
+            game_id,
+            plan.sequence,
+            missing.len(),
+            plan.chunks()
+        );
+    }
+
+    let per_peer = ctx.consensus_config.borrow().sync_chunks_per_peer;
+    let mut asked: HashMap<PlayerId, usize> = HashMap::new();
+    let mut fetches = JoinSet::new();
+    loop {
+        for provider in &providers {
+            let asking = asked.entry(*provider).or_default();
+            while *asking < per_peer
+                && let Some(index) = missing.pop_front()
+            {
+                *asking += 1;
+                let fetch = chunk(
+                    *provider,
+                    game_id.to_string(),
+                    plan.sequence,
+                    plan.delta_from,
+                    index,
+                    ctx.clone(),
+                );
+                fetches.spawn(fetch);
+            }
+        }
+        let Some(fetch) = fetches.join_next().await else {
+            break;
+        };
+        let (provider, index, answer) =
+            fetch.map_err(|e| SwarmhostError::Peer(format!("Snapshot fetch failed: {}", e)))?;
+        if let Some(asking) = asked.get_mut(&provider) {
+            *asking -= 1;
+        }
+        match answer {
+            Ok(Answer::Chunk(Some(chunk))) if plan.holds(&chunk) => {
+                received(chunk, ctx).await;
+            }
+            answer => {
+                if let Err(e) = answer {
+                    tracing::debug!(
+                        "{} did not send chunk {}: {}",
+                        short_id(&provider),
+                        index,
+                        e
+                    );
+                }
+                providers.retain(|held| *held != provider);
+                missing.push_front(index);
+            }
+        }
+    }
+    if !missing.is_empty() {
+        return Err(SwarmhostError::Peer(format!(
+            "No peer left to fetch the snapshot of {} at {} from, {} chunks short",
+            game_id,
+            plan.sequence,
+            missing.len()
+        )));
+    }
+
+    let Some(fetched) = ctx.state.write().await.fetched.take() else {
+        return Err(SwarmhostError::InvalidState(format!(
+            "Fetch of the snapshot of {} was dropped",
+            game_id
+        )));
+    };
+    let data = fetched.chunks.into_values().collect::<Vec<_>>().concat();
+    match plan.delta_from {
+        Some(from) => patch(plan, from, &data, ctx).await,
+        None => Ok(Snapshot::new(game_id, plan.sequence, plan.state_hash, data)),
+    }
+}
+
+/// The snapshot a delta fetched as `plan` lays out turns our own at `from`
+/// into
+async fn patch(plan: &SyncPlan, from: u64, data: &[u8], ctx: &PeerContext) -> Result<Snapshot> {
+    let delta = StateDelta::decode(data)?;
+    if delta.game_id != plan.game_id
+        || delta.from.sequence != from
+        || delta.to.sequence != plan.sequence
+        || delta.state_hash != plan.state_hash
+    {
+        return Err(SwarmhostError::validation(format!(
+            "Delta of {} from {} does not lead to the snapshot at {} planned",
+            plan.game_id, from, plan.sequence
+        )));
+    }
+    let base = ctx
+        .state_manager
+        .lock()
+        .await
+        .snapshot_at(&plan.game_id, from)?
+        .ok_or_else(|| {
+            SwarmhostError::InvalidState(format!(
+                "Snapshot of {} at {} was dropped before its delta came",
+                plan.game_id, from
+            ))
+        })?;
+    apply_delta(&base, &delta)
+}
+
+/// Ask every peer playing the game, and `peer`, for the plan of the
+/// snapshot it would hand us, as a delta from our `base` if it can,
+/// returning the newest with the peers that hold it
+///
+/// Of plans of the same snapshot, a delta is taken over the whole.
+async fn plan(
+    peer: PlayerId,
+    game_id: &str,
+    base: Option<SnapshotBase>,
+    ctx: &PeerContext,
+) -> Result<(SyncPlan, Vec<PlayerId>)> {
+    let mut candidates: Vec<PlayerId> = ctx
+        .state
+        .read()
+        .await
+        .connections
+        .iter()
+        .filter(|(_, handle)| {
+            !handle.info.reconnecting && handle.info.game.as_deref() == Some(game_id)
+        })
+        .map(|(candidate, _)| *candidate)
+        .collect();
+    if !candidates.contains(&peer) {
+        candidates.push(peer);
+    }
+    let mut asks = JoinSet::new();
+    for candidate in candidates {
+        let (game_id, ctx) = (game_id.to_string(), ctx.clone());
+        asks.spawn(async move {
+            let fetch = |id| PeerMessage::FetchSyncPlan { id, game_id, base };
+            (candidate, ask(candidate, fetch, &ctx).await)
+        });
+    }
+    let mut plans: Vec<(SyncPlan, Vec<PlayerId>)> = Vec::new();
+    while let Some(asked) = asks.join_next().await {
+        let Ok((candidate, Ok(Answer::Plan(plan)))) = asked else {
+            continue;
+        };
+        let ours = plan
+            .delta_from
+            .is_none_or(|from| Some(from) == base.map(|base| base.sequence));
+        if plan.game_id != game_id || !ours {
+            continue;
+        }
+        match plans.iter_mut().find(|(held, _)| *held == plan) {
+            Some((_, holders)) => holders.push(candidate),
+            None => plans.push((plan, vec![candidate])),
+        }
+    }
+    plans
+        .into_iter()
+        .max_by_key(|(plan, holders)| (plan.sequence, plan.delta_from.is_some(), holders.len()))
+        .ok_or_else(|| {
+            SwarmhostError::Peer(format!(
+                "No peer playing {} sent a plan to sync by",
+                game_id
+            ))
+        })
+}
+
+/// Ask `provider` for chunk `index` of the snapshot of `game_id` at
+/// `sequence`, or of the delta to it from `delta_from`, answering with who
+/// was asked for what
+async fn chunk(
+    provider: PlayerId,
+    game_id: String,
+    sequence: u64,
+    delta_from: Option<u64>,
+    index: u32,
+    ctx: PeerContext,
+) -> (PlayerId, u32, Result<Answer>) {
+    let fetch = |id| PeerMessage::FetchSnapshot {
+        id,
+        game_id,
+        sequence,
+        delta_from,
+        chunk: index,
+    };
+    (provider, index, ask(provider, fetch, &ctx).await)
+}
+
+/// Keep a chunk checked against the plan, and tell the game how much of
+/// the snapshot is in
+async fn received(chunk: SnapshotChunk, ctx: &PeerContext) {
+    let mut state = ctx.state.write().await;
+    let Some(fetched) = state.fetched.as_mut() else {
+        return;
+    };
+    fetched.chunks.insert(chunk.index, chunk.data);
+    let received = fetched.chunks.values().map(|data| data.len() as u64).sum();
+    let plan = &fetched.plan;
+    let _ = ctx.events.send(NodeEvent::SnapshotProgress {
+        game_id: plan.game_id.clone(),
+        sequence: plan.sequence,
+        received,
+        size: plan.size,
+    });
+}
+
+/// Send the fetch `message` builds under a fresh id, and wait up to
+/// `sync_timeout` for the answer
+pub(super) async fn ask(
+    peer: PlayerId,
+    message: impl FnOnce(u64) -> PeerMessage,
+    ctx: &PeerContext,
+) -> Result<Answer> {
+    let timeout = ctx.consensus_config.borrow().sync_timeout;
+    let (tx, rx) = oneshot::channel();
+    let id = {
+        let mut state = ctx.state.write().await;
+        let id = state.next_request;
+        state.next_request += 1;
+        state.syncs.insert((peer, id), tx);
+        if peers::send_to(&state, &[peer], message(id)) == 0 {
+            state.syncs.remove(&(peer, id));
+            return Err(SwarmhostError::NotConnected { peer });
+        }
+        id
+    };
+
+    match tokio::time::timeout(timeout, rx).await {
+        Ok(Ok(Answer::Failed(error))) => Err(error.into_error(peer)),
+        Ok(Ok(answer)) => Ok(answer),
+        Ok(Err(_)) => Err(SwarmhostError::Disconnected { peer }),
+        Err(_) => {
+            ctx.state.write().await.syncs.remove(&(peer, id));
+            Err(SwarmhostError::NoAnswer {
+                peer,
+                after: timeout,
+            })
+        }
+    }
+}
+
+pub(super) fn unexpected(peer: PlayerId) -> SwarmhostError {
+    SwarmhostError::validation(format!("{} answered the wrong fetch", short_id(&peer)))
+}
+
+/// Hand an answer to the fetch waiting for it
+pub(super) async fn on_answer(peer: PlayerId, id: u64, answer: Answer, ctx: &PeerContext) {
+    match ctx.state.write().await.syncs.remove(&(peer, id)) {
+        Some(waiting) => {
+            let _ = waiting.send(answer);
+        }
+        None => tracing::debug!(
+            "Ignoring catch-up answer {} from {}: timed out or never asked",
+            id,
+            short_id(&peer)
+        ),
+    }
+}
+
+/// Answer a peer catching up with the plan of our snapshot of a game at
+/// its latest checkpoint, or our newest if we have none, as a delta from
+/// its `base` if we hold that too
+pub(super) async fn on_fetch_plan(
+    peer: PlayerId,
+    id: u64,
+    game_id: String,
+    base: Option<SnapshotBase>,
+    ctx: &PeerContext,
+) {
+    let plan = ctx.state_manager.lock().await.sync_plan(&game_id, base);
+    let plan = match plan {
+        Ok(plan) => plan,
+        Err(e) => {
+            tracing::warn!("Could not load a snapshot of {}: {}", game_id, e);
+            let failed = PeerMessage::Error {
+                id: Some(id),
+                error: RemoteError::from_error(&e),
+            };
+            peers::send_to(&*ctx.state.read().await, &[peer], failed);
+            return;
+        }
+    };
+    let state = ctx.state.read().await;
+    peers::send_to(&state, &[peer], PeerMessage::SyncPlan { id, plan });
+}
+
+/// Answer a peer catching up with a chunk of the snapshot of a game at
+/// `sequence`, or of the delta to it from `delta_from`, as the plan we sent
+/// lays it out
+pub(super) async fn on_fetch_snapshot(
+    peer: PlayerId,
+    id: u64,
+    game_id: String,
+    sequence: u64,
+    delta_from: Option<u64>,
+    chunk: u32,
+    ctx: &PeerContext,
+) {
+    let chunk = ctx
+        .state_manager
+        .lock()
+        .await
+        .sync_chunk(&game_id, sequence, delta_from, chunk);
+    let chunk = match chunk {
+        Ok(chunk) => {
+            if chunk.is_some() {
+                ctx.metrics.snapshot_chunks_served.inc();
+            }
+            chunk
+        }
+        Err(e) => {
+            tracing::warn!(
+                "Could not load the snapshot of {} at {}: {}",
+                game_id,
+                sequence,
+                e
+            );
+            None
+        }
+    };
+    let state = ctx.state.read().await;
+    peers::send_to(&state, &[peer], PeerMessage::Snapshot { id, chunk });
+}
+
+/// Answer a peer catching up with the commits we still have and the votes
+/// that certify them
+pub(super) async fn on_fetch_certified(
+    peer: PlayerId,
+    id: u64,
+    game_id: String,
+    from: u64,
+    count: u32,
+    ctx: &PeerContext,
+) {
+    peers::synced(peer, &game_id, from.saturating_sub(1), ctx).await;
+    let commits = ctx.consensus.lock().await.certified(&game_id, from, count);
+    let state = ctx.state.read().await;
+    peers::send_to(&state, &[peer], PeerMessage::Certified { id, commits });
+}
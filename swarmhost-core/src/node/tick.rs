@@ -0,0 +1,37 @@
+// node/tick.rs - Keeping ticked rounds to one beat on every node, and
+// telling the game which actions each tick committed
+
+use super::NodeEvent;
+use super::peers::PeerContext;
+use crate::crypto::PlayerId;
+use tokio::time::Instant;
+
+/// When a round `proposer` put forward that reaches us now was likely
+/// proposed: half the round trip to it ago, in ticked rounds
+///
+/// The next tick is timed from there, so that every node expects it when
+/// its proposer puts it forward, however far each is from the one before.
+/// A round relayed to us by other peers is taken as having come straight
+/// from its proposer; without a round trip measured yet, it is now.
+pub(super) async fn sent_at(proposer: &PlayerId, ctx: &PeerContext) -> Instant {
+    let now = Instant::now();
+    if ctx.consensus_config.borrow().tick_interval().is_none() {
+        return now;
+    }
+    let state = ctx.state.read().await;
+    state
+        .connections
+        .get(proposer)
+        .and_then(|handle| handle.info.rtt)
+        .and_then(|rtt| now.checked_sub(rtt / 2))
+        .unwrap_or(now)
+}
+
+/// Tell the game of the ticks over whose commits are delivered, in order
+pub(super) async fn deliver(ctx: &PeerContext) {
+    let mut consensus = ctx.consensus.lock().await;
+    for (tick, actions) in consensus.take_ticks() {
+        tracing::trace!("Tick {} committed {} actions", tick, actions.len());
+        let _ = ctx.events.send(NodeEvent::TickCommitted { tick, actions });
+    }
+}
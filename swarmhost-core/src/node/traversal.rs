@@ -0,0 +1,226 @@
+// node/traversal.rs - Hole-punched connections to peers that cannot be dialed
+
+use super::peers::{self, ConnectionPath, PeerContext};
+use super::relay;
+use crate::crypto::{PlayerId, short_id};
+use crate::error::{Result, SwarmhostError};
+use crate::network::punch::{self, PunchSignal};
+use crate::network::{Connection, PeerMessage, QuicTransport, Role};
+use std::net::SocketAddr;
+use tokio::sync::oneshot;
+
+/// Reach `target` through a UDP hole punched with the help of `via`, a peer
+/// both of us are connected to
+///
+/// We send an offer with our external address through `via`, wait up to
+/// `nat.punch_window` for the answer, then probe and dial QUIC over the
+/// punched path as the initiator. Refused with an `outbound_proxy` set,
+/// since the punched path would go around it.
+pub(super) async fn connect_punched(
+    target: PlayerId,
+    via: PlayerId,
+    ctx: &PeerContext,
+) -> Result<PlayerId> {
+    let config = ctx.network.borrow().clone();
+    if config.outbound_proxy.is_some() {
+        return Err(bypasses_proxy());
+    }
+    let socket = punch::bind(config.bind_addr).await?;
+    let addr = punch::external_addr(&socket, &config.nat).await?;
+    let nonce = rand::random();
+
+    let (answered, answer) = oneshot::channel();
+    {
+        let mut state = ctx.state.write().await;
+        state.punches.insert(nonce, answered);
+        let offer = PeerMessage::Signal {
+            to: target,
+            signal: PunchSignal::Offer { nonce, addr },
+        };
+        if peers::send_to(&state, &[via], offer) == 0 {
+            state.punches.remove(&nonce);
+            return Err(SwarmhostError::NotConnected { peer: via });
+        }
+    }
+
+    let answer = tokio::time::timeout(config.nat.punch_window, answer).await;
+    ctx.state.write().await.punches.remove(&nonce);
+    let peer_addr = match answer {
+        Ok(Ok(PunchSignal::Answer { addr, .. })) => addr,
+        Ok(_) => {
+            return Err(SwarmhostError::Peer(format!(
+                "{} cannot reach {}",
+                short_id(&via),
+                short_id(&target)
+            )));
+        }
+        Err(_) => {
+            return Err(SwarmhostError::timeout(format!(
+                "No answer from {} via {}",
+                short_id(&target),
+                short_id(&via)
+            )));
+        }
+    };
+
+    let observed = punch::punch(&socket, peer_addr, nonce, &config.nat).await?;
+    let quic = QuicTransport::new(&config, &ctx.keypair)?;
+    let conn = quic.connect_from(socket.into_std()?, observed).await?;
+    peers::open(
+        Box::new(conn),
+        Role::Initiator,
+        Some(target),
+        ConnectionPath::HolePunched,
+        ctx,
+    )
+    .await
+}
+
+/// Reach a peer we could not dial: punch a hole if any connected peer can
+/// introduce us, otherwise fall back to a relay
+pub(super) async fn connect_indirect(target: PlayerId, ctx: &PeerContext) -> Result<PlayerId> {
+    let punched = match connect_any_punched(target, ctx).await {
+        Ok(peer) => return Ok(peer),
+        Err(e) => e,
+    };
+    relay::connect_any_relay(target, ctx).await.map_err(|e| {
+        SwarmhostError::Peer(format!(
+            "hole punching failed ({}), relaying failed ({})",
+            punched, e
+        ))
+    })
+}
+
+/// Try each connected peer as the go-between for a hole punch to `target`
+/// until one works
+async fn connect_any_punched(target: PlayerId, ctx: &PeerContext) -> Result<PlayerId> {
+    {
+        let config = ctx.network.borrow();
+        if !config.nat.hole_punching {
+            return Err(SwarmhostError::Peer(
+                "Hole punching is disabled".to_string(),
+            ));
+        }
+        if config.outbound_proxy.is_some() {
+            return Err(bypasses_proxy());
+        }
+    }
+
+    let candidates: Vec<_> = ctx
+        .state
+        .read()
+        .await
+        .connected_peers
+        .iter()
+        .copied()
+        .filter(|peer| *peer != target)
+        .collect();
+
+    let mut last = SwarmhostError::Peer(format!(
+        "No connected peer can introduce {}",
+        short_id(&target)
+    ));
+    for via in candidates {
+        match connect_punched(target, via, ctx).await {
+            Ok(peer) => return Ok(peer),
+            Err(e) => {
+                tracing::debug!(
+                    "Punching to {} via {} failed: {}",
+                    short_id(&target),
+                    short_id(&via),
+                    e
+                );
+                last = e;
+            }
+        }
+    }
+    Err(last)
+}
+
+/// Hole punching sends UDP straight to the peer, which an outbound proxy
+/// is there to prevent
+fn bypasses_proxy() -> SwarmhostError {
+    SwarmhostError::Peer("Hole punching would bypass outbound_proxy".to_string())
+}
+
+/// React to punch signaling that `via` passed on from `from`
+pub(super) async fn on_signal(
+    from: PlayerId,
+    signal: PunchSignal,
+    via: PlayerId,
+    ctx: &PeerContext,
+) {
+    match signal {
+        PunchSignal::Offer { nonce, addr } => {
+            let declined = {
+                let state = ctx.state.read().await;
+                let config = ctx.network.borrow();
+                !config.nat.hole_punching
+                    || config.outbound_proxy.is_some()
+                    || !state.is_running
+                    || state.connected_peers.contains(&from)
+            };
+            if declined {
+                let refusal = PeerMessage::Signal {
+                    to: from,
+                    signal: PunchSignal::Unreachable { nonce },
+                };
+                peers::send_to(&*ctx.state.read().await, &[via], refusal);
+                return;
+            }
+
+            let ctx = ctx.clone();
+            tokio::spawn(async move {
+                if let Err(e) = answer_offer(from, nonce, addr, via, &ctx).await {
+                    tracing::debug!("Punching back to {} failed: {}", short_id(&from), e);
+                }
+            });
+        }
+        PunchSignal::Answer { nonce, .. } | PunchSignal::Unreachable { nonce } => {
+            // Only offers we made are waiting; anything else is stale or forged
+            if let Some(pending) = ctx.state.write().await.punches.remove(&nonce) {
+                let _ = pending.send(signal);
+            }
+        }
+    }
+}
+
+/// Answer an offer through `via`, probe back and accept the QUIC connection
+/// the offering peer dials over the punched path
+async fn answer_offer(
+    from: PlayerId,
+    nonce: u64,
+    addr: SocketAddr,
+    via: PlayerId,
+    ctx: &PeerContext,
+) -> Result<PlayerId> {
+    let config = ctx.network.borrow().clone();
+    let socket = punch::bind(config.bind_addr).await?;
+    let ours = punch::external_addr(&socket, &config.nat).await?;
+
+    let answer = PeerMessage::Signal {
+        to: from,
+        signal: PunchSignal::Answer { nonce, addr: ours },
+    };
+    if peers::send_to(&*ctx.state.read().await, &[via], answer) == 0 {
+        return Err(SwarmhostError::NotConnected { peer: via });
+    }
+
+    punch::punch(&socket, addr, nonce, &config.nat).await?;
+    let quic = QuicTransport::new(&config, &ctx.keypair)?;
+    let conn = quic.accept_on(socket.into_std()?).await?;
+    if conn.peer_identity() != Some(from) {
+        return Err(SwarmhostError::Peer(format!(
+            "Punched connection was not from {}",
+            short_id(&from)
+        )));
+    }
+    peers::open(
+        Box::new(conn),
+        Role::Responder,
+        None,
+        ConnectionPath::HolePunched,
+        ctx,
+    )
+    .await
+}
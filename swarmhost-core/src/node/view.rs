@@ -0,0 +1,79 @@
+// node/view.rs - Handing sequencing to the next validator when rounds keep
+// failing, without losing what was prepared
+
+use super::peers::{self, PeerContext};
+use super::{recovery, sequence};
+use crate::consensus::{Commit, NewView};
+use crate::error::Result;
+use crate::network::GossipPayload;
+
+/// Ask to move to a later view if enough rounds in a row were skipped,
+/// starting it straight away if we lead it and that made a quorum
+pub(super) async fn request(ctx: &PeerContext) {
+    let change = {
+        let mut consensus = ctx.consensus.lock().await;
+        let change = consensus.view_change(&ctx.keypair);
+        if change.is_some()
+            && let Err(e) = recovery::write_ahead(&consensus, &ctx.state_manager).await
+        {
+            tracing::error!("Could not persist our safety record, not asking: {}", e);
+            return;
+        }
+        change
+    };
+    let Some(change) = change else {
+        return;
+    };
+    tracing::warn!(
+        "Rounds keep failing, asking for view {} with {} prepared actions",
+        change.view,
+        change.prepared.len()
+    );
+    peers::publish(GossipPayload::ViewChange(change), None, ctx).await;
+    lead(ctx).await;
+}
+
+/// Start the view a quorum asked for, if we lead it: apply the commits of
+/// what they prepared here, then gossip the new view
+pub(super) async fn lead(ctx: &PeerContext) {
+    let new_view = {
+        let mut consensus = ctx.consensus.lock().await;
+        let new_view = consensus.new_view(&ctx.keypair);
+        if new_view.is_some()
+            && let Err(e) = recovery::write_ahead(&consensus, &ctx.state_manager).await
+        {
+            tracing::error!("Could not persist our safety record, not leading: {}", e);
+            return;
+        }
+        new_view
+    };
+    let Some(new_view) = new_view else {
+        return;
+    };
+    tracing::info!(
+        "Starting view {}, committing {} prepared actions",
+        new_view.view,
+        new_view.commits.len()
+    );
+    if let Err(e) = apply(new_view.commits.clone(), ctx).await {
+        tracing::warn!("Could not apply our own new view: {}", e);
+    }
+    peers::publish(GossipPayload::NewView(new_view), None, ctx).await;
+    let blocks = ctx.consensus.lock().await.approved_blocks();
+    for block in blocks {
+        sequence::settle(block, None, ctx).await;
+    }
+}
+
+/// Take in the start of a view, applying its commits
+pub(super) async fn receive(new_view: NewView, ctx: &PeerContext) -> Result<()> {
+    let commits = ctx.consensus.lock().await.receive_new_view(new_view)?;
+    apply(commits, ctx).await
+}
+
+async fn apply(commits: Vec<Commit>, ctx: &PeerContext) -> Result<()> {
+    for commit in commits {
+        sequence::receive(commit, ctx).await?;
+    }
+    Ok(())
+}
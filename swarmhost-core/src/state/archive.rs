@@ -0,0 +1,423 @@
+// state/archive.rs - A game exported whole, to back it up, move it to
+// another host or attach it to a bug report
+//
+// An archive is the 8 bytes `SWARMARC`, the format version as 2 big-endian
+// bytes, then seven sections in this order: header, config, checkpoint,
+// snapshot, tail, roots and attestation, the last one missing from
+// archives of version 1. Each section is its tag as 1 byte, the length
+// of its payload as 4 big-endian bytes, the payload, which is bincode, and
+// the BLAKE2s-256 hash of the payload as its checksum.
+
+use super::{
+    ActionLog, Checkpoint, ReplayCheck, ReplayMode, ReplayOutcome, Snapshot, SnapshotAttestation,
+    replay,
+};
+use crate::consensus::{BlockHeader, Commit, Membership};
+use crate::crypto::{self, Hash, PlayerId, short_id};
+use crate::error::{Result, SwarmhostError};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::io::{Read, Write};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// First bytes of every archive
+pub const ARCHIVE_MAGIC: [u8; 8] = *b"SWARMARC";
+
+/// Newest version of the archive format written and read
+pub const ARCHIVE_VERSION: u16 = 2;
+
+const HEADER: u8 = 1;
+const CONFIG: u8 = 2;
+const CHECKPOINT: u8 = 3;
+const SNAPSHOT: u8 = 4;
+const TAIL: u8 = 5;
+const ROOTS: u8 = 6;
+const ATTESTATION: u8 = 7;
+
+/// What the archive holds and where it came from
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ArchiveHeader {
+    pub game_id: String,
+    /// Sequence of the last commit held
+    pub sequence: u64,
+    /// State hash at `sequence`
+    pub state_hash: Hash,
+    /// Export time in milliseconds since the Unix epoch
+    pub exported_at_ms: u64,
+    /// Node that exported it
+    pub exporter: PlayerId,
+}
+
+/// The exporter's settings the game was played under
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ArchiveConfig {
+    /// Validators and the weight of each one's vote
+    pub validators: Vec<(PlayerId, u64)>,
+    /// Weight of their approvals or signatures making a quorum
+    pub required: u64,
+    pub checkpoint_interval: u64,
+    pub snapshot_interval: u32,
+}
+
+impl ArchiveConfig {
+    pub fn membership(&self) -> Membership {
+        Membership::weighted(self.validators.iter().copied())
+    }
+}
+
+/// Commits after the snapshot, with the blocks certifying them
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ArchiveTail {
+    pub commits: Vec<Commit>,
+    pub blocks: Vec<BlockHeader>,
+}
+
+/// Merkle roots of the game's log at the snapshot and at the last commit
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ArchiveRoots {
+    pub snapshot: Hash,
+    pub head: Hash,
+}
+
+/// A game exported whole: its latest checkpoint, a snapshot with its
+/// attestation if it has one, and the commits after it up to the last
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GameArchive {
+    pub header: ArchiveHeader,
+    pub config: ArchiveConfig,
+    pub checkpoint: Option<Checkpoint>,
+    pub snapshot: Snapshot,
+    pub tail: ArchiveTail,
+    pub roots: ArchiveRoots,
+    /// What vouches for the snapshot, when it was taken at a checkpoint
+    pub attestation: Option<SnapshotAttestation>,
+}
+
+impl GameArchive {
+    /// An archive of `snapshot` and the commits in `tail` after it, which
+    /// must follow on from it, with its header and roots worked out from
+    /// them
+    pub fn new(
+        exporter: PlayerId,
+        config: ArchiveConfig,
+        checkpoint: Option<Checkpoint>,
+        snapshot: Snapshot,
+        attestation: Option<SnapshotAttestation>,
+        tail: ArchiveTail,
+    ) -> Result<Self> {
+        let mut log = super::decode(&snapshot)?;
+        let at_snapshot = log.merkle_root();
+        for commit in &tail.commits {
+            log.append(commit.sequence, &commit.action.id())?;
+        }
+        let exported_at_ms = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_millis() as u64)
+            .unwrap_or(0);
+        Ok(Self {
+            header: ArchiveHeader {
+                game_id: snapshot.game_id.clone(),
+                sequence: log.sequence(),
+                state_hash: log.hash(),
+                exported_at_ms,
+                exporter,
+            },
+            config,
+            checkpoint,
+            snapshot,
+            tail,
+            roots: ArchiveRoots {
+                snapshot: at_snapshot,
+                head: log.merkle_root(),
+            },
+            attestation,
+        })
+    }
+
+    /// Write the archive out, returning the bytes written
+    pub fn write_to(&self, writer: &mut impl Write) -> Result<u64> {
+        writer.write_all(&ARCHIVE_MAGIC)?;
+        writer.write_all(&ARCHIVE_VERSION.to_be_bytes())?;
+        let mut written = (ARCHIVE_MAGIC.len() + 2) as u64;
+        written += write_section(writer, HEADER, &self.header)?;
+        written += write_section(writer, CONFIG, &self.config)?;
+        written += write_section(writer, CHECKPOINT, &self.checkpoint)?;
+        written += write_section(writer, SNAPSHOT, &self.snapshot)?;
+        written += write_section(writer, TAIL, &self.tail)?;
+        written += write_section(writer, ROOTS, &self.roots)?;
+        written += write_section(writer, ATTESTATION, &self.attestation)?;
+        Ok(written)
+    }
+
+    /// Read an archive, checking every section's checksum; one written by
+    /// a newer version of the format is refused
+    pub fn read_from(reader: &mut impl Read) -> Result<Self> {
+        let mut magic = [0; 8];
+        reader.read_exact(&mut magic)?;
+        if magic != ARCHIVE_MAGIC {
+            return Err(SwarmhostError::Serialization(
+                "Not a game archive".to_string(),
+            ));
+        }
+        let mut version = [0; 2];
+        reader.read_exact(&mut version)?;
+        let version = u16::from_be_bytes(version);
+        if version > ARCHIVE_VERSION {
+            return Err(SwarmhostError::Serialization(format!(
+                "Game archive is format version {}, newer than the {} supported",
+                version, ARCHIVE_VERSION
+            )));
+        }
+        Ok(Self {
+            header: read_section(reader, HEADER, "header")?,
+            config: read_section(reader, CONFIG, "config")?,
+            checkpoint: read_section(reader, CHECKPOINT, "checkpoint")?,
+            snapshot: read_section(reader, SNAPSHOT, "snapshot")?,
+            tail: read_section(reader, TAIL, "tail")?,
+            roots: read_section(reader, ROOTS, "roots")?,
+            attestation: match version {
+                1 => None,
+                _ => read_section(reader, ATTESTATION, "attestation")?,
+            },
+        })
+    }
+
+    /// Replay the tail on top of the snapshot against `validators` and
+    /// their quorum, returning where it ends, without touching any node
+    ///
+    /// The checkpoint must verify, and the log must match it where it was
+    /// taken; the snapshot and the log at the end must match the header
+    /// and the roots. Anything else is refused as an invalid state. An
+    /// attested snapshot must [verify](SnapshotAttestation::verify) too.
+    pub fn replay(
+        &self,
+        validators: &Membership,
+        required: u64,
+        mode: ReplayMode,
+    ) -> Result<ReplayOutcome> {
+        let game_id = &self.header.game_id;
+        if self.snapshot.game_id != *game_id {
+            return Err(self.mismatch("its snapshot is of another game"));
+        }
+        if let Some(attestation) = &self.attestation {
+            attestation.verify(&self.snapshot, validators, required)?;
+        }
+        let at_snapshot = super::decode(&self.snapshot)?;
+        if at_snapshot.merkle_root() != self.roots.snapshot {
+            return Err(self.mismatch("its snapshot does not match its root"));
+        }
+        let checkpoints: Vec<Checkpoint> = self.checkpoint.iter().cloned().collect();
+        if let Some(checkpoint) = &self.checkpoint {
+            checkpoint.verify(validators, required)?;
+            if checkpoint.game_id != *game_id || checkpoint.sequence > self.header.sequence {
+                return Err(self.mismatch("its checkpoint is not of the log held"));
+            }
+            if checkpoint.sequence == self.snapshot.sequence && !matches(&at_snapshot, checkpoint) {
+                return Err(self.mismatch("its snapshot does not match its checkpoint"));
+            }
+        }
+        let check = ReplayCheck {
+            validators,
+            required,
+            blocks: &self.tail.blocks,
+            checkpoints: &checkpoints,
+            mode,
+        };
+        let outcome = replay(&self.snapshot, &self.tail.commits, &check)?;
+        if outcome.sequence != self.header.sequence
+            || outcome.state_hash != self.header.state_hash
+            || outcome.log.merkle_root() != self.roots.head
+        {
+            return Err(SwarmhostError::InvalidState(format!(
+                "Archive of {} replays to {} at {}, not {} at {}",
+                game_id,
+                short_id(&outcome.state_hash),
+                outcome.sequence,
+                short_id(&self.header.state_hash),
+                self.header.sequence
+            )));
+        }
+        Ok(outcome)
+    }
+
+    fn mismatch(&self, what: &str) -> SwarmhostError {
+        SwarmhostError::InvalidState(format!("Archive of {}: {}", self.header.game_id, what))
+    }
+}
+
+fn matches(log: &ActionLog, checkpoint: &Checkpoint) -> bool {
+    log.hash() == checkpoint.state_hash && log.merkle_root() == checkpoint.log_root
+}
+
+fn write_section(writer: &mut impl Write, tag: u8, value: &impl Serialize) -> Result<u64> {
+    let payload =
+        bincode::serialize(value).map_err(|e| SwarmhostError::Serialization(e.to_string()))?;
+    let length = u32::try_from(payload.len()).map_err(|_| {
+        SwarmhostError::Serialization(format!("Archive section {} is too large", tag))
+    })?;
+    writer.write_all(&[tag])?;
+    writer.write_all(&length.to_be_bytes())?;
+    writer.write_all(&payload)?;
+    writer.write_all(&crypto::hash(&payload))?;
+    Ok(1 + 4 + payload.len() as u64 + 32)
+}
+
+fn read_section<T: DeserializeOwned>(reader: &mut impl Read, tag: u8, what: &str) -> Result<T> {
+    let mut prefix = [0; 5];
+    reader.read_exact(&mut prefix)?;
+    if prefix[0] != tag {
+        return Err(SwarmhostError::Serialization(format!(
+            "Game archive has section {} where its {} belongs",
+            prefix[0], what
+        )));
+    }
+    let length = u32::from_be_bytes([prefix[1], prefix[2], prefix[3], prefix[4]]);
+    // Read through `take` so a forged length cannot make us allocate it
+    let mut payload = Vec::new();
+    reader.take(length.into()).read_to_end(&mut payload)?;
+    if payload.len() != length as usize {
+        return Err(SwarmhostError::Serialization(format!(
+            "Game archive ends inside its {}",
+            what
+        )));
+    }
+    let mut checksum = [0; 32];
+    reader.read_exact(&mut checksum)?;
+    if crypto::hash(&payload) != checksum {
+        return Err(SwarmhostError::Serialization(format!(
+            "Game archive's {} does not match its checksum",
+            what
+        )));
+    }
+    bincode::deserialize(&payload).map_err(|e| SwarmhostError::Serialization(e.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::consensus::SignedAction;
+    use crate::crypto::KeyPair;
+    use crate::state::CheckpointVote;
+
+    /// An archive of 15 commits from a snapshot at 10, signed by one
+    /// validator weighing a quorum on its own
+    fn archive(validator: &KeyPair) -> GameArchive {
+        let commits: Vec<Commit> = (1..=15u64)
+            .map(|sequence| {
+                let data = sequence.to_be_bytes().to_vec();
+                let action = SignedAction::new(validator, "game", sequence, 1, data);
+                Commit::new(validator, sequence, action)
+            })
+            .collect();
+        let mut log = ActionLog::new();
+        for commit in &commits[..10] {
+            log.append(commit.sequence, &commit.action.id()).unwrap();
+        }
+        let config = ArchiveConfig {
+            validators: vec![(validator.public_key(), 1)],
+            required: 1,
+            checkpoint_interval: 0,
+            snapshot_interval: 10,
+        };
+        let tail = ArchiveTail {
+            commits: commits[10..].to_vec(),
+            blocks: Vec::new(),
+        };
+        let snapshot = super::super::snapshot_of("game", &log);
+        GameArchive::new(validator.public_key(), config, None, snapshot, None, tail).unwrap()
+    }
+
+    #[test]
+    fn test_archive_round_trips_and_replays_to_its_header() {
+        let validator = KeyPair::generate();
+        let archive = archive(&validator);
+        let mut bytes = Vec::new();
+        let written = archive.write_to(&mut bytes).unwrap();
+        assert_eq!(written, bytes.len() as u64);
+        let read = GameArchive::read_from(&mut bytes.as_slice()).unwrap();
+        assert_eq!(read, archive);
+
+        let validators = read.config.membership();
+        let outcome = read.replay(&validators, 1, ReplayMode::Strict).unwrap();
+        assert_eq!(outcome.sequence, 15);
+        assert_eq!(outcome.state_hash, archive.header.state_hash);
+
+        // Against validators who never signed it, it is refused
+        let strangers = Membership::equal([KeyPair::generate().public_key()]);
+        assert!(read.replay(&strangers, 1, ReplayMode::Strict).is_err());
+    }
+
+    #[test]
+    fn test_damaged_or_newer_archives_are_refused() {
+        let validator = KeyPair::generate();
+        let mut bytes = Vec::new();
+        archive(&validator).write_to(&mut bytes).unwrap();
+
+        let mut flipped = bytes.clone();
+        let middle = flipped.len() / 2;
+        flipped[middle] ^= 1;
+        assert!(GameArchive::read_from(&mut flipped.as_slice()).is_err());
+
+        let cut = &bytes[..bytes.len() - 40];
+        assert!(GameArchive::read_from(&mut &cut[..]).is_err());
+
+        let mut newer = bytes.clone();
+        newer[8..10].copy_from_slice(&(ARCHIVE_VERSION + 1).to_be_bytes());
+        let refused = GameArchive::read_from(&mut newer.as_slice()).unwrap_err();
+        assert!(refused.to_string().contains("newer"), "{}", refused);
+    }
+
+    #[test]
+    fn test_attested_snapshot_travels_in_the_archive_and_must_verify() {
+        let validator = KeyPair::generate();
+        let validators = Membership::equal([validator.public_key()]);
+        let mut attested = archive(&validator);
+        let log = super::super::decode(&attested.snapshot).unwrap();
+        let vote = CheckpointVote::new(&validator, "game", 10, log.hash(), log.merkle_root());
+        let checkpoint = Checkpoint::from_votes(&[vote]).unwrap();
+        attested.attestation = Some(SnapshotAttestation::new(
+            &validator,
+            &attested.snapshot,
+            checkpoint.clone(),
+        ));
+        let mut bytes = Vec::new();
+        attested.write_to(&mut bytes).unwrap();
+        let read = GameArchive::read_from(&mut bytes.as_slice()).unwrap();
+        assert_eq!(read, attested);
+        assert!(read.replay(&validators, 1, ReplayMode::Strict).is_ok());
+
+        // Snapshot data changed after it was attested
+        let mut tampered = attested.clone();
+        let last = tampered.snapshot.data.len() - 1;
+        tampered.snapshot.data[last] ^= 1;
+        assert!(matches!(
+            tampered.replay(&validators, 1, ReplayMode::Strict),
+            Err(SwarmhostError::Crypto(_))
+        ));
+
+        // Attested by a node that is no validator
+        let stranger = KeyPair::generate();
+        let mut outsider = attested.clone();
+        outsider.attestation = Some(SnapshotAttestation::new(
+            &stranger,
+            &attested.snapshot,
+            checkpoint,
+        ));
+        assert!(matches!(
+            outsider.replay(&validators, 1, ReplayMode::Strict),
+            Err(SwarmhostError::Validation(_))
+        ));
+    }
+
+    #[test]
+    fn test_archive_not_matching_its_header_is_refused() {
+        let validator = KeyPair::generate();
+        let validators = Membership::equal([validator.public_key()]);
+        let mut short = archive(&validator);
+        short.tail.commits.pop();
+        assert!(short.replay(&validators, 1, ReplayMode::Strict).is_err());
+        let mut rootless = archive(&validator);
+        rootless.roots.snapshot = [0; 32];
+        assert!(rootless.replay(&validators, 1, ReplayMode::Strict).is_err());
+    }
+}
@@ -0,0 +1,175 @@
+// state/attestation.rs - What lets a snapshot taken at a checkpoint be
+// trusted wherever its bytes came from: the checkpoint's signatures, and
+// the signature of the validator that produced it
+
+use super::{Checkpoint, Snapshot};
+use crate::consensus::Membership;
+use crate::crypto::{self, Hash, KeyPair, PlayerId, short_id};
+use crate::error::{Result, SwarmhostError};
+use serde::{Deserialize, Serialize};
+
+/// A snapshot taken at a checkpoint, vouched for by the checkpoint's quorum
+/// and signed by the validator that produced it
+///
+/// It travels with the snapshot: stored beside it, sent with its sync plan
+/// and written into archives of it, so that whoever takes the snapshot up
+/// can [verify](Self::verify) the bytes against the validators alone.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SnapshotAttestation {
+    pub checkpoint: Checkpoint,
+    pub producer: PlayerId,
+    /// Producer's signature over [`SnapshotAttestation::signing_bytes`]
+    pub signature: Vec<u8>,
+}
+
+impl SnapshotAttestation {
+    /// Sign `snapshot`, taken at `checkpoint`, as its producer
+    pub fn new(keypair: &KeyPair, snapshot: &Snapshot, checkpoint: Checkpoint) -> Self {
+        Self {
+            checkpoint,
+            producer: keypair.public_key(),
+            signature: keypair.sign(&Self::signing_bytes(snapshot)),
+        }
+    }
+
+    /// Canonical bytes the producer signed for `snapshot`
+    pub fn signing_bytes(snapshot: &Snapshot) -> Vec<u8> {
+        signing_bytes(&snapshot.game_id, snapshot.sequence, &snapshot.digest())
+    }
+
+    /// Check that `snapshot` is the one attested: taken at the checkpoint,
+    /// which distinct members of `validators` weighing `required` signed,
+    /// and signed unchanged by a member of `validators`
+    ///
+    /// A snapshot of another game or sequence, or signed by anyone else,
+    /// is refused as a validation error; data changed since it was signed,
+    /// or a forged signature, as a crypto error.
+    pub fn verify(
+        &self,
+        snapshot: &Snapshot,
+        validators: &Membership,
+        required: u64,
+    ) -> Result<()> {
+        let checkpoint = &self.checkpoint;
+        let what = format!("Snapshot of {} at {}", snapshot.game_id, snapshot.sequence);
+        if checkpoint.game_id != snapshot.game_id
+            || checkpoint.sequence != snapshot.sequence
+            || checkpoint.state_hash != snapshot.state_hash
+        {
+            return Err(SwarmhostError::validation(format!(
+                "{} is attested by the checkpoint of {} at {}",
+                what, checkpoint.game_id, checkpoint.sequence
+            )));
+        }
+        checkpoint
+            .verify(validators, required)
+            .map_err(|e| match e {
+                SwarmhostError::Crypto(e) => {
+                    SwarmhostError::crypto(format!("{}: its checkpoint: {}", what, e))
+                }
+                e => SwarmhostError::validation(format!("{}: its checkpoint: {}", what, e)),
+            })?;
+        if !validators.contains(&self.producer) {
+            return Err(SwarmhostError::validation(format!(
+                "{} is signed by {}, not a validator",
+                what,
+                short_id(&self.producer)
+            )));
+        }
+        crypto::verify_signature(
+            &self.producer,
+            &Self::signing_bytes(snapshot),
+            &self.signature,
+        )
+        .map_err(|e| {
+            SwarmhostError::crypto(format!(
+                "{}: its data is not what {} signed: {}",
+                what,
+                short_id(&self.producer),
+                e
+            ))
+        })
+    }
+}
+
+fn signing_bytes(game_id: &str, sequence: u64, digest: &Hash) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(72 + game_id.len());
+    bytes.extend_from_slice(b"swarmhost-snapshot-v1");
+    bytes.extend_from_slice(&(game_id.len() as u32).to_be_bytes());
+    bytes.extend_from_slice(game_id.as_bytes());
+    bytes.extend_from_slice(&sequence.to_be_bytes());
+    bytes.extend_from_slice(digest);
+    bytes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::{ActionLog, CheckpointVote, snapshot_of};
+
+    /// A snapshot of 20 entries and the checkpoint both `validators`
+    /// signed of it
+    fn checkpointed(validators: &[KeyPair]) -> (Snapshot, Checkpoint) {
+        let mut log = ActionLog::new();
+        for sequence in 1..=20u64 {
+            log.append(sequence, &crypto::hash(&sequence.to_be_bytes()))
+                .unwrap();
+        }
+        let votes: Vec<CheckpointVote> = validators
+            .iter()
+            .map(|keypair| CheckpointVote::new(keypair, "game", 20, log.hash(), log.merkle_root()))
+            .collect();
+        let checkpoint = Checkpoint::from_votes(&votes).unwrap();
+        (snapshot_of("game", &log), checkpoint)
+    }
+
+    #[test]
+    fn test_attested_snapshot_verifies_and_tampering_is_named() {
+        let keypairs = [KeyPair::generate(), KeyPair::generate()];
+        let validators = Membership::equal(keypairs.iter().map(KeyPair::public_key));
+        let (snapshot, checkpoint) = checkpointed(&keypairs);
+        let attestation = SnapshotAttestation::new(&keypairs[0], &snapshot, checkpoint.clone());
+        assert!(attestation.verify(&snapshot, &validators, 2).is_ok());
+
+        // Data changed after it was signed
+        let mut tampered = snapshot.clone();
+        tampered.data[0] ^= 1;
+        let refused = attestation.verify(&tampered, &validators, 2).unwrap_err();
+        assert!(matches!(refused, SwarmhostError::Crypto(_)), "{}", refused);
+        assert!(refused.to_string().contains("not what"), "{}", refused);
+
+        // Signed by a node that is no validator
+        let stranger = KeyPair::generate();
+        let outsider = SnapshotAttestation::new(&stranger, &snapshot, checkpoint.clone());
+        let refused = outsider.verify(&snapshot, &validators, 2).unwrap_err();
+        assert!(
+            matches!(refused, SwarmhostError::Validation(_)),
+            "{}",
+            refused
+        );
+        assert!(
+            refused.to_string().contains("not a validator"),
+            "{}",
+            refused
+        );
+
+        // A checkpoint counting a non-validator towards its quorum
+        let (_, forged) = checkpointed(&[keypairs[0].clone(), stranger]);
+        let forged = SnapshotAttestation::new(&keypairs[0], &snapshot, forged);
+        let refused = forged.verify(&snapshot, &validators, 2).unwrap_err();
+        assert!(
+            matches!(refused, SwarmhostError::Validation(_)),
+            "{}",
+            refused
+        );
+        assert!(refused.to_string().contains("checkpoint"), "{}", refused);
+
+        // Nor does an attestation carry over to another sequence
+        let mut moved = snapshot.clone();
+        moved.sequence = 19;
+        assert!(matches!(
+            attestation.verify(&moved, &validators, 2),
+            Err(SwarmhostError::Validation(_))
+        ));
+    }
+}
@@ -0,0 +1,201 @@
+// state/checkpoint.rs - Points in a game's log a quorum of validators signed,
+// which no later view may reorder
+
+use crate::consensus::Membership;
+use crate::crypto::{self, Hash, KeyPair, PlayerId, short_id};
+use crate::error::{Result, SwarmhostError};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+
+/// A validator's signature over a game's log at a checkpoint sequence
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CheckpointVote {
+    pub game_id: String,
+    /// Sequence number of the last action in the log
+    pub sequence: u64,
+    /// Hash chained over the log
+    pub state_hash: Hash,
+    /// Merkle root over the log's entries
+    pub log_root: Hash,
+    pub signer: PlayerId,
+    /// Signer's signature over [`CheckpointVote::signing_bytes`]
+    pub signature: Vec<u8>,
+}
+
+impl CheckpointVote {
+    /// Sign a game's log as it stands at `sequence`
+    pub fn new(
+        keypair: &KeyPair,
+        game_id: impl Into<String>,
+        sequence: u64,
+        state_hash: Hash,
+        log_root: Hash,
+    ) -> Self {
+        let mut vote = Self {
+            game_id: game_id.into(),
+            sequence,
+            state_hash,
+            log_root,
+            signer: keypair.public_key(),
+            signature: Vec::new(),
+        };
+        vote.signature = keypair.sign(&vote.signing_bytes());
+        vote
+    }
+
+    /// Canonical bytes covered by the signature; the same for every signer
+    pub fn signing_bytes(&self) -> Vec<u8> {
+        signing_bytes(
+            &self.game_id,
+            self.sequence,
+            &self.state_hash,
+            &self.log_root,
+        )
+    }
+
+    /// What the vote is for, so votes for the same log can be counted
+    /// together
+    pub fn digest(&self) -> Hash {
+        crypto::hash(&self.signing_bytes())
+    }
+
+    /// Check the signature against the signer's public key
+    pub fn verify(&self) -> Result<()> {
+        crypto::verify_signature(&self.signer, &self.signing_bytes(), &self.signature)
+    }
+}
+
+/// One validator's signature in a checkpoint
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CheckpointSignature {
+    pub signer: PlayerId,
+    pub signature: Vec<u8>,
+}
+
+/// A game's log up to `sequence`, fixed by a quorum of validators' signatures
+///
+/// Anyone holding the validator set can check it with
+/// [`Checkpoint::verify`], and a log restored from a snapshot against its
+/// state hash and log root.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Checkpoint {
+    pub game_id: String,
+    pub sequence: u64,
+    pub state_hash: Hash,
+    pub log_root: Hash,
+    pub signatures: Vec<CheckpointSignature>,
+}
+
+impl Checkpoint {
+    /// Gather votes for the same log into a checkpoint; none if `votes` is
+    /// empty
+    pub fn from_votes(votes: &[CheckpointVote]) -> Option<Self> {
+        let first = votes.first()?;
+        Some(Self {
+            game_id: first.game_id.clone(),
+            sequence: first.sequence,
+            state_hash: first.state_hash,
+            log_root: first.log_root,
+            signatures: votes
+                .iter()
+                .map(|vote| CheckpointSignature {
+                    signer: vote.signer,
+                    signature: vote.signature.clone(),
+                })
+                .collect(),
+        })
+    }
+
+    /// Canonical bytes each validator signed
+    pub fn signing_bytes(&self) -> Vec<u8> {
+        signing_bytes(
+            &self.game_id,
+            self.sequence,
+            &self.state_hash,
+            &self.log_root,
+        )
+    }
+
+    /// Check that distinct members of `validators` weighing `required`
+    /// signed it
+    pub fn verify(&self, validators: &Membership, required: u64) -> Result<()> {
+        let bytes = self.signing_bytes();
+        let mut signers = HashSet::new();
+        for signed in &self.signatures {
+            if !validators.contains(&signed.signer) || !signers.insert(signed.signer) {
+                return Err(SwarmhostError::consensus(format!(
+                    "checkpoint of {} at {} signed by {}, not a validator or twice",
+                    self.game_id,
+                    self.sequence,
+                    short_id(&signed.signer)
+                )));
+            }
+            crypto::verify_signature(&signed.signer, &bytes, &signed.signature)?;
+        }
+        let signed = validators.weight(&signers);
+        if signed < required {
+            return Err(SwarmhostError::consensus(format!(
+                "checkpoint of {} at {} has signatures weighing {} of the {} it needs",
+                self.game_id, self.sequence, signed, required
+            )));
+        }
+        Ok(())
+    }
+}
+
+fn signing_bytes(game_id: &str, sequence: u64, state_hash: &Hash, log_root: &Hash) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(112 + game_id.len());
+    bytes.extend_from_slice(b"swarmhost-checkpoint-v1");
+    bytes.extend_from_slice(&(game_id.len() as u32).to_be_bytes());
+    bytes.extend_from_slice(game_id.as_bytes());
+    bytes.extend_from_slice(&sequence.to_be_bytes());
+    bytes.extend_from_slice(state_hash);
+    bytes.extend_from_slice(log_root);
+    bytes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_checkpoint_needs_a_quorum_of_distinct_validators() {
+        let validators = [
+            KeyPair::generate(),
+            KeyPair::generate(),
+            KeyPair::generate(),
+        ];
+        let ids = Membership::equal(validators.iter().map(KeyPair::public_key));
+        let votes: Vec<CheckpointVote> = validators
+            .iter()
+            .map(|keypair| CheckpointVote::new(keypair, "game", 100, [1; 32], [2; 32]))
+            .collect();
+        assert!(votes.iter().all(|vote| vote.digest() == votes[0].digest()));
+
+        let checkpoint = Checkpoint::from_votes(&votes[..2]).unwrap();
+        assert!(checkpoint.verify(&ids, 2).is_ok());
+        assert!(checkpoint.verify(&ids, 3).is_err());
+
+        // Counting one signature twice makes no quorum
+        let mut repeated = checkpoint.clone();
+        repeated.signatures[1] = repeated.signatures[0].clone();
+        assert!(repeated.verify(&ids, 2).is_err());
+
+        // Nor does a signature over another log
+        let other = CheckpointVote::new(&validators[1], "game", 100, [3; 32], [2; 32]);
+        let mut mixed = checkpoint.clone();
+        mixed.signatures[1].signature = other.signature;
+        assert!(matches!(
+            mixed.verify(&ids, 2),
+            Err(SwarmhostError::Crypto(_))
+        ));
+
+        // Weighed, the first validator's signature alone makes 3 of 4
+        let weighted = Membership::weighted(ids.ids().copied().zip([3, 1, 1]));
+        let first = weighted.ids().next().copied().unwrap();
+        let alone = votes.iter().find(|vote| vote.signer == first).unwrap();
+        let checkpoint = Checkpoint::from_votes(std::slice::from_ref(alone)).unwrap();
+        assert!(checkpoint.verify(&weighted, 3).is_ok());
+        assert!(checkpoint.verify(&weighted, 4).is_err());
+    }
+}
@@ -0,0 +1,400 @@
+// state/delta.rs - What turns one snapshot of a game into a later one, so a
+// peer holding the older need not be sent the newer whole
+
+use super::snapshot::{self, CHUNK_SIZE};
+use super::{Snapshot, SnapshotAttestation, SnapshotChunk, SyncPlan};
+use crate::crypto::{self, Hash, short_id};
+use crate::error::{Result, SwarmhostError};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// Bytes of the old snapshot's data matched at a time
+pub const DELTA_BLOCK: usize = 512;
+
+/// A snapshot held by a peer, as it names it to be sent deltas from
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SnapshotBase {
+    pub sequence: u64,
+    /// Hash of the snapshot's data
+    pub checksum: Hash,
+}
+
+impl SnapshotBase {
+    pub fn of(snapshot: &Snapshot) -> Self {
+        Self {
+            sequence: snapshot.sequence,
+            checksum: crypto::hash(&snapshot.data),
+        }
+    }
+}
+
+/// A later snapshot of a game, as the pieces of an older one's data it
+/// reuses and the bytes it adds
+///
+/// The new data is `ops` in order, each either a run of the old data or
+/// bytes of its own. Runs are found block by block: the old data is cut
+/// into [`DELTA_BLOCK`]-byte blocks, and the new data is scanned for them
+/// at any offset with a rolling checksum, so that entries shifted by a
+/// pruned log are still found. Both ends are named by the hash of their
+/// data, checked before and after applying.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StateDelta {
+    pub game_id: String,
+    pub from: SnapshotBase,
+    pub to: SnapshotBase,
+    /// State hash of the new snapshot
+    pub state_hash: Hash,
+    /// Bytes of new data
+    pub size: u64,
+    pub ops: Vec<DeltaOp>,
+}
+
+/// A piece of a delta's new data
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum DeltaOp {
+    /// `len` bytes of the old data from `offset`
+    Copy { offset: u64, len: u64 },
+    /// Bytes the old data does not have
+    Insert(Vec<u8>),
+}
+
+impl StateDelta {
+    /// Bytes of new data the delta carries itself
+    pub fn inserted(&self) -> usize {
+        self.ops
+            .iter()
+            .map(|op| match op {
+                DeltaOp::Insert(data) => data.len(),
+                DeltaOp::Copy { .. } => 0,
+            })
+            .sum()
+    }
+}
+
+impl StateDelta {
+    /// The delta a peer sent in the chunks of a plan with a `delta_from`
+    pub fn decode(data: &[u8]) -> Result<Self> {
+        bincode::deserialize(data).map_err(|e| SwarmhostError::Serialization(e.to_string()))
+    }
+}
+
+/// A delta encoded to be sent chunk by chunk to peers catching up, as a
+/// provider keeps it for the next to ask
+#[derive(Debug, Clone)]
+pub struct EncodedDelta {
+    pub game_id: String,
+    pub from: SnapshotBase,
+    /// Sequence of the snapshot it turns `from` into
+    pub sequence: u64,
+    pub state_hash: Hash,
+    pub data: Vec<u8>,
+}
+
+impl EncodedDelta {
+    pub fn of(delta: &StateDelta) -> Self {
+        Self {
+            game_id: delta.game_id.clone(),
+            from: delta.from,
+            sequence: delta.to.sequence,
+            state_hash: delta.state_hash,
+            data: bincode::serialize(delta).unwrap_or_default(),
+        }
+    }
+
+    /// Plan for a peer holding `from` to fetch the delta in place of the
+    /// snapshot, with the snapshot's attestation if it has one
+    pub fn plan(&self, attestation: Option<SnapshotAttestation>) -> SyncPlan {
+        SyncPlan {
+            game_id: self.game_id.clone(),
+            sequence: self.sequence,
+            state_hash: self.state_hash,
+            size: self.data.len() as u64,
+            chunk_size: CHUNK_SIZE as u32,
+            checksums: snapshot::checksums(&self.data),
+            attestation,
+            delta_from: Some(self.from.sequence),
+        }
+    }
+
+    /// Chunk `index` of the encoded delta; none past the last
+    pub fn chunk(&self, index: u32) -> Option<SnapshotChunk> {
+        let (offset, data) = snapshot::piece(&self.data, index)?;
+        Some(SnapshotChunk {
+            game_id: self.game_id.clone(),
+            sequence: self.sequence,
+            state_hash: self.state_hash,
+            index,
+            offset,
+            checksum: crypto::hash(&data),
+            data,
+        })
+    }
+}
+
+/// The delta turning `old` into `new`, two snapshots of the same game
+pub fn diff(old: &Snapshot, new: &Snapshot) -> StateDelta {
+    let blocks = index(&old.data);
+    let (old_data, new_data) = (&old.data, &new.data);
+    let mut ops = Vec::new();
+    let mut literal = Vec::new();
+    let mut at = 0;
+    let mut rolling = Rolling::over(&new_data[..DELTA_BLOCK.min(new_data.len())]);
+    while at + DELTA_BLOCK <= new_data.len() {
+        let window = &new_data[at..at + DELTA_BLOCK];
+        let found = blocks.get(&rolling.sum()).and_then(|starts| {
+            starts
+                .iter()
+                .find(|&&start| &old_data[start..start + DELTA_BLOCK] == window)
+        });
+        match found {
+            Some(&start) => {
+                if !literal.is_empty() {
+                    ops.push(DeltaOp::Insert(std::mem::take(&mut literal)));
+                }
+                copy(&mut ops, start as u64, DELTA_BLOCK as u64);
+                at += DELTA_BLOCK;
+                let next = &new_data[at..(at + DELTA_BLOCK).min(new_data.len())];
+                rolling = Rolling::over(next);
+            }
+            None => {
+                literal.push(new_data[at]);
+                if at + DELTA_BLOCK < new_data.len() {
+                    rolling.roll(new_data[at], new_data[at + DELTA_BLOCK]);
+                }
+                at += 1;
+            }
+        }
+    }
+    literal.extend_from_slice(&new_data[at..]);
+    if !literal.is_empty() {
+        ops.push(DeltaOp::Insert(literal));
+    }
+    StateDelta {
+        game_id: new.game_id.clone(),
+        from: SnapshotBase::of(old),
+        to: SnapshotBase::of(new),
+        state_hash: new.state_hash,
+        size: new.data.len() as u64,
+        ops,
+    }
+}
+
+/// The snapshot `delta` turns `old` into
+///
+/// An `old` that is not the snapshot the delta was taken from is refused
+/// before anything is applied, and what applying gives must be the data
+/// the delta names.
+pub fn apply_delta(old: &Snapshot, delta: &StateDelta) -> Result<Snapshot> {
+    if old.game_id != delta.game_id || SnapshotBase::of(old) != delta.from {
+        return Err(SwarmhostError::InvalidState(format!(
+            "Delta of {} is from {} at {}, not {} at {}",
+            delta.game_id,
+            short_id(&delta.from.checksum),
+            delta.from.sequence,
+            old.game_id,
+            old.sequence
+        )));
+    }
+    let mut data = Vec::with_capacity(delta.size.min(old.data.len() as u64 * 2) as usize);
+    for op in &delta.ops {
+        match op {
+            DeltaOp::Copy { offset, len } => {
+                let run = offset
+                    .checked_add(*len)
+                    .and_then(|end| old.data.get(*offset as usize..end as usize))
+                    .ok_or_else(|| {
+                        SwarmhostError::InvalidState(format!(
+                            "Delta of {} copies past the end of its base",
+                            delta.game_id
+                        ))
+                    })?;
+                data.extend_from_slice(run);
+            }
+            DeltaOp::Insert(bytes) => data.extend_from_slice(bytes),
+        }
+        if data.len() as u64 > delta.size {
+            break;
+        }
+    }
+    if data.len() as u64 != delta.size || crypto::hash(&data) != delta.to.checksum {
+        return Err(SwarmhostError::InvalidState(format!(
+            "Delta of {} to {} does not give the data it names",
+            delta.game_id, delta.to.sequence
+        )));
+    }
+    Ok(Snapshot::new(
+        delta.game_id.clone(),
+        delta.to.sequence,
+        delta.state_hash,
+        data,
+    ))
+}
+
+/// Start of every whole block of `data`, by its rolling checksum
+fn index(data: &[u8]) -> HashMap<u32, Vec<usize>> {
+    let mut blocks: HashMap<u32, Vec<usize>> = HashMap::new();
+    for (i, block) in data.chunks_exact(DELTA_BLOCK).enumerate() {
+        blocks
+            .entry(Rolling::over(block).sum())
+            .or_default()
+            .push(i * DELTA_BLOCK);
+    }
+    blocks
+}
+
+/// Add a run of old data, joined to the one before if it follows on
+fn copy(ops: &mut Vec<DeltaOp>, offset: u64, len: u64) {
+    if let Some(DeltaOp::Copy {
+        offset: last,
+        len: run,
+    }) = ops.last_mut()
+        && *last + *run == offset
+    {
+        *run += len;
+        return;
+    }
+    ops.push(DeltaOp::Copy { offset, len });
+}
+
+/// Adler-style checksum of a window, moved along a byte at a time
+struct Rolling {
+    a: u32,
+    b: u32,
+    len: u32,
+}
+
+impl Rolling {
+    fn over(window: &[u8]) -> Self {
+        let (mut a, mut b) = (0u32, 0u32);
+        for &byte in window {
+            a = a.wrapping_add(byte as u32);
+            b = b.wrapping_add(a);
+        }
+        Self {
+            a,
+            b,
+            len: window.len() as u32,
+        }
+    }
+
+    fn sum(&self) -> u32 {
+        (self.b << 16) ^ (self.a & 0xffff)
+    }
+
+    /// Drop `out` from the front of the window and take `into` on at
+    /// the back
+    fn roll(&mut self, out: u8, into: u8) {
+        self.a = self.a.wrapping_sub(out as u32).wrapping_add(into as u32);
+        self.b = self
+            .b
+            .wrapping_sub(self.len.wrapping_mul(out as u32))
+            .wrapping_add(self.a);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::ActionLog;
+
+    fn snapshot(sequence: u64, data: Vec<u8>) -> Snapshot {
+        Snapshot::new("game", sequence, crypto::hash(&data), data)
+    }
+
+    /// Bytes that do not repeat, as hashes do not
+    fn noise(len: usize, seed: u32) -> Vec<u8> {
+        (0..len.div_ceil(32) as u32)
+            .flat_map(|i| crypto::hash(&[seed.to_be_bytes(), i.to_be_bytes()].concat()))
+            .take(len)
+            .collect()
+    }
+
+    #[test]
+    fn test_applied_delta_is_byte_identical_to_its_target() {
+        let old = noise(100_000, 1);
+        let edits: Vec<Vec<u8>> = vec![
+            old.clone(),
+            [old.as_slice(), &noise(6400, 2)].concat(),
+            [&old[..30_000], &noise(77, 3), &old[30_000..]].concat(),
+            [&old[..10_000], &old[60_001..]].concat(),
+            old[1..].to_vec(),
+            noise(3000, 4),
+            Vec::new(),
+        ];
+        let base = snapshot(1, old.clone());
+        for (i, new) in edits.into_iter().enumerate() {
+            let target = snapshot(2, new);
+            let delta = diff(&base, &target);
+            let applied = apply_delta(&base, &delta).unwrap();
+            assert_eq!(applied.data, target.data, "edit {}", i);
+            assert_eq!(
+                (applied.sequence, applied.state_hash),
+                (2, target.state_hash)
+            );
+            if i < 5 {
+                // Only what changed, and a block either side, is carried
+                let changed = target.data.len().saturating_sub(old.len()).max(77);
+                assert!(delta.inserted() <= changed + 2 * DELTA_BLOCK, "edit {}", i);
+            }
+        }
+        // Nor is an empty base a problem
+        let empty = snapshot(0, Vec::new());
+        let delta = diff(&empty, &base);
+        assert_eq!(apply_delta(&empty, &delta).unwrap().data, old);
+    }
+
+    #[test]
+    fn test_delta_between_log_snapshots_stays_small_as_the_log_is_pruned() {
+        let actions: Vec<_> = (0..3200u32)
+            .map(|i| crypto::hash(&i.to_be_bytes()))
+            .collect();
+        let of = |log: &ActionLog| {
+            let data = bincode::serialize(&log.image()).unwrap();
+            Snapshot::new("game", log.sequence(), log.hash(), data)
+        };
+        let mut log = ActionLog::from_entries(&actions[..3000]).unwrap();
+        let old = of(&log);
+        for (sequence, action_id) in (3001..).zip(&actions[3000..]) {
+            log.append(sequence, action_id).unwrap();
+        }
+        let grown = diff(&old, &of(&log));
+        assert_eq!(apply_delta(&old, &grown).unwrap().data, of(&log).data);
+        assert!(grown.inserted() < 200 * 32 + 2 * DELTA_BLOCK);
+
+        log.prune(1000);
+        let pruned = diff(&old, &of(&log));
+        let applied = apply_delta(&old, &pruned).unwrap();
+        assert_eq!(applied.data, of(&log).data);
+        assert!(pruned.inserted() < 200 * 32 + 4 * DELTA_BLOCK);
+    }
+
+    #[test]
+    fn test_delta_on_the_wrong_base_is_refused_before_applying() {
+        let old = snapshot(1, noise(20_000, 1));
+        let new = snapshot(2, [old.data.as_slice(), &noise(500, 2)].concat());
+        let delta = diff(&old, &new);
+
+        let mut other = old.clone();
+        other.data[19_999] ^= 1;
+        assert!(apply_delta(&other, &delta).is_err());
+        let mut elsewhere = old.clone();
+        elsewhere.game_id = "other".to_string();
+        assert!(apply_delta(&elsewhere, &delta).is_err());
+
+        // Nor does a delta altered on the way give anything
+        let mut altered = delta.clone();
+        if let Some(DeltaOp::Insert(bytes)) = altered.ops.last_mut() {
+            bytes[0] ^= 1;
+        }
+        assert!(apply_delta(&old, &altered).is_err());
+        let mut overrun = delta;
+        overrun.ops.insert(
+            0,
+            DeltaOp::Copy {
+                offset: u64::MAX,
+                len: 2,
+            },
+        );
+        assert!(apply_delta(&old, &overrun).is_err());
+    }
+}
@@ -0,0 +1,497 @@
+// state/log.rs - Committed actions of a game, in sequence order
+
+use crate::consensus::ActionId;
+use crate::consensus::sequence::FIRST_SEQUENCE;
+use crate::crypto::{self, Hash};
+use crate::error::{Result, SwarmhostError};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashSet, VecDeque};
+
+/// State hashes kept for the latest sequences of a log
+pub const STATE_HASH_HISTORY: usize = 1024;
+
+/// Root of a perfect Merkle subtree over some of a log's entries, with its
+/// height
+type Peak = (u32, Hash);
+
+/// The actions applied to a game so far, and a hash chained over them
+///
+/// Nodes that applied the same actions in the same order have the same
+/// hash, so comparing hashes compares whole logs.
+///
+/// The state hash at sequence 0 is 32 zero bytes, and at each sequence
+/// after it the BLAKE2s-256 hash of the one before, the sequence number as
+/// 8 big-endian bytes, and the id of the action applied there, in that
+/// order. Checkpoints sign it, so the rule is frozen: changing it splits
+/// the network.
+///
+/// Entries up to a snapshot can be [pruned](Self::prune); the log keeps the
+/// state hash and Merkle peaks as they stood there, so its hash and root
+/// go on as though they were still held. Logs are equal when they hold the
+/// same entries after the same pruned ones.
+#[derive(Debug, Clone)]
+pub struct ActionLog {
+    next: u64,
+    /// Sequence number of the last entry pruned; 0 when none was
+    pruned: u64,
+    /// State hash at `pruned`
+    base_hash: Hash,
+    /// Merkle peaks over the entries up to `pruned`, tallest first
+    base_peaks: Vec<Peak>,
+    /// Entries after `pruned`, oldest first
+    entries: Vec<ActionId>,
+    applied: HashSet<ActionId>,
+    hash: Hash,
+    /// Merkle peaks over every entry, tallest first
+    peaks: Vec<Peak>,
+    /// State hashes after the latest `STATE_HASH_HISTORY` actions, oldest
+    /// first
+    hashes: VecDeque<Hash>,
+}
+
+/// What a snapshot holds of a log: the entries it still has, and the state
+/// hash and Merkle peaks where those pruned end
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LogImage {
+    pub pruned: u64,
+    pub base_hash: Hash,
+    pub base_peaks: Vec<(u32, Hash)>,
+    pub entries: Vec<ActionId>,
+}
+
+impl PartialEq for ActionLog {
+    fn eq(&self, other: &Self) -> bool {
+        self.pruned == other.pruned
+            && self.base_hash == other.base_hash
+            && self.entries == other.entries
+    }
+}
+
+impl Eq for ActionLog {}
+
+impl Default for ActionLog {
+    fn default() -> Self {
+        Self {
+            next: FIRST_SEQUENCE,
+            pruned: 0,
+            base_hash: [0; 32],
+            base_peaks: Vec::new(),
+            entries: Vec::new(),
+            applied: HashSet::new(),
+            hash: [0; 32],
+            peaks: Vec::new(),
+            hashes: VecDeque::new(),
+        }
+    }
+}
+
+impl ActionLog {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Apply the action at `sequence`, returning the new log hash
+    ///
+    /// Sequence numbers must follow on without gaps, and each action is
+    /// applied once, as far back as entries are held.
+    pub fn append(&mut self, sequence: u64, action_id: &ActionId) -> Result<Hash> {
+        if self.applied.contains(action_id) {
+            return Err(SwarmhostError::InvalidState(format!(
+                "Action {} already applied",
+                crypto::short_id(action_id)
+            )));
+        }
+        if sequence != self.next {
+            return Err(SwarmhostError::InvalidState(format!(
+                "Action {} applied at sequence {}, expected {}",
+                crypto::short_id(action_id),
+                sequence,
+                self.next
+            )));
+        }
+        self.hash = chain(&self.hash, sequence, action_id);
+        push_leaf(&mut self.peaks, sequence, action_id);
+        if self.hashes.len() == STATE_HASH_HISTORY {
+            self.hashes.pop_front();
+        }
+        self.hashes.push_back(self.hash);
+        self.entries.push(*action_id);
+        self.applied.insert(*action_id);
+        self.next += 1;
+        Ok(self.hash)
+    }
+
+    /// The log of `entries` applied in order from the first sequence
+    pub fn from_entries(entries: &[ActionId]) -> Result<Self> {
+        let mut log = Self::new();
+        for (sequence, action_id) in (FIRST_SEQUENCE..).zip(entries) {
+            log.append(sequence, action_id)?;
+        }
+        Ok(log)
+    }
+
+    /// The log an image was taken of
+    ///
+    /// Its peaks must be those of a tree over `pruned` entries; whether its
+    /// hash is the log's is for the caller to check.
+    pub fn from_image(image: LogImage) -> Result<Self> {
+        let heights: Vec<u32> = image.base_peaks.iter().map(|(height, _)| *height).collect();
+        let covered = heights.iter().try_fold(0u64, |sum, height| {
+            sum.checked_add(1u64.checked_shl(*height)?)
+        });
+        if covered != Some(image.pruned) || heights.windows(2).any(|pair| pair[0] <= pair[1]) {
+            return Err(SwarmhostError::InvalidState(format!(
+                "Merkle peaks {:?} do not cover {} pruned entries",
+                heights, image.pruned
+            )));
+        }
+        let mut log = Self {
+            next: image.pruned + FIRST_SEQUENCE,
+            pruned: image.pruned,
+            base_hash: image.base_hash,
+            base_peaks: image.base_peaks.clone(),
+            hash: image.base_hash,
+            peaks: image.base_peaks,
+            ..Self::default()
+        };
+        if log.pruned > 0 {
+            log.hashes.push_back(log.hash);
+        }
+        for (sequence, action_id) in (log.next..).zip(&image.entries) {
+            log.append(sequence, action_id)?;
+        }
+        Ok(log)
+    }
+
+    /// What a snapshot of the log holds
+    pub fn image(&self) -> LogImage {
+        LogImage {
+            pruned: self.pruned,
+            base_hash: self.base_hash,
+            base_peaks: self.base_peaks.clone(),
+            entries: self.entries.clone(),
+        }
+    }
+
+    /// The log as it stood at its last pruned entry
+    pub fn base(&self) -> Self {
+        Self::from_image(LogImage {
+            entries: Vec::new(),
+            ..self.image()
+        })
+        .expect("a log's own base is well formed")
+    }
+
+    /// Drop the entries up to `sequence`, returning how many were; those
+    /// already pruned, and any past the last applied, are passed over
+    pub fn prune(&mut self, sequence: u64) -> usize {
+        let count = sequence.min(self.sequence()).saturating_sub(self.pruned) as usize;
+        for action_id in self.entries.drain(..count) {
+            self.pruned += 1;
+            self.base_hash = chain(&self.base_hash, self.pruned, &action_id);
+            push_leaf(&mut self.base_peaks, self.pruned, &action_id);
+            self.applied.remove(&action_id);
+        }
+        count
+    }
+
+    /// Bytes the log holds, roughly: its entries, the set of them, its
+    /// recent state hashes and its Merkle peaks
+    pub fn memory_bytes(&self) -> u64 {
+        let entries = self.entries.len() + self.applied.len();
+        let hashes = self.hashes.len() + self.peaks.len() + self.base_peaks.len();
+        ((entries + hashes) * std::mem::size_of::<Hash>()) as u64
+    }
+
+    /// Sequence number of the last pruned entry; 0 when none was
+    pub fn pruned(&self) -> u64 {
+        self.pruned
+    }
+
+    /// Applied actions still held, oldest first; those up to
+    /// [`pruned`](Self::pruned) are not
+    pub fn entries(&self) -> &[ActionId] {
+        &self.entries
+    }
+
+    /// Entries held from `first` to `last`, both included
+    pub fn range(&self, first: u64, last: u64) -> &[ActionId] {
+        let offset = |sequence: u64| {
+            (sequence.saturating_sub(self.pruned + 1) as usize).min(self.entries.len())
+        };
+        let (start, end) = (offset(first), offset(last.saturating_add(1)));
+        &self.entries[start..end.max(start)]
+    }
+
+    /// Whether `action_id` has been applied and is still held
+    pub fn contains(&self, action_id: &ActionId) -> bool {
+        self.applied.contains(action_id)
+    }
+
+    /// Hash over every applied action and its sequence number
+    pub fn hash(&self) -> Hash {
+        self.hash
+    }
+
+    /// The state hash as it stood at `sequence`, while that is one of the
+    /// latest `STATE_HASH_HISTORY`; none past the last applied
+    pub fn hash_at(&self, sequence: u64) -> Option<Hash> {
+        let behind = self.sequence().checked_sub(sequence)? as usize;
+        if sequence == 0 {
+            return Some([0; 32]);
+        }
+        self.hashes.iter().rev().nth(behind).copied()
+    }
+
+    /// The state hash at `sequence`, chained over the entries held when it
+    /// is older than [`hash_at`](Self::hash_at) reaches; none past the last
+    /// applied, or before both that and the last pruned entry
+    pub fn hash_through(&self, sequence: u64) -> Option<Hash> {
+        if let Some(hash) = self.hash_at(sequence) {
+            return Some(hash);
+        }
+        if sequence < self.pruned || sequence > self.sequence() {
+            return None;
+        }
+        let mut hash = self.base_hash;
+        for (at, action_id) in (self.pruned + 1..).zip(self.range(self.pruned + 1, sequence)) {
+            hash = chain(&hash, at, action_id);
+        }
+        Some(hash)
+    }
+
+    /// Root of a Merkle tree over every applied action and its sequence
+    /// number, so one entry can be proven to a checkpoint without the rest
+    ///
+    /// An odd node out at any level is carried up as it is; the empty log's
+    /// root is all zeroes. That makes the root the peaks of the perfect
+    /// subtrees the entries fill, tallest first, hashed together from the
+    /// right, which is how it is kept as entries are applied and pruned.
+    pub fn merkle_root(&self) -> Hash {
+        let mut peaks = self.peaks.iter().rev().map(|(_, hash)| *hash);
+        let Some(last) = peaks.next() else {
+            return [0; 32];
+        };
+        peaks.fold(last, |right, left| {
+            crypto::hash_multiple(&[b"node", &left, &right])
+        })
+    }
+
+    /// Sequence number of the last applied action; 0 when empty
+    pub fn sequence(&self) -> u64 {
+        self.next - FIRST_SEQUENCE
+    }
+
+    /// Flip a byte of the action applied at `sequence`, as though the state
+    /// had diverged there, to test that divergence shows
+    #[cfg(test)]
+    pub(crate) fn flip(&mut self, sequence: u64) {
+        let mut entries = self.entries.clone();
+        entries[(sequence - self.pruned - FIRST_SEQUENCE) as usize][0] ^= 1;
+        *self = Self::from_image(LogImage {
+            entries,
+            ..self.image()
+        })
+        .unwrap();
+    }
+}
+
+/// The state hash after `action_id` was applied at `sequence` to the state
+/// hashed as `previous`
+fn chain(previous: &Hash, sequence: u64, action_id: &ActionId) -> Hash {
+    crypto::hash_multiple(&[previous, &sequence.to_be_bytes(), action_id])
+}
+
+/// Add the leaf of `action_id` at `sequence` to Merkle `peaks`, merging
+/// those of equal height
+fn push_leaf(peaks: &mut Vec<Peak>, sequence: u64, action_id: &ActionId) {
+    let mut peak = (
+        0,
+        crypto::hash_multiple(&[b"leaf", &sequence.to_be_bytes(), action_id]),
+    );
+    while let Some(&(height, left)) = peaks.last()
+        && height == peak.0
+    {
+        peaks.pop();
+        peak = (
+            height + 1,
+            crypto::hash_multiple(&[b"node", &left, &peak.1]),
+        );
+    }
+    peaks.push(peak);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_append_in_order_only() {
+        let mut log = ActionLog::new();
+        assert!(log.append(2, &[2; 32]).is_err());
+        log.append(1, &[1; 32]).unwrap();
+        assert!(log.append(1, &[1; 32]).is_err());
+        assert!(log.append(2, &[1; 32]).is_err());
+        log.append(2, &[2; 32]).unwrap();
+        assert!(log.contains(&[2; 32]));
+        assert_eq!(log.entries(), &[[1; 32], [2; 32]]);
+        assert_eq!(log.sequence(), 2);
+    }
+
+    #[test]
+    fn test_hash_depends_on_order() {
+        let mut forward = ActionLog::new();
+        forward.append(1, &[1; 32]).unwrap();
+        forward.append(2, &[2; 32]).unwrap();
+
+        let mut reversed = ActionLog::new();
+        reversed.append(1, &[2; 32]).unwrap();
+        reversed.append(2, &[1; 32]).unwrap();
+
+        let mut again = ActionLog::new();
+        again.append(1, &[1; 32]).unwrap();
+        again.append(2, &[2; 32]).unwrap();
+
+        assert_ne!(forward.hash(), reversed.hash());
+        assert_eq!(forward.hash(), again.hash());
+    }
+
+    #[test]
+    fn test_hash_chain_is_frozen() {
+        let mut log = ActionLog::new();
+        assert_eq!(log.hash_at(0), Some([0; 32]));
+        let mut expected = [0; 32];
+        for sequence in 1..=3u64 {
+            let action_id = [sequence as u8; 32];
+            log.append(sequence, &action_id).unwrap();
+            expected = crypto::hash_multiple(&[&expected, &sequence.to_be_bytes(), &action_id]);
+            assert_eq!(log.hash(), expected);
+        }
+        assert_eq!(
+            crypto::player_id_hex(&log.hash()),
+            "30e5a9ed6993787a2280565030895516e36e5c5efa7baf02eb8cf9b34fed95ca"
+        );
+    }
+
+    #[test]
+    fn test_hash_at_reaches_back_over_the_latest_sequences() {
+        let ids: Vec<ActionId> = (0..STATE_HASH_HISTORY as u32 + 10)
+            .map(|i| crypto::hash(&i.to_be_bytes()))
+            .collect();
+        let log = ActionLog::from_entries(&ids).unwrap();
+        let last = log.sequence();
+        assert_eq!(log.hash_at(last), Some(log.hash()));
+        assert_eq!(log.hash_at(last + 1), None);
+        assert_eq!(log.hash_at(0), Some([0; 32]));
+        let oldest = last - STATE_HASH_HISTORY as u64 + 1;
+        assert_eq!(
+            log.hash_at(oldest),
+            Some(
+                ActionLog::from_entries(&ids[..oldest as usize])
+                    .unwrap()
+                    .hash()
+            )
+        );
+        assert_eq!(log.hash_at(oldest - 1), None);
+    }
+
+    #[test]
+    fn test_merkle_root_covers_every_entry_in_order() {
+        let ids: Vec<ActionId> = (1..=5).map(|i| [i; 32]).collect();
+        let log = ActionLog::from_entries(&ids).unwrap();
+        assert_eq!(
+            log.merkle_root(),
+            ActionLog::from_entries(&ids).unwrap().merkle_root()
+        );
+        assert_ne!(log.merkle_root(), log.hash());
+
+        let mut swapped = ids.clone();
+        swapped.swap(3, 4);
+        let swapped = ActionLog::from_entries(&swapped).unwrap();
+        assert_ne!(log.merkle_root(), swapped.merkle_root());
+
+        let shorter = ActionLog::from_entries(&ids[..4]).unwrap();
+        assert_ne!(log.merkle_root(), shorter.merkle_root());
+        assert_eq!(ActionLog::new().merkle_root(), [0; 32]);
+    }
+
+    /// The root as the tree is built level by level, every entry at hand
+    fn tree_root(ids: &[ActionId]) -> Hash {
+        let mut level: Vec<Hash> = (FIRST_SEQUENCE..)
+            .zip(ids)
+            .map(|(sequence, action_id)| {
+                crypto::hash_multiple(&[b"leaf", &sequence.to_be_bytes(), action_id])
+            })
+            .collect();
+        while level.len() > 1 {
+            level = level
+                .chunks(2)
+                .map(|pair| match pair {
+                    [left, right] => crypto::hash_multiple(&[b"node", left, right]),
+                    [odd] => *odd,
+                    _ => unreachable!(),
+                })
+                .collect();
+        }
+        level.first().copied().unwrap_or([0; 32])
+    }
+
+    #[test]
+    fn test_merkle_peaks_give_the_root_of_the_whole_tree() {
+        let ids: Vec<ActionId> = (0..70u32).map(|i| crypto::hash(&i.to_be_bytes())).collect();
+        for count in 0..=ids.len() {
+            let log = ActionLog::from_entries(&ids[..count]).unwrap();
+            assert_eq!(log.merkle_root(), tree_root(&ids[..count]), "{}", count);
+        }
+    }
+
+    #[test]
+    fn test_pruned_log_goes_on_as_though_it_held_everything() {
+        let ids: Vec<ActionId> = (0..40u32).map(|i| crypto::hash(&i.to_be_bytes())).collect();
+        let whole = ActionLog::from_entries(&ids).unwrap();
+        for at in [2, 7, 16, 25] {
+            let mut log = ActionLog::from_entries(&ids[..30]).unwrap();
+            assert_eq!(log.prune(at), at as usize);
+            assert_eq!(log.prune(at), 0);
+            assert_eq!(log.pruned(), at);
+            assert_eq!(log.entries(), &ids[at as usize..30]);
+            assert_eq!(
+                log.range(at + 1, at + 2),
+                &ids[at as usize..at as usize + 2]
+            );
+            assert!(log.range(1, at).is_empty());
+            assert!(!log.contains(&ids[0]));
+            assert_eq!(log.hash_through(at), whole.hash_at(at));
+            assert_eq!(log.hash_through(at - 1), whole.hash_at(at - 1));
+
+            // It survives a snapshot, and the base alone follows on too
+            let restored = ActionLog::from_image(log.image()).unwrap();
+            assert_eq!(restored, log);
+            assert_eq!(restored.hash_through(at), whole.hash_at(at));
+            assert_eq!(restored.hash_through(at - 1), None);
+            let mut base = log.base();
+            assert_eq!(base.sequence(), at);
+            assert_eq!(base.hash(), whole.hash_at(at).unwrap());
+            for (sequence, action_id) in (at + 1..).zip(&ids[at as usize..30]) {
+                base.append(sequence, action_id).unwrap();
+            }
+            assert_eq!(base.hash(), log.hash());
+
+            for (sequence, action_id) in (31..).zip(&ids[30..]) {
+                log.append(sequence, action_id).unwrap();
+            }
+            assert_eq!(log.hash(), whole.hash());
+            assert_eq!(log.merkle_root(), whole.merkle_root());
+        }
+
+        // Peaks that cannot be those of the entries pruned are refused
+        let mut image = ActionLog::from_entries(&ids[..6]).unwrap().image();
+        image.pruned = 6;
+        assert!(ActionLog::from_image(image).is_err());
+        let mut log = ActionLog::from_entries(&ids[..6]).unwrap();
+        log.prune(6);
+        let mut image = log.image();
+        image.base_peaks.reverse();
+        assert!(ActionLog::from_image(image).is_err());
+    }
+}
@@ -1,10 +1,1595 @@
-// state/mod.rs - State management (placeholder)
+// state/mod.rs - State management
 
-#[derive(Default)]
-pub struct StateManager;
+pub mod archive;
+pub mod attestation;
+pub mod checkpoint;
+pub mod delta;
+pub mod log;
+pub mod replay;
+pub mod snapshot;
+pub mod speculation;
+pub mod storage;
+pub mod store;
+pub mod view;
+
+pub use archive::{
+    ARCHIVE_VERSION, ArchiveConfig, ArchiveHeader, ArchiveRoots, ArchiveTail, GameArchive,
+};
+pub use attestation::SnapshotAttestation;
+pub use checkpoint::{Checkpoint, CheckpointSignature, CheckpointVote};
+pub use delta::{DeltaOp, EncodedDelta, SnapshotBase, StateDelta, apply_delta, diff};
+pub use log::{ActionLog, LogImage};
+pub use replay::{Divergence, ReplayCheck, ReplayMode, ReplayOutcome, replay};
+pub use snapshot::{EncodedSnapshot, Snapshot, SnapshotChunk, SnapshotDue, SyncPlan};
+pub use speculation::Speculation;
+#[cfg(feature = "sled")]
+pub use storage::SledBackend;
+pub use storage::{
+    BatchOp, DirectoryBackend, MemoryBackend, StorageBackend, WriteBatch, open_backend,
+};
+pub use store::{BackendStore, SnapshotStore, open_store};
+pub use view::{ConfirmedStates, StateView};
+
+use crate::consensus::{ActionId, Commit, Membership, SafetyRecord};
+use crate::crypto::{Hash, KeyPair, short_id};
+use crate::error::{Result, SwarmhostError};
+use crate::network::PeerRecord;
+use crate::node::StateConfig;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::sync::Arc;
+
+/// Deltas a provider keeps encoded for the next peer catching up from the
+/// same snapshot to the same one
+pub const DELTA_CACHE: usize = 4;
+
+/// States of games at past sequences kept rebuilt for the next query of
+/// the same one
+pub const HISTORY_CACHE: usize = 8;
+
+/// Owns snapshot storage and the committed action log of every game this
+/// node takes part in, and with optimistic execution a speculative one
+pub struct StateManager {
+    store: Box<dyn SnapshotStore>,
+    /// Commits between snapshots of a game's log; 0 for none
+    snapshot_interval: u64,
+    /// Snapshots kept per game
+    max_snapshots: usize,
+    /// Whether those at every checkpoint are kept too, and commits stored
+    /// as they are made, as they are by a lasting store
+    durable: bool,
+    /// Entries a game's log holds before those behind a snapshot are pruned
+    max_log_size: usize,
+    /// Whether pruned entries are archived to the store
+    archive_pruned: bool,
+    /// Logs captured for snapshots fell due, waiting to be encoded
+    due: Vec<SnapshotDue>,
+    /// Rollbacks and restores so far; snapshots captured before the latest
+    /// are not stored
+    generation: u64,
+    logs: HashMap<String, ActionLog>,
+    /// Most actions speculated on per game, when speculating
+    speculation_depth: Option<usize>,
+    speculations: HashMap<String, Speculation>,
+    /// Games not speculated on until their log is taken up anew or rolled
+    /// back, after their state diverged from a peer's
+    paused: HashSet<String>,
+    /// Latest checkpoint of each game
+    checkpoints: HashMap<String, Checkpoint>,
+    /// Signatures towards checkpoints not yet formed, by what they sign
+    checkpoint_votes: HashMap<Hash, Vec<CheckpointVote>>,
+    /// Checkpoint signatures made here that peers have not been sent yet
+    signed: Vec<CheckpointVote>,
+    /// Snapshot last served to a peer catching up, kept for the chunks
+    /// still to be asked for
+    serving: Option<Snapshot>,
+    /// Deltas last served, by game and the sequences they go from and to,
+    /// the latest at the back
+    deltas: VecDeque<EncodedDelta>,
+    /// Most actions replayed to rebuild a past state unless allowed more
+    max_replay: u64,
+    /// Past states last rebuilt, with the generation they were rebuilt in,
+    /// the latest asked for at the back
+    history: VecDeque<(u64, Snapshot)>,
+    /// Views of each game's log as last published, for readers not to wait
+    /// on the manager
+    confirmed: Arc<ConfirmedStates>,
+}
 
 impl StateManager {
-    pub fn new() -> Self {
-        Self  // Remove ::default()
+    /// Create a state manager using the configured persistence backend
+    pub fn new(config: &StateConfig) -> Result<Self> {
+        Ok(Self::with_store(config, open_store(&config.persistence)?))
+    }
+
+    /// Create a state manager keeping what it keeps in `store`, whatever
+    /// backend the config names
+    pub fn with_store(config: &StateConfig, store: Box<dyn SnapshotStore>) -> Self {
+        let durable = store.durable();
+        Self {
+            store,
+            snapshot_interval: config.snapshot_interval.into(),
+            max_snapshots: if durable {
+                config.max_snapshots_on_disk
+            } else {
+                config.max_snapshots_in_memory
+            },
+            durable,
+            max_log_size: config.max_action_log_size,
+            archive_pruned: config.archive_pruned && durable,
+            due: Vec::new(),
+            generation: 0,
+            logs: HashMap::new(),
+            speculation_depth: None,
+            speculations: HashMap::new(),
+            paused: HashSet::new(),
+            checkpoints: HashMap::new(),
+            checkpoint_votes: HashMap::new(),
+            signed: Vec::new(),
+            serving: None,
+            deltas: VecDeque::new(),
+            max_replay: config.max_replay_actions,
+            history: VecDeque::new(),
+            confirmed: Arc::new(ConfirmedStates::default()),
+        }
+    }
+
+    /// Apply proposed actions speculatively, at most `max_depth` ahead of
+    /// the confirmed state per game
+    pub fn with_speculation(mut self, max_depth: usize) -> Self {
+        self.speculation_depth = Some(max_depth);
+        self
+    }
+
+    /// Persist a snapshot
+    pub fn save_snapshot(&mut self, snapshot: &Snapshot) -> Result<()> {
+        self.store.save(snapshot)
+    }
+
+    /// Persist the attestation of a snapshot, beside it
+    pub fn save_attestation(&mut self, attestation: &SnapshotAttestation) -> Result<()> {
+        self.store.save_attestation(attestation)
+    }
+
+    /// Logs captured every `snapshot_interval` commits since last asked,
+    /// for the caller to encode off the commit path and then
+    /// [store](Self::store_snapshot)
+    pub fn take_due_snapshots(&mut self) -> Vec<SnapshotDue> {
+        std::mem::take(&mut self.due)
+    }
+
+    /// Store a snapshot encoded from one that fell due, returning whether
+    /// it was; one captured before a rollback or restore since is dropped
+    ///
+    /// Past `max_snapshots_in_memory` a game's oldest snapshots are
+    /// removed, except the one at its latest checkpoint, kept for peers
+    /// catching up. On disk the cap is `max_snapshots_on_disk`, and those
+    /// at every `checkpoint_interval`th commit are kept as well.
+    pub fn store_snapshot(
+        &mut self,
+        encoded: EncodedSnapshot,
+        checkpoint_interval: u64,
+    ) -> Result<bool> {
+        if encoded.generation != self.generation {
+            return Ok(false);
+        }
+        let game_id = encoded.snapshot.game_id.clone();
+        self.save_snapshot(&encoded.snapshot)?;
+        let checkpointed = self.checkpointed(&game_id);
+        let at_checkpoint = |sequence: u64| {
+            self.durable && checkpoint_interval > 0 && sequence.is_multiple_of(checkpoint_interval)
+        };
+        let stored = self.store.sequences(&game_id)?;
+        for &old in &stored[..stored.len().saturating_sub(self.max_snapshots)] {
+            if old != checkpointed && !at_checkpoint(old) {
+                self.store.remove(&game_id, old)?;
+            }
+        }
+        Ok(true)
+    }
+
+    /// Newest stored snapshot for a game, used when restoring
+    pub fn latest_snapshot(&self, game_id: &str) -> Result<Option<Snapshot>> {
+        self.store.latest(game_id)
+    }
+
+    /// Stored snapshot of a game at `sequence`, if we still hold it
+    pub fn snapshot_at(&self, game_id: &str, sequence: u64) -> Result<Option<Snapshot>> {
+        self.store.load(game_id, sequence)
+    }
+
+    /// Every game we hold a snapshot or commits of, as a restarted node
+    /// finds them
+    pub fn stored_games(&self) -> Result<Vec<String>> {
+        self.store.games()
+    }
+
+    /// Persist the consensus safety record, ahead of sending what it covers
+    pub fn save_safety(&mut self, record: &SafetyRecord) -> Result<()> {
+        self.store.save_safety(record)
+    }
+
+    /// The safety record persisted before a restart, if any
+    pub fn load_safety(&self) -> Result<Option<SafetyRecord>> {
+        self.store.load_safety()
+    }
+
+    /// Persist the peers we know of, for a restart to dial
+    pub fn save_peers(&mut self, peers: &[PeerRecord]) -> Result<()> {
+        self.store.save_peers(peers)
+    }
+
+    /// The peers known before a restart
+    pub fn load_peers(&self) -> Result<Vec<PeerRecord>> {
+        self.store.load_peers()
+    }
+
+    /// Snapshot to hand a peer catching up on a game, with its
+    /// attestation: the one at the latest checkpoint if we still have it
+    /// attested, else the newest stored, or one of the empty log when there
+    /// is none
+    pub fn sync_snapshot(&self, game_id: &str) -> Result<(Snapshot, Option<SnapshotAttestation>)> {
+        if let Some(checkpoint) = self.checkpoints.get(game_id)
+            && let Some(attestation) = self.store.attestation(game_id, checkpoint.sequence)?
+            && let Some(snapshot) = self.store.load(game_id, checkpoint.sequence)?
+        {
+            return Ok((snapshot, Some(attestation)));
+        }
+        match self.latest_snapshot(game_id)? {
+            Some(snapshot) => Ok((snapshot, None)),
+            None => Ok((snapshot_of(game_id, &ActionLog::new()), None)),
+        }
+    }
+
+    /// Plan for a peer to fetch the snapshot [`sync_snapshot`]
+    /// (Self::sync_snapshot) picks
+    ///
+    /// A peer naming a `base` it holds is planned a delta from it instead,
+    /// if we hold the same snapshot and the delta is the smaller; otherwise
+    /// it is sent the snapshot whole.
+    pub fn sync_plan(&mut self, game_id: &str, base: Option<SnapshotBase>) -> Result<SyncPlan> {
+        let (snapshot, attestation) = self.sync_snapshot(game_id)?;
+        let delta = match base {
+            Some(base) if base.sequence < snapshot.sequence => self
+                .delta(&snapshot, base.sequence)?
+                .filter(|delta| delta.from == base && delta.data.len() < snapshot.data.len())
+                .map(|delta| delta.plan(attestation.clone())),
+            _ => None,
+        };
+        let plan = delta.unwrap_or_else(|| snapshot.plan(attestation));
+        self.serving = Some(snapshot);
+        Ok(plan)
+    }
+
+    /// Chunk `index` of a game's snapshot at `sequence`, or of the delta
+    /// to it from the one at `delta_from`, for a peer following its plan;
+    /// none if we no longer hold either
+    pub fn sync_chunk(
+        &mut self,
+        game_id: &str,
+        sequence: u64,
+        delta_from: Option<u64>,
+        index: u32,
+    ) -> Result<Option<SnapshotChunk>> {
+        let held = self
+            .serving
+            .as_ref()
+            .is_some_and(|snapshot| snapshot.game_id == game_id && snapshot.sequence == sequence);
+        if !held {
+            self.serving = match self.store.load(game_id, sequence)? {
+                Some(snapshot) => Some(snapshot),
+                None if sequence == 0 => Some(snapshot_of(game_id, &ActionLog::new())),
+                None => return Ok(None),
+            };
+        }
+        let Some(from) = delta_from else {
+            return Ok(self
+                .serving
+                .as_ref()
+                .and_then(|snapshot| snapshot.chunk(index)));
+        };
+        let Some(snapshot) = self.serving.take() else {
+            return Ok(None);
+        };
+        let chunk = self
+            .delta(&snapshot, from)
+            .map(|delta| delta.and_then(|delta| delta.chunk(index)));
+        self.serving = Some(snapshot);
+        chunk
+    }
+
+    /// The delta to `to` from the game's snapshot at `from`, as cached or
+    /// else taken and cached now; none if we no longer hold the latter
+    fn delta(&mut self, to: &Snapshot, from: u64) -> Result<Option<&EncodedDelta>> {
+        let cached = self.deltas.iter().position(|delta| {
+            delta.game_id == to.game_id
+                && delta.from.sequence == from
+                && delta.sequence == to.sequence
+                && delta.state_hash == to.state_hash
+        });
+        let at = match cached {
+            Some(at) => at,
+            None => {
+                let Some(old) = self.store.load(&to.game_id, from)? else {
+                    return Ok(None);
+                };
+                if self.deltas.len() >= DELTA_CACHE {
+                    self.deltas.pop_front();
+                }
+                self.deltas.push_back(EncodedDelta::of(&diff(&old, to)));
+                self.deltas.len() - 1
+            }
+        };
+        Ok(self.deltas.get(at))
+    }
+
+    /// A game's state as it stood at `sequence`, as a snapshot of its log
+    /// then, rebuilt without touching the live log
+    ///
+    /// The rebuild starts from the newest stored snapshot at or before
+    /// `sequence` that the log still reaches back to, or else from the
+    /// log's last pruned entry, and replays the entries after it. Replaying
+    /// more than `max_replay_actions` is refused unless `allow_expensive`;
+    /// a sequence the log is pruned past is refused as
+    /// [`Pruned`](SwarmhostError::Pruned).
+    pub fn state_at(
+        &mut self,
+        game_id: &str,
+        sequence: u64,
+        allow_expensive: bool,
+    ) -> Result<Snapshot> {
+        let Some(log) = self.logs.get(game_id) else {
+            return Err(SwarmhostError::InvalidState(format!(
+                "No log of {}",
+                game_id
+            )));
+        };
+        if sequence > log.sequence() {
+            return Err(SwarmhostError::InvalidState(format!(
+                "{} is committed up to {}, not {}",
+                game_id,
+                log.sequence(),
+                sequence
+            )));
+        }
+        if sequence < log.pruned() {
+            return Err(SwarmhostError::Pruned {
+                game_id: game_id.to_string(),
+                earliest: log.pruned(),
+            });
+        }
+        let generation = self.generation;
+        let cached = self.history.iter().position(|(at, snapshot)| {
+            *at == generation && snapshot.game_id == game_id && snapshot.sequence == sequence
+        });
+        if let Some(cached) = cached.and_then(|at| self.history.remove(at)) {
+            self.history.push_back(cached.clone());
+            return Ok(cached.1);
+        }
+
+        let stored = self.store.sequences(game_id)?;
+        let nearest = stored
+            .into_iter()
+            .rev()
+            .find(|&at| at >= log.pruned() && at <= sequence);
+        let mut past = match nearest {
+            Some(at) => self
+                .store
+                .load(game_id, at)?
+                .and_then(|snapshot| decode(&snapshot).ok())
+                .filter(|past| Some(past.hash()) == log.hash_through(at)),
+            None => None,
+        }
+        .unwrap_or_else(|| log.base());
+        let replayed = sequence - past.sequence();
+        if replayed > self.max_replay && !allow_expensive {
+            return Err(SwarmhostError::InvalidState(format!(
+                "State of {} at {} needs {} actions replayed, more than the {} allowed",
+                game_id, sequence, replayed, self.max_replay
+            )));
+        }
+        let first = past.sequence() + 1;
+        for (at, action_id) in (first..).zip(log.range(first, sequence)) {
+            past.append(at, action_id)?;
+        }
+        let snapshot = snapshot_of(game_id, &past);
+        if self.history.len() >= HISTORY_CACHE {
+            self.history.pop_front();
+        }
+        self.history.push_back((generation, snapshot.clone()));
+        Ok(snapshot)
+    }
+
+    /// Bytes of snapshots held in memory: those an in-memory store keeps,
+    /// and the copies kept to serve peers
+    pub fn snapshot_bytes(&self) -> u64 {
+        let serving = self
+            .serving
+            .as_ref()
+            .map_or(0, |snapshot| snapshot.data.len());
+        let deltas: usize = self.deltas.iter().map(|delta| delta.data.len()).sum();
+        self.store.memory_bytes() + (serving + deltas) as u64
+    }
+
+    /// Bytes the logs of every game hold, speculative ones included
+    pub fn log_bytes(&self) -> u64 {
+        let speculated = self.speculations.values().map(Speculation::log);
+        self.logs
+            .values()
+            .chain(speculated)
+            .map(ActionLog::memory_bytes)
+            .sum()
+    }
+
+    /// Bytes of the past states kept rebuilt by [`state_at`](Self::state_at)
+    pub fn history_bytes(&self) -> u64 {
+        let held: usize = self
+            .history
+            .iter()
+            .map(|(_, snapshot)| snapshot.data.len())
+            .sum();
+        held as u64
+    }
+
+    /// Forget the past states kept rebuilt
+    pub fn clear_history(&mut self) {
+        self.history.clear();
+    }
+
+    /// Drop the snapshots held in memory that are not needed to go on: the
+    /// copies kept to serve peers and, with an in-memory store, every one
+    /// but each game's newest and the one at its checkpoint
+    ///
+    /// A lasting store already keeps its snapshots out of memory.
+    pub fn shed_snapshots(&mut self) -> Result<()> {
+        self.serving = None;
+        self.deltas.clear();
+        if self.durable {
+            return Ok(());
+        }
+        for game_id in self.store.games()? {
+            let checkpointed = self.checkpointed(&game_id);
+            let stored = self.store.sequences(&game_id)?;
+            for &old in &stored[..stored.len().saturating_sub(1)] {
+                if old != checkpointed {
+                    self.store.remove(&game_id, old)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Games we hold a log of
+    pub fn games(&self) -> impl Iterator<Item = &String> {
+        self.logs.keys()
+    }
+
+    /// Take up a game's log from a snapshot a peer handed us, once its
+    /// entries are found to chain to its state hash
+    ///
+    /// Speculation on the game starts over from the restored log.
+    pub fn restore(&mut self, snapshot: &Snapshot) -> Result<()> {
+        let log = decode(snapshot)?;
+        self.take_up(&snapshot.game_id, log)
+    }
+
+    /// A game's log as stored: its newest snapshot that loads, or the empty
+    /// log if there is none, and the log entries stored after it that
+    /// follow on from it
+    pub fn stored_log(&self, game_id: &str) -> Result<ActionLog> {
+        let mut log = match self.store.latest(game_id)? {
+            Some(snapshot) => decode(&snapshot)?,
+            None => ActionLog::new(),
+        };
+        for (sequence, action_id) in self.store.log_entries(game_id, log.sequence())? {
+            if sequence != log.sequence() + 1 {
+                break;
+            }
+            log.append(sequence, &action_id)?;
+        }
+        Ok(log)
+    }
+
+    /// Take up a game's [stored log](Self::stored_log) if it holds more
+    /// than `committed`, as after a restart, returning where it ends
+    pub fn restore_stored(&mut self, game_id: &str, committed: u64) -> Result<Option<u64>> {
+        let log = self.stored_log(game_id)?;
+        let sequence = log.sequence();
+        if sequence <= committed {
+            return Ok(None);
+        }
+        self.generation += 1;
+        self.speculations.remove(game_id);
+        self.logs.insert(game_id.to_string(), log);
+        Ok(Some(sequence))
+    }
+
+    /// Replace a game's log with one from elsewhere; the commits stored
+    /// towards the one replaced no longer follow on from what is stored
+    fn take_up(&mut self, game_id: &str, log: ActionLog) -> Result<()> {
+        if self.durable {
+            self.store.remove_commits(game_id, 0, u64::MAX)?;
+        }
+        self.generation += 1;
+        self.speculations.remove(game_id);
+        self.paused.remove(game_id);
+        self.logs.insert(game_id.to_string(), log);
+        self.publish(game_id);
+        Ok(())
+    }
+
+    /// Take up a game's log from a snapshot taken at a checkpoint, once it
+    /// is found to hold the very log the checkpoint's signers signed; the
+    /// caller has verified the checkpoint
+    pub fn restore_checkpoint(
+        &mut self,
+        snapshot: &Snapshot,
+        checkpoint: &Checkpoint,
+    ) -> Result<()> {
+        let log = decode(snapshot)?;
+        if snapshot.game_id != checkpoint.game_id
+            || log.sequence() != checkpoint.sequence
+            || log.hash() != checkpoint.state_hash
+            || log.merkle_root() != checkpoint.log_root
+        {
+            return Err(SwarmhostError::InvalidState(format!(
+                "Snapshot of {} at {} is not the log checkpointed at {}",
+                snapshot.game_id, snapshot.sequence, checkpoint.sequence
+            )));
+        }
+        self.take_up(&snapshot.game_id, log)?;
+        self.set_checkpoint(checkpoint.clone());
+        Ok(())
+    }
+
+    /// Take up a game from an imported archive, at `log` its
+    /// [replay](GameArchive::replay) reached; the caller has replayed it
+    ///
+    /// Its snapshot is stored with its attestation, to serve peers and
+    /// restart from, its checkpoint kept, and its commits stored when the
+    /// store lasts.
+    pub fn import(&mut self, archive: &GameArchive, log: ActionLog) -> Result<()> {
+        self.save_snapshot(&archive.snapshot)?;
+        if let Some(attestation) = &archive.attestation {
+            self.save_attestation(attestation)?;
+        }
+        self.take_up(&archive.header.game_id, log)?;
+        if self.durable {
+            for commit in &archive.tail.commits {
+                self.store.save_commit(commit)?;
+            }
+        }
+        if let Some(checkpoint) = &archive.checkpoint {
+            self.set_checkpoint(checkpoint.clone());
+        }
+        Ok(())
+    }
+
+    /// Snapshot of a game's log as it stands, for export
+    pub fn current_snapshot(&self, game_id: &str) -> Option<Snapshot> {
+        self.logs.get(game_id).map(|log| snapshot_of(game_id, log))
+    }
+
+    /// Snapshot a game's log as it stands, and sign it towards a
+    /// checkpoint; the signature waits in [`take_signed`](Self::take_signed)
+    /// for the caller to count and send
+    pub fn sign_checkpoint(&mut self, game_id: &str, keypair: &KeyPair) -> Result<()> {
+        let Some(log) = self.logs.get(game_id) else {
+            return Ok(());
+        };
+        let snapshot = snapshot_of(game_id, log);
+        let vote = CheckpointVote::new(
+            keypair,
+            game_id,
+            log.sequence(),
+            log.hash(),
+            log.merkle_root(),
+        );
+        self.save_snapshot(&snapshot)?;
+        self.signed.push(vote);
+        Ok(())
+    }
+
+    /// Checkpoint signatures made here since last asked
+    pub fn take_signed(&mut self) -> Vec<CheckpointVote> {
+        std::mem::take(&mut self.signed)
+    }
+
+    /// Count a validator's signature towards a checkpoint, returning the
+    /// checkpoint once members of `validators` weighing `required` signed
+    /// the same log
+    ///
+    /// Signatures at or below the latest checkpoint, and repeats, are
+    /// ignored; those from non-validators are refused as consensus errors.
+    pub fn receive_checkpoint_vote(
+        &mut self,
+        vote: CheckpointVote,
+        validators: &Membership,
+        required: u64,
+    ) -> Result<Option<Checkpoint>> {
+        if !validators.contains(&vote.signer) {
+            return Err(SwarmhostError::consensus(format!(
+                "checkpoint signed by {}, not a validator",
+                short_id(&vote.signer)
+            )));
+        }
+        vote.verify()?;
+        if vote.sequence <= self.checkpointed(&vote.game_id) {
+            return Ok(None);
+        }
+        let votes = self.checkpoint_votes.entry(vote.digest()).or_default();
+        if votes.iter().any(|seen| seen.signer == vote.signer) {
+            return Ok(None);
+        }
+        votes.push(vote);
+        // Signers ejected since they signed no longer count
+        let votes: Vec<CheckpointVote> = votes
+            .iter()
+            .filter(|vote| validators.contains(&vote.signer))
+            .cloned()
+            .collect();
+        if validators.weight(votes.iter().map(|vote| &vote.signer)) < required {
+            return Ok(None);
+        }
+        let checkpoint = Checkpoint::from_votes(&votes);
+        if let Some(checkpoint) = &checkpoint {
+            self.set_checkpoint(checkpoint.clone());
+        }
+        Ok(checkpoint)
+    }
+
+    /// Take in a checkpoint a peer passed on, returning whether it is newer
+    /// than the one we had
+    ///
+    /// One that does not verify against `validators` is refused as a
+    /// consensus or crypto error.
+    pub fn receive_checkpoint(
+        &mut self,
+        checkpoint: Checkpoint,
+        validators: &Membership,
+        required: u64,
+    ) -> Result<bool> {
+        if checkpoint.sequence <= self.checkpointed(&checkpoint.game_id) {
+            return Ok(false);
+        }
+        checkpoint.verify(validators, required)?;
+        Ok(self.set_checkpoint(checkpoint))
+    }
+
+    /// Latest checkpoint of a game
+    pub fn latest_checkpoint(&self, game_id: &str) -> Option<&Checkpoint> {
+        self.checkpoints.get(game_id)
+    }
+
+    /// Sign the snapshot we hold at a game's latest checkpoint as its
+    /// producer and store the attestation beside it, returning it; none if
+    /// we do not hold that snapshot, or already attested it
+    pub fn attest(
+        &mut self,
+        game_id: &str,
+        keypair: &KeyPair,
+    ) -> Result<Option<SnapshotAttestation>> {
+        let Some(checkpoint) = self.checkpoints.get(game_id) else {
+            return Ok(None);
+        };
+        let sequence = checkpoint.sequence;
+        if self.store.attestation(game_id, sequence)?.is_some() {
+            return Ok(None);
+        }
+        let Some(snapshot) = self.store.load(game_id, sequence)? else {
+            return Ok(None);
+        };
+        let attestation = SnapshotAttestation::new(keypair, &snapshot, checkpoint.clone());
+        self.store.save_attestation(&attestation)?;
+        Ok(Some(attestation))
+    }
+
+    /// Attestation stored beside a game's snapshot at `sequence`, if any
+    pub fn attestation(&self, game_id: &str, sequence: u64) -> Result<Option<SnapshotAttestation>> {
+        self.store.attestation(game_id, sequence)
+    }
+
+    /// Sequence of a game's latest checkpoint; 0 for none
+    fn checkpointed(&self, game_id: &str) -> u64 {
+        self.checkpoints
+            .get(game_id)
+            .map_or(0, |checkpoint| checkpoint.sequence)
+    }
+
+    /// Keep `checkpoint` as its game's latest, if it is later, dropping
+    /// signatures towards earlier ones
+    fn set_checkpoint(&mut self, checkpoint: Checkpoint) -> bool {
+        if checkpoint.sequence <= self.checkpointed(&checkpoint.game_id) {
+            return false;
+        }
+        self.checkpoint_votes.retain(|_, votes| {
+            votes.first().is_some_and(|vote| {
+                vote.game_id != checkpoint.game_id || vote.sequence > checkpoint.sequence
+            })
+        });
+        self.checkpoints
+            .insert(checkpoint.game_id.clone(), checkpoint);
+        true
+    }
+
+    /// Apply a committed action; commits must arrive in sequence order
+    ///
+    /// Every `snapshot_interval` commits the log is captured for a snapshot,
+    /// encoded and stored once [taken](Self::take_due_snapshots). Returns the
+    /// speculated actions rolled back because this one was not the next
+    /// speculated on.
+    pub fn apply(
+        &mut self,
+        game_id: &str,
+        sequence: u64,
+        action_id: &ActionId,
+    ) -> Result<Vec<ActionId>> {
+        let confirmed = self.logs.entry(game_id.to_string()).or_default();
+        confirmed.append(sequence, action_id)?;
+        if self.snapshot_interval > 0 && sequence.is_multiple_of(self.snapshot_interval) {
+            self.due
+                .push(SnapshotDue::of(game_id, confirmed, self.generation));
+        }
+        let confirmed = &self.logs[game_id];
+        Ok(match self.speculations.get_mut(game_id) {
+            Some(speculation) => speculation.confirm(confirmed, action_id),
+            None => Vec::new(),
+        })
+    }
+
+    /// [Apply](Self::apply) a delivered commit, first storing it with its
+    /// log entry in one batch when the store lasts, so that a restart
+    /// never finds one without the other
+    pub fn apply_commit(&mut self, commit: &Commit) -> Result<Vec<ActionId>> {
+        if self.durable {
+            self.store.save_commit(commit)?;
+        }
+        self.apply(&commit.action.game_id, commit.sequence, &commit.action.id())
+    }
+
+    /// Roll a game's log back to `sequence`, the last commit kept, for
+    /// another branch of it to be applied after; returns the speculated
+    /// actions rolled back, as speculation starts over
+    ///
+    /// The log is restored from the newest stored snapshot at or before
+    /// `sequence` that it follows on from, and its commits after that
+    /// replayed up to `sequence`. Snapshots holding later commits are
+    /// removed, those still being encoded are not stored, and signatures
+    /// towards later checkpoints are dropped. Rolling back past the latest
+    /// checkpoint, or past the entries pruned, is refused as an invalid
+    /// state.
+    pub fn rollback_to(&mut self, game_id: &str, sequence: u64) -> Result<Vec<ActionId>> {
+        let Some(confirmed) = self.logs.get(game_id) else {
+            return Ok(Vec::new());
+        };
+        let checkpointed = self.checkpointed(game_id);
+        if sequence < checkpointed {
+            return Err(SwarmhostError::InvalidState(format!(
+                "Cannot roll {} back to {}, past its checkpoint at {}",
+                game_id, sequence, checkpointed
+            )));
+        }
+        if sequence < confirmed.pruned() {
+            return Err(SwarmhostError::InvalidState(format!(
+                "Cannot roll {} back to {}, past its entries pruned up to {}",
+                game_id,
+                sequence,
+                confirmed.pruned()
+            )));
+        }
+        let head = confirmed.sequence();
+        let sequence = sequence.min(head);
+        let mut rolled_back = self.restorable(game_id, confirmed, sequence)?;
+        let replayed = confirmed.range(rolled_back.sequence() + 1, sequence);
+        for (at, action_id) in (rolled_back.sequence() + 1..).zip(replayed) {
+            rolled_back.append(at, action_id)?;
+        }
+
+        let reverted = match self.speculations.remove(game_id) {
+            Some(speculation) => speculation.log().range(head + 1, u64::MAX).to_vec(),
+            None => Vec::new(),
+        };
+        self.paused.remove(game_id);
+        self.logs.insert(game_id.to_string(), rolled_back);
+        self.publish(game_id);
+        self.generation += 1;
+        for stored in self.store.sequences(game_id)? {
+            if stored > sequence {
+                self.store.remove(game_id, stored)?;
+            }
+        }
+        if self.durable {
+            self.store.remove_commits(game_id, sequence + 1, u64::MAX)?;
+        }
+        self.checkpoint_votes.retain(|_, votes| {
+            votes
+                .first()
+                .is_some_and(|vote| vote.game_id != game_id || vote.sequence <= sequence)
+        });
+        Ok(reverted)
+    }
+
+    /// The log of the newest stored snapshot of a game at or before
+    /// `sequence` that `confirmed` follows on from; its base if none is
+    fn restorable(&self, game_id: &str, confirmed: &ActionLog, sequence: u64) -> Result<ActionLog> {
+        for stored in self.store.sequences(game_id)?.into_iter().rev() {
+            if stored > sequence || stored < confirmed.pruned() {
+                continue;
+            }
+            let log = match self.store.load(game_id, stored) {
+                Ok(Some(snapshot)) => decode(&snapshot),
+                Ok(None) => continue,
+                Err(e) => Err(e),
+            };
+            match log {
+                Ok(log) if confirmed.hash_through(stored) == Some(log.hash()) => return Ok(log),
+                Ok(_) => {}
+                Err(e) => tracing::warn!(
+                    "Not rolling back onto the snapshot of {} at {}: {}",
+                    game_id,
+                    stored,
+                    e
+                ),
+            }
+        }
+        Ok(confirmed.base())
+    }
+
+    /// Prune a game's log once it holds more than `max_action_log_size`
+    /// entries, up to its newest stored snapshot or its latest checkpoint,
+    /// whichever is later, but not past `floor`; returns the entries pruned
+    ///
+    /// The caller passes the last commit the slowest peer following the
+    /// game has, so that a fork found against it can still be rolled back,
+    /// or `u64::MAX` to prune regardless. With `archive_pruned` the entries
+    /// are archived to the store before they go.
+    pub fn prune(&mut self, game_id: &str, floor: u64) -> Result<usize> {
+        match self.logs.get(game_id) {
+            Some(log) if log.entries().len() > self.max_log_size => {
+                self.prune_behind(game_id, floor)
+            }
+            _ => Ok(0),
+        }
+    }
+
+    /// [Prune](Self::prune) a game's log as far as its snapshots and
+    /// checkpoint let it be whatever its size, to make room in memory
+    pub fn shed_log(&mut self, game_id: &str) -> Result<usize> {
+        self.prune_behind(game_id, u64::MAX)
+    }
+
+    fn prune_behind(&mut self, game_id: &str, floor: u64) -> Result<usize> {
+        let Some(log) = self.logs.get(game_id) else {
+            return Ok(0);
+        };
+        let stored = self.store.sequences(game_id)?.last().copied().unwrap_or(0);
+        let to = stored.max(self.checkpointed(game_id)).min(floor);
+        let pruned = log.range(log.pruned() + 1, to);
+        if pruned.is_empty() {
+            return Ok(0);
+        }
+        if self.archive_pruned {
+            self.store.archive(game_id, log.pruned() + 1, pruned)?;
+        }
+        if self.durable {
+            // Only those a stored snapshot holds are no longer needed to
+            // restart from
+            self.store.remove_commits(game_id, 0, stored)?;
+        }
+        if let Some(speculation) = self.speculations.get_mut(game_id) {
+            speculation.prune(to);
+        }
+        Ok(self.logs.get_mut(game_id).map_or(0, |log| log.prune(to)))
+    }
+
+    /// Apply a proposed action ahead of its commit; false when not
+    /// speculating, or already `max_depth` actions ahead
+    pub fn speculate(&mut self, game_id: &str, action_id: &ActionId) -> bool {
+        let Some(max_depth) = self.speculation_depth else {
+            return false;
+        };
+        if self.paused.contains(game_id) {
+            return false;
+        }
+        let confirmed = &self.logs;
+        self.speculations
+            .entry(game_id.to_string())
+            .or_insert_with(|| {
+                Speculation::new(confirmed.get(game_id).unwrap_or(&ActionLog::new()))
+            })
+            .speculate(action_id, max_depth)
+    }
+
+    /// Stop speculating on a game until its log is taken up anew or rolled
+    /// back, returning the speculated actions rolled back
+    pub fn pause_speculation(&mut self, game_id: &str) -> Vec<ActionId> {
+        self.paused.insert(game_id.to_string());
+        let Some(speculation) = self.speculations.remove(game_id) else {
+            return Vec::new();
+        };
+        let confirmed = self.logs.get(game_id).map_or(0, ActionLog::sequence);
+        speculation.log().range(confirmed + 1, u64::MAX).to_vec()
+    }
+
+    /// Undo a speculated action consensus rejected; returns the speculated
+    /// actions rolled back
+    pub fn reject(&mut self, game_id: &str, action_id: &ActionId) -> Vec<ActionId> {
+        let Some(speculation) = self.speculations.get_mut(game_id) else {
+            return Vec::new();
+        };
+        let empty = ActionLog::new();
+        let confirmed = self.logs.get(game_id).unwrap_or(&empty);
+        speculation.reject(confirmed, action_id)
+    }
+
+    /// Views of each game's confirmed state, to read without this manager
+    pub fn confirmed(&self) -> Arc<ConfirmedStates> {
+        self.confirmed.clone()
+    }
+
+    /// Publish a view of a game's log as it stands, once a batch of commits
+    /// is applied to it
+    pub fn publish(&self, game_id: &str) {
+        if let Some(log) = self.logs.get(game_id) {
+            self.confirmed.publish(StateView::of(game_id, log));
+        }
+    }
+
+    /// Actions committed to a game so far
+    pub fn log(&self, game_id: &str) -> Option<&ActionLog> {
+        self.logs.get(game_id)
+    }
+
+    /// Make a game's state diverge at `sequence`, as [`ActionLog::flip`]
+    #[cfg(test)]
+    pub(crate) fn flip(&mut self, game_id: &str, sequence: u64) {
+        self.logs.get_mut(game_id).unwrap().flip(sequence);
+    }
+
+    /// Actions applied to a game so far, speculated ones included
+    pub fn speculative_log(&self, game_id: &str) -> Option<&ActionLog> {
+        self.speculations
+            .get(game_id)
+            .map(Speculation::log)
+            .or_else(|| self.log(game_id))
+    }
+}
+
+/// The log a snapshot holds, once its entries are found to chain to its
+/// state hash
+fn decode(snapshot: &Snapshot) -> Result<ActionLog> {
+    let image: LogImage = bincode::deserialize(&snapshot.data)
+        .map_err(|e| SwarmhostError::Serialization(e.to_string()))?;
+    let log = ActionLog::from_image(image)?;
+    if log.sequence() != snapshot.sequence || log.hash() != snapshot.state_hash {
+        return Err(SwarmhostError::InvalidState(format!(
+            "Snapshot of {} at {} does not match its state hash {}",
+            snapshot.game_id,
+            snapshot.sequence,
+            short_id(&snapshot.state_hash)
+        )));
+    }
+    Ok(log)
+}
+
+/// Snapshot of a game's log, holding its [image](ActionLog::image)
+fn snapshot_of(game_id: &str, log: &ActionLog) -> Snapshot {
+    let data = bincode::serialize(&log.image()).unwrap_or_default();
+    Snapshot::new(game_id, log.sequence(), log.hash(), data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::consensus::SignedAction;
+    use crate::crypto;
+    use crate::node::PersistenceBackend;
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+    use std::sync::{Arc, Mutex};
+
+    /// A lasting backend over shared memory that stops writing, without
+    /// saying so, after its first `landing` batches, as a crash would
+    struct Crashing {
+        values: Arc<Mutex<MemoryBackend>>,
+        landing: usize,
+    }
+
+    impl StorageBackend for Crashing {
+        fn get(&self, namespace: &str, key: &str) -> Result<Option<Vec<u8>>> {
+            self.values.lock().unwrap().get(namespace, key)
+        }
+
+        fn keys(&self, namespace: &str, prefix: &str) -> Result<Vec<String>> {
+            self.values.lock().unwrap().keys(namespace, prefix)
+        }
+
+        fn write(&mut self, batch: WriteBatch) -> Result<()> {
+            if self.landing == 0 {
+                return Ok(());
+            }
+            self.landing -= 1;
+            self.values.lock().unwrap().write(batch)
+        }
+
+        fn durable(&self) -> bool {
+            true
+        }
+    }
+
+    #[test]
+    fn test_checkpoint_plus_tail_matches_a_full_replay() {
+        let validators = [
+            KeyPair::generate(),
+            KeyPair::generate(),
+            KeyPair::generate(),
+        ];
+        let ids = Membership::equal(validators.iter().map(KeyPair::public_key));
+        let actions: Vec<ActionId> = (0..150u32)
+            .map(|i| crypto::hash(&i.to_be_bytes()))
+            .collect();
+        let fresh = || StateManager::new(&StateConfig::default()).unwrap();
+
+        // Every validator replays everything, signing its log at 100
+        let mut replayed: Vec<StateManager> = validators.iter().map(|_| fresh()).collect();
+        for (manager, keypair) in replayed.iter_mut().zip(&validators) {
+            for (sequence, action_id) in (1..).zip(&actions) {
+                manager.apply("game", sequence, action_id).unwrap();
+                if sequence == 100 {
+                    manager.sign_checkpoint("game", keypair).unwrap();
+                }
+            }
+        }
+        let votes: Vec<CheckpointVote> = replayed
+            .iter_mut()
+            .flat_map(StateManager::take_signed)
+            .collect();
+        let full = &mut replayed[0];
+        let first = full.receive_checkpoint_vote(votes[0].clone(), &ids, 2);
+        assert!(first.unwrap().is_none());
+        let checkpoint = full
+            .receive_checkpoint_vote(votes[1].clone(), &ids, 2)
+            .unwrap()
+            .unwrap();
+        assert_eq!(checkpoint.sequence, 100);
+        assert!(checkpoint.verify(&ids, 2).is_ok());
+
+        // Served once attested, and only once
+        assert_eq!(full.sync_snapshot("game").unwrap().1, None);
+        let attested = full.attest("game", &validators[0]).unwrap().unwrap();
+        assert_eq!(full.attest("game", &validators[0]).unwrap(), None);
+
+        // A newcomer takes up the checkpoint's snapshot and the rest
+        let (snapshot, served) = full.sync_snapshot("game").unwrap();
+        assert_eq!(served.as_ref(), Some(&attested));
+        assert_eq!(attested.checkpoint, checkpoint);
+        assert!(attested.verify(&snapshot, &ids, 2).is_ok());
+        let mut joiner = fresh();
+        joiner.restore_checkpoint(&snapshot, &checkpoint).unwrap();
+        for (sequence, action_id) in (101..).zip(&actions[100..]) {
+            joiner.apply("game", sequence, action_id).unwrap();
+        }
+        assert_eq!(joiner.log("game"), full.log("game"));
+        assert_eq!(joiner.latest_checkpoint("game"), Some(&checkpoint));
+
+        // A snapshot that is not the log signed is refused
+        let mut forged = checkpoint.clone();
+        forged.log_root = [0; 32];
+        assert!(fresh().restore_checkpoint(&snapshot, &forged).is_err());
+    }
+
+    #[test]
+    fn test_snapshots_every_interval_up_to_the_cap() {
+        let actions: Vec<ActionId> = (0..350u32)
+            .map(|i| crypto::hash(&i.to_be_bytes()))
+            .collect();
+        for (cap, kept) in [(10, vec![100, 200, 300]), (2, vec![200, 300])] {
+            let config = StateConfig {
+                snapshot_interval: 100,
+                max_snapshots_in_memory: cap,
+                ..StateConfig::default()
+            };
+            let mut manager = StateManager::new(&config).unwrap();
+            for (sequence, action_id) in (1..).zip(&actions) {
+                manager.apply("game", sequence, action_id).unwrap();
+                for due in manager.take_due_snapshots() {
+                    assert!(manager.store_snapshot(due.encode(), 0).unwrap());
+                }
+            }
+            assert_eq!(manager.store.sequences("game").unwrap(), kept);
+
+            let latest = manager.latest_snapshot("game").unwrap().unwrap();
+            assert_eq!(latest.sequence, 300);
+            let log = ActionLog::from_entries(&actions[..300]).unwrap();
+            assert_eq!(latest.state_hash, log.hash());
+            assert_eq!(decode(&latest).unwrap(), log);
+        }
+    }
+
+    #[test]
+    fn test_snapshot_fetched_chunk_by_chunk_as_its_plan_says() {
+        let actions: Vec<ActionId> = (0..5000u32)
+            .map(|i| crypto::hash(&i.to_be_bytes()))
+            .collect();
+        let config = StateConfig {
+            snapshot_interval: 5000,
+            ..StateConfig::default()
+        };
+        let mut manager = StateManager::new(&config).unwrap();
+        for (sequence, action_id) in (1..).zip(&actions) {
+            manager.apply("game", sequence, action_id).unwrap();
+        }
+        for due in manager.take_due_snapshots() {
+            assert!(manager.store_snapshot(due.encode(), 0).unwrap());
+        }
+        let plan = manager.sync_plan("game", None).unwrap();
+        assert_eq!((plan.sequence, plan.chunks()), (5000, 3));
+        assert_eq!(plan.attestation, None);
+
+        let mut data = Vec::new();
+        for index in 0..plan.chunks() {
+            let chunk = manager
+                .sync_chunk("game", 5000, None, index)
+                .unwrap()
+                .unwrap();
+            assert!(plan.holds(&chunk));
+            data.extend(chunk.data);
+        }
+        assert_eq!(data.len() as u64, plan.size);
+        assert_eq!(manager.sync_chunk("game", 5000, None, 3).unwrap(), None);
+        assert_eq!(manager.sync_chunk("game", 4000, None, 0).unwrap(), None);
+
+        // A chunk altered on the way, even with its checksum redone, or
+        // put in the wrong place is not the plan's
+        let chunk = manager.sync_chunk("game", 5000, None, 1).unwrap().unwrap();
+        let mut altered = chunk.clone();
+        altered.data[7] ^= 1;
+        assert!(!plan.holds(&altered));
+        altered.checksum = crypto::hash(&altered.data);
+        assert!(!plan.holds(&altered));
+        let mut moved = chunk;
+        moved.offset += 1;
+        assert!(!plan.holds(&moved));
+
+        let mut joiner = StateManager::new(&config).unwrap();
+        joiner
+            .restore(&Snapshot::new("game", 5000, plan.state_hash, data))
+            .unwrap();
+        assert_eq!(joiner.log("game"), manager.log("game"));
+    }
+
+    #[test]
+    fn test_peer_holding_an_older_snapshot_is_planned_a_delta_or_else_it_whole() {
+        let actions: Vec<ActionId> = (0..3000u32)
+            .map(|i| crypto::hash(&i.to_be_bytes()))
+            .collect();
+        let config = StateConfig {
+            snapshot_interval: 1000,
+            ..StateConfig::default()
+        };
+        let mut manager = StateManager::new(&config).unwrap();
+        for (sequence, action_id) in (1..).zip(&actions) {
+            manager.apply("game", sequence, action_id).unwrap();
+            for due in manager.take_due_snapshots() {
+                assert!(manager.store_snapshot(due.encode(), 0).unwrap());
+            }
+        }
+        let old = manager.snapshot_at("game", 2000).unwrap().unwrap();
+        let new = manager.latest_snapshot("game").unwrap().unwrap();
+
+        let plan = manager
+            .sync_plan("game", Some(SnapshotBase::of(&old)))
+            .unwrap();
+        assert_eq!((plan.sequence, plan.delta_from), (3000, Some(2000)));
+        assert!(plan.size < new.data.len() as u64 / 2);
+        let data: Vec<u8> = (0..plan.chunks())
+            .flat_map(|index| {
+                let chunk = manager.sync_chunk("game", 3000, Some(2000), index);
+                let chunk = chunk.unwrap().unwrap();
+                assert!(plan.holds(&chunk));
+                chunk.data
+            })
+            .collect();
+        let delta = StateDelta::decode(&data).unwrap();
+        assert_eq!(apply_delta(&old, &delta).unwrap().data, new.data);
+        // The next peer from the same snapshot is sent the same delta
+        let again = manager.sync_plan("game", Some(SnapshotBase::of(&old)));
+        assert_eq!(again.unwrap(), plan);
+        assert_eq!(manager.deltas.len(), 1);
+
+        // A base we do not hold, or hold otherwise, is sent the snapshot
+        let mut forked = SnapshotBase::of(&old);
+        forked.checksum[0] ^= 1;
+        let gone = SnapshotBase {
+            sequence: 1500,
+            ..SnapshotBase::of(&old)
+        };
+        for base in [None, Some(forked), Some(gone), Some(SnapshotBase::of(&new))] {
+            assert_eq!(
+                manager.sync_plan("game", base).unwrap(),
+                new.plan(None),
+                "{:?}",
+                base
+            );
+        }
+        let chunk = manager.sync_chunk("game", 3000, Some(1500), 0);
+        assert_eq!(chunk.unwrap(), None);
+    }
+
+    #[test]
+    fn test_snapshots_on_disk_keep_those_at_checkpoints() {
+        let dir = tempfile::tempdir().unwrap();
+        let actions: Vec<ActionId> = (0..1000u32)
+            .map(|i| crypto::hash(&i.to_be_bytes()))
+            .collect();
+        let config = StateConfig {
+            snapshot_interval: 100,
+            max_snapshots_in_memory: 1,
+            max_snapshots_on_disk: 2,
+            persistence: PersistenceBackend::Directory {
+                path: dir.path().to_path_buf(),
+                fsync: false,
+            },
+            ..StateConfig::default()
+        };
+        let mut manager = StateManager::new(&config).unwrap();
+        for (sequence, action_id) in (1..).zip(&actions) {
+            manager.apply("game", sequence, action_id).unwrap();
+            for due in manager.take_due_snapshots() {
+                assert!(manager.store_snapshot(due.encode(), 200).unwrap());
+            }
+        }
+        let kept = vec![200, 400, 600, 800, 900, 1000];
+        assert_eq!(manager.store.sequences("game").unwrap(), kept);
+
+        let reopened = StateManager::new(&config).unwrap();
+        assert_eq!(reopened.stored_games().unwrap(), vec!["game"]);
+        let latest = reopened.latest_snapshot("game").unwrap().unwrap();
+        assert_eq!(decode(&latest).unwrap(), manager.logs["game"]);
+    }
+
+    #[test]
+    fn test_rolling_back_drops_later_commits_and_their_snapshots() {
+        let actions: Vec<ActionId> = (0..6u32).map(|i| crypto::hash(&i.to_be_bytes())).collect();
+        let config = StateConfig {
+            snapshot_interval: 2,
+            ..StateConfig::default()
+        };
+        let mut manager = StateManager::new(&config).unwrap().with_speculation(4);
+        for (sequence, action_id) in (1..).zip(&actions[..5]) {
+            manager.apply("game", sequence, action_id).unwrap();
+        }
+        assert!(manager.speculate("game", &actions[5]));
+        let mut due = manager.take_due_snapshots().into_iter();
+        let (at_2, at_4) = (due.next().unwrap().encode(), due.next().unwrap().encode());
+        for encoded in [at_2, at_4.clone()] {
+            assert!(manager.store_snapshot(encoded, 0).unwrap());
+        }
+
+        assert_eq!(manager.rollback_to("game", 3).unwrap(), vec![actions[5]]);
+        let rewound = manager.log("game").unwrap();
+        assert_eq!(rewound, &ActionLog::from_entries(&actions[..3]).unwrap());
+        assert_eq!(manager.store.sequences("game").unwrap(), vec![2]);
+        assert_eq!(manager.speculative_log("game"), Some(rewound));
+        // Nor does one still being encoded come back
+        assert!(!manager.store_snapshot(at_4, 0).unwrap());
+        assert_eq!(manager.store.sequences("game").unwrap(), vec![2]);
+
+        // The other branch follows on
+        manager.apply("game", 4, &actions[5]).unwrap();
+        assert_eq!(manager.log("game").unwrap().sequence(), 4);
+    }
+
+    #[test]
+    fn test_paused_speculation_resumes_once_the_game_is_rolled_back() {
+        let actions: Vec<ActionId> = (0..4u32).map(|i| crypto::hash(&i.to_be_bytes())).collect();
+        let mut manager = StateManager::new(&StateConfig::default())
+            .unwrap()
+            .with_speculation(4);
+        manager.apply("game", 1, &actions[0]).unwrap();
+        assert!(manager.speculate("game", &actions[1]));
+        assert!(manager.speculate("game", &actions[2]));
+
+        assert_eq!(manager.pause_speculation("game"), actions[1..3]);
+        assert_eq!(manager.speculative_log("game"), manager.log("game"));
+        assert!(!manager.speculate("game", &actions[3]));
+
+        manager.rollback_to("game", 1).unwrap();
+        assert!(manager.speculate("game", &actions[3]));
+    }
+
+    #[test]
+    fn test_rolling_back_and_recommitting_matches_a_log_that_never_forked() {
+        let action =
+            |branch: u32, i: u32| crypto::hash(&[branch.to_be_bytes(), i.to_be_bytes()].concat());
+        let config = StateConfig {
+            snapshot_interval: 10,
+            ..StateConfig::default()
+        };
+        let mut forked = StateManager::new(&config).unwrap();
+        for sequence in 1..=100 {
+            forked
+                .apply("game", sequence, &action(0, sequence as u32))
+                .unwrap();
+            for due in forked.take_due_snapshots() {
+                forked.store_snapshot(due.encode(), 0).unwrap();
+            }
+        }
+        // The snapshot at 60 is the newest before 63 to restore from
+        forked.store.remove("game", 50).unwrap();
+        forked.rollback_to("game", 63).unwrap();
+        assert_eq!(forked.log("game").unwrap().sequence(), 63);
+        assert_eq!(
+            forked.store.sequences("game").unwrap(),
+            vec![10, 20, 30, 40, 60]
+        );
+        for sequence in 64..=100 {
+            forked
+                .apply("game", sequence, &action(1, sequence as u32))
+                .unwrap();
+        }
+
+        let mut fresh = StateManager::new(&config).unwrap();
+        for sequence in 1..=100u64 {
+            let branch = u32::from(sequence > 63);
+            fresh
+                .apply("game", sequence, &action(branch, sequence as u32))
+                .unwrap();
+        }
+        let (forked, fresh) = (forked.log("game").unwrap(), fresh.log("game").unwrap());
+        assert_eq!(forked.hash(), fresh.hash());
+        assert_eq!(forked.merkle_root(), fresh.merkle_root());
+        assert_eq!(forked, fresh);
+    }
+
+    #[test]
+    fn test_rolling_back_past_a_checkpoint_is_refused() {
+        let validators = [KeyPair::generate(), KeyPair::generate()];
+        let ids = Membership::equal(validators.iter().map(KeyPair::public_key));
+        let actions: Vec<ActionId> = (0..30u32).map(|i| crypto::hash(&i.to_be_bytes())).collect();
+        let mut manager = StateManager::new(&StateConfig::default()).unwrap();
+        for (sequence, action_id) in (1..).zip(&actions[..20]) {
+            manager.apply("game", sequence, action_id).unwrap();
+        }
+        for keypair in &validators {
+            manager.sign_checkpoint("game", keypair).unwrap();
+        }
+        for vote in manager.take_signed() {
+            manager.receive_checkpoint_vote(vote, &ids, 2).unwrap();
+        }
+        assert_eq!(manager.checkpointed("game"), 20);
+        for (sequence, action_id) in (21..).zip(&actions[20..]) {
+            manager.apply("game", sequence, action_id).unwrap();
+        }
+
+        let refused = manager.rollback_to("game", 19);
+        assert!(matches!(refused, Err(SwarmhostError::InvalidState(_))));
+        assert_eq!(manager.log("game").unwrap().sequence(), 30);
+        manager.rollback_to("game", 20).unwrap();
+        assert_eq!(
+            manager.log("game").unwrap(),
+            &ActionLog::from_entries(&actions[..20]).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_log_stays_bounded_over_ten_thousand_commits() {
+        let config = StateConfig {
+            snapshot_interval: 100,
+            max_action_log_size: 500,
+            ..StateConfig::default()
+        };
+        let mut manager = StateManager::new(&config).unwrap();
+        let mut whole = ActionLog::new();
+        let mut longest = 0;
+        for sequence in 1..=10_000u64 {
+            let action_id = crypto::hash(&sequence.to_be_bytes());
+            manager.apply("game", sequence, &action_id).unwrap();
+            whole.append(sequence, &action_id).unwrap();
+            for due in manager.take_due_snapshots() {
+                manager.store_snapshot(due.encode(), 0).unwrap();
+            }
+            // A peer trailing behind, then stuck at 9000, holds pruning back
+            manager
+                .prune("game", sequence.saturating_sub(150).min(9000))
+                .unwrap();
+            if sequence <= 9000 {
+                longest = longest.max(manager.log("game").unwrap().entries().len());
+            }
+        }
+        assert!(longest <= 500, "{}", longest);
+        let log = manager.log("game").unwrap();
+        assert_eq!(log.pruned(), 9000);
+        assert_eq!(log.hash(), whole.hash());
+        assert_eq!(log.merkle_root(), whole.merkle_root());
+        assert_eq!(log.entries(), &whole.entries()[9000..]);
+
+        // Forced, it goes as far as the newest snapshot
+        assert_eq!(manager.prune("game", u64::MAX).unwrap(), 1000);
+        assert_eq!(manager.log("game").unwrap().pruned(), 10_000);
+
+        // And the newest snapshot, taken before, restores the log as it
+        // stood then
+        let latest = manager.latest_snapshot("game").unwrap().unwrap();
+        let mut restored = StateManager::new(&config).unwrap();
+        restored.restore(&latest).unwrap();
+        let restored = restored.log("game").unwrap();
+        assert_eq!(restored.pruned(), 9000);
+        assert_eq!(restored.hash(), whole.hash());
+        assert_eq!(restored.merkle_root(), whole.merkle_root());
+    }
+
+    #[test]
+    fn test_past_states_match_the_hash_chain_and_leave_the_log_alone() {
+        let config = StateConfig {
+            snapshot_interval: 10,
+            max_action_log_size: 20,
+            max_replay_actions: 8,
+            ..StateConfig::default()
+        };
+        let actions: Vec<ActionId> = (0..50u32).map(|i| crypto::hash(&i.to_be_bytes())).collect();
+        let mut manager = StateManager::new(&config).unwrap();
+        for (sequence, action_id) in (1..).zip(&actions) {
+            manager.apply("game", sequence, action_id).unwrap();
+            for due in manager.take_due_snapshots() {
+                manager.store_snapshot(due.encode(), 0).unwrap();
+            }
+        }
+        assert_eq!(manager.prune("game", 25).unwrap(), 25);
+        let live = manager.log("game").unwrap().clone();
+
+        // From the snapshot at 30, the log's base at 25, and the one at 30
+        // again, replaying at most 8 actions each
+        for sequence in [30, 37, 28, 25, 37] {
+            let past = manager.state_at("game", sequence, false).unwrap();
+            assert_eq!(past.sequence, sequence);
+            assert_eq!(Some(past.state_hash), live.hash_through(sequence));
+            assert_eq!(decode(&past).unwrap().sequence(), sequence);
+        }
+        assert_eq!(manager.history.len(), 4);
+
+        let refused = manager.state_at("game", 39, false).unwrap_err();
+        assert!(refused.to_string().contains("replayed"), "{}", refused);
+        let expensive = manager.state_at("game", 39, true).unwrap();
+        assert_eq!(Some(expensive.state_hash), live.hash_through(39));
+        assert!(matches!(
+            manager.state_at("game", 24, false),
+            Err(SwarmhostError::Pruned { earliest: 25, .. })
+        ));
+        assert!(manager.state_at("game", 51, false).is_err());
+        assert_eq!(manager.log("game"), Some(&live));
+    }
+
+    #[test]
+    fn test_shedding_keeps_the_newest_snapshot_and_what_it_covers_goes() {
+        let config = StateConfig {
+            snapshot_interval: 10,
+            ..StateConfig::default()
+        };
+        let actions: Vec<ActionId> = (0..45u32).map(|i| crypto::hash(&i.to_be_bytes())).collect();
+        let mut manager = StateManager::new(&config).unwrap();
+        for (sequence, action_id) in (1..).zip(&actions) {
+            manager.apply("game", sequence, action_id).unwrap();
+            for due in manager.take_due_snapshots() {
+                manager.store_snapshot(due.encode(), 0).unwrap();
+            }
+        }
+        manager.state_at("game", 35, false).unwrap();
+        let (snapshots, log) = (manager.snapshot_bytes(), manager.log_bytes());
+        assert!(manager.history_bytes() > 0);
+
+        manager.clear_history();
+        manager.shed_snapshots().unwrap();
+        assert_eq!(manager.history_bytes(), 0);
+        assert_eq!(manager.store.sequences("game").unwrap(), vec![40]);
+        assert!(manager.snapshot_bytes() < snapshots);
+        // Under max_action_log_size nothing is pruned, unless shed
+        assert_eq!(manager.prune("game", u64::MAX).unwrap(), 0);
+        assert_eq!(manager.shed_log("game").unwrap(), 40);
+        assert!(manager.log_bytes() < log);
+        assert_eq!(
+            manager.log("game").unwrap().hash(),
+            ActionLog::from_entries(&actions).unwrap().hash()
+        );
+    }
+
+    #[test]
+    fn test_pruned_entries_are_archived_and_not_rolled_back_past() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = StateConfig {
+            snapshot_interval: 10,
+            max_action_log_size: 20,
+            archive_pruned: true,
+            persistence: PersistenceBackend::Directory {
+                path: dir.path().to_path_buf(),
+                fsync: false,
+            },
+            ..StateConfig::default()
+        };
+        let actions: Vec<ActionId> = (0..45u32).map(|i| crypto::hash(&i.to_be_bytes())).collect();
+        let mut manager = StateManager::new(&config).unwrap().with_speculation(4);
+        for (sequence, action_id) in (1..).zip(&actions[..44]) {
+            manager.apply("game", sequence, action_id).unwrap();
+            for due in manager.take_due_snapshots() {
+                manager.store_snapshot(due.encode(), 0).unwrap();
+            }
+        }
+        assert!(manager.speculate("game", &actions[44]));
+        assert_eq!(manager.prune("game", 35).unwrap(), 35);
+        assert_eq!(manager.prune("game", 35).unwrap(), 0);
+        assert_eq!(
+            manager.store.archived("game", 1).unwrap(),
+            Some(actions[..35].to_vec())
+        );
+        assert_eq!(manager.speculative_log("game").unwrap().pruned(), 35);
+
+        let refused = manager.rollback_to("game", 34);
+        assert!(matches!(refused, Err(SwarmhostError::InvalidState(_))));
+        // The snapshot at 40 is newer than 36 and the one at 30 pruned past,
+        // so the log is rolled back from what it still holds
+        assert_eq!(manager.rollback_to("game", 36).unwrap(), vec![actions[44]]);
+        let log = manager.log("game").unwrap();
+        assert_eq!(
+            log.hash(),
+            ActionLog::from_entries(&actions[..36]).unwrap().hash()
+        );
+        assert_eq!(log.entries(), &actions[35..36]);
+        manager.apply("game", 37, &actions[44]).unwrap();
+    }
+
+    #[test]
+    fn test_a_crash_never_leaves_a_commit_without_its_log_entry() {
+        let config = StateConfig {
+            snapshot_interval: 10,
+            ..StateConfig::default()
+        };
+        let keypair = KeyPair::generate();
+        let commits: Vec<Commit> = (1..=60u64)
+            .map(|sequence| {
+                let data = sequence.to_be_bytes().to_vec();
+                let action = SignedAction::new(&keypair, "game", sequence, 1, data);
+                Commit::new(&keypair, sequence, action)
+            })
+            .collect();
+        let actions: Vec<ActionId> = commits.iter().map(|commit| commit.action.id()).collect();
+        let crashed_at = |values: &Arc<Mutex<MemoryBackend>>, landing| {
+            let backend = Crashing {
+                values: values.clone(),
+                landing,
+            };
+            let store = BackendStore::new(Box::new(backend));
+            StateManager::with_store(&config, Box::new(store))
+        };
+
+        let mut rng = StdRng::seed_from_u64(391);
+        for _ in 0..20 {
+            let values = Arc::new(Mutex::new(MemoryBackend::default()));
+            let mut manager = crashed_at(&values, rng.gen_range(0..80));
+            for commit in &commits {
+                manager.apply_commit(commit).unwrap();
+                for due in manager.take_due_snapshots() {
+                    manager.store_snapshot(due.encode(), 0).unwrap();
+                }
+            }
+
+            let mut reopened = crashed_at(&values, usize::MAX);
+            let stored = reopened.store.commits("game", 0).unwrap();
+            let entries = reopened.store.log_entries("game", 0).unwrap();
+            assert_eq!(stored.len(), entries.len());
+            for (commit, (sequence, action_id)) in stored.iter().zip(&entries) {
+                assert_eq!(commit.sequence, *sequence);
+                assert_eq!(commit.action.id(), *action_id);
+            }
+
+            // What is taken up is every commit that landed, and no more
+            let landed = stored.last().map_or(0, |commit| commit.sequence);
+            assert_eq!(stored.len() as u64, landed);
+            let restored = reopened.restore_stored("game", 0).unwrap();
+            assert_eq!(restored, (landed > 0).then_some(landed));
+            if landed > 0 {
+                let log = reopened.log("game").unwrap();
+                let whole = ActionLog::from_entries(&actions[..landed as usize]).unwrap();
+                assert_eq!(log.hash(), whole.hash());
+            }
+        }
     }
-}
\ No newline at end of file
+}
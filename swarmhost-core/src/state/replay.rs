@@ -0,0 +1,336 @@
+// state/replay.rs - Replaying a game's commits on top of a snapshot, checking
+// every signature and certificate on the way
+
+use super::{ActionLog, Checkpoint, Snapshot};
+use crate::consensus::{BlockHeader, Commit, Membership, beacon};
+use crate::crypto::{Hash, short_id};
+use crate::error::{Result, SwarmhostError};
+
+/// What a replay does on reaching a checkpoint its log disagrees with
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReplayMode {
+    /// Fail there
+    Strict,
+    /// Note it and go on, reporting every one at the end
+    Lenient,
+}
+
+/// What a replay checks the commits against
+#[derive(Debug, Clone, Copy)]
+pub struct ReplayCheck<'a> {
+    /// Validators in effect over the commits replayed
+    pub validators: &'a Membership,
+    /// Weight of their approvals or signatures making a quorum
+    pub required: u64,
+    /// Blocks commits may be certified by
+    pub blocks: &'a [BlockHeader],
+    /// Checkpoints recorded for the game, in any order
+    pub checkpoints: &'a [Checkpoint],
+    pub mode: ReplayMode,
+}
+
+/// A checkpoint the replayed log did not match
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Divergence {
+    pub sequence: u64,
+    /// State hash the checkpoint's signers signed
+    pub expected: Hash,
+    /// State hash the replay reached
+    pub replayed: Hash,
+}
+
+/// Where a replay ended
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReplayOutcome {
+    /// Sequence number of the last commit applied
+    pub sequence: u64,
+    pub state_hash: Hash,
+    pub log: ActionLog,
+    /// Checkpoints disagreed with, in sequence order; only ever filled in
+    /// lenient mode
+    pub divergences: Vec<Divergence>,
+}
+
+/// Restore a game's log from `snapshot`, then apply `commits` after it in
+/// sequence order, returning where that ends
+///
+/// Each commit's sequencer must be a validator, its signature and its
+/// actor's must hold, and a certificate, where it has one, must be a
+/// quorum's approval of its action or of one of `check.blocks` holding it.
+/// Commits at or before the snapshot are passed over; one failing a check,
+/// from another game, or leaving a gap fails the replay whatever the mode.
+/// At each checkpoint, which must itself verify, the log's state hash and
+/// log root are compared with those signed.
+pub fn replay(
+    snapshot: &Snapshot,
+    commits: &[Commit],
+    check: &ReplayCheck,
+) -> Result<ReplayOutcome> {
+    let mut log = super::decode(snapshot)?;
+    for checkpoint in check.checkpoints {
+        checkpoint.verify(check.validators, check.required)?;
+    }
+    let mut divergences = Vec::new();
+    let mut ordered: Vec<&Commit> = commits
+        .iter()
+        .filter(|commit| commit.sequence > snapshot.sequence)
+        .collect();
+    ordered.sort_by_key(|commit| commit.sequence);
+    for commit in ordered {
+        if commit.action.game_id != snapshot.game_id {
+            return Err(SwarmhostError::InvalidState(format!(
+                "commit at {} is of {}, not {}",
+                commit.sequence, commit.action.game_id, snapshot.game_id
+            )));
+        }
+        verify(commit, check)?;
+        log.append(commit.sequence, &commit.action.id())?;
+        let checkpoint = check.checkpoints.iter().find(|checkpoint| {
+            checkpoint.game_id == snapshot.game_id && checkpoint.sequence == commit.sequence
+        });
+        let Some(checkpoint) = checkpoint else {
+            continue;
+        };
+        if log.hash() == checkpoint.state_hash && log.merkle_root() == checkpoint.log_root {
+            continue;
+        }
+        if check.mode == ReplayMode::Strict {
+            return Err(SwarmhostError::InvalidState(format!(
+                "replay of {} reached {} at {}, checkpointed as {}",
+                snapshot.game_id,
+                short_id(&log.hash()),
+                commit.sequence,
+                short_id(&checkpoint.state_hash)
+            )));
+        }
+        divergences.push(Divergence {
+            sequence: commit.sequence,
+            expected: checkpoint.state_hash,
+            replayed: log.hash(),
+        });
+    }
+    Ok(ReplayOutcome {
+        sequence: log.sequence(),
+        state_hash: log.hash(),
+        log,
+        divergences,
+    })
+}
+
+/// Check a commit's signatures, and its certificate if it has one
+fn verify(commit: &Commit, check: &ReplayCheck) -> Result<()> {
+    if !check.validators.contains(&commit.sequencer) {
+        return Err(SwarmhostError::consensus(format!(
+            "commit at {} by {}, not a validator",
+            commit.sequence,
+            short_id(&commit.sequencer)
+        )));
+    }
+    commit.verify()?;
+    if commit.certificate.is_empty() {
+        return Ok(());
+    }
+    let subject = beacon::verify(&commit.certificate, check.validators, check.required)?;
+    let action_id = commit.action.id();
+    let certified = subject == action_id
+        || check
+            .blocks
+            .iter()
+            .any(|header| header.hash() == subject && header.actions.contains(&action_id));
+    if !certified {
+        return Err(SwarmhostError::consensus(format!(
+            "commit at {} is certified by {}, which does not hold {}",
+            commit.sequence,
+            short_id(&subject),
+            short_id(&action_id)
+        )));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::consensus::{Decision, SignedAction, Vote};
+    use crate::crypto::KeyPair;
+    use crate::state::CheckpointVote;
+
+    struct Game {
+        validators: Vec<KeyPair>,
+        membership: Membership,
+        commits: Vec<Commit>,
+        blocks: Vec<BlockHeader>,
+    }
+
+    /// 20 commits of one game, each certified by every validator, the odd
+    /// ones with their block
+    fn game() -> Game {
+        let validators: Vec<KeyPair> = (0..3).map(|_| KeyPair::generate()).collect();
+        let membership = Membership::equal(validators.iter().map(KeyPair::public_key));
+        let actor = KeyPair::generate();
+        let mut commits = Vec::new();
+        let mut blocks = Vec::new();
+        for sequence in 1..=20u64 {
+            let action = SignedAction::new(&actor, "game", sequence, 1, vec![sequence as u8]);
+            let mut subject = action.id();
+            if sequence % 2 == 1 {
+                let block = BlockHeader {
+                    round: sequence,
+                    proposer: validators[0].public_key(),
+                    parent: None,
+                    actions: vec![action.id()],
+                };
+                subject = block.hash();
+                blocks.push(block);
+            }
+            let certificate = validators
+                .iter()
+                .map(|keypair| Vote::new(keypair, subject, sequence, Decision::Approve))
+                .collect();
+            commits.push(Commit::certified(
+                &validators[0],
+                sequence,
+                action,
+                certificate,
+            ));
+        }
+        Game {
+            validators,
+            membership,
+            commits,
+            blocks,
+        }
+    }
+
+    fn checkpoint(game: &Game, log: &ActionLog) -> Checkpoint {
+        let votes: Vec<CheckpointVote> = game
+            .validators
+            .iter()
+            .map(|keypair| {
+                CheckpointVote::new(
+                    keypair,
+                    "game",
+                    log.sequence(),
+                    log.hash(),
+                    log.merkle_root(),
+                )
+            })
+            .collect();
+        Checkpoint::from_votes(&votes).unwrap()
+    }
+
+    fn log_of(commits: &[Commit]) -> ActionLog {
+        let ids: Vec<_> = commits.iter().map(|commit| commit.action.id()).collect();
+        ActionLog::from_entries(&ids).unwrap()
+    }
+
+    fn check<'a>(
+        game: &'a Game,
+        checkpoints: &'a [Checkpoint],
+        mode: ReplayMode,
+    ) -> ReplayCheck<'a> {
+        ReplayCheck {
+            validators: &game.membership,
+            required: 3,
+            blocks: &game.blocks,
+            checkpoints,
+            mode,
+        }
+    }
+
+    #[test]
+    fn test_replay_from_any_snapshot_lands_on_the_same_hash() {
+        let game = game();
+        let log = log_of(&game.commits);
+        let checkpoints = [
+            checkpoint(&game, &log_of(&game.commits[..10])),
+            checkpoint(&game, &log),
+        ];
+        let check = check(&game, &checkpoints, ReplayMode::Strict);
+
+        let mut shuffled = game.commits.clone();
+        shuffled.reverse();
+        for from in [0, 5, 10, 20] {
+            let snapshot = super::super::snapshot_of("game", &log_of(&game.commits[..from]));
+            let outcome = replay(&snapshot, &shuffled, &check).unwrap();
+            assert_eq!(outcome.sequence, 20);
+            assert_eq!(outcome.state_hash, log.hash());
+            assert_eq!(outcome.log, log);
+            assert!(outcome.divergences.is_empty());
+        }
+    }
+
+    #[test]
+    fn test_replay_catches_a_corrupted_entry() {
+        let game = game();
+        let snapshot = super::super::snapshot_of("game", &ActionLog::new());
+        let check = check(&game, &[], ReplayMode::Lenient);
+        let corrupt = |at: usize, corrupt: &dyn Fn(&mut Commit)| {
+            let mut commits = game.commits.clone();
+            corrupt(&mut commits[at]);
+            replay(&snapshot, &commits, &check)
+        };
+
+        assert!(corrupt(7, &|commit| commit.action.data[0] ^= 1).is_err());
+        assert!(corrupt(7, &|commit| commit.sequence = 30).is_err());
+        assert!(
+            corrupt(7, &|commit| {
+                commit.certificate.pop();
+            })
+            .is_err()
+        );
+        let other = game.commits[8].certificate.clone();
+        assert!(corrupt(7, &|commit| commit.certificate = other.clone()).is_err());
+        // An entry dropped from the log leaves a gap
+        let mut gapped = game.commits.clone();
+        gapped.remove(7);
+        assert!(replay(&snapshot, &gapped, &check).is_err());
+        assert!(replay(&snapshot, &game.commits, &check).is_ok());
+        // Nor is a commit certified by a block we were not given believed
+        let blockless = ReplayCheck {
+            blocks: &game.blocks[1..],
+            ..check
+        };
+        assert!(replay(&snapshot, &game.commits, &blockless).is_err());
+    }
+
+    #[test]
+    fn test_strict_replay_stops_at_a_divergence_and_lenient_reports_them_all() {
+        let game = game();
+        // Checkpoints signed over a log with the commits at 3 and 4 swapped
+        let mut swapped = game.commits.clone();
+        swapped.swap(2, 3);
+        let checkpoints = [
+            checkpoint(&game, &log_of(&swapped[..5])),
+            checkpoint(&game, &log_of(&game.commits[..2])),
+            checkpoint(&game, &log_of(&swapped[..15])),
+        ];
+        let snapshot = super::super::snapshot_of("game", &ActionLog::new());
+
+        let strict = check(&game, &checkpoints, ReplayMode::Strict);
+        assert!(replay(&snapshot, &game.commits, &strict).is_err());
+
+        let lenient = check(&game, &checkpoints, ReplayMode::Lenient);
+        let outcome = replay(&snapshot, &game.commits, &lenient).unwrap();
+        assert_eq!(outcome.state_hash, log_of(&game.commits).hash());
+        let diverged: Vec<u64> = outcome.divergences.iter().map(|d| d.sequence).collect();
+        assert_eq!(diverged, [5, 15]);
+        assert_eq!(outcome.divergences[0].expected, checkpoints[0].state_hash);
+        assert_eq!(
+            outcome.divergences[0].replayed,
+            log_of(&game.commits[..5]).hash()
+        );
+
+        // Nor is a checkpoint short of a quorum's signatures believed
+        let mut forged = checkpoints[1].clone();
+        forged.signatures.truncate(2);
+        assert!(
+            replay(
+                &snapshot,
+                &game.commits,
+                &check(&game, &[forged], ReplayMode::Lenient)
+            )
+            .is_err()
+        );
+    }
+}
@@ -0,0 +1,206 @@
+// state/snapshot.rs - Game state snapshots
+
+use super::log::LogImage;
+use super::{ActionLog, SnapshotAttestation};
+use crate::crypto::{self, Hash};
+use serde::{Deserialize, Serialize};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Bytes of snapshot data sent to a peer in one chunk
+pub const CHUNK_SIZE: usize = 64 * 1024;
+
+/// Serialized game state at a committed sequence number
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Snapshot {
+    /// Game session the snapshot belongs to
+    pub game_id: String,
+
+    /// Sequence number of the last action applied
+    pub sequence: u64,
+
+    /// State hash at `sequence`
+    pub state_hash: Hash,
+
+    /// Creation time in milliseconds since the Unix epoch
+    pub created_at_ms: u64,
+
+    /// Game state bytes as produced by the state machine
+    pub data: Vec<u8>,
+}
+
+impl Snapshot {
+    pub fn new(game_id: impl Into<String>, sequence: u64, state_hash: Hash, data: Vec<u8>) -> Self {
+        let created_at_ms = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_millis() as u64)
+            .unwrap_or(0);
+
+        Self {
+            game_id: game_id.into(),
+            sequence,
+            state_hash,
+            created_at_ms,
+            data,
+        }
+    }
+}
+
+impl Snapshot {
+    /// Hash of the data, which its producer signs in a
+    /// [`SnapshotAttestation`](super::SnapshotAttestation)
+    pub fn digest(&self) -> Hash {
+        crypto::hash(&self.data)
+    }
+
+    /// Plan for a peer catching up to fetch the snapshot, with its
+    /// attestation if it was taken at a checkpoint
+    pub fn plan(&self, attestation: Option<SnapshotAttestation>) -> SyncPlan {
+        SyncPlan {
+            game_id: self.game_id.clone(),
+            sequence: self.sequence,
+            state_hash: self.state_hash,
+            size: self.data.len() as u64,
+            chunk_size: CHUNK_SIZE as u32,
+            checksums: checksums(&self.data),
+            attestation,
+            delta_from: None,
+        }
+    }
+
+    /// Chunk `index` of the data, to send to a peer catching up; none past
+    /// the last
+    pub fn chunk(&self, index: u32) -> Option<SnapshotChunk> {
+        let (offset, data) = piece(&self.data, index)?;
+        Some(SnapshotChunk {
+            game_id: self.game_id.clone(),
+            sequence: self.sequence,
+            state_hash: self.state_hash,
+            index,
+            offset,
+            checksum: crypto::hash(&data),
+            data,
+        })
+    }
+}
+
+/// Hash of each [`CHUNK_SIZE`] chunk of `payload`, or of nothing for an
+/// empty one, sent as a single empty chunk
+pub(super) fn checksums(payload: &[u8]) -> Vec<Hash> {
+    match payload.is_empty() {
+        true => vec![crypto::hash(&[])],
+        false => payload.chunks(CHUNK_SIZE).map(crypto::hash).collect(),
+    }
+}
+
+/// Where chunk `index` of `payload` starts, and its bytes; none past the
+/// last
+pub(super) fn piece(payload: &[u8], index: u32) -> Option<(u64, Vec<u8>)> {
+    let count = payload.len().div_ceil(CHUNK_SIZE).max(1);
+    if index as usize >= count {
+        return None;
+    }
+    let start = index as usize * CHUNK_SIZE;
+    let end = (start + CHUNK_SIZE).min(payload.len());
+    Some((start as u64, payload[start..end].to_vec()))
+}
+
+/// What a peer catching up needs to fetch a snapshot chunk by chunk, from
+/// as many of the peers holding it as it likes
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SyncPlan {
+    pub game_id: String,
+    pub sequence: u64,
+    pub state_hash: Hash,
+    /// Bytes of snapshot data
+    pub size: u64,
+    /// Bytes in every chunk but the last
+    pub chunk_size: u32,
+    /// Hash of each chunk's data, in order
+    pub checksums: Vec<Hash>,
+    /// Checkpoint the snapshot was taken at, if any, and its producer's
+    /// signature over it
+    pub attestation: Option<SnapshotAttestation>,
+    /// Sequence of the snapshot the chunks make a [delta](super::StateDelta)
+    /// from, when they are one rather than the snapshot's data; `size` and
+    /// `checksums` are then those of the encoded delta
+    pub delta_from: Option<u64>,
+}
+
+impl SyncPlan {
+    /// Chunks the data, or the delta, is split into
+    pub fn chunks(&self) -> u32 {
+        self.checksums.len() as u32
+    }
+
+    /// Whether `chunk` is the one of the plan's snapshot it says it is, in
+    /// place, length and checksum
+    pub fn holds(&self, chunk: &SnapshotChunk) -> bool {
+        let Some(checksum) = self.checksums.get(chunk.index as usize) else {
+            return false;
+        };
+        let offset = chunk.index as u64 * self.chunk_size as u64;
+        let len = (self.size.saturating_sub(offset)).min(self.chunk_size as u64);
+        chunk.game_id == self.game_id
+            && chunk.sequence == self.sequence
+            && chunk.state_hash == self.state_hash
+            && chunk.offset == offset
+            && chunk.data.len() as u64 == len
+            && chunk.checksum == *checksum
+            && crypto::hash(&chunk.data) == *checksum
+    }
+}
+
+/// A game's log as it stood when a snapshot fell due, to be encoded off
+/// the commit path
+#[derive(Debug, Clone)]
+pub struct SnapshotDue {
+    game_id: String,
+    sequence: u64,
+    state_hash: Hash,
+    image: LogImage,
+    /// Rollbacks and restores before it was taken, to tell a stale one
+    generation: u64,
+}
+
+impl SnapshotDue {
+    pub(super) fn of(game_id: &str, log: &ActionLog, generation: u64) -> Self {
+        Self {
+            game_id: game_id.to_string(),
+            sequence: log.sequence(),
+            state_hash: log.hash(),
+            image: log.image(),
+            generation,
+        }
+    }
+
+    /// Serialize the log into its snapshot, the slow part, best left to a
+    /// blocking task
+    pub fn encode(self) -> EncodedSnapshot {
+        let data = bincode::serialize(&self.image).unwrap_or_default();
+        EncodedSnapshot {
+            snapshot: Snapshot::new(self.game_id, self.sequence, self.state_hash, data),
+            generation: self.generation,
+        }
+    }
+}
+
+/// A snapshot encoded from a [`SnapshotDue`], ready to be stored
+#[derive(Debug, Clone)]
+pub struct EncodedSnapshot {
+    pub snapshot: Snapshot,
+    pub(super) generation: u64,
+}
+
+/// A piece of a snapshot's data, with what identifies the snapshot
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SnapshotChunk {
+    pub game_id: String,
+    pub sequence: u64,
+    pub state_hash: Hash,
+    pub index: u32,
+    /// Where in the data the chunk starts
+    pub offset: u64,
+    /// Hash of the chunk's data
+    pub checksum: Hash,
+    pub data: Vec<u8>,
+}
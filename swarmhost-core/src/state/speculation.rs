@@ -0,0 +1,187 @@
+// state/speculation.rs - Applying actions ahead of consensus, and undoing them
+
+use super::log::ActionLog;
+use crate::consensus::ActionId;
+use std::collections::VecDeque;
+
+/// A game's state as it will be if the actions speculated on are committed
+/// in the order they were proposed
+///
+/// The speculative log is the confirmed log followed by the speculated
+/// actions. When consensus orders another action first, or rejects one of
+/// them, it is rebuilt from the confirmed log and the speculated actions
+/// still standing re-applied.
+#[derive(Debug, Clone)]
+pub struct Speculation {
+    log: ActionLog,
+    /// Actions applied speculatively but not yet confirmed, oldest first
+    speculated: VecDeque<ActionId>,
+}
+
+impl Speculation {
+    /// Start speculating on top of `confirmed`
+    pub fn new(confirmed: &ActionLog) -> Self {
+        Self {
+            log: confirmed.clone(),
+            speculated: VecDeque::new(),
+        }
+    }
+
+    /// The speculative state
+    pub fn log(&self) -> &ActionLog {
+        &self.log
+    }
+
+    /// Actions applied speculatively but not yet confirmed
+    pub fn depth(&self) -> usize {
+        self.speculated.len()
+    }
+
+    /// Apply `action_id` speculatively, unless it is already applied or
+    /// `max_depth` actions are waiting to be confirmed
+    pub fn speculate(&mut self, action_id: &ActionId, max_depth: usize) -> bool {
+        if self.speculated.len() >= max_depth || self.log.contains(action_id) {
+            return false;
+        }
+        let sequence = self.log.sequence() + 1;
+        if self.log.append(sequence, action_id).is_err() {
+            return false;
+        }
+        self.speculated.push_back(*action_id);
+        true
+    }
+
+    /// Follow `confirmed`, to which `action_id` was just appended; returns
+    /// the speculated actions rolled back
+    ///
+    /// Nothing is rolled back when it was the next action we speculated on.
+    pub fn confirm(&mut self, confirmed: &ActionLog, action_id: &ActionId) -> Vec<ActionId> {
+        if self.speculated.front() == Some(action_id) {
+            self.speculated.pop_front();
+            return Vec::new();
+        }
+        self.speculated.retain(|speculated| speculated != action_id);
+        let reverted: Vec<ActionId> = self.speculated.iter().copied().collect();
+        self.rebuild(confirmed);
+        reverted
+    }
+
+    /// Drop `action_id`, which consensus rejected; returns the speculated
+    /// actions rolled back: it and every one applied after it
+    pub fn reject(&mut self, confirmed: &ActionLog, action_id: &ActionId) -> Vec<ActionId> {
+        let Some(at) = self.speculated.iter().position(|id| id == action_id) else {
+            return Vec::new();
+        };
+        let reverted: Vec<ActionId> = self.speculated.range(at..).copied().collect();
+        self.speculated.remove(at);
+        self.rebuild(confirmed);
+        reverted
+    }
+
+    /// Prune the speculative log as the confirmed one was, up to `sequence`
+    pub fn prune(&mut self, sequence: u64) {
+        self.log.prune(sequence);
+    }
+
+    /// Roll back to `confirmed` and re-apply the actions still speculated
+    fn rebuild(&mut self, confirmed: &ActionLog) {
+        self.log = confirmed.clone();
+        let speculated = std::mem::take(&mut self.speculated);
+        for action_id in speculated {
+            let sequence = self.log.sequence() + 1;
+            if self.log.append(sequence, &action_id).is_ok() {
+                self.speculated.push_back(action_id);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const A: ActionId = [1; 32];
+    const B: ActionId = [2; 32];
+    const C: ActionId = [3; 32];
+
+    /// Append to the confirmed log and let the speculation follow
+    fn commit(
+        confirmed: &mut ActionLog,
+        speculation: &mut Speculation,
+        action_id: ActionId,
+    ) -> Vec<ActionId> {
+        let sequence = confirmed.sequence() + 1;
+        confirmed.append(sequence, &action_id).unwrap();
+        speculation.confirm(confirmed, &action_id)
+    }
+
+    /// The log a node applying only confirmed actions ends up with
+    fn pessimistic(order: &[ActionId]) -> ActionLog {
+        let mut log = ActionLog::new();
+        for (i, action_id) in order.iter().enumerate() {
+            log.append(i as u64 + 1, action_id).unwrap();
+        }
+        log
+    }
+
+    #[test]
+    fn test_matching_order_reverts_nothing() {
+        let mut confirmed = ActionLog::new();
+        let mut speculation = Speculation::new(&confirmed);
+        assert!(speculation.speculate(&A, 8));
+        assert!(speculation.speculate(&B, 8));
+        let ahead = speculation.log().hash();
+
+        assert!(commit(&mut confirmed, &mut speculation, A).is_empty());
+        assert_eq!(speculation.log().hash(), ahead);
+        assert!(commit(&mut confirmed, &mut speculation, B).is_empty());
+        assert_eq!(speculation.depth(), 0);
+        assert_eq!(speculation.log(), &pessimistic(&[A, B]));
+    }
+
+    #[test]
+    fn test_competing_action_first_rolls_back_and_reapplies() {
+        let mut confirmed = ActionLog::new();
+        let mut speculation = Speculation::new(&confirmed);
+        speculation.speculate(&A, 8);
+        speculation.speculate(&B, 8);
+
+        // C, which we never saw proposed, is ordered ahead of both
+        assert_eq!(commit(&mut confirmed, &mut speculation, C), vec![A, B]);
+        assert_eq!(speculation.log(), &pessimistic(&[C, A, B]));
+
+        // B then wins over A
+        assert_eq!(commit(&mut confirmed, &mut speculation, B), vec![A]);
+        assert!(commit(&mut confirmed, &mut speculation, A).is_empty());
+        assert_eq!(speculation.log().hash(), pessimistic(&[C, B, A]).hash());
+    }
+
+    #[test]
+    fn test_rejected_action_rolls_back_what_followed() {
+        let mut confirmed = ActionLog::new();
+        let mut speculation = Speculation::new(&confirmed);
+        for action_id in [A, B, C] {
+            speculation.speculate(&action_id, 8);
+        }
+
+        assert_eq!(speculation.reject(&confirmed, &B), vec![B, C]);
+        assert_eq!(speculation.log(), &pessimistic(&[A, C]));
+        assert!(speculation.reject(&confirmed, &B).is_empty());
+
+        commit(&mut confirmed, &mut speculation, A);
+        commit(&mut confirmed, &mut speculation, C);
+        assert_eq!(speculation.log(), &confirmed);
+    }
+
+    #[test]
+    fn test_depth_limit_falls_back_to_waiting() {
+        let mut confirmed = ActionLog::new();
+        let mut speculation = Speculation::new(&confirmed);
+        assert!(speculation.speculate(&A, 1));
+        assert!(!speculation.speculate(&B, 1));
+        assert!(!speculation.speculate(&A, 1));
+
+        assert!(commit(&mut confirmed, &mut speculation, A).is_empty());
+        assert!(speculation.speculate(&B, 1));
+    }
+}
@@ -0,0 +1,519 @@
+// state/storage.rs - Key-value backends that whatever a node keeps across
+// restarts is written through, a batch at a time
+
+use crate::error::{Result, SwarmhostError};
+use crate::node::PersistenceBackend;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+/// Somewhere to keep values under keys grouped into namespaces
+///
+/// A namespace is a name of lowercase letters, digits, `_` and `-`; a key
+/// is one or more such names, which may also hold uppercase letters and
+/// `.`, joined by `/`. Keys are listed in ascending order.
+pub trait StorageBackend: Send + Sync {
+    /// The value under `key`, none if there is none
+    fn get(&self, namespace: &str, key: &str) -> Result<Option<Vec<u8>>>;
+
+    /// Every key in `namespace` starting with `prefix`, ascending
+    fn keys(&self, namespace: &str, prefix: &str) -> Result<Vec<String>>;
+
+    /// Make every write in `batch`; a crash leaves all of them made or none
+    fn write(&mut self, batch: WriteBatch) -> Result<()>;
+
+    /// Whether what is written outlives the process
+    fn durable(&self) -> bool;
+
+    /// Bytes of the values in `namespace` held in memory; none for a
+    /// backend writing them out
+    fn memory_bytes(&self, _namespace: &str) -> u64 {
+        0
+    }
+
+    /// Set the value under `key`, replacing any there
+    fn put(&mut self, namespace: &str, key: &str, value: Vec<u8>) -> Result<()> {
+        let mut batch = WriteBatch::new();
+        batch.put(namespace, key, value);
+        self.write(batch)
+    }
+
+    /// Remove the value under `key`, if there is one
+    fn delete(&mut self, namespace: &str, key: &str) -> Result<()> {
+        let mut batch = WriteBatch::new();
+        batch.delete(namespace, key);
+        self.write(batch)
+    }
+}
+
+/// Writes a backend makes together or not at all, in order
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct WriteBatch {
+    ops: Vec<BatchOp>,
+}
+
+/// One write of a [`WriteBatch`]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum BatchOp {
+    Put {
+        namespace: String,
+        key: String,
+        value: Vec<u8>,
+    },
+    Delete {
+        namespace: String,
+        key: String,
+    },
+}
+
+impl WriteBatch {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn put(&mut self, namespace: &str, key: &str, value: Vec<u8>) {
+        self.ops.push(BatchOp::Put {
+            namespace: namespace.to_string(),
+            key: key.to_string(),
+            value,
+        });
+    }
+
+    pub fn delete(&mut self, namespace: &str, key: &str) {
+        self.ops.push(BatchOp::Delete {
+            namespace: namespace.to_string(),
+            key: key.to_string(),
+        });
+    }
+
+    pub fn ops(&self) -> &[BatchOp] {
+        &self.ops
+    }
+
+    pub fn len(&self) -> usize {
+        self.ops.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ops.is_empty()
+    }
+
+    /// Refuse the batch if any namespace or key in it is not one every
+    /// backend can hold
+    fn check(&self) -> Result<()> {
+        for op in &self.ops {
+            let (BatchOp::Put { namespace, key, .. } | BatchOp::Delete { namespace, key }) = op;
+            check_key(namespace, key)?;
+        }
+        Ok(())
+    }
+}
+
+/// Refuse a namespace or key of other than the names [`StorageBackend`]
+/// allows
+fn check_key(namespace: &str, key: &str) -> Result<()> {
+    let namespaced = !namespace.is_empty()
+        && namespace
+            .bytes()
+            .all(|b| b.is_ascii_lowercase() || b.is_ascii_digit() || b == b'_' || b == b'-');
+    let keyed = key.split('/').all(|name| {
+        !name.is_empty()
+            && name != "."
+            && name != ".."
+            && !name.ends_with(".tmp")
+            && name
+                .bytes()
+                .all(|b| b.is_ascii_alphanumeric() || matches!(b, b'_' | b'-' | b'.'))
+    });
+    if !namespaced || !keyed {
+        return Err(SwarmhostError::InvalidState(format!(
+            "Cannot store under {:?} in {:?}",
+            key, namespace
+        )));
+    }
+    Ok(())
+}
+
+/// Build the backend selected by the config
+pub fn open_backend(backend: &PersistenceBackend) -> Result<Box<dyn StorageBackend>> {
+    match backend {
+        PersistenceBackend::InMemory => Ok(Box::new(MemoryBackend::default())),
+        PersistenceBackend::Directory { path, fsync } => {
+            Ok(Box::new(DirectoryBackend::open(path.clone(), *fsync)?))
+        }
+        #[cfg(feature = "sled")]
+        PersistenceBackend::Sled { path, fsync } => Ok(Box::new(SledBackend::open(path, *fsync)?)),
+        #[cfg(not(feature = "sled"))]
+        PersistenceBackend::Sled { .. } => Err(SwarmhostError::Config(
+            "the sled backend needs the 'sled' feature".to_string(),
+        )),
+    }
+}
+
+/// Values held in memory only; lost when the process exits
+#[derive(Default)]
+pub struct MemoryBackend {
+    values: BTreeMap<(String, String), Vec<u8>>,
+}
+
+impl StorageBackend for MemoryBackend {
+    fn get(&self, namespace: &str, key: &str) -> Result<Option<Vec<u8>>> {
+        Ok(self
+            .values
+            .get(&(namespace.to_string(), key.to_string()))
+            .cloned())
+    }
+
+    fn keys(&self, namespace: &str, prefix: &str) -> Result<Vec<String>> {
+        let from = (namespace.to_string(), prefix.to_string());
+        Ok(self
+            .values
+            .range(from..)
+            .map(|(at, _)| at)
+            .take_while(|(held, key)| held == namespace && key.starts_with(prefix))
+            .map(|(_, key)| key.clone())
+            .collect())
+    }
+
+    fn write(&mut self, batch: WriteBatch) -> Result<()> {
+        batch.check()?;
+        for op in batch.ops {
+            match op {
+                BatchOp::Put {
+                    namespace,
+                    key,
+                    value,
+                } => {
+                    self.values.insert((namespace, key), value);
+                }
+                BatchOp::Delete { namespace, key } => {
+                    self.values.remove(&(namespace, key));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn durable(&self) -> bool {
+        false
+    }
+
+    fn memory_bytes(&self, namespace: &str) -> u64 {
+        self.values
+            .iter()
+            .filter(|((held, _), _)| held == namespace)
+            .map(|((_, key), value)| key.len() + value.len())
+            .sum::<usize>() as u64
+    }
+}
+
+/// Write `bytes` to a temporary file beside `path` and rename it over, so a
+/// crash mid-write leaves what was at `path` whole
+pub(super) fn write_atomically(path: &Path, bytes: &[u8], fsync: bool) -> Result<()> {
+    let written = path.with_extension("tmp");
+    let mut file = fs::File::create(&written)?;
+    file.write_all(bytes)?;
+    if fsync {
+        file.sync_all()?;
+    }
+    fs::rename(written, path)?;
+    Ok(())
+}
+
+/// Values written as files under a directory
+///
+/// Layout: `<root>/<namespace>/<key>`, each key's names a directory down,
+/// every file written to a temporary one renamed over the last. A batch of
+/// more than one write goes first to `<root>/batch.journal`, the same way,
+/// then is made and the journal removed; one left behind by a crash is made
+/// again on opening, so the batch is made whole or, if the journal never
+/// landed, not at all.
+pub struct DirectoryBackend {
+    root: PathBuf,
+    fsync: bool,
+}
+
+impl DirectoryBackend {
+    pub fn open(root: PathBuf, fsync: bool) -> Result<Self> {
+        fs::create_dir_all(&root)?;
+        let backend = Self { root, fsync };
+        let journal = backend.journal_path();
+        if journal.exists() {
+            let batch: WriteBatch = bincode::deserialize(&fs::read(&journal)?).map_err(|e| {
+                SwarmhostError::Serialization(format!("{}: {}", journal.display(), e))
+            })?;
+            tracing::info!(
+                "Finishing a batch of {} writes cut short under {}",
+                batch.len(),
+                backend.root.display()
+            );
+            backend.make(&batch)?;
+            fs::remove_file(journal)?;
+        }
+        Ok(backend)
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// File the value under `key` is kept in
+    pub fn path(&self, namespace: &str, key: &str) -> PathBuf {
+        self.root.join(namespace).join(key)
+    }
+
+    fn journal_path(&self) -> PathBuf {
+        self.root.join("batch.journal")
+    }
+
+    /// Make a batch's writes one after another, each whole on its own
+    fn make(&self, batch: &WriteBatch) -> Result<()> {
+        for op in batch.ops() {
+            match op {
+                BatchOp::Put {
+                    namespace,
+                    key,
+                    value,
+                } => {
+                    let path = self.path(namespace, key);
+                    if let Some(dir) = path.parent() {
+                        fs::create_dir_all(dir)?;
+                    }
+                    write_atomically(&path, value, self.fsync)?;
+                }
+                BatchOp::Delete { namespace, key } => {
+                    let path = self.path(namespace, key);
+                    if path.exists() {
+                        fs::remove_file(path)?;
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Keys of the files under `dir`, each `prefix` followed by its path below
+/// `dir`, skipping those a crash left half written
+fn walk(dir: &Path, prefix: &str, keys: &mut Vec<String>) -> Result<()> {
+    for entry in fs::read_dir(dir)?.filter_map(|entry| entry.ok()) {
+        let Ok(name) = entry.file_name().into_string() else {
+            continue;
+        };
+        let key = format!("{}{}", prefix, name);
+        if entry.file_type()?.is_dir() {
+            walk(&entry.path(), &format!("{}/", key), keys)?;
+        } else if !name.ends_with(".tmp") {
+            keys.push(key);
+        }
+    }
+    Ok(())
+}
+
+impl StorageBackend for DirectoryBackend {
+    fn get(&self, namespace: &str, key: &str) -> Result<Option<Vec<u8>>> {
+        check_key(namespace, key)?;
+        let path = self.path(namespace, key);
+        if !path.is_file() {
+            return Ok(None);
+        }
+        Ok(Some(fs::read(path)?))
+    }
+
+    fn keys(&self, namespace: &str, prefix: &str) -> Result<Vec<String>> {
+        // Only the directory the prefix's whole names lead to is walked
+        let parent = prefix.rfind('/').map_or("", |at| &prefix[..=at]);
+        let dir = self.root.join(namespace).join(parent);
+        if !dir.is_dir() {
+            return Ok(Vec::new());
+        }
+        let mut keys = Vec::new();
+        walk(&dir, parent, &mut keys)?;
+        keys.retain(|key| key.starts_with(prefix));
+        keys.sort();
+        Ok(keys)
+    }
+
+    fn write(&mut self, batch: WriteBatch) -> Result<()> {
+        batch.check()?;
+        if batch.len() <= 1 {
+            return self.make(&batch);
+        }
+        let journal = self.journal_path();
+        let bytes =
+            bincode::serialize(&batch).map_err(|e| SwarmhostError::Serialization(e.to_string()))?;
+        write_atomically(&journal, &bytes, self.fsync)?;
+        self.make(&batch)?;
+        fs::remove_file(journal)?;
+        Ok(())
+    }
+
+    fn durable(&self) -> bool {
+        true
+    }
+}
+
+/// Values kept in a sled database, every key prefixed by its namespace and
+/// a zero byte; a batch is one of sled's, made atomically
+#[cfg(feature = "sled")]
+pub struct SledBackend {
+    db: sled::Db,
+    fsync: bool,
+}
+
+#[cfg(feature = "sled")]
+impl SledBackend {
+    pub fn open(path: &Path, fsync: bool) -> Result<Self> {
+        let db = sled::open(path).map_err(sled_error)?;
+        Ok(Self { db, fsync })
+    }
+
+    fn key(namespace: &str, key: &str) -> Vec<u8> {
+        [namespace.as_bytes(), &[0], key.as_bytes()].concat()
+    }
+}
+
+#[cfg(feature = "sled")]
+fn sled_error(e: sled::Error) -> SwarmhostError {
+    match e {
+        sled::Error::Io(e) => SwarmhostError::Network(e),
+        e => SwarmhostError::InvalidState(format!("sled: {}", e)),
+    }
+}
+
+#[cfg(feature = "sled")]
+impl StorageBackend for SledBackend {
+    fn get(&self, namespace: &str, key: &str) -> Result<Option<Vec<u8>>> {
+        let value = self.db.get(Self::key(namespace, key)).map_err(sled_error)?;
+        Ok(value.map(|value| value.to_vec()))
+    }
+
+    fn keys(&self, namespace: &str, prefix: &str) -> Result<Vec<String>> {
+        let skip = namespace.len() + 1;
+        self.db
+            .scan_prefix(Self::key(namespace, prefix))
+            .keys()
+            .map(|key| {
+                let key = key.map_err(sled_error)?;
+                String::from_utf8(key[skip..].to_vec())
+                    .map_err(|e| SwarmhostError::Serialization(e.to_string()))
+            })
+            .collect()
+    }
+
+    fn write(&mut self, batch: WriteBatch) -> Result<()> {
+        batch.check()?;
+        let mut writes = sled::Batch::default();
+        for op in batch.ops {
+            match op {
+                BatchOp::Put {
+                    namespace,
+                    key,
+                    value,
+                } => writes.insert(Self::key(&namespace, &key), value),
+                BatchOp::Delete { namespace, key } => writes.remove(Self::key(&namespace, &key)),
+            }
+        }
+        self.db.apply_batch(writes).map_err(sled_error)?;
+        if self.fsync {
+            self.db.flush().map_err(sled_error)?;
+        }
+        Ok(())
+    }
+
+    fn durable(&self) -> bool {
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn exercise(backend: &mut dyn StorageBackend) {
+        assert_eq!(backend.get("games", "a/1").unwrap(), None);
+        let mut batch = WriteBatch::new();
+        batch.put("games", "a/2", b"two".to_vec());
+        batch.put("games", "a/1", b"one".to_vec());
+        batch.put("games", "ab/1", b"other".to_vec());
+        batch.put("safety", "record", b"safe".to_vec());
+        backend.write(batch).unwrap();
+
+        assert_eq!(backend.get("games", "a/1").unwrap(), Some(b"one".to_vec()));
+        assert_eq!(backend.keys("games", "a/").unwrap(), ["a/1", "a/2"]);
+        assert_eq!(backend.keys("games", "a").unwrap(), ["a/1", "a/2", "ab/1"]);
+        assert_eq!(backend.keys("games", "").unwrap().len(), 3);
+        assert_eq!(backend.keys("safety", "").unwrap(), ["record"]);
+        assert!(backend.keys("peers", "").unwrap().is_empty());
+
+        let mut batch = WriteBatch::new();
+        batch.delete("games", "a/1");
+        batch.put("games", "a/2", b"second".to_vec());
+        batch.delete("games", "missing");
+        backend.write(batch).unwrap();
+        assert_eq!(backend.get("games", "a/1").unwrap(), None);
+        assert_eq!(
+            backend.get("games", "a/2").unwrap(),
+            Some(b"second".to_vec())
+        );
+        backend.delete("safety", "record").unwrap();
+        assert!(backend.keys("safety", "").unwrap().is_empty());
+
+        // Nor is a key any backend might not hold taken, or half a batch
+        // holding one made
+        for (namespace, key) in [("games", "../up"), ("Games", "a"), ("games", "a//b")] {
+            let mut batch = WriteBatch::new();
+            batch.put("games", "a/3", b"three".to_vec());
+            batch.put(namespace, key, Vec::new());
+            assert!(backend.write(batch).is_err(), "{}/{}", namespace, key);
+        }
+        assert_eq!(backend.get("games", "a/3").unwrap(), None);
+    }
+
+    #[test]
+    fn test_memory_backend() {
+        exercise(&mut MemoryBackend::default());
+    }
+
+    #[test]
+    fn test_directory_backend() {
+        let dir = tempfile::tempdir().unwrap();
+        exercise(&mut DirectoryBackend::open(dir.path().to_path_buf(), true).unwrap());
+    }
+
+    #[cfg(feature = "sled")]
+    #[test]
+    fn test_sled_backend() {
+        let dir = tempfile::tempdir().unwrap();
+        exercise(&mut SledBackend::open(dir.path(), false).unwrap());
+    }
+
+    #[test]
+    fn test_directory_batch_cut_short_is_finished_on_opening() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut batch = WriteBatch::new();
+        batch.put("commits", "g/1", b"commit".to_vec());
+        batch.put("log", "g/1", b"entry".to_vec());
+
+        // A crash after the journal landed but before the batch was made
+        // in full
+        let backend = DirectoryBackend::open(dir.path().to_path_buf(), false).unwrap();
+        let bytes = bincode::serialize(&batch).unwrap();
+        write_atomically(&backend.journal_path(), &bytes, false).unwrap();
+        let mut first = WriteBatch::new();
+        first.ops.push(batch.ops[0].clone());
+        backend.make(&first).unwrap();
+        drop(backend);
+
+        let reopened = DirectoryBackend::open(dir.path().to_path_buf(), false).unwrap();
+        assert_eq!(reopened.get("log", "g/1").unwrap(), Some(b"entry".to_vec()));
+        assert!(!reopened.journal_path().exists());
+
+        // One before the journal landed leaves nothing
+        fs::write(reopened.journal_path().with_extension("tmp"), b"half").unwrap();
+        let reopened = DirectoryBackend::open(dir.path().to_path_buf(), false).unwrap();
+        assert_eq!(reopened.keys("commits", "").unwrap(), ["g/1"]);
+    }
+}
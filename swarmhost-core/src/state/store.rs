@@ -0,0 +1,675 @@
+// state/store.rs - Snapshots, and everything else a node keeps across
+// restarts, laid out over a storage backend
+
+use super::SnapshotAttestation;
+use super::snapshot::Snapshot;
+use super::storage::{self, StorageBackend, WriteBatch};
+use crate::consensus::{ActionId, Commit, SafetyRecord};
+use crate::crypto::{self, Hash};
+use crate::error::{Result, SwarmhostError};
+use crate::network::PeerRecord;
+use crate::node::PersistenceBackend;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::Path;
+
+/// Version of the snapshot format [`BackendStore`] writes
+pub const SNAPSHOT_FORMAT_VERSION: u16 = 1;
+
+/// Somewhere to keep snapshots, the commits since, the consensus safety
+/// record and the peers we know of
+pub trait SnapshotStore: Send + Sync {
+    /// Store a snapshot, replacing any existing one at the same sequence
+    fn save(&mut self, snapshot: &Snapshot) -> Result<()>;
+
+    /// Load the snapshot for a game at an exact sequence
+    fn load(&self, game_id: &str, sequence: u64) -> Result<Option<Snapshot>>;
+
+    /// Sequence numbers of every stored snapshot for a game, ascending
+    fn sequences(&self, game_id: &str) -> Result<Vec<u64>>;
+
+    /// Remove the snapshot at `sequence`, and its attestation, if present
+    fn remove(&mut self, game_id: &str, sequence: u64) -> Result<()>;
+
+    /// Store the attestation of a snapshot beside it
+    fn save_attestation(&mut self, attestation: &SnapshotAttestation) -> Result<()>;
+
+    /// The attestation stored beside a game's snapshot at `sequence`
+    fn attestation(&self, game_id: &str, sequence: u64) -> Result<Option<SnapshotAttestation>>;
+
+    /// Every game with a snapshot or a commit stored, in order of id
+    fn games(&self) -> Result<Vec<String>>;
+
+    /// Store the safety record, replacing the last one whole; once this
+    /// returns, a crash leaves this one to be loaded
+    fn save_safety(&mut self, record: &SafetyRecord) -> Result<()>;
+
+    /// The safety record last stored, none if none ever was
+    fn load_safety(&self) -> Result<Option<SafetyRecord>>;
+
+    /// Store a commit and the log entry it makes in one write, so that a
+    /// crash leaves both or neither
+    fn save_commit(&mut self, commit: &Commit) -> Result<()>;
+
+    /// Commits of a game stored after `after`, in sequence order
+    fn commits(&self, game_id: &str, after: u64) -> Result<Vec<Commit>>;
+
+    /// Log entries of a game stored after `after`, in sequence order
+    fn log_entries(&self, game_id: &str, after: u64) -> Result<Vec<(u64, ActionId)>>;
+
+    /// Remove a game's commits from `from` to `to`, and their log entries
+    fn remove_commits(&mut self, game_id: &str, from: u64, to: u64) -> Result<()>;
+
+    /// Store the peers we know of, replacing those stored before
+    fn save_peers(&mut self, peers: &[PeerRecord]) -> Result<()>;
+
+    /// The peers stored last
+    fn load_peers(&self) -> Result<Vec<PeerRecord>>;
+
+    /// Whether what is stored outlives the process
+    fn durable(&self) -> bool;
+
+    /// Bytes of stored snapshots held in memory
+    fn memory_bytes(&self) -> u64 {
+        0
+    }
+
+    /// Keep the log entries of a game pruned from `first` on; a store with
+    /// nowhere lasting to put them lets them go
+    fn archive(&mut self, _game_id: &str, _first: u64, _entries: &[ActionId]) -> Result<()> {
+        Ok(())
+    }
+
+    /// The entries archived from `first` on, as they were passed to
+    /// [`archive`](Self::archive)
+    fn archived(&self, _game_id: &str, _first: u64) -> Result<Option<Vec<ActionId>>> {
+        Ok(None)
+    }
+
+    /// The newest snapshot for a game that loads; those that do not, as
+    /// when damaged on disk, are skipped with a warning
+    fn latest(&self, game_id: &str) -> Result<Option<Snapshot>> {
+        for sequence in self.sequences(game_id)?.into_iter().rev() {
+            match self.load(game_id, sequence) {
+                Ok(Some(snapshot)) => return Ok(Some(snapshot)),
+                Ok(None) => {}
+                Err(e) => tracing::warn!("Skipping snapshot of {} at {}: {}", game_id, sequence, e),
+            }
+        }
+        Ok(None)
+    }
+}
+
+/// Build the store selected by the config
+///
+/// A directory an older version wrote to is moved to the present layout
+/// first.
+pub fn open_store(backend: &PersistenceBackend) -> Result<Box<dyn SnapshotStore>> {
+    if let PersistenceBackend::Directory { path, .. } = backend {
+        adopt_old_layout(path)?;
+    }
+    Ok(Box::new(BackendStore::new(storage::open_backend(backend)?)))
+}
+
+/// What precedes a snapshot's data where it is stored
+#[derive(Debug, Serialize, Deserialize)]
+struct SnapshotHeader {
+    version: u16,
+    game_id: String,
+    sequence: u64,
+    state_hash: Hash,
+    created_at_ms: u64,
+    /// Hash of the fields above and the data, telling a damaged file
+    checksum: Hash,
+}
+
+impl SnapshotHeader {
+    fn checksum(snapshot: &Snapshot) -> Hash {
+        crypto::hash_multiple(&[
+            &SNAPSHOT_FORMAT_VERSION.to_le_bytes(),
+            snapshot.game_id.as_bytes(),
+            &snapshot.sequence.to_le_bytes(),
+            &snapshot.state_hash,
+            &snapshot.created_at_ms.to_le_bytes(),
+            &snapshot.data,
+        ])
+    }
+}
+
+/// A snapshot as stored: the header's length as a little-endian `u32`, the
+/// bincode-encoded header, then the data
+fn encode_snapshot(snapshot: &Snapshot) -> Result<Vec<u8>> {
+    let header = SnapshotHeader {
+        version: SNAPSHOT_FORMAT_VERSION,
+        game_id: snapshot.game_id.clone(),
+        sequence: snapshot.sequence,
+        state_hash: snapshot.state_hash,
+        created_at_ms: snapshot.created_at_ms,
+        checksum: SnapshotHeader::checksum(snapshot),
+    };
+    let header =
+        bincode::serialize(&header).map_err(|e| SwarmhostError::Serialization(e.to_string()))?;
+    let mut bytes = Vec::with_capacity(4 + header.len() + snapshot.data.len());
+    bytes.extend_from_slice(&(header.len() as u32).to_le_bytes());
+    bytes.extend_from_slice(&header);
+    bytes.extend_from_slice(&snapshot.data);
+    Ok(bytes)
+}
+
+/// The snapshot stored `bytes` hold, refused as a serialization error if it is
+/// cut short, of another format version, or fails its checksum
+fn decode_snapshot(bytes: &[u8]) -> Result<Snapshot> {
+    let damaged = |what: &str| SwarmhostError::Serialization(format!("snapshot file {}", what));
+    let (length, rest) = bytes
+        .split_first_chunk::<4>()
+        .ok_or_else(|| damaged("cut short"))?;
+    let length = u32::from_le_bytes(*length) as usize;
+    if rest.len() < length {
+        return Err(damaged("cut short"));
+    }
+    let (header, data) = rest.split_at(length);
+    let header: SnapshotHeader =
+        bincode::deserialize(header).map_err(|e| damaged(&e.to_string()))?;
+    if header.version != SNAPSHOT_FORMAT_VERSION {
+        return Err(damaged(&format!("of unknown version {}", header.version)));
+    }
+    let snapshot = Snapshot {
+        game_id: header.game_id,
+        sequence: header.sequence,
+        state_hash: header.state_hash,
+        created_at_ms: header.created_at_ms,
+        data: data.to_vec(),
+    };
+    if SnapshotHeader::checksum(&snapshot) != header.checksum {
+        return Err(damaged("failed its checksum"));
+    }
+    Ok(snapshot)
+}
+
+/// Namespace of each game's snapshots and archived log entries
+const GAMES: &str = "games";
+/// Namespace of the commits since a game's newest snapshot
+const COMMITS: &str = "commits";
+/// Namespace of the log entries those commits make
+const LOG: &str = "log";
+const SAFETY: &str = "safety";
+const PEERS: &str = "peers";
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// A game's id as a key name: its bytes in hex
+fn game_name(game_id: &str) -> String {
+    hex(game_id.as_bytes())
+}
+
+/// The game a name made by [`game_name`] is of
+fn game_of_name(name: &str) -> Option<String> {
+    if !name.len().is_multiple_of(2) {
+        return None;
+    }
+    let bytes = (0..name.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(name.get(i..i + 2)?, 16).ok())
+        .collect::<Option<Vec<u8>>>()?;
+    String::from_utf8(bytes).ok()
+}
+
+fn snapshot_key(game_id: &str, sequence: u64) -> String {
+    format!("{}/{:020}.snap", game_name(game_id), sequence)
+}
+
+fn attestation_key(game_id: &str, sequence: u64) -> String {
+    format!("{}/{:020}.attest", game_name(game_id), sequence)
+}
+
+fn archive_key(game_id: &str, first: u64, last: u64) -> String {
+    format!("{}/{:020}-{:020}.log", game_name(game_id), first, last)
+}
+
+fn commit_key(game_id: &str, sequence: u64) -> String {
+    format!("{}/{:020}", game_name(game_id), sequence)
+}
+
+/// The sequence a key made by [`commit_key`] ends in
+fn sequence_of(key: &str) -> Option<u64> {
+    key.rsplit('/').next()?.parse().ok()
+}
+
+fn encode<T: Serialize + ?Sized>(value: &T) -> Result<Vec<u8>> {
+    bincode::serialize(value).map_err(|e| SwarmhostError::Serialization(e.to_string()))
+}
+
+fn decode<T: for<'de> Deserialize<'de>>(bytes: &[u8], what: &str) -> Result<T> {
+    bincode::deserialize(bytes)
+        .map_err(|e| SwarmhostError::Serialization(format!("{}: {}", what, e)))
+}
+
+/// What a node keeps, as values of a [`StorageBackend`]
+///
+/// Layout, by namespace:
+/// - `games`: `<hex game id>/<sequence, zero padded>.snap` holding one
+///   snapshot behind a header carrying its format version, game, sequence,
+///   state hash and a checksum, `<hex game id>/<sequence, zero padded>.attest`
+///   the bincode-encoded [`SnapshotAttestation`] of the snapshot there, and
+///   `<hex game id>/<first>-<last>.log` the bincode-encoded ids of log
+///   entries archived from `first` to `last`
+/// - `commits` and `log`: `<hex game id>/<sequence, zero padded>` holding a
+///   bincode-encoded commit, and the id of the action it commits
+/// - `safety`: `record`, the bincode-encoded [`SafetyRecord`]
+/// - `peers`: `<hex player id>`, a bincode-encoded [`PeerRecord`]
+///
+/// A commit and its log entry go in one batch. A snapshot damaged anyway
+/// fails its checksum when loaded.
+pub struct BackendStore {
+    backend: Box<dyn StorageBackend>,
+}
+
+impl BackendStore {
+    pub fn new(backend: Box<dyn StorageBackend>) -> Self {
+        Self { backend }
+    }
+
+    /// Sequences of a game's keys in `namespace`, after `after`
+    fn stored_after(&self, namespace: &str, game_id: &str, after: u64) -> Result<Vec<u64>> {
+        let keys = self
+            .backend
+            .keys(namespace, &format!("{}/", game_name(game_id)))?;
+        Ok(keys
+            .iter()
+            .filter_map(|key| sequence_of(key))
+            .filter(|sequence| *sequence > after)
+            .collect())
+    }
+}
+
+impl SnapshotStore for BackendStore {
+    fn save(&mut self, snapshot: &Snapshot) -> Result<()> {
+        let key = snapshot_key(&snapshot.game_id, snapshot.sequence);
+        self.backend.put(GAMES, &key, encode_snapshot(snapshot)?)
+    }
+
+    fn load(&self, game_id: &str, sequence: u64) -> Result<Option<Snapshot>> {
+        let key = snapshot_key(game_id, sequence);
+        let Some(bytes) = self.backend.get(GAMES, &key)? else {
+            return Ok(None);
+        };
+        let snapshot = decode_snapshot(&bytes)
+            .map_err(|e| SwarmhostError::Serialization(format!("{}: {}", key, e)))?;
+        if snapshot.game_id != game_id || snapshot.sequence != sequence {
+            return Err(SwarmhostError::Serialization(format!(
+                "{} holds {} at {}",
+                key, snapshot.game_id, snapshot.sequence
+            )));
+        }
+        Ok(Some(snapshot))
+    }
+
+    fn sequences(&self, game_id: &str) -> Result<Vec<u64>> {
+        let keys = self
+            .backend
+            .keys(GAMES, &format!("{}/", game_name(game_id)))?;
+        let mut sequences: Vec<u64> = keys
+            .iter()
+            .filter_map(|key| key.rsplit('/').next()?.strip_suffix(".snap")?.parse().ok())
+            .collect();
+        sequences.sort_unstable();
+        Ok(sequences)
+    }
+
+    fn remove(&mut self, game_id: &str, sequence: u64) -> Result<()> {
+        let mut batch = WriteBatch::new();
+        batch.delete(GAMES, &snapshot_key(game_id, sequence));
+        batch.delete(GAMES, &attestation_key(game_id, sequence));
+        self.backend.write(batch)
+    }
+
+    fn save_attestation(&mut self, attestation: &SnapshotAttestation) -> Result<()> {
+        let checkpoint = &attestation.checkpoint;
+        let key = attestation_key(&checkpoint.game_id, checkpoint.sequence);
+        self.backend.put(GAMES, &key, encode(attestation)?)
+    }
+
+    fn attestation(&self, game_id: &str, sequence: u64) -> Result<Option<SnapshotAttestation>> {
+        let key = attestation_key(game_id, sequence);
+        self.backend
+            .get(GAMES, &key)?
+            .map(|bytes| decode(&bytes, &key))
+            .transpose()
+    }
+
+    fn games(&self) -> Result<Vec<String>> {
+        let mut games = Vec::new();
+        let snapshots = self.backend.keys(GAMES, "")?;
+        let snapshots = snapshots.iter().filter(|key| key.ends_with(".snap"));
+        for key in snapshots.chain(&self.backend.keys(COMMITS, "")?) {
+            let Some(game_id) = key.split('/').next().and_then(game_of_name) else {
+                continue;
+            };
+            if !games.contains(&game_id) {
+                games.push(game_id);
+            }
+        }
+        games.sort();
+        Ok(games)
+    }
+
+    fn save_safety(&mut self, record: &SafetyRecord) -> Result<()> {
+        self.backend.put(SAFETY, "record", encode(record)?)
+    }
+
+    fn load_safety(&self) -> Result<Option<SafetyRecord>> {
+        self.backend
+            .get(SAFETY, "record")?
+            .map(|bytes| decode(&bytes, "safety record"))
+            .transpose()
+    }
+
+    fn save_commit(&mut self, commit: &Commit) -> Result<()> {
+        let key = commit_key(&commit.action.game_id, commit.sequence);
+        let mut batch = WriteBatch::new();
+        batch.put(COMMITS, &key, encode(commit)?);
+        batch.put(LOG, &key, commit.action.id().to_vec());
+        self.backend.write(batch)
+    }
+
+    fn commits(&self, game_id: &str, after: u64) -> Result<Vec<Commit>> {
+        let mut commits = Vec::new();
+        for sequence in self.stored_after(COMMITS, game_id, after)? {
+            let key = commit_key(game_id, sequence);
+            if let Some(bytes) = self.backend.get(COMMITS, &key)? {
+                commits.push(decode(&bytes, &key)?);
+            }
+        }
+        Ok(commits)
+    }
+
+    fn log_entries(&self, game_id: &str, after: u64) -> Result<Vec<(u64, ActionId)>> {
+        let mut entries = Vec::new();
+        for sequence in self.stored_after(LOG, game_id, after)? {
+            let key = commit_key(game_id, sequence);
+            let Some(bytes) = self.backend.get(LOG, &key)? else {
+                continue;
+            };
+            let action_id = bytes
+                .try_into()
+                .map_err(|_| SwarmhostError::Serialization(format!("{}: not an action id", key)))?;
+            entries.push((sequence, action_id));
+        }
+        Ok(entries)
+    }
+
+    fn remove_commits(&mut self, game_id: &str, from: u64, to: u64) -> Result<()> {
+        let mut batch = WriteBatch::new();
+        for sequence in self.stored_after(COMMITS, game_id, from.saturating_sub(1))? {
+            if sequence >= from && sequence <= to {
+                let key = commit_key(game_id, sequence);
+                batch.delete(COMMITS, &key);
+                batch.delete(LOG, &key);
+            }
+        }
+        if batch.is_empty() {
+            return Ok(());
+        }
+        self.backend.write(batch)
+    }
+
+    fn save_peers(&mut self, peers: &[PeerRecord]) -> Result<()> {
+        let mut batch = WriteBatch::new();
+        let keys: Vec<String> = peers.iter().map(|peer| hex(&peer.player_id)).collect();
+        for stale in self.backend.keys(PEERS, "")? {
+            if !keys.contains(&stale) {
+                batch.delete(PEERS, &stale);
+            }
+        }
+        for (key, peer) in keys.iter().zip(peers) {
+            batch.put(PEERS, key, encode(peer)?);
+        }
+        self.backend.write(batch)
+    }
+
+    fn load_peers(&self) -> Result<Vec<PeerRecord>> {
+        let mut peers = Vec::new();
+        for key in self.backend.keys(PEERS, "")? {
+            if let Some(bytes) = self.backend.get(PEERS, &key)? {
+                peers.push(decode(&bytes, &key)?);
+            }
+        }
+        Ok(peers)
+    }
+
+    fn durable(&self) -> bool {
+        self.backend.durable()
+    }
+
+    fn memory_bytes(&self) -> u64 {
+        self.backend.memory_bytes(GAMES)
+    }
+
+    fn archive(&mut self, game_id: &str, first: u64, entries: &[ActionId]) -> Result<()> {
+        let Some(last) = (first + entries.len() as u64).checked_sub(1) else {
+            return Ok(());
+        };
+        self.backend
+            .put(GAMES, &archive_key(game_id, first, last), encode(entries)?)
+    }
+
+    fn archived(&self, game_id: &str, first: u64) -> Result<Option<Vec<ActionId>>> {
+        let prefix = format!("{}/{:020}-", game_name(game_id), first);
+        for key in self.backend.keys(GAMES, &prefix)? {
+            if !key.ends_with(".log") {
+                continue;
+            }
+            if let Some(bytes) = self.backend.get(GAMES, &key)? {
+                return Ok(Some(decode(&bytes, &key)?));
+            }
+        }
+        Ok(None)
+    }
+}
+
+/// Move what an older version wrote to a directory, a directory of
+/// snapshot and archive files per game and `safety.bin`, to where the
+/// directory backend keeps them now
+fn adopt_old_layout(root: &Path) -> Result<()> {
+    if !root.is_dir() {
+        return Ok(());
+    }
+    for entry in fs::read_dir(root)?.filter_map(|entry| entry.ok()) {
+        let name = entry.file_name();
+        let Some(name) = name.to_str() else {
+            continue;
+        };
+        if game_of_name(name).is_none() || !entry.path().is_dir() {
+            continue;
+        }
+        fs::create_dir_all(root.join(GAMES))?;
+        fs::rename(entry.path(), root.join(GAMES).join(name))?;
+    }
+    let safety = root.join("safety.bin");
+    if safety.exists() {
+        fs::create_dir_all(root.join(SAFETY))?;
+        fs::rename(safety, root.join(SAFETY).join("record"))?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::consensus::SignedAction;
+    use crate::crypto::KeyPair;
+    use crate::state::storage::{DirectoryBackend, MemoryBackend};
+
+    fn commit(keypair: &KeyPair, game_id: &str, sequence: u64) -> Commit {
+        let action = SignedAction::new(keypair, game_id, sequence, 1, vec![sequence as u8]);
+        Commit::new(keypair, sequence, action)
+    }
+
+    fn exercise(store: &mut dyn SnapshotStore) {
+        assert!(store.latest("game/1").unwrap().is_none());
+
+        store
+            .save(&Snapshot::new("game/1", 10, [1; 32], b"ten".to_vec()))
+            .unwrap();
+        store
+            .save(&Snapshot::new("game/1", 20, [2; 32], b"twenty".to_vec()))
+            .unwrap();
+        store
+            .save(&Snapshot::new("other", 5, [3; 32], b"five".to_vec()))
+            .unwrap();
+
+        assert_eq!(store.sequences("game/1").unwrap(), vec![10, 20]);
+        assert_eq!(store.games().unwrap(), vec!["game/1", "other"]);
+        let latest = store.latest("game/1").unwrap().unwrap();
+        assert_eq!(latest.sequence, 20);
+        assert_eq!(latest.data, b"twenty");
+
+        store.remove("game/1", 20).unwrap();
+        assert_eq!(store.latest("game/1").unwrap().unwrap().sequence, 10);
+        assert_eq!(store.sequences("other").unwrap(), vec![5]);
+
+        assert!(store.load_safety().unwrap().is_none());
+        for round in [3, 4] {
+            let record = SafetyRecord {
+                round,
+                ..SafetyRecord::default()
+            };
+            store.save_safety(&record).unwrap();
+            assert_eq!(store.load_safety().unwrap(), Some(record));
+        }
+
+        let keypair = KeyPair::generate();
+        let commits: Vec<Commit> = (1..=12).map(|i| commit(&keypair, "third", i)).collect();
+        for commit in &commits {
+            store.save_commit(commit).unwrap();
+        }
+        assert_eq!(store.games().unwrap(), vec!["game/1", "other", "third"]);
+        assert_eq!(store.commits("third", 9).unwrap(), &commits[9..]);
+        store.remove_commits("third", 1, 10).unwrap();
+        assert_eq!(store.commits("third", 0).unwrap(), &commits[10..]);
+        let entries: Vec<(u64, ActionId)> = commits[10..]
+            .iter()
+            .map(|commit| (commit.sequence, commit.action.id()))
+            .collect();
+        assert_eq!(store.log_entries("third", 0).unwrap(), entries);
+
+        let peers: Vec<PeerRecord> = (0..3)
+            .map(|i| PeerRecord::at([i; 32], format!("10.0.0.{}:7000", i).parse().unwrap()))
+            .collect();
+        store.save_peers(&peers).unwrap();
+        store.save_peers(&peers[1..]).unwrap();
+        assert_eq!(store.load_peers().unwrap(), &peers[1..]);
+    }
+
+    #[test]
+    fn test_memory_store() {
+        exercise(&mut BackendStore::new(Box::new(MemoryBackend::default())));
+    }
+
+    #[test]
+    fn test_directory_store() {
+        let dir = tempfile::tempdir().unwrap();
+        let backend = DirectoryBackend::open(dir.path().to_path_buf(), true).unwrap();
+        exercise(&mut BackendStore::new(Box::new(backend)));
+    }
+
+    #[cfg(feature = "sled")]
+    #[test]
+    fn test_sled_store() {
+        let dir = tempfile::tempdir().unwrap();
+        let backend = storage::SledBackend::open(dir.path(), false).unwrap();
+        exercise(&mut BackendStore::new(Box::new(backend)));
+    }
+
+    #[test]
+    fn test_directory_store_survives_reopen() {
+        let dir = tempfile::tempdir().unwrap();
+        let backend = PersistenceBackend::Directory {
+            path: dir.path().to_path_buf(),
+            fsync: false,
+        };
+
+        let snapshot = Snapshot::new("game", 42, [9; 32], b"state".to_vec());
+        let record = SafetyRecord {
+            round: 7,
+            view: 1,
+            ..SafetyRecord::default()
+        };
+        let commit = commit(&KeyPair::generate(), "game", 43);
+        let mut store = open_store(&backend).unwrap();
+        store.save(&snapshot).unwrap();
+        store.save_safety(&record).unwrap();
+        store.save_commit(&commit).unwrap();
+
+        let reopened = open_store(&backend).unwrap();
+        assert_eq!(reopened.games().unwrap(), vec!["game"]);
+        assert_eq!(reopened.latest("game").unwrap(), Some(snapshot));
+        assert_eq!(reopened.load_safety().unwrap(), Some(record));
+        assert_eq!(reopened.commits("game", 42).unwrap(), vec![commit]);
+    }
+
+    #[test]
+    fn test_directory_written_by_an_older_version_is_taken_up() {
+        let dir = tempfile::tempdir().unwrap();
+        let snapshot = Snapshot::new("game", 42, [9; 32], b"state".to_vec());
+        let record = SafetyRecord {
+            round: 7,
+            ..SafetyRecord::default()
+        };
+        // As the store wrote them before it had a backend under it
+        let game_dir = dir.path().join(game_name("game"));
+        fs::create_dir_all(&game_dir).unwrap();
+        fs::write(
+            game_dir.join(format!("{:020}.snap", 42)),
+            encode_snapshot(&snapshot).unwrap(),
+        )
+        .unwrap();
+        fs::write(dir.path().join("safety.bin"), encode(&record).unwrap()).unwrap();
+
+        let backend = PersistenceBackend::Directory {
+            path: dir.path().to_path_buf(),
+            fsync: false,
+        };
+        let store = open_store(&backend).unwrap();
+        assert_eq!(store.latest("game").unwrap(), Some(snapshot));
+        assert_eq!(store.load_safety().unwrap(), Some(record));
+        assert!(!game_dir.exists());
+    }
+
+    #[test]
+    fn test_damaged_snapshot_files_are_skipped() {
+        let dir = tempfile::tempdir().unwrap();
+        let backend = DirectoryBackend::open(dir.path().to_path_buf(), false).unwrap();
+        let path = |sequence| backend.path(GAMES, &snapshot_key("game", sequence));
+        let mut store = BackendStore::new(Box::new(
+            DirectoryBackend::open(dir.path().to_path_buf(), false).unwrap(),
+        ));
+        let snapshots: Vec<Snapshot> = [10, 20, 30, 40]
+            .into_iter()
+            .map(|sequence| Snapshot::new("game", sequence, [sequence as u8; 32], vec![7; 100]))
+            .collect();
+        for snapshot in &snapshots {
+            store.save(snapshot).unwrap();
+        }
+        // A write a crash cut short never replaced anything
+        fs::write(path(50).with_extension("tmp"), b"half").unwrap();
+        assert_eq!(store.latest("game").unwrap().as_ref(), Some(&snapshots[3]));
+
+        let mut flipped = fs::read(path(40)).unwrap();
+        let last = flipped.len() - 1;
+        flipped[last] ^= 1;
+        fs::write(path(40), flipped).unwrap();
+        let cut = fs::read(path(30)).unwrap();
+        fs::write(path(30), &cut[..cut.len() / 2]).unwrap();
+        fs::copy(path(10), path(20)).unwrap();
+
+        for sequence in [20, 30, 40] {
+            assert!(store.load("game", sequence).is_err(), "{}", sequence);
+        }
+        assert_eq!(store.latest("game").unwrap().as_ref(), Some(&snapshots[0]));
+        fs::write(path(10), b"").unwrap();
+        assert_eq!(store.latest("game").unwrap(), None);
+    }
+}
@@ -0,0 +1,110 @@
+// state/view.rs - The confirmed state of each game, published for readers
+// that must not wait on the node, such as a render loop
+
+use super::ActionLog;
+use crate::crypto::Hash;
+use arc_swap::ArcSwap;
+use std::collections::HashMap;
+use std::sync::Arc;
+
+/// A game's confirmed state as it stood once a batch of commits was
+/// applied, never changed after
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StateView {
+    pub game_id: String,
+    /// Sequence of the last commit applied
+    pub sequence: u64,
+    pub state_hash: Hash,
+    /// The log up to `sequence`, as the node held it
+    pub log: ActionLog,
+}
+
+impl StateView {
+    pub fn of(game_id: &str, log: &ActionLog) -> Self {
+        Self {
+            game_id: game_id.to_string(),
+            sequence: log.sequence(),
+            state_hash: log.hash(),
+            log: log.clone(),
+        }
+    }
+}
+
+/// The latest [`StateView`] of each game, read without locking
+///
+/// A new view replaces a game's last one whole once a batch of delivered
+/// commits is applied, or the log is rolled back or taken up anew, so a
+/// reader sees one or the other and never a view in between. A view is at
+/// most the batch being applied behind the node's log.
+pub struct ConfirmedStates(ArcSwap<HashMap<String, Arc<StateView>>>);
+
+impl Default for ConfirmedStates {
+    fn default() -> Self {
+        Self(ArcSwap::from_pointee(HashMap::new()))
+    }
+}
+
+impl ConfirmedStates {
+    /// The latest view of a game, if one was published
+    pub fn get(&self, game_id: &str) -> Option<Arc<StateView>> {
+        self.0.load().get(game_id).cloned()
+    }
+
+    /// Replace a game's view with `view`
+    pub fn publish(&self, view: StateView) {
+        let view = Arc::new(view);
+        self.0.rcu(|views| {
+            let mut views = HashMap::clone(views);
+            views.insert(view.game_id.clone(), view.clone());
+            views
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto;
+    use std::sync::atomic::{AtomicBool, Ordering};
+
+    #[test]
+    fn test_readers_only_ever_see_whole_views_while_they_are_published() {
+        let states = Arc::new(ConfirmedStates::default());
+        let done = Arc::new(AtomicBool::new(false));
+        let readers: Vec<_> = (0..4)
+            .map(|_| {
+                let (states, done) = (states.clone(), done.clone());
+                std::thread::spawn(move || {
+                    let mut last = 0;
+                    let mut reads = 0u64;
+                    while !(done.load(Ordering::Relaxed) && reads > 0) {
+                        let Some(view) = states.get("game") else {
+                            continue;
+                        };
+                        assert_eq!(view.log.sequence(), view.sequence);
+                        assert_eq!(view.log.hash(), view.state_hash);
+                        assert!(view.sequence >= last, "{} after {}", view.sequence, last);
+                        last = view.sequence;
+                        reads += 1;
+                    }
+                    reads
+                })
+            })
+            .collect();
+
+        let mut log = ActionLog::new();
+        for sequence in 1..=2000u64 {
+            log.append(sequence, &crypto::hash(&sequence.to_be_bytes()))
+                .unwrap();
+            if sequence.is_multiple_of(5) {
+                states.publish(StateView::of("game", &log));
+            }
+        }
+        done.store(true, Ordering::Relaxed);
+        for reader in readers {
+            assert!(reader.join().unwrap() > 0);
+        }
+        assert_eq!(states.get("game").unwrap().sequence, 2000);
+        assert_eq!(states.get("other"), None);
+    }
+}